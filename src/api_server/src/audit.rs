@@ -0,0 +1,148 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Records the API requests which may change the state of the microVM, so that the operators can
+//! reconstruct how it reached its current configuration. Each request is appended to the audit
+//! log as a JSON line, once it was served, e.g.
+//! ```text
+//! {"timestamp":"2020-10-15T10:20:30.123456789","instance_id":"vm0","request_id":"fc-3",
+//!  "method":"PUT","path":"/drives/rootfs","body_len":94,"body_crc64":"0x6c2f5e1b4a3d9e07",
+//!  "status":"204"}
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use logger::{error, request_id, IncMetric, METRICS};
+use micro_http::{Body, Method, Request, Response};
+use serde_json::{json, Value};
+use utils::time::LocalTime;
+
+/// An append-only log of the API requests which may change the state of the microVM.
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path`, creating it when it does not exist. The entries are always
+    /// appended to the file.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(AuditLog { file })
+    }
+
+    /// Records the `request`, served with `response`, unless it cannot change the state of the
+    /// microVM.
+    pub(crate) fn record(&mut self, request: &Request, response: &Response, instance_id: &str) {
+        if request.method() == Method::Get {
+            return;
+        }
+        let mut line = audit_entry(request, response, instance_id).to_string();
+        line.push('\n');
+        if let Err(e) = self.file.write_all(line.as_bytes()) {
+            METRICS.api_server.audit_log_fails.inc();
+            error!("Cannot write to the audit log: {}", e);
+        }
+    }
+}
+
+// Describes the request and the response it was served with. The body of the request is only
+// recorded as a digest, as it may hold secrets, e.g. in the MMDS data store.
+fn audit_entry(request: &Request, response: &Response, instance_id: &str) -> Value {
+    let body = request.body.as_ref().map_or(&[][..], Body::raw);
+    let mut entry = json!({
+        "timestamp": LocalTime::now().to_string(),
+        "instance_id": instance_id,
+        "request_id": request_id(),
+        "method": String::from_utf8_lossy(request.method().raw()),
+        "path": request.uri().get_abs_path(),
+        "body_len": body.len(),
+        "body_crc64": format!("{:#018x}", crc64::crc64(0, body)),
+        "status": String::from_utf8_lossy(response.status().raw()),
+    });
+    // The faults carry the reason the request failed.
+    let fault_message = response
+        .body()
+        .and_then(|body| serde_json::from_slice::<Value>(body.raw()).ok())
+        .and_then(|body| body.get("fault_message").cloned());
+    if let Some(fault_message) = fault_message {
+        entry["error"] = fault_message;
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use micro_http::{StatusCode, Version};
+    use utils::tempfile::TempFile;
+
+    use crate::fault::{ErrorCode, Fault};
+
+    fn request(method: &str, body: &str) -> Request {
+        Request::try_from(
+            format!(
+                "{} /drives/rootfs HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                method,
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_audit_entry() {
+        let body = r#"{"drive_id": "rootfs"}"#;
+        let response = Response::new(Version::Http11, StatusCode::NoContent);
+        let entry = audit_entry(&request("PUT", body), &response, "vm0");
+        assert_eq!(entry["instance_id"], "vm0");
+        assert_eq!(entry["method"], "PUT");
+        assert_eq!(entry["path"], "/drives/rootfs");
+        assert_eq!(entry["body_len"], body.len());
+        assert_eq!(
+            entry["body_crc64"],
+            format!("{:#018x}", crc64::crc64(0, body.as_bytes()))
+        );
+        assert_eq!(entry["status"], "204");
+        assert!(entry["timestamp"].is_string());
+        assert!(entry.get("error").is_none());
+
+        let response: Response = Fault::new(ErrorCode::InvalidId, "Invalid drive ID.").into();
+        let entry = audit_entry(&request("PATCH", body), &response, "vm0");
+        assert_eq!(entry["method"], "PATCH");
+        assert_eq!(entry["status"], "400");
+        assert_eq!(entry["error"], "Invalid drive ID.");
+    }
+
+    #[test]
+    fn test_record() {
+        let file = TempFile::new().unwrap();
+        let mut audit_log = AuditLog::open(file.as_path()).unwrap();
+        let response = Response::new(Version::Http11, StatusCode::NoContent);
+
+        audit_log.record(&request("PUT", "{}"), &response, "vm0");
+        // The requests which cannot change the state of the microVM are not recorded.
+        audit_log.record(&request("GET", ""), &response, "vm0");
+        // The entries are appended to the existing ones.
+        let mut audit_log = AuditLog::open(file.as_path()).unwrap();
+        audit_log.record(&request("PATCH", "{}"), &response, "vm0");
+
+        let lines: Vec<Value> = std::fs::read_to_string(file.as_path())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["method"], "PUT");
+        assert_eq!(lines[1]["method"], "PATCH");
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use micro_http::{Method, PeerCredentials, Request};
+use serde::Deserialize;
+
+use crate::fault::ErrorCode;
+
+/// The operations a client is allowed to perform through the API.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+pub enum ApiAccess {
+    /// Only the `GET` requests are allowed.
+    ReadOnly,
+    /// All the requests are allowed.
+    ReadWrite,
+}
+
+/// Grants an access to the clients presenting a bearer token.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TokenGrant {
+    /// The token, sent as `Authorization: Bearer <token>`.
+    pub token: String,
+    /// The granted access.
+    pub access: ApiAccess,
+}
+
+/// Grants an access to the clients of the API socket running with a user or group ID.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IdGrant {
+    /// The effective user or group ID of the client.
+    pub id: u32,
+    /// The granted access.
+    pub access: ApiAccess,
+}
+
+/// Errors associated with the authorization policy.
+#[derive(Debug)]
+pub enum AuthPolicyError {
+    /// The policy does not grant any access.
+    Empty,
+    /// The policy is not a valid JSON description.
+    InvalidJson(serde_json::Error),
+}
+
+impl fmt::Display for AuthPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AuthPolicyError::*;
+        match self {
+            Empty => write!(f, "The authorization policy does not grant any access."),
+            InvalidJson(err) => write!(f, "Invalid authorization policy: {}", err),
+        }
+    }
+}
+
+/// The reasons for which a request is rejected.
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// The request is a mutation, but the client only has a read-only access.
+    Forbidden,
+    /// The client is not granted any access.
+    Unauthorized,
+}
+
+impl AuthError {
+    /// Returns the error code of the response to a rejected request.
+    pub(crate) fn error_code(&self) -> ErrorCode {
+        match self {
+            AuthError::Forbidden => ErrorCode::Forbidden,
+            AuthError::Unauthorized => ErrorCode::Unauthorized,
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::Forbidden => write!(f, "The client has a read-only access to the API."),
+            AuthError::Unauthorized => write!(f, "The client is not authorized to use the API."),
+        }
+    }
+}
+
+/// Decides which API requests are served, from the bearer token they carry and from the
+/// credentials of the client which sent them through the API socket. A client gets the
+/// broadest access granted to any of them.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ApiAuthPolicy {
+    /// The accesses granted to bearer tokens.
+    #[serde(default)]
+    pub tokens: Vec<TokenGrant>,
+    /// The accesses granted to the effective user IDs of the clients.
+    #[serde(default)]
+    pub uids: Vec<IdGrant>,
+    /// The accesses granted to the effective group IDs of the clients.
+    #[serde(default)]
+    pub gids: Vec<IdGrant>,
+}
+
+impl ApiAuthPolicy {
+    /// Parses a policy from its JSON description.
+    pub fn from_json(json: &str) -> Result<Self, AuthPolicyError> {
+        let policy: ApiAuthPolicy =
+            serde_json::from_str(json).map_err(AuthPolicyError::InvalidJson)?;
+        if policy.tokens.is_empty() && policy.uids.is_empty() && policy.gids.is_empty() {
+            return Err(AuthPolicyError::Empty);
+        }
+        Ok(policy)
+    }
+
+    /// Checks whether `request`, sent by a client with `peer_credentials`, is allowed.
+    pub fn authorize(
+        &self,
+        request: &Request,
+        peer_credentials: Option<PeerCredentials>,
+    ) -> Result<(), AuthError> {
+        let token = request
+            .headers
+            .custom_entry("authorization")
+            .and_then(|value| {
+                let mut parts = value.splitn(2, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => {
+                        Some(token.trim())
+                    }
+                    _ => None,
+                }
+            });
+
+        let token_accesses = self
+            .tokens
+            .iter()
+            .filter(|grant| token.map_or(false, |token| constant_time_eq(&grant.token, token)))
+            .map(|grant| grant.access);
+        let uid_accesses = self
+            .uids
+            .iter()
+            .filter(|grant| peer_credentials.map_or(false, |peer| peer.uid == grant.id))
+            .map(|grant| grant.access);
+        let gid_accesses = self
+            .gids
+            .iter()
+            .filter(|grant| peer_credentials.map_or(false, |peer| peer.gid == grant.id))
+            .map(|grant| grant.access);
+
+        match token_accesses.chain(uid_accesses).chain(gid_accesses).max() {
+            None => Err(AuthError::Unauthorized),
+            Some(ApiAccess::ReadOnly) if request.method() != Method::Get => {
+                Err(AuthError::Forbidden)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+// Compares the tokens in a time which does not depend on the position of their first
+// difference, so that a client cannot guess a token byte after byte.
+fn constant_time_eq(expected: &str, actual: &str) -> bool {
+    if expected.len() != actual.len() {
+        return false;
+    }
+    expected
+        .bytes()
+        .zip(actual.bytes())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, headers: &str) -> Request {
+        Request::try_from(format!("{} / HTTP/1.1\r\n{}\r\n", method, headers).as_bytes()).unwrap()
+    }
+
+    fn peer(uid: u32, gid: u32) -> Option<PeerCredentials> {
+        Some(PeerCredentials { pid: 1, uid, gid })
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let policy = ApiAuthPolicy::from_json(
+            r#"{
+                "tokens": [{ "token": "secret", "access": "ReadWrite" }],
+                "gids": [{ "id": 100, "access": "ReadOnly" }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            ApiAuthPolicy {
+                tokens: vec![TokenGrant {
+                    token: "secret".to_string(),
+                    access: ApiAccess::ReadWrite,
+                }],
+                uids: vec![],
+                gids: vec![IdGrant {
+                    id: 100,
+                    access: ApiAccess::ReadOnly,
+                }],
+            }
+        );
+
+        match ApiAuthPolicy::from_json("{}") {
+            Err(AuthPolicyError::Empty) => (),
+            _ => panic!("Unexpected result."),
+        }
+        match ApiAuthPolicy::from_json(r#"{ "uids": [{ "id": 0, "access": "All" }] }"#) {
+            Err(AuthPolicyError::InvalidJson(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+        match ApiAuthPolicy::from_json(r#"{ "users": [] }"#) {
+            Err(AuthPolicyError::InvalidJson(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_authorize_token() {
+        let policy = ApiAuthPolicy {
+            tokens: vec![
+                TokenGrant {
+                    token: "admin".to_string(),
+                    access: ApiAccess::ReadWrite,
+                },
+                TokenGrant {
+                    token: "viewer".to_string(),
+                    access: ApiAccess::ReadOnly,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let admin = "Authorization: Bearer admin\r\n";
+        assert!(policy.authorize(&request("GET", admin), None).is_ok());
+        assert!(policy.authorize(&request("PUT", admin), None).is_ok());
+        // The scheme is case insensitive.
+        assert!(policy
+            .authorize(&request("PUT", "Authorization: bearer admin\r\n"), None)
+            .is_ok());
+
+        let viewer = "Authorization: Bearer viewer\r\n";
+        assert!(policy.authorize(&request("GET", viewer), None).is_ok());
+        assert_eq!(
+            policy.authorize(&request("PATCH", viewer), None),
+            Err(AuthError::Forbidden)
+        );
+
+        for headers in &[
+            "",
+            "Authorization: Bearer admin2\r\n",
+            "Authorization: Basic admin\r\n",
+            "Authorization: admin\r\n",
+        ] {
+            assert_eq!(
+                policy.authorize(&request("GET", headers), None),
+                Err(AuthError::Unauthorized)
+            );
+        }
+    }
+
+    #[test]
+    fn test_authorize_peer_credentials() {
+        let policy = ApiAuthPolicy {
+            tokens: vec![TokenGrant {
+                token: "admin".to_string(),
+                access: ApiAccess::ReadWrite,
+            }],
+            uids: vec![IdGrant {
+                id: 1000,
+                access: ApiAccess::ReadWrite,
+            }],
+            gids: vec![IdGrant {
+                id: 100,
+                access: ApiAccess::ReadOnly,
+            }],
+        };
+
+        assert!(policy
+            .authorize(&request("PUT", ""), peer(1000, 1000))
+            .is_ok());
+        assert!(policy.authorize(&request("GET", ""), peer(0, 100)).is_ok());
+        assert_eq!(
+            policy.authorize(&request("PUT", ""), peer(0, 100)),
+            Err(AuthError::Forbidden)
+        );
+        assert_eq!(
+            policy.authorize(&request("GET", ""), peer(0, 0)),
+            Err(AuthError::Unauthorized)
+        );
+        // The broadest access wins.
+        assert!(policy
+            .authorize(
+                &request("PUT", "Authorization: Bearer admin\r\n"),
+                peer(0, 100)
+            )
+            .is_ok());
+        // The peers of the TCP and vsock transports are not identified.
+        assert_eq!(
+            policy.authorize(&request("GET", ""), None),
+            Err(AuthError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("token", "token"));
+        assert!(!constant_time_eq("token", "tokem"));
+        assert!(!constant_time_eq("token", "token2"));
+        assert!(!constant_time_eq("token", ""));
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Serialize;
+use serde_json::error::Category;
+
+use micro_http::{Body, Response, StatusCode, Version};
+use vmm::rpc_interface::VmmActionError;
+
+/// The machine-readable code of an API error.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub(crate) enum ErrorCode {
+    /// The client has a read-only access to the API.
+    Forbidden,
+    /// Firecracker failed to serve the request on its own.
+    InternalError,
+    /// A resource ID from the request path is invalid.
+    InvalidId,
+    /// The request body is not valid JSON.
+    InvalidJson,
+    /// The combination of method, path and body is not valid.
+    InvalidRequest,
+    /// The action is not allowed in the current state of the microVM.
+    InvalidState,
+    /// A value of the request body is invalid.
+    InvalidValue,
+    /// A mandatory field is missing from the request body.
+    MissingField,
+    /// The requested resource does not exist.
+    NotFound,
+    /// The VMM failed to carry out a valid action.
+    OperationFailed,
+    /// The request body is larger than allowed.
+    PayloadTooLarge,
+    /// The client sent too many requests.
+    TooManyRequests,
+    /// The client is not authorized to use the API.
+    Unauthorized,
+    /// The request body has a field unknown to the resource.
+    UnknownField,
+    /// The feature is not supported on this host.
+    Unsupported,
+}
+
+impl ErrorCode {
+    /// The HTTP status of the responses carrying this error code.
+    pub(crate) fn status_code(self) -> StatusCode {
+        match self {
+            ErrorCode::Forbidden => StatusCode::Forbidden,
+            ErrorCode::InternalError => StatusCode::InternalServerError,
+            ErrorCode::NotFound => StatusCode::NotFound,
+            ErrorCode::PayloadTooLarge => StatusCode::PayloadTooLarge,
+            ErrorCode::TooManyRequests => StatusCode::TooManyRequests,
+            ErrorCode::Unauthorized => StatusCode::Unauthorized,
+            ErrorCode::InvalidId
+            | ErrorCode::InvalidJson
+            | ErrorCode::InvalidRequest
+            | ErrorCode::InvalidState
+            | ErrorCode::InvalidValue
+            | ErrorCode::MissingField
+            | ErrorCode::OperationFailed
+            | ErrorCode::UnknownField
+            | ErrorCode::Unsupported => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl From<&VmmActionError> for ErrorCode {
+    fn from(err: &VmmActionError) -> Self {
+        match err {
+            VmmActionError::InternalVmm(_) | VmmActionError::StartMicrovm(_) => {
+                ErrorCode::OperationFailed
+            }
+            #[cfg(target_arch = "x86_64")]
+            VmmActionError::CoreDump(_)
+            | VmmActionError::CreateSnapshot(_)
+            | VmmActionError::LoadSnapshot(_) => ErrorCode::OperationFailed,
+            #[cfg(target_arch = "x86_64")]
+            VmmActionError::LoadSnapshotNotAllowed => ErrorCode::InvalidState,
+            VmmActionError::OperationNotSupportedPostBoot
+            | VmmActionError::OperationNotSupportedPreBoot => ErrorCode::InvalidState,
+            // The other errors are about the configuration given by the client.
+            _ => ErrorCode::InvalidValue,
+        }
+    }
+}
+
+impl From<&serde_json::Error> for ErrorCode {
+    fn from(err: &serde_json::Error) -> Self {
+        match err.classify() {
+            Category::Io | Category::Syntax | Category::Eof => ErrorCode::InvalidJson,
+            Category::Data => {
+                let msg = err.to_string();
+                if msg.starts_with("missing field") {
+                    ErrorCode::MissingField
+                } else if msg.starts_with("unknown field") {
+                    ErrorCode::UnknownField
+                } else {
+                    ErrorCode::InvalidValue
+                }
+            }
+        }
+    }
+}
+
+/// The structured body of an API error response.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Fault {
+    /// The description of the error, for humans.
+    pub(crate) fault_message: String,
+    pub(crate) error_code: ErrorCode,
+    /// The path of the offending field of the request body, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) field: Option<String>,
+}
+
+impl Fault {
+    pub(crate) fn new<T: Into<String>>(error_code: ErrorCode, fault_message: T) -> Self {
+        Fault {
+            fault_message: fault_message.into(),
+            error_code,
+            field: None,
+        }
+    }
+
+    /// Sets the path of the offending field of the request body.
+    pub(crate) fn with_field<T: Into<String>>(mut self, field: T) -> Self {
+        self.field = Some(field.into());
+        self
+    }
+
+    /// The fault describing an error which occurred when deserializing a request body.
+    pub(crate) fn from_serde_json<T: Into<String>>(err: &serde_json::Error, msg: T) -> Self {
+        let fault = Fault::new(ErrorCode::from(err), msg);
+        match serde_field(err) {
+            Some(field) => fault.with_field(field),
+            None => fault,
+        }
+    }
+
+    /// The fault as a JSON string.
+    pub(crate) fn to_json(&self) -> String {
+        // Serializing strings and unit variants cannot fail.
+        serde_json::to_string(self).expect("Cannot serialize the fault")
+    }
+}
+
+impl Into<Response> for Fault {
+    fn into(self) -> Response {
+        let mut response = Response::new(Version::Http11, self.error_code.status_code());
+        response.set_body(Body::new(self.to_json()));
+        response
+    }
+}
+
+// The name of the missing or unknown field a deserialization error is about, if any. `serde`
+// quotes it between backticks, as in "missing field `drive_id` at line 1 column 2".
+fn serde_field(err: &serde_json::Error) -> Option<String> {
+    let msg = err.to_string();
+    if !msg.starts_with("missing field `") && !msg.starts_with("unknown field `") {
+        return None;
+    }
+    msg.splitn(3, '`').nth(1).map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Config {
+        id: String,
+        size: u64,
+    }
+
+    fn serde_error(body: &str) -> serde_json::Error {
+        serde_json::from_str::<Config>(body).unwrap_err()
+    }
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(ErrorCode::Forbidden.status_code(), StatusCode::Forbidden);
+        assert_eq!(
+            ErrorCode::InternalError.status_code(),
+            StatusCode::InternalServerError
+        );
+        assert_eq!(ErrorCode::NotFound.status_code(), StatusCode::NotFound);
+        assert_eq!(
+            ErrorCode::PayloadTooLarge.status_code(),
+            StatusCode::PayloadTooLarge
+        );
+        assert_eq!(
+            ErrorCode::TooManyRequests.status_code(),
+            StatusCode::TooManyRequests
+        );
+        assert_eq!(
+            ErrorCode::Unauthorized.status_code(),
+            StatusCode::Unauthorized
+        );
+        assert_eq!(
+            ErrorCode::MissingField.status_code(),
+            StatusCode::BadRequest
+        );
+        assert_eq!(
+            ErrorCode::OperationFailed.status_code(),
+            StatusCode::BadRequest
+        );
+    }
+
+    #[test]
+    fn test_vmm_action_error_code() {
+        assert_eq!(
+            ErrorCode::from(&VmmActionError::OperationNotSupportedPreBoot),
+            ErrorCode::InvalidState
+        );
+        assert_eq!(
+            ErrorCode::from(&VmmActionError::StartMicrovm(
+                vmm::builder::StartMicrovmError::MissingKernelConfig
+            )),
+            ErrorCode::OperationFailed
+        );
+        #[cfg(feature = "block")]
+        assert_eq!(
+            ErrorCode::from(&VmmActionError::DriveConfig(
+                vmm::vmm_config::drive::DriveError::RootBlockDeviceAlreadyAdded
+            )),
+            ErrorCode::InvalidValue
+        );
+    }
+
+    #[test]
+    fn test_from_serde_json() {
+        let fault = Fault::from_serde_json(&serde_error("{\"id\": \"a\""), "msg");
+        assert_eq!(fault, Fault::new(ErrorCode::InvalidJson, "msg"));
+        let fault = Fault::from_serde_json(&serde_error("{\"id\": \"a\"]"), "msg");
+        assert_eq!(fault.error_code, ErrorCode::InvalidJson);
+
+        let fault = Fault::from_serde_json(&serde_error("{\"id\": \"a\"}"), "msg");
+        assert_eq!(
+            fault,
+            Fault::new(ErrorCode::MissingField, "msg").with_field("size")
+        );
+        let fault = Fault::from_serde_json(&serde_error("{\"id\": \"a\", \"sise\": 1}"), "msg");
+        assert_eq!(
+            fault,
+            Fault::new(ErrorCode::UnknownField, "msg").with_field("sise")
+        );
+        let fault = Fault::from_serde_json(&serde_error("{\"id\": \"a\", \"size\": -1}"), "msg");
+        assert_eq!(fault, Fault::new(ErrorCode::InvalidValue, "msg"));
+    }
+
+    #[test]
+    fn test_fault_response() {
+        assert_eq!(
+            Fault::new(ErrorCode::InvalidId, "msg").to_json(),
+            r#"{"fault_message":"msg","error_code":"InvalidId"}"#
+        );
+        assert_eq!(
+            Fault::new(ErrorCode::MissingField, "msg")
+                .with_field("drive_id")
+                .to_json(),
+            r#"{"fault_message":"msg","error_code":"MissingField","field":"drive_id"}"#
+        );
+
+        let response: Response = Fault::new(ErrorCode::NotFound, "msg").into();
+        assert_eq!(response.status(), StatusCode::NotFound);
+        assert_eq!(
+            response.body().unwrap().raw(),
+            br#"{"fault_message":"msg","error_code":"NotFound"}"#
+        );
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::fault::ErrorCode;
+use crate::parsed_request::ParsedRequest;
+use vmm::rpc_interface::{VmmActionError, VmmData};
+
+// The maximum number of jobs kept for reporting. Beyond it, the oldest finished jobs are
+// forgotten.
+const MAX_JOBS: usize = 128;
+
+/// The state of an asynchronous job.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "state")]
+pub(crate) enum JobState {
+    /// The action is queued or running on the VMM.
+    InProgress,
+    /// The action succeeded.
+    Succeeded {
+        /// The body the synchronous request would have responded with, if any.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result: Option<Value>,
+    },
+    /// The action failed.
+    Failed {
+        /// The description of the error.
+        error: String,
+        /// The machine-readable code of the error.
+        error_code: ErrorCode,
+    },
+}
+
+/// An asynchronous job, as reported by `GET /jobs/{id}`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct Job {
+    pub(crate) id: u64,
+    #[serde(flatten)]
+    pub(crate) state: JobState,
+}
+
+/// Keeps track of the actions sent to the VMM without waiting for their outcome.
+pub(crate) struct Jobs {
+    next_id: u64,
+    // The jobs still waiting for an outcome, in the order their actions were sent to the VMM,
+    // which is the order of the outcomes.
+    pending: VecDeque<u64>,
+    states: BTreeMap<u64, JobState>,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Jobs {
+            next_id: 1,
+            pending: VecDeque::new(),
+            states: BTreeMap::new(),
+        }
+    }
+}
+
+impl Jobs {
+    /// Registers a job for an action just sent to the VMM and returns its ID.
+    pub(crate) fn start(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push_back(id);
+        self.states.insert(id, JobState::InProgress);
+
+        if self.states.len() > MAX_JOBS {
+            let oldest_finished = self
+                .states
+                .iter()
+                .find(|(_, state)| **state != JobState::InProgress)
+                .map(|(id, _)| *id);
+            if let Some(oldest_finished) = oldest_finished {
+                self.states.remove(&oldest_finished);
+            }
+        }
+        id
+    }
+
+    /// Whether some jobs are waiting for the outcome of their action.
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// The number of jobs waiting for the outcome of their action.
+    pub(crate) fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Records the outcome of the oldest pending job.
+    pub(crate) fn complete(&mut self, outcome: &std::result::Result<VmmData, VmmActionError>) {
+        let id = match self.pending.pop_front() {
+            Some(id) => id,
+            None => return,
+        };
+        let state = match outcome {
+            Ok(_) => JobState::Succeeded {
+                result: ParsedRequest::convert_to_response(outcome)
+                    .body()
+                    .and_then(|body| serde_json::from_slice(body.raw()).ok()),
+            },
+            Err(e) => JobState::Failed {
+                error: e.to_string(),
+                error_code: ErrorCode::from(e),
+            },
+        };
+        self.states.insert(id, state);
+    }
+
+    /// Returns the job with the given ID, unless it is unknown or was forgotten.
+    pub(crate) fn get(&self, id: u64) -> Option<Job> {
+        self.states.get(&id).map(|state| Job {
+            id,
+            state: state.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jobs() {
+        let mut jobs = Jobs::default();
+        assert!(!jobs.has_pending());
+        assert_eq!(jobs.get(1), None);
+
+        assert_eq!(jobs.start(), 1);
+        assert_eq!(jobs.start(), 2);
+        assert!(jobs.has_pending());
+        assert_eq!(jobs.pending_count(), 2);
+        assert_eq!(jobs.get(1).unwrap().state, JobState::InProgress);
+
+        // The outcomes are assigned in order.
+        jobs.complete(&Ok(VmmData::Empty));
+        assert_eq!(
+            jobs.get(1).unwrap().state,
+            JobState::Succeeded { result: None }
+        );
+        assert_eq!(jobs.get(2).unwrap().state, JobState::InProgress);
+        jobs.complete(&Err(VmmActionError::OperationNotSupportedPreBoot));
+        assert_eq!(
+            jobs.get(2).unwrap().state,
+            JobState::Failed {
+                error: VmmActionError::OperationNotSupportedPreBoot.to_string(),
+                error_code: ErrorCode::InvalidState,
+            }
+        );
+        assert!(!jobs.has_pending());
+        assert_eq!(jobs.pending_count(), 0);
+        // An outcome without a pending job is ignored.
+        jobs.complete(&Ok(VmmData::Empty));
+    }
+
+    #[test]
+    fn test_forget_finished_jobs() {
+        let mut jobs = Jobs::default();
+        for _ in 0..MAX_JOBS {
+            jobs.start();
+            jobs.complete(&Ok(VmmData::Empty));
+        }
+        assert_eq!(jobs.states.len(), MAX_JOBS);
+
+        // The oldest finished job is forgotten.
+        let id = jobs.start();
+        assert_eq!(jobs.states.len(), MAX_JOBS);
+        assert_eq!(jobs.get(id).unwrap().state, JobState::InProgress);
+        assert_eq!(jobs.get(1), None);
+        assert!(jobs.get(2).is_some());
+    }
+
+    #[test]
+    fn test_serialize_job() {
+        let job = Job {
+            id: 3,
+            state: JobState::InProgress,
+        };
+        assert_eq!(
+            serde_json::to_string(&job).unwrap(),
+            r#"{"id":3,"state":"InProgress"}"#
+        );
+
+        let job = Job {
+            id: 3,
+            state: JobState::Succeeded {
+                result: Some(serde_json::json!({ "amount_mib": 1 })),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&job).unwrap(),
+            r#"{"id":3,"state":"Succeeded","result":{"amount_mib":1}}"#
+        );
+
+        let job = Job {
+            id: 3,
+            state: JobState::Failed {
+                error: "error".to_string(),
+                error_code: ErrorCode::OperationFailed,
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&job).unwrap(),
+            r#"{"id":3,"state":"Failed","error":"error","error_code":"OperationFailed"}"#
+        );
+    }
+}
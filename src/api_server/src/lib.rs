@@ -1,34 +1,59 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
+mod audit;
+mod auth;
+mod fault;
+mod jobs;
+mod limits;
+mod metrics_listener;
 mod parsed_request;
 mod request;
 
 use serde_json::json;
 use std::path::PathBuf;
+use std::sync::mpsc::TryRecvError;
 use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::{fmt, io};
 
+pub use crate::audit::AuditLog;
+pub use crate::auth::{ApiAccess, ApiAuthPolicy, AuthPolicyError, IdGrant, TokenGrant};
+use crate::fault::{ErrorCode, Fault};
+use crate::jobs::Jobs;
+pub use crate::limits::ApiLimits;
+use crate::limits::RequestRates;
+pub use crate::metrics_listener::MetricsListener;
 use crate::parsed_request::ParsedRequest;
 use logger::{
-    debug, error, info, update_metric_with_elapsed_time, IncMetric, StoreMetric, METRICS,
+    error, info, next_request_id, request_id, set_request_id, update_metric_with_elapsed_time,
+    IncMetric, Span, StoreMetric, METRICS,
 };
+use micro_http::MediaType;
 pub use micro_http::{
-    Body, HttpServer, Method, Request, RequestError, Response, ServerError, ServerRequest,
-    ServerResponse, StatusCode, Version,
+    Body, HttpServer, Method, PeerCredentials, Request, RequestError, Response, ServerError,
+    ServerRequest, ServerResponse, ServerTransport, StatusCode, Version,
 };
 use mmds::data_store;
 use mmds::data_store::Mmds;
+use mmds::patch::PatchOperation;
 use seccomp::{BpfProgram, SeccompFilter};
 use utils::eventfd::EventFd;
+#[cfg(feature = "vsock")]
+use vmm::agent::{AgentCommand, AgentError, AgentOutput, AGENT_CHANNEL};
 use vmm::rpc_interface::{VmmAction, VmmActionError, VmmData};
+use vmm::sd_notify::SD_NOTIFY;
 use vmm::vmm_config::instance_info::InstanceInfo;
 #[cfg(target_arch = "x86_64")]
 use vmm::vmm_config::snapshot::SnapshotType;
 
 use vmm::FC_EXIT_CODE_BAD_CONFIGURATION;
 
-/// Shorthand type for a request containing a boxed VmmAction.
-pub type ApiRequest = Box<VmmAction>;
+/// A request for the VMM, tagged with the ID of the API request it serves.
+pub struct ApiRequest {
+    /// The action carried out by the VMM.
+    pub action: Box<VmmAction>,
+    /// The ID of the API request, carried by the spans of the VMM thread while serving it.
+    pub request_id: Option<String>,
+}
 /// Shorthand type for a response containing a boxed Result.
 pub type ApiResponse = Box<std::result::Result<VmmData, VmmActionError>>;
 
@@ -57,6 +82,9 @@ impl fmt::Debug for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// The longest request ID accepted from the clients.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
 pub struct ApiServer {
     /// MMDS info directly accessible from the API thread.
     mmds_info: Arc<Mutex<Mmds>>,
@@ -72,6 +100,16 @@ pub struct ApiServer {
     /// If this flag is set, the process encountered a fatal error
     /// and it is going to exit once it sends any pending API response.
     vmm_fatal_error: bool,
+    /// If set, only the requests allowed by this policy are served.
+    auth_policy: Option<ApiAuthPolicy>,
+    /// The actions sent to the VMM without waiting for their outcome.
+    jobs: Jobs,
+    /// The limits on the size and rate of the requests.
+    limits: ApiLimits,
+    /// Whether systemd is notified that the VMM is ready once the API is served.
+    notify_ready: bool,
+    /// If set, the requests which may change the state of the microVM are recorded here.
+    audit_log: Option<AuditLog>,
 }
 
 impl ApiServer {
@@ -89,12 +127,34 @@ impl ApiServer {
             vmm_response_receiver,
             to_vmm_fd,
             vmm_fatal_error: false,
+            auth_policy: None,
+            jobs: Jobs::default(),
+            limits: ApiLimits::default(),
+            notify_ready: false,
+            audit_log: None,
         })
     }
 
+    pub fn set_auth_policy(&mut self, auth_policy: ApiAuthPolicy) {
+        self.auth_policy = Some(auth_policy);
+    }
+
+    pub fn set_limits(&mut self, limits: ApiLimits) {
+        self.limits = limits;
+    }
+
+    pub fn set_notify_ready(&mut self, notify_ready: bool) {
+        self.notify_ready = notify_ready;
+    }
+
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.audit_log = Some(audit_log);
+    }
+
     pub fn bind_and_run(
         &mut self,
         path: PathBuf,
+        transports: &[ServerTransport],
         start_time_us: Option<u64>,
         start_time_cpu_us: Option<u64>,
         seccomp_filter: BpfProgram,
@@ -103,6 +163,12 @@ impl ApiServer {
             error!("Error creating the HTTP server: {}", e);
             std::process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
         });
+        for transport in transports {
+            server.add_transport(*transport).unwrap_or_else(|e| {
+                error!("Error serving the API on {:?}: {}", transport, e);
+                std::process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+            });
+        }
 
         if let Some(start_time) = start_time_us {
             let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic) - start_time;
@@ -131,27 +197,64 @@ impl ApiServer {
             );
         }
 
+        // The body size is checked against the limit of the request path by
+        // `handle_request()`, the server only discards the bodies beyond all of them.
+        server.set_payload_max_size(self.limits.max_payload_limit());
+        let mut request_rates = RequestRates::new(self.limits.max_requests_per_sec);
         server.start_server().expect("Cannot start HTTP server");
+        if self.notify_ready {
+            SD_NOTIFY.notify("READY=1\nSTATUS=Waiting for the microVM configuration");
+        }
         loop {
             match server.requests() {
                 Ok(request_vec) => {
                     for server_request in request_vec {
                         let request_processing_start_us =
                             utils::time::get_time_us(utils::time::ClockType::Monotonic);
+                        let throttled = request_rates.is_throttled(server_request.listener_fd());
+                        if throttled {
+                            METRICS.api_server.rate_limited_requests.inc();
+                        }
+                        let peer_credentials = server_request.peer_credentials();
                         server
                             .respond(
-                                // Use `self.handle_request()` as the processing callback.
+                                // Use `self.handle_request()` as the processing callback for
+                                // the authorized requests.
                                 server_request.process(|request| {
-                                    self.handle_request(request, request_processing_start_us)
+                                    // The requests beyond the rate of their socket are not
+                                    // served.
+                                    if throttled {
+                                        return Fault::new(
+                                            ErrorCode::TooManyRequests,
+                                            "Too many requests on this socket.",
+                                        )
+                                        .into();
+                                    }
+                                    set_request_id(Some(client_request_id(request)));
+                                    let mut span = Span::new("api_request");
+                                    span.record(
+                                        "method",
+                                        String::from_utf8_lossy(request.method().raw()),
+                                    )
+                                    .record("path", request.uri().get_abs_path());
+                                    let response = match self.authorize(request, peer_credentials) {
+                                        Ok(()) => self
+                                            .handle_request(request, request_processing_start_us),
+                                        Err(response) => response,
+                                    };
+                                    span.record(
+                                        "status",
+                                        String::from_utf8_lossy(response.status().raw()),
+                                    );
+                                    self.audit(request, &response);
+                                    response
                                 }),
                             )
                             .or_else(|e| {
                                 error!("API Server encountered an error on response: {}", e);
                                 Ok(())
                             })?;
-                        let delta_us = utils::time::get_time_us(utils::time::ClockType::Monotonic)
-                            - request_processing_start_us;
-                        debug!("Total previous API call duration: {} us.", delta_us);
+                        set_request_id(None);
                         if self.vmm_fatal_error {
                             // Flush the remaining outgoing responses
                             // and proceed to exit
@@ -181,12 +284,36 @@ impl ApiServer {
         request: &Request,
         request_processing_start_us: u64,
     ) -> Response {
+        let path = request.uri().get_abs_path();
+        let payload_limit = self.limits.payload_limit(path);
+        let payload_size = request.body.as_ref().map_or(0, Body::len);
+        if payload_size > payload_limit {
+            METRICS.api_server.payload_too_large.inc();
+            let msg = format!(
+                "The body of the request to {} is {} bytes long, larger than the limit of {} bytes.",
+                path, payload_size, payload_limit
+            );
+            error!("{}", msg);
+            return Fault::new(ErrorCode::PayloadTooLarge, msg).into();
+        }
+
         match ParsedRequest::try_from_request(request) {
             Ok(ParsedRequest::Sync(vmm_action)) => {
-                self.serve_vmm_action_request(vmm_action, request_processing_start_us)
+                if request.method() != Method::Get && prefers_async(request) {
+                    self.start_job(vmm_action)
+                } else {
+                    self.serve_vmm_action_request(vmm_action, request_processing_start_us)
+                }
             }
+            #[cfg(feature = "vsock")]
+            Ok(ParsedRequest::Agent(command)) => ApiServer::agent_response(command),
             Ok(ParsedRequest::GetInstanceInfo) => self.get_instance_info(),
+            Ok(ParsedRequest::GetJob(id)) => self.get_job(id),
+            Ok(ParsedRequest::GetMetrics) => ApiServer::metrics_response(),
             Ok(ParsedRequest::GetMMDS) => self.get_mmds(),
+            Ok(ParsedRequest::GetMMDSStatus) => self.get_mmds_status(),
+            Ok(ParsedRequest::GetTimings) => ApiServer::timings_response(),
+            Ok(ParsedRequest::JsonPatchMMDS(operations)) => self.json_patch_mmds(operations),
             Ok(ParsedRequest::PatchMMDS(value)) => self.patch_mmds(value),
             Ok(ParsedRequest::PutMMDS(value)) => self.put_mmds(value),
             Err(e) => {
@@ -196,51 +323,145 @@ impl ApiServer {
         }
     }
 
+    // Records the request in the audit log, if there is one.
+    fn audit(&mut self, request: &Request, response: &Response) {
+        if let Some(audit_log) = self.audit_log.as_mut() {
+            let instance_id = self
+                .vmm_shared_info
+                .read()
+                .expect("Poisoned lock")
+                .id
+                .clone();
+            audit_log.record(request, response, &instance_id);
+        }
+    }
+
+    fn authorize(
+        &self,
+        request: &Request,
+        peer_credentials: Option<PeerCredentials>,
+    ) -> std::result::Result<(), Response> {
+        match self.auth_policy {
+            Some(ref policy) => policy.authorize(request, peer_credentials).map_err(|e| {
+                METRICS.api_server.auth_fails.inc();
+                error!(
+                    "Rejected API request {:?} {}: {}",
+                    request.method(),
+                    request.uri().get_abs_path(),
+                    e
+                );
+                Fault::new(e.error_code(), e.to_string()).into()
+            }),
+            None => Ok(()),
+        }
+    }
+
     fn serve_vmm_action_request(
         &mut self,
         vmm_action: Box<VmmAction>,
         request_processing_start_us: u64,
     ) -> Response {
-        let metric_with_action = match *vmm_action {
+        let metric = match *vmm_action {
             #[cfg(target_arch = "x86_64")]
             VmmAction::CreateSnapshot(ref params) => match params.snapshot_type {
-                SnapshotType::Full => Some((
-                    &METRICS.latencies_us.full_create_snapshot,
-                    "create full snapshot",
-                )),
-                SnapshotType::Diff => Some((
-                    &METRICS.latencies_us.diff_create_snapshot,
-                    "create diff snapshot",
-                )),
+                SnapshotType::Full => Some(&METRICS.latencies_us.full_create_snapshot),
+                SnapshotType::Diff => Some(&METRICS.latencies_us.diff_create_snapshot),
             },
             #[cfg(target_arch = "x86_64")]
-            VmmAction::LoadSnapshot(_) => {
-                Some((&METRICS.latencies_us.load_snapshot, "load snapshot"))
-            }
-            VmmAction::Pause => Some((&METRICS.latencies_us.pause_vm, "pause vm")),
-            VmmAction::Resume => Some((&METRICS.latencies_us.resume_vm, "resume vm")),
+            VmmAction::LoadSnapshot(_) => Some(&METRICS.latencies_us.load_snapshot),
+            VmmAction::Pause => Some(&METRICS.latencies_us.pause_vm),
+            VmmAction::Resume => Some(&METRICS.latencies_us.resume_vm),
             _ => None,
         };
 
-        self.api_request_sender
-            .send(vmm_action)
-            .expect("Failed to send VMM message");
-        self.to_vmm_fd.write(1).expect("Cannot update send VMM fd");
-        let vmm_outcome = *(self.vmm_response_receiver.recv().expect("VMM disconnected"));
-        #[cfg(target_arch = "x86_64")]
-        self.check_for_fatal_error(&vmm_outcome);
+        self.send_to_vmm(vmm_action);
+        let vmm_outcome = self.recv_vmm_outcome();
         let response = ParsedRequest::convert_to_response(&vmm_outcome);
 
         if vmm_outcome.is_ok() {
-            if let Some((metric, action)) = metric_with_action {
-                let elapsed_time_us =
-                    update_metric_with_elapsed_time(metric, request_processing_start_us);
-                info!("'{}' API request took {} us.", action, elapsed_time_us);
+            if let Some(metric) = metric {
+                update_metric_with_elapsed_time(metric, request_processing_start_us);
             }
         }
         response
     }
 
+    // Sends `vmm_action` to the VMM, tagged with the ID of the API request being served.
+    fn send_to_vmm(&mut self, vmm_action: Box<VmmAction>) {
+        self.api_request_sender
+            .send(ApiRequest {
+                action: vmm_action,
+                request_id: request_id(),
+            })
+            .expect("Failed to send VMM message");
+        self.to_vmm_fd.write(1).expect("Cannot update send VMM fd");
+    }
+
+    // Sends `vmm_action` to the VMM and responds right away with the ID of a job, which
+    // reports the outcome of the action later on.
+    fn start_job(&mut self, vmm_action: Box<VmmAction>) -> Response {
+        self.collect_job_outcomes();
+        if self.jobs.pending_count() >= self.limits.max_in_flight_requests {
+            METRICS.api_server.too_many_in_flight.inc();
+            return Fault::new(
+                ErrorCode::TooManyRequests,
+                format!(
+                    "Too many jobs in progress, the limit is {}.",
+                    self.limits.max_in_flight_requests
+                ),
+            )
+            .into();
+        }
+        self.send_to_vmm(vmm_action);
+        let id = self.jobs.start();
+        info!("The request is served asynchronously by the job {}.", id);
+        ApiServer::json_response(StatusCode::Accepted, json!({ "job_id": id }).to_string())
+    }
+
+    fn get_job(&mut self, id: u64) -> Response {
+        self.collect_job_outcomes();
+        match self.jobs.get(id) {
+            // Serializing the job state cannot fail.
+            Some(job) => ApiServer::json_response(
+                StatusCode::OK,
+                serde_json::to_string(&job).expect("Cannot serialize the job"),
+            ),
+            None => {
+                METRICS.get_api_requests.job_fails.inc();
+                Fault::new(ErrorCode::NotFound, format!("Unknown job {}.", id)).into()
+            }
+        }
+    }
+
+    // Waits for the outcome of the last action sent to the VMM. The outcomes of the jobs
+    // started before it arrive first, and are recorded on the way.
+    fn recv_vmm_outcome(&mut self) -> std::result::Result<VmmData, VmmActionError> {
+        loop {
+            let vmm_outcome = *(self.vmm_response_receiver.recv().expect("VMM disconnected"));
+            #[cfg(target_arch = "x86_64")]
+            self.check_for_fatal_error(&vmm_outcome);
+            if !self.jobs.has_pending() {
+                return vmm_outcome;
+            }
+            self.jobs.complete(&vmm_outcome);
+        }
+    }
+
+    // Records the outcomes of the finished jobs, without waiting for the others.
+    fn collect_job_outcomes(&mut self) {
+        while self.jobs.has_pending() {
+            match self.vmm_response_receiver.try_recv() {
+                Ok(vmm_outcome) => {
+                    #[cfg(target_arch = "x86_64")]
+                    self.check_for_fatal_error(&vmm_outcome);
+                    self.jobs.complete(&vmm_outcome);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => panic!("VMM disconnected"),
+            }
+        }
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn check_for_fatal_error(&mut self, response: &std::result::Result<VmmData, VmmActionError>) {
         // Errors considered as fatal are added here
@@ -260,10 +481,7 @@ impl ApiServer {
             Err(e) => {
                 // This is an api server metrics as the shared info is obtained internally.
                 METRICS.get_api_requests.instance_info_fails.inc();
-                ApiServer::json_response(
-                    StatusCode::BadRequest,
-                    ApiServer::json_fault_message(e.to_string()),
-                )
+                Fault::new(ErrorCode::InternalError, e.to_string()).into()
             }
         }
     }
@@ -278,6 +496,19 @@ impl ApiServer {
         )
     }
 
+    fn get_mmds_status(&self) -> Response {
+        let status = self
+            .mmds_info
+            .lock()
+            .expect("Failed to acquire lock on MMDS info")
+            .status();
+        // Serializing plain integers cannot fail.
+        ApiServer::json_response(
+            StatusCode::OK,
+            serde_json::to_string(&status).expect("Cannot serialize the MMDS status"),
+        )
+    }
+
     fn patch_mmds(&self, value: serde_json::Value) -> Response {
         let mmds_response = self
             .mmds_info
@@ -290,10 +521,38 @@ impl ApiServer {
             Err(e) => match e {
                 data_store::Error::NotFound => unreachable!(),
                 data_store::Error::UnsupportedValueType => unreachable!(),
-                data_store::Error::NotInitialized => ApiServer::json_response(
-                    StatusCode::BadRequest,
-                    ApiServer::json_fault_message(e.to_string()),
-                ),
+                data_store::Error::InvalidDynamicPath(_) => unreachable!(),
+                data_store::Error::InvalidPatch(_) => unreachable!(),
+                data_store::Error::DataStoreLimitExceeded(_) => {
+                    Fault::new(ErrorCode::InvalidValue, e.to_string()).into()
+                }
+                data_store::Error::NotInitialized => {
+                    Fault::new(ErrorCode::InvalidState, e.to_string()).into()
+                }
+            },
+        }
+    }
+
+    fn json_patch_mmds(&self, operations: Vec<PatchOperation>) -> Response {
+        let mmds_response = self
+            .mmds_info
+            .lock()
+            .expect("Failed to acquire lock on MMDS info")
+            .apply_json_patch(operations);
+
+        match mmds_response {
+            Ok(_) => Response::new(Version::Http11, StatusCode::NoContent),
+            Err(e) => match e {
+                data_store::Error::NotFound => unreachable!(),
+                data_store::Error::UnsupportedValueType => unreachable!(),
+                data_store::Error::InvalidDynamicPath(_) => unreachable!(),
+                data_store::Error::DataStoreLimitExceeded(_)
+                | data_store::Error::InvalidPatch(_) => {
+                    Fault::new(ErrorCode::InvalidValue, e.to_string()).into()
+                }
+                data_store::Error::NotInitialized => {
+                    Fault::new(ErrorCode::InvalidState, e.to_string()).into()
+                }
             },
         }
     }
@@ -306,10 +565,53 @@ impl ApiServer {
             .put_data(value);
         match mmds_response {
             Ok(_) => Response::new(Version::Http11, StatusCode::NoContent),
-            Err(e) => ApiServer::json_response(
-                StatusCode::BadRequest,
-                ApiServer::json_fault_message(e.to_string()),
+            Err(e) => Fault::new(ErrorCode::InvalidValue, e.to_string()).into(),
+        }
+    }
+
+    /// Runs a command of the guest agent, blocking the API thread until the agent answers.
+    #[cfg(feature = "vsock")]
+    fn agent_response(command: AgentCommand) -> Response {
+        match command.run(&AGENT_CHANNEL) {
+            Ok(AgentOutput::Empty) => Response::new(Version::Http11, StatusCode::NoContent),
+            // Serializing strings and integers cannot fail.
+            Ok(AgentOutput::Exec(output)) => ApiServer::json_response(
+                StatusCode::OK,
+                serde_json::to_string(&output).expect("Cannot serialize the exec output"),
             ),
+            Err(e) => {
+                error!("{}", e);
+                let error_code = match e {
+                    AgentError::HostFile(_, _) => ErrorCode::InvalidValue,
+                    AgentError::NotConnected => ErrorCode::InvalidState,
+                    _ => ErrorCode::OperationFailed,
+                };
+                Fault::new(error_code, e.to_string()).into()
+            }
+        }
+    }
+
+    /// The metrics rendered in the Prometheus text exposition format.
+    pub(crate) fn metrics_response() -> Response {
+        match METRICS.render_prometheus() {
+            Ok(body) => {
+                let mut response = Response::new(Version::Http11, StatusCode::OK);
+                response.set_content_type(MediaType::PlainText);
+                response.set_body(Body::new(body));
+                response
+            }
+            Err(e) => {
+                METRICS.get_api_requests.metrics_fails.inc();
+                Fault::new(ErrorCode::InternalError, e.to_string()).into()
+            }
+        }
+    }
+
+    // The durations of the phases of the boot and of the snapshots, as in the metrics.
+    pub(crate) fn timings_response() -> Response {
+        match serde_json::to_string(&METRICS.timings_us) {
+            Ok(body) => ApiServer::json_response(StatusCode::OK, body),
+            Err(e) => Fault::new(ErrorCode::InternalError, e.to_string()).into(),
         }
     }
 
@@ -319,12 +621,36 @@ impl ApiServer {
         response.set_body(Body::new(body.into()));
         response
     }
+}
 
-    fn json_fault_message<T: AsRef<str> + serde::Serialize>(msg: T) -> String {
-        json!({ "fault_message": msg }).to_string()
+// The ID of the request, given by the client in the `X-Request-Id` header, or generated when the
+// header is missing or invalid.
+fn client_request_id(request: &Request) -> String {
+    match request.headers.custom_entry("x-request-id") {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            id.to_string()
+        }
+        _ => next_request_id(),
     }
 }
 
+// Whether the client asked for the request to be served by a job, with the
+// `Prefer: respond-async` header of RFC 7240.
+fn prefers_async(request: &Request) -> bool {
+    request
+        .headers
+        .custom_entry("prefer")
+        .map_or(false, |value| {
+            value
+                .split(',')
+                .any(|preference| preference.trim().eq_ignore_ascii_case("respond-async"))
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use std::convert::TryInto;
@@ -401,7 +727,8 @@ mod tests {
                 StartMicrovmError::MissingKernelConfig,
             ))))
             .unwrap();
-        let response = api_server.serve_vmm_action_request(Box::new(VmmAction::StartMicroVm), 0);
+        let response =
+            api_server.serve_vmm_action_request(Box::new(VmmAction::StartMicroVm(false)), 0);
         assert_eq!(response.status(), StatusCode::BadRequest);
 
         let start_time_us = utils::time::get_time_us(ClockType::Monotonic);
@@ -423,6 +750,9 @@ mod tests {
                     snapshot_type: SnapshotType::Diff,
                     snapshot_path: PathBuf::new(),
                     mem_file_path: PathBuf::new(),
+                    single_file: false,
+                    dedup_pages: false,
+                    compact_diff: false,
                     version: None,
                 })),
                 start_time_us,
@@ -437,6 +767,9 @@ mod tests {
                     snapshot_type: SnapshotType::Diff,
                     snapshot_path: PathBuf::new(),
                     mem_file_path: PathBuf::new(),
+                    single_file: false,
+                    dedup_pages: false,
+                    compact_diff: false,
                     version: None,
                 })),
                 start_time_us,
@@ -447,6 +780,183 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_jobs() {
+        let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
+            started: false,
+            id: "test_jobs".to_string(),
+            vmm_version: "version 0.1.0".to_string(),
+            app_name: "app name".to_string(),
+        }));
+
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+        let mmds_info = MMDS.clone();
+
+        let mut api_server = ApiServer::new(
+            mmds_info,
+            vmm_shared_info,
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+        )
+        .unwrap();
+
+        let body = r#"{"action_type": "FlushMetrics"}"#;
+        let async_request = Request::try_from(
+            format!(
+                "PUT /actions HTTP/1.1\r\nContent-Length: {}\r\nPrefer: respond-async\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let sync_request = Request::try_from(
+            format!(
+                "PUT /actions HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let get_job = |id: u64| {
+            Request::try_from(format!("GET /jobs/{} HTTP/1.1\r\n\r\n", id).as_bytes()).unwrap()
+        };
+        let job_state = |response: Response| -> serde_json::Value {
+            serde_json::from_slice(response.body().unwrap().raw()).unwrap()
+        };
+
+        // The job is started without waiting for the VMM, and carries the ID of the request.
+        set_request_id(Some("req-1".to_string()));
+        let response = api_server.handle_request(&async_request, 0);
+        assert_eq!(response.status(), StatusCode::Accepted);
+        assert_eq!(job_state(response)["job_id"], 1);
+        let api_request = from_api.try_recv().unwrap();
+        assert!(*api_request.action == VmmAction::FlushMetrics);
+        assert_eq!(api_request.request_id, Some("req-1".to_string()));
+        set_request_id(None);
+
+        let response = api_server.handle_request(&get_job(1), 0);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(job_state(response)["state"], "InProgress");
+
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        let response = api_server.handle_request(&get_job(1), 0);
+        assert_eq!(job_state(response)["state"], "Succeeded");
+
+        // A synchronous request waits for the outcomes of the jobs started before it.
+        let response = api_server.handle_request(&async_request, 0);
+        assert_eq!(job_state(response)["job_id"], 2);
+        to_api
+            .send(Box::new(Err(VmmActionError::OperationNotSupportedPreBoot)))
+            .unwrap();
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        let response = api_server.handle_request(&sync_request, 0);
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let state = job_state(api_server.handle_request(&get_job(2), 0));
+        assert_eq!(state["state"], "Failed");
+        assert_eq!(
+            state["error"],
+            VmmActionError::OperationNotSupportedPreBoot.to_string()
+        );
+
+        let response = api_server.handle_request(&get_job(3), 0);
+        assert_eq!(response.status(), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_limits() {
+        let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
+            started: false,
+            id: "test_limits".to_string(),
+            vmm_version: "version 0.1.0".to_string(),
+            app_name: "app name".to_string(),
+        }));
+
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+        let mmds_info = MMDS.clone();
+
+        let mut api_server = ApiServer::new(
+            mmds_info,
+            vmm_shared_info,
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+        )
+        .unwrap();
+        api_server.set_limits(ApiLimits {
+            max_payload_size: 40,
+            max_mmds_payload_size: Some(10),
+            max_in_flight_requests: 1,
+            max_requests_per_sec: None,
+        });
+        let request = |method: &str, path: &str, headers: &str, body: &str| {
+            Request::try_from(
+                format!(
+                    "{} {} HTTP/1.1\r\nContent-Length: {}\r\n{}\r\n{}",
+                    method,
+                    path,
+                    body.len(),
+                    headers,
+                    body
+                )
+                .as_bytes(),
+            )
+            .unwrap()
+        };
+        let error_code = |response: Response| -> serde_json::Value {
+            serde_json::from_slice::<serde_json::Value>(response.body().unwrap().raw()).unwrap()
+                ["error_code"]
+                .clone()
+        };
+
+        // The body size is checked against the limit of the request path.
+        let fails = METRICS.api_server.payload_too_large.count();
+        let response =
+            api_server.handle_request(&request("PUT", "/mmds", "", r#"{"key": "value"}"#), 0);
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+        assert_eq!(error_code(response), "PayloadTooLarge");
+        let response =
+            api_server.handle_request(&request("PUT", "/actions", "", &format!("{:50}", "{}")), 0);
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+        assert_eq!(METRICS.api_server.payload_too_large.count(), fails + 2);
+
+        // Beyond the in-flight limit, the jobs are rejected until some finish.
+        let body = r#"{"action_type": "FlushMetrics"}"#;
+        let async_request = request("PUT", "/actions", "Prefer: respond-async\r\n", body);
+        let response = api_server.handle_request(&async_request, 0);
+        assert_eq!(response.status(), StatusCode::Accepted);
+        assert!(*from_api.try_recv().unwrap().action == VmmAction::FlushMetrics);
+        let response = api_server.handle_request(&async_request, 0);
+        assert_eq!(response.status(), StatusCode::TooManyRequests);
+        assert_eq!(error_code(response), "TooManyRequests");
+        assert!(from_api.try_recv().is_err());
+
+        to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
+        let response = api_server.handle_request(&async_request, 0);
+        assert_eq!(response.status(), StatusCode::Accepted);
+    }
+
+    #[test]
+    fn test_client_request_id() {
+        let request_with_id = |id: &str| {
+            Request::try_from(format!("GET / HTTP/1.1\r\nX-Request-Id: {}\r\n\r\n", id).as_bytes())
+                .unwrap()
+        };
+        assert_eq!(client_request_id(&request_with_id("boot-42")), "boot-42");
+
+        // The missing and invalid IDs are replaced.
+        let request = Request::try_from(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(client_request_id(&request).starts_with("fc-"));
+        assert!(client_request_id(&request_with_id(&"a".repeat(65))).starts_with("fc-"));
+        assert!(client_request_id(&request_with_id("a b")).starts_with("fc-"));
+    }
+
     #[test]
     fn test_get_instance_info() {
         let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
@@ -474,6 +984,27 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[test]
+    fn test_metrics_response() {
+        METRICS.vmm.panic_count.inc();
+        let response = ApiServer::metrics_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.content_type(), MediaType::PlainText);
+        let body = String::from_utf8(response.body().unwrap().raw().to_vec()).unwrap();
+        assert!(body.contains("# TYPE firecracker_vmm_panic_count counter\n"));
+    }
+
+    #[test]
+    fn test_timings_response() {
+        METRICS.timings_us.load_kernel.store(1520);
+        let response = ApiServer::timings_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value =
+            serde_json::from_slice(response.body().unwrap().raw()).unwrap();
+        assert_eq!(body["load_kernel"], 1520);
+        assert!(body["first_vcpu_run"].is_u64());
+    }
+
     #[test]
     fn test_get_mmds() {
         let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
@@ -501,6 +1032,41 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[test]
+    fn test_get_mmds_status() {
+        let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
+            started: false,
+            id: "test_get_mmds_status".to_string(),
+            vmm_version: "version 0.1.0".to_string(),
+            app_name: "app name".to_string(),
+        }));
+
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let mmds_info = Arc::new(Mutex::new(Mmds::default()));
+
+        let api_server = ApiServer::new(
+            mmds_info,
+            vmm_shared_info,
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+        )
+        .unwrap();
+
+        let response = api_server.get_mmds_status();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Data that does not fit in the data store is rejected.
+        let response = api_server.put_mmds(serde_json::json!({ "key": "a".repeat(51200) }));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        let response = api_server.put_mmds(serde_json::json!({"key": "value"}));
+        assert_eq!(response.status(), StatusCode::NoContent);
+        let response = api_server.patch_mmds(serde_json::json!({ "key": "a".repeat(51200) }));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+    }
+
     #[test]
     fn test_put_mmds() {
         let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
@@ -528,6 +1094,17 @@ mod tests {
         assert_eq!(response.status(), StatusCode::NoContent);
     }
 
+    #[test]
+    #[cfg(feature = "vsock")]
+    fn test_agent_response() {
+        // No guest agent is connected.
+        let response = ApiServer::agent_response(AgentCommand::Ping);
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert!(std::str::from_utf8(response.body().unwrap().raw())
+            .unwrap()
+            .contains("InvalidState"));
+    }
+
     #[test]
     fn test_patch_mmds() {
         let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
@@ -564,6 +1141,56 @@ mod tests {
         assert_eq!(response.status(), StatusCode::NoContent);
     }
 
+    #[test]
+    fn test_json_patch_mmds() {
+        let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
+            started: false,
+            id: "test_json_patch_mmds".to_string(),
+            vmm_version: "version 0.1.0".to_string(),
+            app_name: "app name".to_string(),
+        }));
+
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let mmds_info = Arc::new(Mutex::new(Mmds::default()));
+
+        let api_server = ApiServer::new(
+            mmds_info,
+            vmm_shared_info,
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+        )
+        .unwrap();
+        let operations = |ops: &str| serde_json::from_str::<Vec<PatchOperation>>(ops).unwrap();
+
+        // MMDS data store is not yet initialized.
+        let response = api_server.json_patch_mmds(operations("[]"));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+
+        let response = api_server.put_mmds(serde_json::json!({"key": "value"}));
+        assert_eq!(response.status(), StatusCode::NoContent);
+
+        let response = api_server.json_patch_mmds(operations(
+            r#"[{"op": "replace", "path": "/key", "value": "other"}]"#,
+        ));
+        assert_eq!(response.status(), StatusCode::NoContent);
+
+        // The patch is rejected as a whole when one of its operations fails.
+        let response = api_server.json_patch_mmds(operations(
+            r#"[
+                {"op": "add", "path": "/new", "value": "value"},
+                {"op": "test", "path": "/key", "value": "value"}
+            ]"#,
+        ));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert_eq!(
+            api_server.mmds_info.lock().unwrap().get_data_str(),
+            r#"{"key":"other"}"#
+        );
+    }
+
     #[test]
     fn test_handle_request() {
         let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
@@ -617,6 +1244,13 @@ mod tests {
         let response = api_server.handle_request(&req, 0);
         assert_eq!(response.status(), StatusCode::OK);
 
+        // Test a Get Metrics request.
+        sender.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        let response = api_server.handle_request(&req, 0);
+        assert_eq!(response.status(), StatusCode::OK);
+
         // Test a Get Mmds request.
         sender.write_all(b"GET /mmds HTTP/1.1\r\n\r\n").unwrap();
         assert!(connection.try_read().is_ok());
@@ -664,6 +1298,57 @@ mod tests {
         assert_eq!(response.status(), StatusCode::BadRequest);
     }
 
+    #[test]
+    fn test_authorize() {
+        let vmm_shared_info = Arc::new(RwLock::new(InstanceInfo {
+            started: false,
+            id: "test_authorize".to_string(),
+            vmm_version: "version 0.1.0".to_string(),
+            app_name: "app name".to_string(),
+        }));
+
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (_to_api, vmm_response_receiver) = channel();
+        let mmds_info = MMDS.clone();
+
+        let mut api_server = ApiServer::new(
+            mmds_info,
+            vmm_shared_info,
+            api_request_sender,
+            vmm_response_receiver,
+            to_vmm_fd,
+        )
+        .unwrap();
+
+        let get = Request::try_from(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let put = Request::try_from(
+            b"PUT /actions HTTP/1.1\r\n\
+              Authorization: Bearer viewer\r\n\r\n",
+        )
+        .unwrap();
+        // Without a policy, all the requests are served.
+        assert!(api_server.authorize(&get, None).is_ok());
+        assert!(api_server.authorize(&put, None).is_ok());
+
+        api_server.set_auth_policy(
+            ApiAuthPolicy::from_json(
+                r#"{ "tokens": [{ "token": "viewer", "access": "ReadOnly" }] }"#,
+            )
+            .unwrap(),
+        );
+        let fails = METRICS.api_server.auth_fails.count();
+        assert_eq!(
+            api_server.authorize(&get, None).unwrap_err().status(),
+            StatusCode::Unauthorized
+        );
+        assert_eq!(
+            api_server.authorize(&put, None).unwrap_err().status(),
+            StatusCode::Forbidden
+        );
+        assert_eq!(METRICS.api_server.auth_fails.count(), fails + 2);
+    }
+
     #[test]
     fn test_bind_and_run() {
         let mut tmp_socket = TempFile::new().unwrap();
@@ -696,6 +1381,7 @@ mod tests {
                 .expect("Cannot create API server")
                 .bind_and_run(
                     PathBuf::from(api_thread_path_to_socket),
+                    &[],
                     Some(1),
                     Some(1),
                     SeccompFilter::empty().try_into().unwrap(),
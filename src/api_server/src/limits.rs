@@ -0,0 +1,144 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+
+use rate_limiter::{BucketReduction, TokenBucket};
+
+/// The default maximum size of a request body, in bytes.
+pub const DEFAULT_MAX_PAYLOAD_SIZE: usize = 51200;
+/// The default maximum number of jobs in progress.
+pub const DEFAULT_MAX_IN_FLIGHT_REQUESTS: usize = 16;
+
+/// The limits protecting the VMM thread from a misbehaving API client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ApiLimits {
+    /// The maximum size of a request body, in bytes.
+    pub max_payload_size: usize,
+    /// The maximum size of the body of a MMDS request, in bytes. Defaults to
+    /// `max_payload_size`.
+    pub max_mmds_payload_size: Option<usize>,
+    /// The maximum number of jobs in progress. Beyond it, the asynchronous requests are
+    /// rejected until some jobs finish.
+    pub max_in_flight_requests: usize,
+    /// The maximum number of requests per second served on each socket, if any.
+    pub max_requests_per_sec: Option<u64>,
+}
+
+impl Default for ApiLimits {
+    fn default() -> Self {
+        ApiLimits {
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+            max_mmds_payload_size: None,
+            max_in_flight_requests: DEFAULT_MAX_IN_FLIGHT_REQUESTS,
+            max_requests_per_sec: None,
+        }
+    }
+}
+
+impl ApiLimits {
+    /// The maximum size of the body of a request to the given path.
+    pub(crate) fn payload_limit(&self, path: &str) -> usize {
+        if path == "/mmds" || path.starts_with("/mmds/") {
+            self.max_mmds_payload_size.unwrap_or(self.max_payload_size)
+        } else {
+            self.max_payload_size
+        }
+    }
+
+    /// The maximum size of a request body, whatever its path.
+    pub(crate) fn max_payload_limit(&self) -> usize {
+        std::cmp::max(
+            self.max_payload_size,
+            self.max_mmds_payload_size.unwrap_or(0),
+        )
+    }
+}
+
+/// The request rate of each socket the API is served on.
+pub(crate) struct RequestRates {
+    // The bucket each socket starts with, if the request rate is limited.
+    initial_bucket: Option<TokenBucket>,
+    // The buckets of the sockets, keyed by their file descriptor.
+    buckets: HashMap<RawFd, TokenBucket>,
+}
+
+impl RequestRates {
+    /// Limits each socket to `requests_per_sec` requests per second, if any.
+    pub(crate) fn new(requests_per_sec: Option<u64>) -> Self {
+        RequestRates {
+            // The bucket refills entirely every second.
+            initial_bucket: requests_per_sec.and_then(|rate| TokenBucket::new(rate, 0, 1000)),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Accounts for a request received on the socket `listener_fd`, and returns whether it
+    /// exceeds the request rate of the socket.
+    pub(crate) fn is_throttled(&mut self, listener_fd: Option<RawFd>) -> bool {
+        let (initial_bucket, listener_fd) = match (self.initial_bucket.as_ref(), listener_fd) {
+            (Some(initial_bucket), Some(listener_fd)) => (initial_bucket, listener_fd),
+            _ => return false,
+        };
+        self.buckets
+            .entry(listener_fd)
+            .or_insert_with(|| initial_bucket.clone())
+            .reduce(1)
+            == BucketReduction::Failure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_limit() {
+        let limits = ApiLimits::default();
+        assert_eq!(limits.payload_limit("/mmds"), DEFAULT_MAX_PAYLOAD_SIZE);
+        assert_eq!(
+            limits.payload_limit("/drives/root"),
+            DEFAULT_MAX_PAYLOAD_SIZE
+        );
+        assert_eq!(limits.max_payload_limit(), DEFAULT_MAX_PAYLOAD_SIZE);
+
+        let limits = ApiLimits {
+            max_mmds_payload_size: Some(1 << 20),
+            ..Default::default()
+        };
+        assert_eq!(limits.payload_limit("/mmds"), 1 << 20);
+        assert_eq!(limits.payload_limit("/mmds/config"), 1 << 20);
+        assert_eq!(limits.payload_limit("/mmdsx"), DEFAULT_MAX_PAYLOAD_SIZE);
+        assert_eq!(
+            limits.payload_limit("/drives/root"),
+            DEFAULT_MAX_PAYLOAD_SIZE
+        );
+        assert_eq!(limits.max_payload_limit(), 1 << 20);
+
+        let limits = ApiLimits {
+            max_mmds_payload_size: Some(1024),
+            ..Default::default()
+        };
+        assert_eq!(limits.payload_limit("/mmds"), 1024);
+        assert_eq!(limits.max_payload_limit(), DEFAULT_MAX_PAYLOAD_SIZE);
+    }
+
+    #[test]
+    fn test_request_rates() {
+        // Without a limit, no request is throttled.
+        let mut request_rates = RequestRates::new(None);
+        for _ in 0..10 {
+            assert!(!request_rates.is_throttled(Some(3)));
+        }
+
+        // Each socket is allowed two requests per second.
+        let mut request_rates = RequestRates::new(Some(2));
+        assert!(!request_rates.is_throttled(Some(3)));
+        assert!(!request_rates.is_throttled(Some(3)));
+        assert!(request_rates.is_throttled(Some(3)));
+        assert!(!request_rates.is_throttled(Some(4)));
+        // The requests not received on a socket are not limited.
+        assert!(!request_rates.is_throttled(None));
+    }
+}
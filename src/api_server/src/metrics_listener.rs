@@ -0,0 +1,126 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use logger::error;
+use micro_http::{Method, Request, Response, StatusCode, Version};
+use seccomp::{BpfProgram, SeccompFilter};
+
+use crate::ApiServer;
+
+// The largest request accepted, which is more than enough for a scraper's GET.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Serves the metrics in the Prometheus format over TCP, to the scrapers which cannot reach the
+/// API socket. Only `GET /metrics` requests are answered, each on its own connection.
+pub struct MetricsListener {
+    listener: TcpListener,
+}
+
+impl MetricsListener {
+    /// Binds the listener to `addr`.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(MetricsListener {
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+
+    /// Provides the address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Loads `seccomp_filter` on the calling thread, then serves the scrapers until the process
+    /// exits.
+    pub fn run(self, seccomp_filter: BpfProgram) {
+        // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping filters
+        // altogether is the desired behaviour.
+        if let Err(e) = SeccompFilter::apply(seccomp_filter) {
+            panic!(
+                "Failed to set the requested seccomp filters on the metrics thread: Error: {:?}",
+                e
+            );
+        }
+
+        loop {
+            match self.listener.accept() {
+                Ok((mut stream, _)) => {
+                    if let Err(e) = Self::serve(&mut stream) {
+                        error!("Cannot serve the metrics: {}", e);
+                    }
+                }
+                Err(e) => error!("Cannot accept a metrics connection: {}", e),
+            }
+        }
+    }
+
+    fn serve(stream: &mut TcpStream) -> io::Result<()> {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 512];
+        // Read up to the end of the headers, since a GET has no body.
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let count = stream.read(&mut buf)?;
+            if count == 0 || request.len() + count > MAX_REQUEST_SIZE {
+                break;
+            }
+            request.extend_from_slice(&buf[..count]);
+        }
+
+        let response = match Request::try_from(&request) {
+            Ok(request)
+                if request.method() == Method::Get
+                    && request.uri().get_abs_path() == "/metrics" =>
+            {
+                ApiServer::metrics_response()
+            }
+            Ok(_) => Response::new(Version::Http11, StatusCode::NotFound),
+            Err(_) => Response::new(Version::Http11, StatusCode::BadRequest),
+        };
+        response.write_all(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::net::Shutdown;
+    use std::thread;
+
+    fn request(addr: SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_metrics_listener() {
+        let listener = MetricsListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for _ in 0..3 {
+                let (mut stream, _) = listener.listener.accept().unwrap();
+                MetricsListener::serve(&mut stream).unwrap();
+            }
+        });
+
+        let response = request(addr, b"GET /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Type: text/plain"));
+        assert!(response.contains("# TYPE firecracker_"));
+
+        let response = request(addr, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        let response = request(addr, b"not http");
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        server.join().unwrap();
+    }
+}
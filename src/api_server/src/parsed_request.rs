@@ -1,34 +1,83 @@
 // Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 
 use super::VmmData;
+use crate::fault::{ErrorCode, Fault};
 use crate::request::actions::parse_put_actions;
+#[cfg(feature = "vsock")]
+use crate::request::agent::parse_put_agent;
+#[cfg(feature = "balloon")]
 use crate::request::balloon::{parse_get_balloon, parse_patch_balloon, parse_put_balloon};
 use crate::request::boot_source::parse_put_boot_source;
-use crate::request::drive::{parse_patch_drive, parse_put_drive};
+#[cfg(feature = "virtio-console")]
+use crate::request::console::parse_put_console;
+use crate::request::cpu_quota::{parse_patch_cpu_quota, parse_put_cpu_quota};
+#[cfg(feature = "block")]
+use crate::request::drive::{parse_get_drive, parse_patch_drive, parse_put_drive};
+#[cfg(feature = "virtio-rng")]
+use crate::request::entropy::parse_put_entropy;
+use crate::request::events::parse_get_events;
 use crate::request::instance_info::parse_get_instance_info;
+use crate::request::jobs::parse_get_job;
 use crate::request::logger::parse_put_logger;
 use crate::request::machine_configuration::{
     parse_get_machine_config, parse_patch_machine_config, parse_put_machine_config,
 };
-use crate::request::metrics::parse_put_metrics;
+use crate::request::machine_stats::parse_get_machine_stats;
+#[cfg(feature = "virtio-mem")]
+use crate::request::memory_hotplug::{
+    parse_get_memory_hotplug, parse_patch_memory_hotplug, parse_put_memory_hotplug,
+};
+use crate::request::metrics::{parse_get_metrics, parse_put_metrics};
 use crate::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
-use crate::request::net::{parse_patch_net, parse_put_net};
-use crate::request::snapshot::parse_patch_vm_state;
+#[cfg(feature = "net")]
+use crate::request::net::{parse_get_net, parse_patch_net, parse_put_net};
+#[cfg(feature = "null-devices")]
+use crate::request::null_device::parse_put_null_device;
+#[cfg(feature = "virtio-pmem")]
+use crate::request::pmem::parse_put_pmem;
+use crate::request::rate_limiter_group::parse_put_rate_limiter_group;
+use crate::request::serial::parse_put_serial_port;
+#[cfg(feature = "virtio-fs")]
+use crate::request::shared_fs::parse_put_shared_fs;
+#[cfg(target_arch = "x86_64")]
+use crate::request::shutdown_behavior::parse_put_shutdown_behavior;
 #[cfg(target_arch = "x86_64")]
 use crate::request::snapshot::parse_put_snapshot;
-use crate::request::vsock::parse_put_vsock;
-use crate::ApiServer;
+use crate::request::snapshot::{
+    parse_get_vm_config, parse_patch_vm_state, parse_post_vm, parse_put_vm_config,
+};
+use crate::request::timings::parse_get_timings;
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+use crate::request::tpm::parse_put_tpm;
+use crate::request::vcpus::parse_get_vcpus;
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use crate::request::vfio::parse_put_vfio;
+#[cfg(feature = "vsock")]
+use crate::request::vsock::{parse_get_vsock, parse_patch_vsock, parse_put_vsock};
+#[cfg(target_arch = "x86_64")]
+use crate::request::watchdog::parse_put_watchdog;
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
+use mmds::patch::PatchOperation;
 
 use logger::{error, info};
+#[cfg(feature = "vsock")]
+use vmm::agent::AgentCommand;
 use vmm::rpc_interface::{VmmAction, VmmActionError};
 
 pub(crate) enum ParsedRequest {
+    #[cfg(feature = "vsock")]
+    Agent(AgentCommand),
     GetInstanceInfo,
+    GetJob(u64),
+    GetMetrics,
     GetMMDS,
+    GetTimings,
+    GetMMDSStatus,
+    JsonPatchMMDS(Vec<PatchOperation>),
     PatchMMDS(Value),
     PutMMDS(Value),
     Sync(Box<VmmAction>),
@@ -58,34 +107,103 @@ impl ParsedRequest {
 
         match (request.method(), path, request.body.as_ref()) {
             (Method::Get, "", None) => parse_get_instance_info(),
+            #[cfg(feature = "balloon")]
             (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.get(1)),
+            #[cfg(feature = "block")]
+            (Method::Get, "drives", None) => {
+                parse_get_drive(path_tokens.get(1), path_tokens.get(2))
+            }
+            (Method::Get, "events", None) => parse_get_events(),
+            (Method::Get, "jobs", None) => parse_get_job(path_tokens.get(1)),
+            (Method::Get, "machine", None) => parse_get_machine_stats(path_tokens.get(1)),
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
-            (Method::Get, "mmds", None) => parse_get_mmds(),
+            #[cfg(feature = "virtio-mem")]
+            (Method::Get, "memory-hotplug", None) => parse_get_memory_hotplug(),
+            (Method::Get, "metrics", None) => parse_get_metrics(),
+            (Method::Get, "mmds", None) => parse_get_mmds(path_tokens.get(1)),
+            #[cfg(feature = "net")]
+            (Method::Get, "network-interfaces", None) => {
+                parse_get_net(path_tokens.get(1), path_tokens.get(2))
+            }
+            (Method::Get, "timings", None) => parse_get_timings(),
+            (Method::Get, "vcpus", None) => parse_get_vcpus(path_tokens.get(1)),
+            (Method::Get, "vm", None) => parse_get_vm_config(path_tokens.get(1)),
+            #[cfg(feature = "vsock")]
+            (Method::Get, "vsock", None) => parse_get_vsock(path_tokens.get(1)),
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
-            (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
+            // Only some of the agent commands take a body.
+            #[cfg(feature = "vsock")]
+            (Method::Put, "agent", body) => parse_put_agent(body, path_tokens.get(1)),
+            #[cfg(feature = "balloon")]
+            (Method::Put, "balloon", Some(body)) => parse_put_balloon(body, path_tokens.get(1)),
             (Method::Put, "boot-source", Some(body)) => parse_put_boot_source(body),
+            #[cfg(feature = "virtio-console")]
+            (Method::Put, "console", Some(body)) => parse_put_console(body),
+            (Method::Put, "cpu-quota", Some(body)) => parse_put_cpu_quota(body),
+            #[cfg(feature = "block")]
             (Method::Put, "drives", Some(body)) => parse_put_drive(body, path_tokens.get(1)),
+            #[cfg(feature = "virtio-rng")]
+            (Method::Put, "entropy", Some(body)) => parse_put_entropy(body),
             (Method::Put, "logger", Some(body)) => parse_put_logger(body),
             (Method::Put, "machine-config", Some(body)) => parse_put_machine_config(body),
+            #[cfg(feature = "virtio-mem")]
+            (Method::Put, "memory-hotplug", Some(body)) => parse_put_memory_hotplug(body),
             (Method::Put, "metrics", Some(body)) => parse_put_metrics(body),
             (Method::Put, "mmds", Some(body)) => parse_put_mmds(body, path_tokens.get(1)),
+            #[cfg(feature = "net")]
             (Method::Put, "network-interfaces", Some(body)) => {
                 parse_put_net(body, path_tokens.get(1))
             }
+            #[cfg(feature = "null-devices")]
+            (Method::Put, "null-devices", Some(body)) => {
+                parse_put_null_device(body, path_tokens.get(1))
+            }
+            #[cfg(feature = "virtio-pmem")]
+            (Method::Put, "pmem", Some(body)) => parse_put_pmem(body, path_tokens.get(1)),
+            (Method::Put, "rate-limiter-groups", Some(body)) => {
+                parse_put_rate_limiter_group(body, path_tokens.get(1))
+            }
+            (Method::Put, "serial-ports", Some(body)) => {
+                parse_put_serial_port(body, path_tokens.get(1))
+            }
+            #[cfg(feature = "virtio-fs")]
+            (Method::Put, "shared-fs", Some(body)) => parse_put_shared_fs(body, path_tokens.get(1)),
+            #[cfg(target_arch = "x86_64")]
+            (Method::Put, "shutdown-behavior", Some(body)) => parse_put_shutdown_behavior(body),
             #[cfg(target_arch = "x86_64")]
             (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.get(1)),
+            #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+            (Method::Put, "tpm", Some(body)) => parse_put_tpm(body),
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            (Method::Put, "vfio", Some(body)) => parse_put_vfio(body, path_tokens.get(1)),
+            (Method::Put, "vm", Some(body)) => parse_put_vm_config(body, path_tokens.get(1)),
+            #[cfg(feature = "vsock")]
             (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
+            #[cfg(target_arch = "x86_64")]
+            (Method::Put, "watchdog", Some(body)) => parse_put_watchdog(body),
             (Method::Put, _, None) => method_to_error(Method::Put),
+            #[cfg(feature = "balloon")]
             (Method::Patch, "balloon", Some(body)) => parse_patch_balloon(body, path_tokens.get(1)),
+            (Method::Patch, "cpu-quota", Some(body)) => parse_patch_cpu_quota(body),
+            #[cfg(feature = "block")]
             (Method::Patch, "drives", Some(body)) => parse_patch_drive(body, path_tokens.get(1)),
             (Method::Patch, "machine-config", Some(body)) => parse_patch_machine_config(body),
-            (Method::Patch, "mmds", Some(body)) => parse_patch_mmds(body),
+            #[cfg(feature = "virtio-mem")]
+            (Method::Patch, "memory-hotplug", Some(body)) => parse_patch_memory_hotplug(body),
+            (Method::Patch, "mmds", Some(body)) => {
+                parse_patch_mmds(body, request.headers.content_type())
+            }
+            #[cfg(feature = "net")]
             (Method::Patch, "network-interfaces", Some(body)) => {
                 parse_patch_net(body, path_tokens.get(1))
             }
             (Method::Patch, "vm", Some(body)) => parse_patch_vm_state(body),
+            #[cfg(feature = "vsock")]
+            (Method::Patch, "vsock", Some(body)) => parse_patch_vsock(body),
             (Method::Patch, _, None) => method_to_error(Method::Patch),
+            (Method::Post, "vm", Some(body)) => parse_post_vm(body, path_tokens.get(1)),
+            (Method::Post, _, None) => method_to_error(Method::Post),
             (method, unknown_uri, _) => {
                 Err(Error::InvalidPathMethod(unknown_uri.to_string(), method))
             }
@@ -101,35 +219,96 @@ impl ParsedRequest {
                     info!("The request was executed successfully. Status code: 204 No Content.");
                     Response::new(Version::Http11, StatusCode::NoContent)
                 }
+                VmmData::GuestEvents(events) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(events).unwrap()));
+                    response
+                }
+                VmmData::FullVmConfig(config) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(config).unwrap()));
+                    response
+                }
                 VmmData::MachineConfiguration(vm_config) => {
                     info!("The request was executed successfully. Status code: 200 OK.");
                     let mut response = Response::new(Version::Http11, StatusCode::OK);
                     response.set_body(Body::new(vm_config.to_string()));
                     response
                 }
+                #[cfg(feature = "balloon")]
                 VmmData::BalloonConfig(balloon_config) => {
                     info!("The request was executed successfully. Status code: 200 OK.");
                     let mut response = Response::new(Version::Http11, StatusCode::OK);
                     response.set_body(Body::new(serde_json::to_string(balloon_config).unwrap()));
                     response
                 }
+                #[cfg(feature = "balloon")]
                 VmmData::BalloonStats(stats) => {
                     info!("The request was executed successfully. Status code: 200 OK.");
                     let mut response = Response::new(Version::Http11, StatusCode::OK);
                     response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
                     response
                 }
+                #[cfg(feature = "virtio-mem")]
+                VmmData::MemoryHotplugStatus(status) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(status).unwrap()));
+                    response
+                }
+                #[cfg(feature = "block")]
+                VmmData::DriveRateLimiter(stats) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
+                    response
+                }
+                VmmData::MachineStats(stats) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
+                    response
+                }
+                #[cfg(feature = "net")]
+                VmmData::NetworkInterfaceRateLimiters(stats) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
+                    response
+                }
+                #[cfg(feature = "net")]
+                VmmData::NetworkInterfaceStats(stats) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
+                    response
+                }
+                VmmData::VcpuStats(stats) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
+                    response
+                }
+                #[cfg(feature = "vsock")]
+                VmmData::VsockStats(stats) => {
+                    info!("The request was executed successfully. Status code: 200 OK.");
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    response.set_body(Body::new(serde_json::to_string(stats).unwrap()));
+                    response
+                }
             },
             Err(vmm_action_error) => {
                 error!(
                     "Received Error. Status code: 400 Bad Request. Message: {}",
                     vmm_action_error
                 );
-                let mut response = Response::new(Version::Http11, StatusCode::BadRequest);
-                response.set_body(Body::new(ApiServer::json_fault_message(
+                Fault::new(
+                    ErrorCode::from(vmm_action_error),
                     vmm_action_error.to_string(),
-                )));
-                response
+                )
+                .into()
             }
         }
     }
@@ -173,26 +352,33 @@ fn describe(method: Method, path: &str, body: Option<&Body>) -> String {
 pub(crate) fn method_to_error(method: Method) -> Result<ParsedRequest, Error> {
     match method {
         Method::Get => Err(Error::Generic(
-            StatusCode::BadRequest,
+            ErrorCode::InvalidRequest,
             "GET request cannot have a body.".to_string(),
         )),
         Method::Put => Err(Error::Generic(
-            StatusCode::BadRequest,
+            ErrorCode::InvalidRequest,
             "Empty PUT request.".to_string(),
         )),
         Method::Patch => Err(Error::Generic(
-            StatusCode::BadRequest,
+            ErrorCode::InvalidRequest,
             "Empty PATCH request.".to_string(),
         )),
+        Method::Post => Err(Error::Generic(
+            ErrorCode::InvalidRequest,
+            "Empty POST request.".to_string(),
+        )),
     }
 }
 
 #[derive(Debug)]
 pub(crate) enum Error {
-    // A generic error, with a given status code and message to be turned into a fault message.
-    Generic(StatusCode, String),
+    // A generic error, with a given error code and message to be turned into a fault message.
+    Generic(ErrorCode, String),
     // The resource ID is empty.
     EmptyID,
+    // An error about a field of the request body, with a given error code, path of the field
+    // and message.
+    Field(ErrorCode, String, String),
     // The resource ID must only contain alphanumeric characters and '_'.
     InvalidID,
     // The HTTP method & request path combination is not valid.
@@ -206,6 +392,7 @@ impl std::fmt::Display for Error {
         match self {
             Error::Generic(_, ref desc) => write!(f, "{}", desc),
             Error::EmptyID => write!(f, "The ID cannot be empty."),
+            Error::Field(_, _, ref desc) => write!(f, "{}", desc),
             Error::InvalidID => write!(
                 f,
                 "API Resource IDs can only contain alphanumeric characters and underscores."
@@ -225,17 +412,24 @@ impl std::fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// The structured description of the error, for the response body.
+    pub(crate) fn fault(&self) -> Fault {
+        let msg = self.to_string();
+        match self {
+            Error::Generic(code, _) => Fault::new(*code, msg),
+            Error::EmptyID | Error::InvalidID => Fault::new(ErrorCode::InvalidId, msg),
+            Error::Field(code, ref field, _) => Fault::new(*code, msg).with_field(field.as_str()),
+            Error::InvalidPathMethod(_, _) => Fault::new(ErrorCode::InvalidRequest, msg),
+            Error::SerdeJson(ref e) => Fault::from_serde_json(e, msg),
+        }
+    }
+}
+
 // It's convenient to turn errors into HTTP responses directly.
 impl Into<Response> for Error {
     fn into(self) -> Response {
-        let msg = ApiServer::json_fault_message(format!("{}", self));
-        match self {
-            Error::Generic(status, _) => ApiServer::json_response(status, msg),
-            Error::EmptyID
-            | Error::InvalidID
-            | Error::InvalidPathMethod(_, _)
-            | Error::SerdeJson(_) => ApiServer::json_response(StatusCode::BadRequest, msg),
-        }
+        self.fault().into()
     }
 }
 
@@ -254,6 +448,28 @@ pub(crate) fn checked_id(id: &str) -> Result<&str, Error> {
     Ok(id)
 }
 
+/// Checks that the ID of a resource from the request path matches the one from the body, held
+/// by the `field` of the body.
+pub(crate) fn check_id_from_body(
+    field: &str,
+    id_from_path: &str,
+    id_from_body: &str,
+) -> Result<(), Error> {
+    if id_from_path != id_from_body {
+        return Err(Error::Field(
+            ErrorCode::InvalidValue,
+            field.to_string(),
+            "The id from the path does not match the id from the body!".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Deserializes the json body of a request into the configuration of a resource.
+pub(crate) fn parse_body<T: DeserializeOwned>(body: &Body) -> Result<T, Error> {
+    serde_json::from_slice::<T>(body.raw()).map_err(Error::SerdeJson)
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -264,9 +480,13 @@ pub(crate) mod tests {
 
     use micro_http::HttpConnection;
     use vmm::builder::StartMicrovmError;
+    use vmm::resources::FullVmConfig;
     use vmm::rpc_interface::VmmActionError;
+    #[cfg(feature = "balloon")]
     use vmm::vmm_config::balloon::BalloonStats;
+    use vmm::vmm_config::guest_panic::GuestEvents;
     use vmm::vmm_config::machine_config::VmConfig;
+    use vmm::vmm_config::machine_stats::MachineStats;
 
     impl PartialEq for ParsedRequest {
         fn eq(&self, other: &ParsedRequest) -> bool {
@@ -274,14 +494,26 @@ pub(crate) mod tests {
                 (&ParsedRequest::Sync(ref sync_req), &ParsedRequest::Sync(ref other_sync_req)) => {
                     sync_req == other_sync_req
                 }
+                #[cfg(feature = "vsock")]
+                (&ParsedRequest::Agent(ref cmd), &ParsedRequest::Agent(ref other_cmd)) => {
+                    cmd == other_cmd
+                }
                 (&ParsedRequest::GetInstanceInfo, &ParsedRequest::GetInstanceInfo) => true,
+                (&ParsedRequest::GetJob(id), &ParsedRequest::GetJob(other_id)) => id == other_id,
+                (&ParsedRequest::GetMetrics, &ParsedRequest::GetMetrics) => true,
                 (&ParsedRequest::GetMMDS, &ParsedRequest::GetMMDS) => true,
+                (&ParsedRequest::GetMMDSStatus, &ParsedRequest::GetMMDSStatus) => true,
+                (&ParsedRequest::GetTimings, &ParsedRequest::GetTimings) => true,
                 (&ParsedRequest::PutMMDS(ref val), &ParsedRequest::PutMMDS(ref other_val)) => {
                     val == other_val
                 }
                 (&ParsedRequest::PatchMMDS(ref val), &ParsedRequest::PatchMMDS(ref other_val)) => {
                     val == other_val
                 }
+                (
+                    &ParsedRequest::JsonPatchMMDS(ref ops),
+                    &ParsedRequest::JsonPatchMMDS(ref other_ops),
+                ) => ops == other_ops,
                 _ => false,
             }
         }
@@ -339,7 +571,7 @@ pub(crate) mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         match ParsedRequest::try_from_request(&req) {
-            Err(Error::Generic(StatusCode::BadRequest, err_msg)) => {
+            Err(Error::Generic(ErrorCode::InvalidRequest, err_msg)) => {
                 if err_msg != "GET request cannot have a body." {
                     panic!("GET request with body.");
                 }
@@ -361,7 +593,7 @@ pub(crate) mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         match ParsedRequest::try_from_request(&req) {
-            Err(Error::Generic(StatusCode::BadRequest, err_msg)) => {
+            Err(Error::Generic(ErrorCode::InvalidRequest, err_msg)) => {
                 if err_msg != "Empty PUT request." {
                     panic!("Empty PUT request.");
                 }
@@ -383,7 +615,7 @@ pub(crate) mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         match ParsedRequest::try_from_request(&req) {
-            Err(Error::Generic(StatusCode::BadRequest, err_msg)) => {
+            Err(Error::Generic(ErrorCode::InvalidRequest, err_msg)) => {
                 if err_msg != "Empty PATCH request." {
                     panic!("Empty PATCH request.");
                 }
@@ -397,9 +629,9 @@ pub(crate) mod tests {
         // Generic error.
         let mut buf = Cursor::new(vec![0]);
         let response: Response =
-            Error::Generic(StatusCode::BadRequest, "message".to_string()).into();
+            Error::Generic(ErrorCode::InvalidValue, "message".to_string()).into();
         assert!(response.write_all(&mut buf).is_ok());
-        let body = ApiServer::json_fault_message("message");
+        let body = Fault::new(ErrorCode::InvalidValue, "message").to_json();
         let expected_response = format!(
             "HTTP/1.1 400 \r\n\
              Server: Firecracker API\r\n\
@@ -416,7 +648,7 @@ pub(crate) mod tests {
         let mut buf = Cursor::new(vec![0]);
         let response: Response = Error::EmptyID.into();
         assert!(response.write_all(&mut buf).is_ok());
-        let body = ApiServer::json_fault_message("The ID cannot be empty.");
+        let body = Fault::new(ErrorCode::InvalidId, "The ID cannot be empty.").to_json();
         let expected_response = format!(
             "HTTP/1.1 400 \r\n\
              Server: Firecracker API\r\n\
@@ -433,9 +665,11 @@ pub(crate) mod tests {
         let mut buf = Cursor::new(vec![0]);
         let response: Response = Error::InvalidID.into();
         assert!(response.write_all(&mut buf).is_ok());
-        let body = ApiServer::json_fault_message(
+        let body = Fault::new(
+            ErrorCode::InvalidId,
             "API Resource IDs can only contain alphanumeric characters and underscores.",
-        );
+        )
+        .to_json();
         let expected_response = format!(
             "HTTP/1.1 400 \r\n\
              Server: Firecracker API\r\n\
@@ -452,11 +686,15 @@ pub(crate) mod tests {
         let mut buf = Cursor::new(vec![0]);
         let response: Response = Error::InvalidPathMethod("path".to_string(), Method::Get).into();
         assert!(response.write_all(&mut buf).is_ok());
-        let body = ApiServer::json_fault_message(format!(
-            "Invalid request method and/or path: {} {}.",
-            std::str::from_utf8(Method::Get.raw()).unwrap(),
-            "path"
-        ));
+        let body = Fault::new(
+            ErrorCode::InvalidRequest,
+            format!(
+                "Invalid request method and/or path: {} {}.",
+                std::str::from_utf8(Method::Get.raw()).unwrap(),
+                "path"
+            ),
+        )
+        .to_json();
         let expected_response = format!(
             "HTTP/1.1 400 \r\n\
              Server: Firecracker API\r\n\
@@ -474,10 +712,12 @@ pub(crate) mod tests {
         let serde_error = serde_json::Value::from_str("").unwrap_err();
         let response: Response = Error::SerdeJson(serde_error).into();
         assert!(response.write_all(&mut buf).is_ok());
-        let body = ApiServer::json_fault_message(
+        let body = Fault::new(
+            ErrorCode::InvalidJson,
             "An error occurred when deserializing the json body of a request: \
              EOF while parsing a value at line 1 column 0.",
-        );
+        )
+        .to_json();
         let expected_response = format!(
             "HTTP/1.1 400 \r\n\
              Server: Firecracker API\r\n\
@@ -489,6 +729,56 @@ pub(crate) mod tests {
             body,
         );
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
+
+        // Field error.
+        let response: Response = Error::Field(
+            ErrorCode::InvalidValue,
+            "drive_id".to_string(),
+            "message".to_string(),
+        )
+        .into();
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert_eq!(
+            response.body().unwrap().raw(),
+            br#"{"fault_message":"message","error_code":"InvalidValue","field":"drive_id"}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_body() {
+        let body = Body::new("{\"drive_id\": \"root\"}");
+        let value: Value = parse_body(&body).unwrap();
+        assert_eq!(value["drive_id"], "root");
+
+        // The missing fields are reported in the fault.
+        #[cfg(feature = "block")]
+        {
+            let fault = parse_body::<vmm::vmm_config::drive::BlockDeviceConfig>(&body)
+                .unwrap_err()
+                .fault();
+            assert_eq!(fault.error_code, ErrorCode::MissingField);
+            assert_eq!(fault.field, Some("path_on_host".to_string()));
+        }
+
+        let fault = parse_body::<Value>(&Body::new("{")).unwrap_err().fault();
+        assert_eq!(fault.error_code, ErrorCode::InvalidJson);
+        assert_eq!(fault.field, None);
+    }
+
+    #[test]
+    fn test_check_id_from_body() {
+        assert!(check_id_from_body("drive_id", "root", "root").is_ok());
+        let fault = check_id_from_body("drive_id", "root", "scratch")
+            .unwrap_err()
+            .fault();
+        assert_eq!(
+            fault,
+            Fault::new(
+                ErrorCode::InvalidValue,
+                "The id from the path does not match the id from the body!"
+            )
+            .with_field("drive_id")
+        );
     }
 
     #[test]
@@ -530,33 +820,84 @@ pub(crate) mod tests {
              Server: Firecracker API\r\n\
              Connection: keep-alive\r\n\
              Content-Type: application/json\r\n\
-             Content-Length: 122\r\n\r\n{}",
+             Content-Length: 179\r\n\r\n{}",
             VmConfig::default().to_string()
         );
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
 
-        // With Balloon Stats Vmm data.
-        let mut stats = BalloonStats::default();
-        stats.swap_in = Some(1);
-        stats.swap_out = Some(1);
+        // With Full VM Config Vmm data.
         let mut buf = Cursor::new(vec![0]);
         let response =
-            ParsedRequest::convert_to_response(&Ok(VmmData::BalloonStats(stats.clone())));
+            ParsedRequest::convert_to_response(&Ok(VmmData::FullVmConfig(FullVmConfig::default())));
         assert!(response.write_all(&mut buf).is_ok());
+        let body = serde_json::to_string(&FullVmConfig::default()).unwrap();
         let expected_response = format!(
             "HTTP/1.1 200 \r\n\
              Server: Firecracker API\r\n\
              Connection: keep-alive\r\n\
              Content-Type: application/json\r\n\
-             Content-Length: 88\r\n\r\n{}",
-            serde_json::to_string(&stats).unwrap(),
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
         );
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
 
+        // With Machine Stats Vmm data.
+        let mut buf = Cursor::new(vec![0]);
+        let response =
+            ParsedRequest::convert_to_response(&Ok(VmmData::MachineStats(MachineStats::default())));
+        assert!(response.write_all(&mut buf).is_ok());
+        let body = serde_json::to_string(&MachineStats::default()).unwrap();
+        let expected_response = format!(
+            "HTTP/1.1 200 \r\n\
+             Server: Firecracker API\r\n\
+             Connection: keep-alive\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        assert_eq!(buf.into_inner(), expected_response.as_bytes());
+
+        // With Guest Events Vmm data.
+        let mut buf = Cursor::new(vec![0]);
+        let response =
+            ParsedRequest::convert_to_response(&Ok(VmmData::GuestEvents(GuestEvents::default())));
+        assert!(response.write_all(&mut buf).is_ok());
+        let expected_response = "HTTP/1.1 200 \r\n\
+                                 Server: Firecracker API\r\n\
+                                 Connection: keep-alive\r\n\
+                                 Content-Type: application/json\r\n\
+                                 Content-Length: 31\r\n\r\n\
+                                 {\"state\":\"Running\",\"events\":[]}"
+            .to_string();
+        assert_eq!(buf.into_inner(), expected_response.as_bytes());
+
+        // With Balloon Stats Vmm data.
+        #[cfg(feature = "balloon")]
+        {
+            let mut stats = BalloonStats::default();
+            stats.swap_in = Some(1);
+            stats.swap_out = Some(1);
+            let mut buf = Cursor::new(vec![0]);
+            let response =
+                ParsedRequest::convert_to_response(&Ok(VmmData::BalloonStats(stats.clone())));
+            assert!(response.write_all(&mut buf).is_ok());
+            let expected_response = format!(
+                "HTTP/1.1 200 \r\n\
+                 Server: Firecracker API\r\n\
+                 Connection: keep-alive\r\n\
+                 Content-Type: application/json\r\n\
+                 Content-Length: 88\r\n\r\n{}",
+                serde_json::to_string(&stats).unwrap(),
+            );
+            assert_eq!(buf.into_inner(), expected_response.as_bytes());
+        }
+
         // Error.
         let error = VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig);
         let mut buf = Cursor::new(vec![0]);
-        let json = ApiServer::json_fault_message(error.to_string());
+        let json = Fault::new(ErrorCode::OperationFailed, error.to_string()).to_json();
         let response = ParsedRequest::convert_to_response(&Err(error));
         response.write_all(&mut buf).unwrap();
 
@@ -582,6 +923,7 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[cfg(feature = "balloon")]
     #[test]
     fn test_try_from_get_balloon() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -592,6 +934,7 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[cfg(feature = "balloon")]
     #[test]
     fn test_try_from_get_balloon_stats() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -604,6 +947,53 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_events() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_job() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender.write_all(b"GET /jobs/1 HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match ParsedRequest::try_from_request(&req) {
+            Ok(ParsedRequest::GetJob(1)) => (),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_get_machine_stats() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /machine/stats HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_vcpu_stats() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /vcpus/stats HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_get_machine_config() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -616,6 +1006,41 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_vm_config() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /vm/config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(
+            ParsedRequest::try_from_request(&req).unwrap()
+                == ParsedRequest::new_sync(VmmAction::GetFullVmConfig)
+        );
+    }
+
+    #[test]
+    fn test_try_from_get_metrics() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).unwrap() == ParsedRequest::GetMetrics);
+    }
+
+    #[test]
+    fn test_try_from_get_timings() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender.write_all(b"GET /timings HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).unwrap() == ParsedRequest::GetTimings);
+    }
+
     #[test]
     fn test_try_from_get_mmds() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -624,6 +1049,76 @@ pub(crate) mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(b"GET /mmds/status HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).unwrap() == ParsedRequest::GetMMDSStatus);
+    }
+
+    #[test]
+    #[cfg(all(feature = "block", feature = "net"))]
+    fn test_try_from_get_rate_limiters() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /drives/rootfs/rate-limiter HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(b"GET /network-interfaces/eth0/rate-limiter HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn test_try_from_get_netif_stats() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /network-interfaces/eth0/stats HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "vsock")]
+    fn test_try_from_get_vsock_stats() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /vsock/stats HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "vsock")]
+    fn test_try_from_put_agent() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        // The agent commands without parameters need no body.
+        sender
+            .write_all(b"PUT /agent/ping HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(
+            ParsedRequest::try_from_request(&req).unwrap()
+                == ParsedRequest::Agent(vmm::agent::AgentCommand::Ping)
+        );
     }
 
     #[test]
@@ -644,6 +1139,7 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[cfg(feature = "balloon")]
     #[test]
     fn test_try_from_put_balloon() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -684,6 +1180,7 @@ pub(crate) mod tests {
     }
 
     #[test]
+    #[cfg(feature = "block")]
     fn test_try_from_put_drives() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
@@ -802,6 +1299,7 @@ pub(crate) mod tests {
     }
 
     #[test]
+    #[cfg(feature = "net")]
     fn test_try_from_put_netif() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
@@ -884,6 +1382,56 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_vm_config() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = r#"{"boot-source": {"kernel_image_path": "vmlinux.bin"}, "drives": []}"#;
+        sender
+            .write_all(
+                format!(
+                    "PUT /vm/config HTTP/1.1\r\n\
+                     Content-Type: application/json\r\n\
+                     Content-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match vmm_action_from_request(ParsedRequest::try_from_request(&req).unwrap()) {
+            VmmAction::SetFullVmConfig(_) => (),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_try_from_post_vm_validate() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = r#"{"boot-source": {"kernel_image_path": "vmlinux.bin"}, "drives": []}"#;
+        sender
+            .write_all(
+                format!(
+                    "POST /vm/validate HTTP/1.1\r\n\
+                     Content-Type: application/json\r\n\
+                     Content-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match vmm_action_from_request(ParsedRequest::try_from_request(&req).unwrap()) {
+            VmmAction::ValidateVmConfig(_) => (),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_try_from_patch_vm() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -903,6 +1451,7 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[cfg(feature = "vsock")]
     #[test]
     fn test_try_from_put_vsock() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -923,6 +1472,280 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[cfg(feature = "virtio-console")]
+    #[test]
+    fn test_try_from_put_console() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /console HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 69\r\n\r\n{ \
+                \"ports\": [{ \"name\": \"console\", \"uds_path\": \"/tmp/console.sock\" }] \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(feature = "virtio-rng")]
+    #[test]
+    fn test_try_from_put_entropy() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /entropy HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 2\r\n\r\n{}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_cpu_quota() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /cpu-quota HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 17\r\n\r\n{\"quota_pct\": 50}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(
+                b"PATCH /cpu-quota HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 17\r\n\r\n{\"quota_pct\": 25}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_try_from_put_shutdown_behavior() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /shutdown-behavior HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 71\r\n\r\n{\"snapshot\": \
+                {\"snapshot_path\": \"/vm.snap\", \"mem_file_path\": \"/vm.mem\"}}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_try_from_put_watchdog() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /watchdog HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 18\r\n\r\n{\"action\": \"Stop\"}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    #[test]
+    fn test_try_from_put_tpm() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /tpm HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 54\r\n\r\n{\"backend\": {\"swtpm\": {\"socket_path\": \"/swtpm.sock\"}}}",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(feature = "virtio-mem")]
+    #[test]
+    fn test_try_from_memory_hotplug() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(b"GET /memory-hotplug HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(
+                b"PUT /memory-hotplug HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 26\r\n\r\n{ \
+                \"total_size_mib\": 1024 \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(
+                b"PATCH /memory-hotplug HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 29\r\n\r\n{ \
+                \"requested_size_mib\": 512 \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(feature = "null-devices")]
+    #[test]
+    fn test_try_from_put_null_device() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /null-devices/snd0 HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 47\r\n\r\n{ \
+                \"device_id\": \"snd0\", \
+                \"device_type\": \"Sound\" \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(feature = "virtio-pmem")]
+    #[test]
+    fn test_try_from_put_pmem() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /pmem/pmem0 HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 57\r\n\r\n{ \
+                \"pmem_id\": \"pmem0\", \
+                \"path_on_host\": \"/tmp/rootfs.img\" \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    #[test]
+    fn test_try_from_put_vfio() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /vfio/nic HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 71\r\n\r\n{ \
+                \"vfio_id\": \"nic\", \
+                \"sysfs_path\": \"/sys/bus/pci/devices/0000:01:00.0\" \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_rate_limiter_group() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /rate-limiter-groups/disks HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 68\r\n\r\n{ \
+                \"group_id\": \"disks\", \
+                \"ops\": { \"size\": 100, \"refill_time\": 1000 } \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_serial_port() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /serial-ports/com2 HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 51\r\n\r\n{ \
+                \"port_id\": \"com2\", \
+                \"file_path\": \"/tmp/com2.log\" \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    #[test]
+    fn test_try_from_put_shared_fs() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PUT /shared-fs/fs0 HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 64\r\n\r\n{ \
+                \"fs_id\": \"fs0\", \
+                \"tag\": \"myfs\", \
+                \"socket_path\": \"/tmp/fs.sock\" \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[cfg(feature = "balloon")]
     #[test]
     fn test_try_from_patch_balloon() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -953,6 +1776,7 @@ pub(crate) mod tests {
     }
 
     #[test]
+    #[cfg(feature = "block")]
     fn test_try_from_patch_drives() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
@@ -1024,9 +1848,24 @@ pub(crate) mod tests {
         assert!(connection.try_read().is_ok());
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        // JSON Patch documents are told apart by their media type.
+        sender
+            .write_all(
+                b"PATCH /mmds HTTP/1.1\r\n\
+                Content-Type: application/json-patch+json\r\n\
+                Content-Length: 2\r\n\r\n[]",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(
+            ParsedRequest::try_from_request(&req).unwrap() == ParsedRequest::JsonPatchMMDS(vec![])
+        );
     }
 
     #[test]
+    #[cfg(feature = "net")]
     fn test_try_from_patch_netif() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
         let mut connection = HttpConnection::new(receiver);
@@ -1043,4 +1882,25 @@ pub(crate) mod tests {
         let req = connection.pop_parsed_request().unwrap();
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
+
+    #[cfg(feature = "vsock")]
+    #[test]
+    fn test_try_from_patch_vsock() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                b"PATCH /vsock HTTP/1.1\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 70\r\n\r\n{ \
+                \"tx_rate_limiter\": { \
+                \"ops\": { \"size\": 100, \"refill_time\": 1000 } \
+                } \
+            }",
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
 }
@@ -2,22 +2,32 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::VmmAction;
-use crate::parsed_request::{Error, ParsedRequest};
+use crate::fault::ErrorCode;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
 use crate::request::Body;
-#[cfg(target_arch = "aarch64")]
-use crate::request::StatusCode;
 use logger::{IncMetric, METRICS};
 
 use serde::{Deserialize, Serialize};
+#[cfg(target_arch = "x86_64")]
+use std::path::PathBuf;
+
+// The time given to the guest to shut down gracefully when the request does not specify it.
+#[cfg(target_arch = "x86_64")]
+const DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_MS: u64 = 30_000;
 
 // The names of the members from this enum must precisely correspond (as a string) to the possible
 // values of "action_type" from the json request body. This is useful to get a strongly typed
 // struct from the Serde deserialization process.
 #[derive(Debug, Deserialize, Serialize)]
 enum ActionType {
+    BootDryRun,
+    CoreDump,
     FlushMetrics,
+    GracefulShutdown,
     InstanceStart,
+    InstanceTeardown,
     SendCtrlAltDel,
+    SendPowerButton,
 }
 
 // The model of the json body from a sync request. We use Serde to transform each associated
@@ -26,29 +36,122 @@ enum ActionType {
 #[serde(deny_unknown_fields)]
 struct ActionBody {
     action_type: ActionType,
+    // Only used by `CoreDump`.
+    #[serde(default)]
+    dump_path: Option<String>,
+    // Only used by `GracefulShutdown`.
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    // Only used by `InstanceStart`.
+    #[serde(default)]
+    start_paused: Option<bool>,
 }
 
 pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.actions_count.inc();
-    let action_body = serde_json::from_slice::<ActionBody>(body.raw()).map_err(|e| {
+    let action_body = parse_body::<ActionBody>(body).map_err(|e| {
         METRICS.put_api_requests.actions_fails.inc();
-        Error::SerdeJson(e)
+        e
     })?;
 
+    if action_body.dump_path.is_some() && !matches!(action_body.action_type, ActionType::CoreDump) {
+        METRICS.put_api_requests.actions_fails.inc();
+        return Err(Error::Field(
+            ErrorCode::InvalidValue,
+            "dump_path".to_string(),
+            "The dump_path field is only supported by the CoreDump action.".to_string(),
+        ));
+    }
+
+    if action_body.timeout_ms.is_some()
+        && !matches!(action_body.action_type, ActionType::GracefulShutdown)
+    {
+        METRICS.put_api_requests.actions_fails.inc();
+        return Err(Error::Field(
+            ErrorCode::InvalidValue,
+            "timeout_ms".to_string(),
+            "The timeout_ms field is only supported by the GracefulShutdown action.".to_string(),
+        ));
+    }
+
+    if action_body.start_paused.is_some()
+        && !matches!(action_body.action_type, ActionType::InstanceStart)
+    {
+        METRICS.put_api_requests.actions_fails.inc();
+        return Err(Error::Field(
+            ErrorCode::InvalidValue,
+            "start_paused".to_string(),
+            "The start_paused field is only supported by the InstanceStart action.".to_string(),
+        ));
+    }
+
     match action_body.action_type {
+        ActionType::BootDryRun => Ok(ParsedRequest::new_sync(VmmAction::BootDryRun)),
+        ActionType::CoreDump => {
+            // Core dumps not supported on aarch64.
+            #[cfg(target_arch = "aarch64")]
+            return Err(Error::Generic(
+                ErrorCode::Unsupported,
+                "CoreDump is not supported on aarch64.".to_string(),
+            ));
+
+            #[cfg(target_arch = "x86_64")]
+            match action_body.dump_path {
+                Some(dump_path) => Ok(ParsedRequest::new_sync(VmmAction::CreateCoreDump(
+                    PathBuf::from(dump_path),
+                ))),
+                None => {
+                    METRICS.put_api_requests.actions_fails.inc();
+                    Err(Error::Field(
+                        ErrorCode::MissingField,
+                        "dump_path".to_string(),
+                        "The CoreDump action requires the dump_path field.".to_string(),
+                    ))
+                }
+            }
+        }
         ActionType::FlushMetrics => Ok(ParsedRequest::new_sync(VmmAction::FlushMetrics)),
-        ActionType::InstanceStart => Ok(ParsedRequest::new_sync(VmmAction::StartMicroVm)),
+        ActionType::GracefulShutdown => {
+            // GracefulShutdown not supported on aarch64.
+            #[cfg(target_arch = "aarch64")]
+            return Err(Error::Generic(
+                ErrorCode::Unsupported,
+                "GracefulShutdown is not supported on aarch64.".to_string(),
+            ));
+
+            #[cfg(target_arch = "x86_64")]
+            Ok(ParsedRequest::new_sync(VmmAction::GracefulShutdown(
+                action_body
+                    .timeout_ms
+                    .unwrap_or(DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_MS),
+            )))
+        }
+        ActionType::InstanceStart => Ok(ParsedRequest::new_sync(VmmAction::StartMicroVm(
+            action_body.start_paused.unwrap_or(false),
+        ))),
+        ActionType::InstanceTeardown => Ok(ParsedRequest::new_sync(VmmAction::Teardown)),
         ActionType::SendCtrlAltDel => {
             // SendCtrlAltDel not supported on aarch64.
             #[cfg(target_arch = "aarch64")]
             return Err(Error::Generic(
-                StatusCode::BadRequest,
+                ErrorCode::Unsupported,
                 "SendCtrlAltDel does not supported on aarch64.".to_string(),
             ));
 
             #[cfg(target_arch = "x86_64")]
             Ok(ParsedRequest::new_sync(VmmAction::SendCtrlAltDel))
         }
+        ActionType::SendPowerButton => {
+            // The ACPI power button is not supported on aarch64.
+            #[cfg(target_arch = "aarch64")]
+            return Err(Error::Generic(
+                ErrorCode::Unsupported,
+                "SendPowerButton is not supported on aarch64.".to_string(),
+            ));
+
+            #[cfg(target_arch = "x86_64")]
+            Ok(ParsedRequest::new_sync(VmmAction::SendPowerButton))
+        }
     }
 }
 
@@ -65,10 +168,27 @@ mod tests {
                 "action_type": "InstanceStart"
             }"#;
 
-            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::StartMicroVm);
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::StartMicroVm(false));
             let result = parse_put_actions(&Body::new(json));
             assert!(result.is_ok());
             assert!(result.unwrap().eq(&req));
+
+            let json = r#"{
+                "action_type": "InstanceStart",
+                "start_paused": true
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::StartMicroVm(true));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+
+            // The paused start only applies to InstanceStart.
+            let json = r#"{
+                "action_type": "FlushMetrics",
+                "start_paused": true
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
         }
 
         #[cfg(target_arch = "x86_64")]
@@ -93,6 +213,47 @@ mod tests {
             assert!(result.is_err());
         }
 
+        {
+            let json = r#"{
+                "action_type": "BootDryRun"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::BootDryRun);
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let json = r#"{
+                "action_type": "CoreDump",
+                "dump_path": "/srv/vmcore"
+            }"#;
+
+            let req: ParsedRequest =
+                ParsedRequest::new_sync(VmmAction::CreateCoreDump(PathBuf::from("/srv/vmcore")));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+
+            let json = r#"{
+                "action_type": "CoreDump"
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let json = r#"{
+                "action_type": "CoreDump",
+                "dump_path": "/srv/vmcore"
+            }"#;
+
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_err());
+        }
+
         {
             let json = r#"{
                 "action_type": "FlushMetrics"
@@ -102,6 +263,79 @@ mod tests {
             let result = parse_put_actions(&Body::new(json));
             assert!(result.is_ok());
             assert!(result.unwrap().eq(&req));
+
+            // The dump path only applies to CoreDump.
+            let json = r#"{
+                "action_type": "FlushMetrics",
+                "dump_path": "/srv/vmcore"
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+
+            // The timeout only applies to GracefulShutdown.
+            let json = r#"{
+                "action_type": "FlushMetrics",
+                "timeout_ms": 1000
+            }"#;
+            assert!(parse_put_actions(&Body::new(json)).is_err());
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let json = r#"{
+                "action_type": "GracefulShutdown",
+                "timeout_ms": 1000
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::GracefulShutdown(1000));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+
+            let json = r#"{
+                "action_type": "GracefulShutdown"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::GracefulShutdown(
+                DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_MS,
+            ));
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let json = r#"{
+                "action_type": "GracefulShutdown"
+            }"#;
+
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_err());
+        }
+
+        {
+            let json = r#"{
+                "action_type": "InstanceTeardown"
+            }"#;
+
+            let req: ParsedRequest = ParsedRequest::new_sync(VmmAction::Teardown);
+            let result = parse_put_actions(&Body::new(json));
+            assert!(result.is_ok());
+            assert!(result.unwrap().eq(&req));
+        }
+
+        {
+            let json = r#"{
+                "action_type": "SendPowerButton"
+            }"#;
+
+            let result = parse_put_actions(&Body::new(json));
+            #[cfg(target_arch = "x86_64")]
+            assert!(result
+                .unwrap()
+                .eq(&ParsedRequest::new_sync(VmmAction::SendPowerButton)));
+            #[cfg(target_arch = "aarch64")]
+            assert!(result.is_err());
         }
     }
 }
@@ -0,0 +1,118 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::fault::ErrorCode;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::agent::AgentCommand;
+
+pub(crate) fn parse_put_agent(
+    body: Option<&Body>,
+    path_second_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let command_name = match path_second_token {
+        Some(command_name) => *command_name,
+        None => {
+            return Err(Error::Generic(
+                ErrorCode::InvalidRequest,
+                "Missing the agent command.".to_string(),
+            ))
+        }
+    };
+    // Only the commands taking parameters require a body.
+    let params_body = || {
+        body.ok_or_else(|| {
+            Error::Generic(
+                ErrorCode::InvalidRequest,
+                format!("The `{}` agent command requires a body.", command_name),
+            )
+        })
+    };
+
+    let command = match command_name {
+        "exec" => AgentCommand::Exec(parse_body(params_body()?)?),
+        "file-pull" => AgentCommand::FilePull(parse_body(params_body()?)?),
+        "file-push" => AgentCommand::FilePush(parse_body(params_body()?)?),
+        "fs-freeze" => AgentCommand::FsFreeze,
+        "fs-thaw" => AgentCommand::FsThaw,
+        "ping" => AgentCommand::Ping,
+        unrecognized => {
+            return Err(Error::Generic(
+                ErrorCode::InvalidRequest,
+                format!("Unrecognized PUT request path `{}`.", unrecognized),
+            ))
+        }
+    };
+    Ok(ParsedRequest::Agent(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vmm::agent::{ExecParams, FilePullParams, FilePushParams};
+
+    #[test]
+    fn test_parse_put_agent_request() {
+        assert!(parse_put_agent(None, None).is_err());
+        assert!(parse_put_agent(None, Some(&"reboot")).is_err());
+
+        assert!(
+            parse_put_agent(None, Some(&"ping")).unwrap()
+                == ParsedRequest::Agent(AgentCommand::Ping)
+        );
+        assert!(
+            parse_put_agent(None, Some(&"fs-freeze")).unwrap()
+                == ParsedRequest::Agent(AgentCommand::FsFreeze)
+        );
+        assert!(
+            parse_put_agent(Some(&Body::new("{}")), Some(&"fs-thaw")).unwrap()
+                == ParsedRequest::Agent(AgentCommand::FsThaw)
+        );
+
+        // The commands taking parameters require a valid body.
+        assert!(parse_put_agent(None, Some(&"exec")).is_err());
+        assert!(parse_put_agent(Some(&Body::new("{}")), Some(&"exec")).is_err());
+        let body = r#"{
+                "argv": ["uname", "-r"],
+                "timeout_ms": 1000
+              }"#;
+        assert!(
+            parse_put_agent(Some(&Body::new(body)), Some(&"exec")).unwrap()
+                == ParsedRequest::Agent(AgentCommand::Exec(ExecParams {
+                    argv: vec!["uname".to_string(), "-r".to_string()],
+                    timeout_ms: Some(1000),
+                }))
+        );
+
+        let body = r#"{
+                "host_path": "/srv/motd",
+                "guest_path": "/etc/motd",
+                "mode": 420
+              }"#;
+        assert!(
+            parse_put_agent(Some(&Body::new(body)), Some(&"file-push")).unwrap()
+                == ParsedRequest::Agent(AgentCommand::FilePush(FilePushParams {
+                    host_path: "/srv/motd".to_string(),
+                    guest_path: "/etc/motd".to_string(),
+                    mode: Some(420),
+                }))
+        );
+
+        let body = r#"{
+                "guest_path": "/var/log/boot.log",
+                "host_path": "/srv/boot.log"
+              }"#;
+        assert!(
+            parse_put_agent(Some(&Body::new(body)), Some(&"file-pull")).unwrap()
+                == ParsedRequest::Agent(AgentCommand::FilePull(FilePullParams {
+                    guest_path: "/var/log/boot.log".to_string(),
+                    host_path: "/srv/boot.log".to_string(),
+                }))
+        );
+        let body = r#"{
+                "guest_path": "/var/log/boot.log"
+              }"#;
+        assert!(parse_put_agent(Some(&Body::new(body)), Some(&"file-pull")).is_err());
+    }
+}
@@ -2,11 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::VmmAction;
-use crate::parsed_request::{Error, ParsedRequest};
+use crate::fault::ErrorCode;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
 use crate::request::Body;
-use micro_http::StatusCode;
 use vmm::vmm_config::balloon::{
-    BalloonDeviceConfig, BalloonUpdateConfig, BalloonUpdateStatsConfig,
+    BalloonDeviceConfig, BalloonPolicy, BalloonUpdateConfig, BalloonUpdateStatsConfig,
 };
 
 pub(crate) fn parse_get_balloon(path_second_token: Option<&&str>) -> Result<ParsedRequest, Error> {
@@ -14,7 +14,7 @@ pub(crate) fn parse_get_balloon(path_second_token: Option<&&str>) -> Result<Pars
         Some(stats_path) => match *stats_path {
             "statistics" => Ok(ParsedRequest::new_sync(VmmAction::GetBalloonStats)),
             _ => Err(Error::Generic(
-                StatusCode::BadRequest,
+                ErrorCode::InvalidRequest,
                 format!("Unrecognized GET request path `{}`.", *stats_path),
             )),
         },
@@ -22,10 +22,24 @@ pub(crate) fn parse_get_balloon(path_second_token: Option<&&str>) -> Result<Pars
     }
 }
 
-pub(crate) fn parse_put_balloon(body: &Body) -> Result<ParsedRequest, Error> {
-    Ok(ParsedRequest::new_sync(VmmAction::SetBalloonDevice(
-        serde_json::from_slice::<BalloonDeviceConfig>(body.raw()).map_err(Error::SerdeJson)?,
-    )))
+pub(crate) fn parse_put_balloon(
+    body: &Body,
+    path_second_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(policy_path) => match *policy_path {
+            "policy" => Ok(ParsedRequest::new_sync(VmmAction::SetBalloonPolicy(
+                parse_body::<BalloonPolicy>(body)?,
+            ))),
+            _ => Err(Error::Generic(
+                ErrorCode::InvalidRequest,
+                format!("Unrecognized PUT request path `{}`.", *policy_path),
+            )),
+        },
+        None => Ok(ParsedRequest::new_sync(VmmAction::SetBalloonDevice(
+            parse_body::<BalloonDeviceConfig>(body)?,
+        ))),
+    }
 }
 
 pub(crate) fn parse_patch_balloon(
@@ -35,16 +49,15 @@ pub(crate) fn parse_patch_balloon(
     match path_second_token {
         Some(config_path) => match *config_path {
             "statistics" => Ok(ParsedRequest::new_sync(VmmAction::UpdateBalloonStatistics(
-                serde_json::from_slice::<BalloonUpdateStatsConfig>(body.raw())
-                    .map_err(Error::SerdeJson)?,
+                parse_body::<BalloonUpdateStatsConfig>(body)?,
             ))),
             _ => Err(Error::Generic(
-                StatusCode::BadRequest,
+                ErrorCode::InvalidRequest,
                 format!("Unrecognized PATCH request path `{}`.", *config_path),
             )),
         },
         None => Ok(ParsedRequest::new_sync(VmmAction::UpdateBalloon(
-            serde_json::from_slice::<BalloonUpdateConfig>(body.raw()).map_err(Error::SerdeJson)?,
+            parse_body::<BalloonUpdateConfig>(body)?,
         ))),
     }
 }
@@ -149,14 +162,14 @@ mod tests {
 
     #[test]
     fn test_parse_put_balloon_request() {
-        assert!(parse_put_balloon(&Body::new("invalid_payload")).is_err());
+        assert!(parse_put_balloon(&Body::new("invalid_payload"), None).is_err());
 
         // PUT with invalid fields.
         let body = r#"{
                 "amount_mb": "bar",
                 "is_read_only": false
               }"#;
-        assert!(parse_put_balloon(&Body::new(body)).is_err());
+        assert!(parse_put_balloon(&Body::new(body), None).is_err());
 
         // PUT with valid input fields.
         let body = r#"{
@@ -164,6 +177,55 @@ mod tests {
                 "deflate_on_oom": true,
                 "stats_polling_interval_s": 0
             }"#;
-        assert!(parse_put_balloon(&Body::new(body)).is_ok());
+        assert!(parse_put_balloon(&Body::new(body), None).is_ok());
+
+        let body = r#"{
+                "amount_mb": 1000,
+                "deflate_on_oom": true,
+                "free_page_reporting": true
+            }"#;
+        match vmm_action_from_request(parse_put_balloon(&Body::new(body), None).unwrap()) {
+            VmmAction::SetBalloonDevice(balloon_cfg) => assert!(balloon_cfg.free_page_reporting),
+            _ => panic!("Test failed."),
+        }
+
+        // PUT on unrecognized path.
+        assert!(parse_put_balloon(&Body::new(body), Some(&"config")).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_balloon_policy_request() {
+        // PUT with missing fields.
+        let body = r#"{
+                "min_mb": 0,
+                "max_mb": 512
+            }"#;
+        assert!(parse_put_balloon(&Body::new(body), Some(&"policy")).is_err());
+
+        // PUT with valid input fields.
+        let body = r#"{
+                "min_mb": 0,
+                "max_mb": 512,
+                "polling_interval_s": 5,
+                "low_available_mb": 1024,
+                "high_available_mb": 4096,
+                "psi_threshold": 10
+            }"#;
+        match vmm_action_from_request(parse_put_balloon(&Body::new(body), Some(&"policy")).unwrap())
+        {
+            VmmAction::SetBalloonPolicy(policy) => assert_eq!(
+                policy,
+                BalloonPolicy {
+                    min_mb: 0,
+                    max_mb: 512,
+                    step_mb: 64,
+                    polling_interval_s: 5,
+                    low_available_mb: 1024,
+                    high_available_mb: 4096,
+                    psi_threshold: Some(10),
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
     }
 }
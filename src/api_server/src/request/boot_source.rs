@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::VmmAction;
-use crate::parsed_request::{Error, ParsedRequest};
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
 use crate::request::Body;
 use logger::{IncMetric, METRICS};
 use vmm::vmm_config::boot_source::BootSourceConfig;
@@ -10,9 +10,9 @@ use vmm::vmm_config::boot_source::BootSourceConfig;
 pub(crate) fn parse_put_boot_source(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.boot_source_count.inc();
     Ok(ParsedRequest::new_sync(VmmAction::ConfigureBootSource(
-        serde_json::from_slice::<BootSourceConfig>(body.raw()).map_err(|e| {
+        parse_body::<BootSourceConfig>(body).map_err(|e| {
             METRICS.put_api_requests.boot_source_fails.inc();
-            Error::SerdeJson(e)
+            e
         })?,
     )))
 }
@@ -31,14 +31,51 @@ mod tests {
                 "boot_args": "foobar"
               }"#;
         let same_body = BootSourceConfig {
-            kernel_image_path: String::from("/foo/bar"),
+            kernel_image_path: Some(String::from("/foo/bar")),
             initrd_path: Some(String::from("/bar/foo")),
+            initrd_paths: Vec::new(),
             boot_args: Some(String::from("foobar")),
+            #[cfg(target_arch = "x86_64")]
+            firmware_path: None,
+            #[cfg(target_arch = "x86_64")]
+            nvram_path: None,
         };
         let result = parse_put_boot_source(&Body::new(body));
         assert!(result.is_ok());
         let parsed_req = result.unwrap_or_else(|_e| panic!("Failed test."));
 
         assert!(parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(same_body)));
+
+        let body = r#"{
+                "kernel_image_path": "/foo/bar",
+                "initrd_paths": ["/bar/foo", "/bar/baz"]
+              }"#;
+        let initrds_body = BootSourceConfig {
+            kernel_image_path: Some(String::from("/foo/bar")),
+            initrd_paths: vec![String::from("/bar/foo"), String::from("/bar/baz")],
+            ..Default::default()
+        };
+        let parsed_req = parse_put_boot_source(&Body::new(body)).unwrap();
+        assert!(
+            parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(initrds_body))
+        );
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let body = r#"{
+                "firmware_path": "/foo/OVMF_CODE.fd",
+                "nvram_path": "/foo/OVMF_VARS.fd"
+              }"#;
+            let firmware_body = BootSourceConfig {
+                firmware_path: Some(String::from("/foo/OVMF_CODE.fd")),
+                nvram_path: Some(String::from("/foo/OVMF_VARS.fd")),
+                ..Default::default()
+            };
+            let parsed_req = parse_put_boot_source(&Body::new(body)).unwrap();
+            assert!(
+                parsed_req
+                    == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(firmware_body))
+            );
+        }
     }
 }
@@ -0,0 +1,60 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::console::ConsoleConfig;
+
+pub(crate) fn parse_put_console(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetConsoleDevice(
+        parse_body::<ConsoleConfig>(body)?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_console_request() {
+        assert!(parse_put_console(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "ports": [],
+                "some_id": 4
+              }"#;
+        assert!(parse_put_console(&Body::new(body)).is_err());
+
+        // PUT without ports.
+        assert!(parse_put_console(&Body::new("{}")).is_err());
+
+        let body = r#"{
+                "ports": [
+                    {
+                        "name": "console",
+                        "uds_path": "/tmp/console.sock",
+                        "is_console": true
+                    },
+                    {
+                        "name": "logs",
+                        "pipe_path": "/tmp/logs.fifo"
+                    }
+                ]
+              }"#;
+        match vmm_action_from_request(parse_put_console(&Body::new(body)).unwrap()) {
+            VmmAction::SetConsoleDevice(config) => {
+                assert_eq!(config.ports.len(), 2);
+                assert!(config.ports[0].is_console);
+                assert!(!config.ports[1].is_console);
+                assert_eq!(
+                    config.ports[1].pipe_path,
+                    Some("/tmp/logs.fifo".to_string())
+                );
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::cpu_quota::CpuQuotaConfig;
+
+pub(crate) fn parse_put_cpu_quota(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetCpuQuota(
+        parse_body::<CpuQuotaConfig>(body)?,
+    )))
+}
+
+// The quota is replaced as a whole, so the request is the same as `PUT`, but reads better for
+// changing the quota of a running microVM.
+pub(crate) fn parse_patch_cpu_quota(body: &Body) -> Result<ParsedRequest, Error> {
+    parse_put_cpu_quota(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_cpu_quota_request() {
+        assert!(parse_put_cpu_quota(&Body::new("invalid_payload")).is_err());
+        assert!(parse_put_cpu_quota(&Body::new("{}")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "quota_pct": 50,
+                "period_us": 1000
+              }"#;
+        assert!(parse_put_cpu_quota(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "quota_pct": 50
+              }"#;
+        match vmm_action_from_request(parse_put_cpu_quota(&Body::new(body)).unwrap()) {
+            VmmAction::SetCpuQuota(config) => assert_eq!(config.quota_pct, 50),
+            _ => panic!("Test failed."),
+        }
+        match vmm_action_from_request(parse_patch_cpu_quota(&Body::new(body)).unwrap()) {
+            VmmAction::SetCpuQuota(config) => assert_eq!(config.quota_pct, 50),
+            _ => panic!("Test failed."),
+        }
+    }
+}
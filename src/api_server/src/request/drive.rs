@@ -2,11 +2,37 @@
 // SPDX-License-Identifier: Apache-2.0<Paste>
 
 use super::super::VmmAction;
-use crate::parsed_request::{checked_id, Error, ParsedRequest};
-use crate::request::{Body, StatusCode};
+use crate::fault::ErrorCode;
+use crate::parsed_request::{check_id_from_body, checked_id, parse_body, Error, ParsedRequest};
+use crate::request::{Body, Method};
 use logger::{IncMetric, METRICS};
 use vmm::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig};
 
+pub(crate) fn parse_get_drive(
+    id_from_path: Option<&&str>,
+    path_third_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    match path_third_token {
+        Some(&"rate-limiter") => Ok(ParsedRequest::new_sync(VmmAction::GetDriveRateLimiter(
+            id.to_string(),
+        ))),
+        Some(unknown_path) => Err(Error::Generic(
+            ErrorCode::InvalidRequest,
+            format!("Unrecognized GET request path `{}`.", unknown_path),
+        )),
+        None => Err(Error::InvalidPathMethod(
+            format!("/drives/{}", id),
+            Method::Get,
+        )),
+    }
+}
+
 pub(crate) fn parse_put_drive(
     body: &Body,
     id_from_path: Option<&&str>,
@@ -19,22 +45,18 @@ pub(crate) fn parse_put_drive(
         return Err(Error::EmptyID);
     };
 
-    let device_cfg = serde_json::from_slice::<BlockDeviceConfig>(body.raw()).map_err(|e| {
+    let device_cfg = parse_body::<BlockDeviceConfig>(body).map_err(|e| {
         METRICS.put_api_requests.drive_fails.inc();
-        Error::SerdeJson(e)
+        e
     })?;
 
-    if id != device_cfg.drive_id {
+    check_id_from_body("drive_id", id, &device_cfg.drive_id).map_err(|e| {
         METRICS.put_api_requests.drive_fails.inc();
-        Err(Error::Generic(
-            StatusCode::BadRequest,
-            "The id from the path does not match the id from the body!".to_string(),
-        ))
-    } else {
-        Ok(ParsedRequest::new_sync(VmmAction::InsertBlockDevice(
-            device_cfg,
-        )))
-    }
+        e
+    })?;
+    Ok(ParsedRequest::new_sync(VmmAction::InsertBlockDevice(
+        device_cfg,
+    )))
 }
 
 pub(crate) fn parse_patch_drive(
@@ -50,30 +72,30 @@ pub(crate) fn parse_patch_drive(
     };
 
     let block_device_update_cfg: BlockDeviceUpdateConfig =
-        serde_json::from_slice::<BlockDeviceUpdateConfig>(body.raw()).map_err(|e| {
+        parse_body::<BlockDeviceUpdateConfig>(body).map_err(|e| {
             METRICS.patch_api_requests.drive_fails.inc();
-            Error::SerdeJson(e)
+            e
         })?;
 
-    if id != block_device_update_cfg.drive_id {
+    check_id_from_body("drive_id", id, &block_device_update_cfg.drive_id).map_err(|e| {
         METRICS.patch_api_requests.drive_fails.inc();
-        return Err(Error::Generic(
-            StatusCode::BadRequest,
-            String::from("The id from the path does not match the id from the body!"),
-        ));
-    }
+        e
+    })?;
 
     // Validate request - we need to have at least one parameter set:
     // - path_on_host
     // - rate_limiter
+    // - io_weight
     if block_device_update_cfg.path_on_host.is_none()
         && block_device_update_cfg.rate_limiter.is_none()
+        && block_device_update_cfg.io_weight.is_none()
     {
         METRICS.patch_api_requests.drive_fails.inc();
         return Err(Error::Generic(
-            StatusCode::BadRequest,
+            ErrorCode::MissingField,
             String::from(
-                "Please specify at least one property to patch: path_on_host, rate_limiter.",
+                "Please specify at least one property to patch: path_on_host, rate_limiter, \
+                 io_weight.",
             ),
         ));
     }
@@ -88,6 +110,20 @@ mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_drive_request() {
+        assert!(parse_get_drive(None, Some(&"rate-limiter")).is_err());
+        assert!(parse_get_drive(Some(&"foo"), None).is_err());
+        assert!(parse_get_drive(Some(&"foo"), Some(&"stats")).is_err());
+        assert!(parse_get_drive(Some(&"foo!"), Some(&"rate-limiter")).is_err());
+
+        match vmm_action_from_request(parse_get_drive(Some(&"foo"), Some(&"rate-limiter")).unwrap())
+        {
+            VmmAction::GetDriveRateLimiter(drive_id) => assert_eq!(drive_id, "foo"),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_parse_patch_drive_request() {
         assert!(parse_patch_drive(&Body::new("invalid_payload"), None).is_err());
@@ -181,6 +217,37 @@ mod tests {
         // Validate that updating just the ratelimiter works.
         assert!(parse_patch_drive(&Body::new(body), Some(&"foo")).is_ok());
 
+        let body = r#"{
+            "drive_id": "foo",
+            "rate_limiter": {
+                "adaptive": {
+                    "target_latency_us": 5000,
+                    "min_rate_pct": 20
+                }
+            }
+        }"#;
+        // Validate that updating just the adaptive tuning works.
+        #[allow(clippy::match_wild_err_arm)]
+        match vmm_action_from_request(parse_patch_drive(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateBlockDevice(cfg) => {
+                let adaptive = cfg.rate_limiter.unwrap().adaptive.unwrap();
+                assert_eq!(adaptive.target_latency_us, 5000);
+                assert_eq!(adaptive.min_rate_pct, Some(20));
+            }
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
+        let body = r#"{
+            "drive_id": "foo",
+            "io_weight": 200
+        }"#;
+        // Validate that updating just the I/O weight works.
+        #[allow(clippy::match_wild_err_arm)]
+        match vmm_action_from_request(parse_patch_drive(&Body::new(body), Some(&"foo")).unwrap()) {
+            VmmAction::UpdateBlockDevice(cfg) => assert_eq!(cfg.io_weight, Some(200)),
+            _ => panic!("Test failed: Invalid parameters"),
+        };
+
         let body = r#"{
             "drive_id": "foo",
             "path_on_host": "/there",
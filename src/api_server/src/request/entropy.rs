@@ -0,0 +1,49 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::entropy::EntropyDeviceConfig;
+
+pub(crate) fn parse_put_entropy(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetEntropyDevice(
+        parse_body::<EntropyDeviceConfig>(body)?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_entropy_request() {
+        assert!(parse_put_entropy(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "some_id": 4
+              }"#;
+        assert!(parse_put_entropy(&Body::new(body)).is_err());
+
+        // PUT without a rate limiter.
+        match vmm_action_from_request(parse_put_entropy(&Body::new("{}")).unwrap()) {
+            VmmAction::SetEntropyDevice(config) => assert!(config.rate_limiter.is_none()),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "rate_limiter": {
+                    "bandwidth": {
+                        "size": 1000,
+                        "refill_time": 100
+                    }
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_entropy(&Body::new(body)).unwrap()) {
+            VmmAction::SetEntropyDevice(config) => assert!(config.rate_limiter.is_some()),
+            _ => panic!("Test failed."),
+        }
+    }
+}
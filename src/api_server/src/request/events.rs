@@ -0,0 +1,23 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+
+pub(crate) fn parse_get_events() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetGuestEvents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_events_request() {
+        match vmm_action_from_request(parse_get_events().unwrap()) {
+            VmmAction::GetGuestEvents => (),
+            _ => panic!("Test failed."),
+        }
+    }
+}
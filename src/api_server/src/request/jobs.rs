@@ -0,0 +1,43 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::fault::ErrorCode;
+use crate::parsed_request::{Error, ParsedRequest};
+use logger::{IncMetric, METRICS};
+
+pub(crate) fn parse_get_job(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.job_count.inc();
+    let id = if let Some(id) = id_from_path {
+        id
+    } else {
+        METRICS.get_api_requests.job_fails.inc();
+        return Err(Error::EmptyID);
+    };
+
+    match id.parse::<u64>() {
+        Ok(id) => Ok(ParsedRequest::GetJob(id)),
+        Err(_) => {
+            METRICS.get_api_requests.job_fails.inc();
+            Err(Error::Generic(
+                ErrorCode::InvalidId,
+                format!("Invalid job ID `{}`.", id),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_job_request() {
+        match parse_get_job(Some(&"42")) {
+            Ok(ParsedRequest::GetJob(42)) => (),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_job(None).is_err());
+        assert!(parse_get_job(Some(&"job")).is_err());
+        assert!(parse_get_job(Some(&"-1")).is_err());
+    }
+}
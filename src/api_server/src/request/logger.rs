@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::VmmAction;
-use crate::parsed_request::{Error, ParsedRequest};
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
 use crate::request::Body;
 use logger::{IncMetric, METRICS};
 use vmm::vmm_config::logger::LoggerConfig;
@@ -10,9 +10,9 @@ use vmm::vmm_config::logger::LoggerConfig;
 pub(crate) fn parse_put_logger(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.logger_count.inc();
     Ok(ParsedRequest::new_sync(VmmAction::ConfigureLogger(
-        serde_json::from_slice::<LoggerConfig>(body.raw()).map_err(|e| {
+        parse_body::<LoggerConfig>(body).map_err(|e| {
             METRICS.put_api_requests.logger_fails.inc();
-            Error::SerdeJson(e)
+            e
         })?,
     )))
 }
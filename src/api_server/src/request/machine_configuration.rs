@@ -2,8 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0<Paste>
 
 use super::super::VmmAction;
-use crate::parsed_request::{method_to_error, Error, ParsedRequest};
-use crate::request::{Body, Method, StatusCode};
+use crate::fault::ErrorCode;
+use crate::parsed_request::{method_to_error, parse_body, Error, ParsedRequest};
+use crate::request::{Body, Method};
 use logger::{IncMetric, METRICS};
 use vmm::vmm_config::machine_config::VmConfig;
 
@@ -14,19 +15,26 @@ pub(crate) fn parse_get_machine_config() -> Result<ParsedRequest, Error> {
 
 pub(crate) fn parse_put_machine_config(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.machine_cfg_count.inc();
-    let vm_config = serde_json::from_slice::<VmConfig>(body.raw()).map_err(|e| {
+    let vm_config = parse_body::<VmConfig>(body).map_err(|e| {
         METRICS.put_api_requests.machine_cfg_fails.inc();
-        Error::SerdeJson(e)
+        e
     })?;
 
     check_unsupported_fields(&vm_config)?;
 
-    if vm_config.vcpu_count.is_none()
-        || vm_config.mem_size_mib.is_none()
-        || vm_config.ht_enabled.is_none()
-    {
-        return Err(Error::Generic(
-            StatusCode::BadRequest,
+    let missing_field = if vm_config.vcpu_count.is_none() {
+        Some("vcpu_count")
+    } else if vm_config.mem_size_mib.is_none() {
+        Some("mem_size_mib")
+    } else if vm_config.ht_enabled.is_none() {
+        Some("ht_enabled")
+    } else {
+        None
+    };
+    if let Some(missing_field) = missing_field {
+        return Err(Error::Field(
+            ErrorCode::MissingField,
+            missing_field.to_string(),
             "Missing mandatory fields.".to_string(),
         ));
     }
@@ -38,17 +46,32 @@ pub(crate) fn parse_put_machine_config(body: &Body) -> Result<ParsedRequest, Err
 
 pub(crate) fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.patch_api_requests.machine_cfg_count.inc();
-    let vm_config = serde_json::from_slice::<VmConfig>(body.raw()).map_err(|e| {
+    let vm_config = parse_body::<VmConfig>(body).map_err(|e| {
         METRICS.patch_api_requests.machine_cfg_fails.inc();
-        Error::SerdeJson(e)
+        e
     })?;
 
     check_unsupported_fields(&vm_config)?;
 
     if vm_config.vcpu_count.is_none()
+        && vm_config.max_vcpu_count.is_none()
         && vm_config.mem_size_mib.is_none()
         && vm_config.cpu_template.is_none()
         && vm_config.ht_enabled.is_none()
+        && vm_config.pit_enabled.is_none()
+        && vm_config.pit_reinject_policy.is_none()
+        && vm_config.hpet_enabled.is_none()
+        && vm_config.cpu_topology.is_none()
+        && vm_config.mem_backend.is_none()
+        && vm_config.nested_virt_enabled.is_none()
+        && vm_config.msr_policy.is_none()
+        && vm_config.mmio_layout.is_none()
+        && vm_config.virtio_transport.is_none()
+        && vm_config.cpu_template_path.is_none()
+        && vm_config.cpu_features.is_none()
+        && vm_config.cpuid_normalization_enabled.is_none()
+        && vm_config.mem_hints.is_none()
+        && vm_config.io_threads.is_none()
     {
         return method_to_error(Method::Patch);
     }
@@ -62,11 +85,109 @@ fn check_unsupported_fields(_vm_config: &VmConfig) -> Result<(), Error> {
     {
         if _vm_config.cpu_template.is_some() {
             // cpu_template is not supported on aarch64
-            return Err(Error::Generic(
-                StatusCode::BadRequest,
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "cpu_template".to_string(),
                 "CPU templates are not supported on aarch64".to_string(),
             ));
         }
+
+        if _vm_config.pit_enabled.is_some()
+            || _vm_config.pit_reinject_policy.is_some()
+            || _vm_config.hpet_enabled.is_some()
+        {
+            // PIT and HPET are x86_64 legacy timers.
+            return Err(Error::Generic(
+                ErrorCode::Unsupported,
+                "PIT and HPET configuration is not supported on aarch64".to_string(),
+            ));
+        }
+
+        if _vm_config.cpu_topology.is_some() {
+            // The topology is exposed through CPUID.
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "cpu_topology".to_string(),
+                "CPU topologies are not supported on aarch64".to_string(),
+            ));
+        }
+
+        if _vm_config.max_vcpu_count.is_some() {
+            // vCPUs are only hotplugged through a port I/O device.
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "max_vcpu_count".to_string(),
+                "vCPU hotplug is not supported on aarch64".to_string(),
+            ));
+        }
+
+        if _vm_config.nested_virt_enabled.is_some() {
+            // VMX and SVM are x86_64 extensions.
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "nested_virt_enabled".to_string(),
+                "Nested virtualization is not supported on aarch64".to_string(),
+            ));
+        }
+
+        if _vm_config.msr_policy.is_some() {
+            // MSRs are x86_64 registers.
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "msr_policy".to_string(),
+                "MSR policies are not supported on aarch64".to_string(),
+            ));
+        }
+
+        if _vm_config
+            .mmio_layout
+            .map_or(false, |layout| layout.gap_size_mib.is_some())
+        {
+            // The MMIO gap sits between fixed regions of the aarch64 memory map.
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "gap_size_mib".to_string(),
+                "The size of the MMIO gap cannot be changed on aarch64".to_string(),
+            ));
+        }
+
+        if _vm_config.virtio_transport
+            == Some(vmm::vmm_config::machine_config::VirtioTransport::Pci)
+        {
+            // The guest finds the PCI root complex through the ACPI tables.
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "virtio_transport".to_string(),
+                "The virtio-pci transport is not supported on aarch64".to_string(),
+            ));
+        }
+
+        if _vm_config.cpu_template_path.is_some() {
+            // Custom CPU templates modify CPUID leaves and MSRs.
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "cpu_template_path".to_string(),
+                "Custom CPU templates are not supported on aarch64".to_string(),
+            ));
+        }
+
+        if _vm_config.cpu_features.is_some() {
+            // The features are CPUID bits.
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "cpu_features".to_string(),
+                "Forcing CPU features is not supported on aarch64".to_string(),
+            ));
+        }
+
+        if _vm_config.cpuid_normalization_enabled.is_some() {
+            // The normalized leaves are CPUID leaves.
+            return Err(Error::Field(
+                ErrorCode::Unsupported,
+                "cpuid_normalization_enabled".to_string(),
+                "CPUID normalization is not supported on aarch64".to_string(),
+            ));
+        }
     }
     Ok(())
 }
@@ -105,7 +226,12 @@ mod tests {
                 "vcpu_count": 8,
                 "mem_size_mib": 1024
               }"#;
-        assert!(parse_put_machine_config(&Body::new(body)).is_err());
+        let fault = parse_put_machine_config(&Body::new(body))
+            .err()
+            .unwrap()
+            .fault();
+        assert_eq!(fault.error_code, ErrorCode::MissingField);
+        assert_eq!(fault.field, Some("ht_enabled".to_string()));
 
         // 3. Test case a success scenario for both architectures.
         let body = r#"{
@@ -116,10 +242,25 @@ mod tests {
               }"#;
         let expected_config = VmConfig {
             vcpu_count: Some(8),
+            max_vcpu_count: None,
             mem_size_mib: Some(1024),
             ht_enabled: Some(true),
             cpu_template: None,
             track_dirty_pages: true,
+            pit_enabled: None,
+            pit_reinject_policy: None,
+            hpet_enabled: None,
+            cpu_topology: None,
+            mem_backend: None,
+            nested_virt_enabled: None,
+            msr_policy: None,
+            mmio_layout: None,
+            virtio_transport: None,
+            cpu_template_path: None,
+            cpu_features: None,
+            cpuid_normalization_enabled: None,
+            mem_hints: None,
+            io_threads: None,
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -141,10 +282,73 @@ mod tests {
             use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
             let expected_config = VmConfig {
                 vcpu_count: Some(8),
+                max_vcpu_count: None,
                 mem_size_mib: Some(1024),
                 ht_enabled: Some(true),
                 cpu_template: Some(CpuFeaturesTemplate::T2),
                 track_dirty_pages: true,
+                pit_enabled: None,
+                pit_enabled: None,
+                pit_reinject_policy: None,
+                hpet_enabled: None,
+                cpu_topology: None,
+                mem_backend: None,
+                nested_virt_enabled: None,
+                msr_policy: None,
+                mmio_layout: None,
+                virtio_transport: None,
+                cpu_template_path: None,
+                cpu_features: None,
+                cpuid_normalization_enabled: None,
+                mem_hints: None,
+                io_threads: None,
+            };
+
+            match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
+                VmmAction::SetVmConfiguration(config) => assert_eq!(config, expected_config),
+                _ => panic!("Test failed."),
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            assert!(parse_put_machine_config(&Body::new(body)).is_err());
+        }
+
+        // 5. Test that the legacy timer policies are accepted on x86_64 only.
+        let body = r#"{
+                "vcpu_count": 8,
+                "mem_size_mib": 1024,
+                "ht_enabled": true,
+                "pit_enabled": true,
+                "pit_reinject_policy": "Discard",
+                "hpet_enabled": false
+              }"#;
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            use vmm::vmm_config::machine_config::PitReinjectPolicy;
+            let expected_config = VmConfig {
+                vcpu_count: Some(8),
+                max_vcpu_count: None,
+                mem_size_mib: Some(1024),
+                ht_enabled: Some(true),
+                cpu_template: None,
+                track_dirty_pages: false,
+                pit_enabled: Some(true),
+                pit_reinject_policy: Some(PitReinjectPolicy::Discard),
+                hpet_enabled: Some(false),
+                cpu_topology: None,
+                mem_backend: None,
+                nested_virt_enabled: None,
+                msr_policy: None,
+                mmio_layout: None,
+                virtio_transport: None,
+                cpu_template_path: None,
+                cpu_features: None,
+                cpuid_normalization_enabled: None,
+                mem_hints: None,
+                io_threads: None,
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -180,6 +384,134 @@ mod tests {
         #[cfg(target_arch = "x86_64")]
         assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
 
+        let body = r#"{
+                "pit_reinject_policy": "Reinject"
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "pit_enabled": false
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "cpu_topology": {
+                    "sockets": 2,
+                    "cores_per_socket": 2,
+                    "threads_per_core": 1
+                }
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+        let body = r#"{
+                "cpu_topology": {
+                    "sockets": 2,
+                    "cores_per_socket": 2
+                }
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "max_vcpu_count": 4
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "nested_virt_enabled": true
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "msr_policy": {
+                    "allow": [{ "index": 16 }],
+                    "deny": [{ "index": 0, "count": 256 }],
+                    "deny_action": "ReadZero"
+                }
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+        let body = r#"{
+                "msr_policy": {
+                    "deny_action": "Ignore"
+                }
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "mmio_layout": {
+                    "device_slots": 4
+                }
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+        let body = r#"{
+                "mmio_layout": {
+                    "gap_size_mib": 1024
+                }
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "virtio_transport": "mmio"
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+        let body = r#"{
+                "virtio_transport": "pci"
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+        let body = r#"{
+                "virtio_transport": "ccw"
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "mem_backend": "hugetlbfs_2m"
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+        let body = r#"{
+                "mem_backend": "hugetlbfs_4m"
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "mem_hints": { "mergeable": true }
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+        let body = r#"{
+                "mem_hints": { "ksm": true }
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "io_threads": 2
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+        let body = r#"{
+                "io_threads": 256
+              }"#;
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+
         let body = r#"{
                 "vcpu_count": 8,
                 "mem_size_mib": 1024
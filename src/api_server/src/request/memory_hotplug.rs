@@ -0,0 +1,100 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::memory_hotplug::{MemoryHotplugConfig, MemoryHotplugSizeUpdate};
+
+pub(crate) fn parse_get_memory_hotplug() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetMemoryHotplugStatus))
+}
+
+pub(crate) fn parse_put_memory_hotplug(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetMemoryHotplug(
+        parse_body::<MemoryHotplugConfig>(body)?,
+    )))
+}
+
+pub(crate) fn parse_patch_memory_hotplug(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::UpdateMemoryHotplug(
+        parse_body::<MemoryHotplugSizeUpdate>(body)?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_memory_hotplug_request() {
+        match vmm_action_from_request(parse_get_memory_hotplug().unwrap()) {
+            VmmAction::GetMemoryHotplugStatus => (),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_parse_put_memory_hotplug_request() {
+        assert!(parse_put_memory_hotplug(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "total_size_mib": 1024,
+                "requested_size_mib": 512
+              }"#;
+        assert!(parse_put_memory_hotplug(&Body::new(body)).is_err());
+
+        // PUT with a negative size.
+        let body = r#"{
+                "total_size_mib": -1024
+              }"#;
+        assert!(parse_put_memory_hotplug(&Body::new(body)).is_err());
+
+        // The block size defaults to 2 MiB.
+        let body = r#"{
+                "total_size_mib": 1024
+              }"#;
+        match vmm_action_from_request(parse_put_memory_hotplug(&Body::new(body)).unwrap()) {
+            VmmAction::SetMemoryHotplug(config) => assert_eq!(
+                config,
+                MemoryHotplugConfig {
+                    total_size_mib: 1024,
+                    block_size_mib: 2,
+                }
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "total_size_mib": 1024,
+                "block_size_mib": 128
+              }"#;
+        match vmm_action_from_request(parse_put_memory_hotplug(&Body::new(body)).unwrap()) {
+            VmmAction::SetMemoryHotplug(config) => assert_eq!(config.block_size_mib, 128),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_parse_patch_memory_hotplug_request() {
+        assert!(parse_patch_memory_hotplug(&Body::new("invalid_payload")).is_err());
+
+        // PATCH trying to update the total size.
+        let body = r#"{
+                "total_size_mib": 2048
+              }"#;
+        assert!(parse_patch_memory_hotplug(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "requested_size_mib": 512
+              }"#;
+        match vmm_action_from_request(parse_patch_memory_hotplug(&Body::new(body)).unwrap()) {
+            VmmAction::UpdateMemoryHotplug(size_update) => {
+                assert_eq!(size_update.requested_size_mib, 512)
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+}
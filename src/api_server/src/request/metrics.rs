@@ -2,17 +2,22 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::VmmAction;
-use crate::parsed_request::{Error, ParsedRequest};
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
 use crate::request::Body;
 use logger::{IncMetric, METRICS};
 use vmm::vmm_config::metrics::MetricsConfig;
 
+pub(crate) fn parse_get_metrics() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.metrics_count.inc();
+    Ok(ParsedRequest::GetMetrics)
+}
+
 pub(crate) fn parse_put_metrics(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.metrics_count.inc();
     Ok(ParsedRequest::new_sync(VmmAction::ConfigureMetrics(
-        serde_json::from_slice::<MetricsConfig>(body.raw()).map_err(|e| {
+        parse_body::<MetricsConfig>(body).map_err(|e| {
             METRICS.put_api_requests.metrics_fails.inc();
-            Error::SerdeJson(e)
+            e
         })?,
     )))
 }
@@ -23,6 +28,15 @@ mod tests {
 
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::metrics::MetricsSinkConfig;
+
+    #[test]
+    fn test_parse_get_metrics_request() {
+        match parse_get_metrics() {
+            Ok(ParsedRequest::GetMetrics) => {}
+            _ => panic!("Test failed."),
+        }
+    }
 
     #[test]
     fn test_parse_put_metrics_request() {
@@ -32,12 +46,44 @@ mod tests {
 
         let expected_cfg = MetricsConfig {
             metrics_path: PathBuf::from("metrics"),
+            line_protocol: None,
+            statsd: None,
+            flush_interval_ms: None,
         };
         match vmm_action_from_request(parse_put_metrics(&Body::new(body)).unwrap()) {
             VmmAction::ConfigureMetrics(cfg) => assert_eq!(cfg, expected_cfg),
             _ => panic!("Test failed."),
         }
 
+        let body = r#"{
+                "metrics_path": "metrics",
+                "statsd": { "address": "127.0.0.1:8125" },
+                "line_protocol": { "address": "[::1]:8089", "enabled": false },
+                "flush_interval_ms": 0
+              }"#;
+        let expected_cfg = MetricsConfig {
+            metrics_path: PathBuf::from("metrics"),
+            line_protocol: Some(MetricsSinkConfig {
+                address: "[::1]:8089".parse().unwrap(),
+                enabled: false,
+            }),
+            statsd: Some(MetricsSinkConfig {
+                address: "127.0.0.1:8125".parse().unwrap(),
+                enabled: true,
+            }),
+            flush_interval_ms: Some(0),
+        };
+        match vmm_action_from_request(parse_put_metrics(&Body::new(body)).unwrap()) {
+            VmmAction::ConfigureMetrics(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        let invalid_body = r#"{
+                "metrics_path": "metrics",
+                "statsd": { "address": "localhost:8125" }
+              }"#;
+        assert!(parse_put_metrics(&Body::new(invalid_body)).is_err());
+
         let invalid_body = r#"{
                 "invalid_field": "metrics"
               }"#;
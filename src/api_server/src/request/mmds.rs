@@ -1,14 +1,22 @@
 // Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::parsed_request::{Error, ParsedRequest};
+use crate::fault::ErrorCode;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
 use crate::request::Body;
-use micro_http::StatusCode;
+use micro_http::MediaType;
 use vmm::rpc_interface::VmmAction::SetMmdsConfiguration;
 use vmm::vmm_config::mmds::MmdsConfig;
 
-pub(crate) fn parse_get_mmds() -> Result<ParsedRequest, Error> {
-    Ok(ParsedRequest::GetMMDS)
+pub(crate) fn parse_get_mmds(path_second_token: Option<&&str>) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"status") => Ok(ParsedRequest::GetMMDSStatus),
+        Some(&unrecognized) => Err(Error::Generic(
+            ErrorCode::InvalidRequest,
+            format!("Unrecognized GET request path `{}`.", unrecognized),
+        )),
+        None => Ok(ParsedRequest::GetMMDS),
+    }
 }
 
 pub(crate) fn parse_put_mmds(
@@ -17,24 +25,28 @@ pub(crate) fn parse_put_mmds(
 ) -> Result<ParsedRequest, Error> {
     match path_second_token {
         Some(config_path) => match *config_path {
-            "config" => Ok(ParsedRequest::new_sync(SetMmdsConfiguration(
-                serde_json::from_slice::<MmdsConfig>(body.raw()).map_err(Error::SerdeJson)?,
-            ))),
+            "config" => Ok(ParsedRequest::new_sync(SetMmdsConfiguration(parse_body::<
+                MmdsConfig,
+            >(
+                body
+            )?))),
             _ => Err(Error::Generic(
-                StatusCode::BadRequest,
+                ErrorCode::InvalidRequest,
                 format!("Unrecognized PUT request path `{}`.", *config_path),
             )),
         },
-        None => Ok(ParsedRequest::PutMMDS(
-            serde_json::from_slice(body.raw()).map_err(Error::SerdeJson)?,
-        )),
+        None => Ok(ParsedRequest::PutMMDS(parse_body(body)?)),
     }
 }
 
-pub(crate) fn parse_patch_mmds(body: &Body) -> Result<ParsedRequest, Error> {
-    Ok(ParsedRequest::PatchMMDS(
-        serde_json::from_slice(body.raw()).map_err(Error::SerdeJson)?,
-    ))
+pub(crate) fn parse_patch_mmds(
+    body: &Body,
+    content_type: MediaType,
+) -> Result<ParsedRequest, Error> {
+    match content_type {
+        MediaType::ApplicationJsonPatch => Ok(ParsedRequest::JsonPatchMMDS(parse_body(body)?)),
+        _ => Ok(ParsedRequest::PatchMMDS(parse_body(body)?)),
+    }
 }
 
 #[cfg(test)]
@@ -43,7 +55,9 @@ mod tests {
 
     #[test]
     fn test_parse_get_mmds_request() {
-        assert!(parse_get_mmds().is_ok());
+        assert!(parse_get_mmds(None).is_ok());
+        assert!(parse_get_mmds(Some(&"status")).is_ok());
+        assert!(parse_get_mmds(Some(&"config")).is_err());
     }
 
     #[test]
@@ -66,6 +80,38 @@ mod tests {
               }"#;
         assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_err());
 
+        let body = r#"{
+                "ipv6_address": "fd00:ec2::254"
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_ok());
+
+        let body = r#"{
+                "ipv6_address": "169.254.170.2"
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_err());
+
+        let body = r#"{
+                "version": "V2",
+                "hop_limit": 1
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_ok());
+
+        let body = r#"{
+                "ipv4_address": "169.254.170.2",
+                "network_interfaces": ["eth0", "eth1"]
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_ok());
+
+        let body = r#"{
+                "network_interfaces": "eth0"
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_err());
+
+        let body = r#"{
+                "version": "V3"
+              }"#;
+        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_err());
+
         // Equivalent to reset the mmds configuration.
         let empty_body = r#"{}"#;
         assert!(parse_put_mmds(&Body::new(empty_body), Some(&path)).is_ok());
@@ -83,7 +129,19 @@ mod tests {
         let body = r#"{
                 "foo": "bar"
               }"#;
-        assert!(parse_patch_mmds(&Body::new(body)).is_ok());
-        assert!(parse_patch_mmds(&Body::new("invalid_body")).is_err());
+        assert!(parse_patch_mmds(&Body::new(body), MediaType::ApplicationJson).is_ok());
+        assert!(parse_patch_mmds(&Body::new("invalid_body"), MediaType::ApplicationJson).is_err());
+
+        let body = r#"[
+                { "op": "add", "path": "/foo", "value": "bar" },
+                { "op": "test", "path": "/foo", "value": "bar" }
+              ]"#;
+        assert!(parse_patch_mmds(&Body::new(body), MediaType::ApplicationJsonPatch).is_ok());
+        let body = r#"{
+                "foo": "bar"
+              }"#;
+        assert!(parse_patch_mmds(&Body::new(body), MediaType::ApplicationJsonPatch).is_err());
+        let body = r#"[{ "op": "merge", "path": "/foo" }]"#;
+        assert!(parse_patch_mmds(&Body::new(body), MediaType::ApplicationJsonPatch).is_err());
     }
 }
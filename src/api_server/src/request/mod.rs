@@ -2,17 +2,51 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod actions;
+#[cfg(feature = "vsock")]
+pub mod agent;
+#[cfg(feature = "balloon")]
 pub mod balloon;
 pub mod boot_source;
+#[cfg(feature = "virtio-console")]
+pub mod console;
+pub mod cpu_quota;
+#[cfg(feature = "block")]
 pub mod drive;
+#[cfg(feature = "virtio-rng")]
+pub mod entropy;
+pub mod events;
 pub mod instance_info;
+pub mod jobs;
 pub mod logger;
 pub mod machine_configuration;
+pub mod machine_stats;
+#[cfg(feature = "virtio-mem")]
+pub mod memory_hotplug;
 pub mod metrics;
 pub mod mmds;
+#[cfg(feature = "net")]
 pub mod net;
+#[cfg(feature = "null-devices")]
+pub mod null_device;
+#[cfg(feature = "virtio-pmem")]
+pub mod pmem;
+pub mod rate_limiter_group;
+pub mod serial;
+#[cfg(feature = "virtio-fs")]
+pub mod shared_fs;
+#[cfg(target_arch = "x86_64")]
+pub mod shutdown_behavior;
 pub mod snapshot;
+pub mod timings;
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+pub mod tpm;
+pub mod vcpus;
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+pub mod vfio;
+#[cfg(feature = "vsock")]
 pub mod vsock;
+#[cfg(target_arch = "x86_64")]
+pub mod watchdog;
 pub use micro_http::{
     Body, HttpServer, Method, Request, RequestError, Response, StatusCode, Version,
 };
@@ -2,11 +2,40 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::VmmAction;
-use crate::parsed_request::{checked_id, Error, ParsedRequest};
-use crate::request::{Body, StatusCode};
+use crate::fault::ErrorCode;
+use crate::parsed_request::{check_id_from_body, checked_id, parse_body, Error, ParsedRequest};
+use crate::request::{Body, Method};
 use logger::{IncMetric, METRICS};
 use vmm::vmm_config::net::{NetworkInterfaceConfig, NetworkInterfaceUpdateConfig};
 
+pub(crate) fn parse_get_net(
+    id_from_path: Option<&&str>,
+    path_third_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    match path_third_token {
+        Some(&"rate-limiter") => Ok(ParsedRequest::new_sync(
+            VmmAction::GetNetworkInterfaceRateLimiters(id.to_string()),
+        )),
+        Some(&"stats") => Ok(ParsedRequest::new_sync(
+            VmmAction::GetNetworkInterfaceStats(id.to_string()),
+        )),
+        Some(unknown_path) => Err(Error::Generic(
+            ErrorCode::InvalidRequest,
+            format!("Unrecognized GET request path `{}`.", unknown_path),
+        )),
+        None => Err(Error::InvalidPathMethod(
+            format!("/network-interfaces/{}", id),
+            Method::Get,
+        )),
+    }
+}
+
 pub(crate) fn parse_put_net(
     body: &Body,
     id_from_path: Option<&&str>,
@@ -19,17 +48,14 @@ pub(crate) fn parse_put_net(
         return Err(Error::EmptyID);
     };
 
-    let netif = serde_json::from_slice::<NetworkInterfaceConfig>(body.raw()).map_err(|e| {
+    let netif = parse_body::<NetworkInterfaceConfig>(body).map_err(|e| {
         METRICS.put_api_requests.network_fails.inc();
-        Error::SerdeJson(e)
+        e
     })?;
-    if id != netif.iface_id.as_str() {
+    check_id_from_body("iface_id", id, &netif.iface_id).map_err(|e| {
         METRICS.put_api_requests.network_fails.inc();
-        return Err(Error::Generic(
-            StatusCode::BadRequest,
-            "The id from the path does not match the id from the body!".to_string(),
-        ));
-    }
+        e
+    })?;
     Ok(ParsedRequest::new_sync(VmmAction::InsertNetworkDevice(
         netif,
     )))
@@ -47,18 +73,14 @@ pub(crate) fn parse_patch_net(
         return Err(Error::EmptyID);
     };
 
-    let netif =
-        serde_json::from_slice::<NetworkInterfaceUpdateConfig>(body.raw()).map_err(|e| {
-            METRICS.patch_api_requests.network_fails.inc();
-            Error::SerdeJson(e)
-        })?;
-    if id != netif.iface_id {
+    let netif = parse_body::<NetworkInterfaceUpdateConfig>(body).map_err(|e| {
+        METRICS.patch_api_requests.network_fails.inc();
+        e
+    })?;
+    check_id_from_body("iface_id", id, &netif.iface_id).map_err(|e| {
         METRICS.patch_api_requests.network_count.inc();
-        return Err(Error::Generic(
-            StatusCode::BadRequest,
-            "The id from the path does not match the id from the body!".to_string(),
-        ));
-    }
+        e
+    })?;
     Ok(ParsedRequest::new_sync(VmmAction::UpdateNetworkInterface(
         netif,
     )))
@@ -69,6 +91,27 @@ mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_net_request() {
+        // 1. The `id_from_path` cannot be None.
+        assert!(parse_get_net(None, Some(&"stats")).is_err());
+        // 2. Only the statistics and rate limiter sub-resources can be retrieved.
+        assert!(parse_get_net(Some(&"foo"), None).is_err());
+        assert!(parse_get_net(Some(&"foo"), Some(&"config")).is_err());
+        // 3. Invalid ID.
+        assert!(parse_get_net(Some(&"foo!"), Some(&"stats")).is_err());
+
+        // 4. Success case.
+        match vmm_action_from_request(parse_get_net(Some(&"foo"), Some(&"stats")).unwrap()) {
+            VmmAction::GetNetworkInterfaceStats(iface_id) => assert_eq!(iface_id, "foo"),
+            _ => panic!("Test failed."),
+        }
+        match vmm_action_from_request(parse_get_net(Some(&"foo"), Some(&"rate-limiter")).unwrap()) {
+            VmmAction::GetNetworkInterfaceRateLimiters(iface_id) => assert_eq!(iface_id, "foo"),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_parse_put_net_request() {
         let body = r#"{
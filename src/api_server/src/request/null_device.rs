@@ -0,0 +1,59 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{check_id_from_body, checked_id, parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::null_device::NullDeviceConfig;
+
+pub(crate) fn parse_put_null_device(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    let config = parse_body::<NullDeviceConfig>(body)?;
+    check_id_from_body("device_id", id, &config.device_id)?;
+    Ok(ParsedRequest::new_sync(VmmAction::InsertNullDevice(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::null_device::NullDeviceType;
+
+    #[test]
+    fn test_parse_put_null_device_request() {
+        let body = r#"{
+                "device_id": "snd0",
+                "device_type": "Sound"
+              }"#;
+        // 1. The id from the path must match the id from the body.
+        assert!(parse_put_null_device(&Body::new(body), Some(&"snd1")).is_err());
+        // 2. The `id_from_path` cannot be None.
+        assert!(parse_put_null_device(&Body::new(body), None).is_err());
+
+        // 3. Success case.
+        match vmm_action_from_request(
+            parse_put_null_device(&Body::new(body), Some(&"snd0")).unwrap(),
+        ) {
+            VmmAction::InsertNullDevice(config) => {
+                assert_eq!(config.device_id, "snd0");
+                assert_eq!(config.device_type, NullDeviceType::Sound);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 4. Only the stubbed device classes are accepted.
+        let body = r#"{
+                "device_id": "snd0",
+                "device_type": "Block"
+              }"#;
+        assert!(parse_put_null_device(&Body::new(body), Some(&"snd0")).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{check_id_from_body, checked_id, parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::pmem::PmemConfig;
+
+pub(crate) fn parse_put_pmem(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    let config = parse_body::<PmemConfig>(body)?;
+    check_id_from_body("pmem_id", id, &config.pmem_id)?;
+    Ok(ParsedRequest::new_sync(VmmAction::InsertPmemDevice(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_pmem_request() {
+        let body = r#"{
+                "pmem_id": "pmem0",
+                "path_on_host": "/tmp/rootfs.img"
+              }"#;
+        // 1. The id from the path must match the id from the body.
+        assert!(parse_put_pmem(&Body::new(body), Some(&"pmem1")).is_err());
+        // 2. The `id_from_path` cannot be None.
+        assert!(parse_put_pmem(&Body::new(body), None).is_err());
+
+        // 3. Success case, the device is writable by default.
+        match vmm_action_from_request(parse_put_pmem(&Body::new(body), Some(&"pmem0")).unwrap()) {
+            VmmAction::InsertPmemDevice(config) => {
+                assert_eq!(config.pmem_id, "pmem0");
+                assert_eq!(config.path_on_host, "/tmp/rootfs.img");
+                assert!(!config.is_read_only);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 4. Unknown fields are rejected.
+        let body = r#"{
+                "pmem_id": "pmem0",
+                "path_on_host": "/tmp/rootfs.img",
+                "is_root_device": true
+              }"#;
+        assert!(parse_put_pmem(&Body::new(body), Some(&"pmem0")).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{check_id_from_body, checked_id, parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::rate_limiter_group::RateLimiterGroupConfig;
+
+pub(crate) fn parse_put_rate_limiter_group(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    let config = parse_body::<RateLimiterGroupConfig>(body)?;
+    check_id_from_body("group_id", id, &config.group_id)?;
+    Ok(ParsedRequest::new_sync(VmmAction::SetRateLimiterGroup(
+        config,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_rate_limiter_group_request() {
+        let body = r#"{
+                "group_id": "disks",
+                "bandwidth": {
+                    "size": 104857600,
+                    "refill_time": 1000
+                }
+              }"#;
+        // 1. The id from the path must match the id from the body.
+        assert!(parse_put_rate_limiter_group(&Body::new(body), Some(&"net")).is_err());
+        // 2. The `id_from_path` cannot be None.
+        assert!(parse_put_rate_limiter_group(&Body::new(body), None).is_err());
+
+        // 3. Success case.
+        match vmm_action_from_request(
+            parse_put_rate_limiter_group(&Body::new(body), Some(&"disks")).unwrap(),
+        ) {
+            VmmAction::SetRateLimiterGroup(config) => {
+                assert_eq!(config.group_id, "disks");
+                assert_eq!(config.bandwidth.unwrap().size, 104_857_600);
+                assert!(config.ops.is_none());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 4. Unknown fields are rejected.
+        let body = r#"{
+                "group_id": "disks",
+                "drives": ["rootfs"]
+              }"#;
+        assert!(parse_put_rate_limiter_group(&Body::new(body), Some(&"disks")).is_err());
+    }
+}
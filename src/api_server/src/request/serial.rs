@@ -0,0 +1,60 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{check_id_from_body, checked_id, parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::serial::SerialPortConfig;
+
+pub(crate) fn parse_put_serial_port(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    let config = parse_body::<SerialPortConfig>(body)?;
+    check_id_from_body("port_id", id, &config.port_id)?;
+    Ok(ParsedRequest::new_sync(VmmAction::InsertSerialPort(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_serial_port_request() {
+        let body = r#"{
+                "port_id": "com2",
+                "uds_path": "/tmp/com2.sock"
+              }"#;
+        // 1. The id from the path must match the id from the body.
+        assert!(parse_put_serial_port(&Body::new(body), Some(&"com3")).is_err());
+        // 2. The `id_from_path` cannot be None.
+        assert!(parse_put_serial_port(&Body::new(body), None).is_err());
+
+        // 3. Success case.
+        match vmm_action_from_request(
+            parse_put_serial_port(&Body::new(body), Some(&"com2")).unwrap(),
+        ) {
+            VmmAction::InsertSerialPort(config) => {
+                assert_eq!(config.port_id, "com2");
+                assert_eq!(config.uds_path, Some("/tmp/com2.sock".to_string()));
+                assert!(config.file_path.is_none());
+                assert!(config.pipe_path.is_none());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 4. Unknown fields are rejected.
+        let body = r#"{
+                "port_id": "com2",
+                "path_on_host": "/tmp/com2.log"
+              }"#;
+        assert!(parse_put_serial_port(&Body::new(body), Some(&"com2")).is_err());
+    }
+}
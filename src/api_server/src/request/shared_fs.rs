@@ -0,0 +1,63 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{check_id_from_body, checked_id, parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::shared_fs::SharedFsConfig;
+
+pub(crate) fn parse_put_shared_fs(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    let config = parse_body::<SharedFsConfig>(body)?;
+    check_id_from_body("fs_id", id, &config.fs_id)?;
+    Ok(ParsedRequest::new_sync(VmmAction::InsertSharedFs(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::shared_fs::CacheMode;
+
+    #[test]
+    fn test_parse_put_shared_fs_request() {
+        let body = r#"{
+                "fs_id": "fs0",
+                "tag": "myfs",
+                "socket_path": "/tmp/fs.sock"
+              }"#;
+        // 1. The id from the path must match the id from the body.
+        assert!(parse_put_shared_fs(&Body::new(body), Some(&"fs1")).is_err());
+        // 2. The `id_from_path` cannot be None.
+        assert!(parse_put_shared_fs(&Body::new(body), None).is_err());
+
+        // 3. Success case, the cache mode defaults to `Auto`.
+        match vmm_action_from_request(parse_put_shared_fs(&Body::new(body), Some(&"fs0")).unwrap())
+        {
+            VmmAction::InsertSharedFs(config) => {
+                assert_eq!(config.fs_id, "fs0");
+                assert_eq!(config.tag, "myfs");
+                assert_eq!(config.socket_path, "/tmp/fs.sock");
+                assert_eq!(config.cache_mode, CacheMode::Auto);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 4. Unknown cache modes are rejected.
+        let body = r#"{
+                "fs_id": "fs0",
+                "tag": "myfs",
+                "socket_path": "/tmp/fs.sock",
+                "cache_mode": "Sometimes"
+              }"#;
+        assert!(parse_put_shared_fs(&Body::new(body), Some(&"fs0")).is_err());
+    }
+}
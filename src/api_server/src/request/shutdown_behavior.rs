@@ -0,0 +1,61 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::shutdown_behavior::ShutdownBehaviorConfig;
+
+pub(crate) fn parse_put_shutdown_behavior(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetShutdownBehavior(
+        parse_body::<ShutdownBehaviorConfig>(body)?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_shutdown_behavior_request() {
+        assert!(parse_put_shutdown_behavior(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "timeout_ms": 10
+              }"#;
+        assert!(parse_put_shutdown_behavior(&Body::new(body)).is_err());
+
+        // PUT with a snapshot missing its memory file.
+        let body = r#"{
+                "snapshot": {
+                    "snapshot_path": "/vm.snap"
+                }
+              }"#;
+        assert!(parse_put_shutdown_behavior(&Body::new(body)).is_err());
+
+        // PUT without a snapshot.
+        match vmm_action_from_request(parse_put_shutdown_behavior(&Body::new("{}")).unwrap()) {
+            VmmAction::SetShutdownBehavior(config) => assert!(config.snapshot.is_none()),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "snapshot": {
+                    "snapshot_path": "/vm.snap",
+                    "mem_file_path": "/vm.mem"
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_shutdown_behavior(&Body::new(body)).unwrap()) {
+            VmmAction::SetShutdownBehavior(config) => {
+                let snapshot = config.snapshot.unwrap();
+                assert_eq!(snapshot.snapshot_path, PathBuf::from("/vm.snap"));
+                assert_eq!(snapshot.mem_file_path, PathBuf::from("/vm.mem"));
+            }
+            _ => panic!("Test failed."),
+        }
+    }
+}
@@ -2,12 +2,13 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::VmmAction;
-use crate::parsed_request::{Error, ParsedRequest};
-use crate::request::Body;
+use crate::fault::ErrorCode;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::{Body, Method};
+use logger::{IncMetric, METRICS};
+use vmm::resources::VmmConfig;
 #[cfg(target_arch = "x86_64")]
-use crate::request::{Method, StatusCode};
-#[cfg(target_arch = "x86_64")]
-use vmm::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams};
+use vmm::vmm_config::snapshot::{CloneSnapshotParams, CreateSnapshotParams, LoadSnapshotParams};
 use vmm::vmm_config::snapshot::{Vm, VmState};
 
 #[cfg(target_arch = "x86_64")]
@@ -18,12 +19,11 @@ pub(crate) fn parse_put_snapshot(
     match request_type_from_path {
         Some(&request_type) => match request_type {
             "create" => Ok(ParsedRequest::new_sync(VmmAction::CreateSnapshot(
-                serde_json::from_slice::<CreateSnapshotParams>(body.raw())
-                    .map_err(Error::SerdeJson)?,
+                parse_body::<CreateSnapshotParams>(body)?,
             ))),
-            "load" => Ok(ParsedRequest::new_sync(VmmAction::LoadSnapshot(
-                serde_json::from_slice::<LoadSnapshotParams>(body.raw())
-                    .map_err(Error::SerdeJson)?,
+            "load" => parse_put_snapshot_load(body),
+            "clone" => Ok(ParsedRequest::new_sync(VmmAction::LoadSnapshot(
+                LoadSnapshotParams::from(parse_body::<CloneSnapshotParams>(body)?),
             ))),
             _ => Err(Error::InvalidPathMethod(
                 format!("/snapshot/{}", request_type),
@@ -31,26 +31,128 @@ pub(crate) fn parse_put_snapshot(
             )),
         },
         None => Err(Error::Generic(
-            StatusCode::BadRequest,
+            ErrorCode::InvalidRequest,
             "Missing snapshot operation type.".to_string(),
         )),
     }
 }
 
+#[cfg(target_arch = "x86_64")]
+fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
+    let load_params = parse_body::<LoadSnapshotParams>(body)?;
+
+    #[cfg(feature = "balloon")]
+    if load_params.deflate_balloon && load_params.balloon_amount_mib.is_some() {
+        return Err(Error::Generic(
+            ErrorCode::InvalidValue,
+            "At most one of `balloon_amount_mib` and `deflate_balloon` can be specified."
+                .to_string(),
+        ));
+    }
+
+    Ok(ParsedRequest::new_sync(VmmAction::LoadSnapshot(
+        load_params,
+    )))
+}
+
+pub(crate) fn parse_get_vm_config(
+    path_second_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"config") => {
+            METRICS.get_api_requests.vm_config_count.inc();
+            Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig))
+        }
+        Some(&token) => Err(Error::InvalidPathMethod(
+            format!("/vm/{}", token),
+            Method::Get,
+        )),
+        None => Err(Error::InvalidPathMethod("/vm".to_string(), Method::Get)),
+    }
+}
+
+pub(crate) fn parse_put_vm_config(
+    body: &Body,
+    path_second_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"config") => {
+            METRICS.put_api_requests.vm_config_count.inc();
+            let vmm_config = parse_body::<VmmConfig>(body).map_err(|e| {
+                METRICS.put_api_requests.vm_config_fails.inc();
+                e
+            })?;
+            Ok(ParsedRequest::new_sync(VmmAction::SetFullVmConfig(
+                Box::new(vmm_config),
+            )))
+        }
+        Some(&token) => Err(Error::InvalidPathMethod(
+            format!("/vm/{}", token),
+            Method::Put,
+        )),
+        None => Err(Error::InvalidPathMethod("/vm".to_string(), Method::Put)),
+    }
+}
+
+pub(crate) fn parse_post_vm(
+    body: &Body,
+    path_second_token: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"validate") => {
+            METRICS.post_api_requests.vm_validate_count.inc();
+            let vmm_config = parse_body::<VmmConfig>(body).map_err(|e| {
+                METRICS.post_api_requests.vm_validate_fails.inc();
+                e
+            })?;
+            Ok(ParsedRequest::new_sync(VmmAction::ValidateVmConfig(
+                Box::new(vmm_config),
+            )))
+        }
+        Some(&token) => Err(Error::InvalidPathMethod(
+            format!("/vm/{}", token),
+            Method::Post,
+        )),
+        None => Err(Error::InvalidPathMethod("/vm".to_string(), Method::Post)),
+    }
+}
+
 pub(crate) fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, Error> {
-    let vm = serde_json::from_slice::<Vm>(body.raw()).map_err(Error::SerdeJson)?;
+    let vm = parse_body::<Vm>(body)?;
 
-    match vm.state {
-        VmState::Paused => Ok(ParsedRequest::new_sync(VmmAction::Pause)),
-        VmState::Resumed => Ok(ParsedRequest::new_sync(VmmAction::Resume)),
+    #[cfg(target_arch = "x86_64")]
+    if let Some(clock_policy) = vm.clock_policy {
+        if vm.state.is_none() && vm.panic_action.is_none() {
+            return Ok(ParsedRequest::new_sync(VmmAction::SetClockPolicy(
+                clock_policy,
+            )));
+        }
+        return Err(Error::Generic(
+            ErrorCode::InvalidValue,
+            "Exactly one of `state`, `panic_action` and `clock_policy` must be specified."
+                .to_string(),
+        ));
+    }
+
+    match (vm.state, vm.panic_action) {
+        (Some(VmState::Paused), None) => Ok(ParsedRequest::new_sync(VmmAction::Pause)),
+        (Some(VmState::Resumed), None) => Ok(ParsedRequest::new_sync(VmmAction::Resume)),
+        (None, Some(panic_action)) => Ok(ParsedRequest::new_sync(VmmAction::SetPanicAction(
+            panic_action,
+        ))),
+        _ => Err(Error::Generic(
+            ErrorCode::InvalidValue,
+            "Exactly one of `state` and `panic_action` must be specified.".to_string(),
+        )),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    #[cfg(target_arch = "x86_64")]
     use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::guest_panic::PanicAction;
+    use vmm::vmm_config::machine_config::{MemoryBackend, MemoryHints};
 
     #[test]
     #[cfg(target_arch = "x86_64")]
@@ -69,6 +171,9 @@ mod tests {
             snapshot_type: SnapshotType::Diff,
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            single_file: false,
+            dedup_pages: false,
+            compact_diff: false,
             version: Some(String::from("0.23.0")),
         };
 
@@ -88,6 +193,9 @@ mod tests {
             snapshot_type: SnapshotType::Full,
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            single_file: false,
+            dedup_pages: false,
+            compact_diff: false,
             version: None,
         };
 
@@ -98,6 +206,65 @@ mod tests {
             _ => panic!("Test failed."),
         }
 
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "dedup_pages": true
+              }"#;
+        expected_cfg.dedup_pages = true;
+
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create")).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => assert_eq!(cfg, expected_cfg),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_type": "Diff",
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "compact_diff": true
+              }"#;
+
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create")).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => {
+                assert_eq!(cfg.snapshot_type, SnapshotType::Diff);
+                assert!(cfg.compact_diff);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "single_file": true
+              }"#;
+
+        match vmm_action_from_request(
+            parse_put_snapshot(&Body::new(body), Some(&"create")).unwrap(),
+        ) {
+            VmmAction::CreateSnapshot(cfg) => {
+                assert!(cfg.single_file);
+                assert_eq!(cfg.mem_file_path, PathBuf::new());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo"
+              }"#;
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => {
+                assert_eq!(cfg.snapshot_path, PathBuf::from("foo"));
+                assert_eq!(cfg.mem_file_path, PathBuf::new());
+            }
+            _ => panic!("Test failed."),
+        }
+
         let invalid_body = r#"{
                 "invalid_field": "foo",
                 "mem_file_path": "bar"
@@ -113,8 +280,18 @@ mod tests {
         let mut expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            mem_diff_paths: Vec::new(),
             enable_diff_snapshots: false,
             resume_vm: false,
+            #[cfg(feature = "balloon")]
+            balloon_amount_mib: None,
+            #[cfg(feature = "balloon")]
+            deflate_balloon: false,
+            mem_backend: None,
+            mem_hints: None,
+            io_threads: None,
+            mmds_data: None,
+            clone: None,
         };
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
         {
@@ -131,8 +308,18 @@ mod tests {
         expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            mem_diff_paths: Vec::new(),
             enable_diff_snapshots: true,
             resume_vm: false,
+            #[cfg(feature = "balloon")]
+            balloon_amount_mib: None,
+            #[cfg(feature = "balloon")]
+            deflate_balloon: false,
+            mem_backend: None,
+            mem_hints: None,
+            io_threads: None,
+            mmds_data: None,
+            clone: None,
         };
 
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
@@ -150,8 +337,18 @@ mod tests {
         expected_cfg = LoadSnapshotParams {
             snapshot_path: PathBuf::from("foo"),
             mem_file_path: PathBuf::from("bar"),
+            mem_diff_paths: Vec::new(),
             enable_diff_snapshots: false,
             resume_vm: true,
+            #[cfg(feature = "balloon")]
+            balloon_amount_mib: None,
+            #[cfg(feature = "balloon")]
+            deflate_balloon: false,
+            mem_backend: None,
+            mem_hints: None,
+            io_threads: None,
+            mmds_data: None,
+            clone: None,
         };
 
         match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
@@ -160,10 +357,253 @@ mod tests {
             _ => panic!("Test failed."),
         }
 
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mem_diff_paths": ["baz", "qux"]
+              }"#;
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(
+                cfg.mem_diff_paths,
+                vec![PathBuf::from("baz"), PathBuf::from("qux")]
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mem_backend": "hugetlbfs_1g"
+              }"#;
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => {
+                assert_eq!(cfg.mem_backend, Some(MemoryBackend::Hugetlbfs1G))
+            }
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mem_hints": {
+                    "mergeable": true,
+                    "huge_pages": true
+                }
+              }"#;
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(
+                cfg.mem_hints,
+                Some(MemoryHints {
+                    mergeable: true,
+                    huge_pages: Some(true),
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "io_threads": 2
+              }"#;
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => assert_eq!(cfg.io_threads, Some(2)),
+            _ => panic!("Test failed."),
+        }
+
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mmds_data": { "hostname": "clone-1", "seed": 42 }
+              }"#;
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => {
+                let data = cfg.mmds_data.unwrap();
+                assert_eq!(data["hostname"], "clone-1");
+                assert_eq!(data["seed"], 42);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // The data must be key/value pairs.
+        body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mmds_data": "hostname=clone-1"
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"load")).is_err());
+
+        #[cfg(feature = "balloon")]
+        {
+            body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "balloon_amount_mib": 64
+              }"#;
+
+            match vmm_action_from_request(
+                parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap(),
+            ) {
+                VmmAction::LoadSnapshot(cfg) => {
+                    assert_eq!(cfg.balloon_amount_mib, Some(64));
+                    assert_eq!(cfg.balloon_target_mib(), Some(64));
+                }
+                _ => panic!("Test failed."),
+            }
+
+            body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "deflate_balloon": true
+              }"#;
+
+            match vmm_action_from_request(
+                parse_put_snapshot(&Body::new(body), Some(&"load")).unwrap(),
+            ) {
+                VmmAction::LoadSnapshot(cfg) => {
+                    assert!(cfg.deflate_balloon);
+                    assert_eq!(cfg.balloon_target_mib(), Some(0));
+                }
+                _ => panic!("Test failed."),
+            }
+
+            let conflicting_body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "balloon_amount_mib": 64,
+                "deflate_balloon": true
+              }"#;
+            assert!(parse_put_snapshot(&Body::new(conflicting_body), Some(&"load")).is_err());
+        }
+
         assert!(parse_put_snapshot(&Body::new(body), Some(&"invalid")).is_err());
         assert!(parse_put_snapshot(&Body::new(body), None).is_err());
     }
 
+    #[test]
+    fn test_parse_put_snapshot_clone() {
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "resume_vm": true,
+                "drives": [
+                    { "drive_id": "rootfs", "path_on_host": "/clones/1/rootfs" }
+                ],
+                "network_interfaces": [
+                    {
+                        "iface_id": "eth0",
+                        "host_dev_name": "tap1",
+                        "guest_mac": "06:00:00:00:00:01"
+                    }
+                ],
+                "vsock": { "guest_cid": 4, "uds_path": "/clones/1/vsock.sock" },
+                "mmds_data": { "hostname": "clone-1" }
+              }"#;
+
+        match vmm_action_from_request(parse_put_snapshot(&Body::new(body), Some(&"clone")).unwrap())
+        {
+            VmmAction::LoadSnapshot(cfg) => {
+                assert!(cfg.resume_vm);
+                assert_eq!(cfg.mem_backend, None);
+                assert_eq!(cfg.mmds_data.unwrap()["hostname"], "clone-1");
+                let clone = cfg.clone.unwrap();
+                assert_eq!(clone.drives[0].drive_id, "rootfs");
+                assert_eq!(clone.drives[0].path_on_host, "/clones/1/rootfs");
+                assert_eq!(clone.network_interfaces[0].host_dev_name, "tap1");
+                assert_eq!(
+                    clone.network_interfaces[0].guest_mac.unwrap().to_string(),
+                    "06:00:00:00:00:01"
+                );
+                assert_eq!(clone.vsock.unwrap().guest_cid, 4);
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // The clones map the memory file of the template, and nothing else.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "mem_backend": "memfd"
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"clone")).is_err());
+
+        // The clone configuration is only accepted by the clone request.
+        let body = r#"{
+                "snapshot_path": "foo",
+                "mem_file_path": "bar",
+                "clone": {}
+              }"#;
+        assert!(parse_put_snapshot(&Body::new(body), Some(&"load")).is_err());
+    }
+
+    #[test]
+    fn test_parse_get_vm_config() {
+        match vmm_action_from_request(parse_get_vm_config(Some(&"config")).unwrap()) {
+            VmmAction::GetFullVmConfig => (),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_vm_config(Some(&"foo")).is_err());
+        assert!(parse_get_vm_config(None).is_err());
+    }
+
+    #[test]
+    fn test_parse_put_vm_config() {
+        let body = r#"{
+                "boot-source": {
+                    "kernel_image_path": "vmlinux.bin"
+                },
+                "drives": [],
+                "machine-config": {
+                    "vcpu_count": 2,
+                    "mem_size_mib": 256
+                },
+                "state": "Not started"
+              }"#;
+        match vmm_action_from_request(
+            parse_put_vm_config(&Body::new(body), Some(&"config")).unwrap(),
+        ) {
+            VmmAction::SetFullVmConfig(vmm_config) => {
+                assert!(*vmm_config == serde_json::from_str::<VmmConfig>(body).unwrap())
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // The boot source is mandatory.
+        assert!(parse_put_vm_config(&Body::new(r#"{"drives": []}"#), Some(&"config")).is_err());
+        assert!(parse_put_vm_config(&Body::new(body), Some(&"foo")).is_err());
+        assert!(parse_put_vm_config(&Body::new(body), None).is_err());
+    }
+
+    #[test]
+    fn test_parse_post_vm() {
+        let body = r#"{
+                "boot-source": {
+                    "kernel_image_path": "vmlinux.bin"
+                },
+                "drives": []
+              }"#;
+        match vmm_action_from_request(parse_post_vm(&Body::new(body), Some(&"validate")).unwrap()) {
+            VmmAction::ValidateVmConfig(vmm_config) => {
+                assert!(*vmm_config == serde_json::from_str::<VmmConfig>(body).unwrap())
+            }
+            _ => panic!("Test failed."),
+        }
+
+        assert!(parse_post_vm(&Body::new(r#"{"drives": []}"#), Some(&"validate")).is_err());
+        assert!(parse_post_vm(&Body::new(body), Some(&"config")).is_err());
+        assert!(parse_post_vm(&Body::new(body), None).is_err());
+    }
+
     #[test]
     fn test_parse_patch_vm_state() {
         let mut body = r#"{
@@ -187,5 +627,55 @@ mod tests {
               }"#;
 
         assert!(parse_patch_vm_state(&Body::new(invalid_body)).is_err());
+
+        body = r#"{
+                "panic_action": {
+                    "action": "Pause"
+                }
+              }"#;
+
+        assert!(parse_patch_vm_state(&Body::new(body))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::SetPanicAction(
+                PanicAction::Pause
+            ))));
+
+        // Changing the state and the panic action at once is not allowed.
+        let invalid_body = r#"{
+                "state": "Paused",
+                "panic_action": {
+                    "action": "None"
+                }
+              }"#;
+
+        assert!(parse_patch_vm_state(&Body::new(invalid_body)).is_err());
+        assert!(parse_patch_vm_state(&Body::new("{}")).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_parse_patch_vm_clock_policy() {
+        use vmm::vmm_config::guest_clock::ClockPolicy;
+
+        let body = r#"{
+                "clock_policy": "Sync"
+              }"#;
+        assert!(parse_patch_vm_state(&Body::new(body))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::SetClockPolicy(
+                ClockPolicy::Sync
+            ))));
+
+        // The clock policy is changed on its own.
+        let invalid_body = r#"{
+                "state": "Resumed",
+                "clock_policy": "Sync"
+              }"#;
+        assert!(parse_patch_vm_state(&Body::new(invalid_body)).is_err());
+
+        let invalid_body = r#"{
+                "clock_policy": "Always"
+              }"#;
+        assert!(parse_patch_vm_state(&Body::new(invalid_body)).is_err());
     }
 }
@@ -0,0 +1,23 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::parsed_request::{Error, ParsedRequest};
+use logger::{IncMetric, METRICS};
+
+pub(crate) fn parse_get_timings() -> Result<ParsedRequest, Error> {
+    METRICS.get_api_requests.timings_count.inc();
+    Ok(ParsedRequest::GetTimings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_get_timings_request() {
+        match parse_get_timings() {
+            Ok(ParsedRequest::GetTimings) => {}
+            _ => panic!("Test failed."),
+        }
+    }
+}
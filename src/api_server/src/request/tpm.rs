@@ -0,0 +1,83 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::tpm::TpmConfig;
+
+pub(crate) fn parse_put_tpm(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetTpm(parse_body::<
+        TpmConfig,
+    >(body)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::tpm::{SoftwareTpmConfig, SwtpmConfig, TpmBackendConfig};
+
+    #[test]
+    fn test_parse_put_tpm_request() {
+        assert!(parse_put_tpm(&Body::new("invalid_payload")).is_err());
+
+        // PUT without a backend.
+        assert!(parse_put_tpm(&Body::new("{}")).is_err());
+
+        // PUT with an unknown backend.
+        let body = r#"{
+                "backend": {
+                    "libtpms": {
+                        "state_path": "/tpm"
+                    }
+                }
+              }"#;
+        assert!(parse_put_tpm(&Body::new(body)).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "backend": {
+                    "software": {
+                        "state_path": "/tpm",
+                        "socket_path": "/swtpm.sock"
+                    }
+                }
+              }"#;
+        assert!(parse_put_tpm(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "backend": {
+                    "software": {
+                        "state_path": "/tpm"
+                    }
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_tpm(&Body::new(body)).unwrap()) {
+            VmmAction::SetTpm(config) => assert_eq!(
+                config.backend,
+                TpmBackendConfig::Software(SoftwareTpmConfig {
+                    state_path: String::from("/tpm"),
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "backend": {
+                    "swtpm": {
+                        "socket_path": "/swtpm.sock"
+                    }
+                }
+              }"#;
+        match vmm_action_from_request(parse_put_tpm(&Body::new(body)).unwrap()) {
+            VmmAction::SetTpm(config) => assert_eq!(
+                config.backend,
+                TpmBackendConfig::Swtpm(SwtpmConfig {
+                    socket_path: String::from("/swtpm.sock"),
+                })
+            ),
+            _ => panic!("Test failed."),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::Method;
+use logger::{IncMetric, METRICS};
+
+pub(crate) fn parse_get_vcpus(path_second_token: Option<&&str>) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"stats") => {
+            METRICS.get_api_requests.vcpu_stats_count.inc();
+            Ok(ParsedRequest::new_sync(VmmAction::GetVcpuStats))
+        }
+        Some(&token) => Err(Error::InvalidPathMethod(
+            format!("/vcpus/{}", token),
+            Method::Get,
+        )),
+        None => Err(Error::InvalidPathMethod("/vcpus".to_string(), Method::Get)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_get_vcpus_request() {
+        match vmm_action_from_request(parse_get_vcpus(Some(&"stats")).unwrap()) {
+            VmmAction::GetVcpuStats => (),
+            _ => panic!("Test failed."),
+        }
+        assert!(parse_get_vcpus(Some(&"config")).is_err());
+        assert!(parse_get_vcpus(None).is_err());
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{check_id_from_body, checked_id, parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::vfio::VfioConfig;
+
+pub(crate) fn parse_put_vfio(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    let config = parse_body::<VfioConfig>(body)?;
+    check_id_from_body("vfio_id", id, &config.vfio_id)?;
+    Ok(ParsedRequest::new_sync(VmmAction::InsertVfioDevice(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+
+    #[test]
+    fn test_parse_put_vfio_request() {
+        let body = r#"{
+                "vfio_id": "nic",
+                "sysfs_path": "/sys/bus/pci/devices/0000:01:00.0"
+              }"#;
+        // 1. The id from the path must match the id from the body.
+        assert!(parse_put_vfio(&Body::new(body), Some(&"gpu")).is_err());
+        // 2. The `id_from_path` cannot be None.
+        assert!(parse_put_vfio(&Body::new(body), None).is_err());
+
+        // 3. Success case.
+        match vmm_action_from_request(parse_put_vfio(&Body::new(body), Some(&"nic")).unwrap()) {
+            VmmAction::InsertVfioDevice(config) => {
+                assert_eq!(config.vfio_id, "nic");
+                assert_eq!(
+                    config.sysfs_path,
+                    PathBuf::from("/sys/bus/pci/devices/0000:01:00.0")
+                );
+            }
+            _ => panic!("Test failed."),
+        }
+
+        // 4. Unknown fields are rejected.
+        let body = r#"{
+                "vfio_id": "nic",
+                "sysfs_path": "/sys/bus/pci/devices/0000:01:00.0",
+                "rom": true
+              }"#;
+        assert!(parse_put_vfio(&Body::new(body), Some(&"nic")).is_err());
+    }
+}
@@ -2,19 +2,50 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::VmmAction;
-use crate::parsed_request::{Error, ParsedRequest};
-use crate::request::Body;
-use vmm::vmm_config::vsock::VsockDeviceConfig;
+use crate::fault::ErrorCode;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::{Body, Method};
+use vmm::vmm_config::vsock::{VsockDeviceConfig, VsockDeviceUpdateConfig};
+
+pub(crate) fn parse_get_vsock(path_second_token: Option<&&str>) -> Result<ParsedRequest, Error> {
+    match path_second_token {
+        Some(&"stats") => Ok(ParsedRequest::new_sync(VmmAction::GetVsockStats)),
+        Some(unknown_path) => Err(Error::Generic(
+            ErrorCode::InvalidRequest,
+            format!("Unrecognized GET request path `{}`.", unknown_path),
+        )),
+        None => Err(Error::InvalidPathMethod("/vsock".to_string(), Method::Get)),
+    }
+}
 
 pub(crate) fn parse_put_vsock(body: &Body) -> Result<ParsedRequest, Error> {
     Ok(ParsedRequest::new_sync(VmmAction::SetVsockDevice(
-        serde_json::from_slice::<VsockDeviceConfig>(body.raw()).map_err(Error::SerdeJson)?,
+        parse_body::<VsockDeviceConfig>(body)?,
+    )))
+}
+
+pub(crate) fn parse_patch_vsock(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::UpdateVsockDevice(
+        parse_body::<VsockDeviceUpdateConfig>(body)?,
     )))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::vsock::VsockBackendType;
+
+    #[test]
+    fn test_parse_get_vsock_request() {
+        assert!(parse_get_vsock(None).is_err());
+        assert!(parse_get_vsock(Some(&"foo")).is_err());
+
+        match vmm_action_from_request(parse_get_vsock(Some(&"stats")).unwrap()) {
+            VmmAction::GetVsockStats => (),
+            _ => panic!("Test failed."),
+        }
+    }
 
     #[test]
     fn test_parse_put_vsock_request() {
@@ -25,6 +56,77 @@ mod tests {
               }"#;
         assert!(parse_put_vsock(&Body::new(body)).is_ok());
 
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "mmds_port": 52
+              }"#;
+        assert!(parse_put_vsock(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "port_mappings": [
+                    {
+                        "name": "ssh",
+                        "guest_port": 22,
+                        "uds_path": "ssh.sock"
+                    }
+                ]
+              }"#;
+        match vmm_action_from_request(parse_put_vsock(&Body::new(body)).unwrap()) {
+            VmmAction::SetVsockDevice(config) => assert_eq!(config.port_mappings.len(), 1),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "port_mappings": [
+                    {
+                        "name": "ssh",
+                        "guest_port": 22
+                    }
+                ]
+              }"#;
+        assert!(parse_put_vsock(&Body::new(body)).is_err());
+
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "uds_path": "vsock.sock",
+                "rx_rate_limiter": {
+                    "bandwidth": {
+                        "size": 1000,
+                        "refill_time": 100
+                    }
+                }
+              }"#;
+        assert!(parse_put_vsock(&Body::new(body)).is_ok());
+
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "backend": "Vhost"
+              }"#;
+        match vmm_action_from_request(parse_put_vsock(&Body::new(body)).unwrap()) {
+            VmmAction::SetVsockDevice(config) => {
+                assert_eq!(config.backend, VsockBackendType::Vhost);
+                assert!(config.uds_path.is_empty());
+            }
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "vsock_id": "foo",
+                "guest_cid": 42,
+                "backend": "Kernel"
+              }"#;
+        assert!(parse_put_vsock(&Body::new(body)).is_err());
+
         let body = r#"{
                 "vsock_id": "foo",
                 "guest_cid": 42,
@@ -32,4 +134,33 @@ mod tests {
               }"#;
         assert!(parse_put_vsock(&Body::new(body)).is_err());
     }
+
+    #[test]
+    fn test_parse_patch_vsock_request() {
+        let body = r#"{
+                "rx_rate_limiter": {
+                    "bandwidth": {
+                        "size": 1000,
+                        "refill_time": 100
+                    }
+                },
+                "tx_rate_limiter": {
+                    "ops": {
+                        "size": 10,
+                        "refill_time": 100
+                    }
+                }
+              }"#;
+        let expected_config = serde_json::from_str::<VsockDeviceUpdateConfig>(body).unwrap();
+        match vmm_action_from_request(parse_patch_vsock(&Body::new(body)).unwrap()) {
+            VmmAction::UpdateVsockDevice(config) => assert_eq!(config, expected_config),
+            _ => panic!("Test failed."),
+        }
+
+        // Only the rate limiters can be updated.
+        let body = r#"{
+                "guest_cid": 42
+              }"#;
+        assert!(parse_patch_vsock(&Body::new(body)).is_err());
+    }
 }
@@ -0,0 +1,51 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::super::VmmAction;
+use crate::parsed_request::{parse_body, Error, ParsedRequest};
+use crate::request::Body;
+use vmm::vmm_config::watchdog::WatchdogConfig;
+
+pub(crate) fn parse_put_watchdog(body: &Body) -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::SetWatchdog(
+        parse_body::<WatchdogConfig>(body)?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsed_request::tests::vmm_action_from_request;
+    use vmm::vmm_config::watchdog::WatchdogAction;
+
+    #[test]
+    fn test_parse_put_watchdog_request() {
+        assert!(parse_put_watchdog(&Body::new("invalid_payload")).is_err());
+
+        // PUT with unknown fields.
+        let body = r#"{
+                "timeout": 10
+              }"#;
+        assert!(parse_put_watchdog(&Body::new(body)).is_err());
+
+        // PUT with an invalid action.
+        let body = r#"{
+                "action": "Reboot"
+              }"#;
+        assert!(parse_put_watchdog(&Body::new(body)).is_err());
+
+        // PUT without an action.
+        match vmm_action_from_request(parse_put_watchdog(&Body::new("{}")).unwrap()) {
+            VmmAction::SetWatchdog(config) => assert_eq!(config.action, WatchdogAction::None),
+            _ => panic!("Test failed."),
+        }
+
+        let body = r#"{
+                "action": "Reset"
+              }"#;
+        match vmm_action_from_request(parse_put_watchdog(&Body::new(body)).unwrap()) {
+            VmmAction::SetWatchdog(config) => assert_eq!(config.action, WatchdogAction::Reset),
+            _ => panic!("Test failed."),
+        }
+    }
+}
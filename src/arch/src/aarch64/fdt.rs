@@ -500,6 +500,19 @@ fn create_rtc_node<T: DeviceInfoForFDT + Clone + Debug>(
     Ok(())
 }
 
+fn create_pvpanic_node<T: DeviceInfoForFDT + Clone + Debug>(
+    fdt: &mut Vec<u8>,
+    dev_info: &T,
+) -> Result<()> {
+    let pvpanic_reg_prop = generate_prop64(&[dev_info.addr(), dev_info.length()]);
+    append_begin_node(fdt, &format!("pvpanic@{:x}", dev_info.addr()))?;
+    append_property_string(fdt, "compatible", "qemu,pvpanic-mmio")?;
+    append_property(fdt, "reg", &pvpanic_reg_prop)?;
+    append_end_node(fdt)?;
+
+    Ok(())
+}
+
 fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildHasher>(
     fdt: &mut Vec<u8>,
     dev_info: &HashMap<(DeviceType, String), T, S>,
@@ -510,6 +523,7 @@ fn create_devices_node<T: DeviceInfoForFDT + Clone + Debug, S: std::hash::BuildH
     for ((device_type, _device_id), info) in dev_info {
         match device_type {
             DeviceType::BootTimer => (), // since it's not a real device
+            DeviceType::PvPanic => create_pvpanic_node(fdt, info)?,
             DeviceType::RTC => create_rtc_node(fdt, info)?,
             DeviceType::Serial => create_serial_node(fdt, info)?,
             DeviceType::Virtio(_) => {
@@ -532,6 +546,7 @@ mod tests {
     use super::*;
     use crate::aarch64::gic::create_gic;
     use crate::aarch64::{arch_memory_regions, layout};
+    use crate::MmioLayout;
     use kvm_ioctls::Kvm;
 
     const LEN: u64 = 4096;
@@ -564,7 +579,7 @@ mod tests {
 
     #[test]
     fn test_create_fdt_with_devices() {
-        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000);
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000, &MmioLayout::default());
         let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
 
         let dev_info: HashMap<(DeviceType, std::string::String), MMIODeviceInfo> = [
@@ -583,6 +598,13 @@ mod tests {
                     irq: 3,
                 },
             ),
+            (
+                (DeviceType::PvPanic, DeviceType::PvPanic.to_string()),
+                MMIODeviceInfo {
+                    addr: 3 * LEN,
+                    irq: 4,
+                },
+            ),
         ]
         .iter()
         .cloned()
@@ -603,7 +625,7 @@ mod tests {
 
     #[test]
     fn test_create_fdt() {
-        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000);
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000, &MmioLayout::default());
         let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
         let kvm = Kvm::new().unwrap();
         let vm = kvm.create_vm().unwrap();
@@ -648,7 +670,7 @@ mod tests {
 
     #[test]
     fn test_create_fdt_with_initrd() {
-        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000);
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000, &MmioLayout::default());
         let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
         let kvm = Kvm::new().unwrap();
         let vm = kvm.create_vm().unwrap();
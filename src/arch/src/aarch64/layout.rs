@@ -67,7 +67,7 @@ pub const FDT_MAX_SIZE: usize = 0x20_0000;
 // * less than 1023 and
 // * a multiple of 32.
 /// The highest usable SPI on aarch64.
-pub const IRQ_MAX: u32 = 128;
+pub const IRQ_MAX: u32 = 256;
 
 /// First usable interrupt on aarch64.
 pub const IRQ_BASE: u32 = 32;
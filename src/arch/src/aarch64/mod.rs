@@ -30,12 +30,23 @@ pub enum Error {
     InitrdAddress,
 }
 
-/// The start of the memory area reserved for MMIO devices.
-pub const MMIO_MEM_START: u64 = layout::MAPPED_IO_START;
+/// Size of the memory gap reserved for MMIO devices, between the GIC and the DRAM. It can't be
+/// resized on aarch64.
+pub const DEFAULT_MMIO_GAP_SIZE: u64 = layout::DRAM_MEM_START - layout::MAPPED_IO_START;
+/// Smallest size of that gap.
+pub const MIN_MMIO_GAP_SIZE: u64 = DEFAULT_MMIO_GAP_SIZE;
+/// Largest size of that gap.
+pub const MAX_MMIO_GAP_SIZE: u64 = DEFAULT_MMIO_GAP_SIZE;
+
+/// Returns the start of the memory gap reserved for MMIO devices, which is fixed on aarch64.
+pub fn mmio_mem_start(_gap_size: u64) -> u64 {
+    layout::MAPPED_IO_START
+}
 
 /// Returns a Vec of the valid memory addresses for aarch64.
 /// See [`layout`](layout) module for a drawing of the specific memory model for this platform.
-pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
+/// The MMIO devices are below the DRAM, whatever their layout.
+pub fn arch_memory_regions(size: usize, _mmio: &crate::MmioLayout) -> Vec<(GuestAddress, usize)> {
     let dram_size = min(size as u64, layout::DRAM_MEM_MAX_SIZE) as usize;
     vec![(GuestAddress(layout::DRAM_MEM_START), dram_size)]
 }
@@ -76,6 +87,16 @@ pub fn get_kernel_start() -> u64 {
     layout::DRAM_MEM_START
 }
 
+/// Returns the start of the memory region which can be hot(un)plugged, given the last address
+/// of the boot memory. The region is aligned to `HOTPLUG_MEM_ALIGNMENT`, so that the guest can
+/// add it as whole memory sections.
+pub fn hotplug_memory_start(boot_last_addr: GuestAddress) -> GuestAddress {
+    GuestAddress(
+        (boot_last_addr.raw_value() + super::HOTPLUG_MEM_ALIGNMENT)
+            & !(super::HOTPLUG_MEM_ALIGNMENT - 1),
+    )
+}
+
 /// Returns the memory address where the initrd could be loaded.
 pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> super::Result<u64> {
     let round_to_pagesize = |size| (size + (super::PAGE_SIZE - 1)) & !(super::PAGE_SIZE - 1);
@@ -110,10 +131,11 @@ fn get_fdt_addr(mem: &GuestMemoryMmap) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::MmioLayout;
 
     #[test]
     fn test_regions_lt_1024gb() {
-        let regions = arch_memory_regions(1usize << 29);
+        let regions = arch_memory_regions(1usize << 29, &MmioLayout::default());
         assert_eq!(1, regions.len());
         assert_eq!(GuestAddress(super::layout::DRAM_MEM_START), regions[0].0);
         assert_eq!(1usize << 29, regions[0].1);
@@ -121,23 +143,40 @@ mod tests {
 
     #[test]
     fn test_regions_gt_1024gb() {
-        let regions = arch_memory_regions(1usize << 41);
+        let regions = arch_memory_regions(1usize << 41, &MmioLayout::default());
         assert_eq!(1, regions.len());
         assert_eq!(GuestAddress(super::layout::DRAM_MEM_START), regions[0].0);
         assert_eq!(super::layout::DRAM_MEM_MAX_SIZE, regions[0].1 as u64);
     }
 
+    #[test]
+    fn test_hotplug_memory_start() {
+        let regions = arch_memory_regions(1usize << 29, &MmioLayout::default());
+        let mem = GuestMemoryMmap::from_ranges(&regions).unwrap();
+        assert_eq!(
+            hotplug_memory_start(mem.last_addr()),
+            GuestAddress(layout::DRAM_MEM_START + (1 << 30))
+        );
+
+        let regions = arch_memory_regions(1usize << 30, &MmioLayout::default());
+        let mem = GuestMemoryMmap::from_ranges(&regions).unwrap();
+        assert_eq!(
+            hotplug_memory_start(mem.last_addr()),
+            GuestAddress(layout::DRAM_MEM_START + (1 << 30))
+        );
+    }
+
     #[test]
     fn test_get_fdt_addr() {
-        let regions = arch_memory_regions(layout::FDT_MAX_SIZE - 0x1000);
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE - 0x1000, &MmioLayout::default());
         let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
         assert_eq!(get_fdt_addr(&mem), layout::DRAM_MEM_START);
 
-        let regions = arch_memory_regions(layout::FDT_MAX_SIZE);
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE, &MmioLayout::default());
         let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
         assert_eq!(get_fdt_addr(&mem), layout::DRAM_MEM_START);
 
-        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000);
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000, &MmioLayout::default());
         let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
         assert_eq!(get_fdt_addr(&mem), 0x1000 + layout::DRAM_MEM_START);
     }
@@ -180,6 +180,7 @@ pub fn read_mpidr(vcpu: &VcpuFd) -> Result<u64> {
 mod tests {
     use super::*;
     use crate::aarch64::{arch_memory_regions, layout};
+    use crate::MmioLayout;
     use kvm_ioctls::Kvm;
 
     #[test]
@@ -187,7 +188,7 @@ mod tests {
         let kvm = Kvm::new().unwrap();
         let vm = kvm.create_vm().unwrap();
         let vcpu = vm.create_vcpu(0).unwrap();
-        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000);
+        let regions = arch_memory_regions(layout::FDT_MAX_SIZE + 0x1000, &MmioLayout::default());
         let mem = GuestMemoryMmap::from_ranges(&regions).expect("Cannot initialize memory");
 
         let res = setup_boot_regs(&vcpu, 0, 0x0, &mem);
@@ -13,8 +13,9 @@ pub mod aarch64;
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr,
-    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, Error, MMIO_MEM_START,
+    arch_memory_regions, configure_system, get_kernel_start, hotplug_memory_start,
+    initrd_load_addr, layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, mmio_mem_start,
+    Error, DEFAULT_MMIO_GAP_SIZE, MAX_MMIO_GAP_SIZE, MIN_MMIO_GAP_SIZE,
 };
 
 /// Module for x86_64 related functionality.
@@ -23,8 +24,9 @@ pub mod x86_64;
 
 #[cfg(target_arch = "x86_64")]
 pub use crate::x86_64::{
-    arch_memory_regions, configure_system, get_kernel_start, initrd_load_addr,
-    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, Error, MMIO_MEM_START,
+    arch_memory_regions, configure_system, get_kernel_start, hotplug_memory_start,
+    initrd_load_addr, layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, layout::IRQ_MAX, mmio_mem_start,
+    Error, DEFAULT_MMIO_GAP_SIZE, MAX_MMIO_GAP_SIZE, MIN_MMIO_GAP_SIZE,
 };
 
 /// Type for returning public functions outcome.
@@ -41,6 +43,9 @@ pub enum DeviceType {
     /// Device Type: RTC.
     #[cfg(target_arch = "aarch64")]
     RTC,
+    /// Device Type: PvPanic.
+    #[cfg(target_arch = "aarch64")]
+    PvPanic,
     /// Device Type: BootTimer.
     BootTimer,
 }
@@ -56,8 +61,111 @@ pub struct InitrdConfig {
 /// Default (smallest) memory page size for the supported architectures.
 pub const PAGE_SIZE: usize = 4096;
 
+/// Alignment of the memory region which can be hot(un)plugged.
+pub const HOTPLUG_MEM_ALIGNMENT: u64 = 1 << 30;
+
+/// Alignment of the size of the memory gap holding the MMIO devices, so that the memory regions
+/// around it can be backed by huge pages.
+pub const MMIO_GAP_ALIGNMENT: u64 = 2 << 20;
+
+/// The largest number of MMIO device slots, one per usable IRQ.
+pub const MAX_MMIO_SLOTS: u32 = IRQ_MAX - IRQ_BASE + 1;
+
+/// The layout of the memory gap holding the MMIO devices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MmioLayout {
+    /// Size of the gap, in bytes.
+    pub gap_size: u64,
+    /// Number of device slots, each having its own IRQ.
+    pub slots: u32,
+}
+
+impl Default for MmioLayout {
+    fn default() -> Self {
+        MmioLayout {
+            gap_size: DEFAULT_MMIO_GAP_SIZE,
+            slots: MAX_MMIO_SLOTS,
+        }
+    }
+}
+
+impl MmioLayout {
+    /// Returns whether the gap fits in the address space, and the slots in the usable IRQs.
+    pub fn is_valid(&self) -> bool {
+        (MIN_MMIO_GAP_SIZE..=MAX_MMIO_GAP_SIZE).contains(&self.gap_size)
+            && self.gap_size % MMIO_GAP_ALIGNMENT == 0
+            && (1..=MAX_MMIO_SLOTS).contains(&self.slots)
+    }
+
+    /// Returns the address of the first device slot.
+    pub fn start(&self) -> u64 {
+        mmio_mem_start(self.gap_size)
+    }
+
+    /// Returns the first and last IRQs of the device slots.
+    pub fn irq_range(&self) -> (u32, u32) {
+        (IRQ_BASE, IRQ_BASE + self.slots - 1)
+    }
+}
+
+/// Returns the start of the window holding the persistent memory regions, given the last address
+/// of the guest memory, hotplug memory included. The window follows the guest memory and is
+/// aligned like the hotplug memory, so that the guest can map the regions as whole sections.
+pub fn pmem_memory_start(mem_last_addr: vm_memory::GuestAddress) -> vm_memory::GuestAddress {
+    hotplug_memory_start(mem_last_addr)
+}
+
 impl fmt::Display for DeviceType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_memory::{Address, GuestAddress};
+
+    #[test]
+    fn test_mmio_layout() {
+        let layout = MmioLayout::default();
+        assert!(layout.is_valid());
+        assert_eq!(layout.start(), mmio_mem_start(DEFAULT_MMIO_GAP_SIZE));
+        assert_eq!(layout.irq_range(), (IRQ_BASE, IRQ_MAX));
+
+        let layout = MmioLayout { slots: 2, ..layout };
+        assert!(layout.is_valid());
+        assert_eq!(layout.irq_range(), (IRQ_BASE, IRQ_BASE + 1));
+
+        assert!(!MmioLayout { slots: 0, ..layout }.is_valid());
+        assert!(!MmioLayout {
+            slots: MAX_MMIO_SLOTS + 1,
+            ..layout
+        }
+        .is_valid());
+        assert!(!MmioLayout {
+            gap_size: MAX_MMIO_GAP_SIZE + MMIO_GAP_ALIGNMENT,
+            ..layout
+        }
+        .is_valid());
+        assert!(!MmioLayout {
+            gap_size: MIN_MMIO_GAP_SIZE - MMIO_GAP_ALIGNMENT,
+            ..layout
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_pmem_memory_start() {
+        let last_addr = GuestAddress((8 << 30) - 1);
+        let start = pmem_memory_start(last_addr);
+        assert!(start > last_addr);
+        assert_eq!(start.raw_value() % HOTPLUG_MEM_ALIGNMENT, 0);
+        assert_eq!(start, GuestAddress(8 << 30));
+
+        // The window doesn't overlap a hotplug region appended to the boot memory.
+        let hotplug_start = hotplug_memory_start(GuestAddress((1 << 30) - 1));
+        let hotplug_last_addr = hotplug_start.unchecked_add((1 << 30) - 1);
+        assert!(pmem_memory_start(hotplug_last_addr) > hotplug_last_addr);
+    }
+}
@@ -0,0 +1,930 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::result;
+
+use vm_memory::{Address, Bytes, GuestAddress, GuestMemoryMmap};
+
+use super::layout;
+
+/// Errors thrown while writing the ACPI tables.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// There was too little guest memory to store the ACPI tables.
+    NotEnoughMemory,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+// Identification of the tables in their headers.
+const OEM_ID: &[u8; 6] = b"FIRECK";
+const OEM_TABLE_ID: &[u8; 8] = b"FCVMACPI";
+const OEM_REVISION: u32 = 1;
+const CREATOR_ID: &[u8; 4] = b"FCVM";
+const CREATOR_REVISION: u32 = 1;
+
+// Size of the header common to all the system description tables.
+const SDT_HEADER_LEN: usize = 36;
+// Offsets of the length and checksum in that header.
+const SDT_LENGTH: usize = 4;
+const SDT_CHECKSUM: usize = 9;
+
+// Size of the RSDP, and the offsets of its checksums. The first checksum only covers the first
+// 20 bytes, as defined in ACPI 1.0.
+const RSDP_LEN: usize = 36;
+const RSDP_CHECKSUM: usize = 8;
+const RSDP_V1_LEN: usize = 20;
+const RSDP_EXT_CHECKSUM: usize = 32;
+
+// Size of the FADT of ACPI 6.0, and the offsets of the fields we fill in.
+const FADT_LEN: usize = 276;
+const FADT_DSDT: usize = 40;
+const FADT_SCI_INT: usize = 46;
+const FADT_PM1A_EVT_BLK: usize = 56;
+const FADT_PM1A_CNT_BLK: usize = 64;
+const FADT_PM1_EVT_LEN: usize = 88;
+const FADT_PM1_CNT_LEN: usize = 89;
+const FADT_IAPC_BOOT_ARCH: usize = 109;
+const FADT_FLAGS: usize = 112;
+const FADT_X_DSDT: usize = 140;
+const FADT_X_PM1A_EVT_BLK: usize = 148;
+const FADT_X_PM1A_CNT_BLK: usize = 172;
+// There are no legacy devices besides the i8042 controller, nor VGA, MSI or ASPM.
+const IAPC_BOOT_ARCH: u16 = (1 << 1) | (1 << 2) | (1 << 5);
+// WBINVD works, and the sleep button is not a fixed feature. The power button is.
+const FADT_FLAGS_WBINVD: u32 = 1 << 0;
+const FADT_FLAGS_SLP_BUTTON: u32 = 1 << 5;
+// Sizes of the PM1 register blocks.
+const PM1_EVT_LEN: u8 = 4;
+const PM1_CNT_LEN: u8 = 2;
+
+// The MADT entries, and where the interrupt controllers are.
+const APIC_DEFAULT_PHYS_BASE: u32 = 0xfee0_0000;
+const IO_APIC_DEFAULT_PHYS_BASE: u32 = 0xfec0_0000;
+const MADT_PCAT_COMPAT: u32 = 1 << 0;
+const MADT_LAPIC: u8 = 0;
+const MADT_LAPIC_LEN: u8 = 8;
+// The vCPUs which aren't plugged at boot have a disabled entry, so that the guest makes room
+// for them.
+const MADT_LAPIC_ENABLED: u32 = 1 << 0;
+const MADT_LAPIC_DISABLED: u32 = 0;
+const MADT_IOAPIC: u8 = 1;
+const MADT_IOAPIC_LEN: u8 = 12;
+const MADT_INT_SRC_OVERRIDE: u8 = 2;
+const MADT_INT_SRC_OVERRIDE_LEN: u8 = 10;
+// The SCI is edge triggered and active high, as it is injected through an irqfd.
+const MPS_INTI_ACTIVE_HIGH: u16 = 1;
+const MPS_INTI_EDGE: u16 = 1 << 2;
+
+// The `\_S5_` package, giving the values of SLP_TYP the guest writes to enter the soft off state.
+const DSDT_S5: [u8; 13] = [
+    0x08, b'_', b'S', b'5', b'_', // NameOp "_S5_"
+    0x12, 0x07, 0x04, // PackageOp, length, 4 elements
+    0x0a, 0x05, // BytePrefix, SLP_TYP for PM1a
+    0x00, 0x00, 0x00, // SLP_TYP for PM1b and reserved values
+];
+
+// The AML opcodes and prefixes used to describe the PCI host bridge and the vCPU hotplug.
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_WORD_PREFIX: u8 = 0x0b;
+const AML_DWORD_PREFIX: u8 = 0x0c;
+const AML_STRING_PREFIX: u8 = 0x0d;
+const AML_QWORD_PREFIX: u8 = 0x0e;
+const AML_SCOPE_OP: u8 = 0x10;
+const AML_BUFFER_OP: u8 = 0x11;
+const AML_METHOD_OP: u8 = 0x14;
+const AML_MULTI_NAME_PREFIX: u8 = 0x2f;
+const AML_LOCAL0_OP: u8 = 0x60;
+const AML_ARG0_OP: u8 = 0x68;
+const AML_STORE_OP: u8 = 0x70;
+const AML_AND_OP: u8 = 0x7b;
+const AML_NOTIFY_OP: u8 = 0x86;
+const AML_LEQUAL_OP: u8 = 0x93;
+const AML_IF_OP: u8 = 0xa0;
+const AML_RETURN_OP: u8 = 0xa4;
+const AML_MUTEX_OP: [u8; 2] = [0x5b, 0x01];
+const AML_ACQUIRE_OP: [u8; 2] = [0x5b, 0x23];
+const AML_RELEASE_OP: [u8; 2] = [0x5b, 0x27];
+const AML_OP_REGION_OP: [u8; 2] = [0x5b, 0x80];
+const AML_FIELD_OP: [u8; 2] = [0x5b, 0x81];
+const AML_DEVICE_OP: [u8; 2] = [0x5b, 0x82];
+const AML_ROOT_SB: [u8; 5] = [b'\\', b'_', b'S', b'B', b'_'];
+// The operation regions are in the I/O space.
+const AML_REGION_SYSTEM_IO: u8 = 0x01;
+// The fields are accessed a byte at a time, and the bits not written are written as zeros, so
+// that writing a flag doesn't clear the others.
+const AML_FIELD_BYTE_ACC_WRITE_AS_ZEROS: u8 = 0x01 | 0x02 << 5;
+// The method takes arguments in its low bits, and is executed by one thread at a time.
+const AML_METHOD_SERIALIZED: u8 = 1 << 3;
+// The method waits for the mutex forever.
+const AML_ACQUIRE_NO_TIMEOUT: u16 = 0xffff;
+// The notification values telling the guest to check the presence of a device, or to eject it.
+const AML_NOTIFY_DEVICE_CHECK: u64 = 1;
+const AML_NOTIFY_EJECT_REQUEST: u64 = 3;
+// The value of `_STA` for a present and enabled device, shown in the UI and functioning.
+const AML_STA_PRESENT: u64 = 0x0f;
+
+// The vCPU hotplug controller has a select register and a flags register, followed by the event
+// register of the GED. They match the controller emulated in the `devices` crate.
+const CPU_HOTPLUG_REGS_LEN: u64 = 2;
+const GED_EVENTS_OFFSET: u64 = 2;
+const GED_EVENT_CPU_HOTPLUG: u64 = 1;
+
+// The resource descriptors of the host bridge: the bus it decodes, the legacy configuration
+// ports, and the window holding the BARs.
+const RES_WORD_ADDRESS: u8 = 0x88;
+const RES_DWORD_ADDRESS: u8 = 0x87;
+const RES_IO_PORT: [u8; 8] = [0x47, 0x01, 0xf8, 0x0c, 0xf8, 0x0c, 0x01, 0x08];
+const RES_END_TAG: [u8; 2] = [0x79, 0x00];
+// The extended interrupt descriptor of the GED: a single interrupt it consumes, edge triggered
+// and active high, as it is injected through an irqfd.
+const RES_EXT_INTERRUPT: [u8; 5] = [0x89, 0x06, 0x00, 0x03, 0x01];
+const RES_TYPE_MEMORY: u8 = 0;
+const RES_TYPE_BUS_NUMBER: u8 = 2;
+// The minimum and maximum addresses are fixed, and the bridge produces the resource.
+const RES_FLAGS_FIXED: u8 = 0x0c;
+const RES_MEMORY_READ_WRITE: u8 = 0x01;
+
+// Size of the MCFG header after the common header: 8 reserved bytes.
+const MCFG_RESERVED_LEN: usize = 8;
+
+// A system description table, whose length and checksum are filled in once complete.
+struct Sdt(Vec<u8>);
+
+impl Sdt {
+    fn new(signature: &[u8; 4], revision: u8) -> Self {
+        let mut data = Vec::with_capacity(SDT_HEADER_LEN);
+        data.extend_from_slice(signature);
+        data.extend_from_slice(&[0; 4]);
+        data.push(revision);
+        data.push(0);
+        data.extend_from_slice(OEM_ID);
+        data.extend_from_slice(OEM_TABLE_ID);
+        data.extend_from_slice(&OEM_REVISION.to_le_bytes());
+        data.extend_from_slice(CREATOR_ID);
+        data.extend_from_slice(&CREATOR_REVISION.to_le_bytes());
+        Sdt(data)
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let len = self.0.len() as u32;
+        self.0[SDT_LENGTH..SDT_LENGTH + 4].copy_from_slice(&len.to_le_bytes());
+        set_checksum(&mut self.0, SDT_CHECKSUM);
+        self.0
+    }
+}
+
+// Sets the byte at `offset` so that all the bytes of `data` sum to zero.
+fn set_checksum(data: &mut [u8], offset: usize) {
+    data[offset] = 0;
+    let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    data[offset] = 0u8.wrapping_sub(sum);
+}
+
+// A generic address structure describing a register block of `bit_width` bits at I/O `port`,
+// accessed 16 bits at a time.
+fn io_gas(bit_width: u8, port: u64) -> [u8; 12] {
+    const SYSTEM_IO: u8 = 1;
+    const WORD_ACCESS: u8 = 2;
+    let mut gas = [0u8; 12];
+    gas[0] = SYSTEM_IO;
+    gas[1] = bit_width;
+    gas[3] = WORD_ACCESS;
+    gas[4..].copy_from_slice(&port.to_le_bytes());
+    gas
+}
+
+// Encodes the package length of an AML object whose content, the package length excluded, is
+// `len` bytes long.
+fn aml_pkg_length(len: usize) -> Vec<u8> {
+    if len + 1 < 1 << 6 {
+        return vec![(len + 1) as u8];
+    }
+    // The lead byte holds the number of following bytes in its two upper bits and the low nibble
+    // of the length.
+    let (count, total) = if len + 2 < 1 << 12 {
+        (1, len + 2)
+    } else {
+        (2, len + 3)
+    };
+    let mut pkg_length = vec![(count << 6) as u8 | (total & 0xf) as u8];
+    for i in 0..count {
+        pkg_length.push((total >> (4 + 8 * i)) as u8);
+    }
+    pkg_length
+}
+
+fn aml_integer(value: u64) -> Vec<u8> {
+    let (prefix, len) = match value {
+        0 => return vec![AML_ZERO_OP],
+        1 => return vec![AML_ONE_OP],
+        0x2..=0xff => (AML_BYTE_PREFIX, 1),
+        0x100..=0xffff => (AML_WORD_PREFIX, 2),
+        0x1_0000..=0xffff_ffff => (AML_DWORD_PREFIX, 4),
+        _ => (AML_QWORD_PREFIX, 8),
+    };
+    let mut integer = vec![prefix];
+    integer.extend_from_slice(&value.to_le_bytes()[..len]);
+    integer
+}
+
+fn aml_name(name: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut object = vec![AML_NAME_OP];
+    object.extend_from_slice(name);
+    object.extend_from_slice(data);
+    object
+}
+
+// Encodes an object made of `opcode`, a package length, and `content`.
+fn aml_package(opcode: &[u8], content: &[u8]) -> Vec<u8> {
+    let mut object = opcode.to_vec();
+    object.extend(aml_pkg_length(content.len()));
+    object.extend_from_slice(content);
+    object
+}
+
+fn aml_buffer(data: &[u8]) -> Vec<u8> {
+    let mut content = aml_integer(data.len() as u64);
+    content.extend_from_slice(data);
+    aml_package(&[AML_BUFFER_OP], &content)
+}
+
+// The compressed form of a PNP ID such as "PNP0A08", as the `EisaId()` macro produces it.
+fn eisa_id(id: &[u8; 7]) -> u64 {
+    let hex = |c: u8| (c as char).to_digit(16).unwrap_or(0);
+    let letter = |c: u8| u32::from(c - 0x40) & 0x1f;
+    let vendor = letter(id[0]) << 10 | letter(id[1]) << 5 | letter(id[2]);
+    let product = hex(id[3]) << 12 | hex(id[4]) << 8 | hex(id[5]) << 4 | hex(id[6]);
+    // Both halves are stored big endian.
+    u64::from(
+        u32::from((vendor as u16).swap_bytes()) | u32::from((product as u16).swap_bytes()) << 16,
+    )
+}
+
+fn aml_string(string: &str) -> Vec<u8> {
+    let mut object = vec![AML_STRING_PREFIX];
+    object.extend_from_slice(string.as_bytes());
+    object.push(0);
+    object
+}
+
+// The absolute path of the object `segments`, under the root namespace.
+fn aml_path(segments: &[&[u8; 4]]) -> Vec<u8> {
+    let mut path = vec![b'\\', AML_MULTI_NAME_PREFIX, segments.len() as u8];
+    for segment in segments {
+        path.extend_from_slice(*segment);
+    }
+    path
+}
+
+fn aml_method(name: &[u8; 4], arg_count: u8, serialized: bool, body: &[u8]) -> Vec<u8> {
+    let mut content = name.to_vec();
+    content.push(if serialized {
+        arg_count | AML_METHOD_SERIALIZED
+    } else {
+        arg_count
+    });
+    content.extend_from_slice(body);
+    aml_package(&[AML_METHOD_OP], &content)
+}
+
+fn aml_store(value: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut object = vec![AML_STORE_OP];
+    object.extend_from_slice(value);
+    object.extend_from_slice(target);
+    object
+}
+
+fn aml_if(predicate: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut content = predicate.to_vec();
+    content.extend_from_slice(body);
+    aml_package(&[AML_IF_OP], &content)
+}
+
+fn aml_equal(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut object = vec![AML_LEQUAL_OP];
+    object.extend_from_slice(left);
+    object.extend_from_slice(right);
+    object
+}
+
+// The bitwise and of `left` and `right`, whose result isn't stored.
+fn aml_and(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut object = vec![AML_AND_OP];
+    object.extend_from_slice(left);
+    object.extend_from_slice(right);
+    object.push(AML_ZERO_OP);
+    object
+}
+
+fn aml_return(value: &[u8]) -> Vec<u8> {
+    let mut object = vec![AML_RETURN_OP];
+    object.extend_from_slice(value);
+    object
+}
+
+fn aml_notify(object: &[u8], value: u64) -> Vec<u8> {
+    let mut notify = vec![AML_NOTIFY_OP];
+    notify.extend_from_slice(object);
+    notify.extend(aml_integer(value));
+    notify
+}
+
+fn aml_mutex(name: &[u8; 4]) -> Vec<u8> {
+    let mut object = AML_MUTEX_OP.to_vec();
+    object.extend_from_slice(name);
+    object.push(0);
+    object
+}
+
+fn aml_acquire(mutex: &[u8; 4]) -> Vec<u8> {
+    let mut object = AML_ACQUIRE_OP.to_vec();
+    object.extend_from_slice(mutex);
+    object.extend_from_slice(&AML_ACQUIRE_NO_TIMEOUT.to_le_bytes());
+    object
+}
+
+fn aml_release(mutex: &[u8; 4]) -> Vec<u8> {
+    let mut object = AML_RELEASE_OP.to_vec();
+    object.extend_from_slice(mutex);
+    object
+}
+
+// The operation region `name`, spanning `len` I/O ports from `port`.
+fn aml_io_region(name: &[u8; 4], port: u64, len: u64) -> Vec<u8> {
+    let mut object = AML_OP_REGION_OP.to_vec();
+    object.extend_from_slice(name);
+    object.push(AML_REGION_SYSTEM_IO);
+    object.extend(aml_integer(port));
+    object.extend(aml_integer(len));
+    object
+}
+
+// The fields of the operation region `region`, given by their name and size in bits, which must
+// be lower than 64.
+fn aml_field(region: &[u8; 4], fields: &[(&[u8; 4], u8)]) -> Vec<u8> {
+    let mut content = region.to_vec();
+    content.push(AML_FIELD_BYTE_ACC_WRITE_AS_ZEROS);
+    for (name, bits) in fields {
+        content.extend_from_slice(*name);
+        // The size is encoded as a package length, without counting its own byte.
+        content.push(*bits);
+    }
+    aml_package(&AML_FIELD_OP, &content)
+}
+
+// The current resource settings of the PCI host bridge.
+fn pci_host_bridge_crs() -> Vec<u8> {
+    let mut crs = vec![
+        RES_WORD_ADDRESS,
+        13,
+        0,
+        RES_TYPE_BUS_NUMBER,
+        RES_FLAGS_FIXED,
+        0,
+    ];
+    // Granularity, minimum, maximum, translation offset, and length of the bus range.
+    for value in &[0u16, 0, 0, 0, 1] {
+        crs.extend_from_slice(&value.to_le_bytes());
+    }
+    crs.extend_from_slice(&RES_IO_PORT);
+    crs.extend_from_slice(&[
+        RES_DWORD_ADDRESS,
+        23,
+        0,
+        RES_TYPE_MEMORY,
+        RES_FLAGS_FIXED,
+        RES_MEMORY_READ_WRITE,
+    ]);
+    let start = layout::PCI_MMIO_START as u32;
+    let size = layout::PCI_MMIO_SIZE as u32;
+    for value in &[0, start, start + (size - 1), 0, size] {
+        crs.extend_from_slice(&value.to_le_bytes());
+    }
+    crs.extend_from_slice(&RES_END_TAG);
+    crs
+}
+
+// The `\_SB_.PCI0` device, describing the PCI Express host bridge.
+fn dsdt_pci_host_bridge() -> Vec<u8> {
+    let mut device = b"PCI0".to_vec();
+    device.extend(aml_name(b"_HID", &aml_integer(eisa_id(b"PNP0A08"))));
+    device.extend(aml_name(b"_CID", &aml_integer(eisa_id(b"PNP0A03"))));
+    device.extend(aml_name(b"_UID", &aml_integer(0)));
+    device.extend(aml_name(b"_SEG", &aml_integer(0)));
+    device.extend(aml_name(b"_BBN", &aml_integer(0)));
+    device.extend(aml_name(b"_CRS", &aml_buffer(&pci_host_bridge_crs())));
+
+    let mut scope = AML_ROOT_SB.to_vec();
+    scope.extend(aml_package(&AML_DEVICE_OP, &device));
+    aml_package(&[AML_SCOPE_OP], &scope)
+}
+
+// The name of the processor device of the vCPU `index`.
+fn cpu_device_name(index: u8) -> [u8; 4] {
+    let mut name = [0u8; 4];
+    name.copy_from_slice(format!("C{:03X}", index).as_bytes());
+    name
+}
+
+// The `\_SB_.CPUS` container, holding the processor devices of the `num_cpus` vCPUs, and the
+// `\_SB_.GED0` device. The presence of the vCPUs is read from the vCPU hotplug controller,
+// through which the guest also ejects them. The GED interrupt tells the guest to scan the
+// controller, and to notify the processor devices of the vCPUs inserted or to eject.
+fn dsdt_cpu_hotplug(num_cpus: u8) -> Vec<u8> {
+    let local0 = [AML_LOCAL0_OP];
+    let one = [AML_ONE_OP];
+
+    let mut cpus = b"CPUS".to_vec();
+    cpus.extend(aml_name(b"_HID", &aml_string("ACPI0010")));
+    cpus.extend(aml_name(b"_CID", &aml_integer(eisa_id(b"PNP0A05"))));
+    // CSEL selects the vCPU whose flags tell whether it is present, is being inserted, or is to
+    // be ejected. Setting CEJF ejects it.
+    cpus.extend(aml_io_region(
+        b"PRST",
+        layout::CPU_HOTPLUG_START,
+        CPU_HOTPLUG_REGS_LEN,
+    ));
+    cpus.extend(aml_field(
+        b"PRST",
+        &[
+            (b"CSEL", 8),
+            (b"CPEN", 1),
+            (b"CINS", 1),
+            (b"CRMV", 1),
+            (b"CEJF", 1),
+        ],
+    ));
+    cpus.extend(aml_mutex(b"CPLK"));
+
+    // CSTA(index) returns the status of a vCPU.
+    let mut csta = aml_acquire(b"CPLK");
+    csta.extend(aml_store(&[AML_ARG0_OP], b"CSEL"));
+    csta.extend(aml_store(&[AML_ZERO_OP], &local0));
+    csta.extend(aml_if(
+        &aml_equal(b"CPEN", &one),
+        &aml_store(&aml_integer(AML_STA_PRESENT), &local0),
+    ));
+    csta.extend(aml_release(b"CPLK"));
+    csta.extend(aml_return(&local0));
+    cpus.extend(aml_method(b"CSTA", 1, true, &csta));
+
+    // CEJ0(index) ejects a vCPU the guest took offline.
+    let mut cej0 = aml_acquire(b"CPLK");
+    cej0.extend(aml_store(&[AML_ARG0_OP], b"CSEL"));
+    cej0.extend(aml_store(&one, b"CEJF"));
+    cej0.extend(aml_release(b"CPLK"));
+    cpus.extend(aml_method(b"CEJ0", 1, true, &cej0));
+
+    // CSCN notifies the processor devices of the pending insertions and ejections, and
+    // acknowledges them.
+    let mut cscn = aml_acquire(b"CPLK");
+    for index in 0..num_cpus {
+        let name = cpu_device_name(index);
+        cscn.extend(aml_store(&aml_integer(index.into()), b"CSEL"));
+        let mut insert = aml_notify(&name, AML_NOTIFY_DEVICE_CHECK);
+        insert.extend(aml_store(&one, b"CINS"));
+        cscn.extend(aml_if(&aml_equal(b"CINS", &one), &insert));
+        let mut eject = aml_notify(&name, AML_NOTIFY_EJECT_REQUEST);
+        eject.extend(aml_store(&one, b"CRMV"));
+        cscn.extend(aml_if(&aml_equal(b"CRMV", &one), &eject));
+    }
+    cscn.extend(aml_release(b"CPLK"));
+    cpus.extend(aml_method(b"CSCN", 0, true, &cscn));
+
+    for index in 0..num_cpus {
+        let mut cpu = cpu_device_name(index).to_vec();
+        cpu.extend(aml_name(b"_HID", &aml_string("ACPI0007")));
+        cpu.extend(aml_name(b"_UID", &aml_integer(index.into())));
+        // The local APIC of a hotplugged vCPU is only found here, since its MADT entry is
+        // disabled.
+        cpu.extend(aml_name(
+            b"_MAT",
+            &aml_buffer(&madt_lapic(index, MADT_LAPIC_ENABLED)),
+        ));
+        let mut csta = b"CSTA".to_vec();
+        csta.extend(aml_integer(index.into()));
+        cpu.extend(aml_method(b"_STA", 0, false, &aml_return(&csta)));
+        let mut cej0 = b"CEJ0".to_vec();
+        cej0.extend(aml_integer(index.into()));
+        cpu.extend(aml_method(b"_EJ0", 1, false, &cej0));
+        cpus.extend(aml_package(&AML_DEVICE_OP, &cpu));
+    }
+
+    let mut ged = b"GED0".to_vec();
+    ged.extend(aml_name(b"_HID", &aml_string("ACPI0013")));
+    ged.extend(aml_name(b"_UID", &aml_integer(0)));
+    let mut crs = RES_EXT_INTERRUPT.to_vec();
+    crs.extend_from_slice(&layout::GED_IRQ.to_le_bytes());
+    crs.extend_from_slice(&RES_END_TAG);
+    ged.extend(aml_name(b"_CRS", &aml_buffer(&crs)));
+    ged.extend(aml_io_region(
+        b"GDST",
+        layout::CPU_HOTPLUG_START + GED_EVENTS_OFFSET,
+        1,
+    ));
+    ged.extend(aml_field(b"GDST", &[(b"GDAT", 8)]));
+    // Reading the events acknowledges them.
+    let evt = aml_if(
+        &aml_and(b"GDAT", &aml_integer(GED_EVENT_CPU_HOTPLUG)),
+        &aml_path(&[b"_SB_", b"CPUS", b"CSCN"]),
+    );
+    ged.extend(aml_method(b"_EVT", 1, true, &evt));
+
+    let mut scope = AML_ROOT_SB.to_vec();
+    scope.extend(aml_package(&AML_DEVICE_OP, &cpus));
+    scope.extend(aml_package(&AML_DEVICE_OP, &ged));
+    aml_package(&[AML_SCOPE_OP], &scope)
+}
+
+fn dsdt(num_cpus: u8, cpu_hotplug: bool, pci_enabled: bool) -> Vec<u8> {
+    let mut dsdt = Sdt::new(b"DSDT", 2);
+    dsdt.append(&DSDT_S5);
+    if pci_enabled {
+        dsdt.append(&dsdt_pci_host_bridge());
+    }
+    if cpu_hotplug {
+        dsdt.append(&dsdt_cpu_hotplug(num_cpus));
+    }
+    dsdt.finish()
+}
+
+// The table giving the ECAM window of bus 0, in segment 0.
+fn mcfg() -> Vec<u8> {
+    let mut mcfg = Sdt::new(b"MCFG", 1);
+    mcfg.append(&[0; MCFG_RESERVED_LEN]);
+    mcfg.append(&layout::PCI_MMCONFIG_START.to_le_bytes());
+    // The segment, the first and last buses, and reserved bytes.
+    mcfg.append(&0u16.to_le_bytes());
+    mcfg.append(&[0, 0]);
+    mcfg.append(&[0; 4]);
+    mcfg.finish()
+}
+
+fn fadt(dsdt_addr: u64) -> Vec<u8> {
+    let pm1a_evt_blk = layout::ACPI_PM_START;
+    let pm1a_cnt_blk = layout::ACPI_PM_START + u64::from(PM1_EVT_LEN);
+
+    let mut fadt = Sdt::new(b"FACP", 6);
+    fadt.append(&[0; FADT_LEN - SDT_HEADER_LEN]);
+    let mut put = |offset: usize, bytes: &[u8]| {
+        fadt.0[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    put(FADT_DSDT, &(dsdt_addr as u32).to_le_bytes());
+    put(FADT_SCI_INT, &(layout::ACPI_SCI_IRQ as u16).to_le_bytes());
+    put(FADT_PM1A_EVT_BLK, &(pm1a_evt_blk as u32).to_le_bytes());
+    put(FADT_PM1A_CNT_BLK, &(pm1a_cnt_blk as u32).to_le_bytes());
+    put(FADT_PM1_EVT_LEN, &[PM1_EVT_LEN]);
+    put(FADT_PM1_CNT_LEN, &[PM1_CNT_LEN]);
+    put(FADT_IAPC_BOOT_ARCH, &IAPC_BOOT_ARCH.to_le_bytes());
+    put(
+        FADT_FLAGS,
+        &(FADT_FLAGS_WBINVD | FADT_FLAGS_SLP_BUTTON).to_le_bytes(),
+    );
+    put(FADT_X_DSDT, &dsdt_addr.to_le_bytes());
+    put(FADT_X_PM1A_EVT_BLK, &io_gas(PM1_EVT_LEN * 8, pm1a_evt_blk));
+    put(FADT_X_PM1A_CNT_BLK, &io_gas(PM1_CNT_LEN * 8, pm1a_cnt_blk));
+    fadt.finish()
+}
+
+// The local APIC entry of the vCPU `cpu_id`, whose processor UID and APIC ID are its index.
+fn madt_lapic(cpu_id: u8, flags: u32) -> Vec<u8> {
+    let mut entry = vec![MADT_LAPIC, MADT_LAPIC_LEN, cpu_id, cpu_id];
+    entry.extend_from_slice(&flags.to_le_bytes());
+    entry
+}
+
+fn madt(num_cpus: u8, boot_cpus: u8) -> Vec<u8> {
+    let mut madt = Sdt::new(b"APIC", 4);
+    madt.append(&APIC_DEFAULT_PHYS_BASE.to_le_bytes());
+    madt.append(&MADT_PCAT_COMPAT.to_le_bytes());
+
+    for cpu_id in 0..num_cpus {
+        let flags = if cpu_id < boot_cpus {
+            MADT_LAPIC_ENABLED
+        } else {
+            MADT_LAPIC_DISABLED
+        };
+        madt.append(&madt_lapic(cpu_id, flags));
+    }
+
+    // The IO APIC has the same ID as in the MP table.
+    madt.append(&[MADT_IOAPIC, MADT_IOAPIC_LEN, num_cpus + 1, 0]);
+    madt.append(&IO_APIC_DEFAULT_PHYS_BASE.to_le_bytes());
+    madt.append(&0u32.to_le_bytes());
+
+    madt.append(&[
+        MADT_INT_SRC_OVERRIDE,
+        MADT_INT_SRC_OVERRIDE_LEN,
+        0,
+        layout::ACPI_SCI_IRQ as u8,
+    ]);
+    madt.append(&layout::ACPI_SCI_IRQ.to_le_bytes());
+    madt.append(&(MPS_INTI_ACTIVE_HIGH | MPS_INTI_EDGE).to_le_bytes());
+    madt.finish()
+}
+
+fn xsdt(table_addrs: &[u64]) -> Vec<u8> {
+    let mut xsdt = Sdt::new(b"XSDT", 1);
+    for addr in table_addrs {
+        xsdt.append(&addr.to_le_bytes());
+    }
+    xsdt.finish()
+}
+
+fn rsdp(xsdt_addr: u64) -> Vec<u8> {
+    const RSDP_REVISION: u8 = 2;
+    let mut rsdp = Vec::with_capacity(RSDP_LEN);
+    rsdp.extend_from_slice(b"RSD PTR ");
+    rsdp.push(0);
+    rsdp.extend_from_slice(OEM_ID);
+    rsdp.push(RSDP_REVISION);
+    // There is no RSDT, only the XSDT.
+    rsdp.extend_from_slice(&0u32.to_le_bytes());
+    rsdp.extend_from_slice(&(RSDP_LEN as u32).to_le_bytes());
+    rsdp.extend_from_slice(&xsdt_addr.to_le_bytes());
+    rsdp.extend_from_slice(&[0; 4]);
+    set_checksum(&mut rsdp[..RSDP_V1_LEN], RSDP_CHECKSUM);
+    set_checksum(&mut rsdp, RSDP_EXT_CHECKSUM);
+    rsdp
+}
+
+/// Writes the ACPI tables describing `num_cpus` vCPUs, the interrupt controllers, the PM
+/// registers and the PCI host bridge if `pci_enabled`, and returns the address of the RSDP
+/// pointing to them. Only the first `boot_cpus` vCPUs are enabled at boot: the others are
+/// hotplugged through the vCPU hotplug controller.
+pub fn setup_acpi_tables(
+    mem: &GuestMemoryMmap,
+    num_cpus: u8,
+    boot_cpus: u8,
+    pci_enabled: bool,
+) -> Result<GuestAddress> {
+    let mut addr = GuestAddress(layout::ACPI_TABLES_START);
+    let mut write_table = |table: Vec<u8>| -> Result<GuestAddress> {
+        let table_addr = addr;
+        mem.write_slice(&table, table_addr)
+            .map_err(|_| Error::NotEnoughMemory)?;
+        // Keep the tables 8-byte aligned.
+        addr = table_addr
+            .checked_add(table.len() as u64)
+            .ok_or(Error::NotEnoughMemory)?
+            .unchecked_align_up(8);
+        Ok(table_addr)
+    };
+
+    let dsdt_addr = write_table(dsdt(num_cpus, boot_cpus < num_cpus, pci_enabled))?;
+    let fadt_addr = write_table(fadt(dsdt_addr.raw_value()))?;
+    let madt_addr = write_table(madt(num_cpus, boot_cpus))?;
+    let mut table_addrs = vec![fadt_addr.raw_value(), madt_addr.raw_value()];
+    if pci_enabled {
+        table_addrs.push(write_table(mcfg())?.raw_value());
+    }
+    let xsdt_addr = write_table(xsdt(&table_addrs))?;
+
+    let rsdp_addr = GuestAddress(layout::RSDP_START);
+    mem.write_slice(&rsdp(xsdt_addr.raw_value()), rsdp_addr)
+        .map_err(|_| Error::NotEnoughMemory)?;
+    Ok(rsdp_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn read_u64(data: &[u8], offset: usize) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&data[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    // Reads the table at `addr`, checking its signature and checksum.
+    fn read_table(mem: &GuestMemoryMmap, addr: u64, signature: &[u8; 4]) -> Vec<u8> {
+        let mut header = [0u8; SDT_HEADER_LEN];
+        mem.read_slice(&mut header, GuestAddress(addr)).unwrap();
+        assert_eq!(&header[..4], signature);
+        let mut table = vec![0u8; read_u32(&header, SDT_LENGTH) as usize];
+        mem.read_slice(&mut table, GuestAddress(addr)).unwrap();
+        assert_eq!(checksum(&table), 0);
+        table
+    }
+
+    #[test]
+    fn test_setup_acpi_tables() {
+        let num_cpus = 4;
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 1 << 20)]).unwrap();
+        let rsdp_addr = setup_acpi_tables(&mem, num_cpus, num_cpus, false).unwrap();
+        assert_eq!(rsdp_addr, GuestAddress(layout::RSDP_START));
+
+        let mut rsdp = [0u8; RSDP_LEN];
+        mem.read_slice(&mut rsdp, rsdp_addr).unwrap();
+        assert_eq!(&rsdp[..8], b"RSD PTR ");
+        assert_eq!(checksum(&rsdp[..RSDP_V1_LEN]), 0);
+        assert_eq!(checksum(&rsdp), 0);
+
+        let xsdt = read_table(&mem, read_u64(&rsdp, 24), b"XSDT");
+        assert_eq!(xsdt.len(), SDT_HEADER_LEN + 16);
+
+        let fadt = read_table(&mem, read_u64(&xsdt, SDT_HEADER_LEN), b"FACP");
+        assert_eq!(fadt.len(), FADT_LEN);
+        assert_eq!(
+            u64::from(read_u32(&fadt, FADT_PM1A_EVT_BLK)),
+            layout::ACPI_PM_START
+        );
+        let dsdt = read_table(&mem, read_u64(&fadt, FADT_X_DSDT), b"DSDT");
+        assert_eq!(&dsdt[SDT_HEADER_LEN..], &DSDT_S5);
+
+        let madt = read_table(&mem, read_u64(&xsdt, SDT_HEADER_LEN + 8), b"APIC");
+        let lapics = madt[SDT_HEADER_LEN + 8..]
+            .chunks(MADT_LAPIC_LEN as usize)
+            .take_while(|entry| entry[0] == MADT_LAPIC)
+            .count();
+        assert_eq!(lapics, num_cpus as usize);
+        assert_eq!(
+            madt.len(),
+            SDT_HEADER_LEN
+                + 8
+                + num_cpus as usize * MADT_LAPIC_LEN as usize
+                + MADT_IOAPIC_LEN as usize
+                + MADT_INT_SRC_OVERRIDE_LEN as usize
+        );
+    }
+
+    #[test]
+    fn test_pci_tables() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 1 << 20)]).unwrap();
+        let rsdp_addr = setup_acpi_tables(&mem, 1, 1, true).unwrap();
+        let mut rsdp = [0u8; RSDP_LEN];
+        mem.read_slice(&mut rsdp, rsdp_addr).unwrap();
+
+        let xsdt = read_table(&mem, read_u64(&rsdp, 24), b"XSDT");
+        assert_eq!(xsdt.len(), SDT_HEADER_LEN + 24);
+        let mcfg = read_table(&mem, read_u64(&xsdt, SDT_HEADER_LEN + 16), b"MCFG");
+        assert_eq!(mcfg.len(), SDT_HEADER_LEN + MCFG_RESERVED_LEN + 16);
+        assert_eq!(
+            read_u64(&mcfg, SDT_HEADER_LEN + MCFG_RESERVED_LEN),
+            layout::PCI_MMCONFIG_START
+        );
+
+        let fadt = read_table(&mem, read_u64(&xsdt, SDT_HEADER_LEN), b"FACP");
+        let dsdt = read_table(&mem, read_u64(&fadt, FADT_X_DSDT), b"DSDT");
+        let body = &dsdt[SDT_HEADER_LEN..];
+        assert_eq!(&body[..DSDT_S5.len()], &DSDT_S5);
+        let pci = &body[DSDT_S5.len()..];
+        assert_eq!(pci, dsdt_pci_host_bridge().as_slice());
+        // The scope covers the whole host bridge device.
+        assert_eq!(pci[0], AML_SCOPE_OP);
+        assert_eq!(
+            usize::from(pci[1] & 0xf) | usize::from(pci[2]) << 4,
+            pci.len() - 1
+        );
+    }
+
+    #[test]
+    fn test_cpu_hotplug_tables() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 1 << 20)]).unwrap();
+        let rsdp_addr = setup_acpi_tables(&mem, 4, 2, false).unwrap();
+        let mut rsdp = [0u8; RSDP_LEN];
+        mem.read_slice(&mut rsdp, rsdp_addr).unwrap();
+        let xsdt = read_table(&mem, read_u64(&rsdp, 24), b"XSDT");
+
+        // The vCPUs past the boot ones are disabled until they are hotplugged.
+        let madt = read_table(&mem, read_u64(&xsdt, SDT_HEADER_LEN + 8), b"APIC");
+        let flags: Vec<u32> = madt[SDT_HEADER_LEN + 8..]
+            .chunks(MADT_LAPIC_LEN as usize)
+            .take(4)
+            .map(|entry| read_u32(entry, 4))
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                MADT_LAPIC_ENABLED,
+                MADT_LAPIC_ENABLED,
+                MADT_LAPIC_DISABLED,
+                MADT_LAPIC_DISABLED
+            ]
+        );
+
+        let fadt = read_table(&mem, read_u64(&xsdt, SDT_HEADER_LEN), b"FACP");
+        let dsdt = read_table(&mem, read_u64(&fadt, FADT_X_DSDT), b"DSDT");
+        let body = &dsdt[SDT_HEADER_LEN..];
+        assert_eq!(&body[..DSDT_S5.len()], &DSDT_S5);
+        let hotplug = &body[DSDT_S5.len()..];
+        assert_eq!(hotplug, dsdt_cpu_hotplug(4).as_slice());
+        assert_eq!(hotplug[0], AML_SCOPE_OP);
+        assert_eq!(
+            usize::from(hotplug[1] & 0xf) | usize::from(hotplug[2]) << 4,
+            hotplug.len() - 1
+        );
+        // There is a processor device per vCPU.
+        for index in 0..4 {
+            let name = cpu_device_name(index);
+            assert!(hotplug.windows(4).any(|window| window == &name[..]));
+        }
+        assert!(!hotplug.windows(4).any(|window| window == &b"C004"[..]));
+
+        // Without vCPUs to hotplug, the DSDT has no processor devices.
+        setup_acpi_tables(&mem, 4, 4, false).unwrap();
+        let dsdt = read_table(&mem, read_u64(&fadt, FADT_X_DSDT), b"DSDT");
+        assert_eq!(&dsdt[SDT_HEADER_LEN..], &DSDT_S5);
+    }
+
+    #[test]
+    fn test_aml_encoding() {
+        assert_eq!(aml_pkg_length(0), vec![1]);
+        assert_eq!(aml_pkg_length(62), vec![63]);
+        assert_eq!(aml_pkg_length(63), vec![0x41, 0x04]);
+        assert_eq!(aml_pkg_length(0x1000), vec![0x83, 0x00, 0x01]);
+
+        assert_eq!(aml_integer(0), vec![AML_ZERO_OP]);
+        assert_eq!(aml_integer(1), vec![AML_ONE_OP]);
+        assert_eq!(aml_integer(0x34), vec![AML_BYTE_PREFIX, 0x34]);
+        assert_eq!(aml_integer(0x1234), vec![AML_WORD_PREFIX, 0x34, 0x12]);
+        assert_eq!(
+            aml_integer(0x0a08_d041),
+            vec![AML_DWORD_PREFIX, 0x41, 0xd0, 0x08, 0x0a]
+        );
+
+        assert_eq!(eisa_id(b"PNP0A08"), 0x080a_d041);
+        assert_eq!(eisa_id(b"PNP0A03"), 0x030a_d041);
+
+        assert_eq!(aml_string("ACPI0007"), b"\x0dACPI0007\x00".to_vec());
+        assert_eq!(
+            aml_path(&[b"_SB_", b"CPUS"]),
+            b"\\\x2f\x02_SB_CPUS".to_vec()
+        );
+        assert_eq!(
+            aml_method(b"CSTA", 1, true, &aml_return(&[AML_LOCAL0_OP])),
+            vec![
+                AML_METHOD_OP,
+                0x08,
+                b'C',
+                b'S',
+                b'T',
+                b'A',
+                0x09,
+                0xa4,
+                0x60
+            ]
+        );
+        assert_eq!(
+            aml_if(
+                &aml_equal(b"CPEN", &[AML_ONE_OP]),
+                &aml_store(&[AML_ONE_OP], b"CEJF")
+            ),
+            vec![
+                AML_IF_OP, 0x0d, 0x93, b'C', b'P', b'E', b'N', 0x01, 0x70, 0x01, b'C', b'E', b'J',
+                b'F'
+            ]
+        );
+        assert_eq!(
+            aml_and(b"GDAT", &[AML_ONE_OP]),
+            vec![0x7b, b'G', b'D', b'A', b'T', 0x01, 0x00]
+        );
+        assert_eq!(
+            aml_notify(b"C001", AML_NOTIFY_EJECT_REQUEST),
+            vec![0x86, b'C', b'0', b'0', b'1', 0x0a, 0x03]
+        );
+        assert_eq!(aml_mutex(b"CPLK"), b"\x5b\x01CPLK\x00".to_vec());
+        assert_eq!(aml_acquire(b"CPLK"), b"\x5b\x23CPLK\xff\xff".to_vec());
+        assert_eq!(aml_release(b"CPLK"), b"\x5b\x27CPLK".to_vec());
+        assert_eq!(
+            aml_io_region(b"PRST", 0xd10, 2),
+            b"\x5b\x80PRST\x01\x0b\x10\x0d\x0a\x02".to_vec()
+        );
+        assert_eq!(
+            aml_field(b"PRST", &[(b"CSEL", 8), (b"CPEN", 1)]),
+            b"\x5b\x81\x10PRST\x41CSEL\x08CPEN\x01".to_vec()
+        );
+        assert_eq!(&cpu_device_name(0x1f), b"C01F");
+
+        let crs = pci_host_bridge_crs();
+        assert_eq!(crs.len(), 52);
+        assert_eq!(&crs[crs.len() - 2..], &RES_END_TAG);
+    }
+
+    #[test]
+    fn test_not_enough_memory() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        assert_eq!(
+            setup_acpi_tables(&mem, 1, 1, false).unwrap_err(),
+            Error::NotEnoughMemory
+        );
+    }
+}
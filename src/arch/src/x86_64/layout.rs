@@ -12,6 +12,13 @@ pub const BOOT_STACK_POINTER: u64 = 0x8ff0;
 
 /// Kernel command line start address.
 pub const CMDLINE_START: u64 = 0x20000;
+
+/// Address of the PVH start info structure.
+pub const PVH_INFO_START: u64 = 0x6000;
+/// Address of the PVH module list, describing the initrd.
+pub const MODLIST_START: u64 = 0x6040;
+/// Address of the PVH memory map.
+pub const MEMMAP_START: u64 = 0x6100;
 /// Kernel command line start address maximum size.
 pub const CMDLINE_MAX_SIZE: usize = 0x10000;
 
@@ -21,11 +28,51 @@ pub const HIMEM_START: u64 = 0x0010_0000; //1 MB.
 // Typically, on x86 systems 24 IRQs are used (0-23).
 /// First usable IRQ ID for virtio device interrupts on x86_64.
 pub const IRQ_BASE: u32 = 5;
-/// Last usable IRQ ID for virtio device interrupts on x86_64.
-pub const IRQ_MAX: u32 = 23;
+/// Last usable IRQ ID for virtio device interrupts on x86_64. The last two IRQs are the ACPI
+/// Generic Event Device and the ACPI SCI.
+pub const IRQ_MAX: u32 = 21;
+/// IRQ of the ACPI Generic Event Device, which notifies the guest of the vCPU hotplug events.
+pub const GED_IRQ: u32 = 22;
+/// IRQ of the ACPI System Control Interrupt.
+pub const ACPI_SCI_IRQ: u32 = 23;
+
+/// Address of the ACPI Root System Description Pointer, in the BIOS area the guest scans for it.
+pub const RSDP_START: u64 = 0x000e_0000;
+/// Address of the ACPI tables the RSDP points to.
+pub const ACPI_TABLES_START: u64 = 0x000e_1000;
+/// First I/O port of the ACPI PM registers: the PM1 event block, followed by the PM1 control
+/// block.
+pub const ACPI_PM_START: u64 = 0x600;
+/// First I/O port of the vCPU hotplug controller, whose registers are followed by the event
+/// register of the ACPI Generic Event Device.
+pub const CPU_HOTPLUG_START: u64 = 0xd10;
+
+/// Address for the TSS setup. The TSS and the EPT identity map below it are kept out of the
+/// 16 MiB below 4 GiB, where a firmware is loaded.
+pub const KVM_TSS_ADDRESS: u64 = 0xfeff_d000;
+
+/// Address of the identity map KVM uses for running real mode code with EPT.
+pub const KVM_IDENTITY_MAP_ADDRESS: u64 = 0xfeff_c000;
+
+/// End of the firmware, whose last bytes hold the reset vector.
+pub const FIRMWARE_END: u64 = 1 << 32;
+
+/// Maximum size of the firmware along with its variable store, which lies right below it.
+pub const FIRMWARE_MAX_SIZE: u64 = 16 << 20;
+
+/// Address of the TPM Command Response Buffer interface.
+pub const TPM_CRB_START: u64 = 0xfed4_0000;
 
-/// Address for the TSS setup.
-pub const KVM_TSS_ADDRESS: u64 = 0xfffb_d000;
+/// Start of the PCI Express configuration space (ECAM) window. It covers bus 0 only, and lies in
+/// the smallest MMIO gap, past the MMIO device slots.
+pub const PCI_MMCONFIG_START: u64 = 0xf800_0000;
+/// Size of the PCI Express configuration space window, 1 MiB per bus.
+pub const PCI_MMCONFIG_SIZE: u64 = 1 << 20;
+/// Start of the window holding the BARs of the PCI devices, right after the ECAM window.
+pub const PCI_MMIO_START: u64 = PCI_MMCONFIG_START + PCI_MMCONFIG_SIZE;
+/// Size of the window holding the BARs of the PCI devices, which ends below the firmware and the
+/// interrupt controllers.
+pub const PCI_MMIO_SIZE: u64 = 0xfc00_0000 - PCI_MMIO_START;
 
 /// The 'zero page', a.k.a linux kernel bootparams.
 pub const ZERO_PAGE_START: u64 = 0x7000;
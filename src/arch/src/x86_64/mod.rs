@@ -5,6 +5,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+mod acpi;
 mod gdt;
 /// Contains logic for setting up Advanced Programmable Interrupt Controller (local version).
 pub mod interrupts;
@@ -16,8 +17,15 @@ pub mod msr;
 /// Logic for configuring x86_64 registers.
 pub mod regs;
 
+use std::cmp::max;
+use std::mem;
+
 use crate::InitrdConfig;
-use arch_gen::x86::bootparam::{boot_params, E820_RAM};
+use arch_gen::x86::bootparam::{boot_params, setup_header, E820_RAM, E820_RESERVED};
+use arch_gen::x86::start_info::{
+    hvm_memmap_table_entry, hvm_modlist_entry, hvm_start_info, XEN_HVM_MEMMAP_TYPE_RAM,
+    XEN_HVM_MEMMAP_TYPE_RESERVED, XEN_HVM_START_MAGIC_VALUE,
+};
 use vm_memory::{
     Address, ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion,
 };
@@ -33,9 +41,23 @@ struct BootParamsWrapper(boot_params);
 // It is safe to initialize BootParamsWrap which is a wrapper over `boot_params` (a series of ints).
 unsafe impl ByteValued for BootParamsWrapper {}
 
+#[derive(Copy, Clone, Default)]
+struct StartInfoWrapper(hvm_start_info);
+#[derive(Copy, Clone, Default)]
+struct ModlistEntryWrapper(hvm_modlist_entry);
+#[derive(Copy, Clone, Default)]
+struct MemmapTableEntryWrapper(hvm_memmap_table_entry);
+
+// It is safe to initialize the wrappers over the PVH structures, which are series of ints.
+unsafe impl ByteValued for StartInfoWrapper {}
+unsafe impl ByteValued for ModlistEntryWrapper {}
+unsafe impl ByteValued for MemmapTableEntryWrapper {}
+
 /// Errors thrown while configuring x86_64 system.
 #[derive(Debug, PartialEq)]
 pub enum Error {
+    /// Error writing the ACPI tables to memory.
+    AcpiSetup(acpi::Error),
     /// Invalid e820 setup params.
     E820Configuration,
     /// Error writing MP table to memory.
@@ -44,28 +66,45 @@ pub enum Error {
     ZeroPageSetup,
     /// Failed to compute initrd address.
     InitrdAddress,
+    /// Error writing the PVH memory map to guest memory.
+    MemmapTableSetup,
+    /// Error writing the PVH module list to guest memory.
+    ModlistSetup,
+    /// Error writing the PVH start info to guest memory.
+    StartInfoSetup,
 }
 
 // Where BIOS/VGA magic would live on a real PC.
 const EBDA_START: u64 = 0x9fc00;
 const FIRST_ADDR_PAST_32BITS: u64 = 1 << 32;
-const MEM_32BIT_GAP_SIZE: u64 = 768 << 20;
-/// The start of the memory area reserved for MMIO devices.
-pub const MMIO_MEM_START: u64 = FIRST_ADDR_PAST_32BITS - MEM_32BIT_GAP_SIZE;
+/// Default size of the memory gap at the end of the 32 bit address space, reserved for the MMIO
+/// devices.
+pub const DEFAULT_MMIO_GAP_SIZE: u64 = 768 << 20;
+/// Smallest size of that gap, which also holds the firmware and the interrupt controllers.
+pub const MIN_MMIO_GAP_SIZE: u64 = 256 << 20;
+/// Largest size of that gap, leaving 1 GiB of memory below it for the kernel and the initrd.
+pub const MAX_MMIO_GAP_SIZE: u64 = 3 << 30;
+
+/// Returns the start of the memory gap reserved for MMIO devices, given its size. The gap ends
+/// at 4 GiB.
+pub fn mmio_mem_start(gap_size: u64) -> u64 {
+    FIRST_ADDR_PAST_32BITS - gap_size
+}
 
 /// Returns a Vec of the valid memory addresses.
 /// These should be used to configure the GuestMemoryMmap structure for the platform.
 /// For x86_64 all addresses are valid from the start of the kernel except a
-/// carve out at the end of 32bit address space.
-pub fn arch_memory_regions(size: usize) -> Vec<(GuestAddress, usize)> {
-    // It's safe to cast MMIO_MEM_START to usize because it fits in a u32 variable
+/// carve out at the end of 32bit address space, holding the MMIO devices of `mmio`.
+pub fn arch_memory_regions(size: usize, mmio: &crate::MmioLayout) -> Vec<(GuestAddress, usize)> {
+    // It's safe to cast the start of the gap to usize because it fits in a u32 variable
     // (It points to an address in the 32 bit space).
-    match size.checked_sub(MMIO_MEM_START as usize) {
+    let mmio_mem_start = mmio.start() as usize;
+    match size.checked_sub(mmio_mem_start) {
         // case1: guest memory fits before the gap
         None | Some(0) => vec![(GuestAddress(0), size)],
         // case2: guest memory extends beyond the gap
         Some(remaining) => vec![
-            (GuestAddress(0), MMIO_MEM_START as usize),
+            (GuestAddress(0), mmio_mem_start),
             (GuestAddress(FIRST_ADDR_PAST_32BITS), remaining),
         ],
     }
@@ -76,6 +115,15 @@ pub fn get_kernel_start() -> u64 {
     layout::HIMEM_START
 }
 
+/// Returns the start of the memory region which can be hot(un)plugged, given the last address
+/// of the boot memory. The region is kept above the 32 bit address space and aligned to
+/// `HOTPLUG_MEM_ALIGNMENT`, so that the guest can add it as whole memory sections.
+pub fn hotplug_memory_start(boot_last_addr: GuestAddress) -> GuestAddress {
+    let aligned_start = (boot_last_addr.raw_value() + super::HOTPLUG_MEM_ALIGNMENT)
+        & !(super::HOTPLUG_MEM_ALIGNMENT - 1);
+    GuestAddress(max(aligned_start, FIRST_ADDR_PAST_32BITS))
+}
+
 /// Returns the memory address where the initrd could be loaded.
 pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> super::Result<u64> {
     let first_region = guest_mem
@@ -92,6 +140,26 @@ pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> supe
     Ok(align_to_pagesize(lowmem_size - initrd_size) as u64)
 }
 
+/// The boot protocol used for starting the guest kernel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BootProtocol {
+    /// The Linux 64-bit boot protocol: the kernel starts in long mode, and finds its boot
+    /// parameters in the zero page.
+    LinuxBoot,
+    /// The PVH boot protocol: the kernel starts in 32-bit protected mode, and finds its boot
+    /// parameters in a start info structure.
+    PvhBoot,
+}
+
+/// The address at which the vCPUs start the guest kernel, and the protocol they follow.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EntryPoint {
+    /// The address of the first instruction of the kernel.
+    pub entry_addr: GuestAddress,
+    /// The boot protocol of the kernel.
+    pub protocol: BootProtocol,
+}
+
 /// Configures the system and should be called once per vm before starting vcpu threads.
 ///
 /// # Arguments
@@ -101,70 +169,88 @@ pub fn initrd_load_addr(guest_mem: &GuestMemoryMmap, initrd_size: usize) -> supe
 /// * `cmdline_size` - Size of the kernel command line in bytes including the null terminator.
 /// * `initrd` - Information about where the ramdisk image was loaded in the `guest_mem`.
 /// * `num_cpus` - Number of virtual CPUs the guest will have.
+/// * `boot_cpus` - Number of virtual CPUs the guest brings up at boot, the others being
+///   hotplugged.
+/// * `boot_prot` - The boot protocol of the kernel.
+/// * `setup_header` - The setup header of the kernel image, for a bzImage.
+/// * `pci_enabled` - Whether the guest has a PCI root complex.
+#[allow(clippy::too_many_arguments)]
 pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
     cmdline_addr: GuestAddress,
     cmdline_size: usize,
     initrd: &Option<InitrdConfig>,
     num_cpus: u8,
+    boot_cpus: u8,
+    boot_prot: BootProtocol,
+    setup_header: Option<&setup_header>,
+    pci_enabled: bool,
+) -> super::Result<()> {
+    // Note that this puts the mptable at the last 1k of Linux's 640k base RAM
+    mptable::setup_mptable(guest_mem, boot_cpus).map_err(Error::MpTableSetup)?;
+    // The MP table is kept for guests booted with `acpi=off`, which can't hotplug vCPUs. Linux
+    // finds the RSDP by scanning the BIOS area, while PVH guests are given its address.
+    let rsdp_addr = acpi::setup_acpi_tables(guest_mem, num_cpus, boot_cpus, pci_enabled)
+        .map_err(Error::AcpiSetup)?;
+    // The guest only uses the ECAM window if it is reserved in the memory map.
+    let reserved_ranges = if pci_enabled {
+        vec![(layout::PCI_MMCONFIG_START, layout::PCI_MMCONFIG_SIZE)]
+    } else {
+        vec![]
+    };
+
+    match boot_prot {
+        BootProtocol::LinuxBoot => configure_64bit_boot(
+            guest_mem,
+            cmdline_addr,
+            cmdline_size,
+            initrd,
+            setup_header,
+            &reserved_ranges,
+        ),
+        BootProtocol::PvhBoot => {
+            configure_pvh(guest_mem, cmdline_addr, initrd, rsdp_addr, &reserved_ranges)
+        }
+    }
+}
+
+// Writes the boot parameters of the Linux 64-bit boot protocol in the zero page.
+fn configure_64bit_boot(
+    guest_mem: &GuestMemoryMmap,
+    cmdline_addr: GuestAddress,
+    cmdline_size: usize,
+    initrd: &Option<InitrdConfig>,
+    setup_header: Option<&setup_header>,
+    reserved_ranges: &[(u64, u64)],
 ) -> super::Result<()> {
     const KERNEL_BOOT_FLAG_MAGIC: u16 = 0xaa55;
     const KERNEL_HDR_MAGIC: u32 = 0x5372_6448;
     const KERNEL_LOADER_OTHER: u8 = 0xff;
     const KERNEL_MIN_ALIGNMENT_BYTES: u32 = 0x0100_0000; // Must be non-zero.
-    let first_addr_past_32bits = GuestAddress(FIRST_ADDR_PAST_32BITS);
-    let end_32bit_gap_start = GuestAddress(MMIO_MEM_START);
-
-    let himem_start = GuestAddress(layout::HIMEM_START);
-
-    // Note that this puts the mptable at the last 1k of Linux's 640k base RAM
-    mptable::setup_mptable(guest_mem, num_cpus).map_err(Error::MpTableSetup)?;
 
     let mut params: BootParamsWrapper = BootParamsWrapper(boot_params::default());
 
+    // The decompressor of a bzImage reads the fields of its own setup header, such as its
+    // alignment and the size it needs for decompressing itself.
+    match setup_header {
+        Some(hdr) => params.0.hdr = *hdr,
+        None => params.0.hdr.kernel_alignment = KERNEL_MIN_ALIGNMENT_BYTES,
+    }
     params.0.hdr.type_of_loader = KERNEL_LOADER_OTHER;
     params.0.hdr.boot_flag = KERNEL_BOOT_FLAG_MAGIC;
     params.0.hdr.header = KERNEL_HDR_MAGIC;
     params.0.hdr.cmd_line_ptr = cmdline_addr.raw_value() as u32;
     params.0.hdr.cmdline_size = cmdline_size as u32;
-    params.0.hdr.kernel_alignment = KERNEL_MIN_ALIGNMENT_BYTES;
     if let Some(initrd_config) = initrd {
         params.0.hdr.ramdisk_image = initrd_config.address.raw_value() as u32;
         params.0.hdr.ramdisk_size = initrd_config.size as u32;
     }
 
-    add_e820_entry(&mut params.0, 0, EBDA_START, E820_RAM)?;
-
-    let last_addr = guest_mem.last_addr();
-    if last_addr < end_32bit_gap_start {
-        add_e820_entry(
-            &mut params.0,
-            himem_start.raw_value() as u64,
-            // it's safe to use unchecked_offset_from because
-            // mem_end > himem_start
-            last_addr.unchecked_offset_from(himem_start) as u64 + 1,
-            E820_RAM,
-        )?;
-    } else {
-        add_e820_entry(
-            &mut params.0,
-            himem_start.raw_value(),
-            // it's safe to use unchecked_offset_from because
-            // end_32bit_gap_start > himem_start
-            end_32bit_gap_start.unchecked_offset_from(himem_start),
-            E820_RAM,
-        )?;
-
-        if last_addr > first_addr_past_32bits {
-            add_e820_entry(
-                &mut params.0,
-                first_addr_past_32bits.raw_value(),
-                // it's safe to use unchecked_offset_from because
-                // mem_end > first_addr_past_32bits
-                last_addr.unchecked_offset_from(first_addr_past_32bits) + 1,
-                E820_RAM,
-            )?;
-        }
+    for (addr, size) in ram_ranges(guest_mem) {
+        add_e820_entry(&mut params.0, addr, size, E820_RAM)?;
+    }
+    for (addr, size) in reserved_ranges {
+        add_e820_entry(&mut params.0, *addr, *size, E820_RESERVED)?;
     }
 
     let zero_page_addr = GuestAddress(layout::ZERO_PAGE_START);
@@ -175,6 +261,92 @@ pub fn configure_system(
     Ok(())
 }
 
+// Writes the start info structure of the PVH boot protocol, with the memory map and the
+// initrd it points to.
+fn configure_pvh(
+    guest_mem: &GuestMemoryMmap,
+    cmdline_addr: GuestAddress,
+    initrd: &Option<InitrdConfig>,
+    rsdp_addr: GuestAddress,
+    reserved_ranges: &[(u64, u64)],
+) -> super::Result<()> {
+    // The version of the start info structure having a memory map.
+    const XEN_HVM_START_INFO_VERSION: u32 = 1;
+
+    let mut start_info = StartInfoWrapper(hvm_start_info {
+        magic: XEN_HVM_START_MAGIC_VALUE,
+        version: XEN_HVM_START_INFO_VERSION,
+        cmdline_paddr: cmdline_addr.raw_value(),
+        memmap_paddr: layout::MEMMAP_START,
+        rsdp_paddr: rsdp_addr.raw_value(),
+        ..Default::default()
+    });
+
+    if let Some(initrd_config) = initrd {
+        let modlist_entry = ModlistEntryWrapper(hvm_modlist_entry {
+            paddr: initrd_config.address.raw_value(),
+            size: initrd_config.size as u64,
+            ..Default::default()
+        });
+        guest_mem
+            .write_obj(modlist_entry, GuestAddress(layout::MODLIST_START))
+            .map_err(|_| Error::ModlistSetup)?;
+        start_info.0.nr_modules = 1;
+        start_info.0.modlist_paddr = layout::MODLIST_START;
+    }
+
+    let ranges: Vec<(u64, u64, u32)> = ram_ranges(guest_mem)
+        .into_iter()
+        .map(|(addr, size)| (addr, size, XEN_HVM_MEMMAP_TYPE_RAM))
+        .chain(
+            reserved_ranges
+                .iter()
+                .map(|(addr, size)| (*addr, *size, XEN_HVM_MEMMAP_TYPE_RESERVED)),
+        )
+        .collect();
+    for (index, (addr, size, type_)) in ranges.iter().enumerate() {
+        let memmap_entry = MemmapTableEntryWrapper(hvm_memmap_table_entry {
+            addr: *addr,
+            size: *size,
+            type_: *type_,
+            ..Default::default()
+        });
+        let entry_addr = GuestAddress(layout::MEMMAP_START)
+            .checked_add((index * mem::size_of::<hvm_memmap_table_entry>()) as u64)
+            .ok_or(Error::MemmapTableSetup)?;
+        guest_mem
+            .write_obj(memmap_entry, entry_addr)
+            .map_err(|_| Error::MemmapTableSetup)?;
+    }
+    start_info.0.memmap_entries = ranges.len() as u32;
+
+    guest_mem
+        .write_obj(start_info, GuestAddress(layout::PVH_INFO_START))
+        .map_err(|_| Error::StartInfoSetup)?;
+
+    Ok(())
+}
+
+// Returns the ranges of the guest memory the guest can use as RAM, as (address, size) pairs.
+// They follow the memory regions, whose layout depends on the size of the MMIO gap, leaving out
+// the BIOS area of the first one.
+fn ram_ranges(guest_mem: &GuestMemoryMmap) -> Vec<(u64, u64)> {
+    let mut ranges = vec![(0, EBDA_START)];
+    let _: std::result::Result<(), ()> = guest_mem.with_regions_mut(|_, region| {
+        let start = region.start_addr().raw_value();
+        let end = start + region.len();
+        if start < layout::HIMEM_START {
+            if end > layout::HIMEM_START {
+                ranges.push((layout::HIMEM_START, end - layout::HIMEM_START));
+            }
+        } else {
+            ranges.push((start, region.len()));
+        }
+        Ok(())
+    });
+    ranges
+}
+
 /// Add an e820 region to the e820 map.
 /// Returns Ok(()) if successful, or an error if there is no space left in the map.
 fn add_e820_entry(
@@ -198,11 +370,12 @@ fn add_e820_entry(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::MmioLayout;
     use arch_gen::x86::bootparam::e820entry;
 
     #[test]
     fn regions_lt_4gb() {
-        let regions = arch_memory_regions(1usize << 29);
+        let regions = arch_memory_regions(1usize << 29, &MmioLayout::default());
         assert_eq!(1, regions.len());
         assert_eq!(GuestAddress(0), regions[0].0);
         assert_eq!(1usize << 29, regions[0].1);
@@ -210,17 +383,44 @@ mod tests {
 
     #[test]
     fn regions_gt_4gb() {
-        let regions = arch_memory_regions((1usize << 32) + 0x8000);
+        let regions = arch_memory_regions((1usize << 32) + 0x8000, &MmioLayout::default());
         assert_eq!(2, regions.len());
         assert_eq!(GuestAddress(0), regions[0].0);
         assert_eq!(GuestAddress(1u64 << 32), regions[1].0);
     }
 
+    #[test]
+    fn test_hotplug_memory_start() {
+        // Boot memory which fits before the gap.
+        let regions = arch_memory_regions(128 << 20, &MmioLayout::default());
+        let mem = GuestMemoryMmap::from_ranges(&regions).unwrap();
+        assert_eq!(
+            hotplug_memory_start(mem.last_addr()),
+            GuestAddress(FIRST_ADDR_PAST_32BITS)
+        );
+
+        // Boot memory which extends beyond the gap.
+        let last_addr = GuestAddress(FIRST_ADDR_PAST_32BITS + (512 << 20) - 1);
+        assert_eq!(hotplug_memory_start(last_addr), GuestAddress(5 << 30));
+        let last_addr = GuestAddress(FIRST_ADDR_PAST_32BITS + (1 << 30) - 1);
+        assert_eq!(hotplug_memory_start(last_addr), GuestAddress(5 << 30));
+    }
+
     #[test]
     fn test_system_configuration() {
         let no_vcpus = 4;
         let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
-        let config_err = configure_system(&gm, GuestAddress(0), 0, &None, 1);
+        let config_err = configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            1,
+            1,
+            BootProtocol::LinuxBoot,
+            None,
+            false,
+        );
         assert!(config_err.is_err());
         assert_eq!(
             config_err.unwrap_err(),
@@ -229,21 +429,171 @@ mod tests {
 
         // Now assigning some memory that falls before the 32bit memory hole.
         let mem_size = 128 << 20;
-        let arch_mem_regions = arch_memory_regions(mem_size);
+        let arch_mem_regions = arch_memory_regions(mem_size, &MmioLayout::default());
         let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            no_vcpus,
+            no_vcpus,
+            BootProtocol::LinuxBoot,
+            None,
+            false,
+        )
+        .unwrap();
 
         // Now assigning some memory that is equal to the start of the 32bit memory hole.
         let mem_size = 3328 << 20;
-        let arch_mem_regions = arch_memory_regions(mem_size);
+        let arch_mem_regions = arch_memory_regions(mem_size, &MmioLayout::default());
         let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            no_vcpus,
+            no_vcpus,
+            BootProtocol::LinuxBoot,
+            None,
+            false,
+        )
+        .unwrap();
 
         // Now assigning some memory that falls after the 32bit memory hole.
         let mem_size = 3330 << 20;
-        let arch_mem_regions = arch_memory_regions(mem_size);
+        let arch_mem_regions = arch_memory_regions(mem_size, &MmioLayout::default());
         let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), 0, &None, no_vcpus).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            no_vcpus,
+            no_vcpus,
+            BootProtocol::LinuxBoot,
+            None,
+            false,
+        )
+        .unwrap();
+
+        // The setup header of a bzImage is passed to the kernel.
+        let hdr = setup_header {
+            kernel_alignment: 0x20_0000,
+            init_size: 0x100_0000,
+            ..Default::default()
+        };
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            no_vcpus,
+            no_vcpus,
+            BootProtocol::LinuxBoot,
+            Some(&hdr),
+            false,
+        )
+        .unwrap();
+        let params: BootParamsWrapper = gm.read_obj(GuestAddress(layout::ZERO_PAGE_START)).unwrap();
+        assert_eq!({ params.0.hdr.kernel_alignment }, 0x20_0000);
+        assert_eq!({ params.0.hdr.init_size }, 0x100_0000);
+        assert_eq!({ params.0.hdr.header }, 0x5372_6448);
+    }
+
+    #[test]
+    fn test_pvh_configuration() {
+        let arch_mem_regions = arch_memory_regions(3330 << 20, &MmioLayout::default());
+        let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
+        let initrd = Some(InitrdConfig {
+            address: GuestAddress(0x20_0000),
+            size: 0x1000,
+        });
+        configure_system(
+            &gm,
+            GuestAddress(layout::CMDLINE_START),
+            0,
+            &initrd,
+            1,
+            1,
+            BootProtocol::PvhBoot,
+            None,
+            false,
+        )
+        .unwrap();
+
+        let start_info: StartInfoWrapper =
+            gm.read_obj(GuestAddress(layout::PVH_INFO_START)).unwrap();
+        assert_eq!(start_info.0.magic, XEN_HVM_START_MAGIC_VALUE);
+        assert_eq!(start_info.0.cmdline_paddr, layout::CMDLINE_START);
+        assert_eq!(start_info.0.rsdp_paddr, layout::RSDP_START);
+        assert_eq!(start_info.0.nr_modules, 1);
+        assert_eq!(start_info.0.memmap_entries, 3);
+
+        let modlist_entry: ModlistEntryWrapper =
+            gm.read_obj(GuestAddress(layout::MODLIST_START)).unwrap();
+        assert_eq!(modlist_entry.0.paddr, 0x20_0000);
+        assert_eq!(modlist_entry.0.size, 0x1000);
+
+        let last_entry: MemmapTableEntryWrapper = gm
+            .read_obj(GuestAddress(
+                layout::MEMMAP_START + 2 * mem::size_of::<hvm_memmap_table_entry>() as u64,
+            ))
+            .unwrap();
+        assert_eq!(last_entry.0.addr, FIRST_ADDR_PAST_32BITS);
+        assert_eq!(last_entry.0.size, 2 << 20);
+        assert_eq!(last_entry.0.type_, XEN_HVM_MEMMAP_TYPE_RAM);
+    }
+
+    #[test]
+    fn test_pci_configuration() {
+        let arch_mem_regions = arch_memory_regions(128 << 20, &MmioLayout::default());
+        let gm = GuestMemoryMmap::from_ranges(&arch_mem_regions).unwrap();
+
+        // The ECAM window is reserved in the e820 map...
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            0,
+            &None,
+            1,
+            1,
+            BootProtocol::LinuxBoot,
+            None,
+            true,
+        )
+        .unwrap();
+        let params: BootParamsWrapper = gm.read_obj(GuestAddress(layout::ZERO_PAGE_START)).unwrap();
+        let last_entry = params.0.e820_map[params.0.e820_entries as usize - 1];
+        assert_eq!({ last_entry.addr }, layout::PCI_MMCONFIG_START);
+        assert_eq!({ last_entry.size }, layout::PCI_MMCONFIG_SIZE);
+        assert_eq!({ last_entry.type_ }, E820_RESERVED);
+
+        // ...and in the PVH memory map.
+        configure_system(
+            &gm,
+            GuestAddress(layout::CMDLINE_START),
+            0,
+            &None,
+            1,
+            1,
+            BootProtocol::PvhBoot,
+            None,
+            true,
+        )
+        .unwrap();
+        let start_info: StartInfoWrapper =
+            gm.read_obj(GuestAddress(layout::PVH_INFO_START)).unwrap();
+        assert_eq!(start_info.0.memmap_entries, 3);
+        let last_entry: MemmapTableEntryWrapper = gm
+            .read_obj(GuestAddress(
+                layout::MEMMAP_START + 2 * mem::size_of::<hvm_memmap_table_entry>() as u64,
+            ))
+            .unwrap();
+        assert_eq!(last_entry.0.addr, layout::PCI_MMCONFIG_START);
+        assert_eq!(last_entry.0.size, layout::PCI_MMCONFIG_SIZE);
+        assert_eq!(last_entry.0.type_, XEN_HVM_MEMMAP_TYPE_RESERVED);
     }
 
     #[test]
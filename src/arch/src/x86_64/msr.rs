@@ -216,8 +216,29 @@ fn create_boot_msr_entries() -> Vec<kvm_msr_entry> {
 ///
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
 pub fn setup_msrs(vcpu: &VcpuFd) -> Result<()> {
-    let entry_vec = create_boot_msr_entries();
-    let msrs = Msrs::from_entries(&entry_vec);
+    set_msr_entries(vcpu, &create_boot_msr_entries())
+}
+
+/// Configure the Model Specific Registers (MSRs) a guest hypervisor needs to turn VMX on, as the
+/// firmware of an Intel host would. The vCPU must be given the VMX CPUID bit beforehand.
+///
+/// # Arguments
+///
+/// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
+pub fn setup_nested_vmx_msrs(vcpu: &VcpuFd) -> Result<()> {
+    set_msr_entries(
+        vcpu,
+        &[kvm_msr_entry {
+            index: MSR_IA32_FEATURE_CONTROL,
+            data: u64::from(FEATURE_CONTROL_LOCKED | FEATURE_CONTROL_VMXON_ENABLED_OUTSIDE_SMX),
+            ..Default::default()
+        }],
+    )
+}
+
+// Sets the given MSRs, checking that all of them were written.
+fn set_msr_entries(vcpu: &VcpuFd, entries: &[kvm_msr_entry]) -> Result<()> {
+    let msrs = Msrs::from_entries(entries);
     vcpu.set_msrs(&msrs)
         .map_err(Error::SetModelSpecificRegisters)
         .and_then(|msrs_written| {
@@ -8,6 +8,7 @@
 use std::mem;
 
 use super::gdt::{gdt_entry, kvm_segment_from_gdt};
+use super::{BootProtocol, EntryPoint};
 use kvm_bindings::{kvm_fpu, kvm_regs, kvm_sregs};
 use kvm_ioctls::VcpuFd;
 use vm_memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
@@ -61,9 +62,24 @@ pub fn setup_fpu(vcpu: &VcpuFd) -> Result<()> {
 /// # Arguments
 ///
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
-/// * `boot_ip` - Starting instruction pointer.
-pub fn setup_regs(vcpu: &VcpuFd, boot_ip: u64) -> Result<()> {
-    let regs: kvm_regs = kvm_regs {
+/// * `entry_point` - Starting instruction pointer and boot protocol.
+pub fn setup_regs(vcpu: &VcpuFd, entry_point: EntryPoint) -> Result<()> {
+    let regs: kvm_regs = match entry_point.protocol {
+        BootProtocol::PvhBoot => kvm_regs {
+            rflags: 0x0000_0000_0000_0002u64,
+            rip: entry_point.entry_addr.raw_value(),
+            // Must point to the start info structure per the PVH boot protocol.
+            rbx: super::layout::PVH_INFO_START,
+            ..Default::default()
+        },
+        BootProtocol::LinuxBoot => linux_boot_regs(entry_point.entry_addr.raw_value()),
+    };
+
+    vcpu.set_regs(&regs).map_err(Error::SetBaseRegisters)
+}
+
+fn linux_boot_regs(boot_ip: u64) -> kvm_regs {
+    kvm_regs {
         rflags: 0x0000_0000_0000_0002u64,
         rip: boot_ip,
         // Frame pointer. It gets a snapshot of the stack pointer (rsp) so that when adjustments are
@@ -75,9 +91,7 @@ pub fn setup_regs(vcpu: &VcpuFd, boot_ip: u64) -> Result<()> {
         // Must point to zero page address per Linux ABI. This is x86_64 specific.
         rsi: super::layout::ZERO_PAGE_START as u64,
         ..Default::default()
-    };
-
-    vcpu.set_regs(&regs).map_err(Error::SetBaseRegisters)
+    }
 }
 
 /// Configures the segment registers and system page tables for a given CPU.
@@ -86,11 +100,15 @@ pub fn setup_regs(vcpu: &VcpuFd, boot_ip: u64) -> Result<()> {
 ///
 /// * `mem` - The memory that will be passed to the guest.
 /// * `vcpu` - Structure for the VCPU that holds the VCPU's fd.
-pub fn setup_sregs(mem: &GuestMemoryMmap, vcpu: &VcpuFd) -> Result<()> {
+/// * `boot_prot` - The boot protocol of the kernel.
+pub fn setup_sregs(mem: &GuestMemoryMmap, vcpu: &VcpuFd, boot_prot: BootProtocol) -> Result<()> {
     let mut sregs: kvm_sregs = vcpu.get_sregs().map_err(Error::GetStatusRegisters)?;
 
-    configure_segments_and_sregs(mem, &mut sregs)?;
-    setup_page_tables(mem, &mut sregs)?; // TODO(dgreid) - Can this be done once per system instead?
+    configure_segments_and_sregs(mem, &mut sregs, boot_prot)?;
+    // The PVH boot protocol starts the kernel with paging disabled.
+    if boot_prot == BootProtocol::LinuxBoot {
+        setup_page_tables(mem, &mut sregs)?; // TODO(dgreid) - Can this be done once per system instead?
+    }
 
     vcpu.set_sregs(&sregs).map_err(Error::SetStatusRegisters)
 }
@@ -127,13 +145,26 @@ fn write_idt_value(val: u64, guest_mem: &GuestMemoryMmap) -> Result<()> {
         .map_err(|_| Error::WriteIDT)
 }
 
-fn configure_segments_and_sregs(mem: &GuestMemoryMmap, sregs: &mut kvm_sregs) -> Result<()> {
-    let gdt_table: [u64; BOOT_GDT_MAX as usize] = [
-        gdt_entry(0, 0, 0),            // NULL
-        gdt_entry(0xa09b, 0, 0xfffff), // CODE
-        gdt_entry(0xc093, 0, 0xfffff), // DATA
-        gdt_entry(0x808b, 0, 0xfffff), // TSS
-    ];
+fn configure_segments_and_sregs(
+    mem: &GuestMemoryMmap,
+    sregs: &mut kvm_sregs,
+    boot_prot: BootProtocol,
+) -> Result<()> {
+    let gdt_table: [u64; BOOT_GDT_MAX as usize] = match boot_prot {
+        BootProtocol::LinuxBoot => [
+            gdt_entry(0, 0, 0),            // NULL
+            gdt_entry(0xa09b, 0, 0xfffff), // CODE
+            gdt_entry(0xc093, 0, 0xfffff), // DATA
+            gdt_entry(0x808b, 0, 0xfffff), // TSS
+        ],
+        // The PVH boot protocol requires flat 32-bit segments, and a 32-bit TSS.
+        BootProtocol::PvhBoot => [
+            gdt_entry(0, 0, 0),            // NULL
+            gdt_entry(0xc09b, 0, 0xfffff), // CODE
+            gdt_entry(0xc093, 0, 0xfffff), // DATA
+            gdt_entry(0x008b, 0, 0x67),    // TSS
+        ],
+    };
 
     let code_seg = kvm_segment_from_gdt(gdt_table[1], 1);
     let data_seg = kvm_segment_from_gdt(gdt_table[2], 2);
@@ -156,9 +187,18 @@ fn configure_segments_and_sregs(mem: &GuestMemoryMmap, sregs: &mut kvm_sregs) ->
     sregs.ss = data_seg;
     sregs.tr = tss_seg;
 
-    /* 64-bit protected mode */
-    sregs.cr0 |= X86_CR0_PE;
-    sregs.efer |= EFER_LME | EFER_LMA;
+    match boot_prot {
+        BootProtocol::LinuxBoot => {
+            /* 64-bit protected mode */
+            sregs.cr0 |= X86_CR0_PE;
+            sregs.efer |= EFER_LME | EFER_LMA;
+        }
+        BootProtocol::PvhBoot => {
+            /* 32-bit protected mode, without paging */
+            sregs.cr0 = X86_CR0_PE;
+            sregs.cr4 = 0;
+        }
+    }
 
     Ok(())
 }
@@ -225,6 +265,22 @@ mod tests {
         assert!(sregs.efer & EFER_LME != 0 && sregs.efer & EFER_LMA != 0);
     }
 
+    fn validate_pvh_segments_and_sregs(gm: &GuestMemoryMmap, sregs: &kvm_sregs) {
+        assert_eq!(0x0, read_u64(&gm, BOOT_GDT_OFFSET));
+        assert_eq!(0xcf_9b00_0000_ffff, read_u64(&gm, BOOT_GDT_OFFSET + 8));
+        assert_eq!(0xcf_9300_0000_ffff, read_u64(&gm, BOOT_GDT_OFFSET + 16));
+        assert_eq!(0x00_8b00_0000_0067, read_u64(&gm, BOOT_GDT_OFFSET + 24));
+
+        assert_eq!(0, sregs.cs.base);
+        assert_eq!(1, sregs.cs.db);
+        assert_eq!(0, sregs.cs.l);
+        assert_eq!(0x10, sregs.ds.selector);
+        assert_eq!(0x67, sregs.tr.limit);
+        assert_eq!(X86_CR0_PE, sregs.cr0);
+        assert_eq!(0, sregs.cr4);
+        assert!(sregs.efer & EFER_LME == 0 && sregs.efer & EFER_LMA == 0);
+    }
+
     fn validate_page_tables(gm: &GuestMemoryMmap, sregs: &kvm_sregs) {
         assert_eq!(0xa003, read_u64(&gm, PML4_START));
         assert_eq!(0xb003, read_u64(&gm, PDPTE_START));
@@ -274,7 +330,33 @@ mod tests {
             ..Default::default()
         };
 
-        setup_regs(&vcpu, expected_regs.rip).unwrap();
+        setup_regs(
+            &vcpu,
+            EntryPoint {
+                entry_addr: GuestAddress(expected_regs.rip),
+                protocol: BootProtocol::LinuxBoot,
+            },
+        )
+        .unwrap();
+
+        let actual_regs: kvm_regs = vcpu.get_regs().unwrap();
+        assert_eq!(actual_regs, expected_regs);
+
+        let expected_regs: kvm_regs = kvm_regs {
+            rflags: 0x0000_0000_0000_0002u64,
+            rip: 1,
+            rbx: super::super::layout::PVH_INFO_START,
+            ..Default::default()
+        };
+
+        setup_regs(
+            &vcpu,
+            EntryPoint {
+                entry_addr: GuestAddress(expected_regs.rip),
+                protocol: BootProtocol::PvhBoot,
+            },
+        )
+        .unwrap();
 
         let actual_regs: kvm_regs = vcpu.get_regs().unwrap();
         assert_eq!(actual_regs, expected_regs);
@@ -288,7 +370,7 @@ mod tests {
         let gm = create_guest_mem(None);
 
         assert!(vcpu.set_sregs(&Default::default()).is_ok());
-        setup_sregs(&gm, &vcpu).unwrap();
+        setup_sregs(&gm, &vcpu, BootProtocol::LinuxBoot).unwrap();
 
         let mut sregs: kvm_sregs = vcpu.get_sregs().unwrap();
         // for AMD KVM_GET_SREGS returns g = 0 for each kvm_segment.
@@ -297,6 +379,14 @@ mod tests {
 
         validate_segments_and_sregs(&gm, &sregs);
         validate_page_tables(&gm, &sregs);
+
+        let gm = create_guest_mem(None);
+        assert!(vcpu.set_sregs(&Default::default()).is_ok());
+        setup_sregs(&gm, &vcpu, BootProtocol::PvhBoot).unwrap();
+
+        let sregs: kvm_sregs = vcpu.get_sregs().unwrap();
+        validate_pvh_segments_and_sregs(&gm, &sregs);
+        assert_eq!(0, read_u64(&gm, PML4_START));
     }
 
     #[test]
@@ -341,9 +431,14 @@ mod tests {
     fn test_configure_segments_and_sregs() {
         let mut sregs: kvm_sregs = Default::default();
         let gm = create_guest_mem(None);
-        configure_segments_and_sregs(&gm, &mut sregs).unwrap();
+        configure_segments_and_sregs(&gm, &mut sregs, BootProtocol::LinuxBoot).unwrap();
 
         validate_segments_and_sregs(&gm, &sregs);
+
+        let mut sregs: kvm_sregs = Default::default();
+        configure_segments_and_sregs(&gm, &mut sregs, BootProtocol::PvhBoot).unwrap();
+
+        validate_pvh_segments_and_sregs(&gm, &sregs);
     }
 
     #[test]
@@ -0,0 +1,84 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+/*
+ * automatically generated by rust-bindgen
+ * From upstream xen include/public/arch-x86/hvm/start_info.h
+ */
+
+pub const XEN_HVM_START_MAGIC_VALUE: ::std::os::raw::c_uint = 0x336e_c578;
+pub const XEN_HVM_MEMMAP_TYPE_RAM: ::std::os::raw::c_uint = 1;
+pub const XEN_HVM_MEMMAP_TYPE_RESERVED: ::std::os::raw::c_uint = 2;
+
+pub type __u32 = ::std::os::raw::c_uint;
+pub type __u64 = ::std::os::raw::c_ulonglong;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct hvm_start_info {
+    pub magic: __u32,
+    pub version: __u32,
+    pub flags: __u32,
+    pub nr_modules: __u32,
+    pub modlist_paddr: __u64,
+    pub cmdline_paddr: __u64,
+    pub rsdp_paddr: __u64,
+    pub memmap_paddr: __u64,
+    pub memmap_entries: __u32,
+    pub reserved: __u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct hvm_modlist_entry {
+    pub paddr: __u64,
+    pub size: __u64,
+    pub cmdline_paddr: __u64,
+    pub reserved: __u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct hvm_memmap_table_entry {
+    pub addr: __u64,
+    pub size: __u64,
+    pub type_: __u32,
+    pub reserved: __u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindgen_test_layout_hvm_start_info() {
+        assert_eq!(
+            ::std::mem::size_of::<hvm_start_info>(),
+            56usize,
+            concat!("Size of: ", stringify!(hvm_start_info))
+        );
+        assert_eq!(
+            ::std::mem::align_of::<hvm_start_info>(),
+            8usize,
+            concat!("Alignment of ", stringify!(hvm_start_info))
+        );
+    }
+
+    #[test]
+    fn bindgen_test_layout_hvm_modlist_entry() {
+        assert_eq!(
+            ::std::mem::size_of::<hvm_modlist_entry>(),
+            32usize,
+            concat!("Size of: ", stringify!(hvm_modlist_entry))
+        );
+    }
+
+    #[test]
+    fn bindgen_test_layout_hvm_memmap_table_entry() {
+        assert_eq!(
+            ::std::mem::size_of::<hvm_memmap_table_entry>(),
+            24usize,
+            concat!("Size of: ", stringify!(hvm_memmap_table_entry))
+        );
+    }
+}
@@ -99,6 +99,17 @@ impl BrandString {
         }
     }
 
+    /// Generates the emulated brand string, without the host frequency.
+    ///
+    /// Unlike `from_vendor_id`, the result only depends on the vendor, not on the host CPU model.
+    pub fn stable_from_vendor_id(vendor_id: &[u8; 12]) -> BrandString {
+        match vendor_id {
+            VENDOR_ID_INTEL => BrandString::from_bytes_unchecked(BRAND_STRING_INTEL),
+            VENDOR_ID_AMD => BrandString::from_bytes_unchecked(BRAND_STRING_AMD),
+            _ => BrandString::from_bytes_unchecked(b""),
+        }
+    }
+
     /// Creates a brand string, initialized from the CPUID leaves 0x80000002 through 0x80000004
     /// of the host CPU.
     pub fn from_host_cpuid() -> Result<Self, Error> {
@@ -34,7 +34,8 @@ pub mod leaf_0x1 {
         pub const MONITOR_BITINDEX: u32 = 3;
         // CPL Qualified Debug Store
         pub const DS_CPL_SHIFT: u32 = 4;
-        // 5 = VMX (Virtual Machine Extensions)
+        // VMX = Virtual Machine Extensions
+        pub const VMX_BITINDEX: u32 = 5;
         // 6 = SMX (Safer Mode Extensions)
         // 7 = EIST (Enhanced Intel SpeedStep® technology)
         // TM2 = Thermal Monitor 2
@@ -71,9 +72,29 @@ pub mod leaf_cache_parameters {
     pub mod eax {
         use crate::bit_helper::BitRange;
 
+        pub const CACHE_TYPE_BITRANGE: BitRange = bit_range!(4, 0);
         pub const CACHE_LEVEL_BITRANGE: BitRange = bit_range!(7, 5);
+        pub const SELF_INIT_BITINDEX: u32 = 8;
         pub const MAX_CPUS_PER_CORE_BITRANGE: BitRange = bit_range!(25, 14);
     }
+
+    pub mod ebx {
+        use crate::bit_helper::BitRange;
+
+        pub const LINE_SIZE_BITRANGE: BitRange = bit_range!(11, 0);
+        pub const PARTITIONS_BITRANGE: BitRange = bit_range!(21, 12);
+        pub const WAYS_BITRANGE: BitRange = bit_range!(31, 22);
+    }
+
+    // The values of the cache type field.
+    pub const DATA_CACHE: u32 = 1;
+    pub const INSTRUCTION_CACHE: u32 = 2;
+    pub const UNIFIED_CACHE: u32 = 3;
+}
+
+// Cache and TLB Information Leaf
+pub mod leaf_0x2 {
+    pub const LEAF_NUM: u32 = 0x2;
 }
 
 // Deterministic Cache Parameters Leaf
@@ -88,6 +109,9 @@ pub mod leaf_0x4 {
 
         pub const MAX_CORES_PER_PACKAGE_BITRANGE: BitRange = bit_range!(31, 26);
     }
+
+    // inherit ebx from leaf_cache_parameters
+    pub use crate::cpu_leaf::leaf_cache_parameters::ebx;
 }
 
 // Thermal and Power Management Leaf
@@ -119,7 +143,8 @@ pub mod leaf_0x7 {
             pub const FPDP_BITINDEX: u32 = 6;
             // 7 = SMEP (Supervisor-Mode Execution Prevention if 1)
             pub const BMI2_BITINDEX: u32 = 8;
-            // 9 = Enhanced REP MOVSB/STOSB if 1
+            // Enhanced REP MOVSB/STOSB if 1
+            pub const ERMS_BITINDEX: u32 = 9;
             // 10 = INVPCID
             pub const INVPCID_BITINDEX: u32 = 10;
             pub const RTM_BITINDEX: u32 = 11;
@@ -171,9 +196,20 @@ pub mod leaf_0x7 {
             // OSPKE = If 1, OS has set CR4.PKE to enable protection keys
             pub const OSPKE_BITINDEX: u32 = 4;
             // 5 = WAITPKG
-            // 7-6 reserved
-            // 8 = GFNI
-            // 13-09 reserved
+            // AVX512_VBMI2 = AVX-512 Vector Byte Manipulation Instructions 2
+            pub const AVX512_VBMI2_BITINDEX: u32 = 6;
+            // 7 = CET_SS (CET Shadow Stack)
+            // GFNI = Galois Field instructions
+            pub const GFNI_BITINDEX: u32 = 8;
+            // VAES = Vector AES instructions
+            pub const VAES_BITINDEX: u32 = 9;
+            // VPCLMULQDQ = Vector carry-less multiplication
+            pub const VPCLMULQDQ_BITINDEX: u32 = 10;
+            // AVX512_VNNI = AVX-512 Vector Neural Network Instructions
+            pub const AVX512_VNNI_BITINDEX: u32 = 11;
+            // AVX512_BITALG = AVX-512 bit algorithms
+            pub const AVX512_BITALG_BITINDEX: u32 = 12;
+            // 13 reserved
             // AVX512_VPOPCNTDQ = Vector population count instruction (Intel® Xeon Phi™ only.)
             pub const AVX512_VPOPCNTDQ_BITINDEX: u32 = 14;
             // 21 - 17 = The value of MAWAU used by the BNDLDX and BNDSTX instructions in 64-bit mode.
@@ -190,6 +226,8 @@ pub mod leaf_0x7 {
             pub const AVX512_4VNNIW_BITINDEX: u32 = 2;
             // AVX-512 4-register Multiply Accumulation Single precision
             pub const AVX512_4FMAPS_BITINDEX: u32 = 3;
+            // Fast Short REP MOVSB
+            pub const FSRM_BITINDEX: u32 = 4;
             pub const ARCH_CAPABILITIES_BITINDEX: u32 = 29;
         }
     }
@@ -240,6 +278,7 @@ pub mod leaf_0xd {
 
             pub const MPX_STATE_BITRANGE: BitRange = bit_range!(4, 3);
             pub const AVX512_STATE_BITRANGE: BitRange = bit_range!(7, 5);
+            pub const PKRU_STATE_BITINDEX: u32 = 9;
         }
     }
 
@@ -252,6 +291,11 @@ pub mod leaf_0xd {
     }
 }
 
+// V2 Extended Topology Leaf, with the same layout as the Extended Topology Leaf
+pub mod leaf_0x1f {
+    pub const LEAF_NUM: u32 = 0x1f;
+}
+
 pub mod leaf_0x80000000 {
     pub const LEAF_NUM: u32 = 0x8000_0000;
 
@@ -269,6 +313,7 @@ pub mod leaf_0x80000001 {
         pub const TOPOEXT_INDEX: u32 = 22;
         pub const PREFETCH_BITINDEX: u32 = 8; // 3DNow! PREFETCH/PREFETCHW instructions
         pub const LZCNT_BITINDEX: u32 = 5; // advanced bit manipulation
+        pub const SVM_BITINDEX: u32 = 2; // Secure Virtual Machine
     }
 
     pub mod edx {
@@ -276,6 +321,54 @@ pub mod leaf_0x80000001 {
     }
 }
 
+// L1 Cache and TLB Information Leaf
+pub mod leaf_0x80000005 {
+    pub const LEAF_NUM: u32 = 0x8000_0005;
+
+    // L1 data cache, edx holds the L1 instruction cache in the same format.
+    pub mod ecx {
+        use crate::bit_helper::BitRange;
+
+        pub const LINE_SIZE_BITRANGE: BitRange = bit_range!(7, 0);
+        pub const LINES_PER_TAG_BITRANGE: BitRange = bit_range!(15, 8);
+        pub const ASSOCIATIVITY_BITRANGE: BitRange = bit_range!(23, 16);
+        // The size in KiB
+        pub const SIZE_BITRANGE: BitRange = bit_range!(31, 24);
+    }
+
+    pub use self::ecx as edx;
+}
+
+// L2 Cache and TLB and L3 Cache Information Leaf
+pub mod leaf_0x80000006 {
+    pub const LEAF_NUM: u32 = 0x8000_0006;
+
+    // The encoded associativity of a 16-way cache.
+    pub const ASSOCIATIVITY_16_WAYS: u32 = 0x8;
+
+    // L2 cache
+    pub mod ecx {
+        use crate::bit_helper::BitRange;
+
+        pub const LINE_SIZE_BITRANGE: BitRange = bit_range!(7, 0);
+        pub const LINES_PER_TAG_BITRANGE: BitRange = bit_range!(11, 8);
+        pub const ASSOCIATIVITY_BITRANGE: BitRange = bit_range!(15, 12);
+        // The size in KiB
+        pub const SIZE_BITRANGE: BitRange = bit_range!(31, 16);
+    }
+
+    // L3 cache
+    pub mod edx {
+        use crate::bit_helper::BitRange;
+
+        pub const LINE_SIZE_BITRANGE: BitRange = bit_range!(7, 0);
+        pub const LINES_PER_TAG_BITRANGE: BitRange = bit_range!(11, 8);
+        pub const ASSOCIATIVITY_BITRANGE: BitRange = bit_range!(15, 12);
+        // The size in 512 KiB units
+        pub const SIZE_BITRANGE: BitRange = bit_range!(31, 18);
+    }
+}
+
 pub mod leaf_0x80000008 {
     pub const LEAF_NUM: u32 = 0x8000_0008;
 
@@ -297,8 +390,8 @@ pub mod leaf_0x80000008 {
 pub mod leaf_0x8000001d {
     pub const LEAF_NUM: u32 = 0x8000_001d;
 
-    // inherit eax from leaf_cache_parameters
-    pub use crate::cpu_leaf::leaf_cache_parameters::eax;
+    // inherit eax and ebx from leaf_cache_parameters
+    pub use crate::cpu_leaf::leaf_cache_parameters::{eax, ebx};
 }
 
 // Extended APIC ID Leaf
@@ -0,0 +1,302 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+
+use crate::bit_helper::BitHelper;
+use crate::template::custom::CpuidRegister;
+use crate::template::custom::CpuidRegister::*;
+
+/// Errors associated with forcing CPUID feature bits.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The feature has no known CPUID bit.
+    UnknownFeature(String),
+    /// The feature cannot be enabled, as the host does not support it.
+    UnsupportedFeature(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            UnknownFeature(name) => write!(f, "Unknown CPU feature {}.", name),
+            UnsupportedFeature(name) => {
+                write!(f, "The CPU feature {} is not supported by the host.", name)
+            }
+        }
+    }
+}
+
+/// A CPUID feature bit, named as in `/proc/cpuinfo`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpuidFeature {
+    /// The name of the feature.
+    pub name: &'static str,
+    /// The leaf holding the bit.
+    pub leaf: u32,
+    /// The subleaf holding the bit.
+    pub subleaf: u32,
+    /// The register holding the bit.
+    pub register: CpuidRegister,
+    /// The index of the bit in the register.
+    pub bit: u32,
+}
+
+macro_rules! feature {
+    ($name:expr, $leaf:expr, $register:expr, $bit:expr) => {
+        CpuidFeature {
+            name: $name,
+            leaf: $leaf,
+            subleaf: 0,
+            register: $register,
+            bit: $bit,
+        }
+    };
+}
+
+/// The features which can be enabled or disabled.
+pub const CPUID_FEATURES: &[CpuidFeature] = &[
+    // Leaf 0x1
+    feature!("sse3", 0x1, Ecx, 0),
+    feature!("pclmulqdq", 0x1, Ecx, 1),
+    feature!("ssse3", 0x1, Ecx, 9),
+    feature!("fma", 0x1, Ecx, 12),
+    feature!("cx16", 0x1, Ecx, 13),
+    feature!("pcid", 0x1, Ecx, 17),
+    feature!("sse4_1", 0x1, Ecx, 19),
+    feature!("sse4_2", 0x1, Ecx, 20),
+    feature!("x2apic", 0x1, Ecx, 21),
+    feature!("movbe", 0x1, Ecx, 22),
+    feature!("popcnt", 0x1, Ecx, 23),
+    feature!("tsc_deadline_timer", 0x1, Ecx, 24),
+    feature!("aes", 0x1, Ecx, 25),
+    feature!("xsave", 0x1, Ecx, 26),
+    feature!("avx", 0x1, Ecx, 28),
+    feature!("f16c", 0x1, Ecx, 29),
+    feature!("rdrand", 0x1, Ecx, 30),
+    feature!("pse36", 0x1, Edx, 17),
+    feature!("clflush", 0x1, Edx, 19),
+    feature!("mmx", 0x1, Edx, 23),
+    feature!("sse", 0x1, Edx, 25),
+    feature!("sse2", 0x1, Edx, 26),
+    feature!("ss", 0x1, Edx, 27),
+    // Leaf 0x7, subleaf 0
+    feature!("fsgsbase", 0x7, Ebx, 0),
+    feature!("bmi1", 0x7, Ebx, 3),
+    feature!("hle", 0x7, Ebx, 4),
+    feature!("avx2", 0x7, Ebx, 5),
+    feature!("smep", 0x7, Ebx, 7),
+    feature!("bmi2", 0x7, Ebx, 8),
+    feature!("erms", 0x7, Ebx, 9),
+    feature!("invpcid", 0x7, Ebx, 10),
+    feature!("rtm", 0x7, Ebx, 11),
+    feature!("mpx", 0x7, Ebx, 14),
+    feature!("avx512f", 0x7, Ebx, 16),
+    feature!("avx512dq", 0x7, Ebx, 17),
+    feature!("rdseed", 0x7, Ebx, 18),
+    feature!("adx", 0x7, Ebx, 19),
+    feature!("smap", 0x7, Ebx, 20),
+    feature!("avx512ifma", 0x7, Ebx, 21),
+    feature!("clflushopt", 0x7, Ebx, 23),
+    feature!("clwb", 0x7, Ebx, 24),
+    feature!("avx512pf", 0x7, Ebx, 26),
+    feature!("avx512er", 0x7, Ebx, 27),
+    feature!("avx512cd", 0x7, Ebx, 28),
+    feature!("sha_ni", 0x7, Ebx, 29),
+    feature!("avx512bw", 0x7, Ebx, 30),
+    feature!("avx512vl", 0x7, Ebx, 31),
+    feature!("avx512vbmi", 0x7, Ecx, 1),
+    feature!("umip", 0x7, Ecx, 2),
+    feature!("pku", 0x7, Ecx, 3),
+    feature!("avx512_vbmi2", 0x7, Ecx, 6),
+    feature!("gfni", 0x7, Ecx, 8),
+    feature!("vaes", 0x7, Ecx, 9),
+    feature!("vpclmulqdq", 0x7, Ecx, 10),
+    feature!("avx512_vnni", 0x7, Ecx, 11),
+    feature!("avx512_bitalg", 0x7, Ecx, 12),
+    feature!("avx512_vpopcntdq", 0x7, Ecx, 14),
+    feature!("la57", 0x7, Ecx, 16),
+    feature!("rdpid", 0x7, Ecx, 22),
+    feature!("avx512_4vnniw", 0x7, Edx, 2),
+    feature!("avx512_4fmaps", 0x7, Edx, 3),
+    feature!("fsrm", 0x7, Edx, 4),
+    feature!("md_clear", 0x7, Edx, 10),
+    feature!("serialize", 0x7, Edx, 14),
+    feature!("amx_bf16", 0x7, Edx, 22),
+    feature!("avx512_fp16", 0x7, Edx, 23),
+    feature!("amx_tile", 0x7, Edx, 24),
+    feature!("amx_int8", 0x7, Edx, 25),
+    feature!("arch_capabilities", 0x7, Edx, 29),
+    // Leaf 0x80000001
+    feature!("lahf_lm", 0x8000_0001, Ecx, 0),
+    feature!("abm", 0x8000_0001, Ecx, 5),
+    feature!("sse4a", 0x8000_0001, Ecx, 6),
+    feature!("misalignsse", 0x8000_0001, Ecx, 7),
+    feature!("3dnowprefetch", 0x8000_0001, Ecx, 8),
+    feature!("xop", 0x8000_0001, Ecx, 11),
+    feature!("fma4", 0x8000_0001, Ecx, 16),
+    feature!("tbm", 0x8000_0001, Ecx, 21),
+    feature!("pdpe1gb", 0x8000_0001, Edx, 26),
+    feature!("rdtscp", 0x8000_0001, Edx, 27),
+];
+
+impl CpuidFeature {
+    /// Returns the feature called `name`, if any.
+    pub fn from_name(name: &str) -> Option<CpuidFeature> {
+        CPUID_FEATURES
+            .iter()
+            .find(|feature| feature.name == name)
+            .copied()
+    }
+
+    // Returns whether `entry` holds the bit.
+    fn is_in(&self, entry: &kvm_cpuid_entry2) -> bool {
+        entry.function == self.leaf && entry.index == self.subleaf
+    }
+
+    // Returns the value of the register holding the bit in `entry`.
+    fn value(&self, entry: &kvm_cpuid_entry2) -> u32 {
+        match self.register {
+            Eax => entry.eax,
+            Ebx => entry.ebx,
+            Ecx => entry.ecx,
+            Edx => entry.edx,
+        }
+    }
+
+    // Returns the register holding the bit in `entry`.
+    fn register<'a>(&self, entry: &'a mut kvm_cpuid_entry2) -> &'a mut u32 {
+        match self.register {
+            Eax => &mut entry.eax,
+            Ebx => &mut entry.ebx,
+            Ecx => &mut entry.ecx,
+            Edx => &mut entry.edx,
+        }
+    }
+
+    // Returns whether the bit is set in `kvm_cpuid`.
+    fn is_set(&self, kvm_cpuid: &CpuId) -> bool {
+        kvm_cpuid
+            .as_slice()
+            .iter()
+            .find(|entry| self.is_in(entry))
+            .map_or(false, |entry| self.value(entry).read_bit(self.bit))
+    }
+
+    // Sets the bit in `kvm_cpuid` to `value`, if it has the leaf holding the bit.
+    fn write(&self, kvm_cpuid: &mut CpuId, value: bool) -> bool {
+        match kvm_cpuid
+            .as_mut_slice()
+            .iter_mut()
+            .find(|entry| self.is_in(entry))
+        {
+            Some(entry) => {
+                self.register(entry).write_bit(self.bit, value);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Forces the bits of the `enable` features on and the ones of the `disable` features off in
+/// `kvm_cpuid`. The `enable` features must be set in `supported_cpuid`, the CPUID the host
+/// supports.
+pub fn set_cpuid_features(
+    kvm_cpuid: &mut CpuId,
+    supported_cpuid: &CpuId,
+    enable: &[String],
+    disable: &[String],
+) -> Result<(), Error> {
+    for name in enable.iter() {
+        let feature =
+            CpuidFeature::from_name(name).ok_or_else(|| Error::UnknownFeature(name.clone()))?;
+        if !feature.is_set(supported_cpuid) || !feature.write(kvm_cpuid, true) {
+            return Err(Error::UnsupportedFeature(name.clone()));
+        }
+    }
+    for name in disable.iter() {
+        let feature =
+            CpuidFeature::from_name(name).ok_or_else(|| Error::UnknownFeature(name.clone()))?;
+        // There is nothing to disable when the leaf is missing.
+        feature.write(kvm_cpuid, false);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpuid(ebx: u32) -> CpuId {
+        CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x1,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                ebx,
+                ..Default::default()
+            },
+        ])
+    }
+
+    #[test]
+    fn test_from_name() {
+        let feature = CpuidFeature::from_name("avx512f").unwrap();
+        assert_eq!(feature.leaf, 0x7);
+        assert_eq!(feature.register, Ebx);
+        assert_eq!(feature.bit, 16);
+        assert!(CpuidFeature::from_name("avx1024").is_none());
+
+        // The names are unique.
+        for (i, feature) in CPUID_FEATURES.iter().enumerate() {
+            assert!(CPUID_FEATURES[i + 1..]
+                .iter()
+                .all(|other| other.name != feature.name));
+        }
+    }
+
+    #[test]
+    fn test_set_cpuid_features() {
+        // The host supports AVX2 and AVX-512F.
+        let supported_cpuid = cpuid(1 << 5 | 1 << 16);
+        let mut kvm_cpuid = cpuid(1 << 16);
+        set_cpuid_features(
+            &mut kvm_cpuid,
+            &supported_cpuid,
+            &["avx2".to_string()],
+            &["avx512f".to_string(), "lahf_lm".to_string()],
+        )
+        .unwrap();
+        assert_eq!(kvm_cpuid.as_slice()[1].ebx, 1 << 5);
+
+        assert_eq!(
+            set_cpuid_features(&mut kvm_cpuid, &supported_cpuid, &["bmi2".to_string()], &[]),
+            Err(Error::UnsupportedFeature("bmi2".to_string()))
+        );
+        // The leaf 0x80000001 is missing.
+        assert_eq!(
+            set_cpuid_features(
+                &mut kvm_cpuid,
+                &supported_cpuid,
+                &["rdtscp".to_string()],
+                &[]
+            ),
+            Err(Error::UnsupportedFeature("rdtscp".to_string()))
+        );
+        assert_eq!(
+            set_cpuid_features(
+                &mut kvm_cpuid,
+                &supported_cpuid,
+                &[],
+                &["avx1024".to_string()]
+            ),
+            Err(Error::UnknownFeature("avx1024".to_string()))
+        );
+    }
+}
@@ -19,6 +19,8 @@ use crate::common::*;
 pub mod bit_helper;
 
 mod template;
+pub use crate::template::amd::t2a;
+pub use crate::template::custom;
 pub use crate::template::intel::c3;
 pub use crate::template::intel::t2;
 
@@ -30,6 +32,12 @@ pub use crate::transformer::{Error, VmSpec};
 
 mod brand_string;
 
+/// Forces CPUID feature bits, named as in `/proc/cpuinfo`.
+pub mod features;
+
+/// Synthesizes the cache and brand string leaves, so they don't depend on the host CPU model.
+pub mod normalize;
+
 /// Sets up the CPUID entries for the given vcpu.
 ///
 /// # Arguments
@@ -67,3 +75,64 @@ pub fn filter_cpuid(kvm_cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error
 
     Ok(())
 }
+
+/// Hides the hardware virtualization extensions, VMX on Intel and SVM on AMD, from the guest.
+///
+/// KVM reports them as supported when nested virtualization is enabled on the host, so they are
+/// only left for the guests meant to run their own hypervisor.
+///
+/// # Arguments
+///
+/// * `kvm_cpuid` - KVM related structure holding the relevant CPUID info.
+pub fn disable_nested_virt(kvm_cpuid: &mut CpuId) {
+    use crate::bit_helper::BitHelper;
+
+    for entry in kvm_cpuid.as_mut_slice().iter_mut() {
+        match entry.function {
+            cpu_leaf::leaf_0x1::LEAF_NUM => {
+                entry
+                    .ecx
+                    .write_bit(cpu_leaf::leaf_0x1::ecx::VMX_BITINDEX, false);
+            }
+            cpu_leaf::leaf_0x80000001::LEAF_NUM => {
+                entry
+                    .ecx
+                    .write_bit(cpu_leaf::leaf_0x80000001::ecx::SVM_BITINDEX, false);
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_helper::BitHelper;
+    use kvm_bindings::kvm_cpuid_entry2;
+
+    #[test]
+    fn test_disable_nested_virt() {
+        let entry = |function, ecx| kvm_cpuid_entry2 {
+            function,
+            ecx,
+            ..Default::default()
+        };
+        let mut kvm_cpuid = CpuId::from_entries(&[
+            entry(cpu_leaf::leaf_0x1::LEAF_NUM, 0xffff_ffff),
+            entry(cpu_leaf::leaf_0x80000001::LEAF_NUM, 0xffff_ffff),
+            entry(0x7, 0xffff_ffff),
+        ]);
+
+        disable_nested_virt(&mut kvm_cpuid);
+        let entries = kvm_cpuid.as_slice();
+        assert!(!entries[0]
+            .ecx
+            .read_bit(cpu_leaf::leaf_0x1::ecx::VMX_BITINDEX));
+        assert_eq!(entries[0].ecx.count_ones(), 31);
+        assert!(!entries[1]
+            .ecx
+            .read_bit(cpu_leaf::leaf_0x80000001::ecx::SVM_BITINDEX));
+        assert_eq!(entries[1].ecx.count_ones(), 31);
+        assert_eq!(entries[2].ecx, 0xffff_ffff);
+    }
+}
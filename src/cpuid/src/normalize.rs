@@ -0,0 +1,325 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use kvm_bindings::{kvm_cpuid_entry2, CpuId, KVM_CPUID_FLAG_SIGNIFCANT_INDEX};
+
+use crate::bit_helper::BitHelper;
+use crate::brand_string::{BrandString, Reg as BsReg};
+use crate::common::{VENDOR_ID_AMD, VENDOR_ID_INTEL};
+use crate::cpu_leaf::leaf_cache_parameters::{DATA_CACHE, INSTRUCTION_CACHE, UNIFIED_CACHE};
+use crate::cpu_leaf::*;
+use crate::transformer::{Error, VmSpec};
+
+// The line size of all the synthesized caches, in bytes.
+const LINE_SIZE: u32 = 64;
+
+// A cache of the synthesized hierarchy.
+struct Cache {
+    level: u32,
+    cache_type: u32,
+    size_kib: u32,
+    ways: u32,
+}
+
+impl Cache {
+    fn sets(&self) -> u32 {
+        self.size_kib * 1024 / (self.ways * LINE_SIZE)
+    }
+}
+
+const L1D: Cache = Cache {
+    level: 1,
+    cache_type: DATA_CACHE,
+    size_kib: 32,
+    ways: 8,
+};
+const L1I: Cache = Cache {
+    level: 1,
+    cache_type: INSTRUCTION_CACHE,
+    size_kib: 32,
+    ways: 8,
+};
+const L2: Cache = Cache {
+    level: 2,
+    cache_type: UNIFIED_CACHE,
+    size_kib: 1024,
+    ways: 16,
+};
+const L3: Cache = Cache {
+    level: 3,
+    cache_type: UNIFIED_CACHE,
+    size_kib: 32 * 1024,
+    ways: 16,
+};
+
+// The synthesized hierarchy, in the order of the subleaves of the cache parameters leaves.
+const CACHES: [&Cache; 4] = [&L1D, &L1I, &L2, &L3];
+
+// The leaf 0x2 descriptor telling to query the cache parameters from the leaf 0x4.
+const USE_LEAF_0X4_DESCRIPTOR: u32 = 0xff;
+
+// Returns the cache parameters entry of `cache` for the subleaf `index` of `function`.
+fn cache_parameters_entry(
+    function: u32,
+    index: u32,
+    cache: &Cache,
+    vm_spec: &VmSpec,
+) -> kvm_cpuid_entry2 {
+    use crate::cpu_leaf::leaf_cache_parameters::*;
+
+    // The L1 & L2 caches are private to a core, the L3 cache is shared by the whole package.
+    let sharing_cpus = if cache.level < 3 {
+        vm_spec.cpus_per_core()
+    } else {
+        vm_spec.cpus_per_package()
+    };
+
+    let mut entry = kvm_cpuid_entry2 {
+        function,
+        index,
+        flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+        ..Default::default()
+    };
+    entry
+        .eax
+        .write_bits_in_range(&eax::CACHE_TYPE_BITRANGE, cache.cache_type)
+        .write_bits_in_range(&eax::CACHE_LEVEL_BITRANGE, cache.level)
+        .write_bit(eax::SELF_INIT_BITINDEX, true)
+        .write_bits_in_range(
+            &eax::MAX_CPUS_PER_CORE_BITRANGE,
+            u32::from(sharing_cpus - 1),
+        );
+    entry
+        .ebx
+        .write_bits_in_range(&ebx::LINE_SIZE_BITRANGE, LINE_SIZE - 1)
+        .write_bits_in_range(&ebx::PARTITIONS_BITRANGE, 0)
+        .write_bits_in_range(&ebx::WAYS_BITRANGE, cache.ways - 1);
+    entry.ecx = cache.sets() - 1;
+
+    if function == leaf_0x4::LEAF_NUM {
+        let cores_per_package = vm_spec.cpus_per_package() / vm_spec.cpus_per_core();
+        entry.eax.write_bits_in_range(
+            &leaf_0x4::eax::MAX_CORES_PER_PACKAGE_BITRANGE,
+            u32::from(cores_per_package - 1),
+        );
+    }
+
+    entry
+}
+
+// Replaces the subleaves of the cache parameters leaf `function` with the synthesized hierarchy,
+// followed by the null subleaf ending the list.
+fn set_cache_parameters(
+    kvm_cpuid: &mut CpuId,
+    function: u32,
+    vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    kvm_cpuid.retain(|entry| entry.function != function);
+
+    for (index, cache) in CACHES.iter().enumerate() {
+        kvm_cpuid
+            .push(cache_parameters_entry(
+                function,
+                index as u32,
+                cache,
+                vm_spec,
+            ))
+            .map_err(Error::FamError)?;
+    }
+    kvm_cpuid
+        .push(kvm_cpuid_entry2 {
+            function,
+            index: CACHES.len() as u32,
+            flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+            ..Default::default()
+        })
+        .map_err(Error::FamError)?;
+
+    Ok(())
+}
+
+// Returns the leaf 0x80000005 description of the L1 `cache`.
+fn l1_cache_info(cache: &Cache) -> u32 {
+    use crate::cpu_leaf::leaf_0x80000005::*;
+
+    let mut info: u32 = 0;
+    info.write_bits_in_range(&ecx::LINE_SIZE_BITRANGE, LINE_SIZE)
+        .write_bits_in_range(&ecx::LINES_PER_TAG_BITRANGE, 1)
+        .write_bits_in_range(&ecx::ASSOCIATIVITY_BITRANGE, cache.ways)
+        .write_bits_in_range(&ecx::SIZE_BITRANGE, cache.size_kib);
+    info
+}
+
+// Describes the synthesized hierarchy in the legacy leaves 0x80000005 and 0x80000006.
+fn update_extended_cache_entry(entry: &mut kvm_cpuid_entry2, vendor_id: &[u8; 12]) {
+    match entry.function {
+        // Intel reserves this leaf.
+        leaf_0x80000005::LEAF_NUM if vendor_id == VENDOR_ID_AMD => {
+            entry.ecx = l1_cache_info(&L1D);
+            entry.edx = l1_cache_info(&L1I);
+        }
+        leaf_0x80000006::LEAF_NUM => {
+            use crate::cpu_leaf::leaf_0x80000006::*;
+
+            // Intel only reports the L2 cache, without the lines per tag.
+            let lines_per_tag = if vendor_id == VENDOR_ID_AMD { 1 } else { 0 };
+            entry.ecx = 0;
+            entry
+                .ecx
+                .write_bits_in_range(&ecx::LINE_SIZE_BITRANGE, LINE_SIZE)
+                .write_bits_in_range(&ecx::LINES_PER_TAG_BITRANGE, lines_per_tag)
+                .write_bits_in_range(&ecx::ASSOCIATIVITY_BITRANGE, ASSOCIATIVITY_16_WAYS)
+                .write_bits_in_range(&ecx::SIZE_BITRANGE, L2.size_kib);
+            entry.edx = 0;
+            if vendor_id == VENDOR_ID_AMD {
+                entry
+                    .edx
+                    .write_bits_in_range(&edx::LINE_SIZE_BITRANGE, LINE_SIZE)
+                    .write_bits_in_range(&edx::LINES_PER_TAG_BITRANGE, 1)
+                    .write_bits_in_range(&edx::ASSOCIATIVITY_BITRANGE, ASSOCIATIVITY_16_WAYS)
+                    .write_bits_in_range(&edx::SIZE_BITRANGE, L3.size_kib / 512);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Replaces the cache descriptors and the brand string in `kvm_cpuid` with synthesized values.
+///
+/// The guest is shown the same cache hierarchy (32 KiB L1 data and instruction caches, a 1 MiB
+/// L2 cache and a 32 MiB L3 cache shared by the package) and a brand string without the host
+/// frequency, whatever the host CPU model. Only the topology of the guest, from `vm_spec`, is
+/// reflected in the cache leaves, so the CPUID of a guest is the same across hosts of the same
+/// vendor.
+///
+/// It is meant to be called after `filter_cpuid`.
+pub fn normalize_cpuid(kvm_cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+    let vendor_id = vm_spec.cpu_vendor_id();
+    match vendor_id {
+        VENDOR_ID_INTEL => {
+            kvm_cpuid.retain(|entry| entry.function != leaf_0x2::LEAF_NUM);
+            kvm_cpuid
+                .push(kvm_cpuid_entry2 {
+                    function: leaf_0x2::LEAF_NUM,
+                    // The low byte of eax is always 1, then comes the only descriptor.
+                    eax: USE_LEAF_0X4_DESCRIPTOR << 8 | 0x1,
+                    ..Default::default()
+                })
+                .map_err(Error::FamError)?;
+            set_cache_parameters(kvm_cpuid, leaf_0x4::LEAF_NUM, vm_spec)?;
+        }
+        VENDOR_ID_AMD => {
+            set_cache_parameters(kvm_cpuid, leaf_0x8000001d::LEAF_NUM, vm_spec)?;
+        }
+        _ => return Err(Error::InvalidVendor),
+    }
+
+    let brand_string = BrandString::stable_from_vendor_id(vendor_id);
+    for entry in kvm_cpuid.as_mut_slice().iter_mut() {
+        match entry.function {
+            0x8000_0002..=0x8000_0004 => {
+                entry.eax = brand_string.get_reg_for_leaf(entry.function, BsReg::EAX);
+                entry.ebx = brand_string.get_reg_for_leaf(entry.function, BsReg::EBX);
+                entry.ecx = brand_string.get_reg_for_leaf(entry.function, BsReg::ECX);
+                entry.edx = brand_string.get_reg_for_leaf(entry.function, BsReg::EDX);
+            }
+            _ => update_extended_cache_entry(entry, vendor_id),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::get_vendor_id_from_host;
+
+    // Returns a CPUID with host-like values in the cache and brand string leaves.
+    fn host_like_cpuid(seed: u32) -> CpuId {
+        let entry = |function, index| kvm_cpuid_entry2 {
+            function,
+            index,
+            eax: seed,
+            ebx: seed,
+            ecx: seed,
+            edx: seed,
+            ..Default::default()
+        };
+        CpuId::from_entries(&[
+            entry(leaf_0x2::LEAF_NUM, 0),
+            entry(leaf_0x4::LEAF_NUM, 0),
+            entry(leaf_0x4::LEAF_NUM, 1),
+            entry(0x8000_0002, 0),
+            entry(0x8000_0003, 0),
+            entry(0x8000_0004, 0),
+            entry(leaf_0x80000005::LEAF_NUM, 0),
+            entry(leaf_0x80000006::LEAF_NUM, 0),
+            entry(leaf_0x8000001d::LEAF_NUM, 0),
+        ])
+    }
+
+    #[test]
+    fn test_normalize_cpuid() {
+        let vm_spec = VmSpec::with_topology(0, 1, 4, 2).unwrap();
+        let mut kvm_cpuid = host_like_cpuid(0x1234_5678);
+        normalize_cpuid(&mut kvm_cpuid, &vm_spec).unwrap();
+
+        // The result doesn't depend on the host values.
+        let mut other_cpuid = host_like_cpuid(0xffff_ffff);
+        normalize_cpuid(&mut other_cpuid, &vm_spec).unwrap();
+        assert_eq!(kvm_cpuid.as_slice(), other_cpuid.as_slice());
+
+        let vendor_id = get_vendor_id_from_host().unwrap();
+        let function = match &vendor_id {
+            VENDOR_ID_INTEL => leaf_0x4::LEAF_NUM,
+            _ => leaf_0x8000001d::LEAF_NUM,
+        };
+        let caches: Vec<&kvm_cpuid_entry2> = kvm_cpuid
+            .as_slice()
+            .iter()
+            .filter(|entry| entry.function == function)
+            .collect();
+        assert_eq!(caches.len(), CACHES.len() + 1);
+        for (index, entry) in caches.iter().enumerate() {
+            assert_eq!(entry.index, index as u32);
+            assert_eq!(entry.flags, KVM_CPUID_FLAG_SIGNIFCANT_INDEX);
+        }
+        // 32 MiB L3 cache, shared by the 8 logical cpus of the package.
+        use crate::cpu_leaf::leaf_cache_parameters::*;
+        let l3 = caches[3];
+        assert_eq!(l3.eax.read_bits_in_range(&eax::CACHE_LEVEL_BITRANGE), 3);
+        assert_eq!(
+            l3.eax.read_bits_in_range(&eax::MAX_CPUS_PER_CORE_BITRANGE),
+            7
+        );
+        let size = (l3.ebx.read_bits_in_range(&ebx::WAYS_BITRANGE) + 1)
+            * (l3.ebx.read_bits_in_range(&ebx::PARTITIONS_BITRANGE) + 1)
+            * (l3.ebx.read_bits_in_range(&ebx::LINE_SIZE_BITRANGE) + 1)
+            * (l3.ecx + 1);
+        assert_eq!(size, 32 << 20);
+        // The null subleaf ends the list.
+        assert_eq!(caches[4].eax, 0);
+
+        let brand_string = BrandString::stable_from_vendor_id(&vendor_id);
+        for entry in kvm_cpuid.as_slice().iter() {
+            match entry.function {
+                0x8000_0002..=0x8000_0004 => {
+                    assert_eq!(
+                        entry.eax,
+                        brand_string.get_reg_for_leaf(entry.function, BsReg::EAX)
+                    );
+                    assert_eq!(
+                        entry.edx,
+                        brand_string.get_reg_for_leaf(entry.function, BsReg::EDX)
+                    );
+                }
+                leaf_0x80000006::LEAF_NUM => {
+                    use crate::cpu_leaf::leaf_0x80000006::*;
+                    assert_eq!(entry.ecx.read_bits_in_range(&ecx::SIZE_BITRANGE), 1024);
+                }
+                _ => (),
+            }
+        }
+    }
+}
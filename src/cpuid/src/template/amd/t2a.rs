@@ -0,0 +1,117 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::bit_helper::BitHelper;
+use crate::cpu_leaf::*;
+use crate::template::amd::validate_vendor_id;
+use crate::transformer::*;
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+
+fn update_feature_info_entry(entry: &mut kvm_cpuid_entry2, _vm_spec: &VmSpec) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x1::*;
+
+    entry
+        .eax
+        // Extended Family ID = 8 (Family 17h)
+        .write_bits_in_range(&eax::EXTENDED_FAMILY_ID_BITRANGE, 8)
+        // Extended Processor Model ID = 3 (Rome)
+        .write_bits_in_range(&eax::EXTENDED_PROCESSOR_MODEL_BITRANGE, 3)
+        // Processor Type = 0 (Primary processor)
+        .write_bits_in_range(&eax::PROCESSOR_TYPE_BITRANGE, 0)
+        // Processor Family = 15
+        .write_bits_in_range(&eax::PROCESSOR_FAMILY_BITRANGE, 15)
+        // Processor Model = 1
+        .write_bits_in_range(&eax::PROCESSOR_MODEL_BITRANGE, 1)
+        // Stepping = 0
+        .write_bits_in_range(&eax::STEPPING_BITRANGE, 0);
+
+    // Disable Features
+    entry
+        .ecx
+        .write_bit(ecx::MONITOR_BITINDEX, false)
+        .write_bit(ecx::OSXSAVE_BITINDEX, false);
+
+    Ok(())
+}
+
+fn update_structured_extended_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x7::index0::*;
+
+    if entry.index == 0 {
+        // Zen 3 features.
+        entry
+            .ebx
+            .write_bit(ebx::ERMS_BITINDEX, false)
+            .write_bit(ebx::INVPCID_BITINDEX, false);
+        entry
+            .ecx
+            .write_bit(ecx::PKU_BITINDEX, false)
+            .write_bit(ecx::OSPKE_BITINDEX, false)
+            .write_bit(ecx::VAES_BITINDEX, false)
+            .write_bit(ecx::VPCLMULQDQ_BITINDEX, false);
+        entry.edx.write_bit(edx::FSRM_BITINDEX, false);
+
+        // Zen 4 features.
+        entry
+            .ebx
+            .write_bit(ebx::AVX512F_BITINDEX, false)
+            .write_bit(ebx::AVX512DQ_BITINDEX, false)
+            .write_bit(ebx::AVX512IFMA_BITINDEX, false)
+            .write_bit(ebx::AVX512CD_BITINDEX, false)
+            .write_bit(ebx::AVX512BW_BITINDEX, false)
+            .write_bit(ebx::AVX512VL_BITINDEX, false);
+        entry
+            .ecx
+            .write_bit(ecx::AVX512_VBMI_BITINDEX, false)
+            .write_bit(ecx::AVX512_VBMI2_BITINDEX, false)
+            .write_bit(ecx::GFNI_BITINDEX, false)
+            .write_bit(ecx::AVX512_VNNI_BITINDEX, false)
+            .write_bit(ecx::AVX512_BITALG_BITINDEX, false)
+            .write_bit(ecx::AVX512_VPOPCNTDQ_BITINDEX, false);
+    }
+
+    Ok(())
+}
+
+fn update_xsave_features_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0xd::*;
+
+    if entry.index == 0 {
+        // AVX-512 instructions and protection keys are masked out with the current template
+        // so the size in bytes of their save areas should be 0 (or invalid).
+        entry
+            .eax
+            .write_bits_in_range(&index0::eax::AVX512_STATE_BITRANGE, 0)
+            .write_bit(index0::eax::PKRU_STATE_BITINDEX, false);
+    }
+
+    Ok(())
+}
+
+/// Sets up the cpuid entries for a given VCPU following a T2A template.
+struct T2ACpuidTransformer {}
+
+impl CpuidTransformer for T2ACpuidTransformer {
+    fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        match entry.function {
+            leaf_0x1::LEAF_NUM => Some(update_feature_info_entry),
+            leaf_0x7::LEAF_NUM => Some(update_structured_extended_entry),
+            leaf_0xd::LEAF_NUM => Some(update_xsave_features_entry),
+            _ => None,
+        }
+    }
+}
+
+/// Sets up the cpuid entries for a given VCPU following a T2A template, which exposes the
+/// features of an AMD EPYC Rome (Zen 2) CPU, so that the snapshots can be restored on the
+/// later EPYC generations.
+pub fn set_cpuid_entries(kvm_cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+    validate_vendor_id()?;
+    T2ACpuidTransformer {}.process_cpuid(kvm_cpuid, vm_spec)
+}
@@ -0,0 +1,374 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use kvm_bindings::{kvm_msr_entry, CpuId, Msrs};
+use serde::{de, Deserialize};
+
+/// Errors associated with the custom CPU templates.
+#[derive(Debug)]
+pub enum Error {
+    /// A CPUID register bitmap has more than 32 bits.
+    CpuidBitmapTooLong(u32, u32),
+    /// The template is not a valid JSON description of a CPU template.
+    InvalidJson(serde_json::Error),
+    /// The template modifies a CPUID leaf and subleaf the vCPU does not have.
+    MissingCpuidLeaf(u32, u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            CpuidBitmapTooLong(leaf, subleaf) => write!(
+                f,
+                "The bitmap of CPUID leaf {:#x}, subleaf {:#x} has more than 32 bits.",
+                leaf, subleaf
+            ),
+            InvalidJson(e) => write!(f, "The CPU template is not valid: {}", e),
+            MissingCpuidLeaf(leaf, subleaf) => write!(
+                f,
+                "The CPUID leaf {:#x}, subleaf {:#x} does not exist.",
+                leaf, subleaf
+            ),
+        }
+    }
+}
+
+/// A CPU template described in JSON, which modifies the bits of CPUID registers and MSRs.
+///
+/// ```json
+/// {
+///     "cpuid_modifiers": [
+///         {
+///             "leaf": "0x1",
+///             "subleaf": "0x0",
+///             "modifiers": [{ "register": "ecx", "bitmap": "0bx0xxxxxxxxxxxxxxxxxxxxxxxxxxxxx" }]
+///         }
+///     ],
+///     "msr_modifiers": [{ "addr": "0x10a", "bitmap": "0b0000xxxx" }]
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CustomCpuTemplate {
+    /// The modifications of the CPUID leaves.
+    #[serde(default)]
+    pub cpuid_modifiers: Vec<CpuidLeafModifier>,
+    /// The modifications of the MSRs.
+    #[serde(default)]
+    pub msr_modifiers: Vec<MsrModifier>,
+}
+
+/// The modifications of the registers of a CPUID leaf and subleaf.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CpuidLeafModifier {
+    /// The leaf, the function in EAX.
+    #[serde(deserialize_with = "deserialize_u32")]
+    pub leaf: u32,
+    /// The subleaf, the index in ECX. Leaves without subleaves have the subleaf 0.
+    #[serde(default, deserialize_with = "deserialize_u32")]
+    pub subleaf: u32,
+    /// The modifications of the registers.
+    pub modifiers: Vec<CpuidRegisterModifier>,
+}
+
+/// The modification of a CPUID register.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CpuidRegisterModifier {
+    /// The modified register.
+    pub register: CpuidRegister,
+    /// The bits to set, in the low 32 bits.
+    pub bitmap: Bitmap,
+}
+
+/// The registers returned by the CPUID instruction.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuidRegister {
+    /// The EAX register.
+    Eax,
+    /// The EBX register.
+    Ebx,
+    /// The ECX register.
+    Ecx,
+    /// The EDX register.
+    Edx,
+}
+
+/// The modification of an MSR.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MsrModifier {
+    /// The index of the MSR.
+    #[serde(deserialize_with = "deserialize_u32")]
+    pub addr: u32,
+    /// The bits to set.
+    pub bitmap: Bitmap,
+}
+
+/// The bits set by a modifier, written as `0b` followed by at most 64 `0`, `1` or `x`
+/// characters, from the most significant bit. The bits marked `x`, and the ones past the
+/// characters, are left as they are.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Bitmap {
+    /// The bits which are set, to the values in `value`.
+    filter: u64,
+    /// The values of the bits which are set.
+    value: u64,
+}
+
+impl Bitmap {
+    /// Sets the bits of `register` to the ones of the bitmap.
+    pub fn apply(&self, register: u64) -> u64 {
+        (register & !self.filter) | self.value
+    }
+}
+
+impl TryFrom<String> for Bitmap {
+    type Error = String;
+
+    fn try_from(bitmap: String) -> Result<Self, Self::Error> {
+        let bits = bitmap
+            .strip_prefix("0b")
+            .ok_or_else(|| format!("the bitmap {} does not start with 0b", bitmap))?;
+        if bits.is_empty() || bits.len() > 64 {
+            return Err(format!("the bitmap {} must have 1 to 64 bits", bitmap));
+        }
+
+        let mut filter = 0;
+        let mut value = 0;
+        for bit in bits.chars() {
+            filter <<= 1;
+            value <<= 1;
+            match bit {
+                '0' => filter |= 1,
+                '1' => {
+                    filter |= 1;
+                    value |= 1;
+                }
+                'x' => (),
+                _ => return Err(format!("the bitmap {} has an invalid bit {}", bitmap, bit)),
+            }
+        }
+        Ok(Bitmap { filter, value })
+    }
+}
+
+// Reads an integer given either as a number or as a hexadecimal string.
+fn deserialize_u32<'de, D>(d: D) -> Result<u32, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Integer {
+        Number(u32),
+        Hex(String),
+    }
+
+    match Integer::deserialize(d)? {
+        Integer::Number(value) => Ok(value),
+        Integer::Hex(hex) => hex
+            .strip_prefix("0x")
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| de::Error::custom(format!("{} is not a hexadecimal integer", hex))),
+    }
+}
+
+impl CustomCpuTemplate {
+    /// Parses the template described by `json`.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let template: CustomCpuTemplate = serde_json::from_str(json).map_err(Error::InvalidJson)?;
+        for leaf in template.cpuid_modifiers.iter() {
+            if leaf.modifiers.iter().any(|m| m.bitmap.filter >> 32 != 0) {
+                return Err(Error::CpuidBitmapTooLong(leaf.leaf, leaf.subleaf));
+            }
+        }
+        Ok(template)
+    }
+
+    /// Modifies the registers of the CPUID leaves of `kvm_cpuid`.
+    pub fn apply_cpuid(&self, kvm_cpuid: &mut CpuId) -> Result<(), Error> {
+        for leaf in self.cpuid_modifiers.iter() {
+            let entry = kvm_cpuid
+                .as_mut_slice()
+                .iter_mut()
+                .find(|entry| entry.function == leaf.leaf && entry.index == leaf.subleaf)
+                .ok_or(Error::MissingCpuidLeaf(leaf.leaf, leaf.subleaf))?;
+            for modifier in leaf.modifiers.iter() {
+                let register = match modifier.register {
+                    CpuidRegister::Eax => &mut entry.eax,
+                    CpuidRegister::Ebx => &mut entry.ebx,
+                    CpuidRegister::Ecx => &mut entry.ecx,
+                    CpuidRegister::Edx => &mut entry.edx,
+                };
+                *register = modifier.bitmap.apply(u64::from(*register)) as u32;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the entries of the MSRs the template modifies, to be read from the vCPU.
+    pub fn msr_entries(&self) -> Vec<kvm_msr_entry> {
+        self.msr_modifiers
+            .iter()
+            .map(|modifier| kvm_msr_entry {
+                index: modifier.addr,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Modifies the values of the MSRs read in `msrs`.
+    pub fn apply_msrs(&self, msrs: &mut Msrs) {
+        for entry in msrs.as_mut_slice().iter_mut() {
+            for modifier in self.msr_modifiers.iter() {
+                if modifier.addr == entry.index {
+                    entry.data = modifier.bitmap.apply(entry.data);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvm_bindings::kvm_cpuid_entry2;
+
+    const TEMPLATE: &str = r#"{
+        "cpuid_modifiers": [
+            {
+                "leaf": "0x1",
+                "modifiers": [
+                    { "register": "ecx", "bitmap": "0b10x0" },
+                    { "register": "edx", "bitmap": "0b1" }
+                ]
+            },
+            {
+                "leaf": 7,
+                "subleaf": "0x1",
+                "modifiers": [{ "register": "eax", "bitmap": "0b0xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx" }]
+            }
+        ],
+        "msr_modifiers": [{ "addr": "0x10a", "bitmap": "0b1xxxxxxx0" }]
+    }"#;
+
+    #[test]
+    fn test_bitmap() {
+        let bitmap = Bitmap::try_from("0b10x0".to_string()).unwrap();
+        assert_eq!(bitmap.filter, 0b1101);
+        assert_eq!(bitmap.value, 0b1000);
+        assert_eq!(bitmap.apply(0xff), 0xfa);
+        assert_eq!(bitmap.apply(0xf0), 0xf8);
+
+        assert!(Bitmap::try_from("10x0".to_string()).is_err());
+        assert!(Bitmap::try_from("0b".to_string()).is_err());
+        assert!(Bitmap::try_from("0b102".to_string()).is_err());
+        assert!(Bitmap::try_from(format!("0b{}", "x".repeat(65))).is_err());
+        let bitmap = Bitmap::try_from(format!("0b1{}", "x".repeat(63))).unwrap();
+        assert_eq!(bitmap.apply(0), 1 << 63);
+    }
+
+    #[test]
+    fn test_from_json() {
+        let template = CustomCpuTemplate::from_json(TEMPLATE).unwrap();
+        assert_eq!(template.cpuid_modifiers.len(), 2);
+        assert_eq!(template.cpuid_modifiers[0].leaf, 1);
+        assert_eq!(template.cpuid_modifiers[0].subleaf, 0);
+        assert_eq!(
+            template.cpuid_modifiers[0].modifiers[1].register,
+            CpuidRegister::Edx
+        );
+        assert_eq!(template.cpuid_modifiers[1].leaf, 7);
+        assert_eq!(template.cpuid_modifiers[1].subleaf, 1);
+        assert_eq!(template.msr_modifiers[0].addr, 0x10a);
+
+        assert_eq!(
+            CustomCpuTemplate::from_json("{}").unwrap(),
+            CustomCpuTemplate::default()
+        );
+
+        let leaf_json = |register: &str, bitmap: &str| {
+            format!(
+                r#"{{ "cpuid_modifiers": [{{ "leaf": 1, "modifiers": [{}] }}] }}"#,
+                format!(
+                    r#"{{ "register": "{}", "bitmap": "{}" }}"#,
+                    register, bitmap
+                )
+            )
+        };
+
+        // Unknown fields, registers and malformed integers are rejected.
+        for json in [
+            r#"{ "msr_modifier": [] }"#.to_string(),
+            leaf_json("esi", "0b1"),
+            r#"{ "msr_modifiers": [{ "addr": "10a", "bitmap": "0b1" }] }"#.to_string(),
+            r#"{ "msr_modifiers": [{ "addr": "0x10a", "bitmap": "1" }] }"#.to_string(),
+        ]
+        .iter()
+        {
+            match CustomCpuTemplate::from_json(json) {
+                Err(Error::InvalidJson(_)) => (),
+                _ => panic!("Unexpected result."),
+            }
+        }
+
+        let json = leaf_json("eax", &format!("0b{}", "0".repeat(33)));
+        match CustomCpuTemplate::from_json(&json) {
+            Err(Error::CpuidBitmapTooLong(1, 0)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_apply_cpuid() {
+        let template = CustomCpuTemplate::from_json(TEMPLATE).unwrap();
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x1,
+                ecx: 0xff,
+                edx: 0x0,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                index: 0x1,
+                eax: 0xffff_ffff,
+                ..Default::default()
+            },
+        ]);
+        template.apply_cpuid(&mut cpuid).unwrap();
+        let entries = cpuid.as_slice();
+        assert_eq!(entries[0].ecx, 0xfa);
+        assert_eq!(entries[0].edx, 0x1);
+        assert_eq!(entries[1].eax, 0x7fff_ffff);
+
+        let mut cpuid = CpuId::from_entries(&[kvm_cpuid_entry2 {
+            function: 0x1,
+            ..Default::default()
+        }]);
+        match template.apply_cpuid(&mut cpuid) {
+            Err(Error::MissingCpuidLeaf(7, 1)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_apply_msrs() {
+        let template = CustomCpuTemplate::from_json(TEMPLATE).unwrap();
+        let mut entries = template.msr_entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].index, 0x10a);
+        entries[0].data = 0x1;
+        let mut msrs = Msrs::from_entries(&entries);
+        template.apply_msrs(&mut msrs);
+        assert_eq!(msrs.as_slice()[0].data, 0x100);
+    }
+}
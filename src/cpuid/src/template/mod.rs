@@ -2,4 +2,8 @@
 // SPDX-License-Identifier: Apache-2.0
 
 // Contains Intel specific templates.
+/// Parses and applies the CPU templates described in JSON.
+pub mod custom;
 pub mod intel;
+// Contains AMD specific templates.
+pub mod amd;
@@ -67,12 +67,19 @@ pub fn update_amd_features_entry(
 ) -> Result<(), Error> {
     use crate::cpu_leaf::leaf_0x80000008::*;
 
-    // We don't support more then 128 threads right now.
-    // It's safe to put them all on the same processor.
+    // We don't support more then 128 threads right now. When there is a single processor,
+    // it's safe to put them all on it. Otherwise, the APIC IDs being contiguous, the processor
+    // ID is right after the bits needed to enumerate the threads of a processor.
     entry
         .ecx
-        .write_bits_in_range(&ecx::THREAD_ID_SIZE_BITRANGE, THREAD_ID_MAX_SIZE)
-        .write_bits_in_range(&ecx::NUM_THREADS_BITRANGE, u32::from(vm_spec.cpu_count - 1));
+        .write_bits_in_range(
+            &ecx::THREAD_ID_SIZE_BITRANGE,
+            vm_spec.package_bits(THREAD_ID_MAX_SIZE),
+        )
+        .write_bits_in_range(
+            &ecx::NUM_THREADS_BITRANGE,
+            u32::from(vm_spec.cpus_per_package() - 1),
+        );
 
     Ok(())
 }
@@ -120,8 +127,8 @@ pub fn update_extended_apic_id_entry(
     entry
         .ecx
         .write_bits_in_range(&ecx::NODES_PER_PROCESSOR_BITRANGE, NODES_PER_PROCESSOR)
-        // Put all the cpus in the same node.
-        .write_bits_in_range(&ecx::NODE_ID_BITRANGE, 0);
+        // There is one node per processor.
+        .write_bits_in_range(&ecx::NODE_ID_BITRANGE, u32::from(vm_spec.package_index()));
 
     Ok(())
 }
@@ -377,4 +384,39 @@ mod tests {
         check_update_extended_apic_id_entry(0, 2, true, 0, 1);
         check_update_extended_apic_id_entry(1, 2, true, 0, 1);
     }
+
+    #[test]
+    fn test_multi_socket_topology() {
+        // 2 processors of 2 cores with 2 threads each; the vCPU 5 is the second thread of the
+        // first core of the second processor.
+        let vm_spec = VmSpec::with_topology(5, 2, 2, 2).expect("Error creating vm_spec");
+
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x80000008::LEAF_NUM,
+            index: 0,
+            flags: 0,
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+            padding: [0, 0, 0],
+        };
+        assert!(update_amd_features_entry(&mut entry, &vm_spec).is_ok());
+        {
+            use crate::cpu_leaf::leaf_0x80000008::*;
+            assert_eq!(entry.ecx.read_bits_in_range(&ecx::NUM_THREADS_BITRANGE), 3);
+            assert_eq!(
+                entry.ecx.read_bits_in_range(&ecx::THREAD_ID_SIZE_BITRANGE),
+                2
+            );
+        }
+
+        entry.function = leaf_0x8000001e::LEAF_NUM;
+        assert!(update_extended_apic_id_entry(&mut entry, &vm_spec).is_ok());
+        {
+            use crate::cpu_leaf::leaf_0x8000001e::*;
+            assert_eq!(entry.ebx.read_bits_in_range(&ebx::CORE_ID_BITRANGE), 2);
+            assert_eq!(entry.ecx.read_bits_in_range(&ecx::NODE_ID_BITRANGE), 1);
+        }
+    }
 }
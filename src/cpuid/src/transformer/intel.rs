@@ -6,7 +6,8 @@ use crate::bit_helper::BitHelper;
 use crate::cpu_leaf::*;
 
 // The APIC ID shift in leaf 0xBh specifies the number of bits to shit the x2APIC ID to get a
-// unique topology of the next level. This allows 128 logical processors/package.
+// unique topology of the next level. This allows 128 logical processors/package, and is used
+// when there is a single package.
 const LEAFBH_INDEX1_APICID: u32 = 7;
 
 fn update_deterministic_cache_entry(
@@ -17,10 +18,9 @@ fn update_deterministic_cache_entry(
 
     common::update_cache_parameters_entry(entry, vm_spec)?;
 
-    // Put all the cores in the same socket
     entry.eax.write_bits_in_range(
         &eax::MAX_CORES_PER_PACKAGE_BITRANGE,
-        u32::from(vm_spec.cpu_count / vm_spec.cpus_per_core()) - 1,
+        u32::from(vm_spec.cores_per_socket) - 1,
     );
 
     Ok(())
@@ -89,12 +89,15 @@ fn update_extended_topology_entry(
         }
         // Core Level Processor Topology; index = 1
         1 => {
-            entry
-                .eax
-                .write_bits_in_range(&eax::APICID_BITRANGE, LEAFBH_INDEX1_APICID);
+            // The APIC IDs are contiguous, so the package ID is right after the bits needed
+            // to enumerate the logical processors of a package.
+            entry.eax.write_bits_in_range(
+                &eax::APICID_BITRANGE,
+                vm_spec.package_bits(LEAFBH_INDEX1_APICID),
+            );
             entry.ebx.write_bits_in_range(
                 &ebx::NUM_LOGICAL_PROCESSORS_BITRANGE,
-                u32::from(vm_spec.cpu_count),
+                u32::from(vm_spec.cpus_per_package()),
             );
             entry
                 .ecx
@@ -125,6 +128,7 @@ impl CpuidTransformer for IntelCpuidTransformer {
             leaf_0x6::LEAF_NUM => Some(intel::update_power_management_entry),
             leaf_0xa::LEAF_NUM => Some(intel::update_perf_mon_entry),
             leaf_0xb::LEAF_NUM => Some(intel::update_extended_topology_entry),
+            leaf_0x1f::LEAF_NUM => Some(intel::update_extended_topology_entry),
             0x8000_0002..=0x8000_0004 => Some(common::update_brand_string_entry),
             _ => None,
         }
@@ -292,4 +296,60 @@ mod tests {
         // index 1
         check_update_extended_topology_entry(2, true, 1, LEAFBH_INDEX1_APICID, 2, LEVEL_TYPE_CORE);
     }
+
+    #[test]
+    fn test_multi_socket_topology() {
+        use crate::cpu_leaf::leaf_0xb::*;
+
+        // 2 packages of 2 cores with 2 threads each.
+        let vm_spec = VmSpec::with_topology(7, 2, 2, 2).expect("Error creating vm_spec");
+
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x4::LEAF_NUM,
+            index: 0,
+            flags: 0,
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+            padding: [0, 0, 0],
+        };
+        assert!(update_deterministic_cache_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!(
+            entry
+                .eax
+                .read_bits_in_range(&leaf_0x4::eax::MAX_CORES_PER_PACKAGE_BITRANGE),
+            1
+        );
+
+        for &function in &[leaf_0xb::LEAF_NUM, leaf_0x1f::LEAF_NUM] {
+            let mut entry = kvm_cpuid_entry2 {
+                function,
+                index: 1,
+                flags: 0,
+                eax: 0,
+                ebx: 0,
+                ecx: 0,
+                edx: 0,
+                padding: [0, 0, 0],
+            };
+            let transformer_fn = IntelCpuidTransformer {}
+                .entry_transformer_fn(&mut entry)
+                .unwrap();
+            assert!(transformer_fn(&mut entry, &vm_spec).is_ok());
+            // 4 logical processors per package need 2 bits of the APIC ID.
+            assert_eq!(entry.eax.read_bits_in_range(&eax::APICID_BITRANGE), 2);
+            assert_eq!(
+                entry
+                    .ebx
+                    .read_bits_in_range(&ebx::NUM_LOGICAL_PROCESSORS_BITRANGE),
+                4
+            );
+            assert_eq!(
+                entry.ecx.read_bits_in_range(&ecx::LEVEL_TYPE_BITRANGE),
+                LEVEL_TYPE_CORE
+            );
+            assert_eq!(entry.edx, 7);
+        }
+    }
 }
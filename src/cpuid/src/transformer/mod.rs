@@ -18,26 +18,50 @@ pub struct VmSpec {
     /// The desired brand string for the guest.
     brand_string: BrandString,
 
-    /// The index of the current logical cpu in the range [0..cpu_count].
+    /// The index of the current logical cpu, numbered thread first, then core, then package.
     cpu_index: u8,
-    /// The total number of logical cpus.
-    cpu_count: u8,
 
     /// The number of bits needed to enumerate logical CPUs per core.
     cpu_bits: u8,
+    /// The number of logical cpus per core.
+    threads_per_core: u8,
+    /// The number of cores per package.
+    cores_per_socket: u8,
+    /// The number of packages.
+    sockets: u8,
 }
 
 impl VmSpec {
     /// Creates a new instance of VmSpec with the specified parameters
     /// The brand string is deduced from the vendor_id
     pub fn new(cpu_index: u8, cpu_count: u8, ht_enabled: bool) -> Result<VmSpec, Error> {
+        let threads_per_core = if cpu_count > 1 && ht_enabled { 2 } else { 1 };
+        VmSpec::with_topology(cpu_index, 1, cpu_count / threads_per_core, threads_per_core)
+    }
+
+    /// Creates a new instance of VmSpec for a guest made of `sockets` packages of
+    /// `cores_per_socket` cores, each running `threads_per_core` logical cpus.
+    /// The logical cpus are numbered thread first, then core, then package.
+    pub fn with_topology(
+        cpu_index: u8,
+        sockets: u8,
+        cores_per_socket: u8,
+        threads_per_core: u8,
+    ) -> Result<VmSpec, Error> {
+        // The total number of logical cpus must fit in the APIC IDs.
+        sockets
+            .checked_mul(cores_per_socket)
+            .and_then(|cores| cores.checked_mul(threads_per_core))
+            .ok_or(Error::VcpuCountOverflow)?;
         let cpu_vendor_id = get_vendor_id_from_host().map_err(Error::InternalError)?;
 
         Ok(VmSpec {
             cpu_vendor_id,
             cpu_index,
-            cpu_count,
-            cpu_bits: (cpu_count > 1 && ht_enabled) as u8,
+            cpu_bits: bits_needed(threads_per_core),
+            threads_per_core,
+            cores_per_socket,
+            sockets,
             brand_string: BrandString::from_vendor_id(&cpu_vendor_id),
         })
     }
@@ -49,10 +73,35 @@ impl VmSpec {
 
     /// Returns the number of cpus per core
     pub fn cpus_per_core(&self) -> u8 {
-        1 << self.cpu_bits
+        self.threads_per_core
+    }
+
+    /// Returns the number of cpus per package
+    pub fn cpus_per_package(&self) -> u8 {
+        self.cores_per_socket * self.threads_per_core
+    }
+
+    /// Returns the index of the package of the current logical cpu
+    pub fn package_index(&self) -> u8 {
+        self.cpu_index / self.cpus_per_package()
+    }
+
+    /// Returns the number of bits to shift the APIC ID right to get the package ID, or
+    /// `single_package_bits` when there is a single package.
+    fn package_bits(&self, single_package_bits: u32) -> u32 {
+        if self.sockets > 1 {
+            u32::from(bits_needed(self.cpus_per_package()))
+        } else {
+            single_package_bits
+        }
     }
 }
 
+// The number of bits needed to enumerate `count` items.
+fn bits_needed(count: u8) -> u8 {
+    (8 - count.saturating_sub(1).leading_zeros()) as u8
+}
+
 /// Errors associated with processing the CPUID leaves.
 #[derive(Debug, Clone)]
 pub enum Error {
@@ -117,6 +166,38 @@ mod tests {
         let vm_spec = VmSpec::new(0, 2, true).unwrap();
         assert_eq!(vm_spec.cpu_bits, 1);
         assert_eq!(vm_spec.cpus_per_core(), 2);
+        assert_eq!(vm_spec.cpus_per_package(), 2);
+    }
+
+    #[test]
+    fn test_vmspec_with_topology() {
+        let vm_spec = VmSpec::with_topology(5, 2, 2, 2).unwrap();
+        assert_eq!(vm_spec.cpu_bits, 1);
+        assert_eq!(vm_spec.cpus_per_core(), 2);
+        assert_eq!(vm_spec.cpus_per_package(), 4);
+        assert_eq!(vm_spec.package_index(), 1);
+        assert_eq!(vm_spec.package_bits(7), 2);
+
+        let vm_spec = VmSpec::with_topology(5, 1, 6, 1).unwrap();
+        assert_eq!(vm_spec.cpu_bits, 0);
+        assert_eq!(vm_spec.cpus_per_package(), 6);
+        assert_eq!(vm_spec.package_index(), 0);
+        assert_eq!(vm_spec.package_bits(7), 7);
+
+        match VmSpec::with_topology(0, 16, 16, 2) {
+            Err(Error::VcpuCountOverflow) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_bits_needed() {
+        assert_eq!(bits_needed(1), 0);
+        assert_eq!(bits_needed(2), 1);
+        assert_eq!(bits_needed(3), 2);
+        assert_eq!(bits_needed(4), 2);
+        assert_eq!(bits_needed(5), 3);
+        assert_eq!(bits_needed(255), 8);
     }
 
     const PROCESSED_FN: u32 = 1;
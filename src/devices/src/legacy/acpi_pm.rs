@@ -0,0 +1,198 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::warn;
+use utils::eventfd::EventFd;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use crate::bus::BusDevice;
+
+// The PM1 event block, made of the status register followed by the enable register, and the PM1
+// control register right after it.
+const OFS_PM1_STS: u64 = 0;
+const OFS_PM1_EN: u64 = 2;
+const OFS_PM1_CNT: u64 = 4;
+
+/// The number of I/O ports used by the ACPI PM registers.
+pub const ACPI_PM_PORTS: u64 = 6;
+
+// The power button was pressed, in the status and enable registers.
+const PM1_PWRBTN: u16 = 1 << 8;
+// The SCI is routed to the guest, which always runs in ACPI mode.
+const PM1_CNT_SCI_EN: u16 = 1 << 0;
+// The sleep state the guest enters when it sets SLP_EN.
+const PM1_CNT_SLP_TYP_SHIFT: u16 = 10;
+const PM1_CNT_SLP_TYP_MASK: u16 = 0x7 << PM1_CNT_SLP_TYP_SHIFT;
+const PM1_CNT_SLP_EN: u16 = 1 << 13;
+
+// The value of SLP_TYP for the soft off state (S5), as found in the `\_S5_` object of the DSDT.
+const SLP_TYP_S5: u16 = 5;
+
+/// The state of the ACPI PM registers.
+#[derive(Clone, Debug, Default, PartialEq, Versionize)]
+pub struct AcpiPmState {
+    /// The PM1 status register.
+    pub pm1_sts: u16,
+    /// The PM1 enable register.
+    pub pm1_en: u16,
+    /// The PM1 control register.
+    pub pm1_cnt: u16,
+}
+
+/// The fixed hardware ACPI PM registers, through which the guest learns about the power button
+/// and powers the microVM off. The device raises the SCI when an enabled event is pending, and
+/// signals `off_evt` when the guest enters the soft off state. The registers are accessed
+/// 16 bits at a time.
+pub struct AcpiPm {
+    state: AcpiPmState,
+    sci_evt: EventFd,
+    off_evt: EventFd,
+}
+
+impl AcpiPm {
+    /// Creates the PM registers, raising the SCI through `sci_evt` and signaling `off_evt` when
+    /// the guest powers off.
+    pub fn new(sci_evt: EventFd, off_evt: EventFd) -> Self {
+        AcpiPm {
+            state: AcpiPmState::default(),
+            sci_evt,
+            off_evt,
+        }
+    }
+
+    /// Returns the state of the registers.
+    pub fn save_state(&self) -> AcpiPmState {
+        self.state.clone()
+    }
+
+    /// Restores the registers from a saved state.
+    pub fn restore_state(&mut self, state: &AcpiPmState) {
+        self.state = state.clone();
+    }
+
+    /// Presses the power button. Returns whether the guest listens to the power button, in
+    /// which case it is notified through the SCI.
+    pub fn press_power_button(&mut self) -> bool {
+        self.state.pm1_sts |= PM1_PWRBTN;
+        self.update_sci();
+        self.state.pm1_en & PM1_PWRBTN != 0
+    }
+
+    // Raises the SCI if an enabled event is pending.
+    fn update_sci(&self) {
+        if self.state.pm1_sts & self.state.pm1_en != 0 {
+            if let Err(e) = self.sci_evt.write(1) {
+                warn!("Failed to raise the SCI: {:?}", e);
+            }
+        }
+    }
+}
+
+impl BusDevice for AcpiPm {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        if data.len() != 2 {
+            return;
+        }
+        let value = match offset {
+            OFS_PM1_STS => self.state.pm1_sts,
+            OFS_PM1_EN => self.state.pm1_en,
+            OFS_PM1_CNT => self.state.pm1_cnt | PM1_CNT_SCI_EN,
+            _ => 0,
+        };
+        data.copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if data.len() != 2 {
+            return;
+        }
+        let value = u16::from_le_bytes([data[0], data[1]]);
+        match offset {
+            // The status bits are cleared by writing ones.
+            OFS_PM1_STS => self.state.pm1_sts &= !value,
+            OFS_PM1_EN => {
+                self.state.pm1_en = value;
+                self.update_sci();
+            }
+            OFS_PM1_CNT => {
+                self.state.pm1_cnt = value & !PM1_CNT_SLP_EN;
+                let slp_typ = (value & PM1_CNT_SLP_TYP_MASK) >> PM1_CNT_SLP_TYP_SHIFT;
+                if value & PM1_CNT_SLP_EN != 0 && slp_typ == SLP_TYP_S5 {
+                    if let Err(e) = self.off_evt.write(1) {
+                        warn!("Failed to signal the power off: {:?}", e);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_reg(pm: &mut AcpiPm, offset: u64) -> u16 {
+        let mut data = [0u8; 2];
+        pm.read(offset, &mut data);
+        u16::from_le_bytes(data)
+    }
+
+    #[test]
+    fn test_power_button() {
+        let sci_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let off_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut pm = AcpiPm::new(sci_evt.try_clone().unwrap(), off_evt.try_clone().unwrap());
+        assert_eq!(read_reg(&mut pm, OFS_PM1_CNT), PM1_CNT_SCI_EN);
+
+        // The guest doesn't listen to the power button yet.
+        assert!(!pm.press_power_button());
+        assert!(sci_evt.read().is_err());
+        assert_eq!(read_reg(&mut pm, OFS_PM1_STS), PM1_PWRBTN);
+
+        // Enabling the pending event raises the SCI.
+        pm.write(OFS_PM1_EN, &PM1_PWRBTN.to_le_bytes());
+        assert_eq!(sci_evt.read().unwrap(), 1);
+        pm.write(OFS_PM1_STS, &PM1_PWRBTN.to_le_bytes());
+        assert_eq!(read_reg(&mut pm, OFS_PM1_STS), 0);
+
+        assert!(pm.press_power_button());
+        assert_eq!(sci_evt.read().unwrap(), 1);
+
+        // Partial accesses are ignored.
+        pm.write(OFS_PM1_STS, &[0xff]);
+        let mut data = [0u8; 4];
+        pm.read(OFS_PM1_STS, &mut data);
+        assert_eq!(data, [0; 4]);
+        assert_eq!(read_reg(&mut pm, OFS_PM1_STS), PM1_PWRBTN);
+
+        let restored_state = pm.save_state();
+        let mut restored = AcpiPm::new(sci_evt, off_evt);
+        restored.restore_state(&restored_state);
+        assert_eq!(read_reg(&mut restored, OFS_PM1_EN), PM1_PWRBTN);
+    }
+
+    #[test]
+    fn test_power_off() {
+        let off_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut pm = AcpiPm::new(
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            off_evt.try_clone().unwrap(),
+        );
+
+        // Setting the sleep type alone doesn't power off.
+        let slp_typ = SLP_TYP_S5 << PM1_CNT_SLP_TYP_SHIFT;
+        pm.write(OFS_PM1_CNT, &slp_typ.to_le_bytes());
+        assert!(off_evt.read().is_err());
+        assert_eq!(read_reg(&mut pm, OFS_PM1_CNT), slp_typ | PM1_CNT_SCI_EN);
+
+        // Other sleep states are not supported.
+        pm.write(OFS_PM1_CNT, &(PM1_CNT_SLP_EN | 3 << 10).to_le_bytes());
+        assert!(off_evt.read().is_err());
+
+        pm.write(OFS_PM1_CNT, &(slp_typ | PM1_CNT_SLP_EN).to_le_bytes());
+        assert_eq!(off_evt.read().unwrap(), 1);
+        assert_eq!(read_reg(&mut pm, OFS_PM1_CNT) & PM1_CNT_SLP_EN, 0);
+    }
+}
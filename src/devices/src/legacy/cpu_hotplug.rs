@@ -0,0 +1,293 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::warn;
+use utils::eventfd::EventFd;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use crate::bus::BusDevice;
+
+// The vCPU the flags register refers to.
+const OFS_SELECT: u64 = 0;
+// The flags of the selected vCPU.
+const OFS_FLAGS: u64 = 1;
+// The pending events of the Generic Event Device, cleared when read.
+const OFS_GED_EVENTS: u64 = 2;
+
+/// The number of I/O ports used by the vCPU hotplug controller and the GED event register.
+pub const CPU_HOTPLUG_PORTS: u64 = 3;
+
+// The vCPU is present, in the flags register. Read-only.
+const FLAG_PRESENT: u8 = 1 << 0;
+// The guest was not yet notified of the insertion of the vCPU. Cleared by writing 1.
+const FLAG_INSERTING: u8 = 1 << 1;
+// The guest was not yet asked to eject the vCPU. Cleared by writing 1.
+const FLAG_REMOVING: u8 = 1 << 2;
+// Writing 1 ejects the vCPU, which the guest took offline.
+const FLAG_EJECT: u8 = 1 << 3;
+
+// The GED event telling the guest to scan the vCPU hotplug controller.
+const GED_EVENT_CPU_HOTPLUG: u8 = 1 << 0;
+
+/// The state of the vCPU hotplug controller.
+#[derive(Clone, Debug, Default, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct CpuHotplugState {
+    /// The number of vCPUs of the microVM.
+    pub cpu_count: u8,
+    /// The vCPU whose flags the guest accesses.
+    pub selected: u8,
+    /// The vCPUs present in the guest, one bit per vCPU.
+    pub present: u32,
+    /// The vCPUs whose insertion the guest was not yet notified of.
+    pub inserting: u32,
+    /// The vCPUs the guest was not yet asked to eject.
+    pub removing: u32,
+    /// The pending events of the Generic Event Device.
+    pub ged_events: u8,
+}
+
+/// The ACPI vCPU hotplug controller, along with the event register of the Generic Event Device
+/// (GED). The guest selects a vCPU through the first register and reads its flags through the
+/// second one. Plugging or unplugging vCPUs raises the GED interrupt through `ged_evt`, upon which
+/// the `_EVT` method of the GED, in the DSDT, scans the flags of the vCPUs and notifies the
+/// processor devices. The guest ejects the unplugged vCPUs once offline, which signals
+/// `eject_evt` so that their threads are paused.
+pub struct CpuHotplug {
+    state: CpuHotplugState,
+    ged_evt: EventFd,
+    eject_evt: EventFd,
+}
+
+impl CpuHotplug {
+    /// Creates a controller for `cpu_count` vCPUs, of which the first `plugged` are present in
+    /// the guest from boot.
+    pub fn new(cpu_count: u8, plugged: u8, ged_evt: EventFd, eject_evt: EventFd) -> Self {
+        CpuHotplug {
+            state: CpuHotplugState {
+                cpu_count,
+                present: cpu_mask(0..plugged),
+                ..Default::default()
+            },
+            ged_evt,
+            eject_evt,
+        }
+    }
+
+    /// Returns the state of the controller.
+    pub fn save_state(&self) -> CpuHotplugState {
+        self.state.clone()
+    }
+
+    /// Restores the controller from a saved state.
+    pub fn restore_state(&mut self, state: &CpuHotplugState) {
+        self.state = state.clone();
+    }
+
+    /// Returns the number of vCPUs of the microVM.
+    pub fn cpu_count(&self) -> u8 {
+        self.state.cpu_count
+    }
+
+    /// Returns whether the vCPU `index` is present in the guest, in which case its thread runs
+    /// while the microVM runs.
+    pub fn is_present(&self, index: u8) -> bool {
+        index < 32 && self.state.present & (1 << index) != 0
+    }
+
+    /// Returns the number of vCPUs present in the guest.
+    pub fn plugged(&self) -> u8 {
+        self.state.present.count_ones() as u8
+    }
+
+    /// Plugs the first `plugged` vCPUs and asks the guest to eject the others, `plugged` being
+    /// between 1 and the number of vCPUs of the microVM. Returns the vCPUs inserted, whose
+    /// threads must run before the guest is notified through `notify_guest`, or `None` if the
+    /// count is out of range.
+    pub fn set_plugged(&mut self, plugged: u8) -> Option<Vec<u8>> {
+        if plugged == 0 || plugged > self.state.cpu_count {
+            return None;
+        }
+        let plugged_mask = cpu_mask(0..plugged);
+        let inserted = plugged_mask & !self.state.present;
+        self.state.present |= inserted;
+        self.state.inserting |= inserted;
+        // An ejection the guest was not asked for yet is cancelled.
+        self.state.removing &= !plugged_mask;
+        self.state.removing |= self.state.present & !plugged_mask;
+        self.state.inserting &= plugged_mask;
+        Some((0..plugged).filter(|i| inserted & (1 << i) != 0).collect())
+    }
+
+    /// Raises the GED interrupt if vCPUs are to be inserted or ejected.
+    pub fn notify_guest(&mut self) {
+        if self.state.inserting | self.state.removing == 0 {
+            return;
+        }
+        self.state.ged_events |= GED_EVENT_CPU_HOTPLUG;
+        if let Err(e) = self.ged_evt.write(1) {
+            warn!("Failed to raise the GED interrupt: {}", e);
+        }
+    }
+
+    /// Returns the event signaled when the guest ejects a vCPU.
+    pub fn eject_evt(&self) -> &EventFd {
+        &self.eject_evt
+    }
+
+    fn selected_mask(&self) -> u32 {
+        if self.state.selected < self.state.cpu_count {
+            1 << self.state.selected
+        } else {
+            0
+        }
+    }
+
+    fn read_flags(&self) -> u8 {
+        let mask = self.selected_mask();
+        let mut flags = 0;
+        if self.state.present & mask != 0 {
+            flags |= FLAG_PRESENT;
+        }
+        if self.state.inserting & mask != 0 {
+            flags |= FLAG_INSERTING;
+        }
+        if self.state.removing & mask != 0 {
+            flags |= FLAG_REMOVING;
+        }
+        flags
+    }
+
+    fn write_flags(&mut self, flags: u8) {
+        let mask = self.selected_mask();
+        if flags & FLAG_INSERTING != 0 {
+            self.state.inserting &= !mask;
+        }
+        if flags & FLAG_REMOVING != 0 {
+            self.state.removing &= !mask;
+        }
+        // The microVM always keeps its first vCPU.
+        if flags & FLAG_EJECT != 0 && mask > 1 && self.state.present & mask != 0 {
+            self.state.present &= !mask;
+            self.state.inserting &= !mask;
+            if let Err(e) = self.eject_evt.write(1) {
+                warn!("Failed to signal the ejection of a vCPU: {}", e);
+            }
+        }
+    }
+}
+
+// Returns the mask of the vCPUs in `range`.
+fn cpu_mask(range: std::ops::Range<u8>) -> u32 {
+    range.fold(0, |mask, i| mask | 1 << i)
+}
+
+impl BusDevice for CpuHotplug {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        if data.len() != 1 {
+            return;
+        }
+        data[0] = match offset {
+            OFS_SELECT => self.state.selected,
+            OFS_FLAGS => self.read_flags(),
+            OFS_GED_EVENTS => std::mem::replace(&mut self.state.ged_events, 0),
+            _ => 0,
+        };
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if data.len() != 1 {
+            return;
+        }
+        match offset {
+            OFS_SELECT => self.state.selected = data[0],
+            OFS_FLAGS => self.write_flags(data[0]),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_byte(hotplug: &mut CpuHotplug, offset: u64) -> u8 {
+        let mut data = [0u8];
+        hotplug.read(offset, &mut data);
+        data[0]
+    }
+
+    fn flags(hotplug: &mut CpuHotplug, index: u8) -> u8 {
+        hotplug.write(OFS_SELECT, &[index]);
+        read_byte(hotplug, OFS_FLAGS)
+    }
+
+    #[test]
+    fn test_cpu_hotplug() {
+        let ged_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let eject_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut hotplug = CpuHotplug::new(
+            4,
+            2,
+            ged_evt.try_clone().unwrap(),
+            eject_evt.try_clone().unwrap(),
+        );
+        assert_eq!(hotplug.plugged(), 2);
+        assert_eq!(hotplug.cpu_count(), 4);
+        assert_eq!(flags(&mut hotplug, 1), FLAG_PRESENT);
+        assert_eq!(flags(&mut hotplug, 2), 0);
+        assert_eq!(flags(&mut hotplug, 4), 0);
+        assert_eq!(read_byte(&mut hotplug, OFS_SELECT), 4);
+
+        // Nothing changed, so the guest isn't notified.
+        hotplug.notify_guest();
+        assert!(ged_evt.read().is_err());
+
+        // The inserted vCPUs are flagged until the guest is notified.
+        assert_eq!(hotplug.set_plugged(3), Some(vec![2]));
+        assert!(hotplug.is_present(2));
+        hotplug.notify_guest();
+        assert_eq!(ged_evt.read().unwrap(), 1);
+        assert_eq!(
+            read_byte(&mut hotplug, OFS_GED_EVENTS),
+            GED_EVENT_CPU_HOTPLUG
+        );
+        assert_eq!(read_byte(&mut hotplug, OFS_GED_EVENTS), 0);
+        assert_eq!(flags(&mut hotplug, 2), FLAG_PRESENT | FLAG_INSERTING);
+        hotplug.write(OFS_FLAGS, &[FLAG_INSERTING]);
+        assert_eq!(flags(&mut hotplug, 2), FLAG_PRESENT);
+
+        // The unplugged vCPUs stay present until the guest ejects them.
+        assert_eq!(hotplug.set_plugged(1), Some(vec![]));
+        assert_eq!(hotplug.plugged(), 3);
+        assert_eq!(flags(&mut hotplug, 1), FLAG_PRESENT | FLAG_REMOVING);
+        hotplug.write(OFS_FLAGS, &[FLAG_REMOVING]);
+        assert_eq!(flags(&mut hotplug, 1), FLAG_PRESENT);
+        hotplug.write(OFS_FLAGS, &[FLAG_EJECT]);
+        assert_eq!(flags(&mut hotplug, 1), 0);
+        assert!(!hotplug.is_present(1));
+        assert_eq!(eject_evt.read().unwrap(), 1);
+
+        // Plugging vCPUs back cancels the pending ejections.
+        assert_eq!(flags(&mut hotplug, 2), FLAG_PRESENT | FLAG_REMOVING);
+        assert_eq!(hotplug.set_plugged(4), Some(vec![1, 3]));
+        assert_eq!(flags(&mut hotplug, 2), FLAG_PRESENT);
+        assert_eq!(flags(&mut hotplug, 3), FLAG_PRESENT | FLAG_INSERTING);
+
+        // The first vCPU can't be ejected.
+        hotplug.write(OFS_SELECT, &[0]);
+        hotplug.write(OFS_FLAGS, &[FLAG_EJECT]);
+        assert!(hotplug.is_present(0));
+        assert!(eject_evt.read().is_err());
+
+        assert_eq!(hotplug.set_plugged(0), None);
+        assert_eq!(hotplug.set_plugged(5), None);
+        assert_eq!(hotplug.plugged(), 4);
+
+        let mut restored = CpuHotplug::new(4, 1, ged_evt, eject_evt);
+        restored.restore_state(&hotplug.save_state());
+        assert_eq!(restored.save_state(), hotplug.save_state());
+        assert_eq!(restored.plugged(), 4);
+    }
+}
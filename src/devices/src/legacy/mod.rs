@@ -5,13 +5,33 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
+#[cfg(target_arch = "x86_64")]
+mod acpi_pm;
+#[cfg(target_arch = "x86_64")]
+mod cpu_hotplug;
 mod i8042;
+mod panic_detector;
+#[cfg(target_arch = "x86_64")]
+mod pflash;
+mod pvpanic;
 #[cfg(target_arch = "aarch64")]
 mod rtc_pl031;
 mod serial;
+#[cfg(target_arch = "x86_64")]
+mod watchdog;
 
+#[cfg(target_arch = "x86_64")]
+pub use self::acpi_pm::{AcpiPm, AcpiPmState, ACPI_PM_PORTS};
+#[cfg(target_arch = "x86_64")]
+pub use self::cpu_hotplug::{CpuHotplug, CpuHotplugState, CPU_HOTPLUG_PORTS};
 pub use self::i8042::Error as I8042DeviceError;
 pub use self::i8042::I8042Device;
+pub use self::panic_detector::{PanicDetector, KERNEL_PANIC_PATTERN};
+#[cfg(target_arch = "x86_64")]
+pub use self::pflash::{Pflash, PFLASH_BLOCK_SIZE};
+pub use self::pvpanic::{PvPanic, PVPANIC_CRASH_LOADED, PVPANIC_PANICKED};
 #[cfg(target_arch = "aarch64")]
 pub use self::rtc_pl031::RTC;
-pub use self::serial::{ReadableFd, Serial};
+pub use self::serial::{ReadableFd, Serial, SerialState};
+#[cfg(target_arch = "x86_64")]
+pub use self::watchdog::{Error as WatchdogError, Watchdog, WatchdogState, WATCHDOG_BAR_SIZE};
@@ -0,0 +1,85 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io;
+
+use logger::warn;
+use utils::eventfd::EventFd;
+
+/// The message printed on the console by the Linux kernel when it panics.
+pub const KERNEL_PANIC_PATTERN: &[u8] = b"Kernel panic - not syncing";
+
+/// Wrapper over the serial console output which signals an `EventFd` whenever the
+/// guest kernel panic message goes through it.
+pub struct PanicDetector {
+    out: Box<dyn io::Write + Send>,
+    panic_evt: EventFd,
+    // The tail of the output written so far, used for matching the pattern across writes,
+    // since the serial device writes one byte at a time.
+    window: Vec<u8>,
+}
+
+impl PanicDetector {
+    /// Creates a new `PanicDetector` writing to `out` and signaling `panic_evt`.
+    pub fn new(out: Box<dyn io::Write + Send>, panic_evt: EventFd) -> Self {
+        PanicDetector {
+            out,
+            panic_evt,
+            window: Vec::with_capacity(KERNEL_PANIC_PATTERN.len()),
+        }
+    }
+
+    fn scan(&mut self, buf: &[u8]) {
+        for &byte in buf {
+            if self.window.len() == KERNEL_PANIC_PATTERN.len() {
+                self.window.remove(0);
+            }
+            self.window.push(byte);
+
+            if self.window.as_slice() == KERNEL_PANIC_PATTERN {
+                self.window.clear();
+                if let Err(e) = self.panic_evt.write(1) {
+                    warn!("Failed to signal the guest panic event: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+impl io::Write for PanicDetector {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.out.write(buf)?;
+        self.scan(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_panic_detector() {
+        let panic_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut detector = PanicDetector::new(Box::new(io::sink()), panic_evt.try_clone().unwrap());
+
+        detector.write_all(b"Kernel panic - syncing\n").unwrap();
+        assert!(panic_evt.read().is_err());
+
+        // The pattern is matched even when written one byte at a time.
+        for byte in b"[    1.0] Kernel panic - not syncing: VFS" {
+            detector.write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(panic_evt.read().unwrap(), 1);
+
+        detector.write_all(KERNEL_PANIC_PATTERN).unwrap();
+        detector.write_all(KERNEL_PANIC_PATTERN).unwrap();
+        assert_eq!(panic_evt.read().unwrap(), 2);
+        assert!(detector.flush().is_ok());
+    }
+}
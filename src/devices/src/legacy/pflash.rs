@@ -0,0 +1,235 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+
+use logger::error;
+
+use crate::bus::BusDevice;
+
+/// The size of the blocks the guest erases at once, which the store is made of.
+pub const PFLASH_BLOCK_SIZE: u64 = 0x1000;
+
+// The commands of the Intel command set of CFI flash memories used by the firmwares.
+const CMD_PROGRAM_BYTE: u8 = 0x10;
+const CMD_BLOCK_ERASE: u8 = 0x20;
+const CMD_PROGRAM_BYTE_ALT: u8 = 0x40;
+const CMD_CLEAR_STATUS: u8 = 0x50;
+const CMD_READ_STATUS: u8 = 0x70;
+const CMD_ERASE_CONFIRM: u8 = 0xd0;
+const CMD_READ_ARRAY: u8 = 0xff;
+
+// The bit of the status register telling that the flash is ready for a new command.
+const STATUS_READY: u8 = 0x80;
+
+// What the flash does with the next access.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    // Reads return the content of the store.
+    ReadArray,
+    // Reads return the status register.
+    ReadStatus,
+    // The next write is the byte to program.
+    Program,
+    // The next write confirms the erasure of a block.
+    EraseSetup,
+}
+
+/// A flash memory holding the persistent variable store of a firmware, such as the NVRAM of
+/// OVMF. It implements the subset of the Intel command set of CFI flash memories that the
+/// firmwares use for reading, programming and erasing it.
+/// The store is backed by a file, which every write goes through to, so that the variables
+/// outlive the microVM.
+pub struct Pflash {
+    file: File,
+    data: Vec<u8>,
+    mode: Mode,
+    status: u8,
+}
+
+impl Pflash {
+    /// Creates a flash memory holding the content of `file`, whose size must be a multiple
+    /// of `PFLASH_BLOCK_SIZE`.
+    pub fn new(mut file: File) -> io::Result<Self> {
+        let size = file.seek(SeekFrom::End(0))?;
+        if size == 0 || size % PFLASH_BLOCK_SIZE != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The size of the store is not a multiple of the flash block size",
+            ));
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut data)?;
+        Ok(Pflash {
+            file,
+            data,
+            mode: Mode::ReadArray,
+            status: 0,
+        })
+    }
+
+    /// Returns the size of the store.
+    pub fn len(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    /// Returns whether the store is empty, which it never is.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // Writes `data` at `offset` in the store, and through to the file.
+    fn store(&mut self, offset: usize, data: &[u8]) {
+        let end = offset + data.len();
+        if end > self.data.len() {
+            return;
+        }
+        self.data[offset..end].copy_from_slice(data);
+        if let Err(e) = self.file.write_all_at(data, offset as u64) {
+            error!("Failed to write to the flash store: {}", e);
+        }
+    }
+
+    fn run_command(&mut self, command: u8) {
+        self.mode = match command {
+            CMD_PROGRAM_BYTE | CMD_PROGRAM_BYTE_ALT => Mode::Program,
+            CMD_BLOCK_ERASE => Mode::EraseSetup,
+            CMD_CLEAR_STATUS => {
+                self.status = 0;
+                self.mode
+            }
+            CMD_READ_STATUS => Mode::ReadStatus,
+            CMD_READ_ARRAY => Mode::ReadArray,
+            _ => {
+                error!("Unsupported flash command: {:#x}", command);
+                Mode::ReadArray
+            }
+        };
+    }
+}
+
+impl BusDevice for Pflash {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        match self.mode {
+            Mode::ReadArray => {
+                let start = offset as usize;
+                if let Some(content) = self.data.get(start..start + data.len()) {
+                    data.copy_from_slice(content);
+                }
+            }
+            _ => {
+                for byte in data.iter_mut() {
+                    *byte = self.status;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        match self.mode {
+            Mode::Program => {
+                self.store(offset as usize, data);
+                self.status |= STATUS_READY;
+                self.mode = Mode::ReadStatus;
+            }
+            Mode::EraseSetup if data[0] == CMD_ERASE_CONFIRM => {
+                let block_start = (offset & !(PFLASH_BLOCK_SIZE - 1)) as usize;
+                self.store(block_start, &[0xff; PFLASH_BLOCK_SIZE as usize]);
+                self.status |= STATUS_READY;
+                self.mode = Mode::ReadStatus;
+            }
+            Mode::EraseSetup => self.mode = Mode::ReadArray,
+            Mode::ReadArray | Mode::ReadStatus => self.run_command(data[0]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    fn read_byte(pflash: &mut Pflash, offset: u64) -> u8 {
+        let mut data = [0u8];
+        pflash.read(offset, &mut data);
+        data[0]
+    }
+
+    #[test]
+    fn test_new() {
+        let store = TempFile::new().unwrap();
+        assert!(Pflash::new(store.as_file().try_clone().unwrap()).is_err());
+        store.as_file().set_len(PFLASH_BLOCK_SIZE + 1).unwrap();
+        assert!(Pflash::new(store.as_file().try_clone().unwrap()).is_err());
+        store.as_file().set_len(2 * PFLASH_BLOCK_SIZE).unwrap();
+        let pflash = Pflash::new(store.as_file().try_clone().unwrap()).unwrap();
+        assert_eq!(pflash.len(), 2 * PFLASH_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_detection() {
+        let store = TempFile::new().unwrap();
+        store.as_file().write_all_at(&[0x5a], 0).unwrap();
+        store.as_file().set_len(PFLASH_BLOCK_SIZE).unwrap();
+        let mut pflash = Pflash::new(store.as_file().try_clone().unwrap()).unwrap();
+
+        // The firmwares tell a flash from RAM and ROM by the status they read after clearing it.
+        pflash.write(0, &[CMD_CLEAR_STATUS]);
+        assert_eq!(read_byte(&mut pflash, 0), 0x5a);
+        pflash.write(0, &[CMD_READ_STATUS]);
+        assert_eq!(read_byte(&mut pflash, 0), 0);
+        pflash.write(0, &[CMD_READ_ARRAY]);
+        assert_eq!(read_byte(&mut pflash, 0), 0x5a);
+    }
+
+    #[test]
+    fn test_program_and_erase() {
+        let store = TempFile::new().unwrap();
+        store.as_file().set_len(2 * PFLASH_BLOCK_SIZE).unwrap();
+        let mut pflash = Pflash::new(store.as_file().try_clone().unwrap()).unwrap();
+
+        let offset = PFLASH_BLOCK_SIZE + 0x10;
+        pflash.write(offset, &[CMD_PROGRAM_BYTE]);
+        pflash.write(offset, &[0xab]);
+        assert_eq!(read_byte(&mut pflash, offset), STATUS_READY);
+        pflash.write(offset + 1, &[CMD_PROGRAM_BYTE_ALT]);
+        pflash.write(offset + 1, &[0xcd]);
+        pflash.write(0, &[CMD_READ_ARRAY]);
+        assert_eq!(read_byte(&mut pflash, offset), 0xab);
+        assert_eq!(read_byte(&mut pflash, offset + 1), 0xcd);
+
+        // The writes go through to the file.
+        let mut content = [0u8; 2];
+        store.as_file().read_exact_at(&mut content, offset).unwrap();
+        assert_eq!(content, [0xab, 0xcd]);
+
+        // An erasure which isn't confirmed leaves the block alone.
+        pflash.write(offset, &[CMD_BLOCK_ERASE]);
+        pflash.write(offset, &[CMD_READ_ARRAY]);
+        assert_eq!(read_byte(&mut pflash, offset), 0xab);
+
+        // The erasure of a block resets all of it, and only it.
+        pflash.write(0, &[CMD_PROGRAM_BYTE]);
+        pflash.write(0, &[0x12]);
+        pflash.write(offset, &[CMD_BLOCK_ERASE]);
+        pflash.write(offset, &[CMD_ERASE_CONFIRM]);
+        assert_eq!(read_byte(&mut pflash, offset), STATUS_READY);
+        pflash.write(0, &[CMD_READ_ARRAY]);
+        assert_eq!(read_byte(&mut pflash, PFLASH_BLOCK_SIZE), 0xff);
+        assert_eq!(read_byte(&mut pflash, offset + 1), 0xff);
+        assert_eq!(read_byte(&mut pflash, 0), 0x12);
+        store.as_file().read_exact_at(&mut content, offset).unwrap();
+        assert_eq!(content, [0xff, 0xff]);
+
+        // Writes past the end of the store are dropped.
+        pflash.write(2 * PFLASH_BLOCK_SIZE, &[CMD_PROGRAM_BYTE]);
+        pflash.write(2 * PFLASH_BLOCK_SIZE, &[0x34]);
+        assert_eq!(pflash.len(), 2 * PFLASH_BLOCK_SIZE);
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{warn, IncMetric, METRICS};
+use utils::eventfd::EventFd;
+
+use crate::bus::BusDevice;
+
+/// The guest kernel panicked.
+pub const PVPANIC_PANICKED: u8 = 1 << 0;
+/// The guest kernel panicked and is about to boot the crash kernel it loaded.
+pub const PVPANIC_CRASH_LOADED: u8 = 1 << 1;
+
+// The events the device reports to the guest as supported.
+const PVPANIC_CAPABILITIES: u8 = PVPANIC_PANICKED | PVPANIC_CRASH_LOADED;
+
+/// A pvpanic device, through which the guest kernel reports its crashes. The guest reads the
+/// supported events from the single byte register of the device, then writes there the event
+/// which occurred from its panic notifier.
+pub struct PvPanic {
+    event_evt: EventFd,
+    // The events written by the guest and not yet handled by the VMM.
+    pending_events: u8,
+}
+
+impl PvPanic {
+    /// Creates a new pvpanic device, signaling `event_evt` when the guest reports an event.
+    pub fn new(event_evt: EventFd) -> Self {
+        PvPanic {
+            event_evt,
+            pending_events: 0,
+        }
+    }
+
+    /// Returns the events reported by the guest since the last call, as a bitmask of
+    /// `PVPANIC_PANICKED` and `PVPANIC_CRASH_LOADED`.
+    pub fn take_events(&mut self) -> u8 {
+        std::mem::replace(&mut self.pending_events, 0)
+    }
+}
+
+impl BusDevice for PvPanic {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        if offset != 0 || data.len() != 1 {
+            return;
+        }
+        data[0] = PVPANIC_CAPABILITIES;
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if offset != 0 || data.len() != 1 {
+            return;
+        }
+        let events = data[0] & PVPANIC_CAPABILITIES;
+        if events == 0 {
+            return;
+        }
+
+        METRICS.vmm.pvpanic_count.inc();
+        self.pending_events |= events;
+        if let Err(e) = self.event_evt.write(1) {
+            warn!("Failed to signal the pvpanic event: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pvpanic() {
+        let event_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut pvpanic = PvPanic::new(event_evt.try_clone().unwrap());
+
+        let mut data = [0u8];
+        pvpanic.read(0, &mut data);
+        assert_eq!(data[0], PVPANIC_PANICKED | PVPANIC_CRASH_LOADED);
+
+        // Out of range and unsupported writes are ignored.
+        pvpanic.write(1, &[PVPANIC_PANICKED]);
+        pvpanic.write(0, &[PVPANIC_PANICKED, 0]);
+        pvpanic.write(0, &[1 << 4]);
+        assert!(event_evt.read().is_err());
+        assert_eq!(pvpanic.take_events(), 0);
+
+        let count = METRICS.vmm.pvpanic_count.count();
+        pvpanic.write(0, &[PVPANIC_PANICKED]);
+        pvpanic.write(0, &[PVPANIC_CRASH_LOADED | 1 << 4]);
+        assert_eq!(event_evt.read().unwrap(), 2);
+        assert_eq!(METRICS.vmm.pvpanic_count.count(), count + 2);
+        assert_eq!(
+            pvpanic.take_events(),
+            PVPANIC_PANICKED | PVPANIC_CRASH_LOADED
+        );
+        assert_eq!(pvpanic.take_events(), 0);
+    }
+}
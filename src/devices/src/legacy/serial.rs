@@ -13,6 +13,8 @@ use logger::{error, warn, IncMetric, METRICS};
 use polly::event_manager::{EventManager, Pollable, Subscriber};
 use utils::epoll::{EpollEvent, EventSet};
 use utils::eventfd::EventFd;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
 
 use crate::bus::BusDevice;
 
@@ -60,6 +62,21 @@ const DEFAULT_BAUD_DIVISOR: u16 = 12; // 9600 bps
 /// Trait that composes the `std::io::Read` and `std::os::unix::io::AsRawFd` traits.
 pub trait ReadableFd: io::Read + AsRawFd {}
 
+/// Holds the register state of a serial port.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct SerialState {
+    interrupt_enable: u8,
+    interrupt_identification: u8,
+    line_control: u8,
+    line_status: u8,
+    modem_control: u8,
+    modem_status: u8,
+    scratch: u8,
+    baud_divisor: u16,
+    in_buffer: Vec<u8>,
+}
+
 /// Emulates serial COM ports commonly seen on x86 I/O ports 0x3f8/0x2f8/0x3e8/0x2e8.
 ///
 /// This can optionally write the guest's output to a Write trait object. To send input to the
@@ -133,6 +150,35 @@ impl Serial {
         &self.interrupt_evt
     }
 
+    /// Saves the register state of the serial port.
+    pub fn save_state(&self) -> SerialState {
+        SerialState {
+            interrupt_enable: self.interrupt_enable,
+            interrupt_identification: self.interrupt_identification,
+            line_control: self.line_control,
+            line_status: self.line_status,
+            modem_control: self.modem_control,
+            modem_status: self.modem_status,
+            scratch: self.scratch,
+            baud_divisor: self.baud_divisor,
+            in_buffer: self.in_buffer.iter().copied().collect(),
+        }
+    }
+
+    /// Restores the register state of the serial port. The input and output are left untouched.
+    pub fn restore_state(&mut self, state: &SerialState) {
+        self.interrupt_enable = state.interrupt_enable;
+        self.interrupt_identification = state.interrupt_identification;
+        self.line_control = state.line_control;
+        self.line_status = state.line_status;
+        self.modem_control = state.modem_control;
+        self.modem_status = state.modem_status;
+        self.scratch = state.scratch;
+        self.baud_divisor = state.baud_divisor;
+        // The buffered input cannot exceed the FIFO, whatever the snapshot says.
+        self.in_buffer = state.in_buffer.iter().take(FIFO_SIZE).copied().collect();
+    }
+
     fn is_dlab_set(&self) -> bool {
         (self.line_control & LCR_DLAB_BIT) != 0
     }
@@ -811,6 +857,42 @@ mod tests {
         assert_eq!(data[0], 0x12 as u8);
     }
 
+    #[test]
+    fn test_serial_state() {
+        let mut serial = Serial::new_sink(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        serial.write(u64::from(LCR), &[LCR_DLAB_BIT as u8]);
+        serial.write(u64::from(DLAB_LOW), &[0x01 as u8]);
+        serial.write(u64::from(LCR), &[DEFAULT_LINE_CONTROL]);
+        serial.write(u64::from(IER), &[IER_RECV_BIT]);
+        serial.write(u64::from(SCR), &[0x12 as u8]);
+        serial.raw_input(&RAW_INPUT_BUF).unwrap();
+
+        let state = serial.save_state();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let state = SerialState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap();
+
+        let mut restored_serial = Serial::new_sink(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        restored_serial.restore_state(&state);
+        assert_eq!(restored_serial.save_state(), serial.save_state());
+
+        let mut data = [0u8];
+        restored_serial.read(u64::from(SCR), &mut data[..]);
+        assert_eq!(data[0], 0x12);
+        restored_serial.read(u64::from(LSR), &mut data[..]);
+        assert_ne!(data[0] & LSR_DATA_BIT, 0);
+        for byte in RAW_INPUT_BUF.iter() {
+            restored_serial.read(u64::from(DATA), &mut data[..]);
+            assert_eq!(data[0], *byte);
+        }
+        restored_serial.write(u64::from(LCR), &[LCR_DLAB_BIT as u8]);
+        restored_serial.read(u64::from(DLAB_LOW), &mut data[..]);
+        assert_eq!(data[0], 0x01);
+    }
+
     #[test]
     fn test_serial_data_len() {
         const LEN: usize = 1;
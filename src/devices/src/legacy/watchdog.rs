@@ -0,0 +1,565 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use logger::{IncMetric, METRICS};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use crate::bus::BusDevice;
+use crate::pci::{Error as PciError, PciConfiguration, PciConfigurationState, PciDevice};
+
+const PCI_VENDOR_ID_INTEL: u16 = 0x8086;
+const PCI_DEVICE_ID_ESB_WDT: u16 = 0x25ab;
+// Base system peripheral, other.
+const PCI_CLASS_SYSTEM: u8 = 0x08;
+const PCI_SUBCLASS_SYSTEM_OTHER: u8 = 0x80;
+
+/// The size of the memory BAR holding the timer registers.
+pub const WATCHDOG_BAR_SIZE: u64 = 16;
+
+// The configuration register, at offset 0x60 of the configuration space.
+const REG_CONFIG: usize = 0x60 / 4;
+// Which interrupt the first stage raises, the 1 MHz prescaler and the disabled reboot.
+const CONFIG_WRITABLE_BITS: u32 = 0x27;
+const CONFIG_PRESCALER_1MHZ: u32 = 1 << 2;
+const CONFIG_NO_REBOOT: u32 = 1 << 5;
+
+// The lock register, at offset 0x68 of the configuration space.
+const REG_LOCK: usize = 0x68 / 4;
+const LOCK_WRITABLE_BITS: u32 = 0x07;
+const LOCK_LOCKED: u32 = 1 << 0;
+const LOCK_ENABLE: u32 = 1 << 1;
+const LOCK_FREE_RUN: u32 = 1 << 2;
+
+// Registers of the memory BAR. The preload registers are only writable right after the unlock
+// sequence is written to the reload register.
+const OFS_TIMER1: u64 = 0x00;
+const OFS_TIMER2: u64 = 0x04;
+const OFS_RELOAD: u64 = 0x0c;
+
+const RELOAD_RELOAD: u32 = 1 << 8;
+const RELOAD_TIMEOUT: u32 = 1 << 9;
+const UNLOCK1: u32 = 0x80;
+const UNLOCK2: u32 = 0x86;
+// Steps of the unlock sequence.
+const UNLOCK_NONE: u8 = 0;
+const UNLOCK_FIRST: u8 = 1;
+const UNLOCK_DONE: u8 = 2;
+
+const PRELOAD_MASK: u32 = 0xf_ffff;
+// A tick of the timer is 2^15 periods of the 33 MHz PCI clock, or 2^5 with the 1 MHz prescaler.
+const PCI_CLOCK_PERIOD_NS: u64 = 30;
+
+/// Errors associated with the watchdog.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to set up the PCI function.
+    Pci(PciError),
+    /// Failed to create the timer.
+    Timer(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+
+        match self {
+            Pci(err) => write!(f, "Failed to set up the PCI function: {}", err),
+            Timer(err) => write!(f, "Failed to create the timer: {}", err),
+        }
+    }
+}
+
+/// The state of the watchdog timer.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+pub struct WatchdogState {
+    /// Whether the timer is running.
+    pub armed: bool,
+    /// The time left before the current stage expires, in milliseconds.
+    pub remaining_ms: u64,
+    /// Whether the timer is in its second stage.
+    pub second_stage: bool,
+    /// The number of ticks of the first stage.
+    pub timer1_preload: u32,
+    /// The number of ticks of the second stage.
+    pub timer2_preload: u32,
+    /// The step reached in the unlock sequence of the preload registers.
+    pub unlock_step: u8,
+    /// Whether the watchdog expired since the guest last acknowledged it.
+    pub timed_out: bool,
+    /// The slot of the watchdog on the PCI root bus.
+    pub slot: u8,
+    /// The address of the memory BAR.
+    pub bar_addr: u64,
+    /// The configuration space, holding the configuration and lock registers.
+    pub config: PciConfigurationState,
+}
+
+/// An Intel 6300ESB watchdog timer, which the guest kernel pets periodically.
+///
+/// The timer has two stages: the first one expiring starts the second one, and the watchdog
+/// expires at the end of the second one, unless the guest disabled the reboot. The first stage
+/// raises no interrupt, since the devices of the PCI root bus only interrupt through MSI-X.
+/// The timer is stopped while the microVM is paused, so that pauses don't count as hangs.
+pub struct Watchdog {
+    config: PciConfiguration,
+    slot: u8,
+    bar_addr: u64,
+    timer: TimerFd,
+    second_stage: bool,
+    timer1_preload: u32,
+    timer2_preload: u32,
+    unlock_step: u8,
+    timed_out: bool,
+    // Whether the microVM is paused.
+    paused: bool,
+    // The time left before expiry, while the microVM is paused and the watchdog armed.
+    remaining: Option<Duration>,
+}
+
+impl Watchdog {
+    /// Creates a new, stopped, watchdog for `slot` of the PCI root bus, with its BAR at
+    /// `bar_addr`.
+    pub fn new(slot: u8, bar_addr: u64) -> Result<Self, Error> {
+        let mut config = PciConfiguration::new(
+            PCI_VENDOR_ID_INTEL,
+            PCI_DEVICE_ID_ESB_WDT,
+            PCI_CLASS_SYSTEM,
+            PCI_SUBCLASS_SYSTEM_OTHER,
+            0,
+            0,
+            0,
+            0,
+        );
+        config
+            .add_memory_bar64(bar_addr, WATCHDOG_BAR_SIZE)
+            .map_err(Error::Pci)?;
+        config.set_writable_bits(REG_CONFIG, CONFIG_WRITABLE_BITS);
+        config.set_writable_bits(REG_LOCK, LOCK_WRITABLE_BITS);
+
+        Ok(Watchdog {
+            config,
+            slot,
+            bar_addr,
+            timer: TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(Error::Timer)?,
+            second_stage: false,
+            timer1_preload: PRELOAD_MASK,
+            timer2_preload: PRELOAD_MASK,
+            unlock_step: UNLOCK_NONE,
+            timed_out: false,
+            paused: false,
+            remaining: None,
+        })
+    }
+
+    /// Creates a watchdog from a saved state. The watchdog starts paused, like the microVM it
+    /// belongs to.
+    pub fn restore(state: &WatchdogState) -> Result<Self, Error> {
+        let mut watchdog = Self::new(state.slot, state.bar_addr)?;
+        watchdog.config.restore_state(&state.config);
+        watchdog.second_stage = state.second_stage;
+        watchdog.timer1_preload = state.timer1_preload;
+        watchdog.timer2_preload = state.timer2_preload;
+        watchdog.unlock_step = state.unlock_step;
+        watchdog.timed_out = state.timed_out;
+        watchdog.paused = true;
+        if state.armed {
+            watchdog.remaining = Some(Duration::from_millis(state.remaining_ms));
+        }
+        Ok(watchdog)
+    }
+
+    /// Saves the state of the watchdog.
+    pub fn save_state(&self) -> WatchdogState {
+        let remaining = if self.paused {
+            self.remaining
+        } else {
+            match self.timer.get_state() {
+                TimerState::Oneshot(remaining) => Some(remaining),
+                _ => None,
+            }
+        };
+        WatchdogState {
+            armed: remaining.is_some(),
+            remaining_ms: remaining.map_or(0, |remaining| remaining.as_millis() as u64),
+            second_stage: self.second_stage,
+            timer1_preload: self.timer1_preload,
+            timer2_preload: self.timer2_preload,
+            unlock_step: self.unlock_step,
+            timed_out: self.timed_out,
+            slot: self.slot,
+            bar_addr: self.bar_addr,
+            config: self.config.save_state(),
+        }
+    }
+
+    /// Returns the slot of the watchdog on the PCI root bus.
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    /// Returns the address of the memory BAR.
+    pub fn bar_addr(&self) -> u64 {
+        self.bar_addr
+    }
+
+    /// Stops the timer while the microVM is paused.
+    pub fn pause(&mut self) {
+        if self.paused {
+            return;
+        }
+        self.remaining = match self
+            .timer
+            .set_state(TimerState::Disarmed, SetTimeFlags::Default)
+        {
+            TimerState::Oneshot(remaining) => Some(remaining),
+            _ => None,
+        };
+        self.paused = true;
+    }
+
+    /// Restarts the timer when the microVM resumes.
+    pub fn resume(&mut self) {
+        if !self.paused {
+            return;
+        }
+        self.paused = false;
+        if let Some(remaining) = self.remaining.take() {
+            self.arm(remaining);
+        }
+    }
+
+    /// Consumes the expiry of the timer, and returns whether the watchdog expired: the end of
+    /// the first stage only starts the second one.
+    pub fn process_timer_event(&mut self) -> bool {
+        self.timer.read();
+        if !self.second_stage {
+            self.restart(true);
+            return false;
+        }
+
+        if self.config.read_reg(REG_CONFIG) & CONFIG_NO_REBOOT == 0 {
+            // The timer stays stopped until the guest pets the watchdog again.
+            self.timed_out = true;
+            self.second_stage = false;
+            return true;
+        }
+        if self.config.read_reg(REG_LOCK) & LOCK_FREE_RUN != 0 {
+            self.restart(false);
+        }
+        false
+    }
+
+    // Starts counting the ticks of the first or the second stage.
+    fn restart(&mut self, second_stage: bool) {
+        self.second_stage = second_stage;
+        let preload = if second_stage {
+            self.timer2_preload
+        } else {
+            self.timer1_preload
+        };
+        let shift = if self.config.read_reg(REG_CONFIG) & CONFIG_PRESCALER_1MHZ != 0 {
+            5
+        } else {
+            15
+        };
+        let timeout = Duration::from_nanos((u64::from(preload) << shift) * PCI_CLOCK_PERIOD_NS);
+        if self.paused {
+            self.remaining = Some(timeout);
+        } else {
+            self.arm(timeout);
+        }
+    }
+
+    fn arm(&mut self, timeout: Duration) {
+        // A zero duration disarms the timer, so the shortest timeout expires right away instead.
+        let timeout = std::cmp::max(timeout, Duration::from_nanos(1));
+        self.timer
+            .set_state(TimerState::Oneshot(timeout), SetTimeFlags::Default);
+    }
+
+    fn disarm(&mut self) {
+        self.second_stage = false;
+        self.remaining = None;
+        self.timer
+            .set_state(TimerState::Disarmed, SetTimeFlags::Default);
+    }
+
+    fn write_reload(&mut self, value: u32) {
+        match value {
+            UNLOCK1 => self.unlock_step = UNLOCK_FIRST,
+            UNLOCK2 if self.unlock_step == UNLOCK_FIRST => self.unlock_step = UNLOCK_DONE,
+            _ => {
+                if self.unlock_step == UNLOCK_DONE {
+                    if value & RELOAD_TIMEOUT != 0 {
+                        self.timed_out = false;
+                    }
+                    if value & RELOAD_RELOAD != 0 {
+                        METRICS.vmm.watchdog_pet_count.inc();
+                        if self.config.read_reg(REG_LOCK) & LOCK_ENABLE != 0 {
+                            self.restart(false);
+                        }
+                    }
+                }
+                self.unlock_step = UNLOCK_NONE;
+            }
+        }
+    }
+}
+
+impl AsRawFd for Watchdog {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+}
+
+impl BusDevice for Watchdog {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            *byte = 0;
+        }
+        // Only the reload register reads back, telling whether the watchdog expired.
+        if offset == OFS_RELOAD && data.len() >= 2 && self.timed_out {
+            data[..2].copy_from_slice(&(RELOAD_TIMEOUT as u16).to_le_bytes());
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        let value = match data.len() {
+            1 => u32::from(data[0]),
+            2 => u32::from(u16::from_le_bytes([data[0], data[1]])),
+            4 => u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            _ => return,
+        };
+        match offset {
+            OFS_RELOAD => self.write_reload(value),
+            OFS_TIMER1 if self.unlock_step == UNLOCK_DONE => {
+                self.timer1_preload = value & PRELOAD_MASK;
+                self.unlock_step = UNLOCK_NONE;
+            }
+            OFS_TIMER2 if self.unlock_step == UNLOCK_DONE => {
+                self.timer2_preload = value & PRELOAD_MASK;
+                self.unlock_step = UNLOCK_NONE;
+            }
+            _ => self.unlock_step = UNLOCK_NONE,
+        }
+    }
+}
+
+impl PciDevice for Watchdog {
+    fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+        self.config.read_reg(reg_idx)
+    }
+
+    fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]) {
+        // Once locked, the watchdog can't be stopped until the next reset.
+        if reg_idx == REG_LOCK && self.config.read_reg(REG_LOCK) & LOCK_LOCKED != 0 {
+            return;
+        }
+        self.config.write_reg(reg_idx, offset, data);
+        if reg_idx == REG_LOCK && offset == 0 {
+            if self.config.read_reg(REG_LOCK) & LOCK_ENABLE != 0 {
+                self.restart(false);
+            } else {
+                self.disarm();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BAR_ADDR: u64 = 0xf810_0000;
+
+    fn unlock(watchdog: &mut Watchdog) {
+        watchdog.write(OFS_RELOAD, &(UNLOCK1 as u16).to_le_bytes());
+        watchdog.write(OFS_RELOAD, &(UNLOCK2 as u16).to_le_bytes());
+    }
+
+    fn pet(watchdog: &mut Watchdog) {
+        unlock(watchdog);
+        watchdog.write(OFS_RELOAD, &(RELOAD_RELOAD as u16).to_le_bytes());
+    }
+
+    fn set_preloads(watchdog: &mut Watchdog, timer1: u32, timer2: u32) {
+        unlock(watchdog);
+        watchdog.write(OFS_TIMER1, &timer1.to_le_bytes());
+        unlock(watchdog);
+        watchdog.write(OFS_TIMER2, &timer2.to_le_bytes());
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(
+            format!("{}", Error::Pci(PciError::BarsFull)),
+            format!("Failed to set up the PCI function: {}", PciError::BarsFull)
+        );
+        let err = io::Error::from_raw_os_error(0);
+        assert_eq!(
+            format!("{}", Error::Timer(io::Error::from_raw_os_error(0))),
+            format!("Failed to create the timer: {}", err)
+        );
+    }
+
+    #[test]
+    fn test_config_space() {
+        let mut watchdog = Watchdog::new(1, BAR_ADDR).unwrap();
+        assert_eq!(watchdog.read_config_register(0), 0x25ab_8086);
+        assert_eq!(watchdog.read_config_register(2) >> 16, 0x0880);
+        assert_eq!(watchdog.read_config_register(4), BAR_ADDR as u32 | 0b100);
+        assert_eq!(watchdog.slot(), 1);
+        assert_eq!(watchdog.bar_addr(), BAR_ADDR);
+
+        // Only the defined bits of the configuration and lock registers are writable.
+        watchdog.write_config_register(REG_CONFIG, 0, &[0xff, 0xff]);
+        assert_eq!(
+            watchdog.read_config_register(REG_CONFIG),
+            CONFIG_WRITABLE_BITS
+        );
+        watchdog.write_config_register(REG_LOCK, 0, &[0xf8]);
+        assert_eq!(watchdog.read_config_register(REG_LOCK), 0);
+    }
+
+    #[test]
+    fn test_preloads() {
+        let mut watchdog = Watchdog::new(1, BAR_ADDR).unwrap();
+        assert_eq!(watchdog.timer1_preload, PRELOAD_MASK);
+
+        // The preload registers are read-only without the unlock sequence.
+        watchdog.write(OFS_TIMER1, &10u32.to_le_bytes());
+        assert_eq!(watchdog.timer1_preload, PRELOAD_MASK);
+        watchdog.write(OFS_RELOAD, &(UNLOCK1 as u16).to_le_bytes());
+        watchdog.write(OFS_TIMER1, &10u32.to_le_bytes());
+        assert_eq!(watchdog.timer1_preload, PRELOAD_MASK);
+
+        // The unlock sequence only allows a single write.
+        set_preloads(&mut watchdog, 0xfff_ffff, 20);
+        assert_eq!(watchdog.timer1_preload, PRELOAD_MASK);
+        assert_eq!(watchdog.timer2_preload, 20);
+        watchdog.write(OFS_TIMER2, &30u32.to_le_bytes());
+        assert_eq!(watchdog.timer2_preload, 20);
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let mut watchdog = Watchdog::new(1, BAR_ADDR).unwrap();
+        assert!(!watchdog.save_state().armed);
+
+        // The guest driver uses a 30 seconds heartbeat, split between the two stages.
+        set_preloads(&mut watchdog, 30 << 9, 30 << 9);
+        watchdog.write_config_register(REG_LOCK, 0, &[LOCK_ENABLE as u8]);
+        let state = watchdog.save_state();
+        assert!(state.armed);
+        assert!(!state.second_stage);
+        assert!(state.remaining_ms > 15_000 && state.remaining_ms <= 15_100);
+
+        let pet_count = METRICS.vmm.watchdog_pet_count.count();
+        pet(&mut watchdog);
+        assert_eq!(METRICS.vmm.watchdog_pet_count.count(), pet_count + 1);
+        // A reload without the unlock sequence is ignored.
+        watchdog.write(OFS_RELOAD, &(RELOAD_RELOAD as u16).to_le_bytes());
+        assert_eq!(METRICS.vmm.watchdog_pet_count.count(), pet_count + 1);
+
+        // The timer is stopped while the microVM is paused.
+        watchdog.pause();
+        assert_eq!(watchdog.timer.get_state(), TimerState::Disarmed);
+        assert!(watchdog.save_state().armed);
+        watchdog.resume();
+        assert!(matches!(watchdog.timer.get_state(), TimerState::Oneshot(_)));
+
+        watchdog.write_config_register(REG_LOCK, 0, &[0]);
+        assert!(!watchdog.save_state().armed);
+        watchdog.pause();
+        watchdog.resume();
+        assert_eq!(watchdog.timer.get_state(), TimerState::Disarmed);
+
+        // The watchdog expires at the end of the second stage.
+        set_preloads(&mut watchdog, 0, 0);
+        watchdog.write_config_register(REG_LOCK, 0, &[LOCK_ENABLE as u8]);
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(!watchdog.process_timer_event());
+        assert!(watchdog.save_state().second_stage);
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(watchdog.process_timer_event());
+        let state = watchdog.save_state();
+        assert!(!state.armed);
+        assert!(state.timed_out);
+
+        // The guest reads and acknowledges the expiry.
+        let mut data = [0u8; 2];
+        watchdog.read(OFS_RELOAD, &mut data);
+        assert_eq!(u16::from_le_bytes(data), RELOAD_TIMEOUT as u16);
+        unlock(&mut watchdog);
+        watchdog.write(OFS_RELOAD, &(RELOAD_TIMEOUT as u16).to_le_bytes());
+        watchdog.read(OFS_RELOAD, &mut data);
+        assert_eq!(u16::from_le_bytes(data), 0);
+    }
+
+    #[test]
+    fn test_no_reboot() {
+        let mut watchdog = Watchdog::new(1, BAR_ADDR).unwrap();
+        set_preloads(&mut watchdog, 0, 0);
+        watchdog.write_config_register(REG_CONFIG, 0, &[CONFIG_NO_REBOOT as u8]);
+        watchdog.write_config_register(REG_LOCK, 0, &[(LOCK_ENABLE | LOCK_FREE_RUN) as u8]);
+        assert!(!watchdog.process_timer_event());
+        // In free running mode, the timer restarts instead of expiring.
+        assert!(!watchdog.process_timer_event());
+        let state = watchdog.save_state();
+        assert!(!state.second_stage);
+        assert!(!state.timed_out);
+    }
+
+    #[test]
+    fn test_lock() {
+        let mut watchdog = Watchdog::new(1, BAR_ADDR).unwrap();
+        watchdog.write_config_register(REG_LOCK, 0, &[(LOCK_LOCKED | LOCK_ENABLE) as u8]);
+        assert!(watchdog.save_state().armed);
+
+        // The locked watchdog can't be stopped.
+        watchdog.write_config_register(REG_LOCK, 0, &[0]);
+        assert_eq!(
+            watchdog.read_config_register(REG_LOCK),
+            LOCK_LOCKED | LOCK_ENABLE
+        );
+        assert!(watchdog.save_state().armed);
+    }
+
+    #[test]
+    fn test_restore() {
+        let mut watchdog = Watchdog::new(3, BAR_ADDR).unwrap();
+        set_preloads(&mut watchdog, 20, 40);
+        watchdog.write_config_register(REG_LOCK, 0, &[LOCK_ENABLE as u8]);
+        watchdog.pause();
+        let state = watchdog.save_state();
+        assert!(state.armed);
+
+        let mut restored = Watchdog::restore(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.timer.get_state(), TimerState::Disarmed);
+        assert_eq!(restored.read_config_register(REG_LOCK), LOCK_ENABLE);
+
+        // A pet while paused only takes effect once resumed.
+        pet(&mut restored);
+        assert!(restored.save_state().armed);
+        restored.resume();
+        assert!(matches!(restored.timer.get_state(), TimerState::Oneshot(_)));
+
+        let mut buf = vec![0; 8192];
+        let version_map = VersionMap::new();
+        state
+            .serialize(&mut buf.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        assert_eq!(
+            WatchdogState::deserialize(&mut buf.as_slice(), &version_map, 1).unwrap(),
+            state
+        );
+    }
+}
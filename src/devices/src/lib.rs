@@ -10,25 +10,76 @@ use std::io;
 
 mod bus;
 pub mod legacy;
+pub mod pci;
 pub mod pseudo;
+#[cfg(feature = "tpm")]
+pub mod tpm;
+#[cfg(feature = "vfio")]
+pub mod vfio;
 pub mod virtio;
 
 pub use self::bus::{Bus, BusDevice, Error as BusError};
 use crate::virtio::QueueError;
 use logger::{error, IncMetric, METRICS};
+#[cfg(feature = "net")]
+use logger::{DeviceMetrics, NetDeviceMetrics};
 
 // Function used for reporting error in terms of logging
 // but also in terms of METRICS net event fails.
-pub(crate) fn report_net_event_fail(err: Error) {
+#[cfg(feature = "net")]
+pub(crate) fn report_net_event_fail(err: Error, metrics: &DeviceMetrics<NetDeviceMetrics>) {
     error!("{:?}", err);
-    METRICS.net.event_fails.inc();
+    metrics.update(|metrics| metrics.event_fails.inc());
 }
 
+#[cfg(feature = "balloon")]
 pub(crate) fn report_balloon_event_fail(err: virtio::balloon::Error) {
     error!("{:?}", err);
     METRICS.balloon.event_fails.inc();
 }
 
+#[cfg(feature = "virtio-console")]
+pub(crate) fn report_console_event_fail(err: virtio::console::Error) {
+    error!("{:?}", err);
+    METRICS.console.event_fails.inc();
+}
+
+#[cfg(feature = "virtio-mem")]
+pub(crate) fn report_mem_event_fail(err: virtio::mem::Error) {
+    error!("{:?}", err);
+    METRICS.mem.event_fails.inc();
+}
+
+#[cfg(feature = "virtio-fs")]
+pub(crate) fn report_shared_fs_event_fail(err: virtio::fs::Error) {
+    error!("{:?}", err);
+    METRICS.shared_fs.event_fails.inc();
+}
+
+#[cfg(feature = "virtio-pmem")]
+pub(crate) fn report_pmem_event_fail(err: virtio::pmem::Error) {
+    error!("{:?}", err);
+    METRICS.pmem.event_fails.inc();
+}
+
+#[cfg(feature = "virtio-rng")]
+pub(crate) fn report_entropy_event_fail(err: virtio::rng::Error) {
+    error!("{:?}", err);
+    METRICS.entropy.event_fails.inc();
+}
+
+#[cfg(feature = "null-devices")]
+pub(crate) fn report_null_device_event_fail(err: virtio::null::Error) {
+    error!("{:?}", err);
+    METRICS.null_device.event_fails.inc();
+}
+
+#[cfg(feature = "vsock")]
+pub(crate) fn report_vhost_vsock_event_fail(err: virtio::vsock::vhost::Error) {
+    error!("{:?}", err);
+    virtio::vsock::VSOCK_METRICS.update(|metrics| metrics.vhost_event_fails.inc());
+}
+
 #[derive(Debug)]
 pub enum Error {
     /// Failed to read from the TAP device.
@@ -43,4 +94,7 @@ pub enum Error {
     MalformedDescriptor,
     /// Error during queue processing.
     QueueError(QueueError),
+    /// Vsock device error.
+    #[cfg(feature = "vsock")]
+    VsockError(virtio::VsockError),
 }
@@ -0,0 +1,470 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::result;
+
+use logger::warn;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use super::PCI_CONFIG_REGISTERS;
+
+// Registers of the type 0 configuration header.
+const REG_ID: usize = 0;
+const REG_COMMAND_STATUS: usize = 1;
+const REG_CLASS_REVISION: usize = 2;
+const REG_BAR0: usize = 4;
+const REG_SUBSYSTEM: usize = 11;
+const REG_CAPABILITY_POINTER: usize = 13;
+const REG_INTERRUPT: usize = 15;
+
+const NUM_BAR_REGS: usize = 6;
+
+// Command bits the guest may change: I/O and memory space, bus master, parity error response,
+// SERR# enable and interrupt disable.
+const COMMAND_WRITABLE_BITS: u32 = 0x0547;
+// The status bit advertising the capability list.
+const STATUS_CAPABILITIES_LIST: u32 = 0x0010_0000;
+// The interrupt line is a scratch register for the guest. The interrupt pin stays 0: the
+// devices on this bus only interrupt through MSI-X.
+const INTERRUPT_LINE_WRITABLE_BITS: u32 = 0xff;
+
+// 64-bit, non-prefetchable memory BAR.
+const BAR_MEM_TYPE_64: u32 = 0b100;
+// The type and prefetchable bits of a memory BAR.
+const BAR_MEM_FLAGS_MASK: u32 = 0b1110;
+const BAR_MEM_TYPE_MASK: u32 = 0b0110;
+const BAR_MEM_ADDR_MASK: u32 = 0xffff_fff0;
+
+// Capabilities live in the rest of the legacy configuration space, after the header.
+const FIRST_CAPABILITY_OFFSET: usize = 0x40;
+const CAPABILITY_SPACE_END: usize = 0x100;
+
+/// Errors associated with the PCI devices and the bus.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// All the BAR registers are already used.
+    BarsFull,
+    /// All the slots of the bus are already used.
+    BusFull,
+    /// There is no room left for another capability.
+    CapabilitySpaceFull,
+    /// The BAR registers are already used or out of range, or the BAR doesn't fit its type.
+    InvalidBar(usize),
+    /// The BAR size is not a power of two, or is smaller than 16 bytes.
+    InvalidBarSize(u64),
+    /// The slot is already used or doesn't exist.
+    InvalidSlot(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+
+        match *self {
+            BarsFull => write!(f, "All the BAR registers are in use."),
+            BusFull => write!(f, "All the PCI slots are in use."),
+            CapabilitySpaceFull => write!(f, "No room left in the capability list."),
+            InvalidBar(bar) => write!(f, "Invalid or used BAR: {}.", bar),
+            InvalidBarSize(size) => write!(f, "Invalid BAR size: {:#x}.", size),
+            InvalidSlot(slot) => write!(f, "Invalid or used PCI slot: {}.", slot),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, Error>;
+
+/// The guest visible state of a PCI configuration space.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct PciConfigurationState {
+    /// The content of the configuration registers.
+    pub registers: Vec<u32>,
+}
+
+// What a BAR register holds.
+#[derive(Clone, Copy, PartialEq)]
+enum BarRegister {
+    Unused,
+    // The low dword of a BAR, or the whole address of a 32-bit BAR.
+    Low,
+    // The high dword of a 64-bit BAR.
+    High,
+}
+
+/// The configuration space of a single function PCI Express endpoint.
+///
+/// Only memory BARs are supported, and they are placed by the VMM: the guest can size them but
+/// not move them, which matches what firmware-less guests do when the BARs are already inside
+/// the host bridge window.
+pub struct PciConfiguration {
+    registers: [u32; PCI_CONFIG_REGISTERS],
+    writable_bits: [u32; PCI_CONFIG_REGISTERS],
+    // The address of each BAR as placed by the VMM, indexed by the low BAR register.
+    bar_addrs: [u64; NUM_BAR_REGS],
+    bar_regs: [BarRegister; NUM_BAR_REGS],
+    next_bar: usize,
+    next_capability: usize,
+}
+
+impl PciConfiguration {
+    /// Creates the configuration header of a type 0 function.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        vendor_id: u16,
+        device_id: u16,
+        class: u8,
+        subclass: u8,
+        prog_if: u8,
+        revision: u8,
+        subsystem_vendor_id: u16,
+        subsystem_id: u16,
+    ) -> Self {
+        let mut registers = [0u32; PCI_CONFIG_REGISTERS];
+        let mut writable_bits = [0u32; PCI_CONFIG_REGISTERS];
+
+        registers[REG_ID] = u32::from(device_id) << 16 | u32::from(vendor_id);
+        writable_bits[REG_COMMAND_STATUS] = COMMAND_WRITABLE_BITS;
+        registers[REG_CLASS_REVISION] = u32::from(class) << 24
+            | u32::from(subclass) << 16
+            | u32::from(prog_if) << 8
+            | u32::from(revision);
+        registers[REG_SUBSYSTEM] = u32::from(subsystem_id) << 16 | u32::from(subsystem_vendor_id);
+        writable_bits[REG_INTERRUPT] = INTERRUPT_LINE_WRITABLE_BITS;
+
+        PciConfiguration {
+            registers,
+            writable_bits,
+            bar_addrs: [0; NUM_BAR_REGS],
+            bar_regs: [BarRegister::Unused; NUM_BAR_REGS],
+            next_bar: 0,
+            next_capability: FIRST_CAPABILITY_OFFSET,
+        }
+    }
+
+    /// Adds a 64-bit memory BAR of `size` bytes at `addr`, returning the BAR index.
+    pub fn add_memory_bar64(&mut self, addr: u64, size: u64) -> Result<usize> {
+        if self.next_bar + 2 > NUM_BAR_REGS {
+            return Err(Error::BarsFull);
+        }
+        let bar = self.next_bar;
+        self.add_memory_bar_at(bar, addr, size, BAR_MEM_TYPE_64)?;
+        Ok(bar)
+    }
+
+    /// Adds a memory BAR of `size` bytes at `addr` as BAR `bar`, for the functions whose drivers
+    /// expect their BARs at given indexes. `flags` holds the type and prefetchable bits of the
+    /// BAR register; a 64-bit BAR also takes the following register.
+    pub fn add_memory_bar_at(
+        &mut self,
+        bar: usize,
+        addr: u64,
+        size: u64,
+        flags: u32,
+    ) -> Result<()> {
+        if size < 16 || !size.is_power_of_two() || addr & (size - 1) != 0 {
+            return Err(Error::InvalidBarSize(size));
+        }
+        let flags = flags & BAR_MEM_FLAGS_MASK;
+        let is_64bit = match flags & BAR_MEM_TYPE_MASK {
+            0 => false,
+            BAR_MEM_TYPE_64 => true,
+            _ => return Err(Error::InvalidBar(bar)),
+        };
+        let num_regs = if is_64bit { 2 } else { 1 };
+        if bar + num_regs > NUM_BAR_REGS
+            || self.bar_regs[bar..bar + num_regs]
+                .iter()
+                .any(|reg| *reg != BarRegister::Unused)
+            || (!is_64bit && addr + size > 1 << 32)
+        {
+            return Err(Error::InvalidBar(bar));
+        }
+
+        let reg = REG_BAR0 + bar;
+        self.registers[reg] = (addr as u32 & BAR_MEM_ADDR_MASK) | flags;
+        self.writable_bits[reg] = !(size - 1) as u32 & BAR_MEM_ADDR_MASK;
+        self.bar_regs[bar] = BarRegister::Low;
+        if is_64bit {
+            self.registers[reg + 1] = (addr >> 32) as u32;
+            self.writable_bits[reg + 1] = (!(size - 1) >> 32) as u32;
+            self.bar_regs[bar + 1] = BarRegister::High;
+        }
+        self.bar_addrs[bar] = addr;
+        self.next_bar = self.next_bar.max(bar + num_regs);
+
+        Ok(())
+    }
+
+    /// Adds a capability to the capability list and returns its offset in the configuration
+    /// space.
+    ///
+    /// The first byte of `data` is the capability ID; the second one is overwritten with the
+    /// pointer to the next capability. `first_dword_writable` tells which bits of the first
+    /// dword the guest may change; the rest of the capability is read-only.
+    pub fn add_capability(&mut self, data: &[u8], first_dword_writable: u32) -> Result<usize> {
+        let offset = self.next_capability;
+        let len = (data.len() + 3) & !3;
+        if data.len() < 2 || offset + len > CAPABILITY_SPACE_END {
+            return Err(Error::CapabilitySpaceFull);
+        }
+
+        // New capabilities go to the head of the list.
+        let mut bytes = data.to_vec();
+        bytes.resize(len, 0);
+        bytes[1] = self.registers[REG_CAPABILITY_POINTER] as u8;
+        for (i, chunk) in bytes.chunks(4).enumerate() {
+            let mut dword = [0u8; 4];
+            dword.copy_from_slice(chunk);
+            self.registers[offset / 4 + i] = u32::from_le_bytes(dword);
+        }
+        self.writable_bits[offset / 4] = first_dword_writable;
+        self.registers[REG_CAPABILITY_POINTER] = offset as u32;
+        self.registers[REG_COMMAND_STATUS] |= STATUS_CAPABILITIES_LIST;
+        self.next_capability = offset + len;
+
+        Ok(offset)
+    }
+
+    /// Lets the guest change the `writable` bits of the device specific register at `reg_idx`.
+    pub fn set_writable_bits(&mut self, reg_idx: usize, writable: u32) {
+        self.writable_bits[reg_idx] = writable;
+    }
+
+    /// Reads the 32-bit register at `reg_idx`.
+    pub fn read_reg(&self, reg_idx: usize) -> u32 {
+        self.registers.get(reg_idx).copied().unwrap_or(0xffff_ffff)
+    }
+
+    /// Writes `data` at `offset` bytes into the register at `reg_idx`, leaving the read-only
+    /// bits untouched.
+    pub fn write_reg(&mut self, reg_idx: usize, offset: u64, data: &[u8]) {
+        if reg_idx >= PCI_CONFIG_REGISTERS || offset as usize + data.len() > 4 {
+            warn!(
+                "invalid PCI configuration write: register {} offset {} len {}",
+                reg_idx,
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        let old = self.registers[reg_idx];
+        let mut bytes = old.to_le_bytes();
+        bytes[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+        let value = u32::from_le_bytes(bytes);
+        let writable = self.writable_bits[reg_idx];
+        self.registers[reg_idx] = (old & !writable) | (value & writable);
+
+        if reg_idx >= REG_BAR0
+            && reg_idx < REG_BAR0 + NUM_BAR_REGS
+            && self.bar_regs[reg_idx - REG_BAR0] != BarRegister::Unused
+        {
+            self.check_bar_write(reg_idx);
+        }
+    }
+
+    // Sizing a BAR writes all ones to it and then restores the original value; anything else
+    // would move the BAR, which is not supported since the VMM maps it at a fixed address.
+    fn check_bar_write(&self, reg_idx: usize) {
+        let mut bar = reg_idx - REG_BAR0;
+        let is_high = self.bar_regs[bar] == BarRegister::High;
+        if is_high {
+            bar -= 1;
+        }
+        let addr = self.bar_addrs[bar];
+        let (expected, value) = if !is_high {
+            (
+                addr as u32 & BAR_MEM_ADDR_MASK,
+                self.registers[reg_idx] & BAR_MEM_ADDR_MASK,
+            )
+        } else {
+            ((addr >> 32) as u32, self.registers[reg_idx])
+        };
+        if value != expected && value != self.writable_bits[reg_idx] & !BAR_MEM_TYPE_64 {
+            warn!(
+                "relocating PCI BAR {} from {:#x} is not supported",
+                bar, addr
+            );
+        }
+    }
+
+    /// Returns whether the guest enabled memory space decoding.
+    pub fn memory_enabled(&self) -> bool {
+        self.registers[REG_COMMAND_STATUS] & 0x2 != 0
+    }
+
+    /// Returns the guest visible state of the configuration space.
+    pub fn save_state(&self) -> PciConfigurationState {
+        PciConfigurationState {
+            registers: self.registers.to_vec(),
+        }
+    }
+
+    /// Restores the guest visible state of a configuration space built like the saved one.
+    pub fn restore_state(&mut self, state: &PciConfigurationState) {
+        for (reg, value) in self.registers.iter_mut().zip(state.registers.iter()) {
+            *reg = *value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_config() -> PciConfiguration {
+        PciConfiguration::new(0x1af4, 0x1041, 0x02, 0x00, 0x00, 0x01, 0x1af4, 0x1100)
+    }
+
+    #[test]
+    fn test_header() {
+        let mut config = default_config();
+        assert_eq!(config.read_reg(REG_ID), 0x1041_1af4);
+        assert_eq!(config.read_reg(REG_CLASS_REVISION), 0x0200_0001);
+        assert_eq!(config.read_reg(REG_SUBSYSTEM), 0x1100_1af4);
+        assert_eq!(config.read_reg(PCI_CONFIG_REGISTERS), 0xffff_ffff);
+
+        // The IDs are read-only.
+        config.write_reg(REG_ID, 0, &[0xff; 4]);
+        assert_eq!(config.read_reg(REG_ID), 0x1041_1af4);
+
+        // Only some of the command bits can be set.
+        config.write_reg(REG_COMMAND_STATUS, 0, &[0xff, 0xff]);
+        assert_eq!(config.read_reg(REG_COMMAND_STATUS), COMMAND_WRITABLE_BITS);
+        assert!(config.memory_enabled());
+
+        // The interrupt line can be written, the pin can't.
+        config.write_reg(REG_INTERRUPT, 0, &[0x0b, 0x01]);
+        assert_eq!(config.read_reg(REG_INTERRUPT), 0x0b);
+
+        // Out of range writes are ignored.
+        config.write_reg(REG_INTERRUPT, 3, &[0xff, 0xff]);
+        assert_eq!(config.read_reg(REG_INTERRUPT), 0x0b);
+    }
+
+    #[test]
+    fn test_bar_sizing() {
+        let mut config = default_config();
+        assert_eq!(
+            config.add_memory_bar64(0x1000, 0x1001),
+            Err(Error::InvalidBarSize(0x1001))
+        );
+        assert_eq!(
+            config.add_memory_bar64(0x1000, 0x8000),
+            Err(Error::InvalidBarSize(0x8000))
+        );
+        assert_eq!(config.add_memory_bar64(0xf810_8000, 0x8000), Ok(0));
+        assert_eq!(config.read_reg(REG_BAR0), 0xf810_8004);
+        assert_eq!(config.read_reg(REG_BAR0 + 1), 0);
+
+        config.write_reg(REG_BAR0, 0, &[0xff; 4]);
+        config.write_reg(REG_BAR0 + 1, 0, &[0xff; 4]);
+        assert_eq!(config.read_reg(REG_BAR0), 0xffff_8004);
+        assert_eq!(config.read_reg(REG_BAR0 + 1), 0xffff_ffff);
+
+        config.write_reg(REG_BAR0, 0, &0xf810_8000u32.to_le_bytes());
+        config.write_reg(REG_BAR0 + 1, 0, &[0; 4]);
+        assert_eq!(config.read_reg(REG_BAR0), 0xf810_8004);
+        assert_eq!(config.read_reg(REG_BAR0 + 1), 0);
+
+        assert_eq!(config.add_memory_bar64(0xf820_0000, 0x1000), Ok(2));
+        assert_eq!(config.add_memory_bar64(0xf830_0000, 0x1000), Ok(4));
+        assert_eq!(
+            config.add_memory_bar64(0xf840_0000, 0x1000),
+            Err(Error::BarsFull)
+        );
+    }
+
+    #[test]
+    fn test_bar_at() {
+        let mut config = default_config();
+        // A 64-bit prefetchable BAR 0 and a 32-bit BAR 3, leaving BAR 2 unused.
+        assert_eq!(
+            config.add_memory_bar_at(0, 0xf840_0000, 0x4000, 0b1100),
+            Ok(())
+        );
+        assert_eq!(
+            config.add_memory_bar_at(1, 0xf850_0000, 0x1000, 0),
+            Err(Error::InvalidBar(1))
+        );
+        assert_eq!(
+            config.add_memory_bar_at(5, 0xf850_0000, 0x1000, BAR_MEM_TYPE_64),
+            Err(Error::InvalidBar(5))
+        );
+        assert_eq!(
+            config.add_memory_bar_at(3, 0xffff_f000, 0x2000, 0),
+            Err(Error::InvalidBarSize(0x2000))
+        );
+        assert_eq!(
+            config.add_memory_bar_at(3, 0x1_0000_0000, 0x1000, 0),
+            Err(Error::InvalidBar(3))
+        );
+        // The reserved memory type can't be used.
+        assert_eq!(
+            config.add_memory_bar_at(3, 0xf850_0000, 0x1000, 0b010),
+            Err(Error::InvalidBar(3))
+        );
+        assert_eq!(config.add_memory_bar_at(3, 0xf850_0000, 0x1000, 0), Ok(()));
+        assert_eq!(config.read_reg(REG_BAR0), 0xf840_000c);
+        assert_eq!(config.read_reg(REG_BAR0 + 1), 0);
+        assert_eq!(config.read_reg(REG_BAR0 + 2), 0);
+        assert_eq!(config.read_reg(REG_BAR0 + 3), 0xf850_0000);
+
+        config.write_reg(REG_BAR0 + 2, 0, &[0xff; 4]);
+        assert_eq!(config.read_reg(REG_BAR0 + 2), 0);
+        config.write_reg(REG_BAR0 + 3, 0, &[0xff; 4]);
+        assert_eq!(config.read_reg(REG_BAR0 + 3), 0xffff_f000);
+        config.write_reg(REG_BAR0 + 3, 0, &0xf850_0000u32.to_le_bytes());
+        assert_eq!(config.read_reg(REG_BAR0 + 3), 0xf850_0000);
+
+        // The next sequential BAR goes after the highest used register.
+        assert_eq!(config.add_memory_bar64(0xf860_0000, 0x1000), Ok(4));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut config = default_config();
+        assert_eq!(config.read_reg(REG_CAPABILITY_POINTER), 0);
+
+        assert_eq!(config.add_capability(&[0x09, 0, 5, 1, 0], 0), Ok(0x40));
+        assert_eq!(
+            config.add_capability(&[0x11, 0, 0, 0], 0xc000_0000),
+            Ok(0x48)
+        );
+        assert_eq!(config.read_reg(REG_CAPABILITY_POINTER), 0x48);
+        assert_ne!(
+            config.read_reg(REG_COMMAND_STATUS) & STATUS_CAPABILITIES_LIST,
+            0
+        );
+        // The list goes from the newest capability to the oldest one.
+        assert_eq!(config.read_reg(0x48 / 4), 0x0000_4011);
+        assert_eq!(config.read_reg(0x40 / 4), 0x0105_0009);
+        assert_eq!(config.read_reg(0x44 / 4), 0);
+
+        config.write_reg(0x48 / 4, 2, &[0xff, 0xff]);
+        assert_eq!(config.read_reg(0x48 / 4), 0xc000_4011);
+        config.write_reg(0x40 / 4, 0, &[0xff; 4]);
+        assert_eq!(config.read_reg(0x40 / 4), 0x0105_0009);
+
+        assert_eq!(
+            config.add_capability(&[0; 0xb8], 0),
+            Err(Error::CapabilitySpaceFull)
+        );
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let mut config = default_config();
+        config.add_memory_bar64(0xf810_8000, 0x8000).unwrap();
+        config.write_reg(REG_COMMAND_STATUS, 0, &[0x06, 0x00]);
+        config.write_reg(REG_INTERRUPT, 0, &[0x0b]);
+
+        let mut restored = default_config();
+        restored.add_memory_bar64(0xf810_8000, 0x8000).unwrap();
+        restored.restore_state(&config.save_state());
+        assert_eq!(restored.save_state(), config.save_state());
+        assert!(restored.memory_enabled());
+    }
+}
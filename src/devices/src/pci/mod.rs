@@ -0,0 +1,29 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Emulates a minimal PCI Express root complex: a single bus behind a host bridge, reachable
+//! through the ECAM window and the legacy configuration ports, with MSI-X capable endpoints.
+
+mod configuration;
+mod msix;
+mod root;
+
+pub use self::configuration::{Error, PciConfiguration, PciConfigurationState};
+pub use self::msix::{
+    MsiSender, MsixConfig, MsixConfigState, MsixTableEntry, MSIX_CAP_ID, MSIX_TABLE_ENTRY_SIZE,
+};
+pub use self::root::{PciConfigIo, PciConfigMmio, PciRoot, PCI_CONFIG_IO_PORT, PCI_CONFIG_IO_SIZE};
+
+/// Number of device slots on the root bus, the host bridge included.
+pub const PCI_MAX_DEVICES: usize = 32;
+
+/// Size of the configuration space of a single PCI Express function, in 32-bit registers.
+pub const PCI_CONFIG_REGISTERS: usize = 1024;
+
+/// A device which exposes a PCI configuration space.
+pub trait PciDevice: Send {
+    /// Reads the 32-bit configuration register at `reg_idx`.
+    fn read_config_register(&mut self, reg_idx: usize) -> u32;
+    /// Writes `data` at `offset` bytes into the configuration register at `reg_idx`.
+    fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]);
+}
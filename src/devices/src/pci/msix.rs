@@ -0,0 +1,354 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io;
+use std::sync::Arc;
+
+use logger::{error, warn};
+use utils::byte_order;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+/// The ID of the MSI-X capability.
+pub const MSIX_CAP_ID: u8 = 0x11;
+/// The size of an entry of the MSI-X table, in bytes.
+pub const MSIX_TABLE_ENTRY_SIZE: u64 = 16;
+
+// Bits of the message control register.
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+// The per vector mask bit, in the vector control dword of a table entry.
+const VECTOR_MASKED: u32 = 1;
+
+/// Delivers message signaled interrupts to the guest.
+pub trait MsiSender: Send + Sync {
+    /// Writes `data` at `address` on behalf of a device.
+    fn send_msi(&self, address: u64, data: u32) -> io::Result<()>;
+}
+
+/// An entry of the MSI-X table, as programmed by the guest.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct MsixTableEntry {
+    pub msg_addr_lo: u32,
+    pub msg_addr_hi: u32,
+    pub msg_data: u32,
+    pub vector_ctl: u32,
+}
+
+impl Default for MsixTableEntry {
+    // Vectors come out of reset masked.
+    fn default() -> Self {
+        MsixTableEntry {
+            msg_addr_lo: 0,
+            msg_addr_hi: 0,
+            msg_data: 0,
+            vector_ctl: VECTOR_MASKED,
+        }
+    }
+}
+
+impl MsixTableEntry {
+    fn masked(&self) -> bool {
+        self.vector_ctl & VECTOR_MASKED != 0
+    }
+
+    fn address(&self) -> u64 {
+        u64::from(self.msg_addr_hi) << 32 | u64::from(self.msg_addr_lo)
+    }
+
+    fn dword(&self, idx: u64) -> u32 {
+        match idx {
+            0 => self.msg_addr_lo,
+            1 => self.msg_addr_hi,
+            2 => self.msg_data,
+            _ => self.vector_ctl,
+        }
+    }
+
+    fn set_dword(&mut self, idx: u64, value: u32) {
+        match idx {
+            0 => self.msg_addr_lo = value,
+            1 => self.msg_addr_hi = value,
+            2 => self.msg_data = value,
+            _ => self.vector_ctl = value & VECTOR_MASKED,
+        }
+    }
+}
+
+/// The guest visible state of an MSI-X capable function.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct MsixConfigState {
+    pub table: Vec<MsixTableEntry>,
+    pub pba: Vec<u64>,
+    pub enabled: bool,
+    pub masked: bool,
+}
+
+/// Emulates the MSI-X table and pending bit array of a function.
+pub struct MsixConfig {
+    table: Vec<MsixTableEntry>,
+    pba: Vec<u64>,
+    enabled: bool,
+    masked: bool,
+    sender: Arc<dyn MsiSender>,
+}
+
+impl MsixConfig {
+    /// Creates the MSI-X state of a function with `num_vectors` vectors, all of them masked.
+    pub fn new(num_vectors: u16, sender: Arc<dyn MsiSender>) -> Self {
+        MsixConfig {
+            table: vec![MsixTableEntry::default(); num_vectors as usize],
+            pba: vec![0; (num_vectors as usize + 63) / 64],
+            enabled: false,
+            masked: false,
+            sender,
+        }
+    }
+
+    /// Builds the MSI-X capability for a function with `num_vectors` vectors, with the table
+    /// and the pending bit array at the given offsets inside the BAR `bir`.
+    pub fn capability(num_vectors: u16, bir: u8, table_offset: u32, pba_offset: u32) -> Vec<u8> {
+        let mut cap = vec![MSIX_CAP_ID, 0];
+        cap.extend_from_slice(&(num_vectors - 1).to_le_bytes());
+        cap.extend_from_slice(&(table_offset | u32::from(bir)).to_le_bytes());
+        cap.extend_from_slice(&(pba_offset | u32::from(bir)).to_le_bytes());
+        cap
+    }
+
+    /// The number of vectors of the function.
+    pub fn num_vectors(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Handles a guest write to the message control register of the capability.
+    pub fn set_message_control(&mut self, msg_ctl: u16) {
+        let was_active = self.enabled && !self.masked;
+        self.enabled = msg_ctl & MSIX_ENABLE != 0;
+        self.masked = msg_ctl & MSIX_FUNCTION_MASK != 0;
+
+        if !was_active && self.enabled && !self.masked {
+            for vector in 0..self.table.len() {
+                if self.is_pending(vector) && !self.table[vector].masked() {
+                    self.deliver(vector);
+                }
+            }
+        }
+    }
+
+    /// Handles a guest read from the MSI-X table.
+    pub fn read_table(&self, offset: u64, data: &mut [u8]) {
+        let entry = match self.table.get((offset / MSIX_TABLE_ENTRY_SIZE) as usize) {
+            Some(entry) => entry,
+            None => {
+                warn!("invalid MSI-X table read at {:#x}", offset);
+                return;
+            }
+        };
+        let dword = (offset % MSIX_TABLE_ENTRY_SIZE) / 4;
+        match data.len() {
+            4 => byte_order::write_le_u32(data, entry.dword(dword)),
+            8 => byte_order::write_le_u64(
+                data,
+                u64::from(entry.dword(dword + 1)) << 32 | u64::from(entry.dword(dword)),
+            ),
+            _ => warn!("invalid MSI-X table read of {} bytes", data.len()),
+        }
+    }
+
+    /// Handles a guest write to the MSI-X table.
+    pub fn write_table(&mut self, offset: u64, data: &[u8]) {
+        let vector = (offset / MSIX_TABLE_ENTRY_SIZE) as usize;
+        let entry = match self.table.get_mut(vector) {
+            Some(entry) => entry,
+            None => {
+                warn!("invalid MSI-X table write at {:#x}", offset);
+                return;
+            }
+        };
+        let was_masked = entry.masked();
+        let dword = (offset % MSIX_TABLE_ENTRY_SIZE) / 4;
+        match data.len() {
+            4 => entry.set_dword(dword, byte_order::read_le_u32(data)),
+            8 => {
+                let value = byte_order::read_le_u64(data);
+                entry.set_dword(dword, value as u32);
+                entry.set_dword(dword + 1, (value >> 32) as u32);
+            }
+            _ => {
+                warn!("invalid MSI-X table write of {} bytes", data.len());
+                return;
+            }
+        }
+
+        // Unmasking a vector delivers the message it held back.
+        if was_masked && !self.table[vector].masked() && self.is_pending(vector) {
+            self.trigger(vector as u16);
+        }
+    }
+
+    /// Handles a guest read from the pending bit array.
+    pub fn read_pba(&self, offset: u64, data: &mut [u8]) {
+        let qword = self.pba.get((offset / 8) as usize).copied().unwrap_or(0);
+        match data.len() {
+            4 => byte_order::write_le_u32(data, (qword >> ((offset % 8) * 8)) as u32),
+            8 => byte_order::write_le_u64(data, qword),
+            _ => warn!("invalid MSI-X PBA read of {} bytes", data.len()),
+        }
+    }
+
+    /// Signals `vector`: the message is sent if the vector is unmasked, or left pending
+    /// otherwise. Nothing happens while MSI-X is disabled.
+    pub fn trigger(&mut self, vector: u16) {
+        let vector = vector as usize;
+        if !self.enabled || vector >= self.table.len() {
+            return;
+        }
+        if self.masked || self.table[vector].masked() {
+            self.pba[vector / 64] |= 1 << (vector % 64);
+        } else {
+            self.deliver(vector);
+        }
+    }
+
+    fn is_pending(&self, vector: usize) -> bool {
+        self.pba[vector / 64] & (1 << (vector % 64)) != 0
+    }
+
+    fn deliver(&mut self, vector: usize) {
+        self.pba[vector / 64] &= !(1 << (vector % 64));
+        let entry = self.table[vector];
+        if let Err(e) = self.sender.send_msi(entry.address(), entry.msg_data) {
+            error!("Failed to send MSI-X vector {}: {:?}", vector, e);
+        }
+    }
+
+    /// Returns the guest visible MSI-X state.
+    pub fn save_state(&self) -> MsixConfigState {
+        MsixConfigState {
+            table: self.table.clone(),
+            pba: self.pba.clone(),
+            enabled: self.enabled,
+            masked: self.masked,
+        }
+    }
+
+    /// Restores the guest visible MSI-X state, keeping the current sender.
+    pub fn restore_state(&mut self, state: &MsixConfigState) {
+        self.table = state.table.clone();
+        self.pba = state.pba.clone();
+        self.enabled = state.enabled;
+        self.masked = state.masked;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    pub(crate) struct DummySender {
+        pub(crate) sent: Mutex<Vec<(u64, u32)>>,
+    }
+
+    impl MsiSender for DummySender {
+        fn send_msi(&self, address: u64, data: u32) -> io::Result<()> {
+            self.sent.lock().unwrap().push((address, data));
+            Ok(())
+        }
+    }
+
+    fn program_vector(msix: &mut MsixConfig, vector: u64, data: u32) {
+        let base = vector * MSIX_TABLE_ENTRY_SIZE;
+        msix.write_table(base, &0xfee0_0000u32.to_le_bytes());
+        msix.write_table(base + 4, &0u32.to_le_bytes());
+        msix.write_table(base + 8, &data.to_le_bytes());
+        msix.write_table(base + 12, &0u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_capability() {
+        let cap = MsixConfig::capability(3, 0, 0x4000, 0x5000);
+        assert_eq!(
+            cap,
+            vec![MSIX_CAP_ID, 0, 2, 0, 0x00, 0x40, 0, 0, 0x00, 0x50, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_table_access() {
+        let sender = Arc::new(DummySender::default());
+        let mut msix = MsixConfig::new(2, sender);
+        assert_eq!(msix.num_vectors(), 2);
+
+        let mut data = [0u8; 4];
+        msix.read_table(12, &mut data);
+        assert_eq!(u32::from_le_bytes(data), VECTOR_MASKED);
+
+        msix.write_table(16, &0x0000_0001_fee0_1000u64.to_le_bytes());
+        let mut data = [0u8; 8];
+        msix.read_table(16, &mut data);
+        assert_eq!(u64::from_le_bytes(data), 0x0000_0001_fee0_1000);
+
+        // Accesses outside the table are ignored.
+        msix.write_table(32, &[0xff; 4]);
+        let mut data = [0xffu8; 4];
+        msix.read_table(32, &mut data);
+        assert_eq!(data, [0xff; 4]);
+    }
+
+    #[test]
+    fn test_trigger() {
+        let sender = Arc::new(DummySender::default());
+        let mut msix = MsixConfig::new(2, sender.clone());
+
+        // Disabled MSI-X drops the interrupts.
+        program_vector(&mut msix, 0, 0x41);
+        msix.trigger(0);
+        assert!(sender.sent.lock().unwrap().is_empty());
+
+        msix.set_message_control(MSIX_ENABLE);
+        msix.trigger(0);
+        assert_eq!(*sender.sent.lock().unwrap(), vec![(0xfee0_0000, 0x41)]);
+
+        // Masked vectors are left pending until they are unmasked.
+        msix.trigger(1);
+        let mut data = [0u8; 8];
+        msix.read_pba(0, &mut data);
+        assert_eq!(u64::from_le_bytes(data), 0b10);
+        program_vector(&mut msix, 1, 0x42);
+        assert_eq!(
+            sender.sent.lock().unwrap().last(),
+            Some(&(0xfee0_0000, 0x42))
+        );
+        msix.read_pba(0, &mut data);
+        assert_eq!(u64::from_le_bytes(data), 0);
+
+        // Same for the function mask.
+        msix.set_message_control(MSIX_ENABLE | MSIX_FUNCTION_MASK);
+        msix.trigger(0);
+        assert_eq!(sender.sent.lock().unwrap().len(), 2);
+        msix.set_message_control(MSIX_ENABLE);
+        assert_eq!(sender.sent.lock().unwrap().len(), 3);
+
+        // Out of range vectors are ignored.
+        msix.trigger(2);
+        assert_eq!(sender.sent.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let sender = Arc::new(DummySender::default());
+        let mut msix = MsixConfig::new(2, sender.clone());
+        msix.set_message_control(MSIX_ENABLE);
+        program_vector(&mut msix, 0, 0x41);
+        msix.trigger(1);
+
+        let mut restored = MsixConfig::new(2, sender);
+        restored.restore_state(&msix.save_state());
+        assert_eq!(restored.save_state(), msix.save_state());
+    }
+}
@@ -0,0 +1,366 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::BTreeMap;
+use std::result;
+use std::sync::{Arc, Mutex};
+
+use logger::warn;
+use utils::byte_order;
+
+use super::configuration::{Error, PciConfiguration};
+use super::{PciDevice, PCI_MAX_DEVICES};
+use crate::bus::BusDevice;
+
+/// The first of the legacy configuration mechanism ports, holding the address register.
+pub const PCI_CONFIG_IO_PORT: u64 = 0xcf8;
+/// The number of legacy configuration mechanism ports: the address and the data registers.
+pub const PCI_CONFIG_IO_SIZE: u64 = 0x8;
+
+// The host bridge identifies as an Intel device, as Linux expects.
+const HOST_BRIDGE_VENDOR_ID: u16 = 0x8086;
+const HOST_BRIDGE_DEVICE_ID: u16 = 0x0d57;
+const CLASS_BRIDGE: u8 = 0x06;
+const SUBCLASS_HOST_BRIDGE: u8 = 0x00;
+
+// The value read from the configuration space of an absent function.
+const NO_DEVICE: u32 = 0xffff_ffff;
+
+type Result<T> = result::Result<T, Error>;
+
+/// The root bus of the PCI hierarchy, with the host bridge in slot 0.
+///
+/// Only function 0 of each slot on bus 0 is populated.
+pub struct PciRoot {
+    host_bridge: PciConfiguration,
+    devices: BTreeMap<u8, Arc<Mutex<dyn PciDevice>>>,
+}
+
+impl Default for PciRoot {
+    fn default() -> Self {
+        PciRoot::new()
+    }
+}
+
+impl PciRoot {
+    /// Creates a root bus with just the host bridge on it.
+    pub fn new() -> Self {
+        PciRoot {
+            host_bridge: PciConfiguration::new(
+                HOST_BRIDGE_VENDOR_ID,
+                HOST_BRIDGE_DEVICE_ID,
+                CLASS_BRIDGE,
+                SUBCLASS_HOST_BRIDGE,
+                0,
+                0,
+                0,
+                0,
+            ),
+            devices: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the first free slot of the bus.
+    pub fn next_free_slot(&self) -> Result<u8> {
+        (1..PCI_MAX_DEVICES as u8)
+            .find(|slot| !self.devices.contains_key(slot))
+            .ok_or(Error::BusFull)
+    }
+
+    /// Plugs `device` in the first free slot and returns the slot number.
+    pub fn add_device(&mut self, device: Arc<Mutex<dyn PciDevice>>) -> Result<u8> {
+        let slot = self.next_free_slot()?;
+        self.devices.insert(slot, device);
+        Ok(slot)
+    }
+
+    /// Plugs `device` in `slot`, which has to be free.
+    pub fn add_device_at(&mut self, slot: u8, device: Arc<Mutex<dyn PciDevice>>) -> Result<()> {
+        if slot == 0 || slot as usize >= PCI_MAX_DEVICES || self.devices.contains_key(&slot) {
+            return Err(Error::InvalidSlot(slot));
+        }
+        self.devices.insert(slot, device);
+        Ok(())
+    }
+
+    fn read_config(&self, bus: u8, slot: u8, function: u8, reg_idx: usize) -> u32 {
+        if bus != 0 || function != 0 {
+            return NO_DEVICE;
+        }
+        if slot == 0 {
+            return self.host_bridge.read_reg(reg_idx);
+        }
+        self.devices.get(&slot).map_or(NO_DEVICE, |device| {
+            device
+                .lock()
+                .expect("Poisoned lock")
+                .read_config_register(reg_idx)
+        })
+    }
+
+    fn write_config(
+        &mut self,
+        bus: u8,
+        slot: u8,
+        function: u8,
+        reg_idx: usize,
+        offset: u64,
+        data: &[u8],
+    ) {
+        if bus != 0 || function != 0 {
+            return;
+        }
+        if slot == 0 {
+            self.host_bridge.write_reg(reg_idx, offset, data);
+        } else if let Some(device) = self.devices.get(&slot) {
+            device
+                .lock()
+                .expect("Poisoned lock")
+                .write_config_register(reg_idx, offset, data);
+        }
+    }
+}
+
+// Copies the bytes at `offset` in the register `value` to `data`.
+fn read_register_bytes(value: u32, offset: u64, data: &mut [u8]) {
+    let offset = offset as usize;
+    if offset + data.len() > 4 {
+        warn!(
+            "invalid PCI configuration read at {} of {} bytes",
+            offset,
+            data.len()
+        );
+        return;
+    }
+    data.copy_from_slice(&value.to_le_bytes()[offset..offset + data.len()]);
+}
+
+/// The Enhanced Configuration Access Mechanism (ECAM) window of the root bus.
+pub struct PciConfigMmio {
+    root: Arc<Mutex<PciRoot>>,
+}
+
+impl PciConfigMmio {
+    /// Creates the ECAM window of the `root` bus.
+    pub fn new(root: Arc<Mutex<PciRoot>>) -> Self {
+        PciConfigMmio { root }
+    }
+
+    // Decodes an offset of the ECAM window into a bus, slot, function and register.
+    fn decode(offset: u64) -> (u8, u8, u8, usize) {
+        (
+            ((offset >> 20) & 0xff) as u8,
+            ((offset >> 15) & 0x1f) as u8,
+            ((offset >> 12) & 0x7) as u8,
+            ((offset & 0xfff) >> 2) as usize,
+        )
+    }
+}
+
+impl BusDevice for PciConfigMmio {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        let (bus, slot, function, reg_idx) = Self::decode(offset);
+        let value = self
+            .root
+            .lock()
+            .expect("Poisoned lock")
+            .read_config(bus, slot, function, reg_idx);
+        read_register_bytes(value, offset & 0x3, data);
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        let (bus, slot, function, reg_idx) = Self::decode(offset);
+        self.root.lock().expect("Poisoned lock").write_config(
+            bus,
+            slot,
+            function,
+            reg_idx,
+            offset & 0x3,
+            data,
+        );
+    }
+}
+
+/// The legacy configuration mechanism of the root bus, through the 0xcf8 and 0xcfc ports.
+///
+/// It only reaches the first 256 bytes of each configuration space. It is there for guests
+/// which don't read the ACPI tables, and thus don't know about the ECAM window.
+pub struct PciConfigIo {
+    root: Arc<Mutex<PciRoot>>,
+    config_address: u32,
+}
+
+impl PciConfigIo {
+    /// Creates the legacy configuration ports of the `root` bus.
+    pub fn new(root: Arc<Mutex<PciRoot>>) -> Self {
+        PciConfigIo {
+            root,
+            config_address: 0,
+        }
+    }
+
+    // Decodes the address register into a bus, slot, function and register, if enabled.
+    fn decode(&self) -> Option<(u8, u8, u8, usize)> {
+        if self.config_address & 0x8000_0000 == 0 {
+            return None;
+        }
+        Some((
+            ((self.config_address >> 16) & 0xff) as u8,
+            ((self.config_address >> 11) & 0x1f) as u8,
+            ((self.config_address >> 8) & 0x7) as u8,
+            ((self.config_address & 0xfc) >> 2) as usize,
+        ))
+    }
+}
+
+impl BusDevice for PciConfigIo {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        let value = match offset {
+            0..=3 => self.config_address,
+            4..=7 => match self.decode() {
+                Some((bus, slot, function, reg_idx)) => self
+                    .root
+                    .lock()
+                    .expect("Poisoned lock")
+                    .read_config(bus, slot, function, reg_idx),
+                None => NO_DEVICE,
+            },
+            _ => NO_DEVICE,
+        };
+        read_register_bytes(value, offset & 0x3, data);
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        match offset {
+            0 if data.len() == 4 => self.config_address = byte_order::read_le_u32(data),
+            4..=7 => {
+                if let Some((bus, slot, function, reg_idx)) = self.decode() {
+                    self.root.lock().expect("Poisoned lock").write_config(
+                        bus,
+                        slot,
+                        function,
+                        reg_idx,
+                        offset - 4,
+                        data,
+                    );
+                }
+            }
+            _ => warn!(
+                "invalid PCI configuration port write at {} of {} bytes",
+                offset,
+                data.len()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct DummyDevice {
+        config: PciConfiguration,
+    }
+
+    impl PciDevice for DummyDevice {
+        fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+            self.config.read_reg(reg_idx)
+        }
+
+        fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]) {
+            self.config.write_reg(reg_idx, offset, data)
+        }
+    }
+
+    fn dummy_device() -> Arc<Mutex<DummyDevice>> {
+        Arc::new(Mutex::new(DummyDevice {
+            config: PciConfiguration::new(0x1af4, 0x1042, 0x01, 0x80, 0, 1, 0x1af4, 0x1100),
+        }))
+    }
+
+    fn ecam_offset(slot: u64, reg: u64) -> u64 {
+        slot << 15 | reg
+    }
+
+    #[test]
+    fn test_slots() {
+        let mut root = PciRoot::new();
+        assert_eq!(root.add_device(dummy_device()), Ok(1));
+        assert_eq!(root.add_device_at(3, dummy_device()), Ok(()));
+        assert_eq!(root.add_device(dummy_device()), Ok(2));
+        assert_eq!(root.next_free_slot(), Ok(4));
+        assert_eq!(
+            root.add_device_at(3, dummy_device()),
+            Err(Error::InvalidSlot(3))
+        );
+        assert_eq!(
+            root.add_device_at(0, dummy_device()),
+            Err(Error::InvalidSlot(0))
+        );
+        assert_eq!(
+            root.add_device_at(32, dummy_device()),
+            Err(Error::InvalidSlot(32))
+        );
+        for _ in 4..32 {
+            root.add_device(dummy_device()).unwrap();
+        }
+        assert_eq!(root.add_device(dummy_device()), Err(Error::BusFull));
+    }
+
+    #[test]
+    fn test_ecam() {
+        let root = Arc::new(Mutex::new(PciRoot::new()));
+        root.lock().unwrap().add_device(dummy_device()).unwrap();
+        let mut ecam = PciConfigMmio::new(root);
+
+        let mut data = [0u8; 4];
+        ecam.read(ecam_offset(0, 0), &mut data);
+        assert_eq!(u32::from_le_bytes(data), 0x0d57_8086);
+        ecam.read(ecam_offset(1, 0), &mut data);
+        assert_eq!(u32::from_le_bytes(data), 0x1042_1af4);
+        ecam.read(ecam_offset(2, 0), &mut data);
+        assert_eq!(u32::from_le_bytes(data), NO_DEVICE);
+        // Other functions and buses are empty.
+        ecam.read(ecam_offset(1, 0) | 1 << 12, &mut data);
+        assert_eq!(u32::from_le_bytes(data), NO_DEVICE);
+        ecam.read(ecam_offset(1, 0) | 1 << 20, &mut data);
+        assert_eq!(u32::from_le_bytes(data), NO_DEVICE);
+
+        let mut data = [0u8; 2];
+        ecam.read(ecam_offset(1, 0xa), &mut data);
+        assert_eq!(u16::from_le_bytes(data), 0x0180);
+
+        ecam.write(ecam_offset(1, 0x3c), &[0x0b]);
+        let mut data = [0u8; 1];
+        ecam.read(ecam_offset(1, 0x3c), &mut data);
+        assert_eq!(data[0], 0x0b);
+    }
+
+    #[test]
+    fn test_config_io() {
+        let root = Arc::new(Mutex::new(PciRoot::new()));
+        root.lock().unwrap().add_device(dummy_device()).unwrap();
+        let mut io = PciConfigIo::new(root);
+
+        // Nothing is selected until the enable bit is set.
+        let mut data = [0u8; 4];
+        io.read(4, &mut data);
+        assert_eq!(u32::from_le_bytes(data), NO_DEVICE);
+
+        io.write(0, &0x8000_0800u32.to_le_bytes());
+        io.read(0, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 0x8000_0800);
+        io.read(4, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 0x1042_1af4);
+
+        let mut data = [0u8; 2];
+        io.read(6, &mut data);
+        assert_eq!(u16::from_le_bytes(data), 0x1042);
+
+        io.write(0, &0x8000_083cu32.to_le_bytes());
+        io.write(4, &[0x0b]);
+        let mut data = [0u8; 1];
+        io.read(4, &mut data);
+        assert_eq!(data[0], 0x0b);
+    }
+}
@@ -0,0 +1,412 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use logger::{error, IncMetric, METRICS};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use super::{Result, TpmBackend, TPM_HEADER_SIZE};
+use crate::bus::BusDevice;
+
+/// The size of the MMIO region of the CRB interface, which only implements locality 0.
+pub const TPM_CRB_MMIO_SIZE: u64 = 0x1000;
+
+// Register offsets, as defined by the TCG PC Client Platform TPM Profile specification.
+const CRB_LOC_STATE: u64 = 0x00;
+const CRB_LOC_CTRL: u64 = 0x08;
+const CRB_LOC_STS: u64 = 0x0c;
+const CRB_INTF_ID: u64 = 0x30;
+const CRB_CTRL_REQ: u64 = 0x40;
+const CRB_CTRL_STS: u64 = 0x44;
+const CRB_CTRL_START: u64 = 0x4c;
+const CRB_INT_ENABLE: u64 = 0x50;
+const CRB_CTRL_CMD_SIZE: u64 = 0x58;
+const CRB_CTRL_CMD_LADDR: u64 = 0x5c;
+const CRB_CTRL_CMD_HADDR: u64 = 0x60;
+const CRB_CTRL_RSP_SIZE: u64 = 0x64;
+const CRB_CTRL_RSP_ADDR: u64 = 0x68;
+const CRB_CTRL_RSP_ADDR_HIGH: u64 = 0x6c;
+const CRB_DATA_BUFFER: u64 = 0x80;
+
+// The command and response buffer fills the rest of the region.
+const CRB_DATA_BUFFER_SIZE: usize = (TPM_CRB_MMIO_SIZE - CRB_DATA_BUFFER) as usize;
+
+// TPM_LOC_STATE bits.
+const LOC_STATE_TPM_ESTABLISHED: u32 = 1 << 0;
+const LOC_STATE_LOC_ASSIGNED: u32 = 1 << 1;
+const LOC_STATE_REG_VALID: u32 = 1 << 7;
+// TPM_LOC_CTRL bits.
+const LOC_CTRL_REQUEST_ACCESS: u32 = 1 << 0;
+const LOC_CTRL_RELINQUISH: u32 = 1 << 1;
+// TPM_LOC_STS bits.
+const LOC_STS_GRANTED: u32 = 1 << 0;
+// TPM_CRB_CTRL_REQ bits.
+const CTRL_REQ_CMD_READY: u32 = 1 << 0;
+const CTRL_REQ_GO_IDLE: u32 = 1 << 1;
+// TPM_CRB_CTRL_STS bits.
+const CTRL_STS_IDLE: u32 = 1 << 1;
+// TPM_CRB_CTRL_START bits.
+const CTRL_START_INVOKE: u32 = 1 << 0;
+
+// The interface identifier: a CRB interface (type and version 1), transferring up to 64 bytes
+// at a time, with the CRB interface selected.
+const CRB_INTF_ID_VALUE: u32 = 1 | (1 << 4) | (3 << 11) | (1 << 14) | (1 << 17);
+
+// The response to the commands which could not be delivered to the backend: `TPM_RC_FAILURE`.
+const TPM_FAILURE_RESPONSE: [u8; TPM_HEADER_SIZE] = [0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x01];
+
+/// The state of the CRB interface and of its backend.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct TpmCrbState {
+    /// The locality state register.
+    pub loc_state: u32,
+    /// The locality status register.
+    pub loc_sts: u32,
+    /// The control status register.
+    pub ctrl_sts: u32,
+    /// The interrupt enable register.
+    pub int_enable: u32,
+    /// The command and response buffer.
+    pub data_buffer: Vec<u8>,
+    /// The volatile state of the backend.
+    pub backend_state: Vec<u8>,
+}
+
+/// A TPM 2.0 device exposing the Command Response Buffer interface. The guest writes a command
+/// in the data buffer and starts it; the command runs synchronously, and its response replaces
+/// the command in the data buffer. The device doesn't raise interrupts: the guest polls the
+/// start register, which is cleared once the response is available.
+pub struct TpmCrb {
+    backend: Box<dyn TpmBackend>,
+    // The guest physical address of the MMIO region.
+    base: u64,
+    loc_state: u32,
+    loc_sts: u32,
+    ctrl_sts: u32,
+    int_enable: u32,
+    data_buffer: Vec<u8>,
+}
+
+impl TpmCrb {
+    /// Creates a TPM device at the `base` guest physical address, forwarding the commands to
+    /// `backend`.
+    pub fn new(backend: Box<dyn TpmBackend>, base: u64) -> Self {
+        TpmCrb {
+            backend,
+            base,
+            loc_state: LOC_STATE_TPM_ESTABLISHED | LOC_STATE_REG_VALID,
+            loc_sts: 0,
+            ctrl_sts: CTRL_STS_IDLE,
+            int_enable: 0,
+            data_buffer: vec![0; CRB_DATA_BUFFER_SIZE],
+        }
+    }
+
+    /// Creates a TPM device from a saved state.
+    pub fn restore(backend: Box<dyn TpmBackend>, base: u64, state: &TpmCrbState) -> Result<Self> {
+        let mut tpm = Self::new(backend, base);
+        tpm.backend.restore_state(&state.backend_state)?;
+        tpm.loc_state = state.loc_state;
+        tpm.loc_sts = state.loc_sts;
+        tpm.ctrl_sts = state.ctrl_sts;
+        tpm.int_enable = state.int_enable;
+        let len = std::cmp::min(state.data_buffer.len(), CRB_DATA_BUFFER_SIZE);
+        tpm.data_buffer[..len].copy_from_slice(&state.data_buffer[..len]);
+        Ok(tpm)
+    }
+
+    /// Saves the state of the device.
+    pub fn save_state(&self) -> TpmCrbState {
+        TpmCrbState {
+            loc_state: self.loc_state,
+            loc_sts: self.loc_sts,
+            ctrl_sts: self.ctrl_sts,
+            int_enable: self.int_enable,
+            data_buffer: self.data_buffer.clone(),
+            backend_state: self.backend.save_state(),
+        }
+    }
+
+    fn read_register(&self, offset: u64) -> u32 {
+        let buffer_addr = self.base + CRB_DATA_BUFFER;
+        match offset {
+            CRB_LOC_STATE => self.loc_state,
+            CRB_LOC_STS => self.loc_sts,
+            CRB_INTF_ID => CRB_INTF_ID_VALUE,
+            CRB_CTRL_STS => self.ctrl_sts,
+            CRB_INT_ENABLE => self.int_enable,
+            CRB_CTRL_CMD_SIZE | CRB_CTRL_RSP_SIZE => CRB_DATA_BUFFER_SIZE as u32,
+            CRB_CTRL_CMD_LADDR | CRB_CTRL_RSP_ADDR => buffer_addr as u32,
+            CRB_CTRL_CMD_HADDR | CRB_CTRL_RSP_ADDR_HIGH => (buffer_addr >> 32) as u32,
+            // The other registers read as zero, including the vendor and device IDs, and the
+            // start register since the commands complete before the guest can read it.
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, offset: u64, value: u32) {
+        match offset {
+            CRB_LOC_CTRL => {
+                if value & LOC_CTRL_REQUEST_ACCESS != 0 {
+                    self.loc_state |= LOC_STATE_LOC_ASSIGNED;
+                    self.loc_sts |= LOC_STS_GRANTED;
+                }
+                if value & LOC_CTRL_RELINQUISH != 0 {
+                    self.loc_state &= !LOC_STATE_LOC_ASSIGNED;
+                    self.loc_sts &= !LOC_STS_GRANTED;
+                }
+            }
+            CRB_CTRL_REQ => {
+                if value & CTRL_REQ_CMD_READY != 0 {
+                    self.ctrl_sts &= !CTRL_STS_IDLE;
+                }
+                if value & CTRL_REQ_GO_IDLE != 0 {
+                    self.ctrl_sts |= CTRL_STS_IDLE;
+                }
+            }
+            CRB_CTRL_START => {
+                if value & CTRL_START_INVOKE != 0 {
+                    self.execute();
+                }
+            }
+            CRB_INT_ENABLE => self.int_enable = value,
+            _ => (),
+        }
+    }
+
+    // Runs the command in the data buffer, and replaces it with the response.
+    fn execute(&mut self) {
+        // The size of the command is in its header.
+        let size = u32::from_be_bytes([
+            self.data_buffer[2],
+            self.data_buffer[3],
+            self.data_buffer[4],
+            self.data_buffer[5],
+        ]) as usize;
+        let command = &self.data_buffer[..std::cmp::min(size, self.data_buffer.len())];
+
+        METRICS.tpm.command_count.inc();
+        let response = match self.backend.execute(command) {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to execute a TPM command: {}", e);
+                METRICS.tpm.command_fails.inc();
+                TPM_FAILURE_RESPONSE.to_vec()
+            }
+        };
+        if response.len() > self.data_buffer.len() {
+            error!(
+                "The TPM response of {} bytes doesn't fit in the data buffer.",
+                response.len()
+            );
+            METRICS.tpm.command_fails.inc();
+        }
+        let len = std::cmp::min(response.len(), self.data_buffer.len());
+        self.data_buffer[..len].copy_from_slice(&response[..len]);
+    }
+}
+
+impl BusDevice for TpmCrb {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        if offset >= CRB_DATA_BUFFER {
+            let start = (offset - CRB_DATA_BUFFER) as usize;
+            if let Some(buffer) = self.data_buffer.get(start..start + data.len()) {
+                data.copy_from_slice(buffer);
+            }
+            return;
+        }
+
+        // The registers can be read with accesses of any size.
+        for (i, byte) in data.iter_mut().enumerate() {
+            let byte_offset = offset + i as u64;
+            let register = self.read_register(byte_offset & !3);
+            *byte = (register >> (8 * (byte_offset & 3))) as u8;
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        if offset >= CRB_DATA_BUFFER {
+            let start = (offset - CRB_DATA_BUFFER) as usize;
+            if let Some(buffer) = self.data_buffer.get_mut(start..start + data.len()) {
+                buffer.copy_from_slice(data);
+            }
+            return;
+        }
+
+        // The registers are written as a whole.
+        if offset & 3 != 0 || data.is_empty() || data.len() > 4 {
+            return;
+        }
+        let mut value = [0u8; 4];
+        value[..data.len()].copy_from_slice(data);
+        self.write_register(offset, u32::from_le_bytes(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::tpm::Error;
+
+    // A backend answering each command with its reversed bytes.
+    #[derive(Default)]
+    struct MockBackend {
+        commands: Arc<Mutex<Vec<Vec<u8>>>>,
+        fail: bool,
+        state: Vec<u8>,
+    }
+
+    impl TpmBackend for MockBackend {
+        fn execute(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+            self.commands.lock().unwrap().push(command.to_vec());
+            if self.fail {
+                return Err(Error::InvalidResponse);
+            }
+            Ok(command.iter().rev().cloned().collect())
+        }
+
+        fn save_state(&self) -> Vec<u8> {
+            self.state.clone()
+        }
+
+        fn restore_state(&mut self, state: &[u8]) -> Result<()> {
+            self.state = state.to_vec();
+            Ok(())
+        }
+    }
+
+    fn read_u32(tpm: &mut TpmCrb, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        tpm.read(offset, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    fn write_u32(tpm: &mut TpmCrb, offset: u64, value: u32) {
+        tpm.write(offset, &value.to_le_bytes());
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut tpm = TpmCrb::new(Box::new(MockBackend::default()), 0x1_fed4_0000);
+
+        assert_eq!(read_u32(&mut tpm, CRB_INTF_ID), CRB_INTF_ID_VALUE);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_CMD_SIZE), 0xf80);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_RSP_SIZE), 0xf80);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_CMD_LADDR), 0xfed4_0080);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_CMD_HADDR), 1);
+        let mut data = [0u8; 8];
+        tpm.read(CRB_CTRL_RSP_ADDR, &mut data);
+        assert_eq!(u64::from_le_bytes(data), 0x1_fed4_0080);
+        // Byte reads.
+        let mut data = [0u8; 1];
+        tpm.read(CRB_INTF_ID + 1, &mut data);
+        assert_eq!(data[0], (CRB_INTF_ID_VALUE >> 8) as u8);
+
+        // Locality requests.
+        assert_eq!(
+            read_u32(&mut tpm, CRB_LOC_STATE),
+            LOC_STATE_TPM_ESTABLISHED | LOC_STATE_REG_VALID
+        );
+        write_u32(&mut tpm, CRB_LOC_CTRL, LOC_CTRL_REQUEST_ACCESS);
+        assert_eq!(
+            read_u32(&mut tpm, CRB_LOC_STATE),
+            LOC_STATE_TPM_ESTABLISHED | LOC_STATE_LOC_ASSIGNED | LOC_STATE_REG_VALID
+        );
+        assert_eq!(read_u32(&mut tpm, CRB_LOC_STS), LOC_STS_GRANTED);
+        write_u32(&mut tpm, CRB_LOC_CTRL, LOC_CTRL_RELINQUISH);
+        assert_eq!(read_u32(&mut tpm, CRB_LOC_STS), 0);
+
+        // Idle transitions.
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_STS), CTRL_STS_IDLE);
+        write_u32(&mut tpm, CRB_CTRL_REQ, CTRL_REQ_CMD_READY);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_STS), 0);
+        write_u32(&mut tpm, CRB_CTRL_REQ, CTRL_REQ_GO_IDLE);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_STS), CTRL_STS_IDLE);
+
+        // Unaligned register writes are ignored.
+        tpm.write(CRB_INT_ENABLE + 1, &[1]);
+        assert_eq!(read_u32(&mut tpm, CRB_INT_ENABLE), 0);
+        write_u32(&mut tpm, CRB_INT_ENABLE, 1);
+        assert_eq!(read_u32(&mut tpm, CRB_INT_ENABLE), 1);
+    }
+
+    #[test]
+    fn test_commands() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let backend = MockBackend {
+            commands: commands.clone(),
+            ..Default::default()
+        };
+        let mut tpm = TpmCrb::new(Box::new(backend), 0xfed4_0000);
+
+        let command = [0x80, 0x01, 0, 0, 0, 12, 0, 0, 0x01, 0x43, 0, 1];
+        tpm.write(CRB_DATA_BUFFER, &command);
+        write_u32(&mut tpm, CRB_CTRL_START, CTRL_START_INVOKE);
+        assert_eq!(read_u32(&mut tpm, CRB_CTRL_START), 0);
+        assert_eq!(commands.lock().unwrap().as_slice(), &[command.to_vec()]);
+        let mut response = [0u8; 12];
+        tpm.read(CRB_DATA_BUFFER, &mut response);
+        let expected: Vec<u8> = command.iter().rev().cloned().collect();
+        assert_eq!(response.to_vec(), expected);
+
+        // Out of bounds buffer accesses are ignored.
+        tpm.write(TPM_CRB_MMIO_SIZE - 2, &[1, 2, 3, 4]);
+        let mut data = [0xffu8; 4];
+        tpm.read(TPM_CRB_MMIO_SIZE - 2, &mut data);
+        assert_eq!(data, [0xff; 4]);
+
+        // An oversized command is truncated to the buffer.
+        tpm.write(CRB_DATA_BUFFER, &[0x80, 0x01, 0xff, 0xff, 0xff, 0xff]);
+        write_u32(&mut tpm, CRB_CTRL_START, CTRL_START_INVOKE);
+        assert_eq!(commands.lock().unwrap()[1].len(), CRB_DATA_BUFFER_SIZE);
+
+        // The backend failures are reported to the guest.
+        let backend = MockBackend {
+            fail: true,
+            ..Default::default()
+        };
+        let mut tpm = TpmCrb::new(Box::new(backend), 0xfed4_0000);
+        let fails = METRICS.tpm.command_fails.count();
+        tpm.write(CRB_DATA_BUFFER, &command);
+        write_u32(&mut tpm, CRB_CTRL_START, CTRL_START_INVOKE);
+        let mut response = [0u8; TPM_HEADER_SIZE];
+        tpm.read(CRB_DATA_BUFFER, &mut response);
+        assert_eq!(response, TPM_FAILURE_RESPONSE);
+        assert_eq!(METRICS.tpm.command_fails.count(), fails + 1);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let backend = MockBackend {
+            state: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let mut tpm = TpmCrb::new(Box::new(backend), 0xfed4_0000);
+        write_u32(&mut tpm, CRB_LOC_CTRL, LOC_CTRL_REQUEST_ACCESS);
+        write_u32(&mut tpm, CRB_CTRL_REQ, CTRL_REQ_CMD_READY);
+        tpm.write(CRB_DATA_BUFFER, &[0xaa; 16]);
+
+        let state = tpm.save_state();
+        assert_eq!(state.backend_state, vec![1, 2, 3]);
+        let mut buf = vec![0; 8192];
+        let version_map = VersionMap::new();
+        state
+            .serialize(&mut buf.as_mut_slice(), &version_map, 1)
+            .unwrap();
+        let restored_state =
+            TpmCrbState::deserialize(&mut buf.as_slice(), &version_map, 1).unwrap();
+        assert_eq!(restored_state, state);
+
+        let restored = TpmCrb::restore(
+            Box::new(MockBackend::default()),
+            0xfed4_0000,
+            &restored_state,
+        )
+        .unwrap();
+        assert_eq!(restored.save_state(), state);
+    }
+}
@@ -0,0 +1,840 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! An in-process software TPM, implementing the subset of the TPM 2.0 commands needed for
+//! measured boot: start up and shut down, self tests, capabilities, random numbers, and reading
+//! and extending the SHA-256 PCR bank. The other commands fail with `TPM_RC_COMMAND_CODE`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use super::sha256::{sha256, SHA256_DIGEST_SIZE};
+use super::{Error, Result, TpmBackend, TPM_HEADER_SIZE};
+
+// Structure tags.
+const TPM_ST_NO_SESSIONS: u16 = 0x8001;
+const TPM_ST_SESSIONS: u16 = 0x8002;
+
+// Response codes, along with the qualifiers telling which handle, session or parameter is
+// faulty.
+const TPM_RC_SUCCESS: u32 = 0x000;
+const TPM_RC_BAD_TAG: u32 = 0x01e;
+const TPM_RC_HASH: u32 = 0x083;
+const TPM_RC_VALUE: u32 = 0x084;
+const TPM_RC_AUTH_FAIL: u32 = 0x08e;
+const TPM_RC_INSUFFICIENT: u32 = 0x09a;
+const TPM_RC_INITIALIZE: u32 = 0x100;
+const TPM_RC_FAILURE: u32 = 0x101;
+const TPM_RC_COMMAND_SIZE: u32 = 0x142;
+const TPM_RC_COMMAND_CODE: u32 = 0x143;
+const TPM_RC_AUTH_MISSING: u32 = 0x125;
+const TPM_RC_AUTH_UNAVAILABLE: u32 = 0x12f;
+const TPM_RC_P: u32 = 0x040;
+const TPM_RC_S: u32 = 0x800;
+const TPM_RC_1: u32 = 0x100;
+
+// Command codes.
+const TPM_CC_SELF_TEST: u32 = 0x143;
+const TPM_CC_STARTUP: u32 = 0x144;
+const TPM_CC_SHUTDOWN: u32 = 0x145;
+const TPM_CC_GET_CAPABILITY: u32 = 0x17a;
+const TPM_CC_GET_RANDOM: u32 = 0x17b;
+const TPM_CC_PCR_READ: u32 = 0x17e;
+const TPM_CC_PCR_EXTEND: u32 = 0x182;
+// The implemented commands, in increasing order, along with their number of handles.
+const COMMANDS: [(u32, u32); 7] = [
+    (TPM_CC_SELF_TEST, 0),
+    (TPM_CC_STARTUP, 0),
+    (TPM_CC_SHUTDOWN, 0),
+    (TPM_CC_GET_CAPABILITY, 0),
+    (TPM_CC_GET_RANDOM, 0),
+    (TPM_CC_PCR_READ, 0),
+    (TPM_CC_PCR_EXTEND, 1),
+];
+
+// Startup and shutdown types.
+const TPM_SU_CLEAR: u16 = 0;
+const TPM_SU_STATE: u16 = 1;
+
+// Hash algorithms, and the size of their digests.
+const TPM_ALG_SHA1: u16 = 0x0004;
+const TPM_ALG_SHA256: u16 = 0x000b;
+const TPM_ALG_SHA384: u16 = 0x000c;
+const TPM_ALG_SHA512: u16 = 0x000d;
+const TPM_ALG_SM3_256: u16 = 0x0012;
+
+fn digest_size(alg: u16) -> Option<usize> {
+    match alg {
+        TPM_ALG_SHA1 => Some(20),
+        TPM_ALG_SHA256 | TPM_ALG_SM3_256 => Some(32),
+        TPM_ALG_SHA384 => Some(48),
+        TPM_ALG_SHA512 => Some(64),
+        _ => None,
+    }
+}
+
+// Capabilities.
+const TPM_CAP_COMMANDS: u32 = 2;
+const TPM_CAP_PCRS: u32 = 5;
+const TPM_CAP_TPM_PROPERTIES: u32 = 6;
+
+// Fixed TPM properties.
+const TPM_PT_FAMILY_INDICATOR: u32 = 0x100;
+const TPM_PT_LEVEL: u32 = 0x101;
+const TPM_PT_REVISION: u32 = 0x102;
+const TPM_PT_MANUFACTURER: u32 = 0x105;
+const TPM_PT_PCR_COUNT: u32 = 0x112;
+const TPM_PT_PCR_SELECT_MIN: u32 = 0x113;
+const TPM_PT_MAX_COMMAND_SIZE: u32 = 0x11e;
+const TPM_PT_MAX_RESPONSE_SIZE: u32 = 0x11f;
+const TPM_PT_MAX_DIGEST: u32 = 0x120;
+const TPM_PT_TOTAL_COMMANDS: u32 = 0x129;
+
+// The position of the handle count in the command attributes.
+const TPMA_CC_C_HANDLES_SHIFT: u32 = 25;
+
+// The handles of the password authorization session and of the null hierarchy.
+const TPM_RS_PW: u32 = 0x4000_0009;
+const TPM_RH_NULL: u32 = 0x4000_0007;
+
+const PCR_COUNT: usize = 24;
+// The size of the PCR selection bitmaps.
+const PCR_SELECT_SIZE: usize = PCR_COUNT / 8;
+// The PCRs which keep their value across a `TPM2_Shutdown(TPM_SU_STATE)` and
+// `TPM2_Startup(TPM_SU_STATE)` sequence.
+const PRESERVED_PCRS: usize = 16;
+// The most digests returned by a single `TPM2_PCR_Read`.
+const MAX_PCR_READ_DIGESTS: usize = 8;
+// The largest command or response exchanged with the guest.
+const MAX_BUFFER_SIZE: u32 = 4096;
+
+// The initial value of a PCR. The locality PCRs, 17 to 22, are reserved for dynamic launches
+// and start with all bits set.
+fn initial_pcr(index: usize) -> [u8; SHA256_DIGEST_SIZE] {
+    if (17..=22).contains(&index) {
+        [0xff; SHA256_DIGEST_SIZE]
+    } else {
+        [0; SHA256_DIGEST_SIZE]
+    }
+}
+
+// Fills `buf` with random bytes from the host kernel.
+fn fill_random(buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let remaining = &mut buf[filled..];
+        // Safe because the kernel only writes within the bounds of `remaining`, and we check
+        // the result.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_getrandom,
+                remaining.as_mut_ptr(),
+                remaining.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        filled += ret as usize;
+    }
+
+    Ok(())
+}
+
+// Reads the big endian fields of a command.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> std::result::Result<&'a [u8], u32> {
+        if self.buf.len() < len {
+            return Err(TPM_RC_INSUFFICIENT);
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> std::result::Result<u8, u32> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, u32> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+// The outcome of a command: either its response parameters or a response code.
+type CommandResult = std::result::Result<Vec<u8>, u32>;
+
+// Qualifies the response code of a faulty parameter.
+fn parameter_error(rc: u32, index: u32) -> u32 {
+    rc | TPM_RC_P | (index * TPM_RC_1)
+}
+
+// The state kept in the state file across runs.
+#[derive(Debug, Default, PartialEq, Versionize)]
+struct PersistentState {
+    // The PCRs saved by `TPM2_Shutdown(TPM_SU_STATE)`, until the next `TPM2_Startup`.
+    saved_pcrs: Option<Vec<u8>>,
+}
+
+/// The volatile state of the software TPM.
+#[derive(Debug, PartialEq, Versionize)]
+struct SoftwareTpmState {
+    started: bool,
+    pcr_update_counter: u32,
+    pcrs: Vec<u8>,
+}
+
+/// A software TPM, keeping its persistent state in a host file.
+pub struct SoftwareTpm {
+    state_file: File,
+    persistent: PersistentState,
+    // Whether the guest sent `TPM2_Startup`.
+    started: bool,
+    pcr_update_counter: u32,
+    pcrs: [[u8; SHA256_DIGEST_SIZE]; PCR_COUNT],
+}
+
+impl SoftwareTpm {
+    /// Creates a software TPM, loading its persistent state from `state_path`, which is created
+    /// if missing.
+    pub fn new<P: AsRef<Path>>(state_path: P) -> Result<Self> {
+        let mut state_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(state_path)
+            .map_err(Error::StateFile)?;
+        let mut contents = Vec::new();
+        state_file
+            .read_to_end(&mut contents)
+            .map_err(Error::StateFile)?;
+        let persistent = if contents.is_empty() {
+            PersistentState::default()
+        } else {
+            PersistentState::deserialize(&mut contents.as_slice(), &VersionMap::new(), 1)
+                .map_err(|_| Error::InvalidState)?
+        };
+
+        let mut pcrs = [[0; SHA256_DIGEST_SIZE]; PCR_COUNT];
+        for (index, pcr) in pcrs.iter_mut().enumerate() {
+            *pcr = initial_pcr(index);
+        }
+        Ok(SoftwareTpm {
+            state_file,
+            persistent,
+            started: false,
+            pcr_update_counter: 0,
+            pcrs,
+        })
+    }
+
+    fn store_persistent_state(&mut self) -> Result<()> {
+        let mut contents = Vec::new();
+        self.persistent
+            .serialize(&mut contents, &VersionMap::new(), 1)
+            .map_err(|_| Error::InvalidState)?;
+        self.state_file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.state_file.write_all(&contents))
+            .and_then(|_| self.state_file.set_len(contents.len() as u64))
+            .and_then(|_| self.state_file.sync_all())
+            .map_err(Error::StateFile)
+    }
+
+    fn startup(&mut self, params: &mut Reader) -> Result<CommandResult> {
+        let startup_type = match params.u16() {
+            Ok(startup_type) => startup_type,
+            Err(rc) => return Ok(Err(parameter_error(rc, 1))),
+        };
+        let saved_pcrs = match startup_type {
+            TPM_SU_CLEAR => None,
+            TPM_SU_STATE => match self.persistent.saved_pcrs.take() {
+                Some(saved_pcrs) => Some(saved_pcrs),
+                None => return Ok(Err(parameter_error(TPM_RC_VALUE, 1))),
+            },
+            _ => return Ok(Err(parameter_error(TPM_RC_VALUE, 1))),
+        };
+
+        for (index, pcr) in self.pcrs.iter_mut().enumerate() {
+            *pcr = initial_pcr(index);
+        }
+        if let Some(saved_pcrs) = saved_pcrs {
+            for (pcr, saved) in self
+                .pcrs
+                .iter_mut()
+                .zip(saved_pcrs.chunks_exact(SHA256_DIGEST_SIZE))
+                .take(PRESERVED_PCRS)
+            {
+                pcr.copy_from_slice(saved);
+            }
+            // The saved state can only be used once.
+            self.store_persistent_state()?;
+        }
+        self.pcr_update_counter = 0;
+        self.started = true;
+        Ok(Ok(Vec::new()))
+    }
+
+    fn shutdown(&mut self, params: &mut Reader) -> Result<CommandResult> {
+        let shutdown_type = match params.u16() {
+            Ok(shutdown_type) => shutdown_type,
+            Err(rc) => return Ok(Err(parameter_error(rc, 1))),
+        };
+        self.persistent.saved_pcrs = match shutdown_type {
+            TPM_SU_CLEAR => None,
+            TPM_SU_STATE => Some(self.pcrs.concat()),
+            _ => return Ok(Err(parameter_error(TPM_RC_VALUE, 1))),
+        };
+        self.store_persistent_state()?;
+        Ok(Ok(Vec::new()))
+    }
+
+    fn get_capability(&self, params: &mut Reader) -> CommandResult {
+        let capability = params.u32().map_err(|rc| parameter_error(rc, 1))?;
+        let property = params.u32().map_err(|rc| parameter_error(rc, 2))?;
+        let count = params.u32().map_err(|rc| parameter_error(rc, 3))? as usize;
+
+        let mut data = Vec::new();
+        let more_data;
+        match capability {
+            TPM_CAP_COMMANDS => {
+                let commands: Vec<&(u32, u32)> = COMMANDS
+                    .iter()
+                    .filter(|(code, _)| *code >= property)
+                    .collect();
+                more_data = commands.len() > count;
+                let commands = &commands[..std::cmp::min(count, commands.len())];
+                data.extend_from_slice(&(commands.len() as u32).to_be_bytes());
+                for (code, handles) in commands {
+                    let attributes = (code & 0xffff) | (handles << TPMA_CC_C_HANDLES_SHIFT);
+                    data.extend_from_slice(&attributes.to_be_bytes());
+                }
+            }
+            TPM_CAP_PCRS => {
+                // Only the SHA-256 bank is allocated.
+                more_data = false;
+                data.extend_from_slice(&1u32.to_be_bytes());
+                data.extend_from_slice(&TPM_ALG_SHA256.to_be_bytes());
+                data.push(PCR_SELECT_SIZE as u8);
+                data.extend_from_slice(&[0xff; PCR_SELECT_SIZE]);
+            }
+            TPM_CAP_TPM_PROPERTIES => {
+                let properties: Vec<(u32, u32)> = [
+                    (TPM_PT_FAMILY_INDICATOR, u32::from_be_bytes(*b"2.0\0")),
+                    (TPM_PT_LEVEL, 0),
+                    (TPM_PT_REVISION, 138),
+                    (TPM_PT_MANUFACTURER, u32::from_be_bytes(*b"FCVM")),
+                    (TPM_PT_PCR_COUNT, PCR_COUNT as u32),
+                    (TPM_PT_PCR_SELECT_MIN, PCR_SELECT_SIZE as u32),
+                    (TPM_PT_MAX_COMMAND_SIZE, MAX_BUFFER_SIZE),
+                    (TPM_PT_MAX_RESPONSE_SIZE, MAX_BUFFER_SIZE),
+                    (TPM_PT_MAX_DIGEST, SHA256_DIGEST_SIZE as u32),
+                    (TPM_PT_TOTAL_COMMANDS, COMMANDS.len() as u32),
+                ]
+                .iter()
+                .filter(|(tag, _)| *tag >= property)
+                .cloned()
+                .collect();
+                more_data = properties.len() > count;
+                let properties = &properties[..std::cmp::min(count, properties.len())];
+                data.extend_from_slice(&(properties.len() as u32).to_be_bytes());
+                for (tag, value) in properties {
+                    data.extend_from_slice(&tag.to_be_bytes());
+                    data.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            _ => return Err(parameter_error(TPM_RC_VALUE, 1)),
+        }
+
+        let mut response = vec![more_data as u8];
+        response.extend_from_slice(&capability.to_be_bytes());
+        response.extend_from_slice(&data);
+        Ok(response)
+    }
+
+    fn get_random(&self, params: &mut Reader) -> CommandResult {
+        let requested = params.u16().map_err(|rc| parameter_error(rc, 1))?;
+        // At most the size of the largest digest is returned.
+        let mut bytes = vec![0; std::cmp::min(usize::from(requested), SHA256_DIGEST_SIZE)];
+        fill_random(&mut bytes).map_err(|_| TPM_RC_FAILURE)?;
+
+        let mut response = (bytes.len() as u16).to_be_bytes().to_vec();
+        response.extend_from_slice(&bytes);
+        Ok(response)
+    }
+
+    fn pcr_read(&self, params: &mut Reader) -> CommandResult {
+        let count = params.u32().map_err(|rc| parameter_error(rc, 1))?;
+        let mut selections = Vec::new();
+        let mut digests = Vec::new();
+        for _ in 0..count {
+            let alg = params.u16().map_err(|rc| parameter_error(rc, 1))?;
+            let size = params.u8().map_err(|rc| parameter_error(rc, 1))?;
+            if usize::from(size) > PCR_SELECT_SIZE {
+                return Err(parameter_error(TPM_RC_VALUE, 1));
+            }
+            let select = params
+                .bytes(usize::from(size))
+                .map_err(|rc| parameter_error(rc, 1))?;
+
+            // Only the selected PCRs of the SHA-256 bank which fit in the response are returned.
+            let mut selected = vec![0u8; usize::from(size)];
+            if alg == TPM_ALG_SHA256 {
+                for index in 0..usize::from(size) * 8 {
+                    if select[index / 8] & (1 << (index % 8)) != 0
+                        && digests.len() < MAX_PCR_READ_DIGESTS
+                    {
+                        selected[index / 8] |= 1 << (index % 8);
+                        digests.push(self.pcrs[index]);
+                    }
+                }
+            }
+            selections.push((alg, selected));
+        }
+
+        let mut response = self.pcr_update_counter.to_be_bytes().to_vec();
+        response.extend_from_slice(&(selections.len() as u32).to_be_bytes());
+        for (alg, selected) in selections {
+            response.extend_from_slice(&alg.to_be_bytes());
+            response.push(selected.len() as u8);
+            response.extend_from_slice(&selected);
+        }
+        response.extend_from_slice(&(digests.len() as u32).to_be_bytes());
+        for digest in digests {
+            response.extend_from_slice(&(SHA256_DIGEST_SIZE as u16).to_be_bytes());
+            response.extend_from_slice(&digest);
+        }
+        Ok(response)
+    }
+
+    fn pcr_extend(&mut self, handle: u32, params: &mut Reader) -> CommandResult {
+        let index = if handle == TPM_RH_NULL {
+            None
+        } else if (handle as usize) < PCR_COUNT {
+            Some(handle as usize)
+        } else {
+            return Err(TPM_RC_VALUE | TPM_RC_1);
+        };
+
+        let count = params.u32().map_err(|rc| parameter_error(rc, 1))?;
+        let mut extends = Vec::new();
+        for _ in 0..count {
+            let alg = params.u16().map_err(|rc| parameter_error(rc, 1))?;
+            let size = digest_size(alg).ok_or_else(|| parameter_error(TPM_RC_HASH, 1))?;
+            let digest = params.bytes(size).map_err(|rc| parameter_error(rc, 1))?;
+            // The digests of the banks which are not allocated are ignored.
+            if alg == TPM_ALG_SHA256 {
+                extends.push(digest);
+            }
+        }
+
+        if let Some(index) = index {
+            for digest in extends {
+                self.pcrs[index] = sha256(&[&self.pcrs[index], digest]);
+                self.pcr_update_counter = self.pcr_update_counter.wrapping_add(1);
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    // Checks the authorization area of a command, returning the attributes of the password
+    // session, which is the only one supported.
+    fn authorize(command: &mut Reader) -> std::result::Result<u8, u32> {
+        let auth_size = command.u32()?;
+        let mut auth = Reader {
+            buf: command.bytes(auth_size as usize)?,
+        };
+        let session = auth.u32()?;
+        if session != TPM_RS_PW {
+            return Err(TPM_RC_AUTH_UNAVAILABLE);
+        }
+        let nonce_size = auth.u16()?;
+        auth.bytes(usize::from(nonce_size))?;
+        let attributes = auth.u8()?;
+        let password_size = auth.u16()?;
+        auth.bytes(usize::from(password_size))?;
+        // The PCRs have an empty authorization value.
+        if password_size != 0 {
+            return Err(TPM_RC_AUTH_FAIL | TPM_RC_S | TPM_RC_1);
+        }
+        if !auth.buf.is_empty() {
+            // More than one session was sent.
+            return Err(TPM_RC_AUTH_UNAVAILABLE);
+        }
+        Ok(attributes)
+    }
+
+    fn dispatch(&mut self, command: &[u8]) -> Result<(CommandResult, Option<u8>)> {
+        let mut reader = Reader { buf: command };
+        // The header was checked by the caller.
+        let tag = reader.u16().unwrap_or_default();
+        let size = reader.u32().unwrap_or_default();
+        let code = reader.u32().unwrap_or_default();
+
+        if size as usize != command.len() {
+            return Ok((Err(TPM_RC_COMMAND_SIZE), None));
+        }
+        if tag != TPM_ST_NO_SESSIONS && tag != TPM_ST_SESSIONS {
+            return Ok((Err(TPM_RC_BAD_TAG), None));
+        }
+        let handles = match COMMANDS.iter().find(|(cc, _)| *cc == code) {
+            Some((_, handles)) => *handles,
+            None => return Ok((Err(TPM_RC_COMMAND_CODE), None)),
+        };
+        if self.started == (code == TPM_CC_STARTUP) {
+            return Ok((Err(TPM_RC_INITIALIZE), None));
+        }
+
+        // Only the commands acting on a PCR need an authorization session.
+        let handle = match reader.bytes(handles as usize * 4) {
+            Ok(bytes) if handles == 1 => {
+                Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            Ok(_) => None,
+            Err(rc) => return Ok((Err(rc), None)),
+        };
+        let session = match (handle.is_some(), tag) {
+            (true, TPM_ST_SESSIONS) => match Self::authorize(&mut reader) {
+                Ok(attributes) => Some(attributes),
+                Err(rc) => return Ok((Err(rc), None)),
+            },
+            (true, _) => return Ok((Err(TPM_RC_AUTH_MISSING), None)),
+            (false, TPM_ST_SESSIONS) => return Ok((Err(TPM_RC_AUTH_UNAVAILABLE), None)),
+            (false, _) => None,
+        };
+
+        let result = match code {
+            TPM_CC_SELF_TEST => Ok(Vec::new()),
+            TPM_CC_STARTUP => self.startup(&mut reader)?,
+            TPM_CC_SHUTDOWN => self.shutdown(&mut reader)?,
+            TPM_CC_GET_CAPABILITY => self.get_capability(&mut reader),
+            TPM_CC_GET_RANDOM => self.get_random(&mut reader),
+            TPM_CC_PCR_READ => self.pcr_read(&mut reader),
+            TPM_CC_PCR_EXTEND => self.pcr_extend(handle.unwrap_or_default(), &mut reader),
+            _ => Err(TPM_RC_COMMAND_CODE),
+        };
+        Ok((result, session))
+    }
+}
+
+impl TpmBackend for SoftwareTpm {
+    fn execute(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+        let (result, session) = if command.len() < TPM_HEADER_SIZE {
+            (Err(TPM_RC_COMMAND_SIZE), None)
+        } else {
+            self.dispatch(command)?
+        };
+
+        let mut response = Vec::with_capacity(TPM_HEADER_SIZE);
+        match (result, session) {
+            (Ok(params), Some(attributes)) => {
+                response.extend_from_slice(&TPM_ST_SESSIONS.to_be_bytes());
+                response.extend_from_slice(&[0; 4]);
+                response.extend_from_slice(&TPM_RC_SUCCESS.to_be_bytes());
+                response.extend_from_slice(&(params.len() as u32).to_be_bytes());
+                response.extend_from_slice(&params);
+                // The password session response: an empty nonce, the session attributes, and
+                // an empty acknowledgment.
+                response.extend_from_slice(&[0, 0, attributes, 0, 0]);
+            }
+            (Ok(params), None) => {
+                response.extend_from_slice(&TPM_ST_NO_SESSIONS.to_be_bytes());
+                response.extend_from_slice(&[0; 4]);
+                response.extend_from_slice(&TPM_RC_SUCCESS.to_be_bytes());
+                response.extend_from_slice(&params);
+            }
+            (Err(rc), _) => {
+                response.extend_from_slice(&TPM_ST_NO_SESSIONS.to_be_bytes());
+                response.extend_from_slice(&[0; 4]);
+                response.extend_from_slice(&rc.to_be_bytes());
+            }
+        }
+        let size = (response.len() as u32).to_be_bytes();
+        response[2..6].copy_from_slice(&size);
+        Ok(response)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let state = SoftwareTpmState {
+            started: self.started,
+            pcr_update_counter: self.pcr_update_counter,
+            pcrs: self.pcrs.concat(),
+        };
+        let mut bytes = Vec::new();
+        // Serializing to memory cannot fail.
+        let _ = state.serialize(&mut bytes, &VersionMap::new(), 1);
+        bytes
+    }
+
+    fn restore_state(&mut self, mut state: &[u8]) -> Result<()> {
+        let state = SoftwareTpmState::deserialize(&mut state, &VersionMap::new(), 1)
+            .map_err(|_| Error::InvalidState)?;
+        if state.pcrs.len() != PCR_COUNT * SHA256_DIGEST_SIZE {
+            return Err(Error::InvalidState);
+        }
+        self.started = state.started;
+        self.pcr_update_counter = state.pcr_update_counter;
+        for (pcr, saved) in self
+            .pcrs
+            .iter_mut()
+            .zip(state.pcrs.chunks_exact(SHA256_DIGEST_SIZE))
+        {
+            pcr.copy_from_slice(saved);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempfile::TempFile;
+
+    // Builds a command from its tag, code and body.
+    fn command(tag: u16, code: u32, body: &[u8]) -> Vec<u8> {
+        let mut command = tag.to_be_bytes().to_vec();
+        command.extend_from_slice(&((TPM_HEADER_SIZE + body.len()) as u32).to_be_bytes());
+        command.extend_from_slice(&code.to_be_bytes());
+        command.extend_from_slice(body);
+        command
+    }
+
+    fn startup_command(startup_type: u16) -> Vec<u8> {
+        command(
+            TPM_ST_NO_SESSIONS,
+            TPM_CC_STARTUP,
+            &startup_type.to_be_bytes(),
+        )
+    }
+
+    fn response_code(response: &[u8]) -> u32 {
+        assert_eq!(
+            u32::from_be_bytes([response[2], response[3], response[4], response[5]]) as usize,
+            response.len()
+        );
+        u32::from_be_bytes([response[6], response[7], response[8], response[9]])
+    }
+
+    fn extend_command(pcr: u32, digest: &[u8]) -> Vec<u8> {
+        let mut body = pcr.to_be_bytes().to_vec();
+        // A password session with an empty password.
+        body.extend_from_slice(&9u32.to_be_bytes());
+        body.extend_from_slice(&TPM_RS_PW.to_be_bytes());
+        body.extend_from_slice(&[0, 0, 1, 0, 0]);
+        body.extend_from_slice(&2u32.to_be_bytes());
+        body.extend_from_slice(&TPM_ALG_SHA1.to_be_bytes());
+        body.extend_from_slice(&[0xaa; 20]);
+        body.extend_from_slice(&TPM_ALG_SHA256.to_be_bytes());
+        body.extend_from_slice(digest);
+        command(TPM_ST_SESSIONS, TPM_CC_PCR_EXTEND, &body)
+    }
+
+    fn read_pcr(tpm: &mut SoftwareTpm, pcr: usize) -> Vec<u8> {
+        let mut select = [0u8; PCR_SELECT_SIZE];
+        select[pcr / 8] = 1 << (pcr % 8);
+        let mut body = 1u32.to_be_bytes().to_vec();
+        body.extend_from_slice(&TPM_ALG_SHA256.to_be_bytes());
+        body.push(PCR_SELECT_SIZE as u8);
+        body.extend_from_slice(&select);
+        let response = tpm
+            .execute(&command(TPM_ST_NO_SESSIONS, TPM_CC_PCR_READ, &body))
+            .unwrap();
+        assert_eq!(response_code(&response), TPM_RC_SUCCESS);
+        // The header, the update counter, the selection and the digest count and size precede
+        // the digest.
+        let offset = TPM_HEADER_SIZE + 4 + 4 + 2 + 1 + PCR_SELECT_SIZE + 4 + 2;
+        assert_eq!(&response[offset - 6 - PCR_SELECT_SIZE..offset - 6], &select);
+        response[offset..].to_vec()
+    }
+
+    #[test]
+    fn test_startup() {
+        let state_file = TempFile::new().unwrap();
+        let mut tpm = SoftwareTpm::new(state_file.as_path()).unwrap();
+
+        // The commands fail until the TPM is started up.
+        let self_test = command(TPM_ST_NO_SESSIONS, TPM_CC_SELF_TEST, &[1]);
+        let response = tpm.execute(&self_test).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_INITIALIZE);
+
+        // There's no state to resume.
+        let response = tpm.execute(&startup_command(TPM_SU_STATE)).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_VALUE | TPM_RC_P | TPM_RC_1);
+
+        let response = tpm.execute(&startup_command(TPM_SU_CLEAR)).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_SUCCESS);
+        let response = tpm.execute(&startup_command(TPM_SU_CLEAR)).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_INITIALIZE);
+        let response = tpm.execute(&self_test).unwrap();
+        assert_eq!(response, vec![0x80, 0x01, 0, 0, 0, 10, 0, 0, 0, 0]);
+
+        // Malformed commands.
+        let response = tpm.execute(&[0x80, 0x01, 0, 0]).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_COMMAND_SIZE);
+        let mut bad_size = self_test.clone();
+        bad_size[5] = 20;
+        let response = tpm.execute(&bad_size).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_COMMAND_SIZE);
+        let response = tpm
+            .execute(&command(0x00c1, TPM_CC_SELF_TEST, &[1]))
+            .unwrap();
+        assert_eq!(response_code(&response), TPM_RC_BAD_TAG);
+        let response = tpm
+            .execute(&command(TPM_ST_NO_SESSIONS, 0x153, &[]))
+            .unwrap();
+        assert_eq!(response_code(&response), TPM_RC_COMMAND_CODE);
+    }
+
+    #[test]
+    fn test_pcrs() {
+        let state_file = TempFile::new().unwrap();
+        let mut tpm = SoftwareTpm::new(state_file.as_path()).unwrap();
+        tpm.execute(&startup_command(TPM_SU_CLEAR)).unwrap();
+
+        assert_eq!(read_pcr(&mut tpm, 0), vec![0; SHA256_DIGEST_SIZE]);
+        assert_eq!(read_pcr(&mut tpm, 17), vec![0xff; SHA256_DIGEST_SIZE]);
+
+        let digest = [0x55; SHA256_DIGEST_SIZE];
+        let response = tpm.execute(&extend_command(7, &digest)).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_SUCCESS);
+        // The parameter size and the password session response follow the header.
+        assert_eq!(&response[TPM_HEADER_SIZE..], &[0, 0, 0, 0, 0, 0, 1, 0, 0]);
+        let expected = sha256(&[&[0; SHA256_DIGEST_SIZE], &digest]);
+        assert_eq!(read_pcr(&mut tpm, 7), expected.to_vec());
+        assert_eq!(tpm.pcr_update_counter, 1);
+
+        // Extending a PCR requires an authorization session.
+        let mut no_session = extend_command(7, &digest);
+        no_session[1] = 0x01;
+        let response = tpm.execute(&no_session).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_AUTH_MISSING);
+        // Invalid PCR.
+        let response = tpm.execute(&extend_command(24, &digest)).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_VALUE | TPM_RC_1);
+        // Truncated digest.
+        let mut truncated = extend_command(7, &digest);
+        truncated.truncate(truncated.len() - 1);
+        let size = (truncated.len() as u32).to_be_bytes();
+        truncated[2..6].copy_from_slice(&size);
+        let response = tpm.execute(&truncated).unwrap();
+        assert_eq!(
+            response_code(&response),
+            TPM_RC_INSUFFICIENT | TPM_RC_P | TPM_RC_1
+        );
+        assert_eq!(read_pcr(&mut tpm, 7), expected.to_vec());
+
+        // The volatile state is saved in snapshots.
+        let state = tpm.save_state();
+        let mut restored = SoftwareTpm::new(state_file.as_path()).unwrap();
+        restored.restore_state(&state).unwrap();
+        assert!(restored.started);
+        assert_eq!(read_pcr(&mut restored, 7), expected.to_vec());
+        assert!(restored.restore_state(&state[..10]).is_err());
+    }
+
+    #[test]
+    fn test_shutdown_state() {
+        let state_file = TempFile::new().unwrap();
+        let mut tpm = SoftwareTpm::new(state_file.as_path()).unwrap();
+        tpm.execute(&startup_command(TPM_SU_CLEAR)).unwrap();
+        let digest = [0x55; SHA256_DIGEST_SIZE];
+        tpm.execute(&extend_command(0, &digest)).unwrap();
+        tpm.execute(&extend_command(16, &digest)).unwrap();
+        let pcr0 = read_pcr(&mut tpm, 0);
+
+        let shutdown = command(
+            TPM_ST_NO_SESSIONS,
+            TPM_CC_SHUTDOWN,
+            &TPM_SU_STATE.to_be_bytes(),
+        );
+        let response = tpm.execute(&shutdown).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_SUCCESS);
+
+        // The preserved PCRs are restored by the next TPM using the same state file.
+        let mut tpm = SoftwareTpm::new(state_file.as_path()).unwrap();
+        let response = tpm.execute(&startup_command(TPM_SU_STATE)).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_SUCCESS);
+        assert_eq!(read_pcr(&mut tpm, 0), pcr0);
+        assert_eq!(read_pcr(&mut tpm, 16), vec![0; SHA256_DIGEST_SIZE]);
+
+        // The saved state can only be resumed once.
+        let mut tpm = SoftwareTpm::new(state_file.as_path()).unwrap();
+        let response = tpm.execute(&startup_command(TPM_SU_STATE)).unwrap();
+        assert_eq!(response_code(&response), TPM_RC_VALUE | TPM_RC_P | TPM_RC_1);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let state_file = TempFile::new().unwrap();
+        let mut tpm = SoftwareTpm::new(state_file.as_path()).unwrap();
+        tpm.execute(&startup_command(TPM_SU_CLEAR)).unwrap();
+
+        let get_capability = |tpm: &mut SoftwareTpm, capability: u32, property: u32, count: u32| {
+            let mut body = capability.to_be_bytes().to_vec();
+            body.extend_from_slice(&property.to_be_bytes());
+            body.extend_from_slice(&count.to_be_bytes());
+            tpm.execute(&command(TPM_ST_NO_SESSIONS, TPM_CC_GET_CAPABILITY, &body))
+                .unwrap()
+        };
+
+        let response = get_capability(&mut tpm, TPM_CAP_TPM_PROPERTIES, TPM_PT_TOTAL_COMMANDS, 1);
+        assert_eq!(response_code(&response), TPM_RC_SUCCESS);
+        assert_eq!(
+            &response[TPM_HEADER_SIZE..],
+            &[0, 0, 0, 0, 6, 0, 0, 0, 1, 0, 0, 1, 0x29, 0, 0, 0, 7]
+        );
+
+        // Only the first property is returned, and more are available.
+        let response = get_capability(&mut tpm, TPM_CAP_TPM_PROPERTIES, 0, 1);
+        assert_eq!(
+            &response[TPM_HEADER_SIZE..],
+            &[1, 0, 0, 0, 6, 0, 0, 0, 1, 0, 0, 1, 0, 0x32, 0x2e, 0x30, 0]
+        );
+
+        let response = get_capability(&mut tpm, TPM_CAP_COMMANDS, TPM_CC_PCR_READ, 8);
+        assert_eq!(
+            &response[TPM_HEADER_SIZE..],
+            &[0, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0x01, 0x7e, 0x02, 0, 0x01, 0x82]
+        );
+
+        let response = get_capability(&mut tpm, TPM_CAP_PCRS, 0, 1);
+        assert_eq!(
+            &response[TPM_HEADER_SIZE..],
+            &[0, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0x0b, 3, 0xff, 0xff, 0xff]
+        );
+
+        let response = get_capability(&mut tpm, 0x100, 0, 1);
+        assert_eq!(response_code(&response), TPM_RC_VALUE | TPM_RC_P | TPM_RC_1);
+
+        let response = tpm
+            .execute(&command(
+                TPM_ST_NO_SESSIONS,
+                TPM_CC_GET_RANDOM,
+                &64u16.to_be_bytes(),
+            ))
+            .unwrap();
+        assert_eq!(response_code(&response), TPM_RC_SUCCESS);
+        assert_eq!(&response[TPM_HEADER_SIZE..TPM_HEADER_SIZE + 2], &[0, 32]);
+        assert_eq!(response.len(), TPM_HEADER_SIZE + 2 + 32);
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements a TPM 2.0 device, exposing the Command Response Buffer (CRB) interface to the
+//! guest, and forwarding the commands to either an in-process software TPM or an external swtpm
+//! process.
+
+mod crb;
+mod emulator;
+mod sha256;
+mod swtpm;
+
+use std::{fmt, io};
+
+pub use self::crb::{TpmCrb, TpmCrbState, TPM_CRB_MMIO_SIZE};
+pub use self::emulator::SoftwareTpm;
+pub use self::swtpm::SwTpm;
+
+/// The size of a TPM command or response header: tag, size and code.
+pub(crate) const TPM_HEADER_SIZE: usize = 10;
+
+/// Errors triggered by the TPM device and its backends.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to connect to the swtpm socket.
+    Connect(io::Error),
+    /// The swtpm process sent a malformed response.
+    InvalidResponse,
+    /// The saved backend state cannot be restored.
+    InvalidState,
+    /// Failed to read or write the software TPM state file.
+    StateFile(io::Error),
+    /// Failed to exchange a command with the swtpm process.
+    Transfer(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            Connect(err) => write!(f, "Cannot connect to the swtpm socket: {}", err),
+            InvalidResponse => write!(f, "Malformed response from swtpm."),
+            InvalidState => write!(f, "Invalid TPM backend state."),
+            StateFile(err) => write!(f, "Cannot access the TPM state file: {}", err),
+            Transfer(err) => write!(f, "Cannot exchange a command with swtpm: {}", err),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The TPM implementation executing the commands sent by the guest.
+pub trait TpmBackend: Send {
+    /// Executes a marshalled TPM 2.0 command and returns the marshalled response.
+    fn execute(&mut self, command: &[u8]) -> Result<Vec<u8>>;
+    /// Returns the volatile state of the TPM, to be saved in a snapshot.
+    fn save_state(&self) -> Vec<u8>;
+    /// Restores the volatile state returned by `save_state`.
+    fn restore_state(&mut self, state: &[u8]) -> Result<()>;
+}
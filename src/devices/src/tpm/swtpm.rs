@@ -0,0 +1,110 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use super::{Error, Result, TpmBackend, TPM_HEADER_SIZE};
+
+// The largest response accepted from swtpm.
+const MAX_RESPONSE_SIZE: usize = 4096;
+
+/// A TPM backed by an external swtpm process, which exchanges the raw TPM 2.0 commands and
+/// responses over the data channel of its unix domain socket server.
+/// swtpm keeps the TPM state, so a snapshot only holds the state of the CRB interface.
+pub struct SwTpm {
+    stream: UnixStream,
+}
+
+impl SwTpm {
+    /// Connects to the swtpm data channel listening at `socket_path`.
+    pub fn connect<P: AsRef<Path>>(socket_path: P) -> Result<Self> {
+        Ok(SwTpm {
+            stream: UnixStream::connect(socket_path).map_err(Error::Connect)?,
+        })
+    }
+}
+
+impl TpmBackend for SwTpm {
+    fn execute(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+        self.stream.write_all(command).map_err(Error::Transfer)?;
+
+        let mut response = vec![0; TPM_HEADER_SIZE];
+        self.stream
+            .read_exact(&mut response)
+            .map_err(Error::Transfer)?;
+        let size =
+            u32::from_be_bytes([response[2], response[3], response[4], response[5]]) as usize;
+        if size < TPM_HEADER_SIZE || size > MAX_RESPONSE_SIZE {
+            return Err(Error::InvalidResponse);
+        }
+        response.resize(size, 0);
+        self.stream
+            .read_exact(&mut response[TPM_HEADER_SIZE..])
+            .map_err(Error::Transfer)?;
+        Ok(response)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore_state(&mut self, _state: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use utils::tempfile::TempFile;
+
+    #[test]
+    fn test_swtpm() {
+        let socket_path = TempFile::new().unwrap().as_path().to_path_buf();
+        assert!(SwTpm::connect(&socket_path).is_err());
+
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0u8; 12];
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(command, [0x80, 0x01, 0, 0, 0, 12, 0, 0, 0x01, 0x43, 0, 1]);
+            // The response is written in several steps.
+            stream.write_all(&[0x80, 0x01, 0, 0, 0]).unwrap();
+            stream.write_all(&[11, 0, 0, 0, 0, 0xaa]).unwrap();
+            // An oversized response.
+            stream.read_exact(&mut command).unwrap();
+            stream
+                .write_all(&[0x80, 0x01, 0, 0, 0x20, 0, 0, 0, 0, 0])
+                .unwrap();
+        });
+
+        let mut swtpm = SwTpm::connect(&socket_path).unwrap();
+        let command = [0x80, 0x01, 0, 0, 0, 12, 0, 0, 0x01, 0x43, 0, 1];
+        assert_eq!(
+            swtpm.execute(&command).unwrap(),
+            vec![0x80, 0x01, 0, 0, 0, 11, 0, 0, 0, 0, 0xaa]
+        );
+        match swtpm.execute(&command) {
+            Err(Error::InvalidResponse) => (),
+            _ => panic!("Unexpected result."),
+        }
+        server.join().unwrap();
+
+        // swtpm went away.
+        match swtpm.execute(&command) {
+            Err(Error::Transfer(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+
+        assert!(swtpm.save_state().is_empty());
+        assert!(swtpm.restore_state(&[]).is_ok());
+        std::fs::remove_file(socket_path).unwrap();
+    }
+}
@@ -0,0 +1,556 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Passes host PCI functions through to the guest with VFIO. The function is bound to the
+//! `vfio-pci` driver on the host, its IOMMU group is attached to a container mapping the whole
+//! guest memory for DMA, and its regions and interrupts are reached through the VFIO device.
+
+mod pci;
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::sync::Arc;
+
+use utils::eventfd::EventFd;
+use utils::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref, ioctl_with_val};
+use utils::{ioctl_expr, ioctl_io_nr, ioctl_ioc_nr};
+use vm_memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+pub use self::pci::{VfioMapping, VfioPciDevice};
+
+// See include/uapi/linux/vfio.h in the kernel code.
+const VFIO_TYPE: u32 = 0x3b;
+const VFIO_API_VERSION: i32 = 0;
+const VFIO_TYPE1V2_IOMMU: libc::c_ulong = 3;
+const VFIO_GROUP_FLAGS_VIABLE: u32 = 1 << 0;
+const VFIO_DEVICE_FLAGS_RESET: u32 = 1 << 0;
+const VFIO_DEVICE_FLAGS_PCI: u32 = 1 << 1;
+const VFIO_REGION_INFO_FLAG_MMAP: u32 = 1 << 2;
+const VFIO_IRQ_SET_DATA_NONE: u32 = 1 << 0;
+const VFIO_IRQ_SET_DATA_EVENTFD: u32 = 1 << 2;
+const VFIO_IRQ_SET_ACTION_TRIGGER: u32 = 1 << 5;
+const VFIO_DMA_MAP_FLAG_READ: u32 = 1 << 0;
+const VFIO_DMA_MAP_FLAG_WRITE: u32 = 1 << 1;
+
+ioctl_io_nr!(VFIO_GET_API_VERSION, VFIO_TYPE, 100);
+ioctl_io_nr!(VFIO_CHECK_EXTENSION, VFIO_TYPE, 101);
+ioctl_io_nr!(VFIO_SET_IOMMU, VFIO_TYPE, 102);
+ioctl_io_nr!(VFIO_GROUP_GET_STATUS, VFIO_TYPE, 103);
+ioctl_io_nr!(VFIO_GROUP_SET_CONTAINER, VFIO_TYPE, 104);
+ioctl_io_nr!(VFIO_GROUP_GET_DEVICE_FD, VFIO_TYPE, 106);
+ioctl_io_nr!(VFIO_DEVICE_GET_INFO, VFIO_TYPE, 107);
+ioctl_io_nr!(VFIO_DEVICE_GET_REGION_INFO, VFIO_TYPE, 108);
+ioctl_io_nr!(VFIO_DEVICE_GET_IRQ_INFO, VFIO_TYPE, 109);
+ioctl_io_nr!(VFIO_DEVICE_SET_IRQS, VFIO_TYPE, 110);
+ioctl_io_nr!(VFIO_DEVICE_RESET, VFIO_TYPE, 111);
+ioctl_io_nr!(VFIO_IOMMU_MAP_DMA, VFIO_TYPE, 113);
+
+#[repr(C)]
+#[derive(Default)]
+struct vfio_group_status {
+    argsz: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct vfio_device_info {
+    argsz: u32,
+    flags: u32,
+    num_regions: u32,
+    num_irqs: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct vfio_region_info {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    cap_offset: u32,
+    size: u64,
+    offset: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct vfio_irq_info {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    count: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct vfio_iommu_type1_dma_map {
+    argsz: u32,
+    flags: u32,
+    vaddr: u64,
+    iova: u64,
+    size: u64,
+}
+
+/// Errors triggered while binding and driving a VFIO device.
+#[derive(Debug)]
+pub enum Error {
+    /// The PCI function is not bound to the vfio-pci driver.
+    DeviceFd(io::Error),
+    /// Failed to query the regions or the interrupts of the function.
+    DeviceInfo(io::Error),
+    /// Failed to create the eventfds signaling the interrupts of the function.
+    EventFd(io::Error),
+    /// The IOMMU group of the function cannot be found.
+    IommuGroup(io::Error),
+    /// Failed to map the guest memory in the IOMMU.
+    MapDma(io::Error),
+    /// The function has no MSI-X capability, which is the only way it can interrupt the guest.
+    NoMsix,
+    /// The VFIO device is not a PCI function.
+    NotPci,
+    /// Failed to open the VFIO container or a VFIO group.
+    Open(io::Error),
+    /// Failed to place the function on the PCI root bus.
+    Pci(crate::pci::Error),
+    /// Failed to access a region of the function.
+    Region(io::Error),
+    /// Failed to attach a VFIO group to the container, or to set the IOMMU of the container.
+    SetContainer(io::Error),
+    /// Failed to route the interrupts of the function.
+    SetIrqs(io::Error),
+    /// The BARs of the function don't fit in the PCI memory window.
+    TooLarge,
+    /// Some devices of the IOMMU group are not bound to the vfio-pci driver.
+    UnviableGroup(u32),
+    /// The host kernel doesn't support the VFIO type 1 v2 IOMMU.
+    Unsupported,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            DeviceFd(err) => write!(f, "Cannot get the VFIO device: {}", err),
+            DeviceInfo(err) => write!(f, "Cannot query the VFIO device: {}", err),
+            EventFd(err) => write!(f, "Cannot create the interrupt eventfds: {}", err),
+            IommuGroup(err) => write!(f, "Cannot find the IOMMU group of the device: {}", err),
+            MapDma(err) => write!(f, "Cannot map the guest memory for DMA: {}", err),
+            NoMsix => write!(f, "The device has no MSI-X capability."),
+            NotPci => write!(f, "The VFIO device is not a PCI function."),
+            Open(err) => write!(f, "Cannot open the VFIO container or group: {}", err),
+            Pci(err) => write!(f, "Cannot place the device on the PCI bus: {}", err),
+            Region(err) => write!(f, "Cannot access a region of the device: {}", err),
+            SetContainer(err) => write!(f, "Cannot set up the VFIO container: {}", err),
+            SetIrqs(err) => write!(f, "Cannot route the interrupts of the device: {}", err),
+            TooLarge => write!(
+                f,
+                "The BARs of the device don't fit in the PCI memory window."
+            ),
+            UnviableGroup(group) => write!(
+                f,
+                "Some devices of IOMMU group {} are not bound to vfio-pci.",
+                group
+            ),
+            Unsupported => write!(f, "The host doesn't support the VFIO type 1 v2 IOMMU."),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A VFIO container: the IOMMU context shared by all the passed-through functions of the
+/// microVM, in which the whole guest memory is mapped at its guest physical addresses.
+pub struct VfioContainer {
+    container: File,
+    // The open groups, with their IDs.
+    groups: Vec<(u32, Arc<File>)>,
+    guest_memory: GuestMemoryMmap,
+}
+
+impl VfioContainer {
+    /// Opens a container which maps `guest_memory` once the first group joins it.
+    pub fn new(guest_memory: GuestMemoryMmap) -> Result<Self> {
+        let container = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/vfio/vfio")
+            .map_err(Error::Open)?;
+        // Safe because the file is a VFIO container and the ioctls take no pointer.
+        let version = unsafe { ioctl(&container, VFIO_GET_API_VERSION()) };
+        let type1v2 =
+            unsafe { ioctl_with_val(&container, VFIO_CHECK_EXTENSION(), VFIO_TYPE1V2_IOMMU) };
+        if version != VFIO_API_VERSION || type1v2 <= 0 {
+            return Err(Error::Unsupported);
+        }
+        Ok(VfioContainer {
+            container,
+            groups: Vec::new(),
+            guest_memory,
+        })
+    }
+
+    /// Returns the open VFIO groups, to be registered with the KVM VFIO device.
+    pub fn groups(&self) -> impl Iterator<Item = &File> {
+        self.groups.iter().map(|(_, group)| group.as_ref())
+    }
+
+    /// Opens the PCI function at `sysfs_path`, such as `/sys/bus/pci/devices/0000:01:00.0`,
+    /// adding its IOMMU group to the container.
+    pub fn open_device(&mut self, sysfs_path: &Path) -> Result<VfioDevice> {
+        let group = self.group(sysfs_path)?;
+        let name = sysfs_path
+            .file_name()
+            .and_then(|name| CString::new(name.to_string_lossy().as_bytes()).ok())
+            .ok_or_else(|| Error::DeviceFd(io::Error::from_raw_os_error(libc::EINVAL)))?;
+        // Safe because the file is a VFIO group, the name is a C string, and we check the result.
+        let fd =
+            unsafe { ioctl_with_ptr(group.as_ref(), VFIO_GROUP_GET_DEVICE_FD(), name.as_ptr()) };
+        if fd < 0 {
+            return Err(Error::DeviceFd(io::Error::last_os_error()));
+        }
+        // Safe because the descriptor is valid and nothing else owns it.
+        let file = unsafe { File::from_raw_fd(fd as RawFd) };
+        VfioDevice::new(file, group)
+    }
+
+    // Returns the group of the function at `sysfs_path`, opening it and adding it to the
+    // container first if needed.
+    fn group(&mut self, sysfs_path: &Path) -> Result<Arc<File>> {
+        let link = fs::read_link(sysfs_path.join("iommu_group")).map_err(Error::IommuGroup)?;
+        let id = link
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<u32>().ok())
+            .ok_or_else(|| Error::IommuGroup(io::Error::from_raw_os_error(libc::EINVAL)))?;
+        if let Some((_, group)) = self.groups.iter().find(|(group_id, _)| *group_id == id) {
+            return Ok(group.clone());
+        }
+
+        let group = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("/dev/vfio/{}", id))
+            .map_err(Error::Open)?;
+        let mut status = vfio_group_status {
+            argsz: size_of::<vfio_group_status>() as u32,
+            ..Default::default()
+        };
+        // Safe because the file is a VFIO group, the kernel only writes within the structure,
+        // and we check the result.
+        if unsafe { ioctl_with_mut_ref(&group, VFIO_GROUP_GET_STATUS(), &mut status) } < 0 {
+            return Err(Error::Open(io::Error::last_os_error()));
+        }
+        if status.flags & VFIO_GROUP_FLAGS_VIABLE == 0 {
+            return Err(Error::UnviableGroup(id));
+        }
+        let container_fd = self.container.as_raw_fd();
+        // Safe because both files are valid and we check the result.
+        if unsafe { ioctl_with_ref(&group, VFIO_GROUP_SET_CONTAINER(), &container_fd) } < 0 {
+            return Err(Error::SetContainer(io::Error::last_os_error()));
+        }
+        // The IOMMU can only be set once the container has a group.
+        if self.groups.is_empty() {
+            // Safe because the file is a VFIO container, and we check the result.
+            let ret =
+                unsafe { ioctl_with_val(&self.container, VFIO_SET_IOMMU(), VFIO_TYPE1V2_IOMMU) };
+            if ret < 0 {
+                return Err(Error::SetContainer(io::Error::last_os_error()));
+            }
+            self.map_guest_memory()?;
+        }
+
+        let group = Arc::new(group);
+        self.groups.push((id, group.clone()));
+        Ok(group)
+    }
+
+    // Maps the guest memory in the IOMMU, which pins it, so that the functions can DMA into it
+    // with the guest physical addresses.
+    fn map_guest_memory(&self) -> Result<()> {
+        self.guest_memory.with_regions(|_, region| {
+            let dma_map = vfio_iommu_type1_dma_map {
+                argsz: size_of::<vfio_iommu_type1_dma_map>() as u32,
+                flags: VFIO_DMA_MAP_FLAG_READ | VFIO_DMA_MAP_FLAG_WRITE,
+                // It's safe to unwrap because the guest address is valid.
+                vaddr: self
+                    .guest_memory
+                    .get_host_address(region.start_addr())
+                    .unwrap() as u64,
+                iova: region.start_addr().raw_value(),
+                size: region.len() as u64,
+            };
+            // Safe because the file is a VFIO container, the mapping outlives the container,
+            // and we check the result.
+            if unsafe { ioctl_with_ref(&self.container, VFIO_IOMMU_MAP_DMA(), &dma_map) } < 0 {
+                return Err(Error::MapDma(io::Error::last_os_error()));
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A region of a VFIO device, such as a BAR or the configuration space.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VfioRegion {
+    /// Whether the region can be mapped in the VMM.
+    pub mappable: bool,
+    /// The size of the region, 0 if the function doesn't implement it.
+    pub size: u64,
+    // The offset of the region in the device file.
+    offset: u64,
+}
+
+/// A memory mapping of a part of a VFIO region, unmapped when dropped.
+pub struct VfioMmap {
+    addr: *mut libc::c_void,
+    size: usize,
+}
+
+// Safe because the mapping is only accessed by the guest, through its KVM memory slot, and is
+// only unmapped when dropped.
+unsafe impl Send for VfioMmap {}
+
+impl VfioMmap {
+    /// The address of the mapping in the VMM.
+    pub fn host_addr(&self) -> u64 {
+        self.addr as u64
+    }
+
+    /// The size of the mapping.
+    pub fn size(&self) -> u64 {
+        self.size as u64
+    }
+}
+
+impl Drop for VfioMmap {
+    fn drop(&mut self) {
+        // Safe because we own the mapping.
+        unsafe { libc::munmap(self.addr, self.size) };
+    }
+}
+
+/// A function bound to the vfio-pci driver, opened through its VFIO group.
+pub struct VfioDevice {
+    file: File,
+    regions: Vec<VfioRegion>,
+    num_irqs: u32,
+    // The device must not outlive its group.
+    _group: Arc<File>,
+}
+
+impl VfioDevice {
+    fn new(file: File, group: Arc<File>) -> Result<Self> {
+        let mut info = vfio_device_info {
+            argsz: size_of::<vfio_device_info>() as u32,
+            ..Default::default()
+        };
+        // Safe because the file is a VFIO device, the kernel only writes within the structure,
+        // and we check the result.
+        if unsafe { ioctl_with_mut_ref(&file, VFIO_DEVICE_GET_INFO(), &mut info) } < 0 {
+            return Err(Error::DeviceInfo(io::Error::last_os_error()));
+        }
+        if info.flags & VFIO_DEVICE_FLAGS_PCI == 0 {
+            return Err(Error::NotPci);
+        }
+
+        let mut regions = Vec::with_capacity(info.num_regions as usize);
+        for index in 0..info.num_regions {
+            let mut region = vfio_region_info {
+                argsz: size_of::<vfio_region_info>() as u32,
+                index,
+                ..Default::default()
+            };
+            // Safe because the file is a VFIO device, the kernel only writes within the
+            // structure, and we check the result. Missing regions have a size of 0.
+            if unsafe { ioctl_with_mut_ref(&file, VFIO_DEVICE_GET_REGION_INFO(), &mut region) } < 0
+            {
+                region.size = 0;
+            }
+            regions.push(VfioRegion {
+                mappable: region.flags & VFIO_REGION_INFO_FLAG_MMAP != 0,
+                size: region.size,
+                offset: region.offset,
+            });
+        }
+
+        // The function starts from a clean state, whatever its previous user left.
+        // Safe because the file is a VFIO device and the ioctl takes no argument.
+        if info.flags & VFIO_DEVICE_FLAGS_RESET != 0
+            && unsafe { ioctl(&file, VFIO_DEVICE_RESET()) } < 0
+        {
+            logger::warn!(
+                "Failed to reset the VFIO device: {}",
+                io::Error::last_os_error()
+            );
+        }
+
+        Ok(VfioDevice {
+            file,
+            regions,
+            num_irqs: info.num_irqs,
+            _group: group,
+        })
+    }
+
+    /// Returns the region at `index`, whose size is 0 if the function doesn't implement it.
+    pub fn region(&self, index: u32) -> VfioRegion {
+        self.regions
+            .get(index as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Reads `data` at `offset` bytes into the region at `index`.
+    pub fn read_region(&self, index: u32, offset: u64, data: &mut [u8]) -> Result<()> {
+        let region = self.region(index);
+        if offset + data.len() as u64 > region.size {
+            return Err(Error::Region(io::Error::from_raw_os_error(libc::EINVAL)));
+        }
+        self.file
+            .read_exact_at(data, region.offset + offset)
+            .map_err(Error::Region)
+    }
+
+    /// Writes `data` at `offset` bytes into the region at `index`.
+    pub fn write_region(&self, index: u32, offset: u64, data: &[u8]) -> Result<()> {
+        let region = self.region(index);
+        if offset + data.len() as u64 > region.size {
+            return Err(Error::Region(io::Error::from_raw_os_error(libc::EINVAL)));
+        }
+        self.file
+            .write_all_at(data, region.offset + offset)
+            .map_err(Error::Region)
+    }
+
+    /// Maps `size` bytes at `offset` into the region at `index`, which must be mappable.
+    pub fn mmap_region(&self, index: u32, offset: u64, size: u64) -> Result<VfioMmap> {
+        let region = self.region(index);
+        if !region.mappable || offset + size > region.size {
+            return Err(Error::Region(io::Error::from_raw_os_error(libc::EINVAL)));
+        }
+        // Safe because we check the result, and the mapping is owned by the returned structure.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.file.as_raw_fd(),
+                (region.offset + offset) as libc::off_t,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(Error::Region(io::Error::last_os_error()));
+        }
+        Ok(VfioMmap {
+            addr,
+            size: size as usize,
+        })
+    }
+
+    /// Returns the number of interrupts of the kind `index`, 0 if the function has none.
+    pub fn irq_count(&self, index: u32) -> u32 {
+        if index >= self.num_irqs {
+            return 0;
+        }
+        let mut info = vfio_irq_info {
+            argsz: size_of::<vfio_irq_info>() as u32,
+            index,
+            ..Default::default()
+        };
+        // Safe because the file is a VFIO device, the kernel only writes within the structure,
+        // and we check the result.
+        if unsafe { ioctl_with_mut_ref(&self.file, VFIO_DEVICE_GET_IRQ_INFO(), &mut info) } < 0 {
+            return 0;
+        }
+        info.count
+    }
+
+    /// Signals `eventfds` when the interrupts of the kind `index` fire, from the first one on.
+    pub fn enable_irqs(&self, index: u32, eventfds: &[EventFd]) -> Result<()> {
+        let fds: Vec<u32> = eventfds.iter().map(|evt| evt.as_raw_fd() as u32).collect();
+        self.set_irqs(index, VFIO_IRQ_SET_DATA_EVENTFD, &fds)
+    }
+
+    /// Disables the interrupts of the kind `index`.
+    pub fn disable_irqs(&self, index: u32) -> Result<()> {
+        self.set_irqs(index, VFIO_IRQ_SET_DATA_NONE, &[])
+    }
+
+    fn set_irqs(&self, index: u32, data_flag: u32, data: &[u32]) -> Result<()> {
+        // The `vfio_irq_set` header is followed by the eventfds.
+        let mut irq_set = vec![
+            (5 + data.len()) as u32 * 4,
+            data_flag | VFIO_IRQ_SET_ACTION_TRIGGER,
+            index,
+            0,
+            data.len() as u32,
+        ];
+        irq_set.extend_from_slice(data);
+        // Safe because the file is a VFIO device, the kernel only reads `argsz` bytes, and we
+        // check the result.
+        if unsafe { ioctl_with_ptr(&self.file, VFIO_DEVICE_SET_IRQS(), irq_set.as_ptr()) } < 0 {
+            return Err(Error::SetIrqs(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ioctl_numbers() {
+        assert_eq!(VFIO_GET_API_VERSION(), 0x3b64);
+        assert_eq!(VFIO_GROUP_GET_DEVICE_FD(), 0x3b6a);
+        assert_eq!(VFIO_DEVICE_SET_IRQS(), 0x3b6e);
+        assert_eq!(VFIO_IOMMU_MAP_DMA(), 0x3b71);
+        assert_eq!(size_of::<vfio_region_info>(), 32);
+        assert_eq!(size_of::<vfio_iommu_type1_dma_map>(), 32);
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::Error::*;
+        let io_err = || io::Error::from_raw_os_error(0);
+        for err in vec![
+            DeviceFd(io_err()),
+            DeviceInfo(io_err()),
+            EventFd(io_err()),
+            IommuGroup(io_err()),
+            MapDma(io_err()),
+            NoMsix,
+            NotPci,
+            Open(io_err()),
+            Pci(crate::pci::Error::BarsFull),
+            Region(io_err()),
+            SetContainer(io_err()),
+            SetIrqs(io_err()),
+            TooLarge,
+            UnviableGroup(3),
+            Unsupported,
+        ] {
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+
+    #[test]
+    fn test_missing_iommu_group() {
+        // Opening the container fails on hosts without VFIO, in which case there is nothing
+        // more to check.
+        let guest_memory = crate::virtio::test_utils::default_mem();
+        if let Ok(mut container) = VfioContainer::new(guest_memory) {
+            match container.open_device(Path::new("/nonexistent/0000:00:00.0")) {
+                Err(Error::IommuGroup(_)) => (),
+                _ => panic!("Unexpected result."),
+            }
+        }
+    }
+}
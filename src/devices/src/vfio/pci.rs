@@ -0,0 +1,680 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+
+use logger::{error, warn};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::byte_order;
+use utils::epoll::{EpollEvent, EventSet};
+use utils::eventfd::EventFd;
+
+use super::{Error, Result, VfioDevice, VfioMmap};
+use crate::bus::BusDevice;
+use crate::pci::{
+    Error as PciError, MsiSender, MsixConfig, PciConfiguration, PciDevice, MSIX_CAP_ID,
+    MSIX_TABLE_ENTRY_SIZE,
+};
+
+// See include/uapi/linux/vfio.h in the kernel code.
+const VFIO_PCI_CONFIG_REGION_INDEX: u32 = 7;
+const VFIO_PCI_MSIX_IRQ_INDEX: u32 = 2;
+
+// The type 0 configuration header.
+const PCI_CONFIG_SPACE_SIZE: usize = 0x100;
+const PCI_COMMAND: usize = 0x04;
+const PCI_STATUS: usize = 0x06;
+const PCI_CLASS_REVISION: usize = 0x08;
+const PCI_BAR0: usize = 0x10;
+const PCI_SUBSYSTEM_VENDOR_ID: usize = 0x2c;
+const PCI_SUBSYSTEM_ID: usize = 0x2e;
+const PCI_CAPABILITY_LIST: usize = 0x34;
+const FIRST_CAPABILITY_OFFSET: usize = 0x40;
+const NUM_BARS: usize = 6;
+
+const COMMAND_REG: usize = PCI_COMMAND / 4;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+
+const BAR_IO: u32 = 0b1;
+const BAR_MEM_TYPE_MASK: u32 = 0b110;
+const BAR_MEM_TYPE_64: u32 = 0b100;
+const BAR_FLAGS_MASK: u32 = 0b1111;
+
+// The PCI Express capability, up to the slot registers in version 1 and up to the second slot
+// registers in version 2.
+const PCI_CAP_ID_EXP: u8 = 0x10;
+const PCI_EXP_CAP_SIZE_V1: usize = 0x24;
+const PCI_EXP_CAP_SIZE_V2: usize = 0x3c;
+const PCI_EXP_FLAGS_VERSION: u8 = 0x0f;
+const PCI_EXP_TYPE_RC_END: u8 = 0x9;
+// The slot implemented bit, in the high byte of the capabilities register.
+const PCI_EXP_FLAGS_SLOT: u8 = 0x01;
+const PCI_EXP_DEVCAP: usize = 4;
+const PCI_EXP_DEVCAP_FLR: u32 = 1 << 28;
+
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_TABLE_SIZE_MASK: u16 = 0x7ff;
+const MSIX_BIR_MASK: u32 = 0x7;
+// The guest may only enable and mask MSI-X, in the message control register.
+const MSIX_CAP_WRITABLE_BITS: u32 = 0xc000_0000;
+
+// The granularity of the BAR mappings.
+const PAGE_SIZE: u64 = 0x1000;
+
+fn align_down(value: u64, align: u64) -> u64 {
+    value & !(align - 1)
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    align_down(value + align - 1, align)
+}
+
+/// A part of a BAR which the guest accesses directly, without exiting to the VMM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VfioMapping {
+    /// The guest physical address of the mapping.
+    pub guest_addr: u64,
+    /// The size of the mapping.
+    pub size: u64,
+    /// The address of the mapping in the VMM.
+    pub host_addr: u64,
+}
+
+// A memory BAR of the function, as placed in the guest. Its VFIO region has the same index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bar {
+    index: usize,
+    addr: u64,
+    size: u64,
+    // The type and prefetchable bits of the BAR register.
+    flags: u32,
+}
+
+// Where the MSI-X table, or the pending bit array, lies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct MsixRegion {
+    bar: usize,
+    offset: u64,
+    size: u64,
+}
+
+impl MsixRegion {
+    // Returns the offset into the region of `bar_offset` in `bar`, if it is inside.
+    fn offset_of(&self, bar: usize, bar_offset: u64) -> Option<u64> {
+        if bar == self.bar && bar_offset >= self.offset && bar_offset < self.offset + self.size {
+            Some(bar_offset - self.offset)
+        } else {
+            None
+        }
+    }
+
+    // The pages of the BAR holding the region, which stay trapped.
+    fn pages(&self) -> Range<u64> {
+        align_down(self.offset, PAGE_SIZE)..align_up(self.offset + self.size, PAGE_SIZE)
+    }
+}
+
+/// Exposes a host PCI function bound to vfio-pci on the PCI root bus.
+///
+/// The guest sees the IDs and the PCI Express capability of the function, and an MSI-X
+/// capability emulated on top of the one of the function. The memory BARs keep their indexes,
+/// but are placed by the VMM, one after the other. Their pages are mapped in the guest, except
+/// for the ones holding the MSI-X table and the pending bit array, whose accesses come to this
+/// device along with the accesses to the BARs which can't be mapped. Besides routing these
+/// accesses, the VMM has to:
+///
+/// 1. register `mappings` as KVM memory slots;
+/// 1. subscribe this device to its event manager, so that the interrupts of the function are
+/// turned into the MSI-X messages programmed by the guest.
+pub struct VfioPciDevice {
+    device: VfioDevice,
+    config: PciConfiguration,
+    msix: MsixConfig,
+    msix_cap_reg: usize,
+    msix_table: MsixRegion,
+    msix_pba: MsixRegion,
+    msix_enabled: bool,
+    // Signaled by VFIO when the vector of the same index fires.
+    irq_evts: Vec<EventFd>,
+    bars: Vec<Bar>,
+    mmaps: Vec<(u64, VfioMmap)>,
+    slot: u8,
+    bars_range: Range<u64>,
+}
+
+impl VfioPciDevice {
+    /// Plugs the function of `device` in `slot`, with its BARs laid out from the start of
+    /// `window`, each one aligned on its size. The interrupts of the function are delivered
+    /// through `msi_sender`.
+    pub fn new(
+        device: VfioDevice,
+        slot: u8,
+        window: Range<u64>,
+        msi_sender: Arc<dyn MsiSender>,
+    ) -> Result<Self> {
+        let mut header = [0u8; PCI_CONFIG_SPACE_SIZE];
+        device.read_region(VFIO_PCI_CONFIG_REGION_INDEX, 0, &mut header)?;
+        let read_u16 = |offset: usize| byte_order::read_le_u16(&header[offset..]);
+
+        let mut config = PciConfiguration::new(
+            read_u16(0),
+            read_u16(2),
+            header[PCI_CLASS_REVISION + 3],
+            header[PCI_CLASS_REVISION + 2],
+            header[PCI_CLASS_REVISION + 1],
+            header[PCI_CLASS_REVISION],
+            read_u16(PCI_SUBSYSTEM_VENDOR_ID),
+            read_u16(PCI_SUBSYSTEM_ID),
+        );
+
+        let bars = layout_bars(
+            &memory_bars(&header, |index| device.region(index).size),
+            &window,
+        )?;
+        for bar in bars.iter() {
+            config
+                .add_memory_bar_at(bar.index, bar.addr, bar.size, bar.flags)
+                .map_err(Error::Pci)?;
+        }
+
+        let capabilities = capabilities(&header);
+        let find_capability = |id| {
+            capabilities
+                .iter()
+                .find(|(cap_id, _)| *cap_id == id)
+                .map(|(_, offset)| *offset)
+        };
+        if let Some(offset) = find_capability(PCI_CAP_ID_EXP) {
+            config
+                .add_capability(&express_capability(&header, offset), 0)
+                .map_err(Error::Pci)?;
+        }
+
+        let msix_offset = find_capability(MSIX_CAP_ID).ok_or(Error::NoMsix)?;
+        let table_size = (read_u16(msix_offset + 2) & MSIX_TABLE_SIZE_MASK) + 1;
+        let num_vectors = device
+            .irq_count(VFIO_PCI_MSIX_IRQ_INDEX)
+            .min(u32::from(table_size)) as u16;
+        if num_vectors == 0 {
+            return Err(Error::NoMsix);
+        }
+        let table = byte_order::read_le_u32(&header[msix_offset + 4..]);
+        let pba = byte_order::read_le_u32(&header[msix_offset + 8..]);
+        let msix_table = MsixRegion {
+            bar: (table & MSIX_BIR_MASK) as usize,
+            offset: u64::from(table & !MSIX_BIR_MASK),
+            size: u64::from(table_size) * MSIX_TABLE_ENTRY_SIZE,
+        };
+        let msix_pba = MsixRegion {
+            bar: (pba & MSIX_BIR_MASK) as usize,
+            offset: u64::from(pba & !MSIX_BIR_MASK),
+            size: (u64::from(table_size) + 63) / 64 * 8,
+        };
+        let mut msix_cap = vec![MSIX_CAP_ID, 0];
+        msix_cap.extend_from_slice(&(num_vectors - 1).to_le_bytes());
+        msix_cap.extend_from_slice(&table.to_le_bytes());
+        msix_cap.extend_from_slice(&pba.to_le_bytes());
+        let msix_cap_offset = config
+            .add_capability(&msix_cap, MSIX_CAP_WRITABLE_BITS)
+            .map_err(Error::Pci)?;
+
+        let irq_evts = (0..num_vectors)
+            .map(|_| EventFd::new(libc::EFD_NONBLOCK))
+            .collect::<std::io::Result<Vec<_>>>()
+            .map_err(Error::EventFd)?;
+
+        let mut mmaps = Vec::new();
+        for bar in bars.iter() {
+            if !device.region(bar.index as u32).mappable {
+                continue;
+            }
+            let trapped: Vec<Range<u64>> = [msix_table, msix_pba]
+                .iter()
+                .filter(|region| region.bar == bar.index)
+                .map(MsixRegion::pages)
+                .collect();
+            for range in mappable_ranges(bar.size, &trapped) {
+                match device.mmap_region(bar.index as u32, range.start, range.end - range.start) {
+                    Ok(mmap) => mmaps.push((bar.addr + range.start, mmap)),
+                    Err(e) => warn!(
+                        "Failed to map BAR {} of the VFIO device, its accesses are trapped: {}",
+                        bar.index, e
+                    ),
+                }
+            }
+        }
+
+        let bars_range = match (bars.first(), bars.last()) {
+            (Some(first), Some(last)) => first.addr..last.addr + last.size,
+            _ => window.start..window.start,
+        };
+        let mut vfio_pci_device = VfioPciDevice {
+            device,
+            config,
+            msix: MsixConfig::new(num_vectors, msi_sender),
+            msix_cap_reg: msix_cap_offset / 4,
+            msix_table,
+            msix_pba,
+            msix_enabled: false,
+            irq_evts,
+            bars,
+            mmaps,
+            slot,
+            bars_range,
+        };
+        vfio_pci_device.update_command();
+        Ok(vfio_pci_device)
+    }
+
+    /// The slot of the function on the root bus.
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    /// The guest physical addresses spanned by the BARs, whose accesses outside of `mappings`
+    /// come to this device.
+    pub fn bars_range(&self) -> Range<u64> {
+        self.bars_range.clone()
+    }
+
+    /// The parts of the BARs which the guest accesses directly, to be registered as KVM memory
+    /// slots.
+    pub fn mappings(&self) -> Vec<VfioMapping> {
+        self.mmaps
+            .iter()
+            .map(|(guest_addr, mmap)| VfioMapping {
+                guest_addr: *guest_addr,
+                size: mmap.size(),
+                host_addr: mmap.host_addr(),
+            })
+            .collect()
+    }
+
+    // Returns the BAR holding the guest physical address `addr`, and the offset into it.
+    fn bar_at(&self, addr: u64) -> Option<(usize, u64)> {
+        self.bars
+            .iter()
+            .find(|bar| addr >= bar.addr && addr < bar.addr + bar.size)
+            .map(|bar| (bar.index, addr - bar.addr))
+    }
+
+    // The guest only controls bus mastering. Memory decoding stays enabled, so that the
+    // mappings of the BARs remain valid, and the legacy interrupt stays disabled.
+    fn update_command(&mut self) {
+        let guest_command = self.config.read_reg(COMMAND_REG) as u16;
+        let command = COMMAND_MEMORY | COMMAND_INTX_DISABLE | (guest_command & COMMAND_BUS_MASTER);
+        if let Err(e) = self.device.write_region(
+            VFIO_PCI_CONFIG_REGION_INDEX,
+            PCI_COMMAND as u64,
+            &command.to_le_bytes(),
+        ) {
+            error!(
+                "Failed to update the command register of the VFIO device: {}",
+                e
+            );
+        }
+    }
+
+    // The vectors of the function are routed to the eventfds while the guest enables MSI-X.
+    fn update_msix(&mut self) {
+        let msg_ctl = (self.config.read_reg(self.msix_cap_reg) >> 16) as u16;
+        self.msix.set_message_control(msg_ctl);
+        let enabled = msg_ctl & MSIX_ENABLE != 0;
+        if enabled == self.msix_enabled {
+            return;
+        }
+        let result = if enabled {
+            self.device
+                .enable_irqs(VFIO_PCI_MSIX_IRQ_INDEX, &self.irq_evts)
+        } else {
+            self.device.disable_irqs(VFIO_PCI_MSIX_IRQ_INDEX)
+        };
+        match result {
+            Ok(()) => self.msix_enabled = enabled,
+            Err(e) => error!(
+                "Failed to route the MSI-X vectors of the VFIO device: {}",
+                e
+            ),
+        }
+    }
+}
+
+// Returns the memory BARs of the function, with the sizes of their regions. The I/O BARs are
+// left out, since the root bus has no I/O window.
+fn memory_bars<F: Fn(u32) -> u64>(header: &[u8], region_size: F) -> Vec<Bar> {
+    let mut bars = Vec::new();
+    let mut index = 0;
+    while index < NUM_BARS {
+        let reg = byte_order::read_le_u32(&header[PCI_BAR0 + 4 * index..]);
+        let size = region_size(index as u32);
+        if reg & BAR_IO != 0 {
+            if size > 0 {
+                warn!("I/O BAR {} of the VFIO device is not exposed", index);
+            }
+            index += 1;
+            continue;
+        }
+        if size > 0 {
+            bars.push(Bar {
+                index,
+                addr: 0,
+                size,
+                flags: reg & BAR_FLAGS_MASK,
+            });
+        }
+        index += if reg & BAR_MEM_TYPE_MASK == BAR_MEM_TYPE_64 {
+            2
+        } else {
+            1
+        };
+    }
+    bars
+}
+
+// Places the BARs in `window`, the largest ones first so that aligning each one on its size
+// leaves no hole, and returns them in the order of their addresses.
+fn layout_bars(bars: &[Bar], window: &Range<u64>) -> Result<Vec<Bar>> {
+    let mut bars = bars.to_vec();
+    bars.sort_by(|a, b| b.size.cmp(&a.size));
+    let mut next_addr = window.start;
+    for bar in bars.iter_mut() {
+        if bar.size < 16 || !bar.size.is_power_of_two() {
+            return Err(Error::Pci(PciError::InvalidBarSize(bar.size)));
+        }
+        bar.addr = align_up(next_addr, bar.size);
+        next_addr = bar.addr + bar.size;
+        if next_addr > window.end {
+            return Err(Error::TooLarge);
+        }
+    }
+    Ok(bars)
+}
+
+// Returns the IDs and offsets of the capabilities of the function.
+fn capabilities(header: &[u8]) -> Vec<(u8, usize)> {
+    let mut capabilities = Vec::new();
+    if byte_order::read_le_u16(&header[PCI_STATUS..]) & STATUS_CAPABILITIES_LIST == 0 {
+        return capabilities;
+    }
+    let mut offset = header[PCI_CAPABILITY_LIST] as usize & !3;
+    // A broken list could loop, while a valid one holds at most 48 capabilities.
+    while offset >= FIRST_CAPABILITY_OFFSET && offset + 4 <= header.len() && capabilities.len() < 48
+    {
+        capabilities.push((header[offset], offset));
+        offset = header[offset + 1] as usize & !3;
+    }
+    capabilities
+}
+
+// Copies the PCI Express capability at `offset`. The function is presented as an integrated
+// endpoint, since there is no root port above it, and without function level reset, which the
+// guest can't trigger on the host function.
+fn express_capability(header: &[u8], offset: usize) -> Vec<u8> {
+    let size = if header[offset + 2] & PCI_EXP_FLAGS_VERSION >= 2 {
+        PCI_EXP_CAP_SIZE_V2
+    } else {
+        PCI_EXP_CAP_SIZE_V1
+    };
+    let mut cap = header[offset..(offset + size).min(header.len())].to_vec();
+    cap[2] = (cap[2] & PCI_EXP_FLAGS_VERSION) | PCI_EXP_TYPE_RC_END << 4;
+    cap[3] &= !PCI_EXP_FLAGS_SLOT;
+    if cap.len() >= PCI_EXP_DEVCAP + 4 {
+        let devcap = byte_order::read_le_u32(&cap[PCI_EXP_DEVCAP..]) & !PCI_EXP_DEVCAP_FLR;
+        byte_order::write_le_u32(&mut cap[PCI_EXP_DEVCAP..], devcap);
+    }
+    cap
+}
+
+// Returns the whole pages of a BAR of `size` bytes which don't hold any of the `trapped`
+// ranges, and can thus be mapped in the guest.
+fn mappable_ranges(size: u64, trapped: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut trapped = trapped.to_vec();
+    trapped.sort_by_key(|range| range.start);
+    trapped.push(size..size);
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for range in trapped {
+        let end = range.start.min(size);
+        if end > start && start % PAGE_SIZE == 0 && end % PAGE_SIZE == 0 {
+            ranges.push(start..end);
+        }
+        start = start.max(range.end);
+    }
+    ranges
+}
+
+impl BusDevice for VfioPciDevice {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        let (bar, bar_offset) = match self.bar_at(self.bars_range.start + offset) {
+            Some(location) => location,
+            None => {
+                warn!("invalid vfio pci read: 0x{:x}:0x{:x}", offset, data.len());
+                return;
+            }
+        };
+        if let Some(table_offset) = self.msix_table.offset_of(bar, bar_offset) {
+            self.msix.read_table(table_offset, data);
+        } else if let Some(pba_offset) = self.msix_pba.offset_of(bar, bar_offset) {
+            self.msix.read_pba(pba_offset, data);
+        } else if let Err(e) = self.device.read_region(bar as u32, bar_offset, data) {
+            warn!("Failed to read BAR {} of the VFIO device: {}", bar, e);
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        let (bar, bar_offset) = match self.bar_at(self.bars_range.start + offset) {
+            Some(location) => location,
+            None => {
+                warn!("invalid vfio pci write: 0x{:x}:0x{:x}", offset, data.len());
+                return;
+            }
+        };
+        if let Some(table_offset) = self.msix_table.offset_of(bar, bar_offset) {
+            if table_offset + data.len() as u64
+                <= self.msix.num_vectors() as u64 * MSIX_TABLE_ENTRY_SIZE
+            {
+                self.msix.write_table(table_offset, data);
+            }
+        } else if self.msix_pba.offset_of(bar, bar_offset).is_some() {
+            // The pending bit array is read-only.
+        } else if let Err(e) = self.device.write_region(bar as u32, bar_offset, data) {
+            warn!("Failed to write BAR {} of the VFIO device: {}", bar, e);
+        }
+    }
+}
+
+impl PciDevice for VfioPciDevice {
+    fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+        self.config.read_reg(reg_idx)
+    }
+
+    fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]) {
+        self.config.write_reg(reg_idx, offset, data);
+        if reg_idx == COMMAND_REG {
+            self.update_command();
+        } else if reg_idx == self.msix_cap_reg {
+            self.update_msix();
+        }
+    }
+}
+
+impl Subscriber for VfioPciDevice {
+    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+        let source = event.fd();
+        let vector = match self
+            .irq_evts
+            .iter()
+            .position(|evt| evt.as_raw_fd() == source)
+        {
+            Some(vector) => vector,
+            None => {
+                warn!("vfio pci: Spurious event received: {:?}", source);
+                return;
+            }
+        };
+
+        if let Err(e) = self.irq_evts[vector].read() {
+            error!("Failed to read the VFIO interrupt event: {:?}", e);
+        }
+        self.msix.trigger(vector as u16);
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        self.irq_evts
+            .iter()
+            .map(|evt| EpollEvent::new(EventSet::IN, evt.as_raw_fd() as u64))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(index: usize, addr: u64, size: u64, flags: u32) -> Bar {
+        Bar {
+            index,
+            addr,
+            size,
+            flags,
+        }
+    }
+
+    #[test]
+    fn test_memory_bars() {
+        let mut header = [0u8; PCI_CONFIG_SPACE_SIZE];
+        // A 64-bit BAR 0, an I/O BAR 2, a 32-bit prefetchable BAR 3 and an unused BAR 4.
+        byte_order::write_le_u32(&mut header[PCI_BAR0..], 0xfe00_0004);
+        byte_order::write_le_u32(&mut header[PCI_BAR0 + 8..], 0xe001);
+        byte_order::write_le_u32(&mut header[PCI_BAR0 + 12..], 0xfd00_0008);
+        let sizes = [0x4000, 0, 0x100, 0x1000, 0, 0];
+        assert_eq!(
+            memory_bars(&header, |index| sizes[index as usize]),
+            vec![bar(0, 0, 0x4000, 0b100), bar(3, 0, 0x1000, 0b1000)]
+        );
+    }
+
+    #[test]
+    fn test_layout_bars() {
+        let window = 0xf820_0000..0xf840_0000;
+        let bars = [
+            bar(0, 0, 0x4000, 0b100),
+            bar(3, 0, 0x10_0000, 0b1100),
+            bar(4, 0, 0x1000, 0),
+        ];
+        assert_eq!(
+            layout_bars(&bars, &window).unwrap(),
+            vec![
+                bar(3, 0xf820_0000, 0x10_0000, 0b1100),
+                bar(0, 0xf830_0000, 0x4000, 0b100),
+                bar(4, 0xf830_4000, 0x1000, 0),
+            ]
+        );
+
+        // The largest BAR is aligned on its size.
+        let window = 0xf820_1000..0xf840_0000;
+        assert_eq!(layout_bars(&bars, &window).unwrap()[0].addr, 0xf830_0000);
+
+        let window = 0xf820_0000..0xf830_0000;
+        match layout_bars(&bars, &window) {
+            Err(Error::TooLarge) => (),
+            _ => panic!("Unexpected result."),
+        }
+        match layout_bars(&[bar(0, 0, 0x3000, 0)], &window) {
+            Err(Error::Pci(PciError::InvalidBarSize(0x3000))) => (),
+            _ => panic!("Unexpected result."),
+        }
+        assert!(layout_bars(&[], &window).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut header = [0u8; PCI_CONFIG_SPACE_SIZE];
+        header[PCI_CAPABILITY_LIST] = 0x40;
+        header[0x40] = 0x01;
+        header[0x41] = 0x70;
+        header[0x70] = PCI_CAP_ID_EXP;
+        header[0x71] = 0xb0;
+        header[0xb0] = MSIX_CAP_ID;
+        // The list is ignored unless the status register advertises it.
+        assert!(capabilities(&header).is_empty());
+
+        byte_order::write_le_u16(&mut header[PCI_STATUS..], STATUS_CAPABILITIES_LIST);
+        assert_eq!(
+            capabilities(&header),
+            vec![(0x01, 0x40), (PCI_CAP_ID_EXP, 0x70), (MSIX_CAP_ID, 0xb0)]
+        );
+
+        // A looping list is cut.
+        header[0xb1] = 0x40;
+        assert_eq!(capabilities(&header).len(), 48);
+    }
+
+    #[test]
+    fn test_express_capability() {
+        let mut header = [0u8; PCI_CONFIG_SPACE_SIZE];
+        // A version 2 endpoint with a slot and function level reset.
+        header[0x70] = PCI_CAP_ID_EXP;
+        header[0x72] = 0x02;
+        header[0x73] = PCI_EXP_FLAGS_SLOT;
+        byte_order::write_le_u32(&mut header[0x74..], PCI_EXP_DEVCAP_FLR | 0x8fc2);
+        let cap = express_capability(&header, 0x70);
+        assert_eq!(cap.len(), PCI_EXP_CAP_SIZE_V2);
+        assert_eq!(cap[2], 0x92);
+        assert_eq!(cap[3], 0);
+        assert_eq!(byte_order::read_le_u32(&cap[PCI_EXP_DEVCAP..]), 0x8fc2);
+
+        header[0x72] = 0x01;
+        assert_eq!(express_capability(&header, 0x70).len(), PCI_EXP_CAP_SIZE_V1);
+        // The capability is cut at the end of the configuration space.
+        header[0xe0] = PCI_CAP_ID_EXP;
+        header[0xe2] = 0x02;
+        assert_eq!(express_capability(&header, 0xe0).len(), 0x20);
+    }
+
+    #[test]
+    fn test_msix_region() {
+        let table = MsixRegion {
+            bar: 0,
+            offset: 0x2000,
+            size: 0x10 * MSIX_TABLE_ENTRY_SIZE,
+        };
+        assert_eq!(table.offset_of(0, 0x2010), Some(0x10));
+        assert_eq!(table.offset_of(0, 0x1ffc), None);
+        assert_eq!(table.offset_of(0, 0x2100), None);
+        assert_eq!(table.offset_of(1, 0x2010), None);
+        assert_eq!(table.pages(), 0x2000..0x3000);
+
+        let pba = MsixRegion {
+            bar: 0,
+            offset: 0x3800,
+            size: 0x10,
+        };
+        assert_eq!(pba.pages(), 0x3000..0x4000);
+    }
+
+    #[test]
+    fn test_mappable_ranges() {
+        // The whole BAR is mapped when nothing is trapped.
+        assert_eq!(mappable_ranges(0x4000, &[]), vec![0..0x4000]);
+        // BARs smaller than a page are never mapped.
+        assert!(mappable_ranges(0x100, &[]).is_empty());
+        // The pages around the trapped ones are mapped.
+        assert_eq!(
+            mappable_ranges(0x8000, &[0x3000..0x4000, 0x1000..0x2000]),
+            vec![0..0x1000, 0x2000..0x3000, 0x4000..0x8000]
+        );
+        // Overlapping and trailing trapped ranges.
+        assert_eq!(
+            mappable_ranges(0x4000, &[0x2000..0x3000, 0x2000..0x4000]),
+            vec![0..0x2000]
+        );
+        assert!(mappable_ranges(0x4000, &[0..0x4000]).is_empty());
+    }
+}
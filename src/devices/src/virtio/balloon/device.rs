@@ -11,7 +11,7 @@ use std::time::Duration;
 
 use ::timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 
-use ::logger::{error, IncMetric, METRICS};
+use ::logger::{error, BalloonStatsMetrics, IncMetric, StoreMetric, METRICS};
 use ::utils::eventfd::EventFd;
 use ::virtio_gen::virtio_blk::*;
 use ::vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
@@ -19,8 +19,10 @@ use ::vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
 use super::*;
 use super::{
     super::{
-        ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BALLOON, VIRTIO_MMIO_INT_VRING,
+        ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BALLOON, VIRTIO_MMIO_INT_CONFIG,
+        VIRTIO_MMIO_INT_VRING,
     },
+    policy::{BalloonPolicy, HostMemoryState},
     utils::{compact_page_frame_numbers, remove_range},
     BALLOON_DEV_ID,
 };
@@ -79,6 +81,7 @@ pub struct BalloonConfig {
     pub amount_mb: u32,
     pub deflate_on_oom: bool,
     pub stats_polling_interval_s: u16,
+    pub free_page_reporting: bool,
 }
 
 // BalloonStats holds statistics returned from the stats_queue.
@@ -132,6 +135,27 @@ impl BalloonStats {
 
         Ok(())
     }
+
+    // Publishes the statistics reported by the guest to the metrics of its balloon device.
+    fn store_metrics(&self, metrics: &BalloonStatsMetrics) {
+        let stats = [
+            (self.swap_in, &metrics.swap_in),
+            (self.swap_out, &metrics.swap_out),
+            (self.major_faults, &metrics.major_faults),
+            (self.minor_faults, &metrics.minor_faults),
+            (self.free_memory, &metrics.free_memory),
+            (self.total_memory, &metrics.total_memory),
+            (self.available_memory, &metrics.available_memory),
+            (self.disk_caches, &metrics.disk_caches),
+            (self.hugetlb_allocations, &metrics.hugetlb_allocations),
+            (self.hugetlb_failures, &metrics.hugetlb_failures),
+        ];
+        for (value, metric) in stats.iter() {
+            if let Some(value) = value {
+                metric.store(*value as usize);
+            }
+        }
+    }
 }
 
 // Virtio balloon device.
@@ -151,12 +175,17 @@ pub struct Balloon {
 
     // Implementation specific fields.
     pub(crate) restored: bool,
+    // The size of the host pages backing the guest memory.
+    pub(crate) backing_page_size: u64,
     pub(crate) stats_polling_interval_s: u16,
     pub(crate) stats_timer: TimerFd,
     // The index of the previous stats descriptor is saved because
     // it is acknowledged after the stats queue is processed.
     pub(crate) stats_desc_index: Option<u16>,
     pub(crate) latest_stats: BalloonStats,
+    // The policy resizing the balloon based on the memory pressure of the host.
+    pub(crate) policy: Option<BalloonPolicy>,
+    pub(crate) policy_timer: TimerFd,
     // A buffer used as pfn accumulator during descriptor processing.
     pub(crate) pfn_buffer: [u32; MAX_PAGE_COMPACT_BUFFER],
 }
@@ -166,6 +195,7 @@ impl Balloon {
         amount_mb: u32,
         deflate_on_oom: bool,
         stats_polling_interval_s: u16,
+        free_page_reporting: bool,
         restored: bool,
     ) -> Result<Balloon, BalloonError> {
         let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
@@ -178,14 +208,25 @@ impl Balloon {
             avail_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
         }
 
+        if free_page_reporting {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_REPORTING;
+        }
+
         let queue_evts = [
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
+            EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
         ];
 
         let mut queues: Vec<Queue> = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
 
+        // The same goes for the free page reporting queue, which follows the
+        // statistics queue.
+        if !free_page_reporting {
+            let _ = queues.remove(REPORTING_INDEX);
+        }
+
         // The VirtIO specification states that the statistics queue should
         // not be present at all if the statistics are not enabled.
         if stats_polling_interval_s == 0 {
@@ -194,6 +235,8 @@ impl Balloon {
 
         let stats_timer =
             TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(BalloonError::Timer)?;
+        let policy_timer =
+            TimerFd::new_custom(ClockId::Monotonic, true, true).map_err(BalloonError::Timer)?;
 
         Ok(Balloon {
             avail_features,
@@ -209,10 +252,13 @@ impl Balloon {
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(BalloonError::EventFd)?,
             restored,
+            backing_page_size: BALLOON_PAGE_SIZE,
             stats_polling_interval_s,
             stats_timer,
             stats_desc_index: None,
             latest_stats: BalloonStats::default(),
+            policy: None,
+            policy_timer,
             pfn_buffer: [0u32; MAX_PAGE_COMPACT_BUFFER],
         })
     }
@@ -238,6 +284,13 @@ impl Balloon {
         self.process_stats_queue()
     }
 
+    pub(crate) fn process_reporting_queue_event(&mut self) -> Result<(), BalloonError> {
+        self.queue_evts[self.reporting_queue_index()]
+            .read()
+            .map_err(BalloonError::EventFd)?;
+        self.process_reporting_queue()
+    }
+
     pub(crate) fn process_stats_timer_event(&mut self) -> Result<(), BalloonError> {
         let mem = mem_of_active_device!(self.device_state);
         self.stats_timer.read();
@@ -254,6 +307,34 @@ impl Balloon {
         }
     }
 
+    pub(crate) fn process_policy_timer_event(&mut self) -> Result<(), BalloonError> {
+        self.policy_timer.read();
+
+        let target_mb = match self.policy {
+            Some(ref policy) => {
+                let host = HostMemoryState::read().map_err(|e| {
+                    METRICS.balloon.policy_fails.inc();
+                    BalloonError::HostMemoryState(e)
+                })?;
+                policy.next_target_mb(self.size_mb(), &host)
+            }
+            None => return Ok(()),
+        };
+
+        if target_mb != self.size_mb() {
+            self.config_space.num_pages = mb_to_pages(target_mb)?;
+            METRICS.balloon.policy_adjustments.inc();
+            // Kick the driver to pick up the new target size.
+            self.interrupt_status
+                .fetch_or(VIRTIO_MMIO_INT_CONFIG as usize, Ordering::SeqCst);
+            self.interrupt_evt
+                .write(1)
+                .map_err(BalloonError::InterruptError)?;
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn process_inflate(&mut self) -> Result<(), BalloonError> {
         let mem = mem_of_active_device!(self.device_state);
         METRICS.balloon.inflate_count.inc();
@@ -323,12 +404,14 @@ impl Balloon {
                 let guest_addr =
                     GuestAddress((page_frame_number as u64) << VIRTIO_BALLOON_PFN_SHIFT);
 
-                if let Err(e) = remove_range(
+                match remove_range(
                     &mem,
                     (guest_addr, u64::from(range_len) << VIRTIO_BALLOON_PFN_SHIFT),
                     self.restored,
+                    self.backing_page_size,
                 ) {
-                    error!("Error removing memory range: {:?}", e);
+                    Ok(reclaimed) => METRICS.balloon.reclaimed_bytes.add(reclaimed as usize),
+                    Err(e) => error!("Error removing memory range: {:?}", e),
                 }
             }
         }
@@ -362,6 +445,48 @@ impl Balloon {
         }
     }
 
+    pub(crate) fn process_reporting_queue(&mut self) -> Result<(), BalloonError> {
+        let mem = mem_of_active_device!(self.device_state);
+        METRICS.balloon.free_page_report_count.inc();
+
+        let queue_index = self.reporting_queue_index();
+        let queue = &mut self.queues[queue_index];
+        let mut needs_interrupt = false;
+
+        while let Some(head) = queue.pop(&mem) {
+            let head_index = head.index;
+            // Each descriptor of the chain points to a range of memory that the
+            // guest has freed, and won't touch until the chain is given back.
+            let mut maybe_desc = Some(head);
+            while let Some(desc) = maybe_desc {
+                match remove_range(
+                    &mem,
+                    (desc.addr, u64::from(desc.len)),
+                    self.restored,
+                    self.backing_page_size,
+                ) {
+                    Ok(reclaimed) => METRICS
+                        .balloon
+                        .free_page_reclaimed_bytes
+                        .add(reclaimed as usize),
+                    Err(e) => error!("Error removing reported free memory range: {:?}", e),
+                }
+                maybe_desc = desc.next_descriptor();
+            }
+
+            queue
+                .add_used(&mem, head_index, 0)
+                .map_err(BalloonError::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()
+        } else {
+            Ok(())
+        }
+    }
+
     pub(crate) fn process_stats_queue(&mut self) -> std::result::Result<(), BalloonError> {
         let mem = mem_of_active_device!(self.device_state);
         METRICS.balloon.stats_updates_count.inc();
@@ -395,6 +520,8 @@ impl Balloon {
 
             self.stats_desc_index = Some(head.index);
         }
+        self.latest_stats
+            .store_metrics(&METRICS.balloon.balloons.get(self.id()));
 
         Ok(())
     }
@@ -414,6 +541,9 @@ impl Balloon {
     pub fn process_virtio_queues(&mut self) {
         let _ = self.process_inflate();
         let _ = self.process_deflate_queue();
+        if self.free_page_reporting() {
+            let _ = self.process_reporting_queue();
+        }
     }
 
     pub fn id(&self) -> &str {
@@ -452,10 +582,56 @@ impl Balloon {
             .set_state(timer_state, SetTimeFlags::Default);
     }
 
+    /// Sets the policy resizing the balloon, replacing the previous one. A policy with a
+    /// polling interval of 0 disables it.
+    pub fn set_policy(&mut self, policy: BalloonPolicy) -> Result<(), BalloonError> {
+        policy.validate()?;
+
+        self.policy = if policy.polling_interval_s > 0 {
+            Some(policy)
+        } else {
+            None
+        };
+        if self.is_activated() {
+            self.update_policy_timer_state();
+        }
+        Ok(())
+    }
+
+    pub fn policy(&self) -> Option<&BalloonPolicy> {
+        self.policy.as_ref()
+    }
+
+    pub(crate) fn update_policy_timer_state(&mut self) {
+        let timer_state = match self.policy {
+            Some(ref policy) => TimerState::Periodic {
+                current: Duration::from_secs(policy.polling_interval_s as u64),
+                interval: Duration::from_secs(policy.polling_interval_s as u64),
+            },
+            None => TimerState::Disarmed,
+        };
+        self.policy_timer
+            .set_state(timer_state, SetTimeFlags::Default);
+    }
+
+    /// Sets the size of the host pages backing the guest memory, so that inflating the
+    /// balloon discards whole huge pages where possible.
+    pub fn set_backing_page_size(&mut self, page_size: u64) {
+        self.backing_page_size = cmp::max(page_size, BALLOON_PAGE_SIZE);
+    }
+
+    pub fn backing_page_size(&self) -> u64 {
+        self.backing_page_size
+    }
+
     pub fn num_pages(&self) -> u32 {
         self.config_space.num_pages
     }
 
+    pub fn actual_pages(&self) -> u32 {
+        self.config_space.actual_pages
+    }
+
     pub fn size_mb(&self) -> u32 {
         pages_to_mb(self.config_space.num_pages)
     }
@@ -468,6 +644,10 @@ impl Balloon {
         self.stats_polling_interval_s
     }
 
+    pub fn free_page_reporting(&self) -> bool {
+        self.avail_features & (1u64 << VIRTIO_BALLOON_F_REPORTING) != 0
+    }
+
     pub fn latest_stats(&mut self) -> Option<&BalloonStats> {
         if self.stats_enabled() {
             self.latest_stats.target_pages = self.config_space.num_pages;
@@ -485,12 +665,23 @@ impl Balloon {
             amount_mb: self.size_mb(),
             deflate_on_oom: self.deflate_on_oom(),
             stats_polling_interval_s: self.stats_polling_interval_s(),
+            free_page_reporting: self.free_page_reporting(),
         }
     }
 
     pub(crate) fn stats_enabled(&self) -> bool {
         self.stats_polling_interval_s > 0
     }
+
+    // The free page reporting queue takes the place of the statistics queue
+    // when the latter is not present.
+    pub(crate) fn reporting_queue_index(&self) -> usize {
+        if self.stats_enabled() {
+            REPORTING_INDEX
+        } else {
+            STATS_INDEX
+        }
+    }
 }
 
 impl VirtioDevice for Balloon {
@@ -577,6 +768,9 @@ impl VirtioDevice for Balloon {
         if self.stats_enabled() {
             self.update_timer_state();
         }
+        if self.policy.is_some() {
+            self.update_policy_timer_state();
+        }
 
         Ok(())
     }
@@ -604,10 +798,6 @@ pub(crate) mod tests {
             self.queues[idx] = q;
         }
 
-        pub(crate) fn actual_pages(&self) -> u32 {
-            self.config_space.actual_pages
-        }
-
         pub fn update_num_pages(&mut self, num_pages: u32) {
             self.config_space.num_pages = num_pages;
         }
@@ -683,36 +873,53 @@ pub(crate) mod tests {
         // Test all feature combinations.
         for deflate_on_oom in vec![true, false].iter() {
             for stats_interval in vec![0, 1].iter() {
-                let mut balloon = Balloon::new(0, *deflate_on_oom, *stats_interval, false).unwrap();
-                assert_eq!(balloon.device_type(), TYPE_BALLOON);
-
-                let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
-                    | ((if *deflate_on_oom { 1 } else { 0 }) << VIRTIO_BALLOON_F_DEFLATE_ON_OOM)
-                    | ((*stats_interval as u64) << VIRTIO_BALLOON_F_STATS_VQ);
+                for free_page_reporting in vec![true, false].iter() {
+                    let mut balloon = Balloon::new(
+                        0,
+                        *deflate_on_oom,
+                        *stats_interval,
+                        *free_page_reporting,
+                        false,
+                    )
+                    .unwrap();
+                    assert_eq!(balloon.device_type(), TYPE_BALLOON);
+
+                    let features: u64 = (1u64 << VIRTIO_F_VERSION_1)
+                        | ((if *deflate_on_oom { 1 } else { 0 })
+                            << VIRTIO_BALLOON_F_DEFLATE_ON_OOM)
+                        | ((*stats_interval as u64) << VIRTIO_BALLOON_F_STATS_VQ)
+                        | ((if *free_page_reporting { 1 } else { 0 })
+                            << VIRTIO_BALLOON_F_REPORTING);
+
+                    assert_eq!(balloon.avail_features_by_page(0), features as u32);
+                    assert_eq!(balloon.avail_features_by_page(1), (features >> 32) as u32);
+                    for i in 2..10 {
+                        assert_eq!(balloon.avail_features_by_page(i), 0u32);
+                    }
 
-                assert_eq!(balloon.avail_features_by_page(0), features as u32);
-                assert_eq!(balloon.avail_features_by_page(1), (features >> 32) as u32);
-                for i in 2..10 {
-                    assert_eq!(balloon.avail_features_by_page(i), 0u32);
-                }
+                    for i in 0..10 {
+                        balloon.ack_features_by_page(i, u32::MAX);
+                    }
+                    // Only present features should be acknowledged.
+                    assert_eq!(balloon.acked_features, features);
 
-                for i in 0..10 {
-                    balloon.ack_features_by_page(i, u32::MAX);
+                    // Only the queues of the present features should exist.
+                    let num_queues = 2 + *stats_interval as usize + *free_page_reporting as usize;
+                    assert_eq!(balloon.queues().len(), num_queues);
                 }
-                // Only present features should be acknowledged.
-                assert_eq!(balloon.acked_features, features);
             }
         }
     }
 
     #[test]
     fn test_virtio_read_config() {
-        let balloon = Balloon::new(0x10, true, 0, false).unwrap();
+        let balloon = Balloon::new(0x10, true, 0, false, false).unwrap();
 
         let cfg = BalloonConfig {
             amount_mb: 16,
             deflate_on_oom: true,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         };
         assert_eq!(balloon.config(), cfg);
 
@@ -738,7 +945,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_virtio_write_config() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
 
         let expected_config_space: [u8; CONFIG_SPACE_SIZE] =
             [0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
@@ -758,7 +965,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_invalid_request() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         // Only initialize the inflate queue to demonstrate invalid request handling.
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
@@ -817,7 +1024,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_inflate() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(INFLATE_INDEX, infq.create_queue());
@@ -863,12 +1070,14 @@ pub(crate) mod tests {
             mem.write_obj::<u32>(0x1, GuestAddress(page_addr)).unwrap();
             set_request(&infq, 0, page_addr, SIZE_OF_U32 as u32, VIRTQ_DESC_F_NEXT);
 
+            let reclaimed_bytes = METRICS.balloon.reclaimed_bytes.count();
             check_metric_after_block!(
                 METRICS.balloon.inflate_count,
                 1,
                 invoke_handler_for_queue_event(&mut balloon, INFLATE_INDEX)
             );
             check_request_completion(&infq, 0);
+            assert!(METRICS.balloon.reclaimed_bytes.count() >= reclaimed_bytes + 0x1000);
 
             // Check that the page was zeroed.
             for i in 0..0x1000 {
@@ -877,9 +1086,22 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_backing_page_size() {
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
+        assert_eq!(balloon.backing_page_size(), BALLOON_PAGE_SIZE);
+
+        balloon.set_backing_page_size(0x20_0000);
+        assert_eq!(balloon.backing_page_size(), 0x20_0000);
+
+        // Backing pages smaller than the balloon pages make no difference.
+        balloon.set_backing_page_size(0x100);
+        assert_eq!(balloon.backing_page_size(), BALLOON_PAGE_SIZE);
+    }
+
     #[test]
     fn test_deflate() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         let mem = default_mem();
         let defq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(DEFLATE_INDEX, defq.create_queue());
@@ -917,9 +1139,49 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_free_page_reporting() {
+        // Without the statistics, the reporting queue takes the place of the statistics queue.
+        let balloon = Balloon::new(0, true, 0, true, false).unwrap();
+        assert_eq!(balloon.reporting_queue_index(), STATS_INDEX);
+        assert!(balloon.config().free_page_reporting);
+
+        let mut balloon = Balloon::new(0, true, 1, true, false).unwrap();
+        assert_eq!(balloon.reporting_queue_index(), REPORTING_INDEX);
+        let mem = default_mem();
+        let repq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        balloon.set_queue(REPORTING_INDEX, repq.create_queue());
+        balloon.activate(mem.clone()).unwrap();
+
+        // Fill the second and third pages with non-zero bytes.
+        for i in 0..0x2000 {
+            assert!(mem.write_obj::<u8>(1, GuestAddress((1 << 12) + i)).is_ok());
+        }
+
+        // Report both pages, in a chain of two descriptors.
+        repq.dtable[0].set(1 << 12, 0x1000, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 1);
+        repq.dtable[1].set(2 << 12, 0x1000, VIRTQ_DESC_F_WRITE, 0);
+        repq.avail.ring[0].set(0);
+        repq.avail.idx.set(1);
+
+        let reclaimed_bytes = METRICS.balloon.free_page_reclaimed_bytes.count();
+        check_metric_after_block!(
+            METRICS.balloon.free_page_report_count,
+            1,
+            invoke_handler_for_queue_event(&mut balloon, REPORTING_INDEX)
+        );
+        check_request_completion(&repq, 0);
+        assert!(METRICS.balloon.free_page_reclaimed_bytes.count() >= reclaimed_bytes + 0x2000);
+
+        // Check that the pages were zeroed.
+        for i in 0..0x2000 {
+            assert_eq!(mem.read_obj::<u8>(GuestAddress((1 << 12) + i)).unwrap(), 0);
+        }
+    }
+
     #[test]
     fn test_stats() {
-        let mut balloon = Balloon::new(0, true, 1, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 1, false, false).unwrap();
         let mem = default_mem();
         let statsq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(STATS_INDEX, statsq.create_queue());
@@ -986,6 +1248,9 @@ pub(crate) mod tests {
                 ..BalloonStats::default()
             };
             assert_eq!(stats, &expected_stats);
+            let metrics = METRICS.balloon.balloons.get(BALLOON_DEV_ID);
+            assert_eq!(metrics.swap_out.fetch(), 0x1);
+            assert_eq!(metrics.free_memory.fetch(), 0x5678);
 
             // Wait for the timer to expire, although as it is non-blocking
             // we could just process the timer event and it would not
@@ -1004,7 +1269,7 @@ pub(crate) mod tests {
 
     #[test]
     fn test_process_balloon_queues() {
-        let mut balloon = Balloon::new(0x10, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0x10, true, 0, false, false).unwrap();
         let mem = default_mem();
         balloon.activate(mem).unwrap();
         balloon.process_virtio_queues()
@@ -1012,14 +1277,14 @@ pub(crate) mod tests {
 
     #[test]
     fn test_update_stats_interval() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         assert_eq!(
             format!("{:?}", balloon.update_stats_polling_interval(1)),
             "Err(StatisticsStateChange)"
         );
         assert!(balloon.update_stats_polling_interval(0).is_ok());
 
-        let mut balloon = Balloon::new(0, true, 1, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 1, false, false).unwrap();
         assert_eq!(
             format!("{:?}", balloon.update_stats_polling_interval(0)),
             "Err(StatisticsStateChange)"
@@ -1028,9 +1293,57 @@ pub(crate) mod tests {
         assert!(balloon.update_stats_polling_interval(2).is_ok());
     }
 
+    #[test]
+    fn test_policy() {
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
+        let policy = BalloonPolicy {
+            min_mb: 16,
+            max_mb: 16,
+            step_mb: 8,
+            polling_interval_s: 1,
+            low_available_mb: 0,
+            high_available_mb: 0,
+            psi_threshold: None,
+        };
+
+        // Inconsistent bounds are rejected.
+        let invalid_policy = BalloonPolicy {
+            min_mb: 32,
+            ..policy.clone()
+        };
+        assert!(balloon.set_policy(invalid_policy).is_err());
+        assert!(balloon.policy().is_none());
+
+        // The policy can be set before activation.
+        balloon.set_policy(policy.clone()).unwrap();
+        assert_eq!(balloon.policy(), Some(&policy));
+        balloon.activate(default_mem()).unwrap();
+
+        // Whatever the memory pressure of the host, the target size is brought
+        // within the bounds of the policy, and the driver is notified.
+        balloon.process_policy_timer_event().unwrap();
+        assert_eq!(balloon.size_mb(), 16);
+        assert_ne!(
+            balloon.interrupt_status().load(Ordering::SeqCst) & VIRTIO_MMIO_INT_CONFIG as usize,
+            0
+        );
+
+        // A polling interval of 0 disables the policy.
+        balloon
+            .set_policy(BalloonPolicy {
+                polling_interval_s: 0,
+                ..policy
+            })
+            .unwrap();
+        assert!(balloon.policy().is_none());
+        assert!(balloon.update_size(0).is_ok());
+        balloon.process_policy_timer_event().unwrap();
+        assert_eq!(balloon.size_mb(), 0);
+    }
+
     #[test]
     fn test_num_pages() {
-        let mut balloon = Balloon::new(0, true, 0, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 0, false, false).unwrap();
         // Assert that we can't update an inactive device.
         assert!(balloon.update_size(1).is_err());
         // Switch the state to active.
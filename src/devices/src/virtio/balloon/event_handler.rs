@@ -63,7 +63,9 @@ impl Subscriber for Balloon {
             let virtq_inflate_ev_fd = self.queue_evts[INFLATE_INDEX].as_raw_fd();
             let virtq_deflate_ev_fd = self.queue_evts[DEFLATE_INDEX].as_raw_fd();
             let virtq_stats_ev_fd = self.queue_evts[STATS_INDEX].as_raw_fd();
+            let virtq_reporting_ev_fd = self.queue_evts[self.reporting_queue_index()].as_raw_fd();
             let stats_timer_fd = self.stats_timer.as_raw_fd();
+            let policy_timer_fd = self.policy_timer.as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
 
             // Looks better than C style if/else if/else.
@@ -74,12 +76,19 @@ impl Subscriber for Balloon {
                 _ if source == virtq_deflate_ev_fd => self
                     .process_deflate_queue_event()
                     .unwrap_or_else(report_balloon_event_fail),
+                // Without the statistics, both queues share the same event.
+                _ if self.free_page_reporting() && source == virtq_reporting_ev_fd => self
+                    .process_reporting_queue_event()
+                    .unwrap_or_else(report_balloon_event_fail),
                 _ if source == virtq_stats_ev_fd => self
                     .process_stats_queue_event()
                     .unwrap_or_else(report_balloon_event_fail),
                 _ if source == stats_timer_fd => self
                     .process_stats_timer_event()
                     .unwrap_or_else(report_balloon_event_fail),
+                _ if source == policy_timer_fd => self
+                    .process_policy_timer_event()
+                    .unwrap_or_else(report_balloon_event_fail),
                 _ if activate_fd == source => self.process_activate_event(evmgr),
                 _ => {
                     warn!("Balloon: Spurious event received: {:?}", source);
@@ -108,6 +117,8 @@ impl Subscriber for Balloon {
                     EventSet::IN,
                     self.queue_evts[DEFLATE_INDEX].as_raw_fd() as u64,
                 ),
+                // The policy can be set at any time, so its timer is always registered.
+                EpollEvent::new(EventSet::IN, self.policy_timer.as_raw_fd() as u64),
             ];
             if self.stats_enabled() {
                 events.extend(vec![
@@ -118,6 +129,12 @@ impl Subscriber for Balloon {
                     EpollEvent::new(EventSet::IN, self.stats_timer.as_raw_fd() as u64),
                 ]);
             }
+            if self.free_page_reporting() {
+                events.push(EpollEvent::new(
+                    EventSet::IN,
+                    self.queue_evts[self.reporting_queue_index()].as_raw_fd() as u64,
+                ));
+            }
             events
         } else {
             vec![EpollEvent::new(
@@ -140,7 +157,7 @@ pub mod tests {
     #[test]
     fn test_event_handler() {
         let mut event_manager = EventManager::new().unwrap();
-        let mut balloon = Balloon::new(0, true, 10, false).unwrap();
+        let mut balloon = Balloon::new(0, true, 10, false, false).unwrap();
         let mem = default_mem();
         let infq = VirtQueue::new(GuestAddress(0), &mem, 16);
         balloon.set_queue(INFLATE_INDEX, infq.create_queue());
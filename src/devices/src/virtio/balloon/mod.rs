@@ -4,6 +4,7 @@
 pub mod device;
 pub mod event_handler;
 pub mod persist;
+pub mod policy;
 pub mod test_utils;
 mod utils;
 
@@ -13,14 +14,15 @@ pub use self::device::Balloon;
 pub use self::device::BalloonConfig;
 pub use self::device::BalloonStats;
 pub use self::event_handler::*;
+pub use self::policy::BalloonPolicy;
 
 /// Device ID used in MMIO device identification.
 /// Because Balloon is unique per-vm, this ID can be hardcoded.
 pub const BALLOON_DEV_ID: &str = "balloon";
 pub const CONFIG_SPACE_SIZE: usize = 8;
 pub const QUEUE_SIZE: u16 = 256;
-pub const NUM_QUEUES: usize = 3;
-pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE, QUEUE_SIZE, QUEUE_SIZE];
+pub const NUM_QUEUES: usize = 4;
+pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE, QUEUE_SIZE, QUEUE_SIZE, QUEUE_SIZE];
 // Number of 4K pages in a MB.
 pub const MB_TO_4K_PAGES: u32 = 256;
 // The maximum number of pages that can be received in a single descriptor.
@@ -30,16 +32,22 @@ pub const MAX_PAGES_IN_DESC: usize = 256;
 pub const MAX_PAGE_COMPACT_BUFFER: usize = 2048;
 // The addresses given by the driver are divided by 4096.
 pub const VIRTIO_BALLOON_PFN_SHIFT: u32 = 12;
+// The size of the pages the driver inflates the balloon with.
+pub const BALLOON_PAGE_SIZE: u64 = 1 << VIRTIO_BALLOON_PFN_SHIFT;
 // The index of the deflate queue from Balloon device queues/queues_evts vector.
 pub const INFLATE_INDEX: usize = 0;
 // The index of the deflate queue from Balloon device queues/queues_evts vector.
 pub const DEFLATE_INDEX: usize = 1;
 // The index of the deflate queue from Balloon device queues/queues_evts vector.
 pub const STATS_INDEX: usize = 2;
+// The index of the free page reporting queue from Balloon device queues/queues_evts vector,
+// when the statistics are enabled. Otherwise, it takes the index of the statistics queue.
+pub const REPORTING_INDEX: usize = 3;
 
 // The feature bitmap for virtio balloon.
 const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1; // Enable statistics.
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2; // Deflate balloon on OOM.
+const VIRTIO_BALLOON_F_REPORTING: u32 = 5; // Free page reporting.
 
 // The statistics tags.
 const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
@@ -67,12 +75,16 @@ pub enum Error {
     FailedSignalingUsedQueue(std::io::Error),
     /// Guest gave us bad memory addresses.
     GuestMemory(GuestMemoryError),
+    /// Failed to read the memory pressure of the host.
+    HostMemoryState(std::io::Error),
     /// Received error while sending an interrupt.
     InterruptError(std::io::Error),
     /// Guest gave us a malformed descriptor.
     MalformedDescriptor,
     /// Guest gave us a malformed payload.
     MalformedPayload,
+    /// The balloon policy bounds are inconsistent.
+    InvalidPolicy(&'static str),
     /// Error restoring the balloon device queues.
     QueueRestoreError,
     /// Received stats querry when stats are disabled.
@@ -9,7 +9,7 @@ use std::time::Duration;
 use timerfd::{SetTimeFlags, TimerState};
 
 use snapshot::Persist;
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 
 use vm_memory::GuestMemoryMmap;
@@ -78,6 +78,47 @@ impl BalloonStatsState {
     }
 }
 
+/// The serializable state of a balloon policy.
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct BalloonPolicyState {
+    min_mb: u32,
+    max_mb: u32,
+    step_mb: u32,
+    polling_interval_s: u16,
+    low_available_mb: u64,
+    high_available_mb: u64,
+    psi_threshold: Option<u8>,
+}
+
+impl From<&BalloonPolicy> for BalloonPolicyState {
+    fn from(policy: &BalloonPolicy) -> Self {
+        BalloonPolicyState {
+            min_mb: policy.min_mb,
+            max_mb: policy.max_mb,
+            step_mb: policy.step_mb,
+            polling_interval_s: policy.polling_interval_s,
+            low_available_mb: policy.low_available_mb,
+            high_available_mb: policy.high_available_mb,
+            psi_threshold: policy.psi_threshold,
+        }
+    }
+}
+
+impl From<&BalloonPolicyState> for BalloonPolicy {
+    fn from(state: &BalloonPolicyState) -> Self {
+        BalloonPolicy {
+            min_mb: state.min_mb,
+            max_mb: state.max_mb,
+            step_mb: state.step_mb,
+            polling_interval_s: state.polling_interval_s,
+            low_available_mb: state.low_available_mb,
+            high_available_mb: state.high_available_mb,
+            psi_threshold: state.psi_threshold,
+        }
+    }
+}
+
 #[derive(Clone, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct BalloonState {
@@ -86,6 +127,26 @@ pub struct BalloonState {
     latest_stats: BalloonStatsState,
     config_space: BalloonConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    /// The policy resizing the balloon, if one is set.
+    #[version(start = 2, ser_fn = "policy_serialize")]
+    policy: Option<BalloonPolicyState>,
+}
+
+impl BalloonState {
+    /// Whether the guest driver had activated the device when it was saved.
+    pub fn is_activated(&self) -> bool {
+        self.virtio_state.activated
+    }
+
+    fn policy_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.policy.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the balloon policy.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub struct BalloonConstructorArgs {
@@ -107,6 +168,7 @@ impl Persist<'_> for Balloon {
                 actual_pages: self.config_space.actual_pages,
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            policy: self.policy.as_ref().map(BalloonPolicyState::from),
         }
     }
 
@@ -114,9 +176,18 @@ impl Persist<'_> for Balloon {
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
+        // The free page reporting queue only exists if the feature was offered.
+        let free_page_reporting =
+            state.virtio_state.avail_features & (1u64 << VIRTIO_BALLOON_F_REPORTING) != 0;
         // We can safely create the balloon with arbitrary flags and
         // num_pages because we will overwrite them after.
-        let mut balloon = Balloon::new(0, false, state.stats_polling_interval_s, true)?;
+        let mut balloon = Balloon::new(
+            0,
+            false,
+            state.stats_polling_interval_s,
+            free_page_reporting,
+            true,
+        )?;
 
         let mut num_queues = NUM_QUEUES;
         // As per the virtio 1.1 specification, the statistics queue
@@ -124,6 +195,9 @@ impl Persist<'_> for Balloon {
         if state.stats_polling_interval_s == 0 {
             num_queues -= 1;
         }
+        if !free_page_reporting {
+            num_queues -= 1;
+        }
         balloon.queues = state
             .virtio_state
             .build_queues_checked(&constructor_args.mem, TYPE_BALLOON, num_queues, QUEUE_SIZE)
@@ -132,6 +206,7 @@ impl Persist<'_> for Balloon {
         balloon.avail_features = state.virtio_state.avail_features;
         balloon.acked_features = state.virtio_state.acked_features;
         balloon.latest_stats = state.latest_stats.create_stats();
+        balloon.policy = state.policy.as_ref().map(BalloonPolicy::from);
         balloon.config_space = ConfigSpace {
             num_pages: state.config_space.num_pages,
             actual_pages: state.config_space.actual_pages,
@@ -150,6 +225,9 @@ impl Persist<'_> for Balloon {
                     .stats_timer
                     .set_state(timer_state, SetTimeFlags::Default);
             }
+            if balloon.policy.is_some() {
+                balloon.update_policy_timer_state();
+            }
         }
 
         Ok(balloon)
@@ -172,7 +250,7 @@ mod tests {
         let version_map = VersionMap::new();
 
         // Create and save the balloon device.
-        let balloon = Balloon::new(0x42, false, 2, false).unwrap();
+        let balloon = Balloon::new(0x42, false, 2, true, false).unwrap();
 
         <Balloon as Persist>::save(&balloon)
             .serialize(&mut mem.as_mut_slice(), &version_map, 1)
@@ -191,6 +269,7 @@ mod tests {
         assert_eq!(restored_balloon.acked_features, balloon.acked_features);
         assert_eq!(restored_balloon.avail_features, balloon.avail_features);
         assert_eq!(restored_balloon.config_space, balloon.config_space);
+        assert!(restored_balloon.free_page_reporting());
         assert_eq!(restored_balloon.queues(), balloon.queues());
         assert_eq!(
             restored_balloon.interrupt_status().load(Ordering::Relaxed),
@@ -205,4 +284,41 @@ mod tests {
         assert_eq!(restored_balloon.stats_desc_index, balloon.stats_desc_index);
         assert_eq!(restored_balloon.latest_stats, balloon.latest_stats);
     }
+
+    #[test]
+    fn test_persist_policy() {
+        let guest_mem = default_mem();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(BalloonState::type_id(), 2);
+
+        let mut balloon = Balloon::new(0x42, false, 0, false, false).unwrap();
+        let policy = BalloonPolicy {
+            min_mb: 0,
+            max_mb: 0x80,
+            step_mb: 0x10,
+            polling_interval_s: 5,
+            low_available_mb: 512,
+            high_available_mb: 1024,
+            psi_threshold: Some(20),
+        };
+        balloon.set_policy(policy.clone()).unwrap();
+
+        // The policy cannot be saved in a snapshot of version 1.
+        assert!(<Balloon as Persist>::save(&balloon)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        <Balloon as Persist>::save(&balloon)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_balloon = Balloon::restore(
+            BalloonConstructorArgs { mem: guest_mem },
+            &BalloonState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_balloon.policy(), Some(&policy));
+    }
 }
@@ -0,0 +1,228 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the policy which automatically resizes the balloon, based on the memory pressure
+//! of the host.
+
+use std::cmp;
+use std::fs;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use super::Error as BalloonError;
+
+/// File holding the memory usage of the host.
+const HOST_MEMINFO_PATH: &str = "/proc/meminfo";
+/// File holding the memory pressure stall information of the host.
+const HOST_MEMORY_PSI_PATH: &str = "/proc/pressure/memory";
+
+fn default_step_mb() -> u32 {
+    64
+}
+
+/// Keeps the balloon target size within `[min_mb, max_mb]`, inflating the balloon
+/// by `step_mb` when the host runs low on memory and deflating it by `step_mb` when
+/// the host has memory to spare.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalloonPolicy {
+    /// Minimum balloon target size in MiB.
+    pub min_mb: u32,
+    /// Maximum balloon target size in MiB.
+    pub max_mb: u32,
+    /// Amount in MiB by which the target size changes at each step.
+    #[serde(default = "default_step_mb")]
+    pub step_mb: u32,
+    /// Interval in seconds between two evaluations of the host memory pressure.
+    /// A value of 0 disables the policy.
+    pub polling_interval_s: u16,
+    /// The balloon is inflated while the host has less memory available, in MiB.
+    pub low_available_mb: u64,
+    /// The balloon is deflated while the host has more memory available, in MiB.
+    pub high_available_mb: u64,
+    /// The balloon is also inflated while some tasks of the host have been stalled on memory
+    /// for at least this percentage of the last 10 seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub psi_threshold: Option<u8>,
+}
+
+impl BalloonPolicy {
+    /// Checks that the bounds of the policy are consistent.
+    pub fn validate(&self) -> Result<(), BalloonError> {
+        if self.min_mb > self.max_mb {
+            return Err(BalloonError::InvalidPolicy(
+                "min_mb must not be greater than max_mb",
+            ));
+        }
+        if self.low_available_mb > self.high_available_mb {
+            return Err(BalloonError::InvalidPolicy(
+                "low_available_mb must not be greater than high_available_mb",
+            ));
+        }
+        if self.step_mb == 0 {
+            return Err(BalloonError::InvalidPolicy("step_mb must not be 0"));
+        }
+        if self.psi_threshold.unwrap_or(0) > 100 {
+            return Err(BalloonError::InvalidPolicy(
+                "psi_threshold must not be greater than 100",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Computes the balloon target size which follows `current_mb`, given the memory
+    /// pressure of the host.
+    pub(crate) fn next_target_mb(&self, current_mb: u32, host: &HostMemoryState) -> u32 {
+        let stalled = match (self.psi_threshold, host.psi_some_avg10) {
+            (Some(threshold), Some(avg10)) => avg10 >= f64::from(threshold),
+            _ => false,
+        };
+
+        let target_mb = if stalled || host.available_mb < self.low_available_mb {
+            current_mb.saturating_add(self.step_mb)
+        } else if host.available_mb > self.high_available_mb {
+            current_mb.saturating_sub(self.step_mb)
+        } else {
+            current_mb
+        };
+
+        cmp::min(cmp::max(target_mb, self.min_mb), self.max_mb)
+    }
+}
+
+/// The memory pressure of the host.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct HostMemoryState {
+    /// Memory available on the host, in MiB.
+    pub available_mb: u64,
+    /// Share of the last 10 seconds during which some tasks were stalled on memory, as a
+    /// percentage. `None` if the host kernel doesn't provide pressure stall information.
+    pub psi_some_avg10: Option<f64>,
+}
+
+impl HostMemoryState {
+    /// Reads the memory pressure of the host from procfs.
+    pub(crate) fn read() -> io::Result<Self> {
+        let meminfo = fs::read_to_string(HOST_MEMINFO_PATH)?;
+        let available_mb = parse_mem_available_mb(&meminfo)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "MemAvailable not found"))?;
+        let psi_some_avg10 = fs::read_to_string(HOST_MEMORY_PSI_PATH)
+            .ok()
+            .and_then(|psi| parse_psi_some_avg10(&psi));
+
+        Ok(HostMemoryState {
+            available_mb,
+            psi_some_avg10,
+        })
+    }
+}
+
+/// Parses the "MemAvailable:   123456 kB" line of /proc/meminfo.
+fn parse_mem_available_mb(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb / 1024)
+}
+
+/// Parses the "some avg10=1.23 avg60=..." line of /proc/pressure/memory.
+fn parse_psi_some_avg10(psi: &str) -> Option<f64> {
+    let line = psi.lines().find(|line| line.starts_with("some "))?;
+    line.split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_policy() -> BalloonPolicy {
+        BalloonPolicy {
+            min_mb: 0,
+            max_mb: 256,
+            step_mb: 64,
+            polling_interval_s: 1,
+            low_available_mb: 1024,
+            high_available_mb: 2048,
+            psi_threshold: Some(10),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(test_policy().validate().is_ok());
+
+        let policy = BalloonPolicy {
+            min_mb: 512,
+            ..test_policy()
+        };
+        assert!(policy.validate().is_err());
+        let policy = BalloonPolicy {
+            low_available_mb: 4096,
+            ..test_policy()
+        };
+        assert!(policy.validate().is_err());
+        let policy = BalloonPolicy {
+            step_mb: 0,
+            ..test_policy()
+        };
+        assert!(policy.validate().is_err());
+        let policy = BalloonPolicy {
+            psi_threshold: Some(101),
+            ..test_policy()
+        };
+        assert!(policy.validate().is_err());
+    }
+
+    #[test]
+    fn test_next_target_mb() {
+        let policy = test_policy();
+        let host = |available_mb, psi_some_avg10| HostMemoryState {
+            available_mb,
+            psi_some_avg10,
+        };
+
+        // Low on memory.
+        assert_eq!(policy.next_target_mb(0, &host(512, None)), 64);
+        assert_eq!(policy.next_target_mb(224, &host(512, None)), 256);
+        // Stalled on memory.
+        assert_eq!(policy.next_target_mb(64, &host(1536, Some(10.0))), 128);
+        assert_eq!(policy.next_target_mb(64, &host(4096, Some(25.5))), 128);
+        // In between the thresholds.
+        assert_eq!(policy.next_target_mb(64, &host(1536, Some(9.99))), 64);
+        assert_eq!(policy.next_target_mb(64, &host(2048, None)), 64);
+        // Memory to spare.
+        assert_eq!(policy.next_target_mb(64, &host(4096, Some(0.0))), 0);
+        assert_eq!(policy.next_target_mb(32, &host(4096, None)), 0);
+        // The target size is brought back within the bounds.
+        assert_eq!(policy.next_target_mb(1024, &host(1536, None)), 256);
+        let policy = BalloonPolicy {
+            min_mb: 128,
+            psi_threshold: None,
+            ..test_policy()
+        };
+        assert_eq!(policy.next_target_mb(0, &host(1536, Some(50.0))), 128);
+    }
+
+    #[test]
+    fn test_parse_host_memory_state() {
+        let meminfo = "MemTotal:       16314980 kB\n\
+                       MemFree:         1140804 kB\n\
+                       MemAvailable:    9823500 kB\n\
+                       Buffers:          646564 kB\n";
+        assert_eq!(parse_mem_available_mb(meminfo), Some(9593));
+        assert_eq!(parse_mem_available_mb("MemTotal: 16314980 kB\n"), None);
+        assert_eq!(parse_mem_available_mb("MemAvailable: kB\n"), None);
+
+        let psi = "some avg10=1.52 avg60=0.31 avg300=0.07 total=2836113\n\
+                   full avg10=0.75 avg60=0.15 avg300=0.03 total=1530386\n";
+        assert_eq!(parse_psi_some_avg10(psi), Some(1.52));
+        assert_eq!(parse_psi_some_avg10("full avg10=0.75\n"), None);
+        assert_eq!(parse_psi_some_avg10("some avg10=n/a\n"), None);
+    }
+}
@@ -3,7 +3,7 @@
 
 use std::io;
 
-use super::{RemoveRegionError, MAX_PAGE_COMPACT_BUFFER};
+use super::{RemoveRegionError, BALLOON_PAGE_SIZE, MAX_PAGE_COMPACT_BUFFER};
 use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
 /// This takes a vector of page frame numbers, and compacts them
@@ -48,11 +48,50 @@ pub(crate) fn compact_page_frame_numbers(v: &mut [u32]) -> Vec<(u32, u32)> {
     result
 }
 
+// Discards the `len` bytes of host memory starting at `host_addr`.
+fn discard_range(host_addr: u64, len: u64, restored: bool) -> Result<(), RemoveRegionError> {
+    // Mmap a new anonymous region over the present one in order to create a hole.
+    // This workaround is (only) needed after resuming from a snapshot because the guest memory
+    // is mmaped from file as private and there is no `madvise` flag that works for this case.
+    if restored {
+        let ret = unsafe {
+            libc::mmap(
+                host_addr as *mut _,
+                len as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_FIXED | libc::MAP_ANONYMOUS | libc::MAP_PRIVATE,
+                -1,
+                0,
+            )
+        };
+        if ret == libc::MAP_FAILED {
+            return Err(RemoveRegionError::MmapFail(io::Error::last_os_error()));
+        }
+    };
+
+    // Madvise the region in order to mark it as not used.
+    let ret = unsafe { libc::madvise(host_addr as *mut _, len as usize, libc::MADV_DONTNEED) };
+    if ret < 0 {
+        return Err(RemoveRegionError::MadviseFail(io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+/// Removes the given range of guest memory, backed by pages of `backing_page_size` bytes.
+///
+/// When the backing pages are larger than the balloon pages, the whole backing pages covered
+/// by the range are discarded at once, while the balloon pages at its unaligned ends are
+/// discarded one by one. Since hugetlbfs mappings cannot be split, the latter are kept around
+/// when the kernel refuses to discard them.
+///
+/// Returns the number of bytes actually reclaimed.
 pub(crate) fn remove_range(
     guest_memory: &GuestMemoryMmap,
     range: (GuestAddress, u64),
     restored: bool,
-) -> std::result::Result<(), RemoveRegionError> {
+    backing_page_size: u64,
+) -> std::result::Result<u64, RemoveRegionError> {
     let (guest_address, range_len) = range;
 
     if let Some(region) = guest_memory.find_region(guest_address) {
@@ -61,45 +100,54 @@ pub(crate) fn remove_range(
         }
         let phys_address = guest_memory
             .get_host_address(guest_address)
-            .map_err(|_| RemoveRegionError::AddressTranslation)?;
-
-        // Mmap a new anonymous region over the present one in order to create a hole.
-        // This workaround is (only) needed after resuming from a snapshot because the guest memory
-        // is mmaped from file as private and there is no `madvise` flag that works for this case.
-        if restored {
-            let ret = unsafe {
-                libc::mmap(
-                    phys_address as *mut _,
-                    range_len as usize,
-                    libc::PROT_READ | libc::PROT_WRITE,
-                    libc::MAP_FIXED | libc::MAP_ANONYMOUS | libc::MAP_PRIVATE,
-                    -1,
-                    0,
-                )
-            };
-            if ret == libc::MAP_FAILED {
-                return Err(RemoveRegionError::MmapFail(io::Error::last_os_error()));
-            }
-        };
+            .map_err(|_| RemoveRegionError::AddressTranslation)? as u64;
 
-        // Madvise the region in order to mark it as not used.
-        let ret = unsafe {
-            libc::madvise(
-                phys_address as *mut _,
-                range_len as usize,
-                libc::MADV_DONTNEED,
-            )
-        };
-        if ret < 0 {
-            return Err(RemoveRegionError::MadviseFail(io::Error::last_os_error()));
+        if backing_page_size <= BALLOON_PAGE_SIZE {
+            discard_range(phys_address, range_len, restored)?;
+            return Ok(range_len);
         }
 
-        Ok(())
+        // Split the range into the backing pages it fully covers and the unaligned ends.
+        let range_end = phys_address + range_len;
+        let huge_start = align_up(phys_address, backing_page_size).min(range_end);
+        let huge_end = align_down(range_end, backing_page_size).max(huge_start);
+
+        let mut reclaimed = 0;
+        if huge_end > huge_start {
+            discard_range(huge_start, huge_end - huge_start, restored)?;
+            reclaimed += huge_end - huge_start;
+        }
+
+        for (start, end) in &[(phys_address, huge_start), (huge_end, range_end)] {
+            for addr in (*start..*end).step_by(BALLOON_PAGE_SIZE as usize) {
+                match discard_range(addr, BALLOON_PAGE_SIZE, restored) {
+                    Ok(()) => reclaimed += BALLOON_PAGE_SIZE,
+                    Err(RemoveRegionError::MadviseFail(ref e))
+                    | Err(RemoveRegionError::MmapFail(ref e))
+                        if e.raw_os_error() == Some(libc::EINVAL) =>
+                    {
+                        // The backing page cannot be split, so it stays in use until
+                        // an inflation covers it entirely.
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(reclaimed)
     } else {
         Err(RemoveRegionError::RegionNotFound)
     }
 }
 
+fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) / align * align
+}
+
+fn align_down(addr: u64, align: u64) -> u64 {
+    addr / align * align
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -161,7 +209,13 @@ mod tests {
         mem.write(&ones[..], GuestAddress(0)).unwrap();
 
         // Remove the first page.
-        assert!(remove_range(&mem, (GuestAddress(0), page_size as u64), false).is_ok());
+        assert!(remove_range(
+            &mem,
+            (GuestAddress(0), page_size as u64),
+            false,
+            BALLOON_PAGE_SIZE
+        )
+        .is_ok());
 
         // Check that the first page is zeroed.
         let mut actual_page = vec![0u8; page_size];
@@ -178,19 +232,31 @@ mod tests {
 
         // Malformed range: the len is too big.
         assert_match!(
-            remove_range(&mem, (GuestAddress(0), 0x10000), false).unwrap_err(),
+            remove_range(&mem, (GuestAddress(0), 0x10000), false, BALLOON_PAGE_SIZE).unwrap_err(),
             RemoveRegionError::MalformedRange
         );
 
         // Region not mapped.
         assert_match!(
-            remove_range(&mem, (GuestAddress(0x10000), 0x10), false).unwrap_err(),
+            remove_range(
+                &mem,
+                (GuestAddress(0x10000), 0x10),
+                false,
+                BALLOON_PAGE_SIZE
+            )
+            .unwrap_err(),
             RemoveRegionError::RegionNotFound
         );
 
         // Madvise fail: the guest address is not aligned to the page size.
         assert_match!(
-            remove_range(&mem, (GuestAddress(0x20), page_size as u64), false).unwrap_err(),
+            remove_range(
+                &mem,
+                (GuestAddress(0x20), page_size as u64),
+                false,
+                BALLOON_PAGE_SIZE
+            )
+            .unwrap_err(),
             RemoveRegionError::MadviseFail(_)
         );
     }
@@ -205,7 +271,13 @@ mod tests {
         mem.write(&ones[..], GuestAddress(0)).unwrap();
 
         // Remove the first page.
-        assert!(remove_range(&mem, (GuestAddress(0), page_size as u64), true).is_ok());
+        assert!(remove_range(
+            &mem,
+            (GuestAddress(0), page_size as u64),
+            true,
+            BALLOON_PAGE_SIZE
+        )
+        .is_ok());
 
         // Check that the first page is zeroed.
         let mut actual_page = vec![0u8; page_size];
@@ -222,20 +294,93 @@ mod tests {
 
         // Malformed range: the len is too big.
         assert_match!(
-            remove_range(&mem, (GuestAddress(0), 0x10000), true).unwrap_err(),
+            remove_range(&mem, (GuestAddress(0), 0x10000), true, BALLOON_PAGE_SIZE).unwrap_err(),
             RemoveRegionError::MalformedRange
         );
 
         // Region not mapped.
         assert_match!(
-            remove_range(&mem, (GuestAddress(0x10000), 0x10), true).unwrap_err(),
+            remove_range(&mem, (GuestAddress(0x10000), 0x10), true, BALLOON_PAGE_SIZE).unwrap_err(),
             RemoveRegionError::RegionNotFound
         );
 
         // Mmap fail: the guest address is not aligned to the page size.
         assert_match!(
-            remove_range(&mem, (GuestAddress(0x20), page_size as u64), true).unwrap_err(),
+            remove_range(
+                &mem,
+                (GuestAddress(0x20), page_size as u64),
+                true,
+                BALLOON_PAGE_SIZE
+            )
+            .unwrap_err(),
             RemoveRegionError::MmapFail(_)
         );
     }
+
+    #[test]
+    fn test_remove_range_large_backing_pages() {
+        let page_size: usize = BALLOON_PAGE_SIZE as usize;
+        let backing_page_size: u64 = 0x20_0000;
+        let mem_size = 2 * backing_page_size as usize;
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), mem_size)]).unwrap();
+        let ones = vec![1u8; mem_size];
+        mem.write(&ones[..], GuestAddress(0)).unwrap();
+
+        // A range smaller than a backing page is discarded one balloon page at a time.
+        assert_eq!(
+            remove_range(
+                &mem,
+                (GuestAddress(page_size as u64), 2 * page_size as u64),
+                false,
+                backing_page_size
+            )
+            .unwrap(),
+            2 * page_size as u64
+        );
+
+        // A range straddling backing pages is reclaimed entirely on regular memory.
+        let range = (
+            GuestAddress(3 * page_size as u64),
+            mem_size as u64 - 4 * page_size as u64,
+        );
+        assert_eq!(
+            remove_range(&mem, range, false, backing_page_size).unwrap(),
+            range.1
+        );
+
+        let mut actual = vec![0u8; mem_size];
+        mem.read(&mut actual.as_mut_slice(), GuestAddress(0))
+            .unwrap();
+        assert_eq!(&actual[..page_size], &ones[..page_size]);
+        assert_eq!(
+            &actual[page_size..mem_size - page_size],
+            vec![0u8; mem_size - 2 * page_size].as_slice()
+        );
+        assert_eq!(&actual[mem_size - page_size..], &ones[..page_size]);
+
+        // The restored path splits the range in the same way.
+        mem.write(&ones[..], GuestAddress(0)).unwrap();
+        assert_eq!(
+            remove_range(&mem, range, true, backing_page_size).unwrap(),
+            range.1
+        );
+        mem.read(&mut actual.as_mut_slice(), GuestAddress(0))
+            .unwrap();
+        assert_eq!(
+            &actual[page_size..mem_size - page_size],
+            vec![0u8; mem_size - 2 * page_size].as_slice()
+        );
+
+        // Malformed range: the len is too big.
+        assert_match!(
+            remove_range(
+                &mem,
+                (GuestAddress(0), 0x100_0000),
+                false,
+                backing_page_size
+            )
+            .unwrap_err(),
+            RemoveRegionError::MalformedRange
+        );
+    }
 }
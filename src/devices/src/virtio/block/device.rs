@@ -14,8 +14,11 @@ use std::path::PathBuf;
 use std::result;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
-use logger::{error, warn, IncMetric, METRICS};
+use logger::{error, warn, BlockDeviceMetrics, DeviceMetrics, IncMetric, METRICS};
+use rate_limiter::adaptive::AdaptiveRate;
+use rate_limiter::group::RateLimiterGroup;
 use rate_limiter::{BucketUpdate, RateLimiter, TokenType};
 use utils::eventfd::EventFd;
 use virtio_gen::virtio_blk::*;
@@ -24,7 +27,7 @@ use vm_memory::{Bytes, GuestMemoryMmap};
 use super::{
     super::{ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BLOCK, VIRTIO_MMIO_INT_VRING},
     request::*,
-    Error, CONFIG_SPACE_SIZE, QUEUE_SIZES, SECTOR_SHIFT, SECTOR_SIZE,
+    Error, CONFIG_SPACE_SIZE, IO_SCHEDULER, QUEUE_SIZES, SECTOR_SHIFT, SECTOR_SIZE,
 };
 
 use crate::virtio::VIRTIO_MMIO_INT_CONFIG;
@@ -146,6 +149,11 @@ pub struct Block {
     pub(crate) partuuid: Option<String>,
     pub(crate) root_device: bool,
     pub(crate) rate_limiter: RateLimiter,
+    pub(crate) metrics: DeviceMetrics<BlockDeviceMetrics>,
+    // The weight of the drive in the I/O scheduler, if its I/O is shared with the other drives.
+    io_weight: Option<u32>,
+    // Signaled by the I/O scheduler when the drive may resume processing its queue.
+    pub(crate) io_scheduler_evt: EventFd,
 }
 
 impl Block {
@@ -171,6 +179,7 @@ impl Block {
         let queue_evts = [EventFd::new(libc::EFD_NONBLOCK)?];
 
         let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+        let metrics = DeviceMetrics::new(&METRICS.block, METRICS.block.drives.get(&id));
 
         Ok(Block {
             id,
@@ -187,16 +196,21 @@ impl Block {
             queues,
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK)?,
+            metrics,
+            io_weight: None,
+            io_scheduler_evt: EventFd::new(libc::EFD_NONBLOCK)?,
         })
     }
 
     pub(crate) fn process_queue_event(&mut self) {
-        METRICS.block.queue_event_count.inc();
+        self.metrics
+            .update(|metrics| metrics.queue_event_count.inc());
         if let Err(e) = self.queue_evts[0].read() {
             error!("Failed to get queue event: {:?}", e);
-            METRICS.block.event_fails.inc();
+            self.metrics.update(|metrics| metrics.event_fails.inc());
         } else if self.rate_limiter.is_blocked() {
-            METRICS.block.rate_limiter_throttled_events.inc();
+            self.metrics
+                .update(|metrics| metrics.rate_limiter_throttled_events.inc());
         } else {
             self.process_virtio_queues();
         }
@@ -210,7 +224,8 @@ impl Block {
     }
 
     pub(crate) fn process_rate_limiter_event(&mut self) {
-        METRICS.block.rate_limiter_event_count.inc();
+        self.metrics
+            .update(|metrics| metrics.rate_limiter_event_count.inc());
         // Upon rate limiter event, call the rate limiter handler
         // and restart processing the queue.
         if self.rate_limiter.event_handler().is_ok() && self.process_queue(0) {
@@ -218,6 +233,15 @@ impl Block {
         }
     }
 
+    pub(crate) fn process_io_scheduler_event(&mut self) {
+        if let Err(e) = self.io_scheduler_evt.read() {
+            error!("Failed to get the I/O scheduler event: {:?}", e);
+            self.metrics.update(|metrics| metrics.event_fails.inc());
+        } else if !self.rate_limiter.is_blocked() {
+            self.process_virtio_queues();
+        }
+    }
+
     pub fn process_queue(&mut self, queue_index: usize) -> bool {
         let mem = match self.device_state {
             DeviceState::Activated(ref mem) => mem,
@@ -226,17 +250,28 @@ impl Block {
         };
         let queue = &mut self.queues[queue_index];
         let mut used_any = false;
+        let mut yielded = false;
         while let Some(head) = queue.pop(mem) {
             let len;
             match Request::parse(&head, mem) {
                 Ok(request) => {
+                    // Let the drives sharing the I/O carry out their share of the requests,
+                    // before resuming on the I/O scheduler event.
+                    if !IO_SCHEDULER.dispatch(&self.id) {
+                        queue.undo_pop();
+                        self.metrics
+                            .update(|metrics| metrics.io_scheduler_yields.inc());
+                        yielded = true;
+                        break;
+                    }
                     // If limiter.consume() fails it means there is no more TokenType::Ops
                     // budget and rate limiting is in effect.
                     if !self.rate_limiter.consume(1, TokenType::Ops) {
                         // Stop processing the queue and return this descriptor chain to the
                         // avail ring, for later processing.
                         queue.undo_pop();
-                        METRICS.block.rate_limiter_throttled_events.inc();
+                        self.metrics
+                            .update(|metrics| metrics.rate_limiter_throttled_events.inc());
                         break;
                     }
                     // Exercise the rate limiter only if this request is of data transfer type.
@@ -254,18 +289,29 @@ impl Block {
                             // Stop processing the queue and return this descriptor chain to the
                             // avail ring, for later processing.
                             queue.undo_pop();
-                            METRICS.block.rate_limiter_throttled_events.inc();
+                            self.metrics
+                                .update(|metrics| metrics.rate_limiter_throttled_events.inc());
                             break;
                         }
                     }
-                    let status = match request.execute(&mut self.disk, mem) {
+                    let start = Instant::now();
+                    let result = request.execute(&mut self.disk, mem, &self.metrics);
+                    // Only the requests reaching the host disk tell about its latency.
+                    match request.request_type {
+                        RequestType::In | RequestType::Out | RequestType::Flush => {
+                            self.rate_limiter.record_latency(start.elapsed())
+                        }
+                        _ => (),
+                    }
+                    let status = match result {
                         Ok(l) => {
                             len = l;
                             VIRTIO_BLK_S_OK
                         }
                         Err(e) => {
                             error!("Failed to execute request: {:?}", e);
-                            METRICS.block.invalid_reqs_count.inc();
+                            self.metrics
+                                .update(|metrics| metrics.invalid_reqs_count.inc());
                             len = 1; // We need at least 1 byte for the status.
                             e.status()
                         }
@@ -276,7 +322,7 @@ impl Block {
                 }
                 Err(e) => {
                     error!("Failed to parse available descriptor chain: {:?}", e);
-                    METRICS.block.execute_fails.inc();
+                    self.metrics.update(|metrics| metrics.execute_fails.inc());
                     len = 0;
                 }
             }
@@ -290,8 +336,11 @@ impl Block {
             used_any = true;
         }
 
+        if !yielded {
+            IO_SCHEDULER.idle(&self.id);
+        }
         if !used_any {
-            METRICS.block.no_avail_buffer.inc();
+            self.metrics.update(|metrics| metrics.no_avail_buffer.inc());
         }
 
         used_any
@@ -303,7 +352,7 @@ impl Block {
 
         self.interrupt_evt.write(1).map_err(|e| {
             error!("Failed to signal used queue: {:?}", e);
-            METRICS.block.event_fails.inc();
+            self.metrics.update(|metrics| metrics.event_fails.inc());
             DeviceError::FailedSignalingUsedQueue(e)
         })?;
         Ok(())
@@ -320,7 +369,7 @@ impl Block {
             .fetch_or(VIRTIO_MMIO_INT_CONFIG as usize, Ordering::SeqCst);
         self.interrupt_evt.write(1).unwrap();
 
-        METRICS.block.update_count.inc();
+        self.metrics.update(|metrics| metrics.update_count.inc());
         Ok(())
     }
 
@@ -329,6 +378,32 @@ impl Block {
         self.rate_limiter.update_buckets(bytes, ops);
     }
 
+    /// Enables the tuning of the rate limiter to the latency of the requests, or disables it
+    /// with `None`.
+    pub fn set_adaptive_rate_limiter(&mut self, adaptive: Option<AdaptiveRate>) {
+        self.rate_limiter.set_adaptive(adaptive);
+    }
+
+    /// Sets the weight of the drive in the I/O scheduler, which shares the I/O between the drives
+    /// given a weight in proportion to their weights. Without a weight, the I/O of the drive is
+    /// not shared.
+    pub fn set_io_weight(&mut self, io_weight: Option<u32>) -> io::Result<()> {
+        match (self.io_weight, io_weight) {
+            (Some(_), Some(weight)) => IO_SCHEDULER.set_weight(&self.id, weight),
+            (_, Some(weight)) => {
+                IO_SCHEDULER.register(&self.id, weight, self.io_scheduler_evt.try_clone()?)
+            }
+            (_, None) => IO_SCHEDULER.unregister(&self.id),
+        }
+        self.io_weight = io_weight;
+        Ok(())
+    }
+
+    /// Provides the weight of the drive in the I/O scheduler, if its I/O is shared.
+    pub fn io_weight(&self) -> Option<u32> {
+        self.io_weight
+    }
+
     /// Provides the ID of this block device.
     pub fn id(&self) -> &String {
         &self.id
@@ -348,6 +423,23 @@ impl Block {
     pub fn is_root_device(&self) -> bool {
         self.root_device
     }
+
+    /// Provides the path of the backing file of this block device.
+    pub fn file_path(&self) -> &String {
+        self.disk.file_path()
+    }
+
+    /// Provides the rate limiter of this block device.
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Attaches the rate limiter of this block device to a group, whose budget it shares with
+    /// the other devices attached to it, or detaches it with `None`.
+    pub fn set_rate_limiter_group(&mut self, group: Option<&Arc<RateLimiterGroup>>) {
+        self.rate_limiter
+            .set_group(group.map(RateLimiterGroup::attach));
+    }
 }
 
 impl VirtioDevice for Block {
@@ -392,7 +484,7 @@ impl VirtioDevice for Block {
         let config_len = self.config_space.len() as u64;
         if offset >= config_len {
             error!("Failed to read config space");
-            METRICS.block.cfg_fails.inc();
+            self.metrics.update(|metrics| metrics.cfg_fails.inc());
             return;
         }
         if let Some(end) = offset.checked_add(data.len() as u64) {
@@ -407,7 +499,7 @@ impl VirtioDevice for Block {
         let config_len = self.config_space.len() as u64;
         if offset + data_len > config_len {
             error!("Failed to write config space");
-            METRICS.block.cfg_fails.inc();
+            self.metrics.update(|metrics| metrics.cfg_fails.inc());
             return;
         }
 
@@ -442,6 +534,7 @@ pub(crate) mod tests {
     use super::*;
     use crate::virtio::queue::tests::*;
     use polly::event_manager::{EventManager, Subscriber};
+    use rate_limiter::TokenBucket;
     use utils::epoll::{EpollEvent, EventSet};
     use utils::tempfile::TempFile;
     use vm_memory::GuestAddress;
@@ -682,11 +775,17 @@ pub(crate) mod tests {
             vq.dtable[1].len.set(8);
             mem.write_obj::<u64>(123_456_789, data_addr).unwrap();
 
+            let drive_write_count = block.metrics.device().write_count.count();
             check_metric_after_block!(
                 &METRICS.block.write_count,
                 1,
                 invoke_handler_for_queue_event(&mut block)
             );
+            // The write is also accounted to the drive.
+            assert_eq!(
+                block.metrics.device().write_count.count(),
+                drive_write_count + 1
+            );
 
             assert_eq!(vq.used.idx.get(), 1);
             assert_eq!(vq.used.ring[0].get().id, 0);
@@ -1012,4 +1111,70 @@ pub(crate) mod tests {
         assert_eq!(block.disk.file.metadata().unwrap().st_ino(), mdata.st_ino());
         assert_eq!(block.disk.image_id, id);
     }
+
+    #[test]
+    fn test_io_weight() {
+        let mut block = default_block();
+        // The other tests share the default ID, and must not be scheduled.
+        block.id = "io_weight".to_string();
+        assert_eq!(block.io_weight(), None);
+
+        block.set_io_weight(Some(2)).unwrap();
+        assert_eq!(block.io_weight(), Some(2));
+        assert_eq!(IO_SCHEDULER.weight("io_weight"), Some(2));
+        block.set_io_weight(Some(5)).unwrap();
+        assert_eq!(IO_SCHEDULER.weight("io_weight"), Some(5));
+
+        block.set_io_weight(None).unwrap();
+        assert_eq!(block.io_weight(), None);
+        assert_eq!(IO_SCHEDULER.weight("io_weight"), None);
+    }
+
+    #[test]
+    fn test_rate_limiter_group() {
+        let mut block = default_block();
+        assert_eq!(block.rate_limiter().group_id(), None);
+
+        // The group allows a single operation per second.
+        let ops = TokenBucket::new(1, 0, 1000);
+        let group = Arc::new(RateLimiterGroup::new("disks".to_string(), None, ops));
+        block.set_rate_limiter_group(Some(&group));
+        assert_eq!(block.rate_limiter().group_id(), Some("disks"));
+        // The own rate limiter of the drive limits nothing, the group does.
+        assert!(block.rate_limiter.consume(1, TokenType::Ops));
+        assert!(!block.rate_limiter.consume(1, TokenType::Ops));
+        assert!(block.rate_limiter().is_blocked());
+
+        block.set_rate_limiter_group(None);
+        assert_eq!(block.rate_limiter().group_id(), None);
+        assert_eq!(Arc::strong_count(&group), 1);
+    }
+
+    #[test]
+    fn test_adaptive_rate_limiter() {
+        let mut block = default_block();
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+
+        // No request can complete under the target, so the rate gets cut.
+        block.set_adaptive_rate_limiter(Some(AdaptiveRate::new(Duration::from_nanos(0), 10)));
+        // Let the adjustment interval elapse.
+        thread::sleep(Duration::from_millis(100));
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+            .unwrap();
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        vq.dtable[1].len.set(8);
+        invoke_handler_for_queue_event(&mut block);
+
+        assert_eq!(vq.used.idx.get(), 1);
+        assert_eq!(block.rate_limiter().adaptive().unwrap().rate_pct(), 75);
+
+        block.set_adaptive_rate_limiter(None);
+        assert!(block.rate_limiter().adaptive().is_none());
+    }
 }
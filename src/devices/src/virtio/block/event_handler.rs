@@ -62,12 +62,14 @@ impl Subscriber for Block {
         if self.is_activated() {
             let queue_evt = self.queue_evts[0].as_raw_fd();
             let rate_limiter_evt = self.rate_limiter.as_raw_fd();
+            let io_scheduler_evt = self.io_scheduler_evt.as_raw_fd();
             let activate_fd = self.activate_evt.as_raw_fd();
 
             // Looks better than C style if/else if/else.
             match source {
                 _ if queue_evt == source => self.process_queue_event(),
                 _ if rate_limiter_evt == source => self.process_rate_limiter_event(),
+                _ if io_scheduler_evt == source => self.process_io_scheduler_event(),
                 _ if activate_fd == source => self.process_activate_event(evmgr),
                 _ => warn!("Block: Spurious event received: {:?}", source),
             }
@@ -88,6 +90,7 @@ impl Subscriber for Block {
             vec![
                 EpollEvent::new(EventSet::IN, self.queue_evts[0].as_raw_fd() as u64),
                 EpollEvent::new(EventSet::IN, self.rate_limiter.as_raw_fd() as u64),
+                EpollEvent::new(EventSet::IN, self.io_scheduler_evt.as_raw_fd() as u64),
             ]
         } else {
             vec![EpollEvent::new(
@@ -96,6 +99,11 @@ impl Subscriber for Block {
             )]
         }
     }
+
+    // The drives are told apart in the dispatch metrics.
+    fn name(&self) -> String {
+        format!("block_{}", self.id)
+    }
 }
 
 #[cfg(test)]
@@ -120,6 +128,7 @@ pub mod tests {
 
         let block = Arc::new(Mutex::new(block));
         event_manager.add_subscriber(block.clone()).unwrap();
+        assert_eq!(block.lock().unwrap().name(), "block_test");
 
         let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
         let data_addr = GuestAddress(vq.dtable[1].addr.get());
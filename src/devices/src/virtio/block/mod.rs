@@ -11,8 +11,15 @@ pub use self::device::Block;
 pub use self::event_handler::*;
 pub use self::request::*;
 
+use lazy_static::lazy_static;
+use rate_limiter::fair_share::FairShareScheduler;
 use vm_memory::GuestMemoryError;
 
+lazy_static! {
+    /// Shares the I/O between the drives given a weight, in proportion to their weights.
+    pub(crate) static ref IO_SCHEDULER: FairShareScheduler = FairShareScheduler::default();
+}
+
 pub const CONFIG_SPACE_SIZE: usize = 8;
 pub const SECTOR_SHIFT: u8 = 9;
 pub const SECTOR_SIZE: u64 = (0x01 as u64) << SECTOR_SHIFT;
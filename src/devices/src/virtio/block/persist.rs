@@ -30,6 +30,13 @@ pub struct BlockState {
     rate_limiter_state: RateLimiterState,
 }
 
+impl BlockState {
+    /// Backs the restored drive with the file at `disk_path`, e.g. for a clone of the microVM.
+    pub fn set_disk_path(&mut self, disk_path: String) {
+        self.disk_path = disk_path;
+    }
+}
+
 pub struct BlockConstructorArgs {
     pub mem: GuestMemoryMmap,
 }
@@ -10,7 +10,7 @@ use std::io::{self, Seek, SeekFrom, Write};
 use std::mem;
 use std::result;
 
-use logger::{IncMetric, METRICS};
+use logger::{BlockDeviceMetrics, DeviceMetrics, IncMetric};
 use virtio_gen::virtio_blk::*;
 use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap};
 
@@ -196,6 +196,7 @@ impl Request {
         &self,
         disk: &mut DiskProperties,
         mem: &GuestMemoryMmap,
+        metrics: &DeviceMetrics<BlockDeviceMetrics>,
     ) -> result::Result<u32, ExecuteError> {
         let mut top: u64 = u64::from(self.data_len) / SECTOR_SIZE;
         if u64::from(self.data_len) % SECTOR_SIZE != 0 {
@@ -217,19 +218,23 @@ impl Request {
             RequestType::In => {
                 mem.read_from(self.data_addr, diskfile, self.data_len as usize)
                     .map_err(ExecuteError::Read)?;
-                METRICS.block.read_bytes.add(self.data_len as usize);
-                METRICS.block.read_count.inc();
+                metrics.update(|metrics| {
+                    metrics.read_bytes.add(self.data_len as usize);
+                    metrics.read_count.inc();
+                });
                 return Ok(self.data_len);
             }
             RequestType::Out => {
                 mem.write_to(self.data_addr, diskfile, self.data_len as usize)
                     .map_err(ExecuteError::Write)?;
-                METRICS.block.write_bytes.add(self.data_len as usize);
-                METRICS.block.write_count.inc();
+                metrics.update(|metrics| {
+                    metrics.write_bytes.add(self.data_len as usize);
+                    metrics.write_count.inc();
+                });
             }
             RequestType::Flush => match diskfile.flush() {
                 Ok(_) => {
-                    METRICS.block.flush_count.inc();
+                    metrics.update(|metrics| metrics.flush_count.inc());
                     return Ok(0);
                 }
                 Err(e) => return Err(ExecuteError::Flush(e)),
@@ -0,0 +1,853 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{error, warn, IncMetric, METRICS};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
+
+use super::*;
+use crate::virtio::{
+    ActivateError, ActivateResult, DescriptorChain, DeviceState, Queue, VirtioDevice, TYPE_CONSOLE,
+    VIRTIO_MMIO_INT_VRING,
+};
+
+// The device lets the guest discover its ports through the control queues.
+const VIRTIO_CONSOLE_F_MULTIPORT: u32 = 1;
+
+// Control events, as defined by the virtio spec.
+pub(crate) const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+pub(crate) const VIRTIO_CONSOLE_DEVICE_ADD: u16 = 1;
+pub(crate) const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+pub(crate) const VIRTIO_CONSOLE_CONSOLE_PORT: u16 = 4;
+pub(crate) const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+pub(crate) const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
+
+// The queues of the first port come first, followed by the control queues and the queues of
+// the other ports.
+pub(crate) const CONTROL_RX_QUEUE: usize = 2;
+pub(crate) const CONTROL_TX_QUEUE: usize = 3;
+
+// The host input is copied to the guest memory through a buffer of at most this size.
+const CHUNK_SIZE: usize = 4096;
+
+/// Returns the index of the receive queue of `port`.
+pub(crate) fn rx_queue_index(port: usize) -> usize {
+    if port == 0 {
+        0
+    } else {
+        2 * port + 2
+    }
+}
+
+/// Returns the index of the transmit queue of `port`.
+pub(crate) fn tx_queue_index(port: usize) -> usize {
+    rx_queue_index(port) + 1
+}
+
+/// Returns the port a data queue belongs to, along with whether it's its receive queue.
+pub(crate) fn queue_port(index: usize) -> Option<(usize, bool)> {
+    match index {
+        0 | 1 => Some((0, index == 0)),
+        CONTROL_RX_QUEUE | CONTROL_TX_QUEUE => None,
+        _ => Some(((index - 2) / 2, index % 2 == 0)),
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C)]
+pub(crate) struct ControlMessage {
+    pub id: u32,
+    pub event: u16,
+    pub value: u16,
+}
+
+// Safe because ControlMessage only contains plain data.
+unsafe impl ByteValued for ControlMessage {}
+
+impl ControlMessage {
+    fn new(id: usize, event: u16, value: u16) -> ControlMessage {
+        ControlMessage {
+            id: id as u32,
+            event,
+            value,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub(crate) struct ConfigSpace {
+    pub cols: u16,
+    pub rows: u16,
+    pub max_nr_ports: u32,
+    pub emerg_wr: u32,
+}
+
+// Safe because ConfigSpace only contains plain data.
+unsafe impl ByteValued for ConfigSpace {}
+
+/// A virtio-console device, exposing several named ports to the guest.
+pub struct Console {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: ConfigSpace,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    pub(crate) interrupt_evt: EventFd,
+    pub(crate) queue_evts: Vec<EventFd>,
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    pub(crate) ports: Vec<Port>,
+    // The control messages waiting for a buffer on the control receive queue.
+    pub(crate) pending_control: VecDeque<Vec<u8>>,
+}
+
+impl Console {
+    /// Creates a virtio-console device exposing `ports` to the guest, in this order.
+    pub fn new(ports: Vec<Port>) -> Result<Console> {
+        if ports.is_empty() || ports.len() > MAX_PORTS {
+            return Err(Error::InvalidPortCount(ports.len()));
+        }
+
+        let num_queues = num_queues(ports.len());
+        let mut queue_evts = Vec::with_capacity(num_queues);
+        for _ in 0..num_queues {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+        let queues = (0..num_queues).map(|_| Queue::new(QUEUE_SIZE)).collect();
+
+        Ok(Console {
+            avail_features: (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_CONSOLE_F_MULTIPORT),
+            acked_features: 0u64,
+            config_space: ConfigSpace {
+                max_nr_ports: ports.len() as u32,
+                ..Default::default()
+            },
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            ports,
+            pending_control: VecDeque::new(),
+        })
+    }
+
+    /// Provides the ID of this device.
+    pub fn id(&self) -> &str {
+        CONSOLE_DEV_ID
+    }
+
+    /// Provides the ports of this device.
+    pub fn ports(&self) -> &[Port] {
+        &self.ports
+    }
+
+    fn queue_control(&mut self, message: ControlMessage, payload: &[u8]) {
+        let mut bytes = message.as_slice().to_vec();
+        bytes.extend_from_slice(payload);
+        self.pending_control.push_back(bytes);
+    }
+
+    // Handles a control message sent by the guest driver.
+    fn handle_control(&mut self, message: ControlMessage) {
+        let id = message.id as usize;
+        match message.event {
+            VIRTIO_CONSOLE_DEVICE_READY if message.value == 1 => {
+                for port in 0..self.ports.len() {
+                    self.queue_control(
+                        ControlMessage::new(port, VIRTIO_CONSOLE_DEVICE_ADD, 1),
+                        &[],
+                    );
+                }
+            }
+            VIRTIO_CONSOLE_DEVICE_READY => {
+                error!("The guest failed to initialize the console device");
+                METRICS.console.control_fails.inc();
+            }
+            VIRTIO_CONSOLE_PORT_READY if id < self.ports.len() && message.value == 1 => {
+                if self.ports[id].is_console() {
+                    self.queue_control(
+                        ControlMessage::new(id, VIRTIO_CONSOLE_CONSOLE_PORT, 1),
+                        &[],
+                    );
+                }
+                let name = self.ports[id].name().as_bytes().to_vec();
+                self.queue_control(ControlMessage::new(id, VIRTIO_CONSOLE_PORT_NAME, 1), &name);
+                self.queue_control(ControlMessage::new(id, VIRTIO_CONSOLE_PORT_OPEN, 1), &[]);
+            }
+            VIRTIO_CONSOLE_PORT_OPEN if id < self.ports.len() => {
+                self.ports[id].guest_connected = message.value == 1;
+            }
+            _ => {
+                warn!("Unexpected console control message: {:?}", message);
+                METRICS.console.control_fails.inc();
+            }
+        }
+    }
+
+    pub(crate) fn process_control_tx(&mut self) -> Result<()> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let mut needs_interrupt = false;
+
+        while let Some(head) = self.queues[CONTROL_TX_QUEUE].pop(&mem) {
+            let message = if head.is_write_only()
+                || (head.len as usize) < std::mem::size_of::<ControlMessage>()
+            {
+                Err(Error::MalformedDescriptor)
+            } else {
+                mem.read_obj::<ControlMessage>(head.addr)
+                    .map_err(Error::GuestMemory)
+            };
+            let head_index = head.index;
+            self.queues[CONTROL_TX_QUEUE]
+                .add_used(&mem, head_index, 0)
+                .map_err(Error::Queue)?;
+            needs_interrupt = true;
+
+            match message {
+                Ok(message) => self.handle_control(message),
+                Err(e) => {
+                    error!("Failed to parse console control message: {:?}", e);
+                    METRICS.console.control_fails.inc();
+                }
+            }
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()?;
+        }
+
+        // Answer the guest right away.
+        self.process_control_rx()
+    }
+
+    pub(crate) fn process_control_rx(&mut self) -> Result<()> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let mut needs_interrupt = false;
+
+        while !self.pending_control.is_empty() {
+            let head = match self.queues[CONTROL_RX_QUEUE].pop(&mem) {
+                Some(head) => head,
+                None => break,
+            };
+            // The message is dropped if it doesn't fit, as the guest would not be able to make
+            // any sense of a partial one.
+            let message = self.pending_control.pop_front().unwrap();
+            let len = if !head.is_write_only() || (head.len as usize) < message.len() {
+                error!("Failed to send console control message: buffer too small");
+                METRICS.console.control_fails.inc();
+                0
+            } else if let Err(e) = mem.write_slice(&message, head.addr) {
+                error!("Failed to send console control message: {:?}", e);
+                METRICS.console.control_fails.inc();
+                0
+            } else {
+                message.len() as u32
+            };
+
+            self.queues[CONTROL_RX_QUEUE]
+                .add_used(&mem, head.index, len)
+                .map_err(Error::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()?;
+        }
+
+        Ok(())
+    }
+
+    // Sends the guest output of `port` to its host end.
+    pub(crate) fn process_port_tx(&mut self, port: usize) -> Result<()> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let queue_index = tx_queue_index(port);
+        let mut needs_interrupt = false;
+
+        while let Some(head) = self.queues[queue_index].pop(&mem) {
+            match read_output(&mem, &head) {
+                Ok(data) => {
+                    let written = self.ports[port].write_output(&data);
+                    METRICS.console.tx_bytes_count.add(written);
+                    METRICS.console.tx_dropped_bytes.add(data.len() - written);
+                }
+                Err(e) => {
+                    error!("Failed to read the console output: {:?}", e);
+                    METRICS.console.event_fails.inc();
+                }
+            }
+
+            self.queues[queue_index]
+                .add_used(&mem, head.index, 0)
+                .map_err(Error::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()?;
+        }
+
+        Ok(())
+    }
+
+    // Sends the host input of `port` to the guest, until either no more input is available or
+    // the guest runs out of buffers. Returns whether the host end hung up.
+    pub(crate) fn process_port_rx(&mut self, port: usize) -> Result<bool> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let queue_index = rx_queue_index(port);
+        let mut chunk = [0u8; CHUNK_SIZE];
+        let mut needs_interrupt = false;
+        let mut hung_up = false;
+
+        while let Some(head) = self.queues[queue_index].pop(&mem) {
+            let buffers = match input_buffers(&head) {
+                Ok(buffers) => buffers,
+                Err(e) => {
+                    error!("Failed to parse the console input buffers: {:?}", e);
+                    METRICS.console.event_fails.inc();
+                    self.queues[queue_index]
+                        .add_used(&mem, head.index, 0)
+                        .map_err(Error::Queue)?;
+                    needs_interrupt = true;
+                    continue;
+                }
+            };
+            let capacity: usize = buffers.iter().map(|(_, len)| *len as usize).sum();
+            let size = cmp::min(capacity, CHUNK_SIZE);
+
+            let len = match self.ports[port].read_input(&mut chunk[..size]) {
+                Ok(0) => {
+                    self.queues[queue_index].undo_pop();
+                    hung_up = true;
+                    break;
+                }
+                Ok(len) => len,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.queues[queue_index].undo_pop();
+                    break;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                    self.queues[queue_index].undo_pop();
+                    continue;
+                }
+                Err(e) => {
+                    error!("Failed to read the console input: {:?}", e);
+                    self.queues[queue_index].undo_pop();
+                    hung_up = true;
+                    break;
+                }
+            };
+
+            write_input(&mem, &buffers, &chunk[..len])?;
+            METRICS.console.rx_bytes_count.add(len);
+            self.queues[queue_index]
+                .add_used(&mem, head.index, len as u32)
+                .map_err(Error::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()?;
+        }
+
+        Ok(hung_up)
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<()> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal used queue: {:?}", e);
+            Error::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        let _ = self.process_control_tx();
+        for port in 0..self.ports.len() {
+            let _ = self.process_port_tx(port);
+            let _ = self.process_port_rx(port);
+        }
+    }
+}
+
+// Gathers the contents of the `head` descriptor chain, which must only hold read-only buffers.
+fn read_output(mem: &GuestMemoryMmap, head: &DescriptorChain) -> Result<Vec<u8>> {
+    let mut buffers = vec![(head.is_write_only(), head.addr, head.len)];
+    let mut next = head.next_descriptor();
+    while let Some(desc) = next {
+        buffers.push((desc.is_write_only(), desc.addr, desc.len));
+        next = desc.next_descriptor();
+    }
+
+    let mut data = Vec::new();
+    for (is_write_only, addr, len) in buffers {
+        if is_write_only {
+            return Err(Error::MalformedDescriptor);
+        }
+        let offset = data.len();
+        data.resize(offset + len as usize, 0);
+        mem.read_slice(&mut data[offset..], addr)
+            .map_err(Error::GuestMemory)?;
+    }
+
+    Ok(data)
+}
+
+// Collects the buffers of the `head` descriptor chain, which must all be write-only.
+fn input_buffers(head: &DescriptorChain) -> Result<Vec<(GuestAddress, u32)>> {
+    if !head.is_write_only() {
+        return Err(Error::MalformedDescriptor);
+    }
+    let mut buffers = vec![(head.addr, head.len)];
+    let mut next = head.next_descriptor();
+    while let Some(desc) = next {
+        if !desc.is_write_only() {
+            return Err(Error::MalformedDescriptor);
+        }
+        buffers.push((desc.addr, desc.len));
+        next = desc.next_descriptor();
+    }
+
+    Ok(buffers)
+}
+
+// Spreads `data` over the guest `buffers`, which are large enough to hold it.
+fn write_input(mem: &GuestMemoryMmap, buffers: &[(GuestAddress, u32)], data: &[u8]) -> Result<()> {
+    let mut written = 0;
+    for (addr, len) in buffers.iter() {
+        if written == data.len() {
+            break;
+        }
+        let size = cmp::min(*len as usize, data.len() - written);
+        mem.write_slice(&data[written..written + size], *addr)
+            .map_err(Error::GuestMemory)?;
+        written += size;
+    }
+
+    Ok(())
+}
+
+impl VirtioDevice for Console {
+    fn device_type(&self) -> u32 {
+        TYPE_CONSOLE
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            METRICS.console.cfg_fails.inc();
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The emergency write feature is not offered, so the configuration space is read-only
+        // for the driver.
+        error!("Failed to write config space");
+        METRICS.console.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Console: Cannot write to activate_evt");
+            METRICS.console.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::virtio::console::port::tests::socket_path;
+    use crate::virtio::test_utils::{default_mem, VirtQueue};
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+    impl Console {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    /// Creates a console device with a console port and an agent port, both backed by unix
+    /// domain sockets. Returns the device along with the socket paths.
+    pub(crate) fn default_console() -> (Console, String, String) {
+        let console_path = socket_path();
+        let agent_path = socket_path();
+        let ports = vec![
+            Port::new(
+                "console".to_string(),
+                PortBackend::Uds(console_path.clone()),
+                true,
+            )
+            .unwrap(),
+            Port::new(
+                "agent".to_string(),
+                PortBackend::Uds(agent_path.clone()),
+                false,
+            )
+            .unwrap(),
+        ];
+
+        (Console::new(ports).unwrap(), console_path, agent_path)
+    }
+
+    // Places a buffer on the queue.
+    fn push_buffer(queue: &VirtQueue, desc: u16, addr: u64, len: u32, flags: u16) {
+        let idx = queue.avail.idx.get();
+        queue.dtable[desc as usize].set(addr, len, flags, 0);
+        queue.avail.ring[idx as usize % 16].set(desc);
+        queue.avail.idx.set(idx + 1);
+    }
+
+    // Sends a control message from the guest driver to the device.
+    fn send_control(
+        console: &mut Console,
+        mem: &GuestMemoryMmap,
+        queue: &VirtQueue,
+        message: ControlMessage,
+    ) {
+        mem.write_obj(message, GuestAddress(0x8000)).unwrap();
+        push_buffer(queue, 0, 0x8000, 8, 0);
+        console.process_control_tx().unwrap();
+    }
+
+    #[test]
+    fn test_new() {
+        let (console, _, _) = default_console();
+
+        assert_eq!(console.device_type(), TYPE_CONSOLE);
+        assert_eq!(console.id(), CONSOLE_DEV_ID);
+        assert_eq!(
+            console.avail_features(),
+            (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_CONSOLE_F_MULTIPORT)
+        );
+        assert_eq!(console.acked_features(), 0);
+        assert_eq!(console.queues().len(), 6);
+        assert_eq!(console.queue_events().len(), 6);
+        assert_eq!(console.ports().len(), 2);
+        assert!(!console.is_activated());
+
+        match Console::new(vec![]) {
+            Err(Error::InvalidPortCount(0)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_queue_indexes() {
+        assert_eq!(rx_queue_index(0), 0);
+        assert_eq!(tx_queue_index(0), 1);
+        assert_eq!(rx_queue_index(1), 4);
+        assert_eq!(tx_queue_index(1), 5);
+        assert_eq!(rx_queue_index(2), 6);
+        assert_eq!(tx_queue_index(2), 7);
+
+        assert_eq!(queue_port(0), Some((0, true)));
+        assert_eq!(queue_port(1), Some((0, false)));
+        assert_eq!(queue_port(CONTROL_RX_QUEUE), None);
+        assert_eq!(queue_port(CONTROL_TX_QUEUE), None);
+        assert_eq!(queue_port(4), Some((1, true)));
+        assert_eq!(queue_port(7), Some((2, false)));
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let (mut console, _, _) = default_console();
+
+        let mut data = [0u8; 4];
+        console.read_config(4, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 2);
+
+        check_metric_after_block!(
+            METRICS.console.cfg_fails,
+            1,
+            console.read_config(12, &mut data)
+        );
+        check_metric_after_block!(METRICS.console.cfg_fails, 1, console.write_config(8, &data));
+    }
+
+    #[test]
+    fn test_control_handshake() {
+        let (mut console, _, _) = default_console();
+        let mem = default_mem();
+        let control_rx = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let control_tx = VirtQueue::new(GuestAddress(0x1000), &mem, 16);
+        console.set_queue(CONTROL_RX_QUEUE, control_rx.create_queue());
+        console.set_queue(CONTROL_TX_QUEUE, control_tx.create_queue());
+        console.activate(mem.clone()).unwrap();
+
+        // The device announces both ports, but has to wait for the guest buffers.
+        send_control(
+            &mut console,
+            &mem,
+            &control_tx,
+            ControlMessage::new(0, VIRTIO_CONSOLE_DEVICE_READY, 1),
+        );
+        assert_eq!(control_tx.used.idx.get(), 1);
+        assert_eq!(console.pending_control.len(), 2);
+        for desc in 0..2u16 {
+            push_buffer(
+                &control_rx,
+                desc,
+                0x9000 + u64::from(desc) * 0x100,
+                64,
+                VIRTQ_DESC_F_WRITE,
+            );
+        }
+        console.process_control_rx().unwrap();
+        assert!(console.pending_control.is_empty());
+        assert_eq!(control_rx.used.idx.get(), 2);
+        control_rx.check_used_elem(1, 1, 8);
+        let message: ControlMessage = mem.read_obj(GuestAddress(0x9100)).unwrap();
+        assert_eq!(
+            message,
+            ControlMessage::new(1, VIRTIO_CONSOLE_DEVICE_ADD, 1)
+        );
+
+        // The console port is flagged as such, then named and opened.
+        send_control(
+            &mut console,
+            &mem,
+            &control_tx,
+            ControlMessage::new(0, VIRTIO_CONSOLE_PORT_READY, 1),
+        );
+        assert_eq!(console.pending_control.len(), 3);
+        for desc in 2..5u16 {
+            push_buffer(
+                &control_rx,
+                desc,
+                0x9000 + u64::from(desc) * 0x100,
+                64,
+                VIRTQ_DESC_F_WRITE,
+            );
+        }
+        console.process_control_rx().unwrap();
+        let message: ControlMessage = mem.read_obj(GuestAddress(0x9200)).unwrap();
+        assert_eq!(
+            message,
+            ControlMessage::new(0, VIRTIO_CONSOLE_CONSOLE_PORT, 1)
+        );
+        control_rx.check_used_elem(3, 3, 8 + "console".len() as u32);
+        let mut name = [0u8; 7];
+        mem.read_slice(&mut name, GuestAddress(0x9308)).unwrap();
+        assert_eq!(&name, b"console");
+        let message: ControlMessage = mem.read_obj(GuestAddress(0x9400)).unwrap();
+        assert_eq!(message, ControlMessage::new(0, VIRTIO_CONSOLE_PORT_OPEN, 1));
+
+        // The guest opens the port.
+        assert!(!console.ports()[0].is_guest_connected());
+        send_control(
+            &mut console,
+            &mem,
+            &control_tx,
+            ControlMessage::new(0, VIRTIO_CONSOLE_PORT_OPEN, 1),
+        );
+        assert!(console.ports()[0].is_guest_connected());
+
+        // Messages about unknown ports are rejected.
+        check_metric_after_block!(
+            METRICS.console.control_fails,
+            1,
+            send_control(
+                &mut console,
+                &mem,
+                &control_tx,
+                ControlMessage::new(5, VIRTIO_CONSOLE_PORT_READY, 1),
+            )
+        );
+        assert!(console.pending_control.is_empty());
+
+        // A message that doesn't fit the guest buffer is dropped.
+        send_control(
+            &mut console,
+            &mem,
+            &control_tx,
+            ControlMessage::new(1, VIRTIO_CONSOLE_PORT_READY, 1),
+        );
+        push_buffer(&control_rx, 5, 0x9500, 4, VIRTQ_DESC_F_WRITE);
+        check_metric_after_block!(
+            METRICS.console.control_fails,
+            1,
+            console.process_control_rx().unwrap()
+        );
+        control_rx.check_used_elem(5, 5, 0);
+        assert_eq!(console.pending_control.len(), 1);
+    }
+
+    #[test]
+    fn test_port_tx() {
+        let (mut console, _, agent_path) = default_console();
+        let mem = default_mem();
+        let tx = VirtQueue::new(GuestAddress(0), &mem, 16);
+        console.set_queue(tx_queue_index(1), tx.create_queue());
+        console.activate(mem.clone()).unwrap();
+
+        // Without a host connection, the output is dropped.
+        mem.write_slice(b"hello", GuestAddress(0x8000)).unwrap();
+        push_buffer(&tx, 0, 0x8000, 5, 0);
+        check_metric_after_block!(
+            METRICS.console.tx_dropped_bytes,
+            5,
+            console.process_port_tx(1).unwrap()
+        );
+        tx.check_used_elem(0, 0, 0);
+
+        let mut client = UnixStream::connect(&agent_path).unwrap();
+        console.ports[1].accept().unwrap();
+
+        // The output spread over a descriptor chain reaches the host.
+        mem.write_slice(b" world", GuestAddress(0x9000)).unwrap();
+        let idx = tx.avail.idx.get();
+        tx.dtable[0].set(0x8000, 5, VIRTQ_DESC_F_NEXT, 1);
+        tx.dtable[1].set(0x9000, 6, 0, 0);
+        tx.avail.ring[idx as usize].set(0);
+        tx.avail.idx.set(idx + 1);
+        check_metric_after_block!(
+            METRICS.console.tx_bytes_count,
+            11,
+            console.process_port_tx(1).unwrap()
+        );
+        let mut data = [0u8; 11];
+        client.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"hello world");
+
+        // Write-only buffers are rejected.
+        push_buffer(&tx, 0, 0x8000, 5, VIRTQ_DESC_F_WRITE);
+        check_metric_after_block!(
+            METRICS.console.event_fails,
+            1,
+            console.process_port_tx(1).unwrap()
+        );
+        tx.check_used_elem(2, 0, 0);
+    }
+
+    #[test]
+    fn test_port_rx() {
+        let (mut console, console_path, _) = default_console();
+        let mem = default_mem();
+        let rx = VirtQueue::new(GuestAddress(0), &mem, 16);
+        console.set_queue(rx_queue_index(0), rx.create_queue());
+        console.activate(mem.clone()).unwrap();
+
+        // Without input, the guest buffer stays available.
+        push_buffer(&rx, 0, 0x8000, 4, VIRTQ_DESC_F_WRITE);
+        assert!(!console.process_port_rx(0).unwrap());
+        assert_eq!(rx.used.idx.get(), 0);
+
+        let mut client = UnixStream::connect(&console_path).unwrap();
+        console.ports[0].accept().unwrap();
+        client.write_all(b"ls -l\n").unwrap();
+
+        // The input is split according to the guest buffers.
+        check_metric_after_block!(
+            METRICS.console.rx_bytes_count,
+            4,
+            assert!(!console.process_port_rx(0).unwrap())
+        );
+        rx.check_used_elem(0, 0, 4);
+        push_buffer(&rx, 1, 0x9000, 64, VIRTQ_DESC_F_WRITE);
+        assert!(!console.process_port_rx(0).unwrap());
+        rx.check_used_elem(1, 1, 2);
+        let mut data = [0u8; 4];
+        mem.read_slice(&mut data, GuestAddress(0x8000)).unwrap();
+        assert_eq!(&data, b"ls -");
+        let mut data = [0u8; 2];
+        mem.read_slice(&mut data, GuestAddress(0x9000)).unwrap();
+        assert_eq!(&data, b"l\n");
+
+        // The host end hanging up is reported.
+        drop(client);
+        push_buffer(&rx, 2, 0x8000, 4, VIRTQ_DESC_F_WRITE);
+        assert!(console.process_port_rx(0).unwrap());
+        assert_eq!(rx.used.idx.get(), 2);
+    }
+}
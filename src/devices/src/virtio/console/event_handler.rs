@@ -0,0 +1,295 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+
+use logger::{debug, error, warn};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::report_console_event_fail;
+use crate::virtio::console::device::{queue_port, Console, CONTROL_RX_QUEUE, CONTROL_TX_QUEUE};
+use crate::virtio::VirtioDevice;
+
+impl Console {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        debug!("console: activate event");
+        if let Err(e) = self.activate_evt.read() {
+            error!("Failed to consume console activate event: {:?}", e);
+        }
+        let activate_fd = self.activate_evt.as_raw_fd();
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = match event_manager.subscriber(activate_fd) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                error!("Failed to process console activate evt: {:?}", e);
+                return;
+            }
+        };
+
+        // Interest list changes when the device is activated.
+        let interest_list = self.interest_list();
+        for event in interest_list {
+            event_manager
+                .register(event.data() as i32, event, self_subscriber.clone())
+                .unwrap_or_else(|e| {
+                    error!("Failed to register console events: {:?}", e);
+                });
+        }
+
+        event_manager.unregister(activate_fd).unwrap_or_else(|e| {
+            error!("Failed to unregister console activate evt: {:?}", e);
+        });
+    }
+
+    fn process_queue_event(&mut self, index: usize, event_manager: &mut EventManager) {
+        if let Err(e) = self.queue_evts[index].read() {
+            error!("Failed to get console queue event: {:?}", e);
+            return;
+        }
+
+        match queue_port(index) {
+            Some((port, true)) => self.process_port_input(port, event_manager),
+            Some((port, false)) => self
+                .process_port_tx(port)
+                .unwrap_or_else(report_console_event_fail),
+            None if index == CONTROL_RX_QUEUE => self
+                .process_control_rx()
+                .unwrap_or_else(report_console_event_fail),
+            None => {
+                debug_assert_eq!(index, CONTROL_TX_QUEUE);
+                self.process_control_tx()
+                    .unwrap_or_else(report_console_event_fail)
+            }
+        }
+    }
+
+    // Sends the pending host input of `port` to the guest, dropping the host connection once
+    // the other end hung up.
+    fn process_port_input(&mut self, port: usize, event_manager: &mut EventManager) {
+        match self.process_port_rx(port) {
+            Ok(true) => {
+                if let Some(stream) = self.ports[port].disconnect() {
+                    event_manager
+                        .unregister(stream.as_raw_fd())
+                        .unwrap_or_else(|e| {
+                            error!("Failed to unregister console connection: {:?}", e);
+                        });
+                }
+            }
+            Ok(false) => (),
+            Err(e) => report_console_event_fail(e),
+        }
+    }
+
+    fn process_listener_event(&mut self, port: usize, event_manager: &mut EventManager) {
+        let listener_fd = match self.ports[port].listener_fd() {
+            Some(fd) => fd,
+            None => return,
+        };
+        let old_stream = match self.ports[port].accept() {
+            Ok(old_stream) => old_stream,
+            Err(e) => {
+                report_console_event_fail(e);
+                return;
+            }
+        };
+        if let Some(stream) = old_stream {
+            event_manager
+                .unregister(stream.as_raw_fd())
+                .unwrap_or_else(|e| {
+                    error!("Failed to unregister console connection: {:?}", e);
+                });
+        }
+
+        // The subscriber must exist as we previously registered the listener via
+        // `interest_list()`.
+        let self_subscriber = match event_manager.subscriber(listener_fd) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                error!("Failed to process console connection: {:?}", e);
+                return;
+            }
+        };
+        if let Some(stream_fd) = self.ports[port].stream_fd() {
+            event_manager
+                .register(
+                    stream_fd,
+                    EpollEvent::new(EventSet::IN | EventSet::EDGE_TRIGGERED, stream_fd as u64),
+                    self_subscriber,
+                )
+                .unwrap_or_else(|e| {
+                    error!("Failed to register console connection: {:?}", e);
+                });
+        }
+
+        // Any input sent before the connection got registered is forwarded right away.
+        self.process_port_input(port, event_manager);
+    }
+}
+
+impl Subscriber for Console {
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+        let supported_events = EventSet::IN | EventSet::HANG_UP | EventSet::ERROR;
+
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            let activate_fd = self.activate_evt.as_raw_fd();
+
+            if let Some(index) = self
+                .queue_evts
+                .iter()
+                .position(|evt| evt.as_raw_fd() == source)
+            {
+                self.process_queue_event(index, evmgr);
+            } else if let Some(port) = self
+                .ports
+                .iter()
+                .position(|port| port.listener_fd() == Some(source))
+            {
+                self.process_listener_event(port, evmgr);
+            } else if let Some(port) = self
+                .ports
+                .iter()
+                .position(|port| port.stream_fd() == Some(source))
+            {
+                // A hang up is detected when reading the remaining input.
+                self.process_port_input(port, evmgr);
+            } else if source == activate_fd {
+                self.process_activate_event(evmgr);
+            } else {
+                warn!("Console: Spurious event received: {:?}", source);
+            }
+        } else {
+            warn!(
+                "Console: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        // This function can be called during different points in the device lifetime:
+        //  - shortly after device creation,
+        //  - on device activation (is-activated already true at this point),
+        //  - on device restore from snapshot.
+        if self.is_activated() {
+            let mut interest_list: Vec<EpollEvent> = self
+                .queue_evts
+                .iter()
+                .map(|evt| EpollEvent::new(EventSet::IN, evt.as_raw_fd() as u64))
+                .collect();
+            for port in self.ports.iter() {
+                if let Some(fd) = port.listener_fd() {
+                    interest_list.push(EpollEvent::new(EventSet::IN, fd as u64));
+                }
+                if let Some(fd) = port.stream_fd() {
+                    interest_list.push(EpollEvent::new(
+                        EventSet::IN | EventSet::EDGE_TRIGGERED,
+                        fd as u64,
+                    ));
+                }
+            }
+            interest_list
+        } else {
+            vec![EpollEvent::new(
+                EventSet::IN,
+                self.activate_evt.as_raw_fd() as u64,
+            )]
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::console::device::tests::default_console;
+    use crate::virtio::console::device::{rx_queue_index, tx_queue_index};
+    use crate::virtio::test_utils::{default_mem, VirtQueue};
+    use crate::virtio::VIRTQ_DESC_F_WRITE;
+    use vm_memory::{Bytes, GuestAddress};
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let (mut console, _, agent_path) = default_console();
+        let mem = default_mem();
+        let rx = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let tx = VirtQueue::new(GuestAddress(0x1000), &mem, 16);
+        console.set_queue(rx_queue_index(1), rx.create_queue());
+        console.set_queue(tx_queue_index(1), tx.create_queue());
+
+        let console = Arc::new(Mutex::new(console));
+        event_manager.add_subscriber(console.clone()).unwrap();
+
+        // Push some guest output on the agent port transmit queue.
+        mem.write_slice(b"ping", GuestAddress(0x8000)).unwrap();
+        tx.avail.idx.set(1);
+        tx.avail.ring[0].set(0);
+        tx.dtable[0].set(0x8000, 4, 0, 0);
+        console.lock().unwrap().queue_evts[tx_queue_index(1)]
+            .write(1)
+            .unwrap();
+
+        // EventManager should report no events since the device has only registered
+        // its activation event so far (even though there is also a queue event pending).
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Now activate the device.
+        console.lock().unwrap().activate(mem.clone()).unwrap();
+        // Process the activate event.
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // Handle the previously pushed queue event. Nobody listens on the host end yet.
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(tx.used.idx.get(), 1);
+
+        // A host connection is accepted, and its input reaches the guest.
+        let mut client = UnixStream::connect(&agent_path).unwrap();
+        client.write_all(b"pong").unwrap();
+        rx.avail.idx.set(1);
+        rx.avail.ring[0].set(0);
+        rx.dtable[0].set(0x9000, 16, VIRTQ_DESC_F_WRITE, 0);
+        event_manager.run_with_timeout(100).unwrap();
+        assert!(console.lock().unwrap().ports()[1].stream_fd().is_some());
+        assert_eq!(rx.used.idx.get(), 1);
+        rx.check_used_elem(0, 0, 4);
+        let mut data = [0u8; 4];
+        mem.read_slice(&mut data, GuestAddress(0x9000)).unwrap();
+        assert_eq!(&data, b"pong");
+
+        // The guest output reaches the host connection.
+        tx.avail.idx.set(2);
+        tx.avail.ring[1].set(0);
+        console.lock().unwrap().queue_evts[tx_queue_index(1)]
+            .write(1)
+            .unwrap();
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(tx.used.idx.get(), 2);
+        client.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"ping");
+
+        // The connection is dropped once the host end hangs up.
+        drop(client);
+        rx.avail.idx.set(2);
+        rx.avail.ring[1].set(0);
+        event_manager.run_with_timeout(100).unwrap();
+        assert!(console.lock().unwrap().ports()[1].stream_fd().is_none());
+    }
+}
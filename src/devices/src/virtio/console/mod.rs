@@ -0,0 +1,51 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements the virtio-console device, which exposes several named ports to the guest. Each
+//! port is backed on the host by a unix domain socket or by a pipe, so the guest logs, the
+//! interactive console and the guest agents can use separate channels.
+
+pub mod device;
+pub mod event_handler;
+pub mod persist;
+pub mod port;
+
+use vm_memory::GuestMemoryError;
+
+pub use self::device::Console;
+pub use self::port::{Port, PortBackend};
+
+/// Device ID used in MMIO device identification.
+/// Because the console device is unique per-vm, this ID can be hardcoded.
+pub const CONSOLE_DEV_ID: &str = "console";
+pub const QUEUE_SIZE: u16 = 256;
+/// Maximum number of ports of the console device.
+pub const MAX_PORTS: usize = 16;
+
+/// Returns the number of queues used by a console device with `num_ports` ports: a receive and
+/// a transmit queue per port, plus the control queues.
+pub fn num_queues(num_ports: usize) -> usize {
+    2 * num_ports + 2
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to set up the host end of a port.
+    Backend(std::io::Error),
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Failed to signal the virtio used queue.
+    FailedSignalingUsedQueue(std::io::Error),
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// The device must have between 1 and `MAX_PORTS` ports.
+    InvalidPortCount(usize),
+    /// Guest gave us a malformed descriptor.
+    MalformedDescriptor,
+    /// Error while processing the virt queues.
+    Queue(super::QueueError),
+    /// Error restoring the console device queues.
+    QueueRestoreError,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,183 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring console devices.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::GuestMemoryMmap;
+
+use super::*;
+
+use crate::virtio::persist::VirtioDeviceState;
+use crate::virtio::{DeviceState, TYPE_CONSOLE};
+
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct PortState {
+    name: String,
+    uds_path: Option<String>,
+    pipe_path: Option<String>,
+    is_console: bool,
+    guest_connected: bool,
+}
+
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct ConsoleState {
+    ports: Vec<PortState>,
+    pending_control: Vec<Vec<u8>>,
+    virtio_state: VirtioDeviceState,
+}
+
+pub struct ConsoleConstructorArgs {
+    pub mem: GuestMemoryMmap,
+}
+
+impl Persist<'_> for Console {
+    type State = ConsoleState;
+    type ConstructorArgs = ConsoleConstructorArgs;
+    type Error = super::Error;
+
+    fn save(&self) -> Self::State {
+        let ports = self
+            .ports
+            .iter()
+            .map(|port| {
+                let (uds_path, pipe_path) = match port.backend() {
+                    PortBackend::Uds(path) => (Some(path.clone()), None),
+                    PortBackend::Pipe(path) => (None, Some(path.clone())),
+                };
+                PortState {
+                    name: port.name().to_string(),
+                    uds_path,
+                    pipe_path,
+                    is_console: port.is_console(),
+                    guest_connected: port.is_guest_connected(),
+                }
+            })
+            .collect();
+
+        ConsoleState {
+            ports,
+            pending_control: self.pending_control.iter().cloned().collect(),
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        // The host connections are not part of the snapshot: the host ends are set up again,
+        // waiting for new connections.
+        let mut ports = Vec::with_capacity(state.ports.len());
+        for port_state in state.ports.iter() {
+            let backend = match (&port_state.uds_path, &port_state.pipe_path) {
+                (Some(path), None) => PortBackend::Uds(path.clone()),
+                (None, Some(path)) => PortBackend::Pipe(path.clone()),
+                _ => {
+                    return Err(Error::Backend(std::io::Error::from(
+                        std::io::ErrorKind::InvalidData,
+                    )))
+                }
+            };
+            let mut port = Port::new(port_state.name.clone(), backend, port_state.is_console)?;
+            port.guest_connected = port_state.guest_connected;
+            ports.push(port);
+        }
+        let mut console = Console::new(ports)?;
+
+        console.queues = state
+            .virtio_state
+            .build_queues_checked(
+                &constructor_args.mem,
+                TYPE_CONSOLE,
+                num_queues(state.ports.len()),
+                QUEUE_SIZE,
+            )
+            .map_err(|_| Self::Error::QueueRestoreError)?;
+        console.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
+        console.avail_features = state.virtio_state.avail_features;
+        console.acked_features = state.virtio_state.acked_features;
+        console.pending_control = state.pending_control.iter().cloned().collect();
+
+        if state.virtio_state.activated {
+            console.device_state = DeviceState::Activated(constructor_args.mem);
+        }
+
+        Ok(console)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::console::port::tests::socket_path;
+    use crate::virtio::device::VirtioDevice;
+    use crate::virtio::test_utils::default_mem;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_persistence() {
+        let guest_mem = default_mem();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        // Create and save the console device.
+        let path = socket_path();
+        let port = Port::new("agent".to_string(), PortBackend::Uds(path.clone()), true).unwrap();
+        let mut console = Console::new(vec![port]).unwrap();
+        console.ports[0].guest_connected = true;
+        console.pending_control.push_back(vec![1, 2, 3]);
+        <Console as Persist>::save(&console)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // The socket path is still in use.
+        let state = ConsoleState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap();
+        assert!(Console::restore(
+            ConsoleConstructorArgs {
+                mem: guest_mem.clone()
+            },
+            &state
+        )
+        .is_err());
+
+        // Deserialize and restore the console device.
+        drop(console);
+        let console = Console::new(vec![Port::new(
+            "agent".to_string(),
+            PortBackend::Uds(socket_path()),
+            true,
+        )
+        .unwrap()])
+        .unwrap();
+        let restored_console =
+            Console::restore(ConsoleConstructorArgs { mem: guest_mem }, &state).unwrap();
+
+        assert_eq!(restored_console.device_type(), TYPE_CONSOLE);
+        assert_eq!(restored_console.acked_features, console.acked_features);
+        assert_eq!(restored_console.avail_features, console.avail_features);
+        assert_eq!(restored_console.queues(), console.queues());
+        assert_eq!(
+            restored_console.interrupt_status().load(Ordering::Relaxed),
+            console.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored_console.is_activated(), console.is_activated());
+        assert_eq!(restored_console.ports().len(), 1);
+        let port = &restored_console.ports()[0];
+        assert_eq!(port.name(), "agent");
+        assert_eq!(port.backend(), &PortBackend::Uds(path));
+        assert!(port.is_console());
+        assert!(port.is_guest_connected());
+        assert_eq!(
+            restored_console.pending_control.iter().collect::<Vec<_>>(),
+            vec![&vec![1u8, 2, 3]]
+        );
+    }
+}
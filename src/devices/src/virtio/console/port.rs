@@ -0,0 +1,265 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use logger::{IncMetric, METRICS};
+
+use super::{Error, Result};
+
+/// The host end of a console port.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PortBackend {
+    /// A unix domain socket created at the given path. A single host connection is served at a
+    /// time, a new connection replacing the previous one.
+    Uds(String),
+    /// An existing named pipe, or regular file, the guest output is appended to. The guest
+    /// doesn't receive any input through such a port.
+    Pipe(String),
+}
+
+enum HostEnd {
+    Uds {
+        listener: UnixListener,
+        stream: Option<UnixStream>,
+    },
+    Pipe(File),
+}
+
+/// A port of the console device, connecting a guest channel to its host end.
+pub struct Port {
+    name: String,
+    backend: PortBackend,
+    is_console: bool,
+    pub(crate) guest_connected: bool,
+    host_end: HostEnd,
+}
+
+impl Port {
+    /// Creates a port named `name`, setting up its host end right away. The guest uses the port
+    /// marked with `is_console` as an interactive console.
+    pub fn new(name: String, backend: PortBackend, is_console: bool) -> Result<Port> {
+        let host_end = match &backend {
+            PortBackend::Uds(path) => {
+                let listener = UnixListener::bind(path).map_err(Error::Backend)?;
+                listener.set_nonblocking(true).map_err(Error::Backend)?;
+                HostEnd::Uds {
+                    listener,
+                    stream: None,
+                }
+            }
+            PortBackend::Pipe(path) => {
+                // Opening a named pipe for both reading and writing doesn't wait for a reader,
+                // and the writes don't fail when there is none.
+                let file = OpenOptions::new()
+                    .read(true)
+                    .append(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(path)
+                    .map_err(Error::Backend)?;
+                HostEnd::Pipe(file)
+            }
+        };
+
+        Ok(Port {
+            name,
+            backend,
+            is_console,
+            guest_connected: false,
+            host_end,
+        })
+    }
+
+    /// Provides the name of the port.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Provides the host end configuration of the port.
+    pub fn backend(&self) -> &PortBackend {
+        &self.backend
+    }
+
+    /// Returns whether the guest uses the port as a console.
+    pub fn is_console(&self) -> bool {
+        self.is_console
+    }
+
+    /// Returns whether a guest application has the port open.
+    pub fn is_guest_connected(&self) -> bool {
+        self.guest_connected
+    }
+
+    pub(crate) fn listener_fd(&self) -> Option<RawFd> {
+        match &self.host_end {
+            HostEnd::Uds { listener, .. } => Some(listener.as_raw_fd()),
+            HostEnd::Pipe(_) => None,
+        }
+    }
+
+    pub(crate) fn stream_fd(&self) -> Option<RawFd> {
+        match &self.host_end {
+            HostEnd::Uds {
+                stream: Some(stream),
+                ..
+            } => Some(stream.as_raw_fd()),
+            _ => None,
+        }
+    }
+
+    // Accepts a pending host connection, which replaces the current one. Returns the replaced
+    // connection, so that the caller can stop monitoring it.
+    pub(crate) fn accept(&mut self) -> Result<Option<UnixStream>> {
+        match &mut self.host_end {
+            HostEnd::Uds { listener, stream } => {
+                let (new_stream, _) = listener.accept().map_err(Error::Backend)?;
+                new_stream.set_nonblocking(true).map_err(Error::Backend)?;
+                METRICS.console.connections.inc();
+                Ok(stream.replace(new_stream))
+            }
+            HostEnd::Pipe(_) => Ok(None),
+        }
+    }
+
+    // Drops the current host connection, returning it so that the caller can stop monitoring
+    // it.
+    pub(crate) fn disconnect(&mut self) -> Option<UnixStream> {
+        match &mut self.host_end {
+            HostEnd::Uds { stream, .. } => stream.take(),
+            HostEnd::Pipe(_) => None,
+        }
+    }
+
+    // Sends the guest output to the host, returning the number of bytes written. The host end
+    // is never waited for: what can't be written right away is dropped.
+    pub(crate) fn write_output(&mut self, data: &[u8]) -> usize {
+        let writer: &mut dyn Write = match &mut self.host_end {
+            HostEnd::Uds {
+                stream: Some(stream),
+                ..
+            } => stream,
+            HostEnd::Uds { stream: None, .. } => return 0,
+            HostEnd::Pipe(file) => file,
+        };
+
+        let mut written = 0;
+        while written < data.len() {
+            match writer.write(&data[written..]) {
+                Ok(0) => break,
+                Ok(len) => written += len,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        written
+    }
+
+    // Reads the host input into `buf`. Fails with `WouldBlock` when no input is available.
+    pub(crate) fn read_input(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.host_end {
+            HostEnd::Uds {
+                stream: Some(stream),
+                ..
+            } => stream.read(buf),
+            _ => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+        }
+    }
+}
+
+impl Drop for Port {
+    fn drop(&mut self) {
+        // The socket file would otherwise prevent any other port from using the same path.
+        if let PortBackend::Uds(path) = &self.backend {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    /// Returns a path where a unix domain socket can be created.
+    pub(crate) fn socket_path() -> String {
+        let mut tmp_file = TempFile::new().unwrap();
+        tmp_file.remove().unwrap();
+        tmp_file.as_path().to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_uds_port() {
+        let path = socket_path();
+        let mut port =
+            Port::new("agent".to_string(), PortBackend::Uds(path.clone()), false).unwrap();
+        assert_eq!(port.name(), "agent");
+        assert_eq!(port.backend(), &PortBackend::Uds(path.clone()));
+        assert!(!port.is_console());
+        assert!(!port.is_guest_connected());
+        assert!(port.listener_fd().is_some());
+        assert!(port.stream_fd().is_none());
+
+        // The path is already in use.
+        assert!(Port::new("other".to_string(), PortBackend::Uds(path.clone()), false).is_err());
+
+        // Without a host connection, the output is dropped and there is no input.
+        assert_eq!(port.write_output(b"hello"), 0);
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            port.read_input(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        assert!(port.accept().unwrap().is_none());
+        assert!(port.stream_fd().is_some());
+        assert_eq!(port.write_output(b"hello"), 5);
+        let mut data = [0u8; 5];
+        client.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"hello");
+
+        client.write_all(b"world").unwrap();
+        assert_eq!(port.read_input(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"world");
+
+        // A new connection replaces the current one.
+        let _client2 = UnixStream::connect(&path).unwrap();
+        assert!(port.accept().unwrap().is_some());
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+
+        assert!(port.disconnect().is_some());
+        assert!(port.stream_fd().is_none());
+
+        // The socket file is removed along with the port.
+        drop(port);
+        assert!(!std::path::Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_pipe_port() {
+        let file = TempFile::new().unwrap();
+        let path = file.as_path().to_str().unwrap().to_string();
+        let mut port =
+            Port::new("logs".to_string(), PortBackend::Pipe(path.clone()), false).unwrap();
+        assert!(port.listener_fd().is_none());
+        assert!(port.stream_fd().is_none());
+
+        assert_eq!(port.write_output(b"log line\n"), 9);
+        assert_eq!(std::fs::read(&path).unwrap(), b"log line\n");
+        assert!(port.accept().unwrap().is_none());
+        assert!(port.disconnect().is_none());
+
+        // The pipe must exist.
+        assert!(Port::new(
+            "logs".to_string(),
+            PortBackend::Pipe("/invalid/path".to_string()),
+            false
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,403 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{error, IncMetric, METRICS};
+use serde::{Deserialize, Serialize};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use virtio_gen::virtio_ring::{VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC};
+use vm_memory::{ByteValued, GuestAddress, GuestMemory, GuestMemoryMmap};
+
+use super::*;
+use crate::virtio::{
+    ActivateError, ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_FS, VIRTIO_MMIO_INT_VRING,
+};
+
+// The virtio features which can be offered to the guest, if the backend supports them. The
+// backend handles the virtqueues by itself, so these only describe its abilities.
+const SUPPORTED_FEATURES: u64 = (1u64 << VIRTIO_F_VERSION_1)
+    | (1u64 << VIRTIO_RING_F_EVENT_IDX)
+    | (1u64 << VIRTIO_RING_F_INDIRECT_DESC);
+
+/// The caching policy of the guest page cache for the shared files, which the backend is
+/// expected to enforce.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum CacheMode {
+    /// The guest does not cache the file data nor the metadata.
+    None,
+    /// The guest caches the file data and the metadata for a short while.
+    Auto,
+    /// The guest caches the file data and the metadata until the files are closed.
+    Always,
+}
+
+impl Default for CacheMode {
+    fn default() -> Self {
+        CacheMode::Auto
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ConfigSpace {
+    tag: [u8; MAX_TAG_LEN],
+    num_request_queues: u32,
+}
+
+// Safe because ConfigSpace only contains plain data.
+unsafe impl ByteValued for ConfigSpace {}
+
+/// A virtio-fs device, whose virtqueues are handed over to a vhost-user backend.
+pub struct SharedFs {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    config_space: ConfigSpace,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    pub(crate) interrupt_evt: EventFd,
+    // Also given to the backend as kick eventfds, since they are signaled by KVM on queue
+    // notifications.
+    pub(crate) queue_evts: Vec<EventFd>,
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    id: String,
+    tag: String,
+    cache_mode: CacheMode,
+    // Signaled by the backend when it uses buffers from the matching queue.
+    pub(crate) call_evts: Vec<EventFd>,
+    backend: VhostUserFrontend,
+}
+
+impl SharedFs {
+    /// Creates a virtio-fs device exposing the filesystem served by `backend` under `tag`.
+    pub fn new(
+        id: String,
+        tag: String,
+        cache_mode: CacheMode,
+        mut backend: VhostUserFrontend,
+    ) -> Result<SharedFs> {
+        if tag.is_empty() || tag.len() > MAX_TAG_LEN {
+            return Err(Error::InvalidTag);
+        }
+        let mut config_space = ConfigSpace {
+            tag: [0u8; MAX_TAG_LEN],
+            num_request_queues: (NUM_QUEUES as u32 - 1).to_le(),
+        };
+        config_space.tag[..tag.len()].copy_from_slice(tag.as_bytes());
+
+        backend.set_owner().map_err(Error::VhostUser)?;
+        let backend_features = backend.get_features().map_err(Error::VhostUser)?;
+
+        let mut queue_evts = Vec::with_capacity(NUM_QUEUES);
+        let mut call_evts = Vec::with_capacity(NUM_QUEUES);
+        for _ in QUEUE_SIZES.iter() {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+            call_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+        let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+
+        Ok(SharedFs {
+            avail_features: (backend_features & SUPPORTED_FEATURES) | 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            config_space,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            id,
+            tag,
+            cache_mode,
+            call_evts,
+            backend,
+        })
+    }
+
+    /// Provides the ID of this device.
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    /// Provides the tag the guest mounts the filesystem with.
+    pub fn tag(&self) -> &String {
+        &self.tag
+    }
+
+    /// Provides the caching policy of the shared files.
+    pub fn cache_mode(&self) -> CacheMode {
+        self.cache_mode
+    }
+
+    // Hands the guest memory and the virtqueues set up by the driver over to the backend.
+    fn setup_backend(&mut self, mem: &GuestMemoryMmap) -> Result<()> {
+        let host_addr = |addr: GuestAddress| {
+            mem.get_host_address(addr)
+                .map(|addr| addr as u64)
+                .map_err(Error::GuestMemory)
+        };
+
+        let backend = &mut self.backend;
+        backend
+            .set_features(self.acked_features)
+            .map_err(Error::VhostUser)?;
+        backend.set_mem_table(mem).map_err(Error::VhostUser)?;
+        for (index, queue) in self.queues.iter().enumerate() {
+            if !queue.ready {
+                continue;
+            }
+            let descriptor = host_addr(queue.desc_table)?;
+            let used = host_addr(queue.used_ring)?;
+            let available = host_addr(queue.avail_ring)?;
+            backend
+                .set_vring_num(index, queue.actual_size())
+                .map_err(Error::VhostUser)?;
+            backend
+                .set_vring_addr(index, descriptor, used, available)
+                .map_err(Error::VhostUser)?;
+            backend
+                .set_vring_base(index, queue.next_avail.0)
+                .map_err(Error::VhostUser)?;
+            backend
+                .set_vring_call(index, self.call_evts[index].as_raw_fd())
+                .map_err(Error::VhostUser)?;
+            backend
+                .set_vring_kick(index, self.queue_evts[index].as_raw_fd())
+                .map_err(Error::VhostUser)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn process_call_event(&mut self, index: usize) -> Result<()> {
+        self.call_evts[index].read().map_err(Error::EventFd)?;
+        METRICS.shared_fs.backend_notifications.inc();
+        self.signal_used_queue()
+    }
+
+    fn signal_used_queue(&self) -> Result<()> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt
+            .write(1)
+            .map_err(Error::FailedSignalingUsedQueue)
+    }
+}
+
+impl VirtioDevice for SharedFs {
+    fn device_type(&self) -> u32 {
+        TYPE_FS
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The configuration space is read-only for the driver.
+        error!("Failed to write config space");
+        METRICS.shared_fs.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if let Err(e) = self.setup_backend(&mem) {
+            error!("Shared fs {}: Cannot set up the backend: {:?}", self.id, e);
+            METRICS.shared_fs.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        if self.activate_evt.write(1).is_err() {
+            error!("Shared fs {}: Cannot write to activate_evt", self.id);
+            METRICS.shared_fs.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::virtio::fs::vhost_user::tests::{read_request, reply_features};
+    use crate::virtio::test_utils::{default_mem, VirtQueue};
+
+    // Creates a device whose backend is the returned end of a socket pair.
+    pub(crate) fn default_shared_fs(backend_features: u64) -> (SharedFs, UnixStream) {
+        let (frontend, mut backend) = UnixStream::pair().unwrap();
+        reply_features(&mut backend, backend_features);
+        let shared_fs = SharedFs::new(
+            String::from("fs0"),
+            String::from("myfs"),
+            CacheMode::default(),
+            VhostUserFrontend::from_stream(frontend),
+        )
+        .unwrap();
+        // SET_OWNER and GET_FEATURES.
+        read_request(&mut backend);
+        read_request(&mut backend);
+
+        (shared_fs, backend)
+    }
+
+    #[test]
+    fn test_new() {
+        let (shared_fs, _backend) =
+            default_shared_fs(1u64 << VIRTIO_RING_F_EVENT_IDX | 1u64 << 30 | 1u64 << 33);
+
+        assert_eq!(shared_fs.device_type(), TYPE_FS);
+        assert_eq!(shared_fs.id(), "fs0");
+        assert_eq!(shared_fs.tag(), "myfs");
+        assert_eq!(shared_fs.cache_mode(), CacheMode::Auto);
+        // Only the supported features are offered to the guest.
+        assert_eq!(
+            shared_fs.avail_features(),
+            1u64 << VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_RING_F_EVENT_IDX
+        );
+        assert_eq!(shared_fs.queues().len(), NUM_QUEUES);
+        assert!(!shared_fs.is_activated());
+
+        // The tag must fit the configuration space.
+        for tag in &["", "a_tag_which_is_way_too_long_to_fit_the_config"] {
+            let (frontend, _) = UnixStream::pair().unwrap();
+            assert!(matches!(
+                SharedFs::new(
+                    String::from("fs0"),
+                    tag.to_string(),
+                    CacheMode::None,
+                    VhostUserFrontend::from_stream(frontend),
+                ),
+                Err(Error::InvalidTag)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let (mut shared_fs, _backend) = default_shared_fs(0);
+
+        let mut tag = [0u8; MAX_TAG_LEN];
+        shared_fs.read_config(0, &mut tag);
+        assert_eq!(&tag[..4], b"myfs");
+        assert!(tag[4..].iter().all(|&b| b == 0));
+
+        let mut num_request_queues = [0u8; 4];
+        shared_fs.read_config(MAX_TAG_LEN as u64, &mut num_request_queues);
+        assert_eq!(u32::from_le_bytes(num_request_queues), 1);
+
+        // Reading past the configuration space leaves the buffer untouched.
+        let mut data = [0xffu8; 4];
+        shared_fs.read_config(MAX_TAG_LEN as u64 + 4, &mut data);
+        assert_eq!(data, [0xffu8; 4]);
+
+        check_metric_after_block!(
+            METRICS.shared_fs.cfg_fails,
+            1,
+            shared_fs.write_config(0, &[0u8; 4])
+        );
+    }
+
+    #[test]
+    fn test_activate() {
+        let (mut shared_fs, mut backend) = default_shared_fs(0);
+        let mem = default_mem();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        shared_fs.queues[1] = queue.create_queue();
+
+        // The anonymous guest memory cannot be shared with the backend.
+        check_metric_after_block!(
+            METRICS.shared_fs.activate_fails,
+            1,
+            assert!(shared_fs.activate(mem).is_err())
+        );
+        assert!(!shared_fs.is_activated());
+        // SET_FEATURES is sent before the memory table fails to build.
+        read_request(&mut backend);
+    }
+
+    #[test]
+    fn test_process_call_event() {
+        let (mut shared_fs, _backend) = default_shared_fs(0);
+
+        shared_fs.call_evts[1].write(1).unwrap();
+        check_metric_after_block!(
+            METRICS.shared_fs.backend_notifications,
+            1,
+            shared_fs.process_call_event(1).unwrap()
+        );
+        assert_eq!(
+            shared_fs.interrupt_status().load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_VRING as usize
+        );
+        assert_eq!(shared_fs.interrupt_evt().read().unwrap(), 1);
+
+        // There is no pending notification anymore.
+        assert!(shared_fs.process_call_event(1).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+
+use logger::{debug, error, warn};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::report_shared_fs_event_fail;
+use crate::virtio::{fs::device::SharedFs, VirtioDevice};
+
+impl SharedFs {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        debug!("shared fs: activate event");
+        if let Err(e) = self.activate_evt.read() {
+            error!("Failed to consume shared fs activate event: {:?}", e);
+        }
+        let activate_fd = self.activate_evt.as_raw_fd();
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = match event_manager.subscriber(activate_fd) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                error!("Failed to process shared fs activate evt: {:?}", e);
+                return;
+            }
+        };
+
+        // Interest list changes when the device is activated.
+        let interest_list = self.interest_list();
+        for event in interest_list {
+            event_manager
+                .register(event.data() as i32, event, self_subscriber.clone())
+                .unwrap_or_else(|e| {
+                    error!("Failed to register shared fs events: {:?}", e);
+                });
+        }
+
+        event_manager.unregister(activate_fd).unwrap_or_else(|e| {
+            error!("Failed to unregister shared fs activate evt: {:?}", e);
+        });
+    }
+}
+
+impl Subscriber for SharedFs {
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+        let supported_events = EventSet::IN;
+
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            let activate_fd = self.activate_evt.as_raw_fd();
+
+            match self
+                .call_evts
+                .iter()
+                .position(|evt| evt.as_raw_fd() == source)
+            {
+                Some(index) => self
+                    .process_call_event(index)
+                    .unwrap_or_else(report_shared_fs_event_fail),
+                None if source == activate_fd => self.process_activate_event(evmgr),
+                None => warn!("Shared fs: Spurious event received: {:?}", source),
+            }
+        } else {
+            warn!(
+                "Shared fs: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        // This function can be called during different points in the device lifetime:
+        //  - shortly after device creation,
+        //  - on device activation (is-activated already true at this point).
+        if self.is_activated() {
+            self.call_evts
+                .iter()
+                .map(|evt| EpollEvent::new(EventSet::IN, evt.as_raw_fd() as u64))
+                .collect()
+        } else {
+            vec![EpollEvent::new(
+                EventSet::IN,
+                self.activate_evt.as_raw_fd() as u64,
+            )]
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::fs::device::tests::default_shared_fs;
+    use crate::virtio::test_utils::default_mem;
+    use crate::virtio::{DeviceState, VIRTIO_MMIO_INT_VRING};
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let (shared_fs, _backend) = default_shared_fs(0);
+        let shared_fs = Arc::new(Mutex::new(shared_fs));
+        event_manager.add_subscriber(shared_fs.clone()).unwrap();
+
+        // The device isn't activated, so the backend notifications are not processed.
+        shared_fs.lock().unwrap().call_evts[1].write(1).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Mark the device as activated, as the backend can't be set up in this test, and
+        // trigger the activation event.
+        {
+            let mut shared_fs = shared_fs.lock().unwrap();
+            shared_fs.device_state = DeviceState::Activated(default_mem());
+            shared_fs.activate_evt.write(1).unwrap();
+        }
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // The pending backend notification is now forwarded to the guest.
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        let shared_fs = shared_fs.lock().unwrap();
+        assert_eq!(
+            shared_fs.interrupt_status().load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_VRING as usize
+        );
+        assert_eq!(shared_fs.interrupt_evt().read().unwrap(), 1);
+    }
+}
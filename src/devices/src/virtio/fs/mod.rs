@@ -0,0 +1,37 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements the virtio-fs device, which shares a host directory with the guest. The requests
+//! of the guest are served by an external vhost-user backend, such as virtiofsd.
+
+pub mod device;
+pub mod event_handler;
+pub mod vhost_user;
+
+use vm_memory::GuestMemoryError;
+
+pub use self::device::{CacheMode, SharedFs};
+pub use self::vhost_user::VhostUserFrontend;
+
+pub const QUEUE_SIZE: u16 = 1024;
+// The high priority queue, followed by a single request queue.
+pub const NUM_QUEUES: usize = 2;
+pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; NUM_QUEUES];
+/// Maximum length of the tag the guest mounts the filesystem with.
+pub const MAX_TAG_LEN: usize = 36;
+
+#[derive(Debug)]
+pub enum Error {
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Failed to signal the virtio used queue.
+    FailedSignalingUsedQueue(std::io::Error),
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// The tag is empty or longer than `MAX_TAG_LEN` bytes.
+    InvalidTag,
+    /// Error while talking to the vhost-user backend.
+    VhostUser(vhost_user::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,489 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal vhost-user frontend, implementing the requests needed to hand the virtqueues of a
+//! device over to a backend process listening on a unix domain socket.
+
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use vm_memory::{ByteValued, GuestMemory, GuestMemoryMmap, GuestMemoryRegion, MemoryRegionAddress};
+
+/// Feature bit advertising the support of the vhost-user protocol features. It is not a virtio
+/// feature, so it must never be offered to the guest.
+pub const VHOST_USER_F_PROTOCOL_FEATURES: u32 = 30;
+/// Maximum number of guest memory regions which can be shared with a backend.
+pub const MAX_MEM_REGIONS: usize = 8;
+
+// Version of the protocol, carried in the flags of every message.
+const VHOST_USER_VERSION: u32 = 0x1;
+// Flag set by the backend on its replies.
+const VHOST_USER_REPLY_FLAG: u32 = 0x4;
+
+/// Errors triggered when talking to a vhost-user backend.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to connect to the backend socket.
+    Connect(io::Error),
+    /// The backend sent a reply which does not match the request.
+    InvalidReply,
+    /// A guest memory region cannot be shared because it is not backed by a file.
+    MemoryNotShared,
+    /// Failed to receive a reply from the backend.
+    Receive(io::Error),
+    /// Failed to send a request to the backend.
+    Send(io::Error),
+    /// The guest memory is split in more regions than a backend accepts.
+    TooManyMemoryRegions(usize),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Request {
+    GetFeatures = 1,
+    SetFeatures = 2,
+    SetOwner = 3,
+    SetMemTable = 5,
+    SetVringNum = 8,
+    SetVringAddr = 9,
+    SetVringBase = 10,
+    SetVringKick = 12,
+    SetVringCall = 13,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Header {
+    request: u32,
+    flags: u32,
+    size: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for Header {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct U64Payload {
+    value: u64,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for U64Payload {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct VringState {
+    index: u32,
+    num: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for VringState {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct VringAddr {
+    index: u32,
+    flags: u32,
+    descriptor: u64,
+    used: u64,
+    available: u64,
+    log: u64,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for VringAddr {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct MemoryTableHeader {
+    num_regions: u32,
+    padding: u32,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for MemoryTableHeader {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct MemoryRegion {
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    mmap_offset: u64,
+}
+
+// Safe because it only has data and has no implicit padding.
+unsafe impl ByteValued for MemoryRegion {}
+
+// Sends `buf` over the `fd` socket, along with the `fds` file descriptors.
+fn send_with_fds(fd: RawFd, buf: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let fds_len = mem::size_of_val(fds) as u32;
+    // Safe because `CMSG_SPACE` only computes a size.
+    let cmsg_space = unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+    // The control message buffer must be aligned for `cmsghdr`.
+    let mut cmsg_buf = vec![0u64; (cmsg_space + 7) / 8];
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // Safe because `msghdr` is plain old data, for which zero is a valid value.
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if !fds.is_empty() {
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = cmsg_space as _;
+        // Safe because the control buffer is large enough for a header and `fds`, as computed
+        // by `CMSG_SPACE`.
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+            std::ptr::copy_nonoverlapping(
+                fds.as_ptr(),
+                libc::CMSG_DATA(cmsg) as *mut RawFd,
+                fds.len(),
+            );
+        }
+    }
+
+    loop {
+        // Safe because `msg` only points to buffers which outlive the call, and we check the
+        // result.
+        let ret = unsafe { libc::sendmsg(fd, &msg, 0) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if ret as usize != buf.len() {
+            return Err(io::Error::from(io::ErrorKind::WriteZero));
+        }
+        return Ok(());
+    }
+}
+
+/// The frontend side of a vhost-user connection.
+pub struct VhostUserFrontend {
+    sock: UnixStream,
+}
+
+impl VhostUserFrontend {
+    /// Connects to the backend listening on `socket_path`.
+    pub fn connect<P: AsRef<Path>>(socket_path: P) -> Result<Self> {
+        UnixStream::connect(socket_path)
+            .map(Self::from_stream)
+            .map_err(Error::Connect)
+    }
+
+    /// Creates a frontend talking to the backend over an already connected socket.
+    pub fn from_stream(sock: UnixStream) -> Self {
+        VhostUserFrontend { sock }
+    }
+
+    /// Claims the backend for this frontend.
+    pub fn set_owner(&mut self) -> Result<()> {
+        self.send(Request::SetOwner, &[], &[])
+    }
+
+    /// Gets the virtio features supported by the backend.
+    pub fn get_features(&mut self) -> Result<u64> {
+        self.send(Request::GetFeatures, &[], &[])?;
+        self.recv_reply::<U64Payload>(Request::GetFeatures)
+            .map(|payload| payload.value)
+    }
+
+    /// Sets the virtio features acked by the driver.
+    pub fn set_features(&mut self, features: u64) -> Result<()> {
+        let payload = U64Payload { value: features };
+        self.send(Request::SetFeatures, payload.as_slice(), &[])
+    }
+
+    /// Shares the guest memory with the backend. All the regions must be backed by a file.
+    pub fn set_mem_table(&mut self, mem: &GuestMemoryMmap) -> Result<()> {
+        let num_regions = mem.num_regions();
+        if num_regions > MAX_MEM_REGIONS {
+            return Err(Error::TooManyMemoryRegions(num_regions));
+        }
+
+        let mut regions = Vec::with_capacity(num_regions);
+        let mut fds = Vec::with_capacity(num_regions);
+        mem.with_regions(|_, region| {
+            let file_offset = region.file_offset().ok_or(Error::MemoryNotShared)?;
+            let host_addr = region
+                .get_host_address(MemoryRegionAddress(0))
+                .map_err(|_| Error::MemoryNotShared)?;
+            regions.push(MemoryRegion {
+                guest_phys_addr: region.start_addr().0,
+                memory_size: region.len(),
+                userspace_addr: host_addr as u64,
+                mmap_offset: file_offset.start(),
+            });
+            fds.push(file_offset.file().as_raw_fd());
+            Ok(())
+        })?;
+
+        let header = MemoryTableHeader {
+            num_regions: num_regions as u32,
+            padding: 0,
+        };
+        let mut payload = header.as_slice().to_vec();
+        for region in regions.iter() {
+            payload.extend_from_slice(region.as_slice());
+        }
+        self.send(Request::SetMemTable, &payload, &fds)
+    }
+
+    /// Sets the number of descriptors of the `index` queue.
+    pub fn set_vring_num(&mut self, index: usize, num: u16) -> Result<()> {
+        let payload = VringState {
+            index: index as u32,
+            num: u32::from(num),
+        };
+        self.send(Request::SetVringNum, payload.as_slice(), &[])
+    }
+
+    /// Sets the addresses of the `index` queue rings, in the address space of this process.
+    pub fn set_vring_addr(
+        &mut self,
+        index: usize,
+        descriptor: u64,
+        used: u64,
+        available: u64,
+    ) -> Result<()> {
+        let payload = VringAddr {
+            index: index as u32,
+            flags: 0,
+            descriptor,
+            used,
+            available,
+            log: 0,
+        };
+        self.send(Request::SetVringAddr, payload.as_slice(), &[])
+    }
+
+    /// Sets the index of the next available descriptor of the `index` queue.
+    pub fn set_vring_base(&mut self, index: usize, base: u16) -> Result<()> {
+        let payload = VringState {
+            index: index as u32,
+            num: u32::from(base),
+        };
+        self.send(Request::SetVringBase, payload.as_slice(), &[])
+    }
+
+    /// Sets the eventfd signaled by the driver when it adds buffers to the `index` queue.
+    pub fn set_vring_kick(&mut self, index: usize, fd: RawFd) -> Result<()> {
+        let payload = U64Payload {
+            value: index as u64,
+        };
+        self.send(Request::SetVringKick, payload.as_slice(), &[fd])
+    }
+
+    /// Sets the eventfd signaled by the backend when it uses buffers of the `index` queue.
+    pub fn set_vring_call(&mut self, index: usize, fd: RawFd) -> Result<()> {
+        let payload = U64Payload {
+            value: index as u64,
+        };
+        self.send(Request::SetVringCall, payload.as_slice(), &[fd])
+    }
+
+    fn send(&mut self, request: Request, payload: &[u8], fds: &[RawFd]) -> Result<()> {
+        let header = Header {
+            request: request as u32,
+            flags: VHOST_USER_VERSION,
+            size: payload.len() as u32,
+        };
+        let mut buf = header.as_slice().to_vec();
+        buf.extend_from_slice(payload);
+        send_with_fds(self.sock.as_raw_fd(), &buf, fds).map_err(Error::Send)
+    }
+
+    fn recv_reply<T: ByteValued + Default>(&mut self, request: Request) -> Result<T> {
+        let mut header = Header::default();
+        self.sock
+            .read_exact(header.as_mut_slice())
+            .map_err(Error::Receive)?;
+        if header.request != request as u32
+            || header.flags & VHOST_USER_REPLY_FLAG == 0
+            || header.size as usize != mem::size_of::<T>()
+        {
+            return Err(Error::InvalidReply);
+        }
+
+        let mut payload = T::default();
+        self.sock
+            .read_exact(payload.as_mut_slice())
+            .map_err(Error::Receive)?;
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use utils::tempfile::TempFile;
+    use vm_memory::{FileOffset, GuestAddress};
+
+    // Reads a request from the backend end of the connection.
+    pub(crate) fn read_request(backend: &mut UnixStream) -> (u32, Vec<u8>) {
+        let mut header = Header::default();
+        backend.read_exact(header.as_mut_slice()).unwrap();
+        assert_eq!(header.flags, VHOST_USER_VERSION);
+        let mut payload = vec![0u8; header.size as usize];
+        backend.read_exact(&mut payload).unwrap();
+        (header.request, payload)
+    }
+
+    // Queues the reply to a `GET_FEATURES` request on the backend end of the connection.
+    pub(crate) fn reply_features(backend: &mut UnixStream, features: u64) {
+        let header = Header {
+            request: Request::GetFeatures as u32,
+            flags: VHOST_USER_VERSION | VHOST_USER_REPLY_FLAG,
+            size: mem::size_of::<U64Payload>() as u32,
+        };
+        backend.write_all(header.as_slice()).unwrap();
+        backend
+            .write_all(U64Payload { value: features }.as_slice())
+            .unwrap();
+    }
+
+    fn frontend() -> (VhostUserFrontend, UnixStream) {
+        let (frontend, backend) = UnixStream::pair().unwrap();
+        (VhostUserFrontend::from_stream(frontend), backend)
+    }
+
+    #[test]
+    fn test_connect() {
+        let tmp_file = TempFile::new().unwrap();
+        assert!(matches!(
+            VhostUserFrontend::connect(tmp_file.as_path()),
+            Err(Error::Connect(_))
+        ));
+    }
+
+    #[test]
+    fn test_features() {
+        let (mut frontend, mut backend) = frontend();
+
+        reply_features(&mut backend, 0x1234);
+        assert_eq!(frontend.get_features().unwrap(), 0x1234);
+        assert_eq!(
+            read_request(&mut backend),
+            (Request::GetFeatures as u32, vec![])
+        );
+
+        frontend.set_features(0x5678).unwrap();
+        let (request, payload) = read_request(&mut backend);
+        assert_eq!(request, Request::SetFeatures as u32);
+        assert_eq!(payload, 0x5678u64.to_ne_bytes().to_vec());
+
+        // A reply to another request is rejected.
+        let header = Header {
+            request: Request::SetOwner as u32,
+            flags: VHOST_USER_VERSION | VHOST_USER_REPLY_FLAG,
+            size: 8,
+        };
+        backend.write_all(header.as_slice()).unwrap();
+        assert!(matches!(frontend.get_features(), Err(Error::InvalidReply)));
+    }
+
+    #[test]
+    fn test_vring_setup() {
+        let (mut frontend, mut backend) = frontend();
+
+        frontend.set_owner().unwrap();
+        assert_eq!(
+            read_request(&mut backend),
+            (Request::SetOwner as u32, vec![])
+        );
+
+        frontend.set_vring_num(1, 256).unwrap();
+        let (request, payload) = read_request(&mut backend);
+        assert_eq!(request, Request::SetVringNum as u32);
+        assert_eq!(payload, VringState { index: 1, num: 256 }.as_slice());
+
+        frontend.set_vring_addr(1, 0x1000, 0x2000, 0x3000).unwrap();
+        let (request, payload) = read_request(&mut backend);
+        assert_eq!(request, Request::SetVringAddr as u32);
+        let addr = VringAddr {
+            index: 1,
+            flags: 0,
+            descriptor: 0x1000,
+            used: 0x2000,
+            available: 0x3000,
+            log: 0,
+        };
+        assert_eq!(payload, addr.as_slice());
+
+        frontend.set_vring_base(1, 5).unwrap();
+        let (request, payload) = read_request(&mut backend);
+        assert_eq!(request, Request::SetVringBase as u32);
+        assert_eq!(payload, VringState { index: 1, num: 5 }.as_slice());
+
+        let evt = utils::eventfd::EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        frontend.set_vring_kick(1, evt.as_raw_fd()).unwrap();
+        let (request, payload) = read_request(&mut backend);
+        assert_eq!(request, Request::SetVringKick as u32);
+        assert_eq!(payload, 1u64.to_ne_bytes().to_vec());
+
+        frontend.set_vring_call(1, evt.as_raw_fd()).unwrap();
+        let (request, payload) = read_request(&mut backend);
+        assert_eq!(request, Request::SetVringCall as u32);
+        assert_eq!(payload, 1u64.to_ne_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_set_mem_table() {
+        let (mut frontend, mut backend) = frontend();
+
+        // Anonymous memory cannot be shared.
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        assert!(matches!(
+            frontend.set_mem_table(&mem),
+            Err(Error::MemoryNotShared)
+        ));
+
+        let file = TempFile::new().unwrap().into_file();
+        file.set_len(0x2000).unwrap();
+        let mem = GuestMemoryMmap::from_ranges_with_files(
+            &[(
+                GuestAddress(0x1000),
+                0x1000,
+                Some(FileOffset::new(file, 0x1000)),
+            )],
+            false,
+        )
+        .unwrap();
+        frontend.set_mem_table(&mem).unwrap();
+
+        let (request, payload) = read_request(&mut backend);
+        assert_eq!(request, Request::SetMemTable as u32);
+        assert_eq!(
+            payload.len(),
+            mem::size_of::<MemoryTableHeader>() + mem::size_of::<MemoryRegion>()
+        );
+        let mut region = MemoryRegion::default();
+        region
+            .as_mut_slice()
+            .copy_from_slice(&payload[mem::size_of::<MemoryTableHeader>()..]);
+        assert_eq!(region.guest_phys_addr, 0x1000);
+        assert_eq!(region.memory_size, 0x1000);
+        assert_eq!(region.mmap_offset, 0x1000);
+        assert_eq!(
+            region.userspace_addr,
+            mem.get_host_address(GuestAddress(0x1000)).unwrap() as u64
+        );
+    }
+}
@@ -0,0 +1,714 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{error, IncMetric, METRICS};
+use serde::Serialize;
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
+
+use super::*;
+use crate::virtio::{
+    ActivateError, ActivateResult, DescriptorChain, DeviceState, Queue, VirtioDevice, TYPE_MEM,
+    VIRTIO_MMIO_INT_VRING,
+};
+
+const BITS_PER_WORD: usize = 64;
+const REQUEST_SIZE: usize = std::mem::size_of::<Request>();
+const RESPONSE_SIZE: usize = std::mem::size_of::<Response>();
+
+// The layout of the configuration space, as defined by the virtio-mem specification.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C)]
+pub(crate) struct ConfigSpace {
+    pub block_size: u64,
+    pub node_id: u16,
+    pub padding: [u8; 6],
+    pub addr: u64,
+    pub region_size: u64,
+    pub usable_region_size: u64,
+    pub plugged_size: u64,
+    pub requested_size: u64,
+}
+
+// Safe because ConfigSpace only contains plain data.
+unsafe impl ByteValued for ConfigSpace {}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct Request {
+    req_type: u16,
+    padding: [u16; 3],
+    // The address and the number of blocks are shared by the plug, unplug and state requests.
+    addr: u64,
+    nb_blocks: u16,
+    padding_1: [u16; 3],
+}
+
+// Safe because Request only contains plain data.
+unsafe impl ByteValued for Request {}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct Response {
+    resp_type: u16,
+    padding: [u16; 3],
+    // Only meaningful for the state requests.
+    state: u16,
+}
+
+// Safe because Response only contains plain data.
+unsafe impl ByteValued for Response {}
+
+impl Response {
+    fn new(resp_type: u16) -> Self {
+        Response {
+            resp_type,
+            ..Default::default()
+        }
+    }
+}
+
+/// The sizes of the hotplug memory region, as reported through the API.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct VirtioMemStatus {
+    /// Size of the hotplug memory region in MiB.
+    pub total_size_mib: u64,
+    /// Size of the memory blocks in MiB.
+    pub block_size_mib: u64,
+    /// Amount of memory plugged by the guest in MiB.
+    pub plugged_size_mib: u64,
+    /// Amount of memory the guest is requested to plug in MiB.
+    pub requested_size_mib: u64,
+}
+
+/// A virtio-mem device, managing the memory blocks of a region of guest memory which is left
+/// out of the boot memory map.
+pub struct VirtioMem {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: ConfigSpace,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    pub(crate) interrupt_evt: EventFd,
+    pub(crate) queue_evts: [EventFd; NUM_QUEUES],
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    // One bit per block of the region, set while the block is plugged.
+    pub(crate) plugged_blocks: Vec<u64>,
+    pub(crate) restored: bool,
+}
+
+impl VirtioMem {
+    /// Creates a virtio-mem device managing the `region_size` bytes of guest memory starting
+    /// at `region_addr`, in blocks of `block_size` bytes. No block is plugged initially.
+    pub fn new(
+        region_addr: GuestAddress,
+        region_size: u64,
+        block_size: u64,
+        restored: bool,
+    ) -> Result<VirtioMem> {
+        if !block_size.is_power_of_two() || block_size < MIN_BLOCK_SIZE {
+            return Err(Error::InvalidBlockSize);
+        }
+        if region_size == 0 || region_size % block_size != 0 {
+            return Err(Error::InvalidRegionSize);
+        }
+
+        let num_blocks = (region_size / block_size) as usize;
+        let queue_evts = [EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?];
+        let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+
+        Ok(VirtioMem {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            config_space: ConfigSpace {
+                block_size,
+                addr: region_addr.0,
+                region_size,
+                usable_region_size: region_size,
+                ..Default::default()
+            },
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            plugged_blocks: vec![0u64; (num_blocks + BITS_PER_WORD - 1) / BITS_PER_WORD],
+            restored,
+        })
+    }
+
+    /// Provides the ID of this device.
+    pub fn id(&self) -> &str {
+        MEM_DEV_ID
+    }
+
+    /// Provides the start address of the hotplug memory region.
+    pub fn region_addr(&self) -> GuestAddress {
+        GuestAddress(self.config_space.addr)
+    }
+
+    /// Provides the size of the hotplug memory region in bytes.
+    pub fn region_size(&self) -> u64 {
+        self.config_space.region_size
+    }
+
+    /// Provides the size of the memory blocks in bytes.
+    pub fn block_size(&self) -> u64 {
+        self.config_space.block_size
+    }
+
+    /// Provides the bitmap of the blocks plugged by the guest, one bit per block of the region.
+    pub fn plugged_blocks(&self) -> &[u64] {
+        &self.plugged_blocks
+    }
+
+    /// Provides the amount of memory plugged by the guest in bytes.
+    pub fn plugged_size(&self) -> u64 {
+        self.config_space.plugged_size
+    }
+
+    /// Provides the amount of memory the guest is requested to plug in bytes.
+    pub fn requested_size(&self) -> u64 {
+        self.config_space.requested_size
+    }
+
+    /// Provides the sizes of the hotplug memory region, in MiB.
+    pub fn status(&self) -> VirtioMemStatus {
+        VirtioMemStatus {
+            total_size_mib: self.region_size() >> 20,
+            block_size_mib: self.block_size() >> 20,
+            plugged_size_mib: self.plugged_size() >> 20,
+            requested_size_mib: self.requested_size() >> 20,
+        }
+    }
+
+    /// Sets the amount of memory the guest is requested to plug. The caller is in charge of
+    /// notifying the driver of the configuration change.
+    pub fn update_requested_size(&mut self, requested_size: u64) -> Result<()> {
+        if requested_size % self.block_size() != 0
+            || requested_size > self.config_space.usable_region_size
+        {
+            return Err(Error::InvalidRequestedSize);
+        }
+        self.config_space.requested_size = requested_size;
+
+        Ok(())
+    }
+
+    pub(crate) fn process_queue_event(&mut self) -> Result<()> {
+        self.queue_evts[0].read().map_err(Error::EventFd)?;
+        self.process_queue()
+    }
+
+    pub(crate) fn process_queue(&mut self) -> Result<()> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let mut needs_interrupt = false;
+
+        while let Some(head) = self.queues[0].pop(&mem) {
+            let head_index = head.index;
+            let len = match self.process_request(&mem, head) {
+                Ok(len) => len,
+                Err(e) => {
+                    error!("Failed to process virtio-mem request: {:?}", e);
+                    METRICS.mem.request_fails.inc();
+                    0
+                }
+            };
+
+            self.queues[0]
+                .add_used(&mem, head_index, len)
+                .map_err(Error::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()?;
+        }
+
+        Ok(())
+    }
+
+    // Handles the request held by the `head` descriptor chain, returning the number of bytes
+    // written in its response descriptor.
+    fn process_request(&mut self, mem: &GuestMemoryMmap, head: DescriptorChain) -> Result<u32> {
+        if head.is_write_only() || (head.len as usize) < REQUEST_SIZE {
+            return Err(Error::MalformedDescriptor);
+        }
+        let resp_desc = head
+            .next_descriptor()
+            .filter(|desc| desc.is_write_only() && desc.len as usize >= RESPONSE_SIZE)
+            .ok_or(Error::MalformedDescriptor)?;
+
+        let request: Request = mem.read_obj(head.addr).map_err(Error::GuestMemory)?;
+        let response = match request.req_type {
+            VIRTIO_MEM_REQ_PLUG => self.plug(request.addr, request.nb_blocks),
+            VIRTIO_MEM_REQ_UNPLUG => self.unplug(mem, request.addr, request.nb_blocks),
+            VIRTIO_MEM_REQ_UNPLUG_ALL => self.unplug_all(mem),
+            VIRTIO_MEM_REQ_STATE => self.state(request.addr, request.nb_blocks),
+            _ => Response::new(VIRTIO_MEM_RESP_ERROR),
+        };
+        if response.resp_type != VIRTIO_MEM_RESP_ACK {
+            METRICS.mem.request_fails.inc();
+        }
+
+        mem.write_obj(response, resp_desc.addr)
+            .map_err(Error::GuestMemory)?;
+        Ok(RESPONSE_SIZE as u32)
+    }
+
+    fn plug(&mut self, addr: u64, nb_blocks: u16) -> Response {
+        let blocks = match self.block_range(addr, nb_blocks) {
+            Some(blocks) => blocks,
+            None => return Response::new(VIRTIO_MEM_RESP_ERROR),
+        };
+        let size = u64::from(nb_blocks) * self.block_size();
+        if self.config_space.plugged_size + size > self.config_space.requested_size {
+            return Response::new(VIRTIO_MEM_RESP_NACK);
+        }
+        if blocks.clone().any(|block| self.is_plugged(block)) {
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        }
+
+        // The blocks are already backed by the region, which only takes up host memory once
+        // the guest touches it.
+        self.set_plugged(blocks, true);
+        self.config_space.plugged_size += size;
+        METRICS.mem.plug_count.inc();
+        Response::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn unplug(&mut self, mem: &GuestMemoryMmap, addr: u64, nb_blocks: u16) -> Response {
+        let blocks = match self.block_range(addr, nb_blocks) {
+            Some(blocks) => blocks,
+            None => return Response::new(VIRTIO_MEM_RESP_ERROR),
+        };
+        if !blocks.clone().all(|block| self.is_plugged(block)) {
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        }
+
+        let size = u64::from(nb_blocks) * self.block_size();
+        if let Err(e) = self.discard_range(mem, GuestAddress(addr), size) {
+            error!("Failed to discard unplugged memory: {:?}", e);
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        }
+        self.set_plugged(blocks, false);
+        self.config_space.plugged_size -= size;
+        METRICS.mem.unplug_count.inc();
+        METRICS.mem.reclaimed_bytes.add(size as usize);
+        Response::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn unplug_all(&mut self, mem: &GuestMemoryMmap) -> Response {
+        let size = self.config_space.usable_region_size;
+        if let Err(e) = self.discard_range(mem, self.region_addr(), size) {
+            error!("Failed to discard unplugged memory: {:?}", e);
+            return Response::new(VIRTIO_MEM_RESP_ERROR);
+        }
+        METRICS
+            .mem
+            .reclaimed_bytes
+            .add(self.config_space.plugged_size as usize);
+        for word in self.plugged_blocks.iter_mut() {
+            *word = 0;
+        }
+        self.config_space.plugged_size = 0;
+        METRICS.mem.unplug_count.inc();
+        Response::new(VIRTIO_MEM_RESP_ACK)
+    }
+
+    fn state(&self, addr: u64, nb_blocks: u16) -> Response {
+        let blocks = match self.block_range(addr, nb_blocks) {
+            Some(blocks) => blocks,
+            None => return Response::new(VIRTIO_MEM_RESP_ERROR),
+        };
+        let num_plugged = blocks
+            .clone()
+            .filter(|&block| self.is_plugged(block))
+            .count();
+
+        let mut response = Response::new(VIRTIO_MEM_RESP_ACK);
+        response.state = if num_plugged == blocks.len() {
+            VIRTIO_MEM_STATE_PLUGGED
+        } else if num_plugged == 0 {
+            VIRTIO_MEM_STATE_UNPLUGGED
+        } else {
+            VIRTIO_MEM_STATE_MIXED
+        };
+        response
+    }
+
+    // Returns the indexes of the `nb_blocks` blocks starting at `addr`, if they are all within
+    // the usable part of the region.
+    fn block_range(&self, addr: u64, nb_blocks: u16) -> Option<Range<usize>> {
+        let offset = addr.checked_sub(self.config_space.addr)?;
+        if nb_blocks == 0 || offset % self.block_size() != 0 {
+            return None;
+        }
+        let size = u64::from(nb_blocks) * self.block_size();
+        if offset + size > self.config_space.usable_region_size {
+            return None;
+        }
+
+        let first = (offset / self.block_size()) as usize;
+        Some(first..first + nb_blocks as usize)
+    }
+
+    pub(crate) fn is_plugged(&self, block: usize) -> bool {
+        self.plugged_blocks[block / BITS_PER_WORD] & (1 << (block % BITS_PER_WORD)) != 0
+    }
+
+    fn set_plugged(&mut self, blocks: Range<usize>, plugged: bool) {
+        for block in blocks {
+            let mask = 1 << (block % BITS_PER_WORD);
+            if plugged {
+                self.plugged_blocks[block / BITS_PER_WORD] |= mask;
+            } else {
+                self.plugged_blocks[block / BITS_PER_WORD] &= !mask;
+            }
+        }
+    }
+
+    // Gives the host memory backing the `len` bytes of guest memory at `addr` back to the host.
+    fn discard_range(&self, mem: &GuestMemoryMmap, addr: GuestAddress, len: u64) -> Result<()> {
+        let host_addr = mem.get_host_address(addr).map_err(Error::GuestMemory)?;
+
+        // After a restore, the guest memory is a private mapping of the snapshot file, which
+        // `madvise` cannot discard. Map fresh anonymous memory over the range instead.
+        if self.restored {
+            let ret = unsafe {
+                libc::mmap(
+                    host_addr as *mut _,
+                    len as usize,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_FIXED | libc::MAP_ANONYMOUS | libc::MAP_PRIVATE | libc::MAP_NORESERVE,
+                    -1,
+                    0,
+                )
+            };
+            if ret == libc::MAP_FAILED {
+                return Err(Error::DiscardMemory(io::Error::last_os_error()));
+            }
+        }
+
+        let ret = unsafe { libc::madvise(host_addr as *mut _, len as usize, libc::MADV_DONTNEED) };
+        if ret < 0 {
+            return Err(Error::DiscardMemory(io::Error::last_os_error()));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<()> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal used queue: {:?}", e);
+            Error::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        let _ = self.process_queue();
+    }
+}
+
+impl VirtioDevice for VirtioMem {
+    fn device_type(&self) -> u32 {
+        TYPE_MEM
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The configuration space is read-only for the driver.
+        error!("Failed to write config space");
+        METRICS.mem.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Virtio-mem: Cannot write to activate_evt");
+            METRICS.mem.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::virtio::test_utils::VirtQueue;
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+    const REGION_ADDR: u64 = 0x100_0000;
+    const BLOCK_SIZE: u64 = MIN_BLOCK_SIZE;
+
+    impl VirtioMem {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    pub(crate) fn default_mem_with_region() -> GuestMemoryMmap {
+        GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 0x10000),
+            (GuestAddress(REGION_ADDR), 4 * BLOCK_SIZE as usize),
+        ])
+        .unwrap()
+    }
+
+    pub(crate) fn default_virtio_mem() -> VirtioMem {
+        VirtioMem::new(GuestAddress(REGION_ADDR), 4 * BLOCK_SIZE, BLOCK_SIZE, false).unwrap()
+    }
+
+    // Places a request on the queue and processes it, returning the response of the device.
+    fn send_request(
+        device: &mut VirtioMem,
+        mem: &GuestMemoryMmap,
+        queue: &VirtQueue,
+        request: Request,
+    ) -> Response {
+        let idx = queue.avail.idx.get();
+        let req_addr = GuestAddress(0x2000);
+        let resp_addr = GuestAddress(0x3000);
+        mem.write_obj(request, req_addr).unwrap();
+        queue.dtable[0].set(req_addr.0, 24, VIRTQ_DESC_F_NEXT, 1);
+        queue.dtable[1].set(resp_addr.0, 10, VIRTQ_DESC_F_WRITE, 0);
+        queue.avail.ring[idx as usize % 16].set(0);
+        queue.avail.idx.set(idx + 1);
+
+        device.queue_evts[0].write(1).unwrap();
+        device.process_queue_event().unwrap();
+        assert_eq!(queue.used.idx.get(), idx + 1);
+        queue.check_used_elem(idx, 0, 10);
+        mem.read_obj(resp_addr).unwrap()
+    }
+
+    fn request(req_type: u16, block: u64, nb_blocks: u16) -> Request {
+        Request {
+            req_type,
+            addr: REGION_ADDR + block * BLOCK_SIZE,
+            nb_blocks,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        let region_addr = GuestAddress(REGION_ADDR);
+        assert!(VirtioMem::new(region_addr, 4 * BLOCK_SIZE, 3 << 20, false).is_err());
+        assert!(VirtioMem::new(region_addr, 4 * BLOCK_SIZE, 1 << 20, false).is_err());
+        assert!(VirtioMem::new(region_addr, 0, BLOCK_SIZE, false).is_err());
+        assert!(VirtioMem::new(region_addr, BLOCK_SIZE + 4096, BLOCK_SIZE, false).is_err());
+
+        let mut device = default_virtio_mem();
+        assert_eq!(device.id(), MEM_DEV_ID);
+        assert_eq!(device.device_type(), TYPE_MEM);
+        assert_eq!(device.queues().len(), NUM_QUEUES);
+        assert_eq!(device.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+        device.set_acked_features(device.avail_features());
+        assert_eq!(device.acked_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(device.region_addr(), region_addr);
+        assert_eq!(device.plugged_blocks.len(), 1);
+        assert_eq!(
+            device.status(),
+            VirtioMemStatus {
+                total_size_mib: 8,
+                block_size_mib: 2,
+                plugged_size_mib: 0,
+                requested_size_mib: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let mut device = default_virtio_mem();
+        device.update_requested_size(2 * BLOCK_SIZE).unwrap();
+        assert!(device.update_requested_size(BLOCK_SIZE + 4096).is_err());
+        assert!(device.update_requested_size(5 * BLOCK_SIZE).is_err());
+
+        let mut config = ConfigSpace::default();
+        assert_eq!(config.as_slice().len(), CONFIG_SPACE_SIZE);
+        device.read_config(0, config.as_mut_slice());
+        assert_eq!(config.block_size, BLOCK_SIZE);
+        assert_eq!(config.node_id, 0);
+        assert_eq!(config.addr, REGION_ADDR);
+        assert_eq!(config.region_size, 4 * BLOCK_SIZE);
+        assert_eq!(config.usable_region_size, 4 * BLOCK_SIZE);
+        assert_eq!(config.plugged_size, 0);
+        assert_eq!(config.requested_size, 2 * BLOCK_SIZE);
+
+        // Partial reads are served, out of bounds reads are ignored.
+        let mut data = [0xffu8; 16];
+        device.read_config(CONFIG_SPACE_SIZE as u64 - 8, &mut data);
+        assert_eq!(data[..8], (2 * BLOCK_SIZE).to_le_bytes());
+        assert_eq!(data[8..], [0xffu8; 8]);
+        let mut data = [0xffu8; 8];
+        device.read_config(CONFIG_SPACE_SIZE as u64, &mut data);
+        assert_eq!(data, [0xffu8; 8]);
+
+        // The configuration space is read-only.
+        device.write_config(48, &[0u8; 8]);
+        assert_eq!(device.requested_size(), 2 * BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_process_requests() {
+        let mut device = default_virtio_mem();
+        let mem = default_mem_with_region();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        device.set_queue(0, queue.create_queue());
+        device.activate(mem.clone()).unwrap();
+        device.update_requested_size(3 * BLOCK_SIZE).unwrap();
+
+        // Plug the first two blocks.
+        let resp = send_request(&mut device, &mem, &queue, request(0, 0, 2));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(device.plugged_size(), 2 * BLOCK_SIZE);
+        assert_eq!(device.interrupt_evt.read().unwrap(), 1);
+        // Plugging them again is an error.
+        let resp = send_request(&mut device, &mem, &queue, request(0, 1, 1));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        // Plugging beyond the requested size is rejected.
+        let resp = send_request(&mut device, &mem, &queue, request(0, 2, 2));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_NACK);
+        // Plugging outside of the region, or at an unaligned address, is an error.
+        let resp = send_request(&mut device, &mem, &queue, request(0, 4, 1));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        let mut unaligned = request(0, 2, 1);
+        unaligned.addr += 4096;
+        let resp = send_request(&mut device, &mem, &queue, unaligned);
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        let resp = send_request(&mut device, &mem, &queue, request(0, 2, 0));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+
+        // Query the state of the blocks.
+        let resp = send_request(&mut device, &mem, &queue, request(3, 0, 2));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_PLUGGED);
+        let resp = send_request(&mut device, &mem, &queue, request(3, 1, 2));
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_MIXED);
+        let resp = send_request(&mut device, &mem, &queue, request(3, 2, 2));
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_UNPLUGGED);
+
+        // Unplug the second block, after the guest wrote to it.
+        let addr = GuestAddress(REGION_ADDR + BLOCK_SIZE);
+        mem.write_obj(0xdead_beefu32, addr).unwrap();
+        let resp = send_request(&mut device, &mem, &queue, request(1, 1, 1));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(device.plugged_size(), BLOCK_SIZE);
+        assert!(device.is_plugged(0));
+        assert!(!device.is_plugged(1));
+        assert_eq!(mem.read_obj::<u32>(addr).unwrap(), 0);
+        // Unplugging blocks which are not plugged is an error.
+        let resp = send_request(&mut device, &mem, &queue, request(1, 0, 2));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        assert!(device.is_plugged(0));
+
+        // Unplug everything.
+        let resp = send_request(&mut device, &mem, &queue, request(2, 0, 0));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(device.plugged_size(), 0);
+        assert!(!device.is_plugged(0));
+
+        // Unknown requests are errors.
+        let resp = send_request(&mut device, &mem, &queue, request(4, 0, 1));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+    }
+
+    #[test]
+    fn test_malformed_request() {
+        let mut device = default_virtio_mem();
+        let mem = default_mem_with_region();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        device.set_queue(0, queue.create_queue());
+        device.activate(mem.clone()).unwrap();
+
+        // A request without a response descriptor is completed without a response.
+        queue.dtable[0].set(0x2000, 24, 0, 0);
+        queue.avail.ring[0].set(0);
+        queue.avail.idx.set(1);
+        device.queue_evts[0].write(1).unwrap();
+        device.process_queue_event().unwrap();
+        assert_eq!(queue.used.idx.get(), 1);
+        queue.check_used_elem(0, 0, 0);
+    }
+}
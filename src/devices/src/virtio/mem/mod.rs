@@ -0,0 +1,75 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements the virtio-mem device, through which the guest plugs and unplugs the memory
+//! blocks of a dedicated region of guest physical memory, as requested by the host.
+
+pub mod device;
+pub mod event_handler;
+pub mod persist;
+
+use vm_memory::GuestMemoryError;
+
+pub use self::device::{VirtioMem, VirtioMemStatus};
+
+/// Device ID used in MMIO device identification.
+/// Because virtio-mem is unique per-vm, this ID can be hardcoded.
+pub const MEM_DEV_ID: &str = "mem";
+pub const CONFIG_SPACE_SIZE: usize = 56;
+pub const QUEUE_SIZE: u16 = 256;
+pub const NUM_QUEUES: usize = 1;
+pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+// The smallest block size the guest driver can work with, which is the size of a huge page.
+pub const MIN_BLOCK_SIZE: u64 = 2 << 20;
+
+// The request types.
+const VIRTIO_MEM_REQ_PLUG: u16 = 0;
+const VIRTIO_MEM_REQ_UNPLUG: u16 = 1;
+const VIRTIO_MEM_REQ_UNPLUG_ALL: u16 = 2;
+const VIRTIO_MEM_REQ_STATE: u16 = 3;
+
+// The response types.
+const VIRTIO_MEM_RESP_ACK: u16 = 0;
+const VIRTIO_MEM_RESP_NACK: u16 = 1;
+const VIRTIO_MEM_RESP_ERROR: u16 = 3;
+
+// The states of a range of memory blocks.
+const VIRTIO_MEM_STATE_PLUGGED: u16 = 0;
+const VIRTIO_MEM_STATE_UNPLUGGED: u16 = 1;
+const VIRTIO_MEM_STATE_MIXED: u16 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Activation error.
+    Activate(super::ActivateError),
+    /// No virtio-mem device found.
+    DeviceNotFound,
+    /// Failed to give unplugged memory back to the host.
+    DiscardMemory(std::io::Error),
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Failed to signal the virtio used queue.
+    FailedSignalingUsedQueue(std::io::Error),
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Received error while sending an interrupt.
+    InterruptError(std::io::Error),
+    /// The block size is not a power of two, or is smaller than `MIN_BLOCK_SIZE`.
+    InvalidBlockSize,
+    /// The region size is zero, or not a multiple of the block size.
+    InvalidRegionSize,
+    /// The requested size is not a multiple of the block size, or exceeds the region size.
+    InvalidRequestedSize,
+    /// Guest gave us a malformed descriptor.
+    MalformedDescriptor,
+    /// The snapshotted plugged block bitmap does not match the region.
+    PluggedBitmapRestoreError,
+    /// Error while processing the virt queues.
+    Queue(super::QueueError),
+    /// Error restoring the virtio-mem device queues.
+    QueueRestoreError,
+    /// The snapshotted region is not part of the restored guest memory.
+    RegionRestoreError,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,184 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring virtio-mem devices.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+use super::*;
+
+use crate::virtio::persist::VirtioDeviceState;
+use crate::virtio::{DeviceState, TYPE_MEM};
+
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct VirtioMemState {
+    region_addr: u64,
+    region_size: u64,
+    block_size: u64,
+    requested_size: u64,
+    plugged_size: u64,
+    plugged_blocks: Vec<u64>,
+    virtio_state: VirtioDeviceState,
+}
+
+pub struct VirtioMemConstructorArgs {
+    pub mem: GuestMemoryMmap,
+}
+
+impl Persist<'_> for VirtioMem {
+    type State = VirtioMemState;
+    type ConstructorArgs = VirtioMemConstructorArgs;
+    type Error = super::Error;
+
+    fn save(&self) -> Self::State {
+        VirtioMemState {
+            region_addr: self.config_space.addr,
+            region_size: self.config_space.region_size,
+            block_size: self.config_space.block_size,
+            requested_size: self.config_space.requested_size,
+            plugged_size: self.config_space.plugged_size,
+            plugged_blocks: self.plugged_blocks.clone(),
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        // We can safely create the virtio-mem device with a restored state, since the memory
+        // of the region is restored from the snapshot along with the rest of the guest memory.
+        let mut device = VirtioMem::new(
+            GuestAddress(state.region_addr),
+            state.region_size,
+            state.block_size,
+            true,
+        )?;
+        if state.plugged_blocks.len() != device.plugged_blocks.len() {
+            return Err(Error::PluggedBitmapRestoreError);
+        }
+        // The region is restored as a region of its own, like when it was added.
+        match constructor_args.mem.find_region(device.region_addr()) {
+            Some(region)
+                if region.start_addr() == device.region_addr()
+                    && region.len() == state.region_size => {}
+            _ => return Err(Error::RegionRestoreError),
+        }
+        device.update_requested_size(state.requested_size)?;
+
+        device.queues = state
+            .virtio_state
+            .build_queues_checked(&constructor_args.mem, TYPE_MEM, NUM_QUEUES, QUEUE_SIZE)
+            .map_err(|_| Self::Error::QueueRestoreError)?;
+        device.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
+        device.avail_features = state.virtio_state.avail_features;
+        device.acked_features = state.virtio_state.acked_features;
+        device.config_space.plugged_size = state.plugged_size;
+        device.plugged_blocks = state.plugged_blocks.clone();
+
+        if state.virtio_state.activated {
+            device.device_state = DeviceState::Activated(constructor_args.mem);
+        }
+
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::device::VirtioDevice;
+    use crate::virtio::mem::device::tests::{default_mem_with_region, default_virtio_mem};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_persistence() {
+        let guest_mem = default_mem_with_region();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        // Create and save the virtio-mem device, with its first and third blocks plugged.
+        let mut device = default_virtio_mem();
+        device.update_requested_size(2 * MIN_BLOCK_SIZE).unwrap();
+        device.plugged_blocks[0] = 0b101;
+        device.config_space.plugged_size = 2 * MIN_BLOCK_SIZE;
+
+        <VirtioMem as Persist>::save(&device)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Deserialize and restore the virtio-mem device.
+        let restored_device = VirtioMem::restore(
+            VirtioMemConstructorArgs { mem: guest_mem },
+            &VirtioMemState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_device.device_type(), TYPE_MEM);
+        assert!(restored_device.restored);
+        assert_eq!(restored_device.acked_features, device.acked_features);
+        assert_eq!(restored_device.avail_features, device.avail_features);
+        assert_eq!(restored_device.config_space, device.config_space);
+        assert_eq!(restored_device.status(), device.status());
+        assert!(restored_device.is_plugged(0));
+        assert!(!restored_device.is_plugged(1));
+        assert!(restored_device.is_plugged(2));
+        assert_eq!(restored_device.queues(), device.queues());
+        assert_eq!(
+            restored_device.interrupt_status().load(Ordering::Relaxed),
+            device.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored_device.is_activated(), device.is_activated());
+    }
+
+    #[test]
+    fn test_restore_bitmap_mismatch() {
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        let mut state = <VirtioMem as Persist>::save(&default_virtio_mem());
+        state.plugged_blocks.push(0);
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        match VirtioMem::restore(
+            VirtioMemConstructorArgs {
+                mem: default_mem_with_region(),
+            },
+            &VirtioMemState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        ) {
+            Err(Error::PluggedBitmapRestoreError) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_restore_missing_region() {
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        <VirtioMem as Persist>::save(&default_virtio_mem())
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // The guest memory only holds the boot memory.
+        match VirtioMem::restore(
+            VirtioMemConstructorArgs {
+                mem: GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap(),
+            },
+            &VirtioMemState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        ) {
+            Err(Error::RegionRestoreError) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+}
@@ -9,23 +9,57 @@
 use std::any::Any;
 use std::io::Error as IOError;
 
+#[cfg(feature = "balloon")]
 pub mod balloon;
+#[cfg(feature = "block")]
 pub mod block;
+#[cfg(feature = "virtio-console")]
+pub mod console;
 pub mod device;
+#[cfg(feature = "virtio-fs")]
+pub mod fs;
+#[cfg(feature = "virtio-mem")]
+pub mod mem;
 mod mmio;
+#[cfg(feature = "net")]
 pub mod net;
+#[cfg(feature = "null-devices")]
+pub mod null;
+mod pci;
 pub mod persist;
+#[cfg(feature = "virtio-pmem")]
+pub mod pmem;
 mod queue;
+#[cfg(feature = "virtio-rng")]
+pub mod rng;
 pub mod test_utils;
+#[cfg(feature = "vsock")]
 pub mod vsock;
 
+#[cfg(feature = "balloon")]
 pub use self::balloon::*;
+#[cfg(feature = "block")]
 pub use self::block::*;
+#[cfg(feature = "virtio-console")]
+pub use self::console::*;
 pub use self::device::*;
+#[cfg(feature = "virtio-fs")]
+pub use self::fs::*;
+#[cfg(feature = "virtio-mem")]
+pub use self::mem::*;
 pub use self::mmio::*;
+#[cfg(feature = "net")]
 pub use self::net::*;
+#[cfg(feature = "null-devices")]
+pub use self::null::*;
+pub use self::pci::*;
 pub use self::persist::*;
+#[cfg(feature = "virtio-pmem")]
+pub use self::pmem::*;
 pub use self::queue::*;
+#[cfg(feature = "virtio-rng")]
+pub use self::rng::*;
+#[cfg(feature = "vsock")]
 pub use self::vsock::*;
 
 /// When the driver initializes the device, it lets the device know about the
@@ -49,7 +83,15 @@ mod device_status {
 /// Type 0 is not used by virtio. Use it as wildcard for non-virtio devices
 pub const TYPE_NET: u32 = 1;
 pub const TYPE_BLOCK: u32 = 2;
+pub const TYPE_CONSOLE: u32 = 3;
+pub const TYPE_RNG: u32 = 4;
 pub const TYPE_BALLOON: u32 = 5;
+pub const TYPE_GPU: u32 = 16;
+pub const TYPE_INPUT: u32 = 18;
+pub const TYPE_MEM: u32 = 24;
+pub const TYPE_SOUND: u32 = 25;
+pub const TYPE_FS: u32 = 26;
+pub const TYPE_PMEM: u32 = 27;
 
 /// Interrupt flags (re: interrupt status & acknowledge registers).
 /// See linux/virtio_mmio.h.
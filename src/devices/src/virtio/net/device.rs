@@ -8,6 +8,7 @@
 use crate::virtio::net::tap::Tap;
 #[cfg(test)]
 use crate::virtio::net::test_utils::Mocks;
+use crate::virtio::net::vlan::{insert_vlan_tag, is_valid_vlan_id, strip_vlan_tag};
 use crate::virtio::net::Error;
 use crate::virtio::net::Result;
 use crate::virtio::net::{MAX_BUFFER_SIZE, QUEUE_SIZE, QUEUE_SIZES, RX_INDEX, TX_INDEX};
@@ -16,11 +17,14 @@ use crate::virtio::{
 };
 use crate::{report_net_event_fail, Error as DeviceError};
 
+use dumbo::dhcp::DhcpServer;
 use dumbo::pdu::ethernet::EthernetFrame;
 use libc::EAGAIN;
-use logger::{error, warn, IncMetric, METRICS};
+use logger::{error, warn, DeviceMetrics, IncMetric, NetDeviceMetrics, METRICS};
 use mmds::ns::MmdsNetworkStack;
+use rate_limiter::group::RateLimiterGroup;
 use rate_limiter::{BucketUpdate, RateLimiter, TokenType};
+use serde::Serialize;
 #[cfg(not(test))]
 use std::io;
 use std::io::{Read, Write};
@@ -91,6 +95,30 @@ impl Default for ConfigSpace {
 
 unsafe impl ByteValued for ConfigSpace {}
 
+/// Live traffic counters for one of the net device queues.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct NetQueueStats {
+    /// Number of frames successfully moved through the queue.
+    pub packets: u64,
+    /// Number of bytes successfully moved through the queue.
+    pub bytes: u64,
+    /// Number of frames (TX) or guest buffers (RX) discarded by the device.
+    pub dropped: u64,
+    /// Number of errors encountered while moving frames through the queue.
+    pub errors: u64,
+    /// Number of times the queue processing was throttled by the rate limiter.
+    pub rate_limiter_throttled: u64,
+}
+
+/// Live traffic counters of a net device, split per queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct NetDeviceStats {
+    /// Counters for the receive queue.
+    pub rx: NetQueueStats,
+    /// Counters for the transmit queue.
+    pub tx: NetQueueStats,
+}
+
 pub struct Net {
     pub(crate) id: String,
 
@@ -124,6 +152,12 @@ pub struct Net {
     pub(crate) activate_evt: EventFd,
 
     pub(crate) mmds_ns: Option<MmdsNetworkStack>,
+    pub(crate) dhcp_server: Option<DhcpServer>,
+
+    pub(crate) vlan_id: Option<u16>,
+
+    pub(crate) stats: NetDeviceStats,
+    pub(crate) metrics: DeviceMetrics<NetDeviceMetrics>,
 
     #[cfg(test)]
     pub(crate) mocks: Mocks,
@@ -131,6 +165,9 @@ pub struct Net {
 
 impl Net {
     /// Create a new virtio network device with the given TAP interface.
+    ///
+    /// When `vlan_id` is set, the frames sent by the guest are tagged for that VLAN before
+    /// reaching the TAP, and only the frames of that VLAN are delivered to the guest, untagged.
     pub fn new_with_tap(
         id: String,
         tap_if_name: String,
@@ -138,7 +175,14 @@ impl Net {
         rx_rate_limiter: RateLimiter,
         tx_rate_limiter: RateLimiter,
         allow_mmds_requests: bool,
+        vlan_id: Option<u16>,
     ) -> Result<Self> {
+        if let Some(vlan_id) = vlan_id {
+            if !is_valid_vlan_id(vlan_id) {
+                return Err(Error::InvalidVlanId(vlan_id));
+            }
+        }
+
         let tap = Tap::open_named(&tap_if_name).map_err(Error::TapOpen)?;
 
         // Set offload flags to match the virtio features below.
@@ -179,6 +223,7 @@ impl Net {
         } else {
             None
         };
+        let metrics = DeviceMetrics::new(&METRICS.net, METRICS.net.ifaces.get(&id));
         Ok(Net {
             id,
             tap,
@@ -200,7 +245,11 @@ impl Net {
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
             config_space,
             mmds_ns,
+            dhcp_server: None,
             guest_mac: guest_mac.copied(),
+            vlan_id,
+            stats: NetDeviceStats::default(),
+            metrics,
 
             #[cfg(test)]
             mocks: Mocks::default(),
@@ -217,17 +266,75 @@ impl Net {
         self.guest_mac.as_ref()
     }
 
+    /// Provides the name of the TAP device backing this net device.
+    pub fn iface_name(&self) -> String {
+        self.tap.if_name_as_str().to_string()
+    }
+
+    /// Provides the VLAN this net device is a member of.
+    pub fn vlan_id(&self) -> Option<u16> {
+        self.vlan_id
+    }
+
+    /// Provides the rate limiter of the frames received by the guest.
+    pub fn rx_rate_limiter(&self) -> &RateLimiter {
+        &self.rx_rate_limiter
+    }
+
+    /// Provides the rate limiter of the frames sent by the guest.
+    pub fn tx_rate_limiter(&self) -> &RateLimiter {
+        &self.tx_rate_limiter
+    }
+
+    /// Attaches the RX and TX rate limiters of this net device to a group, whose budget they
+    /// share with the other devices attached to it, or detaches them with `None`.
+    pub fn set_rate_limiter_group(&mut self, group: Option<&Arc<RateLimiterGroup>>) {
+        self.rx_rate_limiter
+            .set_group(group.map(RateLimiterGroup::attach));
+        self.tx_rate_limiter
+            .set_group(group.map(RateLimiterGroup::attach));
+    }
+
+    /// Specifies if this net device handles the guest requests to the MMDS.
+    pub fn mmds_enabled(&self) -> bool {
+        self.mmds_ns.is_some()
+    }
+
     /// Provides a mutable reference to the `MmdsNetworkStack`.
     pub fn mmds_ns_mut(&mut self) -> Option<&mut MmdsNetworkStack> {
         self.mmds_ns.as_mut()
     }
 
+    /// Enables or disables the handling of the guest requests to the MMDS on this net device.
+    pub fn set_mmds_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.mmds_ns = None;
+        } else if self.mmds_ns.is_none() {
+            self.mmds_ns = Some(MmdsNetworkStack::new_with_defaults(None));
+        }
+    }
+
+    /// Provides a reference to the DHCP server answering the guest on this net device.
+    pub fn dhcp_server(&self) -> Option<&DhcpServer> {
+        self.dhcp_server.as_ref()
+    }
+
+    /// Sets or removes the DHCP server answering the guest on this net device.
+    pub fn set_dhcp_server(&mut self, dhcp_server: Option<DhcpServer>) {
+        self.dhcp_server = dhcp_server;
+    }
+
+    /// Provides a snapshot of the live traffic counters of this net device.
+    pub fn stats(&self) -> NetDeviceStats {
+        self.stats
+    }
+
     fn signal_used_queue(&mut self) -> result::Result<(), DeviceError> {
         self.interrupt_status
             .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
         self.interrupt_evt.write(1).map_err(|e| {
             error!("Failed to signal used queue: {:?}", e);
-            METRICS.net.event_fails.inc();
+            self.metrics.update(|metrics| metrics.event_fails.inc());
             DeviceError::FailedSignalingUsedQueue(e)
         })?;
 
@@ -250,7 +357,9 @@ impl Net {
         // If limiter.consume() fails it means there is no more TokenType::Ops
         // budget and rate limiting is in effect.
         if !self.rx_rate_limiter.consume(1, TokenType::Ops) {
-            METRICS.net.rx_rate_limiter_throttled.inc();
+            self.metrics
+                .update(|metrics| metrics.rx_rate_limiter_throttled.inc());
+            self.stats.rx.rate_limiter_throttled += 1;
             return false;
         }
         // If limiter.consume() fails it means there is no more TokenType::Bytes
@@ -261,7 +370,9 @@ impl Net {
         {
             // revert the OPS consume()
             self.rx_rate_limiter.manual_replenish(1, TokenType::Ops);
-            METRICS.net.rx_rate_limiter_throttled.inc();
+            self.metrics
+                .update(|metrics| metrics.rx_rate_limiter_throttled.inc());
+            self.stats.rx.rate_limiter_throttled += 1;
             return false;
         }
 
@@ -288,9 +399,10 @@ impl Net {
             DeviceState::Inactive => unreachable!(),
         };
 
+        let metrics = &self.metrics;
         let queue = &mut self.queues[RX_INDEX];
         let head_descriptor = queue.pop(mem).ok_or_else(|| {
-            METRICS.net.no_rx_avail_buffer.inc();
+            metrics.update(|metrics| metrics.no_rx_avail_buffer.inc());
             FrontendError::EmptyQueue
         })?;
         let head_index = head_descriptor.index;
@@ -311,16 +423,19 @@ impl Net {
             let len = std::cmp::min(frame_slice.len(), descriptor.len as usize);
             match mem.write_slice(&frame_slice[..len], descriptor.addr) {
                 Ok(()) => {
-                    METRICS.net.rx_count.inc();
+                    metrics.update(|metrics| metrics.rx_count.inc());
                     frame_slice = &frame_slice[len..];
                 }
                 Err(e) => {
                     error!("Failed to write slice: {:?}", e);
-                    match e {
-                        GuestMemoryError::PartialBuffer { .. } => &METRICS.net.rx_partial_writes,
-                        _ => &METRICS.net.rx_fails,
-                    }
-                    .inc();
+                    metrics.update(|metrics| {
+                        match e {
+                            GuestMemoryError::PartialBuffer { .. } => &metrics.rx_partial_writes,
+                            _ => &metrics.rx_fails,
+                        }
+                        .inc()
+                    });
+                    self.stats.rx.errors += 1;
                     result = Err(FrontendError::GuestMemory(e));
                     break;
                 }
@@ -330,7 +445,7 @@ impl Net {
         }
         if result.is_ok() && !frame_slice.is_empty() {
             warn!("Receiving buffer is too small to hold frame of current size");
-            METRICS.net.rx_fails.inc();
+            metrics.update(|metrics| metrics.rx_fails.inc());
             result = Err(FrontendError::DescriptorChainTooSmall);
         }
 
@@ -343,8 +458,15 @@ impl Net {
         self.rx_deferred_irqs = true;
 
         if result.is_ok() {
-            METRICS.net.rx_bytes_count.add(frame_len);
-            METRICS.net.rx_packets_count.inc();
+            metrics.update(|metrics| {
+                metrics.rx_bytes_count.add(frame_len);
+                metrics.rx_packets_count.inc();
+            });
+            self.stats.rx.bytes += frame_len as u64;
+            self.stats.rx.packets += 1;
+        } else {
+            // The descriptor chain was handed back to the guest without any data.
+            self.stats.rx.dropped += 1;
         }
         result
     }
@@ -369,26 +491,36 @@ impl Net {
         false
     }
 
-    // Tries to detour the frame to MMDS and if MMDS doesn't accept it, sends it on the host TAP.
+    // Tries to detour the frame to MMDS, then to the DHCP server, and if neither accepts it,
+    // sends it on the host TAP.
     //
-    // `frame_buf` should contain the frame bytes in a slice of exact length.
-    // Returns whether MMDS consumed the frame.
+    // `buf` should contain the frame bytes in its first `frame_len` bytes. The rest of `buf`
+    // is used for tagging the frame when it goes to the TAP and `vlan_id` is set.
+    // Returns whether MMDS or the DHCP server consumed the frame.
+    #[allow(clippy::too_many_arguments)]
     fn write_to_mmds_or_tap(
         mmds_ns: Option<&mut MmdsNetworkStack>,
+        dhcp_server: Option<&mut DhcpServer>,
         rate_limiter: &mut RateLimiter,
-        frame_buf: &[u8],
+        buf: &mut [u8],
+        frame_len: usize,
         tap: &mut Tap,
         guest_mac: Option<MacAddr>,
+        vlan_id: Option<u16>,
+        stats: &mut NetQueueStats,
+        metrics: &DeviceMetrics<NetDeviceMetrics>,
     ) -> Result<bool> {
-        let checked_frame = |frame_buf| {
+        let frame_buf = &buf[..frame_len];
+        let checked_frame = |frame_buf, stats: &mut NetQueueStats| {
             frame_bytes_from_buf(frame_buf).map_err(|e| {
                 error!("VNET header missing in the TX frame.");
-                METRICS.net.tx_malformed_frames.inc();
+                metrics.update(|metrics| metrics.tx_malformed_frames.inc());
+                stats.dropped += 1;
                 e
             })
         };
         if let Some(ns) = mmds_ns {
-            if ns.detour_frame(checked_frame(frame_buf)?) {
+            if ns.detour_frame(checked_frame(frame_buf, stats)?) {
                 METRICS.mmds.rx_accepted.inc();
 
                 // MMDS frames are not accounted by the rate limiter.
@@ -399,33 +531,63 @@ impl Net {
                 return Ok(true);
             }
         }
+        if let Some(server) = dhcp_server {
+            if server.detour_frame(checked_frame(frame_buf, stats)?) {
+                metrics.update(|metrics| metrics.dhcp_rx_frames.inc());
+
+                // DHCP frames are not accounted by the rate limiter either.
+                rate_limiter.manual_replenish(frame_buf.len() as u64, TokenType::Bytes);
+                rate_limiter.manual_replenish(1, TokenType::Ops);
+
+                return Ok(true);
+            }
+        }
 
         // This frame goes to the TAP.
 
         // Check for guest MAC spoofing.
         if let Some(mac) = guest_mac {
-            let _ = EthernetFrame::from_bytes(checked_frame(frame_buf)?).map(|eth_frame| {
+            let _ = EthernetFrame::from_bytes(checked_frame(frame_buf, stats)?).map(|eth_frame| {
                 if mac != eth_frame.src_mac() {
-                    METRICS.net.tx_spoofed_mac_count.inc();
+                    metrics.update(|metrics| metrics.tx_spoofed_mac_count.inc());
                 }
             });
         }
 
+        let frame_buf = match vlan_id {
+            Some(vlan_id) => match insert_vlan_tag(buf, frame_len, vlan_id) {
+                Some(tagged_len) => &buf[..tagged_len],
+                None => {
+                    error!("Cannot tag the TX frame for VLAN {}.", vlan_id);
+                    metrics.update(|metrics| metrics.tx_malformed_frames.inc());
+                    stats.dropped += 1;
+                    return Ok(false);
+                }
+            },
+            None => frame_buf,
+        };
+
         match tap.write(frame_buf) {
             Ok(_) => {
-                METRICS.net.tx_bytes_count.add(frame_buf.len());
-                METRICS.net.tx_packets_count.inc();
-                METRICS.net.tx_count.inc();
+                metrics.update(|metrics| {
+                    metrics.tx_bytes_count.add(frame_buf.len());
+                    metrics.tx_packets_count.inc();
+                    metrics.tx_count.inc();
+                });
+                stats.bytes += frame_buf.len() as u64;
+                stats.packets += 1;
             }
             Err(e) => {
                 error!("Failed to write to tap: {:?}", e);
-                METRICS.net.tap_write_fails.inc();
+                metrics.update(|metrics| metrics.tap_write_fails.inc());
+                stats.errors += 1;
             }
         };
         Ok(false)
     }
 
-    // We currently prioritize packets from the MMDS over regular network packets.
+    // We currently prioritize packets from the MMDS, then from the DHCP server, over regular
+    // network packets.
     fn read_from_mmds_or_tap(&mut self) -> Result<usize> {
         if let Some(ns) = self.mmds_ns.as_mut() {
             if let Some(len) =
@@ -438,8 +600,32 @@ impl Net {
                 return Ok(vnet_hdr_len() + len);
             }
         }
+        if let Some(server) = self.dhcp_server.as_mut() {
+            if let Some(len) =
+                server.write_next_frame(frame_bytes_from_buf_mut(&mut self.rx_frame_buf)?)
+            {
+                self.metrics.update(|metrics| metrics.dhcp_tx_frames.inc());
+                init_vnet_hdr(&mut self.rx_frame_buf);
+                return Ok(vnet_hdr_len() + len.get());
+            }
+        }
 
-        self.read_tap().map_err(Error::IO)
+        loop {
+            let len = self.read_tap().map_err(Error::IO)?;
+            let vlan_id = match self.vlan_id {
+                Some(vlan_id) => vlan_id,
+                None => return Ok(len),
+            };
+            match strip_vlan_tag(&mut self.rx_frame_buf, len, vlan_id) {
+                Some(untagged_len) => return Ok(untagged_len),
+                None => {
+                    // The frame does not belong to our VLAN, so try the next one.
+                    self.metrics
+                        .update(|metrics| metrics.rx_vlan_filtered_frames.inc());
+                    self.stats.rx.dropped += 1;
+                }
+            }
+        }
     }
 
     fn process_rx(&mut self) -> result::Result<(), DeviceError> {
@@ -448,7 +634,7 @@ impl Net {
             match self.read_from_mmds_or_tap() {
                 Ok(count) => {
                     self.rx_bytes_read = count;
-                    METRICS.net.rx_count.inc();
+                    self.metrics.update(|metrics| metrics.rx_count.inc());
                     if !self.rate_limited_rx_single_frame() {
                         self.rx_deferred_frame = true;
                         break;
@@ -461,7 +647,8 @@ impl Net {
                         Some(err) if err == EAGAIN => (),
                         _ => {
                             error!("Failed to read tap: {:?}", e);
-                            METRICS.net.tap_read_fails.inc();
+                            self.metrics.update(|metrics| metrics.tap_read_fails.inc());
+                            self.stats.rx.errors += 1;
                             return Err(DeviceError::FailedReadTap);
                         }
                     };
@@ -520,7 +707,9 @@ impl Net {
                 // Stop processing the queue and return this descriptor chain to the
                 // avail ring, for later processing.
                 tx_queue.undo_pop();
-                METRICS.net.tx_rate_limiter_throttled.inc();
+                self.metrics
+                    .update(|metrics| metrics.tx_rate_limiter_throttled.inc());
+                self.stats.tx.rate_limiter_throttled += 1;
                 break;
             }
 
@@ -550,7 +739,9 @@ impl Net {
                 // Stop processing the queue and return this descriptor chain to the
                 // avail ring, for later processing.
                 tx_queue.undo_pop();
-                METRICS.net.tx_rate_limiter_throttled.inc();
+                self.metrics
+                    .update(|metrics| metrics.tx_rate_limiter_throttled.inc());
+                self.stats.tx.rate_limiter_throttled += 1;
                 break;
             }
 
@@ -568,15 +759,18 @@ impl Net {
                 match read_result {
                     Ok(()) => {
                         read_count += limit - read_count;
-                        METRICS.net.tx_count.inc();
+                        self.metrics.update(|metrics| metrics.tx_count.inc());
                     }
                     Err(e) => {
                         error!("Failed to read slice: {:?}", e);
-                        match e {
-                            GuestMemoryError::PartialBuffer { .. } => &METRICS.net.tx_partial_reads,
-                            _ => &METRICS.net.tx_fails,
-                        }
-                        .inc();
+                        self.metrics.update(|metrics| {
+                            match e {
+                                GuestMemoryError::PartialBuffer { .. } => &metrics.tx_partial_reads,
+                                _ => &metrics.tx_fails,
+                            }
+                            .inc()
+                        });
+                        self.stats.tx.errors += 1;
                         read_count = 0;
                         break;
                     }
@@ -585,10 +779,15 @@ impl Net {
 
             let frame_consumed_by_mmds = Self::write_to_mmds_or_tap(
                 self.mmds_ns.as_mut(),
+                self.dhcp_server.as_mut(),
                 &mut self.tx_rate_limiter,
-                &self.tx_frame_buf[..read_count],
+                &mut self.tx_frame_buf,
+                read_count,
                 &mut self.tap,
                 self.guest_mac,
+                self.vlan_id,
+                &mut self.stats.tx,
+                &self.metrics,
             )
             .unwrap_or_else(|_| false);
             if frame_consumed_by_mmds && !self.rx_deferred_frame {
@@ -605,7 +804,8 @@ impl Net {
         if raise_irq {
             self.signal_used_queue()?;
         } else {
-            METRICS.net.no_tx_avail_buffer.inc();
+            self.metrics
+                .update(|metrics| metrics.no_tx_avail_buffer.inc());
         }
 
         // An incoming frame for the MMDS may trigger the transmission of a new message.
@@ -634,18 +834,22 @@ impl Net {
     }
 
     pub fn process_rx_queue_event(&mut self) {
-        METRICS.net.rx_queue_event_count.inc();
+        self.metrics
+            .update(|metrics| metrics.rx_queue_event_count.inc());
 
         if let Err(e) = self.queue_evts[RX_INDEX].read() {
             // rate limiters present but with _very high_ allowed rate
             error!("Failed to get rx queue event: {:?}", e);
-            METRICS.net.event_fails.inc();
+            self.metrics.update(|metrics| metrics.event_fails.inc());
         } else {
             // If the limiter is not blocked, resume the receiving of bytes.
             if !self.rx_rate_limiter.is_blocked() {
-                self.resume_rx().unwrap_or_else(report_net_event_fail);
+                self.resume_rx()
+                    .unwrap_or_else(|err| report_net_event_fail(err, &self.metrics));
             } else {
-                METRICS.net.rx_rate_limiter_throttled.inc();
+                self.metrics
+                    .update(|metrics| metrics.rx_rate_limiter_throttled.inc());
+                self.stats.rx.rate_limiter_throttled += 1;
             }
         }
     }
@@ -656,20 +860,24 @@ impl Net {
             // This should never happen, it's been already validated in the event handler.
             DeviceState::Inactive => unreachable!(),
         };
-        METRICS.net.rx_tap_event_count.inc();
+        self.metrics
+            .update(|metrics| metrics.rx_tap_event_count.inc());
 
         // While there are no available RX queue buffers and there's a deferred_frame
         // don't process any more incoming. Otherwise start processing a frame. In the
         // process the deferred_frame flag will be set in order to avoid freezing the
         // RX queue.
         if self.queues[RX_INDEX].is_empty(mem) && self.rx_deferred_frame {
-            METRICS.net.no_rx_avail_buffer.inc();
+            self.metrics
+                .update(|metrics| metrics.no_rx_avail_buffer.inc());
             return;
         }
 
         // While limiter is blocked, don't process any more incoming.
         if self.rx_rate_limiter.is_blocked() {
-            METRICS.net.rx_rate_limiter_throttled.inc();
+            self.metrics
+                .update(|metrics| metrics.rx_rate_limiter_throttled.inc());
+            self.stats.rx.rate_limiter_throttled += 1;
             return;
         }
 
@@ -678,55 +886,64 @@ impl Net {
         // until we manage to receive this deferred frame.
         {
             self.handle_deferred_frame()
-                .unwrap_or_else(report_net_event_fail);
+                .unwrap_or_else(|err| report_net_event_fail(err, &self.metrics));
         } else {
-            self.process_rx().unwrap_or_else(report_net_event_fail);
+            self.process_rx()
+                .unwrap_or_else(|err| report_net_event_fail(err, &self.metrics));
         }
     }
 
     pub fn process_tx_queue_event(&mut self) {
-        METRICS.net.tx_queue_event_count.inc();
+        self.metrics
+            .update(|metrics| metrics.tx_queue_event_count.inc());
         if let Err(e) = self.queue_evts[TX_INDEX].read() {
             error!("Failed to get tx queue event: {:?}", e);
-            METRICS.net.event_fails.inc();
+            self.metrics.update(|metrics| metrics.event_fails.inc());
         } else if !self.tx_rate_limiter.is_blocked()
         // If the limiter is not blocked, continue transmitting bytes.
         {
-            self.process_tx().unwrap_or_else(report_net_event_fail);
+            self.process_tx()
+                .unwrap_or_else(|err| report_net_event_fail(err, &self.metrics));
         } else {
-            METRICS.net.tx_rate_limiter_throttled.inc();
+            self.metrics
+                .update(|metrics| metrics.tx_rate_limiter_throttled.inc());
+            self.stats.tx.rate_limiter_throttled += 1;
         }
     }
 
     pub fn process_rx_rate_limiter_event(&mut self) {
-        METRICS.net.rx_event_rate_limiter_count.inc();
+        self.metrics
+            .update(|metrics| metrics.rx_event_rate_limiter_count.inc());
         // Upon rate limiter event, call the rate limiter handler
         // and restart processing the queue.
 
         match self.rx_rate_limiter.event_handler() {
             Ok(_) => {
                 // There might be enough budget now to receive the frame.
-                self.resume_rx().unwrap_or_else(report_net_event_fail);
+                self.resume_rx()
+                    .unwrap_or_else(|err| report_net_event_fail(err, &self.metrics));
             }
             Err(e) => {
                 error!("Failed to get rx rate-limiter event: {:?}", e);
-                METRICS.net.event_fails.inc();
+                self.metrics.update(|metrics| metrics.event_fails.inc());
             }
         }
     }
 
     pub fn process_tx_rate_limiter_event(&mut self) {
-        METRICS.net.tx_rate_limiter_event_count.inc();
+        self.metrics
+            .update(|metrics| metrics.tx_rate_limiter_event_count.inc());
         // Upon rate limiter event, call the rate limiter handler
         // and restart processing the queue.
         match self.tx_rate_limiter.event_handler() {
             Ok(_) => {
                 // There might be enough budget now to send the frame.
-                self.process_tx().unwrap_or_else(report_net_event_fail);
+                self.process_tx()
+                    .unwrap_or_else(|err| report_net_event_fail(err, &self.metrics));
             }
             Err(e) => {
                 error!("Failed to get tx rate-limiter event: {:?}", e);
-                METRICS.net.event_fails.inc();
+                self.metrics.update(|metrics| metrics.event_fails.inc());
             }
         }
     }
@@ -780,7 +997,7 @@ impl VirtioDevice for Net {
         let config_len = config_space_bytes.len() as u64;
         if offset >= config_len {
             error!("Failed to read config space");
-            METRICS.net.cfg_fails.inc();
+            self.metrics.update(|metrics| metrics.cfg_fails.inc());
             return;
         }
         if let Some(end) = offset.checked_add(data.len() as u64) {
@@ -798,7 +1015,7 @@ impl VirtioDevice for Net {
         let config_len = config_space_bytes.len() as u64;
         if offset + data_len > config_len {
             error!("Failed to write config space");
-            METRICS.net.cfg_fails.inc();
+            self.metrics.update(|metrics| metrics.cfg_fails.inc());
             return;
         }
 
@@ -806,7 +1023,8 @@ impl VirtioDevice for Net {
         self.guest_mac = Some(MacAddr::from_bytes_unchecked(
             &self.config_space.guest_mac[..MAC_ADDR_LEN],
         ));
-        METRICS.net.mac_address_updates.inc();
+        self.metrics
+            .update(|metrics| metrics.mac_address_updates.inc());
     }
 
     fn is_activated(&self) -> bool {
@@ -849,8 +1067,11 @@ pub mod tests {
         Net, VirtioDevice, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX, TYPE_NET, VIRTIO_MMIO_INT_VRING,
         VIRTQ_DESC_F_WRITE,
     };
+    use dumbo::dhcp::DhcpServerConfig;
     use dumbo::pdu::arp::{EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN};
-    use dumbo::pdu::ethernet::ETHERTYPE_ARP;
+    use dumbo::pdu::ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4};
+    use dumbo::pdu::ipv4::{IPv4Packet, PROTOCOL_UDP};
+    use dumbo::pdu::udp::UdpDatagram;
     use logger::{IncMetric, METRICS};
     use rate_limiter::{RateLimiter, TokenBucket, TokenType};
     use virtio_gen::virtio_net::{
@@ -1348,6 +1569,11 @@ pub mod tests {
         let mut buf = vec![0; 1000];
         assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
         assert_eq!(&buf[..1000], &frame[..1000]);
+        // Check that the frame was also accounted to the interface.
+        let iface_id = th.net().id().clone();
+        let iface_metrics = METRICS.net.ifaces.get(&iface_id);
+        assert_eq!(iface_metrics.tx_packets_count.count(), 1);
+        assert_eq!(iface_metrics.tx_bytes_count.count(), 1000);
     }
 
     #[test]
@@ -1427,7 +1653,7 @@ pub mod tests {
         let dst_mac = MacAddr::parse_str("22:22:22:22:22:22").unwrap();
         let dst_ip = Ipv4Addr::new(169, 254, 169, 254);
 
-        let (frame_buf, frame_len) = create_arp_request(src_mac, src_ip, dst_mac, dst_ip);
+        let (mut frame_buf, frame_len) = create_arp_request(src_mac, src_ip, dst_mac, dst_ip);
 
         // Call the code which sends the packet to the host or MMDS.
         // Validate the frame was consumed by MMDS and that the metrics reflect that.
@@ -1436,10 +1662,14 @@ pub mod tests {
             1,
             assert!(Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
+                net.dhcp_server.as_mut(),
                 &mut net.tx_rate_limiter,
-                &frame_buf[..frame_len],
+                &mut frame_buf,
+                frame_len,
                 &mut net.tap,
                 Some(src_mac),
+                None,
+                &mut net.stats.tx,
             )
             .unwrap())
         );
@@ -1452,6 +1682,107 @@ pub mod tests {
         );
     }
 
+    fn create_dhcp_discover(src_mac: MacAddr) -> ([u8; MAX_BUFFER_SIZE], usize) {
+        // A DHCPDISCOVER message with an Ethernet hardware address and no other options.
+        let mut message = vec![0u8; 240];
+        message[..3].copy_from_slice(&[1, 1, 6]);
+        message[28..34].copy_from_slice(src_mac.get_bytes());
+        message[236..].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+        message.extend_from_slice(&[53, 1, 1, 255]);
+
+        let mut frame_buf = [b'\0'; MAX_BUFFER_SIZE];
+        let mut eth = EthernetFrame::write_incomplete(
+            frame_bytes_from_buf_mut(&mut frame_buf).unwrap(),
+            MacAddr::parse_str("ff:ff:ff:ff:ff:ff").unwrap(),
+            src_mac,
+            ETHERTYPE_IPV4,
+        )
+        .unwrap();
+        let mut ip = IPv4Packet::write_header(
+            eth.inner_mut().payload_mut(),
+            PROTOCOL_UDP,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::BROADCAST,
+        )
+        .unwrap();
+        let udp_len =
+            UdpDatagram::write_incomplete_datagram(ip.inner_mut().payload_mut(), &message)
+                .unwrap()
+                .finalize(68, 67, None)
+                .len();
+        let ip_len = ip.with_payload_len_unchecked(udp_len as usize, true).len();
+        let frame_len = vnet_hdr_len() + eth.with_payload_len_unchecked(ip_len).len();
+
+        (frame_buf, frame_len)
+    }
+
+    #[test]
+    fn test_dhcp_detour_and_injection() {
+        let mut net = default_net();
+        net.set_dhcp_server(Some(
+            DhcpServer::new(DhcpServerConfig {
+                pool_start: Ipv4Addr::new(10, 0, 0, 2),
+                pool_end: Ipv4Addr::new(10, 0, 0, 254),
+                subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+                gateway: Ipv4Addr::new(10, 0, 0, 1),
+                dns_servers: vec![],
+                mtu: None,
+                lease_time_s: 3600,
+            })
+            .unwrap(),
+        ));
+
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let (mut frame_buf, frame_len) = create_dhcp_discover(guest_mac);
+
+        // The request is consumed by the DHCP server instead of going to the TAP.
+        check_metric_after_block!(
+            &METRICS.net.dhcp_rx_frames,
+            1,
+            assert!(Net::write_to_mmds_or_tap(
+                net.mmds_ns.as_mut(),
+                net.dhcp_server.as_mut(),
+                &mut net.tx_rate_limiter,
+                &mut frame_buf,
+                frame_len,
+                &mut net.tap,
+                Some(guest_mac),
+                None,
+                &mut net.stats.tx,
+            )
+            .unwrap())
+        );
+        assert_eq!(net.stats().tx.packets, 0);
+
+        // The offer is delivered to the guest.
+        check_metric_after_block!(
+            &METRICS.net.dhcp_tx_frames,
+            1,
+            net.read_from_mmds_or_tap().unwrap()
+        );
+        let frame = frame_bytes_from_buf(&net.rx_frame_buf).unwrap();
+        assert_eq!(
+            EthernetFrame::from_bytes(frame).unwrap().dst_mac(),
+            guest_mac
+        );
+
+        // Without a DHCP server, the request goes to the TAP.
+        net.set_dhcp_server(None);
+        assert!(net.dhcp_server().is_none());
+        assert!(!Net::write_to_mmds_or_tap(
+            net.mmds_ns.as_mut(),
+            net.dhcp_server.as_mut(),
+            &mut net.tx_rate_limiter,
+            &mut frame_buf,
+            frame_len,
+            &mut net.tap,
+            Some(guest_mac),
+            None,
+            &mut net.stats.tx,
+        )
+        .unwrap());
+    }
+
     #[test]
     fn test_mac_spoofing_detection() {
         let mut net = default_net();
@@ -1462,7 +1793,7 @@ pub mod tests {
         let dst_mac = MacAddr::parse_str("22:22:22:22:22:22").unwrap();
         let dst_ip = Ipv4Addr::new(10, 1, 1, 1);
 
-        let (frame_buf, frame_len) = create_arp_request(guest_mac, guest_ip, dst_mac, dst_ip);
+        let (mut frame_buf, frame_len) = create_arp_request(guest_mac, guest_ip, dst_mac, dst_ip);
 
         // Check that a legit MAC doesn't affect the spoofed MAC metric.
         check_metric_after_block!(
@@ -1470,10 +1801,14 @@ pub mod tests {
             0,
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
+                net.dhcp_server.as_mut(),
                 &mut net.tx_rate_limiter,
-                &frame_buf[..frame_len],
+                &mut frame_buf,
+                frame_len,
                 &mut net.tap,
                 Some(guest_mac),
+                None,
+                &mut net.stats.tx,
             )
         );
 
@@ -1483,14 +1818,84 @@ pub mod tests {
             1,
             Net::write_to_mmds_or_tap(
                 net.mmds_ns.as_mut(),
+                net.dhcp_server.as_mut(),
                 &mut net.tx_rate_limiter,
-                &frame_buf[..frame_len],
+                &mut frame_buf,
+                frame_len,
                 &mut net.tap,
                 Some(not_guest_mac),
+                None,
+                &mut net.stats.tx,
             )
         );
     }
 
+    #[test]
+    fn test_vlan_tagging() {
+        let mut net = default_net();
+        net.vlan_id = Some(100);
+
+        let guest_mac = MacAddr::parse_str("11:11:11:11:11:11").unwrap();
+        let dst_mac = MacAddr::parse_str("22:22:22:22:22:22").unwrap();
+        let (mut frame_buf, frame_len) = create_arp_request(
+            guest_mac,
+            Ipv4Addr::new(10, 1, 2, 3),
+            dst_mac,
+            Ipv4Addr::new(10, 1, 1, 1),
+        );
+        let untagged_frame = frame_buf[..frame_len].to_vec();
+
+        // Frames sent to the TAP are tagged.
+        assert!(!Net::write_to_mmds_or_tap(
+            None,
+            None,
+            &mut net.tx_rate_limiter,
+            &mut frame_buf,
+            frame_len,
+            &mut net.tap,
+            Some(guest_mac),
+            net.vlan_id,
+            &mut net.stats.tx,
+        )
+        .unwrap());
+        assert_eq!(net.stats().tx.bytes, frame_len as u64 + 4);
+        assert_eq!(
+            &frame_buf[vnet_hdr_len() + 12..vnet_hdr_len() + 16],
+            &[0x81, 0x00, 0x00, 100]
+        );
+
+        // Frames received from the TAP are untagged.
+        net.mocks
+            .set_read_tap(ReadTapMock::MockFrame(frame_buf[..frame_len + 4].to_vec()));
+        assert_eq!(net.read_from_mmds_or_tap().unwrap(), frame_len);
+        assert_eq!(&net.rx_frame_buf[..frame_len], untagged_frame.as_slice());
+        assert_eq!(net.stats().rx.dropped, 0);
+    }
+
+    #[test]
+    fn test_tx_malformed_frame_stats() {
+        let mut net = default_net();
+        let frame_len = vnet_hdr_len() - 1;
+        let mut frame_buf = vec![0u8; frame_len];
+
+        // A frame without a VNET header cannot be sent and is accounted as dropped.
+        assert!(Net::write_to_mmds_or_tap(
+            net.mmds_ns.as_mut(),
+            net.dhcp_server.as_mut(),
+            &mut net.tx_rate_limiter,
+            &mut frame_buf,
+            frame_len,
+            &mut net.tap,
+            None,
+            None,
+            &mut net.stats.tx,
+        )
+        .is_err());
+        assert_eq!(net.stats().tx.dropped, 1);
+        assert_eq!(net.stats().tx.packets, 0);
+        assert_eq!(net.stats().rx, NetQueueStats::default());
+    }
+
     #[test]
     fn test_process_error_cases() {
         let mut th = TestHelper::default();
@@ -3,7 +3,7 @@
 
 use std::os::unix::io::AsRawFd;
 
-use logger::{debug, error, warn, IncMetric, METRICS};
+use logger::{debug, error, warn, IncMetric};
 use polly::event_manager::{EventManager, Subscriber};
 use utils::epoll::{EpollEvent, EventSet};
 
@@ -77,7 +77,7 @@ impl Subscriber for Net {
                 _ if activate_fd == source => self.process_activate_event(evmgr),
                 _ => {
                     warn!("Net: Spurious event received: {:?}", source);
-                    METRICS.net.event_fails.inc();
+                    self.metrics.update(|metrics| metrics.event_fails.inc());
                 }
             }
         } else {
@@ -111,6 +111,11 @@ impl Subscriber for Net {
             )]
         }
     }
+
+    // The network interfaces are told apart in the dispatch metrics.
+    fn name(&self) -> String {
+        format!("net_{}", self.id)
+    }
 }
 
 #[cfg(test)]
@@ -17,6 +17,7 @@ pub mod event_handler;
 pub mod persist;
 mod tap;
 pub mod test_utils;
+pub mod vlan;
 
 pub use self::device::Net;
 pub use self::event_handler::*;
@@ -38,6 +39,8 @@ pub enum Error {
     IO(io::Error),
     /// The VNET header is missing from the frame.
     VnetHeaderMissing,
+    /// The VLAN ID is outside the 1-4094 range.
+    InvalidVlanId(u16),
 }
 
 pub type Result<T> = result::Result<T, Error>;
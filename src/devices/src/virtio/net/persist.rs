@@ -4,14 +4,16 @@
 //! Defines the structures needed for saving/restoring net devices.
 
 use std::io;
+use std::net::Ipv4Addr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
+use dumbo::dhcp::{ConfigError as DhcpConfigError, DhcpServer, DhcpServerConfig};
 use mmds::{ns::MmdsNetworkStack, persist::MmdsNetworkStackState};
 use rate_limiter::{persist::RateLimiterState, RateLimiter};
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::GuestMemoryMmap;
 
@@ -27,6 +29,54 @@ pub struct NetConfigSpaceState {
     guest_mac: [u8; MAC_ADDR_LEN],
 }
 
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct DhcpServerConfigState {
+    pool_start: u32,
+    pool_end: u32,
+    subnet_mask: u32,
+    gateway: u32,
+    dns_servers: Vec<u32>,
+    mtu: Option<u16>,
+    lease_time_s: u32,
+}
+
+impl From<&DhcpServerConfig> for DhcpServerConfigState {
+    fn from(config: &DhcpServerConfig) -> Self {
+        DhcpServerConfigState {
+            pool_start: u32::from(config.pool_start),
+            pool_end: u32::from(config.pool_end),
+            subnet_mask: u32::from(config.subnet_mask),
+            gateway: u32::from(config.gateway),
+            dns_servers: config
+                .dns_servers
+                .iter()
+                .map(|addr| u32::from(*addr))
+                .collect(),
+            mtu: config.mtu,
+            lease_time_s: config.lease_time_s,
+        }
+    }
+}
+
+impl From<&DhcpServerConfigState> for DhcpServerConfig {
+    fn from(state: &DhcpServerConfigState) -> Self {
+        DhcpServerConfig {
+            pool_start: Ipv4Addr::from(state.pool_start),
+            pool_end: Ipv4Addr::from(state.pool_end),
+            subnet_mask: Ipv4Addr::from(state.subnet_mask),
+            gateway: Ipv4Addr::from(state.gateway),
+            dns_servers: state
+                .dns_servers
+                .iter()
+                .map(|addr| Ipv4Addr::from(*addr))
+                .collect(),
+            mtu: state.mtu,
+            lease_time_s: state.lease_time_s,
+        }
+    }
+}
+
 #[derive(Clone, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct NetState {
@@ -37,6 +87,46 @@ pub struct NetState {
     mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    #[version(start = 2, ser_fn = "vlan_id_serialize")]
+    vlan_id: Option<u16>,
+    // The leases are not saved; the guest renews its address on the restored server.
+    #[version(start = 2, ser_fn = "dhcp_serialize")]
+    dhcp: Option<DhcpServerConfigState>,
+}
+
+impl NetState {
+    /// Attaches the restored device to the TAP device `tap_if_name`, e.g. for a clone of the
+    /// microVM.
+    pub fn set_tap_if_name(&mut self, tap_if_name: String) {
+        self.tap_if_name = tap_if_name;
+    }
+
+    /// Exposes `guest_mac` in the config space of the restored device.
+    pub fn set_guest_mac(&mut self, guest_mac: &MacAddr) {
+        self.config_space
+            .guest_mac
+            .copy_from_slice(guest_mac.get_bytes());
+    }
+
+    fn vlan_id_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.vlan_id.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement VLAN tagging.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn dhcp_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.dhcp.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the DHCP server.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub struct NetConstructorArgs {
@@ -45,6 +135,7 @@ pub struct NetConstructorArgs {
 
 #[derive(Debug)]
 pub enum Error {
+    CreateDhcpServer(DhcpConfigError),
     CreateNet(super::Error),
     CreateRateLimiter(io::Error),
     VirtioState(VirtioStateError),
@@ -66,6 +157,11 @@ impl Persist<'_> for Net {
                 guest_mac: self.config_space.guest_mac,
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            vlan_id: self.vlan_id,
+            dhcp: self
+                .dhcp_server
+                .as_ref()
+                .map(|server| DhcpServerConfigState::from(server.config())),
         }
     }
 
@@ -85,6 +181,7 @@ impl Persist<'_> for Net {
             rx_rate_limiter,
             tx_rate_limiter,
             state.mmds_ns.is_some(),
+            state.vlan_id,
         )
         .map_err(Error::CreateNet)?;
 
@@ -93,6 +190,12 @@ impl Persist<'_> for Net {
             .mmds_ns
             .as_ref()
             .map(|mmds_state| MmdsNetworkStack::restore((), &mmds_state).unwrap());
+        if let Some(dhcp_state) = state.dhcp.as_ref() {
+            net.dhcp_server = Some(
+                DhcpServer::new(DhcpServerConfig::from(dhcp_state))
+                    .map_err(Error::CreateDhcpServer)?,
+            );
+        }
 
         net.queues = state
             .virtio_state
@@ -175,6 +278,95 @@ mod tests {
             assert_eq!(restored_net.mmds_ns.is_some(), allow_mmds_requests);
             assert_eq!(restored_net.rx_rate_limiter, RateLimiter::default());
             assert_eq!(restored_net.tx_rate_limiter, RateLimiter::default());
+            assert_eq!(restored_net.vlan_id(), None);
         }
     }
+
+    #[test]
+    fn test_set_guest_mac() {
+        let guest_mem = default_guest_memory();
+        let net = default_net();
+        let mut state = <Net as Persist>::save(&net);
+        drop(net);
+
+        let guest_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        state.set_guest_mac(&guest_mac);
+        let restored_net = Net::restore(NetConstructorArgs { mem: guest_mem }, &state).unwrap();
+        assert_eq!(restored_net.guest_mac(), Some(&guest_mac));
+    }
+
+    #[test]
+    fn test_persistence_vlan() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        let mut net = default_net();
+        net.vlan_id = Some(100);
+        let state = <Net as Persist>::save(&net);
+
+        // VLAN tagging cannot be saved in the older format.
+        assert!(state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        // Drop the original device so that the TAP can be reopened.
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_guest_memory(),
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.vlan_id(), Some(100));
+    }
+
+    #[test]
+    fn test_persistence_dhcp() {
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        let config = DhcpServerConfig {
+            pool_start: Ipv4Addr::new(10, 0, 0, 2),
+            pool_end: Ipv4Addr::new(10, 0, 0, 254),
+            subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: Ipv4Addr::new(10, 0, 0, 1),
+            dns_servers: vec![Ipv4Addr::new(10, 0, 0, 1)],
+            mtu: Some(1500),
+            lease_time_s: 3600,
+        };
+        let mut net = default_net();
+        net.set_dhcp_server(Some(DhcpServer::new(config.clone()).unwrap()));
+        let state = <Net as Persist>::save(&net);
+
+        // The DHCP server cannot be saved in the older format.
+        assert!(state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        // Drop the original device so that the TAP can be reopened.
+        drop(net);
+
+        let restored_net = Net::restore(
+            NetConstructorArgs {
+                mem: default_guest_memory(),
+            },
+            &NetState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_net.dhcp_server().unwrap().config(), &config);
+    }
 }
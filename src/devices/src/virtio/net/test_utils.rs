@@ -39,6 +39,7 @@ pub fn default_net() -> Net {
         RateLimiter::default(),
         RateLimiter::default(),
         true,
+        None,
     )
     .unwrap();
     enable(&net.tap);
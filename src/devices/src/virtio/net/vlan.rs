@@ -0,0 +1,165 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! 802.1Q tagging of the frames exchanged between the guest and the TAP device.
+//!
+//! All the helpers below work on buffers which start with the VNET header, followed by the
+//! Ethernet frame.
+
+use dumbo::pdu::ethernet::ETHERTYPE_VLAN;
+use utils::byte_order;
+use virtio_gen::virtio_net::VIRTIO_NET_HDR_F_NEEDS_CSUM;
+
+use super::device::vnet_hdr_len;
+
+/// The lowest usable VLAN ID (0 only carries priority information).
+pub const MIN_VLAN_ID: u16 = 1;
+/// The highest usable VLAN ID (4095 is reserved).
+pub const MAX_VLAN_ID: u16 = 4094;
+
+const VLAN_TAG_LEN: usize = 4;
+const VLAN_VID_MASK: u16 = 0x0fff;
+// Offset of the ethertype field (or the TPID, for tagged frames) in the Ethernet header.
+const ETHERTYPE_OFFSET: usize = 12;
+const ETHERTYPE_LEN: usize = 2;
+// Offsets of the VNET header fields which point inside the Ethernet frame.
+const VNET_HDR_FLAGS_OFFSET: usize = 0;
+const VNET_HDR_HDR_LEN_OFFSET: usize = 2;
+const VNET_HDR_CSUM_START_OFFSET: usize = 6;
+
+/// Returns whether `vlan_id` can be used for tagging frames.
+pub fn is_valid_vlan_id(vlan_id: u16) -> bool {
+    (MIN_VLAN_ID..=MAX_VLAN_ID).contains(&vlan_id)
+}
+
+// The checksum offload and segmentation offsets in the VNET header are relative to the start
+// of the Ethernet frame, so they move along with the L3 header when a tag is added or removed.
+fn adjust_vnet_hdr(buf: &mut [u8], tag_inserted: bool) {
+    let adjust = |value: u16| {
+        if tag_inserted {
+            value.wrapping_add(VLAN_TAG_LEN as u16)
+        } else {
+            value.wrapping_sub(VLAN_TAG_LEN as u16)
+        }
+    };
+
+    if u32::from(buf[VNET_HDR_FLAGS_OFFSET]) & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0 {
+        let csum_start = byte_order::read_le_u16(&buf[VNET_HDR_CSUM_START_OFFSET..]);
+        byte_order::write_le_u16(&mut buf[VNET_HDR_CSUM_START_OFFSET..], adjust(csum_start));
+    }
+
+    let hdr_len = byte_order::read_le_u16(&buf[VNET_HDR_HDR_LEN_OFFSET..]);
+    if hdr_len != 0 {
+        byte_order::write_le_u16(&mut buf[VNET_HDR_HDR_LEN_OFFSET..], adjust(hdr_len));
+    }
+}
+
+/// Inserts a tag for `vlan_id` in the frame held by the first `len` bytes of `buf`.
+///
+/// Returns the new length of the frame, or `None` if the frame is too short to be tagged or
+/// `buf` has no room left for the tag.
+pub fn insert_vlan_tag(buf: &mut [u8], len: usize, vlan_id: u16) -> Option<usize> {
+    let tag_offset = vnet_hdr_len() + ETHERTYPE_OFFSET;
+    if len < tag_offset + ETHERTYPE_LEN || len + VLAN_TAG_LEN > buf.len() {
+        return None;
+    }
+
+    buf.copy_within(tag_offset..len, tag_offset + VLAN_TAG_LEN);
+    byte_order::write_be_u16(&mut buf[tag_offset..], ETHERTYPE_VLAN);
+    byte_order::write_be_u16(
+        &mut buf[tag_offset + ETHERTYPE_LEN..],
+        vlan_id & VLAN_VID_MASK,
+    );
+    adjust_vnet_hdr(buf, true);
+
+    Some(len + VLAN_TAG_LEN)
+}
+
+/// Removes the tag from the frame held by the first `len` bytes of `buf`, if the frame belongs
+/// to the `vlan_id` VLAN.
+///
+/// Returns the new length of the frame, or `None` if the frame is not tagged for `vlan_id`.
+pub fn strip_vlan_tag(buf: &mut [u8], len: usize, vlan_id: u16) -> Option<usize> {
+    let tag_offset = vnet_hdr_len() + ETHERTYPE_OFFSET;
+    if len < tag_offset + VLAN_TAG_LEN + ETHERTYPE_LEN
+        || byte_order::read_be_u16(&buf[tag_offset..]) != ETHERTYPE_VLAN
+        || byte_order::read_be_u16(&buf[tag_offset + ETHERTYPE_LEN..]) & VLAN_VID_MASK != vlan_id
+    {
+        return None;
+    }
+
+    buf.copy_within(tag_offset + VLAN_TAG_LEN..len, tag_offset);
+    adjust_vnet_hdr(buf, false);
+
+    Some(len - VLAN_TAG_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dumbo::pdu::ethernet::ETHERTYPE_IPV4;
+
+    // Builds a buffer holding a VNET header and an untagged IPv4 frame.
+    fn untagged_frame(buf_len: usize) -> (Vec<u8>, usize) {
+        let mut buf = vec![0u8; buf_len];
+        let len = vnet_hdr_len() + ETHERTYPE_OFFSET + ETHERTYPE_LEN + 4;
+        buf[VNET_HDR_FLAGS_OFFSET] = VIRTIO_NET_HDR_F_NEEDS_CSUM as u8;
+        byte_order::write_le_u16(&mut buf[VNET_HDR_HDR_LEN_OFFSET..], 54);
+        byte_order::write_le_u16(&mut buf[VNET_HDR_CSUM_START_OFFSET..], 34);
+        byte_order::write_be_u16(
+            &mut buf[vnet_hdr_len() + ETHERTYPE_OFFSET..],
+            ETHERTYPE_IPV4,
+        );
+        buf[len - 4..len].copy_from_slice(&[1, 2, 3, 4]);
+        (buf, len)
+    }
+
+    #[test]
+    fn test_vlan_id_validation() {
+        assert!(!is_valid_vlan_id(0));
+        assert!(is_valid_vlan_id(MIN_VLAN_ID));
+        assert!(is_valid_vlan_id(MAX_VLAN_ID));
+        assert!(!is_valid_vlan_id(4095));
+    }
+
+    #[test]
+    fn test_insert_strip_vlan_tag() {
+        let (mut buf, len) = untagged_frame(128);
+        let original = buf.clone();
+
+        let tagged_len = insert_vlan_tag(&mut buf, len, 100).unwrap();
+        assert_eq!(tagged_len, len + VLAN_TAG_LEN);
+        let tag_offset = vnet_hdr_len() + ETHERTYPE_OFFSET;
+        assert_eq!(byte_order::read_be_u16(&buf[tag_offset..]), ETHERTYPE_VLAN);
+        assert_eq!(byte_order::read_be_u16(&buf[tag_offset + 2..]), 100);
+        assert_eq!(
+            byte_order::read_be_u16(&buf[tag_offset + VLAN_TAG_LEN..]),
+            ETHERTYPE_IPV4
+        );
+        assert_eq!(&buf[tagged_len - 4..tagged_len], &[1, 2, 3, 4]);
+        assert_eq!(
+            byte_order::read_le_u16(&buf[VNET_HDR_CSUM_START_OFFSET..]),
+            38
+        );
+        assert_eq!(byte_order::read_le_u16(&buf[VNET_HDR_HDR_LEN_OFFSET..]), 58);
+
+        // Frames from other VLANs are filtered out.
+        assert!(strip_vlan_tag(&mut buf, tagged_len, 101).is_none());
+
+        assert_eq!(strip_vlan_tag(&mut buf, tagged_len, 100), Some(len));
+        assert_eq!(&buf[..len], &original[..len]);
+
+        // Untagged frames are filtered out as well.
+        assert!(strip_vlan_tag(&mut buf, len, 100).is_none());
+    }
+
+    #[test]
+    fn test_insert_vlan_tag_no_room() {
+        let (mut buf, len) = untagged_frame(vnet_hdr_len() + 18);
+        assert_eq!(len, buf.len());
+        assert!(insert_vlan_tag(&mut buf, len, 100).is_none());
+
+        // Frames shorter than an Ethernet header cannot be tagged.
+        assert!(insert_vlan_tag(&mut buf, vnet_hdr_len() + 4, 100).is_none());
+    }
+}
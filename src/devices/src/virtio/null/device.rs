@@ -0,0 +1,361 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{error, IncMetric, METRICS};
+use serde::{Deserialize, Serialize};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::GuestMemoryMmap;
+
+use super::{Error, Result, QUEUE_SIZE};
+use crate::virtio::{
+    ActivateError, ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_GPU, TYPE_INPUT,
+    TYPE_SOUND, VIRTIO_MMIO_INT_VRING,
+};
+
+/// The device classes which can be stubbed.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum NullDeviceType {
+    /// virtio-gpu, exposing no scanouts.
+    Gpu,
+    /// virtio-input, reporting no events.
+    Input,
+    /// virtio-snd, exposing no jacks or streams.
+    Sound,
+}
+
+impl NullDeviceType {
+    /// Returns the device class matching the virtio device ID `virtio_type`.
+    pub fn from_virtio_type(virtio_type: u32) -> Option<Self> {
+        match virtio_type {
+            TYPE_GPU => Some(NullDeviceType::Gpu),
+            TYPE_INPUT => Some(NullDeviceType::Input),
+            TYPE_SOUND => Some(NullDeviceType::Sound),
+            _ => None,
+        }
+    }
+
+    /// Returns the virtio device ID of the device class.
+    pub fn virtio_type(self) -> u32 {
+        match self {
+            NullDeviceType::Gpu => TYPE_GPU,
+            NullDeviceType::Input => TYPE_INPUT,
+            NullDeviceType::Sound => TYPE_SOUND,
+        }
+    }
+
+    // The number of queues the driver of the device class expects.
+    pub(crate) fn num_queues(self) -> usize {
+        match self {
+            // controlq and cursorq.
+            NullDeviceType::Gpu => 2,
+            // eventq and statusq.
+            NullDeviceType::Input => 2,
+            // controlq, eventq, txq and rxq.
+            NullDeviceType::Sound => 4,
+        }
+    }
+
+    // The size of the configuration space of the device class. An all zero configuration
+    // space describes a device without any functionality.
+    fn config_space_size(self) -> usize {
+        match self {
+            NullDeviceType::Gpu => 16,
+            NullDeviceType::Input => 136,
+            NullDeviceType::Sound => 12,
+        }
+    }
+}
+
+/// A virtio device which completes the requests of the driver without acting upon them.
+///
+/// Buffers the driver makes available for the device to write into (such as event or receive
+/// buffers) are never used, since the device has nothing to report.
+pub struct NullDevice {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: Vec<u8>,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    pub(crate) interrupt_evt: EventFd,
+    pub(crate) queue_evts: Vec<EventFd>,
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    pub(crate) id: String,
+    pub(crate) null_device_type: NullDeviceType,
+}
+
+impl NullDevice {
+    /// Creates a new stub device of the `null_device_type` class.
+    pub fn new(id: String, null_device_type: NullDeviceType) -> Result<NullDevice> {
+        let num_queues = null_device_type.num_queues();
+        let mut queue_evts = Vec::with_capacity(num_queues);
+        for _ in 0..num_queues {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+
+        Ok(NullDevice {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            config_space: vec![0u8; null_device_type.config_space_size()],
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues: (0..num_queues).map(|_| Queue::new(QUEUE_SIZE)).collect(),
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            id,
+            null_device_type,
+        })
+    }
+
+    /// Provides the ID of this device.
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    /// Provides the device class this device stubs.
+    pub fn null_device_type(&self) -> NullDeviceType {
+        self.null_device_type
+    }
+
+    pub(crate) fn process_queue_event(&mut self, queue_index: usize) -> Result<()> {
+        self.queue_evts[queue_index]
+            .read()
+            .map_err(Error::EventFd)?;
+        self.process_queue(queue_index)
+    }
+
+    pub(crate) fn process_queue(&mut self, queue_index: usize) -> Result<()> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let queue = &mut self.queues[queue_index];
+        let mut needs_interrupt = false;
+
+        while let Some(head) = queue.pop(mem) {
+            // Device writable buffers are held on to, since completing them would only make
+            // the driver process an empty message and hand the buffer back right away.
+            if head.is_write_only() {
+                continue;
+            }
+
+            queue.add_used(mem, head.index, 0).map_err(Error::Queue)?;
+            METRICS.null_device.discarded_requests.inc();
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<()> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal used queue: {:?}", e);
+            Error::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        for queue_index in 0..self.queues.len() {
+            let _ = self.process_queue(queue_index);
+        }
+    }
+}
+
+impl VirtioDevice for NullDevice {
+    fn device_type(&self) -> u32 {
+        self.null_device_type.virtio_type()
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_len = self.config_space.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(&self.config_space[offset as usize..cmp::min(end, config_len) as usize])
+                .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        let config_len = self.config_space.len() as u64;
+        match offset.checked_add(data.len() as u64) {
+            Some(end) if end <= config_len => {
+                self.config_space[offset as usize..end as usize].copy_from_slice(data)
+            }
+            _ => error!("Failed to write config space"),
+        }
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Null device: Cannot write to activate_evt");
+            METRICS.null_device.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::virtio::test_utils::{default_mem, VirtQueue};
+    use crate::virtio::VIRTQ_DESC_F_WRITE;
+    use vm_memory::GuestAddress;
+
+    impl NullDevice {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    #[test]
+    fn test_null_device_type() {
+        for null_device_type in &[
+            NullDeviceType::Gpu,
+            NullDeviceType::Input,
+            NullDeviceType::Sound,
+        ] {
+            assert_eq!(
+                NullDeviceType::from_virtio_type(null_device_type.virtio_type()),
+                Some(*null_device_type)
+            );
+        }
+        assert!(NullDeviceType::from_virtio_type(crate::virtio::TYPE_NET).is_none());
+    }
+
+    #[test]
+    fn test_virtio_features_and_config() {
+        let mut device = NullDevice::new("snd0".to_string(), NullDeviceType::Sound).unwrap();
+        assert_eq!(device.id(), "snd0");
+        assert_eq!(device.device_type(), TYPE_SOUND);
+        assert_eq!(device.null_device_type(), NullDeviceType::Sound);
+        assert_eq!(device.queues().len(), 4);
+        assert_eq!(device.queue_events().len(), 4);
+        assert_eq!(device.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+        device.set_acked_features(device.avail_features());
+        assert_eq!(device.acked_features(), 1u64 << VIRTIO_F_VERSION_1);
+
+        // The configuration space describes a device without jacks, streams or channel maps.
+        let mut data = [0xffu8; 12];
+        device.read_config(0, &mut data);
+        assert_eq!(data, [0u8; 12]);
+        let mut data = [0xffu8; 4];
+        device.read_config(12, &mut data);
+        assert_eq!(data, [0xffu8; 4]);
+
+        device.write_config(8, &[1, 0, 0, 0]);
+        device.write_config(10, &[1, 0, 0, 0]);
+        let mut data = [0u8; 4];
+        device.read_config(8, &mut data);
+        assert_eq!(data, [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_process_queue() {
+        let mut device = NullDevice::new("input0".to_string(), NullDeviceType::Input).unwrap();
+        let mem = default_mem();
+        let eventq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let statusq = VirtQueue::new(GuestAddress(0x1000), &mem, 16);
+        device.set_queue(0, eventq.create_queue());
+        device.set_queue(1, statusq.create_queue());
+        assert!(!device.is_activated());
+        device.activate(mem.clone()).unwrap();
+        assert!(device.is_activated());
+
+        // Event buffers are held on to.
+        eventq.avail.idx.set(1);
+        eventq.avail.ring[0].set(0);
+        eventq.dtable[0].set(0x2000, 8, VIRTQ_DESC_F_WRITE, 0);
+        device.queue_evts[0].write(1).unwrap();
+        device.process_queue_event(0).unwrap();
+        assert_eq!(eventq.used.idx.get(), 0);
+        assert!(device.interrupt_evt.read().is_err());
+
+        // Status updates are acknowledged and discarded.
+        statusq.avail.idx.set(2);
+        statusq.avail.ring[0].set(0);
+        statusq.avail.ring[1].set(1);
+        statusq.dtable[0].set(0x3000, 8, 0, 0);
+        statusq.dtable[1].set(0x3100, 8, 0, 0);
+        let discarded_requests = METRICS.null_device.discarded_requests.count();
+        device.queue_evts[1].write(1).unwrap();
+        device.process_queue_event(1).unwrap();
+        assert_eq!(statusq.used.idx.get(), 2);
+        statusq.check_used_elem(0, 0, 0);
+        statusq.check_used_elem(1, 1, 0);
+        assert_eq!(device.interrupt_evt.read().unwrap(), 1);
+        assert!(METRICS.null_device.discarded_requests.count() >= discarded_requests + 2);
+
+        // Nothing happens when the queue is empty.
+        device.process_virtio_queues();
+        assert_eq!(statusq.used.idx.get(), 2);
+        assert!(device.interrupt_evt.read().is_err());
+    }
+}
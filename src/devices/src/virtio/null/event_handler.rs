@@ -0,0 +1,147 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+
+use logger::{debug, error, warn};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::report_null_device_event_fail;
+use crate::virtio::{null::device::NullDevice, VirtioDevice};
+
+impl NullDevice {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        debug!("null device: activate event");
+        if let Err(e) = self.activate_evt.read() {
+            error!("Failed to consume null device activate event: {:?}", e);
+        }
+        let activate_fd = self.activate_evt.as_raw_fd();
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = match event_manager.subscriber(activate_fd) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                error!("Failed to process null device activate evt: {:?}", e);
+                return;
+            }
+        };
+
+        // Interest list changes when the device is activated.
+        let interest_list = self.interest_list();
+        for event in interest_list {
+            event_manager
+                .register(event.data() as i32, event, self_subscriber.clone())
+                .unwrap_or_else(|e| {
+                    error!("Failed to register null device events: {:?}", e);
+                });
+        }
+
+        event_manager.unregister(activate_fd).unwrap_or_else(|e| {
+            error!("Failed to unregister null device activate evt: {:?}", e);
+        });
+    }
+}
+
+impl Subscriber for NullDevice {
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+        let supported_events = EventSet::IN;
+
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            if source == self.activate_evt.as_raw_fd() {
+                self.process_activate_event(evmgr);
+                return;
+            }
+
+            match self
+                .queue_evts
+                .iter()
+                .position(|queue_evt| queue_evt.as_raw_fd() == source)
+            {
+                Some(queue_index) => self
+                    .process_queue_event(queue_index)
+                    .unwrap_or_else(report_null_device_event_fail),
+                None => warn!("Null device: Spurious event received: {:?}", source),
+            }
+        } else {
+            warn!(
+                "Null device: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        // This function can be called during different points in the device lifetime:
+        //  - shortly after device creation,
+        //  - on device activation (is-activated already true at this point),
+        //  - on device restore from snapshot.
+        if self.is_activated() {
+            self.queue_evts
+                .iter()
+                .map(|queue_evt| EpollEvent::new(EventSet::IN, queue_evt.as_raw_fd() as u64))
+                .collect()
+        } else {
+            vec![EpollEvent::new(
+                EventSet::IN,
+                self.activate_evt.as_raw_fd() as u64,
+            )]
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::null::NullDeviceType;
+    use crate::virtio::test_utils::{default_mem, VirtQueue};
+    use vm_memory::GuestAddress;
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let mut device = NullDevice::new("gpu0".to_string(), NullDeviceType::Gpu).unwrap();
+        let mem = default_mem();
+        let controlq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        device.set_queue(0, controlq.create_queue());
+
+        let device = Arc::new(Mutex::new(device));
+        event_manager.add_subscriber(device.clone()).unwrap();
+
+        // Push a request on the control queue.
+        controlq.avail.idx.set(1);
+        controlq.avail.ring[0].set(0);
+        controlq.dtable[0].set(0x1000, 24, 0, 0);
+        device.lock().unwrap().queue_evts[0].write(1).unwrap();
+
+        // EventManager should report no events since the device has only registered
+        // its activation event so far (even though there is also a queue event pending).
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Now activate the device.
+        device.lock().unwrap().activate(mem.clone()).unwrap();
+        // Process the activate event.
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // Handle the previously pushed queue event through EventManager.
+        event_manager
+            .run_with_timeout(100)
+            .expect("Metrics event timeout or error.");
+        // Make sure the request was acknowledged.
+        assert_eq!(controlq.used.idx.get(), 1);
+    }
+}
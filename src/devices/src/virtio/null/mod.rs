@@ -0,0 +1,29 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Stub virtio devices, which let the guest drivers probe device classes that Firecracker does
+//! not emulate. They acknowledge the requests of the driver and discard them.
+
+pub mod device;
+pub mod event_handler;
+pub mod persist;
+
+pub use self::device::{NullDevice, NullDeviceType};
+
+pub const QUEUE_SIZE: u16 = 256;
+
+#[derive(Debug)]
+pub enum Error {
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Failed to signal the virtio used queue.
+    FailedSignalingUsedQueue(std::io::Error),
+    /// Error while processing the virt queues.
+    Queue(super::QueueError),
+    /// Error restoring the device queues.
+    QueueRestoreError,
+    /// The snapshotted device type cannot be stubbed.
+    UnsupportedDeviceType(u32),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
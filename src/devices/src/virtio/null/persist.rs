@@ -0,0 +1,145 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring null devices.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use vm_memory::GuestMemoryMmap;
+
+use super::*;
+
+use crate::virtio::persist::VirtioDeviceState;
+use crate::virtio::DeviceState;
+
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct NullDeviceState {
+    id: String,
+    virtio_type: u32,
+    config_space: Vec<u8>,
+    virtio_state: VirtioDeviceState,
+}
+
+pub struct NullDeviceConstructorArgs {
+    pub mem: GuestMemoryMmap,
+}
+
+impl Persist<'_> for NullDevice {
+    type State = NullDeviceState;
+    type ConstructorArgs = NullDeviceConstructorArgs;
+    type Error = super::Error;
+
+    fn save(&self) -> Self::State {
+        NullDeviceState {
+            id: self.id.clone(),
+            virtio_type: self.null_device_type.virtio_type(),
+            config_space: self.config_space.clone(),
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        let null_device_type = NullDeviceType::from_virtio_type(state.virtio_type)
+            .ok_or(Error::UnsupportedDeviceType(state.virtio_type))?;
+        let mut device = NullDevice::new(state.id.clone(), null_device_type)?;
+
+        device.queues = state
+            .virtio_state
+            .build_queues_checked(
+                &constructor_args.mem,
+                state.virtio_type,
+                null_device_type.num_queues(),
+                QUEUE_SIZE,
+            )
+            .map_err(|_| Self::Error::QueueRestoreError)?;
+        device.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
+        device.avail_features = state.virtio_state.avail_features;
+        device.acked_features = state.virtio_state.acked_features;
+        if state.config_space.len() == device.config_space.len() {
+            device.config_space = state.config_space.clone();
+        }
+
+        if state.virtio_state.activated {
+            device.device_state = DeviceState::Activated(constructor_args.mem);
+        }
+
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::device::VirtioDevice;
+    use crate::virtio::TYPE_INPUT;
+
+    use crate::virtio::test_utils::default_mem;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_persistence() {
+        let guest_mem = default_mem();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        // Create and save the null device.
+        let mut device = NullDevice::new("input0".to_string(), NullDeviceType::Input).unwrap();
+        device.write_config(0, &[1, 2]);
+
+        <NullDevice as Persist>::save(&device)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Deserialize and restore the null device.
+        let restored_device = NullDevice::restore(
+            NullDeviceConstructorArgs { mem: guest_mem },
+            &NullDeviceState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_device.id(), "input0");
+        assert_eq!(restored_device.device_type(), TYPE_INPUT);
+        assert_eq!(restored_device.acked_features, device.acked_features);
+        assert_eq!(restored_device.avail_features, device.avail_features);
+        assert_eq!(restored_device.config_space, device.config_space);
+        assert_eq!(restored_device.queues(), device.queues());
+        assert_eq!(
+            restored_device.interrupt_status().load(Ordering::Relaxed),
+            device.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored_device.is_activated(), device.is_activated());
+    }
+
+    #[test]
+    fn test_restore_unsupported_type() {
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        let mut state = <NullDevice as Persist>::save(
+            &NullDevice::new("foo".to_string(), NullDeviceType::Gpu).unwrap(),
+        );
+        state.virtio_type = crate::virtio::TYPE_NET;
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        match NullDevice::restore(
+            NullDeviceConstructorArgs { mem: default_mem() },
+            &NullDeviceState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        ) {
+            Err(Error::UnsupportedDeviceType(virtio_type)) => {
+                assert_eq!(virtio_type, crate::virtio::TYPE_NET)
+            }
+            _ => panic!("Unexpected result."),
+        }
+    }
+}
@@ -0,0 +1,692 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::num::Wrapping;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use logger::{error, warn};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use super::*;
+use crate::bus::BusDevice;
+use crate::pci::{
+    Error as PciError, MsiSender, MsixConfig, PciConfiguration, PciDevice, MSIX_TABLE_ENTRY_SIZE,
+};
+
+/// The PCI vendor ID of the virtio devices.
+pub const VIRTIO_PCI_VENDOR_ID: u16 = 0x1af4;
+// Modern (non transitional) devices use 0x1040 plus the virtio device type as PCI device ID.
+const VIRTIO_PCI_DEVICE_ID_BASE: u16 = 0x1040;
+// Modern devices have a revision of at least 1.
+const VIRTIO_PCI_REVISION: u8 = 1;
+const VIRTIO_PCI_SUBSYSTEM_ID: u16 = 0x1100;
+
+/// Size of the BAR holding the virtio structures and the MSI-X table of a device.
+pub const VIRTIO_PCI_BAR_SIZE: u64 = 0x8000;
+
+/// The MSI-X vector meaning that an event is not signaled at all.
+pub const VIRTIO_MSI_NO_VECTOR: u16 = 0xffff;
+
+// The layout of the BAR: each structure has its own page.
+const COMMON_CFG_OFFSET: u64 = 0x0000;
+const COMMON_CFG_SIZE: u64 = 0x38;
+const ISR_CFG_OFFSET: u64 = 0x1000;
+const ISR_CFG_SIZE: u64 = 0x1;
+const DEVICE_CFG_OFFSET: u64 = 0x2000;
+const DEVICE_CFG_SIZE: u64 = 0x1000;
+/// Offset in the BAR of the queue notification area.
+pub const NOTIFY_CFG_OFFSET: u64 = 0x3000;
+/// Distance between the notification addresses of two consecutive queues.
+pub const NOTIFY_OFF_MULTIPLIER: u32 = 4;
+const MSIX_TABLE_OFFSET: u64 = 0x4000;
+const MSIX_PBA_OFFSET: u64 = 0x5000;
+const BAR_REGION_SIZE: u64 = 0x1000;
+
+// The vendor specific capabilities pointing to the virtio structures.
+const PCI_CAP_ID_VNDR: u8 = 0x09;
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+const VIRTIO_PCI_CAP_ISR_CFG: u8 = 3;
+const VIRTIO_PCI_CAP_DEVICE_CFG: u8 = 4;
+
+// The guest may only enable and mask MSI-X, in the message control register.
+const MSIX_CAP_WRITABLE_BITS: u32 = 0xc000_0000;
+
+// The MMIO transport registers backing the queue address fields of the common configuration,
+// from the low dword of the descriptor table to the high dword of the used ring.
+const QUEUE_ADDR_MMIO_REGS: [u64; 6] = [0x80, 0x84, 0x90, 0x94, 0xa0, 0xa4];
+
+// Builds a virtio vendor capability pointing at `length` bytes at `offset` in BAR 0.
+fn virtio_capability(cfg_type: u8, offset: u64, length: u64, extra: &[u8]) -> Vec<u8> {
+    let mut cap = vec![
+        PCI_CAP_ID_VNDR,
+        0,
+        16 + extra.len() as u8,
+        cfg_type,
+        0,
+        0,
+        0,
+        0,
+    ];
+    cap.extend_from_slice(&(offset as u32).to_le_bytes());
+    cap.extend_from_slice(&(length as u32).to_le_bytes());
+    cap.extend_from_slice(extra);
+    cap
+}
+
+/// Implements the
+/// [PCI](http://docs.oasis-open.org/virtio/virtio/v1.0/cs04/virtio-v1.0-cs04.html#x1-650001)
+/// transport for virtio devices, on top of the state machine of the MMIO transport.
+///
+/// The device has a single 64-bit memory BAR, placed by the VMM, which holds the virtio
+/// structures along with the MSI-X table. Besides routing the BAR accesses to this device, the
+/// VMM has to:
+///
+/// 1. signal the queue events on writes to the notification area, at `NOTIFY_CFG_OFFSET` plus
+/// `NOTIFY_OFF_MULTIPLIER` times the queue index;
+/// 1. subscribe this device to its event manager, so that the interrupts raised by the inner
+/// virtio device are turned into MSI-X messages.
+pub struct VirtioPciDevice {
+    pub(crate) transport: MmioTransport,
+    pub(crate) config: PciConfiguration,
+    pub(crate) msix: MsixConfig,
+    msix_cap_reg: usize,
+    pub(crate) config_vector: u16,
+    pub(crate) queue_vectors: Vec<u16>,
+    // The `next_used` index of each queue when it was last signaled.
+    pub(crate) signalled_used: Vec<Wrapping<u16>>,
+    pub(crate) slot: u8,
+    pub(crate) bar_addr: u64,
+}
+
+impl VirtioPciDevice {
+    /// Wraps the virtio device of `transport` in a PCI function for `slot`, with its BAR at
+    /// `bar_addr`.
+    pub fn new(
+        transport: MmioTransport,
+        slot: u8,
+        bar_addr: u64,
+        msi_sender: Arc<dyn MsiSender>,
+    ) -> Result<Self, PciError> {
+        let (device_type, num_queues) = {
+            let device = transport.locked_device();
+            (device.device_type(), device.queues().len())
+        };
+        let (class, subclass) = match device_type {
+            TYPE_NET => (0x02, 0x00),
+            TYPE_BLOCK => (0x01, 0x80),
+            _ => (0xff, 0x00),
+        };
+
+        let mut config = PciConfiguration::new(
+            VIRTIO_PCI_VENDOR_ID,
+            VIRTIO_PCI_DEVICE_ID_BASE + device_type as u16,
+            class,
+            subclass,
+            0,
+            VIRTIO_PCI_REVISION,
+            VIRTIO_PCI_VENDOR_ID,
+            VIRTIO_PCI_SUBSYSTEM_ID,
+        );
+        config.add_memory_bar64(bar_addr, VIRTIO_PCI_BAR_SIZE)?;
+
+        // One vector for the configuration changes, and one for each queue.
+        let num_vectors = num_queues as u16 + 1;
+        config.add_capability(
+            &virtio_capability(
+                VIRTIO_PCI_CAP_COMMON_CFG,
+                COMMON_CFG_OFFSET,
+                COMMON_CFG_SIZE,
+                &[],
+            ),
+            0,
+        )?;
+        config.add_capability(
+            &virtio_capability(VIRTIO_PCI_CAP_ISR_CFG, ISR_CFG_OFFSET, ISR_CFG_SIZE, &[]),
+            0,
+        )?;
+        config.add_capability(
+            &virtio_capability(
+                VIRTIO_PCI_CAP_DEVICE_CFG,
+                DEVICE_CFG_OFFSET,
+                DEVICE_CFG_SIZE,
+                &[],
+            ),
+            0,
+        )?;
+        config.add_capability(
+            &virtio_capability(
+                VIRTIO_PCI_CAP_NOTIFY_CFG,
+                NOTIFY_CFG_OFFSET,
+                num_queues as u64 * u64::from(NOTIFY_OFF_MULTIPLIER),
+                &NOTIFY_OFF_MULTIPLIER.to_le_bytes(),
+            ),
+            0,
+        )?;
+        let msix_cap_offset = config.add_capability(
+            &MsixConfig::capability(
+                num_vectors,
+                0,
+                MSIX_TABLE_OFFSET as u32,
+                MSIX_PBA_OFFSET as u32,
+            ),
+            MSIX_CAP_WRITABLE_BITS,
+        )?;
+
+        let signalled_used = transport
+            .locked_device()
+            .queues()
+            .iter()
+            .map(|queue| queue.next_used)
+            .collect();
+
+        Ok(VirtioPciDevice {
+            transport,
+            config,
+            msix: MsixConfig::new(num_vectors, msi_sender),
+            msix_cap_reg: msix_cap_offset / 4,
+            config_vector: VIRTIO_MSI_NO_VECTOR,
+            queue_vectors: vec![VIRTIO_MSI_NO_VECTOR; num_queues],
+            signalled_used,
+            slot,
+            bar_addr,
+        })
+    }
+
+    /// Gets the underlying transport, which holds the virtio device.
+    pub fn transport(&self) -> &MmioTransport {
+        &self.transport
+    }
+
+    /// The slot of the function on the root bus.
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    /// The address of the BAR of the function.
+    pub fn bar_addr(&self) -> u64 {
+        self.bar_addr
+    }
+
+    fn transport_read(&mut self, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        self.transport.read(offset, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    fn transport_write(&mut self, offset: u64, value: u32) {
+        self.transport.write(offset, &value.to_le_bytes());
+    }
+
+    fn with_selected_queue<F: FnOnce(&Queue) -> u32>(&self, f: F) -> u32 {
+        self.transport
+            .locked_device()
+            .queues()
+            .get(self.transport.queue_select as usize)
+            .map_or(0, f)
+    }
+
+    // Vectors outside the MSI-X table are refused, which the driver finds out by reading the
+    // vector back.
+    fn checked_vector(&self, vector: u16) -> u16 {
+        if (vector as usize) < self.msix.num_vectors() {
+            vector
+        } else {
+            VIRTIO_MSI_NO_VECTOR
+        }
+    }
+
+    fn read_common_cfg(&mut self, offset: u64, data: &mut [u8]) {
+        let queue_select = self.transport.queue_select as usize;
+        let value = match (offset, data.len()) {
+            (0x00, 4) => self.transport.features_select,
+            (0x04, 4) => self.transport_read(0x10),
+            (0x08, 4) => self.transport.acked_features_select,
+            // The driver features can't be read back with the MMIO transport either.
+            (0x0c, 4) => 0,
+            (0x10, 2) => u32::from(self.config_vector),
+            (0x12, 2) => self.queue_vectors.len() as u32,
+            (0x14, 1) => self.transport.device_status,
+            (0x15, 1) => self.transport.config_generation,
+            (0x16, 2) => self.transport.queue_select,
+            (0x18, 2) => self
+                .with_selected_queue(|q| u32::from(if q.size == 0 { q.max_size } else { q.size })),
+            (0x1a, 2) => u32::from(
+                *self
+                    .queue_vectors
+                    .get(queue_select)
+                    .unwrap_or(&VIRTIO_MSI_NO_VECTOR),
+            ),
+            (0x1c, 2) => self.transport_read(0x44),
+            (0x1e, 2) => self.transport.queue_select,
+            (0x20..=0x34, 4) if offset % 4 == 0 => self.with_selected_queue(|q| {
+                let addr = match (offset - 0x20) / 8 {
+                    0 => q.desc_table,
+                    1 => q.avail_ring,
+                    _ => q.used_ring,
+                };
+                (addr.0 >> ((offset % 8) * 8)) as u32
+            }),
+            _ => {
+                warn!(
+                    "invalid virtio pci common config read: 0x{:x}:0x{:x}",
+                    offset,
+                    data.len()
+                );
+                return;
+            }
+        };
+        data.copy_from_slice(&value.to_le_bytes()[..data.len()]);
+    }
+
+    fn write_common_cfg(&mut self, offset: u64, data: &[u8]) {
+        let mut bytes = [0u8; 4];
+        if data.len() <= 4 {
+            bytes[..data.len()].copy_from_slice(data);
+        }
+        let value = u32::from_le_bytes(bytes);
+        let queue_select = self.transport.queue_select as usize;
+
+        match (offset, data.len()) {
+            (0x00, 4) => self.transport_write(0x14, value),
+            (0x08, 4) => self.transport_write(0x24, value),
+            (0x0c, 4) => self.transport_write(0x20, value),
+            (0x10, 2) => self.config_vector = self.checked_vector(value as u16),
+            (0x14, 1) => {
+                self.transport_write(0x70, value);
+                if self.transport.device_status == device_status::INIT {
+                    self.reset_vectors();
+                }
+            }
+            (0x16, 2) => self.transport_write(0x30, value),
+            (0x18, 2) => self.transport_write(0x38, value),
+            (0x1a, 2) => {
+                let vector = self.checked_vector(value as u16);
+                if let Some(queue_vector) = self.queue_vectors.get_mut(queue_select) {
+                    *queue_vector = vector;
+                }
+            }
+            (0x1c, 2) => self.transport_write(0x44, value),
+            (0x20..=0x34, 4) if offset % 4 == 0 => {
+                self.transport_write(QUEUE_ADDR_MMIO_REGS[(offset as usize - 0x20) / 4], value)
+            }
+            _ => warn!(
+                "invalid virtio pci common config write: 0x{:x}:0x{:x}",
+                offset,
+                data.len()
+            ),
+        }
+    }
+
+    fn reset_vectors(&mut self) {
+        self.config_vector = VIRTIO_MSI_NO_VECTOR;
+        for vector in self.queue_vectors.iter_mut() {
+            *vector = VIRTIO_MSI_NO_VECTOR;
+        }
+        for used in self.signalled_used.iter_mut() {
+            *used = Wrapping(0);
+        }
+    }
+
+    /// Turns the interrupts raised by the virtio device into MSI-X messages.
+    ///
+    /// The virtio devices only tell which kind of interrupt they raise, so the queues which
+    /// made progress since they were last signaled are found by looking at their used rings.
+    /// If none did, all of them are signaled.
+    pub fn signal_interrupts(&mut self) {
+        let mut status = self.transport.interrupt_status.swap(0, Ordering::SeqCst) as u32;
+        // The vhost backends signal their used rings without setting the interrupt status.
+        if status == 0 {
+            status = VIRTIO_MMIO_INT_VRING;
+        }
+        let mut vectors = Vec::new();
+
+        if status & VIRTIO_MMIO_INT_CONFIG != 0 {
+            vectors.push(self.config_vector);
+        }
+        if status & VIRTIO_MMIO_INT_VRING != 0 {
+            let device = self.transport.locked_device();
+            let mut queue_vectors = Vec::new();
+            for (i, queue) in device.queues().iter().enumerate() {
+                if let Some(used) = self.signalled_used.get_mut(i) {
+                    if *used != queue.next_used {
+                        *used = queue.next_used;
+                        queue_vectors.extend(self.queue_vectors.get(i));
+                    }
+                }
+            }
+            if queue_vectors.is_empty() {
+                queue_vectors = self.queue_vectors.clone();
+            }
+            vectors.append(&mut queue_vectors);
+        }
+
+        vectors.sort_unstable();
+        vectors.dedup();
+        for vector in vectors {
+            if vector != VIRTIO_MSI_NO_VECTOR {
+                self.msix.trigger(vector);
+            }
+        }
+    }
+}
+
+impl BusDevice for VirtioPciDevice {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        let region_offset = offset % BAR_REGION_SIZE;
+        match offset - region_offset {
+            COMMON_CFG_OFFSET => self.read_common_cfg(region_offset, data),
+            // Reading the ISR status acknowledges the interrupts.
+            ISR_CFG_OFFSET if region_offset == 0 && !data.is_empty() => {
+                data[0] = self.transport.interrupt_status.swap(0, Ordering::SeqCst) as u8;
+            }
+            DEVICE_CFG_OFFSET if region_offset < 0xf00 => {
+                self.transport.read(0x100 + region_offset, data)
+            }
+            MSIX_TABLE_OFFSET => self.msix.read_table(region_offset, data),
+            MSIX_PBA_OFFSET => self.msix.read_pba(region_offset, data),
+            _ => warn!("invalid virtio pci read: 0x{:x}:0x{:x}", offset, data.len()),
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        let region_offset = offset % BAR_REGION_SIZE;
+        match offset - region_offset {
+            COMMON_CFG_OFFSET => self.write_common_cfg(region_offset, data),
+            DEVICE_CFG_OFFSET if region_offset < 0xf00 => {
+                self.transport.write(0x100 + region_offset, data)
+            }
+            // The notifications are normally caught by the ioeventfds of the VMM.
+            NOTIFY_CFG_OFFSET => {
+                let queue = (region_offset / u64::from(NOTIFY_OFF_MULTIPLIER)) as usize;
+                if let Some(queue_evt) = self.transport.locked_device().queue_events().get(queue) {
+                    if let Err(e) = queue_evt.write(1) {
+                        error!("Failed to notify virtio queue {}: {:?}", queue, e);
+                    }
+                }
+            }
+            MSIX_TABLE_OFFSET
+                if region_offset + data.len() as u64
+                    <= self.msix.num_vectors() as u64 * MSIX_TABLE_ENTRY_SIZE =>
+            {
+                self.msix.write_table(region_offset, data)
+            }
+            _ => warn!(
+                "invalid virtio pci write: 0x{:x}:0x{:x}",
+                offset,
+                data.len()
+            ),
+        }
+    }
+
+    fn interrupt(&self, irq_mask: u32) -> std::io::Result<()> {
+        self.transport.interrupt(irq_mask)
+    }
+}
+
+impl PciDevice for VirtioPciDevice {
+    fn read_config_register(&mut self, reg_idx: usize) -> u32 {
+        self.config.read_reg(reg_idx)
+    }
+
+    fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]) {
+        self.config.write_reg(reg_idx, offset, data);
+        if reg_idx == self.msix_cap_reg {
+            self.msix
+                .set_message_control((self.config.read_reg(reg_idx) >> 16) as u16);
+        }
+    }
+}
+
+impl Subscriber for VirtioPciDevice {
+    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+        let source = event.fd();
+        let interrupt_evt = self.transport.locked_device().interrupt_evt().as_raw_fd();
+        if source != interrupt_evt {
+            warn!("virtio pci: Spurious event received: {:?}", source);
+            return;
+        }
+
+        if let Err(e) = self.transport.locked_device().interrupt_evt().read() {
+            error!("Failed to read the virtio interrupt event: {:?}", e);
+        }
+        self.signal_interrupts();
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.transport.locked_device().interrupt_evt().as_raw_fd() as u64,
+        )]
+    }
+}
+
+impl dyn BusDevice {
+    /// Returns the virtio transport of the device, whether it sits on the MMIO or the PCI bus.
+    pub fn virtio_transport(&self) -> Option<&MmioTransport> {
+        let device = self.as_any();
+        device.downcast_ref::<MmioTransport>().or_else(|| {
+            device
+                .downcast_ref::<VirtioPciDevice>()
+                .map(VirtioPciDevice::transport)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::pci::MSIX_CAP_ID;
+    use crate::virtio::mmio::tests::DummyDevice;
+    use crate::virtio::test_utils::default_mem;
+    use vm_memory::GuestAddress;
+
+    struct TestSender {
+        sent: Mutex<Vec<u32>>,
+    }
+
+    impl MsiSender for TestSender {
+        fn send_msi(&self, _address: u64, data: u32) -> std::io::Result<()> {
+            self.sent.lock().unwrap().push(data);
+            Ok(())
+        }
+    }
+
+    const BAR_ADDR: u64 = 0xf810_8000;
+
+    fn default_pci_device() -> (VirtioPciDevice, Arc<TestSender>) {
+        let sender = Arc::new(TestSender {
+            sent: Mutex::new(Vec::new()),
+        });
+        let transport = MmioTransport::new(default_mem(), Arc::new(Mutex::new(DummyDevice::new())));
+        let device = VirtioPciDevice::new(transport, 1, BAR_ADDR, sender.clone()).unwrap();
+        (device, sender)
+    }
+
+    fn read_u16(device: &mut VirtioPciDevice, offset: u64) -> u16 {
+        let mut data = [0u8; 2];
+        device.read(offset, &mut data);
+        u16::from_le_bytes(data)
+    }
+
+    fn write_u16(device: &mut VirtioPciDevice, offset: u64, value: u16) {
+        device.write(offset, &value.to_le_bytes());
+    }
+
+    // Walks the capability list, returning the offset of each capability along with its ID.
+    fn capabilities(device: &mut VirtioPciDevice) -> Vec<(u8, usize)> {
+        let mut caps = Vec::new();
+        let mut offset = device.read_config_register(13) as usize & 0xff;
+        while offset != 0 {
+            let reg = device.read_config_register(offset / 4);
+            caps.push((reg as u8, offset));
+            offset = (reg >> 8) as usize & 0xff;
+        }
+        caps
+    }
+
+    #[test]
+    fn test_config_space() {
+        let (mut device, _) = default_pci_device();
+        assert_eq!(device.slot(), 1);
+        assert_eq!(device.bar_addr(), BAR_ADDR);
+        assert_eq!(device.read_config_register(0), 0x10bb_1af4);
+        assert_eq!(device.read_config_register(2) >> 8, 0x00ff_0000);
+        assert_eq!(device.read_config_register(4), 0xf810_8004);
+
+        let caps = capabilities(&mut device);
+        let ids: Vec<u8> = caps.iter().map(|cap| cap.0).collect();
+        assert_eq!(
+            ids,
+            vec![
+                MSIX_CAP_ID,
+                PCI_CAP_ID_VNDR,
+                PCI_CAP_ID_VNDR,
+                PCI_CAP_ID_VNDR,
+                PCI_CAP_ID_VNDR
+            ]
+        );
+        // Two queues and the configuration changes.
+        let msix_reg = caps[0].1 / 4;
+        assert_eq!(device.read_config_register(msix_reg) >> 16, 2);
+        // The notification capability has a multiplier.
+        let notify_reg = caps[1].1 / 4;
+        assert_eq!(
+            device.read_config_register(notify_reg) & 0xffff_0000,
+            u32::from(VIRTIO_PCI_CAP_NOTIFY_CFG) << 24 | 20 << 16
+        );
+        assert_eq!(
+            device.read_config_register(notify_reg + 4),
+            NOTIFY_OFF_MULTIPLIER
+        );
+    }
+
+    #[test]
+    fn test_common_cfg() {
+        let (mut device, _) = default_pci_device();
+
+        assert_eq!(read_u16(&mut device, 0x12), 2);
+        write_u16(&mut device, 0x16, 1);
+        assert_eq!(read_u16(&mut device, 0x16), 1);
+        assert_eq!(read_u16(&mut device, 0x18), 32);
+        assert_eq!(read_u16(&mut device, 0x1e), 1);
+
+        // Only the existing vectors can be used.
+        write_u16(&mut device, 0x1a, 2);
+        assert_eq!(read_u16(&mut device, 0x1a), 2);
+        write_u16(&mut device, 0x1a, 3);
+        assert_eq!(read_u16(&mut device, 0x1a), VIRTIO_MSI_NO_VECTOR);
+        write_u16(&mut device, 0x10, 0);
+        assert_eq!(read_u16(&mut device, 0x10), 0);
+
+        // Walk the device status to FEATURES_OK, to be able to set up the queues.
+        for status in &[
+            device_status::ACKNOWLEDGE,
+            device_status::ACKNOWLEDGE | device_status::DRIVER,
+            device_status::ACKNOWLEDGE | device_status::DRIVER | device_status::FEATURES_OK,
+        ] {
+            device.write(0x14, &[*status as u8]);
+        }
+        let mut data = [0u8; 1];
+        device.read(0x14, &mut data);
+        assert_eq!(u32::from(data[0]), device.transport.device_status);
+
+        write_u16(&mut device, 0x18, 16);
+        assert_eq!(read_u16(&mut device, 0x18), 16);
+        device.write(0x20, &0x1000u32.to_le_bytes());
+        device.write(0x24, &0x1u32.to_le_bytes());
+        device.write(0x30, &0x3000u32.to_le_bytes());
+        let mut data = [0u8; 4];
+        device.read(0x24, &mut data);
+        assert_eq!(u32::from_le_bytes(data), 1);
+        assert_eq!(
+            device.transport.locked_device().queues()[1].desc_table,
+            GuestAddress(0x1_0000_1000)
+        );
+        assert_eq!(
+            device.transport.locked_device().queues()[1].used_ring,
+            GuestAddress(0x3000)
+        );
+
+        // Resetting the device also resets the vectors.
+        device.write(0x14, &[0]);
+        assert_eq!(read_u16(&mut device, 0x10), VIRTIO_MSI_NO_VECTOR);
+        write_u16(&mut device, 0x16, 1);
+        assert_eq!(read_u16(&mut device, 0x1a), VIRTIO_MSI_NO_VECTOR);
+
+        // Invalid accesses are ignored.
+        let mut data = [0xffu8; 4];
+        device.read(0x12, &mut data);
+        assert_eq!(data, [0xff; 4]);
+    }
+
+    #[test]
+    fn test_notify() {
+        let (mut device, _) = default_pci_device();
+        device.write(NOTIFY_CFG_OFFSET + 4, &1u16.to_le_bytes());
+        assert_eq!(
+            device.transport.locked_device().queue_events()[1]
+                .read()
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_msix_interrupts() {
+        let (mut device, sender) = default_pci_device();
+
+        // Enable MSI-X and program the three vectors.
+        let msix_reg = capabilities(&mut device)[0].1 / 4;
+        device.write_config_register(msix_reg, 2, &0x8000u16.to_le_bytes());
+        for vector in 0..3u64 {
+            let entry = MSIX_TABLE_OFFSET + vector * MSIX_TABLE_ENTRY_SIZE;
+            device.write(entry, &0xfee0_0000u32.to_le_bytes());
+            device.write(entry + 8, &(0x40 + vector as u32).to_le_bytes());
+            device.write(entry + 12, &0u32.to_le_bytes());
+        }
+        write_u16(&mut device, 0x10, 0);
+        write_u16(&mut device, 0x16, 0);
+        write_u16(&mut device, 0x1a, 1);
+        write_u16(&mut device, 0x16, 1);
+        write_u16(&mut device, 0x1a, 2);
+
+        // Only the queue which made progress is signaled.
+        device.transport.locked_device().queues_mut()[1].next_used = Wrapping(3);
+        device.interrupt(VIRTIO_MMIO_INT_VRING).unwrap();
+        device.process(
+            &EpollEvent::new(
+                EventSet::IN,
+                device.transport.locked_device().interrupt_evt().as_raw_fd() as u64,
+            ),
+            &mut EventManager::new().unwrap(),
+        );
+        assert_eq!(*sender.sent.lock().unwrap(), vec![0x42]);
+
+        // Otherwise, all of them are.
+        device.interrupt(VIRTIO_MMIO_INT_VRING).unwrap();
+        device.signal_interrupts();
+        assert_eq!(*sender.sent.lock().unwrap(), vec![0x42, 0x41, 0x42]);
+
+        device.interrupt(VIRTIO_MMIO_INT_CONFIG).unwrap();
+        device.signal_interrupts();
+        assert_eq!(sender.sent.lock().unwrap().last(), Some(&0x40));
+        assert_eq!(device.transport.interrupt_status.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_virtio_transport() {
+        let (device, _) = default_pci_device();
+        let device: Box<dyn BusDevice> = Box::new(device);
+        assert!(device.virtio_transport().is_some());
+
+        let transport = MmioTransport::new(default_mem(), Arc::new(Mutex::new(DummyDevice::new())));
+        let device: Box<dyn BusDevice> = Box::new(transport);
+        assert!(device.virtio_transport().is_some());
+    }
+}
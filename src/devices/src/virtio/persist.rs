@@ -5,7 +5,8 @@
 
 use super::device::*;
 use super::queue::*;
-use crate::virtio::MmioTransport;
+use crate::pci::{Error as PciError, MsiSender, MsixConfigState, PciConfigurationState};
+use crate::virtio::{MmioTransport, VirtioPciDevice};
 use snapshot::Persist;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -196,14 +197,82 @@ impl Persist<'_> for MmioTransport {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct VirtioPciDeviceState {
+    /// The slot of the function on the root bus.
+    pub slot: u8,
+    /// The address of the BAR.
+    pub bar_addr: u64,
+    pci_config: PciConfigurationState,
+    msix: MsixConfigState,
+    config_vector: u16,
+    queue_vectors: Vec<u16>,
+}
+
+pub struct VirtioPciDeviceConstructorArgs {
+    /// The restored transport of the virtio device.
+    pub transport: MmioTransport,
+    pub msi_sender: Arc<dyn MsiSender>,
+}
+
+impl Persist<'_> for VirtioPciDevice {
+    type State = VirtioPciDeviceState;
+    type ConstructorArgs = VirtioPciDeviceConstructorArgs;
+    type Error = PciError;
+
+    fn save(&self) -> Self::State {
+        VirtioPciDeviceState {
+            slot: self.slot,
+            bar_addr: self.bar_addr,
+            pci_config: self.config.save_state(),
+            msix: self.msix.save_state(),
+            config_vector: self.config_vector,
+            queue_vectors: self.queue_vectors.clone(),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> Result<Self, Self::Error> {
+        let mut device = VirtioPciDevice::new(
+            constructor_args.transport,
+            state.slot,
+            state.bar_addr,
+            constructor_args.msi_sender,
+        )?;
+        device.config.restore_state(&state.pci_config);
+        device.msix.restore_state(&state.msix);
+        device.config_vector = state.config_vector;
+        for (vector, saved) in device
+            .queue_vectors
+            .iter_mut()
+            .zip(state.queue_vectors.iter())
+        {
+            *vector = *saved;
+        }
+        Ok(device)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::virtio::mmio::tests::DummyDevice;
-    use crate::virtio::{net, Block, Net, Vsock, VsockUnixBackend};
-
+    #[cfg(feature = "block")]
+    use crate::virtio::Block;
+    #[cfg(feature = "net")]
+    use crate::virtio::{net, Net};
+    #[cfg(feature = "vsock")]
+    use crate::virtio::{Vsock, VsockUnixBackend};
+    #[cfg(feature = "vsock")]
+    use rate_limiter::RateLimiter;
+
+    #[cfg(feature = "block")]
     use crate::virtio::block::test_utils::default_block_with_path;
     use crate::virtio::test_utils::default_mem;
+    #[cfg(any(feature = "block", feature = "vsock"))]
     use utils::tempfile::TempFile;
 
     const DEFAULT_QUEUE_MAX_SIZE: u16 = 256;
@@ -342,6 +411,7 @@ mod tests {
         }
     }
 
+    #[cfg(any(feature = "block", feature = "net", feature = "vsock"))]
     fn generic_mmiotransport_persistence_test(
         mmio_transport: MmioTransport,
         mem: GuestMemoryMmap,
@@ -365,6 +435,7 @@ mod tests {
         assert_eq!(restored_mmio_transport, mmio_transport);
     }
 
+    #[cfg(feature = "block")]
     fn default_block() -> (MmioTransport, GuestMemoryMmap, Arc<Mutex<Block>>) {
         let mem = default_mem();
 
@@ -378,6 +449,7 @@ mod tests {
         (mmio_transport, mem, block)
     }
 
+    #[cfg(feature = "net")]
     fn default_net() -> (MmioTransport, GuestMemoryMmap, Arc<Mutex<Net>>) {
         let mem = default_mem();
         let net = Arc::new(Mutex::new(net::test_utils::default_net()));
@@ -386,6 +458,7 @@ mod tests {
         (mmio_transport, mem, net)
     }
 
+    #[cfg(feature = "vsock")]
     fn default_vsock() -> (
         MmioTransport,
         GuestMemoryMmap,
@@ -399,7 +472,13 @@ mod tests {
         temp_uds_path.remove().unwrap();
         let uds_path = String::from(temp_uds_path.as_path().to_str().unwrap());
         let backend = VsockUnixBackend::new(guest_cid, uds_path).unwrap();
-        let vsock = Vsock::new(guest_cid, backend).unwrap();
+        let vsock = Vsock::new(
+            guest_cid,
+            backend,
+            RateLimiter::default(),
+            RateLimiter::default(),
+        )
+        .unwrap();
         let vsock = Arc::new(Mutex::new(vsock));
         let mmio_transport = MmioTransport::new(mem.clone(), vsock.clone());
 
@@ -407,20 +486,65 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "block")]
     fn test_block_over_mmiotransport_persistence() {
         let (mmio_transport, mem, block) = default_block();
         generic_mmiotransport_persistence_test(mmio_transport, mem, block);
     }
 
     #[test]
+    #[cfg(feature = "net")]
     fn test_net_over_mmiotransport_persistence() {
         let (mmio_transport, mem, net) = default_net();
         generic_mmiotransport_persistence_test(mmio_transport, mem, net);
     }
 
     #[test]
+    #[cfg(feature = "vsock")]
     fn test_vsock_over_mmiotransport_persistence() {
         let (mmio_transport, mem, vsock) = default_vsock();
         generic_mmiotransport_persistence_test(mmio_transport, mem, vsock);
     }
+
+    #[cfg(feature = "net")]
+    struct NoopSender;
+
+    #[cfg(feature = "net")]
+    impl MsiSender for NoopSender {
+        fn send_msi(&self, _: u64, _: u32) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
+    fn test_virtio_pci_device_persistence() {
+        let (mmio_transport, mem, net) = default_net();
+        let mut device =
+            VirtioPciDevice::new(mmio_transport, 3, 0xf811_8000, Arc::new(NoopSender)).unwrap();
+        device.config_vector = 0;
+        device.queue_vectors[1] = 2;
+
+        // The configuration space alone takes 4 KiB.
+        let mut buf = vec![0; 8192];
+        let version_map = VersionMap::new();
+        device
+            .save()
+            .serialize(&mut buf.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        let restore_args = VirtioPciDeviceConstructorArgs {
+            transport: MmioTransport::new(mem, net),
+            msi_sender: Arc::new(NoopSender),
+        };
+        let restored = VirtioPciDevice::restore(
+            restore_args,
+            &VirtioPciDeviceState::deserialize(&mut buf.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored.save(), device.save());
+        assert_eq!(restored.slot(), 3);
+        assert_eq!(restored.bar_addr(), 0xf811_8000);
+    }
 }
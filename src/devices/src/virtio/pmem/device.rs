@@ -0,0 +1,498 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{error, IncMetric, METRICS};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{ByteValued, Bytes, FileOffset, GuestAddress, GuestMemoryMmap, MmapRegion};
+
+use super::*;
+use crate::virtio::{
+    ActivateError, ActivateResult, DescriptorChain, DeviceState, Queue, VirtioDevice, TYPE_PMEM,
+    VIRTIO_MMIO_INT_VRING,
+};
+
+// The only request defined by the virtio spec, asking the device to persist the guest writes.
+const VIRTIO_PMEM_REQ_TYPE_FLUSH: u32 = 0;
+const VIRTIO_PMEM_RESP_TYPE_OK: u32 = 0;
+const VIRTIO_PMEM_RESP_TYPE_EIO: u32 = 1;
+// Both the request and the response are made of a single 32 bits word.
+const REQUEST_LEN: u32 = 4;
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub(crate) struct ConfigSpace {
+    pub start: u64,
+    pub size: u64,
+}
+
+// Safe because ConfigSpace only contains plain data.
+unsafe impl ByteValued for ConfigSpace {}
+
+// Returns the address of the response buffer of a request, after checking the layout of the
+// descriptor chain: a read-only buffer holding the request, followed by a write-only buffer
+// for the response.
+fn response_address(head: &DescriptorChain) -> Result<GuestAddress> {
+    if head.is_write_only() || head.len < REQUEST_LEN {
+        return Err(Error::MalformedDescriptor);
+    }
+    let status_desc = head.next_descriptor().ok_or(Error::MalformedDescriptor)?;
+    if !status_desc.is_write_only() || status_desc.len < REQUEST_LEN {
+        return Err(Error::MalformedDescriptor);
+    }
+
+    Ok(status_desc.addr)
+}
+
+/// A virtio-pmem device, exposing a host file mapped in the guest physical address space.
+pub struct Pmem {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) config_space: ConfigSpace,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    pub(crate) interrupt_evt: EventFd,
+    pub(crate) queue_evts: [EventFd; NUM_QUEUES],
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    pub(crate) id: String,
+    pub(crate) path_on_host: String,
+    pub(crate) read_only: bool,
+    file: File,
+    mapping: MmapRegion,
+}
+
+impl Pmem {
+    /// Creates a virtio-pmem device backed by the file at `path_on_host`, which is mapped in the
+    /// address space of the process right away. The device is placed in the guest physical
+    /// address space through `set_guest_address`.
+    pub fn new(id: String, path_on_host: String, read_only: bool) -> Result<Pmem> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .open(&path_on_host)
+            .map_err(Error::File)?;
+        let size = file.metadata().map_err(Error::File)?.len();
+        if size == 0 || size % PMEM_ALIGNMENT != 0 {
+            return Err(Error::BackingFileSize(size));
+        }
+
+        let prot = if read_only {
+            libc::PROT_READ
+        } else {
+            libc::PROT_READ | libc::PROT_WRITE
+        };
+        // The mapping is shared, so that the guest writes reach the backing file.
+        let mapping = MmapRegion::build(
+            Some(FileOffset::new(file.try_clone().map_err(Error::File)?, 0)),
+            size as usize,
+            prot,
+            libc::MAP_SHARED | libc::MAP_NORESERVE,
+        )
+        .map_err(Error::Mmap)?;
+
+        let queue_evts = [EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?];
+        let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+
+        Ok(Pmem {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            config_space: ConfigSpace { start: 0, size },
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            id,
+            path_on_host,
+            read_only,
+            file,
+            mapping,
+        })
+    }
+
+    /// Provides the ID of this device.
+    pub fn id(&self) -> &String {
+        &self.id
+    }
+
+    /// Provides the path of the backing file.
+    pub fn path_on_host(&self) -> &String {
+        &self.path_on_host
+    }
+
+    /// Returns whether the guest is denied write access to the backing file.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns the guest physical address the backing file is mapped at.
+    pub fn guest_address(&self) -> GuestAddress {
+        GuestAddress(self.config_space.start)
+    }
+
+    /// Places the backing file at `addr` in the guest physical address space.
+    pub fn set_guest_address(&mut self, addr: GuestAddress) {
+        self.config_space.start = addr.0;
+    }
+
+    /// Returns the size of the backing file.
+    pub fn size(&self) -> u64 {
+        self.config_space.size
+    }
+
+    /// Returns the address of the backing file mapping in the address space of the process.
+    pub fn host_address(&self) -> u64 {
+        self.mapping.as_ptr() as u64
+    }
+
+    pub(crate) fn process_queue_event(&mut self) -> Result<()> {
+        self.queue_evts[0].read().map_err(Error::EventFd)?;
+        self.process_queue()
+    }
+
+    pub(crate) fn process_queue(&mut self) -> Result<()> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let mut needs_interrupt = false;
+
+        while let Some(head) = self.queues[0].pop(&mem) {
+            let head_index = head.index;
+            let len = match self.handle_request(&mem, &head) {
+                Ok(()) => REQUEST_LEN,
+                Err(e) => {
+                    error!("Failed to parse pmem request: {:?}", e);
+                    METRICS.pmem.flush_fails.inc();
+                    0
+                }
+            };
+
+            self.queues[0]
+                .add_used(&mem, head_index, len)
+                .map_err(Error::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()?;
+        }
+
+        Ok(())
+    }
+
+    // Serves the request at `head`, writing its status to the guest memory.
+    fn handle_request(&mut self, mem: &GuestMemoryMmap, head: &DescriptorChain) -> Result<()> {
+        let response_addr = response_address(head)?;
+        let req_type: u32 = mem.read_obj(head.addr).map_err(Error::GuestMemory)?;
+
+        let status = if req_type != VIRTIO_PMEM_REQ_TYPE_FLUSH {
+            error!("Unsupported pmem request type: {}", req_type);
+            METRICS.pmem.flush_fails.inc();
+            VIRTIO_PMEM_RESP_TYPE_EIO
+        } else {
+            match self.flush() {
+                Ok(()) => {
+                    METRICS.pmem.flush_count.inc();
+                    VIRTIO_PMEM_RESP_TYPE_OK
+                }
+                Err(e) => {
+                    error!("Failed to flush the pmem backing file: {:?}", e);
+                    METRICS.pmem.flush_fails.inc();
+                    VIRTIO_PMEM_RESP_TYPE_EIO
+                }
+            }
+        };
+
+        mem.write_obj(status, response_addr)
+            .map_err(Error::GuestMemory)
+    }
+
+    // Persists the guest writes to the backing file.
+    fn flush(&mut self) -> Result<()> {
+        // The guest can't write a read-only backing file, so there is nothing to persist.
+        if self.read_only {
+            return Ok(());
+        }
+        // The mapping is shared, so the dirty pages are part of the page cache of the file.
+        self.file.sync_all().map_err(Error::File)
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<()> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal used queue: {:?}", e);
+            Error::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        let _ = self.process_queue();
+    }
+}
+
+impl VirtioDevice for Pmem {
+    fn device_type(&self) -> u32 {
+        TYPE_PMEM
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) {
+        let config_space_bytes = self.config_space.as_slice();
+        let config_len = config_space_bytes.len() as u64;
+        if offset >= config_len {
+            error!("Failed to read config space");
+            return;
+        }
+
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            // This write can't fail, offset and end are checked against config_len.
+            data.write_all(
+                &config_space_bytes[offset as usize..cmp::min(end, config_len) as usize],
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The configuration space is read-only for the driver.
+        error!("Failed to write config space");
+        METRICS.pmem.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Pmem: Cannot write to activate_evt");
+            METRICS.pmem.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::virtio::test_utils::{default_mem, VirtQueue};
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+    use utils::tempfile::TempFile;
+
+    impl Pmem {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    /// Creates a backing file of `size` bytes.
+    pub(crate) fn backing_file(size: u64) -> TempFile {
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(size).unwrap();
+        file
+    }
+
+    pub(crate) fn default_pmem(file: &TempFile) -> Pmem {
+        Pmem::new(
+            "pmem0".to_string(),
+            file.as_path().to_str().unwrap().to_string(),
+            false,
+        )
+        .unwrap()
+    }
+
+    // Places a request made of a request buffer at 0x1000 and a response buffer at 0x2000.
+    pub(crate) fn push_request(queue: &VirtQueue, mem: &GuestMemoryMmap, req_type: u32) {
+        mem.write_obj(req_type, GuestAddress(0x1000)).unwrap();
+        mem.write_obj(0xffu32, GuestAddress(0x2000)).unwrap();
+        let idx = queue.avail.idx.get();
+        queue.dtable[0].set(0x1000, REQUEST_LEN, VIRTQ_DESC_F_NEXT, 1);
+        queue.dtable[1].set(0x2000, REQUEST_LEN, VIRTQ_DESC_F_WRITE, 0);
+        queue.avail.ring[idx as usize % 16].set(0);
+        queue.avail.idx.set(idx + 1);
+    }
+
+    #[test]
+    fn test_new() {
+        let file = backing_file(PMEM_ALIGNMENT);
+        let mut pmem = default_pmem(&file);
+
+        assert_eq!(pmem.device_type(), TYPE_PMEM);
+        assert_eq!(pmem.id(), "pmem0");
+        assert_eq!(pmem.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(pmem.acked_features(), 0);
+        assert_eq!(pmem.queues().len(), NUM_QUEUES);
+        assert_eq!(pmem.size(), PMEM_ALIGNMENT);
+        assert!(!pmem.is_read_only());
+        assert!(!pmem.is_activated());
+        assert_ne!(pmem.host_address(), 0);
+
+        pmem.set_guest_address(GuestAddress(1 << 32));
+        assert_eq!(pmem.guest_address(), GuestAddress(1 << 32));
+
+        // The backing file can't be empty.
+        let file = backing_file(0);
+        let path = file.as_path().to_str().unwrap().to_string();
+        match Pmem::new("pmem0".to_string(), path, false) {
+            Err(Error::BackingFileSize(0)) => (),
+            _ => panic!("Unexpected result."),
+        }
+        // The backing file size must be aligned.
+        let file = backing_file(PMEM_ALIGNMENT + 4096);
+        let path = file.as_path().to_str().unwrap().to_string();
+        match Pmem::new("pmem0".to_string(), path, true) {
+            Err(Error::BackingFileSize(size)) => assert_eq!(size, PMEM_ALIGNMENT + 4096),
+            _ => panic!("Unexpected result."),
+        }
+        // The backing file must exist.
+        match Pmem::new("pmem0".to_string(), "/invalid/path".to_string(), true) {
+            Err(Error::File(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let file = backing_file(2 * PMEM_ALIGNMENT);
+        let mut pmem = default_pmem(&file);
+        pmem.set_guest_address(GuestAddress(0x1_0000_0000));
+
+        let mut data = [0u8; 16];
+        pmem.read_config(0, &mut data);
+        assert_eq!(&data[..8], &0x1_0000_0000u64.to_le_bytes());
+        assert_eq!(&data[8..], &(2 * PMEM_ALIGNMENT).to_le_bytes());
+
+        // Reads past the configuration space are ignored.
+        let mut data = [0u8; 8];
+        pmem.read_config(16, &mut data);
+        assert_eq!(data, [0u8; 8]);
+
+        check_metric_after_block!(METRICS.pmem.cfg_fails, 1, pmem.write_config(0, &data));
+        assert_eq!(pmem.size(), 2 * PMEM_ALIGNMENT);
+    }
+
+    #[test]
+    fn test_process_requests() {
+        let file = backing_file(PMEM_ALIGNMENT);
+        let mut pmem = default_pmem(&file);
+        let mem = default_mem();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        pmem.set_queue(0, queue.create_queue());
+        pmem.activate(mem.clone()).unwrap();
+
+        // The guest writes reach the backing file through the mapping.
+        // Safe because the mapping is valid and at least one page long.
+        unsafe { *(pmem.host_address() as *mut u8) = 0xaa };
+
+        // A flush succeeds.
+        push_request(&queue, &mem, VIRTIO_PMEM_REQ_TYPE_FLUSH);
+        check_metric_after_block!(METRICS.pmem.flush_count, 1, pmem.process_queue().unwrap());
+        queue.check_used_elem(0, 0, REQUEST_LEN);
+        assert_eq!(
+            mem.read_obj::<u32>(GuestAddress(0x2000)).unwrap(),
+            VIRTIO_PMEM_RESP_TYPE_OK
+        );
+        assert_eq!(pmem.interrupt_evt.read().unwrap(), 1);
+        let mut contents = [0u8; 1];
+        std::io::Read::read_exact(&mut file.as_file(), &mut contents).unwrap();
+        assert_eq!(contents[0], 0xaa);
+
+        // Unknown requests are answered with an error.
+        push_request(&queue, &mem, 1);
+        check_metric_after_block!(METRICS.pmem.flush_fails, 1, pmem.process_queue().unwrap());
+        queue.check_used_elem(1, 0, REQUEST_LEN);
+        assert_eq!(
+            mem.read_obj::<u32>(GuestAddress(0x2000)).unwrap(),
+            VIRTIO_PMEM_RESP_TYPE_EIO
+        );
+
+        // A request without a response buffer is rejected.
+        let idx = queue.avail.idx.get();
+        queue.dtable[0].set(0x1000, REQUEST_LEN, 0, 0);
+        queue.avail.ring[idx as usize].set(0);
+        queue.avail.idx.set(idx + 1);
+        check_metric_after_block!(METRICS.pmem.flush_fails, 1, pmem.process_queue().unwrap());
+        queue.check_used_elem(2, 0, 0);
+
+        // So is a request whose response buffer is read-only.
+        push_request(&queue, &mem, VIRTIO_PMEM_REQ_TYPE_FLUSH);
+        queue.dtable[1].set(0x2000, REQUEST_LEN, 0, 0);
+        check_metric_after_block!(METRICS.pmem.flush_fails, 1, pmem.process_queue().unwrap());
+        queue.check_used_elem(3, 0, 0);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let file = backing_file(PMEM_ALIGNMENT);
+        let path = file.as_path().to_str().unwrap().to_string();
+        let mut pmem = Pmem::new("pmem0".to_string(), path, true).unwrap();
+        assert!(pmem.is_read_only());
+        let mem = default_mem();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        pmem.set_queue(0, queue.create_queue());
+        pmem.activate(mem.clone()).unwrap();
+
+        // Flushing a read-only device is a no-op.
+        push_request(&queue, &mem, VIRTIO_PMEM_REQ_TYPE_FLUSH);
+        check_metric_after_block!(METRICS.pmem.flush_count, 1, pmem.process_queue().unwrap());
+        assert_eq!(
+            mem.read_obj::<u32>(GuestAddress(0x2000)).unwrap(),
+            VIRTIO_PMEM_RESP_TYPE_OK
+        );
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements the virtio-pmem device, which maps a host file directly into the guest physical
+//! address space. The guest accesses the file contents without going through a block layer
+//! and only asks the device to flush its writes to the host.
+
+pub mod device;
+pub mod event_handler;
+pub mod persist;
+
+use vm_memory::GuestMemoryError;
+
+pub use self::device::Pmem;
+
+pub const QUEUE_SIZE: u16 = 256;
+pub const NUM_QUEUES: usize = 1;
+pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+/// The size of the backing files must be a multiple of this alignment, so that the guest can
+/// map them as whole huge pages.
+pub const PMEM_ALIGNMENT: u64 = 2 << 20;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The backing file is empty or its size is not a multiple of `PMEM_ALIGNMENT`.
+    BackingFileSize(u64),
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Failed to signal the virtio used queue.
+    FailedSignalingUsedQueue(std::io::Error),
+    /// Failed to access the backing file.
+    File(std::io::Error),
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Guest gave us a malformed descriptor.
+    MalformedDescriptor,
+    /// Failed to map the backing file.
+    Mmap(vm_memory::mmap::MmapRegionError),
+    /// Error while processing the virt queues.
+    Queue(super::QueueError),
+    /// Error restoring the pmem device queues.
+    QueueRestoreError,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
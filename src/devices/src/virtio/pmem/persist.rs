@@ -0,0 +1,137 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring pmem devices.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::{GuestAddress, GuestMemoryMmap};
+
+use super::*;
+
+use crate::virtio::persist::VirtioDeviceState;
+use crate::virtio::{DeviceState, TYPE_PMEM};
+
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct PmemState {
+    id: String,
+    path_on_host: String,
+    read_only: bool,
+    guest_address: u64,
+    size: u64,
+    virtio_state: VirtioDeviceState,
+}
+
+pub struct PmemConstructorArgs {
+    pub mem: GuestMemoryMmap,
+}
+
+impl Persist<'_> for Pmem {
+    type State = PmemState;
+    type ConstructorArgs = PmemConstructorArgs;
+    type Error = super::Error;
+
+    fn save(&self) -> Self::State {
+        PmemState {
+            id: self.id().clone(),
+            path_on_host: self.path_on_host().clone(),
+            read_only: self.is_read_only(),
+            guest_address: self.guest_address().0,
+            size: self.size(),
+            virtio_state: VirtioDeviceState::from_device(self),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        // The contents of the backing file are not part of the snapshot, the file is mapped
+        // again at the same guest physical address.
+        let mut pmem = Pmem::new(
+            state.id.clone(),
+            state.path_on_host.clone(),
+            state.read_only,
+        )?;
+        if pmem.size() != state.size {
+            return Err(Error::BackingFileSize(pmem.size()));
+        }
+        pmem.set_guest_address(GuestAddress(state.guest_address));
+
+        pmem.queues = state
+            .virtio_state
+            .build_queues_checked(&constructor_args.mem, TYPE_PMEM, NUM_QUEUES, QUEUE_SIZE)
+            .map_err(|_| Self::Error::QueueRestoreError)?;
+        pmem.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
+        pmem.avail_features = state.virtio_state.avail_features;
+        pmem.acked_features = state.virtio_state.acked_features;
+
+        if state.virtio_state.activated {
+            pmem.device_state = DeviceState::Activated(constructor_args.mem);
+        }
+
+        Ok(pmem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::device::VirtioDevice;
+    use crate::virtio::pmem::device::tests::{backing_file, default_pmem};
+    use crate::virtio::test_utils::default_mem;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_persistence() {
+        let guest_mem = default_mem();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        // Create and save the pmem device.
+        let file = backing_file(PMEM_ALIGNMENT);
+        let mut pmem = default_pmem(&file);
+        pmem.set_guest_address(GuestAddress(1 << 32));
+        <Pmem as Persist>::save(&pmem)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Deserialize and restore the pmem device.
+        let state = PmemState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap();
+        let restored_pmem = Pmem::restore(
+            PmemConstructorArgs {
+                mem: guest_mem.clone(),
+            },
+            &state,
+        )
+        .unwrap();
+
+        assert_eq!(restored_pmem.device_type(), TYPE_PMEM);
+        assert_eq!(restored_pmem.id(), pmem.id());
+        assert_eq!(restored_pmem.path_on_host(), pmem.path_on_host());
+        assert_eq!(restored_pmem.is_read_only(), pmem.is_read_only());
+        assert_eq!(restored_pmem.guest_address(), GuestAddress(1 << 32));
+        assert_eq!(restored_pmem.size(), pmem.size());
+        assert_ne!(restored_pmem.host_address(), pmem.host_address());
+        assert_eq!(restored_pmem.acked_features, pmem.acked_features);
+        assert_eq!(restored_pmem.avail_features, pmem.avail_features);
+        assert_eq!(restored_pmem.queues(), pmem.queues());
+        assert_eq!(
+            restored_pmem.interrupt_status().load(Ordering::Relaxed),
+            pmem.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored_pmem.is_activated(), pmem.is_activated());
+
+        // The backing file can't change size between snapshot and restore.
+        file.as_file().set_len(2 * PMEM_ALIGNMENT).unwrap();
+        match Pmem::restore(PmemConstructorArgs { mem: guest_mem }, &state) {
+            Err(Error::BackingFileSize(size)) => assert_eq!(size, 2 * PMEM_ALIGNMENT),
+            _ => panic!("Unexpected result."),
+        }
+    }
+}
@@ -0,0 +1,444 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::cmp;
+use std::convert::TryFrom;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{error, IncMetric, METRICS};
+use rate_limiter::{BucketUpdate, RateLimiter, TokenType};
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
+use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+
+use super::*;
+use crate::virtio::{
+    ActivateError, ActivateResult, DescriptorChain, DeviceState, Queue, VirtioDevice, TYPE_RNG,
+    VIRTIO_MMIO_INT_VRING,
+};
+
+// The random bytes are copied to the guest memory through a buffer of this size.
+const CHUNK_SIZE: usize = 4096;
+
+// Fills `buf` with random bytes from the host kernel.
+fn fill_random(buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let remaining = &mut buf[filled..];
+        // Safe because the kernel only writes within the bounds of `remaining`, and we check
+        // the result.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_getrandom,
+                remaining.as_mut_ptr(),
+                remaining.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        filled += ret as usize;
+    }
+
+    Ok(())
+}
+
+// Collects the buffers of the `head` descriptor chain, which must all be write-only.
+fn request_buffers(head: &DescriptorChain) -> Result<Vec<(GuestAddress, u32)>> {
+    if !head.is_write_only() {
+        return Err(Error::MalformedDescriptor);
+    }
+    let mut buffers = vec![(head.addr, head.len)];
+    let mut next = head.next_descriptor();
+    while let Some(desc) = next {
+        if !desc.is_write_only() {
+            return Err(Error::MalformedDescriptor);
+        }
+        buffers.push((desc.addr, desc.len));
+        next = desc.next_descriptor();
+    }
+
+    Ok(buffers)
+}
+
+/// A virtio-rng device, which fills the buffers provided by the guest with random bytes from
+/// the host.
+pub struct Entropy {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    pub(crate) interrupt_evt: EventFd,
+    pub(crate) queue_evts: [EventFd; NUM_QUEUES],
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    pub(crate) rate_limiter: RateLimiter,
+}
+
+impl Entropy {
+    /// Creates a virtio-rng device, whose throughput is capped by `rate_limiter`.
+    pub fn new(rate_limiter: RateLimiter) -> Result<Entropy> {
+        let queue_evts = [EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?];
+        let queues = QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+
+        Ok(Entropy {
+            avail_features: 1u64 << VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            rate_limiter,
+        })
+    }
+
+    /// Provides the ID of this device.
+    pub fn id(&self) -> &str {
+        ENTROPY_DEV_ID
+    }
+
+    /// Provides a reference to the rate limiter of this device.
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Updates the parameters for the rate limiter.
+    pub fn update_rate_limiter(&mut self, bytes: BucketUpdate, ops: BucketUpdate) {
+        self.rate_limiter.update_buckets(bytes, ops);
+    }
+
+    pub(crate) fn process_queue_event(&mut self) -> Result<()> {
+        self.queue_evts[0].read().map_err(Error::EventFd)?;
+        if self.rate_limiter.is_blocked() {
+            METRICS.entropy.rate_limiter_throttled_events.inc();
+            return Ok(());
+        }
+        self.process_queue()
+    }
+
+    pub(crate) fn process_rate_limiter_event(&mut self) -> Result<()> {
+        METRICS.entropy.rate_limiter_event_count.inc();
+        // Upon rate limiter event, call the rate limiter handler
+        // and restart processing the queue.
+        if self.rate_limiter.event_handler().is_ok() {
+            self.process_queue()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn process_queue(&mut self) -> Result<()> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem.clone(),
+            // This should never happen, it's been already validated in the event handler.
+            DeviceState::Inactive => unreachable!(),
+        };
+        let mut needs_interrupt = false;
+
+        while let Some(head) = self.queues[0].pop(&mem) {
+            let head_index = head.index;
+            let len = match request_buffers(&head) {
+                Ok(buffers) => {
+                    let len: u64 = buffers.iter().map(|(_, len)| u64::from(*len)).sum();
+                    // If the rate limiter is out of budget, return the descriptor chain to
+                    // the avail ring, for later processing.
+                    if !self.rate_limiter.consume(1, TokenType::Ops) {
+                        self.queues[0].undo_pop();
+                        METRICS.entropy.rate_limiter_throttled_events.inc();
+                        break;
+                    }
+                    if !self.rate_limiter.consume(len, TokenType::Bytes) {
+                        // Revert the OPS consume().
+                        self.rate_limiter.manual_replenish(1, TokenType::Ops);
+                        self.queues[0].undo_pop();
+                        METRICS.entropy.rate_limiter_throttled_events.inc();
+                        break;
+                    }
+
+                    match self.fill_buffers(&mem, &buffers) {
+                        Ok(len) => len,
+                        Err(e) => {
+                            error!("Failed to provide entropy: {:?}", e);
+                            METRICS.entropy.entropy_fails.inc();
+                            0
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to parse entropy request: {:?}", e);
+                    METRICS.entropy.entropy_fails.inc();
+                    0
+                }
+            };
+
+            self.queues[0]
+                .add_used(&mem, head_index, len)
+                .map_err(Error::Queue)?;
+            needs_interrupt = true;
+        }
+
+        if needs_interrupt {
+            self.signal_used_queue()?;
+        }
+
+        Ok(())
+    }
+
+    // Fills the guest `buffers` with random bytes, returning the number of bytes written.
+    fn fill_buffers(&self, mem: &GuestMemoryMmap, buffers: &[(GuestAddress, u32)]) -> Result<u32> {
+        let mut chunk = [0u8; CHUNK_SIZE];
+        let mut written = 0u64;
+
+        for (addr, len) in buffers.iter() {
+            let mut offset = 0u64;
+            while offset < u64::from(*len) {
+                let size = cmp::min(CHUNK_SIZE as u64, u64::from(*len) - offset) as usize;
+                fill_random(&mut chunk[..size]).map_err(Error::HostRandom)?;
+                let chunk_addr = addr.checked_add(offset).ok_or(Error::MalformedDescriptor)?;
+                mem.write_slice(&chunk[..size], chunk_addr)
+                    .map_err(Error::GuestMemory)?;
+                offset += size as u64;
+            }
+            written += u64::from(*len);
+        }
+
+        METRICS.entropy.entropy_bytes.add(written as usize);
+        u32::try_from(written).map_err(|_| Error::MalformedDescriptor)
+    }
+
+    pub(crate) fn signal_used_queue(&self) -> Result<()> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt.write(1).map_err(|e| {
+            error!("Failed to signal used queue: {:?}", e);
+            Error::FailedSignalingUsedQueue(e)
+        })
+    }
+
+    /// Process device virtio queue(s).
+    pub fn process_virtio_queues(&mut self) {
+        let _ = self.process_queue();
+    }
+}
+
+impl VirtioDevice for Entropy {
+    fn device_type(&self) -> u32 {
+        TYPE_RNG
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, _offset: u64, _data: &mut [u8]) {
+        // The virtio-rng device has no configuration space.
+        error!("Failed to read config space");
+        METRICS.entropy.cfg_fails.inc();
+    }
+
+    fn write_config(&mut self, _offset: u64, _data: &[u8]) {
+        // The virtio-rng device has no configuration space.
+        error!("Failed to write config space");
+        METRICS.entropy.cfg_fails.inc();
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if self.activate_evt.write(1).is_err() {
+            error!("Entropy: Cannot write to activate_evt");
+            METRICS.entropy.activate_fails.inc();
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::virtio::test_utils::{default_mem, VirtQueue};
+    use crate::virtio::{VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+
+    impl Entropy {
+        pub(crate) fn set_queue(&mut self, idx: usize, q: Queue) {
+            self.queues[idx] = q;
+        }
+    }
+
+    pub(crate) fn default_entropy() -> Entropy {
+        Entropy::new(RateLimiter::default()).unwrap()
+    }
+
+    // Places a request made of a single buffer on the queue.
+    fn push_request(queue: &VirtQueue, addr: u64, len: u32, flags: u16) {
+        let idx = queue.avail.idx.get();
+        queue.dtable[0].set(addr, len, flags, 0);
+        queue.avail.ring[idx as usize % 16].set(0);
+        queue.avail.idx.set(idx + 1);
+    }
+
+    #[test]
+    fn test_new() {
+        let entropy = default_entropy();
+
+        assert_eq!(entropy.device_type(), TYPE_RNG);
+        assert_eq!(entropy.id(), ENTROPY_DEV_ID);
+        assert_eq!(entropy.avail_features(), 1u64 << VIRTIO_F_VERSION_1);
+        assert_eq!(entropy.acked_features(), 0);
+        assert_eq!(entropy.queues().len(), NUM_QUEUES);
+        assert!(!entropy.is_activated());
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let mut entropy = default_entropy();
+        let mut data = [0u8; 4];
+
+        check_metric_after_block!(
+            METRICS.entropy.cfg_fails,
+            1,
+            entropy.read_config(0, &mut data)
+        );
+        assert_eq!(data, [0u8; 4]);
+        check_metric_after_block!(METRICS.entropy.cfg_fails, 1, entropy.write_config(0, &data));
+    }
+
+    #[test]
+    fn test_fill_random() {
+        let mut buf = [0u8; 64];
+        fill_random(&mut buf).unwrap();
+        // The odds of the kernel returning 64 zeroes are negligible.
+        assert!(buf.iter().any(|&b| b != 0));
+    }
+
+    #[test]
+    fn test_process_requests() {
+        let mut entropy = default_entropy();
+        let mem = default_mem();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        entropy.set_queue(0, queue.create_queue());
+        entropy.activate(mem.clone()).unwrap();
+
+        // A request spanning several chunks is completely filled.
+        let len = 2 * CHUNK_SIZE as u32 + 10;
+        push_request(&queue, 0x2000, len, VIRTQ_DESC_F_WRITE);
+        check_metric_after_block!(
+            METRICS.entropy.entropy_bytes,
+            len as usize,
+            entropy.process_queue().unwrap()
+        );
+        assert_eq!(queue.used.idx.get(), 1);
+        queue.check_used_elem(0, 0, len);
+        let mut data = vec![0u8; len as usize];
+        mem.read_slice(&mut data, GuestAddress(0x2000)).unwrap();
+        assert!(data[len as usize - 64..].iter().any(|&b| b != 0));
+        assert_eq!(entropy.interrupt_evt.read().unwrap(), 1);
+
+        // Read-only buffers are rejected.
+        push_request(&queue, 0x2000, 16, 0);
+        check_metric_after_block!(
+            METRICS.entropy.entropy_fails,
+            1,
+            entropy.process_queue().unwrap()
+        );
+        queue.check_used_elem(1, 0, 0);
+
+        // A chain made of a write-only buffer followed by a read-only one is rejected too.
+        let idx = queue.avail.idx.get();
+        queue.dtable[0].set(0x2000, 16, VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT, 1);
+        queue.dtable[1].set(0x3000, 16, 0, 0);
+        queue.avail.ring[idx as usize].set(0);
+        queue.avail.idx.set(idx + 1);
+        check_metric_after_block!(
+            METRICS.entropy.entropy_fails,
+            1,
+            entropy.process_queue().unwrap()
+        );
+        queue.check_used_elem(2, 0, 0);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        // Allow a single 64 bytes request per 100ms.
+        let mut entropy = Entropy::new(RateLimiter::new(64, 0, 100, 0, 0, 0).unwrap()).unwrap();
+        let mem = default_mem();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        entropy.set_queue(0, queue.create_queue());
+        entropy.activate(mem.clone()).unwrap();
+
+        push_request(&queue, 0x2000, 64, VIRTQ_DESC_F_WRITE);
+        entropy.process_queue().unwrap();
+        assert_eq!(queue.used.idx.get(), 1);
+
+        // The second request is throttled.
+        push_request(&queue, 0x2000, 64, VIRTQ_DESC_F_WRITE);
+        check_metric_after_block!(
+            METRICS.entropy.rate_limiter_throttled_events,
+            1,
+            entropy.process_queue().unwrap()
+        );
+        assert_eq!(queue.used.idx.get(), 1);
+        assert!(entropy.rate_limiter.is_blocked());
+
+        // Wait for the bucket to be replenished, then the request is processed.
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        check_metric_after_block!(
+            METRICS.entropy.rate_limiter_event_count,
+            1,
+            entropy.process_rate_limiter_event().unwrap()
+        );
+        assert_eq!(queue.used.idx.get(), 2);
+        assert!(!entropy.rate_limiter.is_blocked());
+    }
+}
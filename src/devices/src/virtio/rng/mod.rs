@@ -0,0 +1,41 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements the virtio-rng device, which feeds the guest with entropy from the host.
+
+pub mod device;
+pub mod event_handler;
+pub mod persist;
+
+use vm_memory::GuestMemoryError;
+
+pub use self::device::Entropy;
+
+/// Device ID used in MMIO device identification.
+/// Because the entropy device is unique per-vm, this ID can be hardcoded.
+pub const ENTROPY_DEV_ID: &str = "rng";
+pub const QUEUE_SIZE: u16 = 256;
+pub const NUM_QUEUES: usize = 1;
+pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+
+#[derive(Debug)]
+pub enum Error {
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Failed to signal the virtio used queue.
+    FailedSignalingUsedQueue(std::io::Error),
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// Failed to get random bytes from the host.
+    HostRandom(std::io::Error),
+    /// Guest gave us a malformed descriptor.
+    MalformedDescriptor,
+    /// Error while processing the virt queues.
+    Queue(super::QueueError),
+    /// Error restoring the entropy device queues.
+    QueueRestoreError,
+    /// Error creating or restoring the rate limiter.
+    RateLimiter(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,111 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defines the structures needed for saving/restoring entropy devices.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+use rate_limiter::{persist::RateLimiterState, RateLimiter};
+use snapshot::Persist;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::GuestMemoryMmap;
+
+use super::*;
+
+use crate::virtio::persist::VirtioDeviceState;
+use crate::virtio::{DeviceState, TYPE_RNG};
+
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct EntropyState {
+    virtio_state: VirtioDeviceState,
+    rate_limiter_state: RateLimiterState,
+}
+
+pub struct EntropyConstructorArgs {
+    pub mem: GuestMemoryMmap,
+}
+
+impl Persist<'_> for Entropy {
+    type State = EntropyState;
+    type ConstructorArgs = EntropyConstructorArgs;
+    type Error = super::Error;
+
+    fn save(&self) -> Self::State {
+        EntropyState {
+            virtio_state: VirtioDeviceState::from_device(self),
+            rate_limiter_state: self.rate_limiter.save(),
+        }
+    }
+
+    fn restore(
+        constructor_args: Self::ConstructorArgs,
+        state: &Self::State,
+    ) -> std::result::Result<Self, Self::Error> {
+        let rate_limiter =
+            RateLimiter::restore((), &state.rate_limiter_state).map_err(Error::RateLimiter)?;
+        let mut entropy = Entropy::new(rate_limiter)?;
+
+        entropy.queues = state
+            .virtio_state
+            .build_queues_checked(&constructor_args.mem, TYPE_RNG, NUM_QUEUES, QUEUE_SIZE)
+            .map_err(|_| Self::Error::QueueRestoreError)?;
+        entropy.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
+        entropy.avail_features = state.virtio_state.avail_features;
+        entropy.acked_features = state.virtio_state.acked_features;
+
+        if state.virtio_state.activated {
+            entropy.device_state = DeviceState::Activated(constructor_args.mem);
+        }
+
+        Ok(entropy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtio::device::VirtioDevice;
+    use crate::virtio::test_utils::default_mem;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_persistence() {
+        let guest_mem = default_mem();
+        let mut mem = vec![0; 4096];
+        let version_map = VersionMap::new();
+
+        // Create and save the entropy device.
+        let entropy = Entropy::new(RateLimiter::new(64, 0, 100, 0, 0, 0).unwrap()).unwrap();
+        <Entropy as Persist>::save(&entropy)
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .unwrap();
+
+        // Deserialize and restore the entropy device.
+        let restored_entropy = Entropy::restore(
+            EntropyConstructorArgs { mem: guest_mem },
+            &EntropyState::deserialize(&mut mem.as_slice(), &version_map, 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(restored_entropy.device_type(), TYPE_RNG);
+        assert_eq!(restored_entropy.acked_features, entropy.acked_features);
+        assert_eq!(restored_entropy.avail_features, entropy.avail_features);
+        assert_eq!(restored_entropy.queues(), entropy.queues());
+        assert_eq!(
+            restored_entropy.interrupt_status().load(Ordering::Relaxed),
+            entropy.interrupt_status().load(Ordering::Relaxed)
+        );
+        assert_eq!(restored_entropy.is_activated(), entropy.is_activated());
+        assert_eq!(
+            restored_entropy
+                .rate_limiter()
+                .bandwidth()
+                .unwrap()
+                .capacity(),
+            64
+        );
+    }
+}
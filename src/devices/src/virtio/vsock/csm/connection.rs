@@ -83,12 +83,14 @@ use std::num::Wrapping;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::{Duration, Instant};
 
-use logger::{debug, error, info, warn, IncMetric, METRICS};
+use logger::{debug, error, info, warn, IncMetric};
 use utils::epoll::EventSet;
 
 use super::super::defs::uapi;
 use super::super::packet::VsockPacket;
-use super::super::{Result as VsockResult, VsockChannel, VsockEpollListener, VsockError};
+use super::super::{
+    Result as VsockResult, VsockChannel, VsockEpollListener, VsockError, VSOCK_METRICS,
+};
 use super::defs;
 use super::txbuf::TxBuf;
 use super::{ConnState, Error, PendingRx, PendingRxSet, Result};
@@ -154,7 +156,7 @@ where
         // Perform some generic initialization that is the same for any packet operation (e.g.
         // source, destination, credit, etc).
         self.init_pkt(pkt);
-        METRICS.vsock.rx_packets_count.inc();
+        VSOCK_METRICS.update(|metrics| metrics.rx_packets_count.inc());
 
         // If forceful termination is pending, there's no point in checking for anything else.
         // It's dead, Jim.
@@ -228,7 +230,7 @@ where
                         // On a successful data read, we fill in the packet with the RW op, and
                         // length of the read data.
                         pkt.set_op(uapi::VSOCK_OP_RW).set_len(read_cnt as u32);
-                        METRICS.vsock.rx_bytes_count.add(read_cnt);
+                        VSOCK_METRICS.update(|metrics| metrics.rx_bytes_count.add(read_cnt));
                     }
                     self.rx_cnt += Wrapping(pkt.len());
                     self.last_fwd_cnt_to_peer = self.fwd_cnt;
@@ -246,7 +248,7 @@ where
                 Err(err) => {
                     // We are not expecting any other errors when reading from the underlying
                     // stream. If any show up, we'll immediately kill this connection.
-                    METRICS.vsock.rx_read_fails.inc();
+                    VSOCK_METRICS.update(|metrics| metrics.rx_read_fails.inc());
                     error!(
                         "vsock: error reading from backing stream: lp={}, pp={}, err={:?}",
                         self.local_port, self.peer_port, err
@@ -283,7 +285,7 @@ where
         // Update the peer credit information.
         self.peer_buf_alloc = pkt.buf_alloc();
         self.peer_fwd_cnt = Wrapping(pkt.fwd_cnt());
-        METRICS.vsock.tx_packets_count.inc();
+        VSOCK_METRICS.update(|metrics| metrics.tx_packets_count.inc());
 
         match self.state {
             // Most frequent case: this is an established connection that needs to forward some
@@ -446,7 +448,7 @@ where
             // Data can be written to the host stream. Time to flush out the TX buffer.
             //
             if self.tx_buf.is_empty() {
-                METRICS.vsock.conn_event_fails.inc();
+                VSOCK_METRICS.update(|metrics| metrics.conn_event_fails.inc());
                 info!("vsock: connection received unexpected EPOLLOUT event");
                 return;
             }
@@ -454,7 +456,7 @@ where
                 .tx_buf
                 .flush_to(&mut self.stream)
                 .unwrap_or_else(|err| {
-                    METRICS.vsock.tx_flush_fails.inc();
+                    VSOCK_METRICS.update(|metrics| metrics.tx_flush_fails.inc());
                     warn!(
                         "vsock: error flushing TX buf for (lp={}, pp={}): {:?}",
                         self.local_port, self.peer_port, err
@@ -469,7 +471,7 @@ where
                     0
                 });
             self.fwd_cnt += Wrapping(flushed as u32);
-            METRICS.vsock.tx_bytes_count.add(flushed as usize);
+            VSOCK_METRICS.update(|metrics| metrics.tx_bytes_count.add(flushed as usize));
 
             // If this connection was shutting down, but is waiting to drain the TX buffer
             // before forceful termination, the wait might be over.
@@ -576,6 +578,17 @@ where
         self.state
     }
 
+    /// Write out as much of the TX buffer as the host stream accepts without blocking.
+    ///
+    /// This is meant for a connection that is about to be dropped, so the data that doesn't fit
+    /// is discarded, and errors are ignored.
+    pub fn flush_tx_buf(&mut self) {
+        if let Ok(flushed) = self.tx_buf.flush_to(&mut self.stream) {
+            self.fwd_cnt += Wrapping(flushed as u32);
+            VSOCK_METRICS.update(|metrics| metrics.tx_bytes_count.add(flushed));
+        }
+    }
+
     /// Send some raw, untracked, data straight to the underlying connected stream.
     /// Returns: number of bytes written, or the error describing the write failure.
     ///
@@ -609,14 +622,14 @@ where
                 } else {
                     // We don't know how to handle any other write error, so we'll send it up
                     // the call chain.
-                    METRICS.vsock.tx_write_fails.inc();
+                    VSOCK_METRICS.update(|metrics| metrics.tx_write_fails.inc());
                     return Err(Error::StreamWrite(e));
                 }
             }
         };
         // Move the "forwarded bytes" counter ahead by how much we were able to send out.
         self.fwd_cnt += Wrapping(written as u32);
-        METRICS.vsock.tx_bytes_count.add(written);
+        VSOCK_METRICS.update(|metrics| metrics.tx_bytes_count.add(written));
 
         // If we couldn't write the whole slice, we'll need to push the remaining data to our
         // buffer.
@@ -23,10 +23,11 @@ use std::result;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
-use logger::{debug, error, warn, IncMetric, METRICS};
+use logger::{debug, error, warn, IncMetric};
+use rate_limiter::{BucketUpdate, RateLimiter, TokenType};
 use utils::byte_order;
 use utils::eventfd::EventFd;
-use vm_memory::GuestMemoryMmap;
+use vm_memory::{Bytes, GuestMemoryMmap};
 
 use super::super::super::Error as DeviceError;
 use super::super::{
@@ -34,8 +35,8 @@ use super::super::{
     VIRTIO_MMIO_INT_VRING,
 };
 use super::packet::VsockPacket;
-use super::VsockBackend;
 use super::{defs, defs::uapi};
+use super::{VsockBackend, VSOCK_METRICS};
 
 pub(crate) const RXQ_INDEX: usize = 0;
 pub(crate) const TXQ_INDEX: usize = 1;
@@ -45,14 +46,18 @@ pub(crate) const EVQ_INDEX: usize = 2;
 /// - VIRTIO_F_VERSION_1: the device conforms to at least version 1.0 of the VirtIO spec.
 /// - VIRTIO_F_IN_ORDER: the device returns used buffers in the same order that the driver makes
 ///   them available.
-pub(crate) const AVAIL_FEATURES: u64 =
-    1 << uapi::VIRTIO_F_VERSION_1 as u64 | 1 << uapi::VIRTIO_F_IN_ORDER as u64;
+/// - VIRTIO_VSOCK_F_DGRAM: the device supports datagram sockets.
+pub(crate) const AVAIL_FEATURES: u64 = 1 << uapi::VIRTIO_F_VERSION_1 as u64
+    | 1 << uapi::VIRTIO_F_IN_ORDER as u64
+    | 1 << uapi::VIRTIO_VSOCK_F_DGRAM as u64;
 
 pub struct Vsock<B> {
     cid: u64,
     pub(crate) queues: Vec<VirtQueue>,
     pub(crate) queue_events: Vec<EventFd>,
     pub(crate) backend: B,
+    pub(crate) rx_rate_limiter: RateLimiter,
+    pub(crate) tx_rate_limiter: RateLimiter,
     pub(crate) avail_features: u64,
     pub(crate) acked_features: u64,
     pub(crate) interrupt_status: Arc<AtomicUsize>,
@@ -78,6 +83,8 @@ where
     pub(crate) fn with_queues(
         cid: u64,
         backend: B,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
         queues: Vec<VirtQueue>,
     ) -> super::Result<Vsock<B>> {
         let mut queue_events = Vec::new();
@@ -90,6 +97,8 @@ where
             queues,
             queue_events,
             backend,
+            rx_rate_limiter,
+            tx_rate_limiter,
             avail_features: AVAIL_FEATURES,
             acked_features: 0,
             interrupt_status: Arc::new(AtomicUsize::new(0)),
@@ -99,13 +108,19 @@ where
         })
     }
 
-    /// Create a new virtio-vsock device with the given VM CID and vsock backend.
-    pub fn new(cid: u64, backend: B) -> super::Result<Vsock<B>> {
+    /// Create a new virtio-vsock device with the given VM CID, vsock backend, and rate limiters
+    /// for the traffic going to (RX) and coming from (TX) the guest.
+    pub fn new(
+        cid: u64,
+        backend: B,
+        rx_rate_limiter: RateLimiter,
+        tx_rate_limiter: RateLimiter,
+    ) -> super::Result<Vsock<B>> {
         let queues: Vec<VirtQueue> = defs::QUEUE_SIZES
             .iter()
             .map(|&max_size| VirtQueue::new(max_size))
             .collect();
-        Self::with_queues(cid, backend, queues)
+        Self::with_queues(cid, backend, rx_rate_limiter, tx_rate_limiter, queues)
     }
 
     pub fn id(&self) -> &str {
@@ -120,6 +135,14 @@ where
         &self.backend
     }
 
+    pub fn rx_rate_limiter(&self) -> &RateLimiter {
+        &self.rx_rate_limiter
+    }
+
+    pub fn tx_rate_limiter(&self) -> &RateLimiter {
+        &self.tx_rate_limiter
+    }
+
     /// Signal the guest driver that we've used some virtio buffers that it had previously made
     /// available.
     pub fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
@@ -148,9 +171,24 @@ where
         while let Some(head) = self.queues[RXQ_INDEX].pop(mem) {
             let used_len = match VsockPacket::from_rx_virtq_head(&head) {
                 Ok(mut pkt) => {
+                    // The backend decides how much data goes into the buffer, so the rate limiter
+                    // is charged for all of it upfront, then paid back the part that is left
+                    // unused.
+                    let buf_len = pkt.buf().map_or(0, |buf| buf.len() as u64);
+                    if !Self::rate_limiter_consume(&mut self.rx_rate_limiter, buf_len) {
+                        self.queues[RXQ_INDEX].undo_pop();
+                        VSOCK_METRICS.update(|metrics| metrics.rx_rate_limiter_throttled.inc());
+                        break;
+                    }
+
                     if self.backend.recv_pkt(&mut pkt).is_ok() {
+                        self.rx_rate_limiter.manual_replenish(
+                            buf_len.saturating_sub(u64::from(pkt.len())),
+                            TokenType::Bytes,
+                        );
                         pkt.hdr().len() as u32 + pkt.len()
                     } else {
+                        Self::rate_limiter_replenish(&mut self.rx_rate_limiter, buf_len);
                         // We are using a consuming iterator over the virtio buffers, so, if we can't
                         // fill in this buffer, we'll need to undo the last iterator step.
                         self.queues[RXQ_INDEX].undo_pop();
@@ -202,7 +240,15 @@ where
                 }
             };
 
+            let pkt_len = u64::from(pkt.len());
+            if !Self::rate_limiter_consume(&mut self.tx_rate_limiter, pkt_len) {
+                self.queues[TXQ_INDEX].undo_pop();
+                VSOCK_METRICS.update(|metrics| metrics.tx_rate_limiter_throttled.inc());
+                break;
+            }
+
             if self.backend.send_pkt(&pkt).is_err() {
+                Self::rate_limiter_replenish(&mut self.tx_rate_limiter, pkt_len);
                 self.queues[TXQ_INDEX].undo_pop();
                 break;
             }
@@ -217,6 +263,78 @@ where
 
         have_used
     }
+
+    /// Take one operation and `bytes` bytes out of the budget of `rate_limiter`. If either of them
+    /// isn't available, nothing is taken.
+    fn rate_limiter_consume(rate_limiter: &mut RateLimiter, bytes: u64) -> bool {
+        if !rate_limiter.consume(1, TokenType::Ops) {
+            return false;
+        }
+        if !rate_limiter.consume(bytes, TokenType::Bytes) {
+            rate_limiter.manual_replenish(1, TokenType::Ops);
+            return false;
+        }
+        true
+    }
+
+    /// Give back what `rate_limiter_consume()` took out of the budget of `rate_limiter`.
+    fn rate_limiter_replenish(rate_limiter: &mut RateLimiter, bytes: u64) {
+        rate_limiter.manual_replenish(1, TokenType::Ops);
+        rate_limiter.manual_replenish(bytes, TokenType::Bytes);
+    }
+
+    /// Update the parameters of the RX and TX rate limiters.
+    pub fn patch_rate_limiters(
+        &mut self,
+        rx_bytes: BucketUpdate,
+        rx_ops: BucketUpdate,
+        tx_bytes: BucketUpdate,
+        tx_ops: BucketUpdate,
+    ) {
+        self.rx_rate_limiter.update_buckets(rx_bytes, rx_ops);
+        self.tx_rate_limiter.update_buckets(tx_bytes, tx_ops);
+    }
+
+    /// Send a transport reset event to the guest driver, making it drop all its connections.
+    pub fn send_transport_reset_event(&mut self) -> result::Result<(), DeviceError> {
+        let mem = match self.device_state {
+            DeviceState::Activated(ref mem) => mem,
+            // The driver cannot have set up the event queue yet, so it has no connections.
+            DeviceState::Inactive => return Ok(()),
+        };
+
+        let head = self.queues[EVQ_INDEX].pop(mem).ok_or_else(|| {
+            VSOCK_METRICS.update(|metrics| metrics.ev_queue_event_fails.inc());
+            DeviceError::VsockError(VsockError::EmptyEventQueue)
+        })?;
+
+        let event_id = uapi::VIRTIO_VSOCK_EVENT_TRANSPORT_RESET.to_le_bytes();
+        mem.write_slice(&event_id, head.addr)
+            .map_err(|e| DeviceError::VsockError(VsockError::GuestMemoryMmap(e)))?;
+        self.queues[EVQ_INDEX]
+            .add_used(mem, head.index, event_id.len() as u32)
+            .map_err(DeviceError::QueueError)?;
+
+        self.signal_used_queue()
+    }
+
+    /// Tear down all the vsock connections, e.g. ahead of a snapshot.
+    ///
+    /// The packets that the guest has already sent are forwarded first, so that the host peers
+    /// get all the data written before the reset. The backend then drops its end of the
+    /// connections, which the host peers see as a closed stream, and the guest driver is told to
+    /// drop its own end.
+    pub fn reset_connections(&mut self) -> result::Result<(), DeviceError> {
+        if let DeviceState::Inactive = self.device_state {
+            return Ok(());
+        }
+
+        if self.process_tx() {
+            self.signal_used_queue()?;
+        }
+        self.backend.reset();
+        self.send_transport_reset_event()
+    }
 }
 
 impl<B> VirtioDevice for Vsock<B>
@@ -269,7 +387,7 @@ where
                 byte_order::write_le_u32(data, ((self.cid() >> 32) & 0xffff_ffff) as u32)
             }
             _ => {
-                METRICS.vsock.cfg_fails.inc();
+                VSOCK_METRICS.update(|metrics| metrics.cfg_fails.inc());
                 warn!(
                     "vsock: virtio-vsock received invalid read request of {} bytes at offset {}",
                     data.len(),
@@ -280,7 +398,7 @@ where
     }
 
     fn write_config(&mut self, offset: u64, data: &[u8]) {
-        METRICS.vsock.cfg_fails.inc();
+        VSOCK_METRICS.update(|metrics| metrics.cfg_fails.inc());
         warn!(
             "vsock: guest driver attempted to write device config (offset={:x}, len={:x})",
             offset,
@@ -290,7 +408,7 @@ where
 
     fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
         if self.queues.len() != defs::NUM_QUEUES {
-            METRICS.vsock.activate_fails.inc();
+            VSOCK_METRICS.update(|metrics| metrics.activate_fails.inc());
             error!(
                 "Cannot perform activate. Expected {} queue(s), got {}",
                 defs::NUM_QUEUES,
@@ -300,7 +418,7 @@ where
         }
 
         if self.activate_evt.write(1).is_err() {
-            METRICS.vsock.activate_fails.inc();
+            VSOCK_METRICS.update(|metrics| metrics.activate_fails.inc());
             error!("Cannot write to activate_evt",);
             return Err(ActivateError::BadActivate);
         }
@@ -323,6 +441,8 @@ mod tests {
     use super::*;
     use crate::virtio::vsock::defs::uapi;
     use crate::virtio::vsock::test_utils::TestContext;
+    use crate::virtio::VIRTQ_DESC_F_WRITE;
+    use vm_memory::GuestAddress;
 
     #[test]
     fn test_virtio_device() {
@@ -393,4 +513,47 @@ mod tests {
         // Test a correct activation.
         ctx.device.activate(ctx.mem.clone()).unwrap();
     }
+
+    #[test]
+    fn test_reset_connections() {
+        let test_ctx = TestContext::new();
+        let mut ctx = test_ctx.create_event_handler_context();
+
+        // Nothing is sent to a driver which hasn't activated the device.
+        ctx.device.reset_connections().unwrap();
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 0);
+
+        ctx.mock_activate(test_ctx.mem.clone());
+        ctx.device.backend.set_pending_rx(true);
+
+        // The event queue has no available buffer for the transport reset event.
+        match ctx.device.reset_connections() {
+            Err(DeviceError::VsockError(VsockError::EmptyEventQueue)) => (),
+            other => panic!("{:?}", other),
+        }
+        // The packets sent by the guest were processed anyway, and the backend was reset.
+        assert_eq!(ctx.guest_txvq.used.idx.get(), 1);
+        assert_eq!(ctx.device.backend.tx_ok_cnt, 1);
+        assert!(!ctx.device.backend.pending_rx);
+
+        // Set up one available buffer in the event queue.
+        let event_addr = GuestAddress(0x0060_0000);
+        test_ctx.mem.write_obj(0xffff_ffffu32, event_addr).unwrap();
+        ctx.guest_evvq.dtable[0].set(event_addr.0, 8, VIRTQ_DESC_F_WRITE, 0);
+        ctx.guest_evvq.avail.ring[0].set(0);
+        ctx.guest_evvq.avail.idx.set(1);
+
+        ctx.device.interrupt_status.store(0, Ordering::SeqCst);
+        ctx.device.reset_connections().unwrap();
+        assert_eq!(ctx.guest_evvq.used.idx.get(), 1);
+        assert_eq!(ctx.guest_evvq.used.ring[0].get().len, 4);
+        assert_eq!(
+            test_ctx.mem.read_obj::<u32>(event_addr).unwrap(),
+            uapi::VIRTIO_VSOCK_EVENT_TRANSPORT_RESET
+        );
+        assert_eq!(
+            ctx.device.interrupt_status.load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_VRING as usize
+        );
+    }
 }
@@ -24,12 +24,12 @@
 ///   - again, attempt to fetch any incoming packets queued by the backend into virtio RX buffers.
 use std::os::unix::io::AsRawFd;
 
-use logger::{debug, error, warn, IncMetric, METRICS};
+use logger::{debug, error, warn, IncMetric};
 use polly::event_manager::{EventManager, Subscriber};
 use utils::epoll::{EpollEvent, EventSet};
 
 use super::device::{Vsock, EVQ_INDEX, RXQ_INDEX, TXQ_INDEX};
-use super::VsockBackend;
+use super::{VsockBackend, VSOCK_METRICS};
 use crate::virtio::VirtioDevice;
 
 impl<B> Vsock<B>
@@ -42,17 +42,17 @@ where
         let event_set = event.event_set();
         if event_set != EventSet::IN {
             warn!("vsock: rxq unexpected event {:?}", event_set);
-            METRICS.vsock.rx_queue_event_fails.inc();
+            VSOCK_METRICS.update(|metrics| metrics.rx_queue_event_fails.inc());
             return false;
         }
 
         let mut raise_irq = false;
         if let Err(e) = self.queue_events[RXQ_INDEX].read() {
             error!("Failed to get vsock rx queue event: {:?}", e);
-            METRICS.vsock.rx_queue_event_fails.inc();
+            VSOCK_METRICS.update(|metrics| metrics.rx_queue_event_fails.inc());
         } else if self.backend.has_pending_rx() {
             raise_irq |= self.process_rx();
-            METRICS.vsock.rx_queue_event_count.inc();
+            VSOCK_METRICS.update(|metrics| metrics.rx_queue_event_count.inc());
         }
         raise_irq
     }
@@ -63,17 +63,17 @@ where
         let event_set = event.event_set();
         if event_set != EventSet::IN {
             warn!("vsock: txq unexpected event {:?}", event_set);
-            METRICS.vsock.tx_queue_event_fails.inc();
+            VSOCK_METRICS.update(|metrics| metrics.tx_queue_event_fails.inc());
             return false;
         }
 
         let mut raise_irq = false;
         if let Err(e) = self.queue_events[TXQ_INDEX].read() {
             error!("Failed to get vsock tx queue event: {:?}", e);
-            METRICS.vsock.tx_queue_event_fails.inc();
+            VSOCK_METRICS.update(|metrics| metrics.tx_queue_event_fails.inc());
         } else {
             raise_irq |= self.process_tx();
-            METRICS.vsock.tx_queue_event_count.inc();
+            VSOCK_METRICS.update(|metrics| metrics.tx_queue_event_count.inc());
             // The backend may have queued up responses to the packets we sent during
             // TX queue processing. If that happened, we need to fetch those responses
             // and place them into RX buffers.
@@ -90,13 +90,13 @@ where
         let event_set = event.event_set();
         if event_set != EventSet::IN {
             warn!("vsock: evq unexpected event {:?}", event_set);
-            METRICS.vsock.ev_queue_event_fails.inc();
+            VSOCK_METRICS.update(|metrics| metrics.ev_queue_event_fails.inc());
             return false;
         }
 
         if let Err(e) = self.queue_events[EVQ_INDEX].read() {
             error!("Failed to consume vsock evq event: {:?}", e);
-            METRICS.vsock.ev_queue_event_fails.inc();
+            VSOCK_METRICS.update(|metrics| metrics.ev_queue_event_fails.inc());
         }
         false
     }
@@ -117,6 +117,42 @@ where
         raise_irq
     }
 
+    fn handle_rx_rate_limiter_event(&mut self) -> bool {
+        debug!("vsock: RX rate limiter event");
+        VSOCK_METRICS.update(|metrics| metrics.rx_rate_limiter_event_count.inc());
+
+        match self.rx_rate_limiter.event_handler() {
+            // There might be enough budget now to fetch the incoming packets.
+            Ok(_) => self.backend.has_pending_rx() && self.process_rx(),
+            Err(e) => {
+                error!("Failed to get vsock rx rate limiter event: {:?}", e);
+                VSOCK_METRICS.update(|metrics| metrics.rx_queue_event_fails.inc());
+                false
+            }
+        }
+    }
+
+    fn handle_tx_rate_limiter_event(&mut self) -> bool {
+        debug!("vsock: TX rate limiter event");
+        VSOCK_METRICS.update(|metrics| metrics.tx_rate_limiter_event_count.inc());
+
+        match self.tx_rate_limiter.event_handler() {
+            Ok(_) => {
+                // There might be enough budget now to send the pending packets.
+                let mut raise_irq = self.process_tx();
+                if self.backend.has_pending_rx() {
+                    raise_irq |= self.process_rx();
+                }
+                raise_irq
+            }
+            Err(e) => {
+                error!("Failed to get vsock tx rate limiter event: {:?}", e);
+                VSOCK_METRICS.update(|metrics| metrics.tx_queue_event_fails.inc());
+                false
+            }
+        }
+    }
+
     fn handle_activate_event(&self, event_manager: &mut EventManager) {
         debug!("vsock: activate event");
         if let Err(e) = self.activate_evt.read() {
@@ -160,6 +196,8 @@ where
         let evq = self.queue_events[EVQ_INDEX].as_raw_fd();
         let backend = self.backend.as_raw_fd();
         let activate_evt = self.activate_evt.as_raw_fd();
+        let rx_rate_limiter = self.rx_rate_limiter.as_raw_fd();
+        let tx_rate_limiter = self.tx_rate_limiter.as_raw_fd();
 
         if self.is_activated() {
             let mut raise_irq = false;
@@ -170,6 +208,8 @@ where
                 _ if source == backend => {
                     raise_irq = self.notify_backend(event);
                 }
+                _ if source == rx_rate_limiter => raise_irq = self.handle_rx_rate_limiter_event(),
+                _ if source == tx_rate_limiter => raise_irq = self.handle_tx_rate_limiter_event(),
                 _ if source == activate_evt => {
                     self.handle_activate_event(event_manager);
                 }
@@ -209,6 +249,8 @@ where
                     self.backend.get_polled_evset(),
                     self.backend.as_raw_fd() as u64,
                 ),
+                EpollEvent::new(EventSet::IN, self.rx_rate_limiter.as_raw_fd() as u64),
+                EpollEvent::new(EventSet::IN, self.tx_rate_limiter.as_raw_fd() as u64),
             ]
         } else {
             vec![EpollEvent::new(
@@ -231,6 +273,7 @@ mod tests {
     use crate::virtio::vsock::test_utils::{EventHandlerContext, TestContext};
     use crate::virtio::VIRTIO_MMIO_INT_VRING;
     use crate::Error as DeviceError;
+    use rate_limiter::{RateLimiter, TokenType};
     use vm_memory::Bytes;
 
     #[test]
@@ -405,6 +448,50 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rate_limiters() {
+        // Test case: the TX queue is throttled until the TX rate limiter is replenished.
+        {
+            let test_ctx = TestContext::new();
+            let mut ctx = test_ctx.create_event_handler_context();
+            ctx.mock_activate(test_ctx.mem.clone());
+
+            ctx.device.tx_rate_limiter = RateLimiter::new(0, 0, 0, 1, 0, 100).unwrap();
+            assert!(ctx.device.tx_rate_limiter.consume(1, TokenType::Ops));
+            ctx.signal_txq_event();
+            assert_eq!(ctx.guest_txvq.used.idx.get(), 0);
+            assert_eq!(ctx.device.backend.tx_ok_cnt, 0);
+
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            assert!(ctx.device.handle_tx_rate_limiter_event());
+            assert_eq!(ctx.guest_txvq.used.idx.get(), 1);
+            assert_eq!(ctx.device.backend.tx_ok_cnt, 1);
+        }
+
+        // Test case: the RX queue is throttled until the RX rate limiter is replenished, and
+        // the unused part of the RX buffer is paid back.
+        {
+            let test_ctx = TestContext::new();
+            let mut ctx = test_ctx.create_event_handler_context();
+            ctx.mock_activate(test_ctx.mem.clone());
+
+            ctx.device.backend.set_pending_rx(true);
+            ctx.device.rx_rate_limiter = RateLimiter::new(8192, 0, 100, 0, 0, 0).unwrap();
+            assert!(ctx.device.rx_rate_limiter.consume(8000, TokenType::Bytes));
+            ctx.signal_rxq_event();
+            assert_eq!(ctx.guest_rxvq.used.idx.get(), 0);
+            assert_eq!(ctx.device.backend.rx_ok_cnt, 0);
+
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            assert!(ctx.device.handle_rx_rate_limiter_event());
+            assert_eq!(ctx.guest_rxvq.used.idx.get(), 1);
+            assert_eq!(ctx.device.backend.rx_ok_cnt, 1);
+            // The test backend doesn't fill in any data.
+            let bandwidth = ctx.device.rx_rate_limiter.bandwidth().unwrap();
+            assert_eq!(bandwidth.budget(), bandwidth.capacity());
+        }
+    }
+
     #[test]
     fn test_evq_event() {
         // Test case: spurious EVQ_EVENT.
@@ -12,20 +12,34 @@ mod packet;
 pub mod persist;
 pub mod test_utils;
 mod unix;
+pub mod vhost;
 
 use std::os::unix::io::AsRawFd;
 
 use crate::virtio::persist::Error as VirtioStateError;
 
 pub use self::defs::uapi::VIRTIO_ID_VSOCK as TYPE_VSOCK;
+pub use self::defs::VSOCK_DEV_ID;
 pub use self::device::Vsock;
-pub use self::unix::{Error as VsockUnixBackendError, VsockUnixBackend};
-
+pub use self::unix::{
+    AgentChannel, Error as VsockUnixBackendError, VsockConnectionInfo, VsockDeviceStats,
+    VsockPortMapping, VsockPortStats, VsockUnixBackend, AGENT_CHANNEL,
+};
+pub use self::vhost::{Error as VhostVsockError, VhostVsock, VhostVsockHandle, VHOST_VSOCK_PATH};
+
+use lazy_static::lazy_static;
+use logger::{DeviceMetrics, VsockDeviceMetrics, METRICS};
 use utils::epoll::EventSet;
 use vm_memory::GuestMemoryError;
 
 use packet::VsockPacket;
 
+lazy_static! {
+    /// The metrics of the vsock device, which is unique per-vm.
+    pub(crate) static ref VSOCK_METRICS: DeviceMetrics<VsockDeviceMetrics> =
+        DeviceMetrics::new(&METRICS.vsock, METRICS.vsock.vsocks.get(defs::VSOCK_DEV_ID));
+}
+
 mod defs {
     /// Device ID used in MMIO device identification.
     /// Because Vsock is unique per-vm, this ID can be hardcoded.
@@ -53,6 +67,13 @@ mod defs {
         /// The device conforms to the virtio spec version 1.0.
         pub const VIRTIO_F_VERSION_1: u32 = 32;
 
+        /// Vsock feature flags.
+        /// Not yet part of `/include/uapi/linux/virtio_vsock.h`, this follows the proposed
+        /// virtio-vsock datagram extension.
+        ///
+        /// The device supports datagram sockets.
+        pub const VIRTIO_VSOCK_F_DGRAM: u32 = 3;
+
         /// Virtio vsock device ID.
         /// Defined in `include/uapi/linux/virtio_ids.h`.
         pub const VIRTIO_ID_VSOCK: u32 = 19;
@@ -86,8 +107,17 @@ mod defs {
         /// Vsock packet type.
         /// Defined in `/include/uapi/linux/virtio_vsock.h`.
         ///
-        /// Stream / connection-oriented packet (the only currently valid type).
+        /// Stream / connection-oriented packet.
         pub const VSOCK_TYPE_STREAM: u16 = 1;
+        /// Datagram / connectionless packet. Not yet part of the upstream header, this follows
+        /// the proposed virtio-vsock datagram extension.
+        pub const VSOCK_TYPE_DGRAM: u16 = 3;
+
+        /// Vsock event IDs.
+        /// Defined in `/include/uapi/linux/virtio_vsock.h`.
+        ///
+        /// The transport was reset: the driver must drop all its connections.
+        pub const VIRTIO_VSOCK_EVENT_TRANSPORT_RESET: u32 = 0;
 
         pub const VSOCK_HOST_CID: u64 = 2;
     }
@@ -99,6 +129,10 @@ pub enum VsockError {
     BufDescTooSmall,
     /// The vsock data/buffer virtio descriptor is expected, but missing.
     BufDescMissing,
+    /// A rate limiter could not be created.
+    CreateRateLimiter(std::io::Error),
+    /// The vsock event queue has no available buffer.
+    EmptyEventQueue,
     /// EventFd error
     EventFd(std::io::Error),
     /// Chained GuestMemoryMmap error.
@@ -160,4 +194,8 @@ pub trait VsockChannel {
 /// The vsock backend, which is basically an epoll-event-driven vsock channel.
 /// Currently, the only implementation we have is `crate::virtio::unix::muxer::VsockMuxer`, which
 /// translates guest-side vsock connections to host-side Unix domain socket connections.
-pub trait VsockBackend: VsockChannel + VsockEpollListener + Send {}
+pub trait VsockBackend: VsockChannel + VsockEpollListener + Send {
+    /// Drop all the connections, along with any packets still queued up for the guest driver.
+    /// The driver must be told to reset its end of the transport as well.
+    fn reset(&mut self);
+}
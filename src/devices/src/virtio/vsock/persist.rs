@@ -3,10 +3,15 @@
 
 //! Defines state and support structures for persisting Vsock devices and backends.
 
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
 use super::*;
+use logger::warn;
+use rate_limiter::{persist::RateLimiterState, RateLimiter};
 use snapshot::Persist;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
@@ -28,6 +33,26 @@ pub struct VsockState {
 pub struct VsockFrontendState {
     pub cid: u64,
     virtio_state: VirtioDeviceState,
+    /// The state of the RX rate limiter, if one is configured.
+    #[version(start = 2, ser_fn = "rate_limiters_serialize")]
+    rx_rate_limiter_state: Option<RateLimiterState>,
+    /// The state of the TX rate limiter, if one is configured.
+    #[version(start = 2, ser_fn = "rate_limiters_serialize")]
+    tx_rate_limiter_state: Option<RateLimiterState>,
+}
+
+impl VsockFrontendState {
+    fn rate_limiters_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2
+            && (self.rx_rate_limiter_state.is_some() || self.tx_rate_limiter_state.is_some())
+        {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the vsock rate limiters.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 /// An enum for the serializable backend state types.
@@ -37,12 +62,100 @@ pub enum VsockBackendState {
     Uds(VsockUdsState),
 }
 
+impl VsockBackendState {
+    /// Moves the socket of the restored backend to `uds_path`, e.g. for a clone of the microVM.
+    /// The sockets of the port mappings named after the former socket are renamed alike.
+    pub fn set_uds_path(&mut self, uds_path: &str) {
+        match self {
+            VsockBackendState::Uds(uds_state) => {
+                for mapping in uds_state.port_mappings.iter_mut() {
+                    if mapping.uds_path.starts_with(&uds_state.path) {
+                        mapping.uds_path =
+                            format!("{}{}", uds_path, &mapping.uds_path[uds_state.path.len()..]);
+                    }
+                }
+                uds_state.path = uds_path.to_owned();
+            }
+        }
+    }
+}
+
 /// The Vsock Unix Backend serializable state.
 #[derive(Clone, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct VsockUdsState {
     /// The path for the UDS socket.
     pub(crate) path: String,
+    /// The port on which the guest reaches the MMDS, if any.
+    #[version(start = 2, ser_fn = "mmds_port_serialize")]
+    pub(crate) mmds_port: Option<u32>,
+    /// The port on which the guest agent connects, if any.
+    #[version(start = 2, ser_fn = "agent_port_serialize")]
+    pub(crate) agent_port: Option<u32>,
+    /// The port mappings that the backend listens on.
+    #[version(start = 2, ser_fn = "port_mappings_serialize")]
+    pub(crate) port_mappings: Vec<VsockPortMappingState>,
+}
+
+impl VsockUdsState {
+    fn agent_port_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.agent_port.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the vsock agent port.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn mmds_port_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.mmds_port.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the vsock MMDS port.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn port_mappings_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !self.port_mappings.is_empty() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the vsock port mappings.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The serializable state of a vsock port mapping.
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct VsockPortMappingState {
+    name: String,
+    guest_port: u32,
+    uds_path: String,
+}
+
+impl From<&VsockPortMapping> for VsockPortMappingState {
+    fn from(mapping: &VsockPortMapping) -> Self {
+        VsockPortMappingState {
+            name: mapping.name.clone(),
+            guest_port: mapping.guest_port,
+            uds_path: mapping.uds_path.clone(),
+        }
+    }
+}
+
+impl From<&VsockPortMappingState> for VsockPortMapping {
+    fn from(state: &VsockPortMappingState) -> Self {
+        VsockPortMapping {
+            name: state.name.clone(),
+            guest_port: state.guest_port,
+            uds_path: state.uds_path.clone(),
+        }
+    }
 }
 
 /// A helper structure that holds the constructor arguments for VsockUnixBackend
@@ -65,6 +178,13 @@ impl Persist<'_> for VsockUnixBackend {
     fn save(&self) -> Self::State {
         VsockBackendState::Uds(VsockUdsState {
             path: self.host_sock_path.clone(),
+            mmds_port: self.mmds_port(),
+            agent_port: self.agent_port(),
+            port_mappings: self
+                .port_mappings()
+                .iter()
+                .map(VsockPortMappingState::from)
+                .collect(),
         })
     }
 
@@ -73,14 +193,63 @@ impl Persist<'_> for VsockUnixBackend {
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
         match state {
-            VsockBackendState::Uds(uds_state) => Ok(VsockUnixBackend::new(
-                constructor_args.cid,
-                uds_state.path.clone(),
-            )?),
+            VsockBackendState::Uds(uds_state) => {
+                remove_stale_socket(&uds_state.path);
+                remove_stale_socket(&format!("{}_dgram", uds_state.path));
+                let mut backend =
+                    VsockUnixBackend::new(constructor_args.cid, uds_state.path.clone())?;
+                backend.set_mmds_port(uds_state.mmds_port);
+                backend.set_agent_port(uds_state.agent_port);
+                for mapping in uds_state.port_mappings.iter() {
+                    remove_stale_socket(&mapping.uds_path);
+                    backend.add_port_mapping(VsockPortMapping::from(mapping))?;
+                }
+                Ok(backend)
+            }
+        }
+    }
+}
+
+/// Remove the host-side socket left behind at `path` by the microVM that the snapshot was taken
+/// of, so that the restored backend can listen on it again. A socket that is still listened on,
+/// e.g. because that microVM is still running, is left alone, and the restore fails to bind.
+/// Connecting to a datagram socket that is still bound fails with a different error, so the same
+/// check works for the datagram socket as well.
+fn remove_stale_socket(path: &str) {
+    let is_socket = std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false);
+    if !is_socket {
+        return;
+    }
+
+    if let Err(err) = UnixStream::connect(path) {
+        if err.kind() == ErrorKind::ConnectionRefused {
+            std::fs::remove_file(path).unwrap_or_else(|err| {
+                warn!("vsock: unable to remove stale socket {}: {}", path, err)
+            });
         }
     }
 }
 
+/// Save the state of `rate_limiter`, unless it is a no-op. Snapshots of devices without rate
+/// limiters can then still be loaded by older Firecracker versions.
+fn save_rate_limiter(rate_limiter: &RateLimiter) -> Option<RateLimiterState> {
+    if rate_limiter.bandwidth().is_some() || rate_limiter.ops().is_some() {
+        Some(rate_limiter.save())
+    } else {
+        None
+    }
+}
+
+fn restore_rate_limiter(state: &Option<RateLimiterState>) -> Result<RateLimiter> {
+    match state {
+        // RateLimiter::restore() can fail at creating a timerfd.
+        Some(state) => RateLimiter::restore((), state).map_err(VsockError::CreateRateLimiter),
+        None => Ok(RateLimiter::default()),
+    }
+}
+
 impl<B> Persist<'_> for Vsock<B>
 where
     B: VsockBackend + 'static,
@@ -93,6 +262,8 @@ where
         VsockFrontendState {
             cid: self.cid(),
             virtio_state: VirtioDeviceState::from_device(self),
+            rx_rate_limiter_state: save_rate_limiter(&self.rx_rate_limiter),
+            tx_rate_limiter_state: save_rate_limiter(&self.tx_rate_limiter),
         }
     }
 
@@ -110,12 +281,24 @@ where
                 defs::QUEUE_SIZE,
             )
             .map_err(VsockError::VirtioState)?;
-        let mut vsock = Self::with_queues(state.cid, constructor_args.backend, queues)?;
+        let rx_rate_limiter = restore_rate_limiter(&state.rx_rate_limiter_state)?;
+        let tx_rate_limiter = restore_rate_limiter(&state.tx_rate_limiter_state)?;
+        let mut vsock = Self::with_queues(
+            state.cid,
+            constructor_args.backend,
+            rx_rate_limiter,
+            tx_rate_limiter,
+            queues,
+        )?;
 
         vsock.acked_features = state.virtio_state.acked_features;
         vsock.avail_features = state.virtio_state.avail_features;
         vsock.interrupt_status = Arc::new(AtomicUsize::new(state.virtio_state.interrupt_status));
         vsock.device_state = if state.virtio_state.activated {
+            // A transport reset event was sent to the guest driver when the snapshot was taken.
+            // The interrupt that came with it may not have made it into the snapshot, so it is
+            // raised again.
+            vsock.interrupt_evt.write(1).map_err(VsockError::EventFd)?;
             DeviceState::Activated(constructor_args.mem)
         } else {
             DeviceState::Inactive
@@ -132,6 +315,7 @@ pub(crate) mod tests {
     use crate::virtio::vsock::defs::uapi;
     use crate::virtio::vsock::test_utils::{TestBackend, TestContext};
     use utils::byte_order;
+    use utils::tempfile::TempFile;
 
     impl Persist<'_> for TestBackend {
         type State = VsockBackendState;
@@ -141,6 +325,9 @@ pub(crate) mod tests {
         fn save(&self) -> Self::State {
             VsockBackendState::Uds(VsockUdsState {
                 path: "test".to_owned(),
+                mmds_port: None,
+                agent_port: None,
+                port_mappings: Vec::new(),
             })
         }
 
@@ -234,4 +421,236 @@ pub(crate) mod tests {
         restored_device.read_config(2, &mut data);
         assert_eq!(data, [0u8, 1, 2, 3, 4, 5, 6, 7]);
     }
+
+    #[test]
+    fn test_persist_activated_device() {
+        let ctx = TestContext::new();
+        let mut device = Vsock::new(
+            ctx.cid,
+            TestBackend::new(),
+            RateLimiter::default(),
+            RateLimiter::default(),
+        )
+        .unwrap();
+        device.activate(ctx.mem.clone()).unwrap();
+
+        // The guest driver is interrupted, to make sure it sees the transport reset event.
+        let restored_device = Vsock::restore(
+            VsockConstructorArgs {
+                mem: ctx.mem.clone(),
+                backend: TestBackend::new(),
+            },
+            &device.save(),
+        )
+        .unwrap();
+        assert!(restored_device.is_activated());
+        assert_eq!(restored_device.interrupt_evt.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_remove_stale_socket() {
+        const CID: u64 = 52;
+        let path = TempFile::new()
+            .unwrap()
+            .as_path()
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        // Regular files are left alone.
+        std::fs::write(&path, b"").unwrap();
+        remove_stale_socket(&path);
+        assert!(std::path::Path::new(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+
+        // So are the sockets which are still listened on.
+        let backend = VsockUnixBackend::new(CID, path.clone()).unwrap();
+        remove_stale_socket(&path);
+        match VsockUnixBackend::restore(VsockUdsConstructorArgs { cid: CID }, &backend.save()) {
+            Err(VsockUnixBackendError::UnixBind(_)) => (),
+            _ => panic!("Restored a backend on a busy socket."),
+        }
+
+        // The socket left behind by a backend is removed, so it can be listened on again.
+        let state = backend.save();
+        drop(backend);
+        assert!(std::path::Path::new(&path).exists());
+        let backend =
+            VsockUnixBackend::restore(VsockUdsConstructorArgs { cid: CID }, &state).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(backend.dgram_sock_path()).unwrap();
+    }
+
+    #[test]
+    fn test_persist_uds_mmds_port() {
+        let state = VsockUdsState {
+            path: "test".to_owned(),
+            mmds_port: Some(1027),
+            agent_port: None,
+            port_mappings: Vec::new(),
+        };
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(VsockUdsState::type_id(), 2);
+
+        // The MMDS port cannot be saved in the older format.
+        assert!(state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_state =
+            VsockUdsState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(restored_state.path, "test".to_owned());
+        assert_eq!(restored_state.mmds_port, Some(1027));
+    }
+
+    #[test]
+    fn test_persist_uds_agent_port() {
+        let state = VsockUdsState {
+            path: "test".to_owned(),
+            mmds_port: None,
+            agent_port: Some(1028),
+            port_mappings: Vec::new(),
+        };
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(VsockUdsState::type_id(), 2);
+
+        // The agent port cannot be saved in the older format.
+        assert!(state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_state =
+            VsockUdsState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+        assert_eq!(restored_state.agent_port, Some(1028));
+    }
+
+    #[test]
+    fn test_persist_uds_port_mappings() {
+        const CID: u64 = 52;
+        let tmp_path = |prefix: &str| {
+            TempFile::new_with_prefix(prefix)
+                .unwrap()
+                .as_path()
+                .to_str()
+                .unwrap()
+                .to_owned()
+        };
+        let path = tmp_path("port_mappings");
+        let mapping = VsockPortMapping {
+            name: "service".to_owned(),
+            guest_port: 1025,
+            uds_path: tmp_path("port_mappings_service"),
+        };
+        let mut backend = VsockUnixBackend::new(CID, path.clone()).unwrap();
+        backend.add_port_mapping(mapping.clone()).unwrap();
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(VsockUdsState::type_id(), 2);
+        let state = backend.save();
+
+        // The port mappings cannot be saved in the older format.
+        assert!(state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_state =
+            VsockBackendState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap();
+
+        // The mapping socket left behind is listened on again.
+        drop(backend);
+        let backend =
+            VsockUnixBackend::restore(VsockUdsConstructorArgs { cid: CID }, &restored_state)
+                .unwrap();
+        assert_eq!(backend.port_mappings(), &[mapping.clone()]);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(backend.dgram_sock_path()).unwrap();
+        std::fs::remove_file(&mapping.uds_path).unwrap();
+    }
+
+    #[test]
+    fn test_set_uds_path() {
+        let mapping_state = |uds_path: &str| VsockPortMappingState {
+            name: "service".to_owned(),
+            guest_port: 1025,
+            uds_path: uds_path.to_owned(),
+        };
+        let mut state = VsockBackendState::Uds(VsockUdsState {
+            path: "/tmp/template.sock".to_owned(),
+            mmds_port: None,
+            agent_port: None,
+            port_mappings: vec![
+                mapping_state("/tmp/template.sock_1025"),
+                mapping_state("/tmp/service.sock"),
+            ],
+        });
+
+        state.set_uds_path("/tmp/clone.sock");
+        match state {
+            VsockBackendState::Uds(uds_state) => {
+                assert_eq!(uds_state.path, "/tmp/clone.sock");
+                // Only the sockets named after the former one are renamed.
+                assert_eq!(uds_state.port_mappings[0].uds_path, "/tmp/clone.sock_1025");
+                assert_eq!(uds_state.port_mappings[1].uds_path, "/tmp/service.sock");
+            }
+        }
+    }
+
+    #[test]
+    fn test_persist_rate_limiters() {
+        let ctx = TestContext::new();
+        let device = Vsock::new(
+            ctx.cid,
+            TestBackend::new(),
+            RateLimiter::new(1000, 0, 100, 0, 0, 0).unwrap(),
+            RateLimiter::default(),
+        )
+        .unwrap();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(VsockFrontendState::type_id(), 2);
+
+        // The rate limiters cannot be saved in the older format.
+        let state = device.save();
+        assert!(state.tx_rate_limiter_state.is_none());
+        assert!(state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        state
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_device = Vsock::restore(
+            VsockConstructorArgs {
+                mem: ctx.mem.clone(),
+                backend: TestBackend::new(),
+            },
+            &VsockFrontendState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        let bandwidth = restored_device.rx_rate_limiter.bandwidth().unwrap();
+        assert_eq!(bandwidth.capacity(), 1000);
+        assert_eq!(bandwidth.refill_time_ms(), 100);
+        assert!(restored_device.rx_rate_limiter.ops().is_none());
+        assert_eq!(restored_device.tx_rate_limiter, RateLimiter::default());
+    }
 }
@@ -12,6 +12,7 @@ use crate::virtio::{
 };
 use crate::Error as DeviceError;
 use core::result;
+use rate_limiter::RateLimiter;
 use utils::epoll::{EpollEvent, EventSet};
 use utils::eventfd::EventFd;
 use vm_memory::{GuestAddress, GuestMemoryMmap};
@@ -104,7 +105,11 @@ impl VsockEpollListener for TestBackend {
         self.evset = Some(evset);
     }
 }
-impl VsockBackend for TestBackend {}
+impl VsockBackend for TestBackend {
+    fn reset(&mut self) {
+        self.pending_rx = false;
+    }
+}
 
 pub struct TestContext {
     pub cid: u64,
@@ -122,7 +127,13 @@ impl TestContext {
             cid: CID,
             mem,
             mem_size: MEM_SIZE,
-            device: Vsock::new(CID, TestBackend::new()).unwrap(),
+            device: Vsock::new(
+                CID,
+                TestBackend::new(),
+                RateLimiter::default(),
+                RateLimiter::default(),
+            )
+            .unwrap(),
         }
     }
 
@@ -159,7 +170,14 @@ impl TestContext {
             guest_rxvq,
             guest_txvq,
             guest_evvq,
-            device: Vsock::with_queues(self.cid, TestBackend::new(), queues).unwrap(),
+            device: Vsock::with_queues(
+                self.cid,
+                TestBackend::new(),
+                RateLimiter::default(),
+                RateLimiter::default(),
+                queues,
+            )
+            .unwrap(),
         }
     }
 }
@@ -5,13 +5,20 @@
 /// This module implements the Unix Domain Sockets backend for vsock - a mediator between
 /// guest-side AF_VSOCK sockets and host-side AF_UNIX sockets. The heavy lifting is performed by
 /// `muxer::VsockMuxer`, a connection multiplexer that uses `super::csm::VsockConnection` for
-/// handling vsock connection states.
+/// handling vsock connection states, and `muxer_dgram::MuxerDgramSock` for forwarding datagrams.
 /// Check out `muxer.rs` for a more detailed explanation of the inner workings of this backend.
 mod muxer;
+mod muxer_agent;
+mod muxer_dgram;
 mod muxer_killq;
+mod muxer_mmds;
 mod muxer_rxq;
 
 pub use muxer::VsockMuxer as VsockUnixBackend;
+pub use muxer::{VsockConnectionInfo, VsockDeviceStats, VsockPortMapping, VsockPortStats};
+pub use muxer_agent::{AgentChannel, AGENT_CHANNEL};
+
+use logger::{VsockPortMetrics, METRICS};
 
 mod defs {
     /// Maximum number of established connections that we can handle.
@@ -26,12 +33,16 @@ mod defs {
 
 #[derive(Debug)]
 pub enum Error {
+    /// Error creating the Unix socket pair backing a guest connection to the agent.
+    AgentStream(std::io::Error),
     /// Error registering a new epoll-listening FD.
     EpollAdd(std::io::Error),
     /// Error creating an epoll FD.
     EpollFdCreate(std::io::Error),
     /// The host made an invalid vsock port connection request.
     InvalidPortRequest,
+    /// Error creating the Unix socket pair backing a guest connection to the MMDS.
+    MmdsStream(std::io::Error),
     /// Error accepting a new connection from the host-side Unix socket.
     UnixAccept(std::io::Error),
     /// Error binding to the host-side Unix socket.
@@ -45,4 +56,11 @@ pub enum Error {
 }
 
 type Result<T> = std::result::Result<T, Error>;
+
+/// Update the metrics of `port`, unless the port cannot be tracked.
+fn update_port_metrics<F: FnOnce(&VsockPortMetrics)>(port: u32, update_fn: F) {
+    if let Some(metrics) = METRICS.vsock.ports.get(port) {
+        update_fn(&metrics);
+    }
+}
 type MuxerConnection = super::csm::VsockConnection<std::os::unix::net::UnixStream>;
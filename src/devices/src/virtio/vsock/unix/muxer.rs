@@ -30,12 +30,24 @@
 ///    other pollable FDs are then registered under this nested epoll FD.
 ///    To route all these events to their handlers, the muxer uses another `HashMap` object,
 ///    mapping `RawFd`s to `EpollListener`s.
+///
+/// Besides the host Unix socket, the muxer can listen on a Unix socket for each port mapping.
+/// Host connections accepted on such a socket are forwarded to the mapped guest port right away,
+/// without a "connect <port>" command.
+///
+/// Guest connections to the MMDS port, if one is set, are not forwarded to the host. They are
+/// served by the muxer itself instead (see `muxer_mmds.rs`). Likewise, guest connections to the
+/// agent port, if one is set, are handed over to the API thread (see `muxer_agent.rs`).
+///
+/// Datagrams are not connection-oriented, so they bypass the connection pool. They are forwarded
+/// through the muxer's Unix datagram socket (see `muxer_dgram.rs`).
 use std::collections::{HashMap, HashSet};
 use std::io::Read;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 
-use logger::{debug, error, info, warn, IncMetric, METRICS};
+use logger::{debug, error, info, warn, IncMetric, StoreMetric};
+use serde::{Deserialize, Serialize};
 use utils::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
 
 use super::super::csm::ConnState;
@@ -43,12 +55,16 @@ use super::super::defs::uapi;
 use super::super::packet::VsockPacket;
 use super::super::{
     Result as VsockResult, VsockBackend, VsockChannel, VsockEpollListener, VsockError,
+    VSOCK_METRICS,
 };
 use super::defs;
+use super::muxer_agent::AGENT_CHANNEL;
+use super::muxer_dgram::MuxerDgramSock;
 use super::muxer_killq::MuxerKillQ;
+use super::muxer_mmds::MuxerMmdsStream;
 use super::muxer_rxq::MuxerRxQ;
 use super::MuxerConnection;
-use super::{Error, Result};
+use super::{update_port_metrics, Error, Result};
 
 /// A unique identifier of a `MuxerConnection` object. Connections are stored in a hash map,
 /// keyed by a `ConnMapKey` object.
@@ -58,6 +74,59 @@ pub struct ConnMapKey {
     peer_port: u32,
 }
 
+/// Live traffic counters of a vsock port. The traffic of a connection is accounted to the port
+/// that the connection was made to, i.e. the port of the listening end.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct VsockPortStats {
+    /// The port number.
+    pub port: u32,
+    /// Number of bytes sent to the guest.
+    pub rx_bytes: u64,
+    /// Number of bytes sent by the guest.
+    pub tx_bytes: u64,
+    /// Number of packets sent to the guest.
+    pub rx_packets: u64,
+    /// Number of packets sent by the guest.
+    pub tx_packets: u64,
+    /// Number of open connections.
+    pub active_connections: u64,
+    /// Number of guest packets that could not be delivered.
+    pub dropped_packets: u64,
+}
+
+/// An open vsock connection.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct VsockConnectionInfo {
+    /// The host-side port.
+    pub host_port: u32,
+    /// The guest-side port.
+    pub guest_port: u32,
+    /// The connection was initiated by the host.
+    pub host_initiated: bool,
+}
+
+/// Live traffic counters of a vsock device, along with its open connections.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct VsockDeviceStats {
+    /// Counters for each port that saw any traffic, ordered by port number.
+    pub ports: Vec<VsockPortStats>,
+    /// The open connections, ordered by host port.
+    pub connections: Vec<VsockConnectionInfo>,
+}
+
+/// A named service, reached by the host through its own Unix socket, which is forwarded to a
+/// fixed guest port.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VsockPortMapping {
+    /// The name of the service.
+    pub name: String,
+    /// The guest port that the host connections are forwarded to.
+    pub guest_port: u32,
+    /// The file system path of the Unix socket that the host connects to.
+    pub uds_path: String,
+}
+
 /// A muxer RX queue item.
 #[derive(Clone, Copy, Debug)]
 pub enum MuxerRx {
@@ -65,6 +134,8 @@ pub enum MuxerRx {
     ConnRx(ConnMapKey),
     /// The muxer must produce an RST packet.
     RstPkt { local_port: u32, peer_port: u32 },
+    /// The packet must be read from the muxer's datagram socket.
+    DgramRx,
 }
 
 /// An epoll listener, registered under the muxer's nested epoll FD.
@@ -78,6 +149,15 @@ enum EpollListener {
     /// A listener interested in reading host "connect <port>" commands from a freshly
     /// connected host socket.
     LocalStream(UnixStream),
+    /// The server end of a guest connection to the MMDS port.
+    MmdsStream(MuxerMmdsStream),
+    /// A listener interested in datagrams sent by the host.
+    DgramSock,
+    /// A listener interested in new host-initiated connections to the mapped `guest_port`.
+    MappedSock {
+        listener: UnixListener,
+        guest_port: u32,
+    },
 }
 
 /// The vsock connection multiplexer.
@@ -102,6 +182,11 @@ pub struct VsockMuxer {
     /// The file system path of the host-side Unix socket. This is used to figure out the path
     /// to Unix sockets listening on specific ports. I.e. "<this path>_<port number>".
     pub(crate) host_sock_path: String,
+    /// The Unix socket, through which datagrams are exchanged with the host.
+    dgram_sock: MuxerDgramSock,
+    /// The datagram socket has data to be read. While this is the case, the socket isn't polled,
+    /// and a `MuxerRx::DgramRx` item is kept in the RX queue instead.
+    dgram_rx_pending: bool,
     /// The nested epoll event set, used to register epoll listeners.
     epoll: Epoll,
     /// A hash set used to keep track of used host-side (local) ports, in order to assign local
@@ -109,6 +194,15 @@ pub struct VsockMuxer {
     local_port_set: HashSet<u32>,
     /// The last used host-side port.
     local_port_last: u32,
+    /// The port on which the guest can reach the MMDS, if any.
+    mmds_port: Option<u32>,
+    /// The port on which the guest agent connects, if any.
+    agent_port: Option<u32>,
+    /// The port mappings that the muxer listens on.
+    port_mappings: Vec<VsockPortMapping>,
+    /// The host-side ports of the connections accepted on a port mapping socket. These
+    /// connections didn't go through the "connect <port>" handshake, so they aren't acked.
+    mapped_local_ports: HashSet<u32>,
 }
 
 impl VsockChannel for VsockMuxer {
@@ -125,6 +219,9 @@ impl VsockChannel for VsockMuxer {
         // and then try to pop something out again.
         if self.rxq.is_empty() && !self.rxq.is_synced() {
             self.rxq = MuxerRxQ::from_conn_map(&self.conn_map);
+            if self.dgram_rx_pending {
+                self.rxq.push(MuxerRx::DgramRx);
+            }
         }
 
         while let Some(rx) = self.rxq.peek() {
@@ -160,8 +257,26 @@ impl VsockChannel for VsockMuxer {
                     if do_pop {
                         self.rxq.pop().unwrap();
                     }
+                    if conn_res.is_ok() {
+                        update_port_metrics(self.service_port(key), |metrics| {
+                            metrics.rx_packets_count.inc();
+                            metrics.rx_bytes_count.add(pkt.len() as usize);
+                        });
+                    }
                     conn_res
                 }
+
+                // We'll read the next datagram. The item stays queued until the socket is
+                // drained, at which point we can go back to polling it.
+                MuxerRx::DgramRx => {
+                    let dgram_res = self.dgram_sock.recv(pkt, self.cid);
+                    if dgram_res.is_err() {
+                        self.rxq.pop().unwrap();
+                        self.dgram_rx_pending = false;
+                        self.add_dgram_listener();
+                    }
+                    dgram_res
+                }
             };
 
             if res.is_ok() {
@@ -203,9 +318,17 @@ impl VsockChannel for VsockMuxer {
             pkt.hdr()
         );
 
+        // Datagrams don't belong to any connection, so there's nothing to reply with. They are
+        // either forwarded to the host, or dropped.
+        if pkt.type_() == uapi::VSOCK_TYPE_DGRAM {
+            self.handle_peer_dgram_pkt(pkt);
+            return Ok(());
+        }
+
         // If this packet has an unsupported type (!=stream), we must send back an RST.
         //
         if pkt.type_() != uapi::VSOCK_TYPE_STREAM {
+            update_port_metrics(pkt.dst_port(), |metrics| metrics.dropped_packets.inc());
             self.enq_rst(pkt.dst_port(), pkt.src_port());
             return Ok(());
         }
@@ -229,6 +352,7 @@ impl VsockChannel for VsockMuxer {
                 self.handle_peer_request_pkt(&pkt);
             } else {
                 // Send back an RST, to let the drive know we weren't expecting this packet.
+                update_port_metrics(pkt.dst_port(), |metrics| metrics.dropped_packets.inc());
                 self.enq_rst(pkt.dst_port(), pkt.src_port());
             }
             return Ok(());
@@ -243,6 +367,10 @@ impl VsockChannel for VsockMuxer {
         }
 
         // Alright, everything looks in order - forward this packet to its owning connection.
+        update_port_metrics(self.service_port(conn_key), |metrics| {
+            metrics.tx_packets_count.inc();
+            metrics.tx_bytes_count.add(pkt.len() as usize);
+        });
         let mut res: VsockResult<()> = Ok(());
         self.apply_conn_mutation(conn_key, |conn| {
             res = conn.send_pkt(pkt);
@@ -299,13 +427,54 @@ impl VsockEpollListener for VsockMuxer {
             }
             Err(e) => {
                 warn!("vsock: failed to consume muxer epoll event: {}", e);
-                METRICS.vsock.muxer_event_fails.inc();
+                VSOCK_METRICS.update(|metrics| metrics.muxer_event_fails.inc());
             }
         }
     }
 }
 
-impl VsockBackend for VsockMuxer {}
+impl VsockBackend for VsockMuxer {
+    /// Drop all the connections.
+    ///
+    /// The host-side streams are closed, after writing out the data that the guest had already
+    /// sent, as far as they accept it without blocking. The host peers see this as a regular
+    /// connection shutdown. The host socket and the port mapping sockets keep listening for new
+    /// connections, and the datagram socket keeps its unread datagrams.
+    fn reset(&mut self) {
+        let keys: Vec<ConnMapKey> = self.conn_map.keys().copied().collect();
+        for key in keys {
+            if let Some(conn) = self.conn_map.get_mut(&key) {
+                conn.flush_tx_buf();
+            }
+            self.remove_connection(key);
+        }
+
+        // Host-initiated connections that haven't asked for a port yet, and MMDS streams.
+        let fds: Vec<RawFd> = self
+            .listener_map
+            .iter()
+            .filter(|(_, listener)| {
+                !matches!(
+                    listener,
+                    EpollListener::HostSock
+                        | EpollListener::DgramSock
+                        | EpollListener::MappedSock { .. }
+                )
+            })
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in fds {
+            self.remove_listener(fd);
+        }
+
+        self.rxq = MuxerRxQ::new();
+        self.killq = MuxerKillQ::new();
+        if self.dgram_rx_pending {
+            self.dgram_rx_pending = false;
+            self.add_dgram_listener();
+        }
+    }
+}
 
 impl VsockMuxer {
     /// Muxer constructor.
@@ -315,25 +484,127 @@ impl VsockMuxer {
         let host_sock = UnixListener::bind(&host_sock_path)
             .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
             .map_err(Error::UnixBind)?;
+        let dgram_sock = MuxerDgramSock::new(&host_sock_path)?;
 
         let mut muxer = Self {
             cid,
             host_sock,
             host_sock_path,
+            dgram_sock,
+            dgram_rx_pending: false,
             epoll: Epoll::new().map_err(Error::EpollFdCreate)?,
             rxq: MuxerRxQ::new(),
             conn_map: HashMap::with_capacity(defs::MAX_CONNECTIONS),
-            listener_map: HashMap::with_capacity(defs::MAX_CONNECTIONS + 1),
+            listener_map: HashMap::with_capacity(defs::MAX_CONNECTIONS + 2),
             killq: MuxerKillQ::new(),
             local_port_last: (1u32 << 30) - 1,
             local_port_set: HashSet::with_capacity(defs::MAX_CONNECTIONS),
+            mmds_port: None,
+            agent_port: None,
+            port_mappings: Vec::new(),
+            mapped_local_ports: HashSet::new(),
         };
 
         // Listen on the host initiated socket, for incomming connections.
         muxer.add_listener(muxer.host_sock.as_raw_fd(), EpollListener::HostSock)?;
+        // Listen on the datagram socket, for datagrams sent to the guest.
+        muxer.add_listener(muxer.dgram_sock.as_raw_fd(), EpollListener::DgramSock)?;
         Ok(muxer)
     }
 
+    /// Get the file system path of the Unix socket, through which datagrams are exchanged with
+    /// the host.
+    pub fn dgram_sock_path(&self) -> &str {
+        self.dgram_sock.path()
+    }
+
+    /// Serve the MMDS to the guest connections made to `port`, instead of forwarding them to a
+    /// host-side Unix socket.
+    pub fn set_mmds_port(&mut self, port: Option<u32>) {
+        self.mmds_port = port;
+    }
+
+    /// Get the port on which the guest can reach the MMDS, if any.
+    pub fn mmds_port(&self) -> Option<u32> {
+        self.mmds_port
+    }
+
+    /// Hand the guest connections made to `port` over to the API thread, as the connection of
+    /// the guest agent, instead of forwarding them to a host-side Unix socket.
+    pub fn set_agent_port(&mut self, port: Option<u32>) {
+        self.agent_port = port;
+    }
+
+    /// Get the port on which the guest agent connects, if any.
+    pub fn agent_port(&self) -> Option<u32> {
+        self.agent_port
+    }
+
+    /// Listen on the Unix socket of `mapping`, forwarding the connections accepted on it to the
+    /// mapped guest port.
+    pub fn add_port_mapping(&mut self, mapping: VsockPortMapping) -> Result<()> {
+        let listener = UnixListener::bind(&mapping.uds_path)
+            .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
+            .map_err(Error::UnixBind)?;
+        let fd = listener.as_raw_fd();
+        self.add_listener(
+            fd,
+            EpollListener::MappedSock {
+                listener,
+                guest_port: mapping.guest_port,
+            },
+        )?;
+        self.port_mappings.push(mapping);
+        Ok(())
+    }
+
+    /// Get the port mappings that the muxer listens on.
+    pub fn port_mappings(&self) -> &[VsockPortMapping] {
+        &self.port_mappings
+    }
+
+    /// Provides a snapshot of the per-port traffic counters, and of the open connections.
+    pub fn stats(&self) -> VsockDeviceStats {
+        let ports = METRICS
+            .vsock
+            .ports
+            .all()
+            .into_iter()
+            .map(|(port, metrics)| VsockPortStats {
+                port,
+                rx_bytes: metrics.rx_bytes_count.count() as u64,
+                tx_bytes: metrics.tx_bytes_count.count() as u64,
+                rx_packets: metrics.rx_packets_count.count() as u64,
+                tx_packets: metrics.tx_packets_count.count() as u64,
+                active_connections: metrics.active_conns.fetch() as u64,
+                dropped_packets: metrics.dropped_packets.count() as u64,
+            })
+            .collect();
+
+        let mut connections: Vec<VsockConnectionInfo> = self
+            .conn_map
+            .keys()
+            .map(|key| VsockConnectionInfo {
+                host_port: key.local_port,
+                guest_port: key.peer_port,
+                host_initiated: self.local_port_set.contains(&key.local_port),
+            })
+            .collect();
+        connections.sort_by_key(|conn| (conn.host_port, conn.guest_port));
+
+        VsockDeviceStats { ports, connections }
+    }
+
+    /// Get the port of the service that the connection identified by `key` was made to: the
+    /// guest port of host-initiated connections, and the host port of guest-initiated ones.
+    fn service_port(&self, key: ConnMapKey) -> u32 {
+        if self.local_port_set.contains(&key.local_port) {
+            key.peer_port
+        } else {
+            key.local_port
+        }
+    }
+
     /// Handle/dispatch an epoll event to its listener.
     fn handle_event(&mut self, fd: RawFd, evset: EventSet) {
         debug!(
@@ -365,15 +636,7 @@ impl VsockMuxer {
                     self.host_sock.accept().map(|_| 0).unwrap_or(0);
                     return;
                 }
-                self.host_sock
-                    .accept()
-                    .map_err(Error::UnixAccept)
-                    .and_then(|(stream, _)| {
-                        stream
-                            .set_nonblocking(true)
-                            .map(|_| stream)
-                            .map_err(Error::UnixAccept)
-                    })
+                Self::accept_local_stream(&self.host_sock)
                     .and_then(|stream| {
                         // Before forwarding this connection to a listening AF_VSOCK socket on
                         // the guest side, we need to know the destination port. We'll read
@@ -391,21 +654,8 @@ impl VsockMuxer {
             Some(EpollListener::LocalStream(_)) => {
                 if let Some(EpollListener::LocalStream(mut stream)) = self.remove_listener(fd) {
                     Self::read_local_stream_port(&mut stream)
-                        .map(|peer_port| (self.allocate_local_port(), peer_port))
-                        .and_then(|(local_port, peer_port)| {
-                            self.add_connection(
-                                ConnMapKey {
-                                    local_port,
-                                    peer_port,
-                                },
-                                MuxerConnection::new_local_init(
-                                    stream,
-                                    uapi::VSOCK_HOST_CID,
-                                    self.cid,
-                                    local_port,
-                                    peer_port,
-                                ),
-                            )
+                        .and_then(|peer_port| {
+                            self.add_local_init_connection(stream, peer_port, false)
                         })
                         .unwrap_or_else(|err| {
                             info!("vsock: error adding local-init connection: {:?}", err);
@@ -413,13 +663,109 @@ impl VsockMuxer {
                 }
             }
 
+            // A new host-initiated connection to a mapped guest port is ready to be accepted.
+            // The destination port is already known, so the connection is forwarded right away.
+            Some(EpollListener::MappedSock {
+                listener,
+                guest_port,
+            }) => {
+                let peer_port = *guest_port;
+                if self.conn_map.len() == defs::MAX_CONNECTIONS {
+                    warn!("vsock: connection limit reached; refusing new host connection");
+                    listener.accept().map(|_| 0).unwrap_or(0);
+                    return;
+                }
+                Self::accept_local_stream(listener)
+                    .and_then(|stream| self.add_local_init_connection(stream, peer_port, true))
+                    .unwrap_or_else(|err| {
+                        info!("vsock: error adding local-init connection: {:?}", err);
+                    });
+            }
+
+            // MMDS requests are ready to be read, or their responses to be written.
+            Some(EpollListener::MmdsStream(stream)) => {
+                let old_evset = stream.get_polled_evset();
+                stream.notify(evset);
+                let new_evset = stream.get_polled_evset();
+                if new_evset.is_empty() {
+                    // Dropping the stream lets the guest know that the connection was closed.
+                    self.remove_listener(fd);
+                } else if new_evset != old_evset {
+                    self.epoll
+                        .ctl(
+                            ControlOperation::Modify,
+                            fd,
+                            EpollEvent::new(new_evset, fd as u64),
+                        )
+                        .unwrap_or_else(|err| {
+                            self.remove_listener(fd);
+                            error!(
+                                "vsock: error updating MMDS epoll listener for fd {:?}: {:?}",
+                                fd, err
+                            );
+                            VSOCK_METRICS.update(|metrics| metrics.muxer_event_fails.inc());
+                        });
+                }
+            }
+
+            // Datagrams are ready to be read. The socket won't be polled again until they are
+            // all delivered to the guest, since they can only be read as fast as the guest
+            // provides RX buffers.
+            Some(EpollListener::DgramSock) => {
+                if self.rxq.push(MuxerRx::DgramRx) {
+                    self.dgram_rx_pending = true;
+                    self.remove_listener(fd);
+                }
+            }
+
             _ => {
                 info!("vsock: unexpected event: fd={:?}, evset={:?}", fd, evset);
-                METRICS.vsock.muxer_event_fails.inc();
+                VSOCK_METRICS.update(|metrics| metrics.muxer_event_fails.inc());
             }
         }
     }
 
+    /// Accept a new host-initiated connection on `listener`.
+    fn accept_local_stream(listener: &UnixListener) -> Result<UnixStream> {
+        listener
+            .accept()
+            .map_err(Error::UnixAccept)
+            .and_then(|(stream, _)| {
+                stream
+                    .set_nonblocking(true)
+                    .map(|_| stream)
+                    .map_err(Error::UnixAccept)
+            })
+    }
+
+    /// Forward the host-initiated connection on `stream` to the guest port `peer_port`. A
+    /// `mapped` connection was accepted on a port mapping socket.
+    fn add_local_init_connection(
+        &mut self,
+        stream: UnixStream,
+        peer_port: u32,
+        mapped: bool,
+    ) -> Result<()> {
+        let local_port = self.allocate_local_port();
+        self.add_connection(
+            ConnMapKey {
+                local_port,
+                peer_port,
+            },
+            MuxerConnection::new_local_init(
+                stream,
+                uapi::VSOCK_HOST_CID,
+                self.cid,
+                local_port,
+                peer_port,
+            ),
+        )?;
+        if mapped {
+            self.mapped_local_ports.insert(local_port);
+        }
+        Ok(())
+    }
+
     /// Parse a host "connect" command, and extract the destination vsock port.
     fn read_local_stream_port(stream: &mut UnixStream) -> Result<u32> {
         let mut buf = [0u8; 32];
@@ -495,7 +841,10 @@ impl VsockMuxer {
                 self.rxq.push(MuxerRx::ConnRx(key));
             }
             self.conn_map.insert(key, conn);
-            METRICS.vsock.conns_added.inc();
+            VSOCK_METRICS.update(|metrics| metrics.conns_added.inc());
+            update_port_metrics(self.service_port(key), |metrics| {
+                metrics.active_conns.store(metrics.active_conns.fetch() + 1)
+            });
         })
     }
 
@@ -503,8 +852,14 @@ impl VsockMuxer {
     fn remove_connection(&mut self, key: ConnMapKey) {
         if let Some(conn) = self.conn_map.remove(&key) {
             self.remove_listener(conn.as_raw_fd());
-            METRICS.vsock.conns_removed.inc();
+            VSOCK_METRICS.update(|metrics| metrics.conns_removed.inc());
+            update_port_metrics(self.service_port(key), |metrics| {
+                metrics
+                    .active_conns
+                    .store(metrics.active_conns.fetch().saturating_sub(1))
+            });
         }
+        self.mapped_local_ports.remove(&key.local_port);
         self.free_local_port(key.local_port);
     }
 
@@ -513,7 +868,7 @@ impl VsockMuxer {
     /// it an RST packet.
     fn kill_connection(&mut self, key: ConnMapKey) {
         let mut had_rx = false;
-        METRICS.vsock.conns_killed.inc();
+        VSOCK_METRICS.update(|metrics| metrics.conns_killed.inc());
 
         self.conn_map.entry(key).and_modify(|conn| {
             had_rx = conn.has_pending_rx();
@@ -535,6 +890,9 @@ impl VsockMuxer {
             EpollListener::Connection { evset, .. } => evset,
             EpollListener::LocalStream(_) => EventSet::IN,
             EpollListener::HostSock => EventSet::IN,
+            EpollListener::MmdsStream(ref stream) => stream.get_polled_evset(),
+            EpollListener::DgramSock => EventSet::IN,
+            EpollListener::MappedSock { .. } => EventSet::IN,
         };
 
         self.epoll
@@ -588,15 +946,23 @@ impl VsockMuxer {
     /// Handle a new connection request comming from our peer (the guest vsock driver).
     ///
     /// This will attempt to connect to a host-side Unix socket, expected to be listening at
-    /// the file system path corresponing to the destination port. If successful, a new
-    /// connection object will be created and added to the connection pool. On failure, a new
-    /// RST packet will be scheduled for delivery to the guest.
+    /// the file system path corresponing to the destination port, or to the MMDS or the API
+    /// thread if that is the MMDS or the agent port. If successful, a new connection object
+    /// will be created and added to the connection pool. On failure, a new RST packet will be
+    /// scheduled for delivery to the guest.
     fn handle_peer_request_pkt(&mut self, pkt: &VsockPacket) {
-        let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+        let stream = if self.mmds_port == Some(pkt.dst_port()) {
+            self.connect_mmds()
+        } else if self.agent_port == Some(pkt.dst_port()) {
+            Self::connect_agent()
+        } else {
+            let port_path = format!("{}_{}", self.host_sock_path, pkt.dst_port());
+            UnixStream::connect(port_path)
+                .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
+                .map_err(Error::UnixConnect)
+        };
 
-        UnixStream::connect(port_path)
-            .and_then(|stream| stream.set_nonblocking(true).map(|_| stream))
-            .map_err(Error::UnixConnect)
+        stream
             .and_then(|stream| {
                 self.add_connection(
                     ConnMapKey {
@@ -613,7 +979,78 @@ impl VsockMuxer {
                     ),
                 )
             })
-            .unwrap_or_else(|_| self.enq_rst(pkt.dst_port(), pkt.src_port()));
+            .unwrap_or_else(|_| {
+                update_port_metrics(pkt.dst_port(), |metrics| metrics.dropped_packets.inc());
+                self.enq_rst(pkt.dst_port(), pkt.src_port())
+            });
+    }
+
+    /// Handle a datagram coming from our peer (the guest vsock driver).
+    ///
+    /// Only data packets addressed to the host are forwarded, to the host-side Unix datagram
+    /// socket expected to be bound at the file system path corresponding to the destination
+    /// port. Everything else is dropped.
+    fn handle_peer_dgram_pkt(&self, pkt: &VsockPacket) {
+        if pkt.dst_cid() != uapi::VSOCK_HOST_CID {
+            info!(
+                "vsock: dropping guest datagram for unknown CID: {:?}",
+                pkt.hdr()
+            );
+            VSOCK_METRICS.update(|metrics| metrics.dgrams_dropped.inc());
+            return;
+        }
+        if pkt.op() != uapi::VSOCK_OP_RW {
+            info!("vsock: dropping guest datagram: {:?}", pkt.hdr());
+            VSOCK_METRICS.update(|metrics| metrics.dgrams_dropped.inc());
+            update_port_metrics(pkt.dst_port(), |metrics| metrics.dropped_packets.inc());
+            return;
+        }
+
+        let data = pkt
+            .buf()
+            .map(|buf| &buf[..pkt.len() as usize])
+            .unwrap_or(&[]);
+        self.dgram_sock.send(pkt.dst_port(), pkt.src_port(), data);
+    }
+
+    /// Start polling the datagram socket again.
+    fn add_dgram_listener(&mut self) {
+        self.add_listener(self.dgram_sock.as_raw_fd(), EpollListener::DgramSock)
+            .unwrap_or_else(|err| {
+                error!("vsock: error adding datagram epoll listener: {:?}", err);
+                VSOCK_METRICS.update(|metrics| metrics.muxer_event_fails.inc());
+            });
+    }
+
+    /// Create the Unix socket pair backing a guest connection to the MMDS port, and start
+    /// serving the MMDS on one of its ends. The other end is returned, for the connection to
+    /// use.
+    fn connect_mmds(&mut self) -> Result<UnixStream> {
+        let (conn_stream, mmds_stream) = UnixStream::pair().map_err(Error::MmdsStream)?;
+        conn_stream
+            .set_nonblocking(true)
+            .and_then(|_| mmds_stream.set_nonblocking(true))
+            .map(|_| MuxerMmdsStream::new(mmds_stream))
+            .map_err(Error::MmdsStream)
+            .and_then(|mmds_stream| {
+                self.add_listener(
+                    mmds_stream.as_raw_fd(),
+                    EpollListener::MmdsStream(mmds_stream),
+                )
+            })
+            .map(|_| conn_stream)
+    }
+
+    /// Create the Unix socket pair backing a guest connection to the agent port, and attach one
+    /// of its ends to the agent channel, where the API thread uses it in blocking mode. The
+    /// other end is returned, for the connection to use.
+    fn connect_agent() -> Result<UnixStream> {
+        let (conn_stream, agent_stream) = UnixStream::pair().map_err(Error::AgentStream)?;
+        conn_stream
+            .set_nonblocking(true)
+            .map_err(Error::AgentStream)?;
+        AGENT_CHANNEL.attach(agent_stream);
+        Ok(conn_stream)
     }
 
     /// Perform an action that might mutate a connection's state.
@@ -635,8 +1072,11 @@ impl VsockMuxer {
             mut_fn(conn);
 
             // If this is a host-initiated connection that has just become established, we'll have
-            // to send an ack message to the host end.
-            if prev_state == ConnState::LocalInit && conn.state() == ConnState::Established {
+            // to send an ack message to the host end, unless the host didn't ask for a port.
+            if prev_state == ConnState::LocalInit
+                && conn.state() == ConnState::Established
+                && !self.mapped_local_ports.contains(&key.local_port)
+            {
                 let msg = format!("OK {}\n", key.local_port);
                 match conn.send_bytes_raw(msg.as_bytes()) {
                     Ok(written) if written == msg.len() => (),
@@ -698,7 +1138,7 @@ impl VsockMuxer {
                                 "vsock: error updating epoll listener for (lp={}, pp={}): {:?}",
                                 key.local_port, key.peer_port, err
                             );
-                            METRICS.vsock.muxer_event_fails.inc();
+                            VSOCK_METRICS.update(|metrics| metrics.muxer_event_fails.inc());
                         });
                 }
             } else {
@@ -717,7 +1157,7 @@ impl VsockMuxer {
                         "vsock: error updating epoll listener for (lp={}, pp={}): {:?}",
                         key.local_port, key.peer_port, err
                     );
-                    METRICS.vsock.muxer_event_fails.inc();
+                    VSOCK_METRICS.update(|metrics| metrics.muxer_event_fails.inc());
                 });
             }
         }
@@ -741,7 +1181,7 @@ impl VsockMuxer {
 
         if self.killq.is_empty() && !self.killq.is_synced() {
             self.killq = MuxerKillQ::from_conn_map(&self.conn_map);
-            METRICS.vsock.killq_resync.inc();
+            VSOCK_METRICS.update(|metrics| metrics.killq_resync.inc());
             // If we've just re-created the kill queue, we can sweep it again; maybe there's
             // more to kill.
             self.sweep_killq();
@@ -769,9 +1209,10 @@ impl VsockMuxer {
 
 #[cfg(test)]
 mod tests {
+    use logger::METRICS;
     use std::io::{Read, Write};
     use std::ops::Drop;
-    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
     use std::path::{Path, PathBuf};
     use utils::tempfile::TempFile;
 
@@ -793,6 +1234,10 @@ mod tests {
     impl Drop for MuxerTestContext {
         fn drop(&mut self) {
             std::fs::remove_file(self.muxer.host_sock_path.as_str()).unwrap();
+            std::fs::remove_file(self.muxer.dgram_sock_path()).unwrap();
+            for mapping in self.muxer.port_mappings() {
+                std::fs::remove_file(&mapping.uds_path).unwrap();
+            }
         }
     }
 
@@ -936,6 +1381,26 @@ mod tests {
         }
     }
 
+    struct LocalDgramSock {
+        path: PathBuf,
+        sock: UnixDatagram,
+    }
+    impl LocalDgramSock {
+        fn new(path: &str) -> Self {
+            let sock = UnixDatagram::bind(path).unwrap();
+            sock.set_nonblocking(true).unwrap();
+            Self {
+                path: PathBuf::from(path),
+                sock,
+            }
+        }
+    }
+    impl Drop for LocalDgramSock {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.path).unwrap();
+        }
+    }
+
     struct LocalListener {
         path: PathBuf,
         sock: UnixListener,
@@ -973,15 +1438,15 @@ mod tests {
     fn test_bad_peer_pkt() {
         const LOCAL_PORT: u32 = 1026;
         const PEER_PORT: u32 = 1025;
-        const SOCK_DGRAM: u16 = 2;
+        const SOCK_SEQPACKET: u16 = 2;
 
         let mut ctx = MuxerTestContext::new("bad_peer_pkt");
         ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST)
-            .set_type(SOCK_DGRAM);
+            .set_type(SOCK_SEQPACKET);
         ctx.send();
 
-        // The guest sent a SOCK_DGRAM packet. Per the vsock spec, we need to reply with an RST
-        // packet, since vsock only supports stream sockets.
+        // The guest sent a SOCK_SEQPACKET packet. Per the vsock spec, we need to reply with an RST
+        // packet, since we only support stream and datagram sockets.
         assert!(ctx.muxer.has_pending_rx());
         ctx.recv();
         assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
@@ -1110,6 +1575,60 @@ mod tests {
         assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
     }
 
+    #[test]
+    fn test_port_mapping() {
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("port_mapping");
+        let mapping = VsockPortMapping {
+            name: "service".to_owned(),
+            guest_port: PEER_PORT,
+            uds_path: get_file("port_mapping_service"),
+        };
+        ctx.muxer.add_port_mapping(mapping.clone()).unwrap();
+        assert_eq!(ctx.muxer.port_mappings(), &[mapping.clone()]);
+        // The socket of a mapping cannot be bound twice.
+        assert!(ctx.muxer.add_port_mapping(mapping.clone()).is_err());
+        assert_eq!(ctx.muxer.port_mappings().len(), 1);
+
+        // Connections to the mapping socket are forwarded to the guest port right away.
+        let mut stream = UnixStream::connect(&mapping.uds_path).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        ctx.notify_muxer();
+        assert_eq!(ctx.count_epoll_listeners(), (0, 1));
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_REQUEST);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+        let local_port = ctx.pkt.src_port();
+        assert!(ctx.muxer.local_port_set.contains(&local_port));
+        ctx.init_pkt(local_port, PEER_PORT, uapi::VSOCK_OP_RESPONSE);
+        ctx.send();
+
+        // No ack message is sent to the host end, the first bytes it reads come from the guest.
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(local_port, PEER_PORT, &data);
+        ctx.send();
+        let mut buf = vec![0u8; 32];
+        let len = stream.read(&mut buf[..]).unwrap();
+        assert_eq!(&buf[..len], &data);
+
+        let data = [5, 6, 7, 8];
+        stream.write_all(&data).unwrap();
+        ctx.notify_muxer();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.pkt.buf().unwrap()[..data.len()], data);
+
+        // The mapping socket keeps listening after a reset.
+        ctx.muxer.reset();
+        assert!(ctx.muxer.mapped_local_ports.is_empty());
+        assert_eq!(ctx.muxer.listener_map.len(), 3);
+        UnixStream::connect(&mapping.uds_path).unwrap();
+        ctx.notify_muxer();
+        assert_eq!(ctx.count_epoll_listeners(), (0, 1));
+    }
+
     #[test]
     fn test_local_close() {
         let peer_port = 1025;
@@ -1188,6 +1707,333 @@ mod tests {
         assert_eq!(stream.read(buf.as_mut_slice()).unwrap(), 0);
     }
 
+    #[test]
+    fn test_mmds_connection() {
+        const MMDS_PORT: u32 = 1027;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("mmds_connection");
+        ctx.muxer.set_mmds_port(Some(MMDS_PORT));
+        assert_eq!(ctx.muxer.mmds_port(), Some(MMDS_PORT));
+
+        // No host-side Unix socket listens on the MMDS port, but the connection is accepted.
+        ctx.init_pkt(MMDS_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        assert_eq!(ctx.pkt.src_port(), MMDS_PORT);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+        let mmds_stream_count = |ctx: &MuxerTestContext| {
+            ctx.muxer
+                .listener_map
+                .values()
+                .filter(|listener| matches!(listener, EpollListener::MmdsStream(_)))
+                .count()
+        };
+        assert_eq!(mmds_stream_count(&ctx), 1);
+
+        // The MMDS reads the request, then the connection reads the response.
+        ctx.init_data_pkt(MMDS_PORT, PEER_PORT, b"GET /vsock-test HTTP/1.1\r\n\r\n");
+        ctx.send();
+        ctx.notify_muxer();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        let len = ctx.pkt.len() as usize;
+        assert!(ctx.pkt.buf().unwrap()[..len].starts_with(b"HTTP/1.1 404"));
+
+        // Resetting the connection also closes the MMDS end.
+        ctx.init_pkt(MMDS_PORT, PEER_PORT, uapi::VSOCK_OP_RST);
+        ctx.send();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.conn_map.is_empty());
+        assert_eq!(mmds_stream_count(&ctx), 0);
+
+        // Other ports are still forwarded to the host.
+        ctx.init_pkt(MMDS_PORT + 1, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+    }
+
+    #[test]
+    fn test_agent_connection() {
+        const AGENT_PORT: u32 = 1028;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("agent_connection");
+        ctx.muxer.set_agent_port(Some(AGENT_PORT));
+        assert_eq!(ctx.muxer.agent_port(), Some(AGENT_PORT));
+
+        // No host-side Unix socket listens on the agent port, but the connection is accepted.
+        ctx.init_pkt(AGENT_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        assert_eq!(ctx.pkt.src_port(), AGENT_PORT);
+
+        // The data sent by the guest agent is read from the agent channel, and the other way
+        // around.
+        ctx.init_data_pkt(AGENT_PORT, PEER_PORT, b"{\"status\":\"ok\"}\n");
+        ctx.send();
+        let mut buf = [0u8; 16];
+        {
+            let mut channel = AGENT_CHANNEL.lock();
+            let stream = channel.as_mut().unwrap();
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(b"{\"command\":\"ping\"}\n").unwrap();
+        }
+        assert_eq!(&buf, b"{\"status\":\"ok\"}\n");
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        let len = ctx.pkt.len() as usize;
+        assert_eq!(&ctx.pkt.buf().unwrap()[..len], b"{\"command\":\"ping\"}\n");
+
+        // Detaching the agent channel closes the connection.
+        *AGENT_CHANNEL.lock() = None;
+        ctx.notify_muxer();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_SHUTDOWN);
+    }
+
+    #[test]
+    fn test_muxer_reset() {
+        const LOCAL_PORT: u32 = 1026;
+        const MMDS_PORT: u32 = 1027;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("muxer_reset");
+        ctx.muxer.set_mmds_port(Some(MMDS_PORT));
+
+        // A host-initiated connection, and one that has yet to ask for a port.
+        let (mut local_stream, _) = ctx.local_connect(PEER_PORT + 1);
+        let mut pending_stream = UnixStream::connect(ctx.muxer.host_sock_path.clone()).unwrap();
+        ctx.notify_muxer();
+        assert_eq!(ctx.count_epoll_listeners(), (1, 1));
+
+        // A guest-initiated connection, with data yet to be read by the guest.
+        let mut listener = ctx.create_local_listener(LOCAL_PORT);
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        let mut peer_stream = listener.accept();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RESPONSE);
+        peer_stream.write_all(&[1, 2, 3, 4]).unwrap();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.has_pending_rx());
+
+        // A guest connection to the MMDS.
+        ctx.init_pkt(MMDS_PORT, PEER_PORT + 2, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        assert_eq!(ctx.muxer.conn_map.len(), 3);
+
+        ctx.muxer.reset();
+        assert!(ctx.muxer.conn_map.is_empty());
+        assert!(ctx.muxer.local_port_set.is_empty());
+        assert!(!ctx.muxer.has_pending_rx());
+        // Only the host socket and the datagram socket are still polled.
+        assert_eq!(ctx.muxer.listener_map.len(), 2);
+        assert_eq!(ctx.count_epoll_listeners(), (0, 0));
+
+        // The host peers see their connections closed.
+        let mut buf = [0u8; 4];
+        assert_eq!(peer_stream.read(&mut buf).unwrap(), 0);
+        assert_eq!(local_stream.read(&mut buf).unwrap(), 0);
+        assert_eq!(pending_stream.read(&mut buf).unwrap(), 0);
+
+        // New connections are still accepted.
+        ctx.local_connect(PEER_PORT);
+    }
+
+    #[test]
+    fn test_guest_dgram() {
+        const LOCAL_PORT: u32 = 1026;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("guest_dgram");
+        let host_path = format!("{}_{}", ctx.muxer.host_sock_path, LOCAL_PORT);
+        let host_sock = LocalDgramSock::new(&host_path);
+
+        // Datagrams are forwarded to the host, prefixed with the guest port.
+        let tx_bytes_count = METRICS.vsock.tx_bytes_count.count();
+        let device_tx_bytes_count = VSOCK_METRICS.device().tx_bytes_count.count();
+        let data = [1u8, 2, 3, 4];
+        ctx.init_data_pkt(LOCAL_PORT, PEER_PORT, &data)
+            .set_type(uapi::VSOCK_TYPE_DGRAM);
+        ctx.send();
+        let mut buf = [0u8; 32];
+        let len = host_sock.sock.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"1025\n\x01\x02\x03\x04");
+        assert!(METRICS.vsock.tx_bytes_count.count() >= tx_bytes_count + data.len());
+        // The bytes are also accounted to the vsock device.
+        assert!(
+            VSOCK_METRICS.device().tx_bytes_count.count() >= device_tx_bytes_count + data.len()
+        );
+        // No connection is created, and nothing is sent back to the guest.
+        assert!(ctx.muxer.conn_map.is_empty());
+        assert!(!ctx.muxer.has_pending_rx());
+
+        // Datagrams to a port nobody listens on, to another CID, or that aren't data, are dropped.
+        let dgrams_dropped = METRICS.vsock.dgrams_dropped.count();
+        ctx.init_data_pkt(LOCAL_PORT + 1, PEER_PORT, &data)
+            .set_type(uapi::VSOCK_TYPE_DGRAM);
+        ctx.send();
+        ctx.init_data_pkt(LOCAL_PORT, PEER_PORT, &data)
+            .set_type(uapi::VSOCK_TYPE_DGRAM)
+            .set_dst_cid(uapi::VSOCK_HOST_CID + 1);
+        ctx.send();
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST)
+            .set_type(uapi::VSOCK_TYPE_DGRAM);
+        ctx.send();
+        assert!(METRICS.vsock.dgrams_dropped.count() >= dgrams_dropped + 3);
+        assert!(host_sock.sock.recv(&mut buf).is_err());
+        assert!(!ctx.muxer.has_pending_rx());
+    }
+
+    #[test]
+    fn test_host_dgram() {
+        const LOCAL_PORT: u32 = 1026;
+        const PEER_PORT: u32 = 1025;
+
+        let mut ctx = MuxerTestContext::new("host_dgram");
+        let host_path = format!("{}_{}", ctx.muxer.host_sock_path, LOCAL_PORT);
+        let host_sock = LocalDgramSock::new(&host_path);
+        let dgram_path = ctx.muxer.dgram_sock_path().to_owned();
+
+        // Invalid datagrams are dropped: a missing port header, and a sender that isn't bound to
+        // a port path.
+        host_sock.sock.send_to(b"1025", &dgram_path).unwrap();
+        let anon_sock = UnixDatagram::unbound().unwrap();
+        anon_sock.send_to(b"1025\nabc", &dgram_path).unwrap();
+        // Valid datagrams are delivered in order.
+        host_sock.sock.send_to(b"1025\nabc", &dgram_path).unwrap();
+        host_sock.sock.send_to(b"1025\n", &dgram_path).unwrap();
+
+        ctx.notify_muxer();
+        // The datagram socket is no longer polled, until it is drained.
+        assert!(!ctx
+            .muxer
+            .listener_map
+            .values()
+            .any(|listener| matches!(listener, EpollListener::DgramSock)));
+        assert!(ctx.muxer.has_pending_rx());
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RW);
+        assert_eq!(ctx.pkt.type_(), uapi::VSOCK_TYPE_DGRAM);
+        assert_eq!(ctx.pkt.src_cid(), uapi::VSOCK_HOST_CID);
+        assert_eq!(ctx.pkt.dst_cid(), PEER_CID);
+        assert_eq!(ctx.pkt.src_port(), LOCAL_PORT);
+        assert_eq!(ctx.pkt.dst_port(), PEER_PORT);
+        assert_eq!(ctx.pkt.len(), 3);
+        assert_eq!(&ctx.pkt.buf().unwrap()[..3], b"abc");
+        assert_eq!(ctx.pkt.buf_alloc(), 0);
+        ctx.recv();
+        assert_eq!(ctx.pkt.len(), 0);
+
+        // Once drained, the socket is polled again.
+        assert!(ctx.muxer.recv_pkt(&mut ctx.pkt).is_err());
+        assert!(!ctx.muxer.has_pending_rx());
+        assert!(ctx
+            .muxer
+            .listener_map
+            .values()
+            .any(|listener| matches!(listener, EpollListener::DgramSock)));
+
+        // Datagrams that don't fit the guest buffer are dropped.
+        let dgrams_dropped = METRICS.vsock.dgrams_dropped.count();
+        let mut big_dgram = b"1025\n".to_vec();
+        big_dgram.resize(big_dgram.len() + ctx.pkt.buf().unwrap().len() + 1, 0);
+        host_sock.sock.send_to(&big_dgram, &dgram_path).unwrap();
+        ctx.notify_muxer();
+        assert!(ctx.muxer.recv_pkt(&mut ctx.pkt).is_err());
+        assert!(METRICS.vsock.dgrams_dropped.count() > dgrams_dropped);
+    }
+
+    #[test]
+    fn test_vsock_port_stats() {
+        // These ports aren't used by the other tests, which run in parallel and share the
+        // metrics.
+        const LOCAL_PORT: u32 = 5052;
+        const PEER_PORT: u32 = 5053;
+        const GUEST_SERVICE_PORT: u32 = 5054;
+        let port_stats = |ctx: &MuxerTestContext, port: u32| {
+            ctx.muxer
+                .stats()
+                .ports
+                .into_iter()
+                .find(|stats| stats.port == port)
+                .unwrap_or_default()
+        };
+
+        let mut ctx = MuxerTestContext::new("vsock_port_stats");
+
+        // A refused guest connection is accounted as dropped.
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        ctx.recv();
+        assert_eq!(ctx.pkt.op(), uapi::VSOCK_OP_RST);
+        assert_eq!(port_stats(&ctx, LOCAL_PORT).dropped_packets, 1);
+
+        // The traffic of a guest-initiated connection is accounted to the host port.
+        let mut listener = ctx.create_local_listener(LOCAL_PORT);
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_REQUEST);
+        ctx.send();
+        let mut stream = listener.accept();
+        ctx.recv();
+        ctx.init_data_pkt(LOCAL_PORT, PEER_PORT, &[1, 2, 3, 4]);
+        ctx.send();
+        stream.write_all(&[5, 6]).unwrap();
+        ctx.notify_muxer();
+        ctx.recv();
+        assert_eq!(
+            port_stats(&ctx, LOCAL_PORT),
+            VsockPortStats {
+                port: LOCAL_PORT,
+                rx_bytes: 2,
+                tx_bytes: 4,
+                // The connection response, and the data.
+                rx_packets: 2,
+                // Only the data. The connection request isn't forwarded to any connection.
+                tx_packets: 1,
+                active_connections: 1,
+                dropped_packets: 1,
+            }
+        );
+
+        // The traffic of a host-initiated connection is accounted to the guest port.
+        let (_local_stream, local_port) = ctx.local_connect(GUEST_SERVICE_PORT);
+        let stats = port_stats(&ctx, GUEST_SERVICE_PORT);
+        assert_eq!(stats.active_connections, 1);
+        assert_eq!(stats.rx_packets, 1);
+        assert_eq!(stats.tx_packets, 1);
+
+        // The open connections are listed.
+        assert_eq!(
+            ctx.muxer.stats().connections,
+            vec![
+                VsockConnectionInfo {
+                    host_port: LOCAL_PORT,
+                    guest_port: PEER_PORT,
+                    host_initiated: false,
+                },
+                VsockConnectionInfo {
+                    host_port: local_port,
+                    guest_port: GUEST_SERVICE_PORT,
+                    host_initiated: true,
+                },
+            ]
+        );
+
+        ctx.init_pkt(LOCAL_PORT, PEER_PORT, uapi::VSOCK_OP_RST);
+        ctx.send();
+        assert_eq!(port_stats(&ctx, LOCAL_PORT).active_connections, 0);
+        assert_eq!(ctx.muxer.stats().connections.len(), 1);
+    }
+
     #[test]
     fn test_muxer_rxq() {
         let mut ctx = MuxerTestContext::new("muxer_rxq");
@@ -0,0 +1,67 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+
+/// `AgentChannel` hands the connection of the guest agent over to the API thread.
+///
+/// When the guest agent connects to the agent vsock port, the muxer doesn't look for a host-side
+/// Unix socket listening on that port. Instead, it creates a Unix socket pair: one end backs the
+/// `VsockConnection`, as any host-side stream would, while the other end is attached to the
+/// global `AGENT_CHANNEL`. The API thread exchanges the agent commands over this end, blocking
+/// on it while the VMM thread keeps moving the bytes between the socket pair and the guest.
+use std::os::unix::net::UnixStream;
+use std::sync::{Mutex, MutexGuard};
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// The connection of the guest agent, shared between the muxer and the API thread.
+    pub static ref AGENT_CHANNEL: AgentChannel = AgentChannel::default();
+}
+
+/// The host end of the latest guest agent connection, if any.
+#[derive(Default)]
+pub struct AgentChannel {
+    stream: Mutex<Option<UnixStream>>,
+}
+
+impl AgentChannel {
+    /// Attach the host end of a new guest agent connection, which replaces the previous one.
+    pub fn attach(&self, stream: UnixStream) {
+        *self.lock() = Some(stream);
+    }
+
+    /// Lock the channel, for a command to be exchanged over the current connection. The caller
+    /// detaches the connection by setting it to `None`, e.g. when it is out of sync.
+    pub fn lock(&self) -> MutexGuard<Option<UnixStream>> {
+        self.stream.lock().expect("Poisoned lock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_agent_channel() {
+        let channel = AgentChannel::default();
+        assert!(channel.lock().is_none());
+
+        let (first, _first_peer) = UnixStream::pair().unwrap();
+        channel.attach(first);
+        let (second, mut second_peer) = UnixStream::pair().unwrap();
+        channel.attach(second);
+
+        // The latest connection is the one in use.
+        channel.lock().as_mut().unwrap().write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        second_peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // Detaching the connection closes it.
+        *channel.lock() = None;
+        assert_eq!(second_peer.read(&mut buf).unwrap(), 0);
+    }
+}
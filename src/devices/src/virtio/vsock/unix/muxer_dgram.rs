@@ -0,0 +1,194 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+
+/// `MuxerDgramSock` forwards vsock datagrams between the guest and host-side Unix datagram
+/// sockets.
+///
+/// Datagrams don't belong to any connection, so there is no state to keep for them. The muxer
+/// owns a single Unix datagram socket, bound at "<uds_path>_dgram":
+/// - a datagram sent by the guest to host port `P` is sent on from this socket to the Unix
+///   datagram socket expected to be bound at "<uds_path>_<P>";
+/// - a datagram sent to this socket by a host peer is delivered to the guest.
+///
+/// Unix socket addresses have no room for the guest port, so every host-side datagram starts
+/// with a "<guest port>\n" header line. For datagrams going to the host, it holds the source port
+/// of the guest, for the host peer to reply to. For datagrams going to the guest, it holds their
+/// destination port. Their source port is taken from the address of the host peer, which must be
+/// bound at "<uds_path>_<port>". Datagrams that don't follow these rules are dropped.
+use std::io::ErrorKind;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+use logger::{debug, IncMetric};
+
+use super::super::defs::{uapi, MAX_PKT_BUF_SIZE};
+use super::super::packet::VsockPacket;
+use super::super::{Result as VsockResult, VsockError, VSOCK_METRICS};
+use super::{update_port_metrics, Error, Result};
+
+/// Maximum length of the "<guest port>\n" header line.
+const MAX_HDR_LEN: usize = 11;
+
+/// The muxer's Unix datagram socket.
+pub struct MuxerDgramSock {
+    sock: UnixDatagram,
+    /// The file system path the socket is bound at.
+    path: String,
+    /// The file system path of the host-side Unix socket, used to build the paths of the host
+    /// peers. I.e. "<this path>_<port number>".
+    host_sock_path: String,
+    /// Holds the datagrams read from the socket.
+    rx_buf: Vec<u8>,
+}
+
+impl MuxerDgramSock {
+    /// Bind the datagram socket of the muxer listening at `host_sock_path`.
+    pub fn new(host_sock_path: &str) -> Result<Self> {
+        let path = format!("{}_dgram", host_sock_path);
+        let sock = UnixDatagram::bind(&path)
+            .and_then(|sock| sock.set_nonblocking(true).map(|_| sock))
+            .map_err(Error::UnixBind)?;
+
+        Ok(Self {
+            sock,
+            path,
+            host_sock_path: host_sock_path.to_owned(),
+            // One more byte than the largest valid datagram, to detect the ones that don't fit.
+            rx_buf: vec![0u8; MAX_HDR_LEN + MAX_PKT_BUF_SIZE + 1],
+        })
+    }
+
+    /// Get the file system path the socket is bound at.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Send `data`, coming from `guest_port`, to the host peer listening on `host_port`.
+    pub fn send(&self, host_port: u32, guest_port: u32, data: &[u8]) {
+        let mut dgram = format!("{}\n", guest_port).into_bytes();
+        dgram.extend_from_slice(data);
+
+        let peer_path = format!("{}_{}", self.host_sock_path, host_port);
+        match self.sock.send_to(&dgram, &peer_path) {
+            Ok(_) => {
+                VSOCK_METRICS.update(|metrics| {
+                    metrics.tx_packets_count.inc();
+                    metrics.tx_bytes_count.add(data.len());
+                });
+                update_port_metrics(host_port, |metrics| {
+                    metrics.tx_packets_count.inc();
+                    metrics.tx_bytes_count.add(data.len());
+                });
+            }
+            Err(err) => {
+                debug!("vsock: unable to send datagram to {}: {}", peer_path, err);
+                VSOCK_METRICS.update(|metrics| metrics.dgrams_dropped.inc());
+                update_port_metrics(host_port, |metrics| metrics.dropped_packets.inc());
+            }
+        }
+    }
+
+    /// Fill in `pkt` with the next valid datagram sent by a host peer to the guest with CID
+    /// `cid`.
+    ///
+    /// Returns:
+    /// - `Ok(())`: `pkt` has been successfully filled in; or
+    /// - `Err(VsockError::NoData)`: there are no more datagrams to read.
+    pub fn recv(&mut self, pkt: &mut VsockPacket, cid: u64) -> VsockResult<()> {
+        let buf_len = pkt.buf().ok_or(VsockError::PktBufMissing)?.len();
+
+        loop {
+            let (len, addr) = match self.sock.recv_from(&mut self.rx_buf) {
+                Ok(res) => res,
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                    return Err(VsockError::NoData)
+                }
+                Err(err) => {
+                    debug!("vsock: unable to read datagram: {}", err);
+                    VSOCK_METRICS.update(|metrics| metrics.dgrams_dropped.inc());
+                    return Err(VsockError::NoData);
+                }
+            };
+
+            let parsed = self
+                .parse_src_port(&addr)
+                .and_then(|src_port| Self::parse_hdr(&self.rx_buf[..len]).map(|h| (src_port, h)))
+                .filter(|(_, (_, hdr_len))| len - hdr_len <= buf_len);
+            let (src_port, (dst_port, hdr_len)) = match parsed {
+                Some(parsed) => parsed,
+                None => {
+                    debug!("vsock: dropping invalid datagram from {:?}", addr);
+                    VSOCK_METRICS.update(|metrics| metrics.dgrams_dropped.inc());
+                    continue;
+                }
+            };
+
+            let data = &self.rx_buf[hdr_len..len];
+            // It's safe to unwrap, since the buffer has already been checked above.
+            pkt.buf_mut().unwrap()[..data.len()].copy_from_slice(data);
+            pkt.set_op(uapi::VSOCK_OP_RW)
+                .set_type(uapi::VSOCK_TYPE_DGRAM)
+                .set_src_cid(uapi::VSOCK_HOST_CID)
+                .set_dst_cid(cid)
+                .set_src_port(src_port)
+                .set_dst_port(dst_port)
+                .set_len(data.len() as u32)
+                .set_flags(0)
+                .set_buf_alloc(0)
+                .set_fwd_cnt(0);
+            VSOCK_METRICS.update(|metrics| {
+                metrics.rx_packets_count.inc();
+                metrics.rx_bytes_count.add(data.len());
+            });
+            update_port_metrics(src_port, |metrics| {
+                metrics.rx_packets_count.inc();
+                metrics.rx_bytes_count.add(data.len());
+            });
+            return Ok(());
+        }
+    }
+
+    /// Get the host port of a peer bound at "<host_sock_path>_<port>".
+    fn parse_src_port(&self, addr: &SocketAddr) -> Option<u32> {
+        addr.as_pathname()?
+            .to_str()?
+            .strip_prefix(self.host_sock_path.as_str())?
+            .strip_prefix('_')?
+            .parse()
+            .ok()
+    }
+
+    /// Parse the "<guest port>\n" header line of `dgram`, returning the port and the length of
+    /// the line.
+    fn parse_hdr(dgram: &[u8]) -> Option<(u32, usize)> {
+        let eol = dgram.iter().take(MAX_HDR_LEN).position(|&b| b == b'\n')?;
+        let port = std::str::from_utf8(&dgram[..eol]).ok()?.parse().ok()?;
+        Some((port, eol + 1))
+    }
+}
+
+impl AsRawFd for MuxerDgramSock {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hdr() {
+        assert_eq!(MuxerDgramSock::parse_hdr(b"52\ndata"), Some((52, 3)));
+        assert_eq!(
+            MuxerDgramSock::parse_hdr(b"4294967295\n"),
+            Some((u32::MAX, 11))
+        );
+        assert_eq!(MuxerDgramSock::parse_hdr(b"4294967296\n"), None);
+        assert_eq!(MuxerDgramSock::parse_hdr(b"00000000052\n"), None);
+        assert_eq!(MuxerDgramSock::parse_hdr(b"52"), None);
+        assert_eq!(MuxerDgramSock::parse_hdr(b"port\n"), None);
+        assert_eq!(MuxerDgramSock::parse_hdr(b""), None);
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+
+/// `MuxerMmdsStream` is the HTTP server that answers the MMDS requests made by the guest over
+/// vsock.
+///
+/// When the guest connects to the MMDS vsock port, the muxer doesn't look for a host-side Unix
+/// socket listening on that port. Instead, it creates a Unix socket pair: one end backs the
+/// `VsockConnection`, as any host-side stream would, while the other end is wrapped in a
+/// `MuxerMmdsStream` and registered under the muxer's nested epoll FD. The requests read from
+/// this end are served from the same data store as the MMDS network stack.
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+use logger::warn;
+use micro_http::{Body, ConnectionError, HttpConnection, Response, StatusCode, Version};
+use utils::epoll::EventSet;
+
+/// A handle to a stream, which can be shared without duplicating its FD.
+#[derive(Clone)]
+struct SharedStream(Arc<UnixStream>);
+
+impl Read for SharedStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        (&*self.0).read(buf)
+    }
+}
+
+impl Write for SharedStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        (&*self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        (&*self.0).flush()
+    }
+}
+
+/// The server end of a guest connection to the MMDS vsock port.
+pub struct MuxerMmdsStream {
+    /// Parses the requests read from the stream.
+    conn: HttpConnection<SharedStream>,
+    /// The stream the responses are written to. `HttpConnection` cannot be used for writing,
+    /// since it treats a full socket buffer as a closed connection.
+    stream: SharedStream,
+    /// The bytes of the responses that have yet to be written.
+    tx_buf: Vec<u8>,
+    /// No more requests will be read. The stream is done with once `tx_buf` is flushed.
+    rx_closed: bool,
+    /// The stream can no longer be written to.
+    tx_closed: bool,
+}
+
+impl MuxerMmdsStream {
+    /// Wraps `stream`, which must be non-blocking.
+    pub fn new(stream: UnixStream) -> Self {
+        let stream = SharedStream(Arc::new(stream));
+        Self {
+            conn: HttpConnection::new(stream.clone()),
+            stream,
+            tx_buf: Vec::new(),
+            rx_closed: false,
+            tx_closed: false,
+        }
+    }
+
+    /// Check if the stream is done with, in which case it can be dropped. Dropping it lets the
+    /// guest know that the connection was closed.
+    pub fn is_closed(&self) -> bool {
+        self.tx_closed || (self.rx_closed && self.tx_buf.is_empty())
+    }
+
+    /// Get the epoll events the stream is interested in. No new requests are read until the
+    /// previous responses are written, so that a guest which doesn't read them cannot make us
+    /// buffer an unbounded amount of data.
+    pub fn get_polled_evset(&self) -> EventSet {
+        if self.is_closed() {
+            EventSet::empty()
+        } else if !self.tx_buf.is_empty() {
+            EventSet::OUT
+        } else {
+            EventSet::IN
+        }
+    }
+
+    /// Handle the epoll events in `evset`.
+    pub fn notify(&mut self, evset: EventSet) {
+        if evset.contains(EventSet::IN) && !self.rx_closed {
+            self.recv_requests();
+        }
+        if !self.tx_buf.is_empty() {
+            self.flush();
+        }
+    }
+
+    /// Read the available bytes, and queue up the responses to the requests they complete.
+    fn recv_requests(&mut self) {
+        let res = self.conn.try_read();
+        while let Some(request) = self.conn.pop_parsed_request() {
+            self.push_response(mmds::convert_to_response(request));
+        }
+
+        match res {
+            Ok(()) => (),
+            Err(ConnectionError::StreamError(ref err)) if err.kind() == ErrorKind::WouldBlock => (),
+            Err(ConnectionError::ParseError(err)) => {
+                // There is no telling where the next request starts, so this one is the last.
+                let mut response = Response::new(Version::Http11, StatusCode::BadRequest);
+                response.set_body(Body::new(err.to_string()));
+                self.push_response(response);
+                self.rx_closed = true;
+            }
+            Err(_) => self.rx_closed = true,
+        }
+    }
+
+    fn push_response(&mut self, response: Response) {
+        response
+            .write_all(&mut self.tx_buf)
+            .unwrap_or_else(|err| warn!("vsock: unable to serialize MMDS response: {}", err));
+    }
+
+    /// Write as much of `tx_buf` as the stream accepts.
+    fn flush(&mut self) {
+        while !self.tx_buf.is_empty() {
+            match self.stream.write(&self.tx_buf) {
+                Ok(0) => {
+                    self.tx_closed = true;
+                    break;
+                }
+                Ok(written) => {
+                    self.tx_buf.drain(..written);
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warn!("vsock: unable to write MMDS response: {}", err);
+                    self.tx_closed = true;
+                    break;
+                }
+            }
+        }
+        if self.tx_closed {
+            self.tx_buf.clear();
+        }
+    }
+}
+
+impl AsRawFd for MuxerMmdsStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.0.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream_pair() -> (MuxerMmdsStream, UnixStream) {
+        let (server, client) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        client.set_nonblocking(true).unwrap();
+        (MuxerMmdsStream::new(server), client)
+    }
+
+    fn read_all(client: &mut UnixStream) -> String {
+        let mut buf = vec![0u8; 4096];
+        let len = client.read(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_mmds_stream() {
+        let (mut stream, mut client) = stream_pair();
+        assert_eq!(stream.get_polled_evset(), EventSet::IN);
+
+        // Requests are answered as soon as they are complete.
+        client.write_all(b"GET /foo HTTP/1.1\r\n").unwrap();
+        stream.notify(EventSet::IN);
+        assert!(client.read(&mut [0u8; 1]).is_err());
+        client.write_all(b"\r\n").unwrap();
+        stream.notify(EventSet::IN);
+        assert!(read_all(&mut client).starts_with("HTTP/1.1 404"));
+        assert!(!stream.is_closed());
+
+        // The request methods are checked the same way as for the MMDS network stack.
+        client.write_all(b"PATCH / HTTP/1.1\r\n\r\n").unwrap();
+        stream.notify(EventSet::IN);
+        assert!(read_all(&mut client).starts_with("HTTP/1.1 405"));
+
+        // A malformed request is answered, and ends the stream.
+        client.write_all(b"GET / HTTP/3.0\r\n\r\n").unwrap();
+        stream.notify(EventSet::IN);
+        assert!(read_all(&mut client).starts_with("HTTP/1.1 400"));
+        assert!(stream.is_closed());
+        assert_eq!(stream.get_polled_evset(), EventSet::empty());
+    }
+
+    #[test]
+    fn test_mmds_stream_close() {
+        // The pending responses are still written after the guest stops sending.
+        let (mut stream, mut client) = stream_pair();
+        client.write_all(b"GET /foo HTTP/1.1\r\n\r\n").unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        stream.notify(EventSet::IN);
+        stream.notify(EventSet::IN);
+        assert!(stream.is_closed());
+        assert!(read_all(&mut client).starts_with("HTTP/1.1 404"));
+
+        // A stream that can no longer be written to is done with.
+        let (mut stream, client) = stream_pair();
+        drop(client);
+        stream.push_response(Response::new(Version::Http11, StatusCode::OK));
+        stream.notify(EventSet::OUT);
+        assert!(stream.is_closed());
+    }
+}
@@ -66,10 +66,10 @@ impl MuxerRxQ {
     ///
     /// A push will fail when:
     /// - trying to push a connection key onto an out-of-sync, or full queue; or
-    /// - trying to push an RST onto a queue already full of RSTs.
-    /// RSTs take precedence over connections, because connections can always be queried for
-    /// pending RX data later. Aside from this queue, there is no other storage for RSTs, so
-    /// failing to push one means that we have to drop the packet.
+    /// - trying to push an RST, or a datagram indication, onto a queue already full of those.
+    /// RSTs and datagrams take precedence over connections, because connections can always be
+    /// queried for pending RX data later. Aside from this queue, there is no other storage for
+    /// RSTs, so failing to push one means that we have to drop the packet.
     ///
     /// Returns:
     /// - `true` if the new item has been successfully queued; or
@@ -82,7 +82,7 @@ impl MuxerRxQ {
         }
 
         match rx {
-            MuxerRx::RstPkt { .. } => {
+            MuxerRx::RstPkt { .. } | MuxerRx::DgramRx => {
                 // If we just failed to push an RST packet, we'll look through the queue, trying to
                 // find a connection key that we could evict. This way, the queue does lose sync,
                 // but we don't drop any packets.
@@ -0,0 +1,332 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use logger::{error, warn, IncMetric};
+use utils::byte_order;
+use utils::eventfd::EventFd;
+use virtio_gen::virtio_ring::{VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC};
+use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap};
+
+use super::super::defs::{self, uapi};
+use super::super::VSOCK_METRICS;
+use super::{Error, Result, VhostVsockHandle};
+use crate::virtio::{
+    ActivateError, ActivateResult, DeviceState, Queue, VirtioDevice, VIRTIO_MMIO_INT_VRING,
+};
+
+// The virtio features which can be offered to the guest, if the kernel supports them. The
+// kernel handles the virtqueues by itself, so these only describe its abilities.
+const SUPPORTED_FEATURES: u64 = (1u64 << uapi::VIRTIO_F_VERSION_1)
+    | (1u64 << VIRTIO_RING_F_EVENT_IDX)
+    | (1u64 << VIRTIO_RING_F_INDIRECT_DESC);
+
+// The kernel serves the RX and TX queues. The event queue is left to the device, which never
+// uses it, as there is no transport reset to report.
+const NUM_VHOST_QUEUES: usize = 2;
+
+/// A virtio-vsock device, whose RX and TX virtqueues are handed over to the `vhost-vsock`
+/// kernel device.
+pub struct VhostVsock {
+    // Virtio fields.
+    pub(crate) avail_features: u64,
+    pub(crate) acked_features: u64,
+    pub(crate) activate_evt: EventFd,
+
+    // Transport related fields.
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) interrupt_status: Arc<AtomicUsize>,
+    pub(crate) interrupt_evt: EventFd,
+    // Also given to the kernel as kick eventfds, since they are signaled by KVM on queue
+    // notifications.
+    pub(crate) queue_evts: Vec<EventFd>,
+    pub(crate) device_state: DeviceState,
+
+    // Implementation specific fields.
+    cid: u64,
+    // Signaled by the kernel when it uses buffers from the matching queue.
+    pub(crate) call_evts: Vec<EventFd>,
+    handle: VhostVsockHandle,
+}
+
+impl VhostVsock {
+    /// Creates a vsock device giving the guest the `cid` context identifier, whose sockets are
+    /// served by the kernel device behind `handle`.
+    pub fn new(cid: u64, handle: VhostVsockHandle) -> Result<VhostVsock> {
+        handle.set_owner()?;
+        let backend_features = handle.get_features()?;
+        // Setting the CID right away reports a CID already used by another guest when the
+        // device is configured, rather than when the guest driver activates it.
+        handle.set_guest_cid(cid)?;
+
+        Self::with_features(cid, handle, backend_features)
+    }
+
+    fn with_features(
+        cid: u64,
+        handle: VhostVsockHandle,
+        backend_features: u64,
+    ) -> Result<VhostVsock> {
+        let mut queue_evts = Vec::with_capacity(defs::NUM_QUEUES);
+        for _ in defs::QUEUE_SIZES.iter() {
+            queue_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+        let mut call_evts = Vec::with_capacity(NUM_VHOST_QUEUES);
+        for _ in 0..NUM_VHOST_QUEUES {
+            call_evts.push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        }
+        let queues = defs::QUEUE_SIZES.iter().map(|&s| Queue::new(s)).collect();
+
+        Ok(VhostVsock {
+            avail_features: (backend_features & SUPPORTED_FEATURES)
+                | 1u64 << uapi::VIRTIO_F_VERSION_1,
+            acked_features: 0u64,
+            activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queues,
+            interrupt_status: Arc::new(AtomicUsize::new(0)),
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
+            queue_evts,
+            device_state: DeviceState::Inactive,
+            cid,
+            call_evts,
+            handle,
+        })
+    }
+
+    /// Provides the ID of this device.
+    pub fn id(&self) -> &str {
+        defs::VSOCK_DEV_ID
+    }
+
+    /// Provides the context identifier of the guest.
+    pub fn cid(&self) -> u64 {
+        self.cid
+    }
+
+    // Hands the guest memory and the RX and TX virtqueues over to the kernel, and starts it.
+    fn setup_backend(&mut self, mem: &GuestMemoryMmap) -> Result<()> {
+        let host_addr = |addr: GuestAddress| {
+            mem.get_host_address(addr)
+                .map(|addr| addr as u64)
+                .map_err(Error::GuestMemory)
+        };
+
+        let handle = &self.handle;
+        handle.set_features(self.acked_features)?;
+        handle.set_mem_table(mem)?;
+        for (index, queue) in self.queues.iter().enumerate().take(NUM_VHOST_QUEUES) {
+            handle.set_vring_num(index, queue.actual_size())?;
+            handle.set_vring_addr(
+                index,
+                host_addr(queue.desc_table)?,
+                host_addr(queue.used_ring)?,
+                host_addr(queue.avail_ring)?,
+            )?;
+            handle.set_vring_base(index, queue.next_avail.0)?;
+            handle.set_vring_call(index, self.call_evts[index].as_raw_fd())?;
+            handle.set_vring_kick(index, self.queue_evts[index].as_raw_fd())?;
+        }
+
+        handle.set_running(true)
+    }
+
+    pub(crate) fn process_call_event(&mut self, index: usize) -> Result<()> {
+        self.call_evts[index].read().map_err(Error::EventFd)?;
+        VSOCK_METRICS.update(|metrics| metrics.vhost_notifications.inc());
+        self.signal_used_queue()
+    }
+
+    fn signal_used_queue(&self) -> Result<()> {
+        self.interrupt_status
+            .fetch_or(VIRTIO_MMIO_INT_VRING as usize, Ordering::SeqCst);
+        self.interrupt_evt
+            .write(1)
+            .map_err(Error::FailedSignalingUsedQueue)
+    }
+}
+
+impl VirtioDevice for VhostVsock {
+    fn device_type(&self) -> u32 {
+        uapi::VIRTIO_ID_VSOCK
+    }
+
+    fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    fn queues_mut(&mut self) -> &mut [Queue] {
+        &mut self.queues
+    }
+
+    fn queue_events(&self) -> &[EventFd] {
+        &self.queue_evts
+    }
+
+    fn interrupt_evt(&self) -> &EventFd {
+        &self.interrupt_evt
+    }
+
+    fn interrupt_status(&self) -> Arc<AtomicUsize> {
+        self.interrupt_status.clone()
+    }
+
+    fn avail_features(&self) -> u64 {
+        self.avail_features
+    }
+
+    fn acked_features(&self) -> u64 {
+        self.acked_features
+    }
+
+    fn set_acked_features(&mut self, acked_features: u64) {
+        self.acked_features = acked_features;
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        match offset {
+            0 if data.len() == 8 => byte_order::write_le_u64(data, self.cid),
+            0 if data.len() == 4 => byte_order::write_le_u32(data, (self.cid & 0xffff_ffff) as u32),
+            4 if data.len() == 4 => {
+                byte_order::write_le_u32(data, ((self.cid >> 32) & 0xffff_ffff) as u32)
+            }
+            _ => {
+                VSOCK_METRICS.update(|metrics| metrics.cfg_fails.inc());
+                warn!(
+                    "vhost-vsock: received invalid read request of {} bytes at offset {}",
+                    data.len(),
+                    offset
+                )
+            }
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        VSOCK_METRICS.update(|metrics| metrics.cfg_fails.inc());
+        warn!(
+            "vhost-vsock: guest driver attempted to write device config (offset={:x}, len={:x})",
+            offset,
+            data.len()
+        );
+    }
+
+    fn is_activated(&self) -> bool {
+        match self.device_state {
+            DeviceState::Inactive => false,
+            DeviceState::Activated(_) => true,
+        }
+    }
+
+    fn activate(&mut self, mem: GuestMemoryMmap) -> ActivateResult {
+        if let Err(e) = self.setup_backend(&mem) {
+            error!("vhost-vsock: Cannot set up the kernel backend: {:?}", e);
+            VSOCK_METRICS.update(|metrics| metrics.activate_fails.inc());
+            return Err(ActivateError::BadActivate);
+        }
+        if self.activate_evt.write(1).is_err() {
+            error!("vhost-vsock: Cannot write to activate_evt");
+            VSOCK_METRICS.update(|metrics| metrics.activate_fails.inc());
+            return Err(ActivateError::BadActivate);
+        }
+        self.device_state = DeviceState::Activated(mem);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::check_metric_after_block;
+    use crate::virtio::test_utils::{default_mem, VirtQueue};
+    use logger::METRICS;
+    use utils::tempfile::TempFile;
+
+    // Creates a device on top of a regular file, which fails every vhost ioctl.
+    pub(crate) fn default_vhost_vsock(backend_features: u64) -> VhostVsock {
+        let file = TempFile::new().unwrap();
+        let handle = VhostVsockHandle::open(file.as_path()).unwrap();
+        VhostVsock::with_features(0x1_0000_0003, handle, backend_features).unwrap()
+    }
+
+    #[test]
+    fn test_new() {
+        let file = TempFile::new().unwrap();
+        let handle = VhostVsockHandle::open(file.as_path()).unwrap();
+        assert!(matches!(VhostVsock::new(3, handle), Err(Error::Ioctl(_))));
+
+        let vhost_vsock = default_vhost_vsock(1u64 << VIRTIO_RING_F_EVENT_IDX | 1u64 << 35);
+        assert_eq!(vhost_vsock.device_type(), uapi::VIRTIO_ID_VSOCK);
+        assert_eq!(vhost_vsock.id(), defs::VSOCK_DEV_ID);
+        assert_eq!(vhost_vsock.cid(), 0x1_0000_0003);
+        // Only the supported features are offered to the guest.
+        assert_eq!(
+            vhost_vsock.avail_features(),
+            1u64 << uapi::VIRTIO_F_VERSION_1 | 1u64 << VIRTIO_RING_F_EVENT_IDX
+        );
+        assert_eq!(vhost_vsock.queues().len(), defs::NUM_QUEUES);
+        assert_eq!(vhost_vsock.queue_events().len(), defs::NUM_QUEUES);
+        assert!(!vhost_vsock.is_activated());
+    }
+
+    #[test]
+    fn test_virtio_config() {
+        let mut vhost_vsock = default_vhost_vsock(0);
+
+        let mut data = [0u8; 8];
+        vhost_vsock.read_config(0, &mut data);
+        assert_eq!(byte_order::read_le_u64(&data), 0x1_0000_0003);
+        vhost_vsock.read_config(0, &mut data[..4]);
+        assert_eq!(byte_order::read_le_u32(&data[..4]), 3);
+        vhost_vsock.read_config(4, &mut data[..4]);
+        assert_eq!(byte_order::read_le_u32(&data[..4]), 1);
+
+        check_metric_after_block!(
+            METRICS.vsock.cfg_fails,
+            1,
+            vhost_vsock.read_config(2, &mut data)
+        );
+        check_metric_after_block!(
+            METRICS.vsock.cfg_fails,
+            1,
+            vhost_vsock.write_config(0, &data)
+        );
+    }
+
+    #[test]
+    fn test_activate() {
+        let mut vhost_vsock = default_vhost_vsock(0);
+        let mem = default_mem();
+        let queue = VirtQueue::new(GuestAddress(0), &mem, 16);
+        vhost_vsock.queues[0] = queue.create_queue();
+
+        check_metric_after_block!(
+            METRICS.vsock.activate_fails,
+            1,
+            assert!(vhost_vsock.activate(mem).is_err())
+        );
+        assert!(!vhost_vsock.is_activated());
+    }
+
+    #[test]
+    fn test_process_call_event() {
+        let mut vhost_vsock = default_vhost_vsock(0);
+
+        vhost_vsock.call_evts[1].write(1).unwrap();
+        check_metric_after_block!(
+            METRICS.vsock.vhost_notifications,
+            1,
+            vhost_vsock.process_call_event(1).unwrap()
+        );
+        assert_eq!(
+            vhost_vsock.interrupt_status().load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_VRING as usize
+        );
+        assert_eq!(vhost_vsock.interrupt_evt().read().unwrap(), 1);
+
+        // There is no pending notification anymore.
+        assert!(vhost_vsock.process_call_event(1).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::os::unix::io::AsRawFd;
+
+use logger::{debug, error, warn};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+
+use super::VhostVsock;
+use crate::report_vhost_vsock_event_fail;
+use crate::virtio::VirtioDevice;
+
+impl VhostVsock {
+    fn process_activate_event(&self, event_manager: &mut EventManager) {
+        debug!("vhost-vsock: activate event");
+        if let Err(e) = self.activate_evt.read() {
+            error!("Failed to consume vhost-vsock activate event: {:?}", e);
+        }
+        let activate_fd = self.activate_evt.as_raw_fd();
+        // The subscriber must exist as we previously registered activate_evt via
+        // `interest_list()`.
+        let self_subscriber = match event_manager.subscriber(activate_fd) {
+            Ok(subscriber) => subscriber,
+            Err(e) => {
+                error!("Failed to process vhost-vsock activate evt: {:?}", e);
+                return;
+            }
+        };
+
+        // Interest list changes when the device is activated.
+        let interest_list = self.interest_list();
+        for event in interest_list {
+            event_manager
+                .register(event.data() as i32, event, self_subscriber.clone())
+                .unwrap_or_else(|e| {
+                    error!("Failed to register vhost-vsock events: {:?}", e);
+                });
+        }
+
+        event_manager.unregister(activate_fd).unwrap_or_else(|e| {
+            error!("Failed to unregister vhost-vsock activate evt: {:?}", e);
+        });
+    }
+}
+
+impl Subscriber for VhostVsock {
+    fn process(&mut self, event: &EpollEvent, evmgr: &mut EventManager) {
+        let source = event.fd();
+        let event_set = event.event_set();
+        let supported_events = EventSet::IN;
+
+        if !supported_events.contains(event_set) {
+            warn!(
+                "Received unknown event: {:?} from source: {:?}",
+                event_set, source
+            );
+            return;
+        }
+
+        if self.is_activated() {
+            let activate_fd = self.activate_evt.as_raw_fd();
+
+            match self
+                .call_evts
+                .iter()
+                .position(|evt| evt.as_raw_fd() == source)
+            {
+                Some(index) => self
+                    .process_call_event(index)
+                    .unwrap_or_else(report_vhost_vsock_event_fail),
+                None if source == activate_fd => self.process_activate_event(evmgr),
+                None => warn!("vhost-vsock: Spurious event received: {:?}", source),
+            }
+        } else {
+            warn!(
+                "vhost-vsock: The device is not yet activated. Spurious event received: {:?}",
+                source
+            );
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        // This function can be called during different points in the device lifetime:
+        //  - shortly after device creation,
+        //  - on device activation (is-activated already true at this point).
+        if self.is_activated() {
+            self.call_evts
+                .iter()
+                .map(|evt| EpollEvent::new(EventSet::IN, evt.as_raw_fd() as u64))
+                .collect()
+        } else {
+            vec![EpollEvent::new(
+                EventSet::IN,
+                self.activate_evt.as_raw_fd() as u64,
+            )]
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::virtio::test_utils::default_mem;
+    use crate::virtio::vsock::vhost::device::tests::default_vhost_vsock;
+    use crate::virtio::{DeviceState, VIRTIO_MMIO_INT_VRING};
+
+    #[test]
+    fn test_event_handler() {
+        let mut event_manager = EventManager::new().unwrap();
+        let vhost_vsock = Arc::new(Mutex::new(default_vhost_vsock(0)));
+        event_manager.add_subscriber(vhost_vsock.clone()).unwrap();
+
+        // The device isn't activated, so the kernel notifications are not processed.
+        vhost_vsock.lock().unwrap().call_evts[1].write(1).unwrap();
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 0);
+
+        // Mark the device as activated, as the kernel device can't be set up in this test, and
+        // trigger the activation event.
+        {
+            let mut vhost_vsock = vhost_vsock.lock().unwrap();
+            vhost_vsock.device_state = DeviceState::Activated(default_mem());
+            vhost_vsock.activate_evt.write(1).unwrap();
+        }
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+
+        // The pending kernel notification is now forwarded to the guest.
+        let ev_count = event_manager.run_with_timeout(50).unwrap();
+        assert_eq!(ev_count, 1);
+        let vhost_vsock = vhost_vsock.lock().unwrap();
+        assert_eq!(
+            vhost_vsock.interrupt_status().load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_VRING as usize
+        );
+        assert_eq!(vhost_vsock.interrupt_evt().read().unwrap(), 1);
+    }
+}
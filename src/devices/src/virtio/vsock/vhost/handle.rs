@@ -0,0 +1,290 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A handle on the `vhost-vsock` kernel device, implementing the ioctls needed to hand the
+//! virtqueues of the vsock device over to the host kernel.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::raw::{c_int, c_uint};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+use utils::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref};
+use utils::{ioctl_expr, ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr};
+use vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion, MemoryRegionAddress};
+
+use super::{Error, Result};
+
+/// Path of the `vhost-vsock` kernel device.
+pub const VHOST_VSOCK_PATH: &str = "/dev/vhost-vsock";
+
+// See include/uapi/linux/vhost.h in the kernel code.
+const VHOST_VIRTIO: c_uint = 0xAF;
+ioctl_ior_nr!(VHOST_GET_FEATURES, VHOST_VIRTIO, 0x00, u64);
+ioctl_iow_nr!(VHOST_SET_FEATURES, VHOST_VIRTIO, 0x00, u64);
+ioctl_io_nr!(VHOST_SET_OWNER, VHOST_VIRTIO, 0x01);
+ioctl_iow_nr!(VHOST_SET_MEM_TABLE, VHOST_VIRTIO, 0x03, MemoryTableHeader);
+ioctl_iow_nr!(VHOST_SET_VRING_NUM, VHOST_VIRTIO, 0x10, VringState);
+ioctl_iow_nr!(VHOST_SET_VRING_ADDR, VHOST_VIRTIO, 0x11, VringAddr);
+ioctl_iow_nr!(VHOST_SET_VRING_BASE, VHOST_VIRTIO, 0x12, VringState);
+ioctl_iow_nr!(VHOST_SET_VRING_KICK, VHOST_VIRTIO, 0x20, VringFile);
+ioctl_iow_nr!(VHOST_SET_VRING_CALL, VHOST_VIRTIO, 0x21, VringFile);
+ioctl_iow_nr!(VHOST_VSOCK_SET_GUEST_CID, VHOST_VIRTIO, 0x60, u64);
+ioctl_iow_nr!(VHOST_VSOCK_SET_RUNNING, VHOST_VIRTIO, 0x61, c_int);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct VringState {
+    index: u32,
+    num: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct VringAddr {
+    index: u32,
+    flags: u32,
+    descriptor: u64,
+    used: u64,
+    available: u64,
+    log: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct VringFile {
+    index: u32,
+    fd: RawFd,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct MemoryTableHeader {
+    num_regions: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct MemoryRegion {
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    flags_padding: u64,
+}
+
+// Converts the return value of an ioctl into a result.
+fn check_ioctl(ret: c_int) -> Result<()> {
+    if ret < 0 {
+        return Err(Error::Ioctl(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// An open `vhost-vsock` kernel device. The kernel stops serving the guest once it is dropped.
+pub struct VhostVsockHandle {
+    file: File,
+}
+
+impl VhostVsockHandle {
+    /// Opens the `vhost-vsock` kernel device found at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC | libc::O_NONBLOCK)
+            .open(path)
+            .map(|file| VhostVsockHandle { file })
+            .map_err(Error::Open)
+    }
+
+    /// Claims the kernel device for the calling process.
+    pub fn set_owner(&self) -> Result<()> {
+        // Safe because the ioctl doesn't take any argument, and we check the return.
+        check_ioctl(unsafe { ioctl(&self.file, VHOST_SET_OWNER()) })
+    }
+
+    /// Gets the virtio features supported by the kernel.
+    pub fn get_features(&self) -> Result<u64> {
+        let mut features = 0u64;
+        // Safe because the kernel only writes a `u64`, and we check the return.
+        check_ioctl(unsafe {
+            ioctl_with_mut_ref(&self.file, VHOST_GET_FEATURES(), &mut features)
+        })?;
+        Ok(features)
+    }
+
+    /// Sets the virtio features acked by the driver.
+    pub fn set_features(&self, features: u64) -> Result<()> {
+        // Safe because the kernel only reads a `u64`, and we check the return.
+        check_ioctl(unsafe { ioctl_with_ref(&self.file, VHOST_SET_FEATURES(), &features) })
+    }
+
+    /// Describes the guest memory to the kernel, which accesses it through the mappings of the
+    /// calling process.
+    pub fn set_mem_table(&self, mem: &GuestMemoryMmap) -> Result<()> {
+        let mut regions = Vec::with_capacity(mem.num_regions());
+        mem.with_regions(|_, region| {
+            let host_addr = region
+                .get_host_address(MemoryRegionAddress(0))
+                .map_err(Error::GuestMemory)?;
+            regions.push(MemoryRegion {
+                guest_phys_addr: region.start_addr().0,
+                memory_size: region.len(),
+                userspace_addr: host_addr as u64,
+                flags_padding: 0,
+            });
+            Ok(())
+        })?;
+
+        // The kernel expects the header directly followed by the regions. A `u64` buffer keeps
+        // them aligned.
+        let header_words = std::mem::size_of::<MemoryTableHeader>() / 8;
+        let region_words = std::mem::size_of::<MemoryRegion>() / 8;
+        let mut table = vec![0u64; header_words + regions.len() * region_words];
+        // Safe because `table` is large enough to hold the header and all the regions, which
+        // are plain data.
+        unsafe {
+            let header = table.as_mut_ptr() as *mut MemoryTableHeader;
+            *header = MemoryTableHeader {
+                num_regions: regions.len() as u32,
+                padding: 0,
+            };
+            std::ptr::copy_nonoverlapping(
+                regions.as_ptr(),
+                table.as_mut_ptr().add(header_words) as *mut MemoryRegion,
+                regions.len(),
+            );
+        }
+        // Safe because the kernel only reads the table, whose size matches its header, and we
+        // check the return.
+        check_ioctl(unsafe { ioctl_with_ptr(&self.file, VHOST_SET_MEM_TABLE(), table.as_ptr()) })
+    }
+
+    /// Sets the number of descriptors of the `index` queue.
+    pub fn set_vring_num(&self, index: usize, num: u16) -> Result<()> {
+        let state = VringState {
+            index: index as u32,
+            num: u32::from(num),
+        };
+        // Safe because the kernel only reads a `VringState`, and we check the return.
+        check_ioctl(unsafe { ioctl_with_ref(&self.file, VHOST_SET_VRING_NUM(), &state) })
+    }
+
+    /// Sets the host addresses of the descriptor table, used ring and available ring of the
+    /// `index` queue.
+    pub fn set_vring_addr(
+        &self,
+        index: usize,
+        descriptor: u64,
+        used: u64,
+        available: u64,
+    ) -> Result<()> {
+        let addr = VringAddr {
+            index: index as u32,
+            flags: 0,
+            descriptor,
+            used,
+            available,
+            log: 0,
+        };
+        // Safe because the kernel only reads a `VringAddr`, and we check the return.
+        check_ioctl(unsafe { ioctl_with_ref(&self.file, VHOST_SET_VRING_ADDR(), &addr) })
+    }
+
+    /// Sets the index of the next available descriptor of the `index` queue.
+    pub fn set_vring_base(&self, index: usize, base: u16) -> Result<()> {
+        let state = VringState {
+            index: index as u32,
+            num: u32::from(base),
+        };
+        // Safe because the kernel only reads a `VringState`, and we check the return.
+        check_ioctl(unsafe { ioctl_with_ref(&self.file, VHOST_SET_VRING_BASE(), &state) })
+    }
+
+    /// Sets the eventfd signaled by the guest when it makes buffers available in the `index`
+    /// queue.
+    pub fn set_vring_kick(&self, index: usize, fd: RawFd) -> Result<()> {
+        let file = VringFile {
+            index: index as u32,
+            fd,
+        };
+        // Safe because the kernel only reads a `VringFile`, and we check the return.
+        check_ioctl(unsafe { ioctl_with_ref(&self.file, VHOST_SET_VRING_KICK(), &file) })
+    }
+
+    /// Sets the eventfd signaled by the kernel when it uses buffers from the `index` queue.
+    pub fn set_vring_call(&self, index: usize, fd: RawFd) -> Result<()> {
+        let file = VringFile {
+            index: index as u32,
+            fd,
+        };
+        // Safe because the kernel only reads a `VringFile`, and we check the return.
+        check_ioctl(unsafe { ioctl_with_ref(&self.file, VHOST_SET_VRING_CALL(), &file) })
+    }
+
+    /// Sets the context identifier of the guest. The kernel rejects a CID used by another
+    /// guest.
+    pub fn set_guest_cid(&self, cid: u64) -> Result<()> {
+        // Safe because the kernel only reads a `u64`, and we check the return.
+        check_ioctl(unsafe { ioctl_with_ref(&self.file, VHOST_VSOCK_SET_GUEST_CID(), &cid) })
+    }
+
+    /// Starts or stops the processing of the virtqueues by the kernel.
+    pub fn set_running(&self, running: bool) -> Result<()> {
+        let running = c_int::from(running);
+        // Safe because the kernel only reads a `c_int`, and we check the return.
+        check_ioctl(unsafe { ioctl_with_ref(&self.file, VHOST_VSOCK_SET_RUNNING(), &running) })
+    }
+}
+
+impl AsRawFd for VhostVsockHandle {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempfile::TempFile;
+    use vm_memory::GuestAddress;
+
+    #[test]
+    fn test_ioctl_numbers() {
+        // The values of include/uapi/linux/vhost.h, which must not change.
+        assert_eq!(VHOST_GET_FEATURES(), 0x8008_af00);
+        assert_eq!(VHOST_SET_FEATURES(), 0x4008_af00);
+        assert_eq!(VHOST_SET_OWNER(), 0xaf01);
+        assert_eq!(VHOST_SET_MEM_TABLE(), 0x4008_af03);
+        assert_eq!(VHOST_SET_VRING_NUM(), 0x4008_af10);
+        assert_eq!(VHOST_SET_VRING_ADDR(), 0x4028_af11);
+        assert_eq!(VHOST_SET_VRING_BASE(), 0x4008_af12);
+        assert_eq!(VHOST_SET_VRING_KICK(), 0x4008_af20);
+        assert_eq!(VHOST_SET_VRING_CALL(), 0x4008_af21);
+        assert_eq!(VHOST_VSOCK_SET_GUEST_CID(), 0x4008_af60);
+        assert_eq!(VHOST_VSOCK_SET_RUNNING(), 0x4004_af61);
+    }
+
+    #[test]
+    fn test_handle() {
+        assert!(matches!(
+            VhostVsockHandle::open("/dev/does-not-exist"),
+            Err(Error::Open(_))
+        ));
+
+        // A regular file doesn't implement any of the vhost ioctls.
+        let file = TempFile::new().unwrap();
+        let handle = VhostVsockHandle::open(file.as_path()).unwrap();
+        assert!(handle.as_raw_fd() >= 0);
+        assert!(matches!(handle.set_owner(), Err(Error::Ioctl(_))));
+        assert!(matches!(handle.get_features(), Err(Error::Ioctl(_))));
+        assert!(matches!(handle.set_guest_cid(3), Err(Error::Ioctl(_))));
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        assert!(matches!(handle.set_mem_table(&mem), Err(Error::Ioctl(_))));
+        assert!(matches!(handle.set_running(true), Err(Error::Ioctl(_))));
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements a virtio-vsock device whose virtqueues are served by the `vhost-vsock` kernel
+//! device, instead of the userspace connection multiplexer. The guest sockets are then reached
+//! from the host through `AF_VSOCK` sockets, rather than through a unix domain socket.
+
+mod device;
+mod event_handler;
+mod handle;
+
+use vm_memory::GuestMemoryError;
+
+pub use self::device::VhostVsock;
+pub use self::handle::{VhostVsockHandle, VHOST_VSOCK_PATH};
+
+#[derive(Debug)]
+pub enum Error {
+    /// EventFd error.
+    EventFd(std::io::Error),
+    /// Failed to signal the virtio used queue.
+    FailedSignalingUsedQueue(std::io::Error),
+    /// Guest gave us bad memory addresses.
+    GuestMemory(GuestMemoryError),
+    /// A `vhost-vsock` ioctl failed.
+    Ioctl(std::io::Error),
+    /// Failed to open the `vhost-vsock` kernel device.
+    Open(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
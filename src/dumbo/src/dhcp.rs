@@ -0,0 +1,707 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal DHCP server, which hands out the addresses of a pool to the guest behind a network
+//! interface.
+//!
+//! The server only answers the requests coming from its own interface, so it does not support
+//! relay agents, and it keeps the leases for as long as the clients do not release them.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::num::NonZeroUsize;
+use std::result::Result;
+
+use crate::pdu::dhcp::{
+    DhcpMessage, DhcpOption, MessageType, DHCP_CLIENT_PORT, DHCP_SERVER_PORT, FLAG_BROADCAST,
+    OP_BOOTREQUEST,
+};
+use crate::pdu::ethernet::{EthernetFrame, ETHERTYPE_IPV4};
+use crate::pdu::ipv4::{IPv4Packet, PROTOCOL_UDP};
+use crate::pdu::udp::UdpDatagram;
+
+use utils::net::mac::MacAddr;
+
+const DEFAULT_MAC_ADDR: &str = "06:01:23:45:67:02";
+const BROADCAST_MAC_ADDR: &str = "ff:ff:ff:ff:ff:ff";
+// The largest message the server writes is well below the minimum datagram size every host
+// must be able to receive.
+const MAX_MESSAGE_LEN: usize = 576;
+/// The maximum number of addresses in a pool.
+pub const MAX_POOL_SIZE: u32 = 1024;
+/// The maximum number of DNS servers advertised to the clients.
+pub const MAX_DNS_SERVERS: usize = 8;
+/// The minimum MTU every IPv4 host must support.
+pub const MIN_MTU: u16 = 68;
+
+/// Errors associated with the configuration of a DHCP server.
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    /// The gateway address is part of the pool.
+    GatewayInPool(Ipv4Addr),
+    /// The lease time is zero.
+    InvalidLeaseTime,
+    /// The MTU is too small for IPv4.
+    InvalidMtu(u16),
+    /// The first address of the pool comes after the last one.
+    InvalidPool(Ipv4Addr, Ipv4Addr),
+    /// The subnet mask is not made of contiguous ones.
+    InvalidSubnetMask(Ipv4Addr),
+    /// The pool and the gateway are not in the same subnet.
+    PoolOutsideSubnet,
+    /// The pool holds more than `MAX_POOL_SIZE` addresses.
+    PoolTooLarge(u32),
+    /// More than `MAX_DNS_SERVERS` DNS servers were given.
+    TooManyDnsServers(usize),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ConfigError::*;
+        match self {
+            GatewayInPool(gateway) => {
+                write!(f, "The gateway address {} is part of the pool.", gateway)
+            }
+            InvalidLeaseTime => write!(f, "The lease time must be greater than zero."),
+            InvalidMtu(mtu) => write!(
+                f,
+                "Invalid MTU: {}. The MTU must be at least {}.",
+                mtu, MIN_MTU
+            ),
+            InvalidPool(start, end) => {
+                write!(f, "Invalid address pool: {} comes after {}.", start, end)
+            }
+            InvalidSubnetMask(mask) => write!(f, "Invalid subnet mask: {}.", mask),
+            PoolOutsideSubnet => write!(
+                f,
+                "The address pool and the gateway are not in the same subnet."
+            ),
+            PoolTooLarge(size) => write!(
+                f,
+                "The address pool holds {} addresses. The maximum is {}.",
+                size, MAX_POOL_SIZE
+            ),
+            TooManyDnsServers(count) => write!(
+                f,
+                "{} DNS servers were given. The maximum is {}.",
+                count, MAX_DNS_SERVERS
+            ),
+        }
+    }
+}
+
+/// The configuration of a DHCP server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DhcpServerConfig {
+    /// The first address handed out by the server.
+    pub pool_start: Ipv4Addr,
+    /// The last address handed out by the server.
+    pub pool_end: Ipv4Addr,
+    /// The subnet mask of the clients.
+    pub subnet_mask: Ipv4Addr,
+    /// The default gateway of the clients, which the server also uses as its own address.
+    pub gateway: Ipv4Addr,
+    /// The DNS servers advertised to the clients.
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// The MTU advertised to the clients, if any.
+    pub mtu: Option<u16>,
+    /// The lease time of the addresses, in seconds.
+    pub lease_time_s: u32,
+}
+
+impl DhcpServerConfig {
+    /// Checks that the configuration describes a usable pool.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mask = u32::from(self.subnet_mask);
+        // The mask is valid when its zero bits are all at the end.
+        if mask == 0 || (!mask).wrapping_add(1) & !mask != 0 {
+            return Err(ConfigError::InvalidSubnetMask(self.subnet_mask));
+        }
+
+        let start = u32::from(self.pool_start);
+        let end = u32::from(self.pool_end);
+        if start > end {
+            return Err(ConfigError::InvalidPool(self.pool_start, self.pool_end));
+        }
+        let size = end - start + 1;
+        if size > MAX_POOL_SIZE {
+            return Err(ConfigError::PoolTooLarge(size));
+        }
+
+        let subnet = u32::from(self.gateway) & mask;
+        if start & mask != subnet || end & mask != subnet {
+            return Err(ConfigError::PoolOutsideSubnet);
+        }
+        if self.contains(self.gateway) {
+            return Err(ConfigError::GatewayInPool(self.gateway));
+        }
+
+        if self.dns_servers.len() > MAX_DNS_SERVERS {
+            return Err(ConfigError::TooManyDnsServers(self.dns_servers.len()));
+        }
+        if let Some(mtu) = self.mtu {
+            if mtu < MIN_MTU {
+                return Err(ConfigError::InvalidMtu(mtu));
+            }
+        }
+        if self.lease_time_s == 0 {
+            return Err(ConfigError::InvalidLeaseTime);
+        }
+
+        Ok(())
+    }
+
+    fn contains(&self, addr: Ipv4Addr) -> bool {
+        (u32::from(self.pool_start)..=u32::from(self.pool_end)).contains(&u32::from(addr))
+    }
+}
+
+// A reply waiting to be sent to the guest.
+struct PendingReply {
+    dst_mac: MacAddr,
+    dst_addr: Ipv4Addr,
+    message: Vec<u8>,
+}
+
+/// Answers the DHCP requests of the guest behind a network interface.
+pub struct DhcpServer {
+    config: DhcpServerConfig,
+    // The Ethernet MAC address the replies are sent from.
+    mac_addr: MacAddr,
+    // The address leased to each client.
+    leases: Vec<(MacAddr, Ipv4Addr)>,
+    pending_reply: Option<PendingReply>,
+}
+
+impl DhcpServer {
+    /// Creates a server handing out the addresses described by a valid `config`.
+    pub fn new(config: DhcpServerConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+
+        Ok(DhcpServer {
+            config,
+            // The unwrap is safe if parse_str() is implemented properly.
+            mac_addr: MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap(),
+            leases: Vec::new(),
+            pending_reply: None,
+        })
+    }
+
+    /// Returns the configuration of the server.
+    pub fn config(&self) -> &DhcpServerConfig {
+        &self.config
+    }
+
+    /// Returns the address leased to the client with the `mac` hardware address, if any.
+    pub fn lease(&self, mac: MacAddr) -> Option<Ipv4Addr> {
+        self.leases
+            .iter()
+            .find(|(client, _)| *client == mac)
+            .map(|(_, addr)| *addr)
+    }
+
+    /// This is the entry point into the DHCP server. The `src` slice should hold the contents of
+    /// an Ethernet frame (of that exact size, without the CRC). Returns whether the frame was a
+    /// DHCP request, in which case it must not go any further.
+    pub fn detour_frame(&mut self, src: &[u8]) -> bool {
+        let eth = match EthernetFrame::from_bytes(src) {
+            Ok(eth) if eth.ethertype() == ETHERTYPE_IPV4 => eth,
+            _ => return false,
+        };
+        // The checksums are not verified, in case the guest driver offloads their computation.
+        let ip = match IPv4Packet::from_bytes(eth.payload(), false) {
+            Ok(ip) if ip.protocol() == PROTOCOL_UDP => ip,
+            _ => return false,
+        };
+        let udp = match UdpDatagram::from_bytes(ip.payload(), None) {
+            Ok(udp) if udp.destination_port() == DHCP_SERVER_PORT => udp,
+            _ => return false,
+        };
+
+        if let Ok(message) = DhcpMessage::from_bytes(udp.payload()) {
+            // Messages which went through relay agents come from other networks.
+            if message.op() == OP_BOOTREQUEST && message.giaddr().is_unspecified() {
+                self.handle_message(&message);
+            }
+        }
+
+        true
+    }
+
+    fn handle_message(&mut self, message: &DhcpMessage<&[u8]>) {
+        let client = message.chaddr();
+        match message.message_type() {
+            Some(MessageType::Discover) => {
+                if let Some(addr) = self.find_address(client, message.requested_ip()) {
+                    self.set_lease(client, addr);
+                    self.queue_reply(message, MessageType::Offer, addr);
+                }
+            }
+            Some(MessageType::Request) => {
+                // The client picked the offer of another server.
+                if let Some(server) = message.server_identifier() {
+                    if server != self.config.gateway {
+                        self.remove_lease(client);
+                        return;
+                    }
+                }
+
+                let addr = message.requested_ip().unwrap_or_else(|| message.ciaddr());
+                if self.config.contains(addr) && self.is_available(client, addr) {
+                    self.set_lease(client, addr);
+                    self.queue_reply(message, MessageType::Ack, addr);
+                } else {
+                    self.queue_reply(message, MessageType::Nak, Ipv4Addr::UNSPECIFIED);
+                }
+            }
+            Some(MessageType::Inform) => {
+                self.queue_reply(message, MessageType::Ack, Ipv4Addr::UNSPECIFIED)
+            }
+            Some(MessageType::Release) | Some(MessageType::Decline) => self.remove_lease(client),
+            _ => (),
+        }
+    }
+
+    // Returns whether `addr` can be leased to `client`.
+    fn is_available(&self, client: MacAddr, addr: Ipv4Addr) -> bool {
+        self.leases
+            .iter()
+            .all(|(other, leased)| *leased != addr || *other == client)
+    }
+
+    // Picks the address offered to `client`, preferring its current lease and then the address it
+    // asked for.
+    fn find_address(&self, client: MacAddr, requested: Option<Ipv4Addr>) -> Option<Ipv4Addr> {
+        if let Some(addr) = self.lease(client) {
+            return Some(addr);
+        }
+        if let Some(addr) = requested {
+            if self.config.contains(addr) && self.is_available(client, addr) {
+                return Some(addr);
+            }
+        }
+
+        (u32::from(self.config.pool_start)..=u32::from(self.config.pool_end))
+            .map(Ipv4Addr::from)
+            .find(|addr| self.is_available(client, *addr))
+    }
+
+    fn set_lease(&mut self, client: MacAddr, addr: Ipv4Addr) {
+        self.remove_lease(client);
+        self.leases.push((client, addr));
+    }
+
+    fn remove_lease(&mut self, client: MacAddr) {
+        self.leases.retain(|(other, _)| *other != client);
+    }
+
+    fn queue_reply(
+        &mut self,
+        request: &DhcpMessage<&[u8]>,
+        message_type: MessageType,
+        yiaddr: Ipv4Addr,
+    ) {
+        let config = &self.config;
+        let mut options = vec![
+            DhcpOption::MessageType(message_type),
+            DhcpOption::ServerIdentifier(config.gateway),
+        ];
+        if message_type != MessageType::Nak {
+            if !yiaddr.is_unspecified() {
+                options.push(DhcpOption::LeaseTime(config.lease_time_s));
+            }
+            options.push(DhcpOption::SubnetMask(config.subnet_mask));
+            options.push(DhcpOption::Router(config.gateway));
+            if !config.dns_servers.is_empty() {
+                options.push(DhcpOption::DnsServers(&config.dns_servers));
+            }
+            if let Some(mtu) = config.mtu {
+                options.push(DhcpOption::InterfaceMtu(mtu));
+            }
+        }
+
+        let mut message = vec![0u8; MAX_MESSAGE_LEN];
+        let len = match DhcpMessage::write_reply(
+            message.as_mut_slice(),
+            request.xid(),
+            request.flags() & FLAG_BROADCAST,
+            request.chaddr(),
+            yiaddr,
+            &options,
+        ) {
+            Ok(reply) => reply.len(),
+            // The options of the server always fit in the message.
+            Err(_) => return,
+        };
+        message.truncate(len);
+
+        // The unwrap is safe if parse_str() is implemented properly.
+        let broadcast_mac = MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap();
+        let (dst_mac, dst_addr) = if !request.ciaddr().is_unspecified() {
+            // The client already has an address, so it can receive unicast.
+            (request.chaddr(), request.ciaddr())
+        } else if message_type == MessageType::Nak || request.flags() & FLAG_BROADCAST != 0 {
+            (broadcast_mac, Ipv4Addr::BROADCAST)
+        } else {
+            (request.chaddr(), yiaddr)
+        };
+
+        self.pending_reply = Some(PendingReply {
+            dst_mac,
+            dst_addr,
+            message,
+        });
+    }
+
+    /// Allows the DHCP server to write a frame to the specified buffer. Will return:
+    /// - None, if the server has no frame to send at this point. The buffer can be used for
+    /// something else by the device model.
+    /// - Some(len), if a frame of the given length has been written to the specified buffer.
+    pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
+        let reply = self.pending_reply.take()?;
+
+        let mut eth_unsized =
+            EthernetFrame::write_incomplete(buf, reply.dst_mac, self.mac_addr, ETHERTYPE_IPV4)
+                .ok()?;
+        let mut ip_unsized = IPv4Packet::write_header(
+            eth_unsized.inner_mut().payload_mut(),
+            PROTOCOL_UDP,
+            self.config.gateway,
+            reply.dst_addr,
+        )
+        .ok()?;
+        let udp_len = UdpDatagram::write_incomplete_datagram(
+            ip_unsized.inner_mut().payload_mut(),
+            &reply.message,
+        )
+        .ok()?
+        .finalize(
+            DHCP_SERVER_PORT,
+            DHCP_CLIENT_PORT,
+            Some((self.config.gateway, reply.dst_addr)),
+        )
+        .len();
+        let ip_len = ip_unsized
+            .with_payload_len_unchecked(udp_len as usize, true)
+            .len();
+
+        NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(ip_len).len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdu::dhcp::tests::client_message;
+
+    const CLIENT_MAC: &str = "12:34:56:78:9a:bc";
+    const OTHER_CLIENT_MAC: &str = "12:34:56:78:9a:bd";
+    const OPTION_REQUESTED_IP: u8 = 50;
+    const OPTION_SERVER_IDENTIFIER: u8 = 54;
+
+    fn config() -> DhcpServerConfig {
+        DhcpServerConfig {
+            pool_start: Ipv4Addr::new(10, 0, 0, 2),
+            pool_end: Ipv4Addr::new(10, 0, 0, 3),
+            subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: Ipv4Addr::new(10, 0, 0, 1),
+            dns_servers: vec![Ipv4Addr::new(10, 0, 0, 1)],
+            mtu: Some(1500),
+            lease_time_s: 3600,
+        }
+    }
+
+    // Wraps a client message into an Ethernet frame heading to the server.
+    fn client_frame(message: &[u8], dst_port: u16) -> Vec<u8> {
+        let mut buf = vec![0u8; 1000];
+        let len = {
+            let mut eth = EthernetFrame::write_incomplete(
+                buf.as_mut_slice(),
+                MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap(),
+                MacAddr::parse_str(CLIENT_MAC).unwrap(),
+                ETHERTYPE_IPV4,
+            )
+            .unwrap();
+            let mut ip = IPv4Packet::write_header(
+                eth.inner_mut().payload_mut(),
+                PROTOCOL_UDP,
+                Ipv4Addr::UNSPECIFIED,
+                Ipv4Addr::BROADCAST,
+            )
+            .unwrap();
+            let udp_len =
+                UdpDatagram::write_incomplete_datagram(ip.inner_mut().payload_mut(), message)
+                    .unwrap()
+                    .finalize(DHCP_CLIENT_PORT, dst_port, None)
+                    .len();
+            let ip_len = ip.with_payload_len_unchecked(udp_len as usize, true).len();
+            eth.with_payload_len_unchecked(ip_len).len()
+        };
+        buf.truncate(len);
+        buf
+    }
+
+    // Sends `message` to the server and returns the destination addresses and the type of the
+    // reply, and the address it assigns.
+    fn exchange(
+        server: &mut DhcpServer,
+        message: &[u8],
+    ) -> Option<(MacAddr, Ipv4Addr, MessageType, Ipv4Addr)> {
+        assert!(server.detour_frame(&client_frame(message, DHCP_SERVER_PORT)));
+
+        let mut buf = [0u8; 1000];
+        let len = server.write_next_frame(&mut buf)?.get();
+        let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(eth.src_mac(), MacAddr::parse_str(DEFAULT_MAC_ADDR).unwrap());
+        let ip = IPv4Packet::from_bytes(eth.payload(), true).unwrap();
+        assert_eq!(ip.source_address(), server.config().gateway);
+        let udp = UdpDatagram::from_bytes(
+            ip.payload(),
+            Some((ip.source_address(), ip.destination_address())),
+        )
+        .unwrap();
+        assert_eq!(udp.source_port(), DHCP_SERVER_PORT);
+        assert_eq!(udp.destination_port(), DHCP_CLIENT_PORT);
+        let reply = DhcpMessage::from_bytes(udp.payload()).unwrap();
+        assert_eq!(reply.xid(), 0x1234_5678);
+
+        Some((
+            eth.dst_mac(),
+            ip.destination_address(),
+            reply.message_type().unwrap(),
+            reply.yiaddr(),
+        ))
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(config().validate().is_ok());
+
+        let mut bad_config = config();
+        bad_config.subnet_mask = Ipv4Addr::new(255, 0, 255, 0);
+        assert_eq!(
+            bad_config.validate(),
+            Err(ConfigError::InvalidSubnetMask(bad_config.subnet_mask))
+        );
+
+        let mut bad_config = config();
+        bad_config.pool_start = Ipv4Addr::new(10, 0, 0, 4);
+        assert_eq!(
+            bad_config.validate(),
+            Err(ConfigError::InvalidPool(
+                bad_config.pool_start,
+                bad_config.pool_end
+            ))
+        );
+
+        let mut bad_config = config();
+        bad_config.subnet_mask = Ipv4Addr::new(255, 255, 0, 0);
+        bad_config.pool_end = Ipv4Addr::new(10, 0, 255, 254);
+        assert_eq!(bad_config.validate(), Err(ConfigError::PoolTooLarge(65533)));
+
+        let mut bad_config = config();
+        bad_config.pool_end = Ipv4Addr::new(10, 0, 1, 3);
+        assert_eq!(bad_config.validate(), Err(ConfigError::PoolOutsideSubnet));
+
+        let mut bad_config = config();
+        bad_config.gateway = Ipv4Addr::new(10, 0, 0, 3);
+        assert_eq!(
+            bad_config.validate(),
+            Err(ConfigError::GatewayInPool(bad_config.gateway))
+        );
+
+        let mut bad_config = config();
+        bad_config.dns_servers = vec![Ipv4Addr::LOCALHOST; MAX_DNS_SERVERS + 1];
+        assert_eq!(
+            bad_config.validate(),
+            Err(ConfigError::TooManyDnsServers(MAX_DNS_SERVERS + 1))
+        );
+
+        let mut bad_config = config();
+        bad_config.mtu = Some(MIN_MTU - 1);
+        assert_eq!(
+            bad_config.validate(),
+            Err(ConfigError::InvalidMtu(MIN_MTU - 1))
+        );
+
+        let mut bad_config = config();
+        bad_config.lease_time_s = 0;
+        assert_eq!(bad_config.validate(), Err(ConfigError::InvalidLeaseTime));
+        assert!(DhcpServer::new(bad_config).is_err());
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(
+            ConfigError::InvalidMtu(10).to_string(),
+            "Invalid MTU: 10. The MTU must be at least 68."
+        );
+        let _ = format!("{}", ConfigError::GatewayInPool(Ipv4Addr::LOCALHOST));
+        let _ = format!("{}", ConfigError::InvalidLeaseTime);
+        let _ = format!(
+            "{}",
+            ConfigError::InvalidPool(Ipv4Addr::LOCALHOST, Ipv4Addr::UNSPECIFIED)
+        );
+        let _ = format!("{}", ConfigError::InvalidSubnetMask(Ipv4Addr::LOCALHOST));
+        let _ = format!("{}", ConfigError::PoolOutsideSubnet);
+        let _ = format!("{}", ConfigError::PoolTooLarge(2000));
+        let _ = format!("{}", ConfigError::TooManyDnsServers(10));
+    }
+
+    #[test]
+    fn test_detour_frame() {
+        let mut server = DhcpServer::new(config()).unwrap();
+        let client = MacAddr::parse_str(CLIENT_MAC).unwrap();
+        let discover = client_message(MessageType::Discover, client, &[]);
+
+        // Frames which are not heading to the DHCP server port go through.
+        assert!(!server.detour_frame(&client_frame(&discover, 80)));
+        assert!(!server.detour_frame(&[0u8; 10]));
+        // Malformed DHCP messages are dropped without a reply.
+        assert!(server.detour_frame(&client_frame(&[0u8; 10], DHCP_SERVER_PORT)));
+        assert!(server.write_next_frame(&mut [0u8; 1000]).is_none());
+    }
+
+    #[test]
+    fn test_lease_lifecycle() {
+        let mut server = DhcpServer::new(config()).unwrap();
+        let client = MacAddr::parse_str(CLIENT_MAC).unwrap();
+        let broadcast_mac = MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap();
+
+        // The first free address of the pool is offered.
+        let discover = client_message(MessageType::Discover, client, &[]);
+        assert_eq!(
+            exchange(&mut server, &discover),
+            Some((
+                client,
+                Ipv4Addr::new(10, 0, 0, 2),
+                MessageType::Offer,
+                Ipv4Addr::new(10, 0, 0, 2)
+            ))
+        );
+
+        // Requests for the other servers drop the offer.
+        let request = client_message(
+            MessageType::Request,
+            client,
+            &[
+                (OPTION_REQUESTED_IP, &[10, 0, 0, 2]),
+                (OPTION_SERVER_IDENTIFIER, &[10, 0, 0, 254]),
+            ],
+        );
+        assert!(exchange(&mut server, &request).is_none());
+        assert!(server.lease(client).is_none());
+
+        let request = client_message(
+            MessageType::Request,
+            client,
+            &[
+                (OPTION_REQUESTED_IP, &[10, 0, 0, 3]),
+                (OPTION_SERVER_IDENTIFIER, &[10, 0, 0, 1]),
+            ],
+        );
+        assert_eq!(
+            exchange(&mut server, &request),
+            Some((
+                client,
+                Ipv4Addr::new(10, 0, 0, 3),
+                MessageType::Ack,
+                Ipv4Addr::new(10, 0, 0, 3)
+            ))
+        );
+        assert_eq!(server.lease(client), Some(Ipv4Addr::new(10, 0, 0, 3)));
+
+        // Addresses outside of the pool are refused with a broadcast.
+        let request = client_message(
+            MessageType::Request,
+            client,
+            &[(OPTION_REQUESTED_IP, &[10, 0, 0, 9])],
+        );
+        assert_eq!(
+            exchange(&mut server, &request),
+            Some((
+                broadcast_mac,
+                Ipv4Addr::BROADCAST,
+                MessageType::Nak,
+                Ipv4Addr::UNSPECIFIED
+            ))
+        );
+
+        // Another client cannot take the leased address.
+        let other_client = MacAddr::parse_str(OTHER_CLIENT_MAC).unwrap();
+        let discover = client_message(
+            MessageType::Discover,
+            other_client,
+            &[(OPTION_REQUESTED_IP, &[10, 0, 0, 3])],
+        );
+        assert_eq!(
+            exchange(&mut server, &discover).unwrap().3,
+            Ipv4Addr::new(10, 0, 0, 2)
+        );
+        let request = client_message(
+            MessageType::Request,
+            other_client,
+            &[(OPTION_REQUESTED_IP, &[10, 0, 0, 3])],
+        );
+        assert_eq!(exchange(&mut server, &request).unwrap().2, MessageType::Nak);
+
+        // The pool is exhausted.
+        let third_client = MacAddr::parse_str("12:34:56:78:9a:be").unwrap();
+        let discover = client_message(MessageType::Discover, third_client, &[]);
+        assert!(exchange(&mut server, &discover).is_none());
+
+        // Released addresses go back to the pool.
+        let release = client_message(MessageType::Release, client, &[]);
+        assert!(exchange(&mut server, &release).is_none());
+        assert!(server.lease(client).is_none());
+        assert_eq!(
+            exchange(&mut server, &discover).unwrap().3,
+            Ipv4Addr::new(10, 0, 0, 3)
+        );
+    }
+
+    #[test]
+    fn test_reply_destination() {
+        let mut server = DhcpServer::new(config()).unwrap();
+        let client = MacAddr::parse_str(CLIENT_MAC).unwrap();
+
+        // Clients asking for broadcast replies get them.
+        let mut discover = client_message(MessageType::Discover, client, &[]);
+        discover[10] = 0x80;
+        assert_eq!(
+            exchange(&mut server, &discover),
+            Some((
+                MacAddr::parse_str(BROADCAST_MAC_ADDR).unwrap(),
+                Ipv4Addr::BROADCAST,
+                MessageType::Offer,
+                Ipv4Addr::new(10, 0, 0, 2)
+            ))
+        );
+
+        // Renewals are sent to the current address of the client.
+        let mut request = client_message(MessageType::Request, client, &[]);
+        request[12..16].copy_from_slice(&[10, 0, 0, 2]);
+        assert_eq!(
+            exchange(&mut server, &request),
+            Some((
+                client,
+                Ipv4Addr::new(10, 0, 0, 2),
+                MessageType::Ack,
+                Ipv4Addr::new(10, 0, 0, 2)
+            ))
+        );
+
+        // Clients which configured their address themselves only get the parameters.
+        let mut inform = client_message(MessageType::Inform, client, &[]);
+        inform[12..16].copy_from_slice(&[10, 0, 0, 7]);
+        assert_eq!(
+            exchange(&mut server, &inform),
+            Some((
+                client,
+                Ipv4Addr::new(10, 0, 0, 7),
+                MessageType::Ack,
+                Ipv4Addr::UNSPECIFIED
+            ))
+        );
+    }
+}
@@ -3,7 +3,8 @@
 
 #![deny(missing_docs)]
 //! Provides helper logic for parsing and writing protocol data units, and minimalist
-//! implementations of a TCP listener, a TCP connection, and an HTTP/1.1 server.
+//! implementations of a TCP listener, a TCP connection, an HTTP/1.1 server, and a DHCP server.
+pub mod dhcp;
 pub mod pdu;
 pub mod tcp;
 
@@ -0,0 +1,503 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains support for parsing and writing DHCP messages over Ethernet, with a small subset of
+//! the DHCP options.
+//!
+//! Details of the message format and of the options can be found at [1] [2].
+//!
+//! [1]: https://tools.ietf.org/html/rfc2131
+//! [2]: https://tools.ietf.org/html/rfc2132
+
+use std::net::Ipv4Addr;
+use std::result::Result;
+
+use super::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+
+use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
+
+/// The UDP port DHCP servers listen on.
+pub const DHCP_SERVER_PORT: u16 = 67;
+/// The UDP port DHCP clients listen on.
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Operation of the messages sent by clients.
+pub const OP_BOOTREQUEST: u8 = 1;
+/// Operation of the messages sent by servers.
+pub const OP_BOOTREPLY: u8 = 2;
+/// Flag asking the server to broadcast its replies.
+pub const FLAG_BROADCAST: u16 = 0x8000;
+
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: u32 = 0x6382_5363;
+
+const OP_OFFSET: usize = 0;
+const HTYPE_OFFSET: usize = 1;
+const HLEN_OFFSET: usize = 2;
+const XID_OFFSET: usize = 4;
+const FLAGS_OFFSET: usize = 10;
+const CIADDR_OFFSET: usize = 12;
+const YIADDR_OFFSET: usize = 16;
+const SIADDR_OFFSET: usize = 20;
+const GIADDR_OFFSET: usize = 24;
+const CHADDR_OFFSET: usize = 28;
+const MAGIC_COOKIE_OFFSET: usize = 236;
+const OPTIONS_OFFSET: usize = 240;
+// Some clients drop messages shorter than the original BOOTP message, so replies are padded.
+const MIN_MESSAGE_LEN: usize = 300;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVERS: u8 = 6;
+const OPTION_INTERFACE_MTU: u8 = 26;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_IDENTIFIER: u8 = 54;
+const OPTION_END: u8 = 255;
+
+/// Represents errors which may occur while parsing or writing a message.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The hardware address is not an Ethernet one.
+    HardwareType,
+    /// The magic cookie which precedes the options is missing.
+    MagicCookie,
+    /// The specified byte sequence is shorter than the fixed part of a message.
+    MessageTooShort,
+    /// The options of the message do not fit in the provided buffer.
+    OptionsTooLong,
+}
+
+/// The types of DHCP messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageType {
+    /// A client looks for servers.
+    Discover = 1,
+    /// A server offers an address to a client.
+    Offer = 2,
+    /// A client requests the offered address, or renews its lease.
+    Request = 3,
+    /// A client refuses an address which is already in use.
+    Decline = 4,
+    /// A server acknowledges the lease of an address.
+    Ack = 5,
+    /// A server refuses the requested address.
+    Nak = 6,
+    /// A client gives up its lease.
+    Release = 7,
+    /// A client asks for configuration parameters only.
+    Inform = 8,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(MessageType::Discover),
+            2 => Some(MessageType::Offer),
+            3 => Some(MessageType::Request),
+            4 => Some(MessageType::Decline),
+            5 => Some(MessageType::Ack),
+            6 => Some(MessageType::Nak),
+            7 => Some(MessageType::Release),
+            8 => Some(MessageType::Inform),
+            _ => None,
+        }
+    }
+}
+
+/// The DHCP options which can be written to a message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DhcpOption<'a> {
+    /// The type of the message.
+    MessageType(MessageType),
+    /// The address of the server sending the message.
+    ServerIdentifier(Ipv4Addr),
+    /// The lease time of the address, in seconds.
+    LeaseTime(u32),
+    /// The subnet mask of the client.
+    SubnetMask(Ipv4Addr),
+    /// The default gateway of the client.
+    Router(Ipv4Addr),
+    /// The DNS servers available to the client.
+    DnsServers(&'a [Ipv4Addr]),
+    /// The MTU of the client interface.
+    InterfaceMtu(u16),
+}
+
+impl<'a> DhcpOption<'a> {
+    fn code(&self) -> u8 {
+        match self {
+            DhcpOption::MessageType(_) => OPTION_MESSAGE_TYPE,
+            DhcpOption::ServerIdentifier(_) => OPTION_SERVER_IDENTIFIER,
+            DhcpOption::LeaseTime(_) => OPTION_LEASE_TIME,
+            DhcpOption::SubnetMask(_) => OPTION_SUBNET_MASK,
+            DhcpOption::Router(_) => OPTION_ROUTER,
+            DhcpOption::DnsServers(_) => OPTION_DNS_SERVERS,
+            DhcpOption::InterfaceMtu(_) => OPTION_INTERFACE_MTU,
+        }
+    }
+
+    fn value(&self) -> Vec<u8> {
+        match self {
+            DhcpOption::MessageType(message_type) => vec![*message_type as u8],
+            DhcpOption::ServerIdentifier(addr)
+            | DhcpOption::SubnetMask(addr)
+            | DhcpOption::Router(addr) => addr.octets().to_vec(),
+            DhcpOption::LeaseTime(seconds) => seconds.to_be_bytes().to_vec(),
+            DhcpOption::DnsServers(addrs) => addrs.iter().flat_map(|addr| addr.octets()).collect(),
+            DhcpOption::InterfaceMtu(mtu) => mtu.to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// Interprets the inner bytes as a DHCP message.
+pub struct DhcpMessage<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'a, T: NetworkBytes> DhcpMessage<'a, T> {
+    /// Interprets `bytes` as a DHCP message without any validity checks.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        DhcpMessage {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Interprets `bytes` as a DHCP message with an Ethernet hardware address if possible, or
+    /// returns the reason for failing to do so.
+    pub fn from_bytes(bytes: T) -> Result<Self, Error> {
+        if bytes.len() < OPTIONS_OFFSET {
+            return Err(Error::MessageTooShort);
+        }
+
+        let message = DhcpMessage::from_bytes_unchecked(bytes);
+        if message.bytes.ntohl_unchecked(MAGIC_COOKIE_OFFSET) != MAGIC_COOKIE {
+            return Err(Error::MagicCookie);
+        }
+        if message.bytes[HTYPE_OFFSET] != HTYPE_ETHERNET
+            || message.bytes[HLEN_OFFSET] as usize != MAC_ADDR_LEN
+        {
+            return Err(Error::HardwareType);
+        }
+
+        Ok(message)
+    }
+
+    /// Returns the operation of the message.
+    #[inline]
+    pub fn op(&self) -> u8 {
+        self.bytes[OP_OFFSET]
+    }
+
+    /// Returns the transaction ID of the message.
+    #[inline]
+    pub fn xid(&self) -> u32 {
+        self.bytes.ntohl_unchecked(XID_OFFSET)
+    }
+
+    /// Returns the flags of the message.
+    #[inline]
+    pub fn flags(&self) -> u16 {
+        self.bytes.ntohs_unchecked(FLAGS_OFFSET)
+    }
+
+    /// Returns the current address of the client.
+    #[inline]
+    pub fn ciaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(CIADDR_OFFSET))
+    }
+
+    /// Returns the address assigned to the client.
+    #[inline]
+    pub fn yiaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(YIADDR_OFFSET))
+    }
+
+    /// Returns the address of the relay agent the message went through.
+    #[inline]
+    pub fn giaddr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes.ntohl_unchecked(GIADDR_OFFSET))
+    }
+
+    /// Returns the hardware address of the client.
+    #[inline]
+    pub fn chaddr(&self) -> MacAddr {
+        MacAddr::from_bytes_unchecked(&self.bytes[CHADDR_OFFSET..CHADDR_OFFSET + MAC_ADDR_LEN])
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the value of the option with the given `code`, if present. Options which run past
+    /// the end of the message are ignored.
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        let options = &self.bytes[OPTIONS_OFFSET..];
+        let mut offset = 0;
+        while offset < options.len() {
+            match options[offset] {
+                OPTION_PAD => offset += 1,
+                OPTION_END => break,
+                current => {
+                    let len = *options.get(offset + 1)? as usize;
+                    let value = options.get(offset + 2..offset + 2 + len)?;
+                    if current == code {
+                        return Some(value);
+                    }
+                    offset += 2 + len;
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the type of the message, if present and valid.
+    pub fn message_type(&self) -> Option<MessageType> {
+        match self.option(OPTION_MESSAGE_TYPE)? {
+            [value] => MessageType::from_u8(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the address requested by the client, if present.
+    pub fn requested_ip(&self) -> Option<Ipv4Addr> {
+        self.ipv4_option(OPTION_REQUESTED_IP)
+    }
+
+    /// Returns the address of the server chosen by the client, if present.
+    pub fn server_identifier(&self) -> Option<Ipv4Addr> {
+        self.ipv4_option(OPTION_SERVER_IDENTIFIER)
+    }
+
+    fn ipv4_option(&self, code: u8) -> Option<Ipv4Addr> {
+        match self.option(code)? {
+            [a, b, c, d] => Some(Ipv4Addr::new(*a, *b, *c, *d)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, T: NetworkBytesMut> DhcpMessage<'a, T> {
+    /// Writes a reply to the client request with the `xid` transaction ID and `chaddr` hardware
+    /// address, assigning `yiaddr` to the client. The message is shrunk to fit `options`.
+    pub fn write_reply(
+        buf: T,
+        xid: u32,
+        flags: u16,
+        chaddr: MacAddr,
+        yiaddr: Ipv4Addr,
+        options: &[DhcpOption],
+    ) -> Result<Self, Error> {
+        if buf.len() < OPTIONS_OFFSET {
+            return Err(Error::MessageTooShort);
+        }
+
+        let mut message = DhcpMessage::from_bytes_unchecked(buf);
+        for byte in message.bytes[..OPTIONS_OFFSET].iter_mut() {
+            *byte = 0;
+        }
+        message.bytes[OP_OFFSET] = OP_BOOTREPLY;
+        message.bytes[HTYPE_OFFSET] = HTYPE_ETHERNET;
+        message.bytes[HLEN_OFFSET] = MAC_ADDR_LEN as u8;
+        message.bytes.htonl_unchecked(XID_OFFSET, xid);
+        message.bytes.htons_unchecked(FLAGS_OFFSET, flags);
+        message
+            .bytes
+            .htonl_unchecked(YIADDR_OFFSET, u32::from(yiaddr));
+        message.bytes.htonl_unchecked(SIADDR_OFFSET, 0);
+        message.bytes[CHADDR_OFFSET..CHADDR_OFFSET + MAC_ADDR_LEN]
+            .copy_from_slice(chaddr.get_bytes());
+        message
+            .bytes
+            .htonl_unchecked(MAGIC_COOKIE_OFFSET, MAGIC_COOKIE);
+
+        let mut offset = OPTIONS_OFFSET;
+        for option in options {
+            let value = option.value();
+            let end = offset + 2 + value.len();
+            if value.len() > u8::MAX as usize || end >= message.bytes.len() {
+                return Err(Error::OptionsTooLong);
+            }
+            message.bytes[offset] = option.code();
+            message.bytes[offset + 1] = value.len() as u8;
+            message.bytes[offset + 2..end].copy_from_slice(&value);
+            offset = end;
+        }
+        message.bytes[offset] = OPTION_END;
+        offset += 1;
+
+        // Pad the message up to the minimum length, if there is room for it.
+        let len = std::cmp::max(offset, std::cmp::min(MIN_MESSAGE_LEN, message.bytes.len()));
+        for byte in message.bytes[offset..len].iter_mut() {
+            *byte = OPTION_PAD;
+        }
+        message.bytes.shrink_unchecked(len);
+
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Builds a client message of the given type, with the given extra options.
+    pub(crate) fn client_message(
+        message_type: MessageType,
+        chaddr: MacAddr,
+        extra_options: &[(u8, &[u8])],
+    ) -> Vec<u8> {
+        let mut buf = vec![0u8; OPTIONS_OFFSET];
+        buf[OP_OFFSET] = OP_BOOTREQUEST;
+        buf[HTYPE_OFFSET] = HTYPE_ETHERNET;
+        buf[HLEN_OFFSET] = MAC_ADDR_LEN as u8;
+        buf[XID_OFFSET..XID_OFFSET + 4].copy_from_slice(&0x1234_5678u32.to_be_bytes());
+        buf[CHADDR_OFFSET..CHADDR_OFFSET + MAC_ADDR_LEN].copy_from_slice(chaddr.get_bytes());
+        buf[MAGIC_COOKIE_OFFSET..OPTIONS_OFFSET].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        buf.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type as u8, OPTION_PAD]);
+        for (code, value) in extra_options {
+            buf.push(*code);
+            buf.push(value.len() as u8);
+            buf.extend_from_slice(value);
+        }
+        buf.push(OPTION_END);
+        buf
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let chaddr = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let buf = client_message(
+            MessageType::Request,
+            chaddr,
+            &[
+                (OPTION_REQUESTED_IP, &[10, 0, 0, 2]),
+                (OPTION_SERVER_IDENTIFIER, &[10, 0, 0, 1]),
+            ],
+        );
+
+        let message = DhcpMessage::from_bytes(buf.as_slice()).unwrap();
+        assert_eq!(message.op(), OP_BOOTREQUEST);
+        assert_eq!(message.xid(), 0x1234_5678);
+        assert_eq!(message.flags(), 0);
+        assert_eq!(message.ciaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(message.giaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(message.chaddr(), chaddr);
+        assert_eq!(message.message_type(), Some(MessageType::Request));
+        assert_eq!(message.requested_ip(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(
+            message.server_identifier(),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert!(message.option(OPTION_ROUTER).is_none());
+
+        assert_eq!(
+            DhcpMessage::from_bytes(&buf[..OPTIONS_OFFSET - 1]).err(),
+            Some(Error::MessageTooShort)
+        );
+
+        let mut bad_cookie = buf.clone();
+        bad_cookie[MAGIC_COOKIE_OFFSET] = 0;
+        assert_eq!(
+            DhcpMessage::from_bytes(bad_cookie.as_slice()).err(),
+            Some(Error::MagicCookie)
+        );
+
+        let mut bad_htype = buf.clone();
+        bad_htype[HTYPE_OFFSET] = 6;
+        assert_eq!(
+            DhcpMessage::from_bytes(bad_htype.as_slice()).err(),
+            Some(Error::HardwareType)
+        );
+
+        // Options running past the end of the message are ignored.
+        let mut truncated = buf[..OPTIONS_OFFSET].to_vec();
+        truncated.extend_from_slice(&[OPTION_REQUESTED_IP, 4, 10, 0]);
+        let message = DhcpMessage::from_bytes(truncated.as_slice()).unwrap();
+        assert!(message.message_type().is_none());
+        assert!(message.requested_ip().is_none());
+    }
+
+    #[test]
+    fn test_write_reply() {
+        let chaddr = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        let dns_servers = [Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(1, 1, 1, 1)];
+        let options = [
+            DhcpOption::MessageType(MessageType::Offer),
+            DhcpOption::ServerIdentifier(Ipv4Addr::new(10, 0, 0, 1)),
+            DhcpOption::LeaseTime(3600),
+            DhcpOption::SubnetMask(Ipv4Addr::new(255, 255, 255, 0)),
+            DhcpOption::Router(Ipv4Addr::new(10, 0, 0, 1)),
+            DhcpOption::DnsServers(&dns_servers),
+            DhcpOption::InterfaceMtu(1500),
+        ];
+
+        let mut buf = [0xffu8; 1000];
+        let len = DhcpMessage::write_reply(
+            buf.as_mut(),
+            0x1234_5678,
+            FLAG_BROADCAST,
+            chaddr,
+            Ipv4Addr::new(10, 0, 0, 2),
+            &options,
+        )
+        .unwrap()
+        .len();
+        assert_eq!(len, MIN_MESSAGE_LEN);
+
+        let message = DhcpMessage::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(message.op(), OP_BOOTREPLY);
+        assert_eq!(message.xid(), 0x1234_5678);
+        assert_eq!(message.flags(), FLAG_BROADCAST);
+        assert_eq!(message.yiaddr(), Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(message.ciaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(message.chaddr(), chaddr);
+        assert_eq!(message.message_type(), Some(MessageType::Offer));
+        assert_eq!(
+            message.server_identifier(),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(
+            message.option(OPTION_LEASE_TIME),
+            Some(&3600u32.to_be_bytes()[..])
+        );
+        assert_eq!(
+            message.option(OPTION_SUBNET_MASK),
+            Some(&[255, 255, 255, 0][..])
+        );
+        assert_eq!(message.option(OPTION_ROUTER), Some(&[10, 0, 0, 1][..]));
+        assert_eq!(
+            message.option(OPTION_DNS_SERVERS),
+            Some(&[8, 8, 8, 8, 1, 1, 1, 1][..])
+        );
+        assert_eq!(
+            message.option(OPTION_INTERFACE_MTU),
+            Some(&1500u16.to_be_bytes()[..])
+        );
+
+        // The options have to fit in the buffer.
+        let mut buf = [0u8; OPTIONS_OFFSET + 4];
+        assert_eq!(
+            DhcpMessage::write_reply(buf.as_mut(), 0, 0, chaddr, Ipv4Addr::UNSPECIFIED, &options)
+                .err(),
+            Some(Error::OptionsTooLong)
+        );
+        let mut buf = [0u8; OPTIONS_OFFSET - 1];
+        assert_eq!(
+            DhcpMessage::write_reply(buf.as_mut(), 0, 0, chaddr, Ipv4Addr::UNSPECIFIED, &[]).err(),
+            Some(Error::MessageTooShort)
+        );
+    }
+}
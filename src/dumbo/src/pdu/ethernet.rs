@@ -24,6 +24,10 @@ pub const PAYLOAD_OFFSET: usize = 14;
 pub const ETHERTYPE_ARP: u16 = 0x0806;
 /// Ethertype value for IPv4 packets.
 pub const ETHERTYPE_IPV4: u16 = 0x0800;
+/// Ethertype value for IPv6 packets.
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+/// Ethertype value (TPID) of 802.1Q tagged frames.
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
 
 /// Describes the errors which may occur when handling Ethernet frames.
 #[derive(Debug, PartialEq)]
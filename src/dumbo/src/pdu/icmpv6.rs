@@ -0,0 +1,353 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains support for parsing and writing ICMPv6 messages.
+//!
+//! Only the Neighbor Discovery Protocol messages needed for address resolution are supported for
+//! now, which are [Neighbor Solicitation] and [Neighbor Advertisement].
+//!
+//! [Neighbor Solicitation]: https://tools.ietf.org/html/rfc4861#section-4.3
+//! [Neighbor Advertisement]: https://tools.ietf.org/html/rfc4861#section-4.4
+
+use std::net::Ipv6Addr;
+use std::result::Result;
+
+use crate::pdu::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use crate::pdu::ChecksumProto;
+use crate::MacAddr;
+
+const TYPE_OFFSET: usize = 0;
+const CODE_OFFSET: usize = 1;
+const CHECKSUM_OFFSET: usize = 2;
+const HEADER_LEN: usize = 4;
+
+// The following offsets are specific to Neighbor Solicitation/Advertisement messages.
+const ND_FLAGS_OFFSET: usize = 4;
+const ND_TARGET_OFFSET: usize = 8;
+const ND_OPTIONS_OFFSET: usize = 24;
+
+const IPV6_ADDR_LEN: usize = 16;
+const MAC_ADDR_LEN: usize = 6;
+// The length of a link-layer address option carrying an Ethernet address.
+const LINK_LAYER_OPTION_LEN: usize = 8;
+
+/// Message type of ICMPv6 Neighbor Solicitations.
+pub const TYPE_NEIGHBOR_SOLICITATION: u8 = 135;
+/// Message type of ICMPv6 Neighbor Advertisements.
+pub const TYPE_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// Neighbor Discovery option type of the Source Link-Layer Address.
+pub const OPTION_SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+/// Neighbor Discovery option type of the Target Link-Layer Address.
+pub const OPTION_TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+
+/// The Neighbor Advertisement flag set when the sender is a router.
+pub const NA_FLAG_ROUTER: u8 = 0x80;
+/// The Neighbor Advertisement flag set when responding to a Neighbor Solicitation.
+pub const NA_FLAG_SOLICITED: u8 = 0x40;
+/// The Neighbor Advertisement flag set when the advertisement should override existing cache
+/// entries.
+pub const NA_FLAG_OVERRIDE: u8 = 0x20;
+
+/// The hop limit Neighbor Discovery messages are sent and received with, which guarantees they
+/// did not cross a router.
+pub const ND_HOP_LIMIT: u8 = 255;
+
+/// The length of the Neighbor Advertisement written by [`write_neighbor_advertisement`].
+///
+/// [`write_neighbor_advertisement`]: struct.Icmpv6Message.html#method.write_neighbor_advertisement
+pub const NEIGHBOR_ADVERTISEMENT_LEN: usize = ND_OPTIONS_OFFSET + LINK_LAYER_OPTION_LEN;
+
+/// Describes the errors which may occur while handling ICMPv6 messages.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The checksum of the message is invalid.
+    Checksum,
+    /// The length of the given slice is less than the length of the message.
+    SliceTooShort,
+}
+
+/// Interprets the inner bytes as an ICMPv6 message.
+pub struct Icmpv6Message<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'a, T: NetworkBytes> Icmpv6Message<'a, T> {
+    /// Interprets `bytes` as an ICMPv6 message without any validity checks.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        Icmpv6Message {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Attempts to interpret `bytes` as an ICMPv6 message.
+    ///
+    /// The `verify_checksum` parameter must contain the source and destination addresses from the
+    /// enclosing IPv6 packet if the checksum must be validated.
+    pub fn from_bytes(
+        bytes: T,
+        verify_checksum: Option<(Ipv6Addr, Ipv6Addr)>,
+    ) -> Result<Self, Error> {
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let message = Icmpv6Message::from_bytes_unchecked(bytes);
+
+        if let Some((src_addr, dst_addr)) = verify_checksum {
+            if message.compute_checksum(src_addr, dst_addr) != 0 {
+                return Err(Error::Checksum);
+            }
+        }
+
+        Ok(message)
+    }
+
+    /// Returns the message type.
+    #[inline]
+    pub fn message_type(&self) -> u8 {
+        self.bytes[TYPE_OFFSET]
+    }
+
+    /// Returns the message code.
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.bytes[CODE_OFFSET]
+    }
+
+    /// Returns the value of the `checksum` field.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        self.bytes.ntohs_unchecked(CHECKSUM_OFFSET)
+    }
+
+    /// Returns the length of the message.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Computes the checksum of the message, which covers an IPv6 pseudo-header built from the
+    /// given addresses.
+    pub fn compute_checksum(&self, src_addr: Ipv6Addr, dst_addr: Ipv6Addr) -> u16 {
+        crate::pdu::compute_checksum(
+            &self.bytes,
+            src_addr.into(),
+            dst_addr.into(),
+            ChecksumProto::Icmpv6,
+        )
+    }
+
+    /// Returns the target address of a Neighbor Solicitation, or `None` if the message is not
+    /// a well formed one.
+    pub fn neighbor_solicitation_target(&self) -> Option<Ipv6Addr> {
+        if self.message_type() != TYPE_NEIGHBOR_SOLICITATION
+            || self.code() != 0
+            || self.len() < ND_OPTIONS_OFFSET
+        {
+            return None;
+        }
+
+        let mut octets = [0u8; IPV6_ADDR_LEN];
+        octets.copy_from_slice(&self.bytes[ND_TARGET_OFFSET..ND_OPTIONS_OFFSET]);
+        Some(Ipv6Addr::from(octets))
+    }
+
+    /// Looks for a link-layer address option of the given type among the Neighbor Discovery
+    /// options of the message.
+    pub fn link_layer_address_option(&self, option_type: u8) -> Option<MacAddr> {
+        let mut offset = ND_OPTIONS_OFFSET;
+        // Every option starts with a type byte, followed by its length in units of 8 bytes.
+        while offset + 2 <= self.len() {
+            let len = usize::from(self.bytes[offset + 1]) * 8;
+            if len == 0 || offset + len > self.len() {
+                return None;
+            }
+            if self.bytes[offset] == option_type && len == LINK_LAYER_OPTION_LEN {
+                return Some(MacAddr::from_bytes_unchecked(
+                    &self.bytes[offset + 2..offset + 2 + MAC_ADDR_LEN],
+                ));
+            }
+            offset += len;
+        }
+        None
+    }
+}
+
+impl<'a, T: NetworkBytesMut> Icmpv6Message<'a, T> {
+    /// Writes a Neighbor Advertisement for `target` to `buf`, which carries `target_mac` as the
+    /// target link-layer address option, and has the given flags set.
+    ///
+    /// The checksum is computed using the `src_addr` and `dst_addr` of the enclosing IPv6 packet.
+    pub fn write_neighbor_advertisement(
+        buf: T,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+        flags: u8,
+        target: Ipv6Addr,
+        target_mac: MacAddr,
+    ) -> Result<Self, Error> {
+        if buf.len() < NEIGHBOR_ADVERTISEMENT_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let mut message = Icmpv6Message::from_bytes_unchecked(buf);
+        message.bytes.shrink_unchecked(NEIGHBOR_ADVERTISEMENT_LEN);
+        message.bytes[TYPE_OFFSET] = TYPE_NEIGHBOR_ADVERTISEMENT;
+        message.bytes[CODE_OFFSET] = 0;
+        message.bytes.htonl_unchecked(ND_FLAGS_OFFSET, 0);
+        message.bytes[ND_FLAGS_OFFSET] = flags;
+        message.bytes[ND_TARGET_OFFSET..ND_OPTIONS_OFFSET].copy_from_slice(&target.octets());
+        message.bytes[ND_OPTIONS_OFFSET] = OPTION_TARGET_LINK_LAYER_ADDRESS;
+        message.bytes[ND_OPTIONS_OFFSET + 1] = (LINK_LAYER_OPTION_LEN / 8) as u8;
+        message.bytes[ND_OPTIONS_OFFSET + 2..NEIGHBOR_ADVERTISEMENT_LEN]
+            .copy_from_slice(target_mac.get_bytes());
+
+        message.set_checksum(0);
+        let checksum = message.compute_checksum(src_addr, dst_addr);
+        message.set_checksum(checksum);
+
+        Ok(message)
+    }
+
+    /// Sets the value of the `checksum` field.
+    #[inline]
+    pub fn set_checksum(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(CHECKSUM_OFFSET, value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for Icmpv6Message<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(ICMPv6 message)")
+        }
+    }
+
+    // A Neighbor Solicitation for fe80::2 sent by fe80::1 (with the 02:00:00:00:00:01 MAC
+    // address) to the solicited-node multicast address ff02::1:ff00:2.
+    const NEIGHBOR_SOLICITATION: [u8; 32] = [
+        0x87, 0x00, 0x7a, 0x97, 0x00, 0x00, 0x00, 0x00, 0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x01, 0x01, 0x02, 0x00, 0x00, 0x00,
+        0x00, 0x01,
+    ];
+
+    #[test]
+    fn test_neighbor_solicitation() {
+        let src = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00, 2);
+
+        let message =
+            Icmpv6Message::from_bytes(&NEIGHBOR_SOLICITATION[..], Some((src, dst))).unwrap();
+        assert_eq!(message.message_type(), TYPE_NEIGHBOR_SOLICITATION);
+        assert_eq!(message.code(), 0);
+        assert_eq!(message.len(), NEIGHBOR_SOLICITATION.len());
+        assert_eq!(
+            message.neighbor_solicitation_target(),
+            Some(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2))
+        );
+        assert_eq!(
+            message.link_layer_address_option(OPTION_SOURCE_LINK_LAYER_ADDRESS),
+            Some(MacAddr::parse_str("02:00:00:00:00:01").unwrap())
+        );
+        assert_eq!(
+            message.link_layer_address_option(OPTION_TARGET_LINK_LAYER_ADDRESS),
+            None
+        );
+
+        // A message without options.
+        let message =
+            Icmpv6Message::from_bytes(&NEIGHBOR_SOLICITATION[..ND_OPTIONS_OFFSET], None).unwrap();
+        assert!(message.neighbor_solicitation_target().is_some());
+        assert_eq!(
+            message.link_layer_address_option(OPTION_SOURCE_LINK_LAYER_ADDRESS),
+            None
+        );
+
+        // Truncated and corrupt messages.
+        assert!(
+            Icmpv6Message::from_bytes(&NEIGHBOR_SOLICITATION[..ND_OPTIONS_OFFSET - 1], None)
+                .unwrap()
+                .neighbor_solicitation_target()
+                .is_none()
+        );
+        assert_eq!(
+            Icmpv6Message::from_bytes(&NEIGHBOR_SOLICITATION[..HEADER_LEN - 1], None).unwrap_err(),
+            Error::SliceTooShort
+        );
+        assert_eq!(
+            Icmpv6Message::from_bytes(&NEIGHBOR_SOLICITATION[..], Some((src, src))).unwrap_err(),
+            Error::Checksum
+        );
+
+        let mut bytes = NEIGHBOR_SOLICITATION;
+        // An option with a length of zero.
+        bytes[ND_OPTIONS_OFFSET + 1] = 0;
+        let message = Icmpv6Message::from_bytes(&bytes[..], None).unwrap();
+        assert_eq!(
+            message.link_layer_address_option(OPTION_SOURCE_LINK_LAYER_ADDRESS),
+            None
+        );
+        // Not a Neighbor Solicitation.
+        bytes[TYPE_OFFSET] = TYPE_NEIGHBOR_ADVERTISEMENT;
+        let message = Icmpv6Message::from_bytes(&bytes[..], None).unwrap();
+        assert!(message.neighbor_solicitation_target().is_none());
+    }
+
+    #[test]
+    fn test_neighbor_advertisement() {
+        let mut buf = [0u8; 100];
+        let src = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+        let dst = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let mac = MacAddr::parse_str("06:01:23:45:67:01").unwrap();
+
+        assert_eq!(
+            Icmpv6Message::write_neighbor_advertisement(
+                &mut buf[..NEIGHBOR_ADVERTISEMENT_LEN - 1],
+                src,
+                dst,
+                NA_FLAG_SOLICITED,
+                src,
+                mac
+            )
+            .unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        let len = Icmpv6Message::write_neighbor_advertisement(
+            buf.as_mut(),
+            src,
+            dst,
+            NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE,
+            src,
+            mac,
+        )
+        .unwrap()
+        .len();
+        assert_eq!(len, NEIGHBOR_ADVERTISEMENT_LEN);
+
+        let message = Icmpv6Message::from_bytes(&buf[..len], Some((src, dst))).unwrap();
+        assert_eq!(message.message_type(), TYPE_NEIGHBOR_ADVERTISEMENT);
+        assert_eq!(message.code(), 0);
+        assert_eq!(buf[ND_FLAGS_OFFSET], NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE);
+        assert_eq!(&buf[ND_TARGET_OFFSET..ND_OPTIONS_OFFSET], &src.octets());
+        let message = Icmpv6Message::from_bytes(&buf[..len], None).unwrap();
+        assert_eq!(
+            message.link_layer_address_option(OPTION_TARGET_LINK_LAYER_ADDRESS),
+            Some(mac)
+        );
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Contains support for parsing and writing IPv6 packets.
+//!
+//! A picture of the IPv6 packet header can be found [here] (watch out for the MSB 0 bit numbering).
+//! Extension headers are not supported, so the `next header` field always describes the payload.
+//!
+//! [here]: https://en.wikipedia.org/wiki/IPv6_packet#Fixed_header
+
+use std::convert::From;
+use std::net::Ipv6Addr;
+use std::result::Result;
+
+use crate::pdu::bytes::{InnerBytes, NetworkBytes, NetworkBytesMut};
+use crate::pdu::ethernet;
+use crate::pdu::ipv4::DEFAULT_TTL;
+use crate::pdu::Incomplete;
+
+const VERSION_AND_FLOW_OFFSET: usize = 0;
+const PAYLOAD_LEN_OFFSET: usize = 4;
+const NEXT_HEADER_OFFSET: usize = 6;
+const HOP_LIMIT_OFFSET: usize = 7;
+const SOURCE_ADDRESS_OFFSET: usize = 8;
+const DESTINATION_ADDRESS_OFFSET: usize = 24;
+const ADDRESS_LEN: usize = 16;
+
+/// The length of the fixed IPv6 header.
+pub const HEADER_LEN: usize = 40;
+
+/// Indicates version 6 of the IP protocol
+pub const IPV6_VERSION: u8 = 0x06;
+/// Default hop limit value (the same as the default IPv4 TTL)
+pub const DEFAULT_HOP_LIMIT: u8 = DEFAULT_TTL;
+
+/// The IP protocol number (next header value) associated with ICMPv6.
+pub const PROTOCOL_ICMPV6: u8 = 0x3a;
+
+/// Describes the errors which may occur while handling IPv6 packets.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The payload length of the packet is invalid.
+    InvalidPayloadLen,
+    /// The length of the given slice does not match the length of the packet.
+    SliceExactLen,
+    /// The length of the given slice is less than the IPv6 header length.
+    SliceTooShort,
+    /// The version header field is invalid.
+    Version,
+}
+
+/// Interprets the inner bytes as an IPv6 packet.
+pub struct Ipv6Packet<'a, T: 'a> {
+    bytes: InnerBytes<'a, T>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl<'a, T: NetworkBytes> Ipv6Packet<'a, T> {
+    /// Interpret `bytes` as an Ipv6Packet without checking the validity of the header fields, and
+    /// the length of the inner byte sequence.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic, but further method calls on the resulting object may panic if
+    /// `bytes` contains invalid input.
+    #[inline]
+    pub fn from_bytes_unchecked(bytes: T) -> Self {
+        Ipv6Packet {
+            bytes: InnerBytes::new(bytes),
+        }
+    }
+
+    /// Attempts to interpret `bytes` as an IPv6 packet, checking the validity of the header fields
+    /// and the length of the inner byte sequence.
+    pub fn from_bytes(bytes: T) -> Result<Self, Error> {
+        let bytes_len = bytes.len();
+
+        if bytes_len < HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+
+        let packet = Ipv6Packet::from_bytes_unchecked(bytes);
+
+        if packet.version() != IPV6_VERSION {
+            return Err(Error::Version);
+        }
+
+        // Jumbograms (a payload length of 0 followed by a hop-by-hop option) are not supported.
+        let payload_len = packet.payload_len() as usize;
+        if payload_len == 0 && bytes_len > HEADER_LEN {
+            return Err(Error::InvalidPayloadLen);
+        }
+
+        if HEADER_LEN + payload_len != bytes_len {
+            return Err(Error::SliceExactLen);
+        }
+
+        Ok(packet)
+    }
+
+    #[inline]
+    fn address_unchecked(&self, offset: usize) -> Ipv6Addr {
+        let mut octets = [0u8; ADDRESS_LEN];
+        octets.copy_from_slice(&self.bytes[offset..offset + ADDRESS_LEN]);
+        Ipv6Addr::from(octets)
+    }
+
+    /// Returns the value of the `version` header field.
+    #[inline]
+    pub fn version(&self) -> u8 {
+        self.bytes[VERSION_AND_FLOW_OFFSET] >> 4
+    }
+
+    /// Returns the values of the `traffic class` and `flow label` header fields.
+    #[inline]
+    pub fn traffic_class_and_flow_label(&self) -> (u8, u32) {
+        let x = self.bytes.ntohl_unchecked(VERSION_AND_FLOW_OFFSET);
+        ((x >> 20) as u8, x & 0x000f_ffff)
+    }
+
+    /// Returns the value of the `payload length` header field.
+    #[inline]
+    pub fn payload_len(&self) -> u16 {
+        self.bytes.ntohs_unchecked(PAYLOAD_LEN_OFFSET)
+    }
+
+    /// Returns the value of the `next header` header field.
+    #[inline]
+    pub fn next_header(&self) -> u8 {
+        self.bytes[NEXT_HEADER_OFFSET]
+    }
+
+    /// Returns the value of the `hop limit` header field.
+    #[inline]
+    pub fn hop_limit(&self) -> u8 {
+        self.bytes[HOP_LIMIT_OFFSET]
+    }
+
+    /// Returns the source IPv6 address of the packet.
+    #[inline]
+    pub fn source_address(&self) -> Ipv6Addr {
+        self.address_unchecked(SOURCE_ADDRESS_OFFSET)
+    }
+
+    /// Returns the destination IPv6 address of the packet.
+    #[inline]
+    pub fn destination_address(&self) -> Ipv6Addr {
+        self.address_unchecked(DESTINATION_ADDRESS_OFFSET)
+    }
+
+    /// Returns a byte slice that contains the payload of the packet.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        self.bytes.split_at(HEADER_LEN).1
+    }
+
+    /// Returns the length of the inner byte sequence.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl<'a, T: NetworkBytesMut> Ipv6Packet<'a, T> {
+    /// Attempts to write an IPv6 packet header to `buf`, making sure there is enough space.
+    ///
+    /// This method returns an incomplete packet, because the size of the payload might be unknown
+    /// at this point. The `traffic class` and `flow label` fields are set to 0, and the
+    /// `hop limit` is set to a default value. The `payload length` field will be set when the
+    /// length of the incomplete packet is determined.
+    pub fn write_header(
+        buf: T,
+        next_header: u8,
+        src_addr: Ipv6Addr,
+        dst_addr: Ipv6Addr,
+    ) -> Result<Incomplete<Self>, Error> {
+        if buf.len() < HEADER_LEN {
+            return Err(Error::SliceTooShort);
+        }
+        let mut packet = Ipv6Packet::from_bytes_unchecked(buf);
+        packet
+            .set_version_and_traffic_class_and_flow_label(IPV6_VERSION, 0, 0)
+            .set_next_header(next_header)
+            .set_hop_limit(DEFAULT_HOP_LIMIT)
+            .set_source_address(src_addr)
+            .set_destination_address(dst_addr);
+
+        Ok(Incomplete::new(packet))
+    }
+
+    #[inline]
+    fn set_address_unchecked(&mut self, offset: usize, addr: Ipv6Addr) -> &mut Self {
+        self.bytes[offset..offset + ADDRESS_LEN].copy_from_slice(&addr.octets());
+        self
+    }
+
+    /// Sets the values of the `version`, `traffic class`, and `flow label` header fields.
+    #[inline]
+    pub fn set_version_and_traffic_class_and_flow_label(
+        &mut self,
+        version: u8,
+        traffic_class: u8,
+        flow_label: u32,
+    ) -> &mut Self {
+        let value = (u32::from(version) << 28)
+            | (u32::from(traffic_class) << 20)
+            | (flow_label & 0x000f_ffff);
+        self.bytes.htonl_unchecked(VERSION_AND_FLOW_OFFSET, value);
+        self
+    }
+
+    /// Sets the value of the `payload length` header field.
+    #[inline]
+    pub fn set_payload_len(&mut self, value: u16) -> &mut Self {
+        self.bytes.htons_unchecked(PAYLOAD_LEN_OFFSET, value);
+        self
+    }
+
+    /// Sets the value of the `next header` header field.
+    #[inline]
+    pub fn set_next_header(&mut self, value: u8) -> &mut Self {
+        self.bytes[NEXT_HEADER_OFFSET] = value;
+        self
+    }
+
+    /// Sets the value of the `hop limit` header field.
+    #[inline]
+    pub fn set_hop_limit(&mut self, value: u8) -> &mut Self {
+        self.bytes[HOP_LIMIT_OFFSET] = value;
+        self
+    }
+
+    /// Sets the source address of the packet.
+    #[inline]
+    pub fn set_source_address(&mut self, addr: Ipv6Addr) -> &mut Self {
+        self.set_address_unchecked(SOURCE_ADDRESS_OFFSET, addr)
+    }
+
+    /// Sets the destination address of the packet.
+    #[inline]
+    pub fn set_destination_address(&mut self, addr: Ipv6Addr) -> &mut Self {
+        self.set_address_unchecked(DESTINATION_ADDRESS_OFFSET, addr)
+    }
+
+    /// Returns a mutable byte slice representing the payload of the packet.
+    #[inline]
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        self.bytes.split_at_mut(HEADER_LEN).1
+    }
+}
+
+/// An incomplete packet is one where the payload length has not been determined yet.
+///
+/// It can be transformed into an `Ipv6Packet` by specifying the size of the payload, and
+/// shrinking the inner byte sequence to be as large as the packet itself (this includes setting
+/// the `payload length` header field).
+impl<'a, T: NetworkBytesMut> Incomplete<Ipv6Packet<'a, T>> {
+    /// Transforms `self` into an `Ipv6Packet` based on the supplied payload length.
+    ///
+    /// # Panics
+    ///
+    /// This method may panic if the value of `payload_len` is invalid.
+    #[inline]
+    pub fn with_payload_len_unchecked(mut self, payload_len: usize) -> Ipv6Packet<'a, T> {
+        // This unchecked is fine as long as the total length is smaller than the length of the
+        // original slice, which should be the case if our code is not wrong.
+        self.inner.bytes.shrink_unchecked(HEADER_LEN + payload_len);
+        self.inner.set_payload_len(payload_len as u16);
+        self.inner
+    }
+}
+
+/// Returns the solicited-node multicast address associated with `addr`, which is where the
+/// Neighbor Solicitation messages looking for `addr` are sent.
+pub fn solicited_node_multicast_address(addr: Ipv6Addr) -> Ipv6Addr {
+    let octets = addr.octets();
+    Ipv6Addr::from([
+        0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, octets[13], octets[14], octets[15],
+    ])
+}
+
+/// This function checks if `buf` may hold an Ipv6Packet heading towards the given address. Cannot
+/// produce false negatives.
+#[inline]
+pub fn test_speculative_dst_addr(buf: &[u8], addr: Ipv6Addr) -> bool {
+    // The unchecked methods are safe because we actually check the buffer length beforehand.
+    if buf.len() >= ethernet::PAYLOAD_OFFSET + HEADER_LEN {
+        let bytes = &buf[ethernet::PAYLOAD_OFFSET..];
+        if Ipv6Packet::from_bytes_unchecked(bytes).destination_address() == addr {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use super::*;
+
+    impl<'a, T: NetworkBytes> fmt::Debug for Ipv6Packet<'a, T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(IPv6 packet)")
+        }
+    }
+
+    impl<'a, T: NetworkBytes> fmt::Debug for Incomplete<Ipv6Packet<'a, T>> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "(Incomplete IPv6 packet)")
+        }
+    }
+
+    #[test]
+    fn test_set_get() {
+        let mut a = [0u8; 100];
+        let mut p = Ipv6Packet::from_bytes_unchecked(a.as_mut());
+
+        p.set_version_and_traffic_class_and_flow_label(IPV6_VERSION, 0xab, 0x1_2345);
+        assert_eq!(p.version(), IPV6_VERSION);
+        assert_eq!(p.traffic_class_and_flow_label(), (0xab, 0x1_2345));
+
+        p.set_payload_len(60);
+        assert_eq!(p.payload_len(), 60);
+
+        p.set_next_header(PROTOCOL_ICMPV6);
+        assert_eq!(p.next_header(), PROTOCOL_ICMPV6);
+
+        p.set_hop_limit(255);
+        assert_eq!(p.hop_limit(), 255);
+
+        let src = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        p.set_source_address(src);
+        assert_eq!(p.source_address(), src);
+        p.set_destination_address(dst);
+        assert_eq!(p.destination_address(), dst);
+
+        assert_eq!(p.payload_mut().len(), 60);
+        assert_eq!(p.payload().len(), 60);
+        assert_eq!(p.len(), 100);
+    }
+
+    #[test]
+    fn test_constructors() {
+        let mut a = [0u8; 100];
+        let src = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+
+        // The buffer is too short.
+        assert_eq!(
+            Ipv6Packet::write_header(&mut a[..HEADER_LEN - 1], PROTOCOL_ICMPV6, src, dst)
+                .unwrap_err(),
+            Error::SliceTooShort
+        );
+
+        let len = {
+            let mut incomplete =
+                Ipv6Packet::write_header(a.as_mut(), PROTOCOL_ICMPV6, src, dst).unwrap();
+            incomplete.inner_mut().payload_mut()[..10].copy_from_slice(&[1u8; 10]);
+            let p = incomplete.with_payload_len_unchecked(10);
+            assert_eq!(p.hop_limit(), DEFAULT_HOP_LIMIT);
+            p.len()
+        };
+        assert_eq!(len, HEADER_LEN + 10);
+
+        let p = Ipv6Packet::from_bytes(&a[..len]).unwrap();
+        assert_eq!(p.version(), IPV6_VERSION);
+        assert_eq!(p.traffic_class_and_flow_label(), (0, 0));
+        assert_eq!(p.payload_len(), 10);
+        assert_eq!(p.next_header(), PROTOCOL_ICMPV6);
+        assert_eq!(p.source_address(), src);
+        assert_eq!(p.destination_address(), dst);
+        assert_eq!(p.payload(), &[1u8; 10]);
+
+        // Various errors.
+        assert_eq!(
+            Ipv6Packet::from_bytes(&a[..HEADER_LEN - 1]).unwrap_err(),
+            Error::SliceTooShort
+        );
+        assert_eq!(
+            Ipv6Packet::from_bytes(&a[..len + 1]).unwrap_err(),
+            Error::SliceExactLen
+        );
+        Ipv6Packet::from_bytes_unchecked(a.as_mut()).set_payload_len(0);
+        assert_eq!(
+            Ipv6Packet::from_bytes(&a[..len]).unwrap_err(),
+            Error::InvalidPayloadLen
+        );
+        Ipv6Packet::from_bytes_unchecked(a.as_mut())
+            .set_version_and_traffic_class_and_flow_label(4, 0, 0)
+            .set_payload_len(10);
+        assert_eq!(
+            Ipv6Packet::from_bytes(&a[..len]).unwrap_err(),
+            Error::Version
+        );
+    }
+
+    #[test]
+    fn test_solicited_node_multicast_address() {
+        let addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0x1234, 0x5678);
+        assert_eq!(
+            solicited_node_multicast_address(addr),
+            Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff34, 0x5678)
+        );
+    }
+
+    #[test]
+    fn test_speculative() {
+        let mut buf = [0u8; 1000];
+        let addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+
+        assert!(!test_speculative_dst_addr(&buf[..], addr));
+        assert!(!test_speculative_dst_addr(
+            &buf[..ethernet::PAYLOAD_OFFSET + HEADER_LEN - 1],
+            addr
+        ));
+
+        Ipv6Packet::from_bytes_unchecked(&mut buf[ethernet::PAYLOAD_OFFSET..])
+            .set_destination_address(addr);
+        assert!(test_speculative_dst_addr(&buf[..], addr));
+    }
+}
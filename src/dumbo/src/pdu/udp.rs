@@ -118,7 +118,12 @@ impl<'a, T: NetworkBytes> UdpDatagram<'a, T> {
     /// Computes the checksum of a UDP datagram.
     #[inline]
     pub fn compute_checksum(&self, src_addr: Ipv4Addr, dst_addr: Ipv4Addr) -> u16 {
-        crate::pdu::compute_checksum(&self.bytes, src_addr, dst_addr, ChecksumProto::Udp)
+        crate::pdu::compute_checksum(
+            &self.bytes,
+            src_addr.into(),
+            dst_addr.into(),
+            ChecksumProto::Udp,
+        )
     }
 }
 
@@ -356,6 +356,11 @@ fn parse_request_bytes(byte_stream: &[u8], callback: fn(Request) -> Response) ->
                 StatusCode::BadRequest,
                 Body::new(e.to_string()),
             ),
+            RequestError::PayloadTooLarge(_, _) => build_response(
+                Version::default(),
+                StatusCode::PayloadTooLarge,
+                Body::new(e.to_string()),
+            ),
             RequestError::Underflow => build_response(
                 Version::default(),
                 StatusCode::BadRequest,
@@ -607,7 +612,7 @@ mod tests {
         assert_eq!(actual_response, expected_response);
 
         // Test invalid HTTP methods.
-        let invalid_methods = ["POST", "HEAD", "DELETE", "CONNECT", "OPTIONS", "TRACE"];
+        let invalid_methods = ["HEAD", "DELETE", "CONNECT", "OPTIONS", "TRACE"];
         for method in invalid_methods.iter() {
             let request_bytes = format!("{} http://169.254.169.255/ HTTP/1.0\r\n\r\n", method);
             let mut expected_response = Response::new(Version::Http11, StatusCode::NotImplemented);
@@ -617,7 +622,7 @@ mod tests {
         }
 
         // Test valid methods.
-        let valid_methods = ["PUT", "PATCH", "GET"];
+        let valid_methods = ["PUT", "PATCH", "POST", "GET"];
         for method in valid_methods.iter() {
             let request_bytes = format!("{} http://169.254.169.255/ HTTP/1.0\r\n\r\n", method);
             let expected_response = Response::new(Version::Http11, StatusCode::OK);
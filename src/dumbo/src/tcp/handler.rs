@@ -1,23 +1,23 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-//! Exposes simple TCP over IPv4 listener functionality via the [`TcpIPv4Handler`] structure.
+//! Exposes simple TCP over IPv4 (and optionally IPv6) listener functionality via the
+//! [`TcpIPv4Handler`] structure.
 //!
 //! [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
 
 use std::collections::{HashMap, HashSet};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 
 use crate::pdu::bytes::NetworkBytes;
-use crate::pdu::ipv4::{Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP};
+use crate::pdu::ipv4::{Error as IPv4PacketError, IPv4Packet, DEFAULT_TTL, PROTOCOL_TCP};
+use crate::pdu::ipv6::{Error as Ipv6PacketError, Ipv6Packet};
 use crate::pdu::tcp::{Error as TcpSegmentError, Flags as TcpFlags, TcpSegment};
 use crate::tcp::endpoint::Endpoint;
 use crate::tcp::{NextSegmentStatus, RstConfig};
 use micro_http::{Request, Response};
 
-// TODO: This is currently IPv4 specific. Maybe change it to a more generic implementation.
-
 /// Describes events which may occur when the handler receives packets.
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum RecvEvent {
@@ -57,6 +57,8 @@ pub enum WriteEvent {
 /// [`TcpIPv4Handler`]: struct.TcpIPv4Handler.html
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub enum RecvError {
+    /// The handler has no local address of the same family as the incoming packet.
+    InvalidAddress,
     /// The inner segment has an invalid destination port.
     InvalidPort,
     /// The handler encountered an error while parsing the inner TCP segment.
@@ -72,22 +74,25 @@ pub enum RecvError {
 pub enum WriteNextError {
     /// There was an error while writing the contents of the IPv4 packet.
     IPv4Packet(IPv4PacketError),
+    /// There was an error while writing the contents of the IPv6 packet.
+    Ipv6Packet(Ipv6PacketError),
     /// There was an error while writing the contents of the inner TCP segment.
     TcpSegment(TcpSegmentError),
 }
 
 // Generally speaking, a TCP/IPv4 connection is identified using the four-tuple (src_addr, src_port,
-// dst_addr, dst_port). However, the IPv4 address and TCP port of the MMDS endpoint are fixed, so
+// dst_addr, dst_port). However, the IP addresses and TCP port of the MMDS endpoint are fixed, so
 // we can get away with uniquely identifying connections using just the remote address and port.
+// The family of the remote address also determines which local address is used.
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 #[cfg_attr(test, derive(Debug))]
 struct ConnectionTuple {
-    remote_addr: Ipv4Addr,
+    remote_addr: IpAddr,
     remote_port: u16,
 }
 
 impl ConnectionTuple {
-    fn new(remote_addr: Ipv4Addr, remote_port: u16) -> Self {
+    fn new(remote_addr: IpAddr, remote_port: u16) -> Self {
         ConnectionTuple {
             remote_addr,
             remote_port,
@@ -95,20 +100,21 @@ impl ConnectionTuple {
     }
 }
 
-/// Implements a minimalist TCP over IPv4 listener.
+/// Implements a minimalist TCP over IPv4 listener, which also accepts connections over IPv6 when
+/// a local IPv6 address is set.
 ///
 /// Forwards incoming TCP segments to the appropriate connection object, based on the associated
 /// tuple, or attempts to establish new connections (when receiving `SYN` segments). Aside from
 /// constructors, the handler operation is based on three methods:
 ///
-/// * [`receive_packet`] examines an incoming IPv4 packet. It checks whether the destination
+/// * [`receive_packet`] (or [`receive_ipv6_packet`]) examines an incoming IP packet. It checks whether the destination
 ///   address is correct, the attempts examine the inner TCP segment, making sure the destination
 ///   port number is also correct. Then, it steers valid segments towards exiting connections,
 ///   creates new connections for incoming `SYN` segments, and enqueues `RST` replies in response
 ///   to any segments which cannot be associated with a connection (except other `RST` segments).
 ///   On success, also describes any internal status changes triggered by the reception of the
 ///   packet.
-/// * [`write_next_packet`] writes the next IPv4 packet (if available) that would be sent by the
+/// * [`write_next_packet`] writes the next IP packet (if available) that would be sent by the
 ///   handler itself (right now it can only mean an enqueued `RST`), or one of the existing
 ///   connections. On success, also describes any internal status changes triggered as the packet
 ///   gets transmitted.
@@ -118,11 +124,15 @@ impl ConnectionTuple {
 ///   [`write_next_packet`].
 ///
 /// [`receive_packet`]: ../handler/struct.TcpIPv4Handler.html#method.receive_packet
+/// [`receive_ipv6_packet`]: ../handler/struct.TcpIPv4Handler.html#method.receive_ipv6_packet
 /// [`write_next_packet`]: ../handler/struct.TcpIPv4Handler.html#method.write_next_packet
 /// [`next_segment_status`]: ../handler/struct.TcpIPv4Handler.html#method.next_segment_status
 pub struct TcpIPv4Handler {
-    // Handler IPv4 address used for every connection.
+    // Handler IPv4 address used for every IPv4 connection.
     local_ipv4_addr: Ipv4Addr,
+    // Handler IPv6 address used for every IPv6 connection. IPv6 packets are not accepted when
+    // this is missing.
+    local_ipv6_addr: Option<Ipv6Addr>,
     // Handler TCP port used for every connection.
     local_port: u16,
     // This map holds the currently active endpoints, identified by their connection tuple.
@@ -138,6 +148,8 @@ pub struct TcpIPv4Handler {
     rst_queue: Vec<(ConnectionTuple, RstConfig)>,
     // Maximum size of the RST queue.
     max_pending_resets: usize,
+    // The TTL of the packets written by the handler, which limits how many hops they can travel.
+    ttl: u8,
 }
 
 // Only used locally, in the receive_packet method, to differentiate between different outcomes
@@ -166,6 +178,7 @@ impl TcpIPv4Handler {
         let max_pending_resets = max_pending_resets.get();
         TcpIPv4Handler {
             local_ipv4_addr,
+            local_ipv6_addr: None,
             local_port,
             connections: HashMap::with_capacity(max_connections),
             max_connections,
@@ -173,6 +186,7 @@ impl TcpIPv4Handler {
             next_timeout: None,
             rst_queue: Vec::with_capacity(max_pending_resets),
             max_pending_resets,
+            ttl: DEFAULT_TTL,
         }
     }
 
@@ -186,6 +200,24 @@ impl TcpIPv4Handler {
         self.local_ipv4_addr
     }
 
+    /// Setter for the local IPv6 address of this TCP handler. Clearing the address drops the
+    /// existing IPv6 connections.
+    pub fn set_local_ipv6_addr(&mut self, ipv6_addr: Option<Ipv6Addr>) {
+        if self.local_ipv6_addr != ipv6_addr {
+            let is_ipv6 = |tuple: &ConnectionTuple| tuple.remote_addr.is_ipv6();
+            self.connections.retain(|tuple, _| !is_ipv6(tuple));
+            self.active_connections.retain(|tuple| !is_ipv6(tuple));
+            self.rst_queue.retain(|(tuple, _)| !is_ipv6(tuple));
+            self.find_next_timeout();
+        }
+        self.local_ipv6_addr = ipv6_addr;
+    }
+
+    /// Returns the local IPv6 address of this TCP handler, if any.
+    pub fn local_ipv6_addr(&self) -> Option<Ipv6Addr> {
+        self.local_ipv6_addr
+    }
+
     /// Returns the local port of this TCP handler.
     pub fn local_port(&self) -> u16 {
         self.local_port
@@ -201,6 +233,16 @@ impl TcpIPv4Handler {
         self.max_pending_resets
     }
 
+    /// Setter for the TTL of the packets written by this TCP handler.
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+
+    /// Returns the TTL of the packets written by this TCP handler.
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+
     /// Contains logic for handling incoming segments.
     ///
     /// Any changes to the state if the handler are communicated through an `Ok(RecvEvent)`.
@@ -208,18 +250,48 @@ impl TcpIPv4Handler {
         &mut self,
         packet: &IPv4Packet<T>,
         callback: fn(Request) -> Response,
+    ) -> Result<RecvEvent, RecvError> {
+        self.receive_segment(
+            IpAddr::V4(packet.source_address()),
+            packet.payload(),
+            callback,
+        )
+    }
+
+    /// Handles an incoming IPv6 packet, just like [`receive_packet`] does for IPv4 packets.
+    ///
+    /// [`receive_packet`]: struct.TcpIPv4Handler.html#method.receive_packet
+    pub fn receive_ipv6_packet<T: NetworkBytes>(
+        &mut self,
+        packet: &Ipv6Packet<T>,
+        callback: fn(Request) -> Response,
+    ) -> Result<RecvEvent, RecvError> {
+        if self.local_ipv6_addr.is_none() {
+            return Err(RecvError::InvalidAddress);
+        }
+        self.receive_segment(
+            IpAddr::V6(packet.source_address()),
+            packet.payload(),
+            callback,
+        )
+    }
+
+    fn receive_segment(
+        &mut self,
+        remote_addr: IpAddr,
+        payload: &[u8],
+        callback: fn(Request) -> Response,
     ) -> Result<RecvEvent, RecvError> {
         // TODO: We skip verifying the checksum, just in case the device model relies on offloading
         // checksum computation from the guest to some other entity. Clear this up at some point!
         // (Issue #520)
-        let segment =
-            TcpSegment::from_bytes(packet.payload(), None).map_err(RecvError::TcpSegment)?;
+        let segment = TcpSegment::from_bytes(payload, None).map_err(RecvError::TcpSegment)?;
 
         if segment.destination_port() != self.local_port {
             return Err(RecvError::InvalidPort);
         }
 
-        let tuple = ConnectionTuple::new(packet.source_address(), segment.source_port());
+        let tuple = ConnectionTuple::new(remote_addr, segment.source_port());
 
         let outcome = if let Some(endpoint) = self.connections.get_mut(&tuple) {
             endpoint.receive_segment(&segment, callback);
@@ -386,10 +458,15 @@ impl TcpIPv4Handler {
         let mut writer_status = None;
         let mut event = WriteEvent::Nothing;
 
-        // Write an incomplete Ipv4 packet and complete it afterwards with missing information.
-        let mut packet =
-            IPv4Packet::write_header(buf, PROTOCOL_TCP, Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST)
-                .map_err(WriteNextError::IPv4Packet)?;
+        let local_ipv4_addr = self.local_ipv4_addr;
+        // Tuples with an IPv6 remote address only exist while the local IPv6 address is set.
+        let local_ipv6_addr = self.local_ipv6_addr.unwrap_or(Ipv6Addr::UNSPECIFIED);
+        let local_addr = |remote_addr: IpAddr| match remote_addr {
+            IpAddr::V4(_) => IpAddr::V4(local_ipv4_addr),
+            IpAddr::V6(_) => IpAddr::V6(local_ipv6_addr),
+        };
+        let local_port = self.local_port;
+        let ttl = self.ttl;
 
         // We set mss_used to 0, because we don't add any IP options.
         // TODO: Maybe get this nicely from packet at some point.
@@ -400,35 +477,29 @@ impl TcpIPv4Handler {
         // any TCP options, or a payload.
         if let Some((tuple, rst_cfg)) = self.rst_queue.pop() {
             let (seq, ack, flags_after_ns) = rst_cfg.seq_ack_tcp_flags();
-            let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
-                packet.inner_mut().payload_mut(),
-                seq,
-                ack,
-                flags_after_ns,
-                10000,
-                None,
-                0,
-                None,
-            )
-            .map_err(WriteNextError::TcpSegment)?
-            .finalize(
-                self.local_port,
-                tuple.remote_port,
-                Some((self.local_ipv4_addr, tuple.remote_addr)),
-            )
-            .len();
-
-            packet
-                .inner_mut()
-                .set_source_address(self.local_ipv4_addr)
-                .set_destination_address(tuple.remote_addr);
-
-            let packet_len = packet.with_payload_len_unchecked(segment_len, true).len();
-            // The unwrap() is safe because packet_len > 0.
-            return Ok((
-                Some(NonZeroUsize::new(packet_len).unwrap()),
-                WriteEvent::Nothing,
-            ));
+            let src_addr = local_addr(tuple.remote_addr);
+            let packet_len = write_ip_packet(buf, src_addr, tuple.remote_addr, ttl, |buf| {
+                let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
+                    buf,
+                    seq,
+                    ack,
+                    flags_after_ns,
+                    10000,
+                    None,
+                    0,
+                    None,
+                )
+                .map_err(WriteNextError::TcpSegment)?
+                .finalize(
+                    local_port,
+                    tuple.remote_port,
+                    Some((src_addr, tuple.remote_addr)),
+                )
+                .len();
+                Ok(Some(segment_len))
+            })?;
+
+            return Ok((packet_len, WriteEvent::Nothing));
         }
 
         for tuple in self
@@ -439,33 +510,27 @@ impl TcpIPv4Handler {
             // Tuples in self.active_connection or self.next_timeout should also appear as keys
             // in self.connections.
             let endpoint = self.connections.get_mut(tuple).unwrap();
-            // We need this block to clearly delimit the lifetime of the mutable borrow started by
-            // the following packet.inner_mut().
-            let segment_len = {
-                let maybe_segment =
-                    endpoint.write_next_segment(packet.inner_mut().payload_mut(), mss_reserved);
-
-                match maybe_segment {
-                    Some(segment) => segment
-                        .finalize(
-                            self.local_port,
-                            tuple.remote_port,
-                            Some((self.local_ipv4_addr, tuple.remote_addr)),
-                        )
-                        .len(),
-                    None => continue,
-                }
-            };
-
-            packet
-                .inner_mut()
-                .set_source_address(self.local_ipv4_addr)
-                .set_destination_address(tuple.remote_addr);
-
-            let ip_len = packet.with_payload_len_unchecked(segment_len, true).len();
+            let src_addr = local_addr(tuple.remote_addr);
+
+            let packet_len = write_ip_packet(buf, src_addr, tuple.remote_addr, ttl, |buf| {
+                Ok(endpoint
+                    .write_next_segment(buf, mss_reserved)
+                    .map(|segment| {
+                        segment
+                            .finalize(
+                                local_port,
+                                tuple.remote_port,
+                                Some((src_addr, tuple.remote_addr)),
+                            )
+                            .len()
+                    }))
+            })?;
+
+            if packet_len.is_none() {
+                continue;
+            }
 
-            // The unwrap is safe because ip_len > 0.
-            len = Some(NonZeroUsize::new(ip_len).unwrap());
+            len = packet_len;
             writer_status = Some((*tuple, endpoint.is_done()));
 
             break;
@@ -503,6 +568,42 @@ impl TcpIPv4Handler {
     }
 }
 
+// Writes an IP packet from `src_addr` to `dst_addr` (which must belong to the same family) to
+// `buf`. The inner TCP segment is written by `write_segment`, which receives the payload buffer
+// of the packet, and returns the length of the segment, or `None` if there's nothing to send.
+fn write_ip_packet<F>(
+    buf: &mut [u8],
+    src_addr: IpAddr,
+    dst_addr: IpAddr,
+    ttl: u8,
+    write_segment: F,
+) -> Result<Option<NonZeroUsize>, WriteNextError>
+where
+    F: FnOnce(&mut [u8]) -> Result<Option<usize>, WriteNextError>,
+{
+    let packet_len = match (src_addr, dst_addr) {
+        (IpAddr::V4(src_addr), IpAddr::V4(dst_addr)) => {
+            let mut packet = IPv4Packet::write_header(buf, PROTOCOL_TCP, src_addr, dst_addr)
+                .map_err(WriteNextError::IPv4Packet)?;
+            packet.inner_mut().set_ttl(ttl);
+            write_segment(packet.inner_mut().payload_mut())?
+                .map(|segment_len| packet.with_payload_len_unchecked(segment_len, true).len())
+        }
+        (IpAddr::V6(src_addr), IpAddr::V6(dst_addr)) => {
+            let mut packet = Ipv6Packet::write_header(buf, PROTOCOL_TCP, src_addr, dst_addr)
+                .map_err(WriteNextError::Ipv6Packet)?;
+            packet.inner_mut().set_hop_limit(ttl);
+            write_segment(packet.inner_mut().payload_mut())?
+                .map(|segment_len| packet.with_payload_len_unchecked(segment_len).len())
+        }
+        // Connection tuples never mix address families.
+        _ => unreachable!(),
+    };
+
+    // The unwrap() is safe because packet_len > 0.
+    Ok(packet_len.map(|len| NonZeroUsize::new(len).unwrap()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -667,9 +768,18 @@ mod tests {
         );
         assert_eq!(h.rst_queue.len(), 2);
 
+        // Outgoing packets carry the configured TTL.
+        assert_eq!(h.ttl(), DEFAULT_TTL);
+        h.set_ttl(1);
+        {
+            let (o, _) = write_next(&mut h, buf2.as_mut()).unwrap();
+            assert_eq!(o.unwrap().ttl(), 1);
+        }
+        h.set_ttl(DEFAULT_TTL);
+
         // Drain the resets.
         assert_eq!(h.next_segment_status(), NextSegmentStatus::Available);
-        assert_eq!(drain_packets(&mut h, local_addr, remote_addr), Ok(2));
+        assert_eq!(drain_packets(&mut h, local_addr, remote_addr), Ok(1));
         assert_eq!(h.next_segment_status(), NextSegmentStatus::Nothing);
 
         // Ok now let's send a valid SYN.
@@ -709,8 +819,8 @@ mod tests {
         assert_eq!(h.next_segment_status(), NextSegmentStatus::Available);
         assert_eq!(drain_packets(&mut h, local_addr, remote_addr), Ok(1));
 
-        let remote_tuple = ConnectionTuple::new(remote_addr, remote_port);
-        let remote_tuple2 = ConnectionTuple::new(remote_addr, remote_port + 1);
+        let remote_tuple = ConnectionTuple::new(remote_addr.into(), remote_port);
+        let remote_tuple2 = ConnectionTuple::new(remote_addr.into(), remote_port + 1);
 
         // Also, there should be a retransmission timer associated with the previous SYNACK now.
         assert_eq!(h.active_connections.len(), 0);
@@ -771,7 +881,7 @@ mod tests {
         // The timeout associated with the SYNACK of the second connection should be next.
         assert_eq!(h.active_connections.len(), 0);
         if let Some((_, tuple)) = h.next_timeout {
-            assert_ne!(tuple, ConnectionTuple::new(remote_addr, remote_port));
+            assert_ne!(tuple, ConnectionTuple::new(remote_addr.into(), remote_port));
         } else {
             panic!("missing third expected timeout");
         }
@@ -823,4 +933,79 @@ mod tests {
         assert_eq!(h.connections.len(), 1);
         assert_eq!(h.active_connections.len(), 0);
     }
+
+    #[test]
+    fn test_ipv6_handler() {
+        let mut buf = [0u8; 100];
+        let mut buf2 = [0u8; 2000];
+
+        let local_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        let local_port = 80;
+        let remote_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x2);
+        let remote_port = 1012;
+
+        let mut h = TcpIPv4Handler::new(
+            Ipv4Addr::new(169, 254, 169, 254),
+            local_port,
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(2).unwrap(),
+        );
+
+        let mut p =
+            Ipv6Packet::write_header(buf.as_mut(), PROTOCOL_TCP, remote_addr, local_addr).unwrap();
+        let s_len = TcpSegment::write_segment::<[u8]>(
+            p.inner_mut().payload_mut(),
+            remote_port,
+            local_port,
+            123,
+            0,
+            TcpFlags::SYN,
+            10000,
+            None,
+            100,
+            None,
+            None,
+        )
+        .unwrap()
+        .len();
+        let p = p.with_payload_len_unchecked(s_len);
+
+        // IPv6 packets are rejected until the handler gets an IPv6 address.
+        assert_eq!(h.local_ipv6_addr(), None);
+        assert_eq!(
+            h.receive_ipv6_packet(&p, mock_callback).unwrap_err(),
+            RecvError::InvalidAddress
+        );
+
+        h.set_local_ipv6_addr(Some(local_addr));
+        assert_eq!(h.local_ipv6_addr(), Some(local_addr));
+        assert_eq!(
+            h.receive_ipv6_packet(&p, mock_callback),
+            Ok(RecvEvent::NewConnectionSuccessful)
+        );
+        assert_eq!(h.connections.len(), 1);
+
+        // The SYNACK goes out in an IPv6 packet, with a valid TCP checksum.
+        h.set_ttl(64);
+        let (len, event) = h.write_next_packet(buf2.as_mut()).unwrap();
+        assert_eq!(event, WriteEvent::Nothing);
+        let reply = Ipv6Packet::from_bytes(&buf2[..len.unwrap().get()]).unwrap();
+        assert_eq!(reply.next_header(), PROTOCOL_TCP);
+        assert_eq!(reply.hop_limit(), 64);
+        assert_eq!(reply.source_address(), local_addr);
+        assert_eq!(reply.destination_address(), remote_addr);
+        let s = TcpSegment::from_bytes(
+            reply.payload(),
+            Some((local_addr.into(), remote_addr.into())),
+        )
+        .unwrap();
+        assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(s.source_port(), local_port);
+        assert_eq!(s.destination_port(), remote_port);
+
+        // Clearing the IPv6 address drops the IPv6 connections.
+        h.set_local_ipv6_addr(None);
+        assert!(h.connections.is_empty());
+        assert_eq!(h.next_segment_status(), NextSegmentStatus::Nothing);
+    }
 }
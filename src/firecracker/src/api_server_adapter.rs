@@ -4,63 +4,104 @@
 use std::{
     os::unix::io::AsRawFd,
     path::PathBuf,
-    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
     sync::{Arc, Mutex, RwLock},
     thread,
+    time::Duration,
 };
 
-use api_server::{ApiRequest, ApiResponse, ApiServer};
-use logger::{error, warn};
-use mmds::MMDS;
-use polly::event_manager::{EventManager, Subscriber};
+use api_server::{
+    ApiAuthPolicy, ApiLimits, ApiRequest, ApiResponse, ApiServer, AuditLog, ServerTransport,
+};
+use logger::{error, set_request_id, warn, METRICS};
+use mmds::{data_store::Mmds, MMDS};
+use polly::event_manager::{Backend, EventManager, Subscriber};
 use seccomp::BpfProgram;
 use utils::{
     epoll::{EpollEvent, EventSet},
     eventfd::EventFd,
 };
 use vmm::{
+    resources::VmResources,
     rpc_interface::{PrebootApiController, RuntimeApiController, VmmAction},
-    vmm_config::instance_info::InstanceInfo,
-    vmm_config::machine_config::VmConfig,
+    signal_handler::sigterm_pending,
+    vmm_config::{instance_info::InstanceInfo, snapshot::LoadSnapshotParams},
     Vmm,
 };
 
+// How often the paused loop checks whether `SIGTERM` was intercepted.
+const SIGTERM_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 struct ApiServerAdapter {
     api_event_fd: EventFd,
     from_api: Receiver<ApiRequest>,
     to_api: Sender<ApiResponse>,
     controller: RuntimeApiController,
+    // Set once the microVM was torn down, for the VMM to build another one.
+    torn_down: bool,
 }
 
 impl ApiServerAdapter {
-    /// Runs the vmm to completion, while any arising control events are deferred
-    /// to a `RuntimeApiController`.
+    /// Runs the vmm until the microVM is torn down, while any arising control events are
+    /// deferred to a `RuntimeApiController`. Returns the ends of the API channels once the
+    /// microVM and its event subscribers are released, along with the snapshot to restore
+    /// when a guest panic tore the microVM down.
     fn run_microvm(
         api_event_fd: EventFd,
         from_api: Receiver<ApiRequest>,
         to_api: Sender<ApiResponse>,
-        vm_config: VmConfig,
+        vm_resources: VmResources,
         vmm: Arc<Mutex<Vmm>>,
         event_manager: &mut EventManager,
+    ) -> (
+        EventFd,
+        Receiver<ApiRequest>,
+        Sender<ApiResponse>,
+        Option<LoadSnapshotParams>,
     ) {
         let api_adapter = Arc::new(Mutex::new(Self {
             api_event_fd,
             from_api,
             to_api,
-            controller: RuntimeApiController::new(vm_config, vmm),
+            controller: RuntimeApiController::new(vm_resources, vmm.clone()),
+            torn_down: false,
         }));
         event_manager
-            .add_subscriber(api_adapter)
+            .add_subscriber(api_adapter.clone())
             .expect("Cannot register the api event to the event manager.");
-        loop {
+        let mut restore_request = None;
+        while restore_request.is_none() && !api_adapter.lock().expect("Poisoned lock").torn_down {
             event_manager
                 .run()
                 .expect("EventManager events driver fatal error");
+            restore_request = vmm.lock().expect("Poisoned lock").take_restore_request();
         }
+
+        // Removing the subscribers of the microVM drops them, and dropping the controller then
+        // drops the `Vmm`.
+        drop(vmm);
+        event_manager
+            .remove_all_subscribers()
+            .expect("Cannot remove the subscribers of the torn down microVM.");
+        let api_adapter = Arc::try_unwrap(api_adapter)
+            .unwrap_or_else(|_| panic!("The API adapter is still registered."))
+            .into_inner()
+            .expect("Poisoned lock");
+        (
+            api_adapter.api_event_fd,
+            api_adapter.from_api,
+            api_adapter.to_api,
+            restore_request,
+        )
     }
 
-    fn handle_request(&mut self, req_action: VmmAction) {
-        let response = self.controller.handle_request(req_action);
+    fn handle_request(&mut self, request: ApiRequest) {
+        // The spans of the VMM thread carry the ID of the request while serving it.
+        set_request_id(request.request_id);
+        let request_is_teardown = *request.action == VmmAction::Teardown;
+        let response = self.controller.handle_request(*request.action);
+        set_request_id(None);
+        self.torn_down |= request_is_teardown && response.is_ok();
         // Send back the result.
         self.to_api
             .send(Box::new(response))
@@ -77,8 +118,8 @@ impl Subscriber for ApiServerAdapter {
         if source == self.api_event_fd.as_raw_fd() && event_set == EventSet::IN {
             match self.from_api.try_recv() {
                 Ok(api_request) => {
-                    let request_is_pause = *api_request == VmmAction::Pause;
-                    self.handle_request(*api_request);
+                    let request_is_pause = *api_request.action == VmmAction::Pause;
+                    self.handle_request(api_request);
 
                     // If the latest req is a pause request, temporarily switch to a mode where we
                     // do blocking `recv`s on the `from_api` receiver in a loop, until we get
@@ -87,12 +128,21 @@ impl Subscriber for ApiServerAdapter {
                     // `process`.
                     if request_is_pause {
                         // This loop only attempts to process API requests, so things like the
-                        // metric flush timerfd handling are frozen as well.
+                        // metric flush timerfd handling are frozen as well. It still returns to
+                        // the event manager on `SIGTERM`, which the `Vmm` handles.
                         loop {
-                            let req = self.from_api.recv().expect("Error receiving API request.");
-                            let req_is_resume = *req == VmmAction::Resume;
-                            self.handle_request(*req);
-                            if req_is_resume {
+                            let req = match self.from_api.recv_timeout(SIGTERM_POLL_INTERVAL) {
+                                Ok(req) => req,
+                                Err(RecvTimeoutError::Timeout) if sigterm_pending() => break,
+                                Err(RecvTimeoutError::Timeout) => continue,
+                                Err(RecvTimeoutError::Disconnected) => {
+                                    panic!("Error receiving API request.")
+                                }
+                            };
+                            let req_is_resume = *req.action == VmmAction::Resume;
+                            self.handle_request(req);
+                            // The paused microVM may also be torn down.
+                            if req_is_resume || self.torn_down {
                                 break;
                             }
                         }
@@ -119,14 +169,23 @@ impl Subscriber for ApiServerAdapter {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn run_with_api(
     seccomp_filter: BpfProgram,
     config_json: Option<String>,
     bind_path: PathBuf,
+    api_transports: Vec<ServerTransport>,
+    api_auth_policy: Option<ApiAuthPolicy>,
+    api_limits: ApiLimits,
+    audit_log: Option<AuditLog>,
     instance_info: InstanceInfo,
     start_time_us: Option<u64>,
     start_time_cpu_us: Option<u64>,
     boot_timer_enabled: bool,
+    gdb_socket: Option<PathBuf>,
+    landlock: Option<Vec<PathBuf>>,
+    allow_teardown: bool,
+    event_backend: Backend,
 ) {
     // FD to notify of API events. This is a blocking eventfd by design.
     // It is used in the config/pre-boot loop which is a simple blocking loop
@@ -145,20 +204,31 @@ pub(crate) fn run_with_api(
         .expect("Failed to clone API event FD");
 
     let api_seccomp_filter = seccomp_filter.clone();
+    // The VMM is ready once the microVM configured from the JSON is started, if there is one.
+    let notify_ready = config_json.is_none();
     // Start the separate API thread.
     thread::Builder::new()
         .name("fc_api".to_owned())
         .spawn(move || {
-            match ApiServer::new(
+            let mut api_server = ApiServer::new(
                 mmds_info,
                 vmm_shared_info,
                 to_vmm,
                 from_vmm,
                 to_vmm_event_fd,
             )
-            .expect("Cannot create API server")
-            .bind_and_run(
+            .expect("Cannot create API server");
+            if let Some(policy) = api_auth_policy {
+                api_server.set_auth_policy(policy);
+            }
+            api_server.set_limits(api_limits);
+            api_server.set_notify_ready(notify_ready);
+            if let Some(audit_log) = audit_log {
+                api_server.set_audit_log(audit_log);
+            }
+            match api_server.bind_and_run(
                 bind_path,
+                &api_transports,
                 start_time_us,
                 start_time_cpu_us,
                 api_seccomp_filter,
@@ -181,62 +251,102 @@ pub(crate) fn run_with_api(
         })
         .expect("API thread spawn failed.");
 
-    let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+    let (mut api_event_fd, mut from_api, mut to_api) = (api_event_fd, from_api, to_api);
+    // Only the first microVM is configured from the JSON, if there is one.
+    let mut config_json = config_json;
+    // Set when a guest panic tore down the microVM, for the next one to be restored.
+    let mut restore_request = None;
+    // The event manager serves all the microVMs, so that its poller is only created before the
+    // seccomp filters are loaded.
+    let mut event_manager =
+        EventManager::with_backend(event_backend).expect("Unable to create EventManager");
+    // Each iteration builds a microVM and runs it until it is torn down.
+    loop {
+        // Create the firecracker metrics object responsible for periodically printing metrics.
+        let firecracker_metrics = Arc::new(Mutex::new(super::metrics::PeriodicMetrics::new()));
+        event_manager
+            .add_subscriber(firecracker_metrics.clone())
+            .expect("Cannot register the metrics event to the event manager.");
+        super::add_sd_watchdog(&mut event_manager);
+        super::add_log_rotation(&mut event_manager);
 
-    // Create the firecracker metrics object responsible for periodically printing metrics.
-    let firecracker_metrics = Arc::new(Mutex::new(super::metrics::PeriodicMetrics::new()));
-    event_manager
-        .add_subscriber(firecracker_metrics.clone())
-        .expect("Cannot register the metrics event to the event manager.");
+        // Configure, build and start the microVM.
+        let (vm_resources, vmm) = match (config_json.take(), restore_request.take()) {
+            (Some(json), _) => super::build_microvm_from_json(
+                seccomp_filter.clone(),
+                &mut event_manager,
+                json,
+                &instance_info,
+                boot_timer_enabled,
+                gdb_socket.clone(),
+                landlock.clone(),
+                allow_teardown,
+            ),
+            #[cfg(target_arch = "x86_64")]
+            (None, Some(load_params)) => super::restore_microvm(
+                seccomp_filter.clone(),
+                &mut event_manager,
+                &load_params,
+                &instance_info,
+                boot_timer_enabled,
+                gdb_socket.clone(),
+                landlock.clone(),
+                allow_teardown,
+            ),
+            (None, _) => PrebootApiController::build_microvm_from_requests(
+                seccomp_filter.clone(),
+                &mut event_manager,
+                instance_info.clone(),
+                || {
+                    let req = from_api.recv().expect(
+                        "The channel's sending half was disconnected. Cannot receive data.",
+                    );
+                    // Also consume the API event along with the message. It is safe to unwrap()
+                    // because this event_fd is blocking.
+                    api_event_fd
+                        .read()
+                        .expect("VMM: Failed to read the API event_fd");
+                    // The spans of the VMM thread carry the ID of the request until its response.
+                    set_request_id(req.request_id);
+                    *req.action
+                },
+                |response| {
+                    set_request_id(None);
+                    to_api
+                        .send(Box::new(response))
+                        .expect("one-shot channel closed")
+                },
+                boot_timer_enabled,
+                gdb_socket.clone(),
+                landlock.clone(),
+                allow_teardown,
+            ),
+        };
 
-    // Configure, build and start the microVM.
-    let (vm_resources, vmm) = match config_json {
-        Some(json) => super::build_microvm_from_json(
-            seccomp_filter,
-            &mut event_manager,
-            json,
-            &instance_info,
-            boot_timer_enabled,
-        ),
-        None => PrebootApiController::build_microvm_from_requests(
-            seccomp_filter,
+        // Start the metrics.
+        firecracker_metrics
+            .lock()
+            .expect("Poisoned lock")
+            .start(METRICS.flush_interval_ms());
+
+        // Update the api shared instance info.
+        api_shared_info.write().unwrap().started = true;
+
+        let api_channels = ApiServerAdapter::run_microvm(
+            api_event_fd,
+            from_api,
+            to_api,
+            vm_resources,
+            vmm,
             &mut event_manager,
-            instance_info,
-            || {
-                let req = from_api
-                    .recv()
-                    .expect("The channel's sending half was disconnected. Cannot receive data.");
-                // Also consume the API event along with the message. It is safe to unwrap()
-                // because this event_fd is blocking.
-                api_event_fd
-                    .read()
-                    .expect("VMM: Failed to read the API event_fd");
-                *req
-            },
-            |response| {
-                to_api
-                    .send(Box::new(response))
-                    .expect("one-shot channel closed")
-            },
-            boot_timer_enabled,
-        ),
-    };
-
-    // Start the metrics.
-    firecracker_metrics
-        .lock()
-        .expect("Poisoned lock")
-        .start(super::metrics::WRITE_METRICS_PERIOD_MS);
-
-    // Update the api shared instance info.
-    api_shared_info.write().unwrap().started = true;
-
-    ApiServerAdapter::run_microvm(
-        api_event_fd,
-        from_api,
-        to_api,
-        vm_resources.vm_config().clone(),
-        vmm,
-        &mut event_manager,
-    );
+        );
+        api_event_fd = api_channels.0;
+        from_api = api_channels.1;
+        to_api = api_channels.2;
+        restore_request = api_channels.3;
+
+        // The microVM was torn down, the next one starts from a clean state.
+        api_shared_info.write().unwrap().started = false;
+        *MMDS.lock().expect("Poisoned lock") = Mmds::default();
+    }
 }
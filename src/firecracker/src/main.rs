@@ -3,25 +3,36 @@
 mod api_server_adapter;
 mod metrics;
 
+use std::convert::TryInto;
 use std::fs;
 use std::io;
+use std::net::SocketAddr;
 use std::panic;
 use std::path::PathBuf;
 use std::process;
 use std::sync::{Arc, Mutex};
+use std::thread;
 
+use api_server::{ApiAuthPolicy, ApiLimits, AuditLog, MetricsListener, ServerTransport};
 use logger::{error, info, IncMetric, LOGGER, METRICS};
-use polly::event_manager::EventManager;
-use seccomp::{BpfProgram, SeccompLevel};
-use utils::arg_parser::{ArgParser, Argument};
+use polly::event_manager::{Backend, EventManager};
+use seccomp::{deserialize_program, is_serialized_program, BpfProgram, SeccompLevel};
+use utils::arg_parser::{ArgParser, Argument, Arguments};
 use utils::terminal::Terminal;
 use utils::validators::validate_instance_id;
-use vmm::default_syscalls::get_seccomp_filter;
+use vmm::default_syscalls::{get_level_filter, policy_from_json, start_seccomp_audit};
+use vmm::lifecycle::EventsListener;
+use vmm::log_rotation::LogRotation;
 use vmm::resources::VmResources;
+#[cfg(target_arch = "x86_64")]
+use vmm::rpc_interface::PrebootApiController;
+use vmm::sd_notify::{SdWatchdog, SD_NOTIFY};
 use vmm::signal_handler::register_signal_handlers;
 use vmm::version_map::FC_VERSION_TO_SNAP_VERSION;
 use vmm::vmm_config::instance_info::InstanceInfo;
 use vmm::vmm_config::logger::{init_logger, LoggerConfig, LoggerLevel};
+#[cfg(target_arch = "x86_64")]
+use vmm::vmm_config::snapshot::LoadSnapshotParams;
 
 // The reason we place default API socket under /run is that API socket is a
 // runtime file.
@@ -73,6 +84,46 @@ fn main() {
                 .default_value(DEFAULT_API_SOCK_PATH)
                 .help("Path to unix domain socket used by the API."),
         )
+        .arg(
+            Argument::new("api-tcp-addr")
+                .takes_value(true)
+                .help("Loopback address (e.g. 127.0.0.1:8080) on which the API is additionally served over TCP."),
+        )
+        .arg(
+            Argument::new("api-vsock-port")
+                .takes_value(true)
+                .help("Vsock port on which the API is additionally served."),
+        )
+        .arg(
+            Argument::new("api-auth-policy")
+                .takes_value(true)
+                .help("Path to a file that contains the authorization policy of the API in JSON format."),
+        )
+        .arg(
+            Argument::new("api-max-payload-size")
+                .takes_value(true)
+                .help("Maximum size, in bytes, of the body of an API request. Defaults to 51200."),
+        )
+        .arg(
+            Argument::new("api-max-mmds-payload-size")
+                .takes_value(true)
+                .help("Maximum size, in bytes, of the body of a MMDS API request. Defaults to api-max-payload-size."),
+        )
+        .arg(
+            Argument::new("api-max-in-flight-requests")
+                .takes_value(true)
+                .help("Maximum number of asynchronous API requests in progress. Defaults to 16."),
+        )
+        .arg(
+            Argument::new("api-max-requests-per-sec")
+                .takes_value(true)
+                .help("Maximum number of API requests served per second on each API socket. Unlimited by default."),
+        )
+        .arg(
+            Argument::new("audit-log")
+                .takes_value(true)
+                .help("Path to the audit log, to which the API requests changing the microVM are appended."),
+        )
         .arg(
             Argument::new("id")
                 .takes_value(true)
@@ -88,6 +139,21 @@ fn main() {
                      number and argument values) that will be passed to executed path as argument."
                 ),
         )
+        .arg(
+            Argument::new("seccomp-filter")
+                .takes_value(true)
+                .help("Path to a JSON seccomp policy, or to a program compiled by seccompiler, loaded instead of the built-in one. It overrides the seccomp level."),
+        )
+        .arg(
+            Argument::new("seccomp-audit")
+                .takes_value(false)
+                .help("Record the syscalls which the seccomp filter does not allow in the log and the metrics, then carry them out instead of killing the process."),
+        )
+        .arg(
+            Argument::new("allow-teardown")
+                .takes_value(false)
+                .help("Allow tearing down the microVM under the seccomp filters, for the InstanceTeardown action and the RestoreSnapshot panic action. The built-in filters then also allow the syscalls building another microVM, which weakens them."),
+        )
         .arg(
             Argument::new("start-time-us")
                 .takes_value(true)
@@ -133,16 +199,51 @@ fn main() {
                 .requires("log-path")
                 .help("Whether or not to include the file path and line number of the log's origin.")
         )
+        .arg(
+            Argument::new("metrics-http-addr")
+                .takes_value(true)
+                .help("Address (e.g. 127.0.0.1:9100) on which the metrics are served over HTTP in the Prometheus format.")
+        )
+        .arg(
+            Argument::new("events-socket")
+                .takes_value(true)
+                .help("Path to unix domain socket on which the lifecycle events of the microVM are streamed.")
+        )
         .arg(
             Argument::new("boot-timer")
                 .takes_value(false)
                 .help("Whether or not to load boot timer device for logging elapsed time since InstanceStart command.")
         )
+        .arg(
+            Argument::new("event-backend")
+                .takes_value(true)
+                .default_value("epoll")
+                .help("Mechanism the main event loop polls with (epoll | io_uring). io_uring needs Linux 5.10 or later.")
+        )
+        .arg(
+            Argument::new("landlock")
+                .takes_value(false)
+                .help("Restrict the filesystem access to the boot images, the drives and the vsock sockets with Landlock, before starting the vCPUs.")
+        )
+        .arg(
+            Argument::new("landlock-path")
+                .allow_multiple(true)
+                .requires("landlock")
+                .help("Path the microVM keeps access to under Landlock, such as the directory the snapshots are created in.")
+        )
         .arg(
             Argument::new("version")
                 .takes_value(false)
                 .help("Print the binary version number and a list of supported snapshot data format versions.")
         );
+    #[cfg(feature = "gdb")]
+    {
+        arg_parser = arg_parser.arg(
+            Argument::new("gdb")
+                .takes_value(true)
+                .help("Path to unix domain socket on which a debugger of the guest kernel connects. The microVM waits for the debugger before running."),
+        );
+    }
 
     let arguments = match arg_parser.parse_from_cmdline() {
         Err(err) => {
@@ -205,16 +306,37 @@ fn main() {
         });
     }
 
-    // It's safe to unwrap here because the field's been provided with a default value.
-    let seccomp_level = arguments.single_value("seccomp-level").unwrap();
-    let seccomp_filter = get_seccomp_filter(
-        SeccompLevel::from_string(&seccomp_level).unwrap_or_else(|err| {
-            panic!("Invalid value for seccomp-level: {}", err);
-        }),
-    )
-    .unwrap_or_else(|err| {
-        panic!("Could not create seccomp filter: {}", err);
-    });
+    // The notification socket must be created before the threads load their seccomp filters.
+    if SD_NOTIFY.enabled() {
+        info!("Notifying systemd of the state of the VMM.");
+    }
+
+    let event_backend = parse_event_backend(&arguments);
+    let seccomp_filter = build_seccomp_filter(&arguments, event_backend);
+
+    if let Some(addr) = arguments.single_value("metrics-http-addr") {
+        let listener = MetricsListener::bind(addr.as_str()).unwrap_or_else(|err| {
+            error!("Could not bind the metrics listener to {}: {}", addr, err);
+            process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+        });
+        let metrics_seccomp_filter = seccomp_filter.clone();
+        thread::Builder::new()
+            .name("fc_metrics".to_owned())
+            .spawn(move || listener.run(metrics_seccomp_filter))
+            .expect("Metrics thread spawn failed.");
+    }
+
+    if let Some(path) = arguments.single_value("events-socket") {
+        let listener = EventsListener::bind(path.as_str()).unwrap_or_else(|err| {
+            error!("Could not bind the events listener to {}: {}", path, err);
+            process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+        });
+        let events_seccomp_filter = seccomp_filter.clone();
+        thread::Builder::new()
+            .name("fc_events".to_owned())
+            .spawn(move || listener.run(events_seccomp_filter))
+            .expect("Events thread spawn failed.");
+    }
 
     let vmm_config_json = arguments
         .single_value("config-file")
@@ -222,6 +344,23 @@ fn main() {
         .map(|x| x.expect("Unable to open or read from the configuration file"));
 
     let boot_timer_enabled = arguments.flag_present("boot-timer");
+    #[cfg(feature = "gdb")]
+    let gdb_socket = arguments.single_value("gdb").map(PathBuf::from);
+    #[cfg(not(feature = "gdb"))]
+    let gdb_socket = None;
+    let landlock = if arguments.flag_present("landlock") {
+        Some(
+            arguments
+                .multiple_values("landlock-path")
+                .unwrap_or_default()
+                .iter()
+                .map(PathBuf::from)
+                .collect(),
+        )
+    } else {
+        None
+    };
+    let allow_teardown = arguments.flag_present("allow-teardown");
     let api_enabled = !arguments.flag_present("no-api");
 
     if api_enabled {
@@ -230,6 +369,51 @@ fn main() {
             .map(PathBuf::from)
             .expect("Missing argument: api-sock");
 
+        let mut api_transports = Vec::new();
+        if let Some(addr) = arguments.single_value("api-tcp-addr") {
+            let addr = addr
+                .parse::<SocketAddr>()
+                .ok()
+                .filter(|addr| addr.ip().is_loopback())
+                .unwrap_or_else(|| {
+                    error!(
+                        "Invalid value for api-tcp-addr: {} is not a loopback address",
+                        addr
+                    );
+                    process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+                });
+            api_transports.push(ServerTransport::Tcp(addr));
+        }
+        if let Some(port) = arguments.single_value("api-vsock-port") {
+            let port = port.parse::<u32>().unwrap_or_else(|err| {
+                error!("Invalid value for api-vsock-port: {}", err);
+                process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+            });
+            api_transports.push(ServerTransport::Vsock(port));
+        }
+
+        let api_auth_policy = arguments.single_value("api-auth-policy").map(|path| {
+            fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|json| ApiAuthPolicy::from_json(&json).map_err(|err| err.to_string()))
+                .unwrap_or_else(|err| {
+                    error!(
+                        "Could not load the API authorization policy from {}: {}",
+                        path, err
+                    );
+                    process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+                })
+        });
+
+        let api_limits = parse_api_limits(&arguments);
+
+        let audit_log = arguments.single_value("audit-log").map(|path| {
+            AuditLog::open(&PathBuf::from(path)).unwrap_or_else(|err| {
+                error!("Could not open the audit log {}: {}", path, err);
+                process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+            })
+        });
+
         let start_time_us = arguments.single_value("start-time-us").map(|s| {
             s.parse::<u64>()
                 .expect("'start-time-us' parameter expected to be of 'u64' type.")
@@ -243,10 +427,18 @@ fn main() {
             seccomp_filter,
             vmm_config_json,
             bind_path,
+            api_transports,
+            api_auth_policy,
+            api_limits,
+            audit_log,
             instance_info,
             start_time_us,
             start_time_cpu_us,
             boot_timer_enabled,
+            gdb_socket,
+            landlock,
+            allow_teardown,
+            event_backend,
         );
     } else {
         run_without_api(
@@ -254,11 +446,120 @@ fn main() {
             vmm_config_json,
             &instance_info,
             boot_timer_enabled,
+            gdb_socket,
+            landlock,
+            allow_teardown,
+            event_backend,
         );
     }
 }
 
+// Parses the backend of the main event loop, exiting on invalid values.
+fn parse_event_backend(arguments: &Arguments<'_>) -> Backend {
+    // Safe to unwrap because the argument has a default value.
+    match arguments.single_value("event-backend").unwrap().as_str() {
+        "epoll" => Backend::Epoll,
+        "io_uring" => Backend::IoUring,
+        value => {
+            error!(
+                "Invalid value for event-backend: {} is neither epoll nor io_uring",
+                value
+            );
+            process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+        }
+    }
+}
+
+// Parses the limits on the size and rate of the API requests, exiting on invalid values.
+fn parse_api_limits(arguments: &Arguments<'_>) -> ApiLimits {
+    fn parse<T: std::str::FromStr>(arguments: &Arguments<'_>, name: &'static str) -> Option<T> {
+        arguments.single_value(name).map(|value| {
+            value
+                .parse::<T>()
+                .ok()
+                .filter(|_| !value.trim_start_matches('0').is_empty())
+                .unwrap_or_else(|| {
+                    error!(
+                        "Invalid value for {}: {} is not a positive integer",
+                        name, value
+                    );
+                    process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+                })
+        })
+    }
+
+    let defaults = ApiLimits::default();
+    ApiLimits {
+        max_payload_size: parse(arguments, "api-max-payload-size")
+            .unwrap_or(defaults.max_payload_size),
+        max_mmds_payload_size: parse(arguments, "api-max-mmds-payload-size"),
+        max_in_flight_requests: parse(arguments, "api-max-in-flight-requests")
+            .unwrap_or(defaults.max_in_flight_requests),
+        max_requests_per_sec: parse(arguments, "api-max-requests-per-sec"),
+    }
+}
+
 // Print supported snapshot data format versions.
+// Logs the error preventing the seccomp filter in `path` from being loaded, then exits.
+fn exit_seccomp_filter_error(path: &str, err: &dyn std::fmt::Display) -> ! {
+    error!("Could not load the seccomp filter from {}: {}", path, err);
+    process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+}
+
+// Builds the seccomp filter of the Firecracker threads, for the event loop polling through
+// `event_backend`. The built-in filters allow building another microVM after a teardown only
+// with `--allow-teardown`. In audit mode, the filter is loaded here and inherited by all the
+// threads spawned next, so the returned program is empty.
+fn build_seccomp_filter(arguments: &Arguments<'_>, event_backend: Backend) -> BpfProgram {
+    // It's safe to unwrap here because the field's been provided with a default value.
+    let seccomp_level = arguments.single_value("seccomp-level").unwrap();
+    let seccomp_audit = arguments.flag_present("seccomp-audit");
+    let seccomp_rules = match arguments.single_value("seccomp-filter") {
+        Some(path) => {
+            let policy = fs::read(path).unwrap_or_else(|err| exit_seccomp_filter_error(path, &err));
+            // The programs compiled by seccompiler are loaded as they are.
+            if is_serialized_program(&policy) {
+                if seccomp_audit {
+                    exit_seccomp_filter_error(path, &"a compiled program cannot be audited");
+                }
+                return deserialize_program(&policy)
+                    .unwrap_or_else(|err| exit_seccomp_filter_error(path, &err));
+            }
+            Some(
+                String::from_utf8(policy)
+                    .map_err(|err| err.to_string())
+                    .and_then(|json| policy_from_json(&json).map_err(|err| err.to_string()))
+                    .unwrap_or_else(|err| exit_seccomp_filter_error(path, &err)),
+            )
+        }
+        None => get_level_filter(
+            SeccompLevel::from_string(&seccomp_level).unwrap_or_else(|err| {
+                panic!("Invalid value for seccomp-level: {}", err);
+            }),
+            event_backend,
+            arguments.flag_present("allow-teardown"),
+        )
+        .unwrap_or_else(|err| {
+            panic!("Could not create seccomp filter: {}", err);
+        }),
+    };
+    match seccomp_rules {
+        // The audited filter is loaded once, here, and inherited by all the threads spawned next.
+        Some(rules) if seccomp_audit => {
+            start_seccomp_audit(rules).unwrap_or_else(|err| {
+                error!("Could not start the seccomp audit mode: {}", err);
+                process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+            });
+            info!("Auditing the syscalls which the seccomp filter does not allow.");
+            vec![]
+        }
+        Some(rules) => rules.try_into().unwrap_or_else(|err| {
+            panic!("Could not create seccomp filter: {}", err);
+        }),
+        None => vec![],
+    }
+}
+
 fn print_supported_snapshot_versions() {
     let mut snapshot_versions_str = "Supported snapshot data format versions:".to_string();
     let mut snapshot_versions: Vec<String> = FC_VERSION_TO_SNAP_VERSION
@@ -275,22 +576,26 @@ fn print_supported_snapshot_versions() {
 }
 
 // Configure and start a microVM as described by the command-line JSON.
+#[allow(clippy::too_many_arguments)]
 fn build_microvm_from_json(
     seccomp_filter: BpfProgram,
     event_manager: &mut EventManager,
     config_json: String,
     instance_info: &InstanceInfo,
     boot_timer_enabled: bool,
+    gdb_socket: Option<PathBuf>,
+    landlock: Option<Vec<PathBuf>>,
+    allow_teardown: bool,
 ) -> (VmResources, Arc<Mutex<vmm::Vmm>>) {
     let mut vm_resources =
         VmResources::from_json(&config_json, instance_info).unwrap_or_else(|err| {
-            error!(
-                "Configuration for VMM from one single json failed: {:?}",
-                err
-            );
+            error!("Configuration for VMM from one single json failed: {}", err);
             process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
         });
     vm_resources.boot_timer = boot_timer_enabled;
+    vm_resources.gdb_socket = gdb_socket;
+    vm_resources.landlock = landlock;
+    vm_resources.allow_teardown = allow_teardown;
     let vmm = vmm::builder::build_microvm_for_boot(&vm_resources, event_manager, &seccomp_filter)
         .unwrap_or_else(|err| {
             error!(
@@ -300,46 +605,135 @@ fn build_microvm_from_json(
             process::exit(i32::from(vmm::FC_EXIT_CODE_BAD_CONFIGURATION));
         });
     info!("Successfully started microvm that was configured from one single json");
+    SD_NOTIFY.notify("READY=1");
 
     (vm_resources, vmm)
 }
 
+// Restores the microVM which the `RestoreSnapshot` panic action tore down, from its snapshot.
+#[cfg(target_arch = "x86_64")]
+#[allow(clippy::too_many_arguments)]
+fn restore_microvm(
+    seccomp_filter: BpfProgram,
+    event_manager: &mut EventManager,
+    load_params: &LoadSnapshotParams,
+    instance_info: &InstanceInfo,
+    boot_timer_enabled: bool,
+    gdb_socket: Option<PathBuf>,
+    landlock: Option<Vec<PathBuf>>,
+    allow_teardown: bool,
+) -> (VmResources, Arc<Mutex<vmm::Vmm>>) {
+    let restored = PrebootApiController::restore_microvm(
+        seccomp_filter,
+        event_manager,
+        instance_info.clone(),
+        load_params,
+        boot_timer_enabled,
+        gdb_socket,
+        landlock,
+        allow_teardown,
+    )
+    .unwrap_or_else(|err| {
+        error!("Restoring the microVM after a guest panic failed: {}", err);
+        process::exit(i32::from(vmm::FC_EXIT_CODE_GENERIC_ERROR));
+    });
+    info!("Successfully restored the microVM after a guest panic");
+
+    restored
+}
+
+// Sends the systemd watchdog heartbeats from the event loop, if systemd watches the VMM.
+fn add_sd_watchdog(event_manager: &mut EventManager) {
+    if let Some(interval) = vmm::sd_notify::watchdog_interval() {
+        let watchdog = SdWatchdog::new(interval).expect("Cannot create the systemd watchdog.");
+        event_manager
+            .add_subscriber(Arc::new(Mutex::new(watchdog)))
+            .expect("Cannot register the systemd watchdog to the event manager.");
+    }
+}
+
+// Reopens the log and metrics files from the event loop when Firecracker receives `SIGHUP`.
+fn add_log_rotation(event_manager: &mut EventManager) {
+    let log_rotation = LogRotation::new().expect("Cannot create the log rotation event.");
+    event_manager
+        .add_subscriber(Arc::new(Mutex::new(log_rotation)))
+        .expect("Cannot register the log rotation event to the event manager.");
+}
+
+#[allow(clippy::too_many_arguments)]
 fn run_without_api(
     seccomp_filter: BpfProgram,
     config_json: Option<String>,
     instance_info: &InstanceInfo,
     bool_timer_enabled: bool,
+    gdb_socket: Option<PathBuf>,
+    landlock: Option<Vec<PathBuf>>,
+    allow_teardown: bool,
+    event_backend: Backend,
 ) {
-    let mut event_manager = EventManager::new().expect("Unable to create EventManager");
-
-    // Create the firecracker metrics object responsible for periodically printing metrics.
-    let firecracker_metrics = Arc::new(Mutex::new(metrics::PeriodicMetrics::new()));
-    event_manager
-        .add_subscriber(firecracker_metrics.clone())
-        .expect("Cannot register the metrics event to the event manager.");
-
-    // Build the microVm. We can ignore the returned values here because:
-    // - VmResources is not used without api,
-    // - An `Arc` reference of the built `Vmm` is plugged in the `EventManager` by the builder.
-    build_microvm_from_json(
-        seccomp_filter,
-        &mut event_manager,
-        // Safe to unwrap since '--no-api' requires this to be set.
-        config_json.unwrap(),
-        instance_info,
-        bool_timer_enabled,
-    );
-
-    // Start the metrics.
-    firecracker_metrics
-        .lock()
-        .expect("Poisoned lock")
-        .start(metrics::WRITE_METRICS_PERIOD_MS);
-
-    // Run the EventManager that drives everything in the microVM.
+    // Only the first microVM is configured from the JSON, the next ones are restored.
+    let mut config_json = config_json;
+    let mut restore_request = None;
+    // The event manager serves all the microVMs, so that its poller is only created before the
+    // seccomp filters are loaded.
+    let mut event_manager =
+        EventManager::with_backend(event_backend).expect("Unable to create EventManager");
+    // Each iteration builds a microVM and runs it until a guest panic tears it down, for the
+    // next one to be restored from a snapshot.
     loop {
+        // Create the firecracker metrics object responsible for periodically printing metrics.
+        let firecracker_metrics = Arc::new(Mutex::new(metrics::PeriodicMetrics::new()));
+        event_manager
+            .add_subscriber(firecracker_metrics.clone())
+            .expect("Cannot register the metrics event to the event manager.");
+        add_sd_watchdog(&mut event_manager);
+        add_log_rotation(&mut event_manager);
+
+        // Build the microVm. VmResources is not used without api.
+        let (_, vmm) = match (config_json.take(), restore_request.take()) {
+            (Some(json), _) => build_microvm_from_json(
+                seccomp_filter.clone(),
+                &mut event_manager,
+                json,
+                instance_info,
+                bool_timer_enabled,
+                gdb_socket.clone(),
+                landlock.clone(),
+                allow_teardown,
+            ),
+            #[cfg(target_arch = "x86_64")]
+            (None, Some(load_params)) => restore_microvm(
+                seccomp_filter.clone(),
+                &mut event_manager,
+                &load_params,
+                instance_info,
+                bool_timer_enabled,
+                gdb_socket.clone(),
+                landlock.clone(),
+                allow_teardown,
+            ),
+            // '--no-api' requires the JSON to be set, and only the restored microVMs follow.
+            (None, _) => unreachable!("No configuration for the microVM."),
+        };
+
+        // Start the metrics.
+        firecracker_metrics
+            .lock()
+            .expect("Poisoned lock")
+            .start(METRICS.flush_interval_ms());
+
+        // Run the EventManager that drives everything in the microVM.
+        while restore_request.is_none() {
+            event_manager
+                .run()
+                .expect("Failed to start the event manager");
+            restore_request = vmm.lock().expect("Poisoned lock").take_restore_request();
+        }
+
+        // Removing the subscribers of the microVM drops them, and then the `Vmm`.
         event_manager
-            .run()
-            .expect("Failed to start the event manager");
+            .remove_all_subscribers()
+            .expect("Cannot remove the subscribers of the torn down microVM.");
+        drop(vmm);
     }
 }
@@ -9,9 +9,6 @@ use polly::event_manager::{EventManager, Subscriber};
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 use utils::epoll::{EpollEvent, EventSet};
 
-/// Metrics reporting period.
-pub(crate) const WRITE_METRICS_PERIOD_MS: u64 = 60000;
-
 /// Object to drive periodic reporting of metrics.
 pub(crate) struct PeriodicMetrics {
     write_metrics_event_fd: TimerFd,
@@ -31,15 +28,18 @@ impl PeriodicMetrics {
         }
     }
 
-    /// Start the periodic metrics engine which will flush metrics every `interval_ms` millisecs.
+    /// Start the periodic metrics engine which will flush metrics every `interval_ms` millisecs,
+    /// or only on demand if `interval_ms` is 0.
     pub(crate) fn start(&mut self, interval_ms: u64) {
         // Arm the log write timer.
-        let timer_state = TimerState::Periodic {
-            current: Duration::from_millis(interval_ms),
-            interval: Duration::from_millis(interval_ms),
-        };
-        self.write_metrics_event_fd
-            .set_state(timer_state, SetTimeFlags::Default);
+        if interval_ms > 0 {
+            let timer_state = TimerState::Periodic {
+                current: Duration::from_millis(interval_ms),
+                interval: Duration::from_millis(interval_ms),
+            };
+            self.write_metrics_event_fd
+                .set_state(timer_state, SetTimeFlags::Default);
+        }
 
         // Write the metrics straight away to check the process startup time.
         self.write_metrics();
@@ -156,4 +156,16 @@ pub mod tests {
         // Verify there was another flush.
         assert_eq!(metrics.lock().expect("Unlock failed.").flush_counter, 2);
     }
+
+    #[test]
+    fn test_periodic_flush_disabled() {
+        let mut metrics = PeriodicMetrics::new();
+        metrics.start(0);
+        // Only the initial flush happens.
+        assert_eq!(metrics.flush_counter, 1);
+        match metrics.write_metrics_event_fd.get_state() {
+            TimerState::Disarmed => (),
+            _ => panic!("The timer should be disarmed"),
+        }
+    }
 }
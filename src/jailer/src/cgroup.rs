@@ -4,6 +4,7 @@
 use std::ffi::OsStr;
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process;
 
@@ -14,10 +15,31 @@ use super::{Error, Result};
 const PROC_MOUNTS: &str = "/proc/mounts";
 const NODE_TO_CPULIST: &str = "/sys/devices/system/node/node"; // This constant should be removed once the `--node` argument is removed.
 
+// The cgroup v2 file holding the controllers available in a cgroup.
+const CONTROLLERS_FILE: &str = "cgroup.controllers";
+// The cgroup v2 file enabling controllers for the children of a cgroup.
+const SUBTREE_CONTROL_FILE: &str = "cgroup.subtree_control";
+
+// The cgroup v1 cpu.shares range, which is mapped to the cgroup v2 cpu.weight range.
+const CPU_SHARES_MIN: u64 = 2;
+const CPU_SHARES_MAX: u64 = 262_144;
+const CPU_WEIGHT_MIN: u64 = 1;
+const CPU_WEIGHT_MAX: u64 = 10_000;
+
+/// The cgroup hierarchies a controller can be found in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CgroupVersion {
+    /// A cgroup v1 hierarchy, holding some controllers.
+    V1,
+    /// The cgroup v2 unified hierarchy, holding all the controllers not bound to a v1 one.
+    V2,
+}
+
 pub struct Cgroup {
-    file: String,      // file representing the cgroup (e.g cpuset.mems).
-    value: String,     // value that will be written into the file.
-    location: PathBuf, // microVM cgroup location for the specific controller.
+    file: String,           // file representing the cgroup (e.g cpuset.mems).
+    value: String,          // value that will be written into the file.
+    location: PathBuf,      // microVM cgroup location for the specific controller.
+    version: CgroupVersion, // version of the hierarchy holding the controller.
 }
 
 // It's called writeln_special because we have to use this rather convoluted way of writing
@@ -147,14 +169,52 @@ pub fn cgroups_from_numa_node(
     Ok(vec![cpuset_cpus, cpuset_mems])
 }
 
+// Translates a cgroup v1 file and value to their cgroup v2 equivalent. The files which are the
+// same in both versions, like cpuset.cpus and cpuset.mems, or which are already cgroup v2 files,
+// are kept.
+fn translate_to_v2(file: &str, value: &str) -> Result<(String, String)> {
+    let invalid_value = || Error::CgroupFormat(format!("{}={}", file, value));
+    match file {
+        // The shares are relative, so the weight is mapped linearly over its range.
+        "cpu.shares" => {
+            let shares = value
+                .parse::<u64>()
+                .map_err(|_| invalid_value())?
+                .max(CPU_SHARES_MIN)
+                .min(CPU_SHARES_MAX);
+            let weight = CPU_WEIGHT_MIN
+                + (shares - CPU_SHARES_MIN) * (CPU_WEIGHT_MAX - CPU_WEIGHT_MIN)
+                    / (CPU_SHARES_MAX - CPU_SHARES_MIN);
+            Ok(("cpu.weight".to_string(), weight.to_string()))
+        }
+        // cpu.max holds the quota, followed by the period, which is left alone when omitted.
+        "cpu.cfs_quota_us" => {
+            let quota = value.parse::<i64>().map_err(|_| invalid_value())?;
+            let max = if quota < 0 {
+                "max".to_string()
+            } else {
+                quota.to_string()
+            };
+            Ok(("cpu.max".to_string(), max))
+        }
+        _ => Ok((file.to_string(), value.to_string())),
+    }
+}
+
 impl Cgroup {
     pub fn new(file: String, value: String, id: &str, exec_file_name: &OsStr) -> Result<Self> {
-        let cgroup_location = Self::get_location(&file, exec_file_name, id)?;
+        let (cgroup_location, version) =
+            Self::get_location(Path::new(PROC_MOUNTS), &file, exec_file_name, id)?;
+        let (file, value) = match version {
+            CgroupVersion::V1 => (file, value),
+            CgroupVersion::V2 => translate_to_v2(&file, &value)?,
+        };
 
         Ok(Cgroup {
             file,
             value,
             location: cgroup_location,
+            version,
         })
     }
 
@@ -166,20 +226,69 @@ impl Cgroup {
         fs::create_dir_all(&self.location)
             .map_err(|e| Error::CreateDir(self.location.clone(), e))?;
 
-        // Write the corresponding cgroup value. inherit_from_parent is used to
-        // correctly propagate the value if not defined.
-        inherit_from_parent(location, &self.file)?;
+        match self.version {
+            CgroupVersion::V1 => {
+                self.check_file()?;
+                // Write the corresponding cgroup value. inherit_from_parent is used to
+                // correctly propagate the value if not defined.
+                inherit_from_parent(location, &self.file)?;
+            }
+            CgroupVersion::V2 => {
+                // The cgroups of the unified hierarchy inherit the values of their parents,
+                // but their controllers have to be enabled from the root of the hierarchy.
+                self.enable_controller()?;
+                self.check_file()?;
+            }
+        }
         location.push(&self.file);
-        writeln_special(location, &self.value)?;
+        fs::write(&location, format!("{}\n", self.value))
+            .map_err(|e| Error::CgroupWriteValue(location.clone(), self.value.clone(), e))?;
+
+        Ok(())
+    }
+
+    // Checks that the controller exposes the cgroup file, and that it can be written to.
+    fn check_file(&self) -> Result<()> {
+        let path = self.location.join(&self.file);
+        let metadata = fs::metadata(&path)
+            .map_err(|_| Error::CgroupUnknownFile(self.file.clone(), self.location.clone()))?;
+        // The cgroup files which only report statistics have no write permission.
+        if metadata.permissions().mode() & 0o222 == 0 {
+            return Err(Error::CgroupReadOnlyFile(path));
+        }
+
+        Ok(())
+    }
+
+    // Enables the controller of the cgroup for the children of <mountpoint> and of
+    // <mountpoint>/<exec_file_name>, which makes its files appear in the microVM cgroup.
+    fn enable_controller(&self) -> Result<()> {
+        let controller = Self::get_controller(&self.file)?;
+        let parent = self
+            .location
+            .parent()
+            .ok_or_else(|| Error::MissingParent(self.location.clone()))?;
+        let root = parent
+            .parent()
+            .ok_or_else(|| Error::MissingParent(parent.to_path_buf()))?;
+
+        for dir in [root, parent].iter() {
+            writeln_special(&dir.join(SUBTREE_CONTROL_FILE), format!("+{}", controller))?;
+        }
 
         Ok(())
     }
 
-    // This writes the pid of the current process to the tasks file. Tasks files are special files,
-    // that when written to, will assign the process associated with the pid to the respective cgroup.
+    // This writes the pid of the current process to the tasks file (cgroup.procs in cgroup v2).
+    // Tasks files are special files, that when written to, will assign the process associated
+    // with the pid to the respective cgroup.
     pub fn attach_pid(&self) -> Result<()> {
         let pid = process::id();
-        let location = &self.location.join("tasks");
+        let tasks_file = match self.version {
+            CgroupVersion::V1 => "tasks",
+            CgroupVersion::V2 => "cgroup.procs",
+        };
+        let location = &self.location.join(tasks_file);
 
         writeln_special(location, pid)?;
 
@@ -191,28 +300,49 @@ impl Cgroup {
     fn get_controller(file: &str) -> Result<&str> {
         let v: Vec<&str> = file.split('.').collect();
 
-        // Check format <cgroup_controller>.<cgroup_property>
-        if v.len() != 2 {
+        // Check format <cgroup_controller>.<cgroup_property>, where both are made of lowercase
+        // letters, digits and underscores, so that the file can't be outside the cgroup.
+        let is_valid_name = |name: &str| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        };
+        if v.len() != 2 || !v.iter().all(|name| is_valid_name(name)) {
             return Err(Error::CgroupInvalidFile(file.to_string()));
         }
 
         Ok(v[0])
     }
 
-    // Return the path of the cgroup subfolder for a specific controller.
-    // (<mountpoint>/<controller>/<exec_file_name>/<id>).
-    fn get_location(file: &str, exec_file_name: &OsStr, id: &str) -> Result<PathBuf> {
+    // Return the path of the cgroup subfolder for a specific controller, found in the `mounts`
+    // table, and the version of the hierarchy holding it
+    // (<mountpoint>/<exec_file_name>/<id>, where the mountpoint is usually
+    // /sys/fs/cgroup/<controller> in cgroup v1 and /sys/fs/cgroup in cgroup v2).
+    // A cgroup v1 hierarchy is preferred, as controllers bound to one are not available in the
+    // unified hierarchy of hosts mounting both.
+    fn get_location(
+        mounts: &Path,
+        file: &str,
+        exec_file_name: &OsStr,
+        id: &str,
+    ) -> Result<(PathBuf, CgroupVersion)> {
         let controller = Self::get_controller(file)?;
-        let f =
-            File::open(PROC_MOUNTS).map_err(|e| Error::FileOpen(PathBuf::from(PROC_MOUNTS), e))?;
+        let f = File::open(mounts).map_err(|e| Error::FileOpen(mounts.to_path_buf(), e))?;
 
         // Regex courtesy of Filippo.
         let re = Regex::new(
-            r"^([a-z]*)[[:space:]](?P<dir>.*)[[:space:]]cgroup[[:space:]](?P<options>.*)[[:space:]]0[[:space:]]0$",
+            r"^([a-z]*)[[:space:]](?P<dir>.*)[[:space:]](?P<fstype>cgroup2?)[[:space:]](?P<options>.*)[[:space:]]0[[:space:]]0$",
         ).map_err(Error::RegEx)?;
+        let mut unified_dir = None;
         for l in BufReader::new(f).lines() {
-            let l = l.map_err(|e| Error::ReadLine(PathBuf::from(PROC_MOUNTS), e))?;
+            let l = l.map_err(|e| Error::ReadLine(mounts.to_path_buf(), e))?;
             if let Some(capture) = re.captures(&l) {
+                if &capture["fstype"] == "cgroup2" {
+                    unified_dir = Some(PathBuf::from(&capture["dir"]));
+                    continue;
+                }
+
                 let v: Vec<&str> = capture["options"].split(',').collect();
 
                 if v.contains(&controller) {
@@ -220,13 +350,24 @@ impl Cgroup {
                     path.push(exec_file_name);
                     path.push(id);
 
-                    return Ok(path);
+                    return Ok((path, CgroupVersion::V1));
                 }
             }
         }
 
+        if let Some(mut path) = unified_dir {
+            // The unified hierarchy lists the controllers it holds.
+            let controllers = readln_special(&path.join(CONTROLLERS_FILE))?;
+            if controllers.split_whitespace().any(|c| c == controller) {
+                path.push(exec_file_name);
+                path.push(id);
+
+                return Ok((path, CgroupVersion::V2));
+            }
+        }
+
         Err(Error::CgroupLineNotFound(
-            PROC_MOUNTS.to_string(),
+            mounts.to_string_lossy().into_owned(),
             controller.to_string(),
         ))
     }
@@ -295,6 +436,12 @@ mod tests {
         assert!(result.is_err());
         assert!(format!("{:?}", result).contains("CgroupInvalidFile"));
 
+        // Check file outside the cgroup
+        file = "cpuset./cpus";
+        result = Cgroup::get_controller(file);
+        assert!(result.is_err());
+        assert!(format!("{:?}", result).contains("CgroupInvalidFile"));
+
         // Check empty file
         file = "";
         result = Cgroup::get_controller(file);
@@ -302,35 +449,138 @@ mod tests {
         assert!(format!("{:?}", result).contains("CgroupInvalidFile"));
     }
 
+    // Returns a mounts table holding `lines`.
+    fn mounts_file(lines: &[String]) -> TempFile {
+        let mounts = TempFile::new().expect("Cannot create named file.");
+        for line in lines.iter() {
+            writeln!(mounts.as_file(), "{}", line).expect("Cannot write to file.");
+        }
+        mounts
+    }
+
     #[test]
     fn test_get_location() {
-        // Asumming cgroups are mounted on /sys/fs/cgroup
-        let cgroup_path = "/sys/fs/cgroup";
         let id = "microvm-id";
-        let exec_file_name = "firecracker";
+        let exec_file_name = OsStr::new("firecracker");
         let mut file = "cpuset.cpu";
 
         // Check valid file
-        let controller = "cpuset"; // defined to avoid calling get_controller.
-        assert!(&std::path::Path::new(&format!("{}/{}", cgroup_path, controller)).exists());
-        let expected_path = PathBuf::from(format!(
-            "{}/{}/{}/{}",
-            &cgroup_path, &controller, &exec_file_name, &id
-        ));
-        let mut result = Cgroup::get_location(file, OsStr::new(exec_file_name), id);
-        assert!(result.is_ok());
-        assert!(matches!(result, Ok(path) if path == expected_path));
+        let mounts = mounts_file(&[
+            "cgroup /sys/fs/cgroup/cpu,cpuacct cgroup rw,nosuid,cpu,cpuacct 0 0".to_string(),
+            "cgroup /sys/fs/cgroup/cpuset cgroup rw,nosuid,cpuset 0 0".to_string(),
+        ]);
+        let expected_path = PathBuf::from("/sys/fs/cgroup/cpuset/firecracker/microvm-id");
+        let mut result = Cgroup::get_location(mounts.as_path(), file, exec_file_name, id);
+        assert!(matches!(result, Ok((path, CgroupVersion::V1)) if path == expected_path));
 
         // Check file with invalid controller
         file = "invalid.cpu";
-        result = Cgroup::get_location(file, OsStr::new(exec_file_name), id);
+        result = Cgroup::get_location(mounts.as_path(), file, exec_file_name, id);
         assert!(result.is_err());
         assert!(format!("{:?}", result).contains("CgroupLineNotFound"));
 
         // Check empty file
         file = "";
-        result = Cgroup::get_location(file, OsStr::new(exec_file_name), id);
+        result = Cgroup::get_location(mounts.as_path(), file, exec_file_name, id);
         assert!(result.is_err());
         assert!(format!("{:?}", result).contains("CgroupInvalidFile"));
     }
+
+    #[test]
+    fn test_get_location_v2() {
+        let id = "microvm-id";
+        let exec_file_name = OsStr::new("firecracker");
+        let unified_dir = TempDir::new().expect("Cannot create temporary directory.");
+        fs::write(
+            unified_dir.as_path().join(CONTROLLERS_FILE),
+            "cpuset cpu io memory pids\n",
+        )
+        .expect("Cannot write to file.");
+        let unified_mount = format!(
+            "cgroup2 {} cgroup2 rw,nosuid,nodev,noexec,relatime 0 0",
+            unified_dir.as_path().display()
+        );
+
+        // The controllers are found in the unified hierarchy.
+        let mounts = mounts_file(&[unified_mount.clone()]);
+        let result = Cgroup::get_location(mounts.as_path(), "cpu.weight", exec_file_name, id);
+        let expected_path = unified_dir.as_path().join("firecracker/microvm-id");
+        assert!(matches!(result, Ok((path, CgroupVersion::V2)) if path == expected_path));
+        let result = Cgroup::get_location(mounts.as_path(), "hugetlb.max", exec_file_name, id);
+        assert!(format!("{:?}", result).contains("CgroupLineNotFound"));
+
+        // A cgroup v1 hierarchy takes precedence on hybrid hosts.
+        let mounts = mounts_file(&[
+            unified_mount,
+            "cgroup /sys/fs/cgroup/cpuset cgroup rw,nosuid,cpuset 0 0".to_string(),
+        ]);
+        let result = Cgroup::get_location(mounts.as_path(), "cpuset.cpus", exec_file_name, id);
+        assert!(matches!(result, Ok((_, CgroupVersion::V1))));
+        let result = Cgroup::get_location(mounts.as_path(), "cpu.weight", exec_file_name, id);
+        assert!(matches!(result, Ok((_, CgroupVersion::V2))));
+    }
+
+    #[test]
+    fn test_translate_to_v2() {
+        let translate = |file, value| translate_to_v2(file, value).unwrap();
+        assert_eq!(
+            translate("cpu.shares", "1024"),
+            ("cpu.weight".to_string(), "39".to_string())
+        );
+        assert_eq!(
+            translate("cpu.shares", "2"),
+            ("cpu.weight".to_string(), "1".to_string())
+        );
+        assert_eq!(
+            translate("cpu.shares", "1000000"),
+            ("cpu.weight".to_string(), "10000".to_string())
+        );
+        assert_eq!(
+            translate("cpu.cfs_quota_us", "50000"),
+            ("cpu.max".to_string(), "50000".to_string())
+        );
+        assert_eq!(
+            translate("cpu.cfs_quota_us", "-1"),
+            ("cpu.max".to_string(), "max".to_string())
+        );
+        assert_eq!(
+            translate("cpuset.mems", "0"),
+            ("cpuset.mems".to_string(), "0".to_string())
+        );
+        assert!(format!("{:?}", translate_to_v2("cpu.shares", "a lot")).contains("CgroupFormat"));
+    }
+
+    #[test]
+    fn test_write_value_v2() {
+        // This is <mountpoint>.
+        let root = TempDir::new().expect("Cannot create temporary directory.");
+        let location = root.as_path().join("firecracker").join("microvm-id");
+        let cgroup = |file: &str| Cgroup {
+            file: file.to_string(),
+            value: "39".to_string(),
+            location: location.clone(),
+            version: CgroupVersion::V2,
+        };
+
+        // The controller files are missing until the controller is enabled.
+        let result = cgroup("cpu.weight").write_value();
+        assert!(format!("{:?}", result).contains("CgroupUnknownFile"));
+        fs::write(location.join("cpu.weight"), "100\n").expect("Cannot write to file.");
+        cgroup("cpu.weight").write_value().unwrap();
+
+        // The controller is enabled down to the microVM cgroup.
+        for dir in [root.as_path(), location.parent().unwrap()].iter() {
+            let enabled = readln_special(&dir.join(SUBTREE_CONTROL_FILE)).unwrap();
+            assert_eq!(enabled, "+cpu");
+        }
+        assert_eq!(readln_special(&location.join("cpu.weight")).unwrap(), "39");
+
+        // The statistics can't be written.
+        let stat_file = location.join("cpu.stat");
+        fs::write(&stat_file, "usage_usec 0\n").expect("Cannot write to file.");
+        fs::set_permissions(&stat_file, fs::Permissions::from_mode(0o444))
+            .expect("Cannot change permissions.");
+        let result = cgroup("cpu.stat").write_value();
+        assert!(format!("{:?}", result).contains("CgroupReadOnlyFile"));
+    }
 }
@@ -2,8 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::env;
-use std::ffi::CStr;
-use std::path::Path;
+use std::ffi::{CStr, OsStr};
+use std::fs::{self, canonicalize, File};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Component, Path, PathBuf};
 use std::ptr::null;
 
 use super::{to_cstring, Error, Result};
@@ -12,10 +14,160 @@ use utils::syscall::SyscallReturnCode;
 const OLD_ROOT_DIR_NAME_NUL_TERMINATED: &[u8] = b"old_root\0";
 const ROOT_DIR_NUL_TERMINATED: &[u8] = b"/\0";
 const CURRENT_DIR_NUL_TERMINATED: &[u8] = b".\0";
+const TMPFS_NUL_TERMINATED: &[u8] = b"tmpfs\0";
+const TMPFS_OPTIONS_NUL_TERMINATED: &[u8] = b"mode=0700\0";
+
+// The host paths which are never mounted in the jail, neither on their own, nor through one of
+// their ancestors.
+const FORBIDDEN_MOUNT_SOURCES: [&str; 6] = ["/boot", "/dev", "/etc", "/proc", "/root", "/sys"];
+// The jail paths the jailer populates itself.
+const RESERVED_MOUNT_DESTINATIONS: [&str; 2] = ["/dev", "/old_root"];
+
+// Checks whether `path` lies in, or holds, one of the forbidden mount sources.
+fn is_forbidden_mount_source(path: &Path) -> bool {
+    FORBIDDEN_MOUNT_SOURCES.iter().any(|forbidden| {
+        let forbidden = Path::new(forbidden);
+        path.starts_with(forbidden) || forbidden.starts_with(path)
+    })
+}
+
+/// Parses a root directory whose content can be bind mounted in the jail. It can't be `/`, nor
+/// lie in, or hold, one of the forbidden mount sources.
+pub fn parse_mount_allow_root(arg: &str) -> Result<PathBuf> {
+    let root = canonicalize(arg).map_err(|e| Error::Canonicalize(PathBuf::from(arg), e))?;
+    if !root.is_dir() || is_forbidden_mount_source(&root) {
+        return Err(Error::MountAllowRoot(root));
+    }
+    Ok(root)
+}
+
+/// A file or directory of the host, bind mounted in the jail.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BindMount {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    pub read_only: bool,
+}
+
+impl BindMount {
+    /// Parses a mount of the `<src>:<dst>[:ro]` format. The source has to be a regular file, a
+    /// directory or a socket, in one of the `allowed_roots` directories.
+    pub fn parse(arg: &str, allowed_roots: &[PathBuf]) -> Result<Self> {
+        let parts = arg.split(':').collect::<Vec<&str>>();
+        let read_only = match parts.get(2) {
+            None => false,
+            Some(&"ro") if parts.len() == 3 => true,
+            _ => return Err(Error::MountFormat(arg.to_string())),
+        };
+        if parts.len() < 2 || parts[0].is_empty() {
+            return Err(Error::MountFormat(arg.to_string()));
+        }
+
+        let src =
+            canonicalize(parts[0]).map_err(|e| Error::Canonicalize(PathBuf::from(parts[0]), e))?;
+        let file_type = fs::metadata(&src)
+            .map_err(|e| Error::Metadata(src.clone(), e))?
+            .file_type();
+        if !allowed_roots.iter().any(|root| src.starts_with(root))
+            || is_forbidden_mount_source(&src)
+            || !(file_type.is_file() || file_type.is_dir() || file_type.is_socket())
+        {
+            return Err(Error::MountSource(src));
+        }
+
+        // The destination has to be an absolute path without any `.` or `..` component, so
+        // it can't escape the jail.
+        let dst = PathBuf::from(parts[1]);
+        let mut components = dst.components();
+        if components.next() != Some(Component::RootDir)
+            || components.next().is_none()
+            || !components.all(|component| matches!(component, Component::Normal(_)))
+            || parts[1].ends_with('/')
+        {
+            return Err(Error::MountDestination(parts[1].to_string()));
+        }
+
+        Ok(BindMount {
+            src,
+            dst,
+            read_only,
+        })
+    }
+}
+
+/// Checks that the destinations of `mounts` neither overlap, nor hide what the jailer puts in
+/// the jail, like the `exec_file_name` binary.
+pub fn validate_mounts(mounts: &[BindMount], exec_file_name: &OsStr) -> Result<()> {
+    let exec_file = Path::new("/").join(exec_file_name);
+    for (i, mount) in mounts.iter().enumerate() {
+        let invalid = mount.dst.starts_with(&exec_file)
+            || RESERVED_MOUNT_DESTINATIONS
+                .iter()
+                .any(|reserved| mount.dst.starts_with(reserved))
+            || mounts[i + 1..].iter().any(|other| {
+                other.dst.starts_with(&mount.dst) || mount.dst.starts_with(&other.dst)
+            });
+        if invalid {
+            return Err(Error::MountDestination(
+                mount.dst.to_string_lossy().into_owned(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Bind mounts `mount` under the jail root directory `path`, creating its mount point.
+fn bind_mount(path: &Path, mount: &BindMount) -> Result<()> {
+    // Safe to unwrap, since the destination was validated to be absolute.
+    let target = path.join(mount.dst.strip_prefix("/").unwrap());
+    if mount.src.is_dir() {
+        fs::create_dir_all(&target).map_err(|e| Error::CreateDir(target.clone(), e))?;
+    } else {
+        // Safe to unwrap, since the destination has at least one component.
+        let parent = target.parent().unwrap();
+        fs::create_dir_all(parent).map_err(|e| Error::CreateDir(parent.to_path_buf(), e))?;
+        File::create(&target).map_err(|e| Error::FileOpen(target.clone(), e))?;
+    }
+
+    let src = to_cstring(&mount.src)?;
+    let target_cstr = to_cstring(&target)?;
+    // The submounts of the source are left out, so they can't escape the flags below. Safe
+    // because we provide valid parameters.
+    SyscallReturnCode(unsafe {
+        libc::mount(
+            src.as_ptr(),
+            target_cstr.as_ptr(),
+            null(),
+            libc::MS_BIND,
+            null(),
+        )
+    })
+    .into_empty_result()
+    .map_err(|e| Error::MountBindPath(mount.src.clone(), e))?;
+
+    // The flags of a bind mount can only be changed by remounting it. Safe because we provide
+    // valid parameters.
+    let read_only = if mount.read_only { libc::MS_RDONLY } else { 0 };
+    SyscallReturnCode(unsafe {
+        libc::mount(
+            null(),
+            target_cstr.as_ptr(),
+            null(),
+            libc::MS_BIND | libc::MS_REMOUNT | libc::MS_NOSUID | libc::MS_NODEV | read_only,
+            null(),
+        )
+    })
+    .into_empty_result()
+    .map_err(|e| Error::MountBindPath(mount.src.clone(), e))
+}
 
 // This uses switching to a new mount namespace + pivot_root(), together with the regular chroot,
 // to provide a hardened jail (at least compared to only relying on chroot).
-pub fn chroot(path: &Path) -> Result<()> {
+//
+// The jail root directory `path` is made a mount point in the new mount namespace. With `tmpfs`,
+// it is a new tmpfs, so the jail only holds what the jailer puts in it, without leaving anything
+// behind on the host.
+pub fn unshare_mount_ns(path: &Path, tmpfs: bool) -> Result<()> {
     // We unshare into a new mount namespace. The call is safe because we're invoking a C library
     // function with valid parameters.
     SyscallReturnCode(unsafe { libc::unshare(libc::CLONE_NEWNS) })
@@ -42,20 +194,47 @@ pub fn chroot(path: &Path) -> Result<()> {
     // We need a CString for the following mount call.
     let chroot_dir = to_cstring(path)?;
 
-    // Bind mount the jail root directory over itself, so we can go around a restriction
-    // imposed by pivot_root, which states that the new root and the old root should not
-    // be on the same filesystem. Safe because we provide valid parameters.
-    SyscallReturnCode(unsafe {
-        libc::mount(
-            chroot_dir.as_ptr(),
-            chroot_dir.as_ptr(),
-            null(),
-            libc::MS_BIND | libc::MS_REC,
-            null(),
-        )
-    })
-    .into_empty_result()
-    .map_err(Error::MountBind)?;
+    if tmpfs {
+        // The devices are created in the jail, so the tmpfs can't be mounted with nodev. Safe
+        // because we provide valid parameters.
+        SyscallReturnCode(unsafe {
+            libc::mount(
+                TMPFS_NUL_TERMINATED.as_ptr() as *const libc::c_char,
+                chroot_dir.as_ptr(),
+                TMPFS_NUL_TERMINATED.as_ptr() as *const libc::c_char,
+                libc::MS_NOSUID,
+                TMPFS_OPTIONS_NUL_TERMINATED.as_ptr() as *const libc::c_void,
+            )
+        })
+        .into_empty_result()
+        .map_err(Error::MountTmpfs)
+    } else {
+        // Bind mount the jail root directory over itself, so we can go around a restriction
+        // imposed by pivot_root, which states that the new root and the old root should not
+        // be on the same filesystem. Safe because we provide valid parameters.
+        SyscallReturnCode(unsafe {
+            libc::mount(
+                chroot_dir.as_ptr(),
+                chroot_dir.as_ptr(),
+                null(),
+                libc::MS_BIND | libc::MS_REC,
+                null(),
+            )
+        })
+        .into_empty_result()
+        .map_err(Error::MountBind)
+    }
+}
+
+// Jails the process in `path`, after bind mounting `mounts` in it. The mount namespace has to be
+// unshared beforehand.
+pub fn chroot(path: &Path, mounts: &[BindMount]) -> Result<()> {
+    let root_dir =
+        CStr::from_bytes_with_nul(ROOT_DIR_NUL_TERMINATED).map_err(Error::FromBytesWithNul)?;
+
+    for mount in mounts {
+        bind_mount(path, mount)?;
+    }
 
     // Change current dir to the chroot dir, so we only need to handle relative paths from now on.
     env::set_current_dir(path).map_err(Error::SetCurrentDir)?;
@@ -99,3 +278,151 @@ pub fn chroot(path: &Path) -> Result<()> {
         .into_empty_result()
         .map_err(Error::RmOldRootDir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::tempdir::TempDir;
+    use utils::tempfile::TempFile;
+
+    #[test]
+    fn test_parse_mount_allow_root() {
+        let dir = TempDir::new().unwrap();
+        let dir_path = dir.as_path().to_str().unwrap();
+        assert_eq!(
+            parse_mount_allow_root(dir_path).unwrap(),
+            canonicalize(dir_path).unwrap()
+        );
+
+        let file = TempFile::new_in(dir.as_path()).unwrap();
+        for root in [
+            file.as_path().to_str().unwrap(),
+            "/",
+            "/etc",
+            "/root",
+            "/proc/self",
+            "/sys/..",
+            "/proc/../etc",
+        ]
+        .iter()
+        {
+            match parse_mount_allow_root(root) {
+                Err(Error::MountAllowRoot(_)) => (),
+                _ => panic!("{} should not be a valid mount root", root),
+            }
+        }
+        assert!(parse_mount_allow_root("/inexistent/dir").is_err());
+    }
+
+    #[test]
+    fn test_bind_mount_parse() {
+        let root = TempDir::new().unwrap();
+        let allowed_roots = [canonicalize(root.as_path()).unwrap()];
+        let file = TempFile::new_in(root.as_path()).unwrap();
+        let file_path = file.as_path().to_str().unwrap();
+        let dir = TempDir::new_in(root.as_path()).unwrap();
+        let dir_path = dir.as_path().to_str().unwrap();
+
+        let mount =
+            BindMount::parse(&format!("{}:/rootfs.ext4:ro", file_path), &allowed_roots).unwrap();
+        assert_eq!(mount.src, canonicalize(file_path).unwrap());
+        assert_eq!(mount.dst, PathBuf::from("/rootfs.ext4"));
+        assert!(mount.read_only);
+        let mount = BindMount::parse(&format!("{}:/srv/images", dir_path), &allowed_roots).unwrap();
+        assert_eq!(mount.dst, PathBuf::from("/srv/images"));
+        assert!(!mount.read_only);
+
+        for invalid in [
+            file_path.to_string(),
+            format!("{}:/rootfs.ext4:rw", file_path),
+            format!("{}:/rootfs.ext4:ro:ro", file_path),
+            ":/rootfs.ext4".to_string(),
+        ]
+        .iter()
+        {
+            match BindMount::parse(invalid, &allowed_roots) {
+                Err(Error::MountFormat(arg)) => assert_eq!(&arg, invalid),
+                _ => panic!("{} should not parse", invalid),
+            }
+        }
+
+        for dst in [
+            "",
+            "rootfs.ext4",
+            "/",
+            "/../rootfs.ext4",
+            "/srv/../..",
+            "/srv/",
+        ]
+        .iter()
+        {
+            match BindMount::parse(&format!("{}:{}", file_path, dst), &allowed_roots) {
+                Err(Error::MountDestination(arg)) => assert_eq!(arg, *dst),
+                _ => panic!("{} should not be a valid destination", dst),
+            }
+        }
+
+        // The sources have to be in one of the allowed roots, even when they hold them.
+        let outside = TempFile::new().unwrap();
+        for src in [
+            outside.as_path().to_str().unwrap(),
+            "/",
+            "/etc/passwd",
+            "/proc/self/status",
+            "/dev/null",
+            "/sys",
+        ]
+        .iter()
+        {
+            match BindMount::parse(&format!("{}:/foo", src), &allowed_roots) {
+                Err(Error::MountSource(_)) => (),
+                _ => panic!("{} should not be a valid source", src),
+            }
+        }
+        match BindMount::parse("/:/foo", &[PathBuf::from("/")]) {
+            Err(Error::MountSource(_)) => (),
+            _ => panic!("/ should not be a valid source"),
+        }
+        assert!(BindMount::parse("/inexistent/file:/foo", &allowed_roots).is_err());
+    }
+
+    #[test]
+    fn test_validate_mounts() {
+        let mount = |dst: &str| BindMount {
+            src: PathBuf::from("/tmp"),
+            dst: PathBuf::from(dst),
+            read_only: false,
+        };
+        let exec_file_name = OsStr::new("firecracker");
+
+        assert!(validate_mounts(&[], exec_file_name).is_ok());
+        assert!(validate_mounts(
+            &[
+                mount("/rootfs.ext4"),
+                mount("/vmlinux"),
+                mount("/run/sockets")
+            ],
+            exec_file_name
+        )
+        .is_ok());
+        // The exec file name is only reserved as a whole.
+        assert!(validate_mounts(&[mount("/firecracker-data")], exec_file_name).is_ok());
+
+        for mounts in [
+            vec![mount("/firecracker")],
+            vec![mount("/dev")],
+            vec![mount("/dev/kvm")],
+            vec![mount("/old_root")],
+            vec![mount("/srv"), mount("/srv")],
+            vec![mount("/srv"), mount("/srv/images")],
+            vec![mount("/srv/images"), mount("/srv")],
+        ]
+        .iter()
+        {
+            match validate_mounts(mounts, exec_file_name) {
+                Err(Error::MountDestination(_)) => (),
+                _ => panic!("{:?} should not be valid", mounts),
+            }
+        }
+    }
+}
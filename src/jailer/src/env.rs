@@ -11,9 +11,12 @@ use std::process::{Command, Stdio};
 
 use crate::cgroup;
 use crate::cgroup::Cgroup;
-use crate::chroot::chroot;
+use crate::chroot::{self, chroot, BindMount};
+use crate::network::{self, NetNsConfig};
+use crate::resource_limits::ResourceLimit;
+use crate::userns::{IdMaps, UserNsMapper};
 use crate::{Error, Result};
-use utils::arg_parser::Error::MissingValue;
+use utils::arg_parser::Error::{MissingValue, UnexpectedArgument};
 use utils::syscall::SyscallReturnCode;
 use utils::{arg_parser, validators};
 
@@ -59,11 +62,15 @@ pub struct Env {
     uid: u32,
     gid: u32,
     netns: Option<String>,
+    new_netns: Option<NetNsConfig>,
     daemonize: bool,
     start_time_us: u64,
     start_time_cpu_us: u64,
     extra_args: Vec<String>,
     cgroups: Vec<Cgroup>,
+    mounts: Vec<BindMount>,
+    resource_limits: Vec<ResourceLimit>,
+    id_maps: Option<IdMaps>,
 }
 
 impl Env {
@@ -123,8 +130,34 @@ impl Env {
 
         let netns = arguments.single_value("netns").cloned();
 
+        let new_netns = if arguments.flag_present("new-netns") {
+            // The microVM can't both join a network namespace and get a new one.
+            if netns.is_some() {
+                return Err(Error::ArgumentParsing(UnexpectedArgument(
+                    "netns".to_string(),
+                )));
+            }
+            Some(NetNsConfig::new(
+                arguments.single_value("veth").map(String::as_str),
+                arguments.single_value("tap").map(String::as_str),
+            )?)
+        } else {
+            None
+        };
+
         let daemonize = arguments.flag_present("daemonize");
 
+        let id_maps = if arguments.flag_present("userns") {
+            Some(IdMaps::new(
+                arguments.single_value("uid-map").map(String::as_str),
+                arguments.single_value("gid-map").map(String::as_str),
+                uid,
+                gid,
+            )?)
+        } else {
+            None
+        };
+
         // Optional arguments.
         let mut cgroups = Vec::new();
 
@@ -142,10 +175,11 @@ impl Env {
         }
 
         // cgroup format: <cgroup_controller>.<cgroup_property>=<value>,...
+        // The value may hold '=' characters itself (e.g io.max=8:0 rbps=1048576).
         if let Some(cgroups_args) = arguments.multiple_values("cgroup") {
             for cg in cgroups_args {
-                let aux: Vec<&str> = cg.split('=').collect();
-                if aux.len() != 2 || aux[1].is_empty() {
+                let aux: Vec<&str> = cg.splitn(2, '=').collect();
+                if aux.len() != 2 || aux[1].is_empty() || aux[1].contains('\n') {
                     return Err(Error::CgroupFormat(cg.to_string()));
                 }
 
@@ -160,6 +194,28 @@ impl Env {
             }
         }
 
+        // mount format: <src>:<dst>[:ro]
+        let mut mounts = Vec::new();
+        if let Some(mount_args) = arguments.multiple_values("mount") {
+            let mut allowed_roots = Vec::new();
+            // Safe to unwrap, since `mount` requires `mount-allow`.
+            for root in arguments.multiple_values("mount-allow").unwrap() {
+                allowed_roots.push(chroot::parse_mount_allow_root(root)?);
+            }
+            for mount in mount_args {
+                mounts.push(BindMount::parse(mount, &allowed_roots)?);
+            }
+            chroot::validate_mounts(&mounts, exec_file_name)?;
+        }
+
+        // resource limit format: <resource>=<value>
+        let mut resource_limits = Vec::new();
+        if let Some(limit_args) = arguments.multiple_values("resource-limit") {
+            for limit in limit_args {
+                resource_limits.push(ResourceLimit::parse(limit)?);
+            }
+        }
+
         Ok(Env {
             id: id.to_owned(),
             chroot_dir,
@@ -167,11 +223,15 @@ impl Env {
             uid,
             gid,
             netns,
+            new_netns,
             daemonize,
             start_time_us,
             start_time_cpu_us,
             extra_args: arguments.extra_args(),
             cgroups,
+            mounts,
+            resource_limits,
+            id_maps,
         })
     }
 
@@ -283,6 +343,17 @@ impl Env {
     }
 
     pub fn run(mut self) -> Result<()> {
+        // The helper writing the id maps has to be able to reach /proc, so fork it while still
+        // in the host mount namespace.
+        let userns_mapper = match self.id_maps {
+            Some(ref id_maps) => Some(UserNsMapper::spawn(id_maps)?),
+            None => None,
+        };
+
+        // When mounts are requested, the jail root is a tmpfs holding only them and what the
+        // jailer puts in it, so it has to be mounted before copying the exec file.
+        chroot::unshare_mount_ns(self.chroot_dir(), !self.mounts.is_empty())?;
+
         let exec_file_name = self.copy_exec_to_chroot()?;
         let chroot_exec_file = PathBuf::from("/").join(&exec_file_name);
 
@@ -291,17 +362,20 @@ impl Env {
             Env::join_netns(path)?;
         }
 
+        // Or create a new one, still having access to the host network namespace and /dev/net/tun.
+        if let Some(ref config) = self.new_netns {
+            network::setup_netns(config, self.uid(), self.gid())?;
+        }
+
         // We have to setup cgroups at this point, because we can't do it anymore after chrooting.
         // cgroups are iterated two times as some cgroups may require others (e.g cpuset requires
         // cpuset.mems and cpuset.cpus) to be set before attaching any pid.
         for cgroup in &self.cgroups {
-            // it will panic if any cgroup fails to write
-            cgroup.write_value().unwrap();
+            cgroup.write_value()?;
         }
 
         for cgroup in &self.cgroups {
-            // it will panic if any cgroup fails to attach
-            cgroup.attach_pid().unwrap();
+            cgroup.attach_pid()?;
         }
 
         // If daemonization was requested, open /dev/null before chrooting.
@@ -322,7 +396,7 @@ impl Env {
         };
 
         // Jail self.
-        chroot(self.chroot_dir())?;
+        chroot(self.chroot_dir(), &self.mounts)?;
 
         // This will not only create necessary directories, but will also change ownership
         // for all of them.
@@ -360,6 +434,21 @@ impl Env {
                 .map_err(Error::CloseDevNullFd)?;
         }
 
+        // Set the resource limits while still privileged on the host, so they can be raised.
+        for limit in &self.resource_limits {
+            limit.install()?;
+        }
+
+        // Move into the user namespace last, since creating the devices and changing the owners
+        // requires being root on the host.
+        let (exec_uid, exec_gid) = match self.id_maps {
+            Some(ref id_maps) => (id_maps.uid, id_maps.gid),
+            None => (self.uid(), self.gid()),
+        };
+        if let Some(mapper) = userns_mapper {
+            mapper.unshare()?;
+        }
+
         Err(Error::Exec(
             Command::new(chroot_exec_file)
                 .args(&["--id", &self.id])
@@ -368,8 +457,8 @@ impl Env {
                 .stdin(Stdio::inherit())
                 .stdout(Stdio::inherit())
                 .stderr(Stdio::inherit())
-                .uid(self.uid())
-                .gid(self.gid())
+                .uid(exec_uid)
+                .gid(exec_gid)
                 .args(self.extra_args)
                 .exec(),
         ))
@@ -801,6 +890,24 @@ mod tests {
         args.parse(&make_args(&invalid_cgroup_arg_vals)).unwrap();
         assert!(Env::new(&args, 0, 0).is_err());
 
+        // Check value with a newline
+        let mut args = arg_parser.arguments().clone();
+        let invalid_cgroup_arg_vals = ArgVals {
+            cgroups: vec!["cpuset.cpus=2\n3"],
+            ..good_arg_vals.clone()
+        };
+        args.parse(&make_args(&invalid_cgroup_arg_vals)).unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+
+        // Check file name escaping the cgroup
+        let mut args = arg_parser.arguments().clone();
+        let invalid_cgroup_arg_vals = ArgVals {
+            cgroups: vec!["cpuset./cpus=2"],
+            ..good_arg_vals.clone()
+        };
+        args.parse(&make_args(&invalid_cgroup_arg_vals)).unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+
         // Check valid file no value
         let mut args = arg_parser.arguments().clone();
         let invalid_cgroup_arg_vals = ArgVals {
@@ -821,6 +928,15 @@ mod tests {
         args.parse(&make_args(&invalid_cgroup_arg_vals)).unwrap();
         assert!(Env::new(&args, 0, 0).is_ok());
 
+        // Check value with '=' and spaces
+        let mut args = arg_parser.arguments().clone();
+        let invalid_cgroup_arg_vals = ArgVals {
+            cgroups: vec!["cpuset.cpus=2 =3"],
+            ..good_arg_vals.clone()
+        };
+        args.parse(&make_args(&invalid_cgroup_arg_vals)).unwrap();
+        assert!(Env::new(&args, 0, 0).is_ok());
+
         // Check valid case
         let mut args = arg_parser.arguments().clone();
         let invalid_cgroup_arg_vals = ArgVals {
@@ -830,4 +946,147 @@ mod tests {
         args.parse(&make_args(&invalid_cgroup_arg_vals)).unwrap();
         assert!(Env::new(&args, 0, 0).is_ok());
     }
+
+    #[test]
+    fn test_new_netns_parsing() {
+        let arg_parser = build_arg_parser();
+        let arg_vals = ArgVals {
+            netns: None,
+            ..ArgVals::new()
+        };
+        let make_netns_args = |arg_vals: &ArgVals, extra_args: &[&str]| {
+            let mut arg_vec = make_args(arg_vals);
+            arg_vec.extend(extra_args.iter().map(|arg| (*arg).to_string()));
+            arg_vec
+        };
+
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_netns_args(&arg_vals, &[])).unwrap();
+        assert!(Env::new(&args, 0, 0).unwrap().new_netns.is_none());
+
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_netns_args(
+            &arg_vals,
+            &[
+                "--new-netns",
+                "--veth",
+                "veth-fc0:10.0.0.1/30,eth0:10.0.0.2/30",
+                "--tap",
+                "tap0:172.16.0.1/24",
+            ],
+        ))
+        .unwrap();
+        let config = Env::new(&args, 0, 0).unwrap().new_netns.unwrap();
+        assert_eq!(config.veth.unwrap().1.name, "eth0");
+        assert_eq!(config.tap.unwrap().name, "tap0");
+
+        // The plumbing requires a new network namespace.
+        let mut args = arg_parser.arguments().clone();
+        assert!(args
+            .parse(&make_netns_args(&arg_vals, &["--tap", "tap0"]))
+            .is_err());
+
+        // A network namespace can't be both joined and created.
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_netns_args(&ArgVals::new(), &["--new-netns"]))
+            .unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_netns_args(
+            &arg_vals,
+            &["--new-netns", "--veth", "veth-fc0:10.0.0.1/30"],
+        ))
+        .unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_resource_limits_parsing() {
+        let arg_parser = build_arg_parser();
+        let mut arg_vec = make_args(&ArgVals::new());
+        arg_vec.extend(
+            [
+                "--resource-limit",
+                "nofile=1024",
+                "--resource-limit",
+                "core=0",
+            ]
+            .iter()
+            .map(|arg| (*arg).to_string()),
+        );
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&arg_vec).unwrap();
+        let env = Env::new(&args, 0, 0).unwrap();
+        assert_eq!(env.resource_limits.len(), 2);
+        assert_eq!(env.resource_limits[0].value, 1024);
+
+        arg_vec.extend(
+            ["--resource-limit", "nofile=-1"]
+                .iter()
+                .map(|arg| (*arg).to_string()),
+        );
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&arg_vec).unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_userns_parsing() {
+        let arg_parser = build_arg_parser();
+        let good_arg_vals = ArgVals::new();
+        let make_userns_args = |extra_args: &[&str]| {
+            let mut arg_vec = make_args(&good_arg_vals);
+            arg_vec.extend(extra_args.iter().map(|arg| (*arg).to_string()));
+            arg_vec
+        };
+
+        // No user namespace by default.
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_args(&good_arg_vals)).unwrap();
+        assert!(Env::new(&args, 0, 0).unwrap().id_maps.is_none());
+
+        // The namespace root maps to the uid and gid.
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_userns_args(&["--userns"])).unwrap();
+        let id_maps = Env::new(&args, 0, 0).unwrap().id_maps.unwrap();
+        assert_eq!(
+            (id_maps.uid_map[0].outside, id_maps.gid_map[0].outside),
+            (1001, 1002)
+        );
+        assert_eq!((id_maps.uid, id_maps.gid), (0, 0));
+
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_userns_args(&[
+            "--userns",
+            "--uid-map",
+            "0:100000:1000,1000:1001:1",
+            "--gid-map",
+            "0:1002:1",
+        ]))
+        .unwrap();
+        let id_maps = Env::new(&args, 0, 0).unwrap().id_maps.unwrap();
+        assert_eq!((id_maps.uid, id_maps.gid), (1000, 0));
+
+        // The maps require a user namespace.
+        let mut args = arg_parser.arguments().clone();
+        assert!(args
+            .parse(&make_userns_args(&["--uid-map", "0:1001:1"]))
+            .is_err());
+
+        // The uid has to be mapped.
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_userns_args(&[
+            "--userns",
+            "--uid-map",
+            "0:100000:1000",
+        ]))
+        .unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+
+        let mut args = arg_parser.arguments().clone();
+        args.parse(&make_userns_args(&["--userns", "--gid-map", "0:1002"]))
+            .unwrap();
+        assert!(Env::new(&args, 0, 0).is_err());
+    }
 }
@@ -3,6 +3,9 @@
 mod cgroup;
 mod chroot;
 mod env;
+mod network;
+mod resource_limits;
+mod userns;
 
 use std::ffi::{CString, NulError, OsString};
 use std::fmt;
@@ -24,7 +27,10 @@ pub enum Error {
     CgroupInheritFromParent(PathBuf, String),
     CgroupLineNotFound(String, String),
     CgroupInvalidFile(String),
+    CgroupReadOnlyFile(PathBuf),
+    CgroupUnknownFile(String, PathBuf),
     CgroupWrite(String, String, String),
+    CgroupWriteValue(PathBuf, String, io::Error),
     CgroupFormat(String),
     ChangeFileOwner(PathBuf, io::Error),
     ChdirNewRoot(io::Error),
@@ -43,10 +49,21 @@ pub enum Error {
     Gid(String),
     InvalidInstanceId(validators::Error),
     MissingParent(PathBuf),
+    Metadata(PathBuf, io::Error),
     MkdirOldRoot(io::Error),
     MknodDev(io::Error, &'static str),
+    MountAllowRoot(PathBuf),
     MountBind(io::Error),
+    MountBindPath(PathBuf, io::Error),
+    MountDestination(String),
+    MountFormat(String),
     MountPropagationSlave(io::Error),
+    MountSource(PathBuf),
+    MountTmpfs(io::Error),
+    NetIfIndex(String, io::Error),
+    NetIfaceFormat(String),
+    Netlink(String, io::Error),
+    NetlinkSocket(io::Error),
     NotAFile(PathBuf),
     NotADirectory(PathBuf),
     NumaNode(String),
@@ -56,15 +73,29 @@ pub enum Error {
     ReadLine(PathBuf, io::Error),
     ReadToString(PathBuf, io::Error),
     RegEx(regex::Error),
+    ResLimitArgument(String),
+    ResLimitFormat(String),
+    ResLimitValue(String),
     RmOldRootDir(io::Error),
     SetCurrentDir(io::Error),
     SetNetNs(io::Error),
+    SetGroups(io::Error),
     SetSid(io::Error),
+    Setrlimit(String, io::Error),
+    TapCreate(String, io::Error),
     Uid(String),
     UmountOldRoot(io::Error),
     UnexpectedListenerFd(i32),
+    UnshareNewNetNs(io::Error),
     UnshareNewNs(io::Error),
+    UnshareNewUserNs(io::Error),
     UnsetCloexec(io::Error),
+    UserNsFork(io::Error),
+    UserNsMapFormat(String),
+    UserNsMapWrite,
+    UserNsPipe(io::Error),
+    UserNsUnmappedId(&'static str, u32),
+    UserNsWait(io::Error),
     Write(PathBuf, io::Error),
 }
 
@@ -97,11 +128,30 @@ impl fmt::Display for Error {
                 controller, proc_mounts
             ),
             CgroupInvalidFile(ref file) => write!(f, "Cgroup invalid file: {}", file,),
+            CgroupReadOnlyFile(ref path) => write!(
+                f,
+                "{}",
+                format!("Cgroup file {:?} is read-only", path).replace("\"", "")
+            ),
+            CgroupUnknownFile(ref file, ref path) => write!(
+                f,
+                "{}",
+                format!("Cgroup file {} does not exist in {:?}", file, path).replace("\"", "")
+            ),
             CgroupWrite(ref evalue, ref rvalue, ref file) => write!(
                 f,
                 "Expected value {} for {}. Current value: {}",
                 evalue, file, rvalue
             ),
+            CgroupWriteValue(ref path, ref value, ref err) => write!(
+                f,
+                "{}",
+                format!(
+                    "Failed to write {} to cgroup file {:?}: {}",
+                    value, path, err
+                )
+                .replace("\"", "")
+            ),
             CgroupFormat(ref arg) => write!(f, "Invalid format for cgroups: {}", arg,),
             ChangeFileOwner(ref path, ref err) => {
                 write!(f, "Failed to change owner for {:?}: {}", path, err)
@@ -153,12 +203,45 @@ impl fmt::Display for Error {
                 "Failed to create {} via mknod inside the jail: {}",
                 devname, err
             ),
+            Metadata(ref path, ref err) => write!(
+                f,
+                "{}",
+                format!("Failed to get the metadata of {:?}: {}", path, err).replace("\"", "")
+            ),
+            MountAllowRoot(ref path) => write!(
+                f,
+                "{}",
+                format!("Mount root {:?} is not allowed", path).replace("\"", "")
+            ),
             MountBind(ref err) => {
                 write!(f, "Failed to bind mount the jail root directory: {}", err)
             }
+            MountBindPath(ref path, ref err) => write!(
+                f,
+                "{}",
+                format!("Failed to bind mount {:?} in the jail: {}", path, err).replace("\"", "")
+            ),
+            MountDestination(ref dst) => write!(f, "Invalid mount destination: {}", dst),
+            MountFormat(ref arg) => write!(f, "Invalid format for mount: {}", arg),
             MountPropagationSlave(ref err) => {
                 write!(f, "Failed to change the propagation type to slave: {}", err)
             }
+            MountSource(ref path) => write!(
+                f,
+                "{}",
+                format!("Mount source {:?} is not allowed", path).replace("\"", "")
+            ),
+            MountTmpfs(ref err) => write!(
+                f,
+                "Failed to mount a tmpfs on the jail root directory: {}",
+                err
+            ),
+            NetIfIndex(ref name, ref err) => {
+                write!(f, "Failed to find the network interface {}: {}", name, err)
+            }
+            NetIfaceFormat(ref arg) => write!(f, "Invalid format for network interface: {}", arg),
+            Netlink(ref what, ref err) => write!(f, "Failed to {} via netlink: {}", what, err),
+            NetlinkSocket(ref err) => write!(f, "Failed to open a netlink socket: {}", err),
             NotAFile(ref path) => write!(
                 f,
                 "{}",
@@ -188,23 +271,57 @@ impl fmt::Display for Error {
                 format!("Failed to read file {:?} into a string: {}", path, err).replace("\"", "")
             ),
             RegEx(ref err) => write!(f, "Regex failed: {:?}", err),
+            ResLimitArgument(ref arg) => write!(f, "Invalid resource argument: {}", arg),
+            ResLimitFormat(ref arg) => write!(f, "Invalid format for resources limits: {}", arg),
+            ResLimitValue(ref arg) => write!(f, "Invalid limit value for resource: {}", arg),
             RmOldRootDir(ref err) => write!(f, "Failed to remove old jail root directory: {}", err),
             SetCurrentDir(ref err) => write!(f, "Failed to change current directory: {}", err),
             SetNetNs(ref err) => write!(f, "Failed to join network namespace: netns: {}", err),
+            SetGroups(ref err) => write!(f, "Failed to drop the supplementary groups: {}", err),
             SetSid(ref err) => write!(f, "Failed to daemonize: setsid: {}", err),
+            Setrlimit(ref resource, ref err) => write!(
+                f,
+                "Failed to set the limit of the {} resource: {}",
+                resource, err
+            ),
+            TapCreate(ref name, ref err) => {
+                write!(f, "Failed to create the tap device {}: {}", name, err)
+            }
             Uid(ref uid) => write!(f, "Invalid uid: {}", uid),
             UmountOldRoot(ref err) => write!(f, "Failed to unmount the old jail root: {}", err),
             UnexpectedListenerFd(fd) => {
                 write!(f, "Unexpected value for the socket listener fd: {}", fd)
             }
+            UnshareNewNetNs(ref err) => {
+                write!(f, "Failed to unshare into new network namespace: {}", err)
+            }
             UnshareNewNs(ref err) => {
                 write!(f, "Failed to unshare into new mount namespace: {}", err)
             }
+            UnshareNewUserNs(ref err) => {
+                write!(f, "Failed to unshare into new user namespace: {}", err)
+            }
             UnsetCloexec(ref err) => write!(
                 f,
                 "Failed to unset the O_CLOEXEC flag on the socket fd: {}",
                 err
             ),
+            UserNsFork(ref err) => write!(f, "Failed to fork the user namespace helper: {}", err),
+            UserNsMapFormat(ref map) => write!(f, "Invalid format for the id map: {}", map),
+            UserNsMapWrite => write!(f, "Failed to write the user namespace id maps"),
+            UserNsPipe(ref err) => write!(
+                f,
+                "Failed to communicate with the user namespace helper: {}",
+                err
+            ),
+            UserNsUnmappedId(ref kind, id) => write!(
+                f,
+                "The {} {} is not mapped into the user namespace",
+                kind, id
+            ),
+            UserNsWait(ref err) => {
+                write!(f, "Failed to wait for the user namespace helper: {}", err)
+            }
             Write(ref path, ref err) => write!(
                 f,
                 "{}",
@@ -261,15 +378,86 @@ pub fn build_arg_parser() -> ArgParser<'static> {
                 .takes_value(true)
                 .help("Path to the network namespace this microVM should join."),
         )
+        .arg(Argument::new("new-netns").takes_value(false).help(
+            "Create a new network namespace for this microVM, instead of joining one with \
+             netns.",
+        ))
+        .arg(
+            Argument::new("veth")
+                .takes_value(true)
+                .requires("new-netns")
+                .help(
+                    "Veth pair linking the new network namespace to the host, following this \
+                     format: <host_if>:<ip>/<prefix_len>,<jail_if>:<ip>/<prefix_len> \
+                     (e.g veth-fc0:10.0.0.1/30,eth0:10.0.0.2/30).",
+                ),
+        )
+        .arg(
+            Argument::new("tap")
+                .takes_value(true)
+                .requires("new-netns")
+                .help(
+                    "Tap device to create in the new network namespace, following this \
+                     format: <tap_if>[:<ip>/<prefix_len>] (e.g tap0:172.16.0.1/24).",
+                ),
+        )
         .arg(Argument::new("daemonize").takes_value(false).help(
             "Daemonize the jailer before exec, by invoking setsid(), and redirecting \
              the standard I/O file descriptors to /dev/null.",
         ))
+        .arg(
+            Argument::new("mount")
+                .allow_multiple(true)
+                .requires("mount-allow")
+                .help(
+                    "File or directory to bind mount in the jail, following this format: \
+                     <src>:<dst>[:ro] (e.g /srv/vm/rootfs.ext4:/rootfs.ext4:ro). The source has \
+                     to be in one of the --mount-allow directories. The jail root is then a \
+                     tmpfs. This argument can be used multiple times to add multiple mounts.",
+                ),
+        )
+        .arg(Argument::new("mount-allow").allow_multiple(true).help(
+            "Host directory whose content can be bind mounted in the jail with --mount (e.g \
+             /srv/vm). It can't be /, nor hold or lie in /boot, /dev, /etc, /proc, /root or \
+             /sys. This argument can be used multiple times to allow multiple directories.",
+        ))
+        .arg(Argument::new("resource-limit").allow_multiple(true).help(
+            "Resource limit for the jailed process, following this format: \
+             <resource>=<value> (e.g nofile=1024). The value sets both the soft and the hard \
+             limits, and may be `unlimited`. The supported resources are core, fsize, memlock \
+             and nofile. This argument can be used multiple times to set multiple limits.",
+        ))
         .arg(Argument::new("cgroup").allow_multiple(true).help(
             "Cgroup and value to be set by the jailer. It must follow this format: \
-             <cgroup_file>=<value> (e.g cpu.shares=10). This argument can be used
+             <cgroup_file>=<value> (e.g cpu.shares=10). Any writable file of the cgroup \
+             can be set (e.g memory.high=1G). This argument can be used
              multiple times to add multiple cgroups.",
         ))
+        .arg(Argument::new("userns").takes_value(false).help(
+            "Run the jailed process in a new user namespace, where it is root. By default, the \
+             namespace root is mapped to the uid and gid given to the jailer, which is what the \
+             process runs as on the host.",
+        ))
+        .arg(
+            Argument::new("uid-map")
+                .takes_value(true)
+                .requires("userns")
+                .help(
+                    "The uid mapping of the user namespace, following this format: \
+                     <inside>:<outside>:<count>[,<inside>:<outside>:<count>...] \
+                     (e.g 0:1001:1). The jailed process runs as the id which the uid is mapped \
+                     to. Defaults to 0:<uid>:1.",
+                ),
+        )
+        .arg(
+            Argument::new("gid-map")
+                .takes_value(true)
+                .requires("userns")
+                .help(
+                    "The gid mapping of the user namespace, following the format of uid-map. \
+                     Defaults to 0:<gid>:1.",
+                ),
+        )
         .arg(
             Argument::new("version")
                 .takes_value(false)
@@ -447,6 +635,35 @@ mod tests {
             ),
             "Expected value 1 for cpuset.mems. Current value: 2",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::CgroupReadOnlyFile(PathBuf::from("/sys/fs/cgroup/cpu/cpu.stat"))
+            ),
+            "Cgroup file /sys/fs/cgroup/cpu/cpu.stat is read-only",
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::CgroupUnknownFile(
+                    "cpu.limit".to_string(),
+                    PathBuf::from("/sys/fs/cgroup/cpu")
+                )
+            ),
+            "Cgroup file cpu.limit does not exist in /sys/fs/cgroup/cpu",
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::CgroupWriteValue(
+                    PathBuf::from("/sys/fs/cgroup/cpu.max"),
+                    "none".to_string(),
+                    io::Error::from_raw_os_error(22)
+                )
+            ),
+            "Failed to write none to cgroup file /sys/fs/cgroup/cpu.max: Invalid argument \
+             (os error 22)",
+        );
         assert_eq!(
             format!("{}", Error::CgroupFormat(cgroup_file.to_string())),
             "Invalid format for cgroups: cpuset.mems",
@@ -559,6 +776,17 @@ mod tests {
             "Failed to create /dev/net/tun via mknod inside the jail: No message of desired type \
              (os error 42)",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::Metadata(path.clone(), io::Error::from_raw_os_error(42))
+            ),
+            "Failed to get the metadata of /foo: No message of desired type (os error 42)",
+        );
+        assert_eq!(
+            format!("{}", Error::MountAllowRoot(path.clone())),
+            "Mount root /foo is not allowed",
+        );
         assert_eq!(
             format!("{}", Error::MountBind(io::Error::from_raw_os_error(42))),
             "Failed to bind mount the jail root directory: No message of desired type (os error 42)",
@@ -567,6 +795,55 @@ mod tests {
             format!("{}", Error::MountPropagationSlave(io::Error::from_raw_os_error(42))),
             "Failed to change the propagation type to slave: No message of desired type (os error 42)",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::MountBindPath(path.clone(), io::Error::from_raw_os_error(42))
+            ),
+            "Failed to bind mount /foo in the jail: No message of desired type (os error 42)",
+        );
+        assert_eq!(
+            format!("{}", Error::MountDestination("/dev/kvm".to_string())),
+            "Invalid mount destination: /dev/kvm",
+        );
+        assert_eq!(
+            format!("{}", Error::MountFormat("/foo".to_string())),
+            "Invalid format for mount: /foo",
+        );
+        assert_eq!(
+            format!("{}", Error::MountSource(path)),
+            "Mount source /foo is not allowed",
+        );
+        assert_eq!(
+            format!("{}", Error::MountTmpfs(io::Error::from_raw_os_error(42))),
+            "Failed to mount a tmpfs on the jail root directory: No message of desired type (os \
+             error 42)",
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::NetIfIndex("eth0".to_string(), io::Error::from_raw_os_error(42))
+            ),
+            "Failed to find the network interface eth0: No message of desired type (os error 42)",
+        );
+        assert_eq!(
+            format!("{}", Error::NetIfaceFormat("eth0:10.0.0.1".to_string())),
+            "Invalid format for network interface: eth0:10.0.0.1",
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::Netlink(
+                    "bring up interface 2".to_string(),
+                    io::Error::from_raw_os_error(42)
+                )
+            ),
+            "Failed to bring up interface 2 via netlink: No message of desired type (os error 42)",
+        );
+        assert_eq!(
+            format!("{}", Error::NetlinkSocket(io::Error::from_raw_os_error(42))),
+            "Failed to open a netlink socket: No message of desired type (os error 42)",
+        );
         assert_eq!(
             format!("{}", Error::NotAFile(file_path.clone())),
             "/foo/bar is not a file",
@@ -612,6 +889,18 @@ mod tests {
             format!("{}", Error::RegEx(err_regex.clone())),
             format!("Regex failed: {:?}", err_regex),
         );
+        assert_eq!(
+            format!("{}", Error::ResLimitArgument("stack".to_string())),
+            "Invalid resource argument: stack",
+        );
+        assert_eq!(
+            format!("{}", Error::ResLimitFormat("nofile".to_string())),
+            "Invalid format for resources limits: nofile",
+        );
+        assert_eq!(
+            format!("{}", Error::ResLimitValue("1k".to_string())),
+            "Invalid limit value for resource: 1k",
+        );
         assert_eq!(
             format!("{}", Error::RmOldRootDir(io::Error::from_raw_os_error(42))),
             "Failed to remove old jail root directory: No message of desired type (os error 42)",
@@ -624,10 +913,29 @@ mod tests {
             format!("{}", Error::SetNetNs(io::Error::from_raw_os_error(42))),
             "Failed to join network namespace: netns: No message of desired type (os error 42)",
         );
+        assert_eq!(
+            format!("{}", Error::SetGroups(io::Error::from_raw_os_error(42))),
+            "Failed to drop the supplementary groups: No message of desired type (os error 42)",
+        );
         assert_eq!(
             format!("{}", Error::SetSid(io::Error::from_raw_os_error(42))),
             "Failed to daemonize: setsid: No message of desired type (os error 42)",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::Setrlimit("nofile".to_string(), io::Error::from_raw_os_error(42))
+            ),
+            "Failed to set the limit of the nofile resource: No message of desired type (os \
+             error 42)",
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::TapCreate("tap0".to_string(), io::Error::from_raw_os_error(42))
+            ),
+            "Failed to create the tap device tap0: No message of desired type (os error 42)",
+        );
         assert_eq!(
             format!("{}", Error::Uid(id.to_string())),
             "Invalid uid: foobar",
@@ -640,15 +948,56 @@ mod tests {
             format!("{}", Error::UnexpectedListenerFd(42)),
             "Unexpected value for the socket listener fd: 42",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::UnshareNewNetNs(io::Error::from_raw_os_error(42))
+            ),
+            "Failed to unshare into new network namespace: No message of desired type (os error \
+             42)",
+        );
         assert_eq!(
             format!("{}", Error::UnshareNewNs(io::Error::from_raw_os_error(42))),
             "Failed to unshare into new mount namespace: No message of desired type (os error 42)",
         );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::UnshareNewUserNs(io::Error::from_raw_os_error(42))
+            ),
+            "Failed to unshare into new user namespace: No message of desired type (os error 42)",
+        );
         assert_eq!(
             format!("{}", Error::UnsetCloexec(io::Error::from_raw_os_error(42))),
             "Failed to unset the O_CLOEXEC flag on the socket fd: No message of desired type (os \
              error 42)",
         );
+        assert_eq!(
+            format!("{}", Error::UserNsFork(io::Error::from_raw_os_error(42))),
+            "Failed to fork the user namespace helper: No message of desired type (os error 42)",
+        );
+        assert_eq!(
+            format!("{}", Error::UserNsMapFormat("0:1001".to_string())),
+            "Invalid format for the id map: 0:1001",
+        );
+        assert_eq!(
+            format!("{}", Error::UserNsMapWrite),
+            "Failed to write the user namespace id maps",
+        );
+        assert_eq!(
+            format!("{}", Error::UserNsPipe(io::Error::from_raw_os_error(42))),
+            "Failed to communicate with the user namespace helper: No message of desired type \
+             (os error 42)",
+        );
+        assert_eq!(
+            format!("{}", Error::UserNsUnmappedId("uid", 1001)),
+            "The uid 1001 is not mapped into the user namespace",
+        );
+        assert_eq!(
+            format!("{}", Error::UserNsWait(io::Error::from_raw_os_error(42))),
+            "Failed to wait for the user namespace helper: No message of desired type (os error \
+             42)",
+        );
         assert_eq!(
             format!(
                 "{}",
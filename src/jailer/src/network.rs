@@ -0,0 +1,582 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::net::Ipv4Addr;
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+
+use super::{Error, Result};
+use net_gen::ifreq;
+use utils::ioctl::{ioctl_with_mut_ref, ioctl_with_val};
+use utils::syscall::SyscallReturnCode;
+use utils::{ioctl_expr, ioctl_ioc_nr, ioctl_iow_nr};
+
+// As defined in the Linux UAPI, including the trailing NUL byte.
+const IFACE_NAME_MAX_LEN: usize = 16;
+
+const TUNTAP: ::std::os::raw::c_uint = 84;
+ioctl_iow_nr!(TUNSETIFF, TUNTAP, 202, c_int);
+ioctl_iow_nr!(TUNSETPERSIST, TUNTAP, 203, c_int);
+ioctl_iow_nr!(TUNSETOWNER, TUNTAP, 204, c_int);
+ioctl_iow_nr!(TUNSETGROUP, TUNTAP, 206, c_int);
+
+// The rtnetlink definitions we need, from include/uapi/linux/rtnetlink.h, if_link.h,
+// if_addr.h and veth.h.
+const RTM_NEWLINK: u16 = 16;
+const RTM_NEWADDR: u16 = 20;
+const RTM_NEWROUTE: u16 = 24;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_NET_NS_FD: u16 = 28;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const VETH_INFO_PEER: u16 = 1;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const RTA_DST: u16 = 1;
+const RTA_GATEWAY: u16 = 5;
+const RT_TABLE_MAIN: u8 = 254;
+const RTPROT_BOOT: u8 = 3;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RTN_UNICAST: u8 = 1;
+const IFF_UP: u32 = 1;
+
+const NLMSG_ALIGNTO: usize = 4;
+const NLMSG_HDR_LEN: usize = 16;
+const NLMSG_ERROR_LEN: usize = NLMSG_HDR_LEN + 4;
+const NLMSG_BUF_SIZE: usize = 4096;
+
+const LOOPBACK_NAME: &str = "lo";
+const NETNS_PATH: &str = "/proc/self/ns/net";
+const IP_FORWARD_PATH: &str = "/proc/sys/net/ipv4/ip_forward";
+
+/// A network interface, along with the IPv4 address it is given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Iface {
+    pub name: String,
+    pub addr: Option<(Ipv4Addr, u8)>,
+}
+
+impl Iface {
+    /// Parses an interface of the `<name>[:<address>/<prefix_len>]` format.
+    pub fn parse(arg: &str, require_addr: bool) -> Result<Self> {
+        let format_err = || Error::NetIfaceFormat(arg.to_string());
+        let mut parts = arg.splitn(2, ':');
+        // splitn() always yields at least one element.
+        let name = parts.next().unwrap();
+        if name.is_empty()
+            || name.len() >= IFACE_NAME_MAX_LEN
+            || name.contains(|c: char| c == '/' || c.is_whitespace())
+        {
+            return Err(format_err());
+        }
+
+        let addr = match parts.next() {
+            Some(cidr) => {
+                let mut cidr = cidr.splitn(2, '/');
+                let addr = cidr
+                    .next()
+                    .unwrap()
+                    .parse::<Ipv4Addr>()
+                    .map_err(|_| format_err())?;
+                let prefix_len = cidr
+                    .next()
+                    .and_then(|len| len.parse::<u8>().ok())
+                    .filter(|len| *len <= 32)
+                    .ok_or_else(format_err)?;
+                Some((addr, prefix_len))
+            }
+            None if require_addr => return Err(format_err()),
+            None => None,
+        };
+
+        Ok(Iface {
+            name: name.to_string(),
+            addr,
+        })
+    }
+}
+
+/// The plumbing of the network namespace the jailer creates.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NetNsConfig {
+    /// The host and jail ends of a veth pair.
+    pub veth: Option<(Iface, Iface)>,
+    /// A tap device for the microVM.
+    pub tap: Option<Iface>,
+}
+
+impl NetNsConfig {
+    /// Parses the `<host_if>:<ip>/<len>,<jail_if>:<ip>/<len>` veth pair and the
+    /// `<tap_if>[:<ip>/<len>]` tap device.
+    pub fn new(veth: Option<&str>, tap: Option<&str>) -> Result<Self> {
+        let veth = match veth {
+            Some(veth) => {
+                let ends = veth.split(',').collect::<Vec<&str>>();
+                if ends.len() != 2 {
+                    return Err(Error::NetIfaceFormat(veth.to_string()));
+                }
+                Some((Iface::parse(ends[0], true)?, Iface::parse(ends[1], true)?))
+            }
+            None => None,
+        };
+        let tap = match tap {
+            Some(tap) => Some(Iface::parse(tap, false)?),
+            None => None,
+        };
+        Ok(NetNsConfig { veth, tap })
+    }
+}
+
+// Returns the network address of `addr`.
+fn network(addr: Ipv4Addr, prefix_len: u8) -> Ipv4Addr {
+    let mask = u32::max_value()
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0);
+    Ipv4Addr::from(u32::from(addr) & mask)
+}
+
+fn if_index(name: &str) -> Result<u32> {
+    let c_name = CString::new(name).map_err(Error::CStringParsing)?;
+    // Safe because we pass a valid C string.
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(Error::NetIfIndex(
+            name.to_string(),
+            io::Error::last_os_error(),
+        )),
+        index => Ok(index),
+    }
+}
+
+// A netlink request, holding the header of the message family and its attributes.
+struct Request(Vec<u8>);
+
+impl Request {
+    fn new(msg_type: u16, flags: u16, family_header: &[u8]) -> Self {
+        let flags = flags | libc::NLM_F_REQUEST as u16 | libc::NLM_F_ACK as u16;
+        let mut request = Request(Vec::with_capacity(NLMSG_BUF_SIZE));
+        // The length is filled in once the request is complete. The sequence number and the
+        // port id are left to 0, as only one request is in flight at a time.
+        request.push(&0u32.to_ne_bytes());
+        request.push(&[msg_type.to_ne_bytes(), flags.to_ne_bytes()].concat());
+        request.push(&[0u8; 8]);
+        request.push(family_header);
+        request
+    }
+
+    // Appends `data`, padded to the netlink alignment.
+    fn push(&mut self, data: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(data);
+        let padding = (NLMSG_ALIGNTO - self.0.len() % NLMSG_ALIGNTO) % NLMSG_ALIGNTO;
+        self.0.extend_from_slice(&[0u8; NLMSG_ALIGNTO][..padding]);
+        self
+    }
+
+    fn attr(&mut self, attr_type: u16, data: &[u8]) -> &mut Self {
+        let len = (4 + data.len()) as u16;
+        self.push(&[len.to_ne_bytes(), attr_type.to_ne_bytes()].concat())
+            .push(data)
+    }
+
+    // Appends an attribute nesting the ones `build` appends.
+    fn nested<F: FnOnce(&mut Request)>(&mut self, attr_type: u16, build: F) -> &mut Self {
+        // The length is filled in once the nested attributes are appended.
+        let start = self.0.len();
+        self.push(&[0u16.to_ne_bytes(), attr_type.to_ne_bytes()].concat());
+        build(self);
+        let len = (self.0.len() - start) as u16;
+        self.0[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        self
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        let len = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&len.to_ne_bytes());
+        self.0
+    }
+}
+
+// The header of the link messages (struct ifinfomsg).
+fn ifinfomsg(index: u32, flags: u32, change: u32) -> Vec<u8> {
+    [
+        &[libc::AF_UNSPEC as u8, 0, 0, 0][..],
+        &index.to_ne_bytes(),
+        &flags.to_ne_bytes(),
+        &change.to_ne_bytes(),
+    ]
+    .concat()
+}
+
+// The header of the address messages (struct ifaddrmsg).
+fn ifaddrmsg(prefix_len: u8, index: u32) -> Vec<u8> {
+    [
+        &[libc::AF_INET as u8, prefix_len, 0, RT_SCOPE_UNIVERSE][..],
+        &index.to_ne_bytes(),
+    ]
+    .concat()
+}
+
+// The header of the route messages (struct rtmsg).
+fn rtmsg(dst_len: u8) -> Vec<u8> {
+    vec![
+        libc::AF_INET as u8,
+        dst_len,
+        0,
+        0,
+        RT_TABLE_MAIN,
+        RTPROT_BOOT,
+        RT_SCOPE_UNIVERSE,
+        RTN_UNICAST,
+        0,
+        0,
+        0,
+        0,
+    ]
+}
+
+// A rtnetlink socket, operating on the network namespace it was created in.
+struct NetlinkSocket(File);
+
+impl NetlinkSocket {
+    fn new() -> Result<Self> {
+        // Safe because we pass valid parameters, and check the result.
+        let fd = SyscallReturnCode(unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        })
+        .into_result()
+        .map_err(Error::NetlinkSocket)?;
+        // Safe because we own the fd we just checked.
+        Ok(NetlinkSocket(unsafe { File::from_raw_fd(fd) }))
+    }
+
+    // Sends `request` to the kernel, and waits for its acknowledgement.
+    fn send(&self, request: Request, what: String) -> Result<()> {
+        let request = request.into_bytes();
+        let fd = self.0.as_raw_fd();
+        // Safe because we pass a valid buffer, along with its length.
+        let sent = unsafe {
+            libc::send(
+                fd,
+                request.as_ptr() as *const libc::c_void,
+                request.len(),
+                0,
+            )
+        };
+        if sent != request.len() as isize {
+            return Err(Error::Netlink(what, io::Error::last_os_error()));
+        }
+
+        let mut reply = [0u8; NLMSG_BUF_SIZE];
+        // Safe because we pass a valid buffer, along with its length.
+        let received =
+            unsafe { libc::recv(fd, reply.as_mut_ptr() as *mut libc::c_void, reply.len(), 0) };
+        if received < 0 {
+            return Err(Error::Netlink(what, io::Error::last_os_error()));
+        }
+        let invalid_reply = || io::Error::from(io::ErrorKind::InvalidData);
+        if (received as usize) < NLMSG_ERROR_LEN
+            || u16::from_ne_bytes(reply[4..6].try_into().unwrap()) != libc::NLMSG_ERROR as u16
+        {
+            return Err(Error::Netlink(what, invalid_reply()));
+        }
+        match i32::from_ne_bytes(reply[NLMSG_HDR_LEN..NLMSG_ERROR_LEN].try_into().unwrap()) {
+            0 => Ok(()),
+            errno => Err(Error::Netlink(what, io::Error::from_raw_os_error(-errno))),
+        }
+    }
+
+    fn create_veth(&self, name: &str, peer_name: &str) -> Result<()> {
+        let mut request = Request::new(
+            RTM_NEWLINK,
+            (libc::NLM_F_CREATE | libc::NLM_F_EXCL) as u16,
+            &ifinfomsg(0, 0, 0),
+        );
+        request
+            .attr(IFLA_IFNAME, CString::new(name).unwrap().as_bytes_with_nul())
+            .nested(IFLA_LINKINFO, |request| {
+                request
+                    .attr(IFLA_INFO_KIND, b"veth")
+                    .nested(IFLA_INFO_DATA, |request| {
+                        request.nested(VETH_INFO_PEER, |request| {
+                            request.push(&ifinfomsg(0, 0, 0)).attr(
+                                IFLA_IFNAME,
+                                CString::new(peer_name).unwrap().as_bytes_with_nul(),
+                            );
+                        });
+                    });
+            });
+        self.send(request, format!("create the veth pair {}", name))
+    }
+
+    fn move_to_netns(&self, index: u32, netns: &File) -> Result<()> {
+        let mut request = Request::new(RTM_NEWLINK, 0, &ifinfomsg(index, 0, 0));
+        request.attr(IFLA_NET_NS_FD, &(netns.as_raw_fd() as u32).to_ne_bytes());
+        self.send(request, format!("move interface {} to the jail", index))
+    }
+
+    fn set_up(&self, index: u32) -> Result<()> {
+        let request = Request::new(RTM_NEWLINK, 0, &ifinfomsg(index, IFF_UP, IFF_UP));
+        self.send(request, format!("bring up interface {}", index))
+    }
+
+    fn add_address(&self, index: u32, addr: Ipv4Addr, prefix_len: u8) -> Result<()> {
+        let mut request = Request::new(
+            RTM_NEWADDR,
+            (libc::NLM_F_CREATE | libc::NLM_F_EXCL) as u16,
+            &ifaddrmsg(prefix_len, index),
+        );
+        request
+            .attr(IFA_LOCAL, &addr.octets())
+            .attr(IFA_ADDRESS, &addr.octets());
+        self.send(
+            request,
+            format!("add address {}/{} to interface {}", addr, prefix_len, index),
+        )
+    }
+
+    fn add_route(&self, dst: Ipv4Addr, dst_len: u8, gateway: Ipv4Addr) -> Result<()> {
+        let mut request = Request::new(
+            RTM_NEWROUTE,
+            (libc::NLM_F_CREATE | libc::NLM_F_EXCL) as u16,
+            &rtmsg(dst_len),
+        );
+        if dst_len > 0 {
+            request.attr(RTA_DST, &dst.octets());
+        }
+        request.attr(RTA_GATEWAY, &gateway.octets());
+        self.send(
+            request,
+            format!("add route to {}/{} via {}", dst, dst_len, gateway),
+        )
+    }
+}
+
+// Brings up the interface called `name`, after giving it its address, if any.
+fn configure(socket: &NetlinkSocket, name: &str, addr: Option<(Ipv4Addr, u8)>) -> Result<()> {
+    let index = if_index(name)?;
+    if let Some((addr, prefix_len)) = addr {
+        socket.add_address(index, addr, prefix_len)?;
+    }
+    socket.set_up(index)
+}
+
+// Creates a persistent tap device, which the jailed process can open as `uid` and `gid`.
+fn create_tap(name: &str, uid: u32, gid: u32) -> Result<()> {
+    let tap_err = |e| Error::TapCreate(name.to_string(), e);
+    let tun = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_CLOEXEC)
+        .open("/dev/net/tun")
+        .map_err(tap_err)?;
+
+    let mut ifreq = ifreq::default();
+    // Safe because we only access the union fields we set, and the name fits in ifrn_name
+    // along with its NUL terminator.
+    unsafe {
+        ifreq.ifr_ifrn.ifrn_name.as_mut()[..name.len()].copy_from_slice(name.as_bytes());
+        *ifreq.ifr_ifru.ifru_flags.as_mut() =
+            (net_gen::IFF_TAP | net_gen::IFF_NO_PI | net_gen::IFF_VNET_HDR) as i16;
+    }
+
+    // The ioctls are safe because we pass a valid tun fd and valid parameters, and check the
+    // results. The device has to persist once the fd is closed, before exec.
+    let ret = unsafe { ioctl_with_mut_ref(&tun, TUNSETIFF(), &mut ifreq) };
+    if ret < 0 {
+        return Err(tap_err(io::Error::last_os_error()));
+    }
+    for (ioctl, val) in [
+        (TUNSETOWNER(), c_ulong::from(uid)),
+        (TUNSETGROUP(), c_ulong::from(gid)),
+        (TUNSETPERSIST(), 1),
+    ]
+    .iter()
+    {
+        if unsafe { ioctl_with_val(&tun, *ioctl, *val) } < 0 {
+            return Err(tap_err(io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// Moves the jailer into a new network namespace, plumbed as `config` describes. The tap
+/// device is owned by `uid` and `gid`.
+pub fn setup_netns(config: &NetNsConfig, uid: u32, gid: u32) -> Result<()> {
+    // This socket keeps operating on the host namespace once we move to the new one.
+    let host_socket = NetlinkSocket::new()?;
+
+    // Create both ends of the veth pair on the host, so we can still find the jail end by
+    // name when moving it to the jail.
+    let jail_end_index = match config.veth {
+        Some((ref host_end, ref jail_end)) => {
+            host_socket.create_veth(&host_end.name, &jail_end.name)?;
+            configure(&host_socket, &host_end.name, host_end.addr)?;
+            Some(if_index(&jail_end.name)?)
+        }
+        None => None,
+    };
+
+    // Safe because we pass valid parameters, and check the result.
+    SyscallReturnCode(unsafe { libc::unshare(libc::CLONE_NEWNET) })
+        .into_empty_result()
+        .map_err(Error::UnshareNewNetNs)?;
+    let jail_socket = NetlinkSocket::new()?;
+    configure(&jail_socket, LOOPBACK_NAME, None)?;
+
+    if let (Some((host_end, jail_end)), Some(index)) = (&config.veth, jail_end_index) {
+        let netns =
+            File::open(NETNS_PATH).map_err(|e| Error::FileOpen(PathBuf::from(NETNS_PATH), e))?;
+        host_socket.move_to_netns(index, &netns)?;
+        configure(&jail_socket, &jail_end.name, jail_end.addr)?;
+        // Veth addresses are always given, as checked when parsing.
+        if let Some((host_addr, _)) = host_end.addr {
+            jail_socket.add_route(Ipv4Addr::UNSPECIFIED, 0, host_addr)?;
+        }
+    }
+
+    if let Some(ref tap) = config.tap {
+        create_tap(&tap.name, uid, gid)?;
+        configure(&jail_socket, &tap.name, tap.addr)?;
+    }
+
+    // Route the traffic of the microVM between the tap device and the host.
+    if let (Some((_, jail_end)), Some(tap)) = (&config.veth, &config.tap) {
+        if let (Some((jail_addr, _)), Some((tap_addr, tap_prefix_len))) = (jail_end.addr, tap.addr)
+        {
+            fs::write(IP_FORWARD_PATH, "1")
+                .map_err(|e| Error::Write(PathBuf::from(IP_FORWARD_PATH), e))?;
+            host_socket.add_route(network(tap_addr, tap_prefix_len), tap_prefix_len, jail_addr)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iface_parse() {
+        assert_eq!(
+            Iface::parse("veth0:10.0.0.1/30", true).unwrap(),
+            Iface {
+                name: "veth0".to_string(),
+                addr: Some((Ipv4Addr::new(10, 0, 0, 1), 30)),
+            }
+        );
+        assert_eq!(
+            Iface::parse("tap0", false).unwrap(),
+            Iface {
+                name: "tap0".to_string(),
+                addr: None,
+            }
+        );
+
+        for (invalid, require_addr) in [
+            ("veth0", true),
+            ("", false),
+            (":10.0.0.1/30", false),
+            ("a_very_long_name", false),
+            ("tap/0", false),
+            ("tap 0", false),
+            ("tap0:", false),
+            ("tap0:10.0.0.1", false),
+            ("tap0:10.0.0/30", false),
+            ("tap0:10.0.0.1/33", false),
+            ("tap0:10.0.0.1/", false),
+        ]
+        .iter()
+        {
+            match Iface::parse(invalid, *require_addr) {
+                Err(Error::NetIfaceFormat(arg)) => assert_eq!(arg, *invalid),
+                _ => panic!("{} should not parse", invalid),
+            }
+        }
+    }
+
+    #[test]
+    fn test_netns_config() {
+        assert_eq!(
+            NetNsConfig::new(None, None).unwrap(),
+            NetNsConfig::default()
+        );
+
+        let config = NetNsConfig::new(
+            Some("veth-fc0:10.0.0.1/30,eth0:10.0.0.2/30"),
+            Some("tap0:172.16.0.1/24"),
+        )
+        .unwrap();
+        let (host_end, jail_end) = config.veth.unwrap();
+        assert_eq!(host_end.name, "veth-fc0");
+        assert_eq!(jail_end.addr, Some((Ipv4Addr::new(10, 0, 0, 2), 30)));
+        assert_eq!(config.tap.unwrap().name, "tap0");
+
+        assert!(NetNsConfig::new(Some("veth-fc0:10.0.0.1/30"), None).is_err());
+        assert!(NetNsConfig::new(Some("a:10.0.0.1/30,b:10.0.0.2/30,c:10.0.0.3/30"), None).is_err());
+        assert!(NetNsConfig::new(Some("veth-fc0:10.0.0.1/30,eth0"), None).is_err());
+        assert!(NetNsConfig::new(None, Some("tap0:172.16.0.1")).is_err());
+    }
+
+    #[test]
+    fn test_network() {
+        assert_eq!(
+            network(Ipv4Addr::new(172, 16, 3, 1), 24),
+            Ipv4Addr::new(172, 16, 3, 0)
+        );
+        assert_eq!(
+            network(Ipv4Addr::new(10, 0, 0, 6), 30),
+            Ipv4Addr::new(10, 0, 0, 4)
+        );
+        assert_eq!(
+            network(Ipv4Addr::new(10, 0, 0, 6), 0),
+            Ipv4Addr::UNSPECIFIED
+        );
+        assert_eq!(
+            network(Ipv4Addr::new(10, 0, 0, 6), 32),
+            Ipv4Addr::new(10, 0, 0, 6)
+        );
+    }
+
+    #[test]
+    fn test_request() {
+        let mut request = Request::new(RTM_NEWLINK, 0, &ifinfomsg(3, IFF_UP, IFF_UP));
+        request
+            .attr(IFLA_IFNAME, b"eth0\0")
+            .nested(IFLA_LINKINFO, |request| {
+                request.attr(IFLA_INFO_KIND, b"veth");
+            });
+        let bytes = request.into_bytes();
+
+        // The header, the ifinfomsg, the 9 bytes long name padded to 12, and the nested kind.
+        assert_eq!(bytes.len(), 16 + 16 + 12 + 4 + 8);
+        assert_eq!(u32::from_ne_bytes(bytes[0..4].try_into().unwrap()), 56);
+        assert_eq!(
+            u16::from_ne_bytes(bytes[4..6].try_into().unwrap()),
+            RTM_NEWLINK
+        );
+        assert_eq!(
+            u16::from_ne_bytes(bytes[6..8].try_into().unwrap()),
+            (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16
+        );
+        assert_eq!(u32::from_ne_bytes(bytes[20..24].try_into().unwrap()), 3);
+        assert_eq!(u16::from_ne_bytes(bytes[32..34].try_into().unwrap()), 9);
+        assert_eq!(&bytes[36..41], b"eth0\0");
+        assert_eq!(u16::from_ne_bytes(bytes[44..46].try_into().unwrap()), 12);
+        assert_eq!(
+            u16::from_ne_bytes(bytes[46..48].try_into().unwrap()),
+            IFLA_LINKINFO
+        );
+        assert_eq!(u16::from_ne_bytes(bytes[48..50].try_into().unwrap()), 8);
+        assert_eq!(&bytes[52..56], b"veth");
+    }
+}
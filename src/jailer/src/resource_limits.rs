@@ -0,0 +1,163 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use super::{Error, Result};
+use utils::syscall::SyscallReturnCode;
+
+const UNLIMITED: &str = "unlimited";
+
+/// The resources which can be limited for the jailed process.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+    /// The maximum size of a core dump file, in bytes (RLIMIT_CORE).
+    Core,
+    /// The maximum size of a file the process can create, in bytes (RLIMIT_FSIZE).
+    Fsize,
+    /// The maximum amount of memory the process can lock, in bytes (RLIMIT_MEMLOCK).
+    Memlock,
+    /// The maximum number of file descriptors the process can open, plus one (RLIMIT_NOFILE).
+    Nofile,
+}
+
+impl Resource {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "core" => Some(Resource::Core),
+            "fsize" => Some(Resource::Fsize),
+            "memlock" => Some(Resource::Memlock),
+            "nofile" => Some(Resource::Nofile),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Resource::Core => "core",
+            Resource::Fsize => "fsize",
+            Resource::Memlock => "memlock",
+            Resource::Nofile => "nofile",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A limit on a resource, which the jailed process can neither exceed nor raise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResourceLimit {
+    pub resource: Resource,
+    pub value: libc::rlim_t,
+}
+
+impl ResourceLimit {
+    /// Parses a limit of the `<resource>=<value>` format, where the value may be `unlimited`.
+    pub fn parse(arg: &str) -> Result<Self> {
+        let parts = arg.splitn(2, '=').collect::<Vec<&str>>();
+        if parts.len() != 2 {
+            return Err(Error::ResLimitFormat(arg.to_string()));
+        }
+        let resource = Resource::from_name(parts[0])
+            .ok_or_else(|| Error::ResLimitArgument(parts[0].to_string()))?;
+        let value = match parts[1] {
+            UNLIMITED => libc::RLIM_INFINITY,
+            value => value
+                .parse::<libc::rlim_t>()
+                .map_err(|_| Error::ResLimitValue(value.to_string()))?,
+        };
+        Ok(ResourceLimit { resource, value })
+    }
+
+    /// Sets both the soft and the hard limits of the current process.
+    pub fn install(&self) -> Result<()> {
+        let limit = libc::rlimit {
+            rlim_cur: self.value,
+            rlim_max: self.value,
+        };
+        // Safe because we pass a valid rlimit structure, and check the result.
+        SyscallReturnCode(unsafe {
+            match self.resource {
+                Resource::Core => libc::setrlimit(libc::RLIMIT_CORE, &limit),
+                Resource::Fsize => libc::setrlimit(libc::RLIMIT_FSIZE, &limit),
+                Resource::Memlock => libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit),
+                Resource::Nofile => libc::setrlimit(libc::RLIMIT_NOFILE, &limit),
+            }
+        })
+        .into_empty_result()
+        .map_err(|e| Error::Setrlimit(self.resource.to_string(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            ResourceLimit::parse("nofile=2048").unwrap(),
+            ResourceLimit {
+                resource: Resource::Nofile,
+                value: 2048,
+            }
+        );
+        assert_eq!(
+            ResourceLimit::parse("core=0").unwrap().resource,
+            Resource::Core
+        );
+        assert_eq!(
+            ResourceLimit::parse("memlock=unlimited").unwrap().value,
+            libc::RLIM_INFINITY
+        );
+        assert_eq!(
+            ResourceLimit::parse("fsize=1048576").unwrap().resource,
+            Resource::Fsize
+        );
+
+        match ResourceLimit::parse("nofile") {
+            Err(Error::ResLimitFormat(arg)) => assert_eq!(arg, "nofile"),
+            _ => panic!("The limit should not parse"),
+        }
+        match ResourceLimit::parse("stack=8192") {
+            Err(Error::ResLimitArgument(arg)) => assert_eq!(arg, "stack"),
+            _ => panic!("The resource should not be known"),
+        }
+        for value in ["", "-1", "1k", "infinity"].iter() {
+            match ResourceLimit::parse(&format!("nofile={}", value)) {
+                Err(Error::ResLimitValue(arg)) => assert_eq!(arg, *value),
+                _ => panic!("{} should not be a valid value", value),
+            }
+        }
+    }
+
+    #[test]
+    fn test_install() {
+        // Lowering a limit is always allowed.
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(
+            unsafe { libc::getrlimit(libc::RLIMIT_FSIZE, &mut limit) },
+            0
+        );
+        ResourceLimit {
+            resource: Resource::Fsize,
+            value: limit.rlim_max,
+        }
+        .install()
+        .unwrap();
+        let mut new_limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(
+            unsafe { libc::getrlimit(libc::RLIMIT_FSIZE, &mut new_limit) },
+            0
+        );
+        assert_eq!(new_limit.rlim_cur, limit.rlim_max);
+        assert_eq!(new_limit.rlim_max, limit.rlim_max);
+    }
+}
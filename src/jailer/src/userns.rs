@@ -0,0 +1,282 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::ptr::null;
+
+use super::{Error, Result};
+use utils::syscall::SyscallReturnCode;
+
+/// A range of ids mapped into the user namespace, as written to `/proc/<pid>/uid_map`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdMapRange {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+// Parses a mapping of the `<inside>:<outside>:<count>[,<inside>:<outside>:<count>...]` format.
+fn parse_id_map(map: &str) -> Result<Vec<IdMapRange>> {
+    map.split(',')
+        .map(|range| {
+            let fields = range
+                .split(':')
+                .map(|field| field.parse::<u32>())
+                .collect::<std::result::Result<Vec<u32>, _>>()
+                .map_err(|_| Error::UserNsMapFormat(map.to_string()))?;
+            match fields[..] {
+                [inside, outside, count]
+                    if count > 0
+                        && inside.checked_add(count).is_some()
+                        && outside.checked_add(count).is_some() =>
+                {
+                    Ok(IdMapRange {
+                        inside,
+                        outside,
+                        count,
+                    })
+                }
+                _ => Err(Error::UserNsMapFormat(map.to_string())),
+            }
+        })
+        .collect()
+}
+
+/// The id maps of the user namespace, along with the ids the jailed process runs as inside it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdMaps {
+    pub uid_map: Vec<IdMapRange>,
+    pub gid_map: Vec<IdMapRange>,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl IdMaps {
+    /// Creates the id maps, under which the jailed process runs as `uid` and `gid` on the host.
+    /// Missing maps default to mapping the namespace root to them.
+    pub fn new(
+        uid_map: Option<&str>,
+        gid_map: Option<&str>,
+        host_uid: u32,
+        host_gid: u32,
+    ) -> Result<Self> {
+        let parse = |map: Option<&str>, host_id| match map {
+            Some(map) => parse_id_map(map),
+            None => Ok(vec![IdMapRange {
+                inside: 0,
+                outside: host_id,
+                count: 1,
+            }]),
+        };
+        let uid_map = parse(uid_map, host_uid)?;
+        let gid_map = parse(gid_map, host_gid)?;
+        let uid = inside_id(&uid_map, host_uid).ok_or(Error::UserNsUnmappedId("uid", host_uid))?;
+        let gid = inside_id(&gid_map, host_gid).ok_or(Error::UserNsUnmappedId("gid", host_gid))?;
+        Ok(IdMaps {
+            uid_map,
+            gid_map,
+            uid,
+            gid,
+        })
+    }
+}
+
+// Returns the id inside the user namespace which `outside` is mapped to, if any.
+fn inside_id(map: &[IdMapRange], outside: u32) -> Option<u32> {
+    map.iter()
+        .find(|range| outside >= range.outside && outside - range.outside < range.count)
+        .map(|range| range.inside + (outside - range.outside))
+}
+
+// Formats a mapping the way the kernel expects it in `/proc/<pid>/uid_map`.
+fn format_id_map(map: &[IdMapRange]) -> String {
+    map.iter()
+        .map(|range| format!("{} {} {}\n", range.inside, range.outside, range.count))
+        .collect()
+}
+
+// Writes the id maps of the user namespace of `pid`.
+fn write_id_maps(pid: libc::pid_t, uid_map: &str, gid_map: &str) -> Result<()> {
+    let uid_map_path = PathBuf::from(format!("/proc/{}/uid_map", pid));
+    fs::write(&uid_map_path, uid_map).map_err(|e| Error::Write(uid_map_path, e))?;
+    let gid_map_path = PathBuf::from(format!("/proc/{}/gid_map", pid));
+    fs::write(&gid_map_path, gid_map).map_err(|e| Error::Write(gid_map_path, e))
+}
+
+/// A helper process which writes the id maps of the user namespace the jailer unshares into.
+///
+/// Only a process holding `CAP_SETUID` and `CAP_SETGID` in the parent user namespace can map
+/// arbitrary ids, and the jailer loses them in the parent namespace once it unshares. So the
+/// helper is forked beforehand, while the jailer is still root on the host and not yet jailed,
+/// since it needs to reach `/proc`.
+pub struct UserNsMapper {
+    pid: libc::pid_t,
+    pipe_fd: RawFd,
+}
+
+impl UserNsMapper {
+    /// Forks the helper, which waits for the jailer to unshare before writing the maps.
+    pub fn spawn(id_maps: &IdMaps) -> Result<Self> {
+        let uid_map = format_id_map(&id_maps.uid_map);
+        let gid_map = format_id_map(&id_maps.gid_map);
+        // Safe because getpid() cannot fail.
+        let jailer_pid = unsafe { libc::getpid() };
+
+        let mut fds: [RawFd; 2] = [-1; 2];
+        // Safe because we pass a valid array of two fds, and check the result.
+        SyscallReturnCode(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })
+            .into_empty_result()
+            .map_err(Error::UserNsPipe)?;
+
+        // Safe because the jailer is single threaded.
+        let pid = SyscallReturnCode(unsafe { libc::fork() })
+            .into_result()
+            .map_err(Error::UserNsFork)?;
+        if pid == 0 {
+            // Safe because we close our copy of the write end, and read into a valid buffer.
+            let mut byte = 0u8;
+            let read = unsafe {
+                libc::close(fds[1]);
+                libc::read(fds[0], &mut byte as *mut u8 as *mut libc::c_void, 1)
+            };
+            // Nothing is read when the jailer failed before unsharing.
+            let status = if read == 1 && write_id_maps(jailer_pid, &uid_map, &gid_map).is_ok() {
+                0
+            } else {
+                1
+            };
+            // Safe because we exit without running the destructors of the jailer's state.
+            unsafe { libc::_exit(status) };
+        }
+
+        // Safe because the fd is valid, and the helper holds its own copy.
+        unsafe { libc::close(fds[0]) };
+        Ok(UserNsMapper {
+            pid,
+            pipe_fd: fds[1],
+        })
+    }
+
+    /// Moves the jailer into a new user namespace, and waits for the helper to map its ids.
+    pub fn unshare(self) -> Result<()> {
+        // Drop the supplementary groups of the jailer, since they would otherwise be kept on the
+        // host. Safe because we pass an empty list, and check the result.
+        SyscallReturnCode(unsafe { libc::setgroups(0, null()) })
+            .into_empty_result()
+            .map_err(Error::SetGroups)?;
+
+        // Safe because the jailer is single threaded, and we check the result.
+        SyscallReturnCode(unsafe { libc::unshare(libc::CLONE_NEWUSER) })
+            .into_empty_result()
+            .map_err(Error::UnshareNewUserNs)?;
+
+        // Wake up the helper. Safe because we write a valid byte to our end of the pipe.
+        let byte = 1u8;
+        let written = unsafe {
+            let written = libc::write(self.pipe_fd, &byte as *const u8 as *const libc::c_void, 1);
+            libc::close(self.pipe_fd);
+            written
+        };
+        if written != 1 {
+            return Err(Error::UserNsPipe(std::io::Error::last_os_error()));
+        }
+
+        let mut status = 0;
+        // Safe because we wait for our own child, and check the result.
+        SyscallReturnCode(unsafe { libc::waitpid(self.pid, &mut status, 0) })
+            .into_empty_result()
+            .map_err(Error::UserNsWait)?;
+        if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+            return Err(Error::UserNsMapWrite);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_id_map() {
+        assert_eq!(
+            parse_id_map("0:1001:1").unwrap(),
+            vec![IdMapRange {
+                inside: 0,
+                outside: 1001,
+                count: 1
+            }]
+        );
+        let map = parse_id_map("0:1001:1,1:100000:65536").unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[1].outside, 100_000);
+        assert_eq!(map[1].count, 65536);
+
+        for invalid in [
+            "",
+            "0:1001",
+            "0:1001:1:1",
+            "0:1001:0",
+            "0:-1:1",
+            "a:1001:1",
+            "0:1001:1,",
+            "0:4294967295:2",
+        ]
+        .iter()
+        {
+            match parse_id_map(invalid) {
+                Err(Error::UserNsMapFormat(map)) => assert_eq!(map, *invalid),
+                _ => panic!("{} should not parse", invalid),
+            }
+        }
+    }
+
+    #[test]
+    fn test_inside_id() {
+        let map = parse_id_map("0:1001:1,1:100000:65536").unwrap();
+        assert_eq!(inside_id(&map, 1001), Some(0));
+        assert_eq!(inside_id(&map, 100_000), Some(1));
+        assert_eq!(inside_id(&map, 165_535), Some(65536));
+        assert_eq!(inside_id(&map, 165_536), None);
+        assert_eq!(inside_id(&map, 0), None);
+    }
+
+    #[test]
+    fn test_id_maps() {
+        let id_maps = IdMaps::new(None, None, 1001, 1002).unwrap();
+        assert_eq!(format_id_map(&id_maps.uid_map), "0 1001 1\n");
+        assert_eq!(format_id_map(&id_maps.gid_map), "0 1002 1\n");
+        assert_eq!((id_maps.uid, id_maps.gid), (0, 0));
+
+        let id_maps = IdMaps::new(
+            Some("0:1001:1,1:100000:65536"),
+            Some("0:0:1,5:1002:1"),
+            1001,
+            1002,
+        )
+        .unwrap();
+        assert_eq!(id_maps.uid_map.len(), 2);
+        assert_eq!((id_maps.uid, id_maps.gid), (0, 5));
+
+        match IdMaps::new(Some("1:100000:65536"), None, 1001, 1002) {
+            Err(Error::UserNsUnmappedId("uid", 1001)) => (),
+            _ => panic!("The uid should not be mapped"),
+        }
+        match IdMaps::new(None, Some("0:0:1000"), 1001, 1002) {
+            Err(Error::UserNsUnmappedId("gid", 1002)) => (),
+            _ => panic!("The gid should not be mapped"),
+        }
+        match IdMaps::new(Some("0:1001"), None, 1001, 1002) {
+            Err(Error::UserNsMapFormat(_)) => (),
+            _ => panic!("The uid map should not parse"),
+        }
+    }
+
+    #[test]
+    fn test_format_id_map() {
+        let map = parse_id_map("0:1001:1,1:100000:65536").unwrap();
+        assert_eq!(format_id_map(&map), "0 1001 1\n1 100000 65536\n");
+    }
+}
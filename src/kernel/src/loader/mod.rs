@@ -13,6 +13,8 @@ use std::io::{Read, Seek, SeekFrom};
 use std::mem;
 
 use super::cmdline::Error as CmdlineError;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use arch_gen::x86::bootparam::setup_header;
 use utils::structs::read_struct;
 use vm_memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
 
@@ -24,6 +26,9 @@ mod elf;
 #[derive(Debug, PartialEq)]
 pub enum Error {
     BigEndianElfOnLittle,
+    BzImageNot64Bit,
+    BzImageTooLarge,
+    InvalidBzImageLoadAddress,
     InvalidElfMagicNumber,
     InvalidEntryAddress,
     InvalidProgramHeaderSize,
@@ -33,7 +38,9 @@ pub enum Error {
     ReadKernelImage,
     SeekKernelStart,
     SeekKernelImage,
+    SeekNoteHeader,
     SeekProgramHeader,
+    UnsupportedBzImageVersion,
 }
 
 impl fmt::Display for Error {
@@ -43,6 +50,11 @@ impl fmt::Display for Error {
             "{}",
             match *self {
                 Error::BigEndianElfOnLittle => "Unsupported ELF File byte order",
+                Error::BzImageNot64Bit => "The bzImage has no 64-bit entry point",
+                Error::BzImageTooLarge => {
+                    "The guest memory is too small for the decompressed bzImage"
+                }
+                Error::InvalidBzImageLoadAddress => "Invalid bzImage load address",
                 Error::InvalidElfMagicNumber => "Invalid ELF magic number",
                 Error::InvalidEntryAddress => "Invalid entry address found in ELF header",
                 Error::InvalidProgramHeaderSize => "Invalid ELF program header size",
@@ -54,7 +66,11 @@ impl fmt::Display for Error {
                     "Failed to seek to file offset as pointed by the ELF program header"
                 }
                 Error::SeekKernelImage => "Failed to seek to offset of kernel image",
+                Error::SeekNoteHeader => "Failed to seek to ELF note header",
                 Error::SeekProgramHeader => "Failed to seek to ELF program header",
+                Error::UnsupportedBzImageVersion => {
+                    "Unsupported bzImage boot protocol version, 2.12 or later is required"
+                }
             }
         )
     }
@@ -62,21 +78,286 @@ impl fmt::Display for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Loads a kernel from a vmlinux elf image to a slice
+/// A kernel image loaded in the guest memory.
+#[derive(Debug)]
+pub struct KernelLoaderResult {
+    /// The address at which the vCPUs start running the kernel.
+    pub entry_addr: GuestAddress,
+    /// The setup header of a bzImage, to be passed to the kernel in its boot parameters.
+    /// None for a vmlinux elf image.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub setup_header: Option<setup_header>,
+    /// The 32-bit entry address of a vmlinux elf image supporting the PVH boot protocol.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub pvh_entry_addr: Option<GuestAddress>,
+}
+
+// The type of the Xen ELF note giving the physical address of the 32-bit entry point used by
+// the PVH boot protocol.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const XEN_ELFNOTE_PHYS32_ENTRY: u32 = 18;
+// The name of the Xen ELF notes, with its terminating null byte.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+const XEN_ELFNOTE_NAME: &[u8] = b"Xen\0";
+
+// The constants of the x86 boot protocol used for loading a bzImage, as described in the
+// kernel docs Documentation/x86/boot.rst.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod bzimage {
+    /// The offset of the setup header in the image.
+    pub const SETUP_HEADER_OFFSET: u64 = 0x1f1;
+    /// The value of the `boot_flag` field.
+    pub const BOOT_FLAG_MAGIC: u16 = 0xaa55;
+    /// The value of the `header` field, "HdrS".
+    pub const HDR_MAGIC: u32 = 0x5372_6448;
+    /// The first boot protocol version having the `xloadflags` field.
+    pub const MIN_VERSION: u16 = 0x020c;
+    /// The `xloadflags` bit set when the kernel has a 64-bit entry point.
+    pub const XLF_KERNEL_64: u16 = 1 << 0;
+    /// The offset of the 64-bit entry point from the start of the protected-mode kernel.
+    pub const ENTRY_64_OFFSET: u64 = 0x200;
+    /// The size of a sector, counting the real-mode setup code.
+    pub const SECTOR_SIZE: u64 = 512;
+    /// The number of setup sectors of the images giving 0 in their header.
+    pub const DEFAULT_SETUP_SECTS: u8 = 4;
+}
+
+/// Loads a kernel from a vmlinux elf image or a bzImage to a slice. The format is
+/// detected by the magic numbers of the image.
 ///
 /// # Arguments
 ///
 /// * `guest_mem` - The guest memory region the kernel is written to.
-/// * `kernel_image` - Input vmlinux image.
+/// * `kernel_image` - Input vmlinux image or bzImage.
 /// * `start_address` - For x86_64, this is the start of the high memory. Kernel should reside above it.
 ///
-/// Returns the entry address of the kernel.
+/// Returns the entry address of the kernel, and the setup header of a bzImage.
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub fn load_kernel<F>(
     guest_mem: &GuestMemoryMmap,
     kernel_image: &mut F,
     start_address: u64,
-) -> Result<GuestAddress>
+) -> Result<KernelLoaderResult>
+where
+    F: Read + Seek,
+{
+    if let Some(hdr) = read_bzimage_header(kernel_image)? {
+        return load_bzimage(guest_mem, kernel_image, start_address, hdr);
+    }
+
+    let (ehdr, phdrs) = read_elf_headers(kernel_image, start_address)?;
+
+    // Read in each section pointed to by the program headers.
+    for phdr in &phdrs {
+        if (phdr.p_type & elf::PT_LOAD) == 0 || phdr.p_filesz == 0 {
+            continue;
+        }
+
+        kernel_image
+            .seek(SeekFrom::Start(phdr.p_offset))
+            .map_err(|_| Error::SeekKernelStart)?;
+
+        let mem_offset = GuestAddress(phdr.p_paddr);
+        if mem_offset.raw_value() < start_address {
+            return Err(Error::InvalidProgramHeaderAddress);
+        }
+
+        guest_mem
+            .read_from(mem_offset, kernel_image, phdr.p_filesz as usize)
+            .map_err(|_| Error::ReadKernelImage)?;
+    }
+
+    let pvh_entry_addr = read_pvh_entry(kernel_image, &phdrs)?;
+    if let Some(addr) = pvh_entry_addr {
+        if addr.raw_value() < start_address {
+            return Err(Error::InvalidEntryAddress);
+        }
+    }
+
+    Ok(KernelLoaderResult {
+        entry_addr: GuestAddress(ehdr.e_entry),
+        setup_header: None,
+        pvh_entry_addr,
+    })
+}
+
+/// Checks that a vmlinux elf image or a bzImage could be loaded above `start_address`,
+/// without loading it.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn check_kernel<F>(kernel_image: &mut F, start_address: u64) -> Result<()>
+where
+    F: Read + Seek,
+{
+    if let Some(hdr) = read_bzimage_header(kernel_image)? {
+        return bzimage_load_addr(&hdr, start_address).map(|_| ());
+    }
+
+    let (_, phdrs) = read_elf_headers(kernel_image, start_address)?;
+    if phdrs.iter().any(|phdr| {
+        (phdr.p_type & elf::PT_LOAD) != 0 && phdr.p_filesz != 0 && phdr.p_paddr < start_address
+    }) {
+        return Err(Error::InvalidProgramHeaderAddress);
+    }
+    Ok(())
+}
+
+// Reads the setup header of a bzImage. Returns None when the image is not a bzImage.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn read_bzimage_header<F>(kernel_image: &mut F) -> Result<Option<setup_header>>
+where
+    F: Read + Seek,
+{
+    let mut hdr: setup_header = Default::default();
+    kernel_image
+        .seek(SeekFrom::Start(bzimage::SETUP_HEADER_OFFSET))
+        .map_err(|_| Error::SeekKernelImage)?;
+    // read_struct is safe when reading a POD struct. An image too short to hold a setup
+    // header is not a bzImage.
+    if unsafe { read_struct(kernel_image, &mut hdr) }.is_err()
+        || hdr.boot_flag != bzimage::BOOT_FLAG_MAGIC
+        || hdr.header != bzimage::HDR_MAGIC
+    {
+        return Ok(None);
+    }
+
+    if hdr.version < bzimage::MIN_VERSION {
+        return Err(Error::UnsupportedBzImageVersion);
+    }
+    if hdr.xloadflags & bzimage::XLF_KERNEL_64 == 0 {
+        return Err(Error::BzImageNot64Bit);
+    }
+    Ok(Some(hdr))
+}
+
+// Returns the address at which the protected-mode kernel of a bzImage is loaded: its
+// preferred address, or the first suitably aligned one above `start_address` if it is
+// relocatable.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn bzimage_load_addr(hdr: &setup_header, start_address: u64) -> Result<u64> {
+    let pref_address = hdr.pref_address;
+    if pref_address >= start_address {
+        return Ok(pref_address);
+    }
+    let alignment = u64::from(hdr.kernel_alignment);
+    if hdr.relocatable_kernel == 0 || !alignment.is_power_of_two() {
+        return Err(Error::InvalidBzImageLoadAddress);
+    }
+    start_address
+        .checked_add(alignment - 1)
+        .map(|addr| addr & !(alignment - 1))
+        .ok_or(Error::InvalidBzImageLoadAddress)
+}
+
+// Loads the protected-mode kernel of a bzImage. The kernel decompresses itself in place
+// when started at its 64-bit entry point.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn load_bzimage<F>(
+    guest_mem: &GuestMemoryMmap,
+    kernel_image: &mut F,
+    start_address: u64,
+    mut hdr: setup_header,
+) -> Result<KernelLoaderResult>
+where
+    F: Read + Seek,
+{
+    let load_addr = bzimage_load_addr(&hdr, start_address)?;
+    // The decompressed kernel takes up to `init_size` bytes from the load address.
+    let init_end = load_addr
+        .checked_add(u64::from(hdr.init_size))
+        .ok_or(Error::BzImageTooLarge)?;
+    if init_end == 0 || GuestAddress(init_end - 1) > guest_mem.last_addr() {
+        return Err(Error::BzImageTooLarge);
+    }
+
+    let setup_sects = match hdr.setup_sects {
+        0 => bzimage::DEFAULT_SETUP_SECTS,
+        setup_sects => setup_sects,
+    };
+    // The protected-mode kernel follows the boot sector and the real-mode setup code.
+    let kernel_offset = (u64::from(setup_sects) + 1) * bzimage::SECTOR_SIZE;
+    let image_size = kernel_image
+        .seek(SeekFrom::End(0))
+        .map_err(|_| Error::SeekKernelImage)?;
+    if image_size <= kernel_offset {
+        return Err(Error::ReadKernelImage);
+    }
+    kernel_image
+        .seek(SeekFrom::Start(kernel_offset))
+        .map_err(|_| Error::SeekKernelStart)?;
+    guest_mem
+        .read_from(
+            GuestAddress(load_addr),
+            kernel_image,
+            (image_size - kernel_offset) as usize,
+        )
+        .map_err(|_| Error::ReadKernelImage)?;
+
+    hdr.code32_start = load_addr as u32;
+    Ok(KernelLoaderResult {
+        entry_addr: GuestAddress(load_addr + bzimage::ENTRY_64_OFFSET),
+        setup_header: Some(hdr),
+        pvh_entry_addr: None,
+    })
+}
+
+// Returns the PVH entry address of a vmlinux elf image, found in its Xen ELF notes, if any.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn read_pvh_entry<F>(
+    kernel_image: &mut F,
+    phdrs: &[elf::Elf64_Phdr],
+) -> Result<Option<GuestAddress>>
+where
+    F: Read + Seek,
+{
+    // The name and the descriptor of a note are each padded to 4 bytes.
+    let align = |size: u32| (u64::from(size) + 3) & !3;
+    let nhdr_size = mem::size_of::<elf::Elf64_Nhdr>() as u64;
+
+    for phdr in phdrs.iter().filter(|phdr| phdr.p_type == elf::PT_NOTE) {
+        let mut offset = 0;
+        while offset + nhdr_size <= phdr.p_filesz {
+            kernel_image
+                .seek(SeekFrom::Start(phdr.p_offset + offset))
+                .map_err(|_| Error::SeekNoteHeader)?;
+            let mut nhdr: elf::Elf64_Nhdr = Default::default();
+            unsafe {
+                // read_struct is safe when reading a POD struct.
+                read_struct(kernel_image, &mut nhdr)
+                    .map_err(|_| Error::ReadKernelDataStruct("Failed to read ELF note header"))?;
+            }
+
+            if nhdr.n_type == XEN_ELFNOTE_PHYS32_ENTRY
+                && nhdr.n_namesz as usize == XEN_ELFNOTE_NAME.len()
+            {
+                let mut name = [0u8; 4];
+                kernel_image
+                    .read_exact(&mut name)
+                    .map_err(|_| Error::ReadKernelDataStruct("Failed to read ELF note name"))?;
+                if name[..] == *XEN_ELFNOTE_NAME {
+                    // The address is a 32-bit value, possibly stored on 64 bits.
+                    let mut desc = [0u8; 8];
+                    let desc_size = std::cmp::min(nhdr.n_descsz as usize, desc.len());
+                    kernel_image
+                        .read_exact(&mut desc[..desc_size])
+                        .map_err(|_| {
+                            Error::ReadKernelDataStruct("Failed to read ELF note descriptor")
+                        })?;
+                    return Ok(Some(GuestAddress(u64::from_le_bytes(desc))));
+                }
+            }
+
+            offset += nhdr_size + align(nhdr.n_namesz) + align(nhdr.n_descsz);
+        }
+    }
+    Ok(None)
+}
+
+// Reads and checks the ELF header and the program headers of a vmlinux elf image.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn read_elf_headers<F>(
+    kernel_image: &mut F,
+    start_address: u64,
+) -> Result<(elf::Elf64_Ehdr, Vec<elf::Elf64_Phdr>)>
 where
     F: Read + Seek,
 {
@@ -120,28 +401,7 @@ where
         utils::structs::read_struct_slice(kernel_image, ehdr.e_phnum as usize)
             .map_err(|_| Error::ReadKernelDataStruct("Failed to read ELF program header"))?
     };
-
-    // Read in each section pointed to by the program headers.
-    for phdr in &phdrs {
-        if (phdr.p_type & elf::PT_LOAD) == 0 || phdr.p_filesz == 0 {
-            continue;
-        }
-
-        kernel_image
-            .seek(SeekFrom::Start(phdr.p_offset))
-            .map_err(|_| Error::SeekKernelStart)?;
-
-        let mem_offset = GuestAddress(phdr.p_paddr);
-        if mem_offset.raw_value() < start_address {
-            return Err(Error::InvalidProgramHeaderAddress);
-        }
-
-        guest_mem
-            .read_from(mem_offset, kernel_image, phdr.p_filesz as usize)
-            .map_err(|_| Error::ReadKernelImage)?;
-    }
-
-    Ok(GuestAddress(ehdr.e_entry))
+    Ok((ehdr, phdrs))
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -149,7 +409,7 @@ pub fn load_kernel<F>(
     guest_mem: &GuestMemoryMmap,
     kernel_image: &mut F,
     start_address: u64,
-) -> Result<GuestAddress>
+) -> Result<KernelLoaderResult>
 where
     F: Read + Seek,
 {
@@ -170,24 +430,10 @@ where
     ====================================
      */
     const AARCH64_KERNEL_LOAD_ADDR: u64 = 0x80000;
-    const AARCH64_MAGIC_NUMBER: u32 = 0x644d_5241;
-    const AARCH64_MAGIC_OFFSET_HEADER: u64 =
-        2 * mem::size_of::<u32>() as u64 + 6 * mem::size_of::<u64>() as u64; // This should total 56.
     const AARCH64_TEXT_OFFSET: u64 = 2 * mem::size_of::<u32>() as u64;
     let mut kernel_load_offset = AARCH64_KERNEL_LOAD_ADDR;
 
-    /* Look for the magic number inside the elf header. */
-    kernel_image
-        .seek(SeekFrom::Start(AARCH64_MAGIC_OFFSET_HEADER))
-        .map_err(|_| Error::SeekKernelImage)?;
-    let mut magic_number: u32 = 0;
-    unsafe {
-        read_struct(kernel_image, &mut magic_number)
-            .map_err(|_| Error::ReadKernelDataStruct("Failed to read magic number"))?
-    }
-    if u32::from_le(magic_number) != AARCH64_MAGIC_NUMBER {
-        return Err(Error::InvalidElfMagicNumber);
-    }
+    check_kernel(kernel_image, start_address)?;
 
     /* Look for the `text_offset` from the elf header. */
     kernel_image
@@ -223,7 +469,34 @@ where
         )
         .map_err(|_| Error::ReadKernelImage)?;
 
-    Ok(GuestAddress(kernel_load_offset))
+    Ok(KernelLoaderResult {
+        entry_addr: GuestAddress(kernel_load_offset),
+    })
+}
+
+/// Checks that a kernel image could be loaded, without loading it.
+#[cfg(target_arch = "aarch64")]
+pub fn check_kernel<F>(kernel_image: &mut F, _start_address: u64) -> Result<()>
+where
+    F: Read + Seek,
+{
+    const AARCH64_MAGIC_NUMBER: u32 = 0x644d_5241;
+    const AARCH64_MAGIC_OFFSET_HEADER: u64 =
+        2 * mem::size_of::<u32>() as u64 + 6 * mem::size_of::<u64>() as u64; // This should total 56.
+
+    /* Look for the magic number inside the elf header. */
+    kernel_image
+        .seek(SeekFrom::Start(AARCH64_MAGIC_OFFSET_HEADER))
+        .map_err(|_| Error::SeekKernelImage)?;
+    let mut magic_number: u32 = 0;
+    unsafe {
+        read_struct(kernel_image, &mut magic_number)
+            .map_err(|_| Error::ReadKernelDataStruct("Failed to read magic number"))?
+    }
+    if u32::from_le(magic_number) != AARCH64_MAGIC_NUMBER {
+        return Err(Error::InvalidElfMagicNumber);
+    }
+    Ok(())
 }
 
 /// Writes the command line string to the given memory slice.
@@ -292,7 +565,149 @@ mod tests {
         let load_addr = 0x8_0000;
         assert_eq!(
             Ok(GuestAddress(load_addr)),
-            load_kernel(&gm, &mut Cursor::new(&image), 0)
+            load_kernel(&gm, &mut Cursor::new(&image), 0).map(|kernel| kernel.entry_addr)
+        );
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            let kernel = load_kernel(&gm, &mut Cursor::new(&image), 0).unwrap();
+            assert!(kernel.setup_header.is_none());
+            assert!(kernel.pvh_entry_addr.is_none());
+        }
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn test_read_pvh_entry() {
+        // Appends a note to `image`, padding its name and descriptor to 4 bytes.
+        fn push_note(image: &mut Vec<u8>, name: &[u8], n_type: u32, desc: &[u8]) {
+            image.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            image.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+            image.extend_from_slice(&n_type.to_le_bytes());
+            for field in &[name, desc] {
+                image.extend_from_slice(field);
+                image.resize((image.len() + 3) & !3, 0);
+            }
+        }
+
+        let mut image = vec![0u8; 0x40];
+        push_note(&mut image, b"GNU\0", 3, &[0xab; 20]);
+        push_note(&mut image, b"Xen\0", 17, &0x20_0000u64.to_le_bytes());
+        push_note(
+            &mut image,
+            b"Xen\0",
+            XEN_ELFNOTE_PHYS32_ENTRY,
+            &0x10_0000u32.to_le_bytes(),
+        );
+        let mut phdr = elf::Elf64_Phdr {
+            p_type: elf::PT_NOTE,
+            p_offset: 0x40,
+            p_filesz: image.len() as u64 - 0x40,
+            ..Default::default()
+        };
+        assert_eq!(
+            read_pvh_entry(&mut Cursor::new(&image), &[phdr]),
+            Ok(Some(GuestAddress(0x10_0000)))
+        );
+
+        // The notes of the other segments are ignored.
+        phdr.p_type = elf::PT_LOAD;
+        assert_eq!(read_pvh_entry(&mut Cursor::new(&image), &[phdr]), Ok(None));
+
+        // A truncated note cannot be read.
+        let mut image = vec![0u8; 0x40];
+        push_note(&mut image, b"GNU\0", 3, &[0xab; 20]);
+        image.extend_from_slice(&[0u8; 4]);
+        let phdr = elf::Elf64_Phdr {
+            p_type: elf::PT_NOTE,
+            p_offset: 0x40,
+            p_filesz: image.len() as u64 - 0x40 + 8,
+            ..Default::default()
+        };
+        assert_eq!(
+            read_pvh_entry(&mut Cursor::new(&image), &[phdr]).unwrap_err(),
+            Error::ReadKernelDataStruct("Failed to read ELF note header")
+        );
+    }
+
+    // Builds a bzImage with one setup sector, whose protected-mode kernel is `payload`.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn make_test_bzimage(payload: &[u8]) -> Vec<u8> {
+        let mut image = vec![0u8; 0x400];
+        // setup_sects
+        image[0x1f1] = 1;
+        // boot_flag
+        image[0x1fe..0x200].copy_from_slice(&0xaa55u16.to_le_bytes());
+        // header
+        image[0x202..0x206].copy_from_slice(b"HdrS");
+        // version
+        image[0x206..0x208].copy_from_slice(&0x020fu16.to_le_bytes());
+        // kernel_alignment
+        image[0x230..0x234].copy_from_slice(&0x1_0000u32.to_le_bytes());
+        // relocatable_kernel
+        image[0x234] = 1;
+        // xloadflags
+        image[0x236..0x238].copy_from_slice(&1u16.to_le_bytes());
+        // pref_address
+        image[0x258..0x260].copy_from_slice(&0x10_0000u64.to_le_bytes());
+        // init_size
+        image[0x260..0x264].copy_from_slice(&0x1_0000u32.to_le_bytes());
+        image.extend_from_slice(payload);
+        image
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn test_load_bzimage() {
+        let gm = create_guest_mem();
+        let payload = [0xaau8; 0x800];
+        let image = make_test_bzimage(&payload);
+
+        let kernel = load_kernel(&gm, &mut Cursor::new(&image), 0).unwrap();
+        assert_eq!(kernel.entry_addr, GuestAddress(0x10_0200));
+        let hdr = kernel.setup_header.unwrap();
+        assert_eq!({ hdr.code32_start }, 0x10_0000);
+        assert_eq!({ hdr.init_size }, 0x1_0000);
+        let mut loaded = [0u8; 0x800];
+        gm.read_slice(&mut loaded, GuestAddress(0x10_0000)).unwrap();
+        assert_eq!(&loaded[..], &payload[..]);
+        assert_eq!(Ok(()), check_kernel(&mut Cursor::new(&image), 0));
+
+        // A relocatable kernel is moved above the start address.
+        let kernel = load_kernel(&gm, &mut Cursor::new(&image), 0x10_8000).unwrap();
+        assert_eq!(kernel.entry_addr, GuestAddress(0x11_0200));
+
+        // The decompressed kernel must fit in the guest memory.
+        assert_eq!(
+            Error::BzImageTooLarge,
+            load_kernel(&gm, &mut Cursor::new(&image), 0x17_8000).unwrap_err()
+        );
+
+        let mut bad_image = image.clone();
+        // relocatable_kernel
+        bad_image[0x234] = 0;
+        assert_eq!(
+            Error::InvalidBzImageLoadAddress,
+            load_kernel(&gm, &mut Cursor::new(&bad_image), 0x10_8000).unwrap_err()
+        );
+        assert_eq!(
+            Err(Error::InvalidBzImageLoadAddress),
+            check_kernel(&mut Cursor::new(&bad_image), 0x10_8000)
+        );
+
+        let mut bad_image = image.clone();
+        // xloadflags
+        bad_image[0x236] = 0;
+        assert_eq!(
+            Error::BzImageNot64Bit,
+            load_kernel(&gm, &mut Cursor::new(&bad_image), 0).unwrap_err()
+        );
+
+        let mut bad_image = image;
+        // version
+        bad_image[0x206..0x208].copy_from_slice(&0x020bu16.to_le_bytes());
+        assert_eq!(
+            Error::UnsupportedBzImageVersion,
+            load_kernel(&gm, &mut Cursor::new(&bad_image), 0).unwrap_err()
         );
     }
 
@@ -301,8 +716,8 @@ mod tests {
         let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), 79)]).unwrap();
         let image = make_test_bin();
         assert_eq!(
-            Err(Error::ReadKernelImage),
-            load_kernel(&gm, &mut Cursor::new(&image), 0)
+            Error::ReadKernelImage,
+            load_kernel(&gm, &mut Cursor::new(&image), 0).unwrap_err()
         );
     }
 
@@ -313,14 +728,31 @@ mod tests {
         let mut bad_image = make_test_bin();
         bad_image.truncate(56);
         assert_eq!(
-            Err(Error::ReadKernelDataStruct("Failed to read magic number")),
-            load_kernel(&gm, &mut Cursor::new(&bad_image), 0)
+            Error::ReadKernelDataStruct("Failed to read magic number"),
+            load_kernel(&gm, &mut Cursor::new(&bad_image), 0).unwrap_err()
         );
     }
 
     #[test]
     fn test_bad_kernel_magic() {
         let gm = create_guest_mem();
+        let mut bad_image = make_test_bin();
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let offset = 0x1;
+        #[cfg(target_arch = "aarch64")]
+        let offset = 0x38;
+        bad_image[offset] = 0x33;
+        assert_eq!(
+            Error::InvalidElfMagicNumber,
+            load_kernel(&gm, &mut Cursor::new(&bad_image), 0).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_check_kernel() {
+        let image = make_test_bin();
+        assert_eq!(Ok(()), check_kernel(&mut Cursor::new(&image), 0));
+
         let mut bad_image = make_test_bin();
         #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
         let offset = 0x1;
@@ -329,7 +761,13 @@ mod tests {
         bad_image[offset] = 0x33;
         assert_eq!(
             Err(Error::InvalidElfMagicNumber),
-            load_kernel(&gm, &mut Cursor::new(&bad_image), 0)
+            check_kernel(&mut Cursor::new(&bad_image), 0)
+        );
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert_eq!(
+            Err(Error::InvalidEntryAddress),
+            check_kernel(&mut Cursor::new(&image), std::u64::MAX)
         );
     }
 
@@ -341,8 +779,8 @@ mod tests {
         let mut bad_image = make_test_bin();
         bad_image[0x5] = 2;
         assert_eq!(
-            Err(Error::BigEndianElfOnLittle),
-            load_kernel(&gm, &mut Cursor::new(&bad_image), 0)
+            Error::BigEndianElfOnLittle,
+            load_kernel(&gm, &mut Cursor::new(&bad_image), 0).unwrap_err()
         );
     }
 
@@ -354,8 +792,8 @@ mod tests {
         let mut bad_image = make_test_bin();
         bad_image[0x36] = 0x10;
         assert_eq!(
-            Err(Error::InvalidProgramHeaderSize),
-            load_kernel(&gm, &mut Cursor::new(&bad_image), 0)
+            Error::InvalidProgramHeaderSize,
+            load_kernel(&gm, &mut Cursor::new(&bad_image), 0).unwrap_err()
         );
     }
 
@@ -367,8 +805,8 @@ mod tests {
         let mut bad_image = make_test_bin();
         bad_image[0x20] = 0x10;
         assert_eq!(
-            Err(Error::InvalidProgramHeaderOffset),
-            load_kernel(&gm, &mut Cursor::new(&bad_image), 0)
+            Error::InvalidProgramHeaderOffset,
+            load_kernel(&gm, &mut Cursor::new(&bad_image), 0).unwrap_err()
         );
     }
 
@@ -379,8 +817,8 @@ mod tests {
         let gm = create_guest_mem();
         let bad_image = make_test_bin();
         assert_eq!(
-            Err(Error::InvalidEntryAddress),
-            load_kernel(&gm, &mut Cursor::new(&bad_image), std::u64::MAX)
+            Error::InvalidEntryAddress,
+            load_kernel(&gm, &mut Cursor::new(&bad_image), std::u64::MAX).unwrap_err()
         );
     }
 
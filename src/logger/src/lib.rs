@@ -3,13 +3,21 @@
 mod init;
 mod logger;
 mod metrics;
+mod prometheus;
+mod sinks;
+mod spans;
 
 use std::sync::LockResult;
 
 pub use crate::logger::{LoggerError, LOGGER};
 pub use crate::metrics::{
-    IncMetric, MetricsError, SharedIncMetric, SharedStoreMetric, StoreMetric, METRICS,
+    BalloonStatsMetrics, BlockDeviceMetrics, DeviceMetrics, IncMetric, MetricsError,
+    NetDeviceMetrics, SharedIncMetric, SharedMaxMetric, SharedStoreMetric, StoreMetric,
+    SubscriberMetrics, VcpuExitMetrics, VsockDeviceMetrics, VsockPortMetrics,
+    DEFAULT_FLUSH_INTERVAL_MS, METRICS,
 };
+pub use crate::sinks::{MetricsSink, SinkFormat};
+pub use crate::spans::{in_span, in_timed_span, next_request_id, request_id, set_request_id, Span};
 pub use log::Level::*;
 pub use log::*;
 
@@ -215,6 +215,11 @@ impl Logger {
         self
     }
 
+    /// Returns the ID for this logger session.
+    pub(crate) fn instance_id(&self) -> String {
+        extract_guard(self.instance_id.read()).clone()
+    }
+
     /// Explicitly sets the max log level for the Logger.
     /// The default level is WARN. So, ERROR and WARN statements will be shown (i.e. all that is
     /// bigger than the level code).
@@ -356,9 +361,14 @@ impl Logger {
         Ok(())
     }
 
+    /// Replaces the destination of the logs, e.g. with the file reopened after it was rotated.
+    pub fn reopen(&self, log_dest: Box<dyn Write + Send>) {
+        *extract_guard(self.log_buf.lock()) = log_dest;
+    }
+
     /// The `write_log` method takes care of the common logic involved in writing
     /// regular log messages.
-    fn write_log(&self, mut msg: String, msg_level: Level) {
+    pub(crate) fn write_log(&self, mut msg: String, msg_level: Level) {
         let mut guard;
         let mut dest: Box<dyn Write + Send> = if self.init.is_initialized() {
             guard = extract_guard(self.log_buf.lock());
@@ -583,6 +593,16 @@ mod tests {
             "[TEST-INSTANCE-ID:INFO:logger.rs:0] info\n",
         );
         validate_log(&mut Box::new(&mut reader_2), "");
+
+        // Check that the logs are written to the new writer once the logger is reopened.
+        let (writer_3, mut reader_3) = log_channel();
+        logger.reopen(Box::new(writer_3));
+        logger.mock_log(Level::Info, "info");
+        validate_log(&mut Box::new(&mut reader), "");
+        validate_log(
+            &mut Box::new(&mut reader_3),
+            "[TEST-INSTANCE-ID:INFO:logger.rs:0] info\n",
+        );
     }
 
     #[test]
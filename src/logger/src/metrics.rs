@@ -4,9 +4,9 @@
 //! Defines the metrics system.
 //!
 //! # Metrics format
-//! The metrics are flushed in JSON format each 60 seconds. The first field will always be the
-//! timestamp followed by the JSON representation of the structures representing each component on
-//! which we are capturing specific metrics.
+//! The metrics are flushed in JSON format each 60 seconds by default. The first field will
+//! always be the timestamp followed by the JSON representation of the structures representing
+//! each component on which we are capturing specific metrics.
 //!
 //! ## JSON example with metrics:
 //! ```bash
@@ -32,7 +32,7 @@
 //! the block device such as `activate_fails`, `cfg_fails`, etc.
 //!
 //! # Limitations
-//! Metrics are only written to buffers.
+//! Metrics are only written to buffers, and pushed to the sinks described in the `sinks` module.
 //!
 //! # Design
 //! The main design goals of this system are:
@@ -60,16 +60,24 @@
 //! If if turns out this approach is not really what we want, it's pretty easy to resort to
 //! something else, while working behind the same interface.
 
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::io::Write;
 use std::ops::Deref;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 use lazy_static::lazy_static;
+use serde::ser::SerializeMap;
 use serde::{Serialize, Serializer};
 
 use super::extract_guard;
+use crate::prometheus;
+use crate::sinks::MetricsSink;
+
+/// The default interval between the periodic flushes of the metrics.
+pub const DEFAULT_FLUSH_INTERVAL_MS: u64 = 60000;
 
 lazy_static! {
     /// Static instance used for handling metrics.
@@ -83,6 +91,10 @@ pub struct Metrics<T: Serialize> {
     // Metrics will get flushed here.
     metrics_buf: Mutex<Option<Box<dyn Write + Send>>>,
     is_initialized: AtomicBool,
+    // Metrics will also get pushed to these collectors.
+    sinks: Mutex<Vec<MetricsSink>>,
+    // The interval between the periodic flushes, which are disabled when it is 0.
+    flush_interval_ms: AtomicU64,
     pub app_metrics: T,
 }
 
@@ -94,6 +106,8 @@ impl<T: Serialize> Metrics<T> {
         Metrics {
             metrics_buf: Mutex::new(None),
             is_initialized: AtomicBool::new(false),
+            sinks: Mutex::new(Vec::new()),
+            flush_interval_ms: AtomicU64::new(DEFAULT_FLUSH_INTERVAL_MS),
             app_metrics,
         }
     }
@@ -117,12 +131,60 @@ impl<T: Serialize> Metrics<T> {
         Ok(())
     }
 
-    /// Writes metrics to the destination provided as argument upon initialization of the metrics.
+    /// Replaces the destination of the metrics, e.g. with the file reopened after it was rotated.
+    pub fn reopen(&self, metrics_dest: Box<dyn Write + Send>) {
+        *extract_guard(self.metrics_buf.lock()) = Some(metrics_dest);
+    }
+
+    /// Adds a remote collector the metrics are pushed to each time they are written.
+    pub fn add_sink(&self, sink: MetricsSink) {
+        extract_guard(self.sinks.lock()).push(sink);
+    }
+
+    /// Sets the interval between the periodic flushes of the metrics, 0 disabling them.
+    pub fn set_flush_interval_ms(&self, interval_ms: u64) {
+        self.flush_interval_ms.store(interval_ms, Ordering::Relaxed);
+    }
+
+    /// Returns the interval between the periodic flushes of the metrics, 0 if they are disabled.
+    pub fn flush_interval_ms(&self) -> u64 {
+        self.flush_interval_ms.load(Ordering::Relaxed)
+    }
+
+    /// Writes metrics to the destination provided as argument upon initialization of the metrics,
+    /// and pushes them to the sinks.
     /// Upon failure, an error is returned if metrics system is initialized and metrics could not be
-    /// written.
+    /// written, or if they could not be pushed to a sink.
     /// Upon success, the function will return `True` (if metrics system was initialized and metrics
     /// were successfully written to disk) or `False` (if metrics system was not yet initialized).
     pub fn write(&self) -> Result<bool, MetricsError> {
+        let res = self.write_buf();
+        self.push_to_sinks().and(res)
+    }
+
+    // Pushes the metrics to all the sinks, even if some of them fail.
+    fn push_to_sinks(&self) -> Result<(), MetricsError> {
+        let mut sinks = extract_guard(self.sinks.lock());
+        if sinks.is_empty() {
+            return Ok(());
+        }
+        // The sinks keep track of the counters themselves, so they are not reset.
+        RENDERING_TOTALS.with(|totals| totals.set(true));
+        let samples = prometheus::samples(&self.app_metrics);
+        RENDERING_TOTALS.with(|totals| totals.set(false));
+        let samples = samples.map_err(|e| MetricsError::Serde(e.to_string()))?;
+
+        let timestamp_ns = utils::time::get_time_ns(utils::time::ClockType::Real);
+        let mut res = Ok(());
+        for sink in sinks.iter_mut() {
+            if let Err(e) = sink.push(&samples, timestamp_ns) {
+                res = Err(MetricsError::Sink(e));
+            }
+        }
+        res
+    }
+
+    fn write_buf(&self) -> Result<bool, MetricsError> {
         if self.is_initialized.load(Ordering::Relaxed) {
             match serde_json::to_string(&self.app_metrics) {
                 Ok(msg) => {
@@ -149,6 +211,15 @@ impl<T: Serialize> Metrics<T> {
         // metrics were not written.
         Ok(false)
     }
+
+    /// Renders the metrics in the Prometheus text exposition format. Unlike `write`, the counters
+    /// hold their value since the process started, and are not reset.
+    pub fn render_prometheus(&self) -> Result<String, MetricsError> {
+        RENDERING_TOTALS.with(|totals| totals.set(true));
+        let res = prometheus::render(&self.app_metrics);
+        RENDERING_TOTALS.with(|totals| totals.set(false));
+        res.map_err(|e| MetricsError::Serde(e.to_string()))
+    }
 }
 
 impl<T: Serialize> Deref for Metrics<T> {
@@ -170,6 +241,8 @@ pub enum MetricsError {
     Serde(String),
     /// Writing the specified buffer failed.
     Write(std::io::Error),
+    /// Pushing the metrics to a sink failed.
+    Sink(std::io::Error),
 }
 
 impl fmt::Display for MetricsError {
@@ -181,6 +254,7 @@ impl fmt::Display for MetricsError {
             }
             MetricsError::Serde(ref e) => e.to_string(),
             MetricsError::Write(ref e) => format!("Failed to write metrics. Error: {}", e),
+            MetricsError::Sink(ref e) => format!("Failed to push metrics. Error: {}", e),
         };
         write!(f, "{}", printable)
     }
@@ -220,9 +294,27 @@ pub trait StoreMetric {
 #[derive(Default)]
 pub struct SharedIncMetric(AtomicUsize, AtomicUsize);
 
+thread_local! {
+    // Set while the metrics are rendered for Prometheus, which expects the counters since the
+    // process started rather than since the previous flush.
+    static RENDERING_TOTALS: Cell<bool> = Cell::new(false);
+}
+
 #[derive(Default)]
 pub struct SharedStoreMetric(AtomicUsize);
 
+/// Representation of a metric holding the largest value recorded since the metrics were last
+/// flushed.
+#[derive(Default)]
+pub struct SharedMaxMetric(AtomicUsize);
+
+impl SharedMaxMetric {
+    /// Records `value`, which is kept if it is the largest since the last flush.
+    pub fn record(&self, value: usize) {
+        self.0.fetch_max(value, Ordering::Relaxed);
+    }
+}
+
 impl IncMetric for SharedIncMetric {
     // While the order specified for this operation is still Relaxed, the actual instruction will
     // be an asm "LOCK; something" and thus atomic across multiple threads, simply because of the
@@ -247,11 +339,25 @@ impl StoreMetric for SharedStoreMetric {
     }
 }
 
+impl StoreMetric for SharedMaxMetric {
+    fn fetch(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn store(&self, value: usize) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
 impl Serialize for SharedIncMetric {
     /// Reset counters of each metrics. Here we suppose that Serialize's goal is to help with the
     /// flushing of metrics.
     /// !!! Any print of the metrics will also reset them. Use with caution !!!
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if RENDERING_TOTALS.with(Cell::get) {
+            return serializer
+                .serialize_newtype_struct(prometheus::INC_METRIC_NAME, &(self.count() as u64));
+        }
         // There's no serializer.serialize_usize() for some reason :(
         let snapshot = self.0.load(Ordering::Relaxed);
         let res = serializer.serialize_u64(snapshot as u64 - self.1.load(Ordering::Relaxed) as u64);
@@ -269,6 +375,21 @@ impl Serialize for SharedStoreMetric {
     }
 }
 
+impl Serialize for SharedMaxMetric {
+    /// Resets the metric once flushed, unless a larger value was recorded in the meantime.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = self.0.load(Ordering::Relaxed);
+        let res = serializer.serialize_u64(value as u64);
+
+        if res.is_ok() && !RENDERING_TOTALS.with(Cell::get) {
+            let _ = self
+                .0
+                .compare_exchange(value, 0, Ordering::Relaxed, Ordering::Relaxed);
+        }
+        res
+    }
+}
+
 // The following structs are used to define a certain organization for the set of metrics we
 // are interested in. Whenever the name of a field differs from its ideal textual representation
 // in the serialized form, we can use the #[serde(rename = "name")] attribute to, well, rename it.
@@ -276,14 +397,24 @@ impl Serialize for SharedStoreMetric {
 /// Metrics related to the internal API server.
 #[derive(Default, Serialize)]
 pub struct ApiServerMetrics {
+    /// Number of API requests which could not be recorded in the audit log.
+    pub audit_log_fails: SharedIncMetric,
+    /// Number of API requests rejected by the authorization policy.
+    pub auth_fails: SharedIncMetric,
+    /// Number of API requests rejected because their body was too large.
+    pub payload_too_large: SharedIncMetric,
     /// Measures the process's startup time in microseconds.
     pub process_startup_time_us: SharedStoreMetric,
     /// Measures the cpu's startup time in microseconds.
     pub process_startup_time_cpu_us: SharedStoreMetric,
+    /// Number of API requests rejected because the request rate of their socket was exceeded.
+    pub rate_limited_requests: SharedIncMetric,
     /// Number of failures on API requests triggered by internal errors.
     pub sync_response_fails: SharedIncMetric,
     /// Number of timeouts during communication with the VMM.
     pub sync_vmm_send_timeout_count: SharedIncMetric,
+    /// Number of API requests rejected because too many jobs were in progress.
+    pub too_many_in_flight: SharedIncMetric,
 }
 
 /// Metrics specific to GET API Requests for counting user triggered actions and/or failures.
@@ -293,10 +424,26 @@ pub struct GetRequestsMetrics {
     pub instance_info_count: SharedIncMetric,
     /// Number of failures when obtaining information on the current instance.
     pub instance_info_fails: SharedIncMetric,
+    /// Number of GETs for getting the state of an asynchronous job.
+    pub job_count: SharedIncMetric,
+    /// Number of failures when getting the state of an asynchronous job.
+    pub job_fails: SharedIncMetric,
     /// Number of GETs for getting status on attaching machine configuration.
     pub machine_cfg_count: SharedIncMetric,
     /// Number of failures during GETs for getting information on the instance.
     pub machine_cfg_fails: SharedIncMetric,
+    /// Number of GETs for getting the host resources used by the microVM.
+    pub machine_stats_count: SharedIncMetric,
+    /// Number of GETs for getting the metrics in the Prometheus format.
+    pub metrics_count: SharedIncMetric,
+    /// Number of failures when rendering the metrics in the Prometheus format.
+    pub metrics_fails: SharedIncMetric,
+    /// Number of GETs for getting the durations of the phases of the boot and of the snapshots.
+    pub timings_count: SharedIncMetric,
+    /// Number of GETs for getting the KVM exits and the time split of each vCPU.
+    pub vcpu_stats_count: SharedIncMetric,
+    /// Number of GETs for getting the full configuration of the microVM.
+    pub vm_config_count: SharedIncMetric,
 }
 
 /// Metrics specific to PUT API Requests for counting user triggered actions and/or failures.
@@ -330,6 +477,10 @@ pub struct PutRequestsMetrics {
     pub network_count: SharedIncMetric,
     /// Number of failures in creating a new network interface.
     pub network_fails: SharedIncMetric,
+    /// Number of PUTs for configuring the whole microVM at once.
+    pub vm_config_count: SharedIncMetric,
+    /// Number of failures in configuring the whole microVM at once.
+    pub vm_config_fails: SharedIncMetric,
 }
 
 /// Metrics specific to PATCH API Requests for counting user triggered actions and/or failures.
@@ -349,6 +500,15 @@ pub struct PatchRequestsMetrics {
     pub machine_cfg_fails: SharedIncMetric,
 }
 
+/// Metrics specific to POST API Requests for counting user triggered actions and/or failures.
+#[derive(Default, Serialize)]
+pub struct PostRequestsMetrics {
+    /// Number of POSTs for validating a configuration of the whole microVM.
+    pub vm_validate_count: SharedIncMetric,
+    /// Number of failures in parsing a configuration of the whole microVM to validate.
+    pub vm_validate_fails: SharedIncMetric,
+}
+
 /// Balloon Device associated metrics.
 #[derive(Default, Serialize)]
 pub struct BalloonDeviceMetrics {
@@ -360,10 +520,49 @@ pub struct BalloonDeviceMetrics {
     pub stats_updates_count: SharedIncMetric,
     // Number of balloon statistics update failures.
     pub stats_update_fails: SharedIncMetric,
+    /// Number of bytes of guest memory actually given back to the host on inflation.
+    pub reclaimed_bytes: SharedIncMetric,
     /// Number of balloon device deflations.
     pub deflate_count: SharedIncMetric,
+    /// Number of free page reports processed.
+    pub free_page_report_count: SharedIncMetric,
+    /// Number of bytes of guest memory given back to the host from the free page reports.
+    pub free_page_reclaimed_bytes: SharedIncMetric,
+    /// Number of balloon target size changes made by the balloon policy.
+    pub policy_adjustments: SharedIncMetric,
+    /// Number of times when the balloon policy failed to read the memory pressure of the host.
+    pub policy_fails: SharedIncMetric,
     /// Number of times when handling events on a balloon device failed.
     pub event_fails: SharedIncMetric,
+    /// The latest memory statistics reported by the guest, keyed by balloon device ID.
+    #[serde(skip_serializing_if = "DeviceMetricsMap::is_empty")]
+    pub balloons: DeviceMetricsMap<BalloonStatsMetrics>,
+}
+
+/// The memory statistics of the guest, as last reported to the balloon device. The statistics not
+/// reported by the guest keep their previous value.
+#[derive(Default, Serialize)]
+pub struct BalloonStatsMetrics {
+    /// Amount of memory swapped in, in bytes.
+    pub swap_in: SharedStoreMetric,
+    /// Amount of memory swapped out, in bytes.
+    pub swap_out: SharedStoreMetric,
+    /// Number of major page faults.
+    pub major_faults: SharedStoreMetric,
+    /// Number of minor page faults.
+    pub minor_faults: SharedStoreMetric,
+    /// Amount of memory not used for any purpose, in bytes.
+    pub free_memory: SharedStoreMetric,
+    /// Total amount of memory available to the guest, in bytes.
+    pub total_memory: SharedStoreMetric,
+    /// Estimated amount of memory available for starting new applications, in bytes.
+    pub available_memory: SharedStoreMetric,
+    /// Amount of memory which can be reclaimed without additional I/O, in bytes.
+    pub disk_caches: SharedStoreMetric,
+    /// Number of successful hugetlb page allocations.
+    pub hugetlb_allocations: SharedStoreMetric,
+    /// Number of failed hugetlb page allocations.
+    pub hugetlb_failures: SharedStoreMetric,
 }
 
 /// Block Device associated metrics.
@@ -401,6 +600,73 @@ pub struct BlockDeviceMetrics {
     pub write_count: SharedIncMetric,
     /// Number of rate limiter throttling events.
     pub rate_limiter_throttled_events: SharedIncMetric,
+    /// Number of times the device yielded to the drives sharing the I/O with it.
+    pub io_scheduler_yields: SharedIncMetric,
+    /// Metrics split per block device, keyed by drive ID.
+    #[serde(skip_serializing_if = "DeviceMetricsMap::is_empty")]
+    pub drives: DeviceMetricsMap<BlockDeviceMetrics>,
+}
+
+/// Virtio-console device associated metrics.
+#[derive(Default, Serialize)]
+pub struct ConsoleDeviceMetrics {
+    /// Number of times when activate failed on the console device.
+    pub activate_fails: SharedIncMetric,
+    /// Number of times when the driver tried to write the read-only configuration space.
+    pub cfg_fails: SharedIncMetric,
+    /// Number of host connections accepted on the console ports.
+    pub connections: SharedIncMetric,
+    /// Number of control messages from the driver which were malformed.
+    pub control_fails: SharedIncMetric,
+    /// Number of times when handling events on the console device failed.
+    pub event_fails: SharedIncMetric,
+    /// Number of bytes sent from the host to the guest.
+    pub rx_bytes_count: SharedIncMetric,
+    /// Number of bytes sent from the guest to the host.
+    pub tx_bytes_count: SharedIncMetric,
+    /// Number of bytes sent by the guest which could not be delivered to the host.
+    pub tx_dropped_bytes: SharedIncMetric,
+}
+
+/// Entropy device associated metrics.
+#[derive(Default, Serialize)]
+pub struct EntropyDeviceMetrics {
+    /// Number of times when activate failed on the entropy device.
+    pub activate_fails: SharedIncMetric,
+    /// Number of times when the driver tried to access the inexistent configuration space.
+    pub cfg_fails: SharedIncMetric,
+    /// Number of bytes of entropy provided to the guest.
+    pub entropy_bytes: SharedIncMetric,
+    /// Number of entropy requests which were malformed or could not be fulfilled.
+    pub entropy_fails: SharedIncMetric,
+    /// Number of times when handling events on the entropy device failed.
+    pub event_fails: SharedIncMetric,
+    /// Number of events associated with the rate limiter.
+    pub rate_limiter_event_count: SharedIncMetric,
+    /// Number of events throttled because of the rate limiter.
+    pub rate_limiter_throttled_events: SharedIncMetric,
+}
+
+/// Metrics related to the event loops.
+#[derive(Default, Serialize)]
+pub struct EventManagerMetrics {
+    /// Metrics split per subscriber, keyed by subscriber name.
+    pub subscribers: DeviceMetricsMap<SubscriberMetrics>,
+}
+
+/// The events dispatched to the subscribers of a name, and how long they took to be processed.
+#[derive(Default, Serialize)]
+pub struct SubscriberMetrics {
+    /// Number of events dispatched to the subscribers.
+    pub invocations: SharedIncMetric,
+    /// Time spent processing the events, in microseconds.
+    pub handler_time_us: SharedIncMetric,
+    /// Longest time spent processing an event since the last flush, in microseconds.
+    pub max_handler_time_us: SharedMaxMetric,
+    /// Time the events waited to be dispatched once reported ready, in microseconds.
+    pub queue_delay_us: SharedIncMetric,
+    /// Longest time an event waited to be dispatched since the last flush, in microseconds.
+    pub max_queue_delay_us: SharedMaxMetric,
 }
 
 /// Metrics specific to the i8042 device.
@@ -433,6 +699,25 @@ pub struct LoggerSystemMetrics {
     pub log_fails: SharedIncMetric,
 }
 
+/// Virtio-mem device associated metrics.
+#[derive(Default, Serialize)]
+pub struct MemDeviceMetrics {
+    /// Number of times when activate failed on the virtio-mem device.
+    pub activate_fails: SharedIncMetric,
+    /// Number of times when the driver tried to write the read-only configuration space.
+    pub cfg_fails: SharedIncMetric,
+    /// Number of plug requests granted to the driver.
+    pub plug_count: SharedIncMetric,
+    /// Number of unplug requests granted to the driver.
+    pub unplug_count: SharedIncMetric,
+    /// Number of bytes of unplugged guest memory given back to the host.
+    pub reclaimed_bytes: SharedIncMetric,
+    /// Number of driver requests which were rejected or malformed.
+    pub request_fails: SharedIncMetric,
+    /// Number of times when handling events on the virtio-mem device failed.
+    pub event_fails: SharedIncMetric,
+}
+
 /// Metrics for the MMDS functionality.
 #[derive(Default, Serialize)]
 pub struct MmdsMetrics {
@@ -460,6 +745,17 @@ pub struct MmdsMetrics {
     pub connections_destroyed: SharedIncMetric,
 }
 
+/// Null device associated metrics.
+#[derive(Default, Serialize)]
+pub struct NullDeviceMetrics {
+    /// Number of times when activate failed on a null device.
+    pub activate_fails: SharedIncMetric,
+    /// Number of driver requests acknowledged and discarded by the null devices.
+    pub discarded_requests: SharedIncMetric,
+    /// Number of times when handling events on a null device failed.
+    pub event_fails: SharedIncMetric,
+}
+
 /// Network-related metrics.
 #[derive(Default, Serialize)]
 pub struct NetDeviceMetrics {
@@ -473,6 +769,10 @@ pub struct NetDeviceMetrics {
     pub no_rx_avail_buffer: SharedIncMetric,
     /// No available buffer for the net device tx queue.
     pub no_tx_avail_buffer: SharedIncMetric,
+    /// Number of DHCP requests from the guest handled by the DHCP server of a network device.
+    pub dhcp_rx_frames: SharedIncMetric,
+    /// Number of DHCP replies sent to the guest by the DHCP server of a network device.
+    pub dhcp_tx_frames: SharedIncMetric,
     /// Number of times when handling events on a network device failed.
     pub event_fails: SharedIncMetric,
     /// Number of events associated with the receiving queue.
@@ -493,6 +793,9 @@ pub struct NetDeviceMetrics {
     pub rx_fails: SharedIncMetric,
     /// Number of successful read operations while receiving data.
     pub rx_count: SharedIncMetric,
+    /// Number of frames received from the TAP which were discarded for not belonging to the
+    /// VLAN of the interface.
+    pub rx_vlan_filtered_frames: SharedIncMetric,
     /// Number of times reading from TAP failed.
     pub tap_read_fails: SharedIncMetric,
     /// Number of times writing to TAP failed.
@@ -517,6 +820,9 @@ pub struct NetDeviceMetrics {
     pub tx_rate_limiter_throttled: SharedIncMetric,
     /// Number of packets with a spoofed mac, sent by the guest.
     pub tx_spoofed_mac_count: SharedIncMetric,
+    /// Metrics split per network device, keyed by interface ID.
+    #[serde(skip_serializing_if = "DeviceMetricsMap::is_empty")]
+    pub ifaces: DeviceMetricsMap<NetDeviceMetrics>,
 }
 
 /// Performance metrics related for the moment only to snapshots.
@@ -557,6 +863,45 @@ pub struct PerformanceMetrics {
     pub vmm_resume_vm: SharedStoreMetric,
 }
 
+/// Durations of the phases of the boot, of the snapshots and of the restores.
+// As for the `PerformanceMetrics`, only the duration of the last run of each phase is stored,
+// in microseconds. The metrics keep their value when flushed, and are also served by the
+// `GET /timings` API request.
+#[derive(Default, Serialize)]
+pub struct PhaseTimingMetrics {
+    /// Attaching the devices to the microVM at boot.
+    pub attach_devices: SharedStoreMetric,
+    /// Configuring the boot parameters and the vCPUs of the guest.
+    pub configure_system: SharedStoreMetric,
+    /// From the start of the boot until the first vCPU enters the guest.
+    pub first_vcpu_run: SharedStoreMetric,
+    /// Loading the kernel into the guest memory.
+    pub load_kernel: SharedStoreMetric,
+    /// Restoring the guest memory of a snapshot, usually by mapping the memory file.
+    pub restore_memory: SharedStoreMetric,
+    /// Resuming the devices and the vCPUs of the microVM.
+    pub resume_vm: SharedStoreMetric,
+    /// Saving the guest memory to the memory file of a snapshot.
+    pub snapshot_memory: SharedStoreMetric,
+    /// Serializing the microVM state to the snapshot file.
+    pub snapshot_state: SharedStoreMetric,
+}
+
+/// Virtio-pmem device associated metrics.
+#[derive(Default, Serialize)]
+pub struct PmemDeviceMetrics {
+    /// Number of times when activate failed on a pmem device.
+    pub activate_fails: SharedIncMetric,
+    /// Number of times when the driver tried to write the read-only configuration space.
+    pub cfg_fails: SharedIncMetric,
+    /// Number of times when handling events on a pmem device failed.
+    pub event_fails: SharedIncMetric,
+    /// Number of flush requests served.
+    pub flush_count: SharedIncMetric,
+    /// Number of flush requests which were malformed or could not be fulfilled.
+    pub flush_fails: SharedIncMetric,
+}
+
 /// Metrics specific to the RTC device.
 #[derive(Default, Serialize)]
 pub struct RTCDeviceMetrics {
@@ -571,10 +916,35 @@ pub struct RTCDeviceMetrics {
 /// Metrics for the seccomp filtering.
 #[derive(Default, Serialize)]
 pub struct SeccompMetrics {
+    /// Number of syscalls which the seccomp filter does not allow, but audited instead of denied.
+    pub num_audited: SharedIncMetric,
     /// Number of errors inside the seccomp filtering.
     pub num_faults: SharedIncMetric,
 }
 
+/// Shared filesystem devices associated metrics.
+#[derive(Default, Serialize)]
+pub struct SharedFsDeviceMetrics {
+    /// Number of times when activate failed on a shared filesystem device.
+    pub activate_fails: SharedIncMetric,
+    /// Number of times when handling events on a shared filesystem device failed.
+    pub event_fails: SharedIncMetric,
+    /// Number of times when the driver tried to write the read-only configuration space.
+    pub cfg_fails: SharedIncMetric,
+    /// Number of used queue notifications forwarded from the vhost-user backends to the guest.
+    pub backend_notifications: SharedIncMetric,
+}
+
+/// TPM device associated metrics.
+#[derive(Default, Serialize)]
+pub struct TpmDeviceMetrics {
+    /// Number of commands sent by the guest.
+    pub command_count: SharedIncMetric,
+    /// Number of commands which could not be executed by the backend, or whose response didn't
+    /// fit in the command buffer.
+    pub command_fails: SharedIncMetric,
+}
+
 /// Metrics specific to the UART device.
 #[derive(Default, Serialize)]
 pub struct SerialDeviceMetrics {
@@ -609,6 +979,8 @@ pub struct SignalMetrics {
     pub sighup: SharedIncMetric,
     /// Number of times that SIGILL was handled.
     pub sigill: SharedIncMetric,
+    /// Number of times that SIGTERM was handled.
+    pub sigterm: SharedIncMetric,
 }
 
 /// Metrics specific to VCPUs' mode of functioning.
@@ -626,6 +998,63 @@ pub struct VcpuMetrics {
     pub failures: SharedIncMetric,
     /// Failures in configuring the CPUID.
     pub filter_cpuid: SharedIncMetric,
+    /// Metrics split per vCPU.
+    pub vcpus: VcpuExitMetricsMap,
+}
+
+/// The KVM exits of one vCPU, and how its running time splits between the guest and the VMM.
+#[derive(Default, Serialize)]
+pub struct VcpuExitMetrics {
+    /// Number of KVM exits for handling port IO.
+    pub exit_io: SharedIncMetric,
+    /// Number of KVM exits for handling MMIO.
+    pub exit_mmio: SharedIncMetric,
+    /// Number of KVM exits on a halted vCPU.
+    pub exit_hlt: SharedIncMetric,
+    /// Number of runs failing on a guest memory access the host couldn't fault in.
+    pub exit_epfault: SharedIncMetric,
+    /// Number of runs interrupted by a signal sent to the vCPU thread.
+    pub exit_interrupts: SharedIncMetric,
+    /// Time spent running the guest, in microseconds.
+    pub guest_time_us: SharedIncMetric,
+    /// Time spent in the VMM handling the exits, in microseconds.
+    pub vmm_time_us: SharedIncMetric,
+    /// Time the vCPU thread was paused for having used up its CPU quota, in microseconds.
+    pub throttled_time_us: SharedIncMetric,
+}
+
+/// The per-vCPU metrics, keyed by vCPU index.
+#[derive(Default)]
+pub struct VcpuExitMetricsMap(Mutex<BTreeMap<u8, Arc<VcpuExitMetrics>>>);
+
+impl VcpuExitMetricsMap {
+    /// Returns the metrics of the vCPU `index`, which start being tracked if they weren't
+    /// already.
+    pub fn get(&self, index: u8) -> Arc<VcpuExitMetrics> {
+        extract_guard(self.0.lock())
+            .entry(index)
+            .or_default()
+            .clone()
+    }
+
+    /// Returns the metrics of all the tracked vCPUs, ordered by vCPU index.
+    pub fn all(&self) -> Vec<(u8, Arc<VcpuExitMetrics>)> {
+        extract_guard(self.0.lock())
+            .iter()
+            .map(|(index, metrics)| (*index, metrics.clone()))
+            .collect()
+    }
+}
+
+impl Serialize for VcpuExitMetricsMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let vcpus = self.all();
+        let mut map = serializer.serialize_map(Some(vcpus.len()))?;
+        for (index, metrics) in vcpus.iter() {
+            map.serialize_entry(index, metrics.as_ref())?;
+        }
+        map.end()
+    }
 }
 
 /// Metrics specific to the machine manager as a whole.
@@ -633,8 +1062,18 @@ pub struct VcpuMetrics {
 pub struct VmmMetrics {
     /// Number of device related events received for a VM.
     pub device_events: SharedIncMetric,
+    /// Number of graceful shutdowns which timed out, stopping the microVM.
+    pub graceful_shutdown_timeout_count: SharedIncMetric,
+    /// Number of guest kernel panics detected.
+    pub guest_panic_count: SharedIncMetric,
     /// Metric for signaling a panic has occurred.
     pub panic_count: SharedIncMetric,
+    /// Number of guest crashes reported through the pvpanic device.
+    pub pvpanic_count: SharedIncMetric,
+    /// Number of times the guest expired the watchdog.
+    pub watchdog_expired_count: SharedIncMetric,
+    /// Number of times the guest started or petted the watchdog.
+    pub watchdog_pet_count: SharedIncMetric,
 }
 
 /// Vsock-related metrics.
@@ -658,6 +1097,14 @@ pub struct VsockDeviceMetrics {
     pub rx_queue_event_count: SharedIncMetric,
     /// Number of events associated with the transmitting queue.
     pub tx_queue_event_count: SharedIncMetric,
+    /// Number of events associated with the rate limiter installed on the receiving path.
+    pub rx_rate_limiter_event_count: SharedIncMetric,
+    /// Number of events associated with the rate limiter installed on the transmitting path.
+    pub tx_rate_limiter_event_count: SharedIncMetric,
+    /// Number of times the receiving path was throttled by the rate limiter.
+    pub rx_rate_limiter_throttled: SharedIncMetric,
+    /// Number of times the transmitting path was throttled by the rate limiter.
+    pub tx_rate_limiter_throttled: SharedIncMetric,
     /// Number of bytes received.
     pub rx_bytes_count: SharedIncMetric,
     /// Number of transmitted bytes.
@@ -680,6 +1127,163 @@ pub struct VsockDeviceMetrics {
     pub tx_write_fails: SharedIncMetric,
     /// Number of times read() has failed.
     pub rx_read_fails: SharedIncMetric,
+    /// Number of datagrams that could not be delivered.
+    pub dgrams_dropped: SharedIncMetric,
+    /// Number of used buffer notifications forwarded from the vhost-vsock kernel backend.
+    pub vhost_notifications: SharedIncMetric,
+    /// Number of times when handling the vhost-vsock kernel backend events failed.
+    pub vhost_event_fails: SharedIncMetric,
+    /// Metrics split per vsock device, keyed by device ID.
+    #[serde(skip_serializing_if = "DeviceMetricsMap::is_empty")]
+    pub vsocks: DeviceMetricsMap<VsockDeviceMetrics>,
+    /// Metrics split per vsock port.
+    #[serde(skip_serializing_if = "VsockPortMetricsMap::is_empty")]
+    pub ports: VsockPortMetricsMap,
+}
+
+/// Metrics related to the traffic of one vsock port. The traffic of a connection is accounted to
+/// the port that the connection was made to, i.e. the port of the listening end.
+#[derive(Default, Serialize)]
+pub struct VsockPortMetrics {
+    /// Number of bytes received.
+    pub rx_bytes_count: SharedIncMetric,
+    /// Number of transmitted bytes.
+    pub tx_bytes_count: SharedIncMetric,
+    /// Number of packets received.
+    pub rx_packets_count: SharedIncMetric,
+    /// Number of transmitted packets.
+    pub tx_packets_count: SharedIncMetric,
+    /// Number of open connections.
+    pub active_conns: SharedStoreMetric,
+    /// Number of guest packets that could not be delivered.
+    pub dropped_packets: SharedIncMetric,
+}
+
+/// The per-port vsock metrics, keyed by port number.
+// The guest can send packets to any of the 2^32 vsock ports, so the number of tracked ports is
+// capped. The traffic of the ports seen after that is only accounted in the device-wide metrics.
+#[derive(Default)]
+pub struct VsockPortMetricsMap(Mutex<BTreeMap<u32, Arc<VsockPortMetrics>>>);
+
+impl VsockPortMetricsMap {
+    /// Maximum number of tracked ports.
+    pub const MAX_PORTS: usize = 256;
+
+    /// Returns the metrics of `port`, which start being tracked if they weren't already. Returns
+    /// `None` if `port` isn't tracked, and no more ports can be.
+    pub fn get(&self, port: u32) -> Option<Arc<VsockPortMetrics>> {
+        let mut ports = extract_guard(self.0.lock());
+        if ports.len() >= Self::MAX_PORTS && !ports.contains_key(&port) {
+            return None;
+        }
+        Some(ports.entry(port).or_default().clone())
+    }
+
+    /// Returns the metrics of all the tracked ports, ordered by port number.
+    pub fn all(&self) -> Vec<(u32, Arc<VsockPortMetrics>)> {
+        extract_guard(self.0.lock())
+            .iter()
+            .map(|(port, metrics)| (*port, metrics.clone()))
+            .collect()
+    }
+
+    /// Returns whether no port is tracked.
+    pub fn is_empty(&self) -> bool {
+        extract_guard(self.0.lock()).is_empty()
+    }
+}
+
+impl Serialize for VsockPortMetricsMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ports = self.all();
+        let mut map = serializer.serialize_map(Some(ports.len()))?;
+        for (port, metrics) in ports.iter() {
+            map.serialize_entry(port, metrics.as_ref())?;
+        }
+        map.end()
+    }
+}
+
+/// The metrics of the devices of a kind, keyed by device ID.
+// The metrics of a device are of the same type as those of all the devices of its kind, so its
+// own map of devices stays empty, and isn't serialized.
+pub struct DeviceMetricsMap<T>(Mutex<BTreeMap<String, Arc<T>>>);
+
+impl<T> Default for DeviceMetricsMap<T> {
+    fn default() -> Self {
+        DeviceMetricsMap(Mutex::new(BTreeMap::new()))
+    }
+}
+
+impl<T: Default> DeviceMetricsMap<T> {
+    /// Returns the metrics of the device `id`, which start being tracked if they weren't
+    /// already.
+    pub fn get(&self, id: &str) -> Arc<T> {
+        extract_guard(self.0.lock())
+            .entry(id.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+impl<T> DeviceMetricsMap<T> {
+    /// Returns the metrics of all the tracked devices, ordered by device ID.
+    pub fn all(&self) -> Vec<(String, Arc<T>)> {
+        extract_guard(self.0.lock())
+            .iter()
+            .map(|(id, metrics)| (id.clone(), metrics.clone()))
+            .collect()
+    }
+
+    /// Returns whether no device is tracked.
+    pub fn is_empty(&self) -> bool {
+        extract_guard(self.0.lock()).is_empty()
+    }
+}
+
+impl<T: Serialize> Serialize for DeviceMetricsMap<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let devices = self.all();
+        let mut map = serializer.serialize_map(Some(devices.len()))?;
+        for (id, metrics) in devices.iter() {
+            map.serialize_entry(id, metrics.as_ref())?;
+        }
+        map.end()
+    }
+}
+
+/// The metrics of a device, along with those of all the devices of its kind.
+pub struct DeviceMetrics<T: 'static> {
+    total: &'static T,
+    device: Arc<T>,
+}
+
+impl<T> DeviceMetrics<T> {
+    /// Creates the metrics of a device, given those of all the devices of its kind.
+    pub fn new(total: &'static T, device: Arc<T>) -> Self {
+        DeviceMetrics { total, device }
+    }
+
+    /// Applies `update_fn` to the metrics of the device, and to those of all the devices of its
+    /// kind.
+    pub fn update<F: Fn(&T)>(&self, update_fn: F) {
+        update_fn(self.total);
+        update_fn(&self.device);
+    }
+
+    /// Returns the metrics of the device alone.
+    pub fn device(&self) -> &T {
+        &self.device
+    }
+}
+
+impl<T> Clone for DeviceMetrics<T> {
+    fn clone(&self) -> Self {
+        DeviceMetrics {
+            total: self.total,
+            device: self.device.clone(),
+        }
+    }
 }
 
 // The sole purpose of this struct is to produce an UTC timestamp when an instance is serialized.
@@ -704,6 +1308,12 @@ pub struct FirecrackerMetrics {
     pub balloon: BalloonDeviceMetrics,
     /// A block device's related metrics.
     pub block: BlockDeviceMetrics,
+    /// Metrics related to the console device.
+    pub console: ConsoleDeviceMetrics,
+    /// Metrics related to the entropy device.
+    pub entropy: EntropyDeviceMetrics,
+    /// Metrics related to the dispatch of the events to their subscribers.
+    pub event_manager: EventManagerMetrics,
     /// Metrics related to API GET requests.
     pub get_api_requests: GetRequestsMetrics,
     /// Metrics related to the i8042 device.
@@ -712,18 +1322,32 @@ pub struct FirecrackerMetrics {
     pub latencies_us: PerformanceMetrics,
     /// Logging related metrics.
     pub logger: LoggerSystemMetrics,
+    /// Metrics related to the virtio-mem device.
+    pub mem: MemDeviceMetrics,
     /// Metrics specific to MMDS functionality.
     pub mmds: MmdsMetrics,
     /// A network device's related metrics.
     pub net: NetDeviceMetrics,
+    /// Metrics related to the null devices.
+    pub null_device: NullDeviceMetrics,
     /// Metrics related to API PATCH requests.
     pub patch_api_requests: PatchRequestsMetrics,
+    /// Metrics related to the virtio-pmem devices.
+    pub pmem: PmemDeviceMetrics,
+    /// Metrics related to API POST requests.
+    pub post_api_requests: PostRequestsMetrics,
     /// Metrics related to API PUT requests.
     pub put_api_requests: PutRequestsMetrics,
     /// Metrics related to the RTC device.
     pub rtc: RTCDeviceMetrics,
     /// Metrics related to seccomp filtering.
     pub seccomp: SeccompMetrics,
+    /// Metrics related to the shared filesystem devices.
+    pub shared_fs: SharedFsDeviceMetrics,
+    /// Durations of the phases of the boot and of the snapshots.
+    pub timings_us: PhaseTimingMetrics,
+    /// Metrics related to the TPM device.
+    pub tpm: TpmDeviceMetrics,
     /// Metrics related to a vcpu's functioning.
     pub vcpu: VcpuMetrics,
     /// Metrics related to the virtual machine manager.
@@ -765,6 +1389,41 @@ mod tests {
         assert!(m.init(Box::new(f.into_file()),).is_err());
     }
 
+    #[test]
+    fn test_reopen() {
+        let m = Metrics::new(FirecrackerMetrics::default());
+        let f = TempFile::new().expect("Failed to create temporary metrics file");
+        assert!(m.init(Box::new(f.as_file().try_clone().unwrap())).is_ok());
+        assert!(m.write().unwrap());
+
+        // The metrics are only written to the new destination once the metrics are reopened.
+        let rotated = TempFile::new().expect("Failed to create temporary metrics file");
+        m.reopen(Box::new(rotated.as_file().try_clone().unwrap()));
+        assert!(m.write().unwrap());
+        assert_eq!(
+            std::fs::read_to_string(f.as_path())
+                .unwrap()
+                .lines()
+                .count(),
+            1
+        );
+        assert_eq!(
+            std::fs::read_to_string(rotated.as_path())
+                .unwrap()
+                .lines()
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_flush_interval() {
+        let m = Metrics::new(FirecrackerMetrics::default());
+        assert_eq!(m.flush_interval_ms(), DEFAULT_FLUSH_INTERVAL_MS);
+        m.set_flush_interval_ms(0);
+        assert_eq!(m.flush_interval_ms(), 0);
+    }
+
     #[test]
     fn test_shared_inc_metric() {
         let metric = Arc::new(SharedIncMetric::default());
@@ -808,12 +1467,177 @@ mod tests {
         assert_eq!(1, m1.fetch());
     }
 
+    #[test]
+    fn test_shared_max_metric() {
+        let metric = SharedMaxMetric::default();
+        metric.record(3);
+        metric.record(7);
+        metric.record(5);
+        assert_eq!(metric.fetch(), 7);
+
+        // The metric is reset once flushed.
+        assert_eq!(serde_json::to_string(&metric).unwrap(), "7");
+        assert_eq!(metric.fetch(), 0);
+        metric.record(2);
+        assert_eq!(serde_json::to_string(&metric).unwrap(), "2");
+    }
+
     #[test]
     fn test_serialize() {
         let s = serde_json::to_string(&FirecrackerMetrics::default());
         assert!(s.is_ok());
     }
 
+    #[test]
+    fn test_vsock_port_metrics() {
+        let ports = VsockPortMetricsMap::default();
+        assert_eq!(serde_json::to_string(&ports).unwrap(), "{}");
+
+        ports.get(52).unwrap().rx_packets_count.inc();
+        ports.get(52).unwrap().active_conns.store(2);
+        assert_eq!(ports.get(52).unwrap().rx_packets_count.count(), 1);
+        let s = serde_json::to_string(&ports).unwrap();
+        assert!(s.starts_with("{\"52\":{\"rx_bytes_count\":0,"));
+        assert!(s.contains("\"rx_packets_count\":1,"));
+        assert!(s.contains("\"active_conns\":2,"));
+
+        // The number of tracked ports is capped.
+        for port in 0..VsockPortMetricsMap::MAX_PORTS as u32 {
+            assert!(ports.get(port).is_some());
+        }
+        assert!(ports.get(VsockPortMetricsMap::MAX_PORTS as u32).is_none());
+        assert!(ports.get(52).is_some());
+        assert_eq!(ports.all().len(), VsockPortMetricsMap::MAX_PORTS);
+        assert_eq!(ports.all()[0].0, 0);
+    }
+
+    #[test]
+    fn test_device_metrics() {
+        let drives = DeviceMetricsMap::<BlockDeviceMetrics>::default();
+        assert!(drives.is_empty());
+        assert_eq!(serde_json::to_string(&drives).unwrap(), "{}");
+
+        // The metrics of the devices are accounted in the totals as well.
+        let total = Box::leak(Box::new(BlockDeviceMetrics::default()));
+        let rootfs = DeviceMetrics::new(&*total, drives.get("rootfs"));
+        let scratch = DeviceMetrics::new(&*total, drives.get("scratch"));
+        rootfs.update(|metrics| metrics.read_count.inc());
+        rootfs.clone().update(|metrics| metrics.read_count.inc());
+        scratch.update(|metrics| metrics.write_bytes.add(512));
+        assert_eq!(rootfs.device().read_count.count(), 2);
+        assert_eq!(drives.get("rootfs").read_count.count(), 2);
+        assert_eq!(scratch.device().read_count.count(), 0);
+        assert_eq!(total.read_count.count(), 2);
+        assert_eq!(total.write_bytes.count(), 512);
+
+        assert_eq!(
+            drives
+                .all()
+                .iter()
+                .map(|(id, _)| id.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["rootfs", "scratch"]
+        );
+        let s = serde_json::to_string(&drives).unwrap();
+        assert!(s.starts_with("{\"rootfs\":{\"activate_fails\":0,"));
+        assert!(s.contains("\"read_count\":2,"));
+        assert!(s.contains("\"scratch\":{"));
+        assert!(s.contains("\"write_bytes\":512,"));
+        // The devices have no map of devices of their own.
+        assert!(!s.contains("drives"));
+    }
+
+    #[test]
+    fn test_vcpu_exit_metrics() {
+        let vcpus = VcpuExitMetricsMap::default();
+        assert_eq!(serde_json::to_string(&vcpus).unwrap(), "{}");
+
+        vcpus.get(1).exit_io.inc();
+        vcpus.get(1).guest_time_us.add(10);
+        vcpus.get(0).exit_hlt.inc();
+        assert_eq!(vcpus.get(1).exit_io.count(), 1);
+        let s = serde_json::to_string(&vcpus).unwrap();
+        assert!(s.starts_with("{\"0\":{\"exit_io\":0,"));
+        assert!(s.contains("\"1\":{\"exit_io\":1,"));
+        assert!(s.contains("\"guest_time_us\":10,"));
+
+        let all = vcpus.all();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].0, 0);
+        assert_eq!(all[0].1.exit_hlt.count(), 1);
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = Metrics::new(FirecrackerMetrics::default());
+        metrics.block.read_count.add(5);
+        metrics.latencies_us.pause_vm.store(3);
+        metrics.vsock.ports.get(52).unwrap().rx_packets_count.inc();
+        metrics.vcpu.vcpus.get(1).exit_mmio.add(2);
+        metrics.net.ifaces.get("eth0").rx_bytes_count.add(7);
+        metrics
+            .balloon
+            .balloons
+            .get("balloon")
+            .free_memory
+            .store(4096);
+
+        let output = metrics.render_prometheus().unwrap();
+        assert!(output.contains("# TYPE firecracker_device_read_count counter\n"));
+        assert!(output.contains("firecracker_device_read_count{device=\"block\"} 5\n"));
+        assert!(output.contains("# TYPE firecracker_latencies_us_pause_vm gauge\n"));
+        assert!(output.contains("firecracker_latencies_us_pause_vm 3\n"));
+        assert!(output.contains(
+            "firecracker_device_ports_rx_packets_count{device=\"vsock\",port=\"52\"} 1\n"
+        ));
+        assert!(output.contains("firecracker_vcpu_vcpus_exit_mmio{vcpu=\"1\"} 2\n"));
+        assert!(output.contains(
+            "firecracker_device_ifaces_rx_bytes_count{device=\"net\",iface=\"eth0\"} 7\n"
+        ));
+        assert!(output.contains(
+            "firecracker_device_balloons_free_memory{device=\"balloon\",balloon=\"balloon\"} 4096\n"
+        ));
+        assert!(!output.contains("utc_timestamp_ms"));
+
+        // Rendering doesn't reset the counters, which are flushed as usual.
+        let flushed = serde_json::to_value(&metrics.app_metrics).unwrap();
+        assert_eq!(flushed["block"]["read_count"], 5);
+        let flushed = serde_json::to_value(&metrics.app_metrics).unwrap();
+        assert_eq!(flushed["block"]["read_count"], 0);
+        // While Prometheus still gets the counters since the process started.
+        let output = metrics.render_prometheus().unwrap();
+        assert!(output.contains("firecracker_device_read_count{device=\"block\"} 5\n"));
+    }
+
+    #[test]
+    fn test_push_to_sinks() {
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+        let metrics = Metrics::new(FirecrackerMetrics::default());
+        metrics.add_sink(
+            MetricsSink::new(
+                crate::sinks::SinkFormat::Statsd,
+                receiver.local_addr().unwrap(),
+            )
+            .unwrap(),
+        );
+        metrics.block.read_count.add(5);
+
+        // The sinks are pushed to, even though the metrics buffer is not initialized.
+        assert!(!metrics.write().unwrap());
+        let mut pushed = String::new();
+        let mut buf = [0u8; 2048];
+        while !pushed.contains("firecracker.block.read_count:5|c") {
+            let len = receiver.recv(&mut buf).unwrap();
+            pushed.push_str(std::str::from_utf8(&buf[..len]).unwrap());
+        }
+        // The counters are not reset for the metrics buffer.
+        let flushed = serde_json::to_value(&metrics.app_metrics).unwrap();
+        assert_eq!(flushed["block"]["read_count"], 5);
+    }
+
     #[test]
     fn test_error_messages() {
         assert_eq!(
@@ -834,6 +1658,13 @@ mod tests {
             ),
             "Failed to write metrics. Error: write"
         );
+        assert_eq!(
+            format!(
+                "{}",
+                MetricsError::Sink(std::io::Error::new(ErrorKind::ConnectionRefused, "send"))
+            ),
+            "Failed to push metrics. Error: send"
+        );
         assert_eq!(
             format!(
                 "{}",
@@ -0,0 +1,643 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Renders the metrics in the Prometheus text exposition format.
+//!
+//! Each metric is named after its path in the JSON metrics, joined with underscores and prefixed
+//! with `firecracker_`. The metrics of the device types share their names, e.g.
+//! `firecracker_device_activate_fails`, and are told apart by a `device` label. The entries of a
+//! metrics map, such as the per-port vsock metrics, are told apart by a label named after the map,
+//! e.g. `port` for `ports`.
+//!
+//! The incremental metrics are rendered as counters holding their value since the process
+//! started, and the store metrics as gauges.
+//!
+//! The same samples, along with their path in the metrics tree, are pushed to the metrics sinks.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::ser::{self, Impossible, SerializeMap, SerializeStruct};
+use serde::{Serialize, Serializer};
+
+/// The newtype struct name the incremental metrics are serialized with while rendering.
+pub(crate) const INC_METRIC_NAME: &str = "SharedIncMetric";
+
+const METRIC_PREFIX: &str = "firecracker";
+const DEVICE_METRIC_PREFIX: &str = "firecracker_device";
+
+// The top-level metric groups accounting for the devices.
+const DEVICE_GROUPS: &[&str] = &[
+    "balloon",
+    "block",
+    "console",
+    "entropy",
+    "i8042",
+    "mem",
+    "net",
+    "null_device",
+    "pmem",
+    "rtc",
+    "shared_fs",
+    "tpm",
+    "uart",
+    "vsock",
+];
+
+// Prometheus timestamps the samples on its own.
+const SKIPPED_GROUPS: &[&str] = &["utc_timestamp_ms"];
+
+/// Errors triggered while rendering the metrics.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cannot render the metrics for Prometheus: {}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+impl fmt::Display for MetricType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetricType::Counter => write!(f, "counter"),
+            MetricType::Gauge => write!(f, "gauge"),
+        }
+    }
+}
+
+// All the samples of a metric, along with their labels.
+struct Family {
+    metric_type: MetricType,
+    samples: Vec<(Vec<(String, String)>, String)>,
+}
+
+/// A value of the metrics tree, named and labeled as it is rendered.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Sample {
+    /// The fields and map keys leading to the value, e.g. `vsock`, `ports`, `52`, `rx_bytes`.
+    pub path: Vec<String>,
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub is_counter: bool,
+    pub value: String,
+}
+
+#[derive(Default)]
+struct Families {
+    families: BTreeMap<String, Family>,
+    // Every sample, in the order of the metrics tree.
+    samples: Vec<Sample>,
+}
+
+impl Families {
+    fn add(&mut self, context: &Context, value: String) -> Result<()> {
+        let family = self.families.entry(context.name.clone()).or_insert(Family {
+            metric_type: context.metric_type,
+            samples: Vec::new(),
+        });
+        if family.metric_type != context.metric_type {
+            return Err(Error(format!(
+                "{} is both a counter and a gauge",
+                context.name
+            )));
+        }
+        family.samples.push((context.labels.clone(), value.clone()));
+        self.samples.push(Sample {
+            path: context.path.clone(),
+            name: context.name.clone(),
+            labels: context.labels.clone(),
+            is_counter: context.metric_type == MetricType::Counter,
+            value,
+        });
+        Ok(())
+    }
+
+    fn render(&self) -> String {
+        let mut output = String::new();
+        for (name, family) in self.families.iter() {
+            output.push_str(&format!("# TYPE {} {}\n", name, family.metric_type));
+            for (labels, value) in family.samples.iter() {
+                output.push_str(name);
+                if !labels.is_empty() {
+                    let labels: Vec<String> = labels
+                        .iter()
+                        .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                        .collect();
+                    output.push_str(&format!("{{{}}}", labels.join(",")));
+                }
+                output.push_str(&format!(" {}\n", value));
+            }
+        }
+        output
+    }
+}
+
+// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Where a value sits in the metrics tree.
+#[derive(Clone)]
+struct Context {
+    name: String,
+    // The last field name, which names the label of the map entries.
+    field: String,
+    labels: Vec<(String, String)>,
+    metric_type: MetricType,
+    path: Vec<String>,
+}
+
+impl Context {
+    fn root() -> Self {
+        Context {
+            name: String::new(),
+            field: String::new(),
+            labels: Vec::new(),
+            metric_type: MetricType::Gauge,
+            path: Vec::new(),
+        }
+    }
+
+    fn is_root(&self) -> bool {
+        self.name.is_empty()
+    }
+
+    fn field(&self, field: &str) -> Self {
+        let mut context = self.clone();
+        if self.is_root() && DEVICE_GROUPS.contains(&field) {
+            context.name = DEVICE_METRIC_PREFIX.to_string();
+            context
+                .labels
+                .push(("device".to_string(), field.to_string()));
+        } else if self.is_root() {
+            context.name = format!("{}_{}", METRIC_PREFIX, field);
+        } else {
+            context.name = format!("{}_{}", self.name, field);
+        }
+        context.field = field.to_string();
+        context.path.push(field.to_string());
+        context
+    }
+
+    fn entry(&self, key: String) -> Self {
+        let mut context = self.clone();
+        let label = self.field.trim_end_matches('s').to_string();
+        context.path.push(key.clone());
+        context.labels.push((label, key));
+        context
+    }
+}
+
+// Collects the samples of `metrics`, the serializable tree of all the metrics.
+fn collect<T: Serialize>(metrics: &T) -> Result<Families> {
+    let mut families = Families::default();
+    metrics.serialize(MetricSerializer {
+        families: &mut families,
+        context: Context::root(),
+    })?;
+    Ok(families)
+}
+
+/// Renders `metrics`, the serializable tree of all the metrics.
+pub(crate) fn render<T: Serialize>(metrics: &T) -> Result<String> {
+    collect(metrics).map(|families| families.render())
+}
+
+/// Returns the samples of `metrics`, the serializable tree of all the metrics.
+pub(crate) fn samples<T: Serialize>(metrics: &T) -> Result<Vec<Sample>> {
+    collect(metrics).map(|families| families.samples)
+}
+
+struct MetricSerializer<'a> {
+    families: &'a mut Families,
+    context: Context,
+}
+
+impl<'a> MetricSerializer<'a> {
+    fn sample<V: fmt::Display>(self, value: V) -> Result<()> {
+        if self.context.is_root() {
+            return Err(Error("a metric must be named".to_string()));
+        }
+        self.families.add(&self.context, value.to_string())
+    }
+
+    fn unsupported(self, kind: &str) -> Result<()> {
+        Err(Error(format!(
+            "unsupported {} value in {}",
+            kind, self.context.name
+        )))
+    }
+}
+
+impl<'a> Serializer for MetricSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = MapSerializer<'a>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.sample(u8::from(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.sample(v)
+    }
+
+    fn serialize_char(self, _v: char) -> Result<()> {
+        self.unsupported("char")
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<()> {
+        self.unsupported("string")
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
+        self.unsupported("bytes")
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        self.unsupported("enum")
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        if name == INC_METRIC_NAME {
+            self.context.metric_type = MetricType::Counter;
+        }
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        self.unsupported("enum")
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(Error(format!(
+            "unsupported sequence in {}",
+            self.context.name
+        )))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(Error(format!("unsupported tuple in {}", self.context.name)))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(Error(format!("unsupported tuple in {}", self.context.name)))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(Error(format!("unsupported enum in {}", self.context.name)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(MapSerializer {
+            families: self.families,
+            context: self.context,
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(Error(format!("unsupported enum in {}", self.context.name)))
+    }
+}
+
+impl<'a> SerializeStruct for MetricSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        if self.context.is_root() && SKIPPED_GROUPS.contains(&key) {
+            return Ok(());
+        }
+        value.serialize(MetricSerializer {
+            families: self.families,
+            context: self.context.field(key),
+        })
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct MapSerializer<'a> {
+    families: &'a mut Families,
+    context: Context,
+    key: Option<String>,
+}
+
+impl<'a> SerializeMap for MapSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        let key = serde_json::to_value(key).map_err(|e| Error(e.to_string()))?;
+        self.key = Some(match key {
+            serde_json::Value::String(key) => key,
+            key => key.to_string(),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error("map value without a key".to_string()))?;
+        value.serialize(MetricSerializer {
+            families: self.families,
+            context: self.context.entry(key),
+        })
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    // Marks the incremental metrics as counters, the way `Metrics::render_prometheus` does.
+    struct Counter(u64);
+
+    impl Serialize for Counter {
+        fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            serializer.serialize_newtype_struct(INC_METRIC_NAME, &self.0)
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Port {
+        rx_bytes_count: Counter,
+    }
+
+    #[derive(Serialize)]
+    struct Vsock {
+        activate_fails: Counter,
+        ports: BTreeMap<u32, Port>,
+    }
+
+    #[derive(Serialize)]
+    struct Vmm {
+        uptime_us: u64,
+    }
+
+    #[derive(Serialize)]
+    struct Metrics {
+        utc_timestamp_ms: i64,
+        vmm: Vmm,
+        block: Port,
+        vsock: Vsock,
+    }
+
+    #[test]
+    fn test_render() {
+        let mut ports = BTreeMap::new();
+        ports.insert(
+            52,
+            Port {
+                rx_bytes_count: Counter(7),
+            },
+        );
+        ports.insert(
+            1024,
+            Port {
+                rx_bytes_count: Counter(8),
+            },
+        );
+        let metrics = Metrics {
+            utc_timestamp_ms: 1000,
+            vmm: Vmm { uptime_us: 12 },
+            block: Port {
+                rx_bytes_count: Counter(3),
+            },
+            vsock: Vsock {
+                activate_fails: Counter(1),
+                ports,
+            },
+        };
+
+        assert_eq!(
+            render(&metrics).unwrap(),
+            "# TYPE firecracker_device_activate_fails counter\n\
+             firecracker_device_activate_fails{device=\"vsock\"} 1\n\
+             # TYPE firecracker_device_ports_rx_bytes_count counter\n\
+             firecracker_device_ports_rx_bytes_count{device=\"vsock\",port=\"52\"} 7\n\
+             firecracker_device_ports_rx_bytes_count{device=\"vsock\",port=\"1024\"} 8\n\
+             # TYPE firecracker_device_rx_bytes_count counter\n\
+             firecracker_device_rx_bytes_count{device=\"block\"} 3\n\
+             # TYPE firecracker_vmm_uptime_us gauge\n\
+             firecracker_vmm_uptime_us 12\n"
+        );
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut ports = BTreeMap::new();
+        ports.insert(
+            52,
+            Port {
+                rx_bytes_count: Counter(7),
+            },
+        );
+        let metrics = Metrics {
+            utc_timestamp_ms: 1000,
+            vmm: Vmm { uptime_us: 12 },
+            block: Port {
+                rx_bytes_count: Counter(3),
+            },
+            vsock: Vsock {
+                activate_fails: Counter(1),
+                ports,
+            },
+        };
+
+        let samples = samples(&metrics).unwrap();
+        assert_eq!(samples.len(), 4);
+        assert_eq!(
+            samples[0],
+            Sample {
+                path: vec!["vmm".to_string(), "uptime_us".to_string()],
+                name: "firecracker_vmm_uptime_us".to_string(),
+                labels: vec![],
+                is_counter: false,
+                value: "12".to_string(),
+            }
+        );
+        assert_eq!(
+            samples[3],
+            Sample {
+                path: vec![
+                    "vsock".to_string(),
+                    "ports".to_string(),
+                    "52".to_string(),
+                    "rx_bytes_count".to_string()
+                ],
+                name: "firecracker_device_ports_rx_bytes_count".to_string(),
+                labels: vec![
+                    ("device".to_string(), "vsock".to_string()),
+                    ("port".to_string(), "52".to_string())
+                ],
+                is_counter: true,
+                value: "7".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_render_errors() {
+        // A metric must be named.
+        assert!(render(&1u64).is_err());
+        // Only numbers can be rendered.
+        assert!(render(&Named { name: "vmm" }).is_err());
+        // The type of a metric must be the same for all the devices.
+        assert!(render(&Conflict {
+            block: Port {
+                rx_bytes_count: Counter(1)
+            },
+            net: GaugePort { rx_bytes_count: 1 },
+        })
+        .is_err());
+
+        #[derive(Serialize)]
+        struct Named {
+            name: &'static str,
+        }
+
+        #[derive(Serialize)]
+        struct GaugePort {
+            rx_bytes_count: u64,
+        }
+
+        #[derive(Serialize)]
+        struct Conflict {
+            block: Port,
+            net: GaugePort,
+        }
+
+        let err = Error("test".to_string());
+        let _ = format!("{}{:?}", err, err);
+    }
+}
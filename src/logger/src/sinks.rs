@@ -0,0 +1,254 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pushes the metrics to remote collectors over UDP, each time the metrics are flushed.
+//!
+//! The `statsd` sinks send the counters as their increments since the previous flush, and the
+//! gauges as they are, named after their path in the JSON metrics, e.g.
+//! `firecracker.vsock.ports.52.rx_bytes_count:7|c`.
+//!
+//! The `line_protocol` sinks send the samples in the InfluxDB line protocol, named and labeled as
+//! for Prometheus, with the counters holding their value since the process started, e.g.
+//! `firecracker_device_rx_bytes_count,device=block value=3i 1600000000000000000`.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use crate::prometheus::Sample;
+
+// The largest datagram sent, which fits in the MTU of most networks.
+const MAX_DATAGRAM_LEN: usize = 1432;
+
+const STATSD_PREFIX: &str = "firecracker";
+
+/// The formats in which the metrics are pushed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SinkFormat {
+    /// The InfluxDB line protocol.
+    LineProtocol,
+    /// The statsd protocol.
+    Statsd,
+}
+
+/// A remote collector the metrics are pushed to.
+pub struct MetricsSink {
+    format: SinkFormat,
+    socket: UdpSocket,
+    // The totals of the counters at the previous flush, to send their increments to statsd.
+    counters: HashMap<Vec<String>, u64>,
+}
+
+impl MetricsSink {
+    /// Creates a sink pushing the metrics in `format` to the collector at `addr`.
+    pub fn new(format: SinkFormat, addr: SocketAddr) -> io::Result<Self> {
+        let local_addr = match addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local_addr)?;
+        socket.connect(addr)?;
+        // A collector which is down must not stall the thread flushing the metrics.
+        socket.set_nonblocking(true)?;
+        Ok(MetricsSink {
+            format,
+            socket,
+            counters: HashMap::new(),
+        })
+    }
+
+    // Formats a sample as a statsd line, unless a counter did not change since the last flush.
+    fn statsd_line(&mut self, sample: &Sample) -> Option<String> {
+        let name = format!("{}.{}", STATSD_PREFIX, sample.path.join("."));
+        if !sample.is_counter {
+            return Some(format!("{}:{}|g", name, sample.value));
+        }
+        let total = sample.value.parse::<u64>().ok()?;
+        let previous = self
+            .counters
+            .insert(sample.path.clone(), total)
+            .unwrap_or(0);
+        match total.saturating_sub(previous) {
+            0 => None,
+            increment => Some(format!("{}:{}|c", name, increment)),
+        }
+    }
+
+    /// Pushes the samples of the metrics, flushed at `timestamp_ns` since the Unix epoch.
+    pub(crate) fn push(&mut self, samples: &[Sample], timestamp_ns: u64) -> io::Result<()> {
+        let mut datagram = String::new();
+        for sample in samples.iter() {
+            let line = match self.format {
+                SinkFormat::LineProtocol => Some(line_protocol_line(sample, timestamp_ns)),
+                SinkFormat::Statsd => self.statsd_line(sample),
+            };
+            let line = match line {
+                Some(line) => line,
+                None => continue,
+            };
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM_LEN {
+                self.socket.send(datagram.as_bytes())?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            self.socket.send(datagram.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+// Escapes the commas, spaces and equal signs of a tag in the line protocol.
+fn escape_tag(tag: &str) -> String {
+    tag.replace(',', "\\,")
+        .replace(' ', "\\ ")
+        .replace('=', "\\=")
+}
+
+// Formats a sample as a line of the InfluxDB line protocol.
+fn line_protocol_line(sample: &Sample, timestamp_ns: u64) -> String {
+    let mut line = sample.name.clone();
+    for (label, value) in sample.labels.iter() {
+        line.push_str(&format!(",{}={}", escape_tag(label), escape_tag(value)));
+    }
+    // The counters are integers, and the gauges floats, as they may not hold integers.
+    let suffix = if sample.is_counter { "i" } else { "" };
+    line.push_str(&format!(
+        " value={}{} {}",
+        sample.value, suffix, timestamp_ns
+    ));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn sample(path: &[&str], name: &str, is_counter: bool, value: &str) -> Sample {
+        Sample {
+            path: path.iter().map(|field| field.to_string()).collect(),
+            name: name.to_string(),
+            labels: vec![("device".to_string(), path[0].to_string())],
+            is_counter,
+            value: value.to_string(),
+        }
+    }
+
+    fn receiver() -> (UdpSocket, SocketAddr) {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = receiver.local_addr().unwrap();
+        (receiver, addr)
+    }
+
+    fn recv(receiver: &UdpSocket) -> String {
+        let mut buf = [0u8; MAX_DATAGRAM_LEN];
+        let len = receiver.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_statsd() {
+        let (receiver, addr) = receiver();
+        let mut sink = MetricsSink::new(SinkFormat::Statsd, addr).unwrap();
+
+        let samples = vec![
+            sample(
+                &["block", "read_count"],
+                "firecracker_device_read_count",
+                true,
+                "5",
+            ),
+            sample(
+                &["block", "uptime_us"],
+                "firecracker_device_uptime_us",
+                false,
+                "12",
+            ),
+        ];
+        sink.push(&samples, 0).unwrap();
+        assert_eq!(
+            recv(&receiver),
+            "firecracker.block.read_count:5|c\nfirecracker.block.uptime_us:12|g"
+        );
+
+        // Only the increments of the counters are sent.
+        let samples = vec![
+            sample(
+                &["block", "read_count"],
+                "firecracker_device_read_count",
+                true,
+                "7",
+            ),
+            sample(
+                &["block", "write_count"],
+                "firecracker_device_write_count",
+                true,
+                "0",
+            ),
+        ];
+        sink.push(&samples, 0).unwrap();
+        assert_eq!(recv(&receiver), "firecracker.block.read_count:2|c");
+    }
+
+    #[test]
+    fn test_line_protocol() {
+        let (receiver, addr) = receiver();
+        let mut sink = MetricsSink::new(SinkFormat::LineProtocol, addr).unwrap();
+
+        let samples = vec![
+            sample(
+                &["block", "read_count"],
+                "firecracker_device_read_count",
+                true,
+                "5",
+            ),
+            sample(
+                &["block", "uptime_us"],
+                "firecracker_device_uptime_us",
+                false,
+                "12",
+            ),
+        ];
+        sink.push(&samples, 1_600_000_000_000_000_000).unwrap();
+        assert_eq!(
+            recv(&receiver),
+            "firecracker_device_read_count,device=block value=5i 1600000000000000000\n\
+             firecracker_device_uptime_us,device=block value=12 1600000000000000000"
+        );
+    }
+
+    #[test]
+    fn test_datagram_split() {
+        let (receiver, addr) = receiver();
+        let mut sink = MetricsSink::new(SinkFormat::Statsd, addr).unwrap();
+
+        let samples: Vec<Sample> = (0..100)
+            .map(|i| {
+                let field = format!("field_{}", i);
+                sample(&["block", &field], "firecracker_device_field", false, "1")
+            })
+            .collect();
+        sink.push(&samples, 0).unwrap();
+        let mut lines = 0;
+        while lines < samples.len() {
+            let datagram = recv(&receiver);
+            assert!(datagram.len() <= MAX_DATAGRAM_LEN);
+            lines += datagram.lines().count();
+        }
+        assert_eq!(lines, samples.len());
+    }
+
+    #[test]
+    fn test_escape_tag() {
+        assert_eq!(escape_tag("a b,c=d"), "a\\ b\\,c\\=d");
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Structured tracing of the operations carried out by Firecracker.
+//!
+//! A `Span` measures an operation from its creation to its drop, and is written to the log as a
+//! JSON line when the log level is `Info` or more verbose, e.g.
+//! ```text
+//! {"timestamp":"2020-10-15T10:20:30.123456789","instance_id":"vm0","level":"INFO",
+//!  "span":"load_kernel","span_id":7,"parent_id":5,"request_id":"fc-3","duration_us":1520,
+//!  "fields":{}}
+//! ```
+//! The spans opened while another one is live on the same thread are its children. The spans
+//! also carry the ID of the API request the thread is serving, so the operations carried out by
+//! the VMM thread on behalf of a request can be attributed to it.
+//!
+//! The spans of the phases of the boot and of the snapshots are timed: they also store their
+//! duration in a metric, whatever the log level.
+
+use std::cell::{Cell, RefCell};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::{max_level, Level};
+use serde_json::{json, Map, Value};
+use utils::time::{get_time_us, ClockType, LocalTime};
+
+use crate::logger::LOGGER;
+use crate::metrics::{SharedStoreMetric, StoreMetric};
+
+static NEXT_SPAN_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    // The innermost live span of the thread.
+    static CURRENT_SPAN: Cell<Option<u64>> = Cell::new(None);
+    // The ID of the API request the thread is serving.
+    static REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Generates a new ID for an API request which doesn't carry one.
+pub fn next_request_id() -> String {
+    format!("fc-{}", NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Sets the ID of the API request the calling thread is serving, and returns the previous one.
+pub fn set_request_id(request_id: Option<String>) -> Option<String> {
+    REQUEST_ID.with(|current| current.replace(request_id))
+}
+
+/// Returns the ID of the API request the calling thread is serving.
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.with(|current| current.borrow().clone())
+}
+
+/// An operation which is traced, from its creation to its drop.
+pub struct Span {
+    name: &'static str,
+    id: u64,
+    parent_id: Option<u64>,
+    request_id: Option<String>,
+    timestamp: LocalTime,
+    start_us: u64,
+    fields: Map<String, Value>,
+    metric: Option<&'static SharedStoreMetric>,
+}
+
+impl Span {
+    /// Starts tracing the operation `name`, as a child of the innermost live span of the thread.
+    pub fn new(name: &'static str) -> Self {
+        let id = NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed);
+        Span {
+            name,
+            id,
+            parent_id: CURRENT_SPAN.with(|current| current.replace(Some(id))),
+            request_id: request_id(),
+            timestamp: LocalTime::now(),
+            start_us: get_time_us(ClockType::Monotonic),
+            fields: Map::new(),
+            metric: None,
+        }
+    }
+
+    /// Starts tracing the operation `name`, whose duration is stored in `metric` in microseconds.
+    pub fn timed(name: &'static str, metric: &'static SharedStoreMetric) -> Self {
+        let mut span = Span::new(name);
+        span.metric = Some(metric);
+        span
+    }
+
+    /// Records the `value` of the field `key` of the operation.
+    pub fn record<T: Display>(&mut self, key: &str, value: T) -> &mut Self {
+        self.fields
+            .insert(key.to_string(), Value::String(value.to_string()));
+        self
+    }
+
+    /// Records the error the operation failed with, if it did.
+    pub fn record_result<T, E: Display>(&mut self, result: &Result<T, E>) -> &mut Self {
+        if let Err(err) = result {
+            self.record("error", err);
+        }
+        self
+    }
+
+    // Describes the span as a JSON line.
+    fn to_json(&self, instance_id: &str, duration_us: u64) -> String {
+        json!({
+            "timestamp": self.timestamp.to_string(),
+            "instance_id": instance_id,
+            "level": Level::Info.to_string(),
+            "span": self.name,
+            "span_id": self.id,
+            "parent_id": self.parent_id,
+            "request_id": self.request_id,
+            "duration_us": duration_us,
+            "fields": self.fields,
+        })
+        .to_string()
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        CURRENT_SPAN.with(|current| current.set(self.parent_id));
+        let duration_us = get_time_us(ClockType::Monotonic).saturating_sub(self.start_us);
+        if let Some(metric) = self.metric {
+            metric.store(duration_us as usize);
+        }
+        if Level::Info <= max_level() {
+            LOGGER.write_log(
+                self.to_json(&LOGGER.instance_id(), duration_us),
+                Level::Info,
+            );
+        }
+    }
+}
+
+/// Runs `f` within the span `name`.
+pub fn in_span<T, F: FnOnce() -> T>(name: &'static str, f: F) -> T {
+    let _span = Span::new(name);
+    f()
+}
+
+/// Runs `f` within the span `name`, and stores its duration in `metric`.
+pub fn in_timed_span<T, F: FnOnce() -> T>(
+    name: &'static str,
+    metric: &'static SharedStoreMetric,
+    f: F,
+) -> T {
+    let _span = Span::timed(name, metric);
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id() {
+        let first = next_request_id();
+        assert!(first.starts_with("fc-"));
+        assert_ne!(first, next_request_id());
+
+        assert_eq!(set_request_id(Some("req-1".to_string())), None);
+        assert_eq!(request_id(), Some("req-1".to_string()));
+        let span = Span::new("test");
+        assert_eq!(span.request_id, Some("req-1".to_string()));
+        assert_eq!(set_request_id(None), Some("req-1".to_string()));
+        assert_eq!(request_id(), None);
+    }
+
+    #[test]
+    fn test_span_nesting() {
+        let outer = Span::new("outer");
+        assert_eq!(outer.parent_id, None);
+        {
+            let inner = Span::new("inner");
+            assert_eq!(inner.parent_id, Some(outer.id));
+            let innermost = in_span("innermost", || CURRENT_SPAN.with(Cell::get)).unwrap();
+            assert!(innermost > inner.id);
+            assert_eq!(CURRENT_SPAN.with(Cell::get), Some(inner.id));
+        }
+        // The outer span is the innermost live span again.
+        assert_eq!(CURRENT_SPAN.with(Cell::get), Some(outer.id));
+        drop(outer);
+        assert_eq!(CURRENT_SPAN.with(Cell::get), None);
+    }
+
+    #[test]
+    fn test_timed_span() {
+        let metric: &'static SharedStoreMetric = Box::leak(Box::new(SharedStoreMetric::default()));
+        let span = Span::timed("snapshot_memory", metric);
+        assert_eq!(metric.fetch(), 0);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        drop(span);
+        assert!(metric.fetch() >= 2000);
+
+        let start_us = get_time_us(ClockType::Monotonic);
+        assert_eq!(in_timed_span("snapshot_state", metric, || 7), 7);
+        assert!(metric.fetch() <= (get_time_us(ClockType::Monotonic) - start_us) as usize);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut span = Span::new("load_kernel");
+        span.record("path", "/vmlinux")
+            .record_result::<(), _>(&Err("Cannot open the kernel"));
+        span.record_result::<(), String>(&Ok(()));
+        let value: Value = serde_json::from_str(&span.to_json("vm0", 42)).unwrap();
+        assert_eq!(value["instance_id"], "vm0");
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["span"], "load_kernel");
+        assert_eq!(value["span_id"], span.id);
+        assert_eq!(value["parent_id"], Value::Null);
+        assert_eq!(value["request_id"], Value::Null);
+        assert_eq!(value["duration_us"], 42);
+        assert_eq!(
+            value["fields"],
+            json!({"path": "/vmlinux", "error": "Cannot open the kernel"})
+        );
+        assert!(value["timestamp"].is_string());
+    }
+}
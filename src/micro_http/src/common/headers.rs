@@ -1,6 +1,7 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::HashMap;
 use std::result::Result;
 
 use crate::HttpHeaderError;
@@ -69,8 +70,9 @@ impl Header {
 /// Wrapper over the list of headers associated with a Request that we need
 /// in order to parse the request correctly and be able to respond to it.
 ///
-/// The only `Content-Type`s supported are `text/plain` and `application/json`, which are both
-/// in plain text actually and don't influence our parsing process.
+/// The only `Content-Type`s supported are `text/plain`, `application/json` and
+/// `application/json-patch+json`, which are all in plain text actually and don't influence our
+/// parsing process.
 ///
 /// All the other possible header fields are not necessary in order to serve this connection
 /// and, thus, are not of interest to us. However, we still look for header fields that might
@@ -94,6 +96,10 @@ pub struct Headers {
     /// `Accept` header might be used by HTTP clients to enforce server responses with content
     /// formatted in a specific way.
     accept: MediaType,
+    /// `Content-Type` header tells us how the body of the request is formatted.
+    content_type: MediaType,
+    /// Header fields which are not interpreted by the parser, keyed by their lowercase name.
+    custom_entries: HashMap<String, String>,
 }
 
 impl Default for Headers {
@@ -106,6 +112,8 @@ impl Default for Headers {
             // The default `Accept` media type is plain text. This is inclusive enough
             // for structured and unstructured text.
             accept: MediaType::PlainText,
+            content_type: MediaType::default(),
+            custom_entries: HashMap::default(),
         }
     }
 }
@@ -156,7 +164,10 @@ impl Headers {
                         },
                         Header::ContentType => {
                             match MediaType::try_from(entry[1].trim().as_bytes()) {
-                                Ok(_) => Ok(()),
+                                Ok(content_type) => {
+                                    self.content_type = content_type;
+                                    Ok(())
+                                }
                                 Err(_) => Err(RequestError::HeaderError(
                                     HttpHeaderError::UnsupportedValue(
                                         entry[0].to_string(),
@@ -206,12 +217,12 @@ impl Headers {
                         Header::AcceptEncoding => Encoding::try_from(entry[1].trim().as_bytes()),
                     }
                 } else {
-                    Err(RequestError::HeaderError(
-                        HttpHeaderError::UnsupportedValue(
-                            entry[0].to_string(),
-                            entry[1].to_string(),
-                        ),
-                    ))
+                    // Header names are case insensitive, so we store them in lowercase.
+                    self.custom_entries.insert(
+                        entry[0].trim().to_ascii_lowercase(),
+                        entry[1].trim().to_string(),
+                    );
+                    Ok(())
                 }
             }
             Err(utf8_err) => Err(RequestError::HeaderError(
@@ -242,6 +253,29 @@ impl Headers {
         self.accept
     }
 
+    /// Returns the `Content-Type` header `MediaType`.
+    pub fn content_type(&self) -> MediaType {
+        self.content_type
+    }
+
+    /// Returns the value of the header field named `name`, if the field is not one of
+    /// the [`Header`](enum.Header.html)s interpreted by the parser. The lookup is case
+    /// insensitive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use micro_http::Headers;
+    ///
+    /// let request_headers = Headers::try_from(b"X-Custom-Header: 42\r\n\r\n").unwrap();
+    /// assert_eq!(request_headers.custom_entry("x-custom-header"), Some("42"));
+    /// ```
+    pub fn custom_entry(&self, name: &str) -> Option<&str> {
+        self.custom_entries
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
     /// Parses a byte slice into a Headers structure for a HTTP request.
     ///
     /// The byte slice is expected to have the following format: </br>
@@ -355,6 +389,8 @@ pub enum MediaType {
     PlainText,
     /// Media Type: "application/json".
     ApplicationJson,
+    /// Media Type: "application/json-patch+json".
+    ApplicationJsonPatch,
 }
 
 impl Default for MediaType {
@@ -389,6 +425,7 @@ impl MediaType {
         match utf8_slice.as_str().trim() {
             "text/plain" => Ok(Self::PlainText),
             "application/json" => Ok(Self::ApplicationJson),
+            "application/json-patch+json" => Ok(Self::ApplicationJsonPatch),
             _ => Err(RequestError::InvalidRequest),
         }
     }
@@ -407,6 +444,7 @@ impl MediaType {
         match self {
             Self::PlainText => "text/plain",
             Self::ApplicationJson => "application/json",
+            Self::ApplicationJsonPatch => "application/json-patch+json",
         }
     }
 }
@@ -422,6 +460,7 @@ mod tests {
                 expect,
                 chunked,
                 accept: MediaType::PlainText,
+                custom_entries: HashMap::default(),
             }
         }
     }
@@ -446,6 +485,11 @@ mod tests {
             MediaType::PlainText
         );
 
+        assert_eq!(
+            MediaType::try_from(b"application/json-patch+json").unwrap(),
+            MediaType::ApplicationJsonPatch
+        );
+
         assert_eq!(
             MediaType::try_from(b"").unwrap_err(),
             RequestError::InvalidRequest
@@ -464,6 +508,9 @@ mod tests {
 
         let media_type = MediaType::PlainText;
         assert_eq!(media_type.as_str(), "text/plain");
+
+        let media_type = MediaType::ApplicationJsonPatch;
+        assert_eq!(media_type.as_str(), "application/json-patch+json");
     }
 
     #[test]
@@ -606,10 +653,21 @@ mod tests {
         assert!(header.parse_header_line(b"Expect: 100-continue").is_ok());
         assert!(header.expect());
 
+        // Test custom header.
+        assert!(header.custom_entry("X-Custom-Header").is_none());
+        assert!(header.parse_header_line(b"X-Custom-Header:  42 ").is_ok());
+        assert_eq!(header.custom_entry("X-Custom-Header"), Some("42"));
+        assert_eq!(header.custom_entry("x-custom-header"), Some("42"));
+
         // Test valid media type.
+        assert!(header
+            .parse_header_line(b"Content-Type: application/json-patch+json")
+            .is_ok());
+        assert_eq!(header.content_type(), MediaType::ApplicationJsonPatch);
         assert!(header
             .parse_header_line(b"Content-Type: application/json")
             .is_ok());
+        assert_eq!(header.content_type(), MediaType::ApplicationJson);
 
         // Test valid accept media type.
         assert!(header
@@ -82,6 +82,8 @@ pub enum RequestError {
     InvalidRequest,
     /// Overflow occurred when parsing a request.
     Overflow,
+    /// The body of the request is larger than the limit of the connection.
+    PayloadTooLarge(u32, usize),
     /// Underflow occurred when parsing a request.
     Underflow,
 }
@@ -103,6 +105,11 @@ impl Display for RequestError {
             Self::HeaderError(inner) => write!(f, "Invalid header. Reason: {}", inner),
             Self::InvalidRequest => write!(f, "Invalid request."),
             Self::Overflow => write!(f, "Overflow occurred when parsing a request."),
+            Self::PayloadTooLarge(size, limit) => write!(
+                f,
+                "Request payload with size {} is larger than the limit of {} allowed by server.",
+                size, limit
+            ),
             Self::Underflow => write!(f, "Underflow occurred when parsing a request."),
         }
     }
@@ -205,6 +212,8 @@ pub enum Method {
     Put,
     /// PATCH Method.
     Patch,
+    /// POST Method.
+    Post,
 }
 
 impl Method {
@@ -220,6 +229,7 @@ impl Method {
             b"GET" => Ok(Self::Get),
             b"PUT" => Ok(Self::Put),
             b"PATCH" => Ok(Self::Patch),
+            b"POST" => Ok(Self::Post),
             _ => Err(RequestError::InvalidHttpMethod("Unsupported HTTP method.")),
         }
     }
@@ -230,6 +240,7 @@ impl Method {
             Self::Get => b"GET",
             Self::Put => b"PUT",
             Self::Patch => b"PATCH",
+            Self::Post => b"POST",
         }
     }
 }
@@ -330,13 +341,15 @@ mod tests {
         assert_eq!(Method::Get.raw(), b"GET");
         assert_eq!(Method::Put.raw(), b"PUT");
         assert_eq!(Method::Patch.raw(), b"PATCH");
+        assert_eq!(Method::Post.raw(), b"POST");
 
         // Tests for try_from
         assert_eq!(Method::try_from(b"GET").unwrap(), Method::Get);
         assert_eq!(Method::try_from(b"PUT").unwrap(), Method::Put);
         assert_eq!(Method::try_from(b"PATCH").unwrap(), Method::Patch);
+        assert_eq!(Method::try_from(b"POST").unwrap(), Method::Post);
         assert_eq!(
-            Method::try_from(b"POST").unwrap_err(),
+            Method::try_from(b"DELETE").unwrap_err(),
             RequestError::InvalidHttpMethod("Unsupported HTTP method.")
         );
     }
@@ -383,6 +396,10 @@ mod tests {
             format!("{}", RequestError::Overflow),
             "Overflow occurred when parsing a request."
         );
+        assert_eq!(
+            format!("{}", RequestError::PayloadTooLarge(2048, 1024)),
+            "Request payload with size 2048 is larger than the limit of 1024 allowed by server."
+        );
         assert_eq!(
             format!("{}", RequestError::Underflow),
             "Underflow occurred when parsing a request."
@@ -42,6 +42,11 @@ pub struct HttpConnection<T> {
     /// Represents how many bytes from the body of the request are still
     /// to be read.
     body_bytes_to_be_read: u32,
+    /// The maximum size of a request body, if any.
+    payload_max_size: Option<usize>,
+    /// Whether the body being read belongs to a request rejected for its size,
+    /// in which case it is dropped.
+    discarding_body: bool,
     /// A queue of all requests that have been fully received and parsed.
     parsed_requests: VecDeque<Request>,
     /// A queue of requests that are waiting to be sent.
@@ -62,12 +67,21 @@ impl<T: Read + Write> HttpConnection<T> {
             read_cursor: 0,
             body_vec: vec![],
             body_bytes_to_be_read: 0,
+            payload_max_size: None,
+            discarding_body: false,
             parsed_requests: VecDeque::new(),
             response_queue: VecDeque::new(),
             response_buffer: None,
         }
     }
 
+    /// Sets the maximum size of the body of the requests received afterwards. The
+    /// larger requests are rejected with a `PayloadTooLarge` error, and their body
+    /// is dropped.
+    pub fn set_payload_max_size(&mut self, payload_max_size: usize) {
+        self.payload_max_size = Some(payload_max_size);
+    }
+
     /// Tries to read new bytes from the stream and automatically update the request.
     /// Meant to be used only with non-blocking streams and an `EPOLL` structure.
     /// Should be called whenever an `EPOLLIN` event is signaled.
@@ -229,7 +243,27 @@ impl<T: Read + Write> HttpConnection<T> {
                     .ok_or(ConnectionError::ParseError(
                         RequestError::HeadersWithoutPendingRequest,
                     ))?;
-                if request.headers.content_length() == 0 {
+                let content_length = request.headers.content_length();
+                if let Some(payload_max_size) = self.payload_max_size {
+                    if content_length as usize > payload_max_size {
+                        // Drop the request, and its body once it arrives.
+                        self.pending_request = None;
+                        self.body_bytes_to_be_read = content_length;
+                        self.discarding_body = true;
+                        self.state = ConnectionState::WaitingForBody;
+                        *line_start_index = line_start_index
+                            .checked_add(CRLF_LEN)
+                            .ok_or(ConnectionError::ParseError(RequestError::Overflow))?;
+                        // Keep the beginning of the body for the next `try_read` call.
+                        self.shift_buffer_left(*line_start_index, end_cursor)
+                            .map_err(ConnectionError::ParseError)?;
+                        return Err(ConnectionError::ParseError(RequestError::PayloadTooLarge(
+                            content_length,
+                            payload_max_size,
+                        )));
+                    }
+                }
+                if content_length == 0 {
                     self.state = ConnectionState::RequestReady;
                 } else {
                     if request.headers.expect() {
@@ -327,8 +361,10 @@ impl<T: Read + Write> HttpConnection<T> {
             // Append everything that we read to our current incomplete body and update
             // `body_bytes_to_be_read`.
             // The slice access is safe, otherwise `checked_sub` would have failed.
-            self.body_vec
-                .extend_from_slice(&self.buffer[*line_start_index..end_cursor]);
+            if !self.discarding_body {
+                self.body_vec
+                    .extend_from_slice(&self.buffer[*line_start_index..end_cursor]);
+            }
             // Safe to subtract directly as the `if` condition prevents underflow.
             self.body_bytes_to_be_read -= start_to_end;
 
@@ -345,6 +381,14 @@ impl<T: Read + Write> HttpConnection<T> {
         let line_end = line_start_index
             .checked_add(self.body_bytes_to_be_read as usize)
             .ok_or(ConnectionError::ParseError(RequestError::Overflow))?;
+        if self.discarding_body {
+            // The body of the rejected request is over, wait for the next request.
+            *line_start_index = line_end;
+            self.body_bytes_to_be_read = 0;
+            self.discarding_body = false;
+            self.state = ConnectionState::WaitingForRequestLine;
+            return Ok(true);
+        }
         // The slice access is safe as `line_end` is a sum of `line_start_index` + something else.
         self.body_vec
             .extend_from_slice(&self.buffer[*line_start_index..line_end]);
@@ -828,6 +872,47 @@ mod tests {
         assert_eq!(request_second, expected_request_second);
     }
 
+    #[test]
+    fn test_try_read_payload_too_large() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        receiver.set_nonblocking(true).expect("Can't modify socket");
+        let mut conn = HttpConnection::new(receiver);
+        conn.set_payload_max_size(10);
+
+        // A body at the limit is accepted.
+        sender
+            .write_all(
+                b"PUT http://localhost/mmds HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789",
+            )
+            .unwrap();
+        conn.try_read().unwrap();
+        assert_eq!(
+            conn.pop_parsed_request().unwrap().body,
+            Some(Body::new(b"0123456789".to_vec()))
+        );
+
+        // A larger one is rejected.
+        sender
+            .write_all(
+                b"PUT http://localhost/mmds HTTP/1.1\r\nContent-Length: 20\r\n\r\n0123456789",
+            )
+            .unwrap();
+        assert_eq!(
+            conn.try_read().unwrap_err(),
+            ConnectionError::ParseError(RequestError::PayloadTooLarge(20, 10))
+        );
+        assert!(conn.pop_parsed_request().is_none());
+
+        // Its body is dropped, up to the next request.
+        sender
+            .write_all(b"0123456789GET http://localhost/mmds HTTP/1.1\r\n\r\n")
+            .unwrap();
+        conn.try_read().unwrap();
+        let request = conn.pop_parsed_request().unwrap();
+        assert_eq!(request.method(), Method::Get);
+        assert!(conn.pop_parsed_request().is_none());
+    }
+
     #[test]
     fn test_try_read_connection_closed() {
         // Connection abruptly closed.
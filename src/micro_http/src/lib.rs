@@ -111,6 +111,7 @@ mod connection;
 mod request;
 mod response;
 mod server;
+mod transport;
 use crate::common::ascii;
 use crate::common::headers;
 
@@ -118,6 +119,7 @@ pub use crate::connection::{ConnectionError, HttpConnection};
 pub use crate::request::{Request, RequestError};
 pub use crate::response::{Response, ResponseHeaders, StatusCode};
 pub use crate::server::{HttpServer, ServerError, ServerRequest, ServerResponse};
+pub use crate::transport::{PeerCredentials, ServerTransport};
 
 pub use crate::common::headers::{Encoding, Headers, MediaType};
 pub use crate::common::{Body, HttpHeaderError, Method, Version};
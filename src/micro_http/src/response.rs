@@ -19,14 +19,24 @@ pub enum StatusCode {
     Continue,
     /// 200, OK
     OK,
+    /// 202, Accepted
+    Accepted,
     /// 204, No Content
     NoContent,
     /// 400, Bad Request
     BadRequest,
+    /// 401, Unauthorized
+    Unauthorized,
+    /// 403, Forbidden
+    Forbidden,
     /// 404, Not Found
     NotFound,
     /// 405, Method Not Allowed
     MethodNotAllowed,
+    /// 413, Payload Too Large
+    PayloadTooLarge,
+    /// 429, Too Many Requests
+    TooManyRequests,
     /// 500, Internal Server Error
     InternalServerError,
     /// 501, Not Implemented
@@ -39,10 +49,15 @@ impl StatusCode {
         match self {
             Self::Continue => b"100",
             Self::OK => b"200",
+            Self::Accepted => b"202",
             Self::NoContent => b"204",
             Self::BadRequest => b"400",
+            Self::Unauthorized => b"401",
+            Self::Forbidden => b"403",
             Self::NotFound => b"404",
             Self::MethodNotAllowed => b"405",
+            Self::PayloadTooLarge => b"413",
+            Self::TooManyRequests => b"429",
             Self::InternalServerError => b"500",
             Self::NotImplemented => b"501",
         }
@@ -366,10 +381,13 @@ mod tests {
     fn test_status_code() {
         assert_eq!(StatusCode::Continue.raw(), b"100");
         assert_eq!(StatusCode::OK.raw(), b"200");
+        assert_eq!(StatusCode::Accepted.raw(), b"202");
         assert_eq!(StatusCode::NoContent.raw(), b"204");
         assert_eq!(StatusCode::BadRequest.raw(), b"400");
         assert_eq!(StatusCode::NotFound.raw(), b"404");
         assert_eq!(StatusCode::MethodNotAllowed.raw(), b"405");
+        assert_eq!(StatusCode::PayloadTooLarge.raw(), b"413");
+        assert_eq!(StatusCode::TooManyRequests.raw(), b"429");
         assert_eq!(StatusCode::InternalServerError.raw(), b"500");
         assert_eq!(StatusCode::NotImplemented.raw(), b"501");
     }
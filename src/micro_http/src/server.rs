@@ -5,7 +5,7 @@ use logger::error;
 use std::io::{Read, Write};
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::RawFd;
-use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::net::UnixListener;
 use std::path::Path;
 
 use crate::common::{Body, Version};
@@ -13,6 +13,7 @@ pub use crate::common::{ConnectionError, RequestError, ServerError};
 use crate::connection::HttpConnection;
 use crate::request::Request;
 use crate::response::{Response, StatusCode};
+use crate::transport::{Listener, PeerCredentials, ServerStream, ServerTransport};
 use std::collections::HashMap;
 
 use utils::epoll;
@@ -31,13 +32,22 @@ pub struct ServerRequest {
     pub request: Request,
     /// Identification token.
     id: u64,
+    /// Credentials of the client, if it is connected through a Unix Domain Socket.
+    peer_credentials: Option<PeerCredentials>,
+    /// File descriptor of the socket on which the request was received.
+    listener_fd: Option<RawFd>,
 }
 
 impl ServerRequest {
     /// Creates a new `ServerRequest` object from an existing `Request`,
     /// adding an identification token.
     pub fn new(request: Request, id: u64) -> Self {
-        Self { request, id }
+        Self {
+            request,
+            id,
+            peer_credentials: None,
+            listener_fd: None,
+        }
     }
 
     /// Returns a reference to the inner request.
@@ -45,6 +55,18 @@ impl ServerRequest {
         &self.request
     }
 
+    /// Returns the credentials of the client which sent the request, if it is
+    /// connected through a Unix Domain Socket.
+    pub fn peer_credentials(&self) -> Option<PeerCredentials> {
+        self.peer_credentials
+    }
+
+    /// Returns the file descriptor of the socket on which the request was received,
+    /// so that the user can tell apart the transports the server listens on.
+    pub fn listener_fd(&self) -> Option<RawFd> {
+        self.listener_fd
+    }
+
     /// Calls the function provided on the inner request to obtain the response.
     /// The response is then wrapped in a `ServerResponse`.
     ///
@@ -92,14 +114,24 @@ struct ClientConnection<T> {
     /// absorbed responses.
     /// This has to be `0` if we want to drop the connection.
     in_flight_response_count: u32,
+    /// Credentials of the client, if it is connected through a Unix Domain Socket.
+    peer_credentials: Option<PeerCredentials>,
+    /// The file descriptor of the socket on which the connection was accepted.
+    listener_fd: RawFd,
 }
 
 impl<T: Read + Write> ClientConnection<T> {
-    fn new(connection: HttpConnection<T>) -> Self {
+    fn new(
+        connection: HttpConnection<T>,
+        peer_credentials: Option<PeerCredentials>,
+        listener_fd: RawFd,
+    ) -> Self {
         Self {
             connection,
             state: ClientConnectionState::AwaitingIncoming,
             in_flight_response_count: 0,
+            peer_credentials,
+            listener_fd,
         }
     }
 
@@ -129,7 +161,11 @@ impl<T: Read + Write> ClientConnection<T> {
                 while let Some(_discarded_request) = self.connection.pop_parsed_request() {}
 
                 // Send an error response for the request that gave us the error.
-                let mut error_response = Response::new(Version::Http11, StatusCode::BadRequest);
+                let status = match inner {
+                    RequestError::PayloadTooLarge(_, _) => StatusCode::PayloadTooLarge,
+                    _ => StatusCode::BadRequest,
+                };
+                let mut error_response = Response::new(Version::Http11, status);
                 error_response.set_body(Body::new(format!(
                     "{{ \"error\": \"{}\nAll previous unanswered requests will be dropped.\" }}",
                     inner.to_string()
@@ -207,7 +243,8 @@ impl<T: Read + Write> ClientConnection<T> {
 }
 
 /// HTTP Server implementation using Unix Domain Sockets and `EPOLL` to
-/// handle multiple connections on the same thread.
+/// handle multiple connections on the same thread. The server can also
+/// accept connections on TCP and vsock sockets, see `add_transport`.
 ///
 /// The function that handles incoming connections, parses incoming
 /// requests and sends responses for awaiting requests is `requests`.
@@ -250,8 +287,8 @@ impl<T: Read + Write> ClientConnection<T> {
 /// }
 /// ```
 pub struct HttpServer {
-    /// Socket on which we listen for new connections.
-    socket: UnixListener,
+    /// Sockets on which we listen for new connections, keyed by their file descriptor.
+    listeners: HashMap<RawFd, Listener>,
     /// Server's epoll instance.
     epoll: epoll::Epoll,
     /// Holds the token-connection pairs of the server.
@@ -259,7 +296,9 @@ pub struct HttpServer {
     /// the file descriptor of the underlying stream.
     /// We use the file descriptor of the stream as the key for mapping
     /// connections because the 1-to-1 relation is guaranteed by the OS.
-    connections: HashMap<RawFd, ClientConnection<UnixStream>>,
+    connections: HashMap<RawFd, ClientConnection<ServerStream>>,
+    /// The maximum size of a request body, if any.
+    payload_max_size: Option<usize>,
 }
 
 impl HttpServer {
@@ -272,18 +311,42 @@ impl HttpServer {
     pub fn new<P: AsRef<Path>>(path_to_socket: P) -> Result<Self> {
         let socket = UnixListener::bind(path_to_socket).map_err(ServerError::IOError)?;
         let epoll = epoll::Epoll::new().map_err(ServerError::IOError)?;
+        let mut listeners = HashMap::new();
+        listeners.insert(socket.as_raw_fd(), Listener::Unix(socket));
         Ok(Self {
-            socket,
+            listeners,
             epoll,
             connections: HashMap::new(),
+            payload_max_size: None,
         })
     }
 
+    /// Binds an additional socket on which the server listens for new connections.
+    /// It must be called before `start_server`.
+    ///
+    /// # Errors
+    /// Returns an `IOError` when binding fails.
+    pub fn add_transport(&mut self, transport: ServerTransport) -> Result<()> {
+        let listener = Listener::bind(transport).map_err(ServerError::IOError)?;
+        self.listeners.insert(listener.as_raw_fd(), listener);
+        Ok(())
+    }
+
+    /// Sets the maximum size of the request bodies. The larger requests are answered
+    /// with a `413 Payload Too Large` status, without being handed to the user.
+    /// It must be called before `start_server`.
+    pub fn set_payload_max_size(&mut self, payload_max_size: usize) {
+        self.payload_max_size = Some(payload_max_size);
+    }
+
     /// Starts the HTTP Server.
     pub fn start_server(&mut self) -> Result<()> {
-        // Add the socket on which we listen for new connections to the
+        // Add the sockets on which we listen for new connections to the
         // `epoll` structure.
-        Self::epoll_add(&self.epoll, self.socket.as_raw_fd())
+        for fd in self.listeners.keys() {
+            Self::epoll_add(&self.epoll, *fd)?;
+        }
+        Ok(())
     }
 
     /// This function is responsible for the data exchange with the clients and should
@@ -323,18 +386,18 @@ impl HttpServer {
             // Check the file descriptor which produced the notification `e`.
             // It could be that we have a new connection, or one of our open
             // connections is ready to exchange data with a client.
-            if e.fd() == self.socket.as_raw_fd() {
-                // We have received a notification on the listener socket, which
+            if self.listeners.contains_key(&e.fd()) {
+                // We have received a notification on a listener socket, which
                 // means we have a new connection to accept.
-                match self.handle_new_connection() {
+                match self.handle_new_connection(e.fd()) {
                     // If the server is full, we send a message to the client
                     // notifying them that we will close the connection, then
                     // we discard it.
                     Err(ServerError::ServerFull) => {
-                        self.socket
+                        self.listeners[&e.fd()]
                             .accept()
                             .map_err(ServerError::IOError)
-                            .and_then(move |(mut stream, _)| {
+                            .and_then(move |mut stream| {
                                 stream
                                     .write(SERVER_FULL_ERROR_MESSAGE)
                                     .map_err(ServerError::IOError)
@@ -366,13 +429,20 @@ impl HttpServer {
 
                 if e.event_set().contains(epoll::EventSet::IN) {
                     // We have bytes to read from this connection.
-                    // If our `read` yields `Request` objects, we wrap them with an ID before
-                    // handing them to the user.
+                    // If our `read` yields `Request` objects, we wrap them with an ID, the
+                    // credentials of the client and the socket it connected to before handing
+                    // them to the user.
+                    let peer_credentials = client_connection.peer_credentials;
+                    let listener_fd = Some(client_connection.listener_fd);
                     parsed_requests.append(
                         &mut client_connection
                             .read()?
                             .into_iter()
-                            .map(|request| ServerRequest::new(request, e.data()))
+                            .map(|request| ServerRequest {
+                                peer_credentials,
+                                listener_fd,
+                                ..ServerRequest::new(request, e.data())
+                            })
                             .collect(),
                     );
                     // If the connection was incoming before we read and we now have to write
@@ -524,22 +594,23 @@ impl HttpServer {
         Ok(())
     }
 
-    /// Accepts a new incoming connection and adds it to the `epoll` notification structure.
+    /// Accepts a new incoming connection on the listener with the `listener_fd` file
+    /// descriptor and adds it to the `epoll` notification structure.
     ///
     /// # Errors
     /// `IOError` is returned when socket or epoll operations fail.
     /// `ServerFull` is returned if server full capacity has been reached.
-    fn handle_new_connection(&mut self) -> Result<()> {
+    fn handle_new_connection(&mut self, listener_fd: RawFd) -> Result<()> {
         if self.connections.len() == MAX_CONNECTIONS {
             // If we want a replacement policy for connections
             // this is where we will have it.
             return Err(ServerError::ServerFull);
         }
 
-        self.socket
+        self.listeners[&listener_fd]
             .accept()
             .map_err(ServerError::IOError)
-            .and_then(|(stream, _)| {
+            .and_then(|stream| {
                 // `HttpConnection` is supposed to work with non-blocking streams.
                 stream
                     .set_nonblocking(true)
@@ -547,12 +618,18 @@ impl HttpServer {
                     .map_err(ServerError::IOError)
             })
             .and_then(|stream| {
+                let peer_credentials = stream.peer_credentials().map_err(ServerError::IOError)?;
                 // Add the stream to the `epoll` structure and listen for bytes to be read.
                 Self::epoll_add(&self.epoll, stream.as_raw_fd())?;
                 // Then add it to our open connections.
+                let stream_fd = stream.as_raw_fd();
+                let mut connection = HttpConnection::new(stream);
+                if let Some(payload_max_size) = self.payload_max_size {
+                    connection.set_payload_max_size(payload_max_size);
+                }
                 self.connections.insert(
-                    stream.as_raw_fd(),
-                    ClientConnection::new(HttpConnection::new(stream)),
+                    stream_fd,
+                    ClientConnection::new(connection, peer_credentials, listener_fd),
                 );
                 Ok(())
             })
@@ -625,6 +702,10 @@ mod tests {
 
         let mut req_vec = server.requests().unwrap();
         let server_request = req_vec.remove(0);
+        assert_eq!(
+            server_request.peer_credentials().unwrap().pid,
+            std::process::id() as i32
+        );
 
         server
             .respond(server_request.process(|_request| {
@@ -854,6 +935,65 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn test_wait_payload_too_large() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new(path_to_socket.as_path()).unwrap();
+        server.set_payload_max_size(10);
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        // The oversized request is answered by the server, and its body is discarded.
+        socket
+            .write_all(
+                b"PUT /mmds HTTP/1.1\r\n\
+                         Content-Length: 13\r\n\
+                         Content-Type: application/json\r\n\r\nwhatever body",
+            )
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let mut buf: [u8; 1024] = [0; 1024];
+        let len = socket.read(&mut buf[..]).unwrap();
+        assert!(buf[..len].starts_with(b"HTTP/1.1 413 \r\n"));
+
+        // The connection can still be used for smaller requests.
+        socket
+            .write_all(
+                b"PUT /mmds HTTP/1.1\r\n\
+                         Content-Length: 4\r\n\
+                         Content-Type: application/json\r\n\r\nbody",
+            )
+            .unwrap();
+        let req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.len(), 1);
+        assert_eq!(req_vec[0].inner().body.as_ref().unwrap().raw(), b"body");
+    }
+
+    #[test]
+    fn test_wait_listener_fd() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+        let listener_fd = *server.listeners.keys().next().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        // The request tells the socket it was received on.
+        let req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.len(), 1);
+        assert_eq!(req_vec[0].listener_fd(), Some(listener_fd));
+    }
+
     #[test]
     fn test_wait_in_flight_responses() {
         let path_to_socket = get_temp_socket_file();
@@ -933,4 +1073,53 @@ mod tests {
         second_socket.shutdown(std::net::Shutdown::Both).unwrap();
         assert!(server.requests().is_ok());
     }
+
+    #[test]
+    fn test_wait_tcp_connection() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new(path_to_socket.as_path()).unwrap();
+        server
+            .add_transport(ServerTransport::Tcp("127.0.0.1:0".parse().unwrap()))
+            .unwrap();
+        server.start_server().unwrap();
+        let addr = server
+            .listeners
+            .values()
+            .find_map(|listener| match listener {
+                Listener::Tcp(listener) => Some(listener.local_addr().unwrap()),
+                _ => None,
+            })
+            .unwrap();
+
+        // The unix domain socket is still served.
+        let _unix_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        let mut socket = std::net::TcpStream::connect(addr).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert_eq!(server.connections.len(), 2);
+
+        socket
+            .write_all(
+                b"PATCH /machine-config HTTP/1.1\r\n\
+                         Content-Length: 13\r\n\
+                         Content-Type: application/json\r\n\r\nwhatever body",
+            )
+            .unwrap();
+
+        let mut req_vec = server.requests().unwrap();
+        let server_request = req_vec.remove(0);
+        assert!(server_request.peer_credentials().is_none());
+        server
+            .respond(
+                server_request
+                    .process(|_request| Response::new(Version::Http11, StatusCode::NoContent)),
+            )
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        let mut buf: [u8; 1024] = [0; 1024];
+        assert!(socket.read(&mut buf[..]).unwrap() > 0);
+    }
 }
@@ -0,0 +1,297 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::ptr;
+
+// The maximum number of pending connections on a vsock listener.
+const VSOCK_BACKLOG: i32 = 128;
+
+/// A transport on which the server accepts connections, in addition to its
+/// Unix Domain Socket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServerTransport {
+    /// A TCP socket bound to the given address.
+    Tcp(SocketAddr),
+    /// A vsock port, on which connections from any context ID are accepted.
+    Vsock(u32),
+}
+
+/// The credentials of the process which opened a Unix Domain Socket connection,
+/// as captured by the kernel when connecting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerCredentials {
+    /// The process ID.
+    pub pid: i32,
+    /// The effective user ID.
+    pub uid: u32,
+    /// The effective group ID.
+    pub gid: u32,
+}
+
+/// A socket on which the server listens for new connections.
+pub(crate) enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+    Vsock(File),
+}
+
+impl Listener {
+    /// Binds a listener for `transport`.
+    pub(crate) fn bind(transport: ServerTransport) -> io::Result<Self> {
+        match transport {
+            ServerTransport::Tcp(addr) => TcpListener::bind(addr).map(Listener::Tcp),
+            ServerTransport::Vsock(port) => bind_vsock(port).map(Listener::Vsock),
+        }
+    }
+
+    /// Accepts a new connection.
+    pub(crate) fn accept(&self) -> io::Result<ServerStream> {
+        match self {
+            Listener::Unix(listener) => listener
+                .accept()
+                .map(|(stream, _)| ServerStream::Unix(stream)),
+            Listener::Tcp(listener) => listener
+                .accept()
+                .map(|(stream, _)| ServerStream::Tcp(stream)),
+            Listener::Vsock(listener) => accept_vsock(listener).map(ServerStream::Vsock),
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Unix(listener) => listener.as_raw_fd(),
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Vsock(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// A connection accepted by the server, whichever its transport.
+pub(crate) enum ServerStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+    Vsock(File),
+}
+
+impl ServerStream {
+    /// Moves the stream into or out of non-blocking mode.
+    pub(crate) fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            ServerStream::Unix(stream) => stream.set_nonblocking(nonblocking),
+            ServerStream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            ServerStream::Vsock(stream) => set_nonblocking(stream.as_raw_fd(), nonblocking),
+        }
+    }
+
+    /// Returns the credentials of the peer of a Unix Domain Socket connection. The
+    /// peers of the other transports are not identified.
+    pub(crate) fn peer_credentials(&self) -> io::Result<Option<PeerCredentials>> {
+        match self {
+            ServerStream::Unix(stream) => peer_credentials(stream.as_raw_fd()).map(Some),
+            ServerStream::Tcp(_) | ServerStream::Vsock(_) => Ok(None),
+        }
+    }
+}
+
+impl Read for ServerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ServerStream::Unix(stream) => stream.read(buf),
+            ServerStream::Tcp(stream) => stream.read(buf),
+            ServerStream::Vsock(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ServerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ServerStream::Unix(stream) => stream.write(buf),
+            ServerStream::Tcp(stream) => stream.write(buf),
+            ServerStream::Vsock(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ServerStream::Unix(stream) => stream.flush(),
+            ServerStream::Tcp(stream) => stream.flush(),
+            ServerStream::Vsock(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for ServerStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            ServerStream::Unix(stream) => stream.as_raw_fd(),
+            ServerStream::Tcp(stream) => stream.as_raw_fd(),
+            ServerStream::Vsock(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+// The standard library has no vsock sockets, so they are created with raw syscalls and
+// owned by a `File`, which closes them on drop.
+fn bind_vsock(port: u32) -> io::Result<File> {
+    // Safe because the arguments are valid and the result is checked.
+    let fd = unsafe { libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because `fd` is a newly created socket owned by nobody else.
+    let socket = unsafe { File::from_raw_fd(fd) };
+
+    // Safe because `sockaddr_vm` is a plain C struct, for which all zeroes is a valid value.
+    let mut addr: libc::sockaddr_vm = unsafe { mem::zeroed() };
+    addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+    addr.svm_port = port;
+    addr.svm_cid = libc::VMADDR_CID_ANY;
+    // Safe because `addr` is a valid vsock address of the given length, and the result
+    // is checked.
+    let ret = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because `fd` is a valid socket and the result is checked.
+    if unsafe { libc::listen(fd, VSOCK_BACKLOG) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(socket)
+}
+
+fn accept_vsock(listener: &File) -> io::Result<File> {
+    // Safe because the peer address is not requested and the result is checked.
+    let fd = unsafe {
+        libc::accept4(
+            listener.as_raw_fd(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            libc::SOCK_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safe because `fd` is a newly accepted socket owned by nobody else.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<()> {
+    let mut value = nonblocking as libc::c_int;
+    // Safe because `FIONBIO` only reads the integer, and the result is checked.
+    if unsafe { libc::ioctl(fd, libc::FIONBIO, &mut value) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn peer_credentials(fd: RawFd) -> io::Result<PeerCredentials> {
+    // Safe because `ucred` is a plain C struct, for which all zeroes is a valid value.
+    let mut ucred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // Safe because `ucred` is large enough for the option value, and the result is checked.
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut ucred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PeerCredentials {
+        pid: ucred.pid,
+        uid: ucred.uid,
+        gid: ucred.gid,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn test_tcp_transport() {
+        let listener =
+            Listener::bind(ServerTransport::Tcp("127.0.0.1:0".parse().unwrap())).unwrap();
+        let addr = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap(),
+            _ => panic!("Unexpected listener."),
+        };
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut stream = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+
+        let mut buf = [0u8; 5];
+        // Nothing was sent yet.
+        assert_eq!(
+            stream.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        client.write_all(b"hello").unwrap();
+        stream.set_nonblocking(false).unwrap();
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        stream.write_all(b"world").unwrap();
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"world");
+
+        // The peers of TCP connections are not identified.
+        assert_eq!(stream.peer_credentials().unwrap(), None);
+    }
+
+    #[test]
+    fn test_peer_credentials() {
+        let (first, _second) = UnixStream::pair().unwrap();
+        let stream = ServerStream::Unix(first);
+        // Safe because these calls have no side effects.
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        assert_eq!(
+            stream.peer_credentials().unwrap(),
+            Some(PeerCredentials {
+                pid: std::process::id() as i32,
+                uid,
+                gid,
+            })
+        );
+    }
+
+    #[test]
+    fn test_vsock_nonblocking() {
+        // The host may lack vsock support, so the non-blocking mode is checked on a socket
+        // owned by a `File`, the same way as for vsock streams.
+        let (first, _second) = UnixStream::pair().unwrap();
+        // Safe because the `File` takes the ownership of the descriptor.
+        let mut stream = ServerStream::Vsock(unsafe { File::from_raw_fd(first.into_raw_fd()) });
+        stream.set_nonblocking(true).unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(
+            stream.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+}
@@ -1,14 +1,64 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::fmt;
+use std::sync::Arc;
+
+use crate::dynamic::{self, ValueProvider};
+use crate::patch::{self, Error as PatchError, PatchOperation};
+use crate::token::{Error as TokenError, TokenAuthority};
+
+/// The default maximum size, in bytes, of the serialized MMDS data store.
+pub const DEFAULT_DATA_STORE_LIMIT: usize = 51200;
 
 /// The Mmds is the Microvm Metadata Service represented as an untyped json.
 #[derive(Clone)]
 pub struct Mmds {
     data_store: Value,
+    // Maximum size, in bytes, of the serialized data store.
+    data_store_limit: usize,
     is_initialized: bool,
+    version: MmdsVersion,
+    token_authority: TokenAuthority,
+    // The dynamic fields, keyed by the reference tokens of their JSON pointers.
+    providers: Vec<(Vec<String>, Arc<dyn ValueProvider>)>,
+}
+
+/// The ways in which the guest may access the MMDS.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MmdsVersion {
+    /// Session tokens are optional, so plain `GET` requests are served.
+    V1,
+    /// Every `GET` request has to carry a valid session token.
+    V2,
+}
+
+impl Default for MmdsVersion {
+    fn default() -> Self {
+        MmdsVersion::V1
+    }
+}
+
+impl fmt::Display for MmdsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MmdsVersion::V1 => write!(f, "V1"),
+            MmdsVersion::V2 => write!(f, "V2"),
+        }
+    }
+}
+
+/// The current usage of the MMDS data store.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct DataStoreStatus {
+    /// Size, in bytes, of the serialized data store.
+    pub size: usize,
+    /// Maximum size, in bytes, of the serialized data store.
+    pub size_limit: usize,
+    /// Number of keys in the data store, nested ones included.
+    pub key_count: usize,
 }
 
 /// MMDS possible outputs.
@@ -19,6 +69,9 @@ pub enum OutputFormat {
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
+    DataStoreLimitExceeded(usize),
+    InvalidDynamicPath(String),
+    InvalidPatch(PatchError),
     NotFound,
     NotInitialized,
     UnsupportedValueType,
@@ -27,6 +80,18 @@ pub enum Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Error::DataStoreLimitExceeded(limit) => write!(
+                f,
+                "The MMDS data store cannot exceed its size limit of {} bytes.",
+                limit
+            ),
+            Error::InvalidDynamicPath(ref path) => write!(
+                f,
+                "Invalid path for a dynamic MMDS field: {}. Please provide a JSON pointer to a \
+                 location other than the root.",
+                path
+            ),
+            Error::InvalidPatch(ref e) => write!(f, "Cannot apply the JSON patch. {}", e),
             Error::NotFound => write!(f, "The MMDS resource does not exist."),
             Error::NotInitialized => write!(f, "The MMDS data store is not initialized."),
             Error::UnsupportedValueType => write!(
@@ -41,7 +106,11 @@ impl Default for Mmds {
     fn default() -> Self {
         Mmds {
             data_store: Value::default(),
+            data_store_limit: DEFAULT_DATA_STORE_LIMIT,
             is_initialized: false,
+            version: MmdsVersion::default(),
+            token_authority: TokenAuthority::default(),
+            providers: Vec::new(),
         }
     }
 }
@@ -58,7 +127,115 @@ impl Mmds {
         }
     }
 
+    // Returns the size, in bytes, of `data` once serialized. An uninitialized data store is
+    // empty.
+    fn data_store_size(data: &Value) -> usize {
+        if data.is_null() {
+            return 0;
+        }
+        data.to_string().len()
+    }
+
+    fn check_data_store_limit(&self, data: &Value) -> Result<(), Error> {
+        if Mmds::data_store_size(data) > self.data_store_limit {
+            return Err(Error::DataStoreLimitExceeded(self.data_store_limit));
+        }
+        Ok(())
+    }
+
+    /// Sets the maximum size, in bytes, of the serialized data store. Fails, leaving the
+    /// limit untouched, if the data store is already larger than `limit`.
+    pub fn set_data_store_limit(&mut self, limit: usize) -> Result<(), Error> {
+        if Mmds::data_store_size(&self.data_store) > limit {
+            return Err(Error::DataStoreLimitExceeded(limit));
+        }
+        self.data_store_limit = limit;
+        Ok(())
+    }
+
+    /// Returns the maximum size, in bytes, of the serialized data store.
+    pub fn data_store_limit(&self) -> usize {
+        self.data_store_limit
+    }
+
+    /// Returns the current size and key count of the data store.
+    pub fn status(&self) -> DataStoreStatus {
+        fn count_keys(value: &Value) -> usize {
+            match value {
+                Value::Object(map) => map.len() + map.values().map(count_keys).sum::<usize>(),
+                Value::Array(values) => values.iter().map(count_keys).sum(),
+                _ => 0,
+            }
+        }
+
+        DataStoreStatus {
+            size: Mmds::data_store_size(&self.data_store),
+            size_limit: self.data_store_limit,
+            key_count: count_keys(&self.data_store),
+        }
+    }
+
+    /// Sets the way in which the guest may access the MMDS.
+    pub fn set_version(&mut self, version: MmdsVersion) {
+        self.version = version;
+    }
+
+    /// Returns the way in which the guest may access the MMDS.
+    pub fn version(&self) -> MmdsVersion {
+        self.version
+    }
+
+    /// Issues a new session token, valid for `ttl_seconds`.
+    pub fn generate_token(&mut self, ttl_seconds: u32) -> Result<String, TokenError> {
+        self.token_authority.generate_token(ttl_seconds)
+    }
+
+    /// Returns whether `token` is a live session token.
+    pub fn is_valid_token(&self, token: &str) -> bool {
+        self.token_authority.is_valid(token)
+    }
+
+    /// Makes the value at the JSON pointer `path` computed by `provider` whenever the guest
+    /// requests it, replacing any provider previously registered for the same path.
+    pub fn register_provider(
+        &mut self,
+        path: &str,
+        provider: Arc<dyn ValueProvider>,
+    ) -> Result<(), Error> {
+        let tokens = Mmds::dynamic_path_tokens(path)?;
+        self.providers
+            .retain(|(registered, _)| *registered != tokens);
+        self.providers.push((tokens, provider));
+        Ok(())
+    }
+
+    /// Replaces all the dynamic value providers with `providers`. If any of the paths is
+    /// invalid, the registered providers are left untouched.
+    pub fn set_providers(
+        &mut self,
+        providers: Vec<(String, Arc<dyn ValueProvider>)>,
+    ) -> Result<(), Error> {
+        let mut new_providers: Vec<(Vec<String>, Arc<dyn ValueProvider>)> = Vec::new();
+        for (path, provider) in providers {
+            let tokens = Mmds::dynamic_path_tokens(&path)?;
+            new_providers.retain(|(registered, _)| *registered != tokens);
+            new_providers.push((tokens, provider));
+        }
+
+        self.providers = new_providers;
+        Ok(())
+    }
+
+    // Dynamic fields can be placed anywhere but at the root of the data store.
+    fn dynamic_path_tokens(path: &str) -> Result<Vec<String>, Error> {
+        patch::parse_pointer(path)
+            .ok()
+            .filter(|tokens| !tokens.is_empty())
+            .ok_or_else(|| Error::InvalidDynamicPath(path.to_string()))
+    }
+
     pub fn put_data(&mut self, data: Value) -> Result<(), Error> {
+        self.check_data_store_limit(&data)?;
         self.data_store = data;
         self.is_initialized = true;
         Ok(())
@@ -66,7 +243,20 @@ impl Mmds {
 
     pub fn patch_data(&mut self, patch_data: Value) -> Result<(), Error> {
         self.check_data_store_initialized()?;
-        super::json_patch(&mut self.data_store, &patch_data);
+        let mut data_store = self.data_store.clone();
+        super::json_patch(&mut data_store, &patch_data);
+        self.check_data_store_limit(&data_store)?;
+        self.data_store = data_store;
+        Ok(())
+    }
+
+    /// Applies the JSON Patch `operations` to the data store, all or nothing.
+    pub fn apply_json_patch(&mut self, operations: Vec<PatchOperation>) -> Result<(), Error> {
+        self.check_data_store_initialized()?;
+        let mut data_store = self.data_store.clone();
+        patch::apply_patch(&mut data_store, operations).map_err(Error::InvalidPatch)?;
+        self.check_data_store_limit(&data_store)?;
+        self.data_store = data_store;
         Ok(())
     }
 
@@ -148,12 +338,24 @@ impl Mmds {
     /// Returns the subtree located at path. When the path corresponds to a leaf, it returns the value.
     /// Returns Error::NotFound when the path is invalid.
     pub fn get_value(&self, path: String, format: OutputFormat) -> Result<String, Error> {
+        // The dynamic fields are computed at request time, on top of the stored values.
+        let mut data_store;
+        let data = if self.providers.is_empty() {
+            &self.data_store
+        } else {
+            data_store = self.data_store.clone();
+            for (tokens, provider) in self.providers.iter() {
+                dynamic::insert_value(&mut data_store, tokens, provider.value());
+            }
+            &data_store
+        };
+
         // The pointer function splits the input by "/". With a trailing "/", pointer does not
         // know how to get the object.
         let value = if path.ends_with('/') {
-            self.data_store.pointer(&path.as_str()[..(path.len() - 1)])
+            data.pointer(&path.as_str()[..(path.len() - 1)])
         } else {
-            self.data_store.pointer(path.as_str())
+            data.pointer(path.as_str())
         };
 
         if let Some(json) = value {
@@ -196,6 +398,106 @@ mod tests {
         assert_eq!(mmds.get_data_str(), mmds_json);
     }
 
+    #[test]
+    fn test_apply_json_patch() {
+        let mut mmds = Mmds::default();
+        let operations: Vec<PatchOperation> =
+            serde_json::from_str(r#"[{"op": "replace", "path": "/user-data", "value": "10"}]"#)
+                .unwrap();
+
+        assert_eq!(
+            mmds.apply_json_patch(operations.clone()).unwrap_err(),
+            Error::NotInitialized
+        );
+
+        mmds.put_data(serde_json::from_str(r#"{"user-data":"1522850095"}"#).unwrap())
+            .unwrap();
+        mmds.apply_json_patch(operations).unwrap();
+        assert_eq!(mmds.get_data_str(), r#"{"user-data":"10"}"#);
+
+        let operations: Vec<PatchOperation> =
+            serde_json::from_str(r#"[{"op": "remove", "path": "/meta-data"}]"#).unwrap();
+        assert_eq!(
+            mmds.apply_json_patch(operations).unwrap_err().to_string(),
+            "Cannot apply the JSON patch. The path does not exist: /meta-data."
+        );
+        assert_eq!(mmds.get_data_str(), r#"{"user-data":"10"}"#);
+    }
+
+    #[test]
+    fn test_data_store_limit() {
+        let mut mmds = Mmds::default();
+        assert_eq!(mmds.data_store_limit(), DEFAULT_DATA_STORE_LIMIT);
+        assert_eq!(
+            mmds.status(),
+            DataStoreStatus {
+                size: 0,
+                size_limit: DEFAULT_DATA_STORE_LIMIT,
+                key_count: 0
+            }
+        );
+
+        mmds.set_data_store_limit(30).unwrap();
+        let data = r#"{"meta-data":{"iam":"dummy"}}"#;
+        mmds.put_data(serde_json::from_str(data).unwrap()).unwrap();
+        assert_eq!(
+            mmds.status(),
+            DataStoreStatus {
+                size: 29,
+                size_limit: 30,
+                key_count: 2
+            }
+        );
+
+        let too_large = r#"{"meta-data":{"iam":"dummy"},"a":1}"#;
+        assert_eq!(
+            mmds.put_data(serde_json::from_str(too_large).unwrap())
+                .unwrap_err()
+                .to_string(),
+            "The MMDS data store cannot exceed its size limit of 30 bytes."
+        );
+        assert_eq!(
+            mmds.patch_data(serde_json::from_str(r#"{"a":1}"#).unwrap())
+                .unwrap_err(),
+            Error::DataStoreLimitExceeded(30)
+        );
+        let operations: Vec<PatchOperation> =
+            serde_json::from_str(r#"[{"op": "add", "path": "/a", "value": 1}]"#).unwrap();
+        assert_eq!(
+            mmds.apply_json_patch(operations).unwrap_err(),
+            Error::DataStoreLimitExceeded(30)
+        );
+        // Rejected requests leave the data store untouched.
+        assert_eq!(mmds.get_data_str(), data);
+
+        // The limit cannot go below the current size of the data store.
+        assert_eq!(
+            mmds.set_data_store_limit(28).unwrap_err(),
+            Error::DataStoreLimitExceeded(28)
+        );
+        assert_eq!(mmds.data_store_limit(), 30);
+        mmds.set_data_store_limit(29).unwrap();
+
+        mmds.set_data_store_limit(DEFAULT_DATA_STORE_LIMIT).unwrap();
+        mmds.patch_data(serde_json::from_str(r#"{"a":[{"b":1},2]}"#).unwrap())
+            .unwrap();
+        assert_eq!(mmds.status().key_count, 4);
+    }
+
+    #[test]
+    fn test_mmds_version() {
+        let mut mmds = Mmds::default();
+        assert_eq!(mmds.version(), MmdsVersion::V1);
+        mmds.set_version(MmdsVersion::V2);
+        assert_eq!(mmds.version(), MmdsVersion::V2);
+        assert_eq!(mmds.version().to_string(), "V2");
+
+        assert!(!mmds.is_valid_token("foo"));
+        let token = mmds.generate_token(60).unwrap();
+        assert!(mmds.is_valid_token(&token));
+        assert!(mmds.generate_token(0).is_err());
+    }
+
     #[test]
     fn test_get_value() {
         let mut mmds = Mmds::default();
@@ -343,6 +645,80 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_dynamic_fields() {
+        struct Constant(Value);
+        impl ValueProvider for Constant {
+            fn value(&self) -> Value {
+                self.0.clone()
+            }
+        }
+
+        let mut mmds = Mmds::default();
+        assert_eq!(
+            mmds.register_provider("", Arc::new(Constant(Value::from(1))))
+                .unwrap_err(),
+            Error::InvalidDynamicPath(String::new())
+        );
+        assert_eq!(
+            mmds.register_provider("foo", Arc::new(Constant(Value::from(1))))
+                .unwrap_err()
+                .to_string(),
+            "Invalid path for a dynamic MMDS field: foo. Please provide a JSON pointer to a \
+             location other than the root."
+        );
+
+        // Dynamic fields are served even before the data store is initialized.
+        mmds.register_provider("/meta-data/time", Arc::new(Constant(Value::from(1))))
+            .unwrap();
+        assert_eq!(
+            mmds.get_value("/meta-data/time".to_string(), OutputFormat::Json)
+                .unwrap(),
+            "1"
+        );
+
+        mmds.put_data(serde_json::from_str(r#"{"meta-data":{"iam":"dummy","time":"0"}}"#).unwrap())
+            .unwrap();
+        mmds.register_provider("/meta-data/time", Arc::new(Constant(Value::from("2"))))
+            .unwrap();
+        assert_eq!(
+            mmds.get_value("/meta-data".to_string(), OutputFormat::Json)
+                .unwrap(),
+            r#"{"iam":"dummy","time":"2"}"#
+        );
+        assert_eq!(
+            mmds.get_value("/meta-data/time".to_string(), OutputFormat::Imds)
+                .unwrap(),
+            "2"
+        );
+        // The stored values are left untouched.
+        assert_eq!(
+            mmds.get_data_str(),
+            r#"{"meta-data":{"iam":"dummy","time":"0"}}"#
+        );
+
+        // Invalid paths leave the registered providers untouched.
+        let constant: Arc<dyn ValueProvider> = Arc::new(Constant(Value::from(1)));
+        assert!(mmds
+            .set_providers(vec![
+                ("/a".to_string(), constant.clone()),
+                ("b".to_string(), constant),
+            ])
+            .is_err());
+        assert_eq!(
+            mmds.get_value("/meta-data/time".to_string(), OutputFormat::Json)
+                .unwrap(),
+            r#""2""#
+        );
+
+        mmds.set_providers(Vec::new()).unwrap();
+        assert_eq!(
+            mmds.get_value("/meta-data/time".to_string(), OutputFormat::Json)
+                .unwrap(),
+            r#""0""#
+        );
+    }
+
     #[test]
     fn test_update_data_store() {
         let mut mmds = Mmds::default();
@@ -0,0 +1,118 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Values of the MMDS data store which are computed when the guest requests them.
+//!
+//! A `ValueProvider` is registered for a JSON pointer, and every `GET` request going through
+//! that location sees the value produced at request time, in place of whatever is stored there.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use utils::time::{get_time_us, ClockType};
+
+const MICROS_PER_SECOND: u64 = 1_000_000;
+
+/// Produces the value of a dynamic MMDS field.
+///
+/// Embedders can implement it for values Firecracker has no built-in support for, such as an
+/// instance identity document signed with their own key.
+pub trait ValueProvider: Send + Sync {
+    /// Returns the current value of the field.
+    fn value(&self) -> Value;
+}
+
+/// The dynamic values built into Firecracker.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum DynamicValue {
+    /// The current wall-clock time, in seconds since the Unix epoch.
+    EpochTime,
+    /// The number of seconds left until `expires_at`, in seconds since the Unix epoch. It stays
+    /// at zero once the lease has expired.
+    LeaseTtl { expires_at: u64 },
+}
+
+impl ValueProvider for DynamicValue {
+    fn value(&self) -> Value {
+        let now = get_time_us(ClockType::Real) / MICROS_PER_SECOND;
+        match self {
+            DynamicValue::EpochTime => Value::from(now),
+            DynamicValue::LeaseTtl { expires_at } => Value::from(expires_at.saturating_sub(now)),
+        }
+    }
+}
+
+/// Stores `value` at the location given by the reference `tokens` of a JSON pointer, creating
+/// the missing parent objects along the way. Parents which are not objects are replaced.
+pub(crate) fn insert_value(doc: &mut Value, tokens: &[String], value: Value) {
+    let mut target = doc;
+    for token in tokens {
+        if !target.is_object() {
+            *target = Value::Object(Map::new());
+        }
+        // This is safe since we make sure `target` is an object beforehand.
+        target = target
+            .as_object_mut()
+            .unwrap()
+            .entry(token.as_str())
+            .or_insert(Value::Null);
+    }
+
+    *target = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dynamic_values() {
+        let before = get_time_us(ClockType::Real) / MICROS_PER_SECOND;
+        let now = DynamicValue::EpochTime.value().as_u64().unwrap();
+        assert!(now >= before);
+
+        let ttl = DynamicValue::LeaseTtl {
+            expires_at: now + 3600,
+        }
+        .value()
+        .as_u64()
+        .unwrap();
+        assert!(ttl > 3500 && ttl <= 3600);
+        assert_eq!(DynamicValue::LeaseTtl { expires_at: 0 }.value(), 0);
+
+        let value: DynamicValue =
+            serde_json::from_str(r#"{"type": "LeaseTtl", "expires_at": 10}"#).unwrap();
+        assert_eq!(value, DynamicValue::LeaseTtl { expires_at: 10 });
+        assert!(serde_json::from_str::<DynamicValue>(r#"{"type": "Foo"}"#).is_err());
+    }
+
+    #[test]
+    fn test_insert_value() {
+        let mut doc = serde_json::json!({"meta-data": {"iam": "dummy"}, "user-data": "10"});
+
+        insert_value(
+            &mut doc,
+            &["meta-data".to_string(), "time".to_string()],
+            Value::from(1),
+        );
+        insert_value(
+            &mut doc,
+            &["user-data".to_string(), "ttl".to_string()],
+            Value::from(2),
+        );
+        insert_value(&mut doc, &["new".to_string()], Value::from(3));
+        assert_eq!(
+            doc,
+            serde_json::json!({
+                "meta-data": {"iam": "dummy", "time": 1},
+                "user-data": {"ttl": 2},
+                "new": 3
+            })
+        );
+
+        let mut doc = Value::Null;
+        insert_value(&mut doc, &["a".to_string()], Value::from(1));
+        assert_eq!(doc, serde_json::json!({"a": 1}));
+    }
+}
@@ -2,13 +2,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod data_store;
+pub mod dynamic;
 pub mod ns;
+pub mod patch;
 pub mod persist;
+pub mod token;
 
 use serde_json::{Map, Value};
 use std::sync::{Arc, Mutex};
 
-use crate::data_store::{Error as MmdsError, Mmds, OutputFormat};
+use crate::data_store::{Error as MmdsError, Mmds, MmdsVersion, OutputFormat};
+use crate::token::{
+    Error as TokenError, X_METADATA_TOKEN_HEADER, X_METADATA_TOKEN_TTL_SECONDS_HEADER,
+};
 use lazy_static::lazy_static;
 use micro_http::{Body, MediaType, Method, Request, Response, StatusCode, Version};
 
@@ -19,10 +25,15 @@ lazy_static! {
     pub static ref MMDS: Arc<Mutex<Mmds>> = Arc::new(Mutex::new(Mmds::default()));
 }
 
+/// The path on which the guest obtains session tokens.
+const TOKEN_PATH: &str = "/latest/api/token";
+/// Header added by proxies, which marks requests that did not originate in the guest.
+const X_FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
 impl Into<OutputFormat> for MediaType {
     fn into(self) -> OutputFormat {
         match self {
-            MediaType::ApplicationJson => OutputFormat::Json,
+            MediaType::ApplicationJson | MediaType::ApplicationJsonPatch => OutputFormat::Json,
             MediaType::PlainText => OutputFormat::Imds,
         }
     }
@@ -76,7 +87,8 @@ fn sanitize_uri(mut uri: String) -> String {
     uri
 }
 
-fn convert_to_response(request: Request) -> Response {
+/// Builds the response to an MMDS request made by the guest.
+pub fn convert_to_response(request: Request) -> Response {
     let uri = request.uri().get_abs_path();
     if uri.is_empty() {
         return build_response(
@@ -86,26 +98,53 @@ fn convert_to_response(request: Request) -> Response {
         );
     }
 
-    if request.method() != Method::Get {
-        let mut response = build_response(
-            request.http_version(),
-            StatusCode::MethodNotAllowed,
-            Body::new("Not allowed HTTP method."),
-        );
-        response.allow_method(Method::Get);
-        return response;
+    match request.method() {
+        Method::Get => respond_to_get_request(request),
+        Method::Put => respond_to_put_request(request),
+        _ => {
+            let mut response = build_response(
+                request.http_version(),
+                StatusCode::MethodNotAllowed,
+                Body::new("Not allowed HTTP method."),
+            );
+            response.allow_method(Method::Get);
+            response.allow_method(Method::Put);
+            response
+        }
+    }
+}
+
+fn respond_to_get_request(request: Request) -> Response {
+    let uri = request.uri().get_abs_path();
+
+    // The lock can be held by one thread only, so it is safe to unwrap.
+    // If another thread poisoned the lock, we abort the execution.
+    let mmds = MMDS.lock().expect("Poisoned lock");
+
+    // A token is required in V2 mode, and has to be valid whenever present.
+    match request.headers.custom_entry(X_METADATA_TOKEN_HEADER) {
+        Some(token) if !mmds.is_valid_token(token) => {
+            return build_response(
+                request.http_version(),
+                StatusCode::Unauthorized,
+                Body::new("The MMDS session token is invalid or expired."),
+            );
+        }
+        None if mmds.version() == MmdsVersion::V2 => {
+            return build_response(
+                request.http_version(),
+                StatusCode::Unauthorized,
+                Body::new("The MMDS session token is missing."),
+            );
+        }
+        _ => (),
     }
 
     // The data store expects a strict json path, so we need to
     // sanitize the URI.
     let json_pointer = sanitize_uri(uri.to_string());
 
-    // The lock can be held by one thread only, so it is safe to unwrap.
-    // If another thread poisoned the lock, we abort the execution.
-    let response = MMDS
-        .lock()
-        .expect("Poisoned lock")
-        .get_value(json_pointer, request.headers.accept().into());
+    let response = mmds.get_value(json_pointer, request.headers.accept().into());
 
     match response {
         Ok(response_body) => build_response(
@@ -127,11 +166,76 @@ fn convert_to_response(request: Request) -> Response {
                 StatusCode::NotImplemented,
                 Body::new(e.to_string()),
             ),
-            MmdsError::NotInitialized => unreachable!(),
+            MmdsError::DataStoreLimitExceeded(_)
+            | MmdsError::NotInitialized
+            | MmdsError::InvalidDynamicPath(_)
+            | MmdsError::InvalidPatch(_) => unreachable!(),
         },
     }
 }
 
+fn respond_to_put_request(request: Request) -> Response {
+    let uri = request.uri().get_abs_path();
+    if sanitize_uri(uri.to_string()) != TOKEN_PATH {
+        let error_msg = format!("Resource not found: {}.", uri);
+        return build_response(
+            request.http_version(),
+            StatusCode::NotFound,
+            Body::new(error_msg),
+        );
+    }
+
+    // Requests going through a proxy might not originate in the guest, so they don't get tokens.
+    if request
+        .headers
+        .custom_entry(X_FORWARDED_FOR_HEADER)
+        .is_some()
+    {
+        return build_response(
+            request.http_version(),
+            StatusCode::Forbidden,
+            Body::new("MMDS session tokens cannot be requested through a proxy."),
+        );
+    }
+
+    let ttl_seconds = match request
+        .headers
+        .custom_entry(X_METADATA_TOKEN_TTL_SECONDS_HEADER)
+        .map(str::parse::<u32>)
+    {
+        Some(Ok(ttl_seconds)) => ttl_seconds,
+        _ => {
+            let error_msg = format!(
+                "Invalid or missing {} header.",
+                X_METADATA_TOKEN_TTL_SECONDS_HEADER
+            );
+            return build_response(
+                request.http_version(),
+                StatusCode::BadRequest,
+                Body::new(error_msg),
+            );
+        }
+    };
+
+    match MMDS
+        .lock()
+        .expect("Poisoned lock")
+        .generate_token(ttl_seconds)
+    {
+        Ok(token) => build_response(request.http_version(), StatusCode::OK, Body::new(token)),
+        Err(e @ TokenError::InvalidTtlValue(_)) => build_response(
+            request.http_version(),
+            StatusCode::BadRequest,
+            Body::new(e.to_string()),
+        ),
+        Err(e @ TokenError::EntropyUnavailable(_)) => build_response(
+            request.http_version(),
+            StatusCode::InternalServerError,
+            Body::new(e.to_string()),
+        ),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -194,17 +298,14 @@ mod tests {
         assert_eq!(actual_response, expected_response);
 
         // Test not allowed HTTP Method.
-        let not_allowed_methods = ["PUT", "PATCH"];
-        for method in not_allowed_methods.iter() {
-            let request_bytes = format!("{} http://169.254.169.255/ HTTP/1.0\r\n\r\n", method);
-            let request = Request::try_from(request_bytes.as_bytes()).unwrap();
-            let mut expected_response =
-                Response::new(Version::Http10, StatusCode::MethodNotAllowed);
-            expected_response.set_body(Body::new("Not allowed HTTP method.".to_string()));
-            expected_response.allow_method(Method::Get);
-            let actual_response = convert_to_response(request);
-            assert_eq!(actual_response, expected_response);
-        }
+        let request_bytes = b"PATCH http://169.254.169.255/ HTTP/1.0\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::MethodNotAllowed);
+        expected_response.set_body(Body::new("Not allowed HTTP method.".to_string()));
+        expected_response.allow_method(Method::Get);
+        expected_response.allow_method(Method::Put);
+        let actual_response = convert_to_response(request);
+        assert_eq!(actual_response, expected_response);
 
         // Test invalid (empty absolute path) URI.
         let request_bytes = b"GET http:// HTTP/1.0\r\n\r\n";
@@ -238,6 +339,72 @@ mod tests {
         expected_response.set_body(Body::new(body));
         let actual_response = convert_to_response(request);
         assert_eq!(actual_response, expected_response);
+
+        // The version-dependent checks are here, since they alter the global MMDS.
+        check_session_tokens();
+    }
+
+    fn check_session_tokens() {
+        // Tokens are only issued on the token path.
+        let request_bytes = b"PUT http://169.254.169.254/latest/api HTTP/1.0\r\n\
+                              X-metadata-token-ttl-seconds: 60\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::NotFound);
+        expected_response.set_body(Body::new("Resource not found: /latest/api.".to_string()));
+        assert_eq!(convert_to_response(request), expected_response);
+
+        // The TTL header is mandatory and has to be within bounds.
+        let request_bytes = b"PUT http://169.254.169.254/latest/api/token HTTP/1.0\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        assert_eq!(
+            convert_to_response(request).status(),
+            StatusCode::BadRequest
+        );
+        let request_bytes = b"PUT http://169.254.169.254/latest/api/token HTTP/1.0\r\n\
+                              X-metadata-token-ttl-seconds: 21601\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let mut expected_response = Response::new(Version::Http10, StatusCode::BadRequest);
+        expected_response.set_body(Body::new(TokenError::InvalidTtlValue(21601).to_string()));
+        assert_eq!(convert_to_response(request), expected_response);
+
+        // Proxied requests don't get tokens.
+        let request_bytes = b"PUT http://169.254.169.254/latest/api/token HTTP/1.0\r\n\
+                              X-metadata-token-ttl-seconds: 60\r\n\
+                              X-Forwarded-For: 10.0.0.1\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        assert_eq!(convert_to_response(request).status(), StatusCode::Forbidden);
+
+        let request_bytes = b"PUT http://169.254.169.254//latest/api/token HTTP/1.0\r\n\
+                              x-metadata-token-ttl-seconds: 60\r\n\r\n";
+        let request = Request::try_from(request_bytes).unwrap();
+        let response = convert_to_response(request);
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = String::from_utf8(response.body().unwrap().body).unwrap();
+
+        let get_with_token = |token: &str| {
+            let request_bytes = format!(
+                "GET http://169.254.169.254/name/first HTTP/1.0\r\n\
+                 X-metadata-token: {}\r\n\r\n",
+                token
+            );
+            convert_to_response(Request::try_from(request_bytes.as_bytes()).unwrap()).status()
+        };
+        let get_without_token = || {
+            let request_bytes = b"GET http://169.254.169.254/name/first HTTP/1.0\r\n\r\n";
+            convert_to_response(Request::try_from(request_bytes).unwrap()).status()
+        };
+
+        // In V1 mode, tokens are optional but have to be valid when present.
+        assert_eq!(get_without_token(), StatusCode::OK);
+        assert_eq!(get_with_token(&token), StatusCode::OK);
+        assert_eq!(get_with_token("foo"), StatusCode::Unauthorized);
+
+        // In V2 mode, tokens are mandatory.
+        MMDS.lock().unwrap().set_version(MmdsVersion::V2);
+        assert_eq!(get_without_token(), StatusCode::Unauthorized);
+        assert_eq!(get_with_token(&token), StatusCode::OK);
+        assert_eq!(get_with_token("foo"), StatusCode::Unauthorized);
+        MMDS.lock().unwrap().set_version(MmdsVersion::V1);
     }
 
     #[test]
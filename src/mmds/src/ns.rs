@@ -5,7 +5,7 @@
 #![allow(missing_docs)]
 
 use std::convert::From;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
 use std::result::Result;
 
@@ -13,14 +13,22 @@ use dumbo::pdu::arp::{
     test_speculative_tpa, Error as ArpFrameError, EthIPv4ArpFrame, ETH_IPV4_FRAME_LEN,
 };
 use dumbo::pdu::ethernet::{
-    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4,
+    Error as EthernetFrameError, EthernetFrame, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6,
+};
+use dumbo::pdu::icmpv6::{
+    Error as Icmpv6MessageError, Icmpv6Message, NA_FLAG_OVERRIDE, NA_FLAG_SOLICITED, ND_HOP_LIMIT,
+    OPTION_SOURCE_LINK_LAYER_ADDRESS,
 };
 use dumbo::pdu::ipv4::{
     test_speculative_dst_addr, Error as IPv4PacketError, IPv4Packet, PROTOCOL_TCP,
 };
+use dumbo::pdu::ipv6::{
+    self, solicited_node_multicast_address, Error as Ipv6PacketError, Ipv6Packet, IPV6_VERSION,
+    PROTOCOL_ICMPV6,
+};
 use dumbo::pdu::tcp::Error as TcpSegmentError;
 use dumbo::pdu::Incomplete;
-use dumbo::tcp::handler::{self, RecvEvent, TcpIPv4Handler, WriteEvent};
+use dumbo::tcp::handler::{self, RecvError, RecvEvent, TcpIPv4Handler, WriteEvent};
 use dumbo::tcp::NextSegmentStatus;
 use logger::{IncMetric, METRICS};
 use utils::net::mac::MacAddr;
@@ -39,9 +47,18 @@ enum WriteArpFrameError {
     Ethernet(EthernetFrameError),
 }
 
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum WriteNdpFrameError {
+    NoPendingNdpReply,
+    Icmpv6(Icmpv6MessageError),
+    Ipv6Packet(Ipv6PacketError),
+    Ethernet(EthernetFrameError),
+}
+
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum WritePacketError {
     IPv4Packet(IPv4PacketError),
+    Ipv6Packet(Ipv6PacketError),
     Ethernet(EthernetFrameError),
     TcpSegment(TcpSegmentError),
 }
@@ -50,6 +67,7 @@ impl From<handler::WriteNextError> for WritePacketError {
     fn from(error: handler::WriteNextError) -> Self {
         match error {
             handler::WriteNextError::IPv4Packet(inner) => WritePacketError::IPv4Packet(inner),
+            handler::WriteNextError::Ipv6Packet(inner) => WritePacketError::Ipv6Packet(inner),
             handler::WriteNextError::TcpSegment(inner) => WritePacketError::TcpSegment(inner),
         }
     }
@@ -66,6 +84,11 @@ pub struct MmdsNetworkStack {
     // It is the Ipv4Addr of the network interface for which the MmdsNetworkStack
     // routes the packets.
     pending_arp_reply_dest: Option<Ipv4Addr>,
+    // MMDS server IPv6 address. The MMDS is not reachable over IPv6 when this is missing.
+    pub(crate) ipv6_addr: Option<Ipv6Addr>,
+    // Neighbor Advertisement destination IPv6 address (sender of the Neighbor Solicitation, or
+    // the all-nodes multicast address when the sender has no address yet).
+    pending_ndp_reply_dest: Option<Ipv6Addr>,
     // This handles MMDS<->guest interaction at the TCP level.
     pub(crate) tcp_handler: TcpIPv4Handler,
 }
@@ -83,6 +106,8 @@ impl MmdsNetworkStack {
             mac_addr,
             ipv4_addr,
             pending_arp_reply_dest: None,
+            ipv6_addr: None,
+            pending_ndp_reply_dest: None,
             tcp_handler: TcpIPv4Handler::new(
                 ipv4_addr,
                 tcp_port,
@@ -112,6 +137,33 @@ impl MmdsNetworkStack {
         self.tcp_handler.set_local_ipv4_addr(ipv4_addr);
     }
 
+    pub fn ipv4_addr(&self) -> Ipv4Addr {
+        self.ipv4_addr
+    }
+
+    /// Sets the IPv6 address of the MMDS, which makes it reachable by IPv6-only guests as well.
+    /// Passing `None` disables IPv6 support.
+    pub fn set_ipv6_addr(&mut self, ipv6_addr: Option<Ipv6Addr>) {
+        self.ipv6_addr = ipv6_addr;
+        self.pending_ndp_reply_dest = None;
+        self.tcp_handler.set_local_ipv6_addr(ipv6_addr);
+    }
+
+    pub fn ipv6_addr(&self) -> Option<Ipv6Addr> {
+        self.ipv6_addr
+    }
+
+    /// Sets the IPv4 TTL of the packets sent by the MMDS, which limits the number of hops
+    /// the responses can travel past the guest interface.
+    pub fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.tcp_handler.set_ttl(hop_limit);
+    }
+
+    /// Returns the IPv4 TTL of the packets sent by the MMDS.
+    pub fn hop_limit(&self) -> u8 {
+        self.tcp_handler.ttl()
+    }
+
     pub fn default_ipv4_addr() -> Ipv4Addr {
         Ipv4Addr::from(DEFAULT_IPV4_ADDR)
     }
@@ -119,9 +171,11 @@ impl MmdsNetworkStack {
     // This is the entry point into the MMDS network stack. The src slice should hold the contents
     // of an Ethernet frame (of that exact size, without the CRC).
     pub fn detour_frame(&mut self, src: &[u8]) -> bool {
-        // The frame cannot possibly contain an ARP request or IPv4 packet for the MMDS.
+        // The frame cannot possibly contain an ARP request, Neighbor Solicitation, or IP packet
+        // for the MMDS.
         if !test_speculative_tpa(src, self.ipv4_addr)
             && !test_speculative_dst_addr(src, self.ipv4_addr)
+            && !self.test_speculative_ipv6_dst_addr(src)
         {
             return false;
         }
@@ -130,6 +184,7 @@ impl MmdsNetworkStack {
             match eth.ethertype() {
                 ETHERTYPE_ARP => return self.detour_arp(eth),
                 ETHERTYPE_IPV4 => return self.detour_ipv4(eth),
+                ETHERTYPE_IPV6 => return self.detour_ipv6(eth),
                 _ => (),
             };
         } else {
@@ -160,28 +215,10 @@ impl MmdsNetworkStack {
                 // Note-2: For every routed packet we will have a single source MAC address, because
                 // each MmdsNetworkStack routes packets for only one network device.
                 self.remote_mac_addr = eth.src_mac();
-                match self
+                let result = self
                     .tcp_handler
-                    .receive_packet(&ip, super::convert_to_response)
-                {
-                    Ok(event) => {
-                        METRICS.mmds.rx_count.inc();
-                        match event {
-                            RecvEvent::NewConnectionSuccessful => {
-                                METRICS.mmds.connections_created.inc()
-                            }
-                            RecvEvent::NewConnectionReplacing => {
-                                METRICS.mmds.connections_created.inc();
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            RecvEvent::EndpointDone => {
-                                METRICS.mmds.connections_destroyed.inc();
-                            }
-                            _ => (),
-                        }
-                    }
-                    Err(_) => METRICS.mmds.rx_accepted_err.inc(),
-                }
+                    .receive_packet(&ip, super::convert_to_response);
+                Self::record_recv_result(result);
             } else {
                 // A non-TCP IPv4 packet heading towards the MMDS; we consider it unusual.
                 METRICS.mmds.rx_accepted_unusual.inc();
@@ -192,12 +229,108 @@ impl MmdsNetworkStack {
         false
     }
 
+    fn test_speculative_ipv6_dst_addr(&self, src: &[u8]) -> bool {
+        match self.ipv6_addr {
+            Some(addr) => {
+                ipv6::test_speculative_dst_addr(src, addr)
+                    || ipv6::test_speculative_dst_addr(src, solicited_node_multicast_address(addr))
+            }
+            None => false,
+        }
+    }
+
+    fn detour_ipv6(&mut self, eth: EthernetFrame<&[u8]>) -> bool {
+        let ipv6_addr = match self.ipv6_addr {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+        // There's no header checksum to verify for IPv6 packets, and we skip verifying the
+        // ICMPv6/TCP checksums for the same reasons as in detour_ipv4.
+        let ip = match Ipv6Packet::from_bytes(eth.payload()) {
+            Ok(ip) => ip,
+            Err(_) => return false,
+        };
+
+        if ip.next_header() == PROTOCOL_ICMPV6 {
+            return self.detour_ndp(eth.src_mac(), &ip, ipv6_addr);
+        }
+
+        if ip.destination_address() != ipv6_addr {
+            // Only Neighbor Solicitations are accepted on the solicited-node multicast address.
+            return false;
+        }
+
+        if ip.next_header() == PROTOCOL_TCP {
+            self.remote_mac_addr = eth.src_mac();
+            let result = self
+                .tcp_handler
+                .receive_ipv6_packet(&ip, super::convert_to_response);
+            Self::record_recv_result(result);
+        } else {
+            // A non-TCP IPv6 packet heading towards the MMDS; we consider it unusual.
+            METRICS.mmds.rx_accepted_unusual.inc();
+        }
+        true
+    }
+
+    fn detour_ndp(
+        &mut self,
+        src_mac: MacAddr,
+        ip: &Ipv6Packet<&[u8]>,
+        ipv6_addr: Ipv6Addr,
+    ) -> bool {
+        // Neighbor Discovery messages which may have been forwarded by a router are invalid.
+        if ip.hop_limit() != ND_HOP_LIMIT {
+            return false;
+        }
+
+        if let Ok(message) = Icmpv6Message::from_bytes(ip.payload(), None) {
+            if message.neighbor_solicitation_target() == Some(ipv6_addr) {
+                self.remote_mac_addr = message
+                    .link_layer_address_option(OPTION_SOURCE_LINK_LAYER_ADDRESS)
+                    .unwrap_or(src_mac);
+                let src_addr = ip.source_address();
+                // Solicitations sent during Duplicate Address Detection come from the unspecified
+                // address, and are answered to all the nodes on the link.
+                self.pending_ndp_reply_dest = Some(if src_addr.is_unspecified() {
+                    Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1)
+                } else {
+                    src_addr
+                });
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn record_recv_result(result: Result<RecvEvent, RecvError>) {
+        match result {
+            Ok(event) => {
+                METRICS.mmds.rx_count.inc();
+                match event {
+                    RecvEvent::NewConnectionSuccessful => METRICS.mmds.connections_created.inc(),
+                    RecvEvent::NewConnectionReplacing => {
+                        METRICS.mmds.connections_created.inc();
+                        METRICS.mmds.connections_destroyed.inc();
+                    }
+                    RecvEvent::EndpointDone => {
+                        METRICS.mmds.connections_destroyed.inc();
+                    }
+                    _ => (),
+                }
+            }
+            Err(_) => METRICS.mmds.rx_accepted_err.inc(),
+        }
+    }
+
     // Allows the MMDS network stack to write a frame to the specified buffer. Will return:
     // - None, if the MMDS network stack has no frame to send at this point. The buffer can be
     // used for something else by the device model.
     // - Some(len), if a frame of the given length has been written to the specified buffer.
     pub fn write_next_frame(&mut self, buf: &mut [u8]) -> Option<NonZeroUsize> {
-        // We try to send ARP replies first.
+        // We try to send ARP replies and Neighbor Advertisements first.
         if self.pending_arp_reply_dest.is_some() {
             return match self.write_arp_reply(buf) {
                 Ok(something) => {
@@ -210,6 +343,18 @@ impl MmdsNetworkStack {
                     None
                 }
             };
+        } else if self.pending_ndp_reply_dest.is_some() {
+            return match self.write_ndp_reply(buf) {
+                Ok(something) => {
+                    METRICS.mmds.tx_count.inc();
+                    self.pending_ndp_reply_dest = None;
+                    something
+                }
+                Err(_) => {
+                    METRICS.mmds.tx_errors.inc();
+                    None
+                }
+            };
         } else {
             let call_write = match self.tcp_handler.next_segment_status() {
                 NextSegmentStatus::Available => true,
@@ -270,6 +415,54 @@ impl MmdsNetworkStack {
         ))
     }
 
+    fn write_ndp_reply(&self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WriteNdpFrameError> {
+        let ndp_reply_dest = self
+            .pending_ndp_reply_dest
+            .ok_or_else(|| WriteNdpFrameError::NoPendingNdpReply)?;
+        let ipv6_addr = self
+            .ipv6_addr
+            .ok_or_else(|| WriteNdpFrameError::NoPendingNdpReply)?;
+
+        let mut eth_unsized = self
+            .prepare_eth_unsized(buf, ETHERTYPE_IPV6)
+            .map_err(WriteNdpFrameError::Ethernet)?;
+
+        let packet_len = {
+            let mut packet = Ipv6Packet::write_header(
+                eth_unsized.inner_mut().payload_mut(),
+                PROTOCOL_ICMPV6,
+                ipv6_addr,
+                ndp_reply_dest,
+            )
+            .map_err(WriteNdpFrameError::Ipv6Packet)?;
+            packet.inner_mut().set_hop_limit(ND_HOP_LIMIT);
+
+            // Unsolicited advertisements go to the all-nodes multicast address.
+            let flags = if ndp_reply_dest.is_multicast() {
+                NA_FLAG_OVERRIDE
+            } else {
+                NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE
+            };
+            let message_len = Icmpv6Message::write_neighbor_advertisement(
+                packet.inner_mut().payload_mut(),
+                ipv6_addr,
+                ndp_reply_dest,
+                flags,
+                ipv6_addr,
+                self.mac_addr,
+            )
+            .map_err(WriteNdpFrameError::Icmpv6)?
+            .len();
+
+            packet.with_payload_len_unchecked(message_len).len()
+        };
+
+        Ok(Some(
+            // The unwrap() is safe because packet_len > 0.
+            NonZeroUsize::new(eth_unsized.with_payload_len_unchecked(packet_len).len()).unwrap(),
+        ))
+    }
+
     fn write_packet(&mut self, buf: &mut [u8]) -> Result<Option<NonZeroUsize>, WritePacketError> {
         let mut eth_unsized = self
             .prepare_eth_unsized(buf, ETHERTYPE_IPV4)
@@ -284,6 +477,10 @@ impl MmdsNetworkStack {
         }
 
         if let Some(packet_len) = maybe_len {
+            // The handler writes either IPv4 or IPv6 packets, depending on the connection.
+            if eth_unsized.inner().payload()[0] >> 4 == IPV6_VERSION {
+                eth_unsized.inner_mut().set_ethertype(ETHERTYPE_IPV6);
+            }
             return Ok(Some(
                 // The unwrap() is safe because packet_len > 0.
                 NonZeroUsize::new(
@@ -304,6 +501,9 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use dumbo::pdu::icmpv6::{
+        OPTION_TARGET_LINK_LAYER_ADDRESS, TYPE_NEIGHBOR_ADVERTISEMENT, TYPE_NEIGHBOR_SOLICITATION,
+    };
     use dumbo::pdu::tcp::{Flags as TcpFlags, TcpSegment};
 
     // We use LOCALHOST here because const new() is not stable yet, so just reuse this const, since
@@ -364,7 +564,11 @@ mod tests {
                     None,
                 )
                 .unwrap()
-                .finalize(REMOTE_PORT, MMDS_PORT, Some((REMOTE_ADDR, addr)))
+                .finalize(
+                    REMOTE_PORT,
+                    MMDS_PORT,
+                    Some((REMOTE_ADDR.into(), addr.into())),
+                )
                 .len();
 
                 packet.with_payload_len_unchecked(segment_len, true).len()
@@ -473,7 +677,7 @@ mod tests {
 
             let s = TcpSegment::from_bytes(
                 ip.payload(),
-                Some((ip.source_address(), ip.destination_address())),
+                Some((ip.source_address().into(), ip.destination_address().into())),
             )
             .unwrap();
             assert_eq!(s.flags_after_ns(), TcpFlags::RST);
@@ -498,7 +702,7 @@ mod tests {
 
             let s = TcpSegment::from_bytes(
                 ip.payload(),
-                Some((ip.source_address(), ip.destination_address())),
+                Some((ip.source_address().into(), ip.destination_address().into())),
             )
             .unwrap();
             assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
@@ -511,6 +715,154 @@ mod tests {
         assert!(ns.write_next_frame(buf.as_mut()).is_none());
     }
 
+    fn write_neighbor_solicitation(buf: &mut [u8], src_addr: Ipv6Addr, target: Ipv6Addr) -> usize {
+        let remote_mac = MacAddr::parse_str(REMOTE_MAC_STR).unwrap();
+        let dst_addr = solicited_node_multicast_address(target);
+        let mut eth_unsized = EthernetFrame::write_incomplete(
+            buf,
+            MacAddr::parse_str("33:33:ff:00:02:54").unwrap(),
+            remote_mac,
+            ETHERTYPE_IPV6,
+        )
+        .unwrap();
+        let packet_len = {
+            let mut packet = Ipv6Packet::write_header(
+                eth_unsized.inner_mut().payload_mut(),
+                PROTOCOL_ICMPV6,
+                src_addr,
+                dst_addr,
+            )
+            .unwrap();
+            packet.inner_mut().set_hop_limit(ND_HOP_LIMIT);
+            // Build the solicitation out of an advertisement, since only the latter can be
+            // written directly.
+            let message_len = {
+                let payload = packet.inner_mut().payload_mut();
+                let len = Icmpv6Message::write_neighbor_advertisement(
+                    &mut payload[..],
+                    src_addr,
+                    dst_addr,
+                    0,
+                    target,
+                    remote_mac,
+                )
+                .unwrap()
+                .len();
+                payload[0] = TYPE_NEIGHBOR_SOLICITATION;
+                payload[4] = 0;
+                payload[24] = OPTION_SOURCE_LINK_LAYER_ADDRESS;
+                len
+            };
+            packet.with_payload_len_unchecked(message_len).len()
+        };
+        eth_unsized.with_payload_len_unchecked(packet_len).len()
+    }
+
+    #[test]
+    fn test_ns_ipv6() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        let mut buf = [0u8; 2000];
+
+        let remote_mac = MacAddr::parse_str(REMOTE_MAC_STR).unwrap();
+        let remote_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 2);
+        let mmds_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+
+        // IPv6 is disabled by default.
+        let len = write_neighbor_solicitation(buf.as_mut(), remote_addr, mmds_addr);
+        assert!(!ns.detour_frame(&buf[..len]));
+
+        ns.set_ipv6_addr(Some(mmds_addr));
+        assert_eq!(ns.ipv6_addr(), Some(mmds_addr));
+        assert_eq!(ns.tcp_handler.local_ipv6_addr(), Some(mmds_addr));
+
+        // Solicitations for other addresses are not for the MMDS.
+        let other_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 1, 0x254);
+        let len = write_neighbor_solicitation(buf.as_mut(), remote_addr, other_addr);
+        assert!(!ns.detour_frame(&buf[..len]));
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // A solicitation for the MMDS address gets a Neighbor Advertisement in response.
+        let len = write_neighbor_solicitation(buf.as_mut(), remote_addr, mmds_addr);
+        assert!(ns.detour_frame(&buf[..len]));
+        assert_eq!(ns.remote_mac_addr, remote_mac);
+        {
+            let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+            assert_eq!(eth.dst_mac(), remote_mac);
+            let ip = Ipv6Packet::from_bytes(eth.payload()).unwrap();
+            assert_eq!(ip.source_address(), mmds_addr);
+            assert_eq!(ip.destination_address(), remote_addr);
+            assert_eq!(ip.hop_limit(), ND_HOP_LIMIT);
+            let message =
+                Icmpv6Message::from_bytes(ip.payload(), Some((mmds_addr, remote_addr))).unwrap();
+            assert_eq!(message.message_type(), TYPE_NEIGHBOR_ADVERTISEMENT);
+            assert_eq!(
+                message.link_layer_address_option(OPTION_TARGET_LINK_LAYER_ADDRESS),
+                Some(ns.mac_addr)
+            );
+        }
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // Let's send a TCP SYN over IPv6.
+        let len = {
+            let mut eth_unsized = ns
+                .prepare_eth_unsized(buf.as_mut(), ETHERTYPE_IPV6)
+                .unwrap();
+            let packet_len = {
+                let mut packet = Ipv6Packet::write_header(
+                    eth_unsized.inner_mut().payload_mut(),
+                    PROTOCOL_TCP,
+                    remote_addr,
+                    mmds_addr,
+                )
+                .unwrap();
+                let segment_len = TcpSegment::write_incomplete_segment::<[u8]>(
+                    packet.inner_mut().payload_mut(),
+                    SEQ_NUMBER,
+                    1234,
+                    TcpFlags::SYN,
+                    10000,
+                    None,
+                    0,
+                    None,
+                )
+                .unwrap()
+                .finalize(
+                    REMOTE_PORT,
+                    MMDS_PORT,
+                    Some((remote_addr.into(), mmds_addr.into())),
+                )
+                .len();
+                packet.with_payload_len_unchecked(segment_len).len()
+            };
+            eth_unsized.with_payload_len_unchecked(packet_len).len()
+        };
+        assert!(ns.detour_frame(&buf[..len]));
+
+        // We should be getting a SYNACK over IPv6 in response.
+        {
+            let len = ns.write_next_frame(buf.as_mut()).unwrap().get();
+            let eth = EthernetFrame::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(eth.ethertype(), ETHERTYPE_IPV6);
+            let ip = Ipv6Packet::from_bytes(eth.payload()).unwrap();
+            assert_eq!(ip.source_address(), mmds_addr);
+            assert_eq!(ip.destination_address(), remote_addr);
+            let s =
+                TcpSegment::from_bytes(ip.payload(), Some((mmds_addr.into(), remote_addr.into())))
+                    .unwrap();
+            assert_eq!(s.flags_after_ns(), TcpFlags::SYN | TcpFlags::ACK);
+            assert_eq!(s.ack_number(), SEQ_NUMBER.wrapping_add(1));
+        }
+        assert!(ns.write_next_frame(buf.as_mut()).is_none());
+
+        // The MMDS stops answering over IPv6 once it gets disabled.
+        ns.set_ipv6_addr(None);
+        assert_eq!(ns.tcp_handler.local_ipv6_addr(), None);
+        let len = write_neighbor_solicitation(buf.as_mut(), remote_addr, mmds_addr);
+        assert!(!ns.detour_frame(&buf[..len]));
+    }
+
     #[test]
     fn test_set_ipv4_addr() {
         let mut ns = MmdsNetworkStack::new_with_defaults(None);
@@ -521,6 +873,14 @@ mod tests {
         assert_eq!(ns.tcp_handler.local_ipv4_addr(), Ipv4Addr::LOCALHOST);
     }
 
+    #[test]
+    fn test_set_hop_limit() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        ns.set_hop_limit(1);
+        assert_eq!(ns.tcp_handler.ttl(), 1);
+        assert_eq!(ns.hop_limit(), 1);
+    }
+
     #[test]
     fn test_default_ipv4_addr() {
         let actual = MmdsNetworkStack::default_ipv4_addr();
@@ -0,0 +1,393 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! JSON Patch [RFC 6902](https://tools.ietf.org/html/rfc6902) support for the MMDS data store.
+//!
+//! A patch is a list of operations which are applied in order. Either all of them succeed, or
+//! the document is left untouched.
+
+use std::fmt;
+use std::mem;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+/// An operation of a JSON Patch document. Paths are JSON pointers
+/// ([RFC 6901](https://tools.ietf.org/html/rfc6901)).
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    /// Adds `value` at `path`, replacing any existing object member.
+    Add { path: String, value: Value },
+    /// Removes the value at `path`.
+    Remove { path: String },
+    /// Replaces the existing value at `path` with `value`.
+    Replace { path: String, value: Value },
+    /// Removes the value at `from` and adds it at `path`.
+    Move { from: String, path: String },
+    /// Adds a copy of the value at `from` at `path`.
+    Copy { from: String, path: String },
+    /// Checks that the value at `path` is equal to `value`.
+    Test { path: String, value: Value },
+}
+
+/// JSON Patch related errors.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The path is not a valid JSON pointer.
+    InvalidPointer(String),
+    /// The path references a location which does not exist.
+    PathNotFound(String),
+    /// A value cannot be moved into one of its children.
+    MoveIntoChild(String),
+    /// The value at the path differs from the tested one.
+    TestFailed(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidPointer(path) => write!(f, "Invalid JSON pointer: {}.", path),
+            Error::PathNotFound(path) => write!(f, "The path does not exist: {}.", path),
+            Error::MoveIntoChild(path) => {
+                write!(f, "Cannot move a value into one of its children: {}.", path)
+            }
+            Error::TestFailed(path) => write!(f, "Test operation failed for path: {}.", path),
+        }
+    }
+}
+
+/// Applies `operations` to `doc`. If any of them fails, `doc` is left untouched.
+pub fn apply_patch(doc: &mut Value, operations: Vec<PatchOperation>) -> Result<(), Error> {
+    let mut patched = doc.clone();
+    for operation in operations {
+        apply_operation(&mut patched, operation)?;
+    }
+
+    *doc = patched;
+    Ok(())
+}
+
+fn apply_operation(doc: &mut Value, operation: PatchOperation) -> Result<(), Error> {
+    match operation {
+        PatchOperation::Add { path, value } => add(doc, &parse_pointer(&path)?, value, &path),
+        PatchOperation::Remove { path } => remove(doc, &parse_pointer(&path)?, &path).map(|_| ()),
+        PatchOperation::Replace { path, value } => {
+            *resolve_mut(doc, &parse_pointer(&path)?).ok_or(Error::PathNotFound(path))? = value;
+            Ok(())
+        }
+        PatchOperation::Move { from, path } => {
+            let from_tokens = parse_pointer(&from)?;
+            let path_tokens = parse_pointer(&path)?;
+            if path_tokens.len() > from_tokens.len() && path_tokens.starts_with(&from_tokens) {
+                return Err(Error::MoveIntoChild(path));
+            }
+            let value = remove(doc, &from_tokens, &from)?;
+            add(doc, &path_tokens, value, &path)
+        }
+        PatchOperation::Copy { from, path } => {
+            let value = resolve(doc, &parse_pointer(&from)?)
+                .cloned()
+                .ok_or(Error::PathNotFound(from))?;
+            add(doc, &parse_pointer(&path)?, value, &path)
+        }
+        PatchOperation::Test { path, value } => match resolve(doc, &parse_pointer(&path)?) {
+            Some(current) if *current == value => Ok(()),
+            _ => Err(Error::TestFailed(path)),
+        },
+    }
+}
+
+// Splits a JSON pointer into its unescaped reference tokens.
+pub(crate) fn parse_pointer(path: &str) -> Result<Vec<String>, Error> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !path.starts_with('/') {
+        return Err(Error::InvalidPointer(path.to_string()));
+    }
+
+    path[1..]
+        .split('/')
+        .map(|token| unescape(token).ok_or_else(|| Error::InvalidPointer(path.to_string())))
+        .collect()
+}
+
+fn unescape(token: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(token.len());
+    let mut chars = token.chars();
+    while let Some(c) = chars.next() {
+        if c == '~' {
+            match chars.next() {
+                Some('0') => unescaped.push('~'),
+                Some('1') => unescaped.push('/'),
+                _ => return None,
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+
+    Some(unescaped)
+}
+
+// Parses an array index. The "-" token stands for the position past the last element, which is
+// only meaningful when adding values.
+fn array_index(token: &str, len: usize, past_the_end: bool) -> Option<usize> {
+    if token == "-" {
+        return if past_the_end { Some(len) } else { None };
+    }
+    if token.is_empty()
+        || (token.len() > 1 && token.starts_with('0'))
+        || !token.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    match token.parse::<usize>() {
+        Ok(index) if index < len || (past_the_end && index == len) => Some(index),
+        _ => None,
+    }
+}
+
+fn resolve<'a>(doc: &'a Value, tokens: &[String]) -> Option<&'a Value> {
+    tokens.iter().try_fold(doc, |value, token| match value {
+        Value::Object(map) => map.get(token),
+        Value::Array(vec) => array_index(token, vec.len(), false).map(|index| &vec[index]),
+        _ => None,
+    })
+}
+
+fn resolve_mut<'a>(doc: &'a mut Value, tokens: &[String]) -> Option<&'a mut Value> {
+    tokens.iter().try_fold(doc, |value, token| match value {
+        Value::Object(map) => map.get_mut(token),
+        Value::Array(vec) => array_index(token, vec.len(), false).map(move |index| &mut vec[index]),
+        _ => None,
+    })
+}
+
+fn add(doc: &mut Value, tokens: &[String], value: Value, path: &str) -> Result<(), Error> {
+    let (last, parent) = match tokens.split_last() {
+        Some(split) => split,
+        None => {
+            *doc = value;
+            return Ok(());
+        }
+    };
+
+    match resolve_mut(doc, parent) {
+        Some(Value::Object(map)) => {
+            map.insert(last.clone(), value);
+        }
+        Some(Value::Array(vec)) => {
+            let index = array_index(last, vec.len(), true)
+                .ok_or_else(|| Error::PathNotFound(path.to_string()))?;
+            vec.insert(index, value);
+        }
+        _ => return Err(Error::PathNotFound(path.to_string())),
+    }
+
+    Ok(())
+}
+
+fn remove(doc: &mut Value, tokens: &[String], path: &str) -> Result<Value, Error> {
+    let (last, parent) = match tokens.split_last() {
+        Some(split) => split,
+        None => return Ok(mem::replace(doc, Value::Null)),
+    };
+
+    let removed = match resolve_mut(doc, parent) {
+        Some(Value::Object(map)) => map.remove(last),
+        Some(Value::Array(vec)) => {
+            array_index(last, vec.len(), false).map(|index| vec.remove(index))
+        }
+        _ => None,
+    };
+
+    removed.ok_or_else(|| Error::PathNotFound(path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patch(doc: &mut Value, operations: Value) -> Result<(), Error> {
+        apply_patch(doc, serde_json::from_value(operations).unwrap())
+    }
+
+    #[test]
+    fn test_deserialize_operations() {
+        let operations: Vec<PatchOperation> = serde_json::from_str(
+            r#"[
+                {"op": "add", "path": "/a", "value": 1},
+                {"op": "remove", "path": "/a"},
+                {"op": "replace", "path": "/b", "value": "c"},
+                {"op": "move", "from": "/b", "path": "/c"},
+                {"op": "copy", "from": "/c", "path": "/d"},
+                {"op": "test", "path": "/d", "value": "c"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            operations[3],
+            PatchOperation::Move {
+                from: "/b".to_string(),
+                path: "/c".to_string()
+            }
+        );
+
+        assert!(serde_json::from_str::<PatchOperation>(r#"{"op": "merge", "path": ""}"#).is_err());
+        assert!(serde_json::from_str::<PatchOperation>(r#"{"op": "add", "path": "/a"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_pointer() {
+        assert!(parse_pointer("").unwrap().is_empty());
+        assert_eq!(parse_pointer("/").unwrap(), vec![""]);
+        assert_eq!(
+            parse_pointer("/a~1b/c~0d/0").unwrap(),
+            vec!["a/b", "c~d", "0"]
+        );
+        assert_eq!(
+            parse_pointer("a").unwrap_err(),
+            Error::InvalidPointer("a".to_string())
+        );
+        assert!(parse_pointer("/a~2").is_err());
+        assert!(parse_pointer("/a~").is_err());
+    }
+
+    #[test]
+    fn test_array_index() {
+        assert_eq!(array_index("0", 1, false), Some(0));
+        assert_eq!(array_index("1", 1, false), None);
+        assert_eq!(array_index("1", 1, true), Some(1));
+        assert_eq!(array_index("-", 1, true), Some(1));
+        assert_eq!(array_index("-", 1, false), None);
+        assert_eq!(array_index("01", 2, false), None);
+        assert_eq!(array_index("+1", 2, false), None);
+        assert_eq!(array_index("", 2, false), None);
+    }
+
+    #[test]
+    fn test_add_remove_replace() {
+        let mut doc = json!({"foo": {"bar": ["a", "c"]}});
+
+        patch(
+            &mut doc,
+            json!([
+                {"op": "add", "path": "/foo/baz", "value": "qux"},
+                {"op": "add", "path": "/foo/bar/1", "value": "b"},
+                {"op": "add", "path": "/foo/bar/-", "value": "d"},
+                {"op": "replace", "path": "/foo/baz", "value": "quux"},
+                {"op": "remove", "path": "/foo/bar/0"}
+            ]),
+        )
+        .unwrap();
+        assert_eq!(doc, json!({"foo": {"bar": ["b", "c", "d"], "baz": "quux"}}));
+
+        // Adding at the root replaces the whole document.
+        patch(
+            &mut doc,
+            json!([{"op": "add", "path": "", "value": {"a": 1}}]),
+        )
+        .unwrap();
+        assert_eq!(doc, json!({"a": 1}));
+
+        assert_eq!(
+            patch(&mut doc, json!([{"op": "add", "path": "/b/c", "value": 1}])).unwrap_err(),
+            Error::PathNotFound("/b/c".to_string())
+        );
+        assert_eq!(
+            patch(&mut doc, json!([{"op": "remove", "path": "/b"}])).unwrap_err(),
+            Error::PathNotFound("/b".to_string())
+        );
+        assert_eq!(
+            patch(
+                &mut doc,
+                json!([{"op": "replace", "path": "/b", "value": 2}])
+            )
+            .unwrap_err(),
+            Error::PathNotFound("/b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_move_copy_test() {
+        let mut doc = json!({"foo": {"bar": "baz"}, "list": [1, 2]});
+
+        patch(
+            &mut doc,
+            json!([
+                {"op": "copy", "from": "/foo/bar", "path": "/list/0"},
+                {"op": "move", "from": "/foo", "path": "/moved"},
+                {"op": "test", "path": "/list", "value": ["baz", 1, 2]},
+                {"op": "test", "path": "/moved/bar", "value": "baz"}
+            ]),
+        )
+        .unwrap();
+        assert_eq!(doc, json!({"moved": {"bar": "baz"}, "list": ["baz", 1, 2]}));
+
+        assert_eq!(
+            patch(
+                &mut doc,
+                json!([{"op": "move", "from": "/moved", "path": "/moved/bar/x"}])
+            )
+            .unwrap_err(),
+            Error::MoveIntoChild("/moved/bar/x".to_string())
+        );
+        assert_eq!(
+            patch(
+                &mut doc,
+                json!([{"op": "test", "path": "/list/0", "value": 1}])
+            )
+            .unwrap_err(),
+            Error::TestFailed("/list/0".to_string())
+        );
+        assert_eq!(
+            patch(
+                &mut doc,
+                json!([{"op": "copy", "from": "/x", "path": "/y"}])
+            )
+            .unwrap_err(),
+            Error::PathNotFound("/x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_atomic_apply() {
+        let mut doc = json!({"foo": "bar"});
+        let original = doc.clone();
+
+        // The first operation is rolled back when a later one fails.
+        assert!(patch(
+            &mut doc,
+            json!([
+                {"op": "add", "path": "/baz", "value": "qux"},
+                {"op": "test", "path": "/foo", "value": "qux"}
+            ])
+        )
+        .is_err());
+        assert_eq!(doc, original);
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(
+            Error::InvalidPointer("a".to_string()).to_string(),
+            "Invalid JSON pointer: a."
+        );
+        assert_eq!(
+            Error::PathNotFound("/a".to_string()).to_string(),
+            "The path does not exist: /a."
+        );
+        assert_eq!(
+            Error::MoveIntoChild("/a/b".to_string()).to_string(),
+            "Cannot move a value into one of its children: /a/b."
+        );
+        assert_eq!(
+            Error::TestFailed("/a".to_string()).to_string(),
+            "Test operation failed for path: /a."
+        );
+    }
+}
@@ -3,11 +3,12 @@
 
 //! Defines the structures needed for saving/restoring MmdsNetworkStack.
 
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
+use dumbo::pdu::ipv4::DEFAULT_TTL;
 use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 
 use super::ns::MmdsNetworkStack;
@@ -21,6 +22,36 @@ pub struct MmdsNetworkStackState {
     tcp_port: u16,
     max_connections: usize,
     max_pending_resets: usize,
+    #[version(start = 2, default_fn = "default_ttl", ser_fn = "ttl_serialize")]
+    ttl: u8,
+    #[version(start = 2, ser_fn = "ipv6_addr_serialize")]
+    ipv6_addr: Option<[u8; 16]>,
+}
+
+impl MmdsNetworkStackState {
+    fn default_ttl(_: u16) -> u8 {
+        DEFAULT_TTL
+    }
+
+    fn ttl_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.ttl != DEFAULT_TTL {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the MMDS hop limit.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn ipv6_addr_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.ipv6_addr.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the MMDS IPv6 address.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl Persist<'_> for MmdsNetworkStack {
@@ -38,6 +69,8 @@ impl Persist<'_> for MmdsNetworkStack {
             tcp_port: self.tcp_handler.local_port(),
             max_connections: self.tcp_handler.max_connections(),
             max_pending_resets: self.tcp_handler.max_pending_resets(),
+            ttl: self.tcp_handler.ttl(),
+            ipv6_addr: self.ipv6_addr.map(|addr| addr.octets()),
         }
     }
 
@@ -45,13 +78,17 @@ impl Persist<'_> for MmdsNetworkStack {
         _: Self::ConstructorArgs,
         state: &Self::State,
     ) -> std::result::Result<Self, Self::Error> {
-        Ok(MmdsNetworkStack::new(
+        let mut ns = MmdsNetworkStack::new(
             MacAddr::from_bytes_unchecked(&state.mac_addr),
             Ipv4Addr::from(state.ipv4_addr),
             state.tcp_port,
             std::num::NonZeroUsize::new(state.max_connections).unwrap(),
             std::num::NonZeroUsize::new(state.max_pending_resets).unwrap(),
-        ))
+        );
+        ns.set_hop_limit(state.ttl);
+        ns.set_ipv6_addr(state.ipv6_addr.map(Ipv6Addr::from));
+
+        Ok(ns)
     }
 }
 
@@ -90,5 +127,64 @@ mod tests {
             restored_ns.tcp_handler.max_pending_resets(),
             ns.tcp_handler.max_pending_resets()
         );
+        assert_eq!(restored_ns.tcp_handler.ttl(), DEFAULT_TTL);
+    }
+
+    #[test]
+    fn test_persistence_hop_limit() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        ns.set_hop_limit(1);
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(MmdsNetworkStackState::type_id(), 2);
+
+        // The hop limit cannot be saved in the older format.
+        assert!(ns
+            .save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        ns.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_ns = MmdsNetworkStack::restore(
+            (),
+            &MmdsNetworkStackState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_ns.tcp_handler.ttl(), 1);
+    }
+
+    #[test]
+    fn test_persistence_ipv6_addr() {
+        let mut ns = MmdsNetworkStack::new_with_defaults(None);
+        let ipv6_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        ns.set_ipv6_addr(Some(ipv6_addr));
+
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(MmdsNetworkStackState::type_id(), 2);
+
+        // The IPv6 address cannot be saved in the older format.
+        assert!(ns
+            .save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 1)
+            .is_err());
+
+        ns.save()
+            .serialize(&mut mem.as_mut_slice(), &version_map, 2)
+            .unwrap();
+        let restored_ns = MmdsNetworkStack::restore(
+            (),
+            &MmdsNetworkStackState::deserialize(&mut mem.as_slice(), &version_map, 2).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored_ns.ipv6_addr(), Some(ipv6_addr));
+        assert_eq!(restored_ns.tcp_handler.local_ipv6_addr(), Some(ipv6_addr));
     }
 }
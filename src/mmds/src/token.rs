@@ -0,0 +1,179 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Session tokens guarding the access to the MMDS, in the style of IMDSv2.
+//!
+//! The guest obtains a token with a `PUT` request carrying the desired token lifetime in the
+//! `X-metadata-token-ttl-seconds` header, and then presents it in the `X-metadata-token` header
+//! of its `GET` requests, until it expires.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use utils::time::{get_time_us, ClockType};
+
+/// Header carrying the session token of a `GET` request.
+pub const X_METADATA_TOKEN_HEADER: &str = "X-metadata-token";
+/// Header carrying the requested lifetime of a new session token.
+pub const X_METADATA_TOKEN_TTL_SECONDS_HEADER: &str = "X-metadata-token-ttl-seconds";
+/// Minimum lifetime of a session token, in seconds.
+pub const MIN_TOKEN_TTL_SECONDS: u32 = 1;
+/// Maximum lifetime of a session token, in seconds.
+pub const MAX_TOKEN_TTL_SECONDS: u32 = 21600;
+
+// Number of random bytes in a token. The token itself is their hex representation.
+const TOKEN_BYTES: usize = 32;
+// Upper bound for the number of live tokens, so that the guest cannot exhaust the VMM memory.
+const MAX_TOKENS: usize = 1024;
+const MICROS_PER_SECOND: u64 = 1_000_000;
+
+/// Session token related errors.
+#[derive(Debug)]
+pub enum Error {
+    /// The requested lifetime is out of bounds.
+    InvalidTtlValue(u32),
+    /// The random bytes of the token could not be generated.
+    EntropyUnavailable(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidTtlValue(ttl) => write!(
+                f,
+                "Invalid time to live value provided for token: {}. Please provide a value \
+                 between {} and {}.",
+                ttl, MIN_TOKEN_TTL_SECONDS, MAX_TOKEN_TTL_SECONDS
+            ),
+            Error::EntropyUnavailable(e) => write!(f, "Cannot generate the token: {}", e),
+        }
+    }
+}
+
+/// Issues the session tokens and keeps track of their expiration.
+#[derive(Clone, Default)]
+pub struct TokenAuthority {
+    // Maps the live tokens to the moment they expire at, in microseconds.
+    tokens: HashMap<String, u64>,
+}
+
+impl TokenAuthority {
+    /// Creates a new token which stays valid for `ttl_seconds`.
+    pub fn generate_token(&mut self, ttl_seconds: u32) -> Result<String, Error> {
+        if !(MIN_TOKEN_TTL_SECONDS..=MAX_TOKEN_TTL_SECONDS).contains(&ttl_seconds) {
+            return Err(Error::InvalidTtlValue(ttl_seconds));
+        }
+
+        let mut bytes = [0u8; TOKEN_BYTES];
+        fill_random(&mut bytes).map_err(Error::EntropyUnavailable)?;
+        let token = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let now = get_time_us(ClockType::Monotonic);
+        self.tokens.retain(|_, expiry| *expiry > now);
+        if self.tokens.len() >= MAX_TOKENS {
+            // Make room by dropping the token closest to expiring.
+            if let Some(oldest) = self
+                .tokens
+                .iter()
+                .min_by_key(|(_, expiry)| **expiry)
+                .map(|(token, _)| token.clone())
+            {
+                self.tokens.remove(&oldest);
+            }
+        }
+
+        self.tokens.insert(
+            token.clone(),
+            now + u64::from(ttl_seconds) * MICROS_PER_SECOND,
+        );
+        Ok(token)
+    }
+
+    /// Returns whether `token` was issued by this authority and has not expired yet.
+    pub fn is_valid(&self, token: &str) -> bool {
+        match self.tokens.get(token) {
+            Some(expiry) => *expiry > get_time_us(ClockType::Monotonic),
+            None => false,
+        }
+    }
+}
+
+// Fills `buf` with bytes from the kernel random number generator.
+fn fill_random(buf: &mut [u8]) -> io::Result<()> {
+    // Safe because the kernel only writes within the bounds of `buf`, and we check the result.
+    let ret = unsafe { libc::syscall(libc::SYS_getrandom, buf.as_mut_ptr(), buf.len(), 0) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if ret as usize != buf.len() {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_token() {
+        let mut authority = TokenAuthority::default();
+
+        assert!(authority.generate_token(0).is_err());
+        assert!(authority.generate_token(MAX_TOKEN_TTL_SECONDS + 1).is_err());
+
+        let token = authority.generate_token(MIN_TOKEN_TTL_SECONDS).unwrap();
+        assert_eq!(token.len(), 2 * TOKEN_BYTES);
+        assert!(authority.is_valid(&token));
+        assert!(!authority.is_valid("foo"));
+
+        let other_token = authority.generate_token(MAX_TOKEN_TTL_SECONDS).unwrap();
+        assert_ne!(token, other_token);
+        assert!(authority.is_valid(&other_token));
+    }
+
+    #[test]
+    fn test_token_expiry() {
+        let mut authority = TokenAuthority::default();
+        let token = authority.generate_token(MIN_TOKEN_TTL_SECONDS).unwrap();
+
+        // Expire the token without waiting for it.
+        *authority.tokens.get_mut(&token).unwrap() = get_time_us(ClockType::Monotonic);
+        assert!(!authority.is_valid(&token));
+
+        // Expired tokens are dropped when issuing new ones.
+        authority.generate_token(MIN_TOKEN_TTL_SECONDS).unwrap();
+        assert!(!authority.tokens.contains_key(&token));
+    }
+
+    #[test]
+    fn test_max_tokens() {
+        let mut authority = TokenAuthority::default();
+        let first = authority.generate_token(MIN_TOKEN_TTL_SECONDS).unwrap();
+        for _ in 1..MAX_TOKENS {
+            authority.generate_token(MAX_TOKEN_TTL_SECONDS).unwrap();
+        }
+        assert_eq!(authority.tokens.len(), MAX_TOKENS);
+        assert!(authority.is_valid(&first));
+
+        // The token closest to expiring makes room for the new one.
+        authority.generate_token(MAX_TOKEN_TTL_SECONDS).unwrap();
+        assert_eq!(authority.tokens.len(), MAX_TOKENS);
+        assert!(!authority.is_valid(&first));
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(
+            Error::InvalidTtlValue(0).to_string(),
+            "Invalid time to live value provided for token: 0. Please provide a value between 1 \
+             and 21600."
+        );
+        let _ = format!(
+            "{}",
+            Error::EntropyUnavailable(io::Error::from_raw_os_error(0))
+        );
+    }
+}
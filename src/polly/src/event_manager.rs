@@ -7,7 +7,10 @@ use std::io;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::{Arc, Mutex};
 
+use crate::io_uring::IoUringPoller;
+use logger::{IncMetric, SubscriberMetrics, METRICS};
 use utils::epoll::{self, Epoll, EpollEvent};
+use utils::time::{get_time_us, ClockType};
 
 pub type Result<T> = std::result::Result<T, Error>;
 pub type Pollable = RawFd;
@@ -16,6 +19,8 @@ pub type Pollable = RawFd;
 pub enum Error {
     /// Cannot create epoll fd.
     EpollCreate(io::Error),
+    /// Cannot create the io_uring instance.
+    IoUringCreate(io::Error),
     /// Polling I/O error.
     Poll(io::Error),
     /// The specified pollable already registered.
@@ -30,6 +35,7 @@ impl std::fmt::Debug for Error {
 
         match self {
             EpollCreate(err) => write!(f, "Unable to create epoll fd: {}", err),
+            IoUringCreate(err) => write!(f, "Unable to create the io_uring instance: {}", err),
             Poll(err) => write!(f, "Error during epoll call: {}", err),
             AlreadyExists(pollable) => write!(
                 f,
@@ -61,32 +67,104 @@ pub trait Subscriber {
 
     /// Returns a list of `EpollEvent` that this subscriber is interested in.
     fn interest_list(&self) -> Vec<EpollEvent>;
+
+    /// Returns the name the dispatch metrics of this subscriber are accounted under. Defaults
+    /// to the name of its type, shared by all the subscribers of that type.
+    fn name(&self) -> String {
+        let type_name = std::any::type_name::<Self>();
+        // Strips the generic parameters and the module path.
+        let type_name = type_name.split('<').next().unwrap_or(type_name);
+        type_name
+            .rsplit("::")
+            .next()
+            .unwrap_or(type_name)
+            .to_string()
+    }
+}
+
+/// The mechanism an `EventManager` polls its file descriptors with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// Polls through `epoll(7)`.
+    Epoll,
+    /// Polls through io_uring poll requests. Needs Linux 5.10 or later.
+    IoUring,
 }
 
-/// Manages I/O notifications using epoll mechanism.
+// The poller of an `EventManager`, for each backend.
+enum Poller {
+    Epoll(Epoll),
+    IoUring(IoUringPoller),
+}
+
+impl Poller {
+    fn ctl(
+        &mut self,
+        operation: epoll::ControlOperation,
+        fd: RawFd,
+        event: EpollEvent,
+    ) -> io::Result<()> {
+        match self {
+            Poller::Epoll(epoll) => epoll.ctl(operation, fd, event),
+            Poller::IoUring(io_uring) => io_uring.ctl(operation, fd, event),
+        }
+    }
+
+    fn wait(
+        &mut self,
+        max_events: usize,
+        milliseconds: i32,
+        events: &mut [EpollEvent],
+    ) -> io::Result<usize> {
+        match self {
+            Poller::Epoll(epoll) => epoll.wait(max_events, milliseconds, events),
+            Poller::IoUring(io_uring) => io_uring.wait(max_events, milliseconds, events),
+        }
+    }
+}
+
+/// Manages I/O notifications using epoll or io_uring.
 pub struct EventManager {
-    epoll: Epoll,
+    poller: Poller,
     subscribers: HashMap<RawFd, Arc<Mutex<dyn Subscriber>>>,
+    // The dispatch metrics of the subscribers, looked up on their first event.
+    subscriber_metrics: HashMap<RawFd, Arc<SubscriberMetrics>>,
     ready_events: Vec<EpollEvent>,
 }
 
 impl AsRawFd for EventManager {
     fn as_raw_fd(&self) -> RawFd {
-        self.epoll.as_raw_fd()
+        match &self.poller {
+            Poller::Epoll(epoll) => epoll.as_raw_fd(),
+            Poller::IoUring(io_uring) => io_uring.as_raw_fd(),
+        }
     }
 }
 
 impl EventManager {
     const EVENT_BUFFER_SIZE: usize = 128;
 
-    /// Create a new EventManager.
+    /// Create a new EventManager, polling through epoll.
     pub fn new() -> Result<EventManager> {
-        let epoll_fd = epoll::Epoll::new().map_err(Error::EpollCreate)?;
+        EventManager::with_backend(Backend::Epoll)
+    }
+
+    /// Create a new EventManager, polling through `backend`.
+    pub fn with_backend(backend: Backend) -> Result<EventManager> {
+        let poller = match backend {
+            Backend::Epoll => Poller::Epoll(epoll::Epoll::new().map_err(Error::EpollCreate)?),
+            Backend::IoUring => Poller::IoUring(
+                // Leaves room for the timeout requests along with the polls of a full buffer.
+                IoUringPoller::new(EventManager::EVENT_BUFFER_SIZE as u32 * 2)
+                    .map_err(Error::IoUringCreate)?,
+            ),
+        };
 
         Ok(EventManager {
-            epoll: epoll_fd,
+            poller,
             subscribers: HashMap::new(),
-            // This buffer is used for storing the events returned by `epoll_wait()`.
+            subscriber_metrics: HashMap::new(),
+            // This buffer is used for storing the events returned by the poller.
             // We preallocate memory for this buffer in order to not repeat this
             // operation every time `run()` loop is executed.
             ready_events: vec![epoll::EpollEvent::default(); EventManager::EVENT_BUFFER_SIZE],
@@ -118,6 +196,36 @@ impl EventManager {
         Ok(())
     }
 
+    /// Unregister all the file descriptors of `subscriber`, including the ones it registered
+    /// itself while processing events.
+    pub fn remove_subscriber(&mut self, subscriber: &Arc<Mutex<dyn Subscriber>>) -> Result<()> {
+        // Only the data pointers are compared, the vtables of a type may be duplicated.
+        let target = &**subscriber as *const Mutex<dyn Subscriber> as *const u8;
+        let pollables: Vec<Pollable> = self
+            .subscribers
+            .iter()
+            .filter(|(_, s)| &***s as *const Mutex<dyn Subscriber> as *const u8 == target)
+            .map(|(pollable, _)| *pollable)
+            .collect();
+
+        for pollable in pollables {
+            self.unregister(pollable)?;
+        }
+
+        Ok(())
+    }
+
+    /// Unregister the file descriptors of all the subscribers, and drop them. The poller is kept,
+    /// for the event manager to serve other subscribers.
+    pub fn remove_all_subscribers(&mut self) -> Result<()> {
+        let pollables: Vec<Pollable> = self.subscribers.keys().cloned().collect();
+        for pollable in pollables {
+            self.unregister(pollable)?;
+        }
+
+        Ok(())
+    }
+
     /// Register a new `pollable` file descriptor with the corresponding `epoll_event`
     /// for `subscriber`.
     pub fn register(
@@ -130,7 +238,7 @@ impl EventManager {
             return Err(Error::AlreadyExists(pollable));
         };
 
-        self.epoll
+        self.poller
             .ctl(epoll::ControlOperation::Add, pollable, epoll_event)
             .map_err(Error::Poll)?;
 
@@ -142,7 +250,8 @@ impl EventManager {
     pub fn unregister(&mut self, pollable: Pollable) -> Result<()> {
         match self.subscribers.remove(&pollable) {
             Some(_) => {
-                self.epoll
+                self.subscriber_metrics.remove(&pollable);
+                self.poller
                     .ctl(
                         epoll::ControlOperation::Delete,
                         pollable,
@@ -160,7 +269,7 @@ impl EventManager {
     /// Update the events monitored by `pollable`.
     pub fn modify(&mut self, pollable: Pollable, epoll_event: EpollEvent) -> Result<()> {
         if self.subscribers.contains_key(&pollable) {
-            self.epoll
+            self.poller
                 .ctl(epoll::ControlOperation::Modify, pollable, epoll_event)
                 .map_err(Error::Poll)?;
         } else {
@@ -178,7 +287,7 @@ impl EventManager {
     /// Wait for events for a maximum timeout of `miliseconds`. Dispatch the events to the
     /// registered signal handlers.
     pub fn run_with_timeout(&mut self, milliseconds: i32) -> Result<usize> {
-        let event_count = match self.epoll.wait(
+        let event_count = match self.poller.wait(
             EventManager::EVENT_BUFFER_SIZE,
             milliseconds,
             &mut self.ready_events[..],
@@ -187,25 +296,48 @@ impl EventManager {
             Err(e) if e.raw_os_error() == Some(libc::EINTR) => 0,
             Err(e) => return Err(Error::Poll(e)),
         };
-        self.dispatch_events(event_count);
+        self.dispatch_events(event_count, get_time_us(ClockType::Monotonic));
 
         Ok(event_count)
     }
 
-    fn dispatch_events(&mut self, event_count: usize) {
+    // Dispatches the ready events, reported at `ready_us`, and accounts for the time the
+    // subscribers take to process them.
+    fn dispatch_events(&mut self, event_count: usize, ready_us: u64) {
         // Use the temporary, pre-allocated buffer to check ready events.
         for ev_index in 0..event_count {
             let event = &self.ready_events[ev_index].clone();
             let pollable = event.fd();
 
             if self.subscribers.contains_key(&pollable) {
-                self.subscribers
+                let subscriber = self
+                    .subscribers
                     .get_mut(&pollable)
                     .unwrap() // Safe because we have already checked existence
-                    .clone()
-                    .lock()
-                    .expect("Poisoned lock")
-                    .process(&event, self);
+                    .clone();
+                let mut handler = subscriber.lock().expect("Poisoned lock");
+                // The subscriber is only asked for its name once its lock is held, since it
+                // may register file descriptors while processing an event.
+                let metrics = match self.subscriber_metrics.get(&pollable) {
+                    Some(metrics) => metrics.clone(),
+                    None => {
+                        let metrics = METRICS.event_manager.subscribers.get(&handler.name());
+                        self.subscriber_metrics.insert(pollable, metrics.clone());
+                        metrics
+                    }
+                };
+
+                let start_us = get_time_us(ClockType::Monotonic);
+                let queue_delay_us = start_us.saturating_sub(ready_us) as usize;
+                handler.process(&event, self);
+                let handler_time_us =
+                    get_time_us(ClockType::Monotonic).saturating_sub(start_us) as usize;
+
+                metrics.invocations.inc();
+                metrics.handler_time_us.add(handler_time_us);
+                metrics.max_handler_time_us.record(handler_time_us);
+                metrics.queue_delay_us.add(queue_delay_us);
+                metrics.max_queue_delay_us.record(queue_delay_us);
             }
             // TODO: Should we log an error in case the subscriber does not exist?
         }
@@ -215,6 +347,7 @@ impl EventManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use logger::StoreMetric;
     use utils::epoll::EventSet;
     use utils::eventfd::EventFd;
 
@@ -409,6 +542,68 @@ mod tests {
         assert_eq!(dummy_subscriber.lock().unwrap().processed_ev1_out(), false);
     }
 
+    #[test]
+    fn test_remove_subscriber() {
+        let mut event_manager = EventManager::new().unwrap();
+        let dummy_subscriber = Arc::new(Mutex::new(DummySubscriber::new()));
+        let other_subscriber = Arc::new(Mutex::new(DummySubscriber::new()));
+
+        event_manager
+            .add_subscriber(dummy_subscriber.clone())
+            .unwrap();
+        event_manager
+            .add_subscriber(other_subscriber.clone())
+            .unwrap();
+
+        // Register ev2 as well, so that the subscriber has more than one file descriptor.
+        dummy_subscriber.lock().unwrap().register_ev2();
+        event_manager.run().unwrap();
+
+        let as_subscriber: Arc<Mutex<dyn Subscriber>> = dummy_subscriber.clone();
+        event_manager.remove_subscriber(&as_subscriber).unwrap();
+        let ev1_fd = dummy_subscriber.lock().unwrap().event_fd_1.as_raw_fd();
+        let ev2_fd = dummy_subscriber.lock().unwrap().event_fd_2.as_raw_fd();
+        assert!(event_manager.subscriber(ev1_fd).is_err());
+        assert!(event_manager.subscriber(ev2_fd).is_err());
+
+        // The other subscriber is still served.
+        dummy_subscriber.lock().unwrap().reset_state();
+        other_subscriber.lock().unwrap().reset_state();
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(dummy_subscriber.lock().unwrap().processed_ev1_out(), false);
+        assert_eq!(other_subscriber.lock().unwrap().processed_ev1_out(), true);
+    }
+
+    #[test]
+    fn test_remove_all_subscribers() {
+        let mut event_manager = EventManager::new().unwrap();
+        let dummy_subscriber = Arc::new(Mutex::new(DummySubscriber::new()));
+        let other_subscriber = Arc::new(Mutex::new(DummySubscriber::new()));
+
+        event_manager
+            .add_subscriber(dummy_subscriber.clone())
+            .unwrap();
+        event_manager
+            .add_subscriber(other_subscriber.clone())
+            .unwrap();
+        dummy_subscriber.lock().unwrap().register_ev2();
+        event_manager.run().unwrap();
+
+        event_manager.remove_all_subscribers().unwrap();
+        assert_eq!(Arc::strong_count(&dummy_subscriber), 1);
+        assert_eq!(Arc::strong_count(&other_subscriber), 1);
+
+        // The event manager only serves the subscribers added next.
+        dummy_subscriber.lock().unwrap().reset_state();
+        let next_subscriber = Arc::new(Mutex::new(DummySubscriber::new()));
+        event_manager
+            .add_subscriber(next_subscriber.clone())
+            .unwrap();
+        event_manager.run_with_timeout(100).unwrap();
+        assert_eq!(dummy_subscriber.lock().unwrap().processed_ev1_out(), false);
+        assert_eq!(next_subscriber.lock().unwrap().processed_ev1_out(), true);
+    }
+
     #[test]
     fn test_modify() {
         let mut event_manager = EventManager::new().unwrap();
@@ -449,6 +644,60 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_io_uring_backend() {
+        // The host kernel may not support io_uring.
+        let mut event_manager = match EventManager::with_backend(Backend::IoUring) {
+            Ok(event_manager) => event_manager,
+            Err(Error::IoUringCreate(_)) => return,
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        };
+        let dummy_subscriber = Arc::new(Mutex::new(DummySubscriber::new()));
+
+        event_manager
+            .add_subscriber(dummy_subscriber.clone())
+            .unwrap();
+        dummy_subscriber.lock().unwrap().register_ev2();
+
+        event_manager.run().unwrap();
+        assert_eq!(dummy_subscriber.lock().unwrap().processed_ev1_out(), true);
+        assert_eq!(dummy_subscriber.lock().unwrap().processed_ev2_out(), false);
+
+        // The events still ready are reported again.
+        dummy_subscriber.lock().unwrap().reset_state();
+        event_manager.run().unwrap();
+        assert_eq!(dummy_subscriber.lock().unwrap().processed_ev1_out(), true);
+        assert_eq!(dummy_subscriber.lock().unwrap().processed_ev2_out(), true);
+
+        let as_subscriber: Arc<Mutex<dyn Subscriber>> = dummy_subscriber.clone();
+        event_manager.remove_subscriber(&as_subscriber).unwrap();
+        dummy_subscriber.lock().unwrap().reset_state();
+        assert_eq!(event_manager.run_with_timeout(100).unwrap(), 0);
+        assert_eq!(dummy_subscriber.lock().unwrap().processed_ev1_out(), false);
+    }
+
+    #[test]
+    fn test_subscriber_metrics() {
+        let mut event_manager = EventManager::new().unwrap();
+        let dummy_subscriber = Arc::new(Mutex::new(DummySubscriber::new()));
+        assert_eq!(dummy_subscriber.lock().unwrap().name(), "DummySubscriber");
+
+        event_manager
+            .add_subscriber(dummy_subscriber.clone())
+            .unwrap();
+        dummy_subscriber.lock().unwrap().register_ev2();
+        event_manager.run().unwrap();
+        event_manager.run().unwrap();
+
+        // Other tests may dispatch events to dummy subscribers as well.
+        let metrics = METRICS.event_manager.subscribers.get("DummySubscriber");
+        assert!(metrics.invocations.count() >= 3);
+        assert!(metrics.handler_time_us.count() >= metrics.max_handler_time_us.fetch());
+        let as_subscriber: Arc<Mutex<dyn Subscriber>> = dummy_subscriber;
+        event_manager.remove_subscriber(&as_subscriber).unwrap();
+        assert!(event_manager.subscriber_metrics.is_empty());
+    }
+
     // Test that registering the same event twice throws an error.
     #[test]
     fn test_register_errors() {
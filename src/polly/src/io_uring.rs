@@ -0,0 +1,691 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Readiness polling on top of io_uring, as an alternative to epoll.
+//!
+//! The file descriptors are polled through `IORING_OP_POLL_ADD` requests. The level-triggered
+//! registrations are polled once, then polled again by the next wait, after their events were
+//! dispatched, so that a file descriptor still ready is reported again. The edge-triggered ones
+//! are polled in multishot mode when the host kernel supports it. The new polls are submitted
+//! along with the wait, in a single `io_uring_enter` call.
+//!
+//! The ring only accepts the poll and timeout requests, so that it cannot be used to run the
+//! system calls the seccomp filters deny.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use utils::epoll::{ControlOperation, EpollEvent, EventSet};
+
+const IORING_SETUP_R_DISABLED: u32 = 1 << 6;
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x800_0000;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+const IORING_REGISTER_RESTRICTIONS: u32 = 11;
+const IORING_REGISTER_ENABLE_RINGS: u32 = 12;
+const IORING_RESTRICTION_SQE_OP: u16 = 1;
+const IORING_OP_POLL_ADD: u8 = 6;
+const IORING_OP_POLL_REMOVE: u8 = 7;
+const IORING_OP_TIMEOUT: u8 = 11;
+const IORING_OP_TIMEOUT_REMOVE: u8 = 12;
+const IORING_POLL_ADD_MULTI: u32 = 1 << 0;
+const IORING_CQE_F_MORE: u32 = 1 << 1;
+
+// The requests the ring is restricted to.
+const ALLOWED_OPS: [u8; 4] = [
+    IORING_OP_POLL_ADD,
+    IORING_OP_POLL_REMOVE,
+    IORING_OP_TIMEOUT,
+    IORING_OP_TIMEOUT_REMOVE,
+];
+
+// The flags of an `EpollEvent` which are not poll events.
+const EPOLL_FLAGS: u32 =
+    (libc::EPOLLET | libc::EPOLLONESHOT | libc::EPOLLEXCLUSIVE | libc::EPOLLWAKEUP) as u32;
+
+// The tokens of the requests which don't poll a file descriptor. The tokens of the polls hold
+// the generation of their registration, which starts at 1, in their upper half.
+const TIMEOUT_TOKEN: u64 = 0;
+const REMOVE_TOKEN: u64 = 1;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+// A submission queue entry.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    // The poll events of `IORING_OP_POLL_ADD`.
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    pad: [u64; 2],
+}
+
+// A completion queue entry.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Restriction {
+    opcode: u16,
+    sqe_op: u8,
+    resv: u8,
+    resv2: [u32; 3],
+}
+
+#[repr(C)]
+struct KernelTimespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+// A shared mapping of the ring.
+struct Mapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> io::Result<Mapping> {
+        // Safe because the result is checked, and the mapping is unmapped when dropped.
+        let addr = unsafe {
+            libc::mmap(
+                null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            addr: addr as *mut u8,
+            len,
+        })
+    }
+
+    // Returns a pointer to the field of the ring at `offset`, given by the kernel.
+    fn at<T>(&self, offset: u32) -> *mut T {
+        // Safe because the kernel gives offsets within the mapping.
+        unsafe { self.addr.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // Safe because the mapping is no longer used.
+        unsafe {
+            libc::munmap(self.addr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+// The submission and completion queues.
+struct Ring {
+    // The mappings must outlive the pointers into them.
+    _sq_ring: Mapping,
+    _cq_ring: Mapping,
+    _sqes: Mapping,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    sqes: *mut Sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    // The entries queued since the last submission.
+    pending: u32,
+    // Declared last, so that the ring is closed once unmapped.
+    file: File,
+}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Ring> {
+        let mut params = Params {
+            flags: IORING_SETUP_R_DISABLED,
+            ..Default::default()
+        };
+        // Safe because the kernel only writes to `params`, and the result is checked.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safe because the ring file descriptor was just created, and is owned by nothing else.
+        let file = unsafe { File::from_raw_fd(fd as RawFd) };
+
+        let sq_off = &params.sq_off;
+        let cq_off = &params.cq_off;
+        let sq_ring = Mapping::new(
+            fd as RawFd,
+            sq_off.array as usize + params.sq_entries as usize * std::mem::size_of::<u32>(),
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq_ring = Mapping::new(
+            fd as RawFd,
+            cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>(),
+            IORING_OFF_CQ_RING,
+        )?;
+        let sqes = Mapping::new(
+            fd as RawFd,
+            params.sq_entries as usize * std::mem::size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+
+        let ring = Ring {
+            sq_head: sq_ring.at(sq_off.head),
+            sq_tail: sq_ring.at(sq_off.tail),
+            // Safe because the kernel initialized the ring.
+            sq_mask: unsafe { *sq_ring.at::<u32>(sq_off.ring_mask) },
+            sq_entries: params.sq_entries,
+            sq_array: sq_ring.at(sq_off.array),
+            sqes: sqes.at(0),
+            cq_head: cq_ring.at(cq_off.head),
+            cq_tail: cq_ring.at(cq_off.tail),
+            // Safe because the kernel initialized the ring.
+            cq_mask: unsafe { *cq_ring.at::<u32>(cq_off.ring_mask) },
+            cqes: cq_ring.at(cq_off.cqes),
+            pending: 0,
+            _sq_ring: sq_ring,
+            _cq_ring: cq_ring,
+            _sqes: sqes,
+            file,
+        };
+        ring.restrict()?;
+        Ok(ring)
+    }
+
+    fn register(&self, opcode: u32, arg: *const libc::c_void, nr_args: u32) -> io::Result<()> {
+        // Safe because the kernel only reads `nr_args` entries of `arg`, and the result is
+        // checked.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_register,
+                self.file.as_raw_fd(),
+                opcode,
+                arg,
+                nr_args,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Restricts the ring to the allowed requests, then enables it.
+    fn restrict(&self) -> io::Result<()> {
+        let restrictions: Vec<Restriction> = ALLOWED_OPS
+            .iter()
+            .map(|op| Restriction {
+                opcode: IORING_RESTRICTION_SQE_OP,
+                sqe_op: *op,
+                ..Default::default()
+            })
+            .collect();
+        self.register(
+            IORING_REGISTER_RESTRICTIONS,
+            restrictions.as_ptr() as *const libc::c_void,
+            restrictions.len() as u32,
+        )?;
+        self.register(IORING_REGISTER_ENABLE_RINGS, std::ptr::null(), 0)
+    }
+
+    // Queues `sqe`, submitting the queued entries first if the queue is full.
+    fn push(&mut self, sqe: Sqe) -> io::Result<()> {
+        // Safe because the pointers are within the mappings of the ring, and the kernel only
+        // reads the entries past the head and up to the tail.
+        unsafe {
+            let tail = (*self.sq_tail).load(Ordering::Relaxed);
+            if tail.wrapping_sub((*self.sq_head).load(Ordering::Acquire)) == self.sq_entries {
+                self.enter(0)?;
+            }
+            let index = tail & self.sq_mask;
+            *self.sqes.add(index as usize) = sqe;
+            *self.sq_array.add(index as usize) = index;
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.pending += 1;
+        Ok(())
+    }
+
+    // Submits the queued entries, and waits for `min_complete` completions.
+    fn enter(&mut self, min_complete: u32) -> io::Result<()> {
+        let flags = if min_complete > 0 {
+            IORING_ENTER_GETEVENTS
+        } else {
+            0
+        };
+        // Safe because no signal mask is given, and the result is checked.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.file.as_raw_fd(),
+                self.pending,
+                min_complete,
+                flags,
+                std::ptr::null::<libc::sigset_t>(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.pending = self.pending.saturating_sub(ret as u32);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Cqe> {
+        // Safe because the pointers are within the mappings of the ring, and the kernel only
+        // writes the entries past the tail.
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            if head == (*self.cq_tail).load(Ordering::Acquire) {
+                return None;
+            }
+            let cqe = *self.cqes.add((head & self.cq_mask) as usize);
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some(cqe)
+        }
+    }
+}
+
+// A file descriptor polled through the ring.
+struct Registration {
+    event: EpollEvent,
+    token: u64,
+    // Whether a poll request of the registration is in flight.
+    armed: bool,
+    // Whether the registration is polled in multishot mode.
+    multishot: bool,
+    // Whether the registration stopped being polled, after a one-shot event or an error.
+    disabled: bool,
+}
+
+impl Registration {
+    fn poll_events(&self) -> u32 {
+        self.event.events() & !EPOLL_FLAGS
+    }
+}
+
+/// Polls file descriptors for readiness through io_uring, with the interface of `Epoll`.
+pub struct IoUringPoller {
+    ring: Ring,
+    registrations: HashMap<RawFd, Registration>,
+    generation: u32,
+    // Whether the host kernel supports the multishot polls.
+    multishot: bool,
+    // Whether a timeout request is in flight.
+    timeout_armed: bool,
+}
+
+impl AsRawFd for IoUringPoller {
+    fn as_raw_fd(&self) -> RawFd {
+        self.ring.file.as_raw_fd()
+    }
+}
+
+impl IoUringPoller {
+    /// Creates a ring of `entries` submission entries, restricted to the poll requests.
+    pub fn new(entries: u32) -> io::Result<IoUringPoller> {
+        Ok(IoUringPoller {
+            ring: Ring::new(entries)?,
+            registrations: HashMap::new(),
+            generation: 0,
+            multishot: true,
+            timeout_armed: false,
+        })
+    }
+
+    /// Adds, removes or modifies the interest in `fd`, like `epoll_ctl(2)`. The changes are
+    /// submitted along with the next wait.
+    pub fn ctl(
+        &mut self,
+        operation: ControlOperation,
+        fd: RawFd,
+        event: EpollEvent,
+    ) -> io::Result<()> {
+        match operation {
+            ControlOperation::Add => self.add(fd, event),
+            ControlOperation::Delete => self.delete(fd),
+            ControlOperation::Modify => self.delete(fd).and_then(|_| self.add(fd, event)),
+        }
+    }
+
+    fn add(&mut self, fd: RawFd, event: EpollEvent) -> io::Result<()> {
+        if self.registrations.contains_key(&fd) {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST));
+        }
+        self.generation = self.generation.checked_add(1).unwrap_or(1);
+        let edge_triggered = event.events() & libc::EPOLLET as u32 != 0;
+        self.registrations.insert(
+            fd,
+            Registration {
+                event,
+                token: (u64::from(self.generation) << 32) | u64::from(fd as u32),
+                armed: false,
+                multishot: edge_triggered && self.multishot,
+                disabled: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn delete(&mut self, fd: RawFd) -> io::Result<()> {
+        let registration = self
+            .registrations
+            .remove(&fd)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        if registration.armed {
+            // The completion of the cancelled poll is ignored along with the one of the removal.
+            self.ring.push(Sqe {
+                opcode: IORING_OP_POLL_REMOVE,
+                fd: -1,
+                addr: registration.token,
+                user_data: REMOVE_TOKEN,
+                ..Default::default()
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Waits for events for at most `milliseconds`, like `epoll_wait(2)`, and stores up to
+    /// `max_events` of them in `events`. Returns the number of stored events.
+    pub fn wait(
+        &mut self,
+        max_events: usize,
+        milliseconds: i32,
+        events: &mut [EpollEvent],
+    ) -> io::Result<usize> {
+        // Poll again the registrations whose previous poll completed.
+        for registration in self.registrations.values_mut() {
+            if registration.armed || registration.disabled {
+                continue;
+            }
+            self.ring.push(Sqe {
+                opcode: IORING_OP_POLL_ADD,
+                fd: (registration.token as u32) as i32,
+                op_flags: registration.poll_events(),
+                len: if registration.multishot {
+                    IORING_POLL_ADD_MULTI
+                } else {
+                    0
+                },
+                user_data: registration.token,
+                ..Default::default()
+            })?;
+            registration.armed = true;
+        }
+
+        // The timespec must outlive the submission, which reads it.
+        let timespec = KernelTimespec {
+            tv_sec: i64::from(milliseconds / 1000),
+            tv_nsec: i64::from(milliseconds % 1000) * 1_000_000,
+        };
+        if milliseconds > 0 {
+            if self.timeout_armed {
+                // A previous wait returned before its timeout expired.
+                self.ring.push(Sqe {
+                    opcode: IORING_OP_TIMEOUT_REMOVE,
+                    fd: -1,
+                    addr: TIMEOUT_TOKEN,
+                    user_data: REMOVE_TOKEN,
+                    ..Default::default()
+                })?;
+            }
+            self.ring.push(Sqe {
+                opcode: IORING_OP_TIMEOUT,
+                fd: -1,
+                addr: &timespec as *const KernelTimespec as u64,
+                len: 1,
+                user_data: TIMEOUT_TOKEN,
+                ..Default::default()
+            })?;
+            self.timeout_armed = true;
+        }
+        self.ring.enter(if milliseconds == 0 { 0 } else { 1 })?;
+
+        let max_events = max_events.min(events.len());
+        let mut count = 0;
+        while count < max_events {
+            let cqe = match self.ring.pop() {
+                Some(cqe) => cqe,
+                None => break,
+            };
+            if let Some(event) = self.complete(cqe) {
+                events[count] = event;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    // Returns the event reported by `cqe`, if any.
+    fn complete(&mut self, cqe: Cqe) -> Option<EpollEvent> {
+        if cqe.user_data == TIMEOUT_TOKEN {
+            // A cancelled timeout was replaced by the one of the latest wait.
+            if cqe.res != -libc::ECANCELED {
+                self.timeout_armed = false;
+            }
+            return None;
+        }
+        let fd = (cqe.user_data as u32) as RawFd;
+        let registration = match self.registrations.get_mut(&fd) {
+            // The completions of the removed registrations are stale.
+            Some(registration) if registration.token == cqe.user_data => registration,
+            _ => return None,
+        };
+        if cqe.flags & IORING_CQE_F_MORE == 0 {
+            registration.armed = false;
+        }
+        if cqe.res < 0 {
+            if cqe.res == -libc::EINVAL && registration.multishot {
+                // The host kernel doesn't support the multishot polls, poll once instead.
+                self.multishot = false;
+                registration.multishot = false;
+            } else {
+                registration.disabled = true;
+            }
+            return None;
+        }
+        if registration.event.events() & libc::EPOLLONESHOT as u32 != 0 {
+            registration.disabled = true;
+        }
+        Some(EpollEvent::new(
+            EventSet::from_bits_truncate(cqe.res as u32),
+            registration.event.data(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::eventfd::EventFd;
+
+    // The host kernel may not support io_uring, or its restrictions, which need Linux 5.10.
+    fn poller() -> Option<IoUringPoller> {
+        IoUringPoller::new(16).ok()
+    }
+
+    #[test]
+    fn test_level_triggered() {
+        let mut poller = match poller() {
+            Some(poller) => poller,
+            None => return,
+        };
+        let mut events = vec![EpollEvent::default(); 4];
+        let event_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let fd = event_fd.as_raw_fd();
+
+        poller
+            .ctl(
+                ControlOperation::Add,
+                fd,
+                EpollEvent::new(EventSet::IN, fd as u64),
+            )
+            .unwrap();
+        assert_eq!(poller.wait(4, 10, &mut events).unwrap(), 0);
+
+        event_fd.write(1).unwrap();
+        assert_eq!(poller.wait(4, -1, &mut events).unwrap(), 1);
+        assert_eq!(events[0].event_set(), EventSet::IN);
+        assert_eq!(events[0].fd(), fd);
+
+        // The event is reported again as long as the event fd is readable.
+        assert_eq!(poller.wait(4, -1, &mut events).unwrap(), 1);
+        event_fd.read().unwrap();
+        assert_eq!(poller.wait(4, 10, &mut events).unwrap(), 0);
+
+        // The changes are applied to the next wait.
+        poller
+            .ctl(
+                ControlOperation::Modify,
+                fd,
+                EpollEvent::new(EventSet::OUT, fd as u64),
+            )
+            .unwrap();
+        assert_eq!(poller.wait(4, -1, &mut events).unwrap(), 1);
+        assert_eq!(events[0].event_set(), EventSet::OUT);
+
+        poller
+            .ctl(ControlOperation::Delete, fd, EpollEvent::default())
+            .unwrap();
+        assert_eq!(poller.wait(4, 10, &mut events).unwrap(), 0);
+        assert_eq!(
+            poller
+                .ctl(ControlOperation::Delete, fd, EpollEvent::default())
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+
+    #[test]
+    fn test_edge_triggered() {
+        let mut poller = match poller() {
+            Some(poller) => poller,
+            None => return,
+        };
+        let mut events = vec![EpollEvent::default(); 4];
+        let event_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let fd = event_fd.as_raw_fd();
+
+        poller
+            .ctl(
+                ControlOperation::Add,
+                fd,
+                EpollEvent::new(EventSet::IN | EventSet::EDGE_TRIGGERED, fd as u64),
+            )
+            .unwrap();
+        assert_eq!(
+            poller
+                .ctl(
+                    ControlOperation::Add,
+                    fd,
+                    EpollEvent::new(EventSet::IN, fd as u64)
+                )
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EEXIST)
+        );
+        assert_eq!(poller.wait(4, 10, &mut events).unwrap(), 0);
+
+        event_fd.write(1).unwrap();
+        assert_eq!(poller.wait(4, -1, &mut events).unwrap(), 1);
+        assert_eq!(events[0].event_set(), EventSet::IN);
+        if !poller.multishot {
+            // The host kernel doesn't support the multishot polls, which need Linux 5.13.
+            return;
+        }
+        // Each write is reported once, even though the event fd is not read.
+        assert_eq!(poller.wait(4, 10, &mut events).unwrap(), 0);
+        event_fd.write(1).unwrap();
+        assert_eq!(poller.wait(4, -1, &mut events).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_restrictions() {
+        let mut poller = match poller() {
+            Some(poller) => poller,
+            None => return,
+        };
+        // A no-op request is not allowed on the ring.
+        poller.ring.push(Sqe::default()).unwrap();
+        poller.ring.enter(1).unwrap();
+        let cqe = poller.ring.pop().unwrap();
+        assert_eq!(cqe.res, -libc::EACCES);
+    }
+}
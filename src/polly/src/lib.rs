@@ -1,3 +1,5 @@
 // Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 pub mod event_manager;
+pub mod io_uring;
+pub mod worker;
@@ -0,0 +1,352 @@
+// Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Event loops running on threads of their own, so that the events of the busiest subscribers
+//! are not processed on the thread of the main `EventManager`.
+
+use std::fmt::Formatter;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::event_manager::{self, EventManager, Subscriber};
+use logger::error;
+use utils::epoll::{EpollEvent, EventSet};
+use utils::eventfd::EventFd;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors associated with the event worker threads.
+pub enum Error {
+    /// Cannot create the event fd waking up the worker.
+    EventFd(io::Error),
+    /// The event manager of the worker failed.
+    EventManager(event_manager::Error),
+    /// The worker thread failed to set itself up.
+    Setup(String),
+    /// Cannot spawn the worker thread.
+    Spawn(io::Error),
+    /// The worker thread is no longer running.
+    Stopped,
+}
+
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+
+        match self {
+            EventFd(err) => write!(f, "Unable to create the worker event fd: {}", err),
+            EventManager(err) => write!(f, "Event manager error in the worker: {:?}", err),
+            Setup(err) => write!(f, "Unable to set up the worker thread: {}", err),
+            Spawn(err) => write!(f, "Unable to spawn the worker thread: {}", err),
+            Stopped => write!(f, "The worker thread is no longer running."),
+        }
+    }
+}
+
+// Work sent to the worker thread, run with its event manager in between two dispatches.
+type Request = Box<dyn FnOnce(&mut EventManager) + Send>;
+
+// Runs the requests sent to the worker, when woken up.
+struct RequestHandler {
+    wakeup: EventFd,
+    requests: Receiver<Request>,
+}
+
+impl Subscriber for RequestHandler {
+    fn process(&mut self, _: &EpollEvent, event_manager: &mut EventManager) {
+        // The counter is only a wake up call, the requests are all in the channel.
+        let _ = self.wakeup.read();
+        while let Ok(request) = self.requests.try_recv() {
+            request(event_manager);
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.wakeup.as_raw_fd() as u64,
+        )]
+    }
+}
+
+/// Keeps a worker from processing events until dropped. The worker itself must not be dropped
+/// while paused.
+pub struct PausedWorker {
+    _resume: Sender<()>,
+}
+
+/// A thread running an `EventManager` of its own.
+///
+/// The subscribers of the worker are added and removed from other threads through requests,
+/// run by the worker in between two dispatches, so that they are never removed while processing
+/// an event. None of the methods may be called from the worker thread itself.
+pub struct EventWorker {
+    requests: Sender<Request>,
+    wakeup: EventFd,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl EventWorker {
+    /// Spawns a worker thread named `name`, which runs `setup` before processing any event.
+    ///
+    /// The worker stops processing events, and writes to `failed`, if its event loop fails, so
+    /// that the owner of `failed` can tear the worker down.
+    pub fn new<F>(name: String, failed: EventFd, setup: F) -> Result<EventWorker>
+    where
+        F: FnOnce() -> std::result::Result<(), String> + Send + 'static,
+    {
+        let wakeup = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+        let (requests, receiver) = channel();
+        let handler = RequestHandler {
+            wakeup: wakeup.try_clone().map_err(Error::EventFd)?,
+            requests: receiver,
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = stop.clone();
+        let (started_sender, started) = channel();
+
+        let thread = thread::Builder::new()
+            .name(name)
+            .spawn(move || {
+                if let Err(err) = setup() {
+                    let _ = started_sender.send(Err(Error::Setup(err)));
+                    return;
+                }
+                // The event manager is not `Send`, it is created on the worker thread.
+                let event_manager = EventManager::new()
+                    .and_then(|mut event_manager| {
+                        event_manager
+                            .add_subscriber(Arc::new(Mutex::new(handler)))
+                            .map(|_| event_manager)
+                    })
+                    .map_err(Error::EventManager);
+                let mut event_manager = match event_manager {
+                    Ok(event_manager) => {
+                        let _ = started_sender.send(Ok(()));
+                        event_manager
+                    }
+                    Err(err) => {
+                        let _ = started_sender.send(Err(err));
+                        return;
+                    }
+                };
+                while !worker_stop.load(Ordering::Acquire) {
+                    if let Err(err) = event_manager.run() {
+                        error!(
+                            "The event loop of worker {:?} failed: {:?}",
+                            thread::current().name(),
+                            err
+                        );
+                        // Dropping the requests receiver fails the pending and future requests.
+                        let _ = failed.write(1);
+                        return;
+                    }
+                }
+            })
+            .map_err(Error::Spawn)?;
+
+        started.recv().map_err(|_| Error::Stopped)??;
+
+        Ok(EventWorker {
+            requests,
+            wakeup,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    // Sends `request` to the worker and wakes it up.
+    fn send(&self, request: Request) -> Result<()> {
+        self.requests.send(request).map_err(|_| Error::Stopped)?;
+        self.wakeup.write(1).map_err(|_| Error::Stopped)
+    }
+
+    // Runs `request` on the worker thread, and waits for its result.
+    fn run<R, F>(&self, request: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut EventManager) -> R + Send + 'static,
+    {
+        let (result_sender, result) = channel();
+        self.send(Box::new(move |event_manager| {
+            let _ = result_sender.send(request(event_manager));
+        }))?;
+        result.recv().map_err(|_| Error::Stopped)
+    }
+
+    /// Registers all the events `subscriber` is interested in with the worker.
+    pub fn add_subscriber<T>(&self, subscriber: Arc<Mutex<T>>) -> Result<()>
+    where
+        T: Subscriber + Send + 'static,
+    {
+        self.run(move |event_manager| event_manager.add_subscriber(subscriber))?
+            .map_err(Error::EventManager)
+    }
+
+    /// Unregisters all the file descriptors of `subscriber` from the worker. Once this returns,
+    /// the worker no longer processes the events of `subscriber`.
+    pub fn remove_subscriber<T>(&self, subscriber: Arc<Mutex<T>>) -> Result<()>
+    where
+        T: Subscriber + Send + 'static,
+    {
+        self.run(move |event_manager| {
+            let subscriber: Arc<Mutex<dyn Subscriber>> = subscriber;
+            event_manager.remove_subscriber(&subscriber)
+        })?
+        .map_err(Error::EventManager)
+    }
+
+    /// Stops the worker from processing events, as long as the returned `PausedWorker` is alive.
+    ///
+    /// Once this returns, the worker is done with the event it was processing, and holds no
+    /// lock on its subscribers.
+    pub fn pause(&self) -> Result<PausedWorker> {
+        let (paused_sender, paused) = channel();
+        let (resume, resumed) = channel::<()>();
+        self.send(Box::new(move |_| {
+            let _ = paused_sender.send(());
+            // Returns once the `PausedWorker` is dropped, along with the sender.
+            let _ = resumed.recv();
+        }))?;
+        paused.recv().map_err(|_| Error::Stopped)?;
+
+        Ok(PausedWorker { _resume: resume })
+    }
+}
+
+impl Drop for EventWorker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        // The worker notices it was stopped once woken up.
+        let _ = self.wakeup.write(1);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Reports the values read from its event fd.
+    struct CountingSubscriber {
+        event_fd: EventFd,
+        processed: Sender<u64>,
+    }
+
+    impl CountingSubscriber {
+        fn new() -> (Arc<Mutex<Self>>, EventFd, Receiver<u64>) {
+            let event_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+            let trigger = event_fd.try_clone().unwrap();
+            let (processed, receiver) = channel();
+            let subscriber = Arc::new(Mutex::new(CountingSubscriber {
+                event_fd,
+                processed,
+            }));
+            (subscriber, trigger, receiver)
+        }
+    }
+
+    impl Subscriber for CountingSubscriber {
+        fn process(&mut self, _: &EpollEvent, _: &mut EventManager) {
+            if let Ok(count) = self.event_fd.read() {
+                self.processed.send(count).unwrap();
+            }
+        }
+
+        fn interest_list(&self) -> Vec<EpollEvent> {
+            vec![EpollEvent::new(
+                EventSet::IN,
+                self.event_fd.as_raw_fd() as u64,
+            )]
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn failed_evt() -> EventFd {
+        EventFd::new(libc::EFD_NONBLOCK).unwrap()
+    }
+
+    #[test]
+    fn test_add_remove_subscriber() {
+        let worker = EventWorker::new("test_worker".to_string(), failed_evt(), || Ok(())).unwrap();
+        let (subscriber, trigger, processed) = CountingSubscriber::new();
+
+        worker.add_subscriber(subscriber.clone()).unwrap();
+        trigger.write(2).unwrap();
+        assert_eq!(processed.recv_timeout(TIMEOUT * 10).unwrap(), 2);
+
+        // The events of the subscriber are registered only once.
+        match worker.add_subscriber(subscriber.clone()) {
+            Err(Error::EventManager(event_manager::Error::AlreadyExists(_))) => (),
+            _ => panic!("Unexpected result"),
+        }
+
+        worker.remove_subscriber(subscriber).unwrap();
+        trigger.write(1).unwrap();
+        assert!(processed.recv_timeout(TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn test_pause() {
+        let worker = EventWorker::new("test_worker".to_string(), failed_evt(), || Ok(())).unwrap();
+        let (subscriber, trigger, processed) = CountingSubscriber::new();
+        worker.add_subscriber(subscriber).unwrap();
+
+        let paused = worker.pause().unwrap();
+        trigger.write(1).unwrap();
+        assert!(processed.recv_timeout(TIMEOUT).is_err());
+
+        // The pending event is processed once the worker is resumed.
+        drop(paused);
+        assert_eq!(processed.recv_timeout(TIMEOUT * 10).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_setup() {
+        let (sender, receiver) = channel();
+        let worker = EventWorker::new("test_worker".to_string(), failed_evt(), move || {
+            sender
+                .send(thread::current().name().map(str::to_string))
+                .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(receiver.recv().unwrap(), Some("test_worker".to_string()));
+
+        // A worker failing to set itself up is not started.
+        match EventWorker::new("test_worker".to_string(), failed_evt(), || {
+            Err("no filters".to_string())
+        }) {
+            Err(Error::Setup(err)) => assert_eq!(err, "no filters"),
+            _ => panic!("Unexpected result"),
+        }
+
+        // Stopping the worker joins its thread, which drops the subscribers.
+        let (subscriber, _, _) = CountingSubscriber::new();
+        worker.add_subscriber(subscriber.clone()).unwrap();
+        drop(worker);
+        assert_eq!(Arc::strong_count(&subscriber), 1);
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = Error::EventFd(io::Error::from_raw_os_error(0));
+        let _ = format!("{:?}", err);
+        let err = Error::EventManager(event_manager::Error::NotFound(0));
+        let _ = format!("{:?}", err);
+        let err = Error::Setup(String::new());
+        let _ = format!("{:?}", err);
+        let err = Error::Spawn(io::Error::from_raw_os_error(0));
+        let _ = format!("{:?}", err);
+        let _ = format!("{:?}", Error::Stopped);
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Adaptive tuning of the rate of a `RateLimiter`, which keeps the latency of the operations it
+//! lets through under a target, e.g. the latency of the requests a drive sends to a host disk
+//! shared with other workloads.
+//!
+//! The rate is adjusted at most every `ADJUST_INTERVAL_MS`, from the average latency of the
+//! operations completed in the meantime. It is cut by a quarter when the latency is over the
+//! target, and raised by `RATE_STEP_PCT` percents of the configured rate otherwise, so that the
+//! limiter backs off quickly when the host is overloaded and only probes slowly for headroom.
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+/// The smallest rate, in percents of the configured rate, the limiter can be tightened to.
+pub const MIN_RATE_PCT: u64 = 1;
+/// The rate the limiter can be tightened to when the configuration doesn't set one.
+pub const DEFAULT_MIN_RATE_PCT: u64 = 10;
+
+// The interval between two adjustments of the rate.
+const ADJUST_INTERVAL_MS: u64 = 100;
+// The increase of the rate when the latency is under the target.
+const RATE_STEP_PCT: u64 = 5;
+
+/// Tunes the rate of a limiter, in percents of its configured rate, from the latency of the
+/// operations it lets through.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptiveRate {
+    target: Duration,
+    min_rate_pct: u64,
+    rate_pct: u64,
+    // The latency of the operations completed since the last adjustment.
+    latency_sum: Duration,
+    latency_count: u32,
+    last_adjustment: Instant,
+}
+
+impl AdaptiveRate {
+    /// Keeps the latency under `target`, without tightening the limiter under `min_rate_pct`
+    /// percents of its configured rate. The limiter starts at its configured rate.
+    pub fn new(target: Duration, min_rate_pct: u64) -> Self {
+        AdaptiveRate {
+            target,
+            min_rate_pct,
+            rate_pct: 100,
+            latency_sum: Duration::default(),
+            latency_count: 0,
+            last_adjustment: Instant::now(),
+        }
+    }
+
+    /// Returns the latency the limiter is kept under.
+    pub fn target(&self) -> Duration {
+        self.target
+    }
+
+    /// Returns the smallest rate, in percents of the configured rate.
+    pub fn min_rate_pct(&self) -> u64 {
+        self.min_rate_pct
+    }
+
+    /// Returns the current rate, in percents of the configured rate.
+    pub fn rate_pct(&self) -> u64 {
+        self.rate_pct
+    }
+
+    /// Records the latency of a completed operation, and returns the new rate if it changed.
+    pub fn record(&mut self, latency: Duration) -> Option<u64> {
+        self.latency_sum += latency;
+        self.latency_count += 1;
+        if self.last_adjustment.elapsed() < Duration::from_millis(ADJUST_INTERVAL_MS) {
+            return None;
+        }
+        self.adjust()
+    }
+
+    // Adjusts the rate to the average latency of the operations completed since the last
+    // adjustment.
+    fn adjust(&mut self) -> Option<u64> {
+        let average = self.latency_sum / cmp::max(self.latency_count, 1);
+        self.latency_sum = Duration::default();
+        self.latency_count = 0;
+        self.last_adjustment = Instant::now();
+
+        let rate_pct = if average > self.target {
+            cmp::max(self.rate_pct * 3 / 4, self.min_rate_pct)
+        } else {
+            cmp::min(self.rate_pct + RATE_STEP_PCT, 100)
+        };
+        if rate_pct == self.rate_pct {
+            return None;
+        }
+        self.rate_pct = rate_pct;
+        Some(rate_pct)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_rate() {
+        let mut adaptive = AdaptiveRate::new(Duration::from_millis(10), 50);
+        assert_eq!(adaptive.target(), Duration::from_millis(10));
+        assert_eq!(adaptive.min_rate_pct(), 50);
+        assert_eq!(adaptive.rate_pct(), 100);
+
+        // The rate is not adjusted before the interval elapsed.
+        assert_eq!(adaptive.record(Duration::from_millis(30)), None);
+        assert_eq!(adaptive.rate_pct(), 100);
+
+        // The average latency is over the target.
+        adaptive.record(Duration::from_millis(2));
+        assert_eq!(adaptive.adjust(), Some(75));
+        // The rate doesn't go under the minimum.
+        adaptive.record(Duration::from_millis(20));
+        assert_eq!(adaptive.adjust(), Some(56));
+        adaptive.record(Duration::from_millis(20));
+        assert_eq!(adaptive.adjust(), Some(50));
+        adaptive.record(Duration::from_millis(20));
+        assert_eq!(adaptive.adjust(), None);
+
+        // The rate is raised back while the latency is under the target.
+        adaptive.record(Duration::from_millis(5));
+        assert_eq!(adaptive.adjust(), Some(55));
+        // Without operations, the latency is deemed under the target.
+        assert_eq!(adaptive.adjust(), Some(60));
+        for _ in 0..8 {
+            adaptive.adjust();
+        }
+        assert_eq!(adaptive.rate_pct(), 100);
+        assert_eq!(adaptive.adjust(), None);
+
+        // The rate is adjusted once the interval elapsed.
+        std::thread::sleep(Duration::from_millis(ADJUST_INTERVAL_MS));
+        assert_eq!(adaptive.record(Duration::from_millis(11)), Some(75));
+    }
+}
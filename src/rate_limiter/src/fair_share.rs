@@ -0,0 +1,227 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Weighted fair sharing of the requests carried out by several devices, e.g. the drives backed
+//! by the same host disk.
+//!
+//! The scheduler works in rounds: in each round, each device may carry out as many requests as
+//! its weight. A device which used up its share while other devices still have a share left
+//! yields, and its wake up event is signaled once the next round starts. A new round starts as
+//! soon as no device with a share left has requests to carry out, so that the devices are never
+//! kept waiting while the others are idle.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use logger::error;
+use utils::eventfd::EventFd;
+
+/// The smallest weight of a device.
+pub const MIN_WEIGHT: u32 = 1;
+/// The largest weight of a device.
+pub const MAX_WEIGHT: u32 = 1000;
+
+// The share of a device in the current round.
+struct Share {
+    weight: u32,
+    // The number of requests the device may still carry out in the round.
+    credits: u32,
+    // Whether the device has requests to carry out, as far as the scheduler knows.
+    active: bool,
+    // Whether the device yielded, waiting for the next round.
+    waiting: bool,
+    wakeup_evt: EventFd,
+}
+
+/// Shares the requests carried out between the registered devices, in proportion to their
+/// weights.
+#[derive(Default)]
+pub struct FairShareScheduler {
+    shares: Mutex<BTreeMap<String, Share>>,
+}
+
+impl FairShareScheduler {
+    /// Schedules the requests of the device `id` with `weight`. `wakeup_evt` is signaled when
+    /// the device yielded and may carry out requests again.
+    pub fn register(&self, id: &str, weight: u32, wakeup_evt: EventFd) {
+        let share = Share {
+            weight,
+            credits: weight,
+            active: false,
+            waiting: false,
+            wakeup_evt,
+        };
+        self.shares
+            .lock()
+            .expect("Poisoned lock")
+            .insert(id.to_string(), share);
+    }
+
+    /// Stops scheduling the requests of the device `id`.
+    pub fn unregister(&self, id: &str) {
+        let mut shares = self.shares.lock().expect("Poisoned lock");
+        if shares.remove(id).is_some() {
+            Self::check_round(&mut shares);
+        }
+    }
+
+    /// Returns the weight of the device `id`, if its requests are scheduled.
+    pub fn weight(&self, id: &str) -> Option<u32> {
+        self.shares
+            .lock()
+            .expect("Poisoned lock")
+            .get(id)
+            .map(|share| share.weight)
+    }
+
+    /// Changes the weight of the device `id`, which takes effect in the current round.
+    pub fn set_weight(&self, id: &str, weight: u32) {
+        if let Some(share) = self.shares.lock().expect("Poisoned lock").get_mut(id) {
+            share.credits = std::cmp::min(share.credits, weight);
+            share.weight = weight;
+        }
+    }
+
+    /// Returns whether the device `id` may carry out a request now. When it may not, the device
+    /// must stop carrying out requests until its wake up event is signaled. The devices whose
+    /// requests are not scheduled may always carry them out.
+    pub fn dispatch(&self, id: &str) -> bool {
+        let mut shares = self.shares.lock().expect("Poisoned lock");
+        let share = match shares.get_mut(id) {
+            Some(share) => share,
+            None => return true,
+        };
+        share.active = true;
+        if share.credits > 0 {
+            share.credits -= 1;
+            return true;
+        }
+        if shares
+            .iter()
+            .any(|(other_id, other)| other_id != id && other.active && other.credits > 0)
+        {
+            // Safe to unwrap because the share was found above.
+            shares.get_mut(id).unwrap().waiting = true;
+            return false;
+        }
+        Self::start_round(&mut shares);
+        // Safe to unwrap because the share was found above.
+        let share = shares.get_mut(id).unwrap();
+        share.credits -= 1;
+        true
+    }
+
+    /// Tells the scheduler that the device `id` has no request to carry out for now, e.g. as its
+    /// queue is empty or its rate limiter is blocked.
+    pub fn idle(&self, id: &str) {
+        let mut shares = self.shares.lock().expect("Poisoned lock");
+        if let Some(share) = shares.get_mut(id) {
+            share.active = false;
+            Self::check_round(&mut shares);
+        }
+    }
+
+    // Starts the next round if the waiting devices are only waiting for idle ones.
+    fn check_round(shares: &mut BTreeMap<String, Share>) {
+        let waiting = shares.values().any(|share| share.waiting);
+        let pending = shares
+            .values()
+            .any(|share| share.active && !share.waiting && share.credits > 0);
+        if waiting && !pending {
+            Self::start_round(shares);
+        }
+    }
+
+    // Gives all the devices their share of the new round, and wakes up the waiting ones.
+    fn start_round(shares: &mut BTreeMap<String, Share>) {
+        for share in shares.values_mut() {
+            share.credits = share.weight;
+            if share.waiting {
+                share.waiting = false;
+                if let Err(e) = share.wakeup_evt.write(1) {
+                    error!("Failed to wake up the device waiting for its share: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(scheduler: &FairShareScheduler, id: &str, weight: u32) -> EventFd {
+        let wakeup_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        scheduler.register(id, weight, wakeup_evt.try_clone().unwrap());
+        wakeup_evt
+    }
+
+    #[test]
+    fn test_unscheduled_device() {
+        let scheduler = FairShareScheduler::default();
+        assert!(scheduler.dispatch("drive"));
+        assert_eq!(scheduler.weight("drive"), None);
+        scheduler.idle("drive");
+        scheduler.set_weight("drive", 1);
+        assert_eq!(scheduler.weight("drive"), None);
+    }
+
+    #[test]
+    fn test_single_device() {
+        let scheduler = FairShareScheduler::default();
+        let _evt = register(&scheduler, "drive", 2);
+        assert_eq!(scheduler.weight("drive"), Some(2));
+        // A device alone is never kept waiting.
+        for _ in 0..10 {
+            assert!(scheduler.dispatch("drive"));
+        }
+    }
+
+    #[test]
+    fn test_weighted_rounds() {
+        let scheduler = FairShareScheduler::default();
+        let evt_a = register(&scheduler, "a", 2);
+        let evt_b = register(&scheduler, "b", 1);
+
+        // Both devices are busy: `a` carries out two requests per round, and `b` one.
+        assert!(scheduler.dispatch("b"));
+        assert!(scheduler.dispatch("a"));
+        assert!(scheduler.dispatch("a"));
+        // Both used up their share, so the next round starts.
+        assert!(scheduler.dispatch("b"));
+        // `b` used up its share, while `a` has its share of the round left.
+        assert!(!scheduler.dispatch("b"));
+        assert!(scheduler.dispatch("a"));
+        assert!(scheduler.dispatch("a"));
+        assert!(evt_b.read().is_err());
+        // `a` used up its share too, so the next round starts and `b` is woken up.
+        assert!(scheduler.dispatch("a"));
+        assert_eq!(evt_b.read().unwrap(), 1);
+        assert!(scheduler.dispatch("b"));
+        assert!(evt_a.read().is_err());
+    }
+
+    #[test]
+    fn test_yield_and_idle() {
+        let scheduler = FairShareScheduler::default();
+        let evt_a = register(&scheduler, "a", 1);
+        let _evt_b = register(&scheduler, "b", 1);
+
+        assert!(scheduler.dispatch("a"));
+        assert!(scheduler.dispatch("b"));
+        assert!(scheduler.dispatch("b"));
+        // `b` started a new round, so `a` has a share again, and `b` yields.
+        assert!(!scheduler.dispatch("b"));
+        assert!(scheduler.dispatch("a"));
+        // `a` has nothing left to carry out, so `b` gets the next round.
+        scheduler.idle("a");
+        assert!(scheduler.dispatch("b"));
+        assert!(evt_a.read().is_err());
+
+        scheduler.set_weight("b", 3);
+        assert_eq!(scheduler.weight("b"), Some(3));
+        scheduler.unregister("b");
+        assert_eq!(scheduler.weight("b"), None);
+        assert!(scheduler.dispatch("b"));
+    }
+}
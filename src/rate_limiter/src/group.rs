@@ -0,0 +1,212 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Token buckets shared by the rate limiters of several devices, e.g. to cap the total disk
+//! bandwidth of a microVM on top of the bandwidth of each drive.
+//!
+//! The rate limiters attached to a group only go ahead with an operation when both their own
+//! token buckets and the buckets of the group allow it. The budget of the group is drained
+//! fairly: once an operation of a device is refused for lack of tokens in a group bucket, the
+//! bucket serves the refused operations in the order they were refused, before any other one,
+//! so that a busy device cannot starve the others.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::{BucketReduction, TokenBucket, TokenType};
+
+// A token bucket of a group, with the members waiting for its tokens.
+struct SharedBucket {
+    bucket: TokenBucket,
+    // The members whose operations were refused, in the order they were refused.
+    waiting: VecDeque<u64>,
+}
+
+impl SharedBucket {
+    fn new(bucket: TokenBucket) -> Self {
+        SharedBucket {
+            bucket,
+            waiting: VecDeque::new(),
+        }
+    }
+
+    fn reduce(&mut self, member: u64, tokens: u64) -> bool {
+        // The members which were refused first are served first.
+        if self.waiting.front().map_or(false, |first| *first != member) {
+            if !self.waiting.contains(&member) {
+                self.waiting.push_back(member);
+            }
+            return false;
+        }
+        match self.bucket.reduce(tokens) {
+            BucketReduction::Failure => {
+                if self.waiting.is_empty() {
+                    self.waiting.push_back(member);
+                }
+                false
+            }
+            // An operation larger than the bucket empties it, and the next ones wait for it to
+            // refill.
+            BucketReduction::Success | BucketReduction::OverConsumption(_) => {
+                if self.waiting.front() == Some(&member) {
+                    self.waiting.pop_front();
+                }
+                true
+            }
+        }
+    }
+
+    fn remove(&mut self, member: u64) {
+        self.waiting.retain(|waiting| *waiting != member);
+    }
+}
+
+struct GroupBuckets {
+    bandwidth: Option<SharedBucket>,
+    ops: Option<SharedBucket>,
+}
+
+impl GroupBuckets {
+    fn bucket_mut(&mut self, token_type: TokenType) -> Option<&mut SharedBucket> {
+        match token_type {
+            TokenType::Bytes => self.bandwidth.as_mut(),
+            TokenType::Ops => self.ops.as_mut(),
+        }
+    }
+}
+
+/// A named set of token buckets shared by the rate limiters attached to it.
+pub struct RateLimiterGroup {
+    id: String,
+    buckets: Mutex<GroupBuckets>,
+    next_member: AtomicU64,
+}
+
+impl RateLimiterGroup {
+    /// Creates the group `id`, limiting the bandwidth and the operations of its members with the
+    /// given buckets. A group without a bucket limits nothing.
+    pub fn new(id: String, bandwidth: Option<TokenBucket>, ops: Option<TokenBucket>) -> Self {
+        RateLimiterGroup {
+            id,
+            buckets: Mutex::new(GroupBuckets {
+                bandwidth: bandwidth.map(SharedBucket::new),
+                ops: ops.map(SharedBucket::new),
+            }),
+            next_member: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the ID of the group.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Attaches a new member to the `group`, which draws from its buckets.
+    pub fn attach(group: &Arc<RateLimiterGroup>) -> GroupMember {
+        GroupMember {
+            group: group.clone(),
+            id: group.next_member.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+/// A rate limiter attached to a group, which is detached when dropped.
+pub struct GroupMember {
+    group: Arc<RateLimiterGroup>,
+    id: u64,
+}
+
+impl GroupMember {
+    /// Returns the group the member is attached to.
+    pub fn group(&self) -> &Arc<RateLimiterGroup> {
+        &self.group
+    }
+
+    /// Attempts to consume `tokens` from the bucket of the group for `token_type`, and returns
+    /// whether that is possible. The operations refused earlier to the other members go first.
+    pub fn consume(&self, tokens: u64, token_type: TokenType) -> bool {
+        let mut buckets = self.group.buckets.lock().expect("Poisoned lock");
+        buckets
+            .bucket_mut(token_type)
+            .map_or(true, |bucket| bucket.reduce(self.id, tokens))
+    }
+
+    /// Gives back `tokens` to the bucket of the group for `token_type`.
+    pub fn replenish(&self, tokens: u64, token_type: TokenType) {
+        let mut buckets = self.group.buckets.lock().expect("Poisoned lock");
+        if let Some(bucket) = buckets.bucket_mut(token_type) {
+            bucket.bucket.replenish(tokens);
+        }
+    }
+}
+
+impl Drop for GroupMember {
+    fn drop(&mut self) {
+        // A member which goes away must not keep the others waiting.
+        let mut buckets = self.group.buckets.lock().expect("Poisoned lock");
+        if let Some(bucket) = buckets.bandwidth.as_mut() {
+            bucket.remove(self.id);
+        }
+        if let Some(bucket) = buckets.ops.as_mut() {
+            bucket.remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_group_without_buckets() {
+        let group = Arc::new(RateLimiterGroup::new("group".to_string(), None, None));
+        assert_eq!(group.id(), "group");
+        let member = RateLimiterGroup::attach(&group);
+        assert_eq!(member.group().id(), "group");
+        assert!(member.consume(u64::max_value(), TokenType::Bytes));
+        assert!(member.consume(u64::max_value(), TokenType::Ops));
+    }
+
+    #[test]
+    fn test_fair_draining() {
+        // 1000 ops/s shared by the members, and no bandwidth limit.
+        let ops = TokenBucket::new(1000, 0, 1000);
+        let group = Arc::new(RateLimiterGroup::new("group".to_string(), None, ops));
+        let busy = RateLimiterGroup::attach(&group);
+        let other = RateLimiterGroup::attach(&group);
+
+        assert!(busy.consume(1000, TokenType::Ops));
+        assert!(busy.consume(u64::max_value(), TokenType::Bytes));
+        // `other` is refused, so it goes first once the bucket refills.
+        assert!(!other.consume(100, TokenType::Ops));
+        thread::sleep(Duration::from_millis(200));
+        assert!(!busy.consume(1, TokenType::Ops));
+        assert!(other.consume(100, TokenType::Ops));
+        // Then `busy`, which was refused next.
+        assert!(busy.consume(1, TokenType::Ops));
+
+        // The tokens given back can be consumed again.
+        busy.replenish(50, TokenType::Ops);
+        assert!(other.consume(50, TokenType::Ops));
+    }
+
+    #[test]
+    fn test_detach() {
+        let ops = TokenBucket::new(100, 0, 1000);
+        let group = Arc::new(RateLimiterGroup::new("group".to_string(), None, ops));
+        let first = RateLimiterGroup::attach(&group);
+        let second = RateLimiterGroup::attach(&group);
+
+        assert!(first.consume(100, TokenType::Ops));
+        assert!(!first.consume(100, TokenType::Ops));
+        // `first` is waiting, and keeps `second` waiting until it is dropped.
+        thread::sleep(Duration::from_millis(200));
+        assert!(!second.consume(10, TokenType::Ops));
+        drop(first);
+        assert!(second.consume(10, TokenType::Ops));
+    }
+}
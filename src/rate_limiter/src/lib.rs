@@ -34,6 +34,15 @@
 //! The granularity for 'wake up' events when the rate limiter is blocked is
 //! currently hardcoded to `100 milliseconds`.
 //!
+//! A rate limiter can also be attached to a `group::RateLimiterGroup`, whose token buckets are
+//! shared by all the rate limiters attached to it. Its `consume()` operations then only succeed
+//! when both its own buckets and the buckets of the group have enough budget.
+//!
+//! The rate of a rate limiter can also be tuned by an `adaptive::AdaptiveRate`, fed with the
+//! latency of the operations the limiter let through via `record_latency()`. The buckets then
+//! refill at a percentage of their configured rate, which shrinks while the latency is over its
+//! target and grows back once it is under.
+//!
 //! ## Limitations
 //!
 //! This rate limiter implementation relies on the *Linux kernel's timerfd* so its
@@ -43,12 +52,17 @@
 //! It is meant to be used in an external event loop and thus implements the `AsRawFd`
 //! trait and provides an *event-handler* as part of its API. This *event-handler*
 //! needs to be called by the user on every event on the rate limiter's `AsRawFd` FD.
+use adaptive::AdaptiveRate;
+use group::GroupMember;
 use logger::error;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::{Duration, Instant};
 use std::{fmt, io};
 use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
 
+pub mod adaptive;
+pub mod fair_share;
+pub mod group;
 pub mod persist;
 
 #[derive(Debug)]
@@ -96,6 +110,8 @@ pub struct TokenBucket {
     size: u64,
     // Initial burst size (number of free initial tokens, that can be consumed at no cost)
     one_time_burst: u64,
+    // The one time burst the bucket was created with.
+    initial_one_time_burst: u64,
     // Complete refill time in milliseconds.
     refill_time: u64,
 
@@ -106,6 +122,9 @@ pub struct TokenBucket {
     // Fields used for pre-processing optimizations.
     processed_capacity: u64,
     processed_refill_time: u64,
+
+    // The rate the bucket refills at, in percents of the configured rate.
+    rate_pct: u64,
 }
 
 impl TokenBucket {
@@ -137,6 +156,7 @@ impl TokenBucket {
         Some(TokenBucket {
             size,
             one_time_burst,
+            initial_one_time_burst: one_time_burst,
             refill_time: complete_refill_time_ms,
             // Start off full.
             budget: size,
@@ -144,9 +164,24 @@ impl TokenBucket {
             last_update: Instant::now(),
             processed_capacity,
             processed_refill_time,
+            rate_pct: 100,
         })
     }
 
+    // Returns the number of tokens refilled in `time_delta` nanoseconds.
+    fn refill_amount(&self, time_delta: u64) -> u64 {
+        // At each 'time_delta' nanoseconds the bucket should refill with:
+        // refill_amount = (time_delta * size) / (complete_refill_time_ms * 1_000_000)
+        // `processed_capacity` and `processed_refill_time` are the result of simplifying above
+        // fraction formula with their greatest-common-factor. The amount is then scaled to the
+        // rate of the bucket.
+        let refill = u128::from(time_delta)
+            * u128::from(self.processed_capacity)
+            * u128::from(self.rate_pct)
+            / (u128::from(self.processed_refill_time) * 100);
+        std::cmp::min(refill, u128::from(u64::max_value())) as u64
+    }
+
     /// Attempts to consume `tokens` from the bucket and returns whether the action succeeded.
     // TODO (Issue #259): handle cases where a single request is larger than the full capacity
     // for such cases we need to support partial fulfilment of requests
@@ -171,11 +206,7 @@ impl TokenBucket {
         let time_delta = self.last_update.elapsed().as_nanos() as u64;
         self.last_update = Instant::now();
 
-        // At each 'time_delta' nanoseconds the bucket should refill with:
-        // refill_amount = (time_delta * size) / (complete_refill_time_ms * 1_000_000)
-        // `processed_capacity` and `processed_refill_time` are the result of simplifying above
-        // fraction formula with their greatest-common-factor.
-        self.budget += (time_delta * self.processed_capacity) / self.processed_refill_time;
+        self.budget = self.budget.saturating_add(self.refill_amount(time_delta));
 
         if self.budget >= self.size {
             self.budget = self.size;
@@ -225,6 +256,11 @@ impl TokenBucket {
         self.one_time_burst
     }
 
+    /// Returns the one time burst budget the bucket was created with.
+    pub fn initial_one_time_burst(&self) -> u64 {
+        self.initial_one_time_burst
+    }
+
     /// Returns the time in milliseconds required to to completely fill the bucket.
     pub fn refill_time_ms(&self) -> u64 {
         self.refill_time
@@ -234,9 +270,31 @@ impl TokenBucket {
     pub fn budget(&self) -> u64 {
         self.budget
     }
+
+    /// Returns the budget the bucket holds now, counting the tokens refilled since it was last
+    /// updated, without updating it.
+    pub fn current_budget(&self) -> u64 {
+        let time_delta = self.last_update.elapsed().as_nanos() as u64;
+        let refill = self.refill_amount(time_delta);
+        std::cmp::min(self.budget.saturating_add(refill), self.size)
+    }
+
+    /// Returns the rate the bucket refills at, in percents of its configured rate.
+    pub fn rate_pct(&self) -> u64 {
+        self.rate_pct
+    }
+
+    /// Makes the bucket refill at `rate_pct` percents of its configured rate from now on.
+    pub fn set_rate_pct(&mut self, rate_pct: u64) {
+        // Settle the tokens refilled at the previous rate first.
+        self.budget = self.current_budget();
+        self.last_update = Instant::now();
+        self.rate_pct = rate_pct;
+    }
 }
 
 /// Enum that describes the type of token used.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TokenType {
     /// Token type used for bandwidth limiting.
     Bytes,
@@ -276,6 +334,18 @@ pub struct RateLimiter {
     timer_fd: TimerFd,
     // Internal flag that quickly determines timer state.
     timer_active: bool,
+
+    // The number of `consume()` operations which failed for lack of budget.
+    throttled_count: u64,
+    // The time spent blocked, the current blocking notwithstanding.
+    throttled_time: Duration,
+    // When the limiter got blocked, if it is.
+    blocked_since: Option<Instant>,
+
+    // The group whose token buckets the limiter shares, if any.
+    group: Option<GroupMember>,
+    // The tuning of the rate to the latency of the operations, if enabled.
+    adaptive: Option<AdaptiveRate>,
 }
 
 impl PartialEq for RateLimiter {
@@ -346,6 +416,11 @@ impl RateLimiter {
             ops: ops_token_bucket,
             timer_fd,
             timer_active: false,
+            throttled_count: 0,
+            throttled_time: Duration::default(),
+            blocked_since: None,
+            group: None,
+            adaptive: None,
         })
     }
 
@@ -354,6 +429,9 @@ impl RateLimiter {
         // Register the timer; don't care about its previous state
         self.timer_fd.set_state(timer_state, SetTimeFlags::Default);
         self.timer_active = true;
+        if self.blocked_since.is_none() {
+            self.blocked_since = Some(Instant::now());
+        }
     }
 
     /// Attempts to consume tokens and returns whether that is possible.
@@ -362,9 +440,32 @@ impl RateLimiter {
     pub fn consume(&mut self, tokens: u64, token_type: TokenType) -> bool {
         // If the timer is active, we can't consume tokens from any bucket and the function fails.
         if self.timer_active {
+            self.throttled_count += 1;
+            return false;
+        }
+        if !self.consume_own(tokens, token_type) {
+            return false;
+        }
+
+        // The budget of the group is only drawn from once the own buckets allowed the operation.
+        let group_refused = self
+            .group
+            .as_ref()
+            .map_or(false, |group| !group.consume(tokens, token_type));
+        if group_refused {
+            // Give back the tokens, as the operation will be retried once the timer fires.
+            self.manual_replenish(tokens, token_type);
+            self.throttled_count += 1;
+            if !self.timer_active {
+                self.activate_timer(TIMER_REFILL_STATE);
+            }
             return false;
         }
+        true
+    }
 
+    // Attempts to consume tokens from the own buckets of the limiter.
+    fn consume_own(&mut self, tokens: u64, token_type: TokenType) -> bool {
         // Identify the required token bucket.
         let token_bucket = match token_type {
             TokenType::Bytes => self.bandwidth.as_mut(),
@@ -372,12 +473,14 @@ impl RateLimiter {
         };
         // Try to consume from the token bucket.
         if let Some(bucket) = token_bucket {
-            let refill_time = bucket.refill_time_ms();
+            // A bucket refilling at a lower rate takes longer to refill.
+            let refill_time = bucket.refill_time_ms() * 100 / std::cmp::max(bucket.rate_pct(), 1);
             match bucket.reduce(tokens) {
                 // When we report budget is over, there will be no further calls here,
                 // register a timer to replenish the bucket and resume processing;
                 // make sure there is only one running timer for this limiter.
                 BucketReduction::Failure => {
+                    self.throttled_count += 1;
                     if !self.timer_active {
                         self.activate_timer(TIMER_REFILL_STATE);
                     }
@@ -445,6 +548,9 @@ impl RateLimiter {
             )),
             _ => {
                 self.timer_active = false;
+                if let Some(blocked_since) = self.blocked_since.take() {
+                    self.throttled_time += blocked_since.elapsed();
+                }
                 Ok(())
             }
         }
@@ -463,6 +569,42 @@ impl RateLimiter {
             BucketUpdate::Update(tb) => self.ops = Some(tb),
             BucketUpdate::None => (),
         };
+        // The new buckets refill at the rate the limiter is currently tuned to.
+        if let Some(rate_pct) = self.adaptive.as_ref().map(AdaptiveRate::rate_pct) {
+            self.set_rate_pct(rate_pct);
+        }
+    }
+
+    /// Enables the tuning of the rate of the limiter to the latency of its operations, or
+    /// disables it with `None`, in which case the buckets get back to their configured rate.
+    pub fn set_adaptive(&mut self, adaptive: Option<AdaptiveRate>) {
+        let rate_pct = adaptive.as_ref().map_or(100, AdaptiveRate::rate_pct);
+        self.adaptive = adaptive;
+        self.set_rate_pct(rate_pct);
+    }
+
+    /// Returns the tuning of the rate of the limiter, if enabled.
+    pub fn adaptive(&self) -> Option<&AdaptiveRate> {
+        self.adaptive.as_ref()
+    }
+
+    /// Records the latency of an operation the limiter let through, and tunes the rate of the
+    /// buckets accordingly if adaptive rate limiting is enabled.
+    pub fn record_latency(&mut self, latency: Duration) {
+        if let Some(rate_pct) = self
+            .adaptive
+            .as_mut()
+            .and_then(|adaptive| adaptive.record(latency))
+        {
+            self.set_rate_pct(rate_pct);
+        }
+    }
+
+    // Makes both buckets refill at `rate_pct` percents of their configured rate.
+    fn set_rate_pct(&mut self, rate_pct: u64) {
+        for bucket in self.bandwidth.iter_mut().chain(self.ops.iter_mut()) {
+            bucket.set_rate_pct(rate_pct);
+        }
     }
 
     /// Returns an immutable view of the inner bandwidth token bucket.
@@ -474,6 +616,29 @@ impl RateLimiter {
     pub fn ops(&self) -> Option<&TokenBucket> {
         self.ops.as_ref()
     }
+
+    /// Returns the number of `consume()` operations which failed for lack of budget.
+    pub fn throttled_count(&self) -> u64 {
+        self.throttled_count
+    }
+
+    /// Attaches the rate limiter to a group, or detaches it with `None`.
+    pub fn set_group(&mut self, group: Option<GroupMember>) {
+        self.group = group;
+    }
+
+    /// Returns the ID of the group the rate limiter is attached to, if any.
+    pub fn group_id(&self) -> Option<&str> {
+        self.group.as_ref().map(|group| group.group().id())
+    }
+
+    /// Returns the total time this rate limiter spent blocked, including the current blocking.
+    pub fn throttled_time(&self) -> Duration {
+        self.throttled_time
+            + self
+                .blocked_since
+                .map_or_else(Duration::default, |blocked_since| blocked_since.elapsed())
+    }
 }
 
 impl AsRawFd for RateLimiter {
@@ -499,6 +664,8 @@ impl Default for RateLimiter {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use group::RateLimiterGroup;
+    use std::sync::Arc;
     use std::thread;
     use std::time::Duration;
 
@@ -596,6 +763,7 @@ pub(crate) mod tests {
         assert_eq!(tb.one_time_burst(), 100);
         assert_eq!(tb.reduce(500), BucketReduction::Success);
         assert_eq!(tb.one_time_burst(), 0);
+        assert_eq!(tb.initial_one_time_burst(), 1100);
         assert_eq!(tb.reduce(500), BucketReduction::Success);
         assert_eq!(tb.reduce(500), BucketReduction::Failure);
         thread::sleep(Duration::from_millis(500));
@@ -701,6 +869,64 @@ pub(crate) mod tests {
         assert!(l.consume(100, TokenType::Bytes));
     }
 
+    #[test]
+    fn test_rate_limiter_throttle_stats() {
+        // rate limiter with limit of 1000 bytes/s
+        let mut l = RateLimiter::new(1000, 0, 1000, 0, 0, 0).unwrap();
+        assert_eq!(l.throttled_count(), 0);
+        assert_eq!(l.throttled_time(), Duration::default());
+
+        assert!(l.consume(900, TokenType::Bytes));
+        let bw = l.bandwidth().unwrap();
+        assert_eq!(bw.budget(), 100);
+        // The budget is refilled at 1 byte/ms, without updating the bucket.
+        thread::sleep(Duration::from_millis(50));
+        assert!(bw.current_budget() >= 150);
+        assert_eq!(bw.budget(), 100);
+
+        // Both the failed operation and the ones attempted while blocked are throttled.
+        assert!(!l.consume(1000, TokenType::Bytes));
+        assert!(!l.consume(1, TokenType::Bytes));
+        assert_eq!(l.throttled_count(), 2);
+        thread::sleep(Duration::from_millis(REFILL_TIMER_INTERVAL_MS));
+        // The current blocking is accounted for.
+        let blocked_time = l.throttled_time();
+        assert!(blocked_time >= Duration::from_millis(REFILL_TIMER_INTERVAL_MS));
+        assert!(l.event_handler().is_ok());
+        let throttled_time = l.throttled_time();
+        assert!(throttled_time >= blocked_time);
+        // The time stops adding up once unblocked.
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(l.throttled_time(), throttled_time);
+    }
+
+    #[test]
+    fn test_rate_limiter_group() {
+        // Two limiters of 800 bytes/s each, sharing a group of 1000 bytes/s.
+        let bandwidth = TokenBucket::new(1000, 0, 1000);
+        let group = Arc::new(RateLimiterGroup::new("group".to_string(), bandwidth, None));
+        let mut first = RateLimiter::new(800, 0, 1000, 0, 0, 0).unwrap();
+        let mut second = RateLimiter::new(800, 0, 1000, 0, 0, 0).unwrap();
+        assert_eq!(first.group_id(), None);
+        first.set_group(Some(RateLimiterGroup::attach(&group)));
+        second.set_group(Some(RateLimiterGroup::attach(&group)));
+        assert_eq!(first.group_id(), Some("group"));
+
+        assert!(first.consume(800, TokenType::Bytes));
+        // The own bucket of `second` allows the operation, but the group doesn't.
+        assert!(!second.consume(800, TokenType::Bytes));
+        assert!(second.is_blocked());
+        assert_eq!(second.throttled_count(), 1);
+        // The tokens consumed from the own bucket are given back.
+        assert_eq!(second.bandwidth().unwrap().budget(), 800);
+        // The group does not limit the operations.
+        assert!(first.consume(1, TokenType::Ops));
+
+        first.set_group(None);
+        assert_eq!(first.group_id(), None);
+        assert_eq!(Arc::strong_count(&group), 2);
+    }
+
     #[test]
     fn test_rate_limiter_ops() {
         // rate limiter with limit of 1000 ops/s
@@ -859,6 +1085,59 @@ pub(crate) mod tests {
         assert_eq!(x.ops, None);
     }
 
+    #[test]
+    fn test_token_bucket_rate_pct() {
+        // A bucket refilling 1 token/ms at its configured rate.
+        let mut tb = TokenBucket::new(1000, 0, 1000).unwrap();
+        assert_eq!(tb.rate_pct(), 100);
+        assert_eq!(tb.reduce(1000), BucketReduction::Success);
+
+        // At half its rate, the bucket refills 1 token every 2ms.
+        tb.set_rate_pct(50);
+        assert_eq!(tb.rate_pct(), 50);
+        thread::sleep(Duration::from_millis(200));
+        assert!(tb.current_budget() >= 100);
+        assert!(tb.current_budget() < 200);
+
+        // The tokens refilled at the previous rate are kept when the rate changes.
+        tb.set_rate_pct(100);
+        let budget = tb.budget();
+        assert!(budget >= 100);
+        thread::sleep(Duration::from_millis(100));
+        assert!(tb.current_budget() >= budget + 100);
+    }
+
+    #[test]
+    fn test_rate_limiter_adaptive() {
+        let mut l = RateLimiter::new(1000, 0, 1000, 100, 0, 1000).unwrap();
+        assert!(l.adaptive().is_none());
+        // Without adaptive rate limiting, the latency is ignored.
+        l.record_latency(Duration::from_secs(1));
+        assert_eq!(l.bandwidth().unwrap().rate_pct(), 100);
+
+        l.set_adaptive(Some(AdaptiveRate::new(Duration::from_millis(10), 20)));
+        assert_eq!(l.adaptive().unwrap().rate_pct(), 100);
+        // The rate is tuned once the adjustment interval elapsed.
+        thread::sleep(Duration::from_millis(100));
+        l.record_latency(Duration::from_millis(50));
+        assert_eq!(l.adaptive().unwrap().rate_pct(), 75);
+        assert_eq!(l.bandwidth().unwrap().rate_pct(), 75);
+        assert_eq!(l.ops().unwrap().rate_pct(), 75);
+
+        // Updated buckets refill at the tuned rate.
+        l.update_buckets(
+            BucketUpdate::Update(TokenBucket::new(2000, 0, 1000).unwrap()),
+            BucketUpdate::None,
+        );
+        assert_eq!(l.bandwidth().unwrap().rate_pct(), 75);
+
+        // Disabling adaptive rate limiting restores the configured rate.
+        l.set_adaptive(None);
+        assert!(l.adaptive().is_none());
+        assert_eq!(l.bandwidth().unwrap().rate_pct(), 100);
+        assert_eq!(l.ops().unwrap().rate_pct(), 100);
+    }
+
     #[test]
     fn test_rate_limiter_debug() {
         let l = RateLimiter::new(1, 2, 3, 4, 5, 6).unwrap();
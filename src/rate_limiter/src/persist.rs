@@ -85,6 +85,13 @@ impl Persist<'_> for RateLimiter {
             },
             timer_fd: TimerFd::new_custom(ClockId::Monotonic, true, true)?,
             timer_active: false,
+            throttled_count: 0,
+            throttled_time: Duration::default(),
+            blocked_since: None,
+            // The groups are not part of the snapshot, so the restored limiter is detached.
+            group: None,
+            // Neither is the adaptive tuning, so the restored limiter runs at its configured rate.
+            adaptive: None,
         };
 
         Ok(rate_limiter)
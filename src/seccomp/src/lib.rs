@@ -233,6 +233,7 @@
 use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::fmt::{Display, Formatter};
+use std::os::unix::io::RawFd;
 
 /// Maximum number of instructions that a BPF program can have.
 const BPF_MAX_LEN: usize = 4096;
@@ -269,8 +270,14 @@ const SECCOMP_RET_KILL: u32 = 0x0000_0000;
 const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
 const SECCOMP_RET_TRACE: u32 = 0x7ff0_0000;
 const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc0_0000;
 const SECCOMP_RET_MASK: u32 = 0x0000_ffff;
 
+// Operation and flag of the seccomp syscall.
+// See /usr/include/linux/seccomp.h .
+const SECCOMP_SET_MODE_FILTER: libc::c_uint = 1;
+const SECCOMP_FILTER_FLAG_NEW_LISTENER: libc::c_uint = 1 << 3;
+
 // Architecture identifier.
 // See /usr/include/linux/audit.h .
 
@@ -284,6 +291,18 @@ const AUDIT_ARCH_X86_64: u32 = 62 | 0x8000_0000 | 0x4000_0000;
 // `#define AUDIT_ARCH_AARCH64	(EM_AARCH64|__AUDIT_ARCH_64BIT|__AUDIT_ARCH_LE)`
 const AUDIT_ARCH_AARCH64: u32 = 183 | 0x8000_0000 | 0x4000_0000;
 
+// The architecture the BPF programs are built for.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = AUDIT_ARCH_X86_64;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = AUDIT_ARCH_AARCH64;
+
+// The serialized BPF programs start with a magic number followed by their architecture, then
+// hold the little endian `code`, `jt`, `jf` and `k` fields of every instruction.
+const SERIALIZED_MAGIC: &[u8] = b"FCSECBPF";
+const SERIALIZED_HEADER_LEN: usize = 12;
+const SERIALIZED_INSTRUCTION_LEN: usize = 8;
+
 // The maximum number of a syscall argument.
 // A syscall can have at most 6 arguments.
 // Arguments are numbered from 0 to 5.
@@ -319,6 +338,10 @@ pub enum Error {
     InvalidArgumentNumber,
     /// Failed to load seccomp rules into the kernel.
     Load(i32),
+    /// The serialized BPF program is malformed.
+    MalformedProgram,
+    /// The serialized BPF program was built for another architecture.
+    WrongArchitecture(u32),
 }
 
 impl Display for Error {
@@ -337,6 +360,12 @@ impl Display for Error {
                 "Failed to load seccomp rules into the kernel with error {}.",
                 err
             ),
+            MalformedProgram => write!(f, "The serialized seccomp program is malformed."),
+            WrongArchitecture(arch) => write!(
+                f,
+                "The serialized seccomp program was built for another architecture: {:#x}.",
+                arch
+            ),
         }
     }
 }
@@ -399,6 +428,8 @@ pub enum SeccompAction {
     Trace(u32),
     /// Sends `SIGSYS` to the calling process.
     Trap,
+    /// Notifies the process listening on the filter, and waits for its response.
+    UserNotif,
 }
 
 /// Rule that `seccomp` attempts to match for a syscall.
@@ -751,6 +782,7 @@ impl From<SeccompAction> for u32 {
             SeccompAction::Log => SECCOMP_RET_LOG,
             SeccompAction::Trace(x) => SECCOMP_RET_TRACE | (x & SECCOMP_RET_MASK),
             SeccompAction::Trap => SECCOMP_RET_TRAP,
+            SeccompAction::UserNotif => SECCOMP_RET_USER_NOTIF,
         }
     }
 }
@@ -946,6 +978,54 @@ impl SeccompFilter {
         Ok(())
     }
 
+    /// Builds the array of filter instructions and sends them to the kernel, along with a
+    /// request for the listener of the `SeccompAction::UserNotif` notifications.
+    ///
+    /// Returns the file descriptor of the listener. Requires a 5.0 or newer host kernel.
+    ///
+    /// # Arguments
+    ///
+    /// * `filters` - BPF program containing the seccomp rules.
+    pub fn apply_with_listener(filters: BpfProgram) -> Result<RawFd> {
+        // The kernel does not create a listener without a filter.
+        if filters.is_empty() {
+            return Err(Error::EmptyRulesVector);
+        }
+
+        let mut bpf_filter = Vec::new();
+        bpf_filter.extend(VALIDATE_ARCHITECTURE());
+        bpf_filter.extend(filters);
+
+        unsafe {
+            let rc = libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0);
+            if rc != 0 {
+                return Err(Error::Load(*libc::__errno_location()));
+            }
+
+            let bpf_prog = sock_fprog {
+                len: bpf_filter.len() as u16,
+                filter: bpf_filter.as_ptr(),
+            };
+            // Only the seccomp syscall can request the listener.
+            let fd = libc::syscall(
+                libc::SYS_seccomp,
+                SECCOMP_SET_MODE_FILTER,
+                SECCOMP_FILTER_FLAG_NEW_LISTENER,
+                &bpf_prog as *const sock_fprog,
+            );
+            if fd < 0 {
+                return Err(Error::Load(*libc::__errno_location()));
+            }
+            Ok(fd as RawFd)
+        }
+    }
+
+    /// Replaces the action taken when none of the rules of a syscall match.
+    pub fn with_default_action(mut self, default_action: SeccompAction) -> SeccompFilter {
+        self.default_action = default_action;
+        self
+    }
+
     /// Appends a chain of rules to an accumulator, updating the length of the filter.
     ///
     /// # Arguments
@@ -1061,6 +1141,68 @@ impl TryInto<BpfProgram> for SeccompFilter {
     }
 }
 
+/// Serializes a BPF program built for the current architecture, so that it can be loaded with
+/// `deserialize_program` without being built again.
+pub fn serialize_program(program: BpfProgramRef) -> Vec<u8> {
+    let mut bytes =
+        Vec::with_capacity(SERIALIZED_HEADER_LEN + program.len() * SERIALIZED_INSTRUCTION_LEN);
+    bytes.extend_from_slice(SERIALIZED_MAGIC);
+    bytes.extend_from_slice(&AUDIT_ARCH.to_le_bytes());
+    for instruction in program.iter() {
+        bytes.extend_from_slice(&instruction.code.to_le_bytes());
+        bytes.push(instruction.jt);
+        bytes.push(instruction.jf);
+        bytes.extend_from_slice(&instruction.k.to_le_bytes());
+    }
+    bytes
+}
+
+/// Returns whether `bytes` hold a BPF program serialized with `serialize_program`.
+pub fn is_serialized_program(bytes: &[u8]) -> bool {
+    bytes.starts_with(SERIALIZED_MAGIC)
+}
+
+/// Deserializes a BPF program serialized with `serialize_program`.
+///
+/// # Arguments
+///
+/// * `bytes` - The serialized program.
+pub fn deserialize_program(bytes: &[u8]) -> Result<BpfProgram> {
+    if !is_serialized_program(bytes)
+        || bytes.len() < SERIALIZED_HEADER_LEN
+        || (bytes.len() - SERIALIZED_HEADER_LEN) % SERIALIZED_INSTRUCTION_LEN != 0
+    {
+        return Err(Error::MalformedProgram);
+    }
+
+    let mut arch = [0u8; 4];
+    arch.copy_from_slice(&bytes[SERIALIZED_MAGIC.len()..SERIALIZED_HEADER_LEN]);
+    let arch = u32::from_le_bytes(arch);
+    if arch != AUDIT_ARCH {
+        return Err(Error::WrongArchitecture(arch));
+    }
+
+    let program: BpfProgram = bytes[SERIALIZED_HEADER_LEN..]
+        .chunks_exact(SERIALIZED_INSTRUCTION_LEN)
+        .map(|instruction| sock_filter {
+            code: u16::from_le_bytes([instruction[0], instruction[1]]),
+            jt: instruction[2],
+            jf: instruction[3],
+            k: u32::from_le_bytes([
+                instruction[4],
+                instruction[5],
+                instruction[6],
+                instruction[7],
+            ]),
+        })
+        .collect();
+    // The architecture validation is prepended to the program when it is applied.
+    if program.len() + VALIDATE_ARCHITECTURE().len() > BPF_MAX_LEN {
+        return Err(Error::FilterTooLarge);
+    }
+    Ok(program)
+}
+
 /// Builds a `jump` BPF instruction.
 ///
 /// # Arguments
@@ -1995,6 +2137,14 @@ mod tests {
             format!("{}", Error::Load(42)),
             "Failed to load seccomp rules into the kernel with error 42."
         );
+        assert_eq!(
+            format!("{}", Error::MalformedProgram),
+            "The serialized seccomp program is malformed."
+        );
+        assert_eq!(
+            format!("{}", Error::WrongArchitecture(0x2a)),
+            "The serialized seccomp program was built for another architecture: 0x2a."
+        );
     }
 
     #[test]
@@ -2005,6 +2155,66 @@ mod tests {
         assert_eq!(0x7ffc_0000, u32::from(SeccompAction::Log));
         assert_eq!(0x7ff0_002a, u32::from(SeccompAction::Trace(42)));
         assert_eq!(0x0003_0000, u32::from(SeccompAction::Trap));
+        assert_eq!(0x7fc0_0000, u32::from(SeccompAction::UserNotif));
+    }
+
+    #[test]
+    fn test_serialize_program() {
+        let mut filter = SeccompFilter::new(BTreeMap::new(), SeccompAction::Trap).unwrap();
+        filter
+            .add_rules(
+                libc::SYS_ioctl,
+                vec![SeccompRule::new(
+                    vec![Cond::new(1, ArgLen::QWORD, Eq, KVM_GET_PIT2).unwrap()],
+                    SeccompAction::Allow,
+                )],
+            )
+            .unwrap();
+        let program: BpfProgram = filter.try_into().unwrap();
+
+        let bytes = serialize_program(&program);
+        assert!(is_serialized_program(&bytes));
+        assert_eq!(
+            bytes.len(),
+            SERIALIZED_HEADER_LEN + program.len() * SERIALIZED_INSTRUCTION_LEN
+        );
+        assert_eq!(deserialize_program(&bytes).unwrap(), program);
+        assert!(deserialize_program(&serialize_program(&[]))
+            .unwrap()
+            .is_empty());
+
+        assert!(!is_serialized_program(b"{ \"filter\": [] }"));
+        match deserialize_program(b"{ \"filter\": [] }") {
+            Err(Error::MalformedProgram) => (),
+            _ => panic!("A JSON policy is not a serialized program"),
+        }
+        match deserialize_program(&bytes[..bytes.len() - 1]) {
+            Err(Error::MalformedProgram) => (),
+            _ => panic!("The truncated program should be malformed"),
+        }
+        let mut other_arch = bytes;
+        other_arch[SERIALIZED_MAGIC.len()] ^= 0xff;
+        match deserialize_program(&other_arch) {
+            Err(Error::WrongArchitecture(_)) => (),
+            _ => panic!("The program should be built for another architecture"),
+        }
+        let too_large: BpfProgram = vec![BPF_STMT(BPF_RET + BPF_K, 0); BPF_MAX_LEN];
+        match deserialize_program(&serialize_program(&too_large)) {
+            Err(Error::FilterTooLarge) => (),
+            _ => panic!("The program should be too large"),
+        }
+    }
+
+    #[test]
+    fn test_with_default_action() {
+        let filter = SeccompFilter::empty().with_default_action(SeccompAction::UserNotif);
+        assert_eq!(filter.default_action, SeccompAction::UserNotif);
+        assert!(filter.rules.is_empty());
+
+        match SeccompFilter::apply_with_listener(vec![]) {
+            Err(Error::EmptyRulesVector) => (),
+            _ => panic!("No listener should be created without a filter"),
+        }
     }
 
     #[test]
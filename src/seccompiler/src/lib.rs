@@ -0,0 +1,42 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#![deny(missing_docs)]
+//! Compiles the seccomp policies written in JSON into BPF programs.
+//!
+//! Firecracker loads a policy either as it is, compiling it at startup, or serialized ahead of
+//! time by the `seccompiler` binary, which keeps the compilation off the startup path.
+
+mod policy;
+
+pub use crate::policy::{filter_from_json, policy_from_json, syscall_name, PolicyError};
+
+/// Compiles the seccomp policy described by `json` into a serialized BPF program.
+pub fn compile(json: &str) -> Result<Vec<u8>, PolicyError> {
+    filter_from_json(json).map(|program| seccomp::serialize_program(&program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let json = r#"{
+            "default_action": "trap",
+            "filter_action": "allow",
+            "filter": [{ "syscall": "read" }, { "syscall": "write" }]
+        }"#;
+        let bytes = compile(json).unwrap();
+        assert!(seccomp::is_serialized_program(&bytes));
+        assert_eq!(
+            seccomp::deserialize_program(&bytes).unwrap(),
+            filter_from_json(json).unwrap()
+        );
+
+        match compile(r#"{ "default_action": "trap" }"#) {
+            Err(PolicyError::InvalidJson(_)) => (),
+            _ => panic!("The policy should be invalid"),
+        }
+    }
+}
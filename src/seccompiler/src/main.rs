@@ -0,0 +1,151 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use seccompiler::{compile, PolicyError};
+use utils::arg_parser::{ArgParser, Argument};
+
+const SECCOMPILER_VERSION: &str = env!("FIRECRACKER_VERSION");
+
+#[derive(Debug)]
+enum Error {
+    Compile(PathBuf, PolicyError),
+    ReadPolicy(PathBuf, io::Error),
+    WriteProgram(PathBuf, io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            Compile(path, err) => write!(f, "Cannot compile {}: {}", path.display(), err),
+            ReadPolicy(path, err) => write!(f, "Cannot read {}: {}", path.display(), err),
+            WriteProgram(path, err) => write!(f, "Cannot write {}: {}", path.display(), err),
+        }
+    }
+}
+
+fn build_arg_parser() -> ArgParser<'static> {
+    ArgParser::new()
+        .arg(
+            Argument::new("input-file")
+                .required(true)
+                .takes_value(true)
+                .help("Path to the JSON seccomp policy."),
+        )
+        .arg(
+            Argument::new("output-file")
+                .required(true)
+                .takes_value(true)
+                .help("Path to the compiled BPF program, which Firecracker loads with --seccomp-filter."),
+        )
+}
+
+fn compile_file(input: &Path, output: &Path) -> Result<(), Error> {
+    let json = fs::read_to_string(input).map_err(|e| Error::ReadPolicy(input.to_path_buf(), e))?;
+    let program = compile(&json).map_err(|e| Error::Compile(input.to_path_buf(), e))?;
+    fs::write(output, program).map_err(|e| Error::WriteProgram(output.to_path_buf(), e))
+}
+
+fn main() {
+    let mut arg_parser = build_arg_parser();
+
+    match arg_parser.parse_from_cmdline() {
+        Err(err) => {
+            println!(
+                "Arguments parsing error: {} \n\n\
+                 For more information try --help.",
+                err
+            );
+            process::exit(1);
+        }
+        _ => {
+            if arg_parser.arguments().flag_present("help") {
+                println!("Seccompiler v{}\n", SECCOMPILER_VERSION);
+                println!("{}", arg_parser.formatted_help());
+                println!(
+                    "The program is compiled for the architecture seccompiler is built for.\n"
+                );
+                process::exit(0);
+            }
+
+            if arg_parser.arguments().flag_present("version") {
+                println!("Seccompiler v{}\n", SECCOMPILER_VERSION);
+                process::exit(0);
+            }
+        }
+    }
+
+    let arguments = arg_parser.arguments();
+    // It's safe to unwrap here because both arguments are required.
+    let input = PathBuf::from(arguments.single_value("input-file").unwrap());
+    let output = PathBuf::from(arguments.single_value("output-file").unwrap());
+    compile_file(&input, &output).unwrap_or_else(|err| {
+        eprintln!("Seccompiler error: {}", err);
+        process::exit(1);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use utils::tempfile::TempFile;
+
+    #[test]
+    fn test_compile_file() {
+        let input = TempFile::new().unwrap();
+        let output = TempFile::new().unwrap();
+        fs::write(
+            input.as_path(),
+            r#"{ "default_action": "trap", "filter_action": "allow",
+                 "filter": [{ "syscall": "read" }] }"#,
+        )
+        .unwrap();
+        compile_file(input.as_path(), output.as_path()).unwrap();
+        let program = fs::read(output.as_path()).unwrap();
+        assert!(!seccomp::deserialize_program(&program).unwrap().is_empty());
+
+        fs::write(input.as_path(), "{}").unwrap();
+        match compile_file(input.as_path(), output.as_path()) {
+            Err(Error::Compile(_, PolicyError::InvalidJson(_))) => (),
+            _ => panic!("The policy should be invalid"),
+        }
+        match compile_file(Path::new("/no/such/policy.json"), output.as_path()) {
+            Err(Error::ReadPolicy(_, _)) => (),
+            _ => panic!("The policy should not exist"),
+        }
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(
+            format!(
+                "{}",
+                Error::Compile(
+                    PathBuf::from("policy.json"),
+                    PolicyError::UnknownSyscall("fork2".to_string())
+                )
+            ),
+            "Cannot compile policy.json: Unknown syscall fork2."
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::WriteProgram(
+                    PathBuf::from("filter.bpf"),
+                    io::Error::from_raw_os_error(libc::EACCES)
+                )
+            ),
+            format!(
+                "Cannot write filter.bpf: {}",
+                io::Error::from_raw_os_error(libc::EACCES)
+            )
+        );
+    }
+}
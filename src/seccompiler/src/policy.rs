@@ -0,0 +1,439 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+
+use seccomp::{
+    BpfProgram, Error, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+    SeccompFilter, SeccompRule,
+};
+use serde::Deserialize;
+
+/// Errors associated with loading a seccomp policy described in JSON.
+#[derive(Debug)]
+pub enum PolicyError {
+    /// The policy is not valid JSON, or does not follow the policy format.
+    InvalidJson(String),
+    /// The policy cannot be translated into a BPF program.
+    SeccompFilter(Error),
+    /// The policy refers to a syscall which is not known on this architecture.
+    UnknownSyscall(String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PolicyError::*;
+        match self {
+            InvalidJson(err) => write!(f, "Invalid seccomp policy: {}", err),
+            SeccompFilter(err) => write!(f, "{}", err),
+            UnknownSyscall(name) => write!(f, "Unknown syscall {}.", name),
+        }
+    }
+}
+
+// The action taken on a syscall, named as in `SeccompAction`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Allow,
+    Errno(u32),
+    Kill,
+    Log,
+    Trace(u32),
+    Trap,
+}
+
+impl From<Action> for SeccompAction {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Allow => SeccompAction::Allow,
+            Action::Errno(errno) => SeccompAction::Errno(errno),
+            Action::Kill => SeccompAction::Kill,
+            Action::Log => SeccompAction::Log,
+            Action::Trace(value) => SeccompAction::Trace(value),
+            Action::Trap => SeccompAction::Trap,
+        }
+    }
+}
+
+// The length of a syscall argument.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ArgType {
+    Dword,
+    Qword,
+}
+
+// The comparison of a syscall argument, named as in `SeccompCmpOp`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Op {
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    MaskedEq(u64),
+    Ne,
+}
+
+impl From<Op> for SeccompCmpOp {
+    fn from(op: Op) -> Self {
+        match op {
+            Op::Eq => SeccompCmpOp::Eq,
+            Op::Ge => SeccompCmpOp::Ge,
+            Op::Gt => SeccompCmpOp::Gt,
+            Op::Le => SeccompCmpOp::Le,
+            Op::Lt => SeccompCmpOp::Lt,
+            Op::MaskedEq(mask) => SeccompCmpOp::MaskedEq(mask),
+            Op::Ne => SeccompCmpOp::Ne,
+        }
+    }
+}
+
+// A condition on a syscall argument.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArgCondition {
+    index: u8,
+    #[serde(rename = "type")]
+    arg_type: ArgType,
+    op: Op,
+    val: u64,
+    #[allow(dead_code)]
+    comment: Option<String>,
+}
+
+// A rule matching a syscall when all the conditions on its arguments hold.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyscallRule {
+    syscall: String,
+    #[serde(default)]
+    args: Vec<ArgCondition>,
+    // Overrides the `filter_action` of the policy.
+    action: Option<Action>,
+    #[allow(dead_code)]
+    comment: Option<String>,
+}
+
+// A seccomp policy: the rules are tried in order, and the default action is taken on the
+// syscalls none of them matches.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Policy {
+    default_action: Action,
+    filter_action: Action,
+    filter: Vec<SyscallRule>,
+}
+
+/// Builds the seccomp filter described by the policy in `json`.
+pub fn policy_from_json(json: &str) -> Result<SeccompFilter, PolicyError> {
+    let policy: Policy =
+        serde_json::from_str(json).map_err(|e| PolicyError::InvalidJson(e.to_string()))?;
+    let filter_action = SeccompAction::from(policy.filter_action);
+
+    let mut filter = SeccompFilter::new(BTreeMap::new(), policy.default_action.into())
+        .map_err(PolicyError::SeccompFilter)?;
+    for rule in policy.filter.into_iter() {
+        let syscall_number = syscall_number(&rule.syscall)
+            .ok_or_else(|| PolicyError::UnknownSyscall(rule.syscall.clone()))?;
+        let conditions = rule
+            .args
+            .into_iter()
+            .map(|arg| {
+                let arg_len = match arg.arg_type {
+                    ArgType::Dword => SeccompCmpArgLen::DWORD,
+                    ArgType::Qword => SeccompCmpArgLen::QWORD,
+                };
+                SeccompCondition::new(arg.index, arg_len, arg.op.into(), arg.val)
+            })
+            .collect::<Result<Vec<SeccompCondition>, Error>>()
+            .map_err(PolicyError::SeccompFilter)?;
+        let action = rule
+            .action
+            .map_or_else(|| filter_action.clone(), SeccompAction::from);
+        filter
+            .add_rules(syscall_number, vec![SeccompRule::new(conditions, action)])
+            .map_err(PolicyError::SeccompFilter)?;
+    }
+    Ok(filter)
+}
+
+/// Compiles the seccomp policy described by `json` into a BPF program.
+pub fn filter_from_json(json: &str) -> Result<BpfProgram, PolicyError> {
+    policy_from_json(json)?
+        .try_into()
+        .map_err(PolicyError::SeccompFilter)
+}
+
+// The syscalls which can be named in a policy, on all the architectures.
+const SYSCALLS: &[(&str, i64)] = &[
+    ("accept", libc::SYS_accept),
+    ("accept4", libc::SYS_accept4),
+    ("bind", libc::SYS_bind),
+    ("brk", libc::SYS_brk),
+    ("clock_getres", libc::SYS_clock_getres),
+    ("clock_gettime", libc::SYS_clock_gettime),
+    ("clock_nanosleep", libc::SYS_clock_nanosleep),
+    ("clone", libc::SYS_clone),
+    ("close", libc::SYS_close),
+    ("connect", libc::SYS_connect),
+    ("dup", libc::SYS_dup),
+    ("dup3", libc::SYS_dup3),
+    ("epoll_create1", libc::SYS_epoll_create1),
+    ("epoll_ctl", libc::SYS_epoll_ctl),
+    ("epoll_pwait", libc::SYS_epoll_pwait),
+    ("eventfd2", libc::SYS_eventfd2),
+    ("execve", libc::SYS_execve),
+    ("exit", libc::SYS_exit),
+    ("exit_group", libc::SYS_exit_group),
+    ("fallocate", libc::SYS_fallocate),
+    ("fcntl", libc::SYS_fcntl),
+    ("fdatasync", libc::SYS_fdatasync),
+    ("flock", libc::SYS_flock),
+    ("fstat", libc::SYS_fstat),
+    ("fstatfs", libc::SYS_fstatfs),
+    ("fsync", libc::SYS_fsync),
+    ("ftruncate", libc::SYS_ftruncate),
+    ("futex", libc::SYS_futex),
+    ("getcwd", libc::SYS_getcwd),
+    ("getdents64", libc::SYS_getdents64),
+    ("getegid", libc::SYS_getegid),
+    ("geteuid", libc::SYS_geteuid),
+    ("getgid", libc::SYS_getgid),
+    ("getpeername", libc::SYS_getpeername),
+    ("getpid", libc::SYS_getpid),
+    ("getppid", libc::SYS_getppid),
+    ("getrandom", libc::SYS_getrandom),
+    ("getrlimit", libc::SYS_getrlimit),
+    ("getrusage", libc::SYS_getrusage),
+    ("getsockname", libc::SYS_getsockname),
+    ("getsockopt", libc::SYS_getsockopt),
+    ("gettid", libc::SYS_gettid),
+    ("gettimeofday", libc::SYS_gettimeofday),
+    ("getuid", libc::SYS_getuid),
+    ("io_uring_enter", libc::SYS_io_uring_enter),
+    ("io_uring_register", libc::SYS_io_uring_register),
+    ("io_uring_setup", libc::SYS_io_uring_setup),
+    ("ioctl", libc::SYS_ioctl),
+    ("kill", libc::SYS_kill),
+    ("listen", libc::SYS_listen),
+    ("lseek", libc::SYS_lseek),
+    ("madvise", libc::SYS_madvise),
+    ("memfd_create", libc::SYS_memfd_create),
+    ("mincore", libc::SYS_mincore),
+    ("mkdirat", libc::SYS_mkdirat),
+    ("mlock", libc::SYS_mlock),
+    ("mmap", libc::SYS_mmap),
+    ("mprotect", libc::SYS_mprotect),
+    ("mremap", libc::SYS_mremap),
+    ("msync", libc::SYS_msync),
+    ("munlock", libc::SYS_munlock),
+    ("munmap", libc::SYS_munmap),
+    ("nanosleep", libc::SYS_nanosleep),
+    ("openat", libc::SYS_openat),
+    ("pipe2", libc::SYS_pipe2),
+    ("ppoll", libc::SYS_ppoll),
+    ("prctl", libc::SYS_prctl),
+    ("pread64", libc::SYS_pread64),
+    ("preadv", libc::SYS_preadv),
+    ("prlimit64", libc::SYS_prlimit64),
+    ("pselect6", libc::SYS_pselect6),
+    ("pwrite64", libc::SYS_pwrite64),
+    ("pwritev", libc::SYS_pwritev),
+    ("read", libc::SYS_read),
+    ("readlinkat", libc::SYS_readlinkat),
+    ("readv", libc::SYS_readv),
+    ("recvfrom", libc::SYS_recvfrom),
+    ("recvmmsg", libc::SYS_recvmmsg),
+    ("recvmsg", libc::SYS_recvmsg),
+    ("restart_syscall", libc::SYS_restart_syscall),
+    ("rt_sigaction", libc::SYS_rt_sigaction),
+    ("rt_sigprocmask", libc::SYS_rt_sigprocmask),
+    ("rt_sigreturn", libc::SYS_rt_sigreturn),
+    ("rt_sigtimedwait", libc::SYS_rt_sigtimedwait),
+    ("sched_getaffinity", libc::SYS_sched_getaffinity),
+    ("sched_setaffinity", libc::SYS_sched_setaffinity),
+    ("sched_yield", libc::SYS_sched_yield),
+    ("sendmmsg", libc::SYS_sendmmsg),
+    ("sendmsg", libc::SYS_sendmsg),
+    ("sendto", libc::SYS_sendto),
+    ("set_robust_list", libc::SYS_set_robust_list),
+    ("set_tid_address", libc::SYS_set_tid_address),
+    ("setsockopt", libc::SYS_setsockopt),
+    ("shutdown", libc::SYS_shutdown),
+    ("sigaltstack", libc::SYS_sigaltstack),
+    ("socket", libc::SYS_socket),
+    ("socketpair", libc::SYS_socketpair),
+    ("tgkill", libc::SYS_tgkill),
+    ("timerfd_create", libc::SYS_timerfd_create),
+    ("timerfd_gettime", libc::SYS_timerfd_gettime),
+    ("timerfd_settime", libc::SYS_timerfd_settime),
+    ("tkill", libc::SYS_tkill),
+    ("umask", libc::SYS_umask),
+    ("uname", libc::SYS_uname),
+    ("unlinkat", libc::SYS_unlinkat),
+    ("wait4", libc::SYS_wait4),
+    ("write", libc::SYS_write),
+    ("writev", libc::SYS_writev),
+];
+
+// The syscalls which only exist on x86_64, the other architectures having their `*at` or more
+// generic variants only.
+#[cfg(target_arch = "x86_64")]
+const ARCH_SYSCALLS: &[(&str, i64)] = &[
+    ("access", libc::SYS_access),
+    ("arch_prctl", libc::SYS_arch_prctl),
+    ("dup2", libc::SYS_dup2),
+    ("epoll_create", libc::SYS_epoll_create),
+    ("epoll_wait", libc::SYS_epoll_wait),
+    ("fork", libc::SYS_fork),
+    ("lstat", libc::SYS_lstat),
+    ("mkdir", libc::SYS_mkdir),
+    ("open", libc::SYS_open),
+    ("pipe", libc::SYS_pipe),
+    ("poll", libc::SYS_poll),
+    ("readlink", libc::SYS_readlink),
+    ("rename", libc::SYS_rename),
+    ("rmdir", libc::SYS_rmdir),
+    ("select", libc::SYS_select),
+    ("stat", libc::SYS_stat),
+    ("unlink", libc::SYS_unlink),
+    ("vfork", libc::SYS_vfork),
+];
+#[cfg(target_arch = "aarch64")]
+const ARCH_SYSCALLS: &[(&str, i64)] = &[];
+
+// Returns the number of the syscall called `name` on this architecture.
+fn syscall_number(name: &str) -> Option<i64> {
+    SYSCALLS
+        .iter()
+        .chain(ARCH_SYSCALLS.iter())
+        .find(|(syscall, _)| *syscall == name)
+        .map(|(_, number)| *number)
+}
+
+/// Returns the name of a syscall which can be named in a policy.
+pub fn syscall_name(number: i64) -> Option<&'static str> {
+    SYSCALLS
+        .iter()
+        .chain(ARCH_SYSCALLS.iter())
+        .find(|(_, syscall)| *syscall == number)
+        .map(|(name, _)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_syscall_number() {
+        assert_eq!(syscall_number("read"), Some(libc::SYS_read));
+        assert_eq!(syscall_number("ioctl"), Some(libc::SYS_ioctl));
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(syscall_number("open"), Some(libc::SYS_open));
+        assert_eq!(syscall_number("SYS_read"), None);
+        assert_eq!(syscall_number("not_a_syscall"), None);
+        assert_eq!(syscall_name(libc::SYS_read), Some("read"));
+        assert_eq!(syscall_name(-1), None);
+
+        // The names are unique.
+        let names = SYSCALLS.iter().chain(ARCH_SYSCALLS.iter());
+        for (i, (name, _)) in names.clone().enumerate() {
+            assert!(names.clone().skip(i + 1).all(|(other, _)| other != name));
+        }
+    }
+
+    #[test]
+    fn test_filter_from_json() {
+        let json = r#"{
+            "default_action": "trap",
+            "filter_action": "allow",
+            "filter": [
+                { "syscall": "read" },
+                { "syscall": "write", "comment": "Used by the logger" },
+                {
+                    "syscall": "ioctl",
+                    "args": [
+                        { "index": 1, "type": "dword", "op": "eq", "val": 44672 },
+                        { "index": 2, "type": "qword", "op": { "masked_eq": 4095 }, "val": 0 }
+                    ]
+                },
+                { "syscall": "getpid", "action": { "errno": 1 } }
+            ]
+        }"#;
+        assert!(!filter_from_json(json).unwrap().is_empty());
+
+        match filter_from_json(r#"{ "default_action": "trap", "filter": [] }"#) {
+            Err(PolicyError::InvalidJson(_)) => (),
+            _ => panic!("The policy should miss the filter action"),
+        }
+        match filter_from_json(
+            r#"{ "default_action": "trap", "filter_action": "allow", "filter": [],
+                 "seccomp_level": 2 }"#,
+        ) {
+            Err(PolicyError::InvalidJson(_)) => (),
+            _ => panic!("The policy should have an unknown field"),
+        }
+        match filter_from_json(
+            r#"{ "default_action": "trap", "filter_action": "allow",
+                 "filter": [{ "syscall": "launch_missiles" }] }"#,
+        ) {
+            Err(PolicyError::UnknownSyscall(name)) => assert_eq!(name, "launch_missiles"),
+            _ => panic!("The syscall should be unknown"),
+        }
+        match filter_from_json(
+            r#"{ "default_action": "trap", "filter_action": "allow",
+                 "filter": [{
+                     "syscall": "ioctl",
+                     "args": [{ "index": 6, "type": "dword", "op": "eq", "val": 0 }]
+                 }] }"#,
+        ) {
+            Err(PolicyError::SeccompFilter(Error::InvalidArgumentNumber)) => (),
+            _ => panic!("The argument index should be invalid"),
+        }
+    }
+
+    #[test]
+    fn test_apply_filter_from_json() {
+        let filter = filter_from_json(
+            r#"{
+                "default_action": "allow",
+                "filter_action": "allow",
+                "filter": [{ "syscall": "getppid", "action": { "errno": 1000 } }]
+            }"#,
+        )
+        .unwrap();
+        // The filter is loaded in another thread, to leave the test process unrestricted.
+        thread::spawn(move || {
+            SeccompFilter::apply(filter).unwrap();
+            // Safe because getppid takes no arguments.
+            let ret = unsafe { libc::syscall(libc::SYS_getppid) };
+            assert_eq!(ret, -1);
+            assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(1000));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_policy_error_display() {
+        assert_eq!(
+            PolicyError::UnknownSyscall("launch_missiles".to_string()).to_string(),
+            "Unknown syscall launch_missiles."
+        );
+        assert_eq!(
+            PolicyError::SeccompFilter(Error::EmptyRulesVector).to_string(),
+            "The seccomp rules vector is empty."
+        );
+        assert_eq!(
+            PolicyError::InvalidJson("EOF".to_string()).to_string(),
+            "Invalid seccomp policy: EOF"
+        );
+    }
+}
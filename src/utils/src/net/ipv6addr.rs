@@ -0,0 +1,73 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::net::Ipv6Addr;
+
+/// Checks if an IPv6 address is a link-local (RFC 4291) or unique local (RFC 4193) unicast
+/// address, with a non-zero interface identifier.
+/// # Examples
+///
+/// ```
+/// use std::net::Ipv6Addr;
+/// use utils::net::ipv6addr::is_link_local_or_unique_local;
+///
+/// is_link_local_or_unique_local(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+/// ```
+pub fn is_link_local_or_unique_local(ipv6_addr: Ipv6Addr) -> bool {
+    let segments = ipv6_addr.segments();
+    let link_local = segments[0] & 0xffc0 == 0xfe80;
+    let unique_local = segments[0] & 0xfe00 == 0xfc00;
+    // The subnet-router anycast address has an all zero interface identifier.
+    let has_interface_id = segments[4..].iter().any(|segment| *segment != 0);
+
+    (link_local || unique_local) && has_interface_id
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::ipv6addr::is_link_local_or_unique_local;
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn test_is_link_local_or_unique_local() {
+        // Global, loopback, unspecified and multicast addresses.
+        assert!(!is_link_local_or_unique_local(Ipv6Addr::new(
+            0x2001, 0xdb8, 0, 0, 0, 0, 0, 1
+        )));
+        assert!(!is_link_local_or_unique_local(Ipv6Addr::LOCALHOST));
+        assert!(!is_link_local_or_unique_local(Ipv6Addr::UNSPECIFIED));
+        assert!(!is_link_local_or_unique_local(Ipv6Addr::new(
+            0xff02, 0, 0, 0, 0, 0, 0, 1
+        )));
+
+        // Subnet-router anycast addresses.
+        assert!(!is_link_local_or_unique_local(Ipv6Addr::new(
+            0xfe80, 0, 0, 0, 0, 0, 0, 0
+        )));
+        assert!(!is_link_local_or_unique_local(Ipv6Addr::new(
+            0xfd00, 0xec2, 0, 0, 0, 0, 0, 0
+        )));
+
+        // Link-local addresses (fe80::/10).
+        assert!(is_link_local_or_unique_local(Ipv6Addr::new(
+            0xfe80, 0, 0, 0, 0, 0, 0, 0x254
+        )));
+        assert!(is_link_local_or_unique_local(Ipv6Addr::new(
+            0xfebf, 0, 0, 0, 0, 0, 0, 1
+        )));
+        assert!(!is_link_local_or_unique_local(Ipv6Addr::new(
+            0xfec0, 0, 0, 0, 0, 0, 0, 1
+        )));
+
+        // Unique local addresses (fc00::/7).
+        assert!(is_link_local_or_unique_local(Ipv6Addr::new(
+            0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254
+        )));
+        assert!(is_link_local_or_unique_local(Ipv6Addr::new(
+            0xfc00, 0, 0, 0, 0, 0, 0, 1
+        )));
+        assert!(!is_link_local_or_unique_local(Ipv6Addr::new(
+            0xfb00, 0, 0, 0, 0, 0, 0, 1
+        )));
+    }
+}
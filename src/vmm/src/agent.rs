@@ -0,0 +1,532 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Client side of the guest agent protocol, spoken over the vsock agent port.
+//!
+//! Each command is a single line of JSON, tagged by its `command` field, to which the agent
+//! answers with a single line of JSON carrying a `status` of either `ok` or `error`. The
+//! `file_push` request line is followed by the `size` bytes of the file, and the successful
+//! `file_pull` response line is followed by the `size` bytes of the guest file.
+
+use std::cmp::min;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+pub use devices::virtio::{AgentChannel, AGENT_CHANNEL};
+use serde::{Deserialize, Serialize};
+
+/// The time allowed for the agent to answer a command, on top of the run time of the `exec`
+/// commands.
+pub const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+/// The maximum size of a response line, including the output captured by `exec`.
+const MAX_RESPONSE_LEN: u64 = 4 << 20;
+
+/// Errors associated with the guest agent commands.
+#[derive(Debug)]
+pub enum AgentError {
+    /// The agent failed to carry out the command.
+    Command(String),
+    /// The host file of a file transfer cannot be accessed.
+    HostFile(String, io::Error),
+    /// The agent sent a response which does not follow the protocol.
+    InvalidResponse(String),
+    /// The agent connection failed.
+    Io(io::Error),
+    /// The guest agent is not connected.
+    NotConnected,
+    /// The agent did not answer in time.
+    Timeout,
+}
+
+impl AgentError {
+    // Whether the connection is out of sync with the agent after this error, and has to be
+    // dropped for the agent to reconnect.
+    fn breaks_connection(&self) -> bool {
+        matches!(
+            self,
+            AgentError::InvalidResponse(_)
+                | AgentError::Io(_)
+                | AgentError::NotConnected
+                | AgentError::Timeout
+        )
+    }
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AgentError::*;
+        match *self {
+            Command(ref msg) => write!(f, "The guest agent failed to run the command: {}", msg),
+            HostFile(ref path, ref err) => write!(f, "Cannot access host file {}: {}", path, err),
+            InvalidResponse(ref msg) => write!(f, "Invalid guest agent response: {}", msg),
+            Io(ref err) => write!(f, "Guest agent connection error: {}", err),
+            NotConnected => write!(f, "The guest agent is not connected."),
+            Timeout => write!(f, "The guest agent did not answer in time."),
+        }
+    }
+}
+
+impl From<io::Error> for AgentError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            // This is how the socket timeouts surface.
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => AgentError::Timeout,
+            io::ErrorKind::UnexpectedEof => AgentError::NotConnected,
+            _ => AgentError::Io(err),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, AgentError>;
+
+/// The parameters of a command run in the guest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExecParams {
+    /// The program to run, followed by its arguments.
+    pub argv: Vec<String>,
+    /// The time after which the agent kills the program. Defaults to `DEFAULT_TIMEOUT_MS`.
+    pub timeout_ms: Option<u64>,
+}
+
+/// The parameters of a copy of a host file into the guest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilePushParams {
+    /// The path of the file to copy, on the host.
+    pub host_path: String,
+    /// The path of the copy, in the guest.
+    pub guest_path: String,
+    /// The permissions of the copy. Defaults to the agent's choice.
+    pub mode: Option<u32>,
+}
+
+/// The parameters of a copy of a guest file onto the host.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilePullParams {
+    /// The path of the file to copy, in the guest.
+    pub guest_path: String,
+    /// The path of the copy, on the host. The file is created if needed, and truncated.
+    pub host_path: String,
+}
+
+/// A command carried out by the guest agent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AgentCommand {
+    /// Run a program in the guest and capture its output.
+    Exec(ExecParams),
+    /// Copy a guest file onto the host.
+    FilePull(FilePullParams),
+    /// Copy a host file into the guest.
+    FilePush(FilePushParams),
+    /// Freeze the guest file systems, e.g. before taking a consistent disk snapshot.
+    FsFreeze,
+    /// Thaw the guest file systems.
+    FsThaw,
+    /// Check that the agent is responsive.
+    Ping,
+}
+
+/// The outcome of a program run by the guest agent.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ExecOutput {
+    /// The exit code of the program, or -1 if it was killed.
+    pub exit_code: i32,
+    /// The standard output of the program.
+    pub stdout: String,
+    /// The standard error of the program.
+    pub stderr: String,
+}
+
+/// The data returned by a successful agent command.
+#[derive(Debug, PartialEq)]
+pub enum AgentOutput {
+    /// The command returns no data.
+    Empty,
+    /// The output of an `exec` command.
+    Exec(ExecOutput),
+}
+
+/// The request line sent to the agent.
+#[derive(Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum AgentRequest<'a> {
+    Exec {
+        argv: &'a [String],
+        timeout_ms: u64,
+    },
+    FilePull {
+        path: &'a str,
+    },
+    FilePush {
+        path: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
+        size: u64,
+    },
+    FsFreeze,
+    FsThaw,
+    Ping,
+}
+
+/// The response line sent by the agent.
+#[derive(Deserialize)]
+struct AgentResponse {
+    status: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    exit_code: i32,
+    #[serde(default)]
+    stdout: String,
+    #[serde(default)]
+    stderr: String,
+    #[serde(default)]
+    size: u64,
+}
+
+impl AgentCommand {
+    /// Run the command over the agent connection of `channel`, blocking until the agent
+    /// answers. The connection is dropped when it gets out of sync with the agent.
+    pub fn run(&self, channel: &AgentChannel) -> Result<AgentOutput> {
+        let mut stream = channel.lock();
+        let result = match stream.as_ref() {
+            Some(stream) => self.run_on(stream),
+            None => return Err(AgentError::NotConnected),
+        };
+        if let Err(ref err) = result {
+            if err.breaks_connection() {
+                *stream = None;
+            }
+        }
+        result
+    }
+
+    fn run_on(&self, stream: &UnixStream) -> Result<AgentOutput> {
+        match self {
+            AgentCommand::Exec(params) => {
+                let timeout_ms = params.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
+                let request = AgentRequest::Exec {
+                    argv: &params.argv,
+                    timeout_ms,
+                };
+                send(
+                    stream,
+                    &request,
+                    timeout_ms.saturating_add(DEFAULT_TIMEOUT_MS),
+                )?;
+                let response = receive(&mut BufReader::new(stream))?;
+                Ok(AgentOutput::Exec(ExecOutput {
+                    exit_code: response.exit_code,
+                    stdout: response.stdout,
+                    stderr: response.stderr,
+                }))
+            }
+            AgentCommand::FilePull(params) => pull_file(stream, params),
+            AgentCommand::FilePush(params) => push_file(stream, params),
+            AgentCommand::FsFreeze => simple_command(stream, &AgentRequest::FsFreeze),
+            AgentCommand::FsThaw => simple_command(stream, &AgentRequest::FsThaw),
+            AgentCommand::Ping => simple_command(stream, &AgentRequest::Ping),
+        }
+    }
+}
+
+fn simple_command(stream: &UnixStream, request: &AgentRequest) -> Result<AgentOutput> {
+    send(stream, request, DEFAULT_TIMEOUT_MS)?;
+    receive(&mut BufReader::new(stream)).map(|_| AgentOutput::Empty)
+}
+
+fn push_file(stream: &UnixStream, params: &FilePushParams) -> Result<AgentOutput> {
+    let host_file_error = |err| AgentError::HostFile(params.host_path.clone(), err);
+    let mut file = File::open(&params.host_path).map_err(host_file_error)?;
+    // Seeking, rather than `statx`, is allowed by the seccomp filters.
+    let size = file
+        .seek(SeekFrom::End(0))
+        .and_then(|size| file.seek(SeekFrom::Start(0)).map(|_| size))
+        .map_err(host_file_error)?;
+
+    let request = AgentRequest::FilePush {
+        path: &params.guest_path,
+        mode: params.mode,
+        size,
+    };
+    send(stream, &request, DEFAULT_TIMEOUT_MS)?;
+    // The agent expects exactly `size` bytes, so a file which shrinks meanwhile breaks the
+    // connection.
+    let copied = io::copy(&mut file.take(size), &mut &*stream)?;
+    if copied != size {
+        return Err(AgentError::Io(io::Error::from(
+            io::ErrorKind::UnexpectedEof,
+        )));
+    }
+    receive(&mut BufReader::new(stream)).map(|_| AgentOutput::Empty)
+}
+
+fn pull_file(stream: &UnixStream, params: &FilePullParams) -> Result<AgentOutput> {
+    let host_file_error = |err| AgentError::HostFile(params.host_path.clone(), err);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&params.host_path)
+        .map_err(host_file_error)?;
+
+    let request = AgentRequest::FilePull {
+        path: &params.guest_path,
+    };
+    send(stream, &request, DEFAULT_TIMEOUT_MS)?;
+    let mut reader = BufReader::new(stream);
+    let response = receive(&mut reader)?;
+
+    // The whole file is read even if it cannot be written, to keep in sync with the agent.
+    let mut buf = [0u8; 4096];
+    let mut remaining = response.size;
+    let mut write_result = Ok(());
+    while remaining > 0 {
+        let len = min(remaining, buf.len() as u64) as usize;
+        reader.read_exact(&mut buf[..len])?;
+        if write_result.is_ok() {
+            write_result = file.write_all(&buf[..len]);
+        }
+        remaining -= len as u64;
+    }
+    write_result
+        .map(|()| AgentOutput::Empty)
+        .map_err(host_file_error)
+}
+
+fn send(stream: &UnixStream, request: &AgentRequest, timeout_ms: u64) -> Result<()> {
+    stream.set_write_timeout(Some(Duration::from_millis(DEFAULT_TIMEOUT_MS)))?;
+    stream.set_read_timeout(Some(Duration::from_millis(timeout_ms)))?;
+    // Serializing strings and integers cannot fail.
+    let mut line = serde_json::to_vec(request).expect("Cannot serialize the agent request");
+    line.push(b'\n');
+    (&*stream).write_all(&line)?;
+    Ok(())
+}
+
+fn receive(reader: &mut BufReader<&UnixStream>) -> Result<AgentResponse> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_RESPONSE_LEN)
+        .read_until(b'\n', &mut line)?;
+    match line.last() {
+        Some(b'\n') => (),
+        None => return Err(AgentError::NotConnected),
+        Some(_) => {
+            return Err(AgentError::InvalidResponse(
+                "The response is truncated or too long.".to_string(),
+            ))
+        }
+    }
+
+    let response: AgentResponse = serde_json::from_slice(&line)
+        .map_err(|err| AgentError::InvalidResponse(err.to_string()))?;
+    match response.status.as_str() {
+        "ok" => Ok(response),
+        "error" => Err(AgentError::Command(response.message)),
+        status => Err(AgentError::InvalidResponse(format!(
+            "Unknown status `{}`.",
+            status
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    // Attaches a fake agent to a new channel. The agent checks each request line against the
+    // expected one, then writes the matching answer.
+    fn fake_agent(
+        exchanges: Vec<(&'static str, &'static [u8])>,
+    ) -> (AgentChannel, thread::JoinHandle<Vec<u8>>) {
+        let (host, agent) = UnixStream::pair().unwrap();
+        let channel = AgentChannel::default();
+        channel.attach(host);
+        let handle = thread::spawn(move || {
+            let mut reader = BufReader::new(&agent);
+            for (request, answer) in exchanges {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                assert_eq!(line, format!("{}\n", request));
+                (&agent).write_all(answer).unwrap();
+            }
+            // Return whatever the host sent after the last request line.
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).unwrap();
+            rest
+        });
+        (channel, handle)
+    }
+
+    #[test]
+    fn test_not_connected() {
+        let channel = AgentChannel::default();
+        match AgentCommand::Ping.run(&channel) {
+            Err(AgentError::NotConnected) => (),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_simple_commands() {
+        let (channel, handle) = fake_agent(vec![
+            (r#"{"command":"ping"}"#, b"{\"status\":\"ok\"}\n"),
+            (r#"{"command":"fs_freeze"}"#, b"{\"status\":\"ok\"}\n"),
+            (
+                r#"{"command":"fs_thaw"}"#,
+                b"{\"status\":\"error\",\"message\":\"not frozen\"}\n",
+            ),
+        ]);
+        assert_eq!(
+            AgentCommand::Ping.run(&channel).unwrap(),
+            AgentOutput::Empty
+        );
+        assert_eq!(
+            AgentCommand::FsFreeze.run(&channel).unwrap(),
+            AgentOutput::Empty
+        );
+        match AgentCommand::FsThaw.run(&channel) {
+            Err(AgentError::Command(msg)) => assert_eq!(msg, "not frozen"),
+            _ => panic!("Test failed."),
+        }
+        // The agent reporting an error leaves the connection usable.
+        assert!(channel.lock().is_some());
+        *channel.lock() = None;
+        assert!(handle.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_exec() {
+        let (channel, handle) = fake_agent(vec![(
+            r#"{"command":"exec","argv":["uname","-r"],"timeout_ms":1000}"#,
+            b"{\"status\":\"ok\",\"exit_code\":0,\"stdout\":\"5.10\\n\",\"stderr\":\"\"}\n",
+        )]);
+        let command = AgentCommand::Exec(ExecParams {
+            argv: vec!["uname".to_string(), "-r".to_string()],
+            timeout_ms: Some(1000),
+        });
+        assert_eq!(
+            command.run(&channel).unwrap(),
+            AgentOutput::Exec(ExecOutput {
+                exit_code: 0,
+                stdout: "5.10\n".to_string(),
+                stderr: String::new(),
+            })
+        );
+        *channel.lock() = None;
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_response() {
+        let (channel, handle) = fake_agent(vec![(r#"{"command":"ping"}"#, b"pong\n")]);
+        match AgentCommand::Ping.run(&channel) {
+            Err(AgentError::InvalidResponse(_)) => (),
+            _ => panic!("Test failed."),
+        }
+        // The connection is dropped, for the agent to reconnect.
+        assert!(channel.lock().is_none());
+        handle.join().unwrap();
+
+        // The agent going away also drops the connection.
+        let (host, agent) = UnixStream::pair().unwrap();
+        let channel = AgentChannel::default();
+        channel.attach(host);
+        drop(agent);
+        match AgentCommand::Ping.run(&channel) {
+            Err(AgentError::Io(_)) | Err(AgentError::NotConnected) => (),
+            _ => panic!("Test failed."),
+        }
+        assert!(channel.lock().is_none());
+    }
+
+    #[test]
+    fn test_file_push() {
+        let tmp_file = TempFile::new().unwrap();
+        tmp_file.as_file().write_all(b"data").unwrap();
+        let host_path = tmp_file.as_path().to_str().unwrap().to_string();
+        let (channel, handle) = fake_agent(vec![(
+            r#"{"command":"file_push","path":"/etc/motd","mode":420,"size":4}"#,
+            b"{\"status\":\"ok\"}\n",
+        )]);
+        let command = AgentCommand::FilePush(FilePushParams {
+            host_path: host_path.clone(),
+            guest_path: "/etc/motd".to_string(),
+            mode: Some(0o644),
+        });
+        assert_eq!(command.run(&channel).unwrap(), AgentOutput::Empty);
+        *channel.lock() = None;
+        assert_eq!(handle.join().unwrap(), b"data");
+
+        // A missing host file is reported before anything is sent to the agent.
+        let (channel, handle) = fake_agent(vec![]);
+        let command = AgentCommand::FilePush(FilePushParams {
+            host_path: format!("{}_missing", host_path),
+            guest_path: "/etc/motd".to_string(),
+            mode: None,
+        });
+        match command.run(&channel) {
+            Err(AgentError::HostFile(_, _)) => (),
+            _ => panic!("Test failed."),
+        }
+        *channel.lock() = None;
+        assert!(handle.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_file_pull() {
+        let tmp_file = TempFile::new().unwrap();
+        let host_path = tmp_file.as_path().to_str().unwrap().to_string();
+        let (channel, handle) = fake_agent(vec![
+            (
+                r#"{"command":"file_pull","path":"/var/log/boot.log"}"#,
+                b"{\"status\":\"ok\",\"size\":4}\ndata",
+            ),
+            (r#"{"command":"ping"}"#, b"{\"status\":\"ok\"}\n"),
+        ]);
+        let command = AgentCommand::FilePull(FilePullParams {
+            guest_path: "/var/log/boot.log".to_string(),
+            host_path: host_path.clone(),
+        });
+        assert_eq!(command.run(&channel).unwrap(), AgentOutput::Empty);
+        assert_eq!(std::fs::read(&host_path).unwrap(), b"data");
+        // The connection is still in sync with the agent.
+        assert_eq!(
+            AgentCommand::Ping.run(&channel).unwrap(),
+            AgentOutput::Empty
+        );
+        *channel.lock() = None;
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use super::AgentError::*;
+        let errors = vec![
+            Command("msg".to_string()),
+            HostFile("path".to_string(), io::Error::from_raw_os_error(0)),
+            InvalidResponse("msg".to_string()),
+            Io(io::Error::from_raw_os_error(0)),
+            NotConnected,
+            Timeout,
+        ];
+        for err in errors {
+            let _ = format!("{}{:?}", err, err);
+        }
+
+        match AgentError::from(io::Error::from(io::ErrorKind::WouldBlock)) {
+            Timeout => (),
+            _ => panic!("Test failed."),
+        }
+    }
+}
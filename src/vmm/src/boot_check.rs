@@ -0,0 +1,353 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{Display, Formatter};
+#[cfg(any(feature = "net", feature = "vsock"))]
+use std::fs;
+use std::fs::File;
+#[cfg(feature = "block")]
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+#[cfg(feature = "net")]
+use std::path::Path;
+
+use kvm_ioctls::Kvm;
+
+use crate::resources::{host_memory_mib, VmResources};
+use crate::vmm_config::boot_source::align_initrd;
+use crate::vmm_config::machine_config::DEFAULT_MEM_SIZE_MIB;
+#[cfg(feature = "vsock")]
+use crate::vmm_config::vsock::VsockBackendType;
+use crate::vstate::system::{self, KvmContext};
+
+/// A problem that would make the microVM fail to boot from the configured resources.
+#[derive(Debug)]
+pub enum BootCheckError {
+    /// The drive cannot be opened with the access it is configured with.
+    DriveOpen(String, io::Error),
+    /// The guest memory is larger than the memory of the host, in MiB.
+    HostMemoryExceeded(u64, u64),
+    /// The initrd image cannot be read.
+    InitrdOpen(String, io::Error),
+    /// The initrd images do not fit in the first region of the guest memory.
+    InitrdTooLarge(u64, u64),
+    /// The kernel image cannot be loaded.
+    InvalidKernel(kernel::loader::Error),
+    /// The kernel image cannot be read.
+    KernelOpen(String, io::Error),
+    /// The host does not provide the KVM features the microVM needs.
+    KvmContext(system::Error),
+    /// The KVM device cannot be opened.
+    KvmOpen(kvm_ioctls::Error),
+    /// No kernel nor firmware is configured.
+    MissingKernelConfig,
+    /// The TAP device no longer exists.
+    TapNotFound(String),
+    /// The TAP device belongs to another user.
+    TapOwner(String, u32),
+    /// KVM supports fewer vCPUs than the microVM is configured with.
+    TooManyVcpus(u8, usize),
+    /// The socket the host reaches the guest vsock through is gone.
+    #[cfg(feature = "vsock")]
+    VsockSocket(String),
+}
+
+impl Display for BootCheckError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::BootCheckError::*;
+
+        match self {
+            DriveOpen(drive_id, err) => write!(f, "Cannot open the drive {}: {}", drive_id, err),
+            HostMemoryExceeded(guest_mib, host_mib) => write!(
+                f,
+                "The guest memory ({} MiB) exceeds the host memory ({} MiB)",
+                guest_mib, host_mib
+            ),
+            InitrdOpen(path, err) => write!(f, "Cannot read the initrd {}: {}", path, err),
+            InitrdTooLarge(initrd_size, mem_size) => write!(
+                f,
+                "The initrd images ({} bytes) do not fit in the {} bytes of low memory",
+                initrd_size, mem_size
+            ),
+            InvalidKernel(err) => write!(f, "The kernel image cannot be loaded: {}", err),
+            KernelOpen(path, err) => write!(f, "Cannot read the kernel {}: {}", path, err),
+            KvmContext(err) => write!(f, "Failed to validate KVM support: {}", err),
+            KvmOpen(err) => write!(f, "Cannot open the KVM device: {}", err),
+            MissingKernelConfig => write!(f, "Cannot start microvm without kernel configuration"),
+            TapNotFound(name) => write!(f, "The TAP device {} no longer exists", name),
+            TapOwner(name, uid) => write!(f, "The TAP device {} belongs to user {}", name, uid),
+            TooManyVcpus(vcpu_count, max_vcpus) => write!(
+                f,
+                "KVM supports {} vCPUs, fewer than the {} configured",
+                max_vcpus, vcpu_count
+            ),
+            #[cfg(feature = "vsock")]
+            VsockSocket(path) => write!(f, "The vsock socket {} is gone", path),
+        }
+    }
+}
+
+/// Runs the checks `build_microvm_for_boot` would fail on with `vm_resources`, without building
+/// anything, and returns all the problems found.
+pub fn check_boot_resources(vm_resources: &VmResources) -> Vec<BootCheckError> {
+    let mut errors = Vec::new();
+    check_boot_source(vm_resources, &mut errors);
+    check_memory(vm_resources, &mut errors);
+    #[cfg(feature = "block")]
+    check_drives(vm_resources, &mut errors);
+    #[cfg(feature = "net")]
+    check_taps(vm_resources, &mut errors);
+    #[cfg(feature = "vsock")]
+    check_vsock(vm_resources, &mut errors);
+    check_kvm(vm_resources, &mut errors);
+    errors
+}
+
+// The kernel and initrd images are reopened, in case they changed since they were configured.
+fn check_boot_source(vm_resources: &VmResources, errors: &mut Vec<BootCheckError>) {
+    let boot_config = match vm_resources.boot_source() {
+        Some(boot_config) => boot_config,
+        None => {
+            errors.push(BootCheckError::MissingKernelConfig);
+            return;
+        }
+    };
+    let description = &boot_config.description;
+
+    if let Some(path) = description.kernel_image_path.as_ref() {
+        match File::open(path) {
+            Ok(mut kernel_file) => {
+                if let Err(err) =
+                    kernel::loader::check_kernel(&mut kernel_file, arch::get_kernel_start())
+                {
+                    errors.push(BootCheckError::InvalidKernel(err));
+                }
+            }
+            Err(err) => errors.push(BootCheckError::KernelOpen(path.clone(), err)),
+        }
+    }
+
+    let mut initrd_size = 0;
+    for path in description
+        .initrd_path
+        .iter()
+        .chain(description.initrd_paths.iter())
+    {
+        match File::open(path).and_then(|file| file.metadata()) {
+            Ok(metadata) => {
+                initrd_size = align_initrd(initrd_size) + metadata.len();
+            }
+            Err(err) => errors.push(BootCheckError::InitrdOpen(path.clone(), err)),
+        }
+    }
+    // The initrd images are loaded together in the first region of the boot memory.
+    let boot_mem_size = vm_resources
+        .vm_config()
+        .mem_size_mib
+        .unwrap_or(DEFAULT_MEM_SIZE_MIB)
+        << 20;
+    let lowmem_size =
+        arch::arch_memory_regions(boot_mem_size, &vm_resources.mmio_layout())[0].1 as u64;
+    if initrd_size > lowmem_size {
+        errors.push(BootCheckError::InitrdTooLarge(initrd_size, lowmem_size));
+    }
+}
+
+fn check_memory(vm_resources: &VmResources, errors: &mut Vec<BootCheckError>) {
+    #[allow(unused_mut)]
+    let mut mem_size_mib = vm_resources
+        .vm_config()
+        .mem_size_mib
+        .unwrap_or(DEFAULT_MEM_SIZE_MIB) as u64;
+    #[cfg(feature = "virtio-mem")]
+    if let Some(memory_hotplug) = vm_resources.memory_hotplug.as_ref() {
+        mem_size_mib += memory_hotplug.total_size_mib;
+    }
+    let host_mem_size_mib = host_memory_mib();
+    if mem_size_mib > host_mem_size_mib {
+        errors.push(BootCheckError::HostMemoryExceeded(
+            mem_size_mib,
+            host_mem_size_mib,
+        ));
+    }
+}
+
+// The drives are reopened with the access the guest is given to them.
+#[cfg(feature = "block")]
+fn check_drives(vm_resources: &VmResources, errors: &mut Vec<BootCheckError>) {
+    for config in vm_resources.block.configs() {
+        if let Err(err) = OpenOptions::new()
+            .read(true)
+            .write(!config.is_read_only)
+            .open(&config.path_on_host)
+        {
+            errors.push(BootCheckError::DriveOpen(config.drive_id, err));
+        }
+    }
+}
+
+// The TAP devices are opened when configured, but may since have been removed or handed over.
+#[cfg(feature = "net")]
+fn check_taps(vm_resources: &VmResources, errors: &mut Vec<BootCheckError>) {
+    // Safe because `geteuid` cannot fail.
+    let euid = unsafe { libc::geteuid() };
+    for config in vm_resources.net_builder.configs() {
+        let name = config.host_dev_name;
+        let owner_path = Path::new("/sys/class/net").join(&name).join("owner");
+        match fs::read_to_string(owner_path) {
+            // The owner is -1 when the device is not restricted to one user.
+            Ok(owner) => match owner.trim().parse::<i64>() {
+                Ok(uid) if uid >= 0 && uid != i64::from(euid) => {
+                    errors.push(BootCheckError::TapOwner(name, uid as u32))
+                }
+                _ => (),
+            },
+            Err(_) => errors.push(BootCheckError::TapNotFound(name)),
+        }
+    }
+}
+
+// The socket is bound when the device is configured, but may since have been removed.
+#[cfg(feature = "vsock")]
+fn check_vsock(vm_resources: &VmResources, errors: &mut Vec<BootCheckError>) {
+    if let Some(config) = vm_resources.vsock.config() {
+        if config.backend != VsockBackendType::Unix {
+            return;
+        }
+        let is_socket = fs::metadata(&config.uds_path)
+            .map(|metadata| metadata.file_type().is_socket())
+            .unwrap_or(false);
+        if !is_socket {
+            errors.push(BootCheckError::VsockSocket(config.uds_path));
+        }
+    }
+}
+
+fn check_kvm(vm_resources: &VmResources, errors: &mut Vec<BootCheckError>) {
+    // `KvmContext::new` panics when the KVM device cannot be opened.
+    if let Err(err) = Kvm::new() {
+        errors.push(BootCheckError::KvmOpen(err));
+        return;
+    }
+    let kvm = match KvmContext::new() {
+        Ok(kvm) => kvm,
+        Err(err) => {
+            errors.push(BootCheckError::KvmContext(err));
+            return;
+        }
+    };
+
+    let vm_config = vm_resources.vm_config();
+    let vcpu_count = vm_config
+        .max_vcpu_count
+        .or(vm_config.vcpu_count)
+        .unwrap_or(1);
+    let max_vcpus = kvm.fd().get_max_vcpus();
+    if usize::from(vcpu_count) > max_vcpus {
+        errors.push(BootCheckError::TooManyVcpus(vcpu_count, max_vcpus));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vmm_config::boot_source::BootSourceConfig;
+    #[cfg(feature = "block")]
+    use crate::vmm_config::drive::BlockDeviceConfig;
+    use utils::tempfile::TempFile;
+
+    #[test]
+    fn test_missing_kernel() {
+        let errors = check_boot_resources(&VmResources::default());
+        assert!(errors
+            .iter()
+            .any(|err| matches!(err, BootCheckError::MissingKernelConfig)));
+    }
+
+    #[cfg(feature = "block")]
+    #[test]
+    fn test_all_errors_reported() {
+        let mut vm_resources = VmResources::default();
+
+        // An empty file is not a kernel image.
+        let kernel_file = TempFile::new().unwrap();
+        let initrd_file = TempFile::new().unwrap();
+        vm_resources
+            .set_boot_source(BootSourceConfig {
+                kernel_image_path: Some(kernel_file.as_path().to_str().unwrap().to_string()),
+                initrd_path: Some(initrd_file.as_path().to_str().unwrap().to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let drive_file = TempFile::new().unwrap();
+        vm_resources
+            .set_block_device(BlockDeviceConfig {
+                drive_id: "block1".to_string(),
+                path_on_host: drive_file.as_path().to_str().unwrap().to_string(),
+                is_root_device: false,
+                partuuid: None,
+                is_read_only: false,
+                rate_limiter: None,
+                io_weight: None,
+                rate_limiter_group: None,
+            })
+            .unwrap();
+
+        // The files are removed after they were configured.
+        let initrd_path = initrd_file.as_path().to_str().unwrap().to_string();
+        drop(initrd_file);
+        drop(drive_file);
+
+        let errors = check_boot_resources(&vm_resources);
+        assert!(errors
+            .iter()
+            .any(|err| matches!(err, BootCheckError::InvalidKernel(_))));
+        assert!(errors.iter().any(|err| match err {
+            BootCheckError::InitrdOpen(path, _) => *path == initrd_path,
+            _ => false,
+        }));
+        assert!(errors.iter().any(|err| match err {
+            BootCheckError::DriveOpen(drive_id, _) => drive_id == "block1",
+            _ => false,
+        }));
+        assert!(!errors
+            .iter()
+            .any(|err| matches!(err, BootCheckError::MissingKernelConfig)));
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use self::BootCheckError::*;
+
+        let err = DriveOpen("block1".to_string(), io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = HostMemoryExceeded(2, 1);
+        let _ = format!("{}{:?}", err, err);
+        let err = InitrdOpen(String::new(), io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = InitrdTooLarge(2, 1);
+        let _ = format!("{}{:?}", err, err);
+        let err = InvalidKernel(kernel::loader::Error::ReadKernelImage);
+        let _ = format!("{}{:?}", err, err);
+        let err = KernelOpen(String::new(), io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = KvmContext(system::Error::KvmApiVersion(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = KvmOpen(kvm_ioctls::Error::new(0));
+        let _ = format!("{}{:?}", err, err);
+        let _ = format!("{}{:?}", MissingKernelConfig, MissingKernelConfig);
+        let err = TapNotFound("tap0".to_string());
+        let _ = format!("{}{:?}", err, err);
+        let err = TapOwner("tap0".to_string(), 1);
+        let _ = format!("{}{:?}", err, err);
+        let err = TooManyVcpus(2, 1);
+        let _ = format!("{}{:?}", err, err);
+        #[cfg(feature = "vsock")]
+        {
+            let err = VsockSocket(String::new());
+            let _ = format!("{}{:?}", err, err);
+        }
+    }
+}
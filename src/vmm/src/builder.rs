@@ -3,19 +3,40 @@
 
 //! Enables pre-boot setup, instantiation and booting of a Firecracker VMM.
 
+use std::cell::Cell;
 #[cfg(target_arch = "x86_64")]
 use std::convert::TryFrom;
+use std::ffi::CStr;
 use std::fmt::{Display, Formatter};
+use std::fs::File;
 use std::io::{self, Read, Seek, SeekFrom};
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::AtomicU8;
 use std::sync::{Arc, Mutex};
 
 use crate::device_manager::mmio::MMIODeviceManager;
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::{legacy::PortIODeviceManager, persist::MMIODevManagerConstructorArgs};
+use crate::lifecycle::{LifecycleEventKind, LIFECYCLE_EVENTS};
 #[cfg(target_arch = "x86_64")]
 use crate::persist::{MicrovmState, MicrovmStateError};
-use crate::vmm_config::boot_source::BootConfig;
+use crate::vmm_config::boot_source::{align_initrd, BootConfig};
+use crate::vmm_config::cpu_quota::UNLIMITED_CPU_QUOTA_PCT;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::guest_clock::ClockPolicy;
+use crate::vmm_config::guest_panic::{GuestEvents, PanicAction};
+use crate::vmm_config::machine_config::{MemoryBackend, MemoryHints};
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::machine_config::{PitReinjectPolicy, VmConfig};
+use crate::vmm_config::serial::SerialPortConfig;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::shutdown_behavior::ShutdownBehaviorConfig;
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+use crate::vmm_config::tpm::{TpmBackendConfig, TpmConfig};
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use crate::vmm_config::vfio::VfioConfig;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::watchdog::WatchdogAction;
 use crate::vstate::{
     system::KvmContext,
     vcpu::{Vcpu, VcpuConfig},
@@ -24,30 +45,83 @@ use crate::vstate::{
 use crate::{device_manager, Error, Vmm, VmmEventsObserver};
 
 use arch::InitrdConfig;
-use devices::legacy::Serial;
-use devices::virtio::{Balloon, Block, MmioTransport, Net, VirtioDevice, Vsock, VsockUnixBackend};
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::{CpuHotplug, Pflash, Watchdog, WatchdogError};
+use devices::legacy::{PanicDetector, PvPanic, Serial};
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+use devices::tpm::{SoftwareTpm, SwTpm, TpmBackend, TpmCrb};
+#[cfg(feature = "balloon")]
+use devices::virtio::Balloon;
+#[cfg(feature = "block")]
+use devices::virtio::Block;
+#[cfg(feature = "virtio-console")]
+use devices::virtio::Console;
+#[cfg(feature = "virtio-rng")]
+use devices::virtio::Entropy;
+#[cfg(feature = "net")]
+use devices::virtio::Net;
+#[cfg(feature = "null-devices")]
+use devices::virtio::NullDevice;
+#[cfg(feature = "virtio-pmem")]
+use devices::virtio::Pmem;
+#[cfg(feature = "virtio-fs")]
+use devices::virtio::SharedFs;
+#[cfg(feature = "virtio-mem")]
+use devices::virtio::VirtioMem;
+use devices::virtio::{MmioTransport, VirtioDevice};
+#[cfg(feature = "vsock")]
+use devices::virtio::{VhostVsock, Vsock, VsockUnixBackend};
 use kernel::cmdline::Cmdline as KernelCmdline;
-use logger::warn;
+use kernel::loader::KernelLoaderResult;
+use logger::{in_span, in_timed_span, warn, Span, METRICS};
 use polly::event_manager::{Error as EventManagerError, EventManager, Subscriber};
+use polly::worker::{Error as WorkerError, EventWorker};
 use seccomp::{BpfProgramRef, SeccompFilter};
 #[cfg(target_arch = "x86_64")]
 use snapshot::Persist;
+use timerfd::{ClockId, TimerFd};
 use utils::eventfd::EventFd;
 use utils::terminal::Terminal;
 use utils::time::TimestampUs;
-use vm_memory::{GuestAddress, GuestMemoryMmap};
+use vm_memory::{
+    FileOffset, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion, GuestRegionMmap,
+    MmapRegion,
+};
 
 /// Errors associated with starting the instance.
 #[derive(Debug)]
 pub enum StartMicrovmError {
+    /// Failed to give the host kernel the hints about the guest memory.
+    AdviseGuestMemory(io::Error),
     /// Unable to attach block device to Vmm.
     AttachBlockDevice(io::Error),
+    /// Cannot pass a host PCI function through.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    AttachVfioDevice(String, device_manager::mmio::Error),
     /// This error is thrown by the minimal boot loader implementation.
     ConfigureSystem(arch::Error),
     /// Internal errors are due to resource exhaustion.
+    #[cfg(feature = "net")]
     CreateNetDevice(devices::virtio::net::Error),
     /// Failed to create a `RateLimiter` object.
     CreateRateLimiter(io::Error),
+    /// Failed to create the file backing the guest memory.
+    CreateSharedMemory(io::Error),
+    /// Failed to create the TPM backend.
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    CreateTpm(devices::tpm::Error),
+    /// Failed to create the virtio-mem device.
+    #[cfg(feature = "virtio-mem")]
+    CreateVirtioMem(devices::virtio::mem::Error),
+    /// Failed to create the watchdog.
+    #[cfg(target_arch = "x86_64")]
+    CreateWatchdog(WatchdogError),
+    /// Cannot load the firmware or its variable store.
+    #[cfg(target_arch = "x86_64")]
+    FirmwareLoad(io::Error),
+    /// Cannot start the GDB server.
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    GdbServer(crate::gdb::Error),
     /// Memory regions are overlapping or mmap fails.
     GuestMemoryMmap(vm_memory::Error),
     /// Cannot load initrd due to an invalid memory configuration.
@@ -56,10 +130,14 @@ pub enum StartMicrovmError {
     InitrdRead(io::Error),
     /// Internal error encountered while starting a microVM.
     Internal(Error),
+    /// Cannot spawn the I/O worker threads, or register a device with one of them.
+    IoWorker(WorkerError),
     /// The kernel command line is invalid.
     KernelCmdline(String),
     /// Cannot load kernel due to invalid memory configuration or invalid kernel image.
     KernelLoader(kernel::loader::Error),
+    /// Cannot restrict the filesystem access of the microVM.
+    Landlock(crate::landlock::Error),
     /// Cannot load command line string.
     LoadCommandline(kernel::cmdline::Error),
     /// Cannot start the VM because the kernel was not configured.
@@ -70,6 +148,8 @@ pub enum StartMicrovmError {
     NetDeviceNotConfigured,
     /// Cannot open the block device backing file.
     OpenBlockDevice(io::Error),
+    /// Cannot open the host backend of a serial port.
+    OpenSerialPort(String, io::Error),
     /// Cannot register an EventHandler.
     RegisterEvent(EventManagerError),
     /// Cannot initialize a MMIO Device or add a device to the MMIO Bus or cmdline.
@@ -91,17 +171,46 @@ impl Display for StartMicrovmError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         use self::StartMicrovmError::*;
         match self {
+            AdviseGuestMemory(err) => write!(f, "Cannot advise the guest memory: {}", err),
             AttachBlockDevice(err) => {
                 write!(f, "Unable to attach block device to Vmm. Error: {}", err)
             }
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            AttachVfioDevice(id, err) => {
+                write!(
+                    f,
+                    "Cannot pass the host PCI function {} through: {}",
+                    id, err
+                )
+            }
             ConfigureSystem(e) => write!(f, "System configuration error: {:?}", e),
             CreateRateLimiter(err) => write!(f, "Cannot create RateLimiter: {}", err),
+            CreateSharedMemory(err) => {
+                write!(
+                    f,
+                    "Cannot create the file backing the guest memory: {}",
+                    err
+                )
+            }
+            #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+            CreateTpm(err) => write!(f, "Cannot create the TPM: {}", err),
+            #[cfg(feature = "virtio-mem")]
+            CreateVirtioMem(err) => write!(f, "Cannot create the virtio-mem device: {:?}", err),
+            #[cfg(target_arch = "x86_64")]
+            CreateWatchdog(err) => write!(f, "Cannot create the watchdog: {}", err),
+            #[cfg(feature = "net")]
             CreateNetDevice(err) => {
                 let mut err_msg = format!("{:?}", err);
                 err_msg = err_msg.replace("\"", "");
 
                 write!(f, "Cannot create network device. {}", err_msg)
             }
+            #[cfg(target_arch = "x86_64")]
+            FirmwareLoad(err) => {
+                write!(f, "Cannot load the firmware or its variable store: {}", err)
+            }
+            #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+            GdbServer(err) => write!(f, "Cannot start the GDB server: {}", err),
             GuestMemoryMmap(err) => {
                 // Remove imbricated quotes from error message.
                 let mut err_msg = format!("{:?}", err);
@@ -114,6 +223,7 @@ impl Display for StartMicrovmError {
             ),
             InitrdRead(err) => write!(f, "Cannot load initrd due to an invalid image: {}", err),
             Internal(err) => write!(f, "Internal error while starting microVM: {:?}", err),
+            IoWorker(err) => write!(f, "I/O worker thread error: {:?}", err),
             KernelCmdline(err) => write!(f, "Invalid kernel command line: {}", err),
             KernelLoader(err) => {
                 let mut err_msg = format!("{}", err);
@@ -125,6 +235,7 @@ impl Display for StartMicrovmError {
                     err_msg
                 )
             }
+            Landlock(err) => write!(f, "Cannot restrict the filesystem access: {}", err),
             LoadCommandline(err) => {
                 let mut err_msg = format!("{}", err);
                 err_msg = err_msg.replace("\"", "");
@@ -143,6 +254,11 @@ impl Display for StartMicrovmError {
 
                 write!(f, "Cannot open the block device backing file. {}", err_msg)
             }
+            OpenSerialPort(id, err) => write!(
+                f,
+                "Cannot open the backend of the serial port {}: {}",
+                id, err
+            ),
             RegisterEvent(err) => write!(f, "Cannot register EventHandler. {:?}", err),
             RegisterMmioDevice(err) => {
                 let mut err_msg = format!("{}", err);
@@ -210,6 +326,8 @@ fn create_vmm_and_vcpus(
     guest_memory: GuestMemoryMmap,
     track_dirty_pages: bool,
     vcpu_count: u8,
+    serial_ports: &[SerialPortConfig],
+    mmio_layout: arch::MmioLayout,
 ) -> std::result::Result<(Vmm, Vec<Vcpu>), StartMicrovmError> {
     use self::StartMicrovmError::*;
 
@@ -221,11 +339,47 @@ fn create_vmm_and_vcpus(
         .map_err(Error::EventFd)
         .map_err(Internal)?;
 
+    // Guest panic event, signaled when the panic message shows up on the serial console.
+    let panic_evt = EventFd::new(libc::EFD_NONBLOCK)
+        .map_err(Error::EventFd)
+        .map_err(Internal)?;
+
+    // The pvpanic device, through which the guest kernel reports its crashes.
+    let pvpanic_evt = EventFd::new(libc::EFD_NONBLOCK)
+        .map_err(Error::EventFd)
+        .map_err(Internal)?;
+    let pvpanic = Arc::new(Mutex::new(PvPanic::new(
+        pvpanic_evt
+            .try_clone()
+            .map_err(Error::EventFd)
+            .map_err(Internal)?,
+    )));
+
+    // The timer stopping the microVM when a graceful shutdown takes too long.
+    #[cfg(target_arch = "x86_64")]
+    let shutdown_timer = TimerFd::new_custom(ClockId::Monotonic, true, true)
+        .map_err(Error::TimerFd)
+        .map_err(Internal)?;
+
+    // The timer kicking the throttled vCPUs.
+    let throttle_timer = TimerFd::new_custom(ClockId::Monotonic, true, true)
+        .map_err(Error::TimerFd)
+        .map_err(Internal)?;
+
+    // Written to by the `SIGTERM` handler once the `Vmm` is built.
+    let sigterm_evt = EventFd::new(libc::EFD_NONBLOCK)
+        .map_err(Error::EventFd)
+        .map_err(Internal)?;
+
+    // Written to by the I/O workers whose event loop failed.
+    let io_worker_failed_evt = EventFd::new(libc::EFD_NONBLOCK)
+        .map_err(Error::EventFd)
+        .map_err(Internal)?;
+
     // Instantiate the MMIO device manager.
     // 'mmio_base' address has to be an address which is protected by the kernel
-    // and is architectural specific.
-    let mmio_device_manager =
-        MMIODeviceManager::new(arch::MMIO_MEM_START, (arch::IRQ_BASE, arch::IRQ_MAX));
+    // and is architectural specific. Each device slot gets its own IRQ.
+    let mmio_device_manager = MMIODeviceManager::new(mmio_layout.start(), mmio_layout.irq_range());
 
     let vcpus;
     // For x86_64 we need to create the interrupt controller before calling `KVM_CREATE_VCPUS`
@@ -236,10 +390,17 @@ fn create_vmm_and_vcpus(
         vcpus = create_vcpus(&vm, vcpu_count, &exit_evt).map_err(Internal)?;
 
         // Serial device setup.
+        let serial_out = PanicDetector::new(
+            Box::new(io::stdout()),
+            panic_evt
+                .try_clone()
+                .map_err(Error::EventFd)
+                .map_err(Internal)?,
+        );
         let serial_device = setup_serial_device(
             event_manager,
             Box::new(SerialStdin::get()),
-            Box::new(io::stdout()),
+            Box::new(serial_out),
         )
         .map_err(Internal)?;
         // x86_64 uses the i8042 reset event as the Vmm exit event.
@@ -247,8 +408,14 @@ fn create_vmm_and_vcpus(
             .try_clone()
             .map_err(Error::EventFd)
             .map_err(Internal)?;
-        create_pio_dev_manager_with_legacy_devices(&vm, serial_device, reset_evt)
-            .map_err(Internal)?
+        create_pio_dev_manager_with_legacy_devices(
+            event_manager,
+            &vm,
+            serial_device,
+            serial_ports,
+            pvpanic.clone(),
+            reset_evt,
+        )?
     };
 
     // On aarch64, the vCPUs need to be created (i.e call KVM_CREATE_VCPU) before setting up the
@@ -261,12 +428,46 @@ fn create_vmm_and_vcpus(
         setup_interrupt_controller(&mut vm, vcpu_count)?;
     }
 
+    // From now on, `SIGTERM` shuts the microVM down from the event loop.
+    crate::signal_handler::set_sigterm_event_fd(sigterm_evt.as_raw_fd());
     let vmm = Vmm {
         events_observer: Some(Box::new(SerialStdin::get())),
         guest_memory,
         vcpus_handles: Vec::new(),
+        paused: true,
         exit_evt,
         vm,
+        panic_evt,
+        panic_action: PanicAction::default(),
+        pvpanic,
+        pvpanic_evt,
+        guest_events: GuestEvents::default(),
+        restore_request: None,
+        #[cfg(target_arch = "x86_64")]
+        watchdog_action: WatchdogAction::default(),
+        #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+        tpm_config: None,
+        #[cfg(target_arch = "x86_64")]
+        clock_policy: ClockPolicy::default(),
+        #[cfg(target_arch = "x86_64")]
+        snapshot_time_us: None,
+        #[cfg(target_arch = "x86_64")]
+        shutdown_timer,
+        dirty_pages_sampled_us: utils::time::get_time_us(utils::time::ClockType::Monotonic),
+        cpu_quota_pct: Arc::new(AtomicU8::new(UNLIMITED_CPU_QUOTA_PCT)),
+        throttle_timer,
+        io_workers: Vec::new(),
+        #[cfg(any(feature = "block", feature = "net"))]
+        io_devices: 0,
+        io_worker_failed_evt,
+        reusable: false,
+        exit_on_stop: true,
+        exit_code: None,
+        sigterm_evt,
+        #[cfg(target_arch = "x86_64")]
+        shutdown_behavior: ShutdownBehaviorConfig::default(),
+        #[cfg(target_arch = "x86_64")]
+        mmio_layout,
         mmio_device_manager,
         #[cfg(target_arch = "x86_64")]
         pio_device_manager,
@@ -288,19 +489,52 @@ pub fn build_microvm_for_boot(
     seccomp_filter: BpfProgramRef,
 ) -> std::result::Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
     use self::StartMicrovmError::*;
+    // The time until the first vCPU enters the guest is measured from here.
+    let boot_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+    let timings = &METRICS.timings_us;
     let boot_config = vm_resources.boot_source().ok_or(MissingKernelConfig)?;
 
     let track_dirty_pages = vm_resources.track_dirty_pages();
-    let guest_memory = create_guest_memory(
-        vm_resources
-            .vm_config()
-            .mem_size_mib
-            .ok_or(MissingMemSizeConfig)?,
-        track_dirty_pages,
-    )?;
+    let mmio_layout = vm_resources.mmio_layout();
+    // The vhost-user backends of the shared filesystems need to map the guest memory.
+    let shared_memory = vm_resources.shared_guest_memory();
+    let mem_backend = match vm_resources.memory_backend() {
+        MemoryBackend::Anonymous if shared_memory => MemoryBackend::Memfd,
+        mem_backend => mem_backend,
+    };
+    let mem_size_mib = vm_resources
+        .vm_config()
+        .mem_size_mib
+        .ok_or(MissingMemSizeConfig)?;
+    let guest_memory = in_span("create_guest_memory", || {
+        create_guest_memory(mem_size_mib, track_dirty_pages, mem_backend, &mmio_layout)
+    })?;
     let vcpu_config = vm_resources.vcpu_config();
-    let entry_addr = load_kernel(boot_config, &guest_memory)?;
-    let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
+    let loaded_kernel = in_timed_span("load_kernel", &timings.load_kernel, || {
+        load_kernel(boot_config, &guest_memory)
+    })?;
+    let initrd = in_span("load_initrd", || {
+        load_initrd_from_config(boot_config, &guest_memory)
+    })?;
+    // The system is configured for booting with the boot memory only, since the guest
+    // must not use the hotplug memory before the virtio-mem driver plugs it.
+    let boot_memory = guest_memory.clone();
+    #[cfg(feature = "virtio-mem")]
+    let (guest_memory, virtio_mem) = match vm_resources.memory_hotplug.as_ref() {
+        Some(config) => {
+            let (guest_memory, virtio_mem) = create_hotplug_memory(
+                guest_memory,
+                config.total_size_mib << 20,
+                config.block_size_mib << 20,
+                shared_memory,
+            )?;
+            (guest_memory, Some(virtio_mem))
+        }
+        None => (guest_memory, None),
+    };
+    #[cfg(target_arch = "x86_64")]
+    let guest_memory = load_firmware(boot_config, guest_memory)?;
+    advise_guest_memory(&guest_memory, vm_resources.memory_hints()).map_err(AdviseGuestMemory)?;
     // Clone the command-line so that a failed boot doesn't pollute the original.
     #[allow(unused_mut)]
     let mut boot_cmdline = boot_config.cmdline.clone();
@@ -308,13 +542,81 @@ pub fn build_microvm_for_boot(
     // Timestamp for measuring microVM boot duration.
     let request_ts = TimestampUs::default();
 
-    let (mut vmm, mut vcpus) = create_vmm_and_vcpus(
-        event_manager,
-        guest_memory,
-        track_dirty_pages,
+    let (mut vmm, mut vcpus) = in_span("create_vmm_and_vcpus", || {
+        create_vmm_and_vcpus(
+            event_manager,
+            guest_memory,
+            track_dirty_pages,
+            vcpu_config.vcpu_count,
+            vm_resources.serial_ports.configs(),
+            mmio_layout,
+        )
+    })?;
+    vmm.set_panic_action(vm_resources.panic_action.clone());
+    #[cfg(target_arch = "x86_64")]
+    vmm.set_clock_policy(vm_resources.clock_policy);
+    #[cfg(target_arch = "x86_64")]
+    vmm.set_shutdown_behavior(vm_resources.shutdown_behavior.clone());
+    vmm.set_cpu_quota(vm_resources.cpu_quota.quota_pct);
+    // The I/O workers are spawned before the filesystem access of the VMM thread is restricted,
+    // so they restrict their own.
+    let landlock_paths = vm_resources
+        .landlock
+        .as_ref()
+        .map(|_| vm_resources.landlock_paths());
+    vmm.io_workers = create_io_workers(
+        vm_resources.io_threads(),
+        landlock_paths,
+        spawned_thread_filter(seccomp_filter),
+        &vmm.io_worker_failed_evt,
+    )?;
+
+    let attach_devices_span = Span::timed("attach_devices", &timings.attach_devices);
+    #[cfg(target_arch = "x86_64")]
+    attach_pflash(&mut vmm, boot_config)?;
+
+    #[cfg(target_arch = "x86_64")]
+    setup_legacy_timers(&mut vmm, &mut boot_cmdline, vm_resources.vm_config())?;
+
+    #[cfg(target_arch = "x86_64")]
+    setup_cpu_hotplug(
+        &mut vmm,
+        vm_resources.vm_config().vcpu_count.unwrap(),
         vcpu_config.vcpu_count,
     )?;
 
+    // The virtio devices are plugged on the PCI root bus instead of the MMIO bus. The watchdog
+    // and the host PCI functions always sit on the PCI root bus.
+    #[cfg(target_arch = "x86_64")]
+    #[allow(unused_mut)]
+    let mut pci_root = vm_resources.pci_enabled() || vm_resources.watchdog.is_some();
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    {
+        pci_root |= vm_resources.vfio.iter().next().is_some();
+    }
+    #[cfg(target_arch = "x86_64")]
+    if pci_root {
+        attach_pci_root(&mut vmm)?;
+        vmm.mmio_device_manager.virtio_pci = vm_resources.pci_enabled();
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    if let Some(config) = vm_resources.watchdog.as_ref() {
+        let (slot, bar_addr) = vmm
+            .mmio_device_manager
+            .next_free_pci_slot()
+            .map_err(RegisterMmioDevice)?;
+        let watchdog = Watchdog::new(slot, bar_addr).map_err(CreateWatchdog)?;
+        attach_watchdog(&mut vmm, watchdog, config.action)?;
+    }
+
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    if let Some(config) = vm_resources.tpm.as_ref() {
+        let backend = create_tpm_backend(config).map_err(CreateTpm)?;
+        let tpm = TpmCrb::new(backend, arch::x86_64::layout::TPM_CRB_START);
+        attach_tpm(&mut vmm, config.clone(), tpm)?;
+    }
+
     // The boot timer device needs to be the first device attached in order
     // to maintain the same MMIO address referenced in the documentation
     // and tests.
@@ -322,53 +624,177 @@ pub fn build_microvm_for_boot(
         attach_boot_timer_device(&mut vmm, request_ts)?;
     }
 
+    #[cfg(feature = "balloon")]
     if let Some(balloon) = vm_resources.balloon.get() {
         attach_balloon_device(&mut vmm, &mut boot_cmdline, balloon, event_manager)?;
     }
 
+    #[cfg(feature = "block")]
     attach_block_devices(
         &mut vmm,
         &mut boot_cmdline,
         vm_resources.block.list.iter(),
         event_manager,
     )?;
+    #[cfg(feature = "net")]
     attach_net_devices(
         &mut vmm,
         &mut boot_cmdline,
         vm_resources.net_builder.iter(),
         event_manager,
     )?;
+    #[cfg(feature = "vsock")]
     if let Some(unix_vsock) = vm_resources.vsock.get() {
         attach_unixsock_vsock_device(&mut vmm, &mut boot_cmdline, unix_vsock, event_manager)?;
     }
+    #[cfg(feature = "vsock")]
+    if let Some(vhost_vsock) = vm_resources.vsock.get_vhost() {
+        attach_vhost_vsock_device(&mut vmm, &mut boot_cmdline, vhost_vsock, event_manager)?;
+    }
+    #[cfg(feature = "null-devices")]
+    attach_null_devices(
+        &mut vmm,
+        &mut boot_cmdline,
+        vm_resources.null_devices.iter(),
+        event_manager,
+    )?;
+    #[cfg(feature = "virtio-mem")]
+    if let Some(virtio_mem) = virtio_mem {
+        attach_virtio_mem_device(&mut vmm, &mut boot_cmdline, virtio_mem, event_manager)?;
+    }
+    #[cfg(feature = "virtio-rng")]
+    if let Some(entropy) = vm_resources.entropy.get() {
+        attach_entropy_device(&mut vmm, &mut boot_cmdline, entropy, event_manager)?;
+    }
+    #[cfg(feature = "virtio-console")]
+    if let Some(console) = vm_resources.console.get() {
+        attach_console_device(&mut vmm, &mut boot_cmdline, console, event_manager)?;
+    }
+    #[cfg(feature = "virtio-pmem")]
+    attach_pmem_devices(
+        &mut vmm,
+        &mut boot_cmdline,
+        vm_resources.pmem.iter(),
+        event_manager,
+    )?;
+    #[cfg(feature = "virtio-fs")]
+    attach_shared_fs_devices(
+        &mut vmm,
+        &mut boot_cmdline,
+        vm_resources.shared_fs.iter(),
+        event_manager,
+    )?;
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    {
+        // The memory slots of the BARs follow the ones of the persistent memory devices.
+        #[allow(unused_mut)]
+        let mut first_slot = vmm.guest_memory().num_regions() as u32;
+        #[cfg(feature = "virtio-pmem")]
+        {
+            first_slot += vm_resources.pmem.iter().count() as u32;
+        }
+        attach_vfio_devices(
+            &mut vmm,
+            vm_resources.vfio.iter(),
+            first_slot,
+            event_manager,
+        )?;
+    }
 
     #[cfg(target_arch = "aarch64")]
     attach_legacy_devices_aarch64(event_manager, &mut vmm, &mut boot_cmdline).map_err(Internal)?;
+    drop(attach_devices_span);
+
+    in_timed_span("configure_system", &timings.configure_system, || {
+        configure_system_for_boot(
+            &vmm,
+            &boot_memory,
+            vcpus.as_mut(),
+            vcpu_config,
+            loaded_kernel.as_ref(),
+            &initrd,
+            boot_cmdline,
+        )
+    })?;
+
+    // The vCPUs report to the debugger when they hit a breakpoint or complete a single step.
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    let gdb_server = match vm_resources.gdb_socket.as_ref() {
+        Some(path) => {
+            if vmm_thread_filtered() {
+                return Err(GdbServer(crate::gdb::Error::VmmThreadFiltered));
+            }
+            let listener = crate::gdb::bind(path).map_err(GdbServer)?;
+            let (vcpu_stops_sender, vcpu_stops) = std::sync::mpsc::channel();
+            for vcpu in vcpus.iter_mut() {
+                vcpu.set_debug_stop_sender(vcpu_stops_sender.clone());
+            }
+            Some((listener, vcpu_stops, vcpus.len()))
+        }
+        None => None,
+    };
 
-    configure_system_for_boot(
-        &vmm,
-        vcpus.as_mut(),
-        vcpu_config,
-        entry_addr,
-        &initrd,
-        boot_cmdline,
-    )?;
+    // The configuration is final, so the filesystem access can be restricted. The vCPU threads
+    // inherit the restrictions of the VMM thread when it spawns them.
+    if vm_resources.landlock.is_some() {
+        match crate::landlock::restrict_fs_access(&vm_resources.landlock_paths()) {
+            Err(crate::landlock::Error::Unsupported) => {
+                warn!(
+                    "Landlock is not supported by the host kernel, the filesystem access is \
+                     not restricted."
+                )
+            }
+            result => result.map_err(Landlock)?,
+        }
+    }
+
+    // Another microVM can only be built once this one is torn down if the seccomp filter of the
+    // VMM thread allows it, its filesystem access is not restricted to the files of this one, and
+    // no debugger holds on to it.
+    vmm.reusable = (seccomp_filter.is_empty() || vm_resources.allow_teardown)
+        && vm_resources.landlock.is_none()
+        && vm_resources.gdb_socket.is_none();
 
+    // The boot processor is the first vCPU to enter the guest.
+    if let Some(vcpu) = vcpus.first_mut() {
+        vcpu.set_boot_start_us(boot_start_us);
+    }
     // Move vcpus to their own threads and start their state machine in the 'Paused' state.
-    vmm.start_vcpus(vcpus, seccomp_filter).map_err(Internal)?;
+    in_span("start_vcpus", || {
+        vmm.start_vcpus(vcpus, spawned_thread_filter(seccomp_filter))
+    })
+    .map_err(Internal)?;
+
+    let vmm = Arc::new(Mutex::new(vmm));
+
+    // The GDB server thread must not run with the seccomp filters of the VMM thread.
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    let debugged = gdb_server.is_some();
+    #[cfg(not(all(feature = "gdb", target_arch = "x86_64")))]
+    let debugged = false;
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    if let Some((listener, vcpu_stops, vcpu_count)) = gdb_server {
+        let guest_memory = vmm.lock().expect("Poisoned lock").guest_memory().clone();
+        crate::gdb::start_server(listener, vmm.clone(), guest_memory, vcpu_stops, vcpu_count)
+            .map_err(GdbServer)?;
+    }
 
     // Load seccomp filters for the VMM thread.
     // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping filters
     // altogether is the desired behaviour.
     // Keep this as the last step before resuming vcpus.
-    SeccompFilter::apply(seccomp_filter.to_vec())
-        .map_err(Error::SeccompFilters)
-        .map_err(Internal)?;
-
-    // The vcpus start off in the `Paused` state, let them run.
-    vmm.resume_vm().map_err(Internal)?;
+    apply_vmm_thread_filter(seccomp_filter)?;
+
+    // The vcpus start off in the `Paused` state, let them run, unless the debugger resumes them
+    // once connected, or they are to stay paused until the microVM is resumed.
+    if !debugged && !vm_resources.start_paused {
+        vmm.lock()
+            .expect("Poisoned lock")
+            .resume_vm()
+            .map_err(Internal)?;
+    }
+    LIFECYCLE_EVENTS.emit(LifecycleEventKind::Started);
 
-    let vmm = Arc::new(Mutex::new(vmm));
     event_manager
         .add_subscriber(vmm.clone())
         .map_err(RegisterEvent)?;
@@ -379,26 +805,40 @@ pub fn build_microvm_for_boot(
 /// Builds and starts a microVM based on the provided MicrovmState.
 ///
 /// An `Arc` reference of the built `Vmm` is also plugged in the `EventManager`, while another
-/// is returned.
+/// is returned. The microVM can be torn down under `seccomp_filter` if `allow_teardown` is set.
 #[cfg(target_arch = "x86_64")]
 pub fn build_microvm_from_snapshot(
     event_manager: &mut EventManager,
     microvm_state: MicrovmState,
     guest_memory: GuestMemoryMmap,
     track_dirty_pages: bool,
+    io_threads: u8,
     seccomp_filter: BpfProgramRef,
+    allow_teardown: bool,
 ) -> std::result::Result<Arc<Mutex<Vmm>>, StartMicrovmError> {
     use self::StartMicrovmError::*;
     let vcpu_count = u8::try_from(microvm_state.vcpu_states.len())
         .map_err(|_| MicrovmStateError::InvalidInput)
         .map_err(RestoreMicrovmState)?;
+    let mmio_layout = microvm_state
+        .mmio_layout
+        .layout()
+        .map_err(|_| MicrovmStateError::InvalidInput)
+        .map_err(RestoreMicrovmState)?;
 
-    // Build Vmm.
+    // Build Vmm, binding the serial ports to the same host backends.
+    let serial_ports: Vec<SerialPortConfig> = microvm_state
+        .serial_ports
+        .iter()
+        .map(|state| state.config())
+        .collect();
     let (mut vmm, vcpus) = create_vmm_and_vcpus(
         event_manager,
         guest_memory.clone(),
         track_dirty_pages,
         vcpu_count,
+        &serial_ports,
+        mmio_layout,
     )?;
 
     // Restore kvm vm state.
@@ -406,20 +846,82 @@ pub fn build_microvm_from_snapshot(
         .restore_state(&microvm_state.vm_state)
         .map_err(MicrovmStateError::RestoreVmState)
         .map_err(RestoreMicrovmState)?;
+    // The guest clock restarts from its saved value, unless it is advanced on resume.
+    vmm.snapshot_time_us = microvm_state.saved_at_us;
+
+    // Restore the serial ports state.
+    vmm.pio_device_manager
+        .restore_serial_ports(&microvm_state.serial_ports)
+        .map_err(MicrovmStateError::RestoreSerialPorts)
+        .map_err(RestoreMicrovmState)?;
 
-    // Restore devices states.
+    // Restore the ACPI PM registers, which tell whether the guest handles the power button.
+    vmm.pio_device_manager
+        .acpi_pm
+        .lock()
+        .expect("Poisoned lock")
+        .restore_state(&microvm_state.acpi_pm);
+
+    // Restore the vCPU hotplug controller, which tells which vCPUs resume with the microVM.
+    if let Some(cpu_hotplug_state) = microvm_state.cpu_hotplug.as_ref() {
+        attach_cpu_hotplug(&mut vmm, cpu_hotplug_state.cpu_count, 0)?
+            .lock()
+            .expect("Poisoned lock")
+            .restore_state(cpu_hotplug_state);
+    }
+
+    // Restore devices states, spreading the block and network devices across the I/O workers.
+    vmm.io_workers = create_io_workers(
+        io_threads,
+        None,
+        spawned_thread_filter(seccomp_filter),
+        &vmm.io_worker_failed_evt,
+    )?;
     let mmio_ctor_args = MMIODevManagerConstructorArgs {
         mem: guest_memory,
         vm: vmm.vm.fd(),
         event_manager,
+        mmio_layout,
+        io_workers: &vmm.io_workers,
     };
     vmm.mmio_device_manager =
         MMIODeviceManager::restore(mmio_ctor_args, &microvm_state.device_states)
             .map_err(MicrovmStateError::RestoreDevices)
             .map_err(RestoreMicrovmState)?;
+    // The restored device manager holds the PCI root bus, if the devices were plugged on it.
+    register_pci_config_io(&mut vmm)?;
+
+    // Restore the watchdog in its slot of the PCI root bus. It stays stopped until the microVM
+    // resumes.
+    if let Some(watchdog_state) = microvm_state.watchdog.as_ref() {
+        let watchdog = Watchdog::restore(&watchdog_state.state)
+            .map_err(MicrovmStateError::RestoreWatchdog)
+            .map_err(RestoreMicrovmState)?;
+        attach_watchdog(&mut vmm, watchdog, watchdog_state.action)?;
+    }
+
+    // Restore the TPM, reconnecting to the same backend.
+    #[cfg(feature = "tpm")]
+    if let Some(tpm_state) = microvm_state.tpm.as_ref() {
+        let tpm = create_tpm_backend(&tpm_state.config)
+            .and_then(|backend| {
+                TpmCrb::restore(
+                    backend,
+                    arch::x86_64::layout::TPM_CRB_START,
+                    &tpm_state.state,
+                )
+            })
+            .map_err(MicrovmStateError::RestoreTpm)
+            .map_err(RestoreMicrovmState)?;
+        attach_tpm(&mut vmm, tpm_state.config.clone(), tpm)?;
+    }
+
+    // Another microVM can only be built once this one is torn down if the seccomp filter of the
+    // VMM thread allows it.
+    vmm.reusable = seccomp_filter.is_empty() || allow_teardown;
 
     // Move vcpus to their own threads and start their state machine in the 'Paused' state.
-    vmm.start_vcpus(vcpus, seccomp_filter)
+    vmm.start_vcpus(vcpus, spawned_thread_filter(seccomp_filter))
         .map_err(StartMicrovmError::Internal)?;
 
     // Restore vcpus kvm state.
@@ -433,46 +935,201 @@ pub fn build_microvm_from_snapshot(
 
     // Load seccomp filters for the VMM thread.
     // Keep this as the last step of the building process.
-    SeccompFilter::apply(seccomp_filter.to_vec())
-        .map_err(Error::SeccompFilters)
-        .map_err(StartMicrovmError::Internal)?;
+    apply_vmm_thread_filter(seccomp_filter)?;
 
     Ok(vmm)
 }
 
-/// Creates GuestMemory of `mem_size_mib` MiB in size.
+/// Creates GuestMemory of `mem_size_mib` MiB in size, around the MMIO gap of `mmio_layout`.
+///
+/// Unless `mem_backend` is anonymous memory, every region is backed by its own memfd and mapped
+/// as shared, so that it can be mapped by other processes.
 pub fn create_guest_memory(
     mem_size_mib: usize,
     track_dirty_pages: bool,
+    mem_backend: MemoryBackend,
+    mmio_layout: &arch::MmioLayout,
 ) -> std::result::Result<GuestMemoryMmap, StartMicrovmError> {
     let mem_size = mem_size_mib << 20;
-    let arch_mem_regions = arch::arch_memory_regions(mem_size);
+    let arch_mem_regions = arch::arch_memory_regions(mem_size, mmio_layout);
 
-    if !track_dirty_pages {
-        Ok(GuestMemoryMmap::from_ranges(&arch_mem_regions)
-            .map_err(StartMicrovmError::GuestMemoryMmap)?)
-    } else {
-        Ok(
-            GuestMemoryMmap::from_ranges_with_tracking(&arch_mem_regions)
-                .map_err(StartMicrovmError::GuestMemoryMmap)?,
+    match mem_backend {
+        MemoryBackend::Anonymous if !track_dirty_pages => {
+            Ok(GuestMemoryMmap::from_ranges(&arch_mem_regions)
+                .map_err(StartMicrovmError::GuestMemoryMmap)?)
+        }
+        MemoryBackend::Anonymous => Ok(GuestMemoryMmap::from_ranges_with_tracking(
+            &arch_mem_regions,
         )
+        .map_err(StartMicrovmError::GuestMemoryMmap)?),
+        _ => {
+            let regions = arch_mem_regions
+                .iter()
+                .map(|(addr, size)| {
+                    create_file_backed_region(*addr, *size, mem_backend, track_dirty_pages)
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            Ok(GuestMemoryMmap::from_regions(regions)
+                .map_err(StartMicrovmError::GuestMemoryMmap)?)
+        }
+    }
+}
+
+/// Gives the host kernel the `mem_hints` about every region of `guest_memory`.
+pub(crate) fn advise_guest_memory(
+    guest_memory: &GuestMemoryMmap,
+    mem_hints: MemoryHints,
+) -> io::Result<()> {
+    let advices = mem_hints.advices();
+    guest_memory.with_regions(|_, region| {
+        for advice in advices.iter() {
+            // Safe because the region is a valid mapping of `region.len()` bytes, and the
+            // return value is checked.
+            let ret = unsafe {
+                libc::madvise(
+                    region.as_ptr() as *mut libc::c_void,
+                    region.len() as usize,
+                    *advice,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    })
+}
+
+// Creates a region of guest memory backed by a memfd with the pages of `mem_backend`.
+fn create_file_backed_region(
+    addr: GuestAddress,
+    size: usize,
+    mem_backend: MemoryBackend,
+    track_dirty_pages: bool,
+) -> std::result::Result<GuestRegionMmap, StartMicrovmError> {
+    let file = create_memfd(size, mem_backend).map_err(StartMicrovmError::CreateSharedMemory)?;
+    // The mapping reserves the huge pages, so that running out of them fails here rather
+    // than when the guest first touches them.
+    let region = MmapRegion::build(
+        Some(FileOffset::new(file, 0)),
+        size,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+    )
+    .map_err(vm_memory::Error::MmapRegion)
+    .map_err(StartMicrovmError::GuestMemoryMmap)?;
+    let mut region =
+        GuestRegionMmap::new(region, addr).map_err(StartMicrovmError::GuestMemoryMmap)?;
+    if track_dirty_pages {
+        region.enable_dirty_page_tracking();
+    }
+    Ok(region)
+}
+
+/// Creates an anonymous file of `size` bytes, to back a region of guest memory with the
+/// pages of `mem_backend`.
+pub(crate) fn create_memfd(size: usize, mem_backend: MemoryBackend) -> io::Result<File> {
+    // The flags of memfd_create(2) selecting the size of the huge pages, as its log2 shifted
+    // by MFD_HUGE_SHIFT.
+    const MFD_HUGE_SHIFT: libc::c_uint = 26;
+    const MFD_HUGE_2MB: libc::c_uint = 21 << MFD_HUGE_SHIFT;
+    const MFD_HUGE_1GB: libc::c_uint = 30 << MFD_HUGE_SHIFT;
+
+    let page_flags = match mem_backend {
+        MemoryBackend::Hugetlbfs2M => libc::MFD_HUGETLB | MFD_HUGE_2MB,
+        MemoryBackend::Hugetlbfs1G => libc::MFD_HUGETLB | MFD_HUGE_1GB,
+        MemoryBackend::Anonymous | MemoryBackend::Memfd => 0,
+    };
+    let name = CStr::from_bytes_with_nul(b"guest_mem\0").expect("Invalid memfd name");
+    // Safe because the name is a valid C string and the return value is checked.
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | page_flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
     }
+    // Safe because the file descriptor was just created and nothing else owns it.
+    let file = unsafe { File::from_raw_fd(fd) };
+    // A file of huge pages holds a whole number of them, while the guest memory regions may
+    // end in the middle of one, as before the MMIO gap. The mapping only covers `size` bytes.
+    let file_size = match mem_backend.huge_page_size_mib() {
+        Some(huge_page_size_mib) => {
+            let huge_page_size = huge_page_size_mib << 20;
+            (size + huge_page_size - 1) / huge_page_size * huge_page_size
+        }
+        None => size,
+    };
+    file.set_len(file_size as u64)?;
+    Ok(file)
 }
 
+// Loads the kernel, unless booting a firmware.
 fn load_kernel(
     boot_config: &BootConfig,
     guest_memory: &GuestMemoryMmap,
-) -> std::result::Result<GuestAddress, StartMicrovmError> {
-    let mut kernel_file = boot_config
-        .kernel_file
-        .try_clone()
-        .map_err(|e| StartMicrovmError::Internal(Error::KernelFile(e)))?;
+) -> std::result::Result<Option<KernelLoaderResult>, StartMicrovmError> {
+    let mut kernel_file = match boot_config.kernel_file.as_ref() {
+        Some(kernel_file) => kernel_file
+            .try_clone()
+            .map_err(|e| StartMicrovmError::Internal(Error::KernelFile(e)))?,
+        None => return Ok(None),
+    };
 
-    let entry_addr =
+    let loaded_kernel =
         kernel::loader::load_kernel(guest_memory, &mut kernel_file, arch::get_kernel_start())
             .map_err(StartMicrovmError::KernelLoader)?;
 
-    Ok(entry_addr)
+    Ok(Some(loaded_kernel))
+}
+
+/// Maps the firmware right below 4 GiB, where its last bytes hold the reset vector.
+/// The firmware is mapped privately, so that the guest cannot change the file.
+#[cfg(target_arch = "x86_64")]
+fn load_firmware(
+    boot_config: &BootConfig,
+    guest_memory: GuestMemoryMmap,
+) -> std::result::Result<GuestMemoryMmap, StartMicrovmError> {
+    use self::StartMicrovmError::FirmwareLoad;
+
+    let firmware_file = match boot_config.firmware_file.as_ref() {
+        Some(firmware_file) => firmware_file.try_clone().map_err(FirmwareLoad)?,
+        None => return Ok(guest_memory),
+    };
+    let firmware_size = firmware_file.metadata().map_err(FirmwareLoad)?.len();
+    let firmware_addr = arch::x86_64::layout::FIRMWARE_END - firmware_size;
+    let region = MmapRegion::build(
+        Some(FileOffset::new(firmware_file, 0)),
+        firmware_size as usize,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE,
+    )
+    .map_err(vm_memory::Error::MmapRegion)
+    .map_err(StartMicrovmError::GuestMemoryMmap)?;
+    let region = GuestRegionMmap::new(region, GuestAddress(firmware_addr))
+        .map_err(StartMicrovmError::GuestMemoryMmap)?;
+    guest_memory
+        .insert_region(region)
+        .map_err(StartMicrovmError::GuestMemoryMmap)
+}
+
+/// Attaches the flash holding the variable store of the firmware, if there is one, right
+/// below the firmware. The writes of the guest go through to the file of the store.
+#[cfg(target_arch = "x86_64")]
+fn attach_pflash(
+    vmm: &mut Vmm,
+    boot_config: &BootConfig,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::FirmwareLoad;
+
+    let (firmware_file, nvram_file) = match (&boot_config.firmware_file, &boot_config.nvram_file) {
+        (Some(firmware_file), Some(nvram_file)) => (firmware_file, nvram_file),
+        _ => return Ok(()),
+    };
+    let firmware_size = firmware_file.metadata().map_err(FirmwareLoad)?.len();
+    let pflash =
+        Pflash::new(nvram_file.try_clone().map_err(FirmwareLoad)?).map_err(FirmwareLoad)?;
+    let pflash_addr = arch::x86_64::layout::FIRMWARE_END - firmware_size - pflash.len();
+    vmm.mmio_device_manager
+        .register_mmio_pflash(Arc::new(Mutex::new(pflash)), pflash_addr)
+        .map_err(StartMicrovmError::RegisterMmioDevice)
 }
 
 fn load_initrd_from_config(
@@ -481,52 +1138,66 @@ fn load_initrd_from_config(
 ) -> std::result::Result<Option<InitrdConfig>, StartMicrovmError> {
     use self::StartMicrovmError::InitrdRead;
 
-    Ok(match &boot_cfg.initrd_file {
-        Some(f) => Some(load_initrd(
-            vm_memory,
-            &mut f.try_clone().map_err(InitrdRead)?,
-        )?),
-        None => None,
-    })
+    if boot_cfg.initrd_files.is_empty() {
+        return Ok(None);
+    }
+    let mut images = boot_cfg
+        .initrd_files
+        .iter()
+        .map(File::try_clone)
+        .collect::<io::Result<Vec<File>>>()
+        .map_err(InitrdRead)?;
+    Ok(Some(load_initrd(vm_memory, &mut images)?))
 }
 
-/// Loads the initrd from a file into the given memory slice.
+/// Loads the initrd images from files into the given memory slice, one after the other, so
+/// that the guest sees them as a single initrd.
 ///
 /// * `vm_memory` - The guest memory the initrd is written to.
-/// * `image` - The initrd image.
+/// * `images` - The initrd images, each aligned to `INITRD_ALIGNMENT` in guest memory.
 ///
 /// Returns the result of initrd loading
 fn load_initrd<F>(
     vm_memory: &GuestMemoryMmap,
-    image: &mut F,
+    images: &mut [F],
 ) -> std::result::Result<InitrdConfig, StartMicrovmError>
 where
     F: Read + Seek,
 {
     use self::StartMicrovmError::{InitrdLoad, InitrdRead};
 
-    let size: usize;
-    // Get the image size
-    match image.seek(SeekFrom::End(0)) {
-        Err(e) => return Err(InitrdRead(e)),
-        Ok(0) => {
-            return Err(InitrdRead(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Initrd image seek returned a size of zero",
-            )))
-        }
-        Ok(s) => size = s as usize,
-    };
-    // Go back to the image start
-    image.seek(SeekFrom::Start(0)).map_err(InitrdRead)?;
+    // Get the image sizes, and their offsets in the initrd.
+    let mut offsets = Vec::with_capacity(images.len());
+    let mut size: u64 = 0;
+    for image in images.iter_mut() {
+        let image_size = match image.seek(SeekFrom::End(0)) {
+            Err(e) => return Err(InitrdRead(e)),
+            Ok(0) => {
+                return Err(InitrdRead(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Initrd image seek returned a size of zero",
+                )))
+            }
+            Ok(s) => s,
+        };
+        // Go back to the image start
+        image.seek(SeekFrom::Start(0)).map_err(InitrdRead)?;
+        let offset = align_initrd(size);
+        offsets.push((offset, image_size as usize));
+        size = offset + image_size;
+    }
+    let size = size as usize;
 
     // Get the target address
     let address = arch::initrd_load_addr(vm_memory, size).map_err(|_| InitrdLoad)?;
 
-    // Load the image into memory
-    vm_memory
-        .read_from(GuestAddress(address), image, size)
-        .map_err(|_| InitrdLoad)?;
+    // Load the images into memory. The padding between them is left zeroed, as the guest
+    // memory is when booting, and the kernel skips it.
+    for (image, (offset, image_size)) in images.iter_mut().zip(offsets) {
+        vm_memory
+            .read_from(GuestAddress(address + offset), image, image_size)
+            .map_err(|_| InitrdLoad)?;
+    }
 
     Ok(InitrdConfig {
         address: GuestAddress(address),
@@ -557,6 +1228,119 @@ pub fn setup_interrupt_controller(vm: &mut Vm) -> std::result::Result<(), StartM
         .map_err(StartMicrovmError::Internal)
 }
 
+/// Creates the PIT, unless disabled, and applies the PIT and HPET policies from the machine
+/// configuration. The restored microVMs get the PIT and its policy from their snapshot instead.
+#[cfg(target_arch = "x86_64")]
+fn setup_legacy_timers(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    vm_config: &VmConfig,
+) -> std::result::Result<(), StartMicrovmError> {
+    if vm_config.pit_enabled != Some(false) {
+        vmm.vm
+            .create_pit()
+            .map_err(Error::Vm)
+            .map_err(StartMicrovmError::Internal)?;
+    }
+    if let Some(policy) = vm_config.pit_reinject_policy {
+        vmm.vm
+            .set_pit_reinject(policy == PitReinjectPolicy::Reinject)
+            .map_err(Error::Vm)
+            .map_err(StartMicrovmError::Internal)?;
+    }
+    if vm_config.hpet_enabled == Some(false) {
+        cmdline.insert_str("hpet=disable")?;
+    }
+    Ok(())
+}
+
+/// Sets up the hotplug of the vCPUs past the first `boot_vcpu_count` of the `vcpu_count` ones,
+/// which the ACPI tables describe as disabled until they are plugged.
+#[cfg(target_arch = "x86_64")]
+fn setup_cpu_hotplug(
+    vmm: &mut Vmm,
+    boot_vcpu_count: u8,
+    vcpu_count: u8,
+) -> std::result::Result<(), StartMicrovmError> {
+    if boot_vcpu_count >= vcpu_count {
+        return Ok(());
+    }
+    attach_cpu_hotplug(vmm, vcpu_count, boot_vcpu_count).map(|_| ())
+}
+
+/// Attaches the ACPI hotplug controller of `vcpu_count` vCPUs, of which the first `plugged` are
+/// present at boot.
+#[cfg(target_arch = "x86_64")]
+fn attach_cpu_hotplug(
+    vmm: &mut Vmm,
+    vcpu_count: u8,
+    plugged: u8,
+) -> std::result::Result<Arc<Mutex<CpuHotplug>>, StartMicrovmError> {
+    vmm.pio_device_manager
+        .register_cpu_hotplug(vcpu_count, plugged)
+        .map_err(Error::LegacyIOBus)
+        .map_err(StartMicrovmError::Internal)
+}
+
+/// Attaches the PCI root bus, on which the virtio devices are then plugged.
+#[cfg(target_arch = "x86_64")]
+fn attach_pci_root(vmm: &mut Vmm) -> std::result::Result<(), StartMicrovmError> {
+    vmm.mmio_device_manager
+        .enable_pci(vmm.vm.fd())
+        .map_err(StartMicrovmError::RegisterMmioDevice)?;
+    register_pci_config_io(vmm)
+}
+
+/// Puts the legacy configuration ports of the PCI root bus, if there is one, on the PIO bus.
+#[cfg(target_arch = "x86_64")]
+fn register_pci_config_io(vmm: &mut Vmm) -> std::result::Result<(), StartMicrovmError> {
+    if let Some(pci) = vmm.mmio_device_manager.pci.as_ref() {
+        vmm.pio_device_manager
+            .register_pci_config_io(pci.config_io())
+            .map_err(Error::LegacyIOBus)
+            .map_err(StartMicrovmError::Internal)?;
+    }
+    Ok(())
+}
+
+/// Attaches the watchdog, which the guest kernel pets to show it's not hung, to the PCI root bus.
+#[cfg(target_arch = "x86_64")]
+fn attach_watchdog(
+    vmm: &mut Vmm,
+    watchdog: Watchdog,
+    action: WatchdogAction,
+) -> std::result::Result<(), StartMicrovmError> {
+    vmm.mmio_device_manager
+        .register_pci_watchdog(Arc::new(Mutex::new(watchdog)))
+        .map_err(StartMicrovmError::RegisterMmioDevice)?;
+    vmm.set_watchdog_action(action);
+    Ok(())
+}
+
+/// Creates the TPM implementation described by `config`.
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+fn create_tpm_backend(config: &TpmConfig) -> devices::tpm::Result<Box<dyn TpmBackend>> {
+    let backend: Box<dyn TpmBackend> = match &config.backend {
+        TpmBackendConfig::Software(software) => Box::new(SoftwareTpm::new(&software.state_path)?),
+        TpmBackendConfig::Swtpm(swtpm) => Box::new(SwTpm::connect(&swtpm.socket_path)?),
+    };
+    Ok(backend)
+}
+
+/// Attaches the TPM, which the guest uses for measured boot and to seal its secrets.
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+fn attach_tpm(
+    vmm: &mut Vmm,
+    config: TpmConfig,
+    tpm: TpmCrb,
+) -> std::result::Result<(), StartMicrovmError> {
+    vmm.mmio_device_manager
+        .register_mmio_tpm(Arc::new(Mutex::new(tpm)))
+        .map_err(StartMicrovmError::RegisterMmioDevice)?;
+    vmm.set_tpm_config(config);
+    Ok(())
+}
+
 /// Sets up the irqchip for a aarch64 microVM.
 #[cfg(target_arch = "aarch64")]
 pub fn setup_interrupt_controller(
@@ -593,17 +1377,69 @@ pub fn setup_serial_device(
     Ok(serial)
 }
 
-#[cfg(target_arch = "x86_64")]
-fn create_pio_dev_manager_with_legacy_devices(
-    vm: &Vm,
-    serial: Arc<Mutex<devices::legacy::Serial>>,
+/// Sets up a serial port bound to a host backend.
+pub fn setup_serial_port(
+    event_manager: &mut EventManager,
+    config: &SerialPortConfig,
+    interrupt_evt: EventFd,
+) -> std::result::Result<Arc<Mutex<Serial>>, StartMicrovmError> {
+    let (input, out) = config
+        .open()
+        .map_err(|e| StartMicrovmError::OpenSerialPort(config.port_id.clone(), e))?;
+    let serial = match input {
+        Some(input) => {
+            let buffer_ready_evt = EventFd::new(libc::EFD_NONBLOCK)
+                .map_err(Error::EventFd)
+                .map_err(StartMicrovmError::Internal)?;
+            let serial = Arc::new(Mutex::new(Serial::new_in_out(
+                interrupt_evt,
+                input,
+                out,
+                Some(buffer_ready_evt),
+            )));
+            event_manager
+                .add_subscriber(serial.clone())
+                .map_err(StartMicrovmError::RegisterEvent)?;
+            serial
+        }
+        None => Arc::new(Mutex::new(Serial::new_out(interrupt_evt, out))),
+    };
+    Ok(serial)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn create_pio_dev_manager_with_legacy_devices(
+    event_manager: &mut EventManager,
+    vm: &Vm,
+    serial: Arc<Mutex<devices::legacy::Serial>>,
+    serial_ports: &[SerialPortConfig],
+    pvpanic: Arc<Mutex<PvPanic>>,
     i8042_reset_evfd: EventFd,
-) -> std::result::Result<PortIODeviceManager, super::Error> {
-    let mut pio_dev_mgr =
-        PortIODeviceManager::new(serial, i8042_reset_evfd).map_err(Error::CreateLegacyDevice)?;
+) -> std::result::Result<PortIODeviceManager, StartMicrovmError> {
+    use self::StartMicrovmError::Internal;
+
+    let mut pio_dev_mgr = PortIODeviceManager::new(serial, i8042_reset_evfd)
+        .map_err(Error::CreateLegacyDevice)
+        .map_err(Internal)?;
+    for config in serial_ports.iter() {
+        let interrupt_evt = pio_dev_mgr
+            .serial_interrupt_evt(&config.port_id)
+            .map_err(Error::CreateLegacyDevice)
+            .map_err(Internal)?;
+        let serial_port = setup_serial_port(event_manager, config, interrupt_evt)?;
+        pio_dev_mgr
+            .add_serial_port(config.clone(), serial_port)
+            .map_err(Error::CreateLegacyDevice)
+            .map_err(Internal)?;
+    }
     pio_dev_mgr
         .register_devices(vm.fd())
-        .map_err(Error::LegacyIOBus)?;
+        .map_err(Error::LegacyIOBus)
+        .map_err(Internal)?;
+    pio_dev_mgr
+        .register_pvpanic(pvpanic)
+        .map_err(Error::LegacyIOBus)
+        .map_err(Internal)?;
     Ok(pio_dev_mgr)
 }
 
@@ -615,10 +1451,14 @@ fn attach_legacy_devices_aarch64(
 ) -> super::Result<()> {
     // Serial device setup.
     if cmdline.as_str().contains("console=") {
+        let serial_out = PanicDetector::new(
+            Box::new(io::stdout()),
+            vmm.panic_evt.try_clone().map_err(Error::EventFd)?,
+        );
         let serial = setup_serial_device(
             event_manager,
             Box::new(SerialStdin::get()),
-            Box::new(io::stdout()),
+            Box::new(serial_out),
         )?;
         vmm.mmio_device_manager
             .register_mmio_serial(vmm.vm.fd(), serial)
@@ -630,6 +1470,10 @@ fn attach_legacy_devices_aarch64(
 
     vmm.mmio_device_manager
         .register_new_mmio_rtc(vmm.vm.fd())
+        .map_err(Error::RegisterMMIODevice)?;
+
+    vmm.mmio_device_manager
+        .register_mmio_pvpanic(vmm.pvpanic.clone())
         .map_err(Error::RegisterMMIODevice)
 }
 
@@ -645,30 +1489,54 @@ fn create_vcpus(vm: &Vm, vcpu_count: u8, exit_evt: &EventFd) -> super::Result<Ve
     Ok(vcpus)
 }
 
-/// Configures the system for booting Linux.
+/// Configures the system for booting Linux, or a firmware when no kernel was loaded.
 #[cfg_attr(target_arch = "aarch64", allow(unused))]
 pub fn configure_system_for_boot(
     vmm: &Vmm,
+    boot_memory: &GuestMemoryMmap,
     vcpus: &mut [Vcpu],
     vcpu_config: VcpuConfig,
-    entry_addr: GuestAddress,
+    loaded_kernel: Option<&KernelLoaderResult>,
     initrd: &Option<InitrdConfig>,
     boot_cmdline: KernelCmdline,
 ) -> std::result::Result<(), StartMicrovmError> {
     use self::StartMicrovmError::*;
     #[cfg(target_arch = "x86_64")]
     {
+        use arch::x86_64::{BootProtocol, EntryPoint};
+
+        // The kernels supporting the PVH boot protocol are started with it.
+        let entry_point = loaded_kernel.map(|kernel| match kernel.pvh_entry_addr {
+            Some(entry_addr) => EntryPoint {
+                entry_addr,
+                protocol: BootProtocol::PvhBoot,
+            },
+            None => EntryPoint {
+                entry_addr: kernel.entry_addr,
+                protocol: BootProtocol::LinuxBoot,
+            },
+        });
+        // A firmware finds the memory map, and the command line it may hand over to the kernel
+        // it boots, in the start info structure of the PVH boot protocol.
+        let boot_prot =
+            entry_point.map_or(BootProtocol::PvhBoot, |entry_point| entry_point.protocol);
         for vcpu in vcpus.iter_mut() {
             vcpu.kvm_vcpu
                 .configure(
                     vmm.guest_memory(),
-                    entry_addr,
+                    entry_point,
                     &vcpu_config,
                     vmm.vm.supported_cpuid().clone(),
                 )
                 .map_err(Error::VcpuConfigure)
                 .map_err(Internal)?;
         }
+        if let Some(policy) = vcpu_config.msr_policy.as_ref() {
+            vmm.vm
+                .set_msr_policy(policy)
+                .map_err(Error::Vm)
+                .map_err(Internal)?;
+        }
 
         // Write the kernel command line to guest memory. This is x86_64 specific, since on
         // aarch64 the command line will be specified through the FDT.
@@ -678,17 +1546,30 @@ pub fn configure_system_for_boot(
             &boot_cmdline.as_cstring().map_err(LoadCommandline)?,
         )
         .map_err(LoadCommandline)?;
+        // The vCPUs past the plugged ones are hotplugged later on.
+        let boot_vcpu_count = vmm
+            .pio_device_manager
+            .cpu_hotplug
+            .as_ref()
+            .map_or(vcpus.len() as u8, |cpu_hotplug| {
+                cpu_hotplug.lock().expect("Poisoned lock").plugged()
+            });
         arch::x86_64::configure_system(
-            &vmm.guest_memory,
+            boot_memory,
             vm_memory::GuestAddress(arch::x86_64::layout::CMDLINE_START),
             boot_cmdline.len() + 1,
             initrd,
             vcpus.len() as u8,
+            boot_vcpu_count,
+            boot_prot,
+            loaded_kernel.and_then(|kernel| kernel.setup_header.as_ref()),
+            vmm.mmio_device_manager.pci.is_some(),
         )
         .map_err(ConfigureSystem)?;
     }
     #[cfg(target_arch = "aarch64")]
     {
+        let entry_addr = loaded_kernel.ok_or(MissingKernelConfig)?.entry_addr;
         for vcpu in vcpus.iter_mut() {
             vcpu.kvm_vcpu
                 .configure(vmm.vm.fd(), vmm.guest_memory(), entry_addr)
@@ -701,7 +1582,7 @@ pub fn configure_system_for_boot(
             .map(|cpu| cpu.kvm_vcpu.get_mpidr())
             .collect();
         arch::aarch64::configure_system(
-            &vmm.guest_memory,
+            boot_memory,
             &boot_cmdline.as_cstring().map_err(LoadCommandline)?,
             vcpu_mpidr,
             vmm.mmio_device_manager.get_device_info(),
@@ -713,6 +1594,82 @@ pub fn configure_system_for_boot(
     Ok(())
 }
 
+thread_local! {
+    // Whether the VMM thread loaded its seccomp filter while building a previous microVM. The
+    // filter stays loaded once that microVM is torn down.
+    static VMM_THREAD_FILTERED: Cell<bool> = Cell::new(false);
+}
+
+fn vmm_thread_filtered() -> bool {
+    VMM_THREAD_FILTERED.with(Cell::get)
+}
+
+/// Returns the seccomp filter for the threads spawned by the VMM thread to load. Once the VMM
+/// thread loaded `seccomp_filter`, they inherit it, and load no other.
+fn spawned_thread_filter(seccomp_filter: BpfProgramRef) -> BpfProgramRef {
+    if vmm_thread_filtered() {
+        &[]
+    } else {
+        seccomp_filter
+    }
+}
+
+/// Loads `seccomp_filter` in the VMM thread, unless it was loaded for a previous microVM.
+fn apply_vmm_thread_filter(
+    seccomp_filter: BpfProgramRef,
+) -> std::result::Result<(), StartMicrovmError> {
+    if vmm_thread_filtered() {
+        return Ok(());
+    }
+    SeccompFilter::apply(seccomp_filter.to_vec())
+        .map_err(Error::SeccompFilters)
+        .map_err(StartMicrovmError::Internal)?;
+    VMM_THREAD_FILTERED.with(|filtered| filtered.set(!seccomp_filter.is_empty()));
+    Ok(())
+}
+
+/// Spawns `count` threads processing the I/O of the block and network devices. The threads
+/// restrict their filesystem access to `landlock_paths`, if any, and load the seccomp filters
+/// of the VMM thread. A thread whose event loop fails writes to `failed_evt`.
+fn create_io_workers(
+    count: u8,
+    landlock_paths: Option<Vec<crate::landlock::AllowedPath>>,
+    seccomp_filter: BpfProgramRef,
+    failed_evt: &EventFd,
+) -> std::result::Result<Vec<EventWorker>, StartMicrovmError> {
+    (0..count)
+        .map(|index| {
+            let landlock_paths = landlock_paths.clone();
+            let seccomp_filter = seccomp_filter.to_vec();
+            let failed_evt = failed_evt
+                .try_clone()
+                .map_err(Error::EventFd)
+                .map_err(StartMicrovmError::Internal)?;
+            EventWorker::new(format!("fc_io{}", index), failed_evt, move || {
+                if let Some(paths) = landlock_paths {
+                    match crate::landlock::restrict_fs_access(&paths) {
+                        Ok(()) | Err(crate::landlock::Error::Unsupported) => (),
+                        Err(e) => {
+                            return Err(format!(
+                                "Failed to restrict the filesystem access of I/O worker {}: {}",
+                                index, e
+                            ))
+                        }
+                    }
+                }
+                // Use --seccomp-level=0 if skipping filters altogether is the desired behaviour.
+                SeccompFilter::apply(seccomp_filter).map_err(|e| {
+                    format!(
+                        "Failed to set the requested seccomp filters on I/O worker {}: Error: {}",
+                        index, e
+                    )
+                })
+            })
+            .map_err(StartMicrovmError::IoWorker)
+        })
+        .collect()
+}
+
 /// Attaches a VirtioDevice device to the device manager and event manager.
 fn attach_virtio_device<T: 'static + VirtioDevice + Subscriber>(
     event_manager: &mut EventManager,
@@ -721,14 +1678,59 @@ fn attach_virtio_device<T: 'static + VirtioDevice + Subscriber>(
     device: Arc<Mutex<T>>,
     cmdline: &mut KernelCmdline,
 ) -> std::result::Result<(), StartMicrovmError> {
-    use self::StartMicrovmError::*;
-
     event_manager
         .add_subscriber(device.clone())
-        .map_err(RegisterEvent)?;
+        .map_err(StartMicrovmError::RegisterEvent)?;
+
+    plug_virtio_device(event_manager, vmm, id, device, cmdline)
+}
+
+/// Attaches a block or network device to the device manager and to the I/O worker it is
+/// assigned to, or to the event manager when there is none.
+#[cfg(any(feature = "block", feature = "net"))]
+fn attach_io_virtio_device<T: 'static + VirtioDevice + Subscriber + Send>(
+    event_manager: &mut EventManager,
+    vmm: &mut Vmm,
+    id: String,
+    device: Arc<Mutex<T>>,
+    cmdline: &mut KernelCmdline,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
+
+    match vmm.next_io_worker() {
+        Some(io_worker) => io_worker.add_subscriber(device.clone()).map_err(IoWorker)?,
+        None => event_manager
+            .add_subscriber(device.clone())
+            .map_err(RegisterEvent)?,
+    }
+
+    plug_virtio_device(event_manager, vmm, id, device, cmdline)
+}
+
+/// Plugs a VirtioDevice device, already subscribed to its events, on the MMIO bus or the PCI
+/// root bus.
+fn plug_virtio_device<T: 'static + VirtioDevice + Subscriber>(
+    event_manager: &mut EventManager,
+    vmm: &mut Vmm,
+    id: String,
+    device: Arc<Mutex<T>>,
+    cmdline: &mut KernelCmdline,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::*;
 
     // The device mutex mustn't be locked here otherwise it will deadlock.
     let device = MmioTransport::new(vmm.guest_memory().clone(), device);
+    #[cfg(target_arch = "x86_64")]
+    if vmm.mmio_device_manager.virtio_pci {
+        let pci_device = vmm
+            .mmio_device_manager
+            .register_pci_virtio_for_boot(vmm.vm.fd(), id, device)
+            .map_err(RegisterMmioDevice)?;
+        // The PCI function relays the interrupts of the device as MSI-X messages.
+        return event_manager
+            .add_subscriber(pci_device)
+            .map_err(RegisterEvent);
+    }
     vmm.mmio_device_manager
         .register_mmio_virtio_for_boot(vmm.vm.fd(), id, device, cmdline)
         .map_err(RegisterMmioDevice)
@@ -750,6 +1752,7 @@ pub(crate) fn attach_boot_timer_device(
     Ok(())
 }
 
+#[cfg(feature = "block")]
 fn attach_block_devices<'a>(
     vmm: &mut Vmm,
     cmdline: &mut KernelCmdline,
@@ -773,11 +1776,12 @@ fn attach_block_devices<'a>(
             locked.id().clone()
         };
         // The device mutex mustn't be locked here otherwise it will deadlock.
-        attach_virtio_device(event_manager, vmm, id, block.clone(), cmdline)?;
+        attach_io_virtio_device(event_manager, vmm, id, block.clone(), cmdline)?;
     }
     Ok(())
 }
 
+#[cfg(feature = "net")]
 fn attach_net_devices<'a>(
     vmm: &mut Vmm,
     cmdline: &mut KernelCmdline,
@@ -787,11 +1791,12 @@ fn attach_net_devices<'a>(
     for net_device in net_devices {
         let id = net_device.lock().expect("Poisoned lock").id().clone();
         // The device mutex mustn't be locked here otherwise it will deadlock.
-        attach_virtio_device(event_manager, vmm, id, net_device.clone(), cmdline)?;
+        attach_io_virtio_device(event_manager, vmm, id, net_device.clone(), cmdline)?;
     }
     Ok(())
 }
 
+#[cfg(feature = "vsock")]
 fn attach_unixsock_vsock_device(
     vmm: &mut Vmm,
     cmdline: &mut KernelCmdline,
@@ -803,6 +1808,19 @@ fn attach_unixsock_vsock_device(
     attach_virtio_device(event_manager, vmm, id, unix_vsock.clone(), cmdline)
 }
 
+#[cfg(feature = "vsock")]
+fn attach_vhost_vsock_device(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    vhost_vsock: &Arc<Mutex<VhostVsock>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    let id = String::from(vhost_vsock.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_virtio_device(event_manager, vmm, id, vhost_vsock.clone(), cmdline)
+}
+
+#[cfg(feature = "balloon")]
 fn attach_balloon_device(
     vmm: &mut Vmm,
     cmdline: &mut KernelCmdline,
@@ -814,23 +1832,218 @@ fn attach_balloon_device(
     attach_virtio_device(event_manager, vmm, id, balloon.clone(), cmdline)
 }
 
+#[cfg(feature = "virtio-fs")]
+fn attach_shared_fs_devices<'a>(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    shared_fs_devices: impl Iterator<Item = &'a Arc<Mutex<SharedFs>>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    for shared_fs in shared_fs_devices {
+        let id = shared_fs.lock().expect("Poisoned lock").id().clone();
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        attach_virtio_device(event_manager, vmm, id, shared_fs.clone(), cmdline)?;
+    }
+    Ok(())
+}
+
+/// Attaches the persistent memory devices, laying out their mappings one after the other
+/// past the end of the guest memory.
+#[cfg(feature = "virtio-pmem")]
+fn attach_pmem_devices<'a>(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    pmem_devices: impl Iterator<Item = &'a Arc<Mutex<Pmem>>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    let mut guest_address = arch::pmem_memory_start(vmm.guest_memory().last_addr());
+    // The memory slots following the guest memory ones are used by the devices.
+    let first_slot = vmm.guest_memory().num_regions() as u32;
+    for (index, pmem) in pmem_devices.enumerate() {
+        let id = {
+            let mut locked = pmem.lock().expect("Poisoned lock");
+            locked.set_guest_address(guest_address);
+            guest_address = GuestAddress(guest_address.0 + locked.size());
+            device_manager::mmio::register_pmem_memory(
+                vmm.vm.fd(),
+                first_slot + index as u32,
+                &locked,
+            )
+            .map_err(StartMicrovmError::RegisterMmioDevice)?;
+            locked.id().clone()
+        };
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        attach_virtio_device(event_manager, vmm, id, pmem.clone(), cmdline)?;
+    }
+    Ok(())
+}
+
+/// Passes the host PCI functions through on the PCI root bus. The guest memory is pinned for
+/// their DMAs, and their BARs are mapped from the KVM memory slot `first_slot` on.
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+fn attach_vfio_devices<'a>(
+    vmm: &mut Vmm,
+    vfio_devices: impl Iterator<Item = &'a VfioConfig>,
+    mut first_slot: u32,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    use self::StartMicrovmError::AttachVfioDevice;
+
+    let guest_memory = vmm.guest_memory().clone();
+    for config in vfio_devices {
+        let attach_err = |e| AttachVfioDevice(config.vfio_id.clone(), e);
+        let device = vmm
+            .mmio_device_manager
+            .open_vfio_device(&guest_memory, &config.sysfs_path)
+            .map_err(attach_err)?;
+        let (vfio_pci, num_slots) = vmm
+            .mmio_device_manager
+            .register_pci_vfio(vmm.vm.fd(), device, first_slot)
+            .map_err(attach_err)?;
+        first_slot += num_slots;
+        // The PCI function relays the interrupts of the host function as MSI-X messages.
+        event_manager
+            .add_subscriber(vfio_pci)
+            .map_err(StartMicrovmError::RegisterEvent)?;
+    }
+    vmm.mmio_device_manager
+        .register_kvm_vfio_device(vmm.vm.fd())
+        .map_err(StartMicrovmError::RegisterMmioDevice)
+}
+
+#[cfg(feature = "null-devices")]
+fn attach_null_devices<'a>(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    null_devices: impl Iterator<Item = &'a Arc<Mutex<NullDevice>>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    for null_device in null_devices {
+        let id = null_device.lock().expect("Poisoned lock").id().clone();
+        // The device mutex mustn't be locked here otherwise it will deadlock.
+        attach_virtio_device(event_manager, vmm, id, null_device.clone(), cmdline)?;
+    }
+    Ok(())
+}
+
+/// Appends the hotplug memory region to the boot memory and creates the virtio-mem
+/// device managing it. When `shared` is set, the region is backed by a memfd.
+#[cfg(feature = "virtio-mem")]
+fn create_hotplug_memory(
+    boot_memory: GuestMemoryMmap,
+    region_size: u64,
+    block_size: u64,
+    shared: bool,
+) -> std::result::Result<(GuestMemoryMmap, Arc<Mutex<VirtioMem>>), StartMicrovmError> {
+    let region_addr = arch::hotplug_memory_start(boot_memory.last_addr());
+    let region = if shared {
+        let file = create_memfd(region_size as usize, MemoryBackend::Memfd)
+            .map_err(StartMicrovmError::CreateSharedMemory)?;
+        MmapRegion::from_file(FileOffset::new(file, 0), region_size as usize)
+    } else {
+        MmapRegion::new(region_size as usize)
+    }
+    .map_err(vm_memory::Error::MmapRegion)
+    .map_err(StartMicrovmError::GuestMemoryMmap)?;
+    let region =
+        GuestRegionMmap::new(region, region_addr).map_err(StartMicrovmError::GuestMemoryMmap)?;
+    let guest_memory = boot_memory
+        .insert_region(region)
+        .map_err(StartMicrovmError::GuestMemoryMmap)?;
+    let virtio_mem = VirtioMem::new(region_addr, region_size, block_size, false)
+        .map_err(StartMicrovmError::CreateVirtioMem)?;
+
+    Ok((guest_memory, Arc::new(Mutex::new(virtio_mem))))
+}
+
+#[cfg(feature = "virtio-mem")]
+fn attach_virtio_mem_device(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    virtio_mem: Arc<Mutex<VirtioMem>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    let id = String::from(virtio_mem.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_virtio_device(event_manager, vmm, id, virtio_mem, cmdline)
+}
+
+#[cfg(feature = "virtio-rng")]
+fn attach_entropy_device(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    entropy: &Arc<Mutex<Entropy>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    let id = String::from(entropy.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_virtio_device(event_manager, vmm, id, entropy.clone(), cmdline)
+}
+
+#[cfg(feature = "virtio-console")]
+fn attach_console_device(
+    vmm: &mut Vmm,
+    cmdline: &mut KernelCmdline,
+    console: &Arc<Mutex<Console>>,
+    event_manager: &mut EventManager,
+) -> std::result::Result<(), StartMicrovmError> {
+    let id = String::from(console.lock().expect("Poisoned lock").id());
+    // The device mutex mustn't be locked here otherwise it will deadlock.
+    attach_virtio_device(event_manager, vmm, id, console.clone(), cmdline)
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::io::Cursor;
 
     use super::*;
+    #[cfg(feature = "balloon")]
     use crate::vmm_config::balloon::{BalloonBuilder, BalloonDeviceConfig, BALLOON_DEV_ID};
     use crate::vmm_config::boot_source::DEFAULT_KERNEL_CMDLINE;
+    #[cfg(feature = "virtio-console")]
+    use crate::vmm_config::console::{ConsoleBuilder, ConsoleConfig, CONSOLE_DEV_ID};
+    #[cfg(feature = "block")]
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
+    #[cfg(feature = "virtio-rng")]
+    use crate::vmm_config::entropy::{EntropyDeviceBuilder, EntropyDeviceConfig, ENTROPY_DEV_ID};
+    #[cfg(feature = "virtio-mem")]
+    use crate::vmm_config::memory_hotplug::{MemoryHotplugConfig, MEM_DEV_ID};
+    #[cfg(feature = "net")]
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
+    #[cfg(feature = "null-devices")]
+    use crate::vmm_config::null_device::{NullDeviceBuilder, NullDeviceConfig, NullDeviceType};
+    #[cfg(feature = "virtio-pmem")]
+    use crate::vmm_config::pmem::{PmemBuilder, PmemConfig};
+    #[cfg(feature = "virtio-fs")]
+    use crate::vmm_config::shared_fs::tests::spawn_backend;
+    #[cfg(feature = "virtio-fs")]
+    use crate::vmm_config::shared_fs::{CacheMode, SharedFsBuilder, SharedFsConfig};
+    #[cfg(feature = "vsock")]
     use crate::vmm_config::vsock::tests::default_config;
+    #[cfg(feature = "vsock")]
     use crate::vmm_config::vsock::{VsockBuilder, VsockDeviceConfig};
     use arch::DeviceType;
-    use devices::virtio::{TYPE_BALLOON, TYPE_BLOCK, TYPE_VSOCK};
+    #[cfg(feature = "balloon")]
+    use devices::virtio::TYPE_BALLOON;
+    #[cfg(feature = "block")]
+    use devices::virtio::TYPE_BLOCK;
+    #[cfg(feature = "virtio-console")]
+    use devices::virtio::TYPE_CONSOLE;
+    #[cfg(feature = "virtio-fs")]
+    use devices::virtio::TYPE_FS;
+    #[cfg(feature = "virtio-mem")]
+    use devices::virtio::TYPE_MEM;
+    #[cfg(feature = "virtio-pmem")]
+    use devices::virtio::TYPE_PMEM;
+    #[cfg(feature = "virtio-rng")]
+    use devices::virtio::TYPE_RNG;
+    #[cfg(feature = "vsock")]
+    use devices::virtio::TYPE_VSOCK;
     use kernel::cmdline::Cmdline;
     use polly::event_manager::EventManager;
     use utils::tempfile::TempFile;
 
+    #[cfg(feature = "block")]
     pub(crate) struct CustomBlockConfig {
         drive_id: String,
         is_root_device: bool,
@@ -838,6 +2051,7 @@ pub mod tests {
         is_read_only: bool,
     }
 
+    #[cfg(feature = "block")]
     impl CustomBlockConfig {
         pub(crate) fn new(
             drive_id: String,
@@ -855,7 +2069,8 @@ pub mod tests {
     }
 
     fn default_mmio_device_manager() -> MMIODeviceManager {
-        MMIODeviceManager::new(arch::MMIO_MEM_START, (arch::IRQ_BASE, arch::IRQ_MAX))
+        let mmio_layout = arch::MmioLayout::default();
+        MMIODeviceManager::new(mmio_layout.start(), mmio_layout.irq_range())
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -876,7 +2091,13 @@ pub mod tests {
     }
 
     pub(crate) fn default_vmm() -> Vmm {
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory = create_guest_memory(
+            128,
+            false,
+            MemoryBackend::Anonymous,
+            &arch::MmioLayout::default(),
+        )
+        .unwrap();
 
         let exit_evt = EventFd::new(libc::EFD_NONBLOCK)
             .map_err(Error::EventFd)
@@ -892,15 +2113,52 @@ pub mod tests {
             events_observer: Some(Box::new(SerialStdin::get())),
             guest_memory,
             vcpus_handles: Vec::new(),
+            paused: true,
             exit_evt,
             vm,
+            panic_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            panic_action: PanicAction::default(),
+            pvpanic: Arc::new(Mutex::new(PvPanic::new(
+                EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            ))),
+            pvpanic_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            guest_events: GuestEvents::default(),
+            restore_request: None,
+            #[cfg(target_arch = "x86_64")]
+            watchdog_action: WatchdogAction::default(),
+            #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+            tpm_config: None,
+            #[cfg(target_arch = "x86_64")]
+            clock_policy: ClockPolicy::default(),
+            #[cfg(target_arch = "x86_64")]
+            snapshot_time_us: None,
+            #[cfg(target_arch = "x86_64")]
+            shutdown_timer: TimerFd::new_custom(ClockId::Monotonic, true, true).unwrap(),
+            dirty_pages_sampled_us: 0,
+            cpu_quota_pct: Arc::new(AtomicU8::new(UNLIMITED_CPU_QUOTA_PCT)),
+            throttle_timer: TimerFd::new_custom(ClockId::Monotonic, true, true).unwrap(),
+            io_workers: Vec::new(),
+            #[cfg(any(feature = "block", feature = "net"))]
+            io_devices: 0,
+            io_worker_failed_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            reusable: false,
+            exit_on_stop: true,
+            exit_code: None,
+            sigterm_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            #[cfg(target_arch = "x86_64")]
+            shutdown_behavior: ShutdownBehaviorConfig::default(),
+            #[cfg(target_arch = "x86_64")]
+            mmio_layout: arch::MmioLayout::default(),
             mmio_device_manager,
             #[cfg(target_arch = "x86_64")]
             pio_device_manager,
         };
 
         #[cfg(target_arch = "x86_64")]
-        setup_interrupt_controller(&mut vmm.vm).unwrap();
+        {
+            setup_interrupt_controller(&mut vmm.vm).unwrap();
+            vmm.vm.create_pit().unwrap();
+        }
 
         #[cfg(target_arch = "aarch64")]
         setup_interrupt_controller(&mut vmm.vm, 1).unwrap();
@@ -908,6 +2166,7 @@ pub mod tests {
         vmm
     }
 
+    #[cfg(feature = "block")]
     pub(crate) fn insert_block_devices(
         vmm: &mut Vmm,
         cmdline: &mut Cmdline,
@@ -931,6 +2190,8 @@ pub mod tests {
                 partuuid: custom_block_cfg.partuuid.clone(),
                 is_read_only: custom_block_cfg.is_read_only,
                 rate_limiter: None,
+                io_weight: None,
+                rate_limiter_group: None,
             };
             block_dev_configs.insert(block_device_config).unwrap();
         }
@@ -939,6 +2200,7 @@ pub mod tests {
         block_files
     }
 
+    #[cfg(feature = "net")]
     pub(crate) fn insert_net_device(
         vmm: &mut Vmm,
         cmdline: &mut Cmdline,
@@ -952,6 +2214,7 @@ pub mod tests {
         assert!(res.is_ok());
     }
 
+    #[cfg(feature = "vsock")]
     pub(crate) fn insert_vsock_device(
         vmm: &mut Vmm,
         cmdline: &mut Cmdline,
@@ -970,6 +2233,68 @@ pub mod tests {
             .is_some());
     }
 
+    #[cfg(feature = "null-devices")]
+    pub(crate) fn insert_null_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        null_device_config: NullDeviceConfig,
+    ) {
+        let null_device_id = null_device_config.device_id.clone();
+        let virtio_type = null_device_config.device_type.virtio_type();
+        let mut null_devices = NullDeviceBuilder::new();
+        null_devices.build(null_device_config).unwrap();
+
+        let res = attach_null_devices(vmm, cmdline, null_devices.iter(), event_manager);
+        assert!(res.is_ok());
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(virtio_type), &null_device_id)
+            .is_some());
+    }
+
+    #[cfg(feature = "virtio-pmem")]
+    pub(crate) fn insert_pmem_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        pmem_config: PmemConfig,
+    ) {
+        let pmem_id = pmem_config.pmem_id.clone();
+        let mut pmem_devices = PmemBuilder::new();
+        pmem_devices.build(pmem_config).unwrap();
+
+        let res = attach_pmem_devices(vmm, cmdline, pmem_devices.iter(), event_manager);
+        assert!(res.is_ok());
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_PMEM), &pmem_id)
+            .is_some());
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    pub(crate) fn insert_shared_fs_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        shared_fs_config: SharedFsConfig,
+    ) {
+        let fs_id = shared_fs_config.fs_id.clone();
+        let mut shared_fs_devices = SharedFsBuilder::new();
+        shared_fs_devices.build(shared_fs_config).unwrap();
+
+        let res = attach_shared_fs_devices(vmm, cmdline, shared_fs_devices.iter(), event_manager);
+        assert!(res.is_ok());
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_FS), &fs_id)
+            .is_some());
+    }
+
+    #[cfg(feature = "balloon")]
     pub(crate) fn insert_balloon_device(
         vmm: &mut Vmm,
         cmdline: &mut Cmdline,
@@ -988,6 +2313,69 @@ pub mod tests {
             .is_some());
     }
 
+    #[cfg(feature = "virtio-mem")]
+    pub(crate) fn insert_virtio_mem_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        config: MemoryHotplugConfig,
+    ) {
+        let (guest_memory, virtio_mem) = create_hotplug_memory(
+            vmm.guest_memory().clone(),
+            config.total_size_mib << 20,
+            config.block_size_mib << 20,
+            false,
+        )
+        .unwrap();
+        // The hotplug region is not registered with KVM, which is fine for the device tests.
+        vmm.guest_memory = guest_memory;
+
+        assert!(attach_virtio_mem_device(vmm, cmdline, virtio_mem, event_manager).is_ok());
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID)
+            .is_some());
+    }
+
+    #[cfg(feature = "virtio-rng")]
+    pub(crate) fn insert_entropy_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        entropy_config: EntropyDeviceConfig,
+    ) {
+        let mut builder = EntropyDeviceBuilder::new();
+        assert!(builder.set(entropy_config).is_ok());
+        let entropy = builder.get().unwrap();
+
+        assert!(attach_entropy_device(vmm, cmdline, entropy, event_manager).is_ok());
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_RNG), ENTROPY_DEV_ID)
+            .is_some());
+    }
+
+    #[cfg(feature = "virtio-console")]
+    pub(crate) fn insert_console_device(
+        vmm: &mut Vmm,
+        cmdline: &mut Cmdline,
+        event_manager: &mut EventManager,
+        console_config: ConsoleConfig,
+    ) {
+        let mut builder = ConsoleBuilder::new();
+        assert!(builder.set(console_config).is_ok());
+        let console = builder.get().unwrap();
+
+        assert!(attach_console_device(vmm, cmdline, console, event_manager).is_ok());
+
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_CONSOLE), CONSOLE_DEV_ID)
+            .is_some());
+    }
+
     fn make_test_bin() -> Vec<u8> {
         let mut fake_bin = Vec::new();
         fake_bin.resize(1_000_000, 0xAA);
@@ -1016,18 +2404,90 @@ pub mod tests {
         #[cfg(target_arch = "aarch64")]
         let gm = create_guest_mem_with_size(mem_size + arch::aarch64::layout::FDT_MAX_SIZE);
 
-        let res = load_initrd(&gm, &mut Cursor::new(&image));
+        let res = load_initrd(&gm, &mut [Cursor::new(&image)]);
         assert!(res.is_ok());
         let initrd = res.unwrap();
         assert!(gm.address_in_range(initrd.address));
         assert_eq!(initrd.size, image.len());
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_load_firmware() {
+        use crate::vmm_config::boot_source::BootSourceConfig;
+        use std::os::unix::fs::FileExt;
+        use vm_memory::{Bytes, GuestMemory};
+
+        let firmware = TempFile::new().unwrap();
+        firmware.as_file().set_len(0x2000).unwrap();
+        // A far jump at the reset vector.
+        firmware
+            .as_file()
+            .write_all_at(&[0xea, 0x5b, 0xe0, 0x00, 0xf0], 0x1ff0)
+            .unwrap();
+        let mut boot_config = BootConfig {
+            cmdline: default_kernel_cmdline(),
+            kernel_file: None,
+            initrd_files: Vec::new(),
+            firmware_file: None,
+            nvram_file: None,
+            description: BootSourceConfig::default(),
+        };
+
+        // Without a firmware, the guest memory is left alone.
+        let gm = create_guest_mem_with_size(0x10000);
+        let gm = load_firmware(&boot_config, gm).unwrap();
+        assert_eq!(gm.num_regions(), 1);
+
+        boot_config.firmware_file = Some(firmware.as_file().try_clone().unwrap());
+        let gm = load_firmware(&boot_config, gm).unwrap();
+        assert_eq!(gm.num_regions(), 2);
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0xffff_fff0)).unwrap(), 0xea);
+        assert_eq!(gm.last_addr(), GuestAddress(0xffff_ffff));
+
+        // The guest cannot change the file of the firmware.
+        gm.write_obj(0u8, GuestAddress(0xffff_fff0)).unwrap();
+        let mut content = [0u8];
+        firmware
+            .as_file()
+            .read_exact_at(&mut content, 0x1ff0)
+            .unwrap();
+        assert_eq!(content[0], 0xea);
+    }
+
+    #[test]
+    fn test_load_initrd_images() {
+        use vm_memory::Bytes;
+
+        let first = vec![1u8, 2, 3, 4, 5];
+        let second = vec![6u8; 8];
+        #[cfg(target_arch = "x86_64")]
+        let gm = create_guest_mem_with_size(arch::PAGE_SIZE * 4);
+        #[cfg(target_arch = "aarch64")]
+        let gm =
+            create_guest_mem_with_size(arch::PAGE_SIZE * 4 + arch::aarch64::layout::FDT_MAX_SIZE);
+
+        let initrd = load_initrd(&gm, &mut [Cursor::new(&first), Cursor::new(&second)]).unwrap();
+        // The second image starts at the next 4 bytes boundary.
+        assert_eq!(initrd.size, 16);
+        let mut content = [0xffu8; 16];
+        gm.read_slice(&mut content, initrd.address).unwrap();
+        assert_eq!(content, [1, 2, 3, 4, 5, 0, 0, 0, 6, 6, 6, 6, 6, 6, 6, 6]);
+
+        // An empty image is rejected.
+        let empty = Vec::new();
+        let res = load_initrd(&gm, &mut [Cursor::new(&first), Cursor::new(&empty)]);
+        match res {
+            Err(StartMicrovmError::InitrdRead(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
     #[test]
     fn test_load_initrd_no_memory() {
         let gm = create_guest_mem_with_size(79);
         let image = make_test_bin();
-        let res = load_initrd(&gm, &mut Cursor::new(&image));
+        let res = load_initrd(&gm, &mut [Cursor::new(&image)]);
         assert!(res.is_err());
         assert_eq!(
             StartMicrovmError::InitrdLoad.to_string(),
@@ -1040,7 +2500,7 @@ pub mod tests {
         let image = vec![1, 2, 3, 4];
         let gm = create_guest_mem_at(GuestAddress(arch::PAGE_SIZE as u64 + 1), image.len() * 2);
 
-        let res = load_initrd(&gm, &mut Cursor::new(&image));
+        let res = load_initrd(&gm, &mut [Cursor::new(&image)]);
         assert!(res.is_err());
         assert_eq!(
             StartMicrovmError::InitrdLoad.to_string(),
@@ -1060,21 +2520,104 @@ pub mod tests {
 
         // Case 1: create guest memory without dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, false).unwrap();
+            let guest_memory = create_guest_memory(
+                mem_size,
+                false,
+                MemoryBackend::Anonymous,
+                &arch::MmioLayout::default(),
+            )
+            .unwrap();
             assert!(!guest_memory.is_dirty_tracking_enabled());
         }
 
         // Case 2: create guest memory with dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, true).unwrap();
+            let guest_memory = create_guest_memory(
+                mem_size,
+                true,
+                MemoryBackend::Anonymous,
+                &arch::MmioLayout::default(),
+            )
+            .unwrap();
+            assert!(guest_memory.is_dirty_tracking_enabled());
+        }
+
+        // Case 3: create shared guest memory, backed by files
+        {
+            use vm_memory::{GuestMemory, GuestMemoryRegion};
+
+            let guest_memory = create_guest_memory(
+                mem_size,
+                false,
+                MemoryBackend::Memfd,
+                &arch::MmioLayout::default(),
+            )
+            .unwrap();
+            assert!(!guest_memory.is_dirty_tracking_enabled());
+            assert!(guest_memory
+                .iter()
+                .all(|region| region.file_offset().is_some()));
+
+            let guest_memory = create_guest_memory(
+                mem_size,
+                true,
+                MemoryBackend::Memfd,
+                &arch::MmioLayout::default(),
+            )
+            .unwrap();
             assert!(guest_memory.is_dirty_tracking_enabled());
         }
+
+        // Case 4: create guest memory backed by huge pages, which fails when the host has
+        // not reserved enough of them.
+        if let Ok(guest_memory) = create_guest_memory(
+            2,
+            true,
+            MemoryBackend::Hugetlbfs2M,
+            &arch::MmioLayout::default(),
+        ) {
+            use vm_memory::{GuestMemory, GuestMemoryRegion};
+
+            assert!(guest_memory.is_dirty_tracking_enabled());
+            assert!(guest_memory
+                .iter()
+                .all(|region| region.file_offset().is_some()));
+        }
+    }
+
+    #[test]
+    fn test_advise_guest_memory() {
+        let guest_memory = create_guest_memory(
+            128,
+            false,
+            MemoryBackend::Anonymous,
+            &arch::MmioLayout::default(),
+        )
+        .unwrap();
+
+        // Without hints, the memory is left alone.
+        advise_guest_memory(&guest_memory, MemoryHints::default()).unwrap();
+
+        // A host kernel built without KSM or transparent huge pages rejects the advices.
+        let mem_hints = MemoryHints {
+            mergeable: true,
+            huge_pages: Some(false),
+        };
+        if let Err(err) = advise_guest_memory(&guest_memory, mem_hints) {
+            assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+        }
     }
 
     #[test]
     fn test_create_vcpus() {
         let vcpu_count = 2;
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory = create_guest_memory(
+            128,
+            false,
+            MemoryBackend::Anonymous,
+            &arch::MmioLayout::default(),
+        )
+        .unwrap();
 
         #[allow(unused_mut)]
         let mut vm = setup_kvm_vm(&guest_memory, false).unwrap();
@@ -1088,6 +2631,32 @@ pub mod tests {
     }
 
     #[test]
+    #[cfg(feature = "block")]
+    fn test_attach_io_devices() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+        vmm.io_workers = create_io_workers(2, None, &[], &vmm.io_worker_failed_evt).unwrap();
+
+        let block_configs = vec![
+            CustomBlockConfig::new(String::from("root"), true, None, true),
+            CustomBlockConfig::new(String::from("data"), false, None, false),
+            CustomBlockConfig::new(String::from("logs"), false, None, false),
+        ];
+        let block_files =
+            insert_block_devices(&mut vmm, &mut cmdline, &mut event_manager, block_configs);
+        assert_eq!(vmm.io_devices, block_files.len());
+        assert!(vmm
+            .mmio_device_manager
+            .get_device(DeviceType::Virtio(TYPE_BLOCK), "logs")
+            .is_some());
+
+        // The workers stop processing the events of their devices while paused.
+        assert_eq!(vmm.pause_io_workers().unwrap().len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "net")]
     fn test_attach_net_devices() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
         let mut vmm = default_vmm();
@@ -1099,6 +2668,9 @@ pub mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: true,
+            vlan_id: None,
+            dhcp: None,
+            rate_limiter_group: None,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1115,6 +2687,7 @@ pub mod tests {
     }
 
     #[test]
+    #[cfg(feature = "block")]
     fn test_attach_block_devices() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
 
@@ -1256,6 +2829,253 @@ pub mod tests {
     }
 
     #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_setup_legacy_timers() {
+        // Builds a VMM with the interrupt controller only, as it is before the timers are set up.
+        let vmm_without_pit = || {
+            let mut vmm = default_vmm();
+            vmm.vm = setup_kvm_vm(vmm.guest_memory(), false).unwrap();
+            setup_interrupt_controller(&mut vmm.vm).unwrap();
+            vmm
+        };
+
+        // Leaving the policies unset keeps the defaults.
+        let mut vmm = vmm_without_pit();
+        let mut cmdline = default_kernel_cmdline();
+        setup_legacy_timers(&mut vmm, &mut cmdline, &VmConfig::default()).unwrap();
+        assert!(!cmdline.as_str().contains("hpet=disable"));
+        let vm_state = vmm.vm.save_state().unwrap();
+        assert!(vm_state.pit_enabled());
+        assert!(vm_state.pit_reinject());
+
+        let mut vmm = vmm_without_pit();
+        let vm_config = VmConfig {
+            pit_reinject_policy: Some(PitReinjectPolicy::Discard),
+            hpet_enabled: Some(false),
+            ..Default::default()
+        };
+        setup_legacy_timers(&mut vmm, &mut cmdline, &vm_config).unwrap();
+        assert!(cmdline.as_str().contains("hpet=disable"));
+        assert!(!vmm.vm.save_state().unwrap().pit_reinject());
+
+        // Without a PIT, there is no policy to apply.
+        let mut vmm = vmm_without_pit();
+        let vm_config = VmConfig {
+            pit_enabled: Some(false),
+            ..Default::default()
+        };
+        setup_legacy_timers(&mut vmm, &mut cmdline, &vm_config).unwrap();
+        assert!(!vmm.vm.save_state().unwrap().pit_enabled());
+        let vm_config = VmConfig {
+            pit_enabled: Some(false),
+            pit_reinject_policy: Some(PitReinjectPolicy::Discard),
+            ..Default::default()
+        };
+        assert!(setup_legacy_timers(&mut vmm_without_pit(), &mut cmdline, &vm_config).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_attach_watchdog() {
+        let mut vmm = default_vmm();
+        assert!(vmm.mmio_device_manager.watchdog.is_none());
+        let bar_addr = arch::x86_64::layout::PCI_MMIO_START;
+
+        // The watchdog needs the PCI root bus.
+        let watchdog = Watchdog::new(1, bar_addr).unwrap();
+        assert!(attach_watchdog(&mut vmm, watchdog, WatchdogAction::Reset).is_err());
+
+        attach_pci_root(&mut vmm).unwrap();
+        let watchdog = Watchdog::new(1, bar_addr).unwrap();
+        attach_watchdog(&mut vmm, watchdog, WatchdogAction::Reset).unwrap();
+        assert!(vmm.mmio_device_manager.watchdog.is_some());
+        assert_eq!(vmm.watchdog_action, WatchdogAction::Reset);
+
+        // The watchdog can only be attached once.
+        let watchdog = Watchdog::new(1, bar_addr).unwrap();
+        assert!(attach_watchdog(&mut vmm, watchdog, WatchdogAction::None).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_graceful_shutdown() {
+        use timerfd::TimerState;
+
+        let mut vmm = default_vmm();
+        match vmm.shutdown_timer.get_state() {
+            TimerState::Disarmed => (),
+            _ => panic!("Unexpected timer state."),
+        }
+
+        // The guest is sent CTRL+ALT+DEL, and is stopped if it doesn't reset in time.
+        vmm.graceful_shutdown(60_000).unwrap();
+        match vmm.shutdown_timer.get_state() {
+            TimerState::Oneshot(remaining) => {
+                assert!(remaining <= std::time::Duration::from_secs(60))
+            }
+            _ => panic!("Unexpected timer state."),
+        }
+    }
+
+    #[test]
+    fn test_stop_without_exit() {
+        let mut vmm = default_vmm();
+        vmm.set_exit_on_stop(false);
+        assert_eq!(vmm.exit_code(), None);
+        // The process keeps running, with the exit code recorded.
+        vmm.stop(i32::from(crate::FC_EXIT_CODE_GENERIC_ERROR));
+        assert_eq!(
+            vmm.exit_code(),
+            Some(i32::from(crate::FC_EXIT_CODE_GENERIC_ERROR))
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_restore_snapshot_on_guest_panic() {
+        use std::path::PathBuf;
+
+        use crate::vmm_config::guest_panic::{GuestEventKind, GuestEventSource};
+
+        let panic_action = PanicAction::RestoreSnapshot {
+            snapshot_path: PathBuf::from("foo"),
+            mem_file_path: PathBuf::from("bar"),
+        };
+
+        // The embedded microVMs are stopped instead.
+        let mut vmm = default_vmm();
+        vmm.reusable = true;
+        vmm.set_exit_on_stop(false);
+        vmm.set_panic_action(panic_action.clone());
+        vmm.handle_guest_panic(GuestEventKind::Panicked, GuestEventSource::Pvpanic);
+        assert!(vmm.take_restore_request().is_none());
+        assert_eq!(
+            vmm.exit_code(),
+            Some(i32::from(crate::FC_EXIT_CODE_GENERIC_ERROR))
+        );
+
+        let mut vmm = default_vmm();
+        vmm.reusable = true;
+        vmm.set_panic_action(panic_action);
+        vmm.handle_guest_panic(GuestEventKind::Panicked, GuestEventSource::Pvpanic);
+        let params = vmm.take_restore_request().unwrap();
+        assert_eq!(params.snapshot_path, PathBuf::from("foo"));
+        assert_eq!(params.mem_file_path, PathBuf::from("bar"));
+        assert!(params.resume_vm);
+        assert_eq!(vmm.exit_code(), None);
+
+        // The same crash reported again does not restore the microVM twice.
+        vmm.handle_guest_panic(GuestEventKind::CrashLoaded, GuestEventSource::Pvpanic);
+        assert!(vmm.take_restore_request().is_none());
+    }
+
+    #[test]
+    fn test_machine_stats() {
+        let mut vmm = default_vmm();
+        let stats = vmm.machine_stats().unwrap();
+        assert_eq!(stats.guest_memory_bytes, 128 << 20);
+        // The guest memory is not touched yet, and no balloon is attached.
+        assert!(stats.guest_memory_resident_bytes < stats.guest_memory_bytes);
+        assert_eq!(stats.guest_memory_ballooned_bytes, 0);
+        assert!(stats.rss_bytes > 0);
+        assert!(stats.open_fds > 0);
+        // The dirty pages are not tracked.
+        assert_eq!(stats.dirty_pages_per_sec, None);
+    }
+
+    #[test]
+    fn test_set_cpu_quota() {
+        use std::sync::atomic::Ordering;
+        use timerfd::TimerState;
+
+        let mut vmm = default_vmm();
+        match vmm.throttle_timer.get_state() {
+            TimerState::Disarmed => (),
+            _ => panic!("Unexpected timer state."),
+        }
+
+        // The vCPUs are kicked once per period while they are throttled.
+        vmm.set_cpu_quota(50);
+        assert_eq!(vmm.cpu_quota_pct.load(Ordering::Relaxed), 50);
+        match vmm.throttle_timer.get_state() {
+            TimerState::Periodic { interval, .. } => assert_eq!(
+                interval,
+                std::time::Duration::from_micros(crate::vmm_config::cpu_quota::CPU_QUOTA_PERIOD_US)
+            ),
+            _ => panic!("Unexpected timer state."),
+        }
+
+        vmm.set_cpu_quota(UNLIMITED_CPU_QUOTA_PCT);
+        match vmm.throttle_timer.get_state() {
+            TimerState::Disarmed => (),
+            _ => panic!("Unexpected timer state."),
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_sync_guest_clock() {
+        let hour_us = 3600 * 1_000_000;
+        let snapshot_time_us = utils::time::get_time_us(utils::time::ClockType::Real) - hour_us;
+
+        // By default, the guest clock restarts from its saved value.
+        let mut vmm = default_vmm();
+        vmm.snapshot_time_us = Some(snapshot_time_us);
+        let clock = vmm.vm.fd().get_clock().unwrap().clock;
+        vmm.resume_vm().unwrap();
+        assert!(vmm.vm.fd().get_clock().unwrap().clock < clock + hour_us * 1000);
+        assert_eq!(vmm.snapshot_time_us, None);
+
+        // Otherwise, it is advanced by the time elapsed since the snapshot, only once.
+        let mut vmm = default_vmm();
+        vmm.set_clock_policy(ClockPolicy::Sync);
+        vmm.snapshot_time_us = Some(snapshot_time_us);
+        let clock = vmm.vm.fd().get_clock().unwrap().clock;
+        vmm.resume_vm().unwrap();
+        let synced_clock = vmm.vm.fd().get_clock().unwrap().clock;
+        assert!(synced_clock >= clock + hour_us * 1000);
+        vmm.pause_vm().unwrap();
+        vmm.resume_vm().unwrap();
+        assert!(vmm.vm.fd().get_clock().unwrap().clock < synced_clock + hour_us * 1000);
+    }
+
+    #[test]
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    fn test_attach_tpm() {
+        use crate::vmm_config::tpm::{SoftwareTpmConfig, SwtpmConfig};
+
+        let mut vmm = default_vmm();
+        assert!(vmm.mmio_device_manager.tpm.is_none());
+
+        // There's nothing listening on the swtpm socket.
+        let socket_file = TempFile::new().unwrap();
+        let config = TpmConfig {
+            backend: TpmBackendConfig::Swtpm(SwtpmConfig {
+                socket_path: socket_file.as_path().to_str().unwrap().to_string(),
+            }),
+        };
+        assert!(create_tpm_backend(&config).is_err());
+
+        let state_file = TempFile::new().unwrap();
+        let config = TpmConfig {
+            backend: TpmBackendConfig::Software(SoftwareTpmConfig {
+                state_path: state_file.as_path().to_str().unwrap().to_string(),
+            }),
+        };
+        let backend = create_tpm_backend(&config).unwrap();
+        let tpm = TpmCrb::new(backend, arch::x86_64::layout::TPM_CRB_START);
+        attach_tpm(&mut vmm, config.clone(), tpm).unwrap();
+        assert!(vmm.mmio_device_manager.tpm.is_some());
+        assert_eq!(vmm.tpm_config, Some(config.clone()));
+
+        // The TPM can only be attached once.
+        let backend = create_tpm_backend(&config).unwrap();
+        let tpm = TpmCrb::new(backend, arch::x86_64::layout::TPM_CRB_START);
+        assert!(attach_tpm(&mut vmm, config, tpm).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "balloon")]
     fn test_attach_balloon_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
         let mut vmm = default_vmm();
@@ -1264,6 +3084,7 @@ pub mod tests {
             amount_mb: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         };
 
         let mut cmdline = default_kernel_cmdline();
@@ -1276,6 +3097,191 @@ pub mod tests {
     }
 
     #[test]
+    #[cfg(feature = "null-devices")]
+    fn test_attach_null_devices() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let null_device_config = NullDeviceConfig {
+            device_id: "snd0".to_string(),
+            device_type: NullDeviceType::Sound,
+        };
+
+        let mut cmdline = default_kernel_cmdline();
+        insert_null_device(
+            &mut vmm,
+            &mut cmdline,
+            &mut event_manager,
+            null_device_config,
+        );
+        // Check if the null device is described in kernel_cmdline.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert!(cmdline
+            .as_str()
+            .contains("virtio_mmio.device=4K@0xd0000000:5"));
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-fs")]
+    fn test_attach_shared_fs_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let socket_path = tmp_sock_file.as_path().to_str().unwrap().to_string();
+        let backend = spawn_backend(&socket_path);
+        let shared_fs_config = SharedFsConfig {
+            fs_id: "fs0".to_string(),
+            tag: "myfs".to_string(),
+            socket_path,
+            cache_mode: CacheMode::Auto,
+        };
+
+        let mut cmdline = default_kernel_cmdline();
+        insert_shared_fs_device(&mut vmm, &mut cmdline, &mut event_manager, shared_fs_config);
+        let _conn = backend.join().unwrap();
+        // Check if the shared filesystem is described in kernel_cmdline.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert!(cmdline
+            .as_str()
+            .contains("virtio_mmio.device=4K@0xd0000000:5"));
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-pmem")]
+    fn test_attach_pmem_devices() {
+        use crate::vmm_config::pmem::tests::{backing_file, default_config};
+        use devices::virtio::pmem::PMEM_ALIGNMENT;
+        use vm_memory::Address;
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+        let mut cmdline = default_kernel_cmdline();
+
+        let file0 = backing_file(PMEM_ALIGNMENT);
+        let file1 = backing_file(2 * PMEM_ALIGNMENT);
+        let mut config1 = default_config("pmem1", &file1);
+        config1.is_read_only = true;
+        let mut builder = PmemBuilder::new();
+        builder.build(default_config("pmem0", &file0)).unwrap();
+        builder.build(config1).unwrap();
+        assert!(
+            attach_pmem_devices(&mut vmm, &mut cmdline, builder.iter(), &mut event_manager).is_ok()
+        );
+
+        // The mappings follow each other past the end of the guest memory.
+        let start = arch::pmem_memory_start(vmm.guest_memory().last_addr());
+        let mut devices = builder.iter();
+        let pmem0 = devices.next().unwrap().lock().unwrap();
+        let pmem1 = devices.next().unwrap().lock().unwrap();
+        assert_eq!(pmem0.guest_address(), start);
+        assert_eq!(pmem1.guest_address(), start.unchecked_add(PMEM_ALIGNMENT));
+        // Check if the devices are described in kernel_cmdline.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert!(cmdline
+            .as_str()
+            .contains("virtio_mmio.device=4K@0xd0001000:6"));
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-mem")]
+    fn test_create_hotplug_memory() {
+        use vm_memory::{Address, GuestMemory};
+
+        let boot_memory = create_guest_memory(
+            128,
+            false,
+            MemoryBackend::Anonymous,
+            &arch::MmioLayout::default(),
+        )
+        .unwrap();
+        let (guest_memory, virtio_mem) =
+            create_hotplug_memory(boot_memory.clone(), 256 << 20, 2 << 20, false).unwrap();
+
+        let region_addr = arch::hotplug_memory_start(boot_memory.last_addr());
+        assert_eq!(guest_memory.num_regions(), boot_memory.num_regions() + 1);
+        assert_eq!(
+            guest_memory.last_addr(),
+            region_addr.unchecked_add((256 << 20) - 1)
+        );
+        let virtio_mem = virtio_mem.lock().unwrap();
+        assert_eq!(virtio_mem.region_addr(), region_addr);
+        assert_eq!(virtio_mem.region_size(), 256 << 20);
+        assert_eq!(virtio_mem.plugged_size(), 0);
+
+        // The block size must be a power of two.
+        match create_hotplug_memory(boot_memory, 256 << 20, 3 << 20, false) {
+            Err(StartMicrovmError::CreateVirtioMem(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-mem")]
+    fn test_attach_virtio_mem_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let config = MemoryHotplugConfig {
+            total_size_mib: 128,
+            block_size_mib: 2,
+        };
+
+        let mut cmdline = default_kernel_cmdline();
+        insert_virtio_mem_device(&mut vmm, &mut cmdline, &mut event_manager, config);
+        // Check if the virtio-mem device is described in kernel_cmdline.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert!(cmdline
+            .as_str()
+            .contains("virtio_mmio.device=4K@0xd0000000:5"));
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-rng")]
+    fn test_attach_entropy_device() {
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let mut cmdline = default_kernel_cmdline();
+        insert_entropy_device(
+            &mut vmm,
+            &mut cmdline,
+            &mut event_manager,
+            EntropyDeviceConfig::default(),
+        );
+        // Check if the entropy device is described in kernel_cmdline.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert!(cmdline
+            .as_str()
+            .contains("virtio_mmio.device=4K@0xd0000000:5"));
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-console")]
+    fn test_attach_console_device() {
+        use crate::vmm_config::console::tests::{socket_path, uds_port};
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let mut vmm = default_vmm();
+
+        let mut cmdline = default_kernel_cmdline();
+        let config = ConsoleConfig {
+            ports: vec![
+                uds_port("console", &socket_path(), true),
+                uds_port("agent", &socket_path(), false),
+            ],
+        };
+        insert_console_device(&mut vmm, &mut cmdline, &mut event_manager, config);
+        // Check if the console device is described in kernel_cmdline.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        assert!(cmdline
+            .as_str()
+            .contains("virtio_mmio.device=4K@0xd0000000:5"));
+    }
+
+    #[test]
+    #[cfg(feature = "vsock")]
     fn test_attach_vsock_device() {
         let mut event_manager = EventManager::new().expect("Unable to create EventManager");
         let mut vmm = default_vmm();
@@ -1296,26 +3302,65 @@ pub mod tests {
     #[test]
     fn test_error_messages() {
         use crate::builder::StartMicrovmError::*;
+        let err = AdviseGuestMemory(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = AttachBlockDevice(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
-        let err = CreateNetDevice(devices::virtio::net::Error::EventFd(
-            io::Error::from_raw_os_error(0),
-        ));
+        #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+        {
+            let err = AttachVfioDevice(
+                String::from("nic"),
+                device_manager::mmio::Error::Vfio(devices::vfio::Error::NoMsix),
+            );
+            let _ = format!("{}{:?}", err, err);
+        }
+
+        let err = CreateSharedMemory(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        #[cfg(feature = "net")]
+        {
+            let err = CreateNetDevice(devices::virtio::net::Error::EventFd(
+                io::Error::from_raw_os_error(0),
+            ));
+            let _ = format!("{}{:?}", err, err);
+        }
+
         let err = CreateRateLimiter(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+        {
+            let err = CreateTpm(devices::tpm::Error::InvalidState);
+            let _ = format!("{}{:?}", err, err);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let err = CreateWatchdog(WatchdogError::Timer(io::Error::from_raw_os_error(0)));
+            let _ = format!("{}{:?}", err, err);
+
+            let err = FirmwareLoad(io::Error::from_raw_os_error(0));
+            let _ = format!("{}{:?}", err, err);
+        }
+
         let err = Internal(Error::Serial(io::Error::from_raw_os_error(0)));
         let _ = format!("{}{:?}", err, err);
 
+        let err = IoWorker(WorkerError::Stopped);
+        let _ = format!("{}{:?}", err, err);
+
         let err = KernelCmdline(String::from("dummy --cmdline"));
         let _ = format!("{}{:?}", err, err);
 
         let err = KernelLoader(kernel::loader::Error::InvalidElfMagicNumber);
         let _ = format!("{}{:?}", err, err);
 
+        let err = Landlock(crate::landlock::Error::Unsupported);
+        let _ = format!("{}{:?}", err, err);
+
         let err = LoadCommandline(kernel::cmdline::Error::TooLarge);
         let _ = format!("{}{:?}", err, err);
 
@@ -1331,6 +3376,9 @@ pub mod tests {
         let err = OpenBlockDevice(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = OpenSerialPort(String::from("com2"), io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = RegisterEvent(EventManagerError::EpollCreate(
             io::Error::from_raw_os_error(0),
         ));
@@ -0,0 +1,384 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writes the guest memory and the registers of the vCPUs to an ELF core file, which `crash`
+//! and `gdb` can analyze offline.
+//!
+//! The file follows the layout of the kdump and QEMU guest dumps: a `PT_NOTE` segment holds an
+//! `NT_PRSTATUS` note per vCPU, and a `PT_LOAD` segment per guest memory region maps its
+//! guest physical addresses to the file.
+
+// Currently only supported on x86_64.
+#![cfg(target_arch = "x86_64")]
+
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use kvm_bindings::{kvm_regs, kvm_sregs};
+use polly::worker::Error as WorkerError;
+use vm_memory::GuestMemoryMmap;
+
+use crate::memory_snapshot::{self, SnapshotMemory};
+use crate::persist::MicrovmStateError;
+use crate::{Error as VmmError, Vmm};
+
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NOTE_NAME: &[u8] = b"CORE\0";
+
+const ELF_HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+// Size of `struct elf_prstatus`, and offsets of its `pr_pid` and `pr_reg` fields.
+const PRSTATUS_SIZE: usize = 336;
+const PRSTATUS_PID_OFFSET: usize = 32;
+const PRSTATUS_REGS_OFFSET: usize = 112;
+// The guest memory starts at a page boundary of the file.
+const MEMORY_ALIGNMENT: u64 = 4096;
+
+/// Errors associated with creating a core dump.
+#[derive(Debug)]
+pub enum CoreDumpError {
+    /// Failed to write the core file.
+    CoreFile(io::Error),
+    /// Failed to write the guest memory to the core file.
+    Memory(memory_snapshot::Error),
+    /// Failed to pause the threads processing the I/O of the devices.
+    PauseIoWorkers(WorkerError),
+    /// Failed to pause the vCPUs.
+    PauseVm(VmmError),
+    /// Failed to resume the vCPUs.
+    ResumeVm(VmmError),
+    /// Failed to retrieve the registers of the vCPUs.
+    VcpuState(MicrovmStateError),
+}
+
+impl Display for CoreDumpError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::CoreDumpError::*;
+        match self {
+            CoreFile(err) => write!(f, "Cannot write the core file: {}", err),
+            Memory(err) => write!(f, "Cannot write the guest memory: {}", err),
+            PauseIoWorkers(err) => write!(f, "Cannot pause the I/O worker threads: {:?}", err),
+            PauseVm(err) => write!(f, "Cannot pause the microVM: {}", err),
+            ResumeVm(err) => write!(f, "Cannot resume the microVM: {}", err),
+            VcpuState(err) => write!(f, "Cannot save the vCPU registers: {}", err),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, CoreDumpError>;
+
+/// Writes the guest memory and the registers of the vCPUs to an ELF core file at `path`. The
+/// microVM is paused while the file is written, and resumed afterwards unless it was already
+/// paused.
+pub fn create_core_dump(vmm: &mut Vmm, path: &Path) -> Result<()> {
+    let was_paused = vmm.is_paused();
+    if !was_paused {
+        vmm.pause_vm().map_err(CoreDumpError::PauseVm)?;
+    }
+    // Neither the guest memory nor the devices may change while the core file is written.
+    let result = match vmm.pause_io_workers() {
+        Ok(_paused) => dump_to_file(vmm, path),
+        Err(err) => Err(CoreDumpError::PauseIoWorkers(err)),
+    };
+    if !was_paused {
+        vmm.resume_vm().map_err(CoreDumpError::ResumeVm)?;
+    }
+    result
+}
+
+fn dump_to_file(vmm: &mut Vmm, path: &Path) -> Result<()> {
+    let registers = vmm
+        .save_vcpu_states()
+        .map_err(CoreDumpError::VcpuState)?
+        .iter()
+        .map(|state| (*state.regs(), *state.sregs()))
+        .collect::<Vec<_>>();
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(CoreDumpError::CoreFile)?;
+    write_core_file(&file, vmm.guest_memory(), &registers)
+}
+
+// Writes the headers, the notes holding `registers` and then the guest memory to `file`.
+fn write_core_file(
+    mut file: &File,
+    guest_memory: &GuestMemoryMmap,
+    registers: &[(kvm_regs, kvm_sregs)],
+) -> Result<()> {
+    let memory_state = guest_memory.describe();
+    let notes = registers
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (regs, sregs))| note(NT_PRSTATUS, &prstatus(index, regs, sregs)))
+        .collect::<Vec<u8>>();
+
+    let phnum = 1 + memory_state.regions.len() as u64;
+    let notes_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+    let memory_offset = align_up(notes_offset + notes.len() as u64, MEMORY_ALIGNMENT);
+
+    let mut headers = elf_header(phnum as u16);
+    headers.extend(program_header(
+        PT_NOTE,
+        0,
+        notes_offset,
+        0,
+        notes.len() as u64,
+    ));
+    for region in memory_state.regions.iter() {
+        headers.extend(program_header(
+            PT_LOAD,
+            PF_R | PF_W | PF_X,
+            memory_offset + region.offset,
+            region.base_address,
+            region.size as u64,
+        ));
+    }
+    headers.extend(notes);
+
+    file.write_all(&headers).map_err(CoreDumpError::CoreFile)?;
+    // The zero pages are left as holes in the file.
+    guest_memory
+        .dump_at(file, &memory_state, memory_offset)
+        .map_err(CoreDumpError::Memory)?;
+    Ok(())
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) / alignment * alignment
+}
+
+fn elf_header(phnum: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(ELF_HEADER_SIZE as usize);
+    header.extend_from_slice(b"\x7fELF");
+    header.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, EV_CURRENT]);
+    // The OS ABI, its version and the padding of `e_ident`.
+    header.resize(16, 0);
+    header.extend_from_slice(&ET_CORE.to_le_bytes());
+    header.extend_from_slice(&EM_X86_64.to_le_bytes());
+    header.extend_from_slice(&u32::from(EV_CURRENT).to_le_bytes());
+    // The entry point.
+    header.extend_from_slice(&0u64.to_le_bytes());
+    // The program headers follow the ELF header, and there are no section headers.
+    header.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&0u64.to_le_bytes());
+    // The flags.
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&phnum.to_le_bytes());
+    // The size and the number of the section headers, and the index of their string table.
+    header.resize(ELF_HEADER_SIZE as usize, 0);
+    header
+}
+
+// The guest physical address of a segment is its `p_paddr`, while its `p_vaddr` is left to 0,
+// as in the dumps of guests whose paging is not walked.
+fn program_header(p_type: u32, p_flags: u32, offset: u64, paddr: u64, size: u64) -> Vec<u8> {
+    let mut header = Vec::with_capacity(PROGRAM_HEADER_SIZE as usize);
+    header.extend_from_slice(&p_type.to_le_bytes());
+    header.extend_from_slice(&p_flags.to_le_bytes());
+    header.extend_from_slice(&offset.to_le_bytes());
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&paddr.to_le_bytes());
+    // The file size and the memory size of the segment.
+    header.extend_from_slice(&size.to_le_bytes());
+    header.extend_from_slice(&size.to_le_bytes());
+    // The alignment.
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header
+}
+
+fn note(n_type: u32, desc: &[u8]) -> Vec<u8> {
+    let mut note = Vec::new();
+    note.extend_from_slice(&(NOTE_NAME.len() as u32).to_le_bytes());
+    note.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    note.extend_from_slice(&n_type.to_le_bytes());
+    // The name and the descriptor are both padded to 4 bytes.
+    note.extend_from_slice(NOTE_NAME);
+    note.resize(align_up(note.len() as u64, 4) as usize, 0);
+    note.extend_from_slice(desc);
+    note.resize(align_up(note.len() as u64, 4) as usize, 0);
+    note
+}
+
+// The `struct elf_prstatus` of the vCPU with the given index, which the analysis tools see as
+// the thread with the ID `index + 1`.
+fn prstatus(index: usize, regs: &kvm_regs, sregs: &kvm_sregs) -> Vec<u8> {
+    let mut prstatus = vec![0u8; PRSTATUS_SIZE];
+    prstatus[PRSTATUS_PID_OFFSET..PRSTATUS_PID_OFFSET + 4]
+        .copy_from_slice(&(index as u32 + 1).to_le_bytes());
+
+    // The registers, in the order of `struct user_regs_struct`.
+    let user_regs = [
+        regs.r15,
+        regs.r14,
+        regs.r13,
+        regs.r12,
+        regs.rbp,
+        regs.rbx,
+        regs.r11,
+        regs.r10,
+        regs.r9,
+        regs.r8,
+        regs.rax,
+        regs.rcx,
+        regs.rdx,
+        regs.rsi,
+        regs.rdi,
+        // The vCPUs are not interrupted in a system call.
+        u64::MAX,
+        regs.rip,
+        u64::from(sregs.cs.selector),
+        regs.rflags,
+        regs.rsp,
+        u64::from(sregs.ss.selector),
+        sregs.fs.base,
+        sregs.gs.base,
+        u64::from(sregs.ds.selector),
+        u64::from(sregs.es.selector),
+        u64::from(sregs.fs.selector),
+        u64::from(sregs.gs.selector),
+    ];
+    for (position, reg) in user_regs.iter().enumerate() {
+        let offset = PRSTATUS_REGS_OFFSET + position * 8;
+        prstatus[offset..offset + 8].copy_from_slice(&reg.to_le_bytes());
+    }
+    prstatus
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::io::{Read, Seek, SeekFrom};
+
+    use super::*;
+    use utils::tempfile::TempFile;
+    use vm_memory::{Bytes, GuestAddress};
+
+    fn read_u16(buf: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u32(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(buf: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_note() {
+        let note = note(NT_PRSTATUS, &[1, 2, 3, 4, 5]);
+        assert_eq!(read_u32(&note, 0), 5);
+        assert_eq!(read_u32(&note, 4), 5);
+        assert_eq!(read_u32(&note, 8), NT_PRSTATUS);
+        assert_eq!(&note[12..17], b"CORE\0");
+        // Both the name and the descriptor are padded.
+        assert_eq!(&note[20..25], &[1, 2, 3, 4, 5]);
+        assert_eq!(note.len(), 28);
+    }
+
+    #[test]
+    fn test_prstatus() {
+        let regs = kvm_regs {
+            rax: 0xa,
+            rip: 0xffff_ffff_8100_0000,
+            rsp: 0x1000,
+            ..Default::default()
+        };
+        let mut sregs = kvm_sregs::default();
+        sregs.cs.selector = 0x10;
+        sregs.gs.base = 0xffff_8880_0000_0000;
+
+        let prstatus = prstatus(1, &regs, &sregs);
+        assert_eq!(prstatus.len(), PRSTATUS_SIZE);
+        assert_eq!(read_u32(&prstatus, PRSTATUS_PID_OFFSET), 2);
+        let reg = |index: usize| read_u64(&prstatus, PRSTATUS_REGS_OFFSET + index * 8);
+        assert_eq!(reg(10), 0xa);
+        assert_eq!(reg(15), u64::MAX);
+        assert_eq!(reg(16), 0xffff_ffff_8100_0000);
+        assert_eq!(reg(17), 0x10);
+        assert_eq!(reg(19), 0x1000);
+        assert_eq!(reg(22), 0xffff_8880_0000_0000);
+    }
+
+    #[test]
+    fn test_write_core_file() {
+        let page_size = 0x1000;
+        let guest_memory = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), page_size * 2),
+            (GuestAddress(0x10_0000), page_size),
+        ])
+        .unwrap();
+        guest_memory
+            .write(b"first", GuestAddress(page_size as u64))
+            .unwrap();
+        guest_memory
+            .write(b"second", GuestAddress(0x10_0000))
+            .unwrap();
+        let registers = vec![(kvm_regs::default(), kvm_sregs::default()); 2];
+
+        let core_file = TempFile::new().unwrap();
+        let mut file = core_file.as_file();
+        write_core_file(file, &guest_memory, &registers).unwrap();
+        let mut core = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut core).unwrap();
+
+        assert_eq!(&core[..4], b"\x7fELF");
+        assert_eq!(read_u16(&core, 16), ET_CORE);
+        assert_eq!(read_u16(&core, 18), EM_X86_64);
+        assert_eq!(read_u16(&core, 56), 3);
+
+        // The notes hold a `NT_PRSTATUS` note per vCPU.
+        let phdr = |index: usize| 64 + index * 56;
+        assert_eq!(read_u32(&core, phdr(0)), PT_NOTE);
+        let notes_offset = read_u64(&core, phdr(0) + 8) as usize;
+        let notes_size = read_u64(&core, phdr(0) + 32) as usize;
+        assert_eq!(notes_size, 2 * (12 + 8 + PRSTATUS_SIZE));
+        assert_eq!(read_u32(&core, notes_offset + 8), NT_PRSTATUS);
+
+        // Each region is loaded at its guest physical address, from a page aligned offset.
+        assert_eq!(read_u32(&core, phdr(1)), PT_LOAD);
+        assert_eq!(read_u64(&core, phdr(1) + 24), 0);
+        assert_eq!(read_u64(&core, phdr(1) + 32), page_size as u64 * 2);
+        let first_offset = read_u64(&core, phdr(1) + 8) as usize;
+        assert_eq!(first_offset % 4096, 0);
+        assert_eq!(&core[first_offset + page_size..][..5], b"first");
+        assert_eq!(read_u32(&core, phdr(2)), PT_LOAD);
+        assert_eq!(read_u64(&core, phdr(2) + 24), 0x10_0000);
+        let second_offset = read_u64(&core, phdr(2) + 8) as usize;
+        assert_eq!(second_offset, first_offset + page_size * 2);
+        assert_eq!(&core[second_offset..][..6], b"second");
+        assert_eq!(core.len(), second_offset + page_size);
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use super::CoreDumpError::*;
+        let err = CoreFile(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+        let err = PauseVm(VmmError::VcpuPause);
+        let _ = format!("{}{:?}", err, err);
+        let err = ResumeVm(VmmError::VcpuResume);
+        let _ = format!("{}{:?}", err, err);
+        let err = VcpuState(MicrovmStateError::UnexpectedVcpuResponse);
+        let _ = format!("{}{:?}", err, err);
+    }
+}
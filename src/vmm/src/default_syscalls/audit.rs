@@ -0,0 +1,254 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::io::FromRawFd;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use logger::{error, warn, IncMetric, METRICS};
+use seccomp::{BpfProgram, Error, SeccompAction, SeccompFilter};
+use seccompiler::syscall_name;
+use utils::ioctl::{ioctl_with_mut_ref, ioctl_with_ref};
+use utils::{ioctl_expr, ioctl_ioc_nr};
+
+// See include/uapi/linux/seccomp.h in the kernel code.
+const SECCOMP_IOC_MAGIC: u32 = 0x21;
+const SECCOMP_USER_NOTIF_FLAG_CONTINUE: u32 = 1;
+
+#[repr(C)]
+#[derive(Default)]
+struct SeccompData {
+    pub nr: i32,
+    pub arch: u32,
+    pub instruction_pointer: u64,
+    pub args: [u64; 6],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SeccompNotif {
+    pub id: u64,
+    pub pid: u32,
+    pub flags: u32,
+    pub data: SeccompData,
+}
+
+#[repr(C)]
+struct SeccompNotifResp {
+    pub id: u64,
+    pub val: i64,
+    pub error: i32,
+    pub flags: u32,
+}
+
+ioctl_ioc_nr!(
+    SECCOMP_IOCTL_NOTIF_RECV,
+    utils::ioctl::_IOC_READ | utils::ioctl::_IOC_WRITE,
+    SECCOMP_IOC_MAGIC,
+    0,
+    mem::size_of::<SeccompNotif>() as u32
+);
+ioctl_ioc_nr!(
+    SECCOMP_IOCTL_NOTIF_SEND,
+    utils::ioctl::_IOC_READ | utils::ioctl::_IOC_WRITE,
+    SECCOMP_IOC_MAGIC,
+    1,
+    mem::size_of::<SeccompNotifResp>() as u32
+);
+
+/// Errors associated with the seccomp audit mode.
+#[derive(Debug)]
+pub enum AuditError {
+    /// Cannot build or load the audited seccomp filter.
+    Filter(Error),
+    /// Cannot spawn the thread recording the audited syscalls.
+    Spawn(io::Error),
+}
+
+impl Display for AuditError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::AuditError::*;
+        match self {
+            Filter(err) => write!(f, "Cannot load the audited seccomp filter: {}", err),
+            Spawn(err) => write!(f, "Cannot spawn the seccomp audit thread: {}", err),
+        }
+    }
+}
+
+// Describes the audited syscall as a list of `key=value` fields.
+fn audit_entry(notif: &SeccompNotif) -> String {
+    let args = notif
+        .data
+        .args
+        .iter()
+        .map(|arg| format!("{:#x}", arg))
+        .collect::<Vec<String>>()
+        .join(",");
+    format!(
+        "syscall={} number={} tid={} args=[{}]",
+        syscall_name(i64::from(notif.data.nr)).unwrap_or("unknown"),
+        notif.data.nr,
+        notif.pid,
+        args
+    )
+}
+
+// Records the syscalls the filter does not allow, then lets the kernel carry them out. Closing
+// the listener makes the audited syscalls fail with `ENOSYS` instead of blocking.
+fn run_auditor(listener: File) {
+    loop {
+        // The kernel requires the notification to be zeroed.
+        let mut notif = SeccompNotif::default();
+        // Safe because the listener is valid, and we check the result.
+        let ret = unsafe { ioctl_with_mut_ref(&listener, SECCOMP_IOCTL_NOTIF_RECV(), &mut notif) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                // The audited thread was interrupted, or exited.
+                Some(libc::EINTR) | Some(libc::ENOENT) => continue,
+                _ => {
+                    error!("Cannot receive the audited seccomp notification: {}", err);
+                    return;
+                }
+            }
+        }
+
+        METRICS.seccomp.num_audited.inc();
+        warn!("Seccomp audit: {}", audit_entry(&notif));
+
+        let resp = SeccompNotifResp {
+            id: notif.id,
+            val: 0,
+            error: 0,
+            flags: SECCOMP_USER_NOTIF_FLAG_CONTINUE,
+        };
+        // Safe because the listener and the response are valid, and we check the result.
+        let ret = unsafe { ioctl_with_ref(&listener, SECCOMP_IOCTL_NOTIF_SEND(), &resp) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            // The audited thread may have been interrupted in the meantime.
+            if err.raw_os_error() != Some(libc::ENOENT) {
+                error!("Cannot resume the audited syscall: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+/// Loads `filter` on the calling thread, and on the threads it spawns from then on, in audit
+/// mode: the syscalls the filter would deny are recorded in the metrics and in the log, then
+/// carried out. Requires a 5.5 or newer host kernel.
+pub fn start_seccomp_audit(filter: SeccompFilter) -> Result<(), AuditError> {
+    let program: BpfProgram = filter
+        .with_default_action(SeccompAction::UserNotif)
+        .try_into()
+        .map_err(AuditError::Filter)?;
+
+    let (sender, receiver) = channel();
+    // The auditor is spawned before the filter is loaded, so that it is not filtered itself.
+    thread::Builder::new()
+        .name("fc_seccomp_audit".to_owned())
+        .spawn(move || {
+            if let Ok(listener) = receiver.recv() {
+                run_auditor(listener);
+            }
+        })
+        .map_err(AuditError::Spawn)?;
+
+    let listener_fd = SeccompFilter::apply_with_listener(program).map_err(AuditError::Filter)?;
+    // Safe because the listener fd is valid, and only owned by the file.
+    let listener = unsafe { File::from_raw_fd(listener_fd) };
+    sender
+        .send(listener)
+        .expect("The seccomp audit thread exited.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use seccomp::allow_syscall;
+
+    #[test]
+    fn test_ioctl_numbers() {
+        assert_eq!(mem::size_of::<SeccompNotif>(), 80);
+        assert_eq!(mem::size_of::<SeccompNotifResp>(), 24);
+        assert_eq!(SECCOMP_IOCTL_NOTIF_RECV() as u64, 0xc050_2100);
+        assert_eq!(SECCOMP_IOCTL_NOTIF_SEND() as u64, 0xc018_2101);
+    }
+
+    #[test]
+    fn test_audit_entry() {
+        let mut notif = SeccompNotif::default();
+        notif.pid = 42;
+        notif.data.nr = libc::SYS_read as i32;
+        notif.data.args[0] = 3;
+        notif.data.args[2] = 4096;
+        assert_eq!(
+            audit_entry(&notif),
+            "syscall=read number=".to_string()
+                + &libc::SYS_read.to_string()
+                + " tid=42 args=[0x3,0x0,0x1000,0x0,0x0,0x0]"
+        );
+
+        notif.data.nr = -1;
+        assert!(audit_entry(&notif).starts_with("syscall=unknown number=-1 "));
+    }
+
+    #[test]
+    fn test_start_seccomp_audit() {
+        let mut rules = BTreeMap::new();
+        let (syscall, rule) = allow_syscall(libc::SYS_getpid);
+        rules.insert(syscall, rule);
+        let filter = SeccompFilter::new(rules, SeccompAction::Trap).unwrap();
+
+        // The filter is loaded in another thread, to leave the test process unrestricted.
+        thread::spawn(move || {
+            match start_seccomp_audit(filter) {
+                // There is nothing to check on hosts without seccomp user notifications.
+                Err(AuditError::Filter(Error::Load(_))) => return,
+                result => result.unwrap(),
+            }
+            let audited = METRICS.seccomp.num_audited.count();
+            // Safe because getppid takes no arguments. It is not allowed, but still carried out.
+            let ret = unsafe { libc::syscall(libc::SYS_getppid) };
+            assert_eq!(ret, i64::from(unsafe { libc::getppid() }));
+            assert!(METRICS.seccomp.num_audited.count() >= audited + 2);
+        })
+        .join()
+        .unwrap();
+
+        let filter = SeccompFilter::new(BTreeMap::new(), SeccompAction::Trap).unwrap();
+        match start_seccomp_audit(filter) {
+            Err(AuditError::Filter(Error::EmptyRulesVector)) => (),
+            _ => panic!("An empty filter should not be audited"),
+        }
+    }
+
+    #[test]
+    fn test_audit_error_display() {
+        assert_eq!(
+            format!("{}", AuditError::Filter(Error::Load(38))),
+            "Cannot load the audited seccomp filter: Failed to load seccomp rules into the kernel \
+             with error 38."
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                AuditError::Spawn(io::Error::from_raw_os_error(libc::EAGAIN))
+            ),
+            format!(
+                "Cannot spawn the seccomp audit thread: {}",
+                io::Error::from_raw_os_error(libc::EAGAIN)
+            )
+        );
+    }
+}
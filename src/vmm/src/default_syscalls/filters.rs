@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 use std::convert::TryInto;
 
+use polly::event_manager::Backend;
 use seccomp::{
     allow_syscall, allow_syscall_if, BpfProgram, Error, SeccompAction, SeccompCmpArgLen as ArgLen,
     SeccompCmpOp::Eq, SeccompCondition as Cond, SeccompError, SeccompFilter, SeccompLevel,
@@ -16,7 +17,8 @@ use utils::signal::sigrtmin;
 pub fn default_filter() -> Result<SeccompFilter, Error> {
     Ok(SeccompFilter::new(
         vec![
-            // Called by the api thread to receive data on socket
+            // Called by the api thread, the metrics listener and the events listener to accept
+            // connections on their unix, TCP or vsock sockets
             allow_syscall_if(
                 libc::SYS_accept4,
                 or![and![Cond::new(
@@ -28,16 +30,27 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             ),
             // Called for expanding the heap
             allow_syscall(libc::SYS_brk),
-            // Used for metrics, via the helpers in utils/src/time.rs
+            // Used for metrics and the machine statistics, via the helpers in utils/src/time.rs
             allow_syscall_if(
                 libc::SYS_clock_gettime,
-                or![and![Cond::new(
-                    0,
-                    ArgLen::DWORD,
-                    Eq,
-                    libc::CLOCK_PROCESS_CPUTIME_ID as u64
-                )?],],
+                or![
+                    and![Cond::new(
+                        0,
+                        ArgLen::DWORD,
+                        Eq,
+                        libc::CLOCK_PROCESS_CPUTIME_ID as u64
+                    )?],
+                    and![Cond::new(
+                        0,
+                        ArgLen::DWORD,
+                        Eq,
+                        libc::CLOCK_THREAD_CPUTIME_ID as u64
+                    )?],
+                ],
             ),
+            // Used to throttle the vCPUs which used up their CPU quota, on gnu
+            #[cfg(target_env = "gnu")]
+            allow_syscall(libc::SYS_clock_nanosleep),
             allow_syscall(libc::SYS_close),
             // Needed for vsock
             allow_syscall(libc::SYS_connect),
@@ -57,7 +70,10 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             ),
             // Used for drive patching & rescanning, for reading the local timezone
             allow_syscall(libc::SYS_fstat),
-            // Used for snapshotting
+            // Used by the persistent memory devices to flush the guest writes to their files, and
+            // by the software TPM to flush its state file
+            allow_syscall(libc::SYS_fsync),
+            // Used for snapshotting, and by the software TPM to store its state file
             #[cfg(target_arch = "x86_64")]
             allow_syscall(libc::SYS_ftruncate),
             // Used for synchronization
@@ -75,11 +91,27 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                     )?],
                 ],
             ),
+            // Used for listing the threads and the open file descriptors in the machine
+            // statistics
+            allow_syscall(libc::SYS_getdents64),
             // Used by glibc's tgkill
             #[cfg(target_env = "gnu")]
             allow_syscall(libc::SYS_getpid),
+            // Used by the MMDS for generating session tokens and by the entropy device
+            allow_syscall_if(
+                libc::SYS_getrandom,
+                or![and![Cond::new(2, ArgLen::DWORD, Eq, 0u64)?],],
+            ),
+            // Used by the API thread to identify the clients of its unix socket
+            allow_syscall_if(
+                libc::SYS_getsockopt,
+                or![and![
+                    Cond::new(1, ArgLen::DWORD, Eq, libc::SOL_SOCKET as u64)?,
+                    Cond::new(2, ArgLen::DWORD, Eq, libc::SO_PEERCRED as u64)?,
+                ],],
+            ),
             allow_syscall_if(libc::SYS_ioctl, super::create_ioctl_seccomp_rule()?),
-            // Used by the block device
+            // Used by the block device and the software TPM
             allow_syscall(libc::SYS_lseek),
             // Triggered by musl for some customer workloads
             #[cfg(target_env = "musl")]
@@ -92,10 +124,14 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
                     libc::MADV_DONTNEED as u64
                 )?],],
             ),
+            // Used for counting the resident guest memory pages in the machine statistics
+            allow_syscall(libc::SYS_mincore),
             // Used for re-allocating large memory regions, for example vectors
             allow_syscall(libc::SYS_mremap),
             // Used for freeing memory
             allow_syscall(libc::SYS_munmap),
+            // Used to throttle the vCPUs which used up their CPU quota
+            allow_syscall(libc::SYS_nanosleep),
             // Used for reading the timezone in LocalTime::now()
             allow_syscall_if(
                 libc::SYS_mmap,
@@ -113,15 +149,87 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
             allow_syscall(libc::SYS_open),
             #[cfg(target_arch = "aarch64")]
             allow_syscall(libc::SYS_openat),
+            // Used to access the configuration space and the BARs of the PCI functions passed
+            // through with VFIO
+            #[cfg(feature = "vfio")]
+            allow_syscall(libc::SYS_pread64),
+            #[cfg(feature = "vfio")]
+            allow_syscall(libc::SYS_pwrite64),
             allow_syscall(libc::SYS_read),
-            // Used by the API thread and vsock
+            // Used by the API thread, the metrics listener, vsock and the serial ports bound to a
+            // socket
             allow_syscall(libc::SYS_recvfrom),
             // SYS_rt_sigreturn is needed in case a fault does occur, so that the signal handler
             // can return. Otherwise we get stuck in a fault loop.
             allow_syscall(libc::SYS_rt_sigreturn),
-            // Used by the API thread and vsock
+            // Used by the shared filesystems to pass file descriptors to their vhost-user
+            // backends
+            allow_syscall(libc::SYS_sendmsg),
+            // Used by the API thread and the metrics listener to send the responses over TCP, to
+            // stream the lifecycle events, to notify systemd and to push the metrics to the sinks
+            allow_syscall_if(
+                libc::SYS_sendto,
+                or![and![Cond::new(
+                    3,
+                    ArgLen::DWORD,
+                    Eq,
+                    libc::MSG_NOSIGNAL as u64
+                )?],],
+            ),
+            // Used by the API thread to time out the guest agent commands
+            allow_syscall_if(
+                libc::SYS_setsockopt,
+                or![
+                    and![
+                        Cond::new(1, ArgLen::DWORD, Eq, libc::SOL_SOCKET as u64)?,
+                        Cond::new(2, ArgLen::DWORD, Eq, libc::SO_RCVTIMEO as u64)?,
+                    ],
+                    and![
+                        Cond::new(1, ArgLen::DWORD, Eq, libc::SOL_SOCKET as u64)?,
+                        Cond::new(2, ArgLen::DWORD, Eq, libc::SO_SNDTIMEO as u64)?,
+                    ],
+                ],
+            ),
+            // Used by the API thread, vsock, the serial ports bound to a socket and the metrics
+            // sinks
             allow_syscall_if(
                 libc::SYS_socket,
+                or![
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?,
+                        Cond::new(
+                            1,
+                            ArgLen::DWORD,
+                            Eq,
+                            (libc::SOCK_STREAM as u64) | (libc::SOCK_CLOEXEC as u64)
+                        )?,
+                        Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
+                    ],
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_INET as u64)?,
+                        Cond::new(
+                            1,
+                            ArgLen::DWORD,
+                            Eq,
+                            (libc::SOCK_DGRAM as u64) | (libc::SOCK_CLOEXEC as u64)
+                        )?,
+                        Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
+                    ],
+                    and![
+                        Cond::new(0, ArgLen::DWORD, Eq, libc::AF_INET6 as u64)?,
+                        Cond::new(
+                            1,
+                            ArgLen::DWORD,
+                            Eq,
+                            (libc::SOCK_DGRAM as u64) | (libc::SOCK_CLOEXEC as u64)
+                        )?,
+                        Cond::new(2, ArgLen::DWORD, Eq, 0u64)?
+                    ],
+                ],
+            ),
+            // Used by vsock, for serving the MMDS and for connecting the guest agent
+            allow_syscall_if(
+                libc::SYS_socketpair,
                 or![and![
                     Cond::new(0, ArgLen::DWORD, Eq, libc::AF_UNIX as u64)?,
                     Cond::new(
@@ -172,24 +280,274 @@ pub fn default_filter() -> Result<SeccompFilter, Error> {
     )?)
 }
 
-/// Generate a BPF program based on a seccomp level value.
-pub fn get_seccomp_filter(seccomp_level: SeccompLevel) -> Result<BpfProgram, SeccompError> {
+/// The filter the operator opts into to tear down microVMs: the default filter, along with the
+/// syscalls the VMM thread calls to build another microVM once the previous one was torn down.
+/// All the threads share the filter, so it allows them to create VMs, map memory and spawn
+/// threads as well.
+pub fn teardown_filter() -> Result<SeccompFilter, Error> {
+    let mut filter = default_filter()?;
+    let rules = vec![
+        // Used by the metrics sinks, to bind their UDP socket
+        allow_syscall(libc::SYS_bind),
+        // Used by the VMM thread to spawn the vCPU threads and the I/O workers
+        allow_syscall_if(
+            libc::SYS_clone,
+            or![and![Cond::new(0, ArgLen::DWORD, Eq, super::CLONE_FLAGS)?],],
+        ),
+        // Tried first by glibc to spawn threads. Its flags cannot be filtered, so it fails for
+        // glibc to fall back to the filtered clone
+        #[cfg(target_env = "gnu")]
+        (
+            libc::SYS_clone3,
+            vec![SeccompRule::new(
+                vec![],
+                SeccompAction::Errno(libc::ENOSYS as u32),
+            )],
+        ),
+        // Used to create the event managers of the I/O workers
+        allow_syscall_if(
+            libc::SYS_epoll_create1,
+            or![and![Cond::new(
+                0,
+                ArgLen::DWORD,
+                Eq,
+                libc::EPOLL_CLOEXEC as u64
+            )?],],
+        ),
+        // Used to create the events of the devices
+        allow_syscall(libc::SYS_eventfd2),
+        allow_syscall_if(libc::SYS_ioctl, super::create_rebuild_ioctl_seccomp_rule()?),
+        // Used to apply the memory hints to the guest memory
+        allow_syscall_if(
+            libc::SYS_madvise,
+            or![
+                and![Cond::new(
+                    2,
+                    ArgLen::DWORD,
+                    Eq,
+                    libc::MADV_MERGEABLE as u64
+                )?],
+                and![Cond::new(2, ArgLen::DWORD, Eq, libc::MADV_HUGEPAGE as u64)?],
+                and![Cond::new(
+                    2,
+                    ArgLen::DWORD,
+                    Eq,
+                    libc::MADV_NOHUGEPAGE as u64
+                )?],
+            ],
+        ),
+        // Used to back the guest memory with a memfd, of regular or huge pages
+        allow_syscall_if(
+            libc::SYS_memfd_create,
+            or![
+                and![Cond::new(1, ArgLen::DWORD, Eq, libc::MFD_CLOEXEC as u64)?],
+                and![Cond::new(1, ArgLen::DWORD, Eq, super::MFD_HUGE_2MB_FLAGS)?],
+                and![Cond::new(1, ArgLen::DWORD, Eq, super::MFD_HUGE_1GB_FLAGS)?],
+            ],
+        ),
+        // Used by the C library to set the guard pages of the threads it spawns, and to make the
+        // rest of their stack writable. The pages are never made executable.
+        allow_syscall_if(
+            libc::SYS_mprotect,
+            or![
+                and![Cond::new(2, ArgLen::DWORD, Eq, libc::PROT_NONE as u64)?],
+                and![Cond::new(
+                    2,
+                    ArgLen::DWORD,
+                    Eq,
+                    (libc::PROT_READ | libc::PROT_WRITE) as u64
+                )?],
+            ],
+        ),
+        // Used to map the guest memory, the firmware and the persistent memory, and the stacks
+        // of the threads
+        allow_syscall_if(
+            libc::SYS_mmap,
+            or![
+                and![Cond::new(
+                    3,
+                    ArgLen::DWORD,
+                    Eq,
+                    (libc::MAP_ANONYMOUS | libc::MAP_PRIVATE | libc::MAP_NORESERVE) as u64
+                )?],
+                and![Cond::new(
+                    3,
+                    ArgLen::DWORD,
+                    Eq,
+                    (libc::MAP_SHARED | libc::MAP_NORESERVE) as u64
+                )?],
+                and![Cond::new(3, ArgLen::DWORD, Eq, libc::MAP_PRIVATE as u64)?],
+                and![Cond::new(
+                    3,
+                    ArgLen::DWORD,
+                    Eq,
+                    (libc::MAP_PRIVATE | libc::MAP_NORESERVE) as u64
+                )?],
+                and![Cond::new(
+                    3,
+                    ArgLen::DWORD,
+                    Eq,
+                    (libc::MAP_ANONYMOUS | libc::MAP_PRIVATE) as u64
+                )?],
+                #[cfg(target_env = "gnu")]
+                and![Cond::new(
+                    3,
+                    ArgLen::DWORD,
+                    Eq,
+                    (libc::MAP_ANONYMOUS | libc::MAP_PRIVATE | libc::MAP_STACK) as u64
+                )?],
+            ],
+        ),
+        // Used to name the threads
+        allow_syscall_if(
+            libc::SYS_prctl,
+            or![and![Cond::new(
+                0,
+                ArgLen::DWORD,
+                Eq,
+                libc::PR_SET_NAME as u64
+            )?],],
+        ),
+        // Used to find the IOMMU group of a PCI function passed through with VFIO
+        #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+        allow_syscall(libc::SYS_readlink),
+        #[cfg(all(feature = "vfio", target_arch = "aarch64"))]
+        allow_syscall(libc::SYS_readlinkat),
+        // Used by glibc in the threads it spawns
+        #[cfg(target_env = "gnu")]
+        allow_syscall(super::SYS_RSEQ),
+        // Used to block the signals while spawning the threads
+        allow_syscall(libc::SYS_rt_sigprocmask),
+        // Used by glibc in the threads it spawns
+        #[cfg(target_env = "gnu")]
+        allow_syscall(libc::SYS_set_robust_list),
+        // Used to set up the stack overflow handler of the threads
+        allow_syscall(libc::SYS_sigaltstack),
+    ];
+    for (syscall, rules) in rules {
+        filter.add_rules(syscall, rules)?;
+    }
+    Ok(filter)
+}
+
+/// Returns the default filter, or the teardown filter if `allow_teardown` is set, along with the
+/// syscalls the event loop polls with through `backend`.
+fn backend_filter(backend: Backend, allow_teardown: bool) -> Result<SeccompFilter, Error> {
+    let mut filter = if allow_teardown {
+        teardown_filter()?
+    } else {
+        default_filter()?
+    };
+    if backend == Backend::IoUring {
+        // Used by the event manager polling through io_uring. Its ring is created before the
+        // filter is loaded.
+        let (syscall, rules) = allow_syscall(libc::SYS_io_uring_enter);
+        filter.add_rules(syscall, rules)?;
+    }
+    Ok(filter)
+}
+
+/// Returns the filter of a seccomp level value for the event loop polling through `backend`,
+/// unless the level does not filter syscalls. The filter lets the VMM thread build another
+/// microVM after a teardown only if `allow_teardown` is set.
+pub fn get_level_filter(
+    seccomp_level: SeccompLevel,
+    backend: Backend,
+    allow_teardown: bool,
+) -> Result<Option<SeccompFilter>, Error> {
     match seccomp_level {
-        SeccompLevel::None => Ok(vec![]),
-        SeccompLevel::Basic => default_filter()
-            .map(|filter| filter.allow_all())
-            .and_then(|filter| filter.try_into())
-            .map_err(SeccompError::SeccompFilter),
-        SeccompLevel::Advanced => default_filter()
-            .and_then(|filter| filter.try_into())
-            .map_err(SeccompError::SeccompFilter),
+        SeccompLevel::None => Ok(None),
+        SeccompLevel::Basic => {
+            backend_filter(backend, allow_teardown).map(|filter| Some(filter.allow_all()))
+        }
+        SeccompLevel::Advanced => backend_filter(backend, allow_teardown).map(Some),
+    }
+}
+
+/// Generate a BPF program based on a seccomp level value, for the event loop polling through
+/// epoll.
+pub fn get_seccomp_filter(seccomp_level: SeccompLevel) -> Result<BpfProgram, SeccompError> {
+    level_program(seccomp_level, false)
+}
+
+/// Generate a BPF program based on a seccomp level value, for the event loop polling through
+/// epoll, which lets the VMM thread build another microVM after a teardown.
+pub fn get_teardown_seccomp_filter(
+    seccomp_level: SeccompLevel,
+) -> Result<BpfProgram, SeccompError> {
+    level_program(seccomp_level, true)
+}
+
+fn level_program(
+    seccomp_level: SeccompLevel,
+    allow_teardown: bool,
+) -> Result<BpfProgram, SeccompError> {
+    match get_level_filter(seccomp_level, Backend::Epoll, allow_teardown)
+        .map_err(SeccompError::SeccompFilter)?
+    {
+        Some(filter) => filter.try_into().map_err(SeccompError::SeccompFilter),
+        None => Ok(vec![]),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::get_seccomp_filter;
-    use seccomp::SeccompLevel;
+    use std::convert::TryInto;
+
+    use super::{get_level_filter, get_seccomp_filter, get_teardown_seccomp_filter};
+    use polly::event_manager::Backend;
+    use seccomp::{BpfProgram, SeccompLevel};
+
+    #[test]
+    fn test_get_level_filter() {
+        for backend in [Backend::Epoll, Backend::IoUring].iter() {
+            for allow_teardown in [false, true].iter() {
+                assert!(
+                    get_level_filter(SeccompLevel::None, *backend, *allow_teardown)
+                        .unwrap()
+                        .is_none()
+                );
+                assert!(
+                    get_level_filter(SeccompLevel::Basic, *backend, *allow_teardown)
+                        .unwrap()
+                        .is_some()
+                );
+                assert!(
+                    get_level_filter(SeccompLevel::Advanced, *backend, *allow_teardown)
+                        .unwrap()
+                        .is_some()
+                );
+            }
+        }
+
+        // Only the io_uring backend gets to enter a ring.
+        let epoll_program: BpfProgram =
+            get_level_filter(SeccompLevel::Advanced, Backend::Epoll, false)
+                .unwrap()
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let io_uring_program: BpfProgram =
+            get_level_filter(SeccompLevel::Advanced, Backend::IoUring, false)
+                .unwrap()
+                .unwrap()
+                .try_into()
+                .unwrap();
+        assert!(io_uring_program.len() > epoll_program.len());
+    }
+
+    #[test]
+    fn test_get_teardown_seccomp_filter() {
+        assert!(get_teardown_seccomp_filter(SeccompLevel::None)
+            .unwrap()
+            .is_empty());
+        assert!(get_teardown_seccomp_filter(SeccompLevel::Basic).is_ok());
+
+        // Only the opt-in filter allows building another microVM.
+        let default_program = get_seccomp_filter(SeccompLevel::Advanced).unwrap();
+        let teardown_program = get_teardown_seccomp_filter(SeccompLevel::Advanced).unwrap();
+        assert!(teardown_program.len() > default_program.len());
+    }
 
     #[test]
     fn test_get_seccomp_filter() {
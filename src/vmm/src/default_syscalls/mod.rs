@@ -8,10 +8,13 @@ use seccomp::{
 
 #[macro_use]
 mod macros;
+mod audit;
 mod filters;
 
-pub use self::filters::default_filter;
-pub use self::filters::get_seccomp_filter;
+pub use self::audit::{start_seccomp_audit, AuditError};
+pub use self::filters::{default_filter, teardown_filter};
+pub use self::filters::{get_level_filter, get_seccomp_filter, get_teardown_seccomp_filter};
+pub use seccompiler::{filter_from_json, policy_from_json, PolicyError};
 
 // See include/uapi/asm-generic/fcntl.h in the kernel code.
 const FCNTL_FD_CLOEXEC: u64 = 1;
@@ -28,6 +31,35 @@ const FUTEX_WAKE_PRIVATE: u64 = FUTEX_WAKE | FUTEX_PRIVATE_FLAG;
 #[cfg(target_env = "gnu")]
 const FUTEX_CMP_REQUEUE_PRIVATE: u64 = FUTEX_CMP_REQUEUE | FUTEX_PRIVATE_FLAG;
 
+// The syscall glibc calls in the threads it spawns, which the libc crate may not define.
+#[cfg(all(target_env = "gnu", target_arch = "x86_64"))]
+const SYS_RSEQ: i64 = 334;
+#[cfg(all(target_env = "gnu", target_arch = "aarch64"))]
+const SYS_RSEQ: i64 = 293;
+
+// The flags with which the C library clones the threads it spawns, see pthread_create.
+const CLONE_THREAD_FLAGS: i32 = libc::CLONE_VM
+    | libc::CLONE_FS
+    | libc::CLONE_FILES
+    | libc::CLONE_SIGHAND
+    | libc::CLONE_THREAD
+    | libc::CLONE_SYSVSEM
+    | libc::CLONE_SETTLS
+    | libc::CLONE_PARENT_SETTID
+    | libc::CLONE_CHILD_CLEARTID;
+#[cfg(target_env = "gnu")]
+const CLONE_FLAGS: u64 = CLONE_THREAD_FLAGS as u64;
+#[cfg(target_env = "musl")]
+const CLONE_FLAGS: u64 = (CLONE_THREAD_FLAGS | libc::CLONE_DETACHED) as u64;
+
+// The flags with which the guest memory is backed by a memfd of huge pages, see
+// builder::create_memfd and include/uapi/linux/memfd.h in the kernel code.
+const MFD_HUGE_SHIFT: u32 = 26;
+const MFD_HUGE_2MB_FLAGS: u64 =
+    (libc::MFD_CLOEXEC | libc::MFD_HUGETLB | 21 << MFD_HUGE_SHIFT) as u64;
+const MFD_HUGE_1GB_FLAGS: u64 =
+    (libc::MFD_CLOEXEC | libc::MFD_HUGETLB | 30 << MFD_HUGE_SHIFT) as u64;
+
 // See include/uapi/asm-generic/ioctls.h in the kernel code.
 const TCGETS: u64 = 0x5401;
 const TCSETS: u64 = 0x5402;
@@ -39,6 +71,19 @@ const TUNSETIFF: u64 = 0x4004_54ca;
 const TUNSETOFFLOAD: u64 = 0x4004_54d0;
 const TUNSETVNETHDRSZ: u64 = 0x4004_54d8;
 
+// See include/uapi/linux/vhost.h in the kernel code.
+const VHOST_SET_FEATURES: u64 = 0x4008_af00;
+const VHOST_SET_MEM_TABLE: u64 = 0x4008_af03;
+const VHOST_SET_VRING_NUM: u64 = 0x4008_af10;
+const VHOST_SET_VRING_ADDR: u64 = 0x4028_af11;
+const VHOST_SET_VRING_BASE: u64 = 0x4008_af12;
+const VHOST_SET_VRING_KICK: u64 = 0x4008_af20;
+const VHOST_SET_VRING_CALL: u64 = 0x4008_af21;
+const VHOST_VSOCK_SET_RUNNING: u64 = 0x4004_af61;
+const VHOST_GET_FEATURES: u64 = 0x8008_af00;
+const VHOST_SET_OWNER: u64 = 0xaf01;
+const VHOST_VSOCK_SET_GUEST_CID: u64 = 0x4008_af60;
+
 // Hardcoded here instead of getting values from kvm-ioctls, so that filtered values cannot be
 // mistakenly or intentionally altered from outside our codebase.
 const KVM_GET_DIRTY_LOG: u64 = 0x4010_ae42;
@@ -48,6 +93,38 @@ const KVM_SET_MP_STATE: u64 = 0x4004_ae99;
 const KVM_GET_VCPU_EVENTS: u64 = 0x8040_ae9f;
 const KVM_SET_VCPU_EVENTS: u64 = 0x4040_aea0;
 
+// The ioctls building a microVM, which the VMM thread calls again once the previous microVM was
+// torn down.
+const KVM_GET_API_VERSION: u64 = 0xae00;
+const KVM_CREATE_VM: u64 = 0xae01;
+const KVM_CHECK_EXTENSION: u64 = 0xae03;
+const KVM_GET_VCPU_MMAP_SIZE: u64 = 0xae04;
+const KVM_CREATE_VCPU: u64 = 0xae41;
+const KVM_SET_USER_MEMORY_REGION: u64 = 0x4020_ae46;
+const KVM_IRQFD: u64 = 0x4020_ae76;
+const KVM_IOEVENTFD: u64 = 0x4040_ae79;
+
+// The VFIO ioctls binding the PCI functions passed through to the guest.
+#[cfg(feature = "vfio")]
+mod vfio_constants {
+    pub const VFIO_GET_API_VERSION: u64 = 0x3b64;
+    pub const VFIO_CHECK_EXTENSION: u64 = 0x3b65;
+    pub const VFIO_SET_IOMMU: u64 = 0x3b66;
+    pub const VFIO_GROUP_GET_STATUS: u64 = 0x3b67;
+    pub const VFIO_GROUP_SET_CONTAINER: u64 = 0x3b68;
+    pub const VFIO_GROUP_GET_DEVICE_FD: u64 = 0x3b6a;
+    pub const VFIO_DEVICE_GET_INFO: u64 = 0x3b6b;
+    pub const VFIO_DEVICE_GET_REGION_INFO: u64 = 0x3b6c;
+    pub const VFIO_DEVICE_GET_IRQ_INFO: u64 = 0x3b6d;
+    pub const VFIO_DEVICE_SET_IRQS: u64 = 0x3b6e;
+    pub const VFIO_DEVICE_RESET: u64 = 0x3b6f;
+    pub const VFIO_IOMMU_MAP_DMA: u64 = 0x3b71;
+    #[cfg(target_arch = "x86_64")]
+    pub const KVM_CREATE_DEVICE: u64 = 0xc00c_aee0;
+    #[cfg(target_arch = "x86_64")]
+    pub const KVM_SET_DEVICE_ATTR: u64 = 0x4018_aee1;
+}
+
 // Use this mod to define ioctl params that are architecture specific.
 // To add other architectures, add another module declaration with the right cfg attribute.
 #[cfg(target_arch = "x86_64")]
@@ -71,6 +148,33 @@ mod arch_specific_constants {
     pub const KVM_SET_XSAVE: u64 = 0x5000_aea5;
     pub const KVM_GET_XCRS: u64 = 0x8188_aea6;
     pub const KVM_SET_XCRS: u64 = 0x4188_aea7;
+    pub const KVM_SIGNAL_MSI: u64 = 0x4020_aea5;
+    // Building a microVM.
+    pub const KVM_GET_MSR_INDEX_LIST: u64 = 0xc004_ae02;
+    pub const KVM_GET_SUPPORTED_CPUID: u64 = 0xc008_ae05;
+    pub const KVM_SET_TSS_ADDR: u64 = 0xae47;
+    pub const KVM_SET_IDENTITY_MAP_ADDR: u64 = 0x4008_ae48;
+    pub const KVM_CREATE_IRQCHIP: u64 = 0xae60;
+    pub const KVM_SET_IRQCHIP: u64 = 0x8208_ae63;
+    pub const KVM_REINJECT_CONTROL: u64 = 0xae71;
+    pub const KVM_CREATE_PIT2: u64 = 0x4040_ae77;
+    pub const KVM_SET_CLOCK: u64 = 0x4030_ae7b;
+    pub const KVM_SET_FPU: u64 = 0x41a0_ae8d;
+    pub const KVM_SET_PIT2: u64 = 0x4070_aea0;
+    pub const KVM_SET_TSC_KHZ: u64 = 0xaea2;
+    pub const KVM_GET_TSC_KHZ: u64 = 0xaea3;
+    pub const KVM_ENABLE_CAP: u64 = 0x4068_aea3;
+    pub const KVM_X86_SET_MSR_FILTER: u64 = 0x4188_aec6;
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch_specific_constants {
+    // Building a microVM.
+    pub const KVM_SET_ONE_REG: u64 = 0x4010_aeac;
+    pub const KVM_ARM_VCPU_INIT: u64 = 0x4020_aeae;
+    pub const KVM_ARM_PREFERRED_TARGET: u64 = 0x8020_aeaf;
+    pub const KVM_CREATE_DEVICE: u64 = 0xc00c_aee0;
+    pub const KVM_SET_DEVICE_ATTR: u64 = 0x4018_aee1;
 }
 
 fn create_arch_specific_ioctl_conditions() -> Result<Vec<SeccompRule>, Error> {
@@ -98,12 +202,102 @@ fn create_arch_specific_ioctl_conditions() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_XSAVE)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_XCRS)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_XCRS)?],
+        // Triggered when a virtio-pci device interrupts the guest.
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SIGNAL_MSI)?],
     ]);
 
     #[cfg(target_arch = "aarch64")]
     return Ok(or![]);
 }
 
+// The ioctls the VMM thread calls when it builds a microVM after the previous one was torn down.
+// They are only allowed by the teardown filter.
+fn create_rebuild_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
+    use arch_specific_constants::*;
+
+    let mut rule = or![
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_API_VERSION)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_CREATE_VM)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_CHECK_EXTENSION)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_VCPU_MMAP_SIZE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_CREATE_VCPU)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_USER_MEMORY_REGION)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_IRQFD)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_IOEVENTFD)?],
+        // Triggered when the vhost-vsock device is created.
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_GET_FEATURES)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_OWNER)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_VSOCK_SET_GUEST_CID)?],
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    rule.append(&mut or![
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_MSR_INDEX_LIST)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_SUPPORTED_CPUID)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_TSS_ADDR)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_IDENTITY_MAP_ADDR)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_CREATE_IRQCHIP)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_IRQCHIP)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_REINJECT_CONTROL)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_CREATE_PIT2)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_CLOCK)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_FPU)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_PIT2)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_TSC_KHZ)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_TSC_KHZ)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_ENABLE_CAP)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_X86_SET_MSR_FILTER)?],
+    ]);
+
+    #[cfg(target_arch = "aarch64")]
+    rule.append(&mut or![
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_ONE_REG)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_ARM_VCPU_INIT)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_ARM_PREFERRED_TARGET)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_CREATE_DEVICE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_DEVICE_ATTR)?],
+    ]);
+
+    #[cfg(feature = "vfio")]
+    rule.append(&mut create_vfio_ioctl_conditions()?);
+
+    Ok(rule)
+}
+
+// The ioctls binding the PCI functions passed through with VFIO when the microVM is built.
+#[cfg(feature = "vfio")]
+fn create_vfio_ioctl_conditions() -> Result<Vec<SeccompRule>, Error> {
+    use vfio_constants::*;
+
+    let mut rule = or![
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_GET_API_VERSION)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_CHECK_EXTENSION)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_SET_IOMMU)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_GROUP_GET_STATUS)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_GROUP_SET_CONTAINER)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_GROUP_GET_DEVICE_FD)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_DEVICE_GET_INFO)?],
+        and![Cond::new(
+            1,
+            ArgLen::DWORD,
+            Eq,
+            VFIO_DEVICE_GET_REGION_INFO
+        )?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_DEVICE_GET_IRQ_INFO)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_DEVICE_RESET)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VFIO_IOMMU_MAP_DMA)?],
+    ];
+
+    // The aarch64 KVM devices are already allowed.
+    #[cfg(target_arch = "x86_64")]
+    rule.append(&mut or![
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_CREATE_DEVICE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_DEVICE_ATTR)?],
+    ]);
+
+    Ok(rule)
+}
+
 fn create_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
     let mut rule = or![
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_RUN)?],
@@ -120,6 +314,15 @@ fn create_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
         and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETIFF)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETOFFLOAD)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, TUNSETVNETHDRSZ)?],
+        // Triggered when the guest driver activates the vhost-vsock device.
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_FEATURES)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_MEM_TABLE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_NUM)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_ADDR)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_BASE)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_KICK)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_SET_VRING_CALL)?],
+        and![Cond::new(1, ArgLen::DWORD, Eq, VHOST_VSOCK_SET_RUNNING)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_MP_STATE)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_SET_MP_STATE)?],
         and![Cond::new(1, ArgLen::DWORD, Eq, KVM_GET_VCPU_EVENTS)?],
@@ -128,6 +331,16 @@ fn create_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, Error> {
 
     rule.append(&mut create_arch_specific_ioctl_conditions()?);
 
+    // The guest enables and disables the MSI-X vectors of the PCI functions passed through with
+    // VFIO from the vCPU threads.
+    #[cfg(feature = "vfio")]
+    rule.push(and![Cond::new(
+        1,
+        ArgLen::DWORD,
+        Eq,
+        vfio_constants::VFIO_DEVICE_SET_IRQS
+    )?]);
+
     Ok(rule)
 }
 
@@ -187,4 +400,17 @@ mod tests {
         .join()
         .unwrap();
     }
+
+    #[test]
+    fn test_teardown_seccomp() {
+        // Spawn a new thread before running the tests because all tests run
+        // in the same thread. Otherwise other tests will fail because of the
+        // installed seccomp filters.
+        thread::spawn(move || {
+            let filter = teardown_filter().unwrap();
+            add_syscalls_install_filter(filter);
+        })
+        .join()
+        .unwrap();
+    }
 }
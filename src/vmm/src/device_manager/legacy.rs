@@ -9,8 +9,24 @@
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
+use arch::x86_64::layout::{ACPI_PM_START, ACPI_SCI_IRQ, CPU_HOTPLUG_START, GED_IRQ};
+use devices::legacy::{
+    AcpiPm, CpuHotplug, PvPanic, Serial, SerialState, ACPI_PM_PORTS, CPU_HOTPLUG_PORTS,
+};
+use devices::pci::{PciConfigIo, PCI_CONFIG_IO_PORT, PCI_CONFIG_IO_SIZE};
 use kvm_ioctls::VmFd;
 use utils::eventfd::EventFd;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+use crate::vmm_config::serial::{SerialPortConfig, SERIAL_PORT_IDS};
+
+// The I/O port base addresses of COM2, COM3 and COM4.
+const COM2_BASE: u64 = 0x2f8;
+const COM3_BASE: u64 = 0x3e8;
+const COM4_BASE: u64 = 0x2e8;
+// The I/O port of the pvpanic device, as expected by the guest drivers.
+const PVPANIC_PORT: u64 = 0x505;
 
 /// Errors corresponding to the `PortIODeviceManager`.
 #[derive(Debug)]
@@ -19,6 +35,8 @@ pub enum Error {
     BusError(devices::BusError),
     /// Cannot create EventFd.
     EventFd(std::io::Error),
+    /// The serial port does not exist.
+    InvalidSerialPort(String),
 }
 
 impl fmt::Display for Error {
@@ -28,23 +46,62 @@ impl fmt::Display for Error {
         match *self {
             BusError(ref err) => write!(f, "Failed to add legacy device to Bus: {}", err),
             EventFd(ref err) => write!(f, "Failed to create EventFd: {}", err),
+            InvalidSerialPort(ref id) => write!(f, "Invalid serial port: {}", id),
         }
     }
 }
 
 type Result<T> = ::std::result::Result<T, Error>;
 
+/// Holds the state of a serial port bound to a host backend.
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct SerialPortState {
+    /// The serial port identifier.
+    pub port_id: String,
+    /// Path of the file backing the port.
+    pub file_path: Option<String>,
+    /// Path of the named pipe backing the port.
+    pub pipe_path: Option<String>,
+    /// Path of the unix domain socket backing the port.
+    pub uds_path: Option<String>,
+    /// Register state of the port.
+    pub serial_state: SerialState,
+}
+
+impl SerialPortState {
+    /// Returns the configuration the port was created with.
+    pub fn config(&self) -> SerialPortConfig {
+        SerialPortConfig {
+            port_id: self.port_id.clone(),
+            file_path: self.file_path.clone(),
+            pipe_path: self.pipe_path.clone(),
+            uds_path: self.uds_path.clone(),
+        }
+    }
+}
+
 /// The `PortIODeviceManager` is a wrapper that is used for registering legacy devices
 /// on an I/O Bus. It currently manages the uart and i8042 devices.
 /// The `LegacyDeviceManger` should be initialized only by using the constructor.
 pub struct PortIODeviceManager {
     pub io_bus: devices::Bus,
     pub stdio_serial: Arc<Mutex<devices::legacy::Serial>>,
+    /// The serial ports bound to a host backend. The other ones are left unconnected.
+    pub serial_ports: Vec<(SerialPortConfig, Arc<Mutex<Serial>>)>,
     pub i8042: Arc<Mutex<devices::legacy::I8042Device>>,
+    /// The ACPI PM registers, through which the power button is pressed.
+    pub acpi_pm: Arc<Mutex<AcpiPm>>,
+    /// The vCPU hotplug controller, if vCPUs can be hotplugged.
+    pub cpu_hotplug: Option<Arc<Mutex<CpuHotplug>>>,
 
     pub com_evt_1_3: EventFd,
     pub com_evt_2_4: EventFd,
     pub kbd_evt: EventFd,
+    pub sci_evt: EventFd,
+    pub ged_evt: EventFd,
+    /// Signaled when the guest ejects a vCPU, whose thread is then paused.
+    pub cpu_eject_evt: EventFd,
 }
 
 impl PortIODeviceManager {
@@ -62,7 +119,15 @@ impl PortIODeviceManager {
             .map_err(Error::EventFd)?;
         let com_evt_2_4 = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
         let kbd_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+        let sci_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+        let ged_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+        let cpu_eject_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
 
+        // The guest powering off through ACPI stops the microVM, like a reset does.
+        let acpi_pm = Arc::new(Mutex::new(AcpiPm::new(
+            sci_evt.try_clone().map_err(Error::EventFd)?,
+            i8042_reset_evfd.try_clone().map_err(Error::EventFd)?,
+        )));
         let i8042 = Arc::new(Mutex::new(devices::legacy::I8042Device::new(
             i8042_reset_evfd,
             kbd_evt.try_clone().map_err(Error::EventFd)?,
@@ -71,47 +136,108 @@ impl PortIODeviceManager {
         Ok(PortIODeviceManager {
             io_bus,
             stdio_serial: serial,
+            serial_ports: Vec::new(),
             i8042,
+            acpi_pm,
+            cpu_hotplug: None,
             com_evt_1_3,
             com_evt_2_4,
             kbd_evt,
+            sci_evt,
+            ged_evt,
+            cpu_eject_evt,
         })
     }
 
+    // Returns the I/O port base address and the interrupt event of the serial port `port_id`.
+    fn serial_port_location(&self, port_id: &str) -> Result<(u64, &EventFd)> {
+        match port_id {
+            "com2" => Ok((COM2_BASE, &self.com_evt_2_4)),
+            "com3" => Ok((COM3_BASE, &self.com_evt_1_3)),
+            "com4" => Ok((COM4_BASE, &self.com_evt_2_4)),
+            _ => Err(Error::InvalidSerialPort(port_id.to_string())),
+        }
+    }
+
+    /// Returns the interrupt event the serial port `port_id` signals.
+    pub fn serial_interrupt_evt(&self, port_id: &str) -> Result<EventFd> {
+        let (_, evt) = self.serial_port_location(port_id)?;
+        evt.try_clone().map_err(Error::EventFd)
+    }
+
+    /// Binds a serial port to a host backend. Must be called before the devices are registered.
+    pub fn add_serial_port(
+        &mut self,
+        config: SerialPortConfig,
+        serial: Arc<Mutex<Serial>>,
+    ) -> Result<()> {
+        self.serial_port_location(&config.port_id)?;
+        self.serial_ports
+            .retain(|(port, _)| port.port_id != config.port_id);
+        self.serial_ports.push((config, serial));
+        Ok(())
+    }
+
+    /// Registers the pvpanic device, through which the guest kernel reports its crashes.
+    pub fn register_pvpanic(&mut self, pvpanic: Arc<Mutex<PvPanic>>) -> Result<()> {
+        self.io_bus
+            .insert(pvpanic, PVPANIC_PORT, 0x1)
+            .map_err(Error::BusError)
+    }
+
+    /// Registers the hotplug controller of `cpu_count` vCPUs, of which the first `plugged` are
+    /// present at boot. The controller raises the GED interrupt and signals `cpu_eject_evt`.
+    pub fn register_cpu_hotplug(
+        &mut self,
+        cpu_count: u8,
+        plugged: u8,
+    ) -> Result<Arc<Mutex<CpuHotplug>>> {
+        let cpu_hotplug = Arc::new(Mutex::new(CpuHotplug::new(
+            cpu_count,
+            plugged,
+            self.ged_evt.try_clone().map_err(Error::EventFd)?,
+            self.cpu_eject_evt.try_clone().map_err(Error::EventFd)?,
+        )));
+        self.io_bus
+            .insert(cpu_hotplug.clone(), CPU_HOTPLUG_START, CPU_HOTPLUG_PORTS)
+            .map_err(Error::BusError)?;
+        self.cpu_hotplug = Some(cpu_hotplug.clone());
+        Ok(cpu_hotplug)
+    }
+
+    /// Registers the legacy configuration ports of the PCI root bus.
+    pub fn register_pci_config_io(&mut self, config_io: Arc<Mutex<PciConfigIo>>) -> Result<()> {
+        self.io_bus
+            .insert(config_io, PCI_CONFIG_IO_PORT, PCI_CONFIG_IO_SIZE)
+            .map_err(Error::BusError)
+    }
+
     /// Register supported legacy devices.
     pub fn register_devices(&mut self, vm_fd: &VmFd) -> Result<()> {
         self.io_bus
             .insert(self.stdio_serial.clone(), 0x3f8, 0x8)
             .map_err(Error::BusError)?;
-        self.io_bus
-            .insert(
-                Arc::new(Mutex::new(devices::legacy::Serial::new_sink(
-                    self.com_evt_2_4.try_clone().map_err(Error::EventFd)?,
+        for port_id in SERIAL_PORT_IDS.iter() {
+            let (base, evt) = self.serial_port_location(port_id)?;
+            let serial = match self
+                .serial_ports
+                .iter()
+                .find(|(config, _)| config.port_id == *port_id)
+            {
+                Some((_, serial)) => serial.clone(),
+                None => Arc::new(Mutex::new(Serial::new_sink(
+                    evt.try_clone().map_err(Error::EventFd)?,
                 ))),
-                0x2f8,
-                0x8,
-            )
-            .map_err(Error::BusError)?;
-        self.io_bus
-            .insert(
-                Arc::new(Mutex::new(devices::legacy::Serial::new_sink(
-                    self.com_evt_1_3.try_clone().map_err(Error::EventFd)?,
-                ))),
-                0x3e8,
-                0x8,
-            )
-            .map_err(Error::BusError)?;
+            };
+            self.io_bus
+                .insert(serial, base, 0x8)
+                .map_err(Error::BusError)?;
+        }
         self.io_bus
-            .insert(
-                Arc::new(Mutex::new(devices::legacy::Serial::new_sink(
-                    self.com_evt_2_4.try_clone().map_err(Error::EventFd)?,
-                ))),
-                0x2e8,
-                0x8,
-            )
+            .insert(self.i8042.clone(), 0x060, 0x5)
             .map_err(Error::BusError)?;
         self.io_bus
-            .insert(self.i8042.clone(), 0x060, 0x5)
+            .insert(self.acpi_pm.clone(), ACPI_PM_START, ACPI_PM_PORTS)
             .map_err(Error::BusError)?;
 
         vm_fd
@@ -123,14 +249,52 @@ impl PortIODeviceManager {
         vm_fd
             .register_irqfd(&self.kbd_evt, 1)
             .map_err(|e| Error::EventFd(std::io::Error::from_raw_os_error(e.errno())))?;
+        vm_fd
+            .register_irqfd(&self.sci_evt, ACPI_SCI_IRQ)
+            .map_err(|e| Error::EventFd(std::io::Error::from_raw_os_error(e.errno())))?;
+        vm_fd
+            .register_irqfd(&self.ged_evt, GED_IRQ)
+            .map_err(|e| Error::EventFd(std::io::Error::from_raw_os_error(e.errno())))?;
 
         Ok(())
     }
+
+    /// Saves the state of the serial ports bound to a host backend.
+    pub fn save_serial_ports(&self) -> Vec<SerialPortState> {
+        self.serial_ports
+            .iter()
+            .map(|(config, serial)| SerialPortState {
+                port_id: config.port_id.clone(),
+                file_path: config.file_path.clone(),
+                pipe_path: config.pipe_path.clone(),
+                uds_path: config.uds_path.clone(),
+                serial_state: serial.lock().expect("Poisoned lock").save_state(),
+            })
+            .collect()
+    }
+
+    /// Restores the register state of the serial ports bound to a host backend.
+    pub fn restore_serial_ports(&self, states: &[SerialPortState]) -> Result<()> {
+        for state in states.iter() {
+            let (_, serial) = self
+                .serial_ports
+                .iter()
+                .find(|(config, _)| config.port_id == state.port_id)
+                .ok_or_else(|| Error::InvalidSerialPort(state.port_id.clone()))?;
+            serial
+                .lock()
+                .expect("Poisoned lock")
+                .restore_state(&state.serial_state);
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vmm_config::serial::tests::file_port;
+    use utils::tempfile::TempFile;
     use vm_memory::{GuestAddress, GuestMemoryMmap};
 
     #[test]
@@ -147,6 +311,155 @@ mod tests {
         assert!(ldm.register_devices(vm.fd()).is_ok());
     }
 
+    #[test]
+    fn test_acpi_pm() {
+        let guest_mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), 0x1000)]).unwrap();
+        let mut vm = crate::builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        crate::builder::setup_interrupt_controller(&mut vm).unwrap();
+        let serial = Serial::new_sink(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let reset_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut ldm =
+            PortIODeviceManager::new(Arc::new(Mutex::new(serial)), reset_evt.try_clone().unwrap())
+                .unwrap();
+        ldm.register_devices(vm.fd()).unwrap();
+
+        // The guest enables the power button event.
+        ldm.io_bus
+            .write(ACPI_PM_START + 2, &(1u16 << 8).to_le_bytes());
+        assert!(ldm.acpi_pm.lock().unwrap().press_power_button());
+        let mut data = [0u8; 2];
+        ldm.io_bus.read(ACPI_PM_START, &mut data);
+        assert_eq!(u16::from_le_bytes(data), 1 << 8);
+
+        // Entering S5 signals the reset event.
+        ldm.io_bus
+            .write(ACPI_PM_START + 4, &((5u16 << 10) | (1 << 13)).to_le_bytes());
+        assert_eq!(reset_evt.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_register_pvpanic() {
+        let serial = Serial::new_sink(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let mut ldm = PortIODeviceManager::new(
+            Arc::new(Mutex::new(serial)),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        )
+        .unwrap();
+        let pvpanic_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let pvpanic = Arc::new(Mutex::new(PvPanic::new(pvpanic_evt.try_clone().unwrap())));
+        ldm.register_pvpanic(pvpanic.clone()).unwrap();
+        assert!(ldm.register_pvpanic(pvpanic.clone()).is_err());
+
+        let mut data = [0u8];
+        ldm.io_bus.read(PVPANIC_PORT, &mut data);
+        assert_eq!(
+            data[0],
+            devices::legacy::PVPANIC_PANICKED | devices::legacy::PVPANIC_CRASH_LOADED
+        );
+        ldm.io_bus
+            .write(PVPANIC_PORT, &[devices::legacy::PVPANIC_PANICKED]);
+        assert_eq!(pvpanic_evt.read().unwrap(), 1);
+        assert_eq!(
+            pvpanic.lock().unwrap().take_events(),
+            devices::legacy::PVPANIC_PANICKED
+        );
+    }
+
+    #[test]
+    fn test_register_cpu_hotplug() {
+        let serial = Serial::new_sink(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let mut ldm = PortIODeviceManager::new(
+            Arc::new(Mutex::new(serial)),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        )
+        .unwrap();
+        assert!(ldm.cpu_hotplug.is_none());
+
+        let cpu_hotplug = ldm.register_cpu_hotplug(4, 1).unwrap();
+        assert!(ldm.cpu_hotplug.is_some());
+        assert!(ldm.register_cpu_hotplug(4, 1).is_err());
+
+        // The guest reads the flags of the selected vCPU.
+        let mut data = [0u8];
+        ldm.io_bus.write(CPU_HOTPLUG_START, &[0]);
+        ldm.io_bus.read(CPU_HOTPLUG_START + 1, &mut data);
+        assert_eq!(data[0], 1);
+        ldm.io_bus.write(CPU_HOTPLUG_START, &[2]);
+        ldm.io_bus.read(CPU_HOTPLUG_START + 1, &mut data);
+        assert_eq!(data[0], 0);
+
+        // Plugging vCPUs raises the GED interrupt.
+        let mut cpu_hotplug = cpu_hotplug.lock().unwrap();
+        assert_eq!(cpu_hotplug.set_plugged(3), Some(vec![1, 2]));
+        cpu_hotplug.notify_guest();
+        drop(cpu_hotplug);
+        assert_eq!(ldm.ged_evt.read().unwrap(), 1);
+        ldm.io_bus.read(CPU_HOTPLUG_START + 2, &mut data);
+        assert_eq!(data[0], 1);
+        ldm.io_bus.read(CPU_HOTPLUG_START + 1, &mut data);
+        assert_eq!(data[0], 0b11);
+
+        // Ejecting a vCPU signals the eject event.
+        ldm.io_bus.write(CPU_HOTPLUG_START + 1, &[1 << 3]);
+        assert_eq!(ldm.cpu_eject_evt.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_serial_ports() {
+        let guest_mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), 0x1000)]).unwrap();
+        let mut vm = crate::builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        crate::builder::setup_interrupt_controller(&mut vm).unwrap();
+        let serial = Serial::new_sink(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let mut ldm = PortIODeviceManager::new(
+            Arc::new(Mutex::new(serial)),
+            EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        )
+        .unwrap();
+
+        let file = TempFile::new().unwrap();
+        assert!(ldm.serial_interrupt_evt("com1").is_err());
+        match ldm.add_serial_port(
+            file_port("com1", &file),
+            Arc::new(Mutex::new(Serial::new_sink(
+                EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            ))),
+        ) {
+            Err(Error::InvalidSerialPort(id)) => assert_eq!(id, "com1"),
+            _ => panic!("Unexpected result."),
+        }
+
+        let config = file_port("com3", &file);
+        let (_, out) = config.open().unwrap();
+        let com3 = Arc::new(Mutex::new(Serial::new_out(
+            ldm.serial_interrupt_evt("com3").unwrap(),
+            out,
+        )));
+        ldm.add_serial_port(config, com3).unwrap();
+        assert!(ldm.register_devices(vm.fd()).is_ok());
+
+        // The guest output on COM3 reaches the file.
+        ldm.io_bus.write(COM3_BASE, &[b'x']);
+        assert_eq!(std::fs::read(file.as_path()).unwrap(), b"x");
+
+        // The register state of the port is saved and restored.
+        ldm.io_bus.write(COM3_BASE + 7, &[0x12]);
+        let states = ldm.save_serial_ports();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].config(), file_port("com3", &file));
+        ldm.io_bus.write(COM3_BASE + 7, &[0x34]);
+        ldm.restore_serial_ports(&states).unwrap();
+        let mut data = [0u8];
+        ldm.io_bus.read(COM3_BASE + 7, &mut data);
+        assert_eq!(data[0], 0x12);
+
+        let mut state = states[0].clone();
+        state.port_id = "com2".to_string();
+        match ldm.restore_serial_ports(&[state]) {
+            Err(Error::InvalidSerialPort(id)) => assert_eq!(id, "com2"),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
     #[test]
     fn test_debug_error() {
         assert_eq!(
@@ -163,5 +476,9 @@ mod tests {
                 std::io::Error::from_raw_os_error(1)
             )
         );
+        assert_eq!(
+            format!("{}", Error::InvalidSerialPort(String::from("com1"))),
+            "Invalid serial port: com1"
+        );
     }
 }
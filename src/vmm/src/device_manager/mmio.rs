@@ -6,25 +6,74 @@
 // found in the THIRD-PARTY file.
 
 use std::collections::HashMap;
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::{fmt, io};
 
 #[cfg(target_arch = "aarch64")]
 use arch::aarch64::DeviceInfoForFDT;
 use arch::DeviceType;
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::{Pflash, Watchdog, WATCHDOG_BAR_SIZE};
 use devices::pseudo::BootTimer;
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+use devices::tpm::{TpmCrb, TPM_CRB_MMIO_SIZE};
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use devices::vfio::{VfioContainer, VfioDevice, VfioPciDevice};
+#[cfg(feature = "virtio-fs")]
+use devices::virtio::TYPE_FS;
+#[cfg(feature = "vsock")]
+use devices::virtio::TYPE_VSOCK;
+#[cfg(feature = "balloon")]
+use devices::virtio::{Balloon, TYPE_BALLOON};
+#[cfg(feature = "block")]
+use devices::virtio::{Block, TYPE_BLOCK};
+#[cfg(feature = "virtio-console")]
+use devices::virtio::{Console, TYPE_CONSOLE};
+#[cfg(feature = "virtio-rng")]
+use devices::virtio::{Entropy, TYPE_RNG};
+use devices::virtio::{MmioTransport, VirtioDevice};
+#[cfg(feature = "net")]
+use devices::virtio::{Net, TYPE_NET};
+#[cfg(feature = "null-devices")]
+use devices::virtio::{NullDevice, NullDeviceType};
+#[cfg(feature = "virtio-pmem")]
+use devices::virtio::{Pmem, TYPE_PMEM};
+#[cfg(feature = "virtio-mem")]
+use devices::virtio::{VirtioMem, TYPE_MEM};
+#[cfg(target_arch = "x86_64")]
 use devices::virtio::{
-    Balloon, Block, MmioTransport, Net, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET,
-    TYPE_VSOCK,
+    VirtioPciDevice, NOTIFY_CFG_OFFSET, NOTIFY_OFF_MULTIPLIER, VIRTIO_PCI_BAR_SIZE,
 };
 use devices::BusDevice;
 use kernel::cmdline as kernel_cmdline;
+#[cfg(any(feature = "virtio-pmem", all(feature = "vfio", target_arch = "x86_64")))]
+use kvm_bindings::kvm_userspace_memory_region;
+#[cfg(feature = "virtio-pmem")]
+use kvm_bindings::KVM_MEM_READONLY;
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use kvm_bindings::{
+    kvm_create_device, kvm_device_attr, kvm_device_type_KVM_DEV_TYPE_VFIO, KVM_DEV_VFIO_GROUP,
+    KVM_DEV_VFIO_GROUP_ADD,
+};
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use kvm_ioctls::DeviceFd;
+#[cfg(target_arch = "x86_64")]
+use kvm_ioctls::NoDatamatch;
 use kvm_ioctls::{IoEventAddress, VmFd};
 use logger::info;
 #[cfg(target_arch = "aarch64")]
 use utils::eventfd::EventFd;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use vm_memory::GuestMemoryMmap;
+
+#[cfg(target_arch = "x86_64")]
+use super::pci::PciDeviceManager;
 
 /// Errors for MMIO device manager.
 #[derive(Debug)]
@@ -45,12 +94,29 @@ pub enum Error {
     InvalidInput,
     /// No more IRQs are available.
     IrqsExhausted,
+    /// Failed to create the KVM VFIO device, or to add an IOMMU group to it.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    KvmVfioDevice(kvm_ioctls::Error),
+    /// Failed to plug a device on the PCI root bus.
+    Pci(devices::pci::Error),
+    /// A virtio-pci device was registered while the PCI root bus is disabled.
+    PciDisabled,
+    /// Failed to create the sender of the MSI-X messages.
+    PciMsiSender(io::Error),
     /// Registering an IO Event failed.
     RegisterIoEvent(kvm_ioctls::Error),
     /// Registering an IRQ FD failed.
     RegisterIrqFd(kvm_ioctls::Error),
+    /// Registering the memory of a persistent memory device failed.
+    RegisterPmemMemory(kvm_ioctls::Error),
+    /// Registering the mapped BARs of a host PCI function failed.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    RegisterVfioMemory(kvm_ioctls::Error),
     /// Failed to update the mmio device.
     UpdateFailed,
+    /// Failed to pass a host PCI function through.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    Vfio(devices::vfio::Error),
 }
 
 impl fmt::Display for Error {
@@ -63,10 +129,24 @@ impl fmt::Display for Error {
             Error::InternalDeviceError(e) => write!(f, "device error: {}", e),
             Error::InvalidInput => write!(f, "invalid configuration"),
             Error::IrqsExhausted => write!(f, "no more IRQs are available"),
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            Error::KvmVfioDevice(e) => write!(f, "failed to set up the KVM VFIO device: {}", e),
+            Error::Pci(e) => write!(f, "failed to plug the PCI device: {}", e),
+            Error::PciDisabled => write!(f, "the PCI root bus is disabled"),
+            Error::PciMsiSender(e) => write!(f, "failed to create the MSI sender: {}", e),
             Error::RegisterIoEvent(e) => write!(f, "failed to register IO event: {}", e),
             Error::RegisterIrqFd(e) => write!(f, "failed to register irqfd: {}", e),
+            Error::RegisterPmemMemory(e) => {
+                write!(f, "failed to register persistent memory: {}", e)
+            }
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            Error::RegisterVfioMemory(e) => {
+                write!(f, "failed to register the BARs of the PCI function: {}", e)
+            }
             Error::DeviceNotFound => write!(f, "the device couldn't be found"),
             Error::UpdateFailed => write!(f, "failed to update the mmio device"),
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            Error::Vfio(e) => write!(f, "failed to pass the PCI function through: {}", e),
         }
     }
 }
@@ -79,6 +159,14 @@ type Result<T> = ::std::result::Result<T, Error>;
 /// Currently hardcoded to 4K.
 const MMIO_LEN: u64 = 0x1000;
 
+/// The window holding the BARs of the host PCI functions, past the fixed BARs of the slots.
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+const VFIO_BARS_START: u64 = arch::x86_64::layout::PCI_MMIO_START
+    + devices::pci::PCI_MAX_DEVICES as u64 * VIRTIO_PCI_BAR_SIZE;
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+const VFIO_BARS_END: u64 =
+    arch::x86_64::layout::PCI_MMIO_START + arch::x86_64::layout::PCI_MMIO_SIZE;
+
 /// Stores the address range and irq allocated to this device.
 #[derive(Clone, Debug, PartialEq, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
@@ -141,6 +229,30 @@ pub struct MMIODeviceManager {
     next_avail_mmio: u64,
     irqs: IrqManager,
     pub(crate) id_to_dev_info: HashMap<(DeviceType, String), MMIODeviceInfo>,
+    /// The TPM, if configured.
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    pub tpm: Option<Arc<Mutex<TpmCrb>>>,
+    /// The flash holding the variable store of the firmware, if booting one.
+    #[cfg(target_arch = "x86_64")]
+    pub pflash: Option<Arc<Mutex<Pflash>>>,
+    /// The PCI root bus of the virtio-pci devices and the watchdog, if enabled.
+    #[cfg(target_arch = "x86_64")]
+    pub pci: Option<PciDeviceManager>,
+    /// Whether the virtio devices plugged at boot go on the PCI root bus instead of the MMIO
+    /// bus.
+    #[cfg(target_arch = "x86_64")]
+    pub virtio_pci: bool,
+    /// The watchdog, if configured.
+    #[cfg(target_arch = "x86_64")]
+    pub watchdog: Option<Arc<Mutex<Watchdog>>>,
+    /// The VFIO container of the host PCI functions passed through, if any.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    pub vfio_container: Option<VfioContainer>,
+    /// The KVM VFIO device, which knows the IOMMU groups of the host PCI functions.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    kvm_vfio_device: Option<DeviceFd>,
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    next_vfio_bar_addr: u64,
 }
 
 impl MMIODeviceManager {
@@ -153,6 +265,22 @@ impl MMIODeviceManager {
             irqs: IrqManager::new(irq_interval.0, irq_interval.1),
             bus: devices::Bus::new(),
             id_to_dev_info: HashMap::new(),
+            #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+            tpm: None,
+            #[cfg(target_arch = "x86_64")]
+            pflash: None,
+            #[cfg(target_arch = "x86_64")]
+            pci: None,
+            #[cfg(target_arch = "x86_64")]
+            virtio_pci: false,
+            #[cfg(target_arch = "x86_64")]
+            watchdog: None,
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            vfio_container: None,
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            kvm_vfio_device: None,
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            next_vfio_bar_addr: VFIO_BARS_START,
         }
     }
 
@@ -256,6 +384,204 @@ impl MMIODeviceManager {
         Ok(mmio_slot)
     }
 
+    #[cfg(target_arch = "x86_64")]
+    /// Create the PCI root bus, and put its ECAM window on the MMIO bus. The legacy configuration
+    /// ports of the bus are left to the caller, which owns the PIO bus.
+    pub fn enable_pci(&mut self, vm: &VmFd) -> Result<()> {
+        let pci = PciDeviceManager::new(vm).map_err(Error::PciMsiSender)?;
+        self.bus
+            .insert(
+                pci.config_mmio(),
+                arch::x86_64::layout::PCI_MMCONFIG_START,
+                arch::x86_64::layout::PCI_MMCONFIG_SIZE,
+            )
+            .map_err(Error::BusError)?;
+        self.pci = Some(pci);
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Register a virtio-pci device in its slot of the PCI root bus, with its BAR on the MMIO bus.
+    pub fn register_pci_virtio(
+        &mut self,
+        vm: &VmFd,
+        device_id: String,
+        pci_device: Arc<Mutex<VirtioPciDevice>>,
+    ) -> Result<()> {
+        let pci = self.pci.as_ref().ok_or(Error::PciDisabled)?;
+        let (identifier, slot, bar_addr) = {
+            let locked_pci_device = pci_device.lock().expect("Poisoned lock");
+            let bar_addr = locked_pci_device.bar_addr();
+            let locked_device = locked_pci_device.transport().locked_device();
+            // Each queue has its own notification address, so the written value doesn't matter.
+            // The interrupts are delivered as MSI-X messages, so there is no irqfd.
+            for (i, queue_evt) in locked_device.queue_events().iter().enumerate() {
+                let io_addr = IoEventAddress::Mmio(
+                    bar_addr + NOTIFY_CFG_OFFSET + i as u64 * u64::from(NOTIFY_OFF_MULTIPLIER),
+                );
+                vm.register_ioevent(queue_evt, &io_addr, NoDatamatch)
+                    .map_err(Error::RegisterIoEvent)?;
+            }
+            (
+                (DeviceType::Virtio(locked_device.device_type()), device_id),
+                locked_pci_device.slot(),
+                bar_addr,
+            )
+        };
+        pci.add_device_at(slot, pci_device.clone())
+            .map_err(Error::Pci)?;
+
+        let bar = MMIODeviceInfo {
+            addr: bar_addr,
+            len: VIRTIO_PCI_BAR_SIZE,
+            irqs: Vec::new(),
+        };
+        self.register_mmio_device(identifier, bar, pci_device)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Plug an already created virtio device in the first free slot of the PCI root bus. The
+    /// returned device has to be subscribed to the event manager, to relay the interrupts.
+    pub fn register_pci_virtio_for_boot(
+        &mut self,
+        vm: &VmFd,
+        device_id: String,
+        mmio_device: MmioTransport,
+    ) -> Result<Arc<Mutex<VirtioPciDevice>>> {
+        let (slot, bar_addr) = self.next_free_pci_slot()?;
+        let msi_sender = self.pci.as_ref().ok_or(Error::PciDisabled)?.msi_sender();
+        let pci_device = Arc::new(Mutex::new(
+            VirtioPciDevice::new(mmio_device, slot, bar_addr, msi_sender).map_err(Error::Pci)?,
+        ));
+        self.register_pci_virtio(vm, device_id, pci_device.clone())?;
+        Ok(pci_device)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Returns the first free slot of the PCI root bus, along with the address of its BAR.
+    pub fn next_free_pci_slot(&self) -> Result<(u8, u64)> {
+        let pci = self.pci.as_ref().ok_or(Error::PciDisabled)?;
+        let slot = pci.next_free_slot().map_err(Error::Pci)?;
+        // Each slot has a fixed BAR, so that nothing has to be allocated again on restore.
+        let bar_addr = arch::x86_64::layout::PCI_MMIO_START + u64::from(slot) * VIRTIO_PCI_BAR_SIZE;
+        Ok((slot, bar_addr))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Register the watchdog in its slot of the PCI root bus, with its BAR on the MMIO bus.
+    pub fn register_pci_watchdog(&mut self, watchdog: Arc<Mutex<Watchdog>>) -> Result<()> {
+        let pci = self.pci.as_ref().ok_or(Error::PciDisabled)?;
+        let (slot, bar_addr) = {
+            let locked_watchdog = watchdog.lock().expect("Poisoned lock");
+            (locked_watchdog.slot(), locked_watchdog.bar_addr())
+        };
+        pci.add_device_at(slot, watchdog.clone())
+            .map_err(Error::Pci)?;
+        // The watchdog is not a virtio device, so it stays out of `id_to_dev_info`.
+        self.bus
+            .insert(watchdog.clone(), bar_addr, WATCHDOG_BAR_SIZE)
+            .map_err(Error::BusError)?;
+        self.watchdog = Some(watchdog);
+        Ok(())
+    }
+
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    /// Open the host PCI function at `sysfs_path` through VFIO, in the container shared by all
+    /// the functions, which maps `guest_memory` for their DMAs.
+    pub fn open_vfio_device(
+        &mut self,
+        guest_memory: &GuestMemoryMmap,
+        sysfs_path: &Path,
+    ) -> Result<VfioDevice> {
+        if self.vfio_container.is_none() {
+            self.vfio_container =
+                Some(VfioContainer::new(guest_memory.clone()).map_err(Error::Vfio)?);
+        }
+        // It's safe to unwrap because the container was just created if missing.
+        self.vfio_container
+            .as_mut()
+            .unwrap()
+            .open_device(sysfs_path)
+            .map_err(Error::Vfio)
+    }
+
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    /// Plug the host PCI function of `device` in the first free slot of the PCI root bus, with
+    /// its BARs placed past the ones of the previous functions and mapped in the guest from the
+    /// KVM memory slot `first_slot` on. Returns the number of memory slots used, along with the
+    /// device, which has to be subscribed to the event manager to relay the interrupts.
+    pub fn register_pci_vfio(
+        &mut self,
+        vm: &VmFd,
+        device: VfioDevice,
+        first_slot: u32,
+    ) -> Result<(Arc<Mutex<VfioPciDevice>>, u32)> {
+        let pci = self.pci.as_ref().ok_or(Error::PciDisabled)?;
+        let slot = pci.next_free_slot().map_err(Error::Pci)?;
+        let window = self.next_vfio_bar_addr..VFIO_BARS_END;
+        let vfio_pci =
+            VfioPciDevice::new(device, slot, window, pci.msi_sender()).map_err(Error::Vfio)?;
+
+        let mappings = vfio_pci.mappings();
+        for (index, mapping) in mappings.iter().enumerate() {
+            let memory_region = kvm_userspace_memory_region {
+                slot: first_slot + index as u32,
+                guest_phys_addr: mapping.guest_addr,
+                memory_size: mapping.size,
+                userspace_addr: mapping.host_addr,
+                flags: 0,
+            };
+            // Safe because the mapping is owned by the device, which lives as long as the guest.
+            unsafe { vm.set_user_memory_region(memory_region) }
+                .map_err(Error::RegisterVfioMemory)?;
+        }
+
+        let bars = vfio_pci.bars_range();
+        let vfio_pci = Arc::new(Mutex::new(vfio_pci));
+        pci.add_device_at(slot, vfio_pci.clone())
+            .map_err(Error::Pci)?;
+        // The function is not a virtio device, so it stays out of `id_to_dev_info`.
+        if bars.end > bars.start {
+            self.bus
+                .insert(vfio_pci.clone(), bars.start, bars.end - bars.start)
+                .map_err(Error::BusError)?;
+        }
+        self.next_vfio_bar_addr = bars.end;
+        Ok((vfio_pci, mappings.len() as u32))
+    }
+
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    /// Add the IOMMU groups of the host PCI functions to the KVM VFIO device, which KVM needs to
+    /// handle the DMAs of the functions that bypass the cache coherency.
+    pub fn register_kvm_vfio_device(&mut self, vm: &VmFd) -> Result<()> {
+        let container = match self.vfio_container.as_ref() {
+            Some(container) => container,
+            None => return Ok(()),
+        };
+        let mut kvm_vfio_device = kvm_create_device {
+            type_: kvm_device_type_KVM_DEV_TYPE_VFIO,
+            fd: 0,
+            flags: 0,
+        };
+        let device_fd = vm
+            .create_device(&mut kvm_vfio_device)
+            .map_err(Error::KvmVfioDevice)?;
+        for group in container.groups() {
+            let group_fd: RawFd = group.as_raw_fd();
+            let attr = kvm_device_attr {
+                group: KVM_DEV_VFIO_GROUP,
+                attr: u64::from(KVM_DEV_VFIO_GROUP_ADD),
+                addr: &group_fd as *const RawFd as u64,
+                flags: 0,
+            };
+            device_fd
+                .set_device_attr(&attr)
+                .map_err(Error::KvmVfioDevice)?;
+        }
+        self.kvm_vfio_device = Some(device_fd);
+        Ok(())
+    }
+
     #[cfg(target_arch = "aarch64")]
     /// Register an early console at some MMIO address.
     pub fn register_mmio_serial(
@@ -300,6 +626,47 @@ impl MMIODeviceManager {
         self.register_mmio_device(identifier, slot, Arc::new(Mutex::new(device)))
     }
 
+    #[cfg(target_arch = "aarch64")]
+    /// Register the pvpanic device, through which the guest kernel reports its crashes.
+    pub fn register_mmio_pvpanic(
+        &mut self,
+        device: Arc<Mutex<devices::legacy::PvPanic>>,
+    ) -> Result<()> {
+        // The device doesn't raise any interrupt.
+        let slot = self.allocate_new_slot(0)?;
+
+        let identifier = (DeviceType::PvPanic, DeviceType::PvPanic.to_string());
+        self.register_mmio_device(identifier, slot, device)
+    }
+
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    /// Register the TPM at the fixed address where the guest firmware and drivers expect its
+    /// CRB interface.
+    pub fn register_mmio_tpm(&mut self, tpm: Arc<Mutex<TpmCrb>>) -> Result<()> {
+        // The TPM is outside the virtio slots and isn't saved along with the other devices.
+        self.bus
+            .insert(
+                tpm.clone(),
+                arch::x86_64::layout::TPM_CRB_START,
+                TPM_CRB_MMIO_SIZE,
+            )
+            .map_err(Error::BusError)?;
+        self.tpm = Some(tpm);
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Register the flash holding the variable store of the firmware at `addr`.
+    pub fn register_mmio_pflash(&mut self, pflash: Arc<Mutex<Pflash>>, addr: u64) -> Result<()> {
+        // Like the TPM, the flash is outside the virtio slots.
+        let len = pflash.lock().expect("Poisoned lock").len();
+        self.bus
+            .insert(pflash.clone(), addr, len)
+            .map_err(Error::BusError)?;
+        self.pflash = Some(pflash);
+        Ok(())
+    }
+
     /// Register a boot timer device.
     pub fn register_mmio_boot_timer(&mut self, device: BootTimer) -> Result<()> {
         // Attach a new boot timer device.
@@ -372,8 +739,7 @@ impl MMIODeviceManager {
             let virtio_device = busdev
                 .lock()
                 .expect("Poisoned lock")
-                .as_any()
-                .downcast_ref::<MmioTransport>()
+                .virtio_transport()
                 .expect("Unexpected BusDevice type")
                 .device();
             let mut dev = virtio_device.lock().expect("Poisoned lock");
@@ -389,16 +755,30 @@ impl MMIODeviceManager {
     }
 
     /// Artificially kick devices as if they had external events.
+    #[cfg_attr(
+        not(any(
+            feature = "balloon",
+            feature = "block",
+            feature = "net",
+            feature = "null-devices",
+            feature = "virtio-console",
+            feature = "virtio-mem",
+            feature = "virtio-pmem",
+            feature = "virtio-rng"
+        )),
+        allow(unused_variables, unused_mut, clippy::match_single_binding)
+    )]
     pub fn kick_devices(&self) {
         info!("Artificially kick devices.");
         let _: Result<()> = self.for_each_device(|devtype, id, _, bus_dev| {
             // We only kick virtio devices for now.
             if let DeviceType::Virtio(virtio_type) = *devtype {
                 let bus_dev = bus_dev.lock().expect("Poisoned lock");
-                // Virtio devices are guaranteed MmioTransport.
-                let mmio_dev = bus_dev.as_any().downcast_ref::<MmioTransport>().unwrap();
+                // Virtio devices are guaranteed to have a transport.
+                let mmio_dev = bus_dev.virtio_transport().unwrap();
                 let mut virtio = mmio_dev.locked_device();
                 match virtio_type {
+                    #[cfg(feature = "balloon")]
                     TYPE_BALLOON => {
                         info!("kick balloon {}.", id);
                         let balloon = virtio.as_mut_any().downcast_mut::<Balloon>().unwrap();
@@ -409,6 +789,7 @@ impl MMIODeviceManager {
                             balloon.process_virtio_queues();
                         }
                     }
+                    #[cfg(feature = "block")]
                     TYPE_BLOCK => {
                         info!("kick block {}.", id);
                         let block = virtio.as_mut_any().downcast_mut::<Block>().unwrap();
@@ -420,6 +801,7 @@ impl MMIODeviceManager {
                             block.process_virtio_queues();
                         }
                     }
+                    #[cfg(feature = "net")]
                     TYPE_NET => {
                         info!("kick net {}.", id);
                         let net = virtio.as_mut_any().downcast_mut::<Net>().unwrap();
@@ -431,12 +813,69 @@ impl MMIODeviceManager {
                             net.process_virtio_queues();
                         }
                     }
+                    #[cfg(feature = "virtio-mem")]
+                    TYPE_MEM => {
+                        info!("kick virtio-mem {}.", id);
+                        let virtio_mem = virtio.as_mut_any().downcast_mut::<VirtioMem>().unwrap();
+                        // If device is activated, kick the queue to answer any plug or unplug
+                        // requests left pending when the snapshot was taken.
+                        if virtio_mem.is_activated() {
+                            virtio_mem.process_virtio_queues();
+                        }
+                    }
+                    #[cfg(feature = "virtio-pmem")]
+                    TYPE_PMEM => {
+                        info!("kick pmem {}.", id);
+                        let pmem = virtio.as_mut_any().downcast_mut::<Pmem>().unwrap();
+                        // If device is activated, kick the queue to answer any flush requests
+                        // left pending when the snapshot was taken.
+                        if pmem.is_activated() {
+                            pmem.process_virtio_queues();
+                        }
+                    }
+                    #[cfg(feature = "virtio-rng")]
+                    TYPE_RNG => {
+                        info!("kick entropy {}.", id);
+                        let entropy = virtio.as_mut_any().downcast_mut::<Entropy>().unwrap();
+                        // If device is activated, kick the queue to fill any buffers the guest
+                        // queued before the snapshot was taken.
+                        if entropy.is_activated() {
+                            entropy.process_virtio_queues();
+                        }
+                    }
+                    #[cfg(feature = "virtio-console")]
+                    TYPE_CONSOLE => {
+                        info!("kick console {}.", id);
+                        let console = virtio.as_mut_any().downcast_mut::<Console>().unwrap();
+                        // If device is activated, kick the queues to process the control
+                        // messages and the output the guest queued before the snapshot was
+                        // taken.
+                        if console.is_activated() {
+                            console.process_virtio_queues();
+                        }
+                    }
+                    #[cfg(feature = "virtio-fs")]
+                    TYPE_FS => {
+                        // The queues of the shared filesystems are processed by their
+                        // vhost-user backends, so there is nothing to kick.
+                    }
+                    #[cfg(feature = "vsock")]
                     TYPE_VSOCK => {
                         // Vsock has complicated protocol that isn't resilient to any packet loss,
                         // so for Vsock we don't support connection persistence through snapshot.
                         // Any in-flight packets or events are simply lost.
                         // Vsock is restored 'empty'.
                     }
+                    #[cfg(feature = "null-devices")]
+                    _ if NullDeviceType::from_virtio_type(virtio_type).is_some() => {
+                        info!("kick null device {}.", id);
+                        let null_device = virtio.as_mut_any().downcast_mut::<NullDevice>().unwrap();
+                        // If device is activated, kick the queues to acknowledge any requests
+                        // left pending when the snapshot was taken.
+                        if null_device.is_activated() {
+                            null_device.process_virtio_queues();
+                        }
+                    }
                     _ => (),
                 }
             };
@@ -445,6 +884,25 @@ impl MMIODeviceManager {
     }
 }
 
+/// Maps the backing file of a persistent memory device into the guest physical address space,
+/// at the address previously set on the device, using the KVM memory slot `slot`.
+#[cfg(feature = "virtio-pmem")]
+pub fn register_pmem_memory(vm: &VmFd, slot: u32, pmem: &Pmem) -> Result<()> {
+    let memory_region = kvm_userspace_memory_region {
+        slot,
+        guest_phys_addr: pmem.guest_address().0,
+        memory_size: pmem.size(),
+        userspace_addr: pmem.host_address(),
+        flags: if pmem.is_read_only() {
+            KVM_MEM_READONLY
+        } else {
+            0
+        },
+    };
+    // Safe because the mapping is owned by the device, which lives as long as the guest.
+    unsafe { vm.set_user_memory_region(memory_region) }.map_err(Error::RegisterPmemMemory)
+}
+
 #[cfg(target_arch = "aarch64")]
 impl DeviceInfoForFDT for MMIODeviceInfo {
     fn addr(&self) -> u64 {
@@ -652,9 +1110,19 @@ mod tests {
                 Error::InternalDeviceError(_) => format!("{}{:?}", e, e),
                 Error::InvalidInput => format!("{}{:?}", e, e),
                 Error::IrqsExhausted => format!("{}{:?}", e, e),
+                #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+                Error::KvmVfioDevice(_) => format!("{}{:?}", e, e),
+                Error::Pci(_) => format!("{}{:?}", e, e),
+                Error::PciDisabled => format!("{}{:?}", e, e),
+                Error::PciMsiSender(_) => format!("{}{:?}", e, e),
                 Error::RegisterIoEvent(_) => format!("{}{:?}", e, e),
                 Error::RegisterIrqFd(_) => format!("{}{:?}", e, e),
+                Error::RegisterPmemMemory(_) => format!("{}{:?}", e, e),
+                #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+                Error::RegisterVfioMemory(_) => format!("{}{:?}", e, e),
                 Error::UpdateFailed => format!("{}{:?}", e, e),
+                #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+                Error::Vfio(_) => format!("{}{:?}", e, e),
             };
             assert!(!msg.is_empty());
         };
@@ -666,9 +1134,19 @@ mod tests {
         check_fmt_err(Error::InternalDeviceError(String::new()));
         check_fmt_err(Error::InvalidInput);
         check_fmt_err(Error::IrqsExhausted);
+        #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+        check_fmt_err(Error::KvmVfioDevice(errno::Error::new(0)));
+        check_fmt_err(Error::Pci(devices::pci::Error::BarsFull));
+        check_fmt_err(Error::PciDisabled);
+        check_fmt_err(Error::PciMsiSender(io::Error::from_raw_os_error(0)));
         check_fmt_err(Error::RegisterIoEvent(errno::Error::new(0)));
         check_fmt_err(Error::RegisterIrqFd(errno::Error::new(0)));
+        check_fmt_err(Error::RegisterPmemMemory(errno::Error::new(0)));
+        #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+        check_fmt_err(Error::RegisterVfioMemory(errno::Error::new(0)));
         check_fmt_err(Error::UpdateFailed);
+        #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+        check_fmt_err(Error::Vfio(devices::vfio::Error::NoMsix));
     }
 
     #[test]
@@ -810,4 +1288,104 @@ mod tests {
         };
         device_manager.slot_sanity_check(&slot).unwrap_err();
     }
+
+    #[test]
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    fn test_register_tpm() {
+        use devices::tpm::SoftwareTpm;
+        use utils::tempfile::TempFile;
+
+        let mut device_manager =
+            MMIODeviceManager::new(0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
+        assert!(device_manager.tpm.is_none());
+
+        let state_file = TempFile::new().unwrap();
+        let backend = SoftwareTpm::new(state_file.as_path()).unwrap();
+        let tpm = Arc::new(Mutex::new(TpmCrb::new(
+            Box::new(backend),
+            arch::x86_64::layout::TPM_CRB_START,
+        )));
+        device_manager.register_mmio_tpm(tpm.clone()).unwrap();
+        assert!(device_manager.tpm.is_some());
+        // The TPM doesn't take a virtio slot.
+        assert!(device_manager.get_device_info().is_empty());
+
+        // The interface ID register is reachable on the bus.
+        let mut data = [0u8; 4];
+        assert!(device_manager
+            .bus
+            .read(arch::x86_64::layout::TPM_CRB_START + 0x30, &mut data));
+        assert_eq!(u32::from_le_bytes(data) & 0xf, 1);
+
+        // The TPM can only be registered once.
+        assert!(device_manager.register_mmio_tpm(tpm).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_register_pflash() {
+        use devices::legacy::PFLASH_BLOCK_SIZE;
+        use utils::tempfile::TempFile;
+
+        let mut device_manager =
+            MMIODeviceManager::new(0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
+        assert!(device_manager.pflash.is_none());
+
+        let store = TempFile::new().unwrap();
+        store.as_file().set_len(PFLASH_BLOCK_SIZE).unwrap();
+        let pflash = Arc::new(Mutex::new(
+            Pflash::new(store.as_file().try_clone().unwrap()).unwrap(),
+        ));
+        let addr = 0xffc0_0000;
+        device_manager
+            .register_mmio_pflash(pflash.clone(), addr)
+            .unwrap();
+        assert!(device_manager.pflash.is_some());
+        assert!(device_manager.get_device_info().is_empty());
+
+        // The store is reachable on the bus, up to its end.
+        let mut data = [0u8; 1];
+        assert!(device_manager
+            .bus
+            .read(addr + PFLASH_BLOCK_SIZE - 1, &mut data));
+        assert!(!device_manager.bus.read(addr + PFLASH_BLOCK_SIZE, &mut data));
+
+        assert!(device_manager.register_mmio_pflash(pflash, addr).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_register_pci_watchdog() {
+        let guest_mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), 0x1000)]).unwrap();
+        let vm = builder::setup_kvm_vm(&guest_mem, false).unwrap();
+        let mut device_manager =
+            MMIODeviceManager::new(0xd000_0000, (arch::IRQ_BASE, arch::IRQ_MAX));
+
+        // The watchdog needs the PCI root bus.
+        assert!(device_manager.next_free_pci_slot().is_err());
+        device_manager.enable_pci(vm.fd()).unwrap();
+
+        let (slot, bar_addr) = device_manager.next_free_pci_slot().unwrap();
+        assert_eq!(slot, 1);
+        assert_eq!(
+            bar_addr,
+            arch::x86_64::layout::PCI_MMIO_START + VIRTIO_PCI_BAR_SIZE
+        );
+        let watchdog = Arc::new(Mutex::new(Watchdog::new(slot, bar_addr).unwrap()));
+        device_manager
+            .register_pci_watchdog(watchdog.clone())
+            .unwrap();
+        assert!(device_manager.watchdog.is_some());
+        assert!(device_manager.get_device_info().is_empty());
+        assert_eq!(device_manager.next_free_pci_slot().unwrap().0, 2);
+
+        // The registers are reachable on the bus, up to the end of the BAR.
+        let mut data = [0u8; 2];
+        assert!(device_manager.bus.read(bar_addr + 0xc, &mut data));
+        assert!(!device_manager
+            .bus
+            .read(bar_addr + WATCHDOG_BAR_SIZE, &mut data));
+
+        assert!(device_manager.register_pci_watchdog(watchdog).is_err());
+    }
 }
@@ -9,5 +9,8 @@
 pub mod legacy;
 /// Memory Mapped I/O Manager.
 pub mod mmio;
+/// PCI root bus manager.
+#[cfg(target_arch = "x86_64")]
+pub mod pci;
 /// Device managers (de)serialization support.
 pub mod persist;
@@ -0,0 +1,103 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::{Arc, Mutex};
+
+use devices::pci::{self, MsiSender, PciConfigIo, PciConfigMmio, PciRoot};
+use kvm_bindings::{kvm_msi, KVMIO};
+use kvm_ioctls::VmFd;
+use utils::ioctl::ioctl_with_ref;
+use utils::{ioctl_expr, ioctl_ioc_nr, ioctl_iow_nr};
+
+// `kvm-ioctls` does not wrap this ioctl, so we define it here.
+ioctl_iow_nr!(KVM_SIGNAL_MSI, KVMIO, 0xa5, kvm_msi);
+
+/// Delivers the MSI-X messages of the PCI devices through the in-kernel irqchip.
+pub struct KvmMsiSender {
+    // A duplicate of the VM file descriptor, which the devices can't borrow.
+    vm: File,
+}
+
+impl KvmMsiSender {
+    /// Creates a sender injecting the messages in the VM of `vm`.
+    pub fn new(vm: &VmFd) -> io::Result<Self> {
+        // Safe because we check the result, and own the new descriptor.
+        let fd = unsafe { libc::dup(vm.as_raw_fd()) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safe because the descriptor is valid and nothing else owns it.
+        Ok(KvmMsiSender {
+            vm: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+}
+
+impl MsiSender for KvmMsiSender {
+    fn send_msi(&self, address: u64, data: u32) -> io::Result<()> {
+        let msi = kvm_msi {
+            address_lo: address as u32,
+            address_hi: (address >> 32) as u32,
+            data,
+            ..Default::default()
+        };
+        // Safe because we know that our file is a VM fd, we know the kernel will only read
+        // the correct amount of memory from our pointer, and we verify the return result.
+        let ret = unsafe { ioctl_with_ref(&self.vm, KVM_SIGNAL_MSI(), &msi) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Holds the PCI root bus, on which the virtio-pci devices are plugged.
+pub struct PciDeviceManager {
+    root: Arc<Mutex<PciRoot>>,
+    msi_sender: Arc<dyn MsiSender>,
+}
+
+impl PciDeviceManager {
+    /// Creates an empty root bus, whose devices interrupt the guest of `vm`.
+    pub fn new(vm: &VmFd) -> io::Result<Self> {
+        Ok(PciDeviceManager {
+            root: Arc::new(Mutex::new(PciRoot::new())),
+            msi_sender: Arc::new(KvmMsiSender::new(vm)?),
+        })
+    }
+
+    /// Creates the ECAM window of the root bus, to be put on the MMIO bus.
+    pub fn config_mmio(&self) -> Arc<Mutex<PciConfigMmio>> {
+        Arc::new(Mutex::new(PciConfigMmio::new(self.root.clone())))
+    }
+
+    /// Creates the legacy configuration ports of the root bus, to be put on the PIO bus.
+    pub fn config_io(&self) -> Arc<Mutex<PciConfigIo>> {
+        Arc::new(Mutex::new(PciConfigIo::new(self.root.clone())))
+    }
+
+    /// Returns the first free slot of the root bus.
+    pub fn next_free_slot(&self) -> Result<u8, pci::Error> {
+        self.root.lock().expect("Poisoned lock").next_free_slot()
+    }
+
+    /// Plugs `device` in `slot` of the root bus.
+    pub fn add_device_at(
+        &self,
+        slot: u8,
+        device: Arc<Mutex<dyn pci::PciDevice>>,
+    ) -> Result<(), pci::Error> {
+        self.root
+            .lock()
+            .expect("Poisoned lock")
+            .add_device_at(slot, device)
+    }
+
+    /// Returns the sender through which the devices deliver their MSI-X messages.
+    pub fn msi_sender(&self) -> Arc<dyn MsiSender> {
+        self.msi_sender.clone()
+    }
+}
@@ -6,44 +6,115 @@
 // Currently only supports x86_64.
 #![cfg(target_arch = "x86_64")]
 
+#[cfg(feature = "block")]
 use std::io;
 use std::result::Result;
 use std::sync::{Arc, Mutex};
 
 use super::mmio::*;
 
+#[cfg(feature = "balloon")]
 use devices::virtio::balloon::persist::{BalloonConstructorArgs, BalloonState};
+#[cfg(feature = "balloon")]
 use devices::virtio::balloon::{Balloon, Error as BalloonError};
+#[cfg(feature = "block")]
 use devices::virtio::block::persist::{BlockConstructorArgs, BlockState};
+#[cfg(feature = "block")]
 use devices::virtio::block::Block;
+#[cfg(feature = "virtio-console")]
+use devices::virtio::console::persist::{ConsoleConstructorArgs, ConsoleState};
+#[cfg(feature = "virtio-console")]
+use devices::virtio::console::{Console, Error as ConsoleError};
+#[cfg(feature = "virtio-mem")]
+use devices::virtio::mem::persist::{VirtioMemConstructorArgs, VirtioMemState};
+#[cfg(feature = "virtio-mem")]
+use devices::virtio::mem::{Error as VirtioMemError, VirtioMem};
+#[cfg(feature = "net")]
 use devices::virtio::net::persist::{Error as NetError, NetConstructorArgs, NetState};
+#[cfg(feature = "net")]
 use devices::virtio::net::Net;
-use devices::virtio::persist::{MmioTransportConstructorArgs, MmioTransportState};
+#[cfg(feature = "null-devices")]
+use devices::virtio::null::persist::{NullDeviceConstructorArgs, NullDeviceState};
+#[cfg(feature = "null-devices")]
+use devices::virtio::null::{Error as NullDeviceError, NullDevice, NullDeviceType};
+use devices::virtio::persist::{
+    MmioTransportConstructorArgs, MmioTransportState, VirtioPciDeviceConstructorArgs,
+    VirtioPciDeviceState,
+};
+#[cfg(feature = "virtio-pmem")]
+use devices::virtio::pmem::persist::{PmemConstructorArgs, PmemState};
+#[cfg(feature = "virtio-pmem")]
+use devices::virtio::pmem::{Error as PmemError, Pmem};
+#[cfg(feature = "virtio-rng")]
+use devices::virtio::rng::persist::{EntropyConstructorArgs, EntropyState};
+#[cfg(feature = "virtio-rng")]
+use devices::virtio::rng::{Entropy, Error as EntropyError};
+#[cfg(feature = "vsock")]
 use devices::virtio::vsock::persist::{VsockConstructorArgs, VsockState, VsockUdsConstructorArgs};
+#[cfg(feature = "vsock")]
 use devices::virtio::vsock::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
-use devices::virtio::{
-    MmioTransport, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET, TYPE_VSOCK,
-};
+#[cfg(feature = "balloon")]
+use devices::virtio::TYPE_BALLOON;
+#[cfg(feature = "block")]
+use devices::virtio::TYPE_BLOCK;
+#[cfg(feature = "virtio-console")]
+use devices::virtio::TYPE_CONSOLE;
+#[cfg(feature = "virtio-fs")]
+use devices::virtio::TYPE_FS;
+#[cfg(feature = "virtio-mem")]
+use devices::virtio::TYPE_MEM;
+#[cfg(feature = "net")]
+use devices::virtio::TYPE_NET;
+#[cfg(feature = "virtio-pmem")]
+use devices::virtio::TYPE_PMEM;
+#[cfg(feature = "virtio-rng")]
+use devices::virtio::TYPE_RNG;
+#[cfg(feature = "vsock")]
+use devices::virtio::TYPE_VSOCK;
+use devices::virtio::{MmioTransport, VirtioDevice, VirtioPciDevice};
 use kvm_ioctls::VmFd;
+#[cfg(feature = "vsock")]
+use logger::error;
 use polly::event_manager::{Error as EventMgrError, EventManager, Subscriber};
+use polly::worker::{Error as WorkerError, EventWorker};
 use snapshot::Persist;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
+#[cfg(feature = "virtio-pmem")]
+use vm_memory::GuestMemory;
 use vm_memory::GuestMemoryMmap;
 
 /// Errors for (de)serialization of the MMIO device manager.
 #[derive(Debug)]
 pub enum Error {
+    #[cfg(feature = "balloon")]
     Balloon(BalloonError),
+    #[cfg(feature = "block")]
     Block(io::Error),
+    #[cfg(feature = "virtio-console")]
+    Console(ConsoleError),
     EventManager(EventMgrError),
     DeviceManager(super::mmio::Error),
+    #[cfg(feature = "virtio-rng")]
+    Entropy(EntropyError),
+    IoWorker(WorkerError),
+    #[cfg(feature = "virtio-mem")]
+    Mem(VirtioMemError),
     MmioTransport,
+    #[cfg(feature = "net")]
     Net(NetError),
+    #[cfg(feature = "null-devices")]
+    NullDevice(NullDeviceError),
+    Pci(devices::pci::Error),
+    #[cfg(feature = "virtio-pmem")]
+    Pmem(PmemError),
+    #[cfg(feature = "vsock")]
     Vsock(VsockError),
+    #[cfg(feature = "vsock")]
     VsockUnixBackend(VsockUnixBackendError),
 }
 
+#[cfg(feature = "balloon")]
 #[derive(Clone, Versionize)]
 /// Holds the state of a balloon device connected to the MMIO space.
 // NOTICE: Any changes to this structure require a snapshot version bump.
@@ -58,6 +129,7 @@ pub struct ConnectedBalloonState {
     pub mmio_slot: MMIODeviceInfo,
 }
 
+#[cfg(feature = "block")]
 #[derive(Clone, Versionize)]
 /// Holds the state of a block device connected to the MMIO space.
 // NOTICE: Any changes to this structure require a snapshot version bump.
@@ -72,6 +144,7 @@ pub struct ConnectedBlockState {
     pub mmio_slot: MMIODeviceInfo,
 }
 
+#[cfg(feature = "net")]
 #[derive(Clone, Versionize)]
 /// Holds the state of a net device connected to the MMIO space.
 // NOTICE: Any changes to this structure require a snapshot version bump.
@@ -86,6 +159,82 @@ pub struct ConnectedNetState {
     pub mmio_slot: MMIODeviceInfo,
 }
 
+#[cfg(feature = "virtio-mem")]
+#[derive(Clone, Versionize)]
+/// Holds the state of a virtio-mem device connected to the MMIO space.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct ConnectedMemState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: VirtioMemState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub mmio_slot: MMIODeviceInfo,
+}
+
+#[cfg(feature = "virtio-rng")]
+#[derive(Clone, Versionize)]
+/// Holds the state of an entropy device connected to the MMIO space.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct ConnectedEntropyState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: EntropyState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub mmio_slot: MMIODeviceInfo,
+}
+
+#[cfg(feature = "virtio-console")]
+#[derive(Clone, Versionize)]
+/// Holds the state of a console device connected to the MMIO space.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct ConnectedConsoleState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: ConsoleState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub mmio_slot: MMIODeviceInfo,
+}
+
+#[cfg(feature = "null-devices")]
+#[derive(Clone, Versionize)]
+/// Holds the state of a null device connected to the MMIO space.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct ConnectedNullState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: NullDeviceState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub mmio_slot: MMIODeviceInfo,
+}
+
+#[cfg(feature = "virtio-pmem")]
+#[derive(Clone, Versionize)]
+/// Holds the state of a persistent memory device connected to the MMIO space.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct ConnectedPmemState {
+    /// Device identifier.
+    pub device_id: String,
+    /// Device state.
+    pub device_state: PmemState,
+    /// Mmio transport state.
+    pub transport_state: MmioTransportState,
+    /// VmmResources.
+    pub mmio_slot: MMIODeviceInfo,
+}
+
+#[cfg(feature = "vsock")]
 #[derive(Clone, Versionize)]
 /// Holds the state of a vsock device connected to the MMIO space.
 // NOTICE: Any changes to this structure require a snapshot version bump.
@@ -100,22 +249,70 @@ pub struct ConnectedVsockState {
     pub mmio_slot: MMIODeviceInfo,
 }
 
+#[derive(Clone, Versionize)]
+/// Holds the PCI state of a virtio device plugged on the PCI root bus.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct ConnectedPciDeviceState {
+    /// Type of the virtio device.
+    pub virtio_type: u32,
+    /// Device identifier.
+    pub device_id: String,
+    /// PCI function state.
+    pub device_state: VirtioPciDeviceState,
+}
+
+#[derive(Clone, Versionize)]
+/// Holds the state of the PCI root bus.
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct PciState {
+    /// States of the virtio-pci devices, whose virtio states are saved with the other devices.
+    pub devices: Vec<ConnectedPciDeviceState>,
+}
+
 #[derive(Clone, Versionize)]
 /// Holds the device states.
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct DeviceStates {
     /// Block device states.
+    #[cfg(feature = "block")]
     pub block_devices: Vec<ConnectedBlockState>,
     /// Net device states.
+    #[cfg(feature = "net")]
     pub net_devices: Vec<ConnectedNetState>,
     /// Vsock device state.
+    #[cfg(feature = "vsock")]
     pub vsock_device: Option<ConnectedVsockState>,
     /// Balloon device state.
+    #[cfg(feature = "balloon")]
     #[version(start = 2, ser_fn = "balloon_serialize")]
     pub balloon_device: Option<ConnectedBalloonState>,
+    /// Null device states.
+    #[cfg(feature = "null-devices")]
+    #[version(start = 2, ser_fn = "null_devices_serialize")]
+    pub null_devices: Vec<ConnectedNullState>,
+    /// Virtio-mem device state.
+    #[cfg(feature = "virtio-mem")]
+    #[version(start = 2, ser_fn = "mem_serialize")]
+    pub mem_device: Option<ConnectedMemState>,
+    /// Entropy device state.
+    #[cfg(feature = "virtio-rng")]
+    #[version(start = 2, ser_fn = "entropy_serialize")]
+    pub entropy_device: Option<ConnectedEntropyState>,
+    /// Persistent memory device states.
+    #[cfg(feature = "virtio-pmem")]
+    #[version(start = 2, ser_fn = "pmem_serialize")]
+    pub pmem_devices: Vec<ConnectedPmemState>,
+    /// Console device state.
+    #[cfg(feature = "virtio-console")]
+    #[version(start = 2, ser_fn = "console_serialize")]
+    pub console_device: Option<ConnectedConsoleState>,
+    /// PCI root bus state, if the virtio devices are plugged on it.
+    #[version(start = 2, ser_fn = "pci_serialize")]
+    pub pci: Option<PciState>,
 }
 
 impl DeviceStates {
+    #[cfg(feature = "balloon")]
     fn balloon_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
         if target_version < 2 && self.balloon_device.is_some() {
             return Err(VersionizeError::Semantic(
@@ -125,12 +322,95 @@ impl DeviceStates {
 
         Ok(())
     }
+
+    #[cfg(feature = "null-devices")]
+    fn null_devices_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !self.null_devices.is_empty() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the null devices.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "virtio-mem")]
+    fn mem_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.mem_device.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the virtio-mem device.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "virtio-rng")]
+    fn entropy_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.entropy_device.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the entropy device.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "virtio-pmem")]
+    fn pmem_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !self.pmem_devices.is_empty() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the virtio-pmem device.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "virtio-console")]
+    fn console_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.console_device.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the virtio-console device.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn pci_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.pci.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the virtio-pci transport.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub struct MMIODevManagerConstructorArgs<'a> {
     pub mem: GuestMemoryMmap,
     pub vm: &'a VmFd,
     pub event_manager: &'a mut EventManager,
+    pub mmio_layout: arch::MmioLayout,
+    /// The threads the block and network devices are spread across, if any.
+    pub io_workers: &'a [EventWorker],
+}
+
+// Registers `device` with `io_worker`, or leaves it to the event manager when there is none.
+#[cfg(any(feature = "block", feature = "net"))]
+fn subscribe_io_device<T: Subscriber + Send + 'static>(
+    io_worker: Option<&EventWorker>,
+    device: Arc<Mutex<T>>,
+) -> Result<Option<Arc<Mutex<dyn Subscriber>>>, Error> {
+    match io_worker {
+        Some(io_worker) => io_worker
+            .add_subscriber(device)
+            .map(|_| None)
+            .map_err(Error::IoWorker),
+        None => Ok(Some(device)),
+    }
 }
 
 impl<'a> Persist<'a> for MMIODeviceManager {
@@ -138,11 +418,43 @@ impl<'a> Persist<'a> for MMIODeviceManager {
     type ConstructorArgs = MMIODevManagerConstructorArgs<'a>;
     type Error = Error;
 
+    // Without any snapshotted virtio device, the transport states are only walked over.
+    #[cfg_attr(
+        not(any(
+            feature = "balloon",
+            feature = "block",
+            feature = "net",
+            feature = "null-devices",
+            feature = "virtio-console",
+            feature = "virtio-mem",
+            feature = "virtio-pmem",
+            feature = "virtio-rng",
+            feature = "vsock"
+        )),
+        allow(unused_variables, clippy::match_single_binding)
+    )]
     fn save(&self) -> Self::State {
         let mut states = DeviceStates {
+            #[cfg(feature = "balloon")]
             balloon_device: None,
+            #[cfg(feature = "block")]
             block_devices: Vec::new(),
+            #[cfg(feature = "virtio-console")]
+            console_device: None,
+            #[cfg(feature = "virtio-rng")]
+            entropy_device: None,
+            #[cfg(feature = "virtio-mem")]
+            mem_device: None,
+            #[cfg(feature = "net")]
             net_devices: Vec::new(),
+            #[cfg(feature = "null-devices")]
+            null_devices: Vec::new(),
+            pci: self.pci.as_ref().map(|_| PciState {
+                devices: Vec::new(),
+            }),
+            #[cfg(feature = "virtio-pmem")]
+            pmem_devices: Vec::new(),
+            #[cfg(feature = "vsock")]
             vsock_device: None,
         };
         let _: Result<(), ()> = self.for_each_device(|devtype, devid, devinfo, bus_dev| {
@@ -153,15 +465,27 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             let locked_bus_dev = bus_dev.lock().expect("Poisoned lock");
             let mmio_transport = locked_bus_dev
-                .as_any()
-                // Only MmioTransport implements BusDevice on x86_64 at this point.
-                .downcast_ref::<MmioTransport>()
+                .virtio_transport()
                 .expect("Unexpected BusDevice type");
 
             let transport_state = mmio_transport.save();
 
-            let locked_device = mmio_transport.locked_device();
+            // The devices on the PCI root bus also have the state of their PCI function.
+            if let (Some(pci), Some(pci_device)) = (
+                states.pci.as_mut(),
+                locked_bus_dev.as_any().downcast_ref::<VirtioPciDevice>(),
+            ) {
+                pci.devices.push(ConnectedPciDeviceState {
+                    virtio_type: mmio_transport.locked_device().device_type(),
+                    device_id: devid.clone(),
+                    device_state: pci_device.save(),
+                });
+            }
+
+            #[cfg_attr(not(feature = "vsock"), allow(unused_mut))]
+            let mut locked_device = mmio_transport.locked_device();
             match locked_device.device_type() {
+                #[cfg(feature = "balloon")]
                 TYPE_BALLOON => {
                     let balloon_state = locked_device
                         .as_any()
@@ -175,6 +499,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         mmio_slot: devinfo.clone(),
                     });
                 }
+                #[cfg(feature = "block")]
                 TYPE_BLOCK => {
                     let block_state = locked_device
                         .as_any()
@@ -188,6 +513,63 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         mmio_slot: devinfo.clone(),
                     });
                 }
+                #[cfg(feature = "virtio-console")]
+                TYPE_CONSOLE => {
+                    let console_state = locked_device
+                        .as_any()
+                        .downcast_ref::<Console>()
+                        .unwrap()
+                        .save();
+                    states.console_device = Some(ConnectedConsoleState {
+                        device_id: devid.clone(),
+                        device_state: console_state,
+                        transport_state,
+                        mmio_slot: devinfo.clone(),
+                    });
+                }
+                #[cfg(feature = "virtio-pmem")]
+                TYPE_PMEM => {
+                    let pmem_state = locked_device
+                        .as_any()
+                        .downcast_ref::<Pmem>()
+                        .unwrap()
+                        .save();
+                    states.pmem_devices.push(ConnectedPmemState {
+                        device_id: devid.clone(),
+                        device_state: pmem_state,
+                        transport_state,
+                        mmio_slot: devinfo.clone(),
+                    });
+                }
+                #[cfg(feature = "virtio-rng")]
+                TYPE_RNG => {
+                    let entropy_state = locked_device
+                        .as_any()
+                        .downcast_ref::<Entropy>()
+                        .unwrap()
+                        .save();
+                    states.entropy_device = Some(ConnectedEntropyState {
+                        device_id: devid.clone(),
+                        device_state: entropy_state,
+                        transport_state,
+                        mmio_slot: devinfo.clone(),
+                    });
+                }
+                #[cfg(feature = "virtio-mem")]
+                TYPE_MEM => {
+                    let mem_state = locked_device
+                        .as_any()
+                        .downcast_ref::<VirtioMem>()
+                        .unwrap()
+                        .save();
+                    states.mem_device = Some(ConnectedMemState {
+                        device_id: devid.clone(),
+                        device_state: mem_state,
+                        transport_state,
+                        mmio_slot: devinfo.clone(),
+                    });
+                }
+                #[cfg(feature = "net")]
                 TYPE_NET => {
                     let net_state = locked_device.as_any().downcast_ref::<Net>().unwrap().save();
                     states.net_devices.push(ConnectedNetState {
@@ -197,12 +579,19 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         mmio_slot: devinfo.clone(),
                     });
                 }
+                #[cfg(feature = "vsock")]
                 TYPE_VSOCK => {
                     let vsock = locked_device
-                        .as_any()
+                        .as_mut_any()
                         // Currently, VsockUnixBackend is the only implementation of VsockBackend.
-                        .downcast_ref::<Vsock<VsockUnixBackend>>()
+                        .downcast_mut::<Vsock<VsockUnixBackend>>()
                         .unwrap();
+                    // The vsock connections don't survive a snapshot, so they are torn down
+                    // before saving the device state. This way, both the resumed and the
+                    // restored microVMs start off without any.
+                    vsock.reset_connections().unwrap_or_else(|err| {
+                        error!("Failed to reset the vsock connections: {:?}", err);
+                    });
                     let vsock_state = VsockState {
                         backend: vsock.backend().save(),
                         frontend: vsock.save(),
@@ -214,6 +603,24 @@ impl<'a> Persist<'a> for MMIODeviceManager {
                         mmio_slot: devinfo.clone(),
                     });
                 }
+                #[cfg(feature = "null-devices")]
+                virtio_type if NullDeviceType::from_virtio_type(virtio_type).is_some() => {
+                    let null_device_state = locked_device
+                        .as_any()
+                        .downcast_ref::<NullDevice>()
+                        .unwrap()
+                        .save();
+                    states.null_devices.push(ConnectedNullState {
+                        device_id: devid.clone(),
+                        device_state: null_device_state,
+                        transport_state,
+                        mmio_slot: devinfo.clone(),
+                    });
+                }
+                // The shared filesystems are not snapshotted, as the state of their vhost-user
+                // backends can't be saved. Snapshot creation is refused before getting here.
+                #[cfg(feature = "virtio-fs")]
+                TYPE_FS => (),
                 _ => unreachable!(),
             };
 
@@ -222,25 +629,52 @@ impl<'a> Persist<'a> for MMIODeviceManager {
         states
     }
 
+    #[cfg_attr(
+        not(any(
+            feature = "balloon",
+            feature = "block",
+            feature = "net",
+            feature = "null-devices",
+            feature = "virtio-console",
+            feature = "virtio-mem",
+            feature = "virtio-pmem",
+            feature = "virtio-rng",
+            feature = "vsock"
+        )),
+        allow(unused_variables, unused_mut)
+    )]
     fn restore(
         constructor_args: Self::ConstructorArgs,
         state: &Self::State,
     ) -> Result<Self, Self::Error> {
-        let mut dev_manager =
-            MMIODeviceManager::new(arch::MMIO_MEM_START, (arch::IRQ_BASE, arch::IRQ_MAX));
+        let mmio_layout = &constructor_args.mmio_layout;
+        let mut dev_manager = MMIODeviceManager::new(mmio_layout.start(), mmio_layout.irq_range());
         let mem = &constructor_args.mem;
         let vm = constructor_args.vm;
+        if state.pci.is_some() {
+            dev_manager.enable_pci(vm).map_err(Error::DeviceManager)?;
+        }
+        let pci_states = state.pci.as_ref();
 
         let mut restore_helper = |device: Arc<Mutex<dyn VirtioDevice>>,
-                                  as_subscriber: Arc<Mutex<dyn Subscriber>>,
+                                  as_subscriber: Option<Arc<Mutex<dyn Subscriber>>>,
                                   id: &String,
                                   state: &MmioTransportState,
                                   slot: &MMIODeviceInfo,
                                   event_manager: &mut EventManager|
          -> Result<(), Self::Error> {
-            dev_manager
-                .slot_sanity_check(slot)
-                .map_err(Error::DeviceManager)?;
+            let virtio_type = device.lock().expect("Poisoned lock").device_type();
+            let pci_state = pci_states.and_then(|pci| {
+                pci.devices.iter().find(|pci_device| {
+                    pci_device.virtio_type == virtio_type && pci_device.device_id == *id
+                })
+            });
+            // The BARs of the PCI functions are outside the MMIO device slots.
+            if pci_state.is_none() {
+                dev_manager
+                    .slot_sanity_check(slot)
+                    .map_err(Error::DeviceManager)?;
+            }
 
             let restore_args = MmioTransportConstructorArgs {
                 mem: mem.clone(),
@@ -248,15 +682,45 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             };
             let mmio_transport =
                 MmioTransport::restore(restore_args, state).map_err(|()| Error::MmioTransport)?;
-            dev_manager
-                .register_mmio_virtio(vm, id.clone(), mmio_transport, slot)
-                .map_err(Error::DeviceManager)?;
+            if let Some(pci_state) = pci_state {
+                let msi_sender = dev_manager
+                    .pci
+                    .as_ref()
+                    .ok_or(Error::DeviceManager(super::mmio::Error::PciDisabled))?
+                    .msi_sender();
+                let restore_args = VirtioPciDeviceConstructorArgs {
+                    transport: mmio_transport,
+                    msi_sender,
+                };
+                let pci_device = Arc::new(Mutex::new(
+                    VirtioPciDevice::restore(restore_args, &pci_state.device_state)
+                        .map_err(Error::Pci)?,
+                ));
+                dev_manager
+                    .register_pci_virtio(vm, id.clone(), pci_device.clone())
+                    .map_err(Error::DeviceManager)?;
+                // The PCI function relays the interrupts of the device as MSI-X messages.
+                event_manager
+                    .add_subscriber(pci_device)
+                    .map_err(Error::EventManager)?;
+            } else {
+                dev_manager
+                    .register_mmio_virtio(vm, id.clone(), mmio_transport, slot)
+                    .map_err(Error::DeviceManager)?;
+            }
 
-            event_manager
-                .add_subscriber(as_subscriber)
-                .map_err(Error::EventManager)
+            match as_subscriber {
+                Some(as_subscriber) => event_manager
+                    .add_subscriber(as_subscriber)
+                    .map_err(Error::EventManager),
+                // The device was registered with an I/O worker.
+                None => Ok(()),
+            }
         };
+        #[cfg(any(feature = "block", feature = "net"))]
+        let mut io_workers = constructor_args.io_workers.iter().cycle();
 
+        #[cfg(feature = "balloon")]
         if let Some(balloon_state) = &state.balloon_device {
             let device = Arc::new(Mutex::new(
                 Balloon::restore(
@@ -268,7 +732,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                device,
+                Some(device),
                 &balloon_state.device_id,
                 &balloon_state.transport_state,
                 &balloon_state.mmio_slot,
@@ -276,6 +740,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
             )?;
         }
 
+        #[cfg(feature = "block")]
         for block_state in &state.block_devices {
             let device = Arc::new(Mutex::new(
                 Block::restore(
@@ -287,13 +752,14 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                device,
+                subscribe_io_device(io_workers.next(), device)?,
                 &block_state.device_id,
                 &block_state.transport_state,
                 &block_state.mmio_slot,
                 constructor_args.event_manager,
             )?;
         }
+        #[cfg(feature = "net")]
         for net_state in &state.net_devices {
             let device = Arc::new(Mutex::new(
                 Net::restore(
@@ -305,13 +771,112 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                device,
+                subscribe_io_device(io_workers.next(), device)?,
                 &net_state.device_id,
                 &net_state.transport_state,
                 &net_state.mmio_slot,
                 constructor_args.event_manager,
             )?;
         }
+        #[cfg(feature = "null-devices")]
+        for null_state in &state.null_devices {
+            let device = Arc::new(Mutex::new(
+                NullDevice::restore(
+                    NullDeviceConstructorArgs { mem: mem.clone() },
+                    &null_state.device_state,
+                )
+                .map_err(Error::NullDevice)?,
+            ));
+
+            restore_helper(
+                device.clone(),
+                Some(device),
+                &null_state.device_id,
+                &null_state.transport_state,
+                &null_state.mmio_slot,
+                constructor_args.event_manager,
+            )?;
+        }
+        #[cfg(feature = "virtio-mem")]
+        if let Some(mem_state) = &state.mem_device {
+            let device = Arc::new(Mutex::new(
+                VirtioMem::restore(
+                    VirtioMemConstructorArgs { mem: mem.clone() },
+                    &mem_state.device_state,
+                )
+                .map_err(Error::Mem)?,
+            ));
+
+            restore_helper(
+                device.clone(),
+                Some(device),
+                &mem_state.device_id,
+                &mem_state.transport_state,
+                &mem_state.mmio_slot,
+                constructor_args.event_manager,
+            )?;
+        }
+        #[cfg(feature = "virtio-rng")]
+        if let Some(entropy_state) = &state.entropy_device {
+            let device = Arc::new(Mutex::new(
+                Entropy::restore(
+                    EntropyConstructorArgs { mem: mem.clone() },
+                    &entropy_state.device_state,
+                )
+                .map_err(Error::Entropy)?,
+            ));
+
+            restore_helper(
+                device.clone(),
+                Some(device),
+                &entropy_state.device_id,
+                &entropy_state.transport_state,
+                &entropy_state.mmio_slot,
+                constructor_args.event_manager,
+            )?;
+        }
+        #[cfg(feature = "virtio-console")]
+        if let Some(console_state) = &state.console_device {
+            let device = Arc::new(Mutex::new(
+                Console::restore(
+                    ConsoleConstructorArgs { mem: mem.clone() },
+                    &console_state.device_state,
+                )
+                .map_err(Error::Console)?,
+            ));
+
+            restore_helper(
+                device.clone(),
+                Some(device),
+                &console_state.device_id,
+                &console_state.transport_state,
+                &console_state.mmio_slot,
+                constructor_args.event_manager,
+            )?;
+        }
+        #[cfg(feature = "virtio-pmem")]
+        for (index, pmem_state) in state.pmem_devices.iter().enumerate() {
+            let device = Pmem::restore(
+                PmemConstructorArgs { mem: mem.clone() },
+                &pmem_state.device_state,
+            )
+            .map_err(Error::Pmem)?;
+            // The mappings live outside the guest memory, so they are registered again here,
+            // in the memory slots following the guest memory ones.
+            super::mmio::register_pmem_memory(vm, mem.num_regions() as u32 + index as u32, &device)
+                .map_err(Error::DeviceManager)?;
+            let device = Arc::new(Mutex::new(device));
+
+            restore_helper(
+                device.clone(),
+                Some(device),
+                &pmem_state.device_id,
+                &pmem_state.transport_state,
+                &pmem_state.mmio_slot,
+                constructor_args.event_manager,
+            )?;
+        }
+        #[cfg(feature = "vsock")]
         if let Some(vsock_state) = &state.vsock_device {
             let ctor_args = VsockUdsConstructorArgs {
                 cid: vsock_state.device_state.frontend.cid,
@@ -331,7 +896,7 @@ impl<'a> Persist<'a> for MMIODeviceManager {
 
             restore_helper(
                 device.clone(),
-                device,
+                Some(device),
                 &vsock_state.device_id,
                 &vsock_state.transport_state,
                 &vsock_state.mmio_slot,
@@ -343,13 +908,32 @@ impl<'a> Persist<'a> for MMIODeviceManager {
     }
 }
 
-#[cfg(test)]
+// The tests below exercise every device type, so they need all the optional ones built in.
+#[cfg(all(
+    test,
+    feature = "balloon",
+    feature = "block",
+    feature = "net",
+    feature = "null-devices",
+    feature = "virtio-console",
+    feature = "virtio-mem",
+    feature = "virtio-pmem",
+    feature = "virtio-rng",
+    feature = "vsock"
+))]
 mod tests {
     use super::*;
     use crate::builder::tests::*;
     use crate::vmm_config::balloon::BalloonDeviceConfig;
+    use crate::vmm_config::console::tests::{socket_path, uds_port};
+    use crate::vmm_config::console::ConsoleConfig;
+    use crate::vmm_config::entropy::EntropyDeviceConfig;
+    use crate::vmm_config::memory_hotplug::MemoryHotplugConfig;
     use crate::vmm_config::net::NetworkInterfaceConfig;
-    use crate::vmm_config::vsock::VsockDeviceConfig;
+    use crate::vmm_config::null_device::NullDeviceConfig;
+    use crate::vmm_config::pmem::tests::{backing_file, default_config};
+    use crate::vmm_config::vsock::{VsockBackendType, VsockDeviceConfig};
+    use devices::virtio::pmem::PMEM_ALIGNMENT;
     use polly::event_manager::EventManager;
     use utils::tempfile::TempFile;
 
@@ -387,6 +971,57 @@ mod tests {
         }
     }
 
+    impl PartialEq for ConnectedConsoleState {
+        fn eq(&self, other: &ConnectedConsoleState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.transport_state == other.transport_state && self.mmio_slot == other.mmio_slot
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedConsoleState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedConsoleDevice {{ transport_state: {:?}, mmio_slot: {:?} }}",
+                self.transport_state, self.mmio_slot
+            )
+        }
+    }
+
+    impl PartialEq for ConnectedEntropyState {
+        fn eq(&self, other: &ConnectedEntropyState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.transport_state == other.transport_state && self.mmio_slot == other.mmio_slot
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedEntropyState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedEntropyDevice {{ transport_state: {:?}, mmio_slot: {:?} }}",
+                self.transport_state, self.mmio_slot
+            )
+        }
+    }
+
+    impl PartialEq for ConnectedMemState {
+        fn eq(&self, other: &ConnectedMemState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.transport_state == other.transport_state && self.mmio_slot == other.mmio_slot
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedMemState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedMemDevice {{ transport_state: {:?}, mmio_slot: {:?} }}",
+                self.transport_state, self.mmio_slot
+            )
+        }
+    }
+
     impl PartialEq for ConnectedNetState {
         fn eq(&self, other: &ConnectedNetState) -> bool {
             // Actual device state equality is checked by the device's tests.
@@ -404,6 +1039,40 @@ mod tests {
         }
     }
 
+    impl PartialEq for ConnectedNullState {
+        fn eq(&self, other: &ConnectedNullState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.transport_state == other.transport_state && self.mmio_slot == other.mmio_slot
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedNullState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedNullDevice {{ transport_state: {:?}, mmio_slot: {:?} }}",
+                self.transport_state, self.mmio_slot
+            )
+        }
+    }
+
+    impl PartialEq for ConnectedPmemState {
+        fn eq(&self, other: &ConnectedPmemState) -> bool {
+            // Actual device state equality is checked by the device's tests.
+            self.transport_state == other.transport_state && self.mmio_slot == other.mmio_slot
+        }
+    }
+
+    impl std::fmt::Debug for ConnectedPmemState {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "ConnectedPmemDevice {{ transport_state: {:?}, mmio_slot: {:?} }}",
+                self.transport_state, self.mmio_slot
+            )
+        }
+    }
+
     impl PartialEq for ConnectedVsockState {
         fn eq(&self, other: &ConnectedVsockState) -> bool {
             // Actual device state equality is checked by the device's tests.
@@ -425,7 +1094,12 @@ mod tests {
         fn eq(&self, other: &DeviceStates) -> bool {
             self.balloon_device == other.balloon_device
                 && self.block_devices == other.block_devices
+                && self.console_device == other.console_device
+                && self.entropy_device == other.entropy_device
+                && self.mem_device == other.mem_device
                 && self.net_devices == other.net_devices
+                && self.null_devices == other.null_devices
+                && self.pmem_devices == other.pmem_devices
                 && self.vsock_device == other.vsock_device
         }
     }
@@ -479,6 +1153,7 @@ mod tests {
         let mut version_map = VersionMap::new();
         // These need to survive so the restored blocks find them.
         let _block_files;
+        let pmem_file = backing_file(PMEM_ALIGNMENT);
         let mut tmp_sock_file = TempFile::new().unwrap();
         tmp_sock_file.remove().unwrap();
         // Set up a vmm with one of each device, and get the serialized DeviceStates.
@@ -492,6 +1167,7 @@ mod tests {
                 amount_mb: 123,
                 deflate_on_oom: false,
                 stats_polling_interval_s: 1,
+                free_page_reporting: false,
             };
             insert_balloon_device(&mut vmm, &mut cmdline, &mut event_manager, balloon_cfg);
             // Add a block device.
@@ -507,6 +1183,9 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: true,
+                vlan_id: None,
+                dhcp: None,
+                rate_limiter_group: None,
             };
             insert_net_device(
                 &mut vmm,
@@ -514,12 +1193,59 @@ mod tests {
                 &mut event_manager,
                 network_interface,
             );
+            // Add a null device.
+            let null_device_config = NullDeviceConfig {
+                device_id: String::from("snd0"),
+                device_type: NullDeviceType::Sound,
+            };
+            insert_null_device(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                null_device_config,
+            );
+            // Add a virtio-mem device.
+            let memory_hotplug_config = MemoryHotplugConfig {
+                total_size_mib: 128,
+                block_size_mib: 2,
+            };
+            insert_virtio_mem_device(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                memory_hotplug_config,
+            );
+            // Add a persistent memory device.
+            insert_pmem_device(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                default_config("pmem0", &pmem_file),
+            );
+            // Add an entropy device.
+            insert_entropy_device(
+                &mut vmm,
+                &mut cmdline,
+                &mut event_manager,
+                EntropyDeviceConfig::default(),
+            );
+            // Add a console device.
+            let console_config = ConsoleConfig {
+                ports: vec![uds_port("console", &socket_path(), true)],
+            };
+            insert_console_device(&mut vmm, &mut cmdline, &mut event_manager, console_config);
             // Add a vsock device.
             let vsock_dev_id = "vsock";
             let vsock_config = VsockDeviceConfig {
                 vsock_id: vsock_dev_id.to_string(),
                 guest_cid: 3,
+                backend: VsockBackendType::Unix,
                 uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+                mmds_port: None,
+                agent_port: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                port_mappings: Vec::new(),
             };
             insert_vsock_device(&mut vmm, &mut cmdline, &mut event_manager, vsock_config);
 
@@ -553,10 +1279,81 @@ mod tests {
             mem: vmm.guest_memory().clone(),
             vm: vmm.vm.fd(),
             event_manager: &mut event_manager,
+            mmio_layout: arch::MmioLayout::default(),
+            io_workers: &[],
+        };
+        let restored_dev_manager =
+            MMIODeviceManager::restore(restore_args, &device_states).unwrap();
+
+        assert_eq!(restored_dev_manager, original_mmio_device_manager);
+    }
+
+    #[test]
+    fn test_pci_device_persistence() {
+        let mut buf = vec![0; 16384];
+        let mut version_map = VersionMap::new();
+        let _block_files;
+        let original_mmio_device_manager = {
+            let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+            let mut vmm = default_vmm();
+            let mut cmdline = default_kernel_cmdline();
+            vmm.mmio_device_manager.enable_pci(vmm.vm.fd()).unwrap();
+            vmm.mmio_device_manager.virtio_pci = true;
+
+            let block_configs = vec![CustomBlockConfig::new(
+                String::from("root"),
+                true,
+                None,
+                true,
+            )];
+            _block_files =
+                insert_block_devices(&mut vmm, &mut cmdline, &mut event_manager, block_configs);
+
+            let device_states = vmm.mmio_device_manager.save();
+            let pci_devices = &device_states.pci.as_ref().unwrap().devices;
+            assert_eq!(pci_devices.len(), 1);
+            assert_eq!(pci_devices[0].virtio_type, TYPE_BLOCK);
+            assert_eq!(pci_devices[0].device_state.slot, 1);
+            assert_eq!(
+                device_states.serialize(&mut buf.as_mut_slice(), &version_map, 1),
+                Err(VersionizeError::Semantic(
+                    "Target version does not implement the virtio-pci transport.".to_string()
+                ))
+            );
+
+            version_map
+                .new_version()
+                .set_type_version(DeviceStates::type_id(), 2);
+            device_states
+                .serialize(&mut buf.as_mut_slice(), &version_map, 2)
+                .unwrap();
+            vmm.mmio_device_manager.soft_clone()
+        };
+
+        let mut event_manager = EventManager::new().expect("Unable to create EventManager");
+        let vmm = default_vmm();
+        let device_states: DeviceStates =
+            DeviceStates::deserialize(&mut buf.as_slice(), &version_map, 2).unwrap();
+        let restore_args = MMIODevManagerConstructorArgs {
+            mem: vmm.guest_memory().clone(),
+            vm: vmm.vm.fd(),
+            event_manager: &mut event_manager,
+            mmio_layout: arch::MmioLayout::default(),
+            io_workers: &[],
         };
         let restored_dev_manager =
             MMIODeviceManager::restore(restore_args, &device_states).unwrap();
 
         assert_eq!(restored_dev_manager, original_mmio_device_manager);
+        assert!(restored_dev_manager.pci.is_some());
+        let device = restored_dev_manager
+            .get_device(arch::DeviceType::Virtio(TYPE_BLOCK), "root")
+            .unwrap();
+        assert!(device
+            .lock()
+            .unwrap()
+            .as_any()
+            .downcast_ref::<VirtioPciDevice>()
+            .is_some());
     }
 }
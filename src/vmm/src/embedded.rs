@@ -0,0 +1,424 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Embeds microVMs in other Rust programs, without the API server and its HTTP socket.
+//!
+//! A `VmBuilder` gathers the configuration of the microVM, then boots it or restores it from a
+//! snapshot, giving a `MicroVm`. The program drives the devices of the microVM by calling
+//! `MicroVm::run` in a loop, and controls the microVM between the calls:
+//!
+//! ```ignore
+//! let mut builder = VmBuilder::new(instance_info);
+//! builder
+//!     .boot_source(boot_source)?
+//!     .machine_config(machine_config)?
+//!     .add_drive(rootfs)?
+//!     .on_event(|event| println!("{:?}", event.kind));
+//! let mut microvm = builder.boot(false)?;
+//! let exit_code = loop {
+//!     if let Some(exit_code) = microvm.run(100)? {
+//!         break exit_code;
+//!     }
+//! };
+//! microvm.teardown()?;
+//! ```
+//!
+//! The logger, the metrics and the lifecycle events of the VMM are process-wide, so a process
+//! embeds a single microVM at a time.
+
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+use logger::{update_metric_with_elapsed_time, METRICS};
+use polly::event_manager::{Error as EventManagerError, EventManager};
+use seccomp::BpfProgram;
+
+use crate::builder::build_microvm_for_boot;
+use crate::lifecycle::{EventCallback, LifecycleEvent, LIFECYCLE_EVENTS};
+#[cfg(target_arch = "x86_64")]
+use crate::persist::{create_snapshot, restore_from_snapshot, CreateSnapshotError};
+use crate::resources::{VmResources, VmmConfig};
+use crate::rpc_interface::VmmActionError;
+#[cfg(target_arch = "x86_64")]
+use crate::version_map::VERSION_MAP;
+use crate::vmm_config::boot_source::BootSourceConfig;
+#[cfg(feature = "block")]
+use crate::vmm_config::drive::BlockDeviceConfig;
+use crate::vmm_config::instance_info::InstanceInfo;
+use crate::vmm_config::logger::{init_logger, LoggerConfig};
+use crate::vmm_config::machine_config::VmConfig;
+use crate::vmm_config::metrics::{init_metrics, MetricsConfig};
+#[cfg(feature = "net")]
+use crate::vmm_config::net::NetworkInterfaceConfig;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
+#[cfg(feature = "vsock")]
+use crate::vmm_config::vsock::VsockDeviceConfig;
+use crate::Vmm;
+
+/// Errors associated with the embedded microVMs.
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration, the start or the control of the microVM failed.
+    Action(VmmActionError),
+    /// Cannot create the event manager, or run the events of the microVM.
+    EventManager(EventManagerError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+        match self {
+            Action(err) => write!(f, "{}", err),
+            EventManager(err) => write!(f, "Event manager error: {:?}", err),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Gathers the configuration of a microVM, then boots it or restores it from a snapshot.
+///
+/// Each configuration is checked right away, as the API server would check its request.
+pub struct VmBuilder {
+    instance_info: InstanceInfo,
+    vm_resources: VmResources,
+    seccomp_filter: BpfProgram,
+    callbacks: Vec<EventCallback>,
+    // Whether the microVM was configured for a boot, and thus cannot be restored from a snapshot.
+    boot_path: bool,
+}
+
+impl VmBuilder {
+    /// Creates a builder for a microVM with the default configuration. The logs carry the ID
+    /// and the application name of `instance_info`.
+    pub fn new(instance_info: InstanceInfo) -> Self {
+        VmBuilder {
+            instance_info,
+            vm_resources: VmResources::default(),
+            seccomp_filter: BpfProgram::new(),
+            callbacks: Vec::new(),
+            boot_path: false,
+        }
+    }
+
+    /// Configures the logger of the process.
+    pub fn logger(&mut self, config: LoggerConfig) -> Result<&mut Self> {
+        init_logger(config, &self.instance_info)
+            .map_err(|err| Error::Action(VmmActionError::Logger(err)))?;
+        Ok(self)
+    }
+
+    /// Configures the metrics of the process.
+    pub fn metrics(&mut self, config: MetricsConfig) -> Result<&mut Self> {
+        init_metrics(config).map_err(|err| Error::Action(VmmActionError::Metrics(err)))?;
+        Ok(self)
+    }
+
+    /// Sets the kernel, the initrd and the command line the microVM boots.
+    pub fn boot_source(&mut self, config: BootSourceConfig) -> Result<&mut Self> {
+        self.boot_path = true;
+        self.vm_resources
+            .set_boot_source(config)
+            .map_err(|err| Error::Action(VmmActionError::BootSource(err)))?;
+        Ok(self)
+    }
+
+    /// Sets the vCPUs and the guest memory of the microVM.
+    pub fn machine_config(&mut self, config: VmConfig) -> Result<&mut Self> {
+        self.boot_path = true;
+        self.vm_resources
+            .set_vm_config(&config)
+            .map_err(|err| Error::Action(VmmActionError::MachineConfig(err)))?;
+        Ok(self)
+    }
+
+    /// Adds a block device, or replaces the one with the same ID.
+    #[cfg(feature = "block")]
+    pub fn add_drive(&mut self, config: BlockDeviceConfig) -> Result<&mut Self> {
+        self.boot_path = true;
+        self.vm_resources
+            .set_block_device(config)
+            .map_err(|err| Error::Action(VmmActionError::DriveConfig(err)))?;
+        Ok(self)
+    }
+
+    /// Adds a network interface, or replaces the one with the same ID.
+    #[cfg(feature = "net")]
+    pub fn add_network_interface(&mut self, config: NetworkInterfaceConfig) -> Result<&mut Self> {
+        self.boot_path = true;
+        self.vm_resources
+            .build_net_device(config)
+            .map_err(|err| Error::Action(VmmActionError::NetworkConfig(err)))?;
+        Ok(self)
+    }
+
+    /// Sets the vsock device of the microVM.
+    #[cfg(feature = "vsock")]
+    pub fn vsock(&mut self, config: VsockDeviceConfig) -> Result<&mut Self> {
+        self.boot_path = true;
+        self.vm_resources
+            .set_vsock_device(config)
+            .map_err(|err| Error::Action(VmmActionError::VsockConfig(err)))?;
+        Ok(self)
+    }
+
+    /// Applies a full configuration, in the format of the `--config-file` of Firecracker. It
+    /// covers the devices without a dedicated method of the builder.
+    pub fn config(&mut self, config: VmmConfig) -> Result<&mut Self> {
+        self.boot_path = true;
+        self.vm_resources
+            .apply_vmm_config(config)
+            .map_err(|err| Error::Action(VmmActionError::FullVmConfig(err)))?;
+        Ok(self)
+    }
+
+    /// Sets the seccomp filter the calling thread and the vCPU threads load when the microVM
+    /// is built. There is none by default, since the filter would also restrict the program
+    /// embedding the microVM.
+    pub fn seccomp_filter(&mut self, seccomp_filter: BpfProgram) -> &mut Self {
+        self.seccomp_filter = seccomp_filter;
+        self
+    }
+
+    /// Adds a callback, called with each lifecycle event of the microVM on the thread emitting
+    /// it. The callbacks must not call back into the microVM.
+    pub fn on_event<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(&LifecycleEvent) + Send + 'static,
+    {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Builds the microVM and boots it, or leaves its vCPUs paused until `MicroVm::resume`
+    /// when `start_paused` is set.
+    pub fn boot(mut self, start_paused: bool) -> Result<MicroVm> {
+        let mut event_manager = EventManager::new().map_err(Error::EventManager)?;
+        self.register_callbacks();
+        self.vm_resources.start_paused |= start_paused;
+        let vmm =
+            build_microvm_for_boot(&self.vm_resources, &mut event_manager, &self.seccomp_filter)
+                .map_err(|err| Error::Action(VmmActionError::StartMicrovm(err)))?;
+        Ok(MicroVm::new(event_manager, vmm, self.vm_resources))
+    }
+
+    /// Builds the microVM from a snapshot. Only the logger, the metrics, the seccomp filter and
+    /// the callbacks of the builder apply, the rest of the configuration comes from the snapshot.
+    #[cfg(target_arch = "x86_64")]
+    pub fn restore(mut self, params: &LoadSnapshotParams) -> Result<MicroVm> {
+        if self.boot_path {
+            return Err(Error::Action(VmmActionError::LoadSnapshotNotAllowed));
+        }
+        let load_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+        let mut event_manager = EventManager::new().map_err(Error::EventManager)?;
+        self.register_callbacks();
+        let vmm = restore_from_snapshot(
+            &mut event_manager,
+            &self.seccomp_filter,
+            self.vm_resources.allow_teardown,
+            params,
+            VERSION_MAP.clone(),
+        )
+        .map_err(|err| Error::Action(VmmActionError::LoadSnapshot(err)))?;
+        update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_load_snapshot, load_start_us);
+        {
+            let mut locked_vmm = vmm.lock().expect("Poisoned lock");
+            locked_vmm.set_panic_action(self.vm_resources.panic_action.clone());
+            locked_vmm.set_clock_policy(self.vm_resources.clock_policy);
+            locked_vmm.set_shutdown_behavior(self.vm_resources.shutdown_behavior.clone());
+            locked_vmm.set_cpu_quota(self.vm_resources.cpu_quota.quota_pct);
+        }
+
+        let mut microvm = MicroVm::new(event_manager, vmm, self.vm_resources);
+        if params.resume_vm {
+            microvm.resume()?;
+        }
+        Ok(microvm)
+    }
+
+    fn register_callbacks(&mut self) {
+        for callback in self.callbacks.drain(..) {
+            LIFECYCLE_EVENTS.add_callback(callback);
+        }
+    }
+}
+
+/// A microVM embedded in the calling program.
+///
+/// Dropping it exits the vCPUs, without waiting for their threads.
+pub struct MicroVm {
+    // Dropped first, since its subscribers hold on to the devices of the `Vmm`.
+    event_manager: EventManager,
+    vmm: Arc<Mutex<Vmm>>,
+    vm_resources: VmResources,
+}
+
+impl MicroVm {
+    fn new(event_manager: EventManager, vmm: Arc<Mutex<Vmm>>, vm_resources: VmResources) -> Self {
+        // The program embedding the microVM outlives it.
+        vmm.lock().expect("Poisoned lock").set_exit_on_stop(false);
+        MicroVm {
+            event_manager,
+            vmm,
+            vm_resources,
+        }
+    }
+
+    /// Processes the events of the devices, waiting up to `timeout_ms` milliseconds for them,
+    /// or forever when negative. Returns the exit code of the microVM once it stopped, because
+    /// the guest shut down or crashed. A stopped microVM is then torn down or dropped.
+    pub fn run(&mut self, timeout_ms: i32) -> Result<Option<i32>> {
+        self.event_manager
+            .run_with_timeout(timeout_ms)
+            .map_err(Error::EventManager)?;
+        Ok(self.vmm.lock().expect("Poisoned lock").exit_code())
+    }
+
+    /// Pauses the vCPUs.
+    pub fn pause(&mut self) -> Result<()> {
+        let pause_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .pause_vm()
+            .map_err(|err| Error::Action(VmmActionError::InternalVmm(err)))?;
+        update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_pause_vm, pause_start_us);
+        Ok(())
+    }
+
+    /// Resumes the vCPUs.
+    pub fn resume(&mut self) -> Result<()> {
+        let resume_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .resume_vm()
+            .map_err(|err| Error::Action(VmmActionError::InternalVmm(err)))?;
+        update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_resume_vm, resume_start_us);
+        Ok(())
+    }
+
+    /// Returns whether the vCPUs are paused.
+    pub fn is_paused(&self) -> bool {
+        self.vmm.lock().expect("Poisoned lock").is_paused()
+    }
+
+    /// Creates a snapshot of the paused microVM.
+    #[cfg(target_arch = "x86_64")]
+    pub fn create_snapshot(&mut self, params: &CreateSnapshotParams) -> Result<()> {
+        // KVM does not save the state of the nested guests.
+        if self.vm_resources.vm_config().nested_virt_enabled == Some(true) {
+            return Err(Error::Action(VmmActionError::CreateSnapshot(
+                CreateSnapshotError::NestedVirtEnabled,
+            )));
+        }
+        let create_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+        create_snapshot(
+            &mut self.vmm.lock().expect("Poisoned lock"),
+            params,
+            VERSION_MAP.clone(),
+        )
+        .map_err(|err| Error::Action(VmmActionError::CreateSnapshot(err)))?;
+        let metric = match params.snapshot_type {
+            SnapshotType::Full => &METRICS.latencies_us.vmm_full_create_snapshot,
+            SnapshotType::Diff => &METRICS.latencies_us.vmm_diff_create_snapshot,
+        };
+        update_metric_with_elapsed_time(metric, create_start_us);
+        Ok(())
+    }
+
+    /// Returns the `Vmm`, for the operations without a dedicated method. The lock must not be
+    /// held across the calls to `run`.
+    pub fn vmm(&self) -> &Arc<Mutex<Vmm>> {
+        &self.vmm
+    }
+
+    /// Exits the vCPUs and waits for their threads, then releases the microVM, so that the
+    /// process can embed another one. This fails once the microVM loaded a seccomp filter.
+    pub fn teardown(self) -> Result<()> {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .teardown()
+            .map_err(|err| Error::Action(VmmActionError::InternalVmm(err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::vmm_config::boot_source::BootSourceConfigError;
+
+    fn instance_info() -> InstanceInfo {
+        InstanceInfo {
+            id: "embedded".to_string(),
+            started: false,
+            vmm_version: "1.0".to_string(),
+            app_name: "Test".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_builder_config_errors() {
+        let mut builder = VmBuilder::new(instance_info());
+        assert!(!builder.boot_path);
+
+        // The configuration is checked right away.
+        let err = builder
+            .boot_source(BootSourceConfig {
+                kernel_image_path: Some("/no/such/kernel".to_string()),
+                ..Default::default()
+            })
+            .err()
+            .unwrap();
+        match err {
+            Error::Action(VmmActionError::BootSource(
+                BootSourceConfigError::InvalidKernelPath(_),
+            )) => (),
+            err => panic!("Unexpected error: {}", err),
+        }
+        assert!(builder.boot_path);
+
+        // A microVM configured for a boot cannot be restored from a snapshot.
+        #[cfg(target_arch = "x86_64")]
+        match builder.restore(&LoadSnapshotParams {
+            snapshot_path: std::path::PathBuf::new(),
+            mem_file_path: std::path::PathBuf::new(),
+            mem_diff_paths: Vec::new(),
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            #[cfg(feature = "balloon")]
+            balloon_amount_mib: None,
+            #[cfg(feature = "balloon")]
+            deflate_balloon: false,
+            mem_backend: None,
+            mem_hints: None,
+            io_threads: None,
+            mmds_data: None,
+            clone: None,
+        }) {
+            Err(Error::Action(VmmActionError::LoadSnapshotNotAllowed)) => (),
+            _ => panic!("The snapshot cannot be restored."),
+        }
+    }
+
+    #[test]
+    fn test_builder_callbacks() {
+        let mut builder = VmBuilder::new(instance_info());
+        builder
+            .seccomp_filter(BpfProgram::new())
+            .on_event(|_| ())
+            .on_event(|_| ());
+        assert_eq!(builder.callbacks.len(), 2);
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = Error::Action(VmmActionError::OperationNotSupportedPostBoot);
+        let _ = format!("{}{:?}", err, err);
+        let err = Error::EventManager(EventManagerError::NotFound(0));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
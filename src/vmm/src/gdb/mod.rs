@@ -0,0 +1,527 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A GDB server, through which the guest kernel is debugged. Each vCPU is a thread of the
+//! debugged target, and the whole microVM stops when the debugger takes control.
+
+mod packet;
+mod target;
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use self::packet::{decode_hex, encode_hex, parse_hex, Connection, Packet};
+use self::target::{
+    decode_regs, encode_regs, guest_debug, translate_gva, MAX_HW_BREAKPOINTS, PAGE_SIZE,
+};
+use crate::vstate::vcpu::{DebugRequest, DebugResponse};
+use crate::Vmm;
+use kvm_bindings::{kvm_regs, kvm_sregs};
+use logger::{error, info};
+use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+
+// How often the debugger is checked for interrupts while the microVM runs.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// The largest memory access served at once, so that replies fit in a packet.
+const MAX_MEMORY_ACCESS: usize = 0x800;
+// The opcode of int3, which makes the guest trap to the debugger.
+const INT3: u8 = 0xcc;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// Errors associated with the GDB server.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to bind the socket of the debugger.
+    Bind(io::Error),
+    /// Failed to communicate with the debugger.
+    Connection(io::Error),
+    /// Failed to spawn the GDB server thread.
+    Spawn(io::Error),
+    /// Failed to control the microVM.
+    Vmm(crate::Error),
+    /// The vCPUs can no longer report their stops.
+    VcpusDisconnected,
+    /// The GDB server thread would inherit the seccomp filter of the VMM thread.
+    VmmThreadFiltered,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+
+        match self {
+            Bind(e) => write!(f, "Cannot bind the GDB socket: {}", e),
+            Connection(e) => write!(f, "Cannot communicate with the debugger: {}", e),
+            Spawn(e) => write!(f, "Cannot spawn the GDB server thread: {}", e),
+            Vmm(e) => write!(f, "Cannot control the microVM: {}", e),
+            VcpusDisconnected => write!(f, "The vCPUs can no longer report their stops."),
+            VmmThreadFiltered => write!(
+                f,
+                "The VMM thread loaded its seccomp filter for a previous microVM, which the GDB \
+                 server thread would inherit."
+            ),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Binds the socket on which the debugger connects.
+pub fn bind<P: AsRef<Path>>(path: P) -> Result<UnixListener> {
+    UnixListener::bind(path).map_err(Error::Bind)
+}
+
+/// Serves the first debugger connecting on `listener`, from a dedicated thread. The vCPUs
+/// send their index on `vcpu_stops` when they stop for the debugger. The microVM must not run
+/// before the debugger connects, so that it can set breakpoints from the start.
+pub fn start_server(
+    listener: UnixListener,
+    vmm: Arc<Mutex<Vmm>>,
+    guest_memory: GuestMemoryMmap,
+    vcpu_stops: Receiver<u8>,
+    vcpu_count: usize,
+) -> Result<()> {
+    thread::Builder::new()
+        .name("fc_gdb".to_owned())
+        .spawn(move || {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("Failed to accept the debugger connection: {}", e);
+                    return;
+                }
+            };
+            info!("Debugger connected.");
+
+            let mut server = GdbServer {
+                connection: Connection::new(stream),
+                vmm,
+                guest_memory,
+                vcpu_stops,
+                vcpu_count,
+                current_vcpu: 0,
+                step_vcpu: 0,
+                stop_reply: stop_reply(SIGTRAP, 0),
+                sw_breakpoints: HashMap::new(),
+                hw_breakpoints: Vec::new(),
+            };
+            if let Err(e) = server.run() {
+                error!("GDB server failed: {}", e);
+            }
+            // Let the microVM run freely once the debugger is gone.
+            if let Err(e) = server.detach() {
+                error!("Failed to detach the debugger: {}", e);
+            }
+            info!("Debugger disconnected.");
+        })
+        .map(|_| ())
+        .map_err(Error::Spawn)
+}
+
+// What to do after a command of the debugger.
+enum Action {
+    // Send the reply and wait for the next command.
+    Reply(String),
+    // Let the microVM run until a vCPU stops or the debugger interrupts it.
+    Resume { single_step: bool },
+    // Stop debugging.
+    Detach,
+}
+
+struct GdbServer {
+    connection: Connection,
+    vmm: Arc<Mutex<Vmm>>,
+    guest_memory: GuestMemoryMmap,
+    vcpu_stops: Receiver<u8>,
+    vcpu_count: usize,
+    // The vCPU whose registers and memory are accessed.
+    current_vcpu: usize,
+    // The vCPU stepped by the next single step.
+    step_vcpu: usize,
+    // The reply to the last stop of the microVM.
+    stop_reply: String,
+    // The software breakpoints, by guest virtual address, with the physical address and the
+    // original byte of the patched instruction.
+    sw_breakpoints: HashMap<u64, (GuestAddress, u8)>,
+    hw_breakpoints: Vec<u64>,
+}
+
+// Thread IDs start at 1, as 0 stands for any thread.
+fn stop_reply(signal: u8, vcpu: usize) -> String {
+    format!("T{:02x}thread:{:x};", signal, vcpu + 1)
+}
+
+impl GdbServer {
+    fn run(&mut self) -> Result<()> {
+        loop {
+            let command = match self.connection.read_packet().map_err(Error::Connection)? {
+                Some(Packet::Command(command)) => command,
+                // The microVM is already stopped.
+                Some(Packet::Interrupt) => continue,
+                None => return Ok(()),
+            };
+
+            match self.handle_command(&command) {
+                Action::Reply(reply) => self.reply(&reply)?,
+                Action::Resume { single_step } => {
+                    self.resume(single_step)?;
+                    self.wait_for_stop()?;
+                    let reply = self.stop_reply.clone();
+                    self.reply(&reply)?;
+                }
+                Action::Detach => {
+                    self.reply("OK")?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn reply(&mut self, reply: &str) -> Result<()> {
+        self.connection
+            .write_packet(reply.as_bytes())
+            .map_err(Error::Connection)
+    }
+
+    fn handle_command(&mut self, command: &[u8]) -> Action {
+        let (&kind, args) = match command.split_first() {
+            Some(split) => split,
+            None => return Action::Reply(String::new()),
+        };
+        let reply = match kind {
+            b'?' => Some(self.stop_reply.clone()),
+            b'c' | b's' => {
+                if !args.is_empty() && self.set_rip(args).is_none() {
+                    return Action::Reply("E01".to_owned());
+                }
+                return Action::Resume {
+                    single_step: kind == b's',
+                };
+            }
+            b'D' | b'k' => return Action::Detach,
+            b'g' => self.read_registers(),
+            b'G' => self.write_registers(args),
+            b'H' => self.set_thread(args),
+            b'm' => self.read_memory(args),
+            b'M' => self.write_memory(args),
+            b'q' => Some(self.query(args)),
+            b'T' => self
+                .parse_thread(args)
+                .filter(|vcpu| vcpu.is_some())
+                .map(|_| "OK".to_owned()),
+            b'Z' => self.insert_breakpoint(args),
+            b'z' => self.remove_breakpoint(args),
+            // Unsupported commands get an empty reply.
+            _ => Some(String::new()),
+        };
+        Action::Reply(reply.unwrap_or_else(|| "E01".to_owned()))
+    }
+
+    fn query(&self, query: &[u8]) -> String {
+        if query.starts_with(b"Supported") {
+            format!("PacketSize={:x}", MAX_MEMORY_ACCESS * 2 + 32)
+        } else if query == b"Attached" {
+            "1".to_owned()
+        } else if query == b"C" {
+            format!("QC{:x}", self.current_vcpu + 1)
+        } else if query == b"fThreadInfo" {
+            let threads: Vec<String> = (1..=self.vcpu_count)
+                .map(|thread| format!("{:x}", thread))
+                .collect();
+            format!("m{}", threads.join(","))
+        } else if query == b"sThreadInfo" {
+            "l".to_owned()
+        } else {
+            String::new()
+        }
+    }
+
+    // Parses a thread ID, where `None` stands for all or any of the vCPUs.
+    fn parse_thread(&self, thread: &[u8]) -> Option<Option<usize>> {
+        if thread == b"-1" {
+            return Some(None);
+        }
+        match parse_hex(thread)? as usize {
+            0 => Some(None),
+            thread if thread <= self.vcpu_count => Some(Some(thread - 1)),
+            _ => None,
+        }
+    }
+
+    fn set_thread(&mut self, args: &[u8]) -> Option<String> {
+        let (&operation, thread) = args.split_first()?;
+        let vcpu = self.parse_thread(thread)?;
+        match operation {
+            b'g' => self.current_vcpu = vcpu.unwrap_or(self.current_vcpu),
+            b'c' => self.step_vcpu = vcpu.unwrap_or(self.current_vcpu),
+            _ => return None,
+        }
+        Some("OK".to_owned())
+    }
+
+    fn debug_vcpu(&self, index: usize, request: DebugRequest) -> Option<DebugResponse> {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .debug_vcpu(index, request)
+            .map_err(|e| error!("Failed to debug vCPU {}: {}", index, e))
+            .ok()
+    }
+
+    fn regs(&self) -> Option<kvm_regs> {
+        match self.debug_vcpu(self.current_vcpu, DebugRequest::GetRegs)? {
+            DebugResponse::Regs(regs) => Some(*regs),
+            _ => None,
+        }
+    }
+
+    fn sregs(&self) -> Option<kvm_sregs> {
+        match self.debug_vcpu(self.current_vcpu, DebugRequest::GetSregs)? {
+            DebugResponse::Sregs(sregs) => Some(*sregs),
+            _ => None,
+        }
+    }
+
+    fn set_regs(&self, regs: kvm_regs) -> Option<()> {
+        self.debug_vcpu(self.current_vcpu, DebugRequest::SetRegs(Box::new(regs)))
+            .map(|_| ())
+    }
+
+    fn read_registers(&self) -> Option<String> {
+        let regs = self.regs()?;
+        let sregs = self.sregs()?;
+        Some(encode_hex(&encode_regs(&regs, &sregs)))
+    }
+
+    fn write_registers(&self, args: &[u8]) -> Option<String> {
+        let mut regs = self.regs()?;
+        decode_regs(&decode_hex(args)?, &mut regs)?;
+        self.set_regs(regs)?;
+        Some("OK".to_owned())
+    }
+
+    fn set_rip(&self, addr: &[u8]) -> Option<()> {
+        let mut regs = self.regs()?;
+        regs.rip = parse_hex(addr)?;
+        self.set_regs(regs)
+    }
+
+    // Calls `access` on each page of the guest memory range starting at the guest virtual
+    // address `addr`, with the physical address of the page and the offset in the range.
+    fn access_memory<F>(&self, addr: u64, len: usize, mut access: F) -> Option<()>
+    where
+        F: FnMut(GuestAddress, usize, usize) -> Option<()>,
+    {
+        let sregs = self.sregs()?;
+        let mut offset = 0;
+        while offset < len {
+            let gva = addr.checked_add(offset as u64)?;
+            let gpa = translate_gva(&self.guest_memory, &sregs, gva)?;
+            let chunk = (len - offset).min((PAGE_SIZE - (gva % PAGE_SIZE)) as usize);
+            access(GuestAddress(gpa), offset, chunk)?;
+            offset += chunk;
+        }
+        Some(())
+    }
+
+    // Parses the `addr,length` arguments of the memory commands.
+    fn parse_range(args: &[u8]) -> Option<(u64, usize)> {
+        let separator = args.iter().position(|&byte| byte == b',')?;
+        let addr = parse_hex(&args[..separator])?;
+        let len = parse_hex(&args[separator + 1..])? as usize;
+        Some((addr, len.min(MAX_MEMORY_ACCESS)))
+    }
+
+    fn read_memory(&self, args: &[u8]) -> Option<String> {
+        let (addr, len) = Self::parse_range(args)?;
+        let mut data = vec![0u8; len];
+        self.access_memory(addr, len, |gpa, offset, chunk| {
+            self.guest_memory
+                .read_slice(&mut data[offset..offset + chunk], gpa)
+                .ok()
+        })?;
+        Some(encode_hex(&data))
+    }
+
+    fn write_memory(&self, args: &[u8]) -> Option<String> {
+        let separator = args.iter().position(|&byte| byte == b':')?;
+        let (addr, len) = Self::parse_range(&args[..separator])?;
+        let data = decode_hex(&args[separator + 1..])?;
+        if data.len() != len {
+            return None;
+        }
+        self.access_memory(addr, len, |gpa, offset, chunk| {
+            self.guest_memory
+                .write_slice(&data[offset..offset + chunk], gpa)
+                .ok()
+        })?;
+        Some("OK".to_owned())
+    }
+
+    // Parses the `type,addr,kind` arguments of the breakpoint commands.
+    fn parse_breakpoint(args: &[u8]) -> Option<(u8, u64)> {
+        let mut fields = args.split(|&byte| byte == b',');
+        let kind = fields.next()?;
+        let addr = parse_hex(fields.next()?)?;
+        match kind {
+            b"0" => Some((0, addr)),
+            b"1" => Some((1, addr)),
+            _ => None,
+        }
+    }
+
+    fn insert_breakpoint(&mut self, args: &[u8]) -> Option<String> {
+        let (kind, addr) = match Self::parse_breakpoint(args) {
+            Some(breakpoint) => breakpoint,
+            // Watchpoints are not supported.
+            None => return Some(String::new()),
+        };
+        if kind == 1 {
+            if !self.hw_breakpoints.contains(&addr) {
+                if self.hw_breakpoints.len() == MAX_HW_BREAKPOINTS {
+                    return None;
+                }
+                self.hw_breakpoints.push(addr);
+            }
+            return Some("OK".to_owned());
+        }
+
+        if !self.sw_breakpoints.contains_key(&addr) {
+            let gpa = GuestAddress(translate_gva(&self.guest_memory, &self.sregs()?, addr)?);
+            let original: u8 = self.guest_memory.read_obj(gpa).ok()?;
+            self.guest_memory.write_obj(INT3, gpa).ok()?;
+            self.sw_breakpoints.insert(addr, (gpa, original));
+        }
+        Some("OK".to_owned())
+    }
+
+    fn remove_breakpoint(&mut self, args: &[u8]) -> Option<String> {
+        let (kind, addr) = match Self::parse_breakpoint(args) {
+            Some(breakpoint) => breakpoint,
+            None => return Some(String::new()),
+        };
+        if kind == 1 {
+            self.hw_breakpoints.retain(|&bp| bp != addr);
+        } else if let Some((gpa, original)) = self.sw_breakpoints.remove(&addr) {
+            self.guest_memory.write_obj(original, gpa).ok()?;
+        }
+        Some("OK".to_owned())
+    }
+
+    // Applies the breakpoints to the vCPUs and lets the microVM run.
+    fn resume(&mut self, single_step: bool) -> Result<()> {
+        // Forget the vCPUs which stopped while the microVM was being stopped: they hit their
+        // breakpoint again once resumed.
+        while self.vcpu_stops.try_recv().is_ok() {}
+
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        for index in 0..self.vcpu_count {
+            let debug = guest_debug(
+                &self.hw_breakpoints,
+                !self.sw_breakpoints.is_empty(),
+                single_step && index == self.step_vcpu,
+            );
+            vmm.debug_vcpu(index, DebugRequest::SetGuestDebug(Box::new(debug)))
+                .map_err(Error::Vmm)?;
+        }
+        vmm.resume_vm().map_err(Error::Vmm)
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .pause_vm()
+            .map_err(Error::Vmm)
+    }
+
+    // Waits for a vCPU to stop or for the debugger to interrupt the microVM, then stops it.
+    fn wait_for_stop(&mut self) -> Result<()> {
+        loop {
+            match self.vcpu_stops.recv_timeout(POLL_INTERVAL) {
+                Ok(index) => {
+                    self.pause()?;
+                    self.current_vcpu = usize::from(index);
+                    self.step_vcpu = self.current_vcpu;
+                    self.stop_reply = stop_reply(SIGTRAP, self.current_vcpu);
+                    return Ok(());
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if self
+                        .connection
+                        .poll_interrupt()
+                        .map_err(Error::Connection)?
+                    {
+                        self.pause()?;
+                        self.stop_reply = stop_reply(SIGINT, self.current_vcpu);
+                        return Ok(());
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::VcpusDisconnected),
+            }
+        }
+    }
+
+    // Removes the breakpoints and lets the microVM run without the debugger.
+    fn detach(&mut self) -> Result<()> {
+        for (_, (gpa, original)) in self.sw_breakpoints.drain() {
+            if let Err(e) = self.guest_memory.write_obj(original, gpa) {
+                error!("Failed to remove a breakpoint: {}", e);
+            }
+        }
+        self.hw_breakpoints.clear();
+
+        let mut vmm = self.vmm.lock().expect("Poisoned lock");
+        if !vmm.is_paused() {
+            vmm.pause_vm().map_err(Error::Vmm)?;
+        }
+        for index in 0..self.vcpu_count {
+            vmm.debug_vcpu(
+                index,
+                DebugRequest::SetGuestDebug(Box::new(Default::default())),
+            )
+            .map_err(Error::Vmm)?;
+        }
+        vmm.resume_vm().map_err(Error::Vmm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_reply() {
+        assert_eq!(stop_reply(SIGTRAP, 0), "T05thread:1;");
+        assert_eq!(stop_reply(SIGINT, 10), "T02thread:b;");
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            GdbServer::parse_range(b"ffffffff81000000,4"),
+            Some((0xffff_ffff_8100_0000, 4))
+        );
+        assert_eq!(
+            GdbServer::parse_range(b"1000,10000"),
+            Some((0x1000, MAX_MEMORY_ACCESS))
+        );
+        assert_eq!(GdbServer::parse_range(b"1000"), None);
+
+        assert_eq!(
+            GdbServer::parse_breakpoint(b"0,ffffffff81000000,1"),
+            Some((0, 0xffff_ffff_8100_0000))
+        );
+        assert_eq!(GdbServer::parse_breakpoint(b"1,1000,1"), Some((1, 0x1000)));
+        assert_eq!(GdbServer::parse_breakpoint(b"2,1000,4"), None);
+    }
+}
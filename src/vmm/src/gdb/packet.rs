@@ -0,0 +1,200 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+
+// The byte sent by the debugger to interrupt the running target (Ctrl-C).
+const INTERRUPT: u8 = 0x03;
+
+/// What the debugger sent.
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    /// A command, stripped of its framing.
+    Command(Vec<u8>),
+    /// A request to stop the running target.
+    Interrupt,
+}
+
+/// The connection to the debugger, over which packets of the GDB remote protocol are exchanged.
+pub struct Connection {
+    stream: UnixStream,
+}
+
+impl Connection {
+    /// Wraps the stream accepted from the debugger.
+    pub fn new(stream: UnixStream) -> Self {
+        Connection { stream }
+    }
+
+    // Reads one byte, or `None` at the end of the stream.
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        loop {
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads the next packet, acknowledging it, or `None` once the debugger disconnected.
+    /// Packets with a wrong checksum are rejected, for the debugger to send them again.
+    pub fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Packet::Interrupt)),
+                Some(b'$') => (),
+                // Acknowledgements and line noise.
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            for digit in checksum.iter_mut() {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+
+            if parse_hex(&checksum) == Some(u64::from(self::checksum(&data))) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(Packet::Command(data)));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    /// Sends a packet, until the debugger acknowledges it.
+    pub fn write_packet(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:02x}", checksum(data)).as_bytes());
+
+        loop {
+            self.stream.write_all(&packet)?;
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                Some(_) => return Ok(()),
+                None => return Err(ErrorKind::UnexpectedEof.into()),
+            }
+        }
+    }
+
+    /// Returns whether the debugger asked to stop the target, without waiting for it.
+    pub fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let result = self.read_byte();
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(Some(byte)) => Ok(byte == INTERRUPT),
+            Ok(None) => Err(ErrorKind::UnexpectedEof.into()),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// The modulo 256 sum of the packet data.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Parses a big endian hexadecimal number, as found in the command arguments.
+pub fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, digit| {
+        (*digit as char)
+            .to_digit(16)
+            .map(|digit| (value << 4) | u64::from(digit))
+    })
+}
+
+/// Encodes bytes as pairs of hexadecimal digits.
+pub fn encode_hex(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len() * 2);
+    for byte in data {
+        // Writing to a `String` can't fail.
+        let _ = write!(encoded, "{:02x}", byte);
+    }
+    encoded
+}
+
+/// Decodes pairs of hexadecimal digits into bytes.
+pub fn decode_hex(encoded: &[u8]) -> Option<Vec<u8>> {
+    if encoded.len() % 2 != 0 {
+        return None;
+    }
+    encoded
+        .chunks(2)
+        .map(|pair| parse_hex(pair).map(|byte| byte as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(parse_hex(b"ffffffff81000000"), Some(0xffff_ffff_8100_0000));
+        assert_eq!(parse_hex(b"1A"), Some(0x1a));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"12g"), None);
+        assert_eq!(parse_hex(b"10000000000000000"), None);
+
+        assert_eq!(encode_hex(&[0x0f, 0xcc, 0x90]), "0fcc90");
+        assert_eq!(decode_hex(b"0fcc90"), Some(vec![0x0f, 0xcc, 0x90]));
+        assert_eq!(decode_hex(b"0fc"), None);
+        assert_eq!(decode_hex(b"zz"), None);
+    }
+
+    #[test]
+    fn test_packets() {
+        let (stream, mut debugger) = UnixStream::pair().unwrap();
+        let mut connection = Connection::new(stream);
+
+        // A corrupted packet is rejected, then sent again.
+        debugger.write_all(b"+$g#00$g#67").unwrap();
+        assert_eq!(
+            connection.read_packet().unwrap(),
+            Some(Packet::Command(b"g".to_vec()))
+        );
+        let mut acks = [0u8; 2];
+        debugger.read_exact(&mut acks).unwrap();
+        assert_eq!(&acks, b"-+");
+
+        debugger.write_all(&[INTERRUPT]).unwrap();
+        assert_eq!(connection.read_packet().unwrap(), Some(Packet::Interrupt));
+
+        debugger.write_all(b"+").unwrap();
+        connection.write_packet(b"OK").unwrap();
+        let mut reply = [0u8; 6];
+        debugger.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"$OK#9a");
+
+        assert!(!connection.poll_interrupt().unwrap());
+        debugger.write_all(&[INTERRUPT]).unwrap();
+        assert!(connection.poll_interrupt().unwrap());
+
+        drop(debugger);
+        assert_eq!(connection.read_packet().unwrap(), None);
+        assert!(connection.poll_interrupt().is_err());
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::TryInto;
+
+use kvm_bindings::{
+    kvm_guest_debug, kvm_regs, kvm_sregs, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP,
+    KVM_GUESTDBG_USE_HW_BP, KVM_GUESTDBG_USE_SW_BP,
+};
+use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+
+/// The number of hardware breakpoints, one per debug address register.
+pub const MAX_HW_BREAKPOINTS: usize = 4;
+
+/// The size of the guest pages, which are translated one at a time.
+pub const PAGE_SIZE: u64 = 0x1000;
+
+// The general purpose registers in the `g` packet, followed by rip, then eflags and the segment
+// selectors on 32 bits.
+const GPR_COUNT: usize = 16;
+const REGS_SIZE: usize = (GPR_COUNT + 1) * 8 + 7 * 4;
+
+const CR0_PG: u64 = 1 << 31;
+const CR4_LA57: u64 = 1 << 12;
+const EFER_LMA: u64 = 1 << 10;
+
+const PTE_PRESENT: u64 = 1;
+const PTE_PAGE_SIZE: u64 = 1 << 7;
+const PTE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+// DR7 enables the breakpoint of each address register with one bit out of two; the
+// breakpoints left with null conditions trigger on instruction execution.
+const DR7_FIXED: u64 = 1 << 10;
+
+/// Encodes the registers in the layout of the `g` packet of the x86-64 GDB target.
+pub fn encode_regs(regs: &kvm_regs, sregs: &kvm_sregs) -> Vec<u8> {
+    let gprs = [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+    ];
+    let segments = [
+        sregs.cs.selector,
+        sregs.ss.selector,
+        sregs.ds.selector,
+        sregs.es.selector,
+        sregs.fs.selector,
+        sregs.gs.selector,
+    ];
+
+    let mut data = Vec::with_capacity(REGS_SIZE);
+    for value in gprs.iter() {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    // The guest can't see the upper half of rflags.
+    data.extend_from_slice(&(regs.rflags as u32).to_le_bytes());
+    for selector in segments.iter() {
+        data.extend_from_slice(&u32::from(*selector).to_le_bytes());
+    }
+    data
+}
+
+/// Updates the registers from the layout of the `G` packet. The segment selectors can't be
+/// changed. Returns `None` if the registers are missing.
+pub fn decode_regs(data: &[u8], regs: &mut kvm_regs) -> Option<()> {
+    if data.len() < (GPR_COUNT + 1) * 8 + 4 {
+        return None;
+    }
+    let mut values = data
+        .chunks_exact(8)
+        .take(GPR_COUNT + 1)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
+    let mut gprs = [
+        &mut regs.rax,
+        &mut regs.rbx,
+        &mut regs.rcx,
+        &mut regs.rdx,
+        &mut regs.rsi,
+        &mut regs.rdi,
+        &mut regs.rbp,
+        &mut regs.rsp,
+        &mut regs.r8,
+        &mut regs.r9,
+        &mut regs.r10,
+        &mut regs.r11,
+        &mut regs.r12,
+        &mut regs.r13,
+        &mut regs.r14,
+        &mut regs.r15,
+        &mut regs.rip,
+    ];
+    for reg in gprs.iter_mut() {
+        **reg = values.next()?;
+    }
+    let eflags_offset = (GPR_COUNT + 1) * 8;
+    let eflags = u32::from_le_bytes(data[eflags_offset..eflags_offset + 4].try_into().unwrap());
+    regs.rflags = (regs.rflags & !0xffff_ffff) | u64::from(eflags);
+    Some(())
+}
+
+/// Translates a guest virtual address to a guest physical address, walking the page tables
+/// of the vCPU. Only the identity mapping of the boot and the 4 and 5-level paging of the long
+/// mode are handled. Returns `None` if the address is not mapped.
+pub fn translate_gva(mem: &GuestMemoryMmap, sregs: &kvm_sregs, gva: u64) -> Option<u64> {
+    if sregs.cr0 & CR0_PG == 0 {
+        return Some(gva);
+    }
+    if sregs.efer & EFER_LMA == 0 {
+        return None;
+    }
+
+    let shifts: &[u64] = if sregs.cr4 & CR4_LA57 != 0 {
+        &[48, 39, 30, 21, 12]
+    } else {
+        &[39, 30, 21, 12]
+    };
+    let mut table = sregs.cr3 & PTE_ADDR_MASK;
+    for (level, shift) in shifts.iter().enumerate() {
+        let index = (gva >> shift) & 0x1ff;
+        let entry: u64 = mem.read_obj(GuestAddress(table + index * 8)).ok()?;
+        if entry & PTE_PRESENT == 0 {
+            return None;
+        }
+        // The 1GiB and 2MiB pages end the walk early.
+        let last = level == shifts.len() - 1;
+        if last || (*shift <= 30 && entry & PTE_PAGE_SIZE != 0) {
+            let offset_mask = (1u64 << shift) - 1;
+            return Some((entry & PTE_ADDR_MASK & !offset_mask) | (gva & offset_mask));
+        }
+        table = entry & PTE_ADDR_MASK;
+    }
+    None
+}
+
+/// Builds the debug control of a vCPU, for the breakpoints inserted by the debugger.
+pub fn guest_debug(
+    hw_breakpoints: &[u64],
+    sw_breakpoints: bool,
+    single_step: bool,
+) -> kvm_guest_debug {
+    let mut debug = kvm_guest_debug {
+        control: KVM_GUESTDBG_ENABLE,
+        ..Default::default()
+    };
+    if sw_breakpoints {
+        debug.control |= KVM_GUESTDBG_USE_SW_BP;
+    }
+    if single_step {
+        debug.control |= KVM_GUESTDBG_SINGLESTEP;
+    }
+    if !hw_breakpoints.is_empty() {
+        debug.control |= KVM_GUESTDBG_USE_HW_BP;
+        debug.arch.debugreg[7] = DR7_FIXED;
+        for (index, addr) in hw_breakpoints.iter().take(MAX_HW_BREAKPOINTS).enumerate() {
+            debug.arch.debugreg[index] = *addr;
+            debug.arch.debugreg[7] |= 1 << (index * 2);
+        }
+    }
+    debug
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regs() {
+        let regs = kvm_regs {
+            rax: 1,
+            r15: 2,
+            rip: 0xffff_ffff_8100_0000,
+            rflags: 0x246,
+            ..Default::default()
+        };
+        let mut sregs = kvm_sregs::default();
+        sregs.cs.selector = 0x10;
+
+        let data = encode_regs(&regs, &sregs);
+        assert_eq!(data.len(), REGS_SIZE);
+        assert_eq!(data[0], 1);
+        assert_eq!(data[15 * 8], 2);
+        assert_eq!(&data[16 * 8..17 * 8], &regs.rip.to_le_bytes());
+        assert_eq!(&data[17 * 8..17 * 8 + 4], &[0x46, 0x02, 0, 0]);
+        assert_eq!(data[17 * 8 + 4], 0x10);
+
+        let mut decoded = kvm_regs::default();
+        assert!(decode_regs(&data, &mut decoded).is_some());
+        assert_eq!(decoded.rax, regs.rax);
+        assert_eq!(decoded.r15, regs.r15);
+        assert_eq!(decoded.rip, regs.rip);
+        assert_eq!(decoded.rflags, regs.rflags);
+        assert!(decode_regs(&data[..17 * 8], &mut decoded).is_none());
+    }
+
+    #[test]
+    fn test_translate_gva() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap();
+        let mut sregs = kvm_sregs::default();
+
+        // Without paging, the addresses are physical.
+        assert_eq!(translate_gva(&mem, &sregs, 0x1234), Some(0x1234));
+
+        // PML4 at 0x1000, PDPT at 0x2000, PD at 0x3000, PT at 0x4000.
+        sregs.cr0 = CR0_PG;
+        sregs.efer = EFER_LMA;
+        sregs.cr3 = 0x1000;
+        mem.write_obj(0x2000u64 | PTE_PRESENT, GuestAddress(0x1000))
+            .unwrap();
+        mem.write_obj(0x3000u64 | PTE_PRESENT, GuestAddress(0x2000))
+            .unwrap();
+        // The first 2MiB are mapped by a huge page, the next ones by 4KiB pages.
+        mem.write_obj(
+            0x20_0000u64 | PTE_PRESENT | PTE_PAGE_SIZE,
+            GuestAddress(0x3000),
+        )
+        .unwrap();
+        mem.write_obj(0x4000u64 | PTE_PRESENT, GuestAddress(0x3008))
+            .unwrap();
+        mem.write_obj(0x5000u64 | PTE_PRESENT, GuestAddress(0x4008))
+            .unwrap();
+
+        assert_eq!(translate_gva(&mem, &sregs, 0x1234), Some(0x20_1234));
+        assert_eq!(translate_gva(&mem, &sregs, 0x20_1abc), Some(0x5abc));
+        assert_eq!(translate_gva(&mem, &sregs, 0x20_2000), None);
+        assert_eq!(translate_gva(&mem, &sregs, 0x4000_0000), None);
+
+        // 32-bit paging is not handled.
+        sregs.efer = 0;
+        assert_eq!(translate_gva(&mem, &sregs, 0x1234), None);
+    }
+
+    #[test]
+    fn test_guest_debug() {
+        let debug = guest_debug(&[], false, false);
+        assert_eq!(debug.control, KVM_GUESTDBG_ENABLE);
+
+        let debug = guest_debug(&[0x1000, 0x2000], true, true);
+        assert_eq!(
+            debug.control,
+            KVM_GUESTDBG_ENABLE
+                | KVM_GUESTDBG_USE_SW_BP
+                | KVM_GUESTDBG_SINGLESTEP
+                | KVM_GUESTDBG_USE_HW_BP
+        );
+        assert_eq!(debug.arch.debugreg[0], 0x1000);
+        assert_eq!(debug.arch.debugreg[1], 0x2000);
+        assert_eq!(debug.arch.debugreg[7], DR7_FIXED | 0b101);
+    }
+}
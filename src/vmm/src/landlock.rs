@@ -0,0 +1,267 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+use utils::syscall::SyscallReturnCode;
+
+// The Landlock syscalls, which have the same numbers on all architectures.
+const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+// The filesystem access rights of the first Landlock ABI.
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+// All the rights, from `ACCESS_FS_EXECUTE` to `ACCESS_FS_MAKE_SYM`.
+const ACCESS_FS_ALL: u64 = (ACCESS_FS_MAKE_SYM << 1) - 1;
+
+#[repr(C)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Errors associated with restricting the filesystem access of the microVM.
+#[derive(Debug)]
+pub enum Error {
+    /// Cannot add the rule allowing the access to a path.
+    AddRule(PathBuf, io::Error),
+    /// Cannot create the Landlock ruleset.
+    CreateRuleset(io::Error),
+    /// Cannot forbid the process from gaining new privileges.
+    NoNewPrivs(io::Error),
+    /// Cannot open a path to be allowed.
+    OpenPath(PathBuf, io::Error),
+    /// Cannot enforce the Landlock ruleset.
+    RestrictSelf(io::Error),
+    /// The host kernel does not support Landlock, or has it disabled.
+    Unsupported,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::Error::*;
+        match self {
+            AddRule(path, err) => {
+                write!(f, "Cannot allow the access to {}: {}", path.display(), err)
+            }
+            CreateRuleset(err) => write!(f, "Cannot create the Landlock ruleset: {}", err),
+            NoNewPrivs(err) => write!(f, "Cannot set the no_new_privs bit: {}", err),
+            OpenPath(path, err) => write!(f, "Cannot open {}: {}", path.display(), err),
+            RestrictSelf(err) => write!(f, "Cannot enforce the Landlock ruleset: {}", err),
+            Unsupported => write!(f, "Landlock is not supported by the host kernel."),
+        }
+    }
+}
+
+/// A path the microVM keeps access to, along with everything beneath it for a directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AllowedPath {
+    /// The path on the host.
+    pub path: PathBuf,
+    /// Whether the microVM may only read from the path.
+    pub read_only: bool,
+}
+
+// Returns the rights granted on a path. Executing is never allowed, and Landlock rejects the
+// rights which only apply to directories on regular files.
+fn allowed_access(is_dir: bool, read_only: bool) -> u64 {
+    match (is_dir, read_only) {
+        (true, true) => ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR,
+        (true, false) => ACCESS_FS_ALL & !ACCESS_FS_EXECUTE,
+        (false, true) => ACCESS_FS_READ_FILE,
+        (false, false) => ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE,
+    }
+}
+
+// Allows the access to `allowed` in the ruleset.
+fn add_rule(ruleset_fd: RawFd, allowed: &AllowedPath) -> Result<(), Error> {
+    let open_error = |err| Error::OpenPath(allowed.path.clone(), err);
+    let c_path = CString::new(allowed.path.as_os_str().as_bytes())
+        .map_err(|_| open_error(io::Error::from_raw_os_error(libc::EINVAL)))?;
+    let is_dir = allowed.path.metadata().map_err(open_error)?.is_dir();
+    // Safe because we pass a valid C string, and check the result.
+    let parent_fd =
+        SyscallReturnCode(unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) })
+            .into_result()
+            .map_err(open_error)?;
+
+    let attr = LandlockPathBeneathAttr {
+        allowed_access: allowed_access(is_dir, allowed.read_only),
+        parent_fd,
+    };
+    // Safe because we pass a valid rule, and check the result.
+    let result = SyscallReturnCode(unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_ADD_RULE,
+            ruleset_fd,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const LandlockPathBeneathAttr,
+            0,
+        ) as libc::c_int
+    })
+    .into_empty_result()
+    .map_err(|e| Error::AddRule(allowed.path.clone(), e));
+    // Safe because the fd is valid, and not used anymore.
+    unsafe { libc::close(parent_fd) };
+    result
+}
+
+// Creates the ruleset, and restricts the calling thread with it.
+fn restrict_with_ruleset(ruleset_fd: RawFd, allowed: &[AllowedPath]) -> Result<(), Error> {
+    for path in allowed.iter() {
+        add_rule(ruleset_fd, path)?;
+    }
+
+    // Safe because the arguments are valid, and we check the result.
+    SyscallReturnCode(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })
+        .into_empty_result()
+        .map_err(Error::NoNewPrivs)?;
+
+    // Safe because the ruleset fd is valid, and we check the result.
+    SyscallReturnCode(unsafe {
+        libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset_fd, 0) as libc::c_int
+    })
+    .into_empty_result()
+    .map_err(Error::RestrictSelf)
+}
+
+/// Restricts the filesystem access of the calling thread, and of the threads it spawns from
+/// then on, to the `allowed` paths. The file descriptors opened beforehand are not affected.
+pub fn restrict_fs_access(allowed: &[AllowedPath]) -> Result<(), Error> {
+    let attr = LandlockRulesetAttr {
+        handled_access_fs: ACCESS_FS_ALL,
+    };
+    // Safe because we pass a valid ruleset attribute along with its size, and check the result.
+    let ruleset_fd = SyscallReturnCode(unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            &attr as *const LandlockRulesetAttr,
+            std::mem::size_of::<LandlockRulesetAttr>(),
+            0,
+        ) as libc::c_int
+    })
+    .into_result()
+    .map_err(|e| match e.raw_os_error() {
+        Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => Error::Unsupported,
+        _ => Error::CreateRuleset(e),
+    })?;
+
+    let result = restrict_with_ruleset(ruleset_fd, allowed);
+    // Safe because the fd is valid, and the enforced ruleset does not need it.
+    unsafe { libc::close(ruleset_fd) };
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::thread;
+
+    use utils::tempdir::TempDir;
+
+    #[test]
+    fn test_allowed_access() {
+        assert_eq!(allowed_access(false, true), ACCESS_FS_READ_FILE);
+        assert_eq!(
+            allowed_access(false, false),
+            ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE
+        );
+        assert_eq!(
+            allowed_access(true, true),
+            ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR
+        );
+        let access = allowed_access(true, false);
+        assert_eq!(access & ACCESS_FS_EXECUTE, 0);
+        assert_ne!(access & ACCESS_FS_MAKE_SYM, 0);
+        assert_eq!(ACCESS_FS_ALL, 0x1fff);
+    }
+
+    #[test]
+    fn test_restrict_fs_access() {
+        let allowed_dir = TempDir::new().unwrap();
+        let other_dir = TempDir::new().unwrap();
+        let allowed_file = allowed_dir.as_path().join("allowed");
+        let other_file = other_dir.as_path().join("other");
+        File::create(&allowed_file).unwrap();
+        File::create(&other_file).unwrap();
+
+        let allowed = vec![AllowedPath {
+            path: allowed_dir.as_path().to_path_buf(),
+            read_only: true,
+        }];
+        // Landlock only restricts the calling thread, so the test runs in its own.
+        thread::spawn(move || {
+            match restrict_fs_access(&allowed) {
+                // There is nothing to check on hosts without Landlock.
+                Err(Error::Unsupported) => return,
+                result => result.unwrap(),
+            }
+            assert!(File::open(&allowed_file).is_ok());
+            assert!(File::create(&allowed_file).is_err());
+            assert!(File::open(&other_file).is_err());
+        })
+        .join()
+        .unwrap();
+        // The other threads keep their access.
+        assert!(File::open(other_dir.as_path().join("other")).is_ok());
+
+        let missing = vec![AllowedPath {
+            path: PathBuf::from("/no/such/path"),
+            read_only: true,
+        }];
+        thread::spawn(move || match restrict_fs_access(&missing) {
+            Err(Error::Unsupported) | Err(Error::OpenPath(_, _)) => (),
+            _ => panic!("The missing path should not be allowed"),
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_error_display() {
+        let path = PathBuf::from("/srv/rootfs.ext4");
+        assert_eq!(
+            format!(
+                "{}",
+                Error::AddRule(path.clone(), io::Error::from_raw_os_error(libc::EINVAL))
+            ),
+            format!(
+                "Cannot allow the access to /srv/rootfs.ext4: {}",
+                io::Error::from_raw_os_error(libc::EINVAL)
+            )
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::OpenPath(path, io::Error::from_raw_os_error(libc::ENOENT))
+            ),
+            format!(
+                "Cannot open /srv/rootfs.ext4: {}",
+                io::Error::from_raw_os_error(libc::ENOENT)
+            )
+        );
+        assert_eq!(
+            format!("{}", Error::Unsupported),
+            "Landlock is not supported by the host kernel."
+        );
+    }
+}
@@ -10,11 +10,29 @@
 //! machine (microVM).
 #![deny(missing_docs)]
 
+/// Commands carried out by the guest agent, over the vsock agent port.
+#[cfg(feature = "vsock")]
+pub mod agent;
+/// Checks that the configured resources can boot a microVM, without booting it.
+pub mod boot_check;
 /// Handles setup and initialization a `Vmm` object.
 pub mod builder;
+/// Guest memory core dumps in the ELF format.
+pub mod coredump;
 /// Syscalls allowed through the seccomp filter.
 pub mod default_syscalls;
 pub(crate) mod device_manager;
+/// Embedding of microVMs in other Rust programs, without the API server.
+pub mod embedded;
+/// GDB remote protocol server, to debug the guest kernel.
+#[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+pub(crate) mod gdb;
+/// Landlock restrictions on the filesystem access of the microVM.
+pub mod landlock;
+/// Stream of the lifecycle events of the microVM.
+pub mod lifecycle;
+/// Reopening of the log and metrics files, so that they can be rotated.
+pub mod log_rotation;
 pub mod memory_snapshot;
 /// Save/restore utilities.
 pub mod persist;
@@ -22,6 +40,8 @@ pub mod persist;
 pub mod resources;
 /// microVM RPC API adapters.
 pub mod rpc_interface;
+/// Readiness, status and watchdog notifications to systemd.
+pub mod sd_notify;
 /// Signal handling utilities.
 pub mod signal_handler;
 /// microVM state versions.
@@ -33,38 +53,94 @@ mod vstate;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::io;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicU8, Ordering};
 #[cfg(target_arch = "x86_64")]
 use std::sync::mpsc::RecvTimeoutError;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
+use crate::lifecycle::{LifecycleEventKind, LIFECYCLE_EVENTS};
+#[cfg(all(feature = "virtio-mem", target_arch = "x86_64"))]
+use crate::memory_snapshot::GuestMemoryPluggedBlocks;
 #[cfg(target_arch = "x86_64")]
 use crate::memory_snapshot::SnapshotMemory;
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+use crate::persist::TpmDeviceState;
 #[cfg(target_arch = "x86_64")]
-use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
+use crate::persist::{
+    create_snapshot, MicrovmState, MicrovmStateError, VmInfo, WatchdogDeviceState,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::version_map::VERSION_MAP;
+use crate::vmm_config::cpu_quota::{CPU_QUOTA_PERIOD_US, UNLIMITED_CPU_QUOTA_PCT};
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::guest_clock::ClockPolicy;
+use crate::vmm_config::guest_panic::{
+    GuestEventKind, GuestEventSource, GuestEvents, GuestState, PanicAction,
+};
+use crate::vmm_config::machine_stats::{self, MachineStats, VcpuStats};
+#[cfg(feature = "net")]
+use crate::vmm_config::net::NetRateLimiterStats;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::shutdown_behavior::ShutdownBehaviorConfig;
+use crate::vmm_config::snapshot::LoadSnapshotParams;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::snapshot::{CreateSnapshotParams, SnapshotType};
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+use crate::vmm_config::tpm::TpmConfig;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::watchdog::WatchdogAction;
+#[cfg(any(feature = "block", feature = "net"))]
+use crate::vmm_config::RateLimiterStats;
 #[cfg(target_arch = "x86_64")]
 use crate::vstate::vcpu::VcpuState;
+#[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+use crate::vstate::vcpu::{DebugRequest, DebugResponse};
 use crate::vstate::{
     vcpu::{Vcpu, VcpuEvent, VcpuHandle, VcpuResponse},
     vm::Vm,
 };
 use arch::DeviceType;
+use devices::legacy::{PvPanic, PVPANIC_CRASH_LOADED, PVPANIC_PANICKED};
+#[cfg(feature = "balloon")]
 use devices::virtio::balloon::Error as BalloonError;
+#[cfg(feature = "virtio-mem")]
+use devices::virtio::mem::Error as VirtioMemError;
+#[cfg(feature = "net")]
+use devices::virtio::net::device::NetDeviceStats;
+#[cfg(all(feature = "virtio-fs", target_arch = "x86_64"))]
+use devices::virtio::TYPE_FS;
+#[cfg(feature = "balloon")]
+use devices::virtio::{
+    Balloon, BalloonConfig, BalloonPolicy, BalloonStats, BALLOON_DEV_ID, BALLOON_PAGE_SIZE,
+    TYPE_BALLOON,
+};
+#[cfg(feature = "block")]
+use devices::virtio::{Block, TYPE_BLOCK};
+#[cfg(feature = "net")]
+use devices::virtio::{Net, TYPE_NET};
+#[cfg(feature = "vsock")]
 use devices::virtio::{
-    Balloon, BalloonConfig, BalloonStats, Block, MmioTransport, Net, BALLOON_DEV_ID, TYPE_BALLOON,
-    TYPE_BLOCK, TYPE_NET,
+    VhostVsock, Vsock, VsockDeviceStats, VsockUnixBackend, TYPE_VSOCK, VSOCK_DEV_ID,
 };
+#[cfg(feature = "virtio-mem")]
+use devices::virtio::{VirtioMem, VirtioMemStatus, MEM_DEV_ID, TYPE_MEM};
 use devices::BusDevice;
-use logger::{error, info, warn, LoggerError, MetricsError, METRICS};
+use logger::{error, info, warn, IncMetric, LoggerError, MetricsError, Span, METRICS};
 use polly::event_manager::{EventManager, Subscriber};
+use polly::worker::{Error as WorkerError, EventWorker, PausedWorker};
+#[cfg(feature = "block")]
+use rate_limiter::adaptive::AdaptiveRate;
+#[cfg(any(feature = "block", feature = "net", feature = "vsock"))]
 use rate_limiter::BucketUpdate;
 use seccomp::BpfProgramRef;
 #[cfg(target_arch = "x86_64")]
 use snapshot::Persist;
+use timerfd::{SetTimeFlags, TimerFd, TimerState};
 use utils::epoll::{EpollEvent, EventSet};
 use utils::eventfd::EventFd;
 use vm_memory::{GuestMemory, GuestMemoryMmap, GuestMemoryRegion, GuestRegionMmap};
@@ -87,10 +163,11 @@ pub const FC_EXIT_CODE_SIGXFSZ: u8 = 151;
 pub const FC_EXIT_CODE_SIGXCPU: u8 = 154;
 /// Firecracker was shut down after intercepting `SIGPIPE`.
 pub const FC_EXIT_CODE_SIGPIPE: u8 = 155;
-/// Firecracker was shut down after intercepting `SIGHUP`.
-pub const FC_EXIT_CODE_SIGHUP: u8 = 156;
 /// Firecracker was shut down after intercepting `SIGILL`.
 pub const FC_EXIT_CODE_SIGILL: u8 = 157;
+/// Firecracker was shut down after intercepting `SIGTERM`, once the microVM was paused and its
+/// metrics written.
+pub const FC_EXIT_CODE_SIGTERM: u8 = 158;
 /// Bad configuration for microvm's resources, when using a single json.
 pub const FC_EXIT_CODE_BAD_CONFIGURATION: u8 = 152;
 /// Command line arguments parsing error.
@@ -113,6 +190,9 @@ pub enum Error {
     EventFd(io::Error),
     /// I8042 Error.
     I8042Error(devices::legacy::I8042DeviceError),
+    /// The number of plugged vCPUs is not between 1 and the number of vCPUs of the microVM.
+    #[cfg(target_arch = "x86_64")]
+    InvalidVcpuCount(u8),
     /// Cannot access kernel file.
     KernelFile(io::Error),
     /// Cannot open /dev/kvm. Either the host does not have KVM or Firecracker does not have
@@ -123,8 +203,12 @@ pub enum Error {
     LegacyIOBus(device_manager::legacy::Error),
     /// Internal logger error.
     Logger(LoggerError),
+    /// Cannot gather the host resources used by the microVM.
+    MachineStats(io::Error),
     /// Internal metrics system error.
     Metrics(MetricsError),
+    /// The microVM cannot be torn down for the process to build another one.
+    NotReusable,
     /// Cannot add a device to the MMIO Bus.
     RegisterMMIODevice(device_manager::mmio::Error),
     /// Cannot build seccomp filters.
@@ -137,6 +221,9 @@ pub enum Error {
     VcpuConfigure(vstate::vcpu::VcpuError),
     /// Vcpu create error.
     VcpuCreate(vstate::vcpu::Error),
+    /// A vCPU failed to serve a request of the debugger.
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    VcpuDebug,
     /// Cannot send event to vCPU.
     VcpuEvent(vstate::vcpu::Error),
     /// Cannot create a vCPU handle.
@@ -149,6 +236,9 @@ pub enum Error {
     VcpuResume,
     /// Vcpu send message failed.
     VcpuMessage,
+    /// The vCPUs cannot be hotplugged, since the microVM has no more vCPUs than it booted with.
+    #[cfg(target_arch = "x86_64")]
+    VcpuHotplugNotConfigured,
     /// Cannot spawn a new Vcpu thread.
     VcpuSpawn(io::Error),
     /// Vm error.
@@ -170,24 +260,45 @@ impl Display for Error {
             DirtyBitmap(e) => write!(f, "Error getting the KVM dirty bitmap. {}", e),
             EventFd(e) => write!(f, "Event fd error: {}", e),
             I8042Error(e) => write!(f, "I8042 error: {}", e),
+            #[cfg(target_arch = "x86_64")]
+            InvalidVcpuCount(count) => write!(
+                f,
+                "Cannot plug {} vCPUs: the count must be between 1 and the maximum vCPU count.",
+                count
+            ),
             KernelFile(e) => write!(f, "Cannot access kernel file: {}", e),
             KvmContext(e) => write!(f, "Failed to validate KVM support: {}", e),
             #[cfg(target_arch = "x86_64")]
             LegacyIOBus(e) => write!(f, "Cannot add devices to the legacy I/O Bus. {}", e),
             Logger(e) => write!(f, "Logger error: {}", e),
+            MachineStats(e) => write!(f, "Cannot gather the machine statistics: {}", e),
             Metrics(e) => write!(f, "Metrics error: {}", e),
+            NotReusable => write!(
+                f,
+                "The microVM cannot be torn down, since the VMM thread is restricted by seccomp \
+                 filters which do not allow building another microVM, or by Landlock, or a \
+                 debugger is attached."
+            ),
             RegisterMMIODevice(e) => write!(f, "Cannot add a device to the MMIO Bus. {}", e),
             SeccompFilters(e) => write!(f, "Cannot build seccomp filters: {}", e),
             Serial(e) => write!(f, "Error writing to the serial console: {}", e),
             TimerFd(e) => write!(f, "Error creating timer fd: {}", e),
             VcpuConfigure(e) => write!(f, "Error configuring the vcpu for boot: {}", e),
             VcpuCreate(e) => write!(f, "Error creating the vcpu: {}", e),
+            #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+            VcpuDebug => write!(f, "Failed to serve the debugger request on the vCPU."),
             VcpuEvent(e) => write!(f, "Cannot send event to vCPU. {}", e),
             VcpuHandle(e) => write!(f, "Cannot create a vCPU handle. {}", e),
             VcpuPause => write!(f, "Failed to pause the vCPUs."),
             VcpuExit => write!(f, "Failed to exit the vCPUs."),
             VcpuResume => write!(f, "Failed to resume the vCPUs."),
             VcpuMessage => write!(f, "Failed to message the vCPUs."),
+            #[cfg(target_arch = "x86_64")]
+            VcpuHotplugNotConfigured => write!(
+                f,
+                "vCPU hotplug is not configured: the maximum vCPU count must exceed the \
+                 vCPU count at boot."
+            ),
             VcpuSpawn(e) => write!(f, "Cannot spawn Vcpu thread: {}", e),
             Vm(e) => write!(f, "Vm error: {}", e),
             VmmObserverInit(e) => write!(
@@ -233,10 +344,61 @@ pub struct Vmm {
     guest_memory: GuestMemoryMmap,
 
     vcpus_handles: Vec<VcpuHandle>,
+    // The vCPUs are started paused.
+    paused: bool,
     exit_evt: EventFd,
     vm: Vm,
 
+    // Guest panic handling.
+    panic_evt: EventFd,
+    panic_action: PanicAction,
+    pvpanic: Arc<Mutex<PvPanic>>,
+    pvpanic_evt: EventFd,
+    guest_events: GuestEvents,
+    // The snapshot to restore once this microVM was torn down after a guest panic.
+    restore_request: Option<LoadSnapshotParams>,
+    #[cfg(target_arch = "x86_64")]
+    watchdog_action: WatchdogAction,
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    tpm_config: Option<TpmConfig>,
+    // How the guest clock is kept right when the microVM resumes.
+    #[cfg(target_arch = "x86_64")]
+    clock_policy: ClockPolicy,
+    // When the state the microVM was restored from was saved, until the microVM resumes.
+    #[cfg(target_arch = "x86_64")]
+    snapshot_time_us: Option<u64>,
+    // Stops the microVM when a graceful shutdown takes too long.
+    #[cfg(target_arch = "x86_64")]
+    shutdown_timer: TimerFd,
+    // When the dirty pages were last counted for the machine statistics.
+    dirty_pages_sampled_us: u64,
+    // The CPU quota of each vCPU thread, in percents of a host core, shared with the vCPUs.
+    cpu_quota_pct: Arc<AtomicU8>,
+    // Kicks the vCPUs out of `KVM_RUN` once per accounting period while they are throttled.
+    throttle_timer: TimerFd,
+    // The threads the block and network devices are spread across, besides the VMM thread.
+    io_workers: Vec<EventWorker>,
+    // How many block and network devices were assigned to the I/O workers.
+    #[cfg(any(feature = "block", feature = "net"))]
+    io_devices: usize,
+    // Written to by the I/O workers whose event loop failed.
+    io_worker_failed_evt: EventFd,
+    // Whether the VMM thread can build another microVM once this one is torn down.
+    reusable: bool,
+    // Whether stopping the microVM terminates the process, unlike for the embedded microVMs.
+    exit_on_stop: bool,
+    // The exit code of the stopped microVM, when stopping it does not terminate the process.
+    exit_code: Option<i32>,
+    // Written to by the `SIGTERM` handler, so that the microVM is shut down gracefully.
+    sigterm_evt: EventFd,
+    // What is done with the microVM before exiting on `SIGTERM`.
+    #[cfg(target_arch = "x86_64")]
+    shutdown_behavior: ShutdownBehaviorConfig,
+
     // Guest VM devices.
+    // Where the MMIO devices are, and how many of them there can be.
+    #[cfg(target_arch = "x86_64")]
+    mmio_layout: arch::MmioLayout,
     mmio_device_manager: MMIODeviceManager,
     #[cfg(target_arch = "x86_64")]
     pio_device_manager: PortIODeviceManager,
@@ -270,6 +432,7 @@ impl Vmm {
 
         for mut vcpu in vcpus.drain(..) {
             vcpu.set_mmio_bus(self.mmio_device_manager.bus.clone());
+            vcpu.set_cpu_quota(self.cpu_quota_pct.clone());
             #[cfg(target_arch = "x86_64")]
             vcpu.kvm_vcpu
                 .set_pio_bus(self.pio_device_manager.io_bus.clone());
@@ -300,17 +463,435 @@ impl Vmm {
         Ok(())
     }
 
+    // Returns the indexes of the vCPUs whose threads run while the microVM runs. The vCPUs which
+    // aren't present in the guest stay paused.
+    fn running_vcpus(&self) -> Vec<usize> {
+        let vcpus = 0..self.vcpus_handles.len();
+        #[cfg(target_arch = "x86_64")]
+        if let Some(cpu_hotplug) = self.pio_device_manager.cpu_hotplug.as_ref() {
+            let cpu_hotplug = cpu_hotplug.lock().expect("Poisoned lock");
+            return vcpus
+                .filter(|index| cpu_hotplug.is_present(*index as u8))
+                .collect();
+        }
+        vcpus.collect()
+    }
+
+    // Sends a resume command to the vCPUs in `vcpus` and waits for them to resume.
+    fn resume_vcpus(&self, vcpus: &[usize]) -> Result<()> {
+        for index in vcpus.iter() {
+            self.vcpus_handles[*index]
+                .send_event(VcpuEvent::Resume)
+                .map_err(|_| Error::VcpuMessage)?;
+        }
+        for index in vcpus.iter() {
+            match self.vcpus_handles[*index]
+                .response_receiver()
+                .recv_timeout(Duration::from_millis(1000))
+            {
+                Ok(VcpuResponse::Resumed) => (),
+                _ => return Err(Error::VcpuResume),
+            }
+        }
+        Ok(())
+    }
+
+    // Sends a pause command to the vCPUs in `vcpus` and waits for them to pause.
+    #[cfg(target_arch = "x86_64")]
+    fn pause_vcpus(&self, vcpus: &[usize]) -> Result<()> {
+        for index in vcpus.iter() {
+            self.vcpus_handles[*index]
+                .send_event(VcpuEvent::Pause)
+                .map_err(|_| Error::VcpuMessage)?;
+        }
+        for index in vcpus.iter() {
+            match self.vcpus_handles[*index]
+                .response_receiver()
+                .recv_timeout(Duration::from_millis(1000))
+            {
+                Ok(VcpuResponse::Paused) => (),
+                _ => return Err(Error::VcpuPause),
+            }
+        }
+        Ok(())
+    }
+
     /// Sends a resume command to the vCPUs.
     pub fn resume_vm(&mut self) -> Result<()> {
+        let _span = Span::timed("resume_microvm", &METRICS.timings_us.resume_vm);
+        #[cfg(target_arch = "x86_64")]
+        self.sync_guest_clock()?;
         self.mmio_device_manager.kick_devices();
-        self.broadcast_vcpu_event(VcpuEvent::Resume, VcpuResponse::Resumed)
-            .map_err(|_| Error::VcpuResume)
+        #[cfg(target_arch = "x86_64")]
+        if let Some(watchdog) = self.mmio_device_manager.watchdog.as_ref() {
+            watchdog.lock().expect("Poisoned lock").resume();
+        }
+        self.resume_vcpus(&self.running_vcpus())?;
+        self.paused = false;
+        LIFECYCLE_EVENTS.emit(LifecycleEventKind::Resumed);
+        Ok(())
     }
 
     /// Sends a pause command to the vCPUs.
     pub fn pause_vm(&mut self) -> Result<()> {
         self.broadcast_vcpu_event(VcpuEvent::Pause, VcpuResponse::Paused)
-            .map_err(|_| Error::VcpuPause)
+            .map_err(|_| Error::VcpuPause)?;
+        self.paused = true;
+        LIFECYCLE_EVENTS.emit(LifecycleEventKind::Paused);
+        // The guest can't pet the watchdog while paused.
+        #[cfg(target_arch = "x86_64")]
+        if let Some(watchdog) = self.mmio_device_manager.watchdog.as_ref() {
+            watchdog.lock().expect("Poisoned lock").pause();
+        }
+        Ok(())
+    }
+
+    /// Sets the number of vCPUs present in the guest. The threads of the inserted vCPUs are
+    /// resumed before the guest is notified through the ACPI GED. The guest is asked to eject the
+    /// vCPUs past the count, whose threads are paused once it took them offline and ejected them.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_vcpu_count(&mut self, vcpu_count: u8) -> Result<()> {
+        let cpu_hotplug = self
+            .pio_device_manager
+            .cpu_hotplug
+            .as_ref()
+            .ok_or(Error::VcpuHotplugNotConfigured)?;
+        let inserted: Vec<usize> = cpu_hotplug
+            .lock()
+            .expect("Poisoned lock")
+            .set_plugged(vcpu_count)
+            .ok_or(Error::InvalidVcpuCount(vcpu_count))?
+            .into_iter()
+            .map(usize::from)
+            .collect();
+        // The vCPUs of a paused microVM resume along with the others.
+        if !self.paused {
+            self.resume_vcpus(&inserted)?;
+        }
+        cpu_hotplug.lock().expect("Poisoned lock").notify_guest();
+        LIFECYCLE_EVENTS.emit(LifecycleEventKind::VcpusPlugged { vcpu_count });
+        Ok(())
+    }
+
+    /// Pauses the threads of the vCPUs the guest ejected.
+    #[cfg(target_arch = "x86_64")]
+    fn handle_cpu_eject(&mut self) {
+        let _ = self.pio_device_manager.cpu_eject_evt.read();
+        if self.paused {
+            return;
+        }
+        let running = self.running_vcpus();
+        let ejected: Vec<usize> = (0..self.vcpus_handles.len())
+            .filter(|index| !running.contains(index))
+            .collect();
+        // The vCPUs which were already paused just answer again.
+        if let Err(e) = self.pause_vcpus(&ejected) {
+            error!("Failed to pause the ejected vCPUs: {}", e);
+        }
+    }
+
+    /// Sends a request of the debugger to the vCPU `index`, which must be paused, and returns
+    /// its response.
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    pub(crate) fn debug_vcpu(&self, index: usize, request: DebugRequest) -> Result<DebugResponse> {
+        let handle = self.vcpus_handles.get(index).ok_or(Error::VcpuDebug)?;
+        handle
+            .send_event(VcpuEvent::Debug(request))
+            .map_err(|_| Error::VcpuMessage)?;
+        match handle
+            .response_receiver()
+            .recv_timeout(Duration::from_millis(1000))
+        {
+            Ok(VcpuResponse::Debug(response)) => Ok(response),
+            _ => Err(Error::VcpuDebug),
+        }
+    }
+
+    /// Specifies if the vCPUs are paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Sets the action taken when the guest kernel panics.
+    pub fn set_panic_action(&mut self, panic_action: PanicAction) {
+        self.panic_action = panic_action;
+    }
+
+    /// Returns the snapshot a new microVM is to be restored from, once this one was torn down
+    /// by the `RestoreSnapshot` panic action.
+    pub fn take_restore_request(&mut self) -> Option<LoadSnapshotParams> {
+        self.restore_request.take()
+    }
+
+    /// Sets the share of a host core each vCPU thread may use, in percents. The vCPU threads
+    /// which used up their quota are paused until the next accounting period.
+    pub fn set_cpu_quota(&mut self, quota_pct: u8) {
+        self.cpu_quota_pct.store(quota_pct, Ordering::Relaxed);
+        // The vCPUs only account for their CPU time when they exit out of `KVM_RUN`, which a
+        // guest busy computing may not do on its own.
+        let timer_state = if quota_pct < UNLIMITED_CPU_QUOTA_PCT {
+            let period = Duration::from_micros(CPU_QUOTA_PERIOD_US);
+            TimerState::Periodic {
+                current: period,
+                interval: period,
+            }
+        } else {
+            TimerState::Disarmed
+        };
+        self.throttle_timer
+            .set_state(timer_state, SetTimeFlags::Default);
+    }
+
+    // Kicks the running vCPUs, so that they check whether they used up their CPU quota.
+    fn handle_throttle_timer(&mut self) {
+        self.throttle_timer.read();
+        if self.paused {
+            return;
+        }
+        for index in self.running_vcpus() {
+            if let Err(e) = self.vcpus_handles[index].kick() {
+                error!("Failed to kick a throttled vCPU: {}", e);
+            }
+        }
+    }
+
+    /// Sets how the guest clock is kept right when the microVM resumes.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_clock_policy(&mut self, clock_policy: ClockPolicy) {
+        self.clock_policy = clock_policy;
+    }
+
+    // Advances the guest clock restored from a snapshot by the time elapsed since the snapshot
+    // was created, and tells the guest that it was paused, if the clock policy asks for it.
+    #[cfg(target_arch = "x86_64")]
+    fn sync_guest_clock(&mut self) -> Result<()> {
+        let snapshot_time_us = self.snapshot_time_us.take();
+        if self.clock_policy != ClockPolicy::Sync || !self.paused {
+            return Ok(());
+        }
+        if let Some(snapshot_time_us) = snapshot_time_us {
+            let elapsed_us = utils::time::get_time_us(utils::time::ClockType::Real)
+                .saturating_sub(snapshot_time_us);
+            self.vm
+                .advance_clock(elapsed_us.saturating_mul(1000))
+                .map_err(Error::Vm)?;
+        }
+        self.broadcast_vcpu_event(VcpuEvent::NotifyPause, VcpuResponse::PauseNotified)
+    }
+
+    /// Returns the state of the guest kernel and the latest events which changed it.
+    pub fn guest_events(&self) -> GuestEvents {
+        self.guest_events.clone()
+    }
+
+    /// Records the events reported through the pvpanic device.
+    fn handle_pvpanic(&mut self) {
+        let events = self.pvpanic.lock().expect("Poisoned lock").take_events();
+        if events & PVPANIC_PANICKED != 0 {
+            self.handle_guest_panic(GuestEventKind::Panicked, GuestEventSource::Pvpanic);
+        }
+        if events & PVPANIC_CRASH_LOADED != 0 {
+            self.handle_guest_panic(GuestEventKind::CrashLoaded, GuestEventSource::Pvpanic);
+        }
+    }
+
+    /// Records a guest kernel crash and applies the configured `PanicAction` the first time
+    /// one is noticed.
+    fn handle_guest_panic(&mut self, kind: GuestEventKind, source: GuestEventSource) {
+        METRICS.vmm.guest_panic_count.inc();
+        let first_crash = self.guest_events.state != GuestState::Crashed;
+        self.guest_events.record(kind, source);
+        LIFECYCLE_EVENTS.emit(LifecycleEventKind::GuestEvent { kind, source });
+        if !first_crash {
+            warn!(
+                "Guest kernel crash reported again: {:?} ({:?})",
+                kind, source
+            );
+            return;
+        }
+        warn!(
+            "Guest kernel crash detected: {:?} ({:?}), applying action: {:?}",
+            kind, source, self.panic_action
+        );
+
+        match self.panic_action.clone() {
+            PanicAction::None => (),
+            PanicAction::Pause => {
+                if let Err(e) = self.pause_vm() {
+                    error!("Failed to pause the microVM after a guest panic: {}", e);
+                }
+            }
+            #[cfg(target_arch = "x86_64")]
+            PanicAction::SnapshotAndStop {
+                snapshot_path,
+                mem_file_path,
+            } => {
+                let params = CreateSnapshotParams {
+                    snapshot_type: SnapshotType::Full,
+                    snapshot_path,
+                    mem_file_path,
+                    single_file: false,
+                    dedup_pages: false,
+                    compact_diff: false,
+                    version: None,
+                };
+                let exit_code = match self.pause_vm() {
+                    Ok(()) => match create_snapshot(self, &params, VERSION_MAP.clone()) {
+                        Ok(()) => FC_EXIT_CODE_OK,
+                        Err(e) => {
+                            error!("Failed to snapshot the microVM after a guest panic: {}", e);
+                            FC_EXIT_CODE_GENERIC_ERROR
+                        }
+                    },
+                    Err(e) => {
+                        error!("Failed to pause the microVM after a guest panic: {}", e);
+                        FC_EXIT_CODE_GENERIC_ERROR
+                    }
+                };
+                self.stop(i32::from(exit_code));
+            }
+            #[cfg(target_arch = "x86_64")]
+            PanicAction::RestoreSnapshot {
+                snapshot_path,
+                mem_file_path,
+            } => {
+                // Nothing restores the embedded microVMs once they are torn down.
+                let result = if self.exit_on_stop {
+                    self.teardown()
+                } else {
+                    Err(Error::NotReusable)
+                };
+                match result {
+                    Ok(()) => {
+                        self.restore_request = Some(LoadSnapshotParams {
+                            snapshot_path,
+                            mem_file_path,
+                            mem_diff_paths: Vec::new(),
+                            enable_diff_snapshots: false,
+                            resume_vm: true,
+                            #[cfg(feature = "balloon")]
+                            balloon_amount_mib: None,
+                            #[cfg(feature = "balloon")]
+                            deflate_balloon: false,
+                            mem_backend: None,
+                            mem_hints: None,
+                            io_threads: None,
+                            mmds_data: None,
+                            clone: None,
+                        })
+                    }
+                    Err(e) => {
+                        error!("Failed to tear down the microVM after a guest panic: {}", e);
+                        self.stop(i32::from(FC_EXIT_CODE_GENERIC_ERROR));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sets what is done with the microVM before exiting on `SIGTERM`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_shutdown_behavior(&mut self, shutdown_behavior: ShutdownBehaviorConfig) {
+        self.shutdown_behavior = shutdown_behavior;
+    }
+
+    /// Pauses the microVM, saves its snapshot if the shutdown behavior asks for it, and exits
+    /// with `FC_EXIT_CODE_SIGTERM`.
+    fn handle_sigterm(&mut self) {
+        let _ = self.sigterm_evt.read();
+        info!("Shutting down after intercepting SIGTERM.");
+
+        if !self.paused {
+            if let Err(e) = self.pause_vm() {
+                error!("Failed to pause the microVM on SIGTERM: {}", e);
+                self.stop(i32::from(FC_EXIT_CODE_GENERIC_ERROR));
+                return;
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(snapshot) = self.shutdown_behavior.snapshot.clone() {
+            let params = CreateSnapshotParams {
+                snapshot_type: SnapshotType::Full,
+                snapshot_path: snapshot.snapshot_path,
+                mem_file_path: snapshot.mem_file_path,
+                single_file: false,
+                dedup_pages: false,
+                compact_diff: false,
+                version: None,
+            };
+            if let Err(e) = create_snapshot(self, &params, VERSION_MAP.clone()) {
+                error!("Failed to snapshot the microVM on SIGTERM: {}", e);
+                self.stop(i32::from(FC_EXIT_CODE_GENERIC_ERROR));
+                return;
+            }
+            info!("Saved the snapshot of the microVM on SIGTERM.");
+        }
+
+        self.stop(i32::from(FC_EXIT_CODE_SIGTERM));
+    }
+
+    /// Sets the action taken when the watchdog expires.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_watchdog_action(&mut self, watchdog_action: WatchdogAction) {
+        self.watchdog_action = watchdog_action;
+    }
+
+    /// Sets the configuration of the TPM backend, saved along with the TPM state.
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    pub fn set_tpm_config(&mut self, tpm_config: TpmConfig) {
+        self.tpm_config = Some(tpm_config);
+    }
+
+    // Returns the file descriptor signaling the expiry of the watchdog, if present.
+    #[cfg(target_arch = "x86_64")]
+    fn watchdog_fd(&self) -> Option<RawFd> {
+        self.mmio_device_manager
+            .watchdog
+            .as_ref()
+            .map(|watchdog| watchdog.lock().expect("Poisoned lock").as_raw_fd())
+    }
+
+    // There is no watchdog on aarch64.
+    #[cfg(target_arch = "aarch64")]
+    fn watchdog_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Records the expiry of the watchdog and applies the configured `WatchdogAction`.
+    #[cfg(target_arch = "x86_64")]
+    fn handle_watchdog_expiry(&mut self) {
+        // The end of the first stage of the timer only starts the second one.
+        let expired = self
+            .mmio_device_manager
+            .watchdog
+            .as_ref()
+            .map_or(false, |watchdog| {
+                watchdog
+                    .lock()
+                    .expect("Poisoned lock")
+                    .process_timer_event()
+            });
+        if !expired {
+            return;
+        }
+        METRICS.vmm.watchdog_expired_count.inc();
+        self.guest_events
+            .record(GuestEventKind::WatchdogExpired, GuestEventSource::Watchdog);
+        LIFECYCLE_EVENTS.emit(LifecycleEventKind::GuestEvent {
+            kind: GuestEventKind::WatchdogExpired,
+            source: GuestEventSource::Watchdog,
+        });
+        warn!(
+            "Guest watchdog expired, applying action: {:?}",
+            self.watchdog_action
+        );
+
+        match self.watchdog_action {
+            WatchdogAction::None => (),
+            WatchdogAction::Reset => self.stop(i32::from(FC_EXIT_CODE_OK)),
+            WatchdogAction::Stop => self.stop(i32::from(FC_EXIT_CODE_GENERIC_ERROR)),
+        }
     }
 
     /// Sends an exit command to the vCPUs.
@@ -326,6 +907,30 @@ impl Vmm {
         &self.guest_memory
     }
 
+    /// Returns the I/O worker the next block or network device is assigned to, if any.
+    #[cfg(any(feature = "block", feature = "net"))]
+    pub(crate) fn next_io_worker(&mut self) -> Option<&EventWorker> {
+        if self.io_workers.is_empty() {
+            return None;
+        }
+        let index = self.io_devices % self.io_workers.len();
+        self.io_devices += 1;
+        self.io_workers.get(index)
+    }
+
+    /// Stops the I/O workers from processing the events of their devices, until the returned
+    /// `PausedWorker`s are dropped.
+    pub(crate) fn pause_io_workers(&self) -> std::result::Result<Vec<PausedWorker>, WorkerError> {
+        self.io_workers.iter().map(EventWorker::pause).collect()
+    }
+
+    /// Stops the microVM once an I/O worker no longer processes the events of its devices.
+    fn handle_io_worker_failure(&mut self) {
+        let _ = self.io_worker_failed_evt.read();
+        error!("An I/O worker stopped processing the I/O of its devices.");
+        self.stop(i32::from(FC_EXIT_CODE_GENERIC_ERROR));
+    }
+
     /// Injects CTRL+ALT+DEL keystroke combo in the i8042 device.
     #[cfg(target_arch = "x86_64")]
     pub fn send_ctrl_alt_del(&mut self) -> Result<()> {
@@ -337,7 +942,72 @@ impl Vmm {
             .map_err(Error::I8042Error)
     }
 
-    /// Waits for all vCPUs to exit and terminates the Firecracker process.
+    /// Presses the ACPI power button, which the guest usually handles by shutting down.
+    #[cfg(target_arch = "x86_64")]
+    pub fn send_power_button(&mut self) {
+        let listening = self
+            .pio_device_manager
+            .acpi_pm
+            .lock()
+            .expect("Poisoned lock")
+            .press_power_button();
+        if !listening {
+            warn!("The power button was pressed, but the guest does not handle it yet.");
+        }
+    }
+
+    /// Asks the guest to shut down by injecting CTRL+ALT+DEL in the i8042 device. The microVM is
+    /// stopped if the guest has not reset the CPU after `timeout_ms` milliseconds.
+    #[cfg(target_arch = "x86_64")]
+    pub fn graceful_shutdown(&mut self, timeout_ms: u64) -> Result<()> {
+        self.send_ctrl_alt_del()?;
+        // A zero duration disarms the timer, so the shortest timeout expires right away instead.
+        let timeout = std::cmp::max(Duration::from_millis(timeout_ms), Duration::from_nanos(1));
+        self.shutdown_timer
+            .set_state(TimerState::Oneshot(timeout), SetTimeFlags::Default);
+        info!(
+            "Graceful shutdown requested, the microVM is stopped in {} ms.",
+            timeout_ms
+        );
+        LIFECYCLE_EVENTS.emit(LifecycleEventKind::ShutdownRequested { timeout_ms });
+        Ok(())
+    }
+
+    // Returns the file descriptor signaling that a graceful shutdown timed out, if supported.
+    #[cfg(target_arch = "x86_64")]
+    fn shutdown_timer_fd(&self) -> Option<RawFd> {
+        Some(self.shutdown_timer.as_raw_fd())
+    }
+
+    // There is no graceful shutdown on aarch64.
+    #[cfg(target_arch = "aarch64")]
+    fn shutdown_timer_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    // Returns the file descriptor signaled when the guest ejects a vCPU.
+    #[cfg(target_arch = "x86_64")]
+    fn cpu_eject_fd(&self) -> Option<RawFd> {
+        Some(self.pio_device_manager.cpu_eject_evt.as_raw_fd())
+    }
+
+    // There is no vCPU hotplug on aarch64.
+    #[cfg(target_arch = "aarch64")]
+    fn cpu_eject_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Stops the microVM whose guest did not shut down gracefully in time.
+    #[cfg(target_arch = "x86_64")]
+    fn handle_shutdown_timeout(&mut self) {
+        self.shutdown_timer.read();
+        METRICS.vmm.graceful_shutdown_timeout_count.inc();
+        warn!("The guest did not shut down gracefully in time, stopping the microVM.");
+        self.stop(i32::from(FC_EXIT_CODE_GENERIC_ERROR));
+    }
+
+    /// Waits for all vCPUs to exit and terminates the Firecracker process. When the process
+    /// embeds the microVM, the exit code is only recorded instead.
     pub fn stop(&mut self, exit_code: i32) {
         info!("Vmm is stopping.");
 
@@ -352,6 +1022,13 @@ impl Vmm {
             error!("Failed to write metrics while stopping: {}", e);
         }
 
+        LIFECYCLE_EVENTS.emit(LifecycleEventKind::Shutdown { exit_code });
+
+        if !self.exit_on_stop {
+            self.exit_code = Some(exit_code);
+            return;
+        }
+
         // Exit from Firecracker using the provided exit code. Safe because we're terminating
         // the process anyway.
         unsafe {
@@ -359,18 +1036,130 @@ impl Vmm {
         }
     }
 
+    /// Sets whether `stop` terminates the process, which the programs embedding the microVM
+    /// do not want.
+    pub fn set_exit_on_stop(&mut self, exit_on_stop: bool) {
+        self.exit_on_stop = exit_on_stop;
+    }
+
+    /// Returns the exit code of the microVM, once it stopped without terminating the process.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Exits the vCPUs and waits for their threads to finish, so that dropping the `Vmm` along
+    /// with its event subscribers releases the microVM, and the process can build another one.
+    pub fn teardown(&mut self) -> Result<()> {
+        if !self.reusable {
+            return Err(Error::NotReusable);
+        }
+        info!("Vmm is tearing down the microVM.");
+
+        if let Some(observer) = self.events_observer.as_mut() {
+            if let Err(e) = observer.on_vmm_stop() {
+                warn!("{}", Error::VmmObserverTeardown(e));
+            }
+        }
+
+        self.exit_vcpus()?;
+        for handle in self.vcpus_handles.iter_mut() {
+            handle.join().map_err(|_| Error::VcpuExit)?;
+        }
+        self.vcpus_handles.clear();
+        // The exited vCPUs signaled the exit event, which must not stop the process.
+        let _ = self.exit_evt.read();
+
+        LIFECYCLE_EVENTS.emit(LifecycleEventKind::TornDown);
+        Ok(())
+    }
+
     /// Saves the state of a paused Microvm.
     #[cfg(target_arch = "x86_64")]
     pub fn save_state(&mut self) -> std::result::Result<MicrovmState, MicrovmStateError> {
         use self::MicrovmStateError::SaveVmState;
+        #[cfg(feature = "virtio-fs")]
+        if self
+            .mmio_device_manager
+            .get_device_info()
+            .keys()
+            .any(|(device_type, _)| *device_type == DeviceType::Virtio(TYPE_FS))
+        {
+            return Err(MicrovmStateError::NotAllowed(
+                "Snapshotting microVMs with shared filesystems is not supported.".to_string(),
+            ));
+        }
+        // The vsock connections served by the host kernel cannot be saved.
+        #[cfg(feature = "vsock")]
+        if self
+            .mmio_device_manager
+            .with_virtio_device_with_id::<VhostVsock, _>(TYPE_VSOCK, VSOCK_DEV_ID, |_| Ok(()))
+            .is_ok()
+        {
+            return Err(MicrovmStateError::NotAllowed(
+                "Snapshotting microVMs with a vhost vsock device is not supported.".to_string(),
+            ));
+        }
+        // The variable store of the firmware lives in its file, which the snapshot can't refer to.
+        if self.mmio_device_manager.pflash.is_some() {
+            return Err(MicrovmStateError::NotAllowed(
+                "Snapshotting microVMs with a firmware variable store is not supported."
+                    .to_string(),
+            ));
+        }
         let vcpu_states = self.save_vcpu_states()?;
 
         let vm_state = self.vm.save_state().map_err(SaveVmState)?;
+        let saved_at_us = utils::time::get_time_us(utils::time::ClockType::Real);
 
         let device_states = self.mmio_device_manager.save();
+        let serial_ports = self.pio_device_manager.save_serial_ports();
+        let watchdog =
+            self.mmio_device_manager
+                .watchdog
+                .as_ref()
+                .map(|watchdog| WatchdogDeviceState {
+                    action: self.watchdog_action,
+                    state: watchdog.lock().expect("Poisoned lock").save_state(),
+                });
+        #[cfg(feature = "tpm")]
+        let tpm = self.mmio_device_manager.tpm.as_ref().and_then(|tpm| {
+            self.tpm_config.as_ref().map(|config| TpmDeviceState {
+                config: config.clone(),
+                state: tpm.lock().expect("Poisoned lock").save_state(),
+            })
+        });
+        #[cfg(target_arch = "x86_64")]
+        let cpu_hotplug = self
+            .pio_device_manager
+            .cpu_hotplug
+            .as_ref()
+            .map(|cpu_hotplug| cpu_hotplug.lock().expect("Poisoned lock").save_state());
+        #[cfg(target_arch = "x86_64")]
+        let acpi_pm = self
+            .pio_device_manager
+            .acpi_pm
+            .lock()
+            .expect("Poisoned lock")
+            .save_state();
 
         let mem_size_mib = mem_size_mib(self.guest_memory());
-        let memory_state = self.guest_memory().describe();
+        #[allow(unused_mut)]
+        let mut memory_state = self.guest_memory().describe();
+        // The blocks of the hotplug memory region unplugged by the guest hold no data, so they
+        // are left out of the memory snapshot.
+        #[cfg(feature = "virtio-mem")]
+        let _ = self
+            .mmio_device_manager
+            .with_virtio_device_with_id::<VirtioMem, _>(TYPE_MEM, MEM_DEV_ID, |virtio_mem| {
+                memory_state.set_plugged_blocks(
+                    virtio_mem.region_addr().0,
+                    GuestMemoryPluggedBlocks {
+                        block_size: virtio_mem.block_size(),
+                        bitmap: virtio_mem.plugged_blocks().to_vec(),
+                    },
+                );
+                Ok(())
+            });
 
         Ok(MicrovmState {
             vm_info: VmInfo { mem_size_mib },
@@ -378,6 +1167,16 @@ impl Vmm {
             vm_state,
             vcpu_states,
             device_states,
+            serial_ports,
+            watchdog,
+            #[cfg(feature = "tpm")]
+            tpm,
+            #[cfg(target_arch = "x86_64")]
+            cpu_hotplug,
+            #[cfg(target_arch = "x86_64")]
+            acpi_pm,
+            saved_at_us: Some(saved_at_us),
+            mmio_layout: self.mmio_layout.into(),
         })
     }
 
@@ -442,6 +1241,15 @@ impl Vmm {
         if vcpu_states.len() != self.vcpus_handles.len() {
             return Err(InvalidInput);
         }
+        // The MSR policy is the same for all the vCPUs, and is enforced by the VM.
+        if let Some(policy) = vcpu_states
+            .first()
+            .and_then(|state| state.msr_policy.as_ref())
+        {
+            self.vm
+                .set_msr_policy(policy)
+                .map_err(MicrovmStateError::RestoreVmState)?;
+        }
         for (handle, state) in self.vcpus_handles.iter().zip(vcpu_states.drain(..)) {
             handle
                 .send_event(VcpuEvent::RestoreState(Box::new(state)))
@@ -505,6 +1313,7 @@ impl Vmm {
 
     /// Updates the path of the host file backing the emulated block device with id `drive_id`.
     /// We update the disk image on the device and its virtio configuration.
+    #[cfg(feature = "block")]
     pub fn update_block_device_path(&mut self, drive_id: &str, path_on_host: String) -> Result<()> {
         self.mmio_device_manager
             .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
@@ -512,10 +1321,15 @@ impl Vmm {
                     .update_disk_image(path_on_host)
                     .map_err(|e| e.to_string())
             })
-            .map_err(Error::DeviceManager)
+            .map_err(Error::DeviceManager)?;
+        LIFECYCLE_EVENTS.emit(LifecycleEventKind::DriveUpdated {
+            drive_id: drive_id.to_string(),
+        });
+        Ok(())
     }
 
     /// Updates the rate limiter parameters for block device with `drive_id` id.
+    #[cfg(feature = "block")]
     pub fn update_block_rate_limiter(
         &mut self,
         drive_id: &str,
@@ -530,7 +1344,36 @@ impl Vmm {
             .map_err(Error::DeviceManager)
     }
 
+    /// Enables the tuning of the rate limiter of the block device with `drive_id` id to the
+    /// latency of its requests, or disables it with `None`.
+    #[cfg(feature = "block")]
+    pub fn update_block_adaptive_rate_limiter(
+        &mut self,
+        drive_id: &str,
+        adaptive: Option<AdaptiveRate>,
+    ) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                block.set_adaptive_rate_limiter(adaptive);
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Updates the weight of the block device with `drive_id` id in the I/O scheduler.
+    #[cfg(feature = "block")]
+    pub fn update_block_io_weight(&mut self, drive_id: &str, io_weight: u32) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                block
+                    .set_io_weight(Some(io_weight))
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(Error::DeviceManager)
+    }
+
     /// Updates the rate limiter parameters for net device with `net_id` id.
+    #[cfg(feature = "net")]
     pub fn update_net_rate_limiters(
         &mut self,
         net_id: &str,
@@ -547,16 +1390,233 @@ impl Vmm {
             .map_err(Error::DeviceManager)
     }
 
+    /// Updates the rate limiter parameters for the vsock device.
+    #[cfg(feature = "vsock")]
+    pub fn update_vsock_rate_limiters(
+        &mut self,
+        rx_bytes: BucketUpdate,
+        rx_ops: BucketUpdate,
+        tx_bytes: BucketUpdate,
+        tx_ops: BucketUpdate,
+    ) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(
+                TYPE_VSOCK,
+                VSOCK_DEV_ID,
+                |vsock: &mut Vsock<VsockUnixBackend>| {
+                    vsock.patch_rate_limiters(rx_bytes, rx_ops, tx_bytes, tx_ops);
+                    Ok(())
+                },
+            )
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Tells the guest driver of the vsock device to drop its connections, and to read the
+    /// context identifier of the device again.
+    #[cfg(feature = "vsock")]
+    pub fn reset_vsock_transport(&mut self) -> Result<()> {
+        self.mmio_device_manager
+            .with_virtio_device_with_id(
+                TYPE_VSOCK,
+                VSOCK_DEV_ID,
+                |vsock: &mut Vsock<VsockUnixBackend>| {
+                    vsock
+                        .send_transport_reset_event()
+                        .map_err(|err| format!("{:?}", err))
+                },
+            )
+            .map_err(Error::DeviceManager)
+    }
+
+    /// Returns the live per-port traffic counters and the open connections of the vsock device.
+    #[cfg(feature = "vsock")]
+    pub fn vsock_stats(&self) -> Result<VsockDeviceStats> {
+        let mut stats = VsockDeviceStats::default();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(
+                TYPE_VSOCK,
+                VSOCK_DEV_ID,
+                |vsock: &mut Vsock<VsockUnixBackend>| {
+                    stats = vsock.backend().stats();
+                    Ok(())
+                },
+            )
+            .map_err(Error::DeviceManager)?;
+        Ok(stats)
+    }
+
+    /// Returns the live traffic counters of the net device with `net_id` id.
+    #[cfg(feature = "net")]
+    pub fn net_stats(&self, net_id: &str) -> Result<NetDeviceStats> {
+        let mut stats = NetDeviceStats::default();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                stats = net.stats();
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)?;
+        Ok(stats)
+    }
+
+    /// Returns the live state of the rate limiter of the block device with `drive_id` id.
+    #[cfg(feature = "block")]
+    pub fn block_rate_limiter_stats(&self, drive_id: &str) -> Result<RateLimiterStats> {
+        let mut stats = RateLimiterStats::default();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                stats = RateLimiterStats::from(block.rate_limiter());
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)?;
+        Ok(stats)
+    }
+
+    /// Returns the live state of the rate limiters of the net device with `net_id` id.
+    #[cfg(feature = "net")]
+    pub fn net_rate_limiter_stats(&self, net_id: &str) -> Result<NetRateLimiterStats> {
+        let mut stats = NetRateLimiterStats::default();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                stats = NetRateLimiterStats {
+                    rx_rate_limiter: RateLimiterStats::from(net.rx_rate_limiter()),
+                    tx_rate_limiter: RateLimiterStats::from(net.tx_rate_limiter()),
+                };
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)?;
+        Ok(stats)
+    }
+
+    /// Returns the host resources used by the microVM.
+    pub fn machine_stats(&mut self) -> Result<MachineStats> {
+        Ok(MachineStats {
+            process_cpu_time_us: utils::time::get_time_us(utils::time::ClockType::ProcessCpu),
+            // The statistics are gathered on the VMM thread.
+            vmm_cpu_time_us: utils::time::get_time_us(utils::time::ClockType::ThreadCpu),
+            vcpu_cpu_time_us: machine_stats::vcpu_cpu_time_us().map_err(Error::MachineStats)?,
+            rss_bytes: machine_stats::rss_bytes().map_err(Error::MachineStats)?,
+            open_fds: machine_stats::open_fds().map_err(Error::MachineStats)?,
+            guest_memory_bytes: self.guest_memory.map_and_fold(
+                0,
+                |(_, region)| region.len(),
+                |a, b| a + b,
+            ),
+            guest_memory_resident_bytes: self.guest_memory_resident_bytes()?,
+            guest_memory_ballooned_bytes: self.guest_memory_ballooned_bytes(),
+            dirty_pages_per_sec: self.dirty_pages_per_sec()?,
+        })
+    }
+
+    /// Returns the KVM exits of each vCPU, and how its running time splits between the guest and
+    /// the VMM, ordered by vCPU index.
+    pub fn vcpu_stats(&self) -> Vec<VcpuStats> {
+        (0..self.vcpus_handles.len() as u8)
+            .map(|index| VcpuStats::new(index, &METRICS.vcpu.vcpus.get(index)))
+            .collect()
+    }
+
+    // Returns the part of the guest memory backed by host memory, in bytes.
+    fn guest_memory_resident_bytes(&self) -> Result<u64> {
+        let page_size = sysconf::page::pagesize();
+        let mut resident_pages = 0u64;
+        self.guest_memory
+            .with_regions_mut(|_, region| -> Result<()> {
+                let len = region.len() as usize;
+                // One byte per page, whose least significant bit tells whether it is resident.
+                let mut residency = vec![0u8; (len + page_size - 1) / page_size];
+                // Safe because the region is a valid mapping of `len` bytes, and `residency`
+                // holds a byte for each of its pages.
+                let ret = unsafe {
+                    libc::mincore(
+                        region.as_ptr() as *mut libc::c_void,
+                        len,
+                        residency.as_mut_ptr(),
+                    )
+                };
+                if ret < 0 {
+                    return Err(Error::MachineStats(io::Error::last_os_error()));
+                }
+                resident_pages += residency.iter().filter(|page| *page & 1 != 0).count() as u64;
+                Ok(())
+            })?;
+        Ok(resident_pages * page_size as u64)
+    }
+
+    // Returns the part of the guest memory given back to the host by the balloon device, in
+    // bytes.
+    #[cfg(feature = "balloon")]
+    fn guest_memory_ballooned_bytes(&self) -> u64 {
+        let busdev = match self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID) {
+            Some(busdev) => busdev,
+            None => return 0,
+        };
+        let virtio_device = busdev
+            .lock()
+            .expect("Poisoned lock")
+            .virtio_transport()
+            .expect("Unexpected BusDevice type")
+            .device();
+
+        let actual_pages = virtio_device
+            .lock()
+            .expect("Poisoned lock")
+            .as_mut_any()
+            .downcast_mut::<Balloon>()
+            .unwrap()
+            .actual_pages();
+        u64::from(actual_pages) * BALLOON_PAGE_SIZE
+    }
+
+    #[cfg(not(feature = "balloon"))]
+    fn guest_memory_ballooned_bytes(&self) -> u64 {
+        0
+    }
+
+    // Returns the rate at which the guest dirtied its memory since the previous call, in pages
+    // per second, or `None` if the dirty pages are not tracked.
+    fn dirty_pages_per_sec(&mut self) -> Result<Option<u64>> {
+        let tracked = self.guest_memory.map_and_fold(
+            true,
+            |(_, region)| region.dirty_bitmap().is_some(),
+            |a, b| a && b,
+        );
+        if !tracked {
+            return Ok(None);
+        }
+
+        // Reading the KVM dirty bitmap clears it, so the dirty pages are recorded in the
+        // Firecracker bitmap for the next diff snapshot to still save them.
+        let dirty_bitmap = self.get_dirty_bitmap()?;
+        let page_size = sysconf::page::pagesize();
+        let mut dirty_pages = 0u64;
+        self.guest_memory
+            .with_regions_mut(|slot, region| -> Result<()> {
+                for (i, v) in dirty_bitmap[&slot].iter().enumerate() {
+                    for j in 0..64 {
+                        if ((v >> j) & 1u64) != 0u64 {
+                            region.mark_dirty_pages(((i * 64) + j) * page_size, page_size);
+                            dirty_pages += 1;
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+
+        let now_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
+        let elapsed_us = std::cmp::max(now_us.saturating_sub(self.dirty_pages_sampled_us), 1);
+        self.dirty_pages_sampled_us = now_us;
+        Ok(Some(dirty_pages * 1_000_000 / elapsed_us))
+    }
+
     /// Returns a reference to the balloon device if present.
+    #[cfg(feature = "balloon")]
     pub fn balloon_config(&self) -> std::result::Result<BalloonConfig, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
         {
             let virtio_device = busdev
                 .lock()
                 .expect("Poisoned lock")
-                .as_any()
-                .downcast_ref::<MmioTransport>()
-                // Only MmioTransport implements BusDevice at this point.
+                .virtio_transport()
                 .expect("Unexpected BusDevice type")
                 .device();
 
@@ -575,15 +1635,14 @@ impl Vmm {
     }
 
     /// Returns the latest balloon statistics if they are enabled.
+    #[cfg(feature = "balloon")]
     pub fn latest_balloon_stats(&self) -> std::result::Result<BalloonStats, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
         {
             let virtio_device = busdev
                 .lock()
                 .expect("Poisoned lock")
-                .as_any()
-                .downcast_ref::<MmioTransport>()
-                // Only MmioTransport implements BusDevice at this point.
+                .virtio_transport()
                 .expect("Unexpected BusDevice type")
                 .device();
 
@@ -604,6 +1663,7 @@ impl Vmm {
     }
 
     /// Updates configuration for the balloon device target size.
+    #[cfg(feature = "balloon")]
     pub fn update_balloon_config(
         &mut self,
         amount_mb: u32,
@@ -620,9 +1680,7 @@ impl Vmm {
                 let virtio_device = busdev
                     .lock()
                     .expect("Poisoned lock")
-                    .as_any()
-                    .downcast_ref::<MmioTransport>()
-                    // Only MmioTransport implements BusDevice at this point.
+                    .virtio_transport()
                     .expect("Unexpected BusDevice type")
                     .device();
 
@@ -645,6 +1703,7 @@ impl Vmm {
     }
 
     /// Updates configuration for the balloon device as described in `balloon_stats_update`.
+    #[cfg(feature = "balloon")]
     pub fn update_balloon_stats_config(
         &mut self,
         stats_polling_interval_s: u16,
@@ -655,9 +1714,7 @@ impl Vmm {
                 let virtio_device = busdev
                     .lock()
                     .expect("Poisoned lock")
-                    .as_any()
-                    .downcast_ref::<MmioTransport>()
-                    // Only MmioTransport implements BusDevice at this point.
+                    .virtio_transport()
                     .expect("Unexpected BusDevice type")
                     .device();
 
@@ -674,10 +1731,104 @@ impl Vmm {
             Err(BalloonError::DeviceNotFound)
         }
     }
+
+    /// Sets the policy resizing the balloon based on the memory pressure of the host.
+    #[cfg(feature = "balloon")]
+    pub fn set_balloon_policy(
+        &mut self,
+        policy: BalloonPolicy,
+    ) -> std::result::Result<(), BalloonError> {
+        // The balloon cannot have a target size greater than the size of
+        // the guest memory.
+        if policy.max_mb as u64 > mem_size_mib(self.guest_memory()) {
+            return Err(BalloonError::TooManyPagesRequested);
+        }
+
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+        {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .virtio_transport()
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<Balloon>()
+                .unwrap()
+                .set_policy(policy)
+        } else {
+            Err(BalloonError::DeviceNotFound)
+        }
+    }
+
+    /// Returns the status of the hotplug memory if present.
+    #[cfg(feature = "virtio-mem")]
+    pub fn memory_hotplug_status(&self) -> std::result::Result<VirtioMemStatus, VirtioMemError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID) {
+            let virtio_device = busdev
+                .lock()
+                .expect("Poisoned lock")
+                .virtio_transport()
+                .expect("Unexpected BusDevice type")
+                .device();
+
+            let status = virtio_device
+                .lock()
+                .expect("Poisoned lock")
+                .as_mut_any()
+                .downcast_mut::<VirtioMem>()
+                .unwrap()
+                .status();
+
+            Ok(status)
+        } else {
+            Err(VirtioMemError::DeviceNotFound)
+        }
+    }
+
+    /// Updates the amount of hotplug memory the guest is requested to plug.
+    #[cfg(feature = "virtio-mem")]
+    pub fn update_memory_hotplug_size(
+        &mut self,
+        requested_size_mib: u64,
+    ) -> std::result::Result<(), VirtioMemError> {
+        if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_MEM), MEM_DEV_ID) {
+            {
+                let virtio_device = busdev
+                    .lock()
+                    .expect("Poisoned lock")
+                    .virtio_transport()
+                    .expect("Unexpected BusDevice type")
+                    .device();
+
+                virtio_device
+                    .lock()
+                    .expect("Poisoned lock")
+                    .as_mut_any()
+                    .downcast_mut::<VirtioMem>()
+                    .unwrap()
+                    .update_requested_size(requested_size_mib << 20)?;
+            }
+
+            let locked_dev = busdev.lock().expect("Poisoned lock");
+            locked_dev
+                .interrupt(devices::virtio::VIRTIO_MMIO_INT_CONFIG)
+                .map_err(VirtioMemError::InterruptError)?;
+            LIFECYCLE_EVENTS.emit(LifecycleEventKind::MemoryHotplugResized { requested_size_mib });
+            Ok(())
+        } else {
+            Err(VirtioMemError::DeviceNotFound)
+        }
+    }
 }
 
 impl Drop for Vmm {
     fn drop(&mut self) {
+        signal_handler::clear_sigterm_event_fd(self.sigterm_evt.as_raw_fd());
         let _ = self.exit_vcpus();
     }
 }
@@ -703,15 +1854,50 @@ impl Subscriber for Vmm {
                 })
                 .unwrap_or(FC_EXIT_CODE_OK);
             self.stop(i32::from(exit_code));
+        } else if source == self.panic_evt.as_raw_fd() && event_set == EventSet::IN {
+            let _ = self.panic_evt.read();
+            self.handle_guest_panic(GuestEventKind::Panicked, GuestEventSource::Serial);
+        } else if source == self.pvpanic_evt.as_raw_fd() && event_set == EventSet::IN {
+            let _ = self.pvpanic_evt.read();
+            self.handle_pvpanic();
+        } else if Some(source) == self.watchdog_fd() && event_set == EventSet::IN {
+            #[cfg(target_arch = "x86_64")]
+            self.handle_watchdog_expiry();
+        } else if Some(source) == self.shutdown_timer_fd() && event_set == EventSet::IN {
+            #[cfg(target_arch = "x86_64")]
+            self.handle_shutdown_timeout();
+        } else if Some(source) == self.cpu_eject_fd() && event_set == EventSet::IN {
+            #[cfg(target_arch = "x86_64")]
+            self.handle_cpu_eject();
+        } else if source == self.throttle_timer.as_raw_fd() && event_set == EventSet::IN {
+            self.handle_throttle_timer();
+        } else if source == self.sigterm_evt.as_raw_fd() && event_set == EventSet::IN {
+            self.handle_sigterm();
+        } else if source == self.io_worker_failed_evt.as_raw_fd() && event_set == EventSet::IN {
+            self.handle_io_worker_failure();
         } else {
             error!("Spurious EventManager event for handler: Vmm");
         }
     }
 
     fn interest_list(&self) -> Vec<EpollEvent> {
-        vec![EpollEvent::new(
-            EventSet::IN,
-            self.exit_evt.as_raw_fd() as u64,
-        )]
+        let mut events = vec![
+            EpollEvent::new(EventSet::IN, self.exit_evt.as_raw_fd() as u64),
+            EpollEvent::new(EventSet::IN, self.panic_evt.as_raw_fd() as u64),
+            EpollEvent::new(EventSet::IN, self.pvpanic_evt.as_raw_fd() as u64),
+            EpollEvent::new(EventSet::IN, self.throttle_timer.as_raw_fd() as u64),
+            EpollEvent::new(EventSet::IN, self.sigterm_evt.as_raw_fd() as u64),
+            EpollEvent::new(EventSet::IN, self.io_worker_failed_evt.as_raw_fd() as u64),
+        ];
+        if let Some(fd) = self.watchdog_fd() {
+            events.push(EpollEvent::new(EventSet::IN, fd as u64));
+        }
+        if let Some(fd) = self.shutdown_timer_fd() {
+            events.push(EpollEvent::new(EventSet::IN, fd as u64));
+        }
+        if let Some(fd) = self.cpu_eject_fd() {
+            events.push(EpollEvent::new(EventSet::IN, fd as u64));
+        }
+        events
     }
 }
@@ -0,0 +1,303 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use logger::{error, warn};
+use seccomp::{BpfProgram, SeccompFilter};
+use serde::Serialize;
+
+use crate::sd_notify::SD_NOTIFY;
+use crate::vmm_config::guest_panic::{GuestEventKind, GuestEventSource};
+
+lazy_static! {
+    /// Static instance used for streaming the lifecycle events of the microVM.
+    pub static ref LIFECYCLE_EVENTS: LifecycleEvents = LifecycleEvents::default();
+}
+
+/// A change in the lifecycle of the microVM.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event")]
+pub enum LifecycleEventKind {
+    /// The microVM booted and its vCPUs are running.
+    Started,
+    /// The vCPUs were paused.
+    Paused,
+    /// The vCPUs were resumed.
+    Resumed,
+    /// The creation of a snapshot started. The microVM is paused.
+    SnapshotStarted,
+    /// The snapshot was created.
+    SnapshotCreated,
+    /// The snapshot could not be created.
+    SnapshotFailed,
+    /// The microVM was restored from a snapshot. Its vCPUs are paused.
+    SnapshotLoaded,
+    /// The backing file of a block device was replaced.
+    DriveUpdated {
+        /// The ID of the block device.
+        drive_id: String,
+    },
+    /// The guest was requested to plug a new amount of hotplug memory.
+    MemoryHotplugResized {
+        /// The requested amount of hotplug memory.
+        requested_size_mib: u64,
+    },
+    /// The number of vCPUs the guest may use changed.
+    VcpusPlugged {
+        /// The number of plugged vCPUs.
+        vcpu_count: u8,
+    },
+    /// The guest kernel crashed or stopped petting the watchdog.
+    GuestEvent {
+        /// The event.
+        kind: GuestEventKind,
+        /// The way the event was noticed.
+        source: GuestEventSource,
+    },
+    /// The guest was asked to shut down, and is stopped if it does not after the timeout.
+    ShutdownRequested {
+        /// The timeout, in milliseconds.
+        timeout_ms: u64,
+    },
+    /// The VMM is exiting.
+    Shutdown {
+        /// The exit code of the process.
+        exit_code: i32,
+    },
+    /// The microVM was torn down, and the VMM waits for a new configuration.
+    TornDown,
+}
+
+/// A timestamped lifecycle event.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LifecycleEvent {
+    /// The wall clock time of the event, in microseconds since the Unix epoch.
+    pub timestamp_us: u64,
+    /// The event.
+    #[serde(flatten)]
+    pub kind: LifecycleEventKind,
+}
+
+/// A function called with each lifecycle event, on the thread emitting it. It must not call
+/// back into the microVM.
+pub type EventCallback = Box<dyn Fn(&LifecycleEvent) + Send>;
+
+/// Streams the lifecycle events to the subscribers, one JSON object per line, and hands them to
+/// the callbacks of the programs embedding the VMM.
+/// The writes never block the VMM: a subscriber which does not keep up is disconnected.
+#[derive(Default)]
+pub struct LifecycleEvents {
+    subscribers: Mutex<Vec<UnixStream>>,
+    callbacks: Mutex<Vec<EventCallback>>,
+}
+
+impl LifecycleEvents {
+    /// Adds a subscriber, which receives the events emitted from now on.
+    pub fn subscribe(&self, stream: UnixStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        self.subscribers.lock().expect("Poisoned lock").push(stream);
+        Ok(())
+    }
+
+    /// Adds a callback, which is called with the events emitted from now on.
+    pub fn add_callback(&self, callback: EventCallback) {
+        self.callbacks.lock().expect("Poisoned lock").push(callback);
+    }
+
+    /// Sends a new event to the subscribers and the callbacks.
+    pub fn emit(&self, kind: LifecycleEventKind) {
+        SD_NOTIFY.lifecycle_event(&kind);
+
+        let mut subscribers = self.subscribers.lock().expect("Poisoned lock");
+        let callbacks = self.callbacks.lock().expect("Poisoned lock");
+        if subscribers.is_empty() && callbacks.is_empty() {
+            return;
+        }
+
+        let event = LifecycleEvent {
+            timestamp_us: utils::time::get_time_us(utils::time::ClockType::Real),
+            kind,
+        };
+        for callback in callbacks.iter() {
+            callback(&event);
+        }
+        if subscribers.is_empty() {
+            return;
+        }
+        let mut line = match serde_json::to_string(&event) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Cannot serialize the lifecycle event {:?}: {}", event, e);
+                return;
+            }
+        };
+        line.push('\n');
+
+        subscribers.retain(|stream| match send_all(stream, line.as_bytes()) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Disconnecting a lifecycle events subscriber: {}", e);
+                false
+            }
+        });
+    }
+}
+
+// Writes `buf` to `stream` without raising `SIGPIPE` when the subscriber went away, since
+// Firecracker exits on that signal.
+fn send_all(stream: &UnixStream, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        // Safe because `buf` is a valid slice, and the result is checked.
+        let ret = unsafe {
+            libc::send(
+                stream.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                libc::MSG_NOSIGNAL,
+            )
+        };
+        if ret < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        buf = &buf[ret as usize..];
+    }
+    Ok(())
+}
+
+/// Accepts the subscribers to the lifecycle events on a unix domain socket.
+pub struct EventsListener {
+    listener: UnixListener,
+}
+
+impl EventsListener {
+    /// Binds the listener to the socket at `path`.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(EventsListener {
+            listener: UnixListener::bind(path)?,
+        })
+    }
+
+    /// Loads `seccomp_filter` on the calling thread, then subscribes the clients to
+    /// `LIFECYCLE_EVENTS` until the process exits.
+    pub fn run(self, seccomp_filter: BpfProgram) {
+        // Execution panics if filters cannot be loaded, use --seccomp-level=0 if skipping filters
+        // altogether is the desired behaviour.
+        if let Err(e) = SeccompFilter::apply(seccomp_filter) {
+            panic!(
+                "Failed to set the requested seccomp filters on the events thread: Error: {:?}",
+                e
+            );
+        }
+
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = LIFECYCLE_EVENTS.subscribe(stream) {
+                        error!("Cannot subscribe to the lifecycle events: {}", e);
+                    }
+                }
+                Err(e) => error!("Cannot accept a lifecycle events connection: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_serialize_lifecycle_event() {
+        let event = LifecycleEvent {
+            timestamp_us: 42,
+            kind: LifecycleEventKind::Paused,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"timestamp_us":42,"event":"Paused"}"#
+        );
+
+        let event = LifecycleEvent {
+            timestamp_us: 42,
+            kind: LifecycleEventKind::GuestEvent {
+                kind: GuestEventKind::Panicked,
+                source: GuestEventSource::Pvpanic,
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"timestamp_us":42,"event":"GuestEvent","kind":"Panicked","source":"Pvpanic"}"#
+        );
+    }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let events = LifecycleEvents::default();
+        // Nobody listens.
+        events.emit(LifecycleEventKind::Started);
+
+        let (first, first_peer) = UnixStream::pair().unwrap();
+        let (second, second_peer) = UnixStream::pair().unwrap();
+        events.subscribe(first).unwrap();
+        events.subscribe(second).unwrap();
+
+        events.emit(LifecycleEventKind::Shutdown { exit_code: 0 });
+        let mut reader = BufReader::new(first_peer);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(event["event"], "Shutdown");
+        assert_eq!(event["exit_code"], 0);
+
+        // A closed subscriber is disconnected.
+        drop(second_peer);
+        events.emit(LifecycleEventKind::Resumed);
+        assert_eq!(events.subscribers.lock().unwrap().len(), 1);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.contains(r#""event":"Resumed""#));
+    }
+
+    #[test]
+    fn test_lifecycle_event_callbacks() {
+        let events = LifecycleEvents::default();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        events.add_callback(Box::new(move |event| {
+            sender.send(event.kind.clone()).unwrap();
+        }));
+
+        // The callbacks are called without any subscriber.
+        events.emit(LifecycleEventKind::Paused);
+        events.emit(LifecycleEventKind::TornDown);
+        assert_eq!(receiver.try_recv(), Ok(LifecycleEventKind::Paused));
+        assert_eq!(receiver.try_recv(), Ok(LifecycleEventKind::TornDown));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_events_listener() {
+        let path = utils::tempfile::TempFile::new()
+            .unwrap()
+            .as_path()
+            .to_path_buf();
+        let listener = EventsListener::bind(&path).unwrap();
+        let _client = UnixStream::connect(&path).unwrap();
+        let (stream, _) = listener.listener.accept().unwrap();
+        let events = LifecycleEvents::default();
+        events.subscribe(stream).unwrap();
+        assert_eq!(events.subscribers.lock().unwrap().len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
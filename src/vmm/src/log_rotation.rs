@@ -0,0 +1,110 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use logger::{error, info};
+use polly::event_manager::{EventManager, Subscriber};
+use utils::epoll::{EpollEvent, EventSet};
+use utils::eventfd::EventFd;
+
+use crate::vmm_config::logger::reopen_logger;
+use crate::vmm_config::metrics::reopen_metrics;
+
+// The event fd of the registered `LogRotation`, which the `SIGHUP` handler writes to.
+static SIGHUP_EVENT_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Asks the event loop to reopen the logger and metrics destinations. Only carries out
+/// async-signal-safe operations, so that it can be called from the `SIGHUP` handler.
+pub(crate) fn request_log_rotation() {
+    let fd = SIGHUP_EVENT_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let value: u64 = 1;
+        // Safe because the value outlives the call. A failure means that the counter of the
+        // event fd is already set, so there is nothing to do about it.
+        unsafe {
+            libc::write(
+                fd,
+                &value as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+    }
+}
+
+/// Reopens the named pipes or files the logs and the metrics are written to when Firecracker
+/// receives `SIGHUP`, so that they can be rotated without restarting the microVM.
+pub struct LogRotation {
+    event_fd: EventFd,
+}
+
+impl LogRotation {
+    /// Creates the subscriber, which handles the `SIGHUP` signals from then on.
+    pub fn new() -> io::Result<Self> {
+        let event_fd = EventFd::new(libc::EFD_NONBLOCK)?;
+        SIGHUP_EVENT_FD.store(event_fd.as_raw_fd(), Ordering::SeqCst);
+        Ok(LogRotation { event_fd })
+    }
+
+    fn reopen(&self) {
+        if let Err(e) = reopen_logger() {
+            error!("{}", e);
+        }
+        if let Err(e) = reopen_metrics() {
+            error!("{}", e);
+        }
+        info!("Reopened the log and metrics files after intercepting SIGHUP.");
+    }
+}
+
+impl Drop for LogRotation {
+    fn drop(&mut self) {
+        SIGHUP_EVENT_FD.store(-1, Ordering::SeqCst);
+    }
+}
+
+impl Subscriber for LogRotation {
+    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+        if event.fd() == self.event_fd.as_raw_fd() && event.event_set() == EventSet::IN {
+            if let Err(e) = self.event_fd.read() {
+                error!("Failed to read the log rotation event: {}", e);
+            }
+            self.reopen();
+        } else {
+            error!("Spurious log rotation event!");
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.event_fd.as_raw_fd() as u64,
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_log_rotation() {
+        let log_rotation = LogRotation::new().unwrap();
+        assert_eq!(
+            log_rotation.interest_list()[0].fd(),
+            log_rotation.event_fd.as_raw_fd()
+        );
+
+        request_log_rotation();
+        request_log_rotation();
+        // The signal handler tests may also request a rotation in the meantime.
+        assert!(log_rotation.event_fd.read().unwrap() >= 2);
+
+        // Nothing is requested once the subscriber is gone.
+        drop(log_rotation);
+        assert_eq!(SIGHUP_EVENT_FD.load(Ordering::SeqCst), -1);
+        request_log_rotation();
+    }
+}
@@ -6,11 +6,16 @@
 // Currently only used on x86_64.
 #![cfg(target_arch = "x86_64")]
 
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::SeekFrom;
+use std::hash::Hasher;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
 
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::{
     Bytes, FileOffset, GuestAddress, GuestMemory, GuestMemoryError, GuestMemoryMmap,
@@ -19,8 +24,15 @@ use vm_memory::{
 
 use crate::DirtyBitmap;
 
+// Magic number at the start of the compact diff files.
+const DIFF_MAGIC: u64 = u64::from_le_bytes(*b"FCMEMDIF");
+// Size of the buffers used to read and write the compact diff files.
+const DIFF_BUFFER_SIZE: usize = 1 << 20;
+// Size of the buffer of zeros written in place of the holes the file system cannot punch.
+const ZEROS_BUFFER_SIZE: u64 = 1 << 20;
+
 /// State of a guest memory region saved to file/buffer.
-#[derive(Debug, PartialEq, Versionize)]
+#[derive(Clone, Debug, PartialEq, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct GuestMemoryRegionState {
     /// Base address.
@@ -29,14 +41,153 @@ pub struct GuestMemoryRegionState {
     pub size: usize,
     /// Offset in file/buffer where the region is saved.
     pub offset: u64,
+    /// The blocks of the region plugged by the guest, for the regions added through memory
+    /// hotplug. `None` when all of the region is plugged.
+    #[version(start = 2)]
+    pub plugged_blocks: Option<GuestMemoryPluggedBlocks>,
+}
+
+/// Blocks of a hotplug memory region plugged by the guest.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct GuestMemoryPluggedBlocks {
+    /// Block size.
+    pub block_size: u64,
+    /// One bit per block of the region, set while the block is plugged.
+    pub bitmap: Vec<u64>,
+}
+
+impl GuestMemoryPluggedBlocks {
+    // Tells whether the block holding the byte at `offset` in the region is plugged.
+    fn is_plugged(&self, offset: u64) -> bool {
+        let block = (offset / self.block_size) as usize;
+        self.bitmap
+            .get(block / 64)
+            .map_or(false, |word| word & (1u64 << (block % 64)) != 0)
+    }
+}
+
+/// Range of the memory file holding data.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct GuestMemoryDataRange {
+    /// Offset in file where the range starts.
+    pub offset: u64,
+    /// Range size.
+    pub len: u64,
+}
+
+/// Run of guest memory pages saved in a page store.
+#[derive(Clone, Copy, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct GuestMemoryPageRun {
+    /// Offset where the run starts, laid out as the regions are in a memory file.
+    pub offset: u64,
+    /// Offset in the page store where the pages of the run are saved.
+    pub store_offset: u64,
+    /// Run size.
+    pub len: u64,
 }
 
 /// Guest memory state.
-#[derive(Debug, Default, PartialEq, Versionize)]
+#[derive(Clone, Debug, Default, PartialEq, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct GuestMemoryState {
     /// List of regions.
     pub regions: Vec<GuestMemoryRegionState>,
+    /// The ranges of the memory file holding data, the rest of the file being holes which read
+    /// as zeros. `None` when the layout is unknown, in which case the whole file holds data.
+    #[version(start = 2)]
+    pub data_ranges: Option<Vec<GuestMemoryDataRange>>,
+    /// The runs of pages saved in a page store rather than in a memory file, the other pages
+    /// being zero. `None` when the memory is saved in a memory file.
+    #[version(start = 2, ser_fn = "page_index_serialize")]
+    pub page_index: Option<Vec<GuestMemoryPageRun>>,
+}
+
+impl GuestMemoryState {
+    /// Records the blocks plugged by the guest in the region starting at `base_address`.
+    /// Returns false if there is no such region.
+    pub fn set_plugged_blocks(
+        &mut self,
+        base_address: u64,
+        plugged_blocks: GuestMemoryPluggedBlocks,
+    ) -> bool {
+        match self
+            .regions
+            .iter_mut()
+            .find(|region| region.base_address == base_address)
+        {
+            Some(region) => {
+                region.plugged_blocks = Some(plugged_blocks);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the state of the same memory saved `offset` bytes further in a file, e.g. after
+    /// the header of a single file snapshot.
+    pub fn at_offset(&self, offset: u64) -> GuestMemoryState {
+        let mut state = self.clone();
+        for region in state.regions.iter_mut() {
+            region.offset += offset;
+        }
+        for range in state.data_ranges.iter_mut().flatten() {
+            range.offset += offset;
+        }
+        for run in state.page_index.iter_mut().flatten() {
+            run.offset += offset;
+            run.store_offset += offset;
+        }
+        state
+    }
+
+    // Returns the blocks plugged in the region starting at `base_address`, `None` when all of
+    // its memory is plugged.
+    fn plugged_blocks(&self, base_address: u64) -> Option<&GuestMemoryPluggedBlocks> {
+        self.regions
+            .iter()
+            .find(|region| region.base_address == base_address)
+            .and_then(|region| region.plugged_blocks.as_ref())
+    }
+
+    fn page_index_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.page_index.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement page stores.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Returns the runs of pages to copy from the file the memory is saved in, the other pages
+    // being zero.
+    fn page_runs(&self) -> Vec<GuestMemoryPageRun> {
+        if let Some(page_index) = self.page_index.as_ref() {
+            return page_index.clone();
+        }
+        match self.data_ranges.as_ref() {
+            Some(data_ranges) => data_ranges
+                .iter()
+                .map(|range| GuestMemoryPageRun {
+                    offset: range.offset,
+                    store_offset: range.offset,
+                    len: range.len,
+                })
+                .collect(),
+            None => self
+                .regions
+                .iter()
+                .map(|region| GuestMemoryPageRun {
+                    offset: region.offset,
+                    store_offset: region.offset,
+                    len: region.size as u64,
+                })
+                .collect(),
+        }
+    }
 }
 
 /// Defines the interface for snapshotting memory.
@@ -46,14 +197,47 @@ where
 {
     /// Describes GuestMemoryMmap through a GuestMemoryState struct.
     fn describe(&self) -> GuestMemoryState;
-    /// Dumps all contents of GuestMemoryMmap to a writer.
-    fn dump<T: std::io::Write>(&self, writer: &mut T) -> std::result::Result<(), Error>;
+    /// Dumps all contents of GuestMemoryMmap, described by `state`, to a file, leaving holes
+    /// in place of the zero pages and of the unplugged blocks, and returns the ranges of the
+    /// file holding data.
+    fn dump(
+        &self,
+        file: &File,
+        state: &GuestMemoryState,
+    ) -> std::result::Result<Vec<GuestMemoryDataRange>, Error>;
+    /// Same as `dump`, but lays the regions out from `offset` in the file onwards, e.g. after
+    /// the headers of a core file.
+    fn dump_at(
+        &self,
+        file: &File,
+        state: &GuestMemoryState,
+        offset: u64,
+    ) -> std::result::Result<Vec<GuestMemoryDataRange>, Error>;
     /// Dumps all pages of GuestMemoryMmap present in `dirty_bitmap` to a writer.
     fn dump_dirty<T: std::io::Write + std::io::Seek>(
         &self,
         writer: &mut T,
         dirty_bitmap: &DirtyBitmap,
     ) -> std::result::Result<(), Error>;
+    /// Appends all pages of GuestMemoryMmap present in `dirty_bitmap` to a compact diff file,
+    /// as records made of the offset of the page, laid out as the regions are in a memory file,
+    /// followed by its data.
+    fn dump_dirty_compact(
+        &self,
+        file: &File,
+        dirty_bitmap: &DirtyBitmap,
+    ) -> std::result::Result<(), Error>;
+    /// Dumps all non-zero pages of the plugged blocks of GuestMemoryMmap, described by `state`,
+    /// to a page store, only appending the pages it doesn't hold yet, and returns where the
+    /// pages are saved in the store.
+    fn dump_dedup(
+        &self,
+        store: &File,
+        state: &GuestMemoryState,
+    ) -> std::result::Result<Vec<GuestMemoryPageRun>, Error>;
+    /// Writes the pages of the compact `diff` file to GuestMemoryMmap, described by `state`,
+    /// without marking them dirty.
+    fn apply_diff(&self, diff: &File, state: &GuestMemoryState) -> std::result::Result<(), Error>;
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -61,6 +245,212 @@ where
         state: &GuestMemoryState,
         track_dirty_pages: bool,
     ) -> std::result::Result<Self, Error>;
+    /// Creates a GuestMemoryMmap given a `file` containing the data and a `state`
+    /// containing mapping information, copying the data into regions backed by the
+    /// files returned by `create_backing_file` for their size.
+    fn restore_copy<F>(
+        file: &File,
+        state: &GuestMemoryState,
+        track_dirty_pages: bool,
+        create_backing_file: F,
+    ) -> std::result::Result<Self, Error>
+    where
+        F: Fn(usize) -> std::io::Result<File>;
+}
+
+// Punches a hole between `start` and `end` in `file`, without changing its size. The range is
+// overwritten with zeros instead on the file systems which cannot punch holes.
+fn punch_hole(file: &File, start: u64, end: u64) -> std::io::Result<()> {
+    if start >= end {
+        return Ok(());
+    }
+    // Safe because the file descriptor is valid, and the return value is checked.
+    let ret = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            start as libc::off_t,
+            (end - start) as libc::off_t,
+        )
+    };
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+            return write_zeros(file, start, end);
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+// Overwrites the range between `start` and `end` of `file` with zeros.
+fn write_zeros(file: &File, start: u64, end: u64) -> std::io::Result<()> {
+    let zeros = vec![0u8; std::cmp::min(end - start, ZEROS_BUFFER_SIZE) as usize];
+    let mut offset = start;
+    while offset < end {
+        let len = std::cmp::min(end - offset, zeros.len() as u64) as usize;
+        file.write_all_at(&zeros[..len], offset)?;
+        offset += len as u64;
+    }
+    Ok(())
+}
+
+fn page_hash(page: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(page);
+    hasher.finish()
+}
+
+// Copies the pages of `runs` laid out in `region_state` from `file` into `region`.
+fn copy_page_runs(
+    file: &File,
+    region_state: &GuestMemoryRegionState,
+    region: &GuestRegionMmap,
+    runs: &[GuestMemoryPageRun],
+) -> std::result::Result<(), Error> {
+    let mut reader = file.try_clone().map_err(Error::FileHandle)?;
+    let region_end = region_state.offset + region_state.size as u64;
+    for run in runs.iter() {
+        let start = std::cmp::max(run.offset, region_state.offset);
+        let end = std::cmp::min(run.offset + run.len, region_end);
+        if start >= end {
+            continue;
+        }
+        reader
+            .seek(SeekFrom::Start(run.store_offset + start - run.offset))
+            .map_err(Error::FileHandle)?;
+        region
+            .read_exact_from(
+                MemoryRegionAddress(start - region_state.offset),
+                &mut reader,
+                (end - start) as usize,
+            )
+            .map_err(Error::ReadMemory)?;
+    }
+    Ok(())
+}
+
+// Creates a GuestMemoryMmap out of the regions returned by `create_region` for their size,
+// copying the data of `file` into them.
+fn copy_regions<F>(
+    file: &File,
+    state: &GuestMemoryState,
+    track_dirty_pages: bool,
+    create_region: F,
+) -> std::result::Result<GuestMemoryMmap, Error>
+where
+    F: Fn(usize) -> std::result::Result<MmapRegion, Error>,
+{
+    // The holes of the file are left alone, the new regions being zeroed already.
+    let page_runs = state.page_runs();
+    let mut mmap_regions = Vec::new();
+    for region in state.regions.iter() {
+        let mut guest_region = GuestRegionMmap::new(
+            create_region(region.size)?,
+            GuestAddress(region.base_address),
+        )
+        .map_err(Error::CreateMemory)?;
+        copy_page_runs(file, region, &guest_region, &page_runs)?;
+        // The copied pages are not dirtied by the guest.
+        if track_dirty_pages {
+            guest_region.enable_dirty_page_tracking();
+        }
+
+        mmap_regions.push(guest_region);
+    }
+
+    Ok(GuestMemoryMmap::from_regions(mmap_regions).map_err(Error::CreateMemory)?)
+}
+
+// Checks the header of the compact diff `file`, and returns a reader of its records.
+fn diff_records(file: &File) -> std::result::Result<BufReader<&File>, Error> {
+    let mut reader = BufReader::with_capacity(DIFF_BUFFER_SIZE, file);
+    reader.seek(SeekFrom::Start(0)).map_err(Error::FileHandle)?;
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header).map_err(Error::FileHandle)?;
+    if u64::from_le_bytes(header) != DIFF_MAGIC {
+        return Err(Error::InvalidDiff);
+    }
+    // The pages are restored on a host with the same page size.
+    reader.read_exact(&mut header).map_err(Error::FileHandle)?;
+    if u64::from_le_bytes(header) != sysconf::page::pagesize() as u64 {
+        return Err(Error::InvalidDiff);
+    }
+    Ok(reader)
+}
+
+// Reads the next record of a compact diff into `page`, and returns the offset of the page.
+fn next_diff_record<R: BufRead>(
+    reader: &mut R,
+    page: &mut [u8],
+) -> std::result::Result<Option<u64>, Error> {
+    if reader.fill_buf().map_err(Error::FileHandle)?.is_empty() {
+        return Ok(None);
+    }
+    let mut offset = [0u8; 8];
+    reader.read_exact(&mut offset).map_err(Error::FileHandle)?;
+    reader.read_exact(page).map_err(Error::FileHandle)?;
+    Ok(Some(u64::from_le_bytes(offset)))
+}
+
+// Appends the pages of `memory` present in `dirty_bitmap` to a compact diff, starting with
+// the header of the file if it is `empty`.
+fn write_diff_records(
+    memory: &GuestMemoryMmap,
+    writer: &mut BufWriter<&File>,
+    empty: bool,
+    dirty_bitmap: &DirtyBitmap,
+) -> std::result::Result<(), Error> {
+    let page_size = sysconf::page::pagesize();
+    let mut writer_offset = 0;
+
+    writer.seek(SeekFrom::End(0)).map_err(Error::FileHandle)?;
+    if empty {
+        writer
+            .write_all(&DIFF_MAGIC.to_le_bytes())
+            .and_then(|_| writer.write_all(&(page_size as u64).to_le_bytes()))
+            .map_err(Error::FileHandle)?;
+    }
+
+    memory.with_regions_mut(|slot, region| {
+        let kvm_bitmap = dirty_bitmap.get(&slot).unwrap();
+        let firecracker_bitmap = region.dirty_bitmap().unwrap();
+
+        for (i, v) in kvm_bitmap.iter().enumerate() {
+            for j in 0..64 {
+                let is_kvm_page_dirty = ((v >> j) & 1u64) != 0u64;
+                let page_offset = ((i * 64) + j) * page_size;
+                let is_firecracker_page_dirty = firecracker_bitmap.is_addr_set(page_offset);
+                if is_kvm_page_dirty || is_firecracker_page_dirty {
+                    writer
+                        .write_all(&(writer_offset + page_offset as u64).to_le_bytes())
+                        .map_err(Error::FileHandle)?;
+                    region
+                        .write_all_to(MemoryRegionAddress(page_offset as u64), writer, page_size)
+                        .map_err(Error::WriteMemory)?;
+                }
+            }
+        }
+
+        writer_offset += region.len();
+        firecracker_bitmap.reset();
+
+        Ok(())
+    })?;
+
+    writer.flush().map_err(Error::FileHandle)
+}
+
+/// Folds the compact `diff` file into the memory file `base` of the snapshot it follows, so
+/// that the snapshot the diff was taken for can be loaded from `base` alone.
+pub fn fold_diff(base: &File, diff: &File) -> std::result::Result<(), Error> {
+    let mut reader = diff_records(diff)?;
+    let mut page = vec![0u8; sysconf::page::pagesize()];
+    while let Some(offset) = next_diff_record(&mut reader, &mut page)? {
+        base.write_all_at(&page, offset)
+            .map_err(Error::FileHandle)?;
+    }
+    Ok(())
 }
 
 /// Errors associated with dumping guest memory to file.
@@ -72,6 +462,10 @@ pub enum Error {
     CreateMemory(vm_memory::Error),
     /// Cannot create region.
     CreateRegion(vm_memory::mmap::MmapRegionError),
+    /// Invalid compact diff file.
+    InvalidDiff,
+    /// Cannot load memory.
+    ReadMemory(GuestMemoryError),
     /// Cannot dump memory.
     WriteMemory(GuestMemoryError),
 }
@@ -83,6 +477,11 @@ impl Display for Error {
             FileHandle(err) => write!(f, "Cannot access file: {:?}", err),
             CreateMemory(err) => write!(f, "Cannot create memory: {:?}", err),
             CreateRegion(err) => write!(f, "Cannot create memory region: {:?}", err),
+            InvalidDiff => write!(
+                f,
+                "Invalid memory diff file, or saved with another page size"
+            ),
+            ReadMemory(err) => write!(f, "Cannot load memory: {:?}", err),
             WriteMemory(err) => write!(f, "Cannot dump memory: {:?}", err),
         }
     }
@@ -98,6 +497,7 @@ impl SnapshotMemory for GuestMemoryMmap {
                 base_address: region.start_addr().0,
                 size: region.len() as usize,
                 offset,
+                plugged_blocks: None,
             });
 
             offset += region.len();
@@ -106,12 +506,95 @@ impl SnapshotMemory for GuestMemoryMmap {
         guest_memory_state
     }
 
-    /// Dumps all contents of GuestMemoryMmap to a writer.
-    fn dump<T: std::io::Write>(&self, writer: &mut T) -> std::result::Result<(), Error> {
+    /// Dumps all contents of GuestMemoryMmap, described by `state`, to a file, leaving holes
+    /// in place of the zero pages and of the unplugged blocks, and returns the ranges of the
+    /// file holding data.
+    fn dump(
+        &self,
+        file: &File,
+        state: &GuestMemoryState,
+    ) -> std::result::Result<Vec<GuestMemoryDataRange>, Error> {
+        self.dump_at(file, state, 0)
+    }
+
+    /// Same as `dump`, but lays the regions out from `offset` in the file onwards.
+    fn dump_at(
+        &self,
+        file: &File,
+        state: &GuestMemoryState,
+        offset: u64,
+    ) -> std::result::Result<Vec<GuestMemoryDataRange>, Error> {
+        let page_size = sysconf::page::pagesize();
+        let file_len = file.metadata().map_err(Error::FileHandle)?.len();
+        let mut writer = file;
+        let mut writer_offset = offset;
+        let mut data_ranges: Vec<GuestMemoryDataRange> = Vec::new();
+        let mut page = vec![0u8; page_size];
+
         self.with_regions_mut(|_, region| {
-            region.write_all_to(MemoryRegionAddress(0), writer, region.len() as usize)
-        })
-        .map_err(Error::WriteMemory)
+            let region_len = region.len() as usize;
+            let plugged_blocks = state.plugged_blocks(region.start_addr().0);
+            let first_range = data_ranges.len();
+            let mut page_offset = 0;
+            while page_offset < region_len {
+                let len = std::cmp::min(page_size, region_len - page_offset);
+                // The unplugged blocks are not read, since the guest gave their memory back.
+                if let Some(plugged_blocks) = plugged_blocks {
+                    if !plugged_blocks.is_plugged(page_offset as u64) {
+                        page_offset += len;
+                        continue;
+                    }
+                }
+                region
+                    .read_slice(&mut page[..len], MemoryRegionAddress(page_offset as u64))
+                    .map_err(Error::ReadMemory)?;
+                let offset = writer_offset + page_offset as u64;
+                if page[..len].iter().any(|byte| *byte != 0) {
+                    // Contiguous data pages are written at once.
+                    match data_ranges[first_range..].last_mut() {
+                        Some(range) if range.offset + range.len == offset => {
+                            range.len += len as u64
+                        }
+                        _ => data_ranges.push(GuestMemoryDataRange {
+                            offset,
+                            len: len as u64,
+                        }),
+                    }
+                }
+                page_offset += len;
+            }
+
+            // The file may hold the data of an earlier snapshot in place of the zero pages.
+            let region_end = writer_offset + region.len();
+            let mut hole_start = writer_offset;
+            for range in data_ranges[first_range..].iter() {
+                punch_hole(file, hole_start, std::cmp::min(range.offset, file_len))
+                    .map_err(Error::FileHandle)?;
+                hole_start = range.offset + range.len;
+                writer
+                    .seek(SeekFrom::Start(range.offset))
+                    .map_err(Error::FileHandle)?;
+                region
+                    .write_all_to(
+                        MemoryRegionAddress(range.offset - writer_offset),
+                        &mut writer,
+                        range.len as usize,
+                    )
+                    .map_err(Error::WriteMemory)?;
+            }
+            punch_hole(file, hole_start, std::cmp::min(region_end, file_len))
+                .map_err(Error::FileHandle)?;
+
+            writer_offset = region_end;
+            Ok(())
+        })?;
+
+        // The trailing zero pages are not written, so the file is extended to hold them.
+        if file_len < writer_offset {
+            file.set_len(writer_offset).map_err(Error::FileHandle)?;
+        }
+
+        Ok(data_ranges)
     }
 
     /// Dumps all pages of GuestMemoryMmap present in `dirty_bitmap` to a writer.
@@ -168,6 +651,153 @@ impl SnapshotMemory for GuestMemoryMmap {
         .map_err(Error::WriteMemory)
     }
 
+    /// Appends all pages of GuestMemoryMmap present in `dirty_bitmap` to a compact diff file,
+    /// as records made of the offset of the page, laid out as the regions are in a memory file,
+    /// followed by its data.
+    fn dump_dirty_compact(
+        &self,
+        file: &File,
+        dirty_bitmap: &DirtyBitmap,
+    ) -> std::result::Result<(), Error> {
+        let file_len = file.metadata().map_err(Error::FileHandle)?.len();
+        // Each diff is appended to the earlier ones.
+        if file_len > 0 {
+            diff_records(file)?;
+        }
+        let mut writer = BufWriter::with_capacity(DIFF_BUFFER_SIZE, file);
+        let result = write_diff_records(self, &mut writer, file_len == 0, dirty_bitmap);
+
+        // A diff which is not saved completely is dropped, so that the file only holds the
+        // earlier ones.
+        if result.is_err() {
+            drop(writer);
+            let _ = file.set_len(file_len);
+        }
+        result
+    }
+
+    /// Dumps all non-zero pages of the plugged blocks of GuestMemoryMmap, described by `state`,
+    /// to a page store, only appending the pages it doesn't hold yet, and returns where the
+    /// pages are saved in the store.
+    fn dump_dedup(
+        &self,
+        store: &File,
+        state: &GuestMemoryState,
+    ) -> std::result::Result<Vec<GuestMemoryPageRun>, Error> {
+        let page_size = sysconf::page::pagesize();
+        let mut page = vec![0u8; page_size];
+        let mut stored_page = vec![0u8; page_size];
+
+        // Index the pages the store already holds by their hash. A partial page at the end of
+        // the store is overwritten.
+        let store_len = store.metadata().map_err(Error::FileHandle)?.len();
+        let mut store_end = store_len - store_len % page_size as u64;
+        let mut store_index: HashMap<u64, u64> = HashMap::new();
+        let mut store_offset = 0;
+        while store_offset < store_end {
+            store
+                .read_exact_at(&mut page, store_offset)
+                .map_err(Error::FileHandle)?;
+            store_index.entry(page_hash(&page)).or_insert(store_offset);
+            store_offset += page_size as u64;
+        }
+
+        let mut page_index: Vec<GuestMemoryPageRun> = Vec::new();
+        let mut offset = 0;
+        self.with_regions_mut(|_, region| {
+            let plugged_blocks = state.plugged_blocks(region.start_addr().0);
+            let first_run = page_index.len();
+            for page_offset in (0..region.len()).step_by(page_size) {
+                if let Some(plugged_blocks) = plugged_blocks {
+                    if !plugged_blocks.is_plugged(page_offset) {
+                        continue;
+                    }
+                }
+                region
+                    .read_slice(&mut page, MemoryRegionAddress(page_offset))
+                    .map_err(Error::ReadMemory)?;
+                if page.iter().all(|byte| *byte == 0) {
+                    continue;
+                }
+
+                // The pages with the same hash are compared, and a collision leaves the page
+                // out of the index.
+                let hash = page_hash(&page);
+                let mut page_store_offset = None;
+                if let Some(candidate) = store_index.get(&hash) {
+                    store
+                        .read_exact_at(&mut stored_page, *candidate)
+                        .map_err(Error::FileHandle)?;
+                    if stored_page == page {
+                        page_store_offset = Some(*candidate);
+                    }
+                }
+                let page_store_offset = match page_store_offset {
+                    Some(page_store_offset) => page_store_offset,
+                    None => {
+                        store
+                            .write_all_at(&page, store_end)
+                            .map_err(Error::FileHandle)?;
+                        store_index.entry(hash).or_insert(store_end);
+                        store_end += page_size as u64;
+                        store_end - page_size as u64
+                    }
+                };
+
+                // The pages contiguous both in the region and in the store form a single run.
+                let page_offset = offset + page_offset;
+                match page_index[first_run..].last_mut() {
+                    Some(run)
+                        if run.offset + run.len == page_offset
+                            && run.store_offset + run.len == page_store_offset =>
+                    {
+                        run.len += page_size as u64
+                    }
+                    _ => page_index.push(GuestMemoryPageRun {
+                        offset: page_offset,
+                        store_offset: page_store_offset,
+                        len: page_size as u64,
+                    }),
+                }
+            }
+
+            offset += region.len();
+            Ok(())
+        })?;
+
+        Ok(page_index)
+    }
+
+    /// Writes the pages of the compact `diff` file to GuestMemoryMmap, described by `state`,
+    /// without marking them dirty.
+    fn apply_diff(&self, diff: &File, state: &GuestMemoryState) -> std::result::Result<(), Error> {
+        let mut reader = diff_records(diff)?;
+        let mut page = vec![0u8; sysconf::page::pagesize()];
+        while let Some(offset) = next_diff_record(&mut reader, &mut page)? {
+            let region = state
+                .regions
+                .iter()
+                .find(|region| {
+                    region.offset <= offset
+                        && offset + page.len() as u64 <= region.offset + region.size as u64
+                })
+                .ok_or(Error::InvalidDiff)?;
+            self.write_slice(
+                &page,
+                GuestAddress(region.base_address + offset - region.offset),
+            )
+            .map_err(Error::WriteMemory)?;
+        }
+
+        // The pages of the diffs are part of the snapshot, not dirtied by the guest.
+        self.with_regions(|_, region| {
+            if let Some(bitmap) = region.dirty_bitmap() {
+                bitmap.reset();
+            }
+            Ok::<(), Error>(())
+        })
+    }
+
     /// Creates a GuestMemoryMmap given a `file` containing the data
     /// and a `state` containing mapping information.
     fn restore(
@@ -175,6 +805,13 @@ impl SnapshotMemory for GuestMemoryMmap {
         state: &GuestMemoryState,
         track_dirty_pages: bool,
     ) -> std::result::Result<Self, Error> {
+        // The pages of a page store are scattered, so they are copied rather than mapped.
+        if state.page_index.is_some() {
+            return copy_regions(file, state, track_dirty_pages, |size| {
+                MmapRegion::new(size).map_err(Error::CreateRegion)
+            });
+        }
+
         let mut mmap_regions = Vec::new();
         for region in state.regions.iter() {
             let mmap_region = MmapRegion::build(
@@ -201,6 +838,32 @@ impl SnapshotMemory for GuestMemoryMmap {
 
         Ok(Self::from_regions(mmap_regions).map_err(Error::CreateMemory)?)
     }
+
+    /// Creates a GuestMemoryMmap given a `file` containing the data and a `state`
+    /// containing mapping information, copying the data into regions backed by the
+    /// files returned by `create_backing_file` for their size.
+    fn restore_copy<F>(
+        file: &File,
+        state: &GuestMemoryState,
+        track_dirty_pages: bool,
+        create_backing_file: F,
+    ) -> std::result::Result<Self, Error>
+    where
+        F: Fn(usize) -> std::io::Result<File>,
+    {
+        copy_regions(file, state, track_dirty_pages, |size| {
+            let backing_file = create_backing_file(size).map_err(Error::FileHandle)?;
+            // The pages are reserved when mapped, so that a lack of huge pages is
+            // reported here rather than when the guest touches them.
+            MmapRegion::build(
+                Some(FileOffset::new(backing_file, 0)),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+            )
+            .map_err(Error::CreateRegion)
+        })
+    }
 }
 
 #[cfg(test)]
@@ -208,7 +871,8 @@ mod tests {
     use std::collections::HashMap;
 
     use super::*;
-    use std::io::{Read, Seek};
+    use crate::version_map::VERSION_MAP;
+    use std::io::{Read, Seek, Write};
     use utils::tempfile::TempFile;
     use vm_memory::GuestAddress;
 
@@ -229,13 +893,17 @@ mod tests {
                     base_address: 0,
                     size: page_size,
                     offset: 0,
+                    plugged_blocks: None,
                 },
                 GuestMemoryRegionState {
                     base_address: page_size as u64 * 2,
                     size: page_size,
                     offset: page_size as u64,
+                    plugged_blocks: None,
                 },
             ],
+            data_ranges: None,
+            page_index: None,
         };
 
         let actual_memory_state = guest_memory.describe();
@@ -254,13 +922,17 @@ mod tests {
                     base_address: 0,
                     size: page_size * 3,
                     offset: 0,
+                    plugged_blocks: None,
                 },
                 GuestMemoryRegionState {
                     base_address: page_size as u64 * 4,
                     size: page_size * 3,
                     offset: page_size as u64 * 3,
+                    plugged_blocks: None,
                 },
             ],
+            data_ranges: None,
+            page_index: None,
         };
 
         let actual_memory_state = guest_memory.describe();
@@ -295,12 +967,16 @@ mod tests {
             .write(&second_region[..], GuestAddress(page_size as u64 * 3))
             .unwrap();
 
-        let memory_state = guest_memory.describe();
+        let mut memory_state = guest_memory.describe();
 
         // Case 1: dump the full memory.
         {
             let memory_file = TempFile::new().unwrap();
-            guest_memory.dump(&mut memory_file.as_file()).unwrap();
+            memory_state.data_ranges = Some(
+                guest_memory
+                    .dump(memory_file.as_file(), &memory_state)
+                    .unwrap(),
+            );
 
             let restored_guest_memory =
                 GuestMemoryMmap::restore(&memory_file.as_file(), &memory_state, false).unwrap();
@@ -319,10 +995,44 @@ mod tests {
                 )
                 .unwrap();
             assert_eq!(second_region, actual_region);
+
+            // Copy the memory into regions backed by other files.
+            let restored_guest_memory = GuestMemoryMmap::restore_copy(
+                &memory_file.as_file(),
+                &memory_state,
+                true,
+                |size| {
+                    let file = TempFile::new().unwrap().into_file();
+                    file.set_len(size as u64)?;
+                    Ok(file)
+                },
+            )
+            .unwrap();
+            restored_guest_memory
+                .read(&mut actual_region.as_mut_slice(), GuestAddress(0))
+                .unwrap();
+            assert_eq!(first_region, actual_region);
+            restored_guest_memory
+                .read(
+                    &mut actual_region.as_mut_slice(),
+                    GuestAddress(page_size as u64 * 3),
+                )
+                .unwrap();
+            assert_eq!(second_region, actual_region);
+            // The copied pages are clean.
+            let _res: std::result::Result<(), Error> =
+                restored_guest_memory.with_regions(|_, r| {
+                    assert!(r.file_offset().is_some());
+                    assert!(!r.dirty_bitmap().unwrap().is_bit_set(0));
+                    assert!(!r.dirty_bitmap().unwrap().is_bit_set(1));
+                    Ok(())
+                });
         }
 
         // Case 2: dump only the dirty pages.
         {
+            memory_state.data_ranges = None;
+
             // KVM Bitmap
             // First region pages: [dirty, clean]
             // Second region pages: [clean, dirty]
@@ -384,4 +1094,487 @@ mod tests {
             assert_eq!(expected_first_region, diff_file_content);
         }
     }
+
+    #[test]
+    fn test_compact_diffs() {
+        let page_size: usize = sysconf::page::pagesize();
+
+        // Two regions of two pages each, with a one page gap between them.
+        let mem_regions = [
+            (GuestAddress(0), page_size * 2),
+            (GuestAddress(page_size as u64 * 3), page_size * 2),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges_with_tracking(&mem_regions[..]).unwrap();
+        let pages: Vec<Vec<u8>> = (1..=4).map(|byte| vec![byte; page_size]).collect();
+        guest_memory
+            .write(&[&pages[0][..], &pages[0][..]].concat(), GuestAddress(0))
+            .unwrap();
+        guest_memory
+            .write(
+                &[&pages[1][..], &pages[1][..]].concat(),
+                GuestAddress(page_size as u64 * 3),
+            )
+            .unwrap();
+        let memory_state = guest_memory.describe();
+        let base = TempFile::new().unwrap();
+        guest_memory.dump(base.as_file(), &memory_state).unwrap();
+        let _res: std::result::Result<(), Error> = guest_memory.with_regions(|_, r| {
+            r.dirty_bitmap().unwrap().reset();
+            Ok(())
+        });
+
+        // First diff: the second page of the first region.
+        guest_memory
+            .write(&pages[2][..], GuestAddress(page_size as u64))
+            .unwrap();
+        let mut dirty_bitmap: DirtyBitmap = HashMap::new();
+        dirty_bitmap.insert(0, vec![0; 1]);
+        dirty_bitmap.insert(1, vec![0; 1]);
+        let diff = TempFile::new().unwrap();
+        guest_memory
+            .dump_dirty_compact(diff.as_file(), &dirty_bitmap)
+            .unwrap();
+        assert_eq!(
+            diff.as_file().metadata().unwrap().len(),
+            16 + 8 + page_size as u64
+        );
+
+        // Second diff, appended to the first one: the pages of the second region.
+        guest_memory
+            .write(&pages[3][..], GuestAddress(page_size as u64 * 3))
+            .unwrap();
+        dirty_bitmap.insert(1, vec![0b10; 1]);
+        guest_memory
+            .dump_dirty_compact(diff.as_file(), &dirty_bitmap)
+            .unwrap();
+        assert_eq!(
+            diff.as_file().metadata().unwrap().len(),
+            16 + (8 + page_size as u64) * 3
+        );
+
+        let expected_content =
+            [&pages[0][..], &pages[2][..], &pages[3][..], &pages[1][..]].concat();
+        let restored_guest_memory =
+            GuestMemoryMmap::restore(&base.as_file(), &memory_state, true).unwrap();
+        restored_guest_memory
+            .apply_diff(diff.as_file(), &memory_state)
+            .unwrap();
+        let mut actual_region = vec![0u8; page_size * 2];
+        restored_guest_memory
+            .read(&mut actual_region.as_mut_slice(), GuestAddress(0))
+            .unwrap();
+        assert_eq!(actual_region, &expected_content[..page_size * 2]);
+        restored_guest_memory
+            .read(
+                &mut actual_region.as_mut_slice(),
+                GuestAddress(page_size as u64 * 3),
+            )
+            .unwrap();
+        assert_eq!(actual_region, &expected_content[page_size * 2..]);
+        // The pages of the diffs are clean.
+        let _res: std::result::Result<(), Error> = restored_guest_memory.with_regions(|_, r| {
+            assert!(!r.dirty_bitmap().unwrap().is_bit_set(0));
+            assert!(!r.dirty_bitmap().unwrap().is_bit_set(1));
+            Ok(())
+        });
+
+        // Folding the diffs into the base brings it up to date.
+        fold_diff(base.as_file(), diff.as_file()).unwrap();
+        let mut content = Vec::new();
+        let mut reader = base.as_file();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut content).unwrap();
+        assert_eq!(content, expected_content);
+
+        // The memory files are not compact diffs.
+        assert!(matches!(
+            restored_guest_memory.apply_diff(base.as_file(), &memory_state),
+            Err(Error::InvalidDiff)
+        ));
+        assert!(matches!(
+            guest_memory.dump_dirty_compact(base.as_file(), &dirty_bitmap),
+            Err(Error::InvalidDiff)
+        ));
+        assert_eq!(
+            base.as_file().metadata().unwrap().len(),
+            page_size as u64 * 4
+        );
+    }
+
+    #[test]
+    fn test_dump_zero_pages() {
+        let page_size: usize = sysconf::page::pagesize();
+
+        // Two regions of three pages each, with a one page gap between them.
+        let mem_regions = [
+            (GuestAddress(0), page_size * 3),
+            (GuestAddress(page_size as u64 * 4), page_size * 3),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&mem_regions[..]).unwrap();
+        // First region pages: [data, zero, data]
+        // Second region pages: [data, data, zero]
+        let ones = vec![1u8; page_size];
+        for page in [0u64, 2, 4, 5].iter() {
+            guest_memory
+                .write(&ones[..], GuestAddress(page_size as u64 * *page))
+                .unwrap();
+        }
+
+        // The file holds the data of an earlier dump, which is replaced.
+        let memory_file = TempFile::new().unwrap();
+        let mut file = memory_file.as_file();
+        file.write_all(&vec![2u8; page_size * 6]).unwrap();
+
+        let data_ranges = guest_memory.dump(file, &guest_memory.describe()).unwrap();
+        // The data pages contiguous in the same region form a single range.
+        let expected_ranges = vec![
+            GuestMemoryDataRange {
+                offset: 0,
+                len: page_size as u64,
+            },
+            GuestMemoryDataRange {
+                offset: page_size as u64 * 2,
+                len: page_size as u64,
+            },
+            GuestMemoryDataRange {
+                offset: page_size as u64 * 3,
+                len: page_size as u64 * 2,
+            },
+        ];
+        assert_eq!(data_ranges, expected_ranges);
+
+        let zeros = vec![0u8; page_size];
+        let expected_content = [
+            ones.as_slice(),
+            zeros.as_slice(),
+            ones.as_slice(),
+            ones.as_slice(),
+            ones.as_slice(),
+            zeros.as_slice(),
+        ]
+        .concat();
+        let mut content = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(content, expected_content);
+
+        // The trailing zero page is part of the file, even when it is not written.
+        let memory_file = TempFile::new().unwrap();
+        guest_memory
+            .dump(memory_file.as_file(), &guest_memory.describe())
+            .unwrap();
+        assert_eq!(
+            memory_file.as_file().metadata().unwrap().len(),
+            page_size as u64 * 6
+        );
+
+        // Only the data ranges are copied on restore.
+        let mut memory_state = guest_memory.describe();
+        memory_state.data_ranges = Some(data_ranges);
+        let restored_guest_memory =
+            GuestMemoryMmap::restore_copy(&memory_file.as_file(), &memory_state, false, |size| {
+                let file = TempFile::new().unwrap().into_file();
+                file.set_len(size as u64)?;
+                Ok(file)
+            })
+            .unwrap();
+        let mut actual_region = vec![0u8; page_size * 3];
+        restored_guest_memory
+            .read(&mut actual_region.as_mut_slice(), GuestAddress(0))
+            .unwrap();
+        assert_eq!(actual_region, &expected_content[..page_size * 3]);
+        restored_guest_memory
+            .read(
+                &mut actual_region.as_mut_slice(),
+                GuestAddress(page_size as u64 * 4),
+            )
+            .unwrap();
+        assert_eq!(actual_region, &expected_content[page_size * 3..]);
+    }
+
+    #[test]
+    fn test_write_zeros() {
+        let memory_file = TempFile::new().unwrap();
+        let mut file = memory_file.as_file();
+        let len = ZEROS_BUFFER_SIZE * 2 + 10;
+        file.write_all_at(&vec![1u8; len as usize], 0).unwrap();
+
+        // The range spans several buffers of zeros, and the data around it is kept.
+        write_zeros(file, 5, len - 5).unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(content.len() as u64, len);
+        assert_eq!(&content[..5], &[1u8; 5]);
+        assert!(content[5..len as usize - 5].iter().all(|byte| *byte == 0));
+        assert_eq!(&content[len as usize - 5..], &[1u8; 5]);
+
+        // An empty range writes nothing.
+        write_zeros(file, len, len).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_restore_at_offset() {
+        let page_size: usize = sysconf::page::pagesize();
+
+        // Two regions of two pages each, with a one page gap between them.
+        let mem_regions = [
+            (GuestAddress(0), page_size * 2),
+            (GuestAddress(page_size as u64 * 3), page_size * 2),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&mem_regions[..]).unwrap();
+        let ones = vec![1u8; page_size];
+        for page in [1u64, 3].iter() {
+            guest_memory
+                .write(&ones[..], GuestAddress(page_size as u64 * *page))
+                .unwrap();
+        }
+
+        // The memory is saved after a one page header.
+        let offset = page_size as u64;
+        let memory_file = TempFile::new().unwrap();
+        let mut memory_state = guest_memory.describe();
+        let data_ranges = guest_memory
+            .dump_at(memory_file.as_file(), &memory_state, offset)
+            .unwrap();
+        assert_eq!(
+            data_ranges,
+            vec![
+                GuestMemoryDataRange {
+                    offset: offset + page_size as u64,
+                    len: page_size as u64,
+                },
+                GuestMemoryDataRange {
+                    offset: offset + page_size as u64 * 2,
+                    len: page_size as u64,
+                },
+            ]
+        );
+        memory_state.data_ranges = Some(
+            data_ranges
+                .iter()
+                .map(|range| GuestMemoryDataRange {
+                    offset: range.offset - offset,
+                    len: range.len,
+                })
+                .collect(),
+        );
+
+        let file_state = memory_state.at_offset(offset);
+        assert_eq!(file_state.regions[1].offset, offset + page_size as u64 * 2);
+        assert_eq!(file_state.data_ranges.as_ref().unwrap(), &data_ranges);
+        assert_eq!(file_state.at_offset(0), file_state);
+
+        let mut expected_content = vec![0u8; page_size * 2];
+        expected_content[page_size..].copy_from_slice(&ones);
+        for restored_guest_memory in [
+            GuestMemoryMmap::restore(memory_file.as_file(), &file_state, false).unwrap(),
+            GuestMemoryMmap::restore_copy(memory_file.as_file(), &file_state, false, |size| {
+                let file = TempFile::new().unwrap().into_file();
+                file.set_len(size as u64)?;
+                Ok(file)
+            })
+            .unwrap(),
+        ]
+        .iter()
+        {
+            let mut actual_region = vec![0u8; page_size * 2];
+            restored_guest_memory
+                .read(&mut actual_region.as_mut_slice(), GuestAddress(0))
+                .unwrap();
+            assert_eq!(actual_region, expected_content);
+            restored_guest_memory
+                .read(
+                    &mut actual_region.as_mut_slice(),
+                    GuestAddress(page_size as u64 * 3),
+                )
+                .unwrap();
+            assert_eq!(&actual_region[..page_size], ones.as_slice());
+            assert_eq!(&actual_region[page_size..], &vec![0u8; page_size][..]);
+        }
+    }
+
+    #[test]
+    fn test_dump_dedup() {
+        let page_size: usize = sysconf::page::pagesize();
+
+        // Two regions of three pages each, with a one page gap between them.
+        let mem_regions = [
+            (GuestAddress(0), page_size * 3),
+            (GuestAddress(page_size as u64 * 4), page_size * 3),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&mem_regions[..]).unwrap();
+        // First region pages: [ones, twos, zero]
+        // Second region pages: [ones, threes, threes]
+        let pages = [1u8, 2, 0, 1, 3, 3];
+        let mut expected_content = Vec::new();
+        for (i, byte) in pages.iter().enumerate() {
+            let page = vec![*byte; page_size];
+            let address = if i < 3 { i } else { i + 1 };
+            guest_memory
+                .write(&page[..], GuestAddress((page_size * address) as u64))
+                .unwrap();
+            expected_content.extend_from_slice(&page);
+        }
+
+        let store_file = TempFile::new().unwrap();
+        let store = store_file.as_file();
+        let mut memory_state = guest_memory.describe();
+        let page_index = guest_memory.dump_dedup(store, &memory_state).unwrap();
+        let page_size = page_size as u64;
+        let expected_index = vec![
+            GuestMemoryPageRun {
+                offset: 0,
+                store_offset: 0,
+                len: page_size * 2,
+            },
+            GuestMemoryPageRun {
+                offset: page_size * 3,
+                store_offset: 0,
+                len: page_size,
+            },
+            GuestMemoryPageRun {
+                offset: page_size * 4,
+                store_offset: page_size * 2,
+                len: page_size,
+            },
+            GuestMemoryPageRun {
+                offset: page_size * 5,
+                store_offset: page_size * 2,
+                len: page_size,
+            },
+        ];
+        assert_eq!(page_index, expected_index);
+        // Each distinct page is only stored once.
+        assert_eq!(store.metadata().unwrap().len(), page_size * 3);
+
+        // The pages are already in the store, so that dumping them again doesn't grow it.
+        assert_eq!(
+            guest_memory.dump_dedup(store, &memory_state).unwrap(),
+            expected_index
+        );
+        assert_eq!(store.metadata().unwrap().len(), page_size * 3);
+
+        memory_state.page_index = Some(page_index);
+        let restored_guest_memory = GuestMemoryMmap::restore(store, &memory_state, true).unwrap();
+        let copied_guest_memory =
+            GuestMemoryMmap::restore_copy(store, &memory_state, true, |size| {
+                let file = TempFile::new().unwrap().into_file();
+                file.set_len(size as u64)?;
+                Ok(file)
+            })
+            .unwrap();
+        for guest_memory in [restored_guest_memory, copied_guest_memory].iter() {
+            let mut actual_region = vec![0u8; page_size as usize * 3];
+            guest_memory
+                .read(&mut actual_region.as_mut_slice(), GuestAddress(0))
+                .unwrap();
+            assert_eq!(actual_region, &expected_content[..page_size as usize * 3]);
+            guest_memory
+                .read(
+                    &mut actual_region.as_mut_slice(),
+                    GuestAddress(page_size * 4),
+                )
+                .unwrap();
+            assert_eq!(actual_region, &expected_content[page_size as usize * 3..]);
+            // The copied pages are clean.
+            let _res: std::result::Result<(), Error> = guest_memory.with_regions(|_, r| {
+                assert!(!r.dirty_bitmap().unwrap().is_bit_set(0));
+                Ok(())
+            });
+        }
+
+        // The page stores cannot be described to earlier versions.
+        let mut buf = Vec::new();
+        assert!(memory_state.serialize(&mut buf, &VERSION_MAP, 1).is_err());
+    }
+
+    #[test]
+    fn test_dump_plugged_blocks() {
+        let page_size: usize = sysconf::page::pagesize();
+
+        // A boot region of one page, and a hotplug region of four blocks of two pages each.
+        let block_size = page_size as u64 * 2;
+        let hotplug_addr = page_size as u64 * 4;
+        let mem_regions = [
+            (GuestAddress(0), page_size),
+            (GuestAddress(hotplug_addr), block_size as usize * 4),
+        ];
+        let guest_memory = GuestMemoryMmap::from_ranges(&mem_regions[..]).unwrap();
+        let ones = vec![1u8; page_size];
+        guest_memory.write(&ones[..], GuestAddress(0)).unwrap();
+        // Each block holds data, though only the first and third ones are plugged.
+        for block in 0..4 {
+            guest_memory
+                .write(&ones[..], GuestAddress(hotplug_addr + block_size * block))
+                .unwrap();
+        }
+
+        let mut memory_state = guest_memory.describe();
+        let plugged_blocks = GuestMemoryPluggedBlocks {
+            block_size,
+            bitmap: vec![0b101],
+        };
+        assert!(!memory_state.set_plugged_blocks(page_size as u64, plugged_blocks.clone()));
+        assert!(memory_state.set_plugged_blocks(hotplug_addr, plugged_blocks.clone()));
+        assert_eq!(memory_state.regions[0].plugged_blocks, None);
+        assert_eq!(memory_state.regions[1].plugged_blocks, Some(plugged_blocks));
+
+        // The unplugged blocks are left out of the memory file and of the page store.
+        let memory_file = TempFile::new().unwrap();
+        let data_ranges = guest_memory
+            .dump(memory_file.as_file(), &memory_state)
+            .unwrap();
+        let page_size = page_size as u64;
+        let expected_ranges = vec![
+            GuestMemoryDataRange {
+                offset: 0,
+                len: page_size,
+            },
+            GuestMemoryDataRange {
+                offset: page_size,
+                len: page_size,
+            },
+            GuestMemoryDataRange {
+                offset: page_size + block_size * 2,
+                len: page_size,
+            },
+        ];
+        assert_eq!(data_ranges, expected_ranges);
+        assert_eq!(
+            memory_file.as_file().metadata().unwrap().len(),
+            page_size + block_size * 4
+        );
+
+        let store_file = TempFile::new().unwrap();
+        let page_index = guest_memory
+            .dump_dedup(store_file.as_file(), &memory_state)
+            .unwrap();
+        let offsets: Vec<u64> = page_index.iter().map(|run| run.offset).collect();
+        assert_eq!(offsets, vec![0, page_size, page_size + block_size * 2]);
+        assert_eq!(store_file.as_file().metadata().unwrap().len(), page_size);
+
+        // The hotplug region is restored with its plugged blocks only.
+        memory_state.data_ranges = Some(data_ranges);
+        let restored_guest_memory =
+            GuestMemoryMmap::restore(memory_file.as_file(), &memory_state, false).unwrap();
+        let mut actual_page = vec![0u8; page_size as usize];
+        for block in 0..4 {
+            restored_guest_memory
+                .read(
+                    &mut actual_page.as_mut_slice(),
+                    GuestAddress(hotplug_addr + block_size * block),
+                )
+                .unwrap();
+            assert_eq!(actual_page.iter().all(|byte| *byte == 1), block % 2 == 0);
+        }
+
+        // The plugged blocks are saved along with the region from version 2 on.
+        let mut buf = Vec::new();
+        memory_state.serialize(&mut buf, &VERSION_MAP, 2).unwrap();
+        assert_eq!(
+            GuestMemoryState::deserialize(&mut buf.as_slice(), &VERSION_MAP, 2).unwrap(),
+            memory_state
+        );
+    }
 }
@@ -8,29 +8,52 @@
 
 use std::fmt::{Display, Formatter};
 use std::fs::{File, OpenOptions};
-use std::io;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 use crate::builder::{self, StartMicrovmError};
+use crate::device_manager::legacy::{Error as LegacyDeviceError, SerialPortState};
 use crate::device_manager::persist::Error as DevicePersistError;
 use crate::mem_size_mib;
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
+#[cfg(feature = "vsock")]
+use crate::resources::MIN_GUEST_CID;
+use crate::vmm_config::machine_config::{MemoryBackend, MmioLayoutConfig, MAX_IO_THREADS};
+use crate::vmm_config::snapshot::{
+    CloneConfig, CreateSnapshotParams, LoadSnapshotParams, SnapshotType,
+};
+#[cfg(feature = "tpm")]
+use crate::vmm_config::tpm::TpmConfig;
 use crate::vstate::{self, vcpu::VcpuState, vm::VmState};
 
 use crate::device_manager::mmio::MMIODeviceManager;
 use crate::device_manager::persist::DeviceStates;
+use crate::lifecycle::{LifecycleEventKind, LIFECYCLE_EVENTS};
 use crate::memory_snapshot;
-use crate::memory_snapshot::{GuestMemoryState, SnapshotMemory};
+use crate::memory_snapshot::{GuestMemoryDataRange, GuestMemoryState, SnapshotMemory};
 use crate::version_map::FC_VERSION_TO_SNAP_VERSION;
+#[cfg(feature = "balloon")]
+use crate::vmm_config::balloon::BalloonConfigError;
+use crate::vmm_config::watchdog::WatchdogAction;
 use crate::{Error as VmmError, Vmm};
 use arch::IRQ_BASE;
 use cpuid::common::{get_vendor_id_from_cpuid, get_vendor_id_from_host};
-use logger::{error, info};
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::{AcpiPmState, CpuHotplugState};
+use devices::legacy::{WatchdogError, WatchdogState};
+#[cfg(feature = "tpm")]
+use devices::tpm::TpmCrbState;
+use logger::{error, in_span, in_timed_span, info, METRICS};
+use mmds::data_store::{Error as MmdsError, Mmds};
+use mmds::MMDS;
 use polly::event_manager::EventManager;
+use polly::worker::Error as WorkerError;
 use seccomp::BpfProgramRef;
+use serde_json::{Map, Value};
 use snapshot::Snapshot;
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::GuestMemoryMmap;
 
@@ -38,6 +61,19 @@ const FC_V0_23_SNAP_VERSION: u16 = 1;
 const FC_V0_23_IRQ_NUMBER: u32 = 16;
 const FC_V0_23_MAX_DEVICES: u32 = FC_V0_23_IRQ_NUMBER - IRQ_BASE;
 
+// The MMDS key holding the random bytes each clone gets, in hexadecimal.
+const CLONE_ENTROPY_KEY: &str = "clone_entropy";
+const CLONE_ENTROPY_LEN: usize = 32;
+
+// The header of a single file snapshot starts with the magic, followed by the version of the
+// format, then by the offset and size of the guest memory and of the microVM state, in little
+// endian.
+const SINGLE_FILE_MAGIC: [u8; 8] = *b"FCSNAPSF";
+const SINGLE_FILE_VERSION: u32 = 1;
+const SINGLE_FILE_HEADER_LEN: usize = 48;
+// The guest memory starts on the page after the header, so that it can be mapped.
+const SINGLE_FILE_MEM_OFFSET: u64 = 4096;
+
 /// Holds information related to the VM that is not part of VmState.
 #[derive(Debug, PartialEq, Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
@@ -46,6 +82,27 @@ pub struct VmInfo {
     pub mem_size_mib: u64,
 }
 
+/// Holds the state of the watchdog, along with the action taken when it expires.
+#[derive(Clone, Debug, PartialEq, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct WatchdogDeviceState {
+    /// The action taken when the watchdog expires.
+    pub action: WatchdogAction,
+    /// The state of the watchdog timer.
+    pub state: WatchdogState,
+}
+
+/// Holds the state of the TPM, along with the configuration of its backend.
+#[cfg(feature = "tpm")]
+#[derive(Clone, Versionize)]
+// NOTICE: Any changes to this structure require a snapshot version bump.
+pub struct TpmDeviceState {
+    /// The backend of the TPM.
+    pub config: TpmConfig,
+    /// The state of the CRB interface and of the software TPM, if used.
+    pub state: TpmCrbState,
+}
+
 /// Contains the necesary state for saving/restoring a microVM.
 #[derive(Versionize)]
 // NOTICE: Any changes to this structure require a snapshot version bump.
@@ -60,6 +117,84 @@ pub struct MicrovmState {
     pub vcpu_states: Vec<VcpuState>,
     /// Device states.
     pub device_states: DeviceStates,
+    /// States of the serial ports bound to a host backend.
+    #[version(start = 2, ser_fn = "serial_ports_serialize")]
+    pub serial_ports: Vec<SerialPortState>,
+    /// State of the watchdog, if configured.
+    #[version(start = 2, ser_fn = "watchdog_serialize")]
+    pub watchdog: Option<WatchdogDeviceState>,
+    /// State of the TPM, if configured.
+    #[cfg(feature = "tpm")]
+    #[version(start = 2, ser_fn = "tpm_serialize")]
+    pub tpm: Option<TpmDeviceState>,
+    /// State of the vCPU hotplug controller, if vCPUs can be hotplugged.
+    #[cfg(target_arch = "x86_64")]
+    #[version(start = 2, ser_fn = "cpu_hotplug_serialize")]
+    pub cpu_hotplug: Option<CpuHotplugState>,
+    /// State of the ACPI PM registers.
+    #[cfg(target_arch = "x86_64")]
+    #[version(start = 2)]
+    pub acpi_pm: AcpiPmState,
+    /// The wall clock time at which the state was saved, in microseconds since the Unix epoch.
+    #[version(start = 2)]
+    pub saved_at_us: Option<u64>,
+    /// The layout of the memory gap holding the MMIO devices.
+    #[version(start = 2, ser_fn = "mmio_layout_serialize")]
+    pub mmio_layout: MmioLayoutConfig,
+}
+
+impl MicrovmState {
+    fn serial_ports_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !self.serial_ports.is_empty() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the additional serial ports.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn watchdog_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.watchdog.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the watchdog.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tpm")]
+    fn tpm_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.tpm.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the TPM.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn cpu_hotplug_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.cpu_hotplug.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement vCPU hotplug.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn mmio_layout_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.mmio_layout.layout() != Ok(arch::MmioLayout::default()) {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement custom MMIO layouts.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 /// Errors related to saving and restoring Microvm state.
@@ -71,10 +206,17 @@ pub enum MicrovmStateError {
     NotAllowed(String),
     /// Failed to restore devices.
     RestoreDevices(DevicePersistError),
+    /// Failed to restore the serial ports.
+    RestoreSerialPorts(LegacyDeviceError),
+    /// Failed to restore the TPM.
+    #[cfg(feature = "tpm")]
+    RestoreTpm(devices::tpm::Error),
     /// Failed to restore Vcpu state.
     RestoreVcpuState(vstate::vcpu::Error),
     /// Failed to restore VM state.
     RestoreVmState(vstate::vm::Error),
+    /// Failed to restore the watchdog.
+    RestoreWatchdog(WatchdogError),
     /// Failed to save Vcpu state.
     SaveVcpuState(vstate::vcpu::Error),
     /// Failed to save VM state.
@@ -92,8 +234,12 @@ impl Display for MicrovmStateError {
             InvalidInput => write!(f, "Provided MicroVM state is invalid."),
             NotAllowed(msg) => write!(f, "Operation not allowed: {}", msg),
             RestoreDevices(err) => write!(f, "Cannot restore devices. Error: {:?}", err),
+            RestoreSerialPorts(err) => write!(f, "Cannot restore serial ports. Error: {}", err),
+            #[cfg(feature = "tpm")]
+            RestoreTpm(err) => write!(f, "Cannot restore the TPM. Error: {}", err),
             RestoreVcpuState(err) => write!(f, "Cannot restore Vcpu state. Error: {:?}", err),
             RestoreVmState(err) => write!(f, "Cannot restore Vm state. Error: {:?}", err),
+            RestoreWatchdog(err) => write!(f, "Cannot restore the watchdog. Error: {}", err),
             SaveVcpuState(err) => write!(f, "Cannot save Vcpu state. Error: {:?}", err),
             SaveVmState(err) => write!(f, "Cannot save Vm state. Error: {:?}", err),
             SignalVcpu(err) => write!(f, "Cannot signal Vcpu: {:?}", err),
@@ -105,6 +251,10 @@ impl Display for MicrovmStateError {
 /// Errors associated with creating a snapshot.
 #[derive(Debug)]
 pub enum CreateSnapshotError {
+    /// Full snapshots cannot be saved as compact diffs.
+    CompactFullSnapshot,
+    /// Diff snapshots cannot be saved to a page store.
+    DiffDedupPages,
     /// Failed to get dirty bitmap.
     DirtyBitmap,
     /// Failed to translate microVM version to snapshot data version.
@@ -117,18 +267,32 @@ pub enum CreateSnapshotError {
     MemoryBackingFile(io::Error),
     /// Failed to save MicrovmState.
     MicrovmState(MicrovmStateError),
+    /// The state of the hypervisors run by the guest cannot be saved.
+    NestedVirtEnabled,
+    /// Failed to pause the threads processing the I/O of the devices.
+    PauseIoWorkers(WorkerError),
     /// Failed to serialize microVM state.
     SerializeMicrovmState(snapshot::Error),
+    /// A memory file was given for a single file snapshot.
+    SingleFileMemFile,
+    /// Only the full snapshots saving the guest memory to a memory file can be saved as a single
+    /// file.
+    SingleFileUnsupported,
     /// Failed to open the snapshot backing file.
     SnapshotBackingFile(io::Error),
     /// Number of devices exceeds the maximum supported devices for the snapshot data version.
     TooManyDevices(usize),
+    /// The state of the host PCI functions passed through cannot be saved.
+    #[cfg(feature = "vfio")]
+    VfioDevices,
 }
 
 impl Display for CreateSnapshotError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         use self::CreateSnapshotError::*;
         match self {
+            CompactFullSnapshot => write!(f, "Cannot save a full snapshot as a compact diff"),
+            DiffDedupPages => write!(f, "Cannot save a diff snapshot to a page store"),
             DirtyBitmap => write!(f, "Cannot get dirty bitmap"),
             InvalidVersion => write!(
                 f,
@@ -138,8 +302,26 @@ impl Display for CreateSnapshotError {
             Memory(err) => write!(f, "Cannot write memory file: {:?}", err),
             MemoryBackingFile(err) => write!(f, "Cannot open memory file: {:?}", err),
             MicrovmState(err) => write!(f, "Cannot save microvm state: {}", err),
+            NestedVirtEnabled => write!(
+                f,
+                "Cannot snapshot a microVM with nested virtualization enabled"
+            ),
+            PauseIoWorkers(err) => write!(f, "Cannot pause the I/O worker threads: {:?}", err),
             SerializeMicrovmState(err) => write!(f, "Cannot serialize MicrovmState: {:?}", err),
+            SingleFileMemFile => write!(
+                f,
+                "A single file snapshot holds the guest memory, no memory file can be given"
+            ),
+            SingleFileUnsupported => write!(
+                f,
+                "Only full snapshots without page deduplication can be saved as a single file"
+            ),
             SnapshotBackingFile(err) => write!(f, "Cannot open snapshot file: {:?}", err),
+            #[cfg(feature = "vfio")]
+            VfioDevices => write!(
+                f,
+                "Cannot snapshot a microVM with host PCI functions passed through"
+            ),
             TooManyDevices(val) => write!(
                 f,
                 "Too many devices attached: {}. The maximum number allowed \
@@ -153,36 +335,93 @@ impl Display for CreateSnapshotError {
 /// Errors associated with loading a snapshot.
 #[derive(Debug)]
 pub enum LoadSnapshotError {
+    /// Failed to give the host kernel the hints about the guest memory.
+    AdviseMemory(io::Error),
     /// Failed to build a microVM from snapshot.
     BuildMicroVm(StartMicrovmError),
+    /// The snapshot has no device with the identifier the clone configuration refers to.
+    CloneDeviceNotFound(String),
+    /// Failed to draw the random bytes given to the guest of a clone.
+    CloneEntropy(io::Error),
     /// Failed to deserialize memory.
     DeserializeMemory(memory_snapshot::Error),
     /// Failed to deserialize microVM state.
     DeserializeMicrovmState(snapshot::Error),
+    /// The number of I/O threads is above `MAX_IO_THREADS`.
+    InvalidIoThreads,
+    /// The header of the single file snapshot is invalid.
+    InvalidSingleFileHeader(String),
+    /// The context identifier of the vsock device of a clone is invalid.
+    #[cfg(feature = "vsock")]
+    InvalidVsockCid(u32),
     /// Failed to open memory backing file.
     MemoryBackingFile(io::Error),
+    /// Failed to merge the restore time data into the MMDS data store.
+    MmdsData(MmdsError),
     /// Failed to resume Vm after loading snapshot.
     ResumeMicroVm(VmmError),
+    /// A memory file was given for a single file snapshot.
+    SingleFileMemFile,
     /// Failed to open the snapshot backing file.
     SnapshotBackingFile(io::Error),
     /// Failed to retrieve the metadata of the snapshot backing file.
     SnapshotBackingFileMetadata(io::Error),
     /// Snapshot cpu vendor differs than host cpu vendor.
     CpuVendorMismatch(String),
+    /// The snapshot has a balloon device, which cannot be used with huge pages.
+    #[cfg(feature = "balloon")]
+    IncompatibleMemoryBackend,
+    /// The memory hints cannot be given about huge pages.
+    IncompatibleMemoryHints,
+    /// Failed to override the balloon target size.
+    #[cfg(feature = "balloon")]
+    UpdateBalloon(BalloonConfigError),
 }
 
 impl Display for LoadSnapshotError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         use self::LoadSnapshotError::*;
         match self {
+            AdviseMemory(err) => write!(f, "Cannot advise the guest memory: {}", err),
             BuildMicroVm(err) => write!(f, "Cannot build a microVM from snapshot: {}", err),
+            CloneDeviceNotFound(id) => write!(f, "The snapshot has no device with id {}.", id),
+            CloneEntropy(err) => write!(f, "Cannot draw the entropy of the clone: {}", err),
             DeserializeMemory(err) => write!(f, "Cannot deserialize memory: {}", err),
             DeserializeMicrovmState(err) => write!(f, "Cannot deserialize MicrovmState: {:?}", err),
+            InvalidIoThreads => write!(
+                f,
+                "The number of I/O threads is invalid. It can be at most {}.",
+                MAX_IO_THREADS
+            ),
+            InvalidSingleFileHeader(msg) => write!(f, "Invalid single file snapshot: {}", msg),
+            #[cfg(feature = "vsock")]
+            InvalidVsockCid(cid) => write!(
+                f,
+                "Invalid vsock CID of the clone: {}. The CID must be at least {}.",
+                cid, MIN_GUEST_CID
+            ),
             MemoryBackingFile(err) => write!(f, "Cannot open memory file: {}", err),
+            MmdsData(err) => write!(f, "Cannot merge the data into the MMDS: {}", err),
             ResumeMicroVm(err) => write!(f, "Failed to resume Vm after loading snapshot: {}", err),
+            SingleFileMemFile => write!(
+                f,
+                "The snapshot holds the guest memory, no memory file can be given."
+            ),
             SnapshotBackingFile(err) => write!(f, "Cannot open snapshot file: {}", err),
             SnapshotBackingFileMetadata(err) => write!(f, "Cannot retrieve file metadata: {}", err),
             CpuVendorMismatch(err) => write!(f, "Snapshot cpu vendor mismatch: {}", err),
+            #[cfg(feature = "balloon")]
+            IncompatibleMemoryBackend => write!(
+                f,
+                "The balloon device of the snapshot cannot be used with guest memory \
+                 backed by huge pages."
+            ),
+            IncompatibleMemoryHints => write!(
+                f,
+                "The memory hints cannot be used with guest memory backed by huge pages."
+            ),
+            #[cfg(feature = "balloon")]
+            UpdateBalloon(err) => write!(f, "Cannot update the balloon target size: {}", err),
         }
     }
 }
@@ -193,49 +432,271 @@ pub fn create_snapshot(
     params: &CreateSnapshotParams,
     version_map: VersionMap,
 ) -> std::result::Result<(), CreateSnapshotError> {
-    let microvm_state = vmm
-        .save_state()
+    // The state of the functions lives in the host hardware, out of reach of the VMM.
+    #[cfg(feature = "vfio")]
+    if vmm.mmio_device_manager.vfio_container.is_some() {
+        return Err(CreateSnapshotError::VfioDevices);
+    }
+    LIFECYCLE_EVENTS.emit(LifecycleEventKind::SnapshotStarted);
+    // The devices served by the I/O workers must not change their state, nor the guest memory,
+    // while the snapshot is saved.
+    let result = match vmm.pause_io_workers() {
+        Ok(_paused) if params.single_file => snapshot_to_single_file(vmm, params, version_map),
+        Ok(_paused) => snapshot_to_files(vmm, params, version_map),
+        Err(err) => Err(CreateSnapshotError::PauseIoWorkers(err)),
+    };
+    LIFECYCLE_EVENTS.emit(match result {
+        Ok(()) => LifecycleEventKind::SnapshotCreated,
+        Err(_) => LifecycleEventKind::SnapshotFailed,
+    });
+    result
+}
+
+fn snapshot_to_files(
+    vmm: &mut Vmm,
+    params: &CreateSnapshotParams,
+    version_map: VersionMap,
+) -> std::result::Result<(), CreateSnapshotError> {
+    if params.dedup_pages && params.snapshot_type == SnapshotType::Diff {
+        return Err(CreateSnapshotError::DiffDedupPages);
+    }
+    if params.compact_diff && params.snapshot_type == SnapshotType::Full {
+        return Err(CreateSnapshotError::CompactFullSnapshot);
+    }
+
+    let mut microvm_state = in_span("save_microvm_state", || vmm.save_state())
         .map_err(CreateSnapshotError::MicrovmState)?;
 
-    snapshot_memory_to_file(vmm, &params.mem_file_path, &params.snapshot_type)?;
+    let timings = &METRICS.timings_us;
+    in_timed_span("snapshot_memory", &timings.snapshot_memory, || {
+        if params.dedup_pages {
+            snapshot_memory_to_page_store(
+                vmm,
+                &params.mem_file_path,
+                &mut microvm_state.memory_state,
+            )
+        } else if params.compact_diff {
+            snapshot_memory_to_compact_diff(vmm, &params.mem_file_path)
+        } else {
+            snapshot_memory_to_file(
+                vmm,
+                &params.mem_file_path,
+                &params.snapshot_type,
+                &mut microvm_state.memory_state,
+            )
+        }
+    })?;
 
-    snapshot_state_to_file(
-        &microvm_state,
-        &params.snapshot_path,
-        &params.version,
-        version_map,
-        &vmm.mmio_device_manager,
-    )?;
+    in_timed_span("snapshot_state", &timings.snapshot_state, || {
+        snapshot_state_to_file(
+            &microvm_state,
+            &params.snapshot_path,
+            &params.version,
+            version_map,
+            &vmm.mmio_device_manager,
+        )
+    })?;
 
     Ok(())
 }
 
-fn snapshot_state_to_file(
-    microvm_state: &MicrovmState,
-    snapshot_path: &PathBuf,
-    version: &Option<String>,
+// Saves the guest memory and the microVM state to a single file: a header describing where they
+// are, the guest memory laid out as in a memory file, then the microVM state.
+fn snapshot_to_single_file(
+    vmm: &mut Vmm,
+    params: &CreateSnapshotParams,
     version_map: VersionMap,
-    device_manager: &MMIODeviceManager,
 ) -> std::result::Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
+    if params.snapshot_type != SnapshotType::Full || params.dedup_pages || params.compact_diff {
+        return Err(SingleFileUnsupported);
+    }
+    if !params.mem_file_path.as_os_str().is_empty() {
+        return Err(SingleFileMemFile);
+    }
+    let snapshot_data_version =
+        snapshot_data_version(&params.version, &version_map, &vmm.mmio_device_manager)?;
+
+    let mut microvm_state = in_span("save_microvm_state", || vmm.save_state())
+        .map_err(CreateSnapshotError::MicrovmState)?;
+
     let mut snapshot_file = OpenOptions::new()
-        .create(true)
+        .read(true)
         .write(true)
-        .open(snapshot_path)
+        .create(true)
+        .truncate(true)
+        .open(&params.snapshot_path)
         .map_err(SnapshotBackingFile)?;
 
-    // Translate the microVM version to its corresponding snapshot data format.
-    let snapshot_data_version = match version {
+    // The zero pages and the unplugged blocks are left as holes in the file.
+    let timings = &METRICS.timings_us;
+    let data_ranges = in_timed_span("snapshot_memory", &timings.snapshot_memory, || {
+        vmm.guest_memory().dump_at(
+            &snapshot_file,
+            &microvm_state.memory_state,
+            SINGLE_FILE_MEM_OFFSET,
+        )
+    })
+    .map_err(Memory)?;
+    // The memory state is the one of a memory file, so that it is the same in both formats.
+    microvm_state.memory_state.data_ranges = Some(
+        data_ranges
+            .iter()
+            .map(|range| GuestMemoryDataRange {
+                offset: range.offset - SINGLE_FILE_MEM_OFFSET,
+                len: range.len,
+            })
+            .collect(),
+    );
+    let mem_len = microvm_state
+        .memory_state
+        .regions
+        .iter()
+        .map(|region| region.size as u64)
+        .sum::<u64>();
+
+    let state_offset = SINGLE_FILE_MEM_OFFSET + mem_len;
+    snapshot_file
+        .seek(SeekFrom::Start(state_offset))
+        .map_err(SnapshotBackingFile)?;
+    in_timed_span("snapshot_state", &timings.snapshot_state, || {
+        Snapshot::new(version_map, snapshot_data_version)
+            .save(&mut snapshot_file, &microvm_state)
+            .map_err(SerializeMicrovmState)
+    })?;
+    let state_end = snapshot_file
+        .seek(SeekFrom::Current(0))
+        .map_err(SnapshotBackingFile)?;
+
+    // The header is written last, so that the file is not taken for a snapshot until it is
+    // complete.
+    let header = SingleFileHeader {
+        mem_offset: SINGLE_FILE_MEM_OFFSET,
+        mem_len,
+        state_offset,
+        state_len: state_end - state_offset,
+    };
+    snapshot_file
+        .write_all_at(&header.to_bytes(), 0)
+        .map_err(SnapshotBackingFile)
+}
+
+// Where the guest memory and the microVM state of a single file snapshot are.
+#[derive(Debug, PartialEq)]
+struct SingleFileHeader {
+    mem_offset: u64,
+    mem_len: u64,
+    state_offset: u64,
+    state_len: u64,
+}
+
+impl SingleFileHeader {
+    fn to_bytes(&self) -> [u8; SINGLE_FILE_HEADER_LEN] {
+        let mut bytes = [0u8; SINGLE_FILE_HEADER_LEN];
+        bytes[0..8].copy_from_slice(&SINGLE_FILE_MAGIC);
+        bytes[8..12].copy_from_slice(&SINGLE_FILE_VERSION.to_le_bytes());
+        let fields = [
+            self.mem_offset,
+            self.mem_len,
+            self.state_offset,
+            self.state_len,
+        ];
+        for (i, field) in fields.iter().enumerate() {
+            bytes[16 + i * 8..24 + i * 8].copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    // Reads the header of `file`, if it is a single file snapshot.
+    fn read(file: &File) -> std::result::Result<Option<Self>, LoadSnapshotError> {
+        use self::LoadSnapshotError::{InvalidSingleFileHeader, SnapshotBackingFile};
+        let mut bytes = [0u8; SINGLE_FILE_HEADER_LEN];
+        match file.read_exact_at(&mut bytes, 0) {
+            Ok(()) => (),
+            // The state of a snapshot saved to separate files may be shorter than the header.
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(SnapshotBackingFile(err)),
+        }
+        if bytes[0..8] != SINGLE_FILE_MAGIC {
+            return Ok(None);
+        }
+
+        let field = |i: usize| {
+            let mut field = [0u8; 8];
+            field.copy_from_slice(&bytes[16 + i * 8..24 + i * 8]);
+            u64::from_le_bytes(field)
+        };
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&bytes[8..12]);
+        let version = u32::from_le_bytes(version);
+        if version != SINGLE_FILE_VERSION {
+            return Err(InvalidSingleFileHeader(format!(
+                "unsupported format version {}",
+                version
+            )));
+        }
+        let header = SingleFileHeader {
+            mem_offset: field(0),
+            mem_len: field(1),
+            state_offset: field(2),
+            state_len: field(3),
+        };
+
+        // The guest memory is mapped from the file.
+        if header.mem_offset % SINGLE_FILE_MEM_OFFSET != 0 {
+            return Err(InvalidSingleFileHeader(format!(
+                "unaligned guest memory offset {}",
+                header.mem_offset
+            )));
+        }
+        let file_len = file.metadata().map_err(SnapshotBackingFile)?.len();
+        let mem_end = header.mem_offset.checked_add(header.mem_len);
+        let state_end = header.state_offset.checked_add(header.state_len);
+        match (mem_end, state_end) {
+            (Some(mem_end), Some(state_end)) if mem_end <= file_len && state_end <= file_len => {
+                Ok(Some(header))
+            }
+            _ => Err(InvalidSingleFileHeader(
+                "the sections exceed the file".to_string(),
+            )),
+        }
+    }
+}
+
+// Translates the microVM version to its corresponding snapshot data format.
+fn snapshot_data_version(
+    version: &Option<String>,
+    version_map: &VersionMap,
+    device_manager: &MMIODeviceManager,
+) -> std::result::Result<u16, CreateSnapshotError> {
+    match version {
         Some(version) => match FC_VERSION_TO_SNAP_VERSION.get(version) {
             Some(&FC_V0_23_SNAP_VERSION) => {
                 validate_devices_number(device_manager.used_irqs_count())?;
                 Ok(FC_V0_23_SNAP_VERSION)
             }
             Some(data_version) => Ok(*data_version),
-            _ => Err(InvalidVersion),
+            _ => Err(CreateSnapshotError::InvalidVersion),
         },
         _ => Ok(version_map.latest_version()),
-    }?;
+    }
+}
+
+fn snapshot_state_to_file(
+    microvm_state: &MicrovmState,
+    snapshot_path: &PathBuf,
+    version: &Option<String>,
+    version_map: VersionMap,
+    device_manager: &MMIODeviceManager,
+) -> std::result::Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+    let mut snapshot_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(snapshot_path)
+        .map_err(SnapshotBackingFile)?;
+
+    let snapshot_data_version = snapshot_data_version(version, &version_map, device_manager)?;
 
     let mut snapshot = Snapshot::new(version_map, snapshot_data_version);
     snapshot
@@ -249,6 +710,7 @@ fn snapshot_memory_to_file(
     vmm: &Vmm,
     mem_file_path: &PathBuf,
     snapshot_type: &SnapshotType,
+    memory_state: &mut GuestMemoryState,
 ) -> std::result::Result<(), CreateSnapshotError> {
     use self::CreateSnapshotError::*;
     let mut file = OpenOptions::new()
@@ -264,14 +726,72 @@ fn snapshot_memory_to_file(
         .map_err(MemoryBackingFile)?;
 
     match snapshot_type {
+        // The diff is meant to be merged on top of an earlier memory file, so the pages it
+        // leaves out are not known to be zero.
         SnapshotType::Diff => {
             let dirty_bitmap = vmm.get_dirty_bitmap().map_err(|_| DirtyBitmap)?;
             vmm.guest_memory()
                 .dump_dirty(&mut file, &dirty_bitmap)
                 .map_err(Memory)
         }
-        SnapshotType::Full => vmm.guest_memory().dump(&mut file).map_err(Memory),
+        // The zero pages and the unplugged blocks are left as holes in the file.
+        SnapshotType::Full => {
+            let data_ranges = vmm
+                .guest_memory()
+                .dump(&file, memory_state)
+                .map_err(Memory)?;
+            memory_state.data_ranges = Some(data_ranges);
+            Ok(())
+        }
+    }
+}
+
+fn snapshot_memory_to_compact_diff(
+    vmm: &Vmm,
+    mem_diff_path: &PathBuf,
+) -> std::result::Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+    // The diffs taken earlier are kept, the new one being appended to them.
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(mem_diff_path)
+        .map_err(MemoryBackingFile)?;
+
+    let dirty_bitmap = vmm.get_dirty_bitmap().map_err(|_| DirtyBitmap)?;
+    vmm.guest_memory()
+        .dump_dirty_compact(&file, &dirty_bitmap)
+        .map_err(Memory)
+}
+
+fn snapshot_memory_to_page_store(
+    vmm: &Vmm,
+    page_store_path: &PathBuf,
+    memory_state: &mut GuestMemoryState,
+) -> std::result::Result<(), CreateSnapshotError> {
+    use self::CreateSnapshotError::*;
+    // The pages already in the store are kept, since other snapshots refer to them.
+    let store = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(page_store_path)
+        .map_err(MemoryBackingFile)?;
+
+    // The microVMs snapshotted to the same store at the same time take turns, so that they
+    // don't append their pages at the same offsets. The lock is released with the file.
+    // Safe because the file descriptor is valid, and the return value is checked.
+    if unsafe { libc::flock(store.as_raw_fd(), libc::LOCK_EX) } < 0 {
+        return Err(MemoryBackingFile(io::Error::last_os_error()));
     }
+
+    let page_index = vmm
+        .guest_memory()
+        .dump_dedup(&store, memory_state)
+        .map_err(Memory)?;
+    memory_state.page_index = Some(page_index);
+    Ok(())
 }
 
 /// Validates that snapshot CPU vendor matches the host CPU vendor.
@@ -303,54 +823,312 @@ pub fn validate_x86_64_cpu_vendor(
     Ok(())
 }
 
-/// Loads a Microvm snapshot producing a 'paused' Microvm.
+/// Loads a Microvm snapshot producing a 'paused' Microvm, which can be torn down under
+/// `seccomp_filter` if `allow_teardown` is set.
 pub fn restore_from_snapshot(
     event_manager: &mut EventManager,
     seccomp_filter: BpfProgramRef,
+    allow_teardown: bool,
     params: &LoadSnapshotParams,
     version_map: VersionMap,
 ) -> std::result::Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
     use self::LoadSnapshotError::*;
+    if params.io_threads > Some(MAX_IO_THREADS) {
+        return Err(InvalidIoThreads);
+    }
     let track_dirty_pages = params.enable_diff_snapshots;
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+    let snapshot_file = File::open(&params.snapshot_path).map_err(SnapshotBackingFile)?;
+    // The single file snapshots are recognized by their header.
+    let single_file_header = SingleFileHeader::read(&snapshot_file)?;
+    if single_file_header.is_some() && !params.mem_file_path.as_os_str().is_empty() {
+        return Err(SingleFileMemFile);
+    }
+    let mut microvm_state = in_span("load_snapshot_state", || {
+        snapshot_state_from_file(&snapshot_file, single_file_header.as_ref(), version_map)
+    })?;
+    if let Some(clone) = params.clone.as_ref() {
+        apply_clone_config(&mut microvm_state.device_states, clone)?;
+    }
     #[cfg(target_arch = "x86_64")]
     validate_x86_64_cpu_vendor(&microvm_state)?;
-    let guest_memory = guest_memory_from_file(
-        &params.mem_file_path,
-        &microvm_state.memory_state,
-        track_dirty_pages,
-    )?;
-    builder::build_microvm_from_snapshot(
-        event_manager,
-        microvm_state,
-        guest_memory,
-        track_dirty_pages,
-        seccomp_filter,
-    )
-    .map_err(BuildMicroVm)
+    // The balloon target size is checked before building the microVM, so that a wrong one fails
+    // the request before any resource is allocated.
+    #[cfg(feature = "balloon")]
+    if let Some(amount_mib) = params.balloon_target_mib() {
+        validate_balloon_target(&microvm_state, amount_mib)?;
+    }
+    let mem_backend = params.mem_backend.unwrap_or_default();
+    #[cfg(feature = "balloon")]
+    if mem_backend.huge_page_size_mib().is_some()
+        && microvm_state.device_states.balloon_device.is_some()
+    {
+        return Err(IncompatibleMemoryBackend);
+    }
+    // The huge pages of hugetlbfs are neither merged nor split by the host kernel.
+    let mem_hints = params.mem_hints.unwrap_or_default();
+    if mem_backend.huge_page_size_mib().is_some() && !mem_hints.advices().is_empty() {
+        return Err(IncompatibleMemoryHints);
+    }
+    let timings = &METRICS.timings_us;
+    let guest_memory = in_timed_span("load_snapshot_memory", &timings.restore_memory, || {
+        let mem_file = match single_file_header.as_ref() {
+            Some(header) => MemoryFile::SingleFile(&snapshot_file, header.mem_offset),
+            None => MemoryFile::Path(&params.mem_file_path),
+        };
+        guest_memory_from_file(
+            mem_file,
+            &params.mem_diff_paths,
+            &microvm_state.memory_state,
+            track_dirty_pages,
+            mem_backend,
+        )
+    })?;
+    builder::advise_guest_memory(&guest_memory, mem_hints).map_err(AdviseMemory)?;
+    let vmm = in_span("restore_microvm_state", || {
+        builder::build_microvm_from_snapshot(
+            event_manager,
+            microvm_state,
+            guest_memory,
+            track_dirty_pages,
+            params.io_threads.unwrap_or(0),
+            seccomp_filter,
+            allow_teardown,
+        )
+    })
+    .map_err(BuildMicroVm)?;
+
+    // The vcpus are still paused, so the guest sees the new balloon target
+    // size as soon as it is resumed.
+    #[cfg(feature = "balloon")]
+    if let Some(amount_mib) = params.balloon_target_mib() {
+        vmm.lock()
+            .expect("Poisoned lock")
+            .update_balloon_config(amount_mib)
+            .map_err(|e| UpdateBalloon(BalloonConfigError::from(e)))?;
+    }
+
+    if let Some(clone) = params.clone.as_ref() {
+        // The guest of each clone gets random bytes of its own, since the state of its random
+        // number generator is the one of the template.
+        let mut data = Map::new();
+        data.insert(
+            CLONE_ENTROPY_KEY.to_string(),
+            Value::String(clone_entropy()?),
+        );
+        merge_mmds_data(&mut MMDS.lock().expect("Poisoned lock"), &data).map_err(MmdsData)?;
+
+        // The guest driver reads the context identifier again when its transport is reset.
+        #[cfg(feature = "vsock")]
+        if clone.vsock.is_some() {
+            vmm.lock()
+                .expect("Poisoned lock")
+                .reset_vsock_transport()
+                .unwrap_or_else(|err| {
+                    error!("Failed to reset the vsock transport of the clone: {}", err);
+                });
+        }
+    }
+
+    // The kernel command line is baked into the snapshot, so the parameters
+    // which differ between the clones reach the guest through the MMDS.
+    if let Some(data) = params.mmds_data.as_ref() {
+        merge_mmds_data(&mut MMDS.lock().expect("Poisoned lock"), data).map_err(MmdsData)?;
+    }
+
+    LIFECYCLE_EVENTS.emit(LifecycleEventKind::SnapshotLoaded);
+    Ok(vmm)
 }
 
+// Checks that the balloon of the microVM restored from `microvm_state` can be given the
+// `amount_mib` target size once the microVM is built.
+#[cfg(feature = "balloon")]
+fn validate_balloon_target(
+    microvm_state: &MicrovmState,
+    amount_mib: u32,
+) -> std::result::Result<(), LoadSnapshotError> {
+    use self::LoadSnapshotError::UpdateBalloon;
+
+    let balloon_state = microvm_state
+        .device_states
+        .balloon_device
+        .as_ref()
+        .ok_or_else(|| UpdateBalloon(BalloonConfigError::DeviceNotFound))?;
+    // The balloon cannot have a target size greater than the size of the guest memory.
+    if u64::from(amount_mib) > microvm_state.vm_info.mem_size_mib {
+        return Err(UpdateBalloon(BalloonConfigError::TooManyPagesRequested));
+    }
+    // Only the guest driver can act on the target size.
+    if !balloon_state.device_state.is_activated() {
+        return Err(UpdateBalloon(BalloonConfigError::DeviceNotActive));
+    }
+    Ok(())
+}
+
+// Gives a clone the resources of its own in place of the ones of the template
+// it is restored from.
+#[cfg_attr(
+    not(any(feature = "block", feature = "net", feature = "vsock")),
+    allow(unused_variables)
+)]
+fn apply_clone_config(
+    device_states: &mut DeviceStates,
+    clone: &CloneConfig,
+) -> std::result::Result<(), LoadSnapshotError> {
+    use self::LoadSnapshotError::CloneDeviceNotFound;
+
+    #[cfg(feature = "block")]
+    for drive in clone.drives.iter() {
+        let block_state = device_states
+            .block_devices
+            .iter_mut()
+            .find(|block_state| block_state.device_id == drive.drive_id)
+            .ok_or_else(|| CloneDeviceNotFound(drive.drive_id.clone()))?;
+        block_state
+            .device_state
+            .set_disk_path(drive.path_on_host.clone());
+    }
+    #[cfg(not(feature = "block"))]
+    if let Some(drive) = clone.drives.first() {
+        return Err(CloneDeviceNotFound(drive.drive_id.clone()));
+    }
+
+    #[cfg(feature = "net")]
+    for iface in clone.network_interfaces.iter() {
+        let net_state = device_states
+            .net_devices
+            .iter_mut()
+            .find(|net_state| net_state.device_id == iface.iface_id)
+            .ok_or_else(|| CloneDeviceNotFound(iface.iface_id.clone()))?;
+        net_state
+            .device_state
+            .set_tap_if_name(iface.host_dev_name.clone());
+        if let Some(guest_mac) = iface.guest_mac.as_ref() {
+            net_state.device_state.set_guest_mac(guest_mac);
+        }
+    }
+    #[cfg(not(feature = "net"))]
+    if let Some(iface) = clone.network_interfaces.first() {
+        return Err(CloneDeviceNotFound(iface.iface_id.clone()));
+    }
+
+    if let Some(vsock) = clone.vsock.as_ref() {
+        #[cfg(feature = "vsock")]
+        {
+            if vsock.guest_cid < MIN_GUEST_CID {
+                return Err(LoadSnapshotError::InvalidVsockCid(vsock.guest_cid));
+            }
+            let vsock_state = device_states
+                .vsock_device
+                .as_mut()
+                .ok_or_else(|| CloneDeviceNotFound("vsock".to_string()))?;
+            vsock_state.device_state.frontend.cid = u64::from(vsock.guest_cid);
+            vsock_state
+                .device_state
+                .backend
+                .set_uds_path(&vsock.uds_path);
+        }
+        #[cfg(not(feature = "vsock"))]
+        return Err(CloneDeviceNotFound(format!("vsock {}", vsock.uds_path)));
+    }
+
+    Ok(())
+}
+
+// Draws the random bytes given to the guest of a clone, in hexadecimal.
+fn clone_entropy() -> std::result::Result<String, LoadSnapshotError> {
+    let mut bytes = [0u8; CLONE_ENTROPY_LEN];
+    File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut bytes))
+        .map_err(LoadSnapshotError::CloneEntropy)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Merges `data` into the data store of `mmds`, initializing the store if it
+// hasn't been yet.
+fn merge_mmds_data(
+    mmds: &mut Mmds,
+    data: &Map<String, Value>,
+) -> std::result::Result<(), MmdsError> {
+    let data = Value::Object(data.clone());
+    match mmds.patch_data(data.clone()) {
+        Err(MmdsError::NotInitialized) => mmds.put_data(data),
+        result => result,
+    }
+}
+
+// Reads the microVM state from the snapshot file, or from its section of a single file snapshot.
 fn snapshot_state_from_file(
-    snapshot_path: &PathBuf,
+    snapshot_file: &File,
+    single_file_header: Option<&SingleFileHeader>,
     version_map: VersionMap,
 ) -> std::result::Result<MicrovmState, LoadSnapshotError> {
     use self::LoadSnapshotError::{
         DeserializeMicrovmState, SnapshotBackingFile, SnapshotBackingFileMetadata,
     };
-    let mut snapshot_reader = File::open(snapshot_path).map_err(SnapshotBackingFile)?;
-    let metadata = std::fs::metadata(snapshot_path).map_err(SnapshotBackingFileMetadata)?;
-    let snapshot_len = metadata.len() as usize;
-    Snapshot::load(&mut snapshot_reader, snapshot_len, version_map).map_err(DeserializeMicrovmState)
+    let mut snapshot_reader = snapshot_file;
+    let snapshot_len = match single_file_header {
+        Some(header) => {
+            snapshot_reader
+                .seek(SeekFrom::Start(header.state_offset))
+                .map_err(SnapshotBackingFile)?;
+            header.state_len
+        }
+        None => snapshot_reader
+            .metadata()
+            .map_err(SnapshotBackingFileMetadata)?
+            .len(),
+    };
+    Snapshot::load(&mut snapshot_reader, snapshot_len as usize, version_map)
+        .map_err(DeserializeMicrovmState)
 }
 
+// The file the guest memory is restored from.
+enum MemoryFile<'a> {
+    // A memory file.
+    Path(&'a PathBuf),
+    // A single file snapshot, holding the guest memory from the offset onwards.
+    SingleFile(&'a File, u64),
+}
+
+// Restores the guest memory from the memory file, then from the compact diffs taken after it,
+// in order.
 fn guest_memory_from_file(
-    mem_file_path: &PathBuf,
+    mem_file: MemoryFile,
+    mem_diff_paths: &[PathBuf],
     mem_state: &GuestMemoryState,
     track_dirty_pages: bool,
+    mem_backend: MemoryBackend,
 ) -> std::result::Result<GuestMemoryMmap, LoadSnapshotError> {
     use self::LoadSnapshotError::{DeserializeMemory, MemoryBackingFile};
-    let mem_file = File::open(mem_file_path).map_err(MemoryBackingFile)?;
-    GuestMemoryMmap::restore(&mem_file, mem_state, track_dirty_pages).map_err(DeserializeMemory)
+    let (mem_file, file_state) = match mem_file {
+        MemoryFile::Path(mem_file_path) => (
+            File::open(mem_file_path).map_err(MemoryBackingFile)?,
+            mem_state.clone(),
+        ),
+        MemoryFile::SingleFile(snapshot_file, mem_offset) => (
+            snapshot_file.try_clone().map_err(MemoryBackingFile)?,
+            mem_state.at_offset(mem_offset),
+        ),
+    };
+    let guest_memory = match mem_backend {
+        // The memory file is mapped, and its pages are only loaded when the guest touches them.
+        MemoryBackend::Anonymous => {
+            GuestMemoryMmap::restore(&mem_file, &file_state, track_dirty_pages)
+        }
+        // The memory file is copied into memory of the requested kind.
+        _ => GuestMemoryMmap::restore_copy(&mem_file, &file_state, track_dirty_pages, |size| {
+            builder::create_memfd(size, mem_backend)
+        }),
+    }
+    .map_err(DeserializeMemory)?;
+
+    for mem_diff_path in mem_diff_paths.iter() {
+        let mem_diff = File::open(mem_diff_path).map_err(MemoryBackingFile)?;
+        guest_memory
+            .apply_diff(&mem_diff, mem_state)
+            .map_err(DeserializeMemory)?;
+    }
+    Ok(guest_memory)
 }
 
 fn validate_devices_number(device_number: usize) -> std::result::Result<(), CreateSnapshotError> {
@@ -364,31 +1142,48 @@ fn validate_devices_number(device_number: usize) -> std::result::Result<(), Crea
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "balloon")]
+    use crate::builder::tests::insert_balloon_device;
+    #[cfg(feature = "vsock")]
+    use crate::builder::tests::insert_vsock_device;
     use crate::builder::tests::{
-        default_kernel_cmdline, default_vmm, insert_balloon_device, insert_block_devices,
-        insert_net_device, insert_vsock_device, CustomBlockConfig,
+        default_kernel_cmdline, default_vmm, insert_block_devices, insert_net_device,
+        CustomBlockConfig,
     };
-    use crate::memory_snapshot::SnapshotMemory;
+    use crate::memory_snapshot::{GuestMemoryRegionState, SnapshotMemory};
+    use crate::version_map::VERSION_MAP;
+    #[cfg(feature = "balloon")]
     use crate::vmm_config::balloon::BalloonDeviceConfig;
     use crate::vmm_config::net::NetworkInterfaceConfig;
+    #[cfg(feature = "vsock")]
+    use crate::vmm_config::snapshot::CloneVsockConfig;
+    use crate::vmm_config::snapshot::{CloneDriveConfig, CloneNetConfig};
+    #[cfg(feature = "vsock")]
     use crate::vmm_config::vsock::tests::default_config;
     use crate::Vmm;
 
     use polly::event_manager::EventManager;
     use snapshot::Persist;
-    use utils::{errno, tempfile::TempFile};
+    use utils::errno;
+    use utils::net::mac::MacAddr;
+    #[cfg(any(feature = "balloon", feature = "vsock"))]
+    use utils::tempfile::TempFile;
 
     fn default_vmm_with_devices(event_manager: &mut EventManager) -> Vmm {
         let mut vmm = default_vmm();
         let mut cmdline = default_kernel_cmdline();
 
         // Add a balloon device.
-        let balloon_config = BalloonDeviceConfig {
-            amount_mb: 0,
-            deflate_on_oom: false,
-            stats_polling_interval_s: 0,
-        };
-        insert_balloon_device(&mut vmm, &mut cmdline, event_manager, balloon_config);
+        #[cfg(feature = "balloon")]
+        {
+            let balloon_config = BalloonDeviceConfig {
+                amount_mb: 0,
+                deflate_on_oom: false,
+                stats_polling_interval_s: 0,
+                free_page_reporting: false,
+            };
+            insert_balloon_device(&mut vmm, &mut cmdline, event_manager, balloon_config);
+        }
 
         // Add a block device.
         let drive_id = String::from("root");
@@ -403,15 +1198,21 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: true,
+            vlan_id: None,
+            dhcp: None,
+            rate_limiter_group: None,
         };
         insert_net_device(&mut vmm, &mut cmdline, event_manager, network_interface);
 
         // Add vsock device.
-        let mut tmp_sock_file = TempFile::new().unwrap();
-        tmp_sock_file.remove().unwrap();
-        let vsock_config = default_config(&tmp_sock_file);
+        #[cfg(feature = "vsock")]
+        {
+            let mut tmp_sock_file = TempFile::new().unwrap();
+            tmp_sock_file.remove().unwrap();
+            let vsock_config = default_config(&tmp_sock_file);
 
-        insert_vsock_device(&mut vmm, &mut cmdline, event_manager, vsock_config);
+            insert_vsock_device(&mut vmm, &mut cmdline, event_manager, vsock_config);
+        }
 
         vmm
     }
@@ -426,7 +1227,9 @@ mod tests {
         // is tested by that device's tests.
         assert_eq!(states.block_devices.len(), 1);
         assert_eq!(states.net_devices.len(), 1);
+        #[cfg(feature = "vsock")]
         assert!(states.vsock_device.is_some());
+        #[cfg(feature = "balloon")]
         assert!(states.balloon_device.is_some());
 
         let memory_state = vmm.guest_memory().describe();
@@ -437,6 +1240,16 @@ mod tests {
             vcpu_states: vec![VcpuState::default()],
             vm_info: VmInfo { mem_size_mib: 1u64 },
             vm_state: vmm.vm.save_state().unwrap(),
+            serial_ports: Vec::new(),
+            watchdog: None,
+            #[cfg(feature = "tpm")]
+            tpm: None,
+            #[cfg(target_arch = "x86_64")]
+            cpu_hotplug: None,
+            #[cfg(target_arch = "x86_64")]
+            acpi_pm: AcpiPmState::default(),
+            saved_at_us: None,
+            mmio_layout: MmioLayoutConfig::default(),
         };
 
         let mut buf = vec![0; 10000];
@@ -463,11 +1276,221 @@ mod tests {
         )
     }
 
+    // Returns the snapshot data versions of the Firecracker releases, oldest first.
+    fn supported_snapshot_versions() -> Vec<u16> {
+        let mut versions: Vec<u16> = FC_VERSION_TO_SNAP_VERSION.values().copied().collect();
+        versions.sort_unstable();
+        versions.dedup();
+        versions
+    }
+
+    // Builds the state of `vmm` as it can be saved in the `version` format, leaving out the
+    // devices that format does not implement.
+    fn microvm_state_fixture(vmm: &Vmm, version: u16) -> MicrovmState {
+        #[allow(unused_mut)]
+        let mut device_states = vmm.mmio_device_manager.save();
+        if version < 2 {
+            #[cfg(feature = "balloon")]
+            {
+                device_states.balloon_device = None;
+            }
+            #[cfg(feature = "null-devices")]
+            device_states.null_devices.clear();
+            #[cfg(feature = "virtio-mem")]
+            {
+                device_states.mem_device = None;
+            }
+            #[cfg(feature = "virtio-rng")]
+            {
+                device_states.entropy_device = None;
+            }
+            #[cfg(feature = "virtio-pmem")]
+            device_states.pmem_devices.clear();
+            #[cfg(feature = "virtio-console")]
+            {
+                device_states.console_device = None;
+            }
+        }
+
+        MicrovmState {
+            device_states,
+            memory_state: vmm.guest_memory().describe(),
+            vcpu_states: vec![VcpuState::default()],
+            vm_info: VmInfo { mem_size_mib: 1u64 },
+            vm_state: vmm.vm.save_state().unwrap(),
+            serial_ports: Vec::new(),
+            watchdog: None,
+            #[cfg(feature = "tpm")]
+            tpm: None,
+            #[cfg(target_arch = "x86_64")]
+            cpu_hotplug: None,
+            #[cfg(target_arch = "x86_64")]
+            acpi_pm: AcpiPmState::default(),
+            saved_at_us: None,
+            mmio_layout: MmioLayoutConfig::default(),
+        }
+    }
+
+    fn save_snapshot(state: &MicrovmState, version: u16) -> Vec<u8> {
+        let mut buf = Vec::new();
+        Snapshot::new(VERSION_MAP.clone(), version)
+            .save(&mut buf, state)
+            .unwrap();
+        buf
+    }
+
+    fn load_snapshot(buf: &[u8]) -> MicrovmState {
+        Snapshot::load(&mut &buf[..], buf.len(), VERSION_MAP.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_snapshot_versions() {
+        // Every data version has to be reachable from a Firecracker release.
+        let versions = supported_snapshot_versions();
+        assert_eq!(
+            versions,
+            (1..=VERSION_MAP.latest_version()).collect::<Vec<u16>>()
+        );
+
+        let mut event_manager = EventManager::new().expect("Cannot create EventManager");
+        let vmm = default_vmm_with_devices(&mut event_manager);
+        let latest = VERSION_MAP.latest_version();
+
+        for version in versions {
+            let microvm_state = microvm_state_fixture(&vmm, version);
+            let buf = save_snapshot(&microvm_state, version);
+
+            // The state is restored as it was saved.
+            let restored_state = load_snapshot(&buf);
+            assert_eq!(restored_state.vm_info, microvm_state.vm_info);
+            assert_eq!(restored_state.memory_state, microvm_state.memory_state);
+            assert_eq!(
+                restored_state.vcpu_states.len(),
+                microvm_state.vcpu_states.len()
+            );
+            assert_eq!(restored_state.device_states, microvm_state.device_states);
+
+            // Saving the restored state again yields the very same bytes.
+            assert_eq!(
+                save_snapshot(&restored_state, version),
+                buf,
+                "Snapshot version {} is not stable across a restore.",
+                version
+            );
+
+            // Snapshots of older versions are upgraded to the latest one without losing state.
+            assert_eq!(
+                save_snapshot(&restored_state, latest),
+                save_snapshot(&microvm_state, latest),
+                "Snapshot version {} does not upgrade to version {}.",
+                version,
+                latest
+            );
+        }
+    }
+
+    #[test]
+    fn test_mmio_layout_serialize() {
+        let vmm = default_vmm();
+        let mut microvm_state = microvm_state_fixture(&vmm, 1);
+        microvm_state.mmio_layout = MmioLayoutConfig {
+            gap_size_mib: None,
+            device_slots: Some(4),
+        };
+
+        let mut buf = Vec::new();
+        assert!(Snapshot::new(VERSION_MAP.clone(), 1)
+            .save(&mut buf, &microvm_state)
+            .is_err());
+
+        let restored_state = load_snapshot(&save_snapshot(&microvm_state, 2));
+        assert_eq!(restored_state.mmio_layout, microvm_state.mmio_layout);
+    }
+
+    #[test]
+    fn test_snapshot_format_fixtures() {
+        // The host independent parts of the state are expected to keep the same encoding in
+        // every supported version. Any change here breaks the restoring of existing snapshots.
+        let vm_info = VmInfo { mem_size_mib: 128 };
+        let vm_info_fixture = [0x80, 0, 0, 0, 0, 0, 0, 0];
+        let memory_state = GuestMemoryState {
+            regions: vec![
+                GuestMemoryRegionState {
+                    base_address: 0,
+                    size: 0x1000,
+                    offset: 0,
+                    plugged_blocks: None,
+                },
+                GuestMemoryRegionState {
+                    base_address: 0x1_0000_0000,
+                    size: 0x2000,
+                    offset: 0x1000,
+                    plugged_blocks: None,
+                },
+            ],
+            data_ranges: None,
+            page_index: None,
+        };
+        #[rustfmt::skip]
+        let memory_state_fixture = [
+            // Number of regions.
+            2, 0, 0, 0, 0, 0, 0, 0,
+            // First region.
+            0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0x10, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0,
+            // Second region.
+            0, 0, 0, 0, 1, 0, 0, 0,
+            0, 0x20, 0, 0, 0, 0, 0, 0,
+            0, 0x10, 0, 0, 0, 0, 0, 0,
+        ];
+        // The plugged blocks are appended to each region from version 2 on.
+        let region_len = (memory_state_fixture.len() - 8) / 2;
+        let memory_state_v2_fixture = [
+            &memory_state_fixture[..8 + region_len],
+            &[0],
+            &memory_state_fixture[8 + region_len..],
+            &[0],
+        ]
+        .concat();
+
+        for version in supported_snapshot_versions() {
+            let mut buf = Vec::new();
+            vm_info.serialize(&mut buf, &VERSION_MAP, version).unwrap();
+            assert_eq!(buf, vm_info_fixture);
+            assert_eq!(
+                VmInfo::deserialize(&mut buf.as_slice(), &VERSION_MAP, version).unwrap(),
+                vm_info
+            );
+
+            let mut buf = Vec::new();
+            memory_state
+                .serialize(&mut buf, &VERSION_MAP, version)
+                .unwrap();
+            // The layout of the memory file and the page index are appended from version 2 on.
+            if version < 2 {
+                assert_eq!(buf, memory_state_fixture);
+            } else {
+                assert_eq!(buf, [&memory_state_v2_fixture[..], &[0, 0]].concat());
+            }
+            assert_eq!(
+                GuestMemoryState::deserialize(&mut buf.as_slice(), &VERSION_MAP, version).unwrap(),
+                memory_state
+            );
+        }
+    }
+
     #[test]
     fn test_create_snapshot_error_display() {
         use crate::persist::CreateSnapshotError::*;
         use vm_memory::GuestMemoryError;
 
+        let err = CompactFullSnapshot;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DiffDedupPages;
+        let _ = format!("{}{:?}", err, err);
+
         let err = DirtyBitmap;
         let _ = format!("{}{:?}", err, err);
 
@@ -488,23 +1511,50 @@ mod tests {
         let err = MicrovmState(MicrovmStateError::UnexpectedVcpuResponse);
         let _ = format!("{}{:?}", err, err);
 
+        let err = NestedVirtEnabled;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = PauseIoWorkers(WorkerError::Stopped);
+        let _ = format!("{}{:?}", err, err);
+
         let err = SerializeMicrovmState(snapshot::Error::InvalidMagic(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = SingleFileMemFile;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = SingleFileUnsupported;
+        let _ = format!("{}{:?}", err, err);
+
         let err = SnapshotBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
         let err = TooManyDevices(0);
         let _ = format!("{}{:?}", err, err);
+
+        #[cfg(feature = "vfio")]
+        {
+            let err = VfioDevices;
+            let _ = format!("{}{:?}", err, err);
+        }
     }
 
     #[test]
     fn test_load_snapshot_error_display() {
         use crate::persist::LoadSnapshotError::*;
 
+        let err = AdviseMemory(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = BuildMicroVm(StartMicrovmError::InitrdLoad);
         let _ = format!("{}{:?}", err, err);
 
+        let err = CloneDeviceNotFound(String::new());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = CloneEntropy(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
         let err = DeserializeMemory(memory_snapshot::Error::FileHandle(
             io::Error::from_raw_os_error(0),
         ));
@@ -513,19 +1563,318 @@ mod tests {
         let err = DeserializeMicrovmState(snapshot::Error::Io(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = InvalidIoThreads;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidSingleFileHeader(String::new());
+        let _ = format!("{}{:?}", err, err);
+
+        #[cfg(feature = "vsock")]
+        {
+            let err = InvalidVsockCid(0);
+            let _ = format!("{}{:?}", err, err);
+        }
+
         let err = MemoryBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        let err = MmdsData(MmdsError::NotInitialized);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = SingleFileMemFile;
+        let _ = format!("{}{:?}", err, err);
+
         let err = SnapshotBackingFile(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
         let err = SnapshotBackingFileMetadata(io::Error::from_raw_os_error(0));
         let _ = format!("{}{:?}", err, err);
 
+        #[cfg(feature = "balloon")]
+        {
+            let err = IncompatibleMemoryBackend;
+            let _ = format!("{}{:?}", err, err);
+        }
+
+        let err = IncompatibleMemoryHints;
+        let _ = format!("{}{:?}", err, err);
+
         let err = CpuVendorMismatch(String::new());
         let _ = format!("{}{:?}", err, err);
     }
 
+    #[test]
+    fn test_single_file_header() {
+        use std::io::Write;
+        use utils::tempfile::TempFile;
+
+        let header = SingleFileHeader {
+            mem_offset: SINGLE_FILE_MEM_OFFSET,
+            mem_len: 4096,
+            state_offset: SINGLE_FILE_MEM_OFFSET + 4096,
+            state_len: 100,
+        };
+        let snapshot_file = TempFile::new().unwrap();
+        let mut file = snapshot_file.as_file();
+        file.write_all(&header.to_bytes()).unwrap();
+        file.set_len(SINGLE_FILE_MEM_OFFSET + 4096 + 100).unwrap();
+        assert_eq!(SingleFileHeader::read(file).unwrap(), Some(header));
+
+        // The sections must be in the file.
+        file.set_len(SINGLE_FILE_MEM_OFFSET + 4096 + 99).unwrap();
+        assert!(matches!(
+            SingleFileHeader::read(file),
+            Err(LoadSnapshotError::InvalidSingleFileHeader(_))
+        ));
+
+        // The guest memory must be aligned.
+        let header = SingleFileHeader {
+            mem_offset: 100,
+            mem_len: 0,
+            state_offset: 0,
+            state_len: 0,
+        };
+        file.write_all_at(&header.to_bytes(), 0).unwrap();
+        assert!(matches!(
+            SingleFileHeader::read(file),
+            Err(LoadSnapshotError::InvalidSingleFileHeader(_))
+        ));
+
+        // Later versions of the format are not read.
+        file.write_all_at(&2u32.to_le_bytes(), 8).unwrap();
+        assert!(matches!(
+            SingleFileHeader::read(file),
+            Err(LoadSnapshotError::InvalidSingleFileHeader(_))
+        ));
+
+        // The state of a snapshot saved to separate files has no header.
+        let snapshot_file = TempFile::new().unwrap();
+        let mut buf = Vec::new();
+        Snapshot::new(VERSION_MAP.clone(), VERSION_MAP.latest_version())
+            .save(&mut buf, &VmInfo { mem_size_mib: 1 })
+            .unwrap();
+        snapshot_file.as_file().write_all(&buf).unwrap();
+        assert_eq!(
+            SingleFileHeader::read(snapshot_file.as_file()).unwrap(),
+            None
+        );
+        let empty_file = TempFile::new().unwrap();
+        assert_eq!(SingleFileHeader::read(empty_file.as_file()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_apply_clone_config() {
+        let mut event_manager = EventManager::new().expect("Cannot create EventManager");
+        let vmm = default_vmm_with_devices(&mut event_manager);
+        let mut states = vmm.mmio_device_manager.save();
+
+        let drive = CloneDriveConfig {
+            drive_id: "root".to_string(),
+            path_on_host: "/clone/rootfs".to_string(),
+        };
+        let iface = CloneNetConfig {
+            iface_id: "netif".to_string(),
+            host_dev_name: "clone_tap".to_string(),
+            guest_mac: Some(MacAddr::parse_str("12:34:56:78:9a:bc").unwrap()),
+        };
+        let clone = CloneConfig {
+            drives: vec![drive.clone()],
+            network_interfaces: vec![iface],
+            vsock: None,
+        };
+        apply_clone_config(&mut states, &clone).unwrap();
+
+        // The devices are looked up by the identifiers they were configured with.
+        let clone = CloneConfig {
+            drives: vec![CloneDriveConfig {
+                drive_id: "data".to_string(),
+                ..drive
+            }],
+            ..Default::default()
+        };
+        match apply_clone_config(&mut states, &clone) {
+            Err(LoadSnapshotError::CloneDeviceNotFound(id)) => assert_eq!(id, "data"),
+            _ => panic!("Unexpected result."),
+        }
+
+        #[cfg(feature = "vsock")]
+        {
+            let clone = CloneConfig {
+                vsock: Some(CloneVsockConfig {
+                    guest_cid: 42,
+                    uds_path: "/clone/vsock.sock".to_string(),
+                }),
+                ..Default::default()
+            };
+            apply_clone_config(&mut states, &clone).unwrap();
+            assert_eq!(
+                states
+                    .vsock_device
+                    .as_ref()
+                    .unwrap()
+                    .device_state
+                    .frontend
+                    .cid,
+                42
+            );
+
+            let clone = CloneConfig {
+                vsock: Some(CloneVsockConfig {
+                    guest_cid: 2,
+                    uds_path: "/clone/vsock.sock".to_string(),
+                }),
+                ..Default::default()
+            };
+            match apply_clone_config(&mut states, &clone) {
+                Err(LoadSnapshotError::InvalidVsockCid(2)) => (),
+                _ => panic!("Unexpected result."),
+            }
+        }
+    }
+
+    #[cfg(feature = "balloon")]
+    #[test]
+    fn test_restore_balloon_target() {
+        use crate::vmm_config::snapshot::LoadSnapshotParams;
+        use arch::DeviceType;
+        use devices::virtio::{Balloon, VirtioDevice, BALLOON_DEV_ID, TYPE_BALLOON};
+
+        let mut event_manager = EventManager::new().expect("Cannot create EventManager");
+        let vmm = default_vmm_with_devices(&mut event_manager);
+        let mem_size_mib = mem_size_mib(vmm.guest_memory());
+
+        // Loads the snapshot of `state`, overriding the balloon target size. The memory file
+        // does not exist, so a target size which passes the checks fails the load afterwards.
+        let load = |state: &MicrovmState, balloon_amount_mib, deflate_balloon| {
+            let snapshot_file = TempFile::new().unwrap();
+            snapshot_file
+                .as_file()
+                .write_all_at(&save_snapshot(state, VERSION_MAP.latest_version()), 0)
+                .unwrap();
+            let params = LoadSnapshotParams {
+                snapshot_path: snapshot_file.as_path().to_path_buf(),
+                mem_file_path: PathBuf::from("/inexistent/memory"),
+                mem_diff_paths: Vec::new(),
+                enable_diff_snapshots: false,
+                resume_vm: false,
+                balloon_amount_mib,
+                deflate_balloon,
+                mem_backend: None,
+                mem_hints: None,
+                io_threads: None,
+                mmds_data: None,
+                clone: None,
+            };
+            let mut event_manager = EventManager::new().expect("Cannot create EventManager");
+            match restore_from_snapshot(
+                &mut event_manager,
+                &[],
+                false,
+                &params,
+                VERSION_MAP.clone(),
+            ) {
+                Err(LoadSnapshotError::UpdateBalloon(err)) => Some(err),
+                Err(LoadSnapshotError::MemoryBackingFile(_)) => None,
+                Err(err) => panic!("Unexpected error: {}", err),
+                Ok(_) => panic!("Unexpected success"),
+            }
+        };
+        let state_fixture = |vmm: &Vmm| {
+            let mut state = microvm_state_fixture(vmm, VERSION_MAP.latest_version());
+            state.vm_info.mem_size_mib = mem_size_mib;
+            #[cfg(target_arch = "x86_64")]
+            {
+                state.vcpu_states[0].cpuid = vmm.vm.supported_cpuid().clone();
+            }
+            state
+        };
+
+        // The guest driver has not activated the balloon yet.
+        let state = state_fixture(&vmm);
+        assert!(load(&state, None, false).is_none());
+        assert!(matches!(
+            load(&state, Some(1), false),
+            Some(BalloonConfigError::DeviceNotActive)
+        ));
+        assert!(matches!(
+            load(&state, None, true),
+            Some(BalloonConfigError::DeviceNotActive)
+        ));
+
+        let busdev = vmm
+            .get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
+            .unwrap();
+        busdev
+            .lock()
+            .unwrap()
+            .virtio_transport()
+            .unwrap()
+            .device()
+            .lock()
+            .unwrap()
+            .as_mut_any()
+            .downcast_mut::<Balloon>()
+            .unwrap()
+            .activate(vmm.guest_memory().clone())
+            .unwrap();
+        let mut state = state_fixture(&vmm);
+        assert!(load(&state, Some(1), false).is_none());
+        assert!(load(&state, Some(mem_size_mib as u32), false).is_none());
+        assert!(load(&state, None, true).is_none());
+        // The balloon cannot be bigger than the guest memory.
+        assert!(matches!(
+            load(&state, Some(mem_size_mib as u32 + 1), false),
+            Some(BalloonConfigError::TooManyPagesRequested)
+        ));
+
+        // The snapshot has no balloon.
+        state.device_states.balloon_device = None;
+        assert!(matches!(
+            load(&state, Some(1), false),
+            Some(BalloonConfigError::DeviceNotFound)
+        ));
+        assert!(matches!(
+            load(&state, None, true),
+            Some(BalloonConfigError::DeviceNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_clone_entropy() {
+        let entropy = clone_entropy().unwrap();
+        assert_eq!(entropy.len(), 2 * CLONE_ENTROPY_LEN);
+        assert!(entropy.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(entropy, clone_entropy().unwrap());
+    }
+
+    #[test]
+    fn test_merge_mmds_data() {
+        let data = |json: &str| -> Map<String, Value> { serde_json::from_str(json).unwrap() };
+
+        // An uninitialized data store is initialized with the data.
+        let mut mmds = Mmds::default();
+        merge_mmds_data(&mut mmds, &data(r#"{"hostname": "clone-1"}"#)).unwrap();
+        assert_eq!(mmds.get_data_str(), r#"{"hostname":"clone-1"}"#);
+
+        // The data is merged into the existing data store.
+        let mut mmds = Mmds::default();
+        mmds.put_data(serde_json::from_str(r#"{"hostname": "base", "region": "eu"}"#).unwrap())
+            .unwrap();
+        merge_mmds_data(&mut mmds, &data(r#"{"hostname": "clone-2", "seed": 42}"#)).unwrap();
+        assert_eq!(
+            mmds.get_data_str(),
+            r#"{"hostname":"clone-2","region":"eu","seed":42}"#
+        );
+
+        // The data store limit still applies.
+        mmds.set_data_store_limit(64).unwrap();
+        let large = format!(r#"{{"blob": "{}"}}"#, "a".repeat(64));
+        match merge_mmds_data(&mut mmds, &data(&large)) {
+            Err(MmdsError::DataStoreLimitExceeded(64)) => (),
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn test_microvm_state_error_display() {
         use crate::persist::MicrovmStateError::*;
@@ -539,12 +1888,24 @@ mod tests {
         let err = RestoreDevices(DevicePersistError::MmioTransport);
         let _ = format!("{}{:?}", err, err);
 
+        let err = RestoreSerialPorts(LegacyDeviceError::InvalidSerialPort(String::from("com1")));
+        let _ = format!("{}{:?}", err, err);
+
+        #[cfg(feature = "tpm")]
+        {
+            let err = RestoreTpm(devices::tpm::Error::InvalidState);
+            let _ = format!("{}{:?}", err, err);
+        }
+
         let err = RestoreVcpuState(vstate::vcpu::Error::VcpuTlsInit);
         let _ = format!("{}{:?}", err, err);
 
         let err = RestoreVmState(vstate::vm::Error::NotEnoughMemorySlots);
         let _ = format!("{}{:?}", err, err);
 
+        let err = RestoreWatchdog(WatchdogError::Timer(io::Error::from_raw_os_error(0)));
+        let _ = format!("{}{:?}", err, err);
+
         let err = SaveVcpuState(vstate::vcpu::Error::VcpuTlsNotPresent);
         let _ = format!("{}{:?}", err, err);
 
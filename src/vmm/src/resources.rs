@@ -3,74 +3,438 @@
 
 #![deny(warnings)]
 
+#[cfg(feature = "net")]
+use std::collections::HashSet;
+use std::fmt;
 use std::fs::File;
+#[cfg(target_arch = "x86_64")]
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::sync::Arc;
 
+use crate::landlock::AllowedPath;
+
+#[cfg(feature = "balloon")]
 use crate::vmm_config::balloon::*;
 use crate::vmm_config::boot_source::{
     BootConfig, BootSourceConfig, BootSourceConfigError, DEFAULT_KERNEL_CMDLINE,
 };
+#[cfg(feature = "virtio-console")]
+use crate::vmm_config::console::*;
+use crate::vmm_config::cpu_quota::CpuQuotaConfig;
+#[cfg(feature = "block")]
 use crate::vmm_config::drive::*;
-use crate::vmm_config::instance_info::InstanceInfo;
+#[cfg(feature = "virtio-rng")]
+use crate::vmm_config::entropy::*;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::guest_clock::ClockPolicy;
+use crate::vmm_config::guest_panic::PanicAction;
+use crate::vmm_config::instance_info::{InstanceInfo, InstanceState};
 use crate::vmm_config::logger::{init_logger, LoggerConfig, LoggerConfigError};
-use crate::vmm_config::machine_config::{VmConfig, VmConfigError, DEFAULT_MEM_SIZE_MIB};
+use crate::vmm_config::machine_config::{
+    nested_virt_supported, MemoryBackend, MemoryHints, VirtioTransport, VmConfig, VmConfigError,
+    DEFAULT_MEM_SIZE_MIB, MAX_IO_THREADS, MAX_SUPPORTED_VCPUS,
+};
+#[cfg(feature = "virtio-mem")]
+use crate::vmm_config::memory_hotplug::{MemoryHotplugConfig, MemoryHotplugConfigError};
 use crate::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
+#[cfg(feature = "net")]
 use crate::vmm_config::net::*;
+#[cfg(feature = "null-devices")]
+use crate::vmm_config::null_device::*;
+#[cfg(feature = "virtio-pmem")]
+use crate::vmm_config::pmem::*;
+use crate::vmm_config::rate_limiter_group::{
+    RateLimiterGroupConfig, RateLimiterGroupError, RateLimiterGroups,
+};
+use crate::vmm_config::serial::{SerialConfigError, SerialPortConfig, SerialPortsBuilder};
+#[cfg(feature = "virtio-fs")]
+use crate::vmm_config::shared_fs::*;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::shutdown_behavior::ShutdownBehaviorConfig;
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+use crate::vmm_config::tpm::TpmConfig;
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use crate::vmm_config::vfio::*;
+#[cfg(feature = "vsock")]
 use crate::vmm_config::vsock::*;
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::watchdog::WatchdogConfig;
 use crate::vstate::vcpu::VcpuConfig;
+#[cfg(target_arch = "x86_64")]
+use cpuid::custom::CustomCpuTemplate;
+#[cfg(feature = "net")]
+use devices::virtio::Net;
+use mmds::data_store::DEFAULT_DATA_STORE_LIMIT;
+use mmds::dynamic::ValueProvider;
+#[cfg(feature = "net")]
 use mmds::ns::MmdsNetworkStack;
+use mmds::MMDS;
 use utils::net::ipv4addr::is_link_local_valid;
+use utils::net::ipv6addr::is_link_local_or_unique_local;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 type Result<E> = std::result::Result<(), E>;
 
+// The lowest guest CID, the lower ones being reserved for the hypervisor and the host.
+#[cfg(feature = "vsock")]
+pub(crate) const MIN_GUEST_CID: u32 = 3;
+
 /// Errors encountered when configuring microVM resources.
 #[derive(Debug)]
 pub enum Error {
     /// Balloon device configuration error.
+    #[cfg(feature = "balloon")]
     BalloonDevice(BalloonConfigError),
     /// Block device configuration error.
+    #[cfg(feature = "block")]
     BlockDevice(DriveError),
     /// Boot source configuration error.
     BootSource(BootSourceConfigError),
+    /// Console device configuration error.
+    #[cfg(feature = "virtio-console")]
+    Console(ConsoleConfigError),
+    /// Entropy device configuration error.
+    #[cfg(feature = "virtio-rng")]
+    EntropyDevice(EntropyConfigError),
+    /// The guest memory, in MiB, is larger than the host memory, in MiB.
+    HostMemoryExceeded(u64, u64),
+    /// The initrd images, in bytes, are larger than the guest memory they are loaded in.
+    InitrdTooLarge(u64, u64),
     /// JSON is invalid.
-    InvalidJson,
+    InvalidJson(String),
+    /// The kernel image cannot be loaded.
+    InvalidKernel(kernel::loader::Error),
+    /// The vsock guest CID is reserved.
+    #[cfg(feature = "vsock")]
+    InvalidVsockCid(u32),
     /// Logger configuration error.
     Logger(LoggerConfigError),
+    /// Hotplug memory configuration error.
+    #[cfg(feature = "virtio-mem")]
+    MemoryHotplug(MemoryHotplugConfigError),
     /// Metrics system configuration error.
     Metrics(MetricsConfigError),
     /// MMDS configuration error.
     MmdsConfig(MmdsConfigError),
     /// Net device configuration error.
+    #[cfg(feature = "net")]
     NetDevice(NetworkInterfaceError),
+    /// The logger or the metrics are part of a configuration applied through the API.
+    NotMicrovmConfig,
+    /// Null device configuration error.
+    #[cfg(feature = "null-devices")]
+    NullDevice(NullDeviceConfigError),
+    /// Persistent memory device configuration error.
+    #[cfg(feature = "virtio-pmem")]
+    Pmem(PmemConfigError),
+    /// Rate limiter group configuration error.
+    RateLimiterGroup(RateLimiterGroupError),
+    /// Serial port configuration error.
+    SerialPort(SerialConfigError),
+    /// Shared filesystem configuration error.
+    #[cfg(feature = "virtio-fs")]
+    SharedFs(SharedFsConfigError),
+    /// Several network interfaces use the same TAP device.
+    #[cfg(feature = "net")]
+    TapDeviceInUse(String),
+    /// Host PCI function configuration error.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    Vfio(VfioConfigError),
     /// microVM vCpus or memory configuration error.
     VmConfig(VmConfigError),
     /// Vsock device configuration error.
+    #[cfg(feature = "vsock")]
     VsockDevice(VsockConfigError),
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match self {
+            #[cfg(feature = "balloon")]
+            BalloonDevice(err) => write!(f, "Balloon device configuration error: {}", err),
+            #[cfg(feature = "block")]
+            BlockDevice(err) => write!(f, "Block device configuration error: {}", err),
+            BootSource(err) => write!(f, "Boot source configuration error: {}", err),
+            #[cfg(feature = "virtio-console")]
+            Console(err) => write!(f, "Console device configuration error: {}", err),
+            #[cfg(feature = "virtio-rng")]
+            EntropyDevice(err) => write!(f, "Entropy device configuration error: {}", err),
+            HostMemoryExceeded(guest_mib, host_mib) => write!(
+                f,
+                "The guest memory of {} MiB is larger than the host memory of {} MiB.",
+                guest_mib, host_mib
+            ),
+            InitrdTooLarge(initrd_size, mem_size) => write!(
+                f,
+                "The initrd images take {} bytes, more than the {} bytes of guest memory they \
+                 are loaded in.",
+                initrd_size, mem_size
+            ),
+            InvalidJson(err) => write!(f, "Invalid JSON configuration: {}", err),
+            InvalidKernel(err) => write!(f, "The kernel image cannot be loaded: {}", err),
+            #[cfg(feature = "vsock")]
+            InvalidVsockCid(cid) => write!(
+                f,
+                "The guest CID {} is reserved, it must be at least {}.",
+                cid, MIN_GUEST_CID
+            ),
+            Logger(err) => write!(f, "Logger configuration error: {}", err),
+            #[cfg(feature = "virtio-mem")]
+            MemoryHotplug(err) => write!(f, "Hotplug memory configuration error: {}", err),
+            Metrics(err) => write!(f, "Metrics system configuration error: {}", err),
+            MmdsConfig(err) => write!(f, "MMDS configuration error: {}", err),
+            #[cfg(feature = "net")]
+            NetDevice(err) => write!(f, "Net device configuration error: {}", err),
+            NotMicrovmConfig => write!(
+                f,
+                "The logger and the metrics must be configured with their own API requests."
+            ),
+            #[cfg(feature = "null-devices")]
+            NullDevice(err) => write!(f, "Null device configuration error: {}", err),
+            #[cfg(feature = "virtio-pmem")]
+            Pmem(err) => write!(f, "Persistent memory device configuration error: {}", err),
+            RateLimiterGroup(err) => write!(f, "Rate limiter group configuration error: {}", err),
+            SerialPort(err) => write!(f, "Serial port configuration error: {}", err),
+            #[cfg(feature = "virtio-fs")]
+            SharedFs(err) => write!(f, "Shared filesystem configuration error: {}", err),
+            #[cfg(feature = "net")]
+            TapDeviceInUse(name) => write!(
+                f,
+                "The TAP device {} is used by several network interfaces.",
+                name
+            ),
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            Vfio(err) => write!(f, "Host PCI function configuration error: {}", err),
+            VmConfig(err) => write!(f, "Machine configuration error: {}", err),
+            #[cfg(feature = "vsock")]
+            VsockDevice(err) => write!(f, "Vsock device configuration error: {}", err),
+        }
+    }
+}
+
 /// Used for configuring a vmm from one single json passed to the Firecracker process.
-#[derive(Deserialize)]
+#[derive(Deserialize, PartialEq)]
 pub struct VmmConfig {
+    #[cfg(feature = "balloon")]
     #[serde(rename = "balloon")]
     balloon_device: Option<BalloonDeviceConfig>,
+    #[cfg(feature = "block")]
     #[serde(rename = "drives")]
     block_devices: Vec<BlockDeviceConfig>,
     #[serde(rename = "boot-source")]
     boot_source: BootSourceConfig,
+    #[cfg(feature = "virtio-console")]
+    #[serde(rename = "console")]
+    console_device: Option<ConsoleConfig>,
+    #[cfg(feature = "virtio-rng")]
+    #[serde(rename = "entropy")]
+    entropy_device: Option<EntropyDeviceConfig>,
     #[serde(rename = "logger")]
     logger: Option<LoggerConfig>,
     #[serde(rename = "machine-config")]
     machine_config: Option<VmConfig>,
+    #[cfg(feature = "virtio-mem")]
+    #[serde(rename = "memory-hotplug")]
+    memory_hotplug: Option<MemoryHotplugConfig>,
     #[serde(rename = "metrics")]
     metrics: Option<MetricsConfig>,
     #[serde(rename = "mmds-config")]
     mmds_config: Option<MmdsConfig>,
+    #[cfg(feature = "net")]
     #[serde(rename = "network-interfaces", default)]
     net_devices: Vec<NetworkInterfaceConfig>,
+    #[cfg(feature = "null-devices")]
+    #[serde(rename = "null-devices", default)]
+    null_devices: Vec<NullDeviceConfig>,
+    #[cfg(feature = "virtio-pmem")]
+    #[serde(rename = "pmem", default)]
+    pmem_devices: Vec<PmemConfig>,
+    #[serde(rename = "rate-limiter-groups", default)]
+    rate_limiter_groups: Vec<RateLimiterGroupConfig>,
+    #[serde(rename = "serial-ports", default)]
+    serial_ports: Vec<SerialPortConfig>,
+    #[cfg(feature = "virtio-fs")]
+    #[serde(rename = "shared-fs", default)]
+    shared_fs_devices: Vec<SharedFsConfig>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(rename = "shutdown-behavior")]
+    shutdown_behavior: Option<ShutdownBehaviorConfig>,
+    #[serde(rename = "start-paused", default)]
+    start_paused: bool,
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    #[serde(rename = "tpm")]
+    tpm: Option<TpmConfig>,
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    #[serde(rename = "vfio", default)]
+    vfio_devices: Vec<VfioConfig>,
+    #[cfg(feature = "vsock")]
     #[serde(rename = "vsock")]
     vsock_device: Option<VsockDeviceConfig>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(rename = "watchdog")]
+    watchdog: Option<WatchdogConfig>,
+}
+
+impl VmmConfig {
+    /// Checks that a microVM could be configured and booted from this configuration, without
+    /// creating its devices nor touching the current configuration. The kernel and the drives
+    /// are checked for access, the kernel image for its format, the network interfaces and the
+    /// vsock device for address conflicts, and the machine configuration against the host.
+    pub fn validate(self) -> Result<Error> {
+        if self.logger.is_some() || self.metrics.is_some() {
+            return Err(Error::NotMicrovmConfig);
+        }
+
+        // The scratch resources are only used for their validations, then dropped.
+        let mut resources = VmResources::default();
+        if let Some(machine_config) = self.machine_config.as_ref() {
+            resources
+                .set_vm_config(machine_config)
+                .map_err(Error::VmConfig)?;
+        }
+        #[allow(unused_mut)]
+        let mut mem_size_mib = resources
+            .vm_config()
+            .mem_size_mib
+            .unwrap_or(DEFAULT_MEM_SIZE_MIB) as u64;
+        #[cfg(feature = "virtio-mem")]
+        if let Some(memory_hotplug) = self.memory_hotplug.as_ref() {
+            memory_hotplug.validate().map_err(Error::MemoryHotplug)?;
+            mem_size_mib += memory_hotplug.total_size_mib;
+        }
+        let host_mem_size_mib = host_memory_mib();
+        if mem_size_mib > host_mem_size_mib {
+            return Err(Error::HostMemoryExceeded(mem_size_mib, host_mem_size_mib));
+        }
+
+        resources
+            .set_boot_source(self.boot_source)
+            .map_err(Error::BootSource)?;
+        if let Some(mut kernel_file) = resources
+            .boot_source()
+            .and_then(|boot_config| boot_config.kernel_file.as_ref())
+        {
+            kernel::loader::check_kernel(&mut kernel_file, arch::get_kernel_start())
+                .map_err(Error::InvalidKernel)?;
+        }
+        // The initrd images are loaded together in the first region of the boot memory.
+        if let Some(boot_config) = resources.boot_source() {
+            let initrd_size = boot_config
+                .initrd_size()
+                .map_err(BootSourceConfigError::InvalidInitrdPath)
+                .map_err(Error::BootSource)?;
+            let boot_mem_size = resources
+                .vm_config()
+                .mem_size_mib
+                .unwrap_or(DEFAULT_MEM_SIZE_MIB)
+                << 20;
+            let lowmem_size =
+                arch::arch_memory_regions(boot_mem_size, &resources.mmio_layout())[0].1 as u64;
+            if initrd_size > lowmem_size {
+                return Err(Error::InitrdTooLarge(initrd_size, lowmem_size));
+            }
+        }
+
+        // The groups are set first, so that the devices can be attached to them.
+        for group_config in self.rate_limiter_groups.into_iter() {
+            resources
+                .set_rate_limiter_group(group_config)
+                .map_err(Error::RateLimiterGroup)?;
+        }
+
+        #[cfg(feature = "block")]
+        for drive_config in self.block_devices.into_iter() {
+            resources
+                .set_block_device(drive_config)
+                .map_err(Error::BlockDevice)?;
+        }
+
+        // The TAP devices are not opened, since opening them may create them.
+        #[cfg(feature = "net")]
+        {
+            let mut tap_names = HashSet::new();
+            let mut guest_macs = HashSet::new();
+            for net_config in self.net_devices.iter() {
+                if let Some(group_id) = net_config.rate_limiter_group.as_ref() {
+                    if resources.rate_limiter_groups.get(group_id).is_none() {
+                        return Err(Error::NetDevice(
+                            NetworkInterfaceError::RateLimiterGroupNotFound(group_id.clone()),
+                        ));
+                    }
+                }
+                if !tap_names.insert(net_config.host_dev_name.as_str()) {
+                    return Err(Error::TapDeviceInUse(net_config.host_dev_name.clone()));
+                }
+                if let Some(guest_mac) = net_config.guest_mac.as_ref() {
+                    if !guest_macs.insert(guest_mac.to_string()) {
+                        return Err(Error::NetDevice(
+                            NetworkInterfaceError::GuestMacAddressInUse(guest_mac.to_string()),
+                        ));
+                    }
+                }
+            }
+        }
+
+        // The vsock sockets are not bound, since binding them cannot be undone by dropping them.
+        #[cfg(feature = "vsock")]
+        if let Some(vsock_config) = self.vsock_device.as_ref() {
+            if vsock_config.guest_cid < MIN_GUEST_CID {
+                return Err(Error::InvalidVsockCid(vsock_config.guest_cid));
+            }
+        }
+
+        #[cfg(feature = "balloon")]
+        if let Some(balloon_config) = self.balloon_device {
+            resources
+                .set_balloon_device(balloon_config)
+                .map_err(Error::BalloonDevice)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The size of the host memory, in MiB.
+pub(crate) fn host_memory_mib() -> u64 {
+    // Safe because `sysconf` has no side effects.
+    let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) };
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if pages <= 0 || page_size <= 0 {
+        // The limit cannot be checked.
+        return u64::MAX;
+    }
+    (pages as u64 * page_size as u64) >> 20
+}
+
+/// The effective configuration of the microVM, assembled from the `VmResources`. It uses the
+/// layout of `VmmConfig`, so it can be used as a configuration file.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct FullVmConfig {
+    #[cfg(feature = "balloon")]
+    #[serde(rename = "balloon")]
+    pub(crate) balloon_device: Option<BalloonDeviceConfig>,
+    #[cfg(feature = "block")]
+    #[serde(rename = "drives")]
+    pub(crate) block_devices: Vec<BlockDeviceConfig>,
+    #[serde(rename = "boot-source")]
+    pub(crate) boot_source: Option<BootSourceConfig>,
+    #[serde(rename = "machine-config")]
+    pub(crate) machine_config: VmConfig,
+    #[serde(rename = "mmds-config")]
+    pub(crate) mmds_config: Option<MmdsConfig>,
+    #[cfg(feature = "net")]
+    #[serde(rename = "network-interfaces")]
+    pub(crate) net_devices: Vec<NetworkInterfaceConfig>,
+    #[serde(rename = "rate-limiter-groups")]
+    pub(crate) rate_limiter_groups: Vec<RateLimiterGroupConfig>,
+    pub(crate) state: InstanceState,
+    #[cfg(feature = "vsock")]
+    #[serde(rename = "vsock")]
+    pub(crate) vsock_device: Option<VsockDeviceConfig>,
 }
 
 /// A data structure that encapsulates the device configurations
@@ -82,17 +446,75 @@ pub struct VmResources {
     /// The boot configuration for this microVM.
     boot_config: Option<BootConfig>,
     /// The block devices.
+    #[cfg(feature = "block")]
     pub block: BlockBuilder,
     /// The vsock device.
+    #[cfg(feature = "vsock")]
     pub vsock: VsockBuilder,
     /// The balloon device.
+    #[cfg(feature = "balloon")]
     pub balloon: BalloonBuilder,
+    /// The entropy device.
+    #[cfg(feature = "virtio-rng")]
+    pub entropy: EntropyDeviceBuilder,
+    /// The console device.
+    #[cfg(feature = "virtio-console")]
+    pub console: ConsoleBuilder,
     /// The network devices builder.
+    #[cfg(feature = "net")]
     pub net_builder: NetBuilder,
+    /// The rate limiter groups the drives and network interfaces can be attached to.
+    pub rate_limiter_groups: RateLimiterGroups,
+    /// The hotplug memory configuration.
+    #[cfg(feature = "virtio-mem")]
+    pub memory_hotplug: Option<MemoryHotplugConfig>,
+    /// The null devices builder.
+    #[cfg(feature = "null-devices")]
+    pub null_devices: NullDeviceBuilder,
+    /// The persistent memory devices builder.
+    #[cfg(feature = "virtio-pmem")]
+    pub pmem: PmemBuilder,
+    /// The serial ports bound to a host backend.
+    pub serial_ports: SerialPortsBuilder,
+    /// The shared filesystems builder.
+    #[cfg(feature = "virtio-fs")]
+    pub shared_fs: SharedFsBuilder,
     /// The configuration for `MmdsNetworkStack`.
     pub mmds_config: Option<MmdsConfig>,
     /// Whether or not to load boot timer device.
     pub boot_timer: bool,
+    /// Whether the vCPUs stay paused once the microVM is built, until it is resumed.
+    pub start_paused: bool,
+    /// The socket on which the debugger of the guest kernel connects.
+    pub gdb_socket: Option<PathBuf>,
+    /// The paths the microVM keeps access to under Landlock, besides the ones of the
+    /// configured resources. The filesystem access is not restricted when `None`.
+    pub landlock: Option<Vec<PathBuf>>,
+    /// Whether the seccomp filters allow the VMM thread to build another microVM, once this one
+    /// is torn down.
+    pub allow_teardown: bool,
+    /// The action taken when the guest kernel panics.
+    pub panic_action: PanicAction,
+    /// How the guest clock is kept right when the microVM resumes.
+    #[cfg(target_arch = "x86_64")]
+    pub clock_policy: ClockPolicy,
+    /// The share of a host core each vCPU thread may use.
+    pub cpu_quota: CpuQuotaConfig,
+    /// What is done with the microVM before exiting on `SIGTERM`.
+    #[cfg(target_arch = "x86_64")]
+    pub shutdown_behavior: ShutdownBehaviorConfig,
+    /// The watchdog configuration.
+    #[cfg(target_arch = "x86_64")]
+    pub watchdog: Option<WatchdogConfig>,
+    /// The TPM configuration.
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    pub tpm: Option<TpmConfig>,
+    /// The host PCI functions passed through.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    pub vfio: VfioBuilder,
+    /// The custom CPU template, loaded from the file at `cpu_template_path`.
+    #[cfg(target_arch = "x86_64")]
+    custom_cpu_template: Option<CustomCpuTemplate>,
 }
 
 impl VmResources {
@@ -101,18 +523,48 @@ impl VmResources {
         config_json: &str,
         instance_info: &InstanceInfo,
     ) -> std::result::Result<Self, Error> {
-        let vmm_config: VmmConfig = serde_json::from_slice::<VmmConfig>(config_json.as_bytes())
-            .map_err(|_| Error::InvalidJson)?;
+        let mut vmm_config: VmmConfig = serde_json::from_slice::<VmmConfig>(config_json.as_bytes())
+            .map_err(|err| Error::InvalidJson(err.to_string()))?;
 
-        if let Some(logger) = vmm_config.logger {
+        if let Some(logger) = vmm_config.logger.take() {
             init_logger(logger, instance_info).map_err(Error::Logger)?;
         }
 
-        if let Some(metrics) = vmm_config.metrics {
+        if let Some(metrics) = vmm_config.metrics.take() {
             init_metrics(metrics).map_err(Error::Metrics)?;
         }
 
+        Self::from_vmm_config(vmm_config)
+    }
+
+    /// Replaces the configuration of the microVM with the one described by `vmm_config`.
+    /// The resources are all validated before being applied, so they are left untouched on
+    /// failure. The logger and the metrics are not part of the microVM, so `vmm_config` cannot
+    /// configure them.
+    pub fn apply_vmm_config(&mut self, vmm_config: VmmConfig) -> Result<Error> {
+        if vmm_config.logger.is_some() || vmm_config.metrics.is_some() {
+            return Err(Error::NotMicrovmConfig);
+        }
+
+        let mut resources = Self::from_vmm_config(vmm_config)?;
+        resources.boot_timer = self.boot_timer;
+        resources.gdb_socket = self.gdb_socket.clone();
+        resources.landlock = self.landlock.clone();
+        resources.allow_teardown = self.allow_teardown;
+        resources.panic_action = self.panic_action.clone();
+        #[cfg(target_arch = "x86_64")]
+        {
+            resources.clock_policy = self.clock_policy;
+        }
+        resources.cpu_quota = self.cpu_quota;
+        *self = resources;
+        Ok(())
+    }
+
+    // Builds the resources described by `vmm_config`, ignoring the logger and the metrics.
+    fn from_vmm_config(vmm_config: VmmConfig) -> std::result::Result<Self, Error> {
         let mut resources: Self = Self::default();
+        resources.start_paused = vmm_config.start_paused;
         if let Some(machine_config) = vmm_config.machine_config {
             resources
                 .set_vm_config(&machine_config)
@@ -123,60 +575,225 @@ impl VmResources {
             .set_boot_source(vmm_config.boot_source)
             .map_err(Error::BootSource)?;
 
+        // The groups are set first, so that the devices can be attached to them.
+        for group_config in vmm_config.rate_limiter_groups.into_iter() {
+            resources
+                .set_rate_limiter_group(group_config)
+                .map_err(Error::RateLimiterGroup)?;
+        }
+
+        #[cfg(feature = "block")]
         for drive_config in vmm_config.block_devices.into_iter() {
             resources
                 .set_block_device(drive_config)
                 .map_err(Error::BlockDevice)?;
         }
 
+        #[cfg(feature = "net")]
         for net_config in vmm_config.net_devices.into_iter() {
             resources
                 .build_net_device(net_config)
                 .map_err(Error::NetDevice)?;
         }
 
+        #[cfg(feature = "null-devices")]
+        for null_device_config in vmm_config.null_devices.into_iter() {
+            resources
+                .set_null_device(null_device_config)
+                .map_err(Error::NullDevice)?;
+        }
+
+        #[cfg(feature = "virtio-pmem")]
+        for pmem_config in vmm_config.pmem_devices.into_iter() {
+            resources
+                .set_pmem_device(pmem_config)
+                .map_err(Error::Pmem)?;
+        }
+
+        for serial_port_config in vmm_config.serial_ports.into_iter() {
+            resources
+                .set_serial_port(serial_port_config)
+                .map_err(Error::SerialPort)?;
+        }
+
+        #[cfg(feature = "virtio-fs")]
+        for shared_fs_config in vmm_config.shared_fs_devices.into_iter() {
+            resources
+                .set_shared_fs(shared_fs_config)
+                .map_err(Error::SharedFs)?;
+        }
+
+        #[cfg(feature = "vsock")]
         if let Some(vsock_config) = vmm_config.vsock_device {
             resources
                 .set_vsock_device(vsock_config)
                 .map_err(Error::VsockDevice)?;
         }
 
+        #[cfg(feature = "balloon")]
         if let Some(balloon_config) = vmm_config.balloon_device {
             resources
                 .set_balloon_device(balloon_config)
                 .map_err(Error::BalloonDevice)?;
         }
 
+        #[cfg(feature = "virtio-rng")]
+        if let Some(entropy_config) = vmm_config.entropy_device {
+            resources
+                .set_entropy_device(entropy_config)
+                .map_err(Error::EntropyDevice)?;
+        }
+
+        #[cfg(feature = "virtio-console")]
+        if let Some(console_config) = vmm_config.console_device {
+            resources
+                .set_console_device(console_config)
+                .map_err(Error::Console)?;
+        }
+
+        #[cfg(feature = "virtio-mem")]
+        if let Some(memory_hotplug_config) = vmm_config.memory_hotplug {
+            resources
+                .set_memory_hotplug(memory_hotplug_config)
+                .map_err(Error::MemoryHotplug)?;
+        }
+
         if let Some(mmds_config) = vmm_config.mmds_config {
             resources
                 .set_mmds_config(mmds_config)
                 .map_err(Error::MmdsConfig)?;
         }
 
+        #[cfg(target_arch = "x86_64")]
+        if let Some(shutdown_behavior) = vmm_config.shutdown_behavior {
+            resources.set_shutdown_behavior(shutdown_behavior);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(watchdog_config) = vmm_config.watchdog {
+            resources.set_watchdog(watchdog_config);
+        }
+
+        #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+        if let Some(tpm_config) = vmm_config.tpm {
+            resources.set_tpm(tpm_config);
+        }
+
+        #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+        for vfio_config in vmm_config.vfio_devices.into_iter() {
+            resources
+                .set_vfio_device(vfio_config)
+                .map_err(Error::Vfio)?;
+        }
+
         Ok(resources)
     }
 
+    /// Returns the effective configuration of the microVM, which is in the given `state`.
+    pub fn full_config(&self, state: InstanceState) -> FullVmConfig {
+        FullVmConfig {
+            #[cfg(feature = "balloon")]
+            balloon_device: self.balloon.get_config().ok(),
+            #[cfg(feature = "block")]
+            block_devices: self.block.configs(),
+            boot_source: self
+                .boot_config
+                .as_ref()
+                .map(|boot_config| boot_config.description.clone()),
+            machine_config: self.vm_config.clone(),
+            mmds_config: self.mmds_config.clone(),
+            #[cfg(feature = "net")]
+            net_devices: self.net_builder.configs(),
+            rate_limiter_groups: self.rate_limiter_groups.configs(),
+            state,
+            #[cfg(feature = "vsock")]
+            vsock_device: self.vsock.config(),
+        }
+    }
+
     /// Returns a VcpuConfig based on the vm config.
     pub fn vcpu_config(&self) -> VcpuConfig {
         // The unwraps are ok to use because the values are initialized using defaults if not
         // supplied by the user.
+        let vcpu_count = self.vm_config().vcpu_count.unwrap();
+        // On x86_64, the vcpus which can be hotplugged are created at boot too.
+        #[cfg(target_arch = "x86_64")]
+        let vcpu_count = self.vm_config().max_vcpu_count.unwrap_or(vcpu_count);
         VcpuConfig {
-            vcpu_count: self.vm_config().vcpu_count.unwrap(),
+            vcpu_count,
             ht_enabled: self.vm_config().ht_enabled.unwrap(),
             cpu_template: self.vm_config().cpu_template,
+            cpu_topology: self.vm_config().cpu_topology,
+            nested_virt_enabled: self.vm_config().nested_virt_enabled.unwrap_or(false),
+            msr_policy: self.vm_config().msr_policy.clone(),
+            #[cfg(target_arch = "x86_64")]
+            custom_cpu_template: self.custom_cpu_template.clone(),
+            cpu_features: self.vm_config().cpu_features.clone(),
+            cpuid_normalization_enabled: self
+                .vm_config()
+                .cpuid_normalization_enabled
+                .unwrap_or(false),
         }
     }
 
+    /// Returns the kind of host memory backing the guest memory.
+    pub fn memory_backend(&self) -> MemoryBackend {
+        self.vm_config().mem_backend.unwrap_or_default()
+    }
+
+    /// Returns the hints given to the host kernel about the guest memory.
+    pub fn memory_hints(&self) -> MemoryHints {
+        self.vm_config().mem_hints.unwrap_or_default()
+    }
+
+    /// Returns the number of threads processing the I/O of the block and network devices.
+    pub fn io_threads(&self) -> u8 {
+        self.vm_config().io_threads.unwrap_or(0)
+    }
+
+    /// Returns the layout of the memory gap holding the MMIO devices.
+    pub fn mmio_layout(&self) -> arch::MmioLayout {
+        // The layout is validated when it is set.
+        self.vm_config()
+            .mmio_layout
+            .and_then(|config| config.layout().ok())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether the virtio devices sit on a PCI root bus rather than on the MMIO bus.
+    pub fn pci_enabled(&self) -> bool {
+        self.vm_config().virtio_transport == Some(VirtioTransport::Pci)
+    }
+
     /// Returns whether dirty page tracking is enabled or not.
     pub fn track_dirty_pages(&self) -> bool {
         self.vm_config().track_dirty_pages
     }
 
+    /// Returns whether the guest memory must be shared with other processes, which is the
+    /// case when the vhost-user backends of shared filesystems access it.
+    #[cfg(feature = "virtio-fs")]
+    pub fn shared_guest_memory(&self) -> bool {
+        self.shared_fs.iter().next().is_some()
+    }
+
+    /// Returns whether the guest memory must be shared with other processes.
+    #[cfg(not(feature = "virtio-fs"))]
+    pub fn shared_guest_memory(&self) -> bool {
+        false
+    }
+
     /// Returns the VmConfig.
     pub fn vm_config(&self) -> &VmConfig {
         &self.vm_config
     }
 
+    /// Sets the number of vCPUs the guest uses, once they were plugged in the running microVM.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_vcpu_count(&mut self, vcpu_count: u8) {
+        self.vm_config.vcpu_count = Some(vcpu_count);
+    }
+
     /// Set the machine configuration of the microVM.
     pub fn set_vm_config(&mut self, machine_config: &VmConfig) -> Result<VmConfigError> {
         if machine_config.vcpu_count == Some(0) {
@@ -189,6 +806,7 @@ impl VmResources {
 
         // The VM cannot have a memory size greater than the target size
         // of the balloon device, if present.
+        #[cfg(feature = "balloon")]
         if self.balloon.get().is_some()
             && machine_config
                 .mem_size_mib
@@ -203,23 +821,136 @@ impl VmResources {
             return Err(VmConfigError::IncompatibleBalloonSize);
         }
 
+        let mem_backend = machine_config
+            .mem_backend
+            .or(self.vm_config.mem_backend)
+            .unwrap_or_default();
+        if let Some(huge_page_size_mib) = mem_backend.huge_page_size_mib() {
+            let mem_size_mib = machine_config
+                .mem_size_mib
+                .or(self.vm_config.mem_size_mib)
+                .unwrap_or(DEFAULT_MEM_SIZE_MIB);
+            if mem_size_mib % huge_page_size_mib != 0 {
+                return Err(VmConfigError::UnalignedMemorySize);
+            }
+            // The balloon device gives back pages smaller than the huge pages.
+            #[cfg(feature = "balloon")]
+            if self.balloon.get().is_some() {
+                return Err(VmConfigError::IncompatibleMemoryBackend);
+            }
+            // The huge pages of hugetlbfs are neither merged nor split by the host kernel.
+            let mem_hints = machine_config
+                .mem_hints
+                .or(self.vm_config.mem_hints)
+                .unwrap_or_default();
+            if !mem_hints.advices().is_empty() {
+                return Err(VmConfigError::IncompatibleMemoryHints);
+            }
+        }
+
+        if machine_config.nested_virt_enabled == Some(true) && !nested_virt_supported() {
+            return Err(VmConfigError::NestedVirtUnsupported);
+        }
+
+        if let Some(msr_policy) = machine_config.msr_policy.as_ref() {
+            msr_policy.validate()?;
+        }
+
+        if let Some(cpu_features) = machine_config.cpu_features.as_ref() {
+            cpu_features.validate()?;
+        }
+
+        if let Some(mmio_layout) = machine_config.mmio_layout {
+            mmio_layout.layout()?;
+        }
+
+        if machine_config.io_threads > Some(MAX_IO_THREADS) {
+            return Err(VmConfigError::InvalidIoThreads);
+        }
+
+        // A disabled PIT has no reinjection policy to apply.
+        let pit_enabled = machine_config.pit_enabled.or(self.vm_config.pit_enabled);
+        let pit_reinject_policy = machine_config
+            .pit_reinject_policy
+            .or(self.vm_config.pit_reinject_policy);
+        if pit_enabled == Some(false) && pit_reinject_policy.is_some() {
+            return Err(VmConfigError::IncompatiblePitPolicy);
+        }
+
+        // The template is loaded now, so that a wrong file fails this request rather than the boot.
+        #[cfg(target_arch = "x86_64")]
+        let custom_cpu_template = match machine_config.cpu_template_path.as_ref() {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|e| format!("cannot read {}: {}", path, e))
+                    .and_then(|json| CustomCpuTemplate::from_json(&json).map_err(|e| e.to_string()))
+                    .map_err(VmConfigError::InvalidCustomCpuTemplate)?,
+            ),
+            None => None,
+        };
+
+        // A new topology implies the vcpu count and hyperthreading, unless they are given too.
+        let topology = machine_config.cpu_topology;
         let ht_enabled = machine_config
             .ht_enabled
+            .or_else(|| topology.map(|t| t.threads_per_core > 1))
             .unwrap_or_else(|| self.vm_config.ht_enabled.unwrap());
 
-        let vcpu_count_value = machine_config
-            .vcpu_count
-            .unwrap_or_else(|| self.vm_config.vcpu_count.unwrap());
+        let vcpu_count_value = match machine_config.vcpu_count {
+            Some(vcpu_count) => vcpu_count,
+            None => match topology {
+                Some(topology) => topology
+                    .cpu_count()
+                    .ok_or(VmConfigError::InvalidCpuTopology)?,
+                None => self.vm_config.vcpu_count.unwrap(),
+            },
+        };
 
         // If hyperthreading is enabled or is to be enabled in this call
         // only allow vcpu count to be 1 or even.
         if ht_enabled && vcpu_count_value > 1 && vcpu_count_value % 2 == 1 {
             return Err(VmConfigError::InvalidVcpuCount);
         }
+        if vcpu_count_value > MAX_SUPPORTED_VCPUS {
+            return Err(VmConfigError::InvalidVcpuCount);
+        }
+
+        // The hotpluggable vcpus follow the same rules as the boot ones.
+        let max_vcpu_count = machine_config
+            .max_vcpu_count
+            .or(self.vm_config.max_vcpu_count);
+        if let Some(max_vcpu_count) = max_vcpu_count {
+            if max_vcpu_count < vcpu_count_value
+                || max_vcpu_count > MAX_SUPPORTED_VCPUS
+                || (ht_enabled && max_vcpu_count > 1 && max_vcpu_count % 2 == 1)
+            {
+                return Err(VmConfigError::InvalidMaxVcpuCount);
+            }
+        }
+        // The topology describes all the vcpus, including the hotpluggable ones.
+        let total_vcpu_count = max_vcpu_count.unwrap_or(vcpu_count_value);
+
+        if let Some(topology) = topology {
+            topology.validate(total_vcpu_count)?;
+            if ht_enabled != (topology.threads_per_core > 1) {
+                return Err(VmConfigError::InvalidCpuTopology);
+            }
+        }
+
+        // A topology set earlier is dropped when it no longer matches the vcpus.
+        let topology = topology.or_else(|| {
+            self.vm_config.cpu_topology.filter(|_| {
+                Some(total_vcpu_count)
+                    == self.vm_config.max_vcpu_count.or(self.vm_config.vcpu_count)
+                    && Some(ht_enabled) == self.vm_config.ht_enabled
+            })
+        });
 
         // Update all the fields that have a new value.
         self.vm_config.vcpu_count = Some(vcpu_count_value);
+        self.vm_config.max_vcpu_count = max_vcpu_count;
         self.vm_config.ht_enabled = Some(ht_enabled);
+        self.vm_config.cpu_topology = topology;
         self.vm_config.track_dirty_pages = machine_config.track_dirty_pages;
 
         if machine_config.mem_size_mib.is_some() {
@@ -230,6 +961,62 @@ impl VmResources {
             self.vm_config.cpu_template = machine_config.cpu_template;
         }
 
+        if machine_config.pit_enabled.is_some() {
+            self.vm_config.pit_enabled = machine_config.pit_enabled;
+        }
+
+        if machine_config.pit_reinject_policy.is_some() {
+            self.vm_config.pit_reinject_policy = machine_config.pit_reinject_policy;
+        }
+
+        if machine_config.hpet_enabled.is_some() {
+            self.vm_config.hpet_enabled = machine_config.hpet_enabled;
+        }
+
+        if machine_config.mem_backend.is_some() {
+            self.vm_config.mem_backend = machine_config.mem_backend;
+        }
+
+        if machine_config.nested_virt_enabled.is_some() {
+            self.vm_config.nested_virt_enabled = machine_config.nested_virt_enabled;
+        }
+
+        if machine_config.msr_policy.is_some() {
+            self.vm_config.msr_policy = machine_config.msr_policy.clone();
+        }
+
+        if machine_config.mmio_layout.is_some() {
+            self.vm_config.mmio_layout = machine_config.mmio_layout;
+        }
+
+        if machine_config.virtio_transport.is_some() {
+            self.vm_config.virtio_transport = machine_config.virtio_transport;
+        }
+
+        if machine_config.cpu_template_path.is_some() {
+            self.vm_config.cpu_template_path = machine_config.cpu_template_path.clone();
+            #[cfg(target_arch = "x86_64")]
+            {
+                self.custom_cpu_template = custom_cpu_template;
+            }
+        }
+
+        if machine_config.cpu_features.is_some() {
+            self.vm_config.cpu_features = machine_config.cpu_features.clone();
+        }
+
+        if machine_config.cpuid_normalization_enabled.is_some() {
+            self.vm_config.cpuid_normalization_enabled = machine_config.cpuid_normalization_enabled;
+        }
+
+        if machine_config.mem_hints.is_some() {
+            self.vm_config.mem_hints = machine_config.mem_hints;
+        }
+
+        if machine_config.io_threads.is_some() {
+            self.vm_config.io_threads = machine_config.io_threads;
+        }
+
         Ok(())
     }
 
@@ -238,7 +1025,64 @@ impl VmResources {
         self.boot_config.as_ref()
     }
 
+    /// Returns the paths the microVM keeps access to once its filesystem access is restricted:
+    /// the boot images, the drives, the directory of the vsock sockets and the extra
+    /// `landlock` paths.
+    pub fn landlock_paths(&self) -> Vec<AllowedPath> {
+        let allow = |path: &str, read_only| AllowedPath {
+            path: PathBuf::from(path),
+            read_only,
+        };
+        let mut paths = Vec::new();
+        if let Some(boot_config) = self.boot_config.as_ref() {
+            let boot_source = &boot_config.description;
+            paths.extend(
+                boot_source
+                    .kernel_image_path
+                    .iter()
+                    .chain(boot_source.initrd_path.iter())
+                    .chain(boot_source.initrd_paths.iter())
+                    .map(|path| allow(path, true)),
+            );
+            #[cfg(target_arch = "x86_64")]
+            {
+                paths.extend(
+                    boot_source
+                        .firmware_path
+                        .iter()
+                        .map(|path| allow(path, true)),
+                );
+                paths.extend(boot_source.nvram_path.iter().map(|path| allow(path, false)));
+            }
+        }
+        #[cfg(feature = "block")]
+        paths.extend(
+            self.block
+                .configs()
+                .iter()
+                .map(|config| allow(&config.path_on_host, config.is_read_only)),
+        );
+        // The sockets of the host-initiated connections are created next to `uds_path`.
+        #[cfg(feature = "vsock")]
+        if let Some(config) = self.vsock.config() {
+            if let Some(dir) = std::path::Path::new(&config.uds_path).parent() {
+                if !dir.as_os_str().is_empty() {
+                    paths.push(AllowedPath {
+                        path: dir.to_path_buf(),
+                        read_only: false,
+                    });
+                }
+            }
+        }
+        paths.extend(self.landlock.iter().flatten().map(|path| AllowedPath {
+            path: path.clone(),
+            read_only: false,
+        }));
+        paths
+    }
+
     /// Sets a balloon device to be attached when the VM starts.
+    #[cfg(feature = "balloon")]
     pub fn set_balloon_device(
         &mut self,
         config: BalloonDeviceConfig,
@@ -255,6 +1099,10 @@ impl VmResources {
             return Err(BalloonConfigError::TooManyPagesRequested);
         }
 
+        if self.memory_backend().huge_page_size_mib().is_some() {
+            return Err(BalloonConfigError::IncompatibleMemoryBackend);
+        }
+
         self.balloon.set(config)
     }
 
@@ -264,16 +1112,31 @@ impl VmResources {
         boot_source_cfg: BootSourceConfig,
     ) -> Result<BootSourceConfigError> {
         use self::BootSourceConfigError::{
-            InvalidInitrdPath, InvalidKernelCommandLine, InvalidKernelPath,
+            ConflictingInitrdPaths, InvalidInitrdPath, InvalidKernelCommandLine, InvalidKernelPath,
+            MissingBootImage,
         };
 
         // Validate boot source config.
-        let kernel_file =
-            File::open(&boot_source_cfg.kernel_image_path).map_err(InvalidKernelPath)?;
-        let initrd_file: Option<File> = match &boot_source_cfg.initrd_path {
-            Some(path) => Some(File::open(path).map_err(InvalidInitrdPath)?),
-            None => None,
+        #[cfg(target_arch = "x86_64")]
+        let (firmware_file, nvram_file) = open_firmware(&boot_source_cfg)?;
+        #[cfg(target_arch = "x86_64")]
+        let firmware_boot = firmware_file.is_some();
+        #[cfg(target_arch = "aarch64")]
+        let firmware_boot = false;
+        let kernel_file = match &boot_source_cfg.kernel_image_path {
+            Some(path) => Some(File::open(path).map_err(InvalidKernelPath)?),
+            None if firmware_boot => None,
+            None => return Err(MissingBootImage),
         };
+        if boot_source_cfg.initrd_path.is_some() && !boot_source_cfg.initrd_paths.is_empty() {
+            return Err(ConflictingInitrdPaths);
+        }
+        let initrd_files = boot_source_cfg
+            .initrd_path
+            .iter()
+            .chain(boot_source_cfg.initrd_paths.iter())
+            .map(|path| File::open(path).map_err(InvalidInitrdPath))
+            .collect::<std::result::Result<Vec<File>, _>>()?;
         let mut cmdline = kernel::cmdline::Cmdline::new(arch::CMDLINE_MAX_SIZE);
         let boot_args = match boot_source_cfg.boot_args.as_ref() {
             None => DEFAULT_KERNEL_CMDLINE,
@@ -286,40 +1149,157 @@ impl VmResources {
         self.boot_config = Some(BootConfig {
             cmdline,
             kernel_file,
-            initrd_file,
+            initrd_files,
+            #[cfg(target_arch = "x86_64")]
+            firmware_file,
+            #[cfg(target_arch = "x86_64")]
+            nvram_file,
+            description: boot_source_cfg,
         });
         Ok(())
     }
 
     /// Inserts a block to be attached when the VM starts.
+    #[cfg(feature = "block")]
     // Only call this function as part of user configuration.
     // If the drive_id does not exist, a new Block Device Config is added to the list.
     pub fn set_block_device(
         &mut self,
         block_device_config: BlockDeviceConfig,
     ) -> Result<DriveError> {
-        self.block.insert(block_device_config)
+        let group = match block_device_config.rate_limiter_group.as_ref() {
+            Some(group_id) => Some(
+                self.rate_limiter_groups
+                    .get(group_id)
+                    .cloned()
+                    .ok_or_else(|| DriveError::RateLimiterGroupNotFound(group_id.clone()))?,
+            ),
+            None => None,
+        };
+        let drive_id = block_device_config.drive_id.clone();
+        self.block.insert(block_device_config)?;
+        if let Some(block) = self
+            .block
+            .list
+            .iter()
+            .find(|block| block.lock().expect("Poisoned lock").id() == &drive_id)
+        {
+            block
+                .lock()
+                .expect("Poisoned lock")
+                .set_rate_limiter_group(group.as_ref());
+        }
+        Ok(())
     }
 
     /// Builds a network device to be attached when the VM starts.
+    #[cfg(feature = "net")]
     pub fn build_net_device(
         &mut self,
         body: NetworkInterfaceConfig,
     ) -> Result<NetworkInterfaceError> {
+        let group = match body.rate_limiter_group.as_ref() {
+            Some(group_id) => Some(self.rate_limiter_groups.get(group_id).cloned().ok_or_else(
+                || NetworkInterfaceError::RateLimiterGroupNotFound(group_id.clone()),
+            )?),
+            None => None,
+        };
+        let mmds_config = &self.mmds_config;
         self.net_builder.build(body).map(|net_device| {
-            // Update `Net` device `MmdsNetworkStack` IPv4 address.
-            match &self.mmds_config {
-                Some(cfg) => cfg.ipv4_addr().map_or((), |ipv4_addr| {
-                    if let Some(mmds_ns) = net_device.lock().expect("Poisoned lock").mmds_ns_mut() {
-                        mmds_ns.set_ipv4_addr(ipv4_addr);
-                    };
-                }),
-                None => (),
-            };
+            let mut net_device = net_device.lock().expect("Poisoned lock");
+            net_device.set_rate_limiter_group(group.as_ref());
+            // Bind the MMDS to the `Net` device and update its `MmdsNetworkStack` IPv4
+            // address and hop limit.
+            if let Some(cfg) = mmds_config {
+                configure_mmds_ns(cfg, &mut net_device);
+            }
         })
     }
 
+    /// Sets a rate limiter group the drives and network interfaces can be attached to.
+    pub fn set_rate_limiter_group(
+        &mut self,
+        config: RateLimiterGroupConfig,
+    ) -> Result<RateLimiterGroupError> {
+        self.rate_limiter_groups.insert(config)
+    }
+
+    /// Sets an entropy device to be attached when the VM starts.
+    #[cfg(feature = "virtio-rng")]
+    pub fn set_entropy_device(
+        &mut self,
+        config: EntropyDeviceConfig,
+    ) -> Result<EntropyConfigError> {
+        self.entropy.set(config)
+    }
+
+    /// Sets a console device to be attached when the VM starts.
+    #[cfg(feature = "virtio-console")]
+    pub fn set_console_device(&mut self, config: ConsoleConfig) -> Result<ConsoleConfigError> {
+        self.console.set(config)
+    }
+
+    /// Sets the hotplug memory region and the virtio-mem device managing it,
+    /// to be attached when the VM starts.
+    #[cfg(feature = "virtio-mem")]
+    pub fn set_memory_hotplug(
+        &mut self,
+        config: MemoryHotplugConfig,
+    ) -> Result<MemoryHotplugConfigError> {
+        config.validate()?;
+        self.memory_hotplug = Some(config);
+        Ok(())
+    }
+
+    /// Builds a null device to be attached when the VM starts.
+    #[cfg(feature = "null-devices")]
+    pub fn set_null_device(&mut self, config: NullDeviceConfig) -> Result<NullDeviceConfigError> {
+        self.null_devices.build(config).map(|_| ())
+    }
+
+    /// Builds a persistent memory device to be attached when the VM starts.
+    #[cfg(feature = "virtio-pmem")]
+    pub fn set_pmem_device(&mut self, config: PmemConfig) -> Result<PmemConfigError> {
+        self.pmem.build(config).map(|_| ())
+    }
+
+    /// Binds a serial port to a host backend when the VM starts.
+    pub fn set_serial_port(&mut self, config: SerialPortConfig) -> Result<SerialConfigError> {
+        self.serial_ports.insert(config)
+    }
+
+    /// Builds a shared filesystem to be attached when the VM starts.
+    #[cfg(feature = "virtio-fs")]
+    pub fn set_shared_fs(&mut self, config: SharedFsConfig) -> Result<SharedFsConfigError> {
+        self.shared_fs.build(config).map(|_| ())
+    }
+
+    /// Sets a TPM to be attached when the VM starts.
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    pub fn set_tpm(&mut self, config: TpmConfig) {
+        self.tpm = Some(config);
+    }
+
+    /// Sets a host PCI function to be passed through when the VM starts.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    pub fn set_vfio_device(&mut self, config: VfioConfig) -> Result<VfioConfigError> {
+        self.vfio.build(config)
+    }
+
+    /// Sets what is done with the microVM before exiting on `SIGTERM`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_shutdown_behavior(&mut self, config: ShutdownBehaviorConfig) {
+        self.shutdown_behavior = config;
+    }
+
+    /// Sets a watchdog to be attached when the VM starts.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_watchdog(&mut self, config: WatchdogConfig) {
+        self.watchdog = Some(config);
+    }
+
     /// Sets a vsock device to be attached when the VM starts.
+    #[cfg(feature = "vsock")]
     pub fn set_vsock_device(&mut self, config: VsockDeviceConfig) -> Result<VsockConfigError> {
         self.vsock.insert(config)
     }
@@ -327,35 +1307,165 @@ impl VmResources {
     /// Setter for mmds config.
     pub fn set_mmds_config(&mut self, config: MmdsConfig) -> Result<MmdsConfigError> {
         // Check IPv4 address validity.
-        let ipv4_addr = match config.ipv4_addr() {
-            Some(ipv4_addr) if is_link_local_valid(ipv4_addr) => Ok(ipv4_addr),
-            None => Ok(MmdsNetworkStack::default_ipv4_addr()),
-            _ => Err(MmdsConfigError::InvalidIpv4Addr),
-        }?;
-
-        // Update existing built network device `MmdsNetworkStack` IPv4 address.
-        for net_device in self.net_builder.iter_mut() {
-            if let Some(mmds_ns) = net_device.lock().expect("Poisoned lock").mmds_ns_mut() {
-                mmds_ns.set_ipv4_addr(ipv4_addr)
+        match config.ipv4_addr() {
+            Some(ipv4_addr) if !is_link_local_valid(ipv4_addr) => {
+                return Err(MmdsConfigError::InvalidIpv4Addr)
+            }
+            _ => (),
+        };
+        // Check IPv6 address validity.
+        match config.ipv6_address {
+            Some(ipv6_addr) if !is_link_local_or_unique_local(ipv6_addr) => {
+                return Err(MmdsConfigError::InvalidIpv6Addr)
+            }
+            _ => (),
+        };
+        if config.hop_limit == Some(0) {
+            return Err(MmdsConfigError::InvalidHopLimit);
+        }
+        // Check that the MMDS is bound to existing network interfaces.
+        if let Some(iface_ids) = &config.network_interfaces {
+            if iface_ids.is_empty() {
+                return Err(MmdsConfigError::EmptyNetworkInterfaces);
             }
+            #[cfg(feature = "net")]
+            let unknown_iface = iface_ids.iter().find(|iface_id| {
+                !self
+                    .net_builder
+                    .iter()
+                    .any(|net| net.lock().expect("Poisoned lock").id() == *iface_id)
+            });
+            // Without the network devices, there is no interface to bind the MMDS to.
+            #[cfg(not(feature = "net"))]
+            let unknown_iface = iface_ids.first();
+            if let Some(iface_id) = unknown_iface {
+                return Err(MmdsConfigError::InvalidNetworkInterfaceId(iface_id.clone()));
+            }
+        }
+        MMDS.lock()
+            .expect("Poisoned lock")
+            .set_data_store_limit(config.data_store_limit.unwrap_or(DEFAULT_DATA_STORE_LIMIT))
+            .map_err(MmdsConfigError::InvalidDataStoreLimit)?;
+        // Replace the dynamic fields of the data store.
+        let providers = config
+            .dynamic_fields
+            .iter()
+            .flatten()
+            .map(|field| {
+                let provider: Arc<dyn ValueProvider> = Arc::new(field.value);
+                (field.path.clone(), provider)
+            })
+            .collect();
+        MMDS.lock()
+            .expect("Poisoned lock")
+            .set_providers(providers)
+            .map_err(MmdsConfigError::InvalidDynamicField)?;
+
+        // Update existing built network devices.
+        #[cfg(feature = "net")]
+        for net_device in self.net_builder.iter_mut() {
+            configure_mmds_ns(&config, &mut net_device.lock().expect("Poisoned lock"));
         }
 
+        MMDS.lock()
+            .expect("Poisoned lock")
+            .set_version(config.version);
+
         self.mmds_config = Some(config);
         Ok(())
     }
 }
 
+// Binds the MMDS to `net` as requested by `config`, and configures its `MmdsNetworkStack`.
+#[cfg(feature = "net")]
+fn configure_mmds_ns(config: &MmdsConfig, net: &mut Net) {
+    if let Some(iface_ids) = &config.network_interfaces {
+        net.set_mmds_enabled(iface_ids.contains(net.id()));
+    }
+    if let Some(mmds_ns) = net.mmds_ns_mut() {
+        mmds_ns.set_ipv4_addr(
+            config
+                .ipv4_addr()
+                .unwrap_or_else(MmdsNetworkStack::default_ipv4_addr),
+        );
+        mmds_ns.set_ipv6_addr(config.ipv6_address);
+        if let Some(hop_limit) = config.hop_limit {
+            mmds_ns.set_hop_limit(hop_limit);
+        }
+    }
+}
+
+// Opens the firmware and its variable store, if booting a firmware, and checks that they fit
+// together below 4 GiB.
+#[cfg(target_arch = "x86_64")]
+fn open_firmware(
+    config: &BootSourceConfig,
+) -> std::result::Result<(Option<File>, Option<File>), BootSourceConfigError> {
+    use self::BootSourceConfigError::{
+        InvalidFirmwarePath, InvalidFirmwareSize, InvalidNvramPath, KernelWithFirmware,
+        NvramWithoutFirmware,
+    };
+
+    let firmware_path = match &config.firmware_path {
+        Some(path) => path,
+        None if config.nvram_path.is_some() => return Err(NvramWithoutFirmware),
+        None => return Ok((None, None)),
+    };
+    if config.kernel_image_path.is_some()
+        || config.initrd_path.is_some()
+        || !config.initrd_paths.is_empty()
+    {
+        return Err(KernelWithFirmware);
+    }
+
+    let firmware_file = File::open(firmware_path).map_err(InvalidFirmwarePath)?;
+    let firmware_size = firmware_file.metadata().map_err(InvalidFirmwarePath)?.len();
+    // The guest writes to the variable store, which go through to the file.
+    let nvram_file = match &config.nvram_path {
+        Some(path) => Some(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .map_err(InvalidNvramPath)?,
+        ),
+        None => None,
+    };
+    let nvram_size = match &nvram_file {
+        Some(file) => file.metadata().map_err(InvalidNvramPath)?.len(),
+        None => 0,
+    };
+
+    let page_size = arch::PAGE_SIZE as u64;
+    let total_size = firmware_size + nvram_size;
+    if firmware_size == 0
+        || firmware_size % page_size != 0
+        || (nvram_file.is_some() && nvram_size == 0)
+        || nvram_size % devices::legacy::PFLASH_BLOCK_SIZE != 0
+        || total_size > arch::x86_64::layout::FIRMWARE_MAX_SIZE
+    {
+        return Err(InvalidFirmwareSize(total_size));
+    }
+    Ok((Some(firmware_file), nvram_file))
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
+    use std::net::{Ipv4Addr, Ipv6Addr};
     use std::os::linux::fs::MetadataExt;
 
     use super::*;
     use crate::resources::VmResources;
     use crate::vmm_config::boot_source::{BootConfig, BootSourceConfig, DEFAULT_KERNEL_CMDLINE};
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig};
-    use crate::vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig, VmConfigError};
+    use crate::vmm_config::machine_config::{
+        CpuFeaturesTemplate, CpuTopology, MmioLayoutConfig, MsrPolicy, MsrRange, PitReinjectPolicy,
+        VmConfig, VmConfigError,
+    };
+    use crate::vmm_config::mmds::{DynamicValue, MmdsDynamicField, MmdsVersion};
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
+    #[cfg(feature = "vsock")]
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::RateLimiterConfig;
     use crate::vstate::vcpu::VcpuConfig;
@@ -377,6 +1487,9 @@ mod tests {
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: false,
+            vlan_id: None,
+            dhcp: None,
+            rate_limiter_group: None,
         }
     }
 
@@ -397,6 +1510,8 @@ mod tests {
                 partuuid: Some("0eaa91a0-01".to_string()),
                 is_read_only: false,
                 rate_limiter: Some(RateLimiterConfig::default()),
+                io_weight: None,
+                rate_limiter_group: None,
             },
             tmp_file,
         )
@@ -415,8 +1530,22 @@ mod tests {
         let tmp_file = TempFile::new().unwrap();
         BootConfig {
             cmdline: kernel_cmdline,
-            kernel_file: File::open(tmp_file.as_path()).unwrap(),
-            initrd_file: Some(File::open(tmp_file.as_path()).unwrap()),
+            kernel_file: Some(File::open(tmp_file.as_path()).unwrap()),
+            initrd_files: vec![File::open(tmp_file.as_path()).unwrap()],
+            #[cfg(target_arch = "x86_64")]
+            firmware_file: None,
+            #[cfg(target_arch = "x86_64")]
+            nvram_file: None,
+            description: BootSourceConfig {
+                kernel_image_path: Some(tmp_file.as_path().to_str().unwrap().to_string()),
+                initrd_path: Some(tmp_file.as_path().to_str().unwrap().to_string()),
+                initrd_paths: Vec::new(),
+                boot_args: None,
+                #[cfg(target_arch = "x86_64")]
+                firmware_path: None,
+                #[cfg(target_arch = "x86_64")]
+                nvram_path: None,
+            },
         }
     }
 
@@ -425,33 +1554,73 @@ mod tests {
             vm_config: VmConfig::default(),
             boot_config: Some(default_boot_cfg()),
             block: default_blocks(),
+            #[cfg(feature = "vsock")]
             vsock: Default::default(),
+            #[cfg(feature = "balloon")]
             balloon: Default::default(),
+            #[cfg(feature = "virtio-rng")]
+            entropy: Default::default(),
+            #[cfg(feature = "virtio-console")]
+            console: Default::default(),
             net_builder: default_net_builder(),
+            rate_limiter_groups: Default::default(),
+            #[cfg(feature = "virtio-mem")]
+            memory_hotplug: None,
+            #[cfg(feature = "null-devices")]
+            null_devices: Default::default(),
+            #[cfg(feature = "virtio-pmem")]
+            pmem: Default::default(),
+            serial_ports: Default::default(),
+            #[cfg(feature = "virtio-fs")]
+            shared_fs: Default::default(),
             mmds_config: None,
             boot_timer: false,
+            start_paused: false,
+            gdb_socket: None,
+            landlock: None,
+            allow_teardown: false,
+            panic_action: PanicAction::default(),
+            #[cfg(target_arch = "x86_64")]
+            clock_policy: ClockPolicy::default(),
+            cpu_quota: CpuQuotaConfig::default(),
+            #[cfg(target_arch = "x86_64")]
+            shutdown_behavior: ShutdownBehaviorConfig::default(),
+            #[cfg(target_arch = "x86_64")]
+            watchdog: None,
+            #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+            tpm: None,
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            vfio: Default::default(),
+            #[cfg(target_arch = "x86_64")]
+            custom_cpu_template: None,
         }
     }
 
     impl PartialEq for BootConfig {
         fn eq(&self, other: &Self) -> bool {
             self.cmdline.as_str().eq(other.cmdline.as_str())
-                && self.kernel_file.metadata().unwrap().st_ino()
-                    == other.kernel_file.metadata().unwrap().st_ino()
                 && self
-                    .initrd_file
+                    .kernel_file
                     .as_ref()
                     .unwrap()
                     .metadata()
                     .unwrap()
                     .st_ino()
                     == other
-                        .initrd_file
+                        .kernel_file
                         .as_ref()
                         .unwrap()
                         .metadata()
                         .unwrap()
                         .st_ino()
+                && self
+                    .initrd_files
+                    .iter()
+                    .map(|file| file.metadata().unwrap().st_ino())
+                    .eq(other
+                        .initrd_files
+                        .iter()
+                        .map(|file| file.metadata().unwrap().st_ino()))
         }
     }
 
@@ -472,6 +1641,16 @@ mod tests {
         // in every json because they are mandatory fields. If we don't configure
         // these resources, it is considered an invalid json and the test will crash.
 
+        // Malformed JSON, and a missing mandatory resource.
+        for json in &["{", r#"{"drives": []}"#] {
+            match VmResources::from_json(json, &default_instance_info) {
+                Err(err @ Error::InvalidJson(_)) => {
+                    assert!(err.to_string().starts_with("Invalid JSON configuration: "))
+                }
+                _ => unreachable!(),
+            }
+        }
+
         // Invalid kernel path.
         let mut json = format!(
             r#"{{
@@ -712,7 +1891,8 @@ mod tests {
             kernel_file.as_path().to_str().unwrap(),
             rootfs_file.as_path().to_str().unwrap(),
         );
-        assert!(VmResources::from_json(json.as_str(), &default_instance_info).is_ok());
+        let vm_resources = VmResources::from_json(json.as_str(), &default_instance_info).unwrap();
+        assert!(!vm_resources.start_paused);
 
         // Test all configuration, this time trying to configure the MMDS with an
         // empty body. It will make it access the code path in which it sets the
@@ -744,17 +1924,25 @@ mod tests {
                             "allow_mmds_requests": true
                         }}
                     ],
+                    "null-devices": [
+                        {{
+                            "device_id": "snd0",
+                            "device_type": "Sound"
+                        }}
+                    ],
                     "machine-config": {{
                         "vcpu_count": 2,
                         "mem_size_mib": 1024,
                         "ht_enabled": false
                     }},
-                    "mmds-config": {{}}
+                    "mmds-config": {{}},
+                    "start-paused": true
             }}"#,
             kernel_file.as_path().to_str().unwrap(),
             rootfs_file.as_path().to_str().unwrap(),
         );
-        assert!(VmResources::from_json(json.as_str(), &default_instance_info).is_ok());
+        let vm_resources = VmResources::from_json(json.as_str(), &default_instance_info).unwrap();
+        assert!(vm_resources.start_paused);
     }
 
     #[test]
@@ -764,6 +1952,13 @@ mod tests {
             vcpu_count: vm_resources.vm_config().vcpu_count.unwrap(),
             ht_enabled: vm_resources.vm_config().ht_enabled.unwrap(),
             cpu_template: vm_resources.vm_config().cpu_template,
+            cpu_topology: vm_resources.vm_config().cpu_topology,
+            nested_virt_enabled: false,
+            msr_policy: None,
+            #[cfg(target_arch = "x86_64")]
+            custom_cpu_template: None,
+            cpu_features: None,
+            cpuid_normalization_enabled: false,
         };
 
         let vcpu_config = vm_resources.vcpu_config();
@@ -783,10 +1978,25 @@ mod tests {
         let mut vm_resources = default_vm_resources();
         let mut aux_vm_config = VmConfig {
             vcpu_count: Some(32),
+            max_vcpu_count: None,
             mem_size_mib: Some(512),
             ht_enabled: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
             track_dirty_pages: false,
+            pit_enabled: None,
+            pit_reinject_policy: Some(PitReinjectPolicy::Discard),
+            hpet_enabled: Some(false),
+            cpu_topology: None,
+            mem_backend: None,
+            nested_virt_enabled: None,
+            msr_policy: None,
+            mmio_layout: None,
+            virtio_transport: None,
+            cpu_template_path: None,
+            cpu_features: None,
+            cpuid_normalization_enabled: None,
+            mem_hints: None,
+            io_threads: None,
         };
 
         assert_ne!(vm_resources.vm_config, aux_vm_config);
@@ -814,41 +2024,528 @@ mod tests {
         );
 
         // Incompatible mem_size_mib with balloon size.
-        vm_resources.vm_config.mem_size_mib = Some(128);
+        #[cfg(feature = "balloon")]
+        {
+            vm_resources.vm_config.mem_size_mib = Some(128);
+            vm_resources
+                .set_balloon_device(BalloonDeviceConfig {
+                    amount_mb: 100,
+                    deflate_on_oom: false,
+                    stats_polling_interval_s: 0,
+                    free_page_reporting: false,
+                })
+                .unwrap();
+            aux_vm_config.mem_size_mib = Some(90);
+            assert_eq!(
+                vm_resources.set_vm_config(&aux_vm_config),
+                Err(VmConfigError::IncompatibleBalloonSize)
+            );
+        }
+
+        // mem_size_mib compatible with balloon size.
+        aux_vm_config.mem_size_mib = Some(256);
+        assert!(vm_resources.set_vm_config(&aux_vm_config).is_ok());
+    }
+
+    #[test]
+    fn test_set_memory_hints() {
+        let mut vm_resources = default_vm_resources();
+        assert_eq!(vm_resources.memory_hints(), MemoryHints::default());
+
+        let mem_hints = MemoryHints {
+            mergeable: true,
+            huge_pages: Some(false),
+        };
+        let vm_config = VmConfig {
+            mem_hints: Some(mem_hints),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.memory_hints(), mem_hints);
+
+        // The hints are kept when other fields are updated.
+        let vm_config = VmConfig {
+            mem_size_mib: Some(256),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.memory_hints(), mem_hints);
+
+        // The huge pages of hugetlbfs cannot be advised.
+        let vm_config = VmConfig {
+            mem_backend: Some(MemoryBackend::Hugetlbfs2M),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config),
+            Err(VmConfigError::IncompatibleMemoryHints)
+        );
+        let vm_config = VmConfig {
+            mem_backend: Some(MemoryBackend::Hugetlbfs2M),
+            mem_hints: Some(MemoryHints::default()),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.memory_backend(), MemoryBackend::Hugetlbfs2M);
+    }
+
+    #[test]
+    fn test_set_io_threads() {
+        let mut vm_resources = default_vm_resources();
+        assert_eq!(vm_resources.io_threads(), 0);
+
+        let vm_config = VmConfig {
+            io_threads: Some(2),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.io_threads(), 2);
+
+        // The number of threads is kept when other fields are updated.
+        let vm_config = VmConfig {
+            mem_size_mib: Some(256),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.io_threads(), 2);
+
+        let vm_config = VmConfig {
+            io_threads: Some(MAX_IO_THREADS + 1),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config),
+            Err(VmConfigError::InvalidIoThreads)
+        );
+        assert_eq!(vm_resources.io_threads(), 2);
+    }
+
+    #[test]
+    fn test_set_pit_enabled() {
+        let mut vm_resources = default_vm_resources();
+        let vm_config = VmConfig {
+            pit_enabled: Some(false),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config().pit_enabled, Some(false));
+
+        // The reinject policy cannot be set on a disabled PIT, in the same or a later request.
+        let vm_config = VmConfig {
+            pit_reinject_policy: Some(PitReinjectPolicy::Discard),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config),
+            Err(VmConfigError::IncompatiblePitPolicy)
+        );
+        let vm_config = VmConfig {
+            pit_enabled: Some(false),
+            pit_reinject_policy: Some(PitReinjectPolicy::Discard),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config),
+            Err(VmConfigError::IncompatiblePitPolicy)
+        );
+        assert_eq!(vm_resources.vm_config().pit_reinject_policy, None);
+
+        // Enabling the PIT again allows the policy.
+        let vm_config = VmConfig {
+            pit_enabled: Some(true),
+            pit_reinject_policy: Some(PitReinjectPolicy::Discard),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config().pit_enabled, Some(true));
+        assert_eq!(
+            vm_resources.vm_config().pit_reinject_policy,
+            Some(PitReinjectPolicy::Discard)
+        );
+    }
+
+    #[test]
+    fn test_set_cpu_topology() {
+        let mut vm_resources = default_vm_resources();
+        let topology = CpuTopology {
+            sockets: 2,
+            cores_per_socket: 2,
+            threads_per_core: 2,
+        };
+
+        // The topology implies the vcpu count and hyperthreading.
+        let vm_config = VmConfig {
+            vcpu_count: None,
+            mem_size_mib: None,
+            ht_enabled: None,
+            cpu_topology: Some(topology),
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config.vcpu_count, Some(8));
+        assert_eq!(vm_resources.vm_config.ht_enabled, Some(true));
+        assert_eq!(vm_resources.vm_config.cpu_topology, Some(topology));
+
+        // The topology is kept while the vcpus do not change.
+        let vm_config = VmConfig {
+            vcpu_count: None,
+            mem_size_mib: Some(256),
+            ht_enabled: None,
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config.cpu_topology, Some(topology));
+
+        // And dropped otherwise.
+        let vm_config = VmConfig {
+            vcpu_count: Some(4),
+            mem_size_mib: None,
+            ht_enabled: None,
+            ..Default::default()
+        };
+        vm_resources.set_vm_config(&vm_config).unwrap();
+        assert_eq!(vm_resources.vm_config.cpu_topology, None);
+
+        // The vcpu count and hyperthreading must match the topology.
+        let vm_config = VmConfig {
+            vcpu_count: Some(4),
+            ht_enabled: None,
+            cpu_topology: Some(topology),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config),
+            Err(VmConfigError::InvalidCpuTopology)
+        );
+        let vm_config = VmConfig {
+            vcpu_count: Some(8),
+            ht_enabled: Some(false),
+            cpu_topology: Some(topology),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config),
+            Err(VmConfigError::InvalidCpuTopology)
+        );
+
+        // Too many vcpus.
+        let vm_config = VmConfig {
+            vcpu_count: None,
+            ht_enabled: None,
+            cpu_topology: Some(CpuTopology {
+                sockets: 4,
+                cores_per_socket: 8,
+                threads_per_core: 2,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config),
+            Err(VmConfigError::InvalidVcpuCount)
+        );
+        assert_eq!(vm_resources.vm_config.vcpu_count, Some(4));
+    }
+
+    #[test]
+    fn test_set_nested_virt() {
+        let mut vm_resources = default_vm_resources();
+        let vm_config = |nested_virt_enabled| VmConfig {
+            vcpu_count: None,
+            mem_size_mib: None,
+            ht_enabled: None,
+            nested_virt_enabled,
+            ..Default::default()
+        };
+
+        // Disabling nested virtualization does not depend on the host.
+        vm_resources.set_vm_config(&vm_config(Some(false))).unwrap();
+        assert_eq!(vm_resources.vm_config.nested_virt_enabled, Some(false));
+        assert!(!vm_resources.vcpu_config().nested_virt_enabled);
+
+        if nested_virt_supported() {
+            vm_resources.set_vm_config(&vm_config(Some(true))).unwrap();
+            assert!(vm_resources.vcpu_config().nested_virt_enabled);
+        } else {
+            assert_eq!(
+                vm_resources.set_vm_config(&vm_config(Some(true))),
+                Err(VmConfigError::NestedVirtUnsupported)
+            );
+            assert!(!vm_resources.vcpu_config().nested_virt_enabled);
+        }
+
+        // The setting is kept when it is not given.
+        let enabled = vm_resources.vm_config.nested_virt_enabled;
+        vm_resources.set_vm_config(&vm_config(None)).unwrap();
+        assert_eq!(vm_resources.vm_config.nested_virt_enabled, enabled);
+    }
+
+    #[test]
+    fn test_set_msr_policy() {
+        let mut vm_resources = default_vm_resources();
+        let mut policy = MsrPolicy {
+            deny: vec![MsrRange {
+                index: 0x10,
+                count: 0,
+            }],
+            ..Default::default()
+        };
+        let vm_config = |msr_policy| VmConfig {
+            vcpu_count: None,
+            mem_size_mib: None,
+            ht_enabled: None,
+            msr_policy,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config(Some(policy.clone()))),
+            Err(VmConfigError::InvalidMsrPolicy)
+        );
+        assert_eq!(vm_resources.vcpu_config().msr_policy, None);
+
+        policy.deny[0].count = 1;
         vm_resources
-            .set_balloon_device(BalloonDeviceConfig {
-                amount_mb: 100,
-                deflate_on_oom: false,
-                stats_polling_interval_s: 0,
-            })
+            .set_vm_config(&vm_config(Some(policy.clone())))
             .unwrap();
-        aux_vm_config.mem_size_mib = Some(90);
+        assert_eq!(vm_resources.vcpu_config().msr_policy, Some(policy.clone()));
+
+        // The policy is kept when it is not given.
+        vm_resources.set_vm_config(&vm_config(None)).unwrap();
+        assert_eq!(vm_resources.vm_config.msr_policy, Some(policy));
+    }
+
+    #[test]
+    fn test_set_mmio_layout() {
+        let mut vm_resources = default_vm_resources();
+        let vm_config = |mmio_layout| VmConfig {
+            vcpu_count: None,
+            mem_size_mib: None,
+            ht_enabled: None,
+            mmio_layout,
+            ..Default::default()
+        };
+        assert_eq!(vm_resources.mmio_layout(), arch::MmioLayout::default());
+
+        let config = MmioLayoutConfig {
+            gap_size_mib: None,
+            device_slots: Some(arch::MAX_MMIO_SLOTS + 1),
+        };
         assert_eq!(
-            vm_resources.set_vm_config(&aux_vm_config),
-            Err(VmConfigError::IncompatibleBalloonSize)
+            vm_resources.set_vm_config(&vm_config(Some(config))),
+            Err(VmConfigError::InvalidMmioLayout)
         );
+        assert_eq!(vm_resources.vm_config.mmio_layout, None);
 
-        // mem_size_mib compatible with balloon size.
-        aux_vm_config.mem_size_mib = Some(256);
-        assert!(vm_resources.set_vm_config(&aux_vm_config).is_ok());
+        let config = MmioLayoutConfig {
+            gap_size_mib: None,
+            device_slots: Some(4),
+        };
+        vm_resources
+            .set_vm_config(&vm_config(Some(config)))
+            .unwrap();
+        assert_eq!(vm_resources.mmio_layout().slots, 4);
+
+        // The layout is kept when it is not given.
+        vm_resources.set_vm_config(&vm_config(None)).unwrap();
+        assert_eq!(vm_resources.vm_config.mmio_layout, Some(config));
     }
 
     #[test]
+    fn test_set_virtio_transport() {
+        let mut vm_resources = default_vm_resources();
+        let vm_config = |virtio_transport| VmConfig {
+            vcpu_count: None,
+            mem_size_mib: None,
+            ht_enabled: None,
+            virtio_transport,
+            ..Default::default()
+        };
+        assert!(!vm_resources.pci_enabled());
+
+        vm_resources
+            .set_vm_config(&vm_config(Some(VirtioTransport::Pci)))
+            .unwrap();
+        assert!(vm_resources.pci_enabled());
+        // The transport is kept when it is not given.
+        vm_resources.set_vm_config(&vm_config(None)).unwrap();
+        assert!(vm_resources.pci_enabled());
+
+        vm_resources
+            .set_vm_config(&vm_config(Some(VirtioTransport::Mmio)))
+            .unwrap();
+        assert!(!vm_resources.pci_enabled());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_set_cpu_template_path() {
+        let mut vm_resources = default_vm_resources();
+        let vm_config = |cpu_template_path| VmConfig {
+            vcpu_count: None,
+            mem_size_mib: None,
+            ht_enabled: None,
+            cpu_template_path,
+            ..Default::default()
+        };
+        let template_file = TempFile::new().unwrap();
+        let template_path = template_file.as_path().to_str().unwrap().to_string();
+
+        // The file has to describe a template.
+        std::fs::write(&template_path, "{ \"cpuid_modifiers\": 1 }").unwrap();
+        match vm_resources.set_vm_config(&vm_config(Some(template_path.clone()))) {
+            Err(VmConfigError::InvalidCustomCpuTemplate(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+        assert!(vm_resources.vcpu_config().custom_cpu_template.is_none());
+
+        std::fs::write(
+            &template_path,
+            r#"{ "msr_modifiers": [{ "addr": "0x10a", "bitmap": "0b0" }] }"#,
+        )
+        .unwrap();
+        vm_resources
+            .set_vm_config(&vm_config(Some(template_path.clone())))
+            .unwrap();
+        assert_eq!(
+            vm_resources.vm_config.cpu_template_path,
+            Some(template_path.clone())
+        );
+        let template = vm_resources.vcpu_config().custom_cpu_template.unwrap();
+        assert_eq!(template.msr_modifiers[0].addr, 0x10a);
+        // The template is kept when no path is given.
+        vm_resources.set_vm_config(&vm_config(None)).unwrap();
+        assert!(vm_resources.vcpu_config().custom_cpu_template.is_some());
+
+        match vm_resources.set_vm_config(&vm_config(Some("/no/such/template".to_string()))) {
+            Err(VmConfigError::InvalidCustomCpuTemplate(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_set_max_vcpu_count() {
+        let mut vm_resources = default_vm_resources();
+        let vm_config = |vcpu_count, max_vcpu_count| VmConfig {
+            vcpu_count,
+            max_vcpu_count,
+            ht_enabled: None,
+            mem_size_mib: None,
+            ..Default::default()
+        };
+
+        vm_resources
+            .set_vm_config(&vm_config(Some(2), Some(4)))
+            .unwrap();
+        assert_eq!(vm_resources.vm_config.max_vcpu_count, Some(4));
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(vm_resources.vcpu_config().vcpu_count, 4);
+
+        // The maximum is kept when only the vcpu count changes, and bounds it.
+        vm_resources
+            .set_vm_config(&vm_config(Some(3), None))
+            .unwrap();
+        assert_eq!(vm_resources.vm_config.max_vcpu_count, Some(4));
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config(Some(5), None)),
+            Err(VmConfigError::InvalidMaxVcpuCount)
+        );
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config(None, Some(2))),
+            Err(VmConfigError::InvalidMaxVcpuCount)
+        );
+        assert_eq!(
+            vm_resources.set_vm_config(&vm_config(None, Some(MAX_SUPPORTED_VCPUS + 1))),
+            Err(VmConfigError::InvalidMaxVcpuCount)
+        );
+
+        // With hyperthreading, the maximum must be even too.
+        vm_resources
+            .set_vm_config(&vm_config(Some(2), None))
+            .unwrap();
+        let ht_config = VmConfig {
+            ht_enabled: Some(true),
+            ..vm_config(None, Some(5))
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&ht_config),
+            Err(VmConfigError::InvalidMaxVcpuCount)
+        );
+        assert_eq!(vm_resources.vm_config.max_vcpu_count, Some(4));
+
+        // A topology describes all the vcpus.
+        let topology = CpuTopology {
+            sockets: 2,
+            cores_per_socket: 2,
+            threads_per_core: 1,
+        };
+        let topology_config = VmConfig {
+            cpu_topology: Some(topology),
+            ..vm_config(Some(2), None)
+        };
+        vm_resources.set_vm_config(&topology_config).unwrap();
+        assert_eq!(vm_resources.vm_config.cpu_topology, Some(topology));
+        let topology_config = VmConfig {
+            cpu_topology: Some(topology),
+            ..vm_config(Some(2), Some(2))
+        };
+        assert_eq!(
+            vm_resources.set_vm_config(&topology_config),
+            Err(VmConfigError::InvalidCpuTopology)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "balloon")]
     fn test_set_balloon_device() {
         let mut vm_resources = VmResources {
             vm_config: VmConfig::default(),
             boot_config: Some(default_boot_cfg()),
             block: default_blocks(),
+            #[cfg(feature = "vsock")]
             vsock: Default::default(),
+            #[cfg(feature = "balloon")]
             balloon: BalloonBuilder::new(),
+            #[cfg(feature = "virtio-rng")]
+            entropy: Default::default(),
+            #[cfg(feature = "virtio-console")]
+            console: Default::default(),
             net_builder: default_net_builder(),
+            rate_limiter_groups: Default::default(),
+            #[cfg(feature = "virtio-mem")]
+            memory_hotplug: None,
+            #[cfg(feature = "null-devices")]
+            null_devices: Default::default(),
+            #[cfg(feature = "virtio-pmem")]
+            pmem: Default::default(),
+            serial_ports: Default::default(),
+            #[cfg(feature = "virtio-fs")]
+            shared_fs: Default::default(),
             mmds_config: None,
             boot_timer: false,
+            start_paused: false,
+            gdb_socket: None,
+            landlock: None,
+            allow_teardown: false,
+            panic_action: PanicAction::default(),
+            #[cfg(target_arch = "x86_64")]
+            clock_policy: ClockPolicy::default(),
+            cpu_quota: CpuQuotaConfig::default(),
+            #[cfg(target_arch = "x86_64")]
+            shutdown_behavior: ShutdownBehaviorConfig::default(),
+            #[cfg(target_arch = "x86_64")]
+            watchdog: None,
+            #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+            tpm: None,
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            vfio: Default::default(),
+            #[cfg(target_arch = "x86_64")]
+            custom_cpu_template: None,
         };
         let mut new_balloon_cfg = BalloonDeviceConfig {
             amount_mb: 100,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         };
         assert!(vm_resources.balloon.get().is_none());
         vm_resources
@@ -870,11 +2567,45 @@ mod tests {
             vm_config: VmConfig::default(),
             boot_config: Some(default_boot_cfg()),
             block: default_blocks(),
+            #[cfg(feature = "vsock")]
             vsock: Default::default(),
+            #[cfg(feature = "balloon")]
             balloon: BalloonBuilder::new(),
+            #[cfg(feature = "virtio-rng")]
+            entropy: Default::default(),
+            #[cfg(feature = "virtio-console")]
+            console: Default::default(),
             net_builder: default_net_builder(),
+            rate_limiter_groups: Default::default(),
+            #[cfg(feature = "virtio-mem")]
+            memory_hotplug: None,
+            #[cfg(feature = "null-devices")]
+            null_devices: Default::default(),
+            #[cfg(feature = "virtio-pmem")]
+            pmem: Default::default(),
+            serial_ports: Default::default(),
+            #[cfg(feature = "virtio-fs")]
+            shared_fs: Default::default(),
             mmds_config: None,
             boot_timer: false,
+            start_paused: false,
+            gdb_socket: None,
+            landlock: None,
+            allow_teardown: false,
+            panic_action: PanicAction::default(),
+            #[cfg(target_arch = "x86_64")]
+            clock_policy: ClockPolicy::default(),
+            cpu_quota: CpuQuotaConfig::default(),
+            #[cfg(target_arch = "x86_64")]
+            shutdown_behavior: ShutdownBehaviorConfig::default(),
+            #[cfg(target_arch = "x86_64")]
+            watchdog: None,
+            #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+            tpm: None,
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            vfio: Default::default(),
+            #[cfg(target_arch = "x86_64")]
+            custom_cpu_template: None,
         };
         new_balloon_cfg.amount_mb = 256;
         assert!(vm_resources.set_balloon_device(new_balloon_cfg).is_err());
@@ -889,14 +2620,264 @@ mod tests {
         assert_eq!(actual_boot_cfg, expected_boot_cfg);
     }
 
+    #[test]
+    fn test_apply_vmm_config() {
+        let kernel_file = TempFile::new().unwrap();
+        let rootfs_file = TempFile::new().unwrap();
+        let vmm_config = |vcpu_count: u8, extra: &str| -> VmmConfig {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "machine-config": {{
+                        "vcpu_count": {},
+                        "mem_size_mib": 1024,
+                        "ht_enabled": false
+                    }}{}
+                }}"#,
+                kernel_file.as_path().to_str().unwrap(),
+                rootfs_file.as_path().to_str().unwrap(),
+                vcpu_count,
+                extra
+            ))
+            .unwrap()
+        };
+
+        let mut vm_resources = default_vm_resources();
+        vm_resources.boot_timer = true;
+
+        // An invalid resource leaves the configuration untouched.
+        match vm_resources.apply_vmm_config(vmm_config(0, "")) {
+            Err(Error::VmConfig(VmConfigError::InvalidVcpuCount)) => (),
+            _ => unreachable!(),
+        }
+        assert_eq!(vm_resources.block.configs()[0].drive_id, "block1");
+        assert_eq!(vm_resources.vm_config().vcpu_count, Some(1));
+
+        // The logger and the metrics cannot be configured along with the microVM.
+        let metrics = r#", "metrics": { "metrics_path": "/tmp/metrics" }"#;
+        match vm_resources.apply_vmm_config(vmm_config(2, metrics)) {
+            Err(err @ Error::NotMicrovmConfig) => assert_eq!(
+                err.to_string(),
+                "The logger and the metrics must be configured with their own API requests."
+            ),
+            _ => unreachable!(),
+        }
+        assert_eq!(vm_resources.vm_config().vcpu_count, Some(1));
+
+        vm_resources.apply_vmm_config(vmm_config(2, "")).unwrap();
+        assert_eq!(vm_resources.block.configs().len(), 1);
+        assert_eq!(vm_resources.block.configs()[0].drive_id, "rootfs");
+        assert_eq!(vm_resources.vm_config().vcpu_count, Some(2));
+        assert!(vm_resources.net_builder.configs().is_empty());
+        assert!(vm_resources.boot_timer);
+    }
+
+    #[test]
+    fn test_landlock_paths() {
+        let mut vm_resources = default_vm_resources();
+        let boot_source = &vm_resources.boot_source().unwrap().description;
+        let kernel_path = PathBuf::from(boot_source.kernel_image_path.as_ref().unwrap());
+        let block_path = PathBuf::from(&vm_resources.block.configs()[0].path_on_host);
+
+        let paths = vm_resources.landlock_paths();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(
+            paths[0],
+            AllowedPath {
+                path: kernel_path.clone(),
+                read_only: true,
+            }
+        );
+        // The initrd is the same file as the kernel.
+        assert_eq!(paths[1].path, kernel_path);
+        assert_eq!(
+            paths[2],
+            AllowedPath {
+                path: block_path,
+                read_only: false,
+            }
+        );
+
+        vm_resources.landlock = Some(vec![PathBuf::from("/srv/snapshots")]);
+        assert_eq!(
+            vm_resources.landlock_paths().last(),
+            Some(&AllowedPath {
+                path: PathBuf::from("/srv/snapshots"),
+                read_only: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_vmm_config() {
+        let manifest_dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        #[cfg(target_arch = "x86_64")]
+        let kernel_image = "kernel/src/loader/test_elf.bin";
+        #[cfg(target_arch = "aarch64")]
+        let kernel_image = "kernel/src/loader/test_pe.bin";
+        let kernel_path = manifest_dir.parent().unwrap().join(kernel_image);
+        let bad_kernel_file = TempFile::new().unwrap();
+        let rootfs_file = TempFile::new().unwrap();
+        let vmm_config = |kernel_path: &str, mem_size_mib: u64, extra: &str| -> VmmConfig {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "boot-source": {{
+                        "kernel_image_path": "{}"
+                    }},
+                    "drives": [
+                        {{
+                            "drive_id": "rootfs",
+                            "path_on_host": "{}",
+                            "is_root_device": true,
+                            "is_read_only": false
+                        }}
+                    ],
+                    "machine-config": {{
+                        "vcpu_count": 2,
+                        "mem_size_mib": {},
+                        "ht_enabled": false
+                    }}{}
+                }}"#,
+                kernel_path,
+                rootfs_file.as_path().to_str().unwrap(),
+                mem_size_mib,
+                extra
+            ))
+            .unwrap()
+        };
+        let kernel_path = kernel_path.to_str().unwrap();
+
+        vmm_config(kernel_path, 128, "").validate().unwrap();
+
+        match vmm_config(bad_kernel_file.as_path().to_str().unwrap(), 128, "").validate() {
+            Err(Error::InvalidKernel(_)) => (),
+            _ => unreachable!(),
+        }
+        match vmm_config("/invalid/path", 128, "").validate() {
+            Err(Error::BootSource(BootSourceConfigError::InvalidKernelPath(_))) => (),
+            _ => unreachable!(),
+        }
+        match vmm_config(kernel_path, host_memory_mib() + 1, "").validate() {
+            Err(Error::HostMemoryExceeded(_, _)) => (),
+            _ => unreachable!(),
+        }
+
+        let initrd = TempFile::new().unwrap();
+        initrd.as_file().set_len(129 << 20).unwrap();
+        let mut config = vmm_config(kernel_path, 128, "");
+        config.boot_source.initrd_paths = vec![initrd.as_path().to_str().unwrap().to_string()];
+        match config.validate() {
+            Err(Error::InitrdTooLarge(size, _)) => assert_eq!(size, 129 << 20),
+            _ => unreachable!(),
+        }
+
+        let net = |iface_id: &str, host_dev_name: &str, guest_mac: &str| {
+            format!(
+                r#"{{ "iface_id": "{}", "host_dev_name": "{}", "guest_mac": "{}" }}"#,
+                iface_id, host_dev_name, guest_mac
+            )
+        };
+        let extra = format!(
+            r#", "network-interfaces": [{}, {}]"#,
+            net("eth0", "tap0", "06:00:00:00:00:01"),
+            net("eth1", "tap1", "06:00:00:00:00:02")
+        );
+        vmm_config(kernel_path, 128, &extra).validate().unwrap();
+        let extra = format!(
+            r#", "network-interfaces": [{}, {}]"#,
+            net("eth0", "tap0", "06:00:00:00:00:01"),
+            net("eth1", "tap0", "06:00:00:00:00:02")
+        );
+        match vmm_config(kernel_path, 128, &extra).validate() {
+            Err(Error::TapDeviceInUse(name)) => assert_eq!(name, "tap0"),
+            _ => unreachable!(),
+        }
+        let extra = format!(
+            r#", "network-interfaces": [{}, {}]"#,
+            net("eth0", "tap0", "06:00:00:00:00:01"),
+            net("eth1", "tap1", "06:00:00:00:00:01")
+        );
+        match vmm_config(kernel_path, 128, &extra).validate() {
+            Err(Error::NetDevice(NetworkInterfaceError::GuestMacAddressInUse(_))) => (),
+            _ => unreachable!(),
+        }
+
+        #[cfg(feature = "vsock")]
+        {
+            let extra =
+                r#", "vsock": { "vsock_id": "vsock", "guest_cid": 2, "uds_path": "v.sock" }"#;
+            match vmm_config(kernel_path, 128, extra).validate() {
+                Err(Error::InvalidVsockCid(2)) => (),
+                _ => unreachable!(),
+            }
+        }
+
+        let metrics = r#", "metrics": { "metrics_path": "/tmp/metrics" }"#;
+        match vmm_config(kernel_path, 128, metrics).validate() {
+            Err(Error::NotMicrovmConfig) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_full_config() {
+        let mut vm_resources = default_vm_resources();
+        let full_config = vm_resources.full_config(InstanceState::NotStarted);
+        assert_eq!(
+            full_config.boot_source.as_ref(),
+            Some(&vm_resources.boot_source().unwrap().description)
+        );
+        assert_eq!(full_config.block_devices, vm_resources.block.configs());
+        assert_eq!(full_config.block_devices[0].drive_id, "block1");
+        // The default rate limiter limits nothing, so it is not part of the configuration.
+        assert!(full_config.block_devices[0].rate_limiter.is_none());
+        assert_eq!(&full_config.machine_config, vm_resources.vm_config());
+        assert!(full_config.mmds_config.is_none());
+        assert!(full_config.net_devices.is_empty());
+
+        vm_resources
+            .set_mmds_config(MmdsConfig {
+                ipv4_address: Some(Ipv4Addr::new(169, 254, 170, 2)),
+                ..Default::default()
+            })
+            .unwrap();
+        let full_config = vm_resources.full_config(InstanceState::Paused);
+        assert_eq!(
+            full_config.mmds_config.unwrap().ipv4_address,
+            Some(Ipv4Addr::new(169, 254, 170, 2))
+        );
+
+        let json =
+            serde_json::to_value(vm_resources.full_config(InstanceState::NotStarted)).unwrap();
+        assert_eq!(json["state"], "Not started");
+        assert_eq!(json["drives"][0]["drive_id"], "block1");
+        assert_eq!(json["machine-config"]["vcpu_count"], 1);
+        assert_eq!(json["mmds-config"]["ipv4_address"], "169.254.170.2");
+    }
+
     #[test]
     fn test_set_boot_source() {
         let tmp_file = TempFile::new().unwrap();
         let cmdline = "reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0";
         let expected_boot_cfg = BootSourceConfig {
-            kernel_image_path: String::from(tmp_file.as_path().to_str().unwrap()),
+            kernel_image_path: Some(String::from(tmp_file.as_path().to_str().unwrap())),
             initrd_path: Some(String::from(tmp_file.as_path().to_str().unwrap())),
+            initrd_paths: Vec::new(),
             boot_args: Some(cmdline.to_string()),
+            #[cfg(target_arch = "x86_64")]
+            firmware_path: None,
+            #[cfg(target_arch = "x86_64")]
+            nvram_path: None,
         };
 
         let mut vm_resources = default_vm_resources();
@@ -904,10 +2885,9 @@ mod tests {
         let tmp_ino = tmp_file.as_file().metadata().unwrap().st_ino();
 
         assert_ne!(boot_cfg.cmdline.as_str(), cmdline);
-        assert_ne!(boot_cfg.kernel_file.metadata().unwrap().st_ino(), tmp_ino);
         assert_ne!(
             boot_cfg
-                .initrd_file
+                .kernel_file
                 .as_ref()
                 .unwrap()
                 .metadata()
@@ -915,14 +2895,20 @@ mod tests {
                 .st_ino(),
             tmp_ino
         );
+        assert_ne!(
+            boot_cfg.initrd_files[0].metadata().unwrap().st_ino(),
+            tmp_ino
+        );
 
-        vm_resources.set_boot_source(expected_boot_cfg).unwrap();
+        vm_resources
+            .set_boot_source(expected_boot_cfg.clone())
+            .unwrap();
         let boot_cfg = vm_resources.boot_source().unwrap();
+        assert_eq!(boot_cfg.description, expected_boot_cfg);
         assert_eq!(boot_cfg.cmdline.as_str(), cmdline);
-        assert_eq!(boot_cfg.kernel_file.metadata().unwrap().st_ino(), tmp_ino);
         assert_eq!(
             boot_cfg
-                .initrd_file
+                .kernel_file
                 .as_ref()
                 .unwrap()
                 .metadata()
@@ -930,6 +2916,154 @@ mod tests {
                 .st_ino(),
             tmp_ino
         );
+        assert_eq!(
+            boot_cfg.initrd_files[0].metadata().unwrap().st_ino(),
+            tmp_ino
+        );
+    }
+
+    #[test]
+    fn test_set_boot_source_initrd_images() {
+        let kernel = TempFile::new().unwrap();
+        let first = TempFile::new().unwrap();
+        first.as_file().set_len(5).unwrap();
+        let second = TempFile::new().unwrap();
+        second.as_file().set_len(8).unwrap();
+        let path = |file: &TempFile| file.as_path().to_str().unwrap().to_string();
+        let mut boot_source_cfg = BootSourceConfig {
+            kernel_image_path: Some(path(&kernel)),
+            initrd_paths: vec![path(&first), path(&second)],
+            ..Default::default()
+        };
+
+        let mut vm_resources = default_vm_resources();
+        vm_resources
+            .set_boot_source(boot_source_cfg.clone())
+            .unwrap();
+        let boot_cfg = vm_resources.boot_source().unwrap();
+        let inodes: Vec<u64> = boot_cfg
+            .initrd_files
+            .iter()
+            .map(|file| file.metadata().unwrap().st_ino())
+            .collect();
+        assert_eq!(
+            inodes,
+            vec![
+                first.as_file().metadata().unwrap().st_ino(),
+                second.as_file().metadata().unwrap().st_ino()
+            ]
+        );
+        // The second image is aligned to 4 bytes.
+        assert_eq!(boot_cfg.initrd_size().unwrap(), 16);
+
+        boot_source_cfg
+            .initrd_paths
+            .push("/invalid/initrd".to_string());
+        match vm_resources.set_boot_source(boot_source_cfg.clone()) {
+            Err(BootSourceConfigError::InvalidInitrdPath(_)) => (),
+            _ => unreachable!(),
+        }
+
+        boot_source_cfg.initrd_paths.pop();
+        boot_source_cfg.initrd_path = Some(path(&first));
+        match vm_resources.set_boot_source(boot_source_cfg) {
+            Err(BootSourceConfigError::ConflictingInitrdPaths) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_set_firmware_boot_source() {
+        let firmware = TempFile::new().unwrap();
+        firmware.as_file().set_len(0x20_0000).unwrap();
+        let nvram = TempFile::new().unwrap();
+        nvram.as_file().set_len(0x8_4000).unwrap();
+        let path = |file: &TempFile| Some(file.as_path().to_str().unwrap().to_string());
+        let firmware_cfg = BootSourceConfig {
+            kernel_image_path: None,
+            initrd_path: None,
+            initrd_paths: Vec::new(),
+            boot_args: None,
+            firmware_path: path(&firmware),
+            nvram_path: path(&nvram),
+        };
+
+        let mut vm_resources = default_vm_resources();
+        vm_resources.set_boot_source(firmware_cfg.clone()).unwrap();
+        let boot_cfg = vm_resources.boot_source().unwrap();
+        assert!(boot_cfg.kernel_file.is_none());
+        assert!(boot_cfg.firmware_file.is_some());
+        assert!(boot_cfg.nvram_file.is_some());
+
+        // The variable store is optional.
+        let mut cfg = firmware_cfg.clone();
+        cfg.nvram_path = None;
+        vm_resources.set_boot_source(cfg).unwrap();
+        assert!(vm_resources.boot_source().unwrap().nvram_file.is_none());
+
+        // Either a kernel or a firmware is booted.
+        let mut cfg = firmware_cfg.clone();
+        cfg.firmware_path = None;
+        cfg.nvram_path = None;
+        match vm_resources.set_boot_source(cfg) {
+            Err(BootSourceConfigError::MissingBootImage) => (),
+            _ => unreachable!(),
+        }
+        let mut cfg = firmware_cfg.clone();
+        cfg.kernel_image_path = path(&firmware);
+        match vm_resources.set_boot_source(cfg) {
+            Err(BootSourceConfigError::KernelWithFirmware) => (),
+            _ => unreachable!(),
+        }
+        let mut cfg = firmware_cfg.clone();
+        cfg.initrd_path = path(&firmware);
+        match vm_resources.set_boot_source(cfg) {
+            Err(BootSourceConfigError::KernelWithFirmware) => (),
+            _ => unreachable!(),
+        }
+        let mut cfg = firmware_cfg.clone();
+        cfg.initrd_paths = vec![firmware.as_path().to_str().unwrap().to_string()];
+        match vm_resources.set_boot_source(cfg) {
+            Err(BootSourceConfigError::KernelWithFirmware) => (),
+            _ => unreachable!(),
+        }
+        let mut cfg = firmware_cfg.clone();
+        cfg.firmware_path = None;
+        cfg.kernel_image_path = path(&firmware);
+        match vm_resources.set_boot_source(cfg) {
+            Err(BootSourceConfigError::NvramWithoutFirmware) => (),
+            _ => unreachable!(),
+        }
+
+        let mut cfg = firmware_cfg.clone();
+        cfg.firmware_path = Some("/invalid/firmware".to_string());
+        match vm_resources.set_boot_source(cfg) {
+            Err(BootSourceConfigError::InvalidFirmwarePath(_)) => (),
+            _ => unreachable!(),
+        }
+        let mut cfg = firmware_cfg.clone();
+        cfg.nvram_path = Some("/invalid/nvram".to_string());
+        match vm_resources.set_boot_source(cfg) {
+            Err(BootSourceConfigError::InvalidNvramPath(_)) => (),
+            _ => unreachable!(),
+        }
+
+        // The firmware must be made of pages and fit below 4 GiB along with its store.
+        firmware.as_file().set_len(0x20_0001).unwrap();
+        match vm_resources.set_boot_source(firmware_cfg.clone()) {
+            Err(BootSourceConfigError::InvalidFirmwareSize(size)) => {
+                assert_eq!(size, 0x28_4001)
+            }
+            _ => unreachable!(),
+        }
+        firmware.as_file().set_len(0xf8_0000).unwrap();
+        match vm_resources.set_boot_source(firmware_cfg) {
+            Err(BootSourceConfigError::InvalidFirmwareSize(_)) => (),
+            _ => unreachable!(),
+        }
+        // A failed configuration leaves the previous one in place.
+        assert!(vm_resources.boot_source().unwrap().firmware_file.is_some());
     }
 
     #[test]
@@ -945,6 +3079,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "vsock")]
     fn test_set_vsock_device() {
         let mut vm_resources = default_vm_resources();
         let mut tmp_sock_file = TempFile::new().unwrap();
@@ -961,6 +3096,205 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg(feature = "null-devices")]
+    fn test_set_null_device() {
+        let mut vm_resources = default_vm_resources();
+        assert_eq!(vm_resources.null_devices.iter().count(), 0);
+
+        let null_device_cfg = NullDeviceConfig {
+            device_id: "gpu0".to_string(),
+            device_type: NullDeviceType::Gpu,
+        };
+        vm_resources
+            .set_null_device(null_device_cfg.clone())
+            .unwrap();
+        assert_eq!(vm_resources.null_devices.configs(), vec![null_device_cfg]);
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-pmem")]
+    fn test_set_pmem_device() {
+        use crate::vmm_config::pmem::tests::{backing_file, default_config};
+        use devices::virtio::pmem::PMEM_ALIGNMENT;
+
+        let mut vm_resources = default_vm_resources();
+        let file = backing_file(PMEM_ALIGNMENT);
+        vm_resources
+            .set_pmem_device(default_config("pmem0", &file))
+            .unwrap();
+        assert_eq!(vm_resources.pmem.iter().count(), 1);
+
+        let file = backing_file(0);
+        assert!(vm_resources
+            .set_pmem_device(default_config("pmem1", &file))
+            .is_err());
+        assert_eq!(vm_resources.pmem.iter().count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-fs")]
+    fn test_set_shared_fs() {
+        use crate::vmm_config::shared_fs::tests::{default_config, spawn_backend};
+
+        let mut vm_resources = default_vm_resources();
+        assert!(!vm_resources.shared_guest_memory());
+
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let socket_path = tmp_sock_file.as_path().to_str().unwrap();
+        let backend = spawn_backend(socket_path);
+        vm_resources
+            .set_shared_fs(default_config("fs0", "myfs", socket_path))
+            .unwrap();
+        let _conn = backend.join().unwrap();
+        assert_eq!(vm_resources.shared_fs.iter().count(), 1);
+        assert!(vm_resources.shared_guest_memory());
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-rng")]
+    fn test_set_entropy_device() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.entropy.get().is_none());
+
+        vm_resources
+            .set_entropy_device(EntropyDeviceConfig::default())
+            .unwrap();
+        assert!(vm_resources.entropy.get().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-console")]
+    fn test_set_console_device() {
+        use crate::vmm_config::console::tests::{socket_path, uds_port};
+
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.console.get().is_none());
+
+        let config = ConsoleConfig {
+            ports: vec![uds_port("console", &socket_path(), true)],
+        };
+        vm_resources.set_console_device(config).unwrap();
+        assert!(vm_resources.console.get().is_some());
+
+        let config = ConsoleConfig { ports: vec![] };
+        assert!(vm_resources.set_console_device(config).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_set_serial_port() {
+        use crate::vmm_config::serial::tests::file_port;
+        use utils::tempfile::TempFile;
+
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.serial_ports.configs().is_empty());
+
+        let file = TempFile::new().unwrap();
+        vm_resources
+            .set_serial_port(file_port("com2", &file))
+            .unwrap();
+        assert_eq!(vm_resources.serial_ports.configs().len(), 1);
+
+        match vm_resources.set_serial_port(file_port("com1", &file)) {
+            Err(SerialConfigError::InvalidPortId(_)) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_set_shutdown_behavior() {
+        use crate::vmm_config::shutdown_behavior::ShutdownSnapshotConfig;
+
+        let mut vm_resources = default_vm_resources();
+        assert_eq!(
+            vm_resources.shutdown_behavior,
+            ShutdownBehaviorConfig::default()
+        );
+
+        let config = ShutdownBehaviorConfig {
+            snapshot: Some(ShutdownSnapshotConfig {
+                snapshot_path: PathBuf::from("/tmp/vm.snap"),
+                mem_file_path: PathBuf::from("/tmp/vm.mem"),
+            }),
+        };
+        vm_resources.set_shutdown_behavior(config.clone());
+        assert_eq!(vm_resources.shutdown_behavior, config);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_set_watchdog() {
+        use crate::vmm_config::watchdog::WatchdogAction;
+
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.watchdog.is_none());
+
+        let config = WatchdogConfig {
+            action: WatchdogAction::Stop,
+        };
+        vm_resources.set_watchdog(config.clone());
+        assert_eq!(vm_resources.watchdog, Some(config));
+    }
+
+    #[test]
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    fn test_set_tpm() {
+        use crate::vmm_config::tpm::{SoftwareTpmConfig, TpmBackendConfig};
+
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.tpm.is_none());
+
+        let config = TpmConfig {
+            backend: TpmBackendConfig::Software(SoftwareTpmConfig {
+                state_path: "/tpm".to_string(),
+            }),
+        };
+        vm_resources.set_tpm(config.clone());
+        assert_eq!(vm_resources.tpm, Some(config));
+    }
+
+    #[test]
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    fn test_set_vfio_device() {
+        let mut vm_resources = default_vm_resources();
+        let config = VfioConfig {
+            vfio_id: "nic".to_string(),
+            sysfs_path: PathBuf::from("/sys/bus/pci/devices/0000:ff:1f.7"),
+        };
+        assert_eq!(
+            vm_resources.set_vfio_device(config.clone()),
+            Err(VfioConfigError::NotBoundToVfio(config.sysfs_path))
+        );
+        assert_eq!(vm_resources.vfio.iter().count(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "virtio-mem")]
+    fn test_set_memory_hotplug() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.memory_hotplug.is_none());
+
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 3,
+        };
+        match vm_resources.set_memory_hotplug(config) {
+            Err(MemoryHotplugConfigError::InvalidBlockSize) => (),
+            _ => unreachable!(),
+        }
+        assert!(vm_resources.memory_hotplug.is_none());
+
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        };
+        vm_resources.set_memory_hotplug(config.clone()).unwrap();
+        assert_eq!(vm_resources.memory_hotplug, Some(config));
+    }
+
     #[test]
     fn test_set_net_device() {
         let mut vm_resources = default_vm_resources();
@@ -975,4 +3309,245 @@ mod tests {
         vm_resources.build_net_device(new_net_device_cfg).unwrap();
         assert_eq!(vm_resources.net_builder.len(), 2);
     }
+
+    #[test]
+    fn test_set_rate_limiter_group() {
+        use crate::vmm_config::TokenBucketConfig;
+
+        let mut vm_resources = default_vm_resources();
+        let group_config = RateLimiterGroupConfig {
+            group_id: "disks".to_string(),
+            bandwidth: Some(TokenBucketConfig {
+                size: 1000,
+                one_time_burst: None,
+                refill_time: 100,
+            }),
+            ops: None,
+        };
+
+        // The devices can only be attached to an existing group.
+        let (mut block_cfg, _file) = default_block_cfg();
+        block_cfg.rate_limiter_group = Some("disks".to_string());
+        match vm_resources.set_block_device(block_cfg.clone()) {
+            Err(DriveError::RateLimiterGroupNotFound(group_id)) => assert_eq!(group_id, "disks"),
+            _ => unreachable!(),
+        }
+        let mut net_cfg = default_net_cfg();
+        net_cfg.rate_limiter_group = Some("disks".to_string());
+        match vm_resources.build_net_device(net_cfg.clone()) {
+            Err(NetworkInterfaceError::RateLimiterGroupNotFound(group_id)) => {
+                assert_eq!(group_id, "disks")
+            }
+            _ => unreachable!(),
+        }
+
+        vm_resources
+            .set_rate_limiter_group(group_config.clone())
+            .unwrap();
+        vm_resources.set_block_device(block_cfg).unwrap();
+        vm_resources.build_net_device(net_cfg).unwrap();
+        let full_config = vm_resources.full_config(InstanceState::NotStarted);
+        assert_eq!(full_config.rate_limiter_groups, vec![group_config.clone()]);
+        assert_eq!(
+            full_config.block_devices[0].rate_limiter_group,
+            Some("disks".to_string())
+        );
+        assert_eq!(
+            full_config.net_devices[0].rate_limiter_group,
+            Some("disks".to_string())
+        );
+
+        // The group cannot be replaced while devices are attached to it.
+        assert_eq!(
+            vm_resources.set_rate_limiter_group(group_config),
+            Err(RateLimiterGroupError::GroupInUse("disks".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_set_mmds_config() {
+        let mut vm_resources = default_vm_resources();
+        let mut net_cfg = default_net_cfg();
+        net_cfg.iface_id = "mmds_net_if".to_string();
+        net_cfg.guest_mac = Some(MacAddr::parse_str("01:23:45:67:89:0d").unwrap());
+        net_cfg.host_dev_name = "mmds_dummy_path".to_string();
+        net_cfg.allow_mmds_requests = true;
+        vm_resources.build_net_device(net_cfg).unwrap();
+
+        let invalid_cfg = MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::V2,
+            hop_limit: Some(0),
+            network_interfaces: None,
+            dynamic_fields: None,
+            data_store_limit: None,
+        };
+        match vm_resources.set_mmds_config(invalid_cfg) {
+            Err(MmdsConfigError::InvalidHopLimit) => (),
+            _ => unreachable!(),
+        }
+        assert!(vm_resources.mmds_config.is_none());
+
+        let cfg = MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::V2,
+            hop_limit: Some(1),
+            network_interfaces: None,
+            dynamic_fields: None,
+            data_store_limit: None,
+        };
+        vm_resources.set_mmds_config(cfg).unwrap();
+        assert_eq!(MMDS.lock().unwrap().version(), MmdsVersion::V2);
+        let net = vm_resources
+            .net_builder
+            .iter()
+            .find(|net| net.lock().unwrap().id() == "mmds_net_if")
+            .unwrap()
+            .clone();
+        assert_eq!(net.lock().unwrap().mmds_ns_mut().unwrap().hop_limit(), 1);
+
+        vm_resources
+            .set_mmds_config(MmdsConfig {
+                ipv4_address: None,
+                ipv6_address: None,
+                version: MmdsVersion::V1,
+                hop_limit: None,
+                network_interfaces: None,
+                dynamic_fields: None,
+                data_store_limit: None,
+            })
+            .unwrap();
+        assert_eq!(MMDS.lock().unwrap().version(), MmdsVersion::V1);
+
+        let invalid_cfg = MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::V2,
+            hop_limit: None,
+            network_interfaces: None,
+            dynamic_fields: Some(vec![MmdsDynamicField {
+                path: "meta-data/time".to_string(),
+                value: DynamicValue::EpochTime,
+            }]),
+            data_store_limit: None,
+        };
+        match vm_resources.set_mmds_config(invalid_cfg) {
+            Err(MmdsConfigError::InvalidDynamicField(_)) => (),
+            _ => unreachable!(),
+        }
+        assert_eq!(MMDS.lock().unwrap().version(), MmdsVersion::V1);
+
+        let invalid_cfg = MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: Some(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x254)),
+            version: MmdsVersion::V1,
+            hop_limit: None,
+            network_interfaces: None,
+            dynamic_fields: None,
+            data_store_limit: None,
+        };
+        match vm_resources.set_mmds_config(invalid_cfg) {
+            Err(MmdsConfigError::InvalidIpv6Addr) => (),
+            _ => unreachable!(),
+        }
+        let ipv6_addr = Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254);
+        let cfg = MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: Some(ipv6_addr),
+            version: MmdsVersion::V1,
+            hop_limit: None,
+            network_interfaces: None,
+            dynamic_fields: None,
+            data_store_limit: None,
+        };
+        vm_resources.set_mmds_config(cfg).unwrap();
+        assert_eq!(
+            net.lock().unwrap().mmds_ns_mut().unwrap().ipv6_addr(),
+            Some(ipv6_addr)
+        );
+
+        let cfg = MmdsConfig {
+            ipv4_address: None,
+            ipv6_address: None,
+            version: MmdsVersion::V1,
+            hop_limit: None,
+            network_interfaces: None,
+            dynamic_fields: None,
+            data_store_limit: Some(1024),
+        };
+        vm_resources.set_mmds_config(cfg).unwrap();
+        assert_eq!(MMDS.lock().unwrap().data_store_limit(), 1024);
+    }
+
+    #[test]
+    fn test_mmds_network_interfaces() {
+        let mut vm_resources = default_vm_resources();
+        let mut net_cfg = default_net_cfg();
+        net_cfg.iface_id = "mmds_net_if".to_string();
+        net_cfg.guest_mac = Some(MacAddr::parse_str("01:23:45:67:89:0e").unwrap());
+        net_cfg.host_dev_name = "mmds_bind_path".to_string();
+        net_cfg.allow_mmds_requests = true;
+        vm_resources.build_net_device(net_cfg).unwrap();
+
+        let mmds_cfg = |network_interfaces: Option<Vec<&str>>| MmdsConfig {
+            ipv4_address: Some(Ipv4Addr::new(169, 254, 170, 2)),
+            ipv6_address: None,
+            version: MmdsVersion::V1,
+            hop_limit: None,
+            network_interfaces: network_interfaces
+                .map(|ids| ids.into_iter().map(String::from).collect()),
+            dynamic_fields: None,
+            data_store_limit: None,
+        };
+        let mmds_enabled = |vm_resources: &VmResources, iface_id: &str| {
+            vm_resources
+                .net_builder
+                .iter()
+                .find(|net| net.lock().unwrap().id() == iface_id)
+                .unwrap()
+                .lock()
+                .unwrap()
+                .mmds_ns_mut()
+                .is_some()
+        };
+
+        match vm_resources.set_mmds_config(mmds_cfg(Some(vec![]))) {
+            Err(MmdsConfigError::EmptyNetworkInterfaces) => (),
+            _ => unreachable!(),
+        }
+        match vm_resources.set_mmds_config(mmds_cfg(Some(vec!["net_if1", "foo"]))) {
+            Err(MmdsConfigError::InvalidNetworkInterfaceId(iface_id)) => {
+                assert_eq!(iface_id, "foo")
+            }
+            _ => unreachable!(),
+        }
+        assert!(vm_resources.mmds_config.is_none());
+        assert!(!mmds_enabled(&vm_resources, "net_if1"));
+        assert!(mmds_enabled(&vm_resources, "mmds_net_if"));
+
+        // The MMDS moves to the listed interface only.
+        vm_resources
+            .set_mmds_config(mmds_cfg(Some(vec!["net_if1"])))
+            .unwrap();
+        assert!(mmds_enabled(&vm_resources, "net_if1"));
+        assert!(!mmds_enabled(&vm_resources, "mmds_net_if"));
+        let net = vm_resources.net_builder.iter().next().unwrap().clone();
+        assert_eq!(
+            net.lock().unwrap().mmds_ns_mut().unwrap().ipv4_addr(),
+            Ipv4Addr::new(169, 254, 170, 2)
+        );
+
+        // Interfaces configured afterwards are bound according to the list.
+        let mut net_cfg = default_net_cfg();
+        net_cfg.host_dev_name = "mmds_bind_path2".to_string();
+        vm_resources.build_net_device(net_cfg).unwrap();
+        assert!(mmds_enabled(&vm_resources, "net_if1"));
+
+        // Without a list, the interfaces keep their current binding.
+        vm_resources.set_mmds_config(mmds_cfg(None)).unwrap();
+        assert!(mmds_enabled(&vm_resources, "net_if1"));
+        assert!(!mmds_enabled(&vm_resources, "mmds_net_if"));
+    }
 }
@@ -2,44 +2,95 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
 use std::result;
 use std::sync::{Arc, Mutex};
 
 #[cfg(not(test))]
-use super::{builder::build_microvm_for_boot, resources::VmResources, Vmm};
+use super::{
+    boot_check::check_boot_resources, builder::build_microvm_for_boot, resources::VmResources, Vmm,
+};
 #[cfg(all(not(test), target_arch = "x86_64"))]
-use super::{persist::create_snapshot, persist::restore_from_snapshot};
+use super::{coredump::create_core_dump, persist::create_snapshot, persist::restore_from_snapshot};
 
 #[cfg(test)]
-use tests::{build_microvm_for_boot, MockVmRes as VmResources, MockVmm as Vmm};
+use tests::{
+    build_microvm_for_boot, check_boot_resources, MockVmRes as VmResources, MockVmm as Vmm,
+};
 #[cfg(all(test, target_arch = "x86_64"))]
-use tests::{create_snapshot, restore_from_snapshot};
+use tests::{create_core_dump, create_snapshot, restore_from_snapshot};
 
 use super::Error as VmmError;
+use crate::boot_check::BootCheckError;
 use crate::builder::StartMicrovmError;
 #[cfg(target_arch = "x86_64")]
+use crate::coredump::CoreDumpError;
+#[cfg(target_arch = "x86_64")]
 use crate::persist::{CreateSnapshotError, LoadSnapshotError};
+use crate::resources::{Error as ResourcesError, FullVmConfig, VmmConfig};
 #[cfg(target_arch = "x86_64")]
 use crate::version_map::VERSION_MAP;
+use crate::vmm_config;
+#[cfg(feature = "balloon")]
 use crate::vmm_config::balloon::{
-    BalloonConfigError, BalloonDeviceConfig, BalloonStats, BalloonUpdateConfig,
+    BalloonConfigError, BalloonDeviceConfig, BalloonPolicy, BalloonStats, BalloonUpdateConfig,
     BalloonUpdateStatsConfig,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
-use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError};
-use crate::vmm_config::instance_info::InstanceInfo;
+#[cfg(feature = "virtio-console")]
+use crate::vmm_config::console::{ConsoleConfig, ConsoleConfigError};
+use crate::vmm_config::cpu_quota::{CpuQuotaConfig, CpuQuotaConfigError};
+#[cfg(feature = "block")]
+use crate::vmm_config::drive::{
+    validate_io_weight, BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError,
+};
+#[cfg(feature = "virtio-rng")]
+use crate::vmm_config::entropy::{EntropyConfigError, EntropyDeviceConfig};
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::guest_clock::ClockPolicy;
+use crate::vmm_config::guest_panic::{GuestEvents, PanicAction};
+use crate::vmm_config::instance_info::{InstanceInfo, InstanceState};
 use crate::vmm_config::logger::{LoggerConfig, LoggerConfigError};
 use crate::vmm_config::machine_config::{VmConfig, VmConfigError};
+use crate::vmm_config::machine_stats::{MachineStats, VcpuStats};
+#[cfg(feature = "virtio-mem")]
+use crate::vmm_config::memory_hotplug::{
+    MemoryHotplugConfig, MemoryHotplugConfigError, MemoryHotplugSizeUpdate, VirtioMemStatus,
+};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
+#[cfg(feature = "net")]
 use crate::vmm_config::net::{
-    NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
+    NetDeviceStats, NetRateLimiterStats, NetworkInterfaceConfig, NetworkInterfaceError,
+    NetworkInterfaceUpdateConfig,
 };
+#[cfg(feature = "null-devices")]
+use crate::vmm_config::null_device::{NullDeviceConfig, NullDeviceConfigError};
+#[cfg(feature = "virtio-pmem")]
+use crate::vmm_config::pmem::{PmemConfig, PmemConfigError};
+use crate::vmm_config::rate_limiter_group::{RateLimiterGroupConfig, RateLimiterGroupError};
+use crate::vmm_config::serial::{SerialConfigError, SerialPortConfig};
+#[cfg(feature = "virtio-fs")]
+use crate::vmm_config::shared_fs::{SharedFsConfig, SharedFsConfigError};
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::shutdown_behavior::ShutdownBehaviorConfig;
 #[cfg(target_arch = "x86_64")]
 use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
-use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
-use crate::vmm_config::{self, RateLimiterUpdate};
-use logger::{info, update_metric_with_elapsed_time, METRICS};
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+use crate::vmm_config::tpm::TpmConfig;
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+use crate::vmm_config::vfio::{VfioConfig, VfioConfigError};
+#[cfg(feature = "vsock")]
+use crate::vmm_config::vsock::{
+    VsockConfigError, VsockDeviceConfig, VsockDeviceStats, VsockDeviceUpdateConfig,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::watchdog::WatchdogConfig;
+#[cfg(feature = "block")]
+use crate::vmm_config::RateLimiterStats;
+#[cfg(any(feature = "block", feature = "net", feature = "vsock"))]
+use crate::vmm_config::RateLimiterUpdate;
+use logger::{info, update_metric_with_elapsed_time, Span, METRICS};
 use polly::event_manager::EventManager;
 use seccomp::BpfProgram;
 
@@ -47,6 +98,9 @@ use seccomp::BpfProgram;
 /// bits of information (ids, paths, etc.).
 #[derive(PartialEq)]
 pub enum VmmAction {
+    /// Check that the microVM could be booted from the configured resources, reporting all the
+    /// problems found. This action can only be called before the microVM has booted.
+    BootDryRun,
     /// Configure the boot source of the microVM using as input the `ConfigureBootSource`. This
     /// action can only be called before the microVM has booted.
     ConfigureBootSource(BootSourceConfig),
@@ -56,25 +110,88 @@ pub enum VmmAction {
     /// Configure the metrics using as input the `MetricsConfig`. This action can only be called
     /// before the microVM has booted.
     ConfigureMetrics(MetricsConfig),
+    /// Write the guest memory and the vCPU registers to an ELF core file at the given path. This
+    /// action can only be called after the microVM has booted.
+    #[cfg(target_arch = "x86_64")]
+    CreateCoreDump(PathBuf),
     /// Create a snapshot using as input the `CreateSnapshotParams`. This action can only be called
     /// after the microVM has booted and only when the microVM is in `Paused` state.
     #[cfg(target_arch = "x86_64")]
     CreateSnapshot(CreateSnapshotParams),
     /// Get the balloon device configuration.
+    #[cfg(feature = "balloon")]
     GetBalloonConfig,
     /// Get the ballon device latest statistics.
+    #[cfg(feature = "balloon")]
     GetBalloonStats,
+    /// Get the live state of the rate limiter of the drive with the given ID. This action can
+    /// only be called after the microVM has booted.
+    #[cfg(feature = "block")]
+    GetDriveRateLimiter(String),
+    /// Get the effective configuration of the microVM and its devices, along with its state.
+    GetFullVmConfig,
+    /// Get the state of the guest kernel and the latest crashes it reported. This action can only
+    /// be called after the microVM has booted.
+    GetGuestEvents,
+    /// Get the host resources used by the microVM. This action can only be called after the
+    /// microVM has booted.
+    GetMachineStats,
+    /// Get the status of the hotplug memory. This action can only be called after the microVM
+    /// has booted.
+    #[cfg(feature = "virtio-mem")]
+    GetMemoryHotplugStatus,
+    /// Get the live state of the rate limiters of the network interface with the given ID. This
+    /// action can only be called after the microVM has booted.
+    #[cfg(feature = "net")]
+    GetNetworkInterfaceRateLimiters(String),
+    /// Get the live traffic statistics of the network interface with the given ID. This action
+    /// can only be called after the microVM has booted.
+    #[cfg(feature = "net")]
+    GetNetworkInterfaceStats(String),
+    /// Get the KVM exits of each vCPU, and how its running time splits between the guest and the
+    /// VMM. This action can only be called after the microVM has booted.
+    GetVcpuStats,
     /// Get the configuration of the microVM.
     GetVmConfiguration,
+    /// Get the live per-port traffic statistics and the open connections of the vsock device.
+    /// This action can only be called after the microVM has booted.
+    #[cfg(feature = "vsock")]
+    GetVsockStats,
     /// Flush the metrics. This action can only be called after the logger has been configured.
     FlushMetrics,
+    /// Send CTRL+ALT+DEL to the microVM to shut it down gracefully, and stop it if the guest has
+    /// not shut down after the given timeout, in milliseconds. This action can only be called
+    /// after the microVM has booted.
+    #[cfg(target_arch = "x86_64")]
+    GracefulShutdown(u64),
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
     /// input. This action can only be called before the microVM has booted.
+    #[cfg(feature = "block")]
     InsertBlockDevice(BlockDeviceConfig),
     /// Add a new network interface config or update one that already exists using the
     /// `NetworkInterfaceConfig` as input. This action can only be called before the microVM has
     /// booted.
+    #[cfg(feature = "net")]
     InsertNetworkDevice(NetworkInterfaceConfig),
+    /// Add a new null device or update one that already exists using the `NullDeviceConfig` as
+    /// input. This action can only be called before the microVM has booted.
+    #[cfg(feature = "null-devices")]
+    InsertNullDevice(NullDeviceConfig),
+    /// Add a new persistent memory device or update one that already exists using the
+    /// `PmemConfig` as input. This action can only be called before the microVM has booted.
+    #[cfg(feature = "virtio-pmem")]
+    InsertPmemDevice(PmemConfig),
+    /// Bind a serial port to a host backend or update the one it is already bound to using the
+    /// `SerialPortConfig` as input. This action can only be called before the microVM has booted.
+    InsertSerialPort(SerialPortConfig),
+    /// Add a new shared filesystem or update one that already exists using the `SharedFsConfig`
+    /// as input. This action can only be called before the microVM has booted.
+    #[cfg(feature = "virtio-fs")]
+    InsertSharedFs(SharedFsConfig),
+    /// Pass a host PCI function through or update one that already is using the `VfioConfig`
+    /// as input. This action can only be called before the microVM has booted.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    InsertVfioDevice(VfioConfig),
     /// Load the microVM state using as input the `LoadSnapshotParams`. This action can only be
     /// called before the microVM has booted. If this action is successful, the loaded microVM will
     /// be in `Paused` state. Should change this state to `Resumed` for the microVM to run.
@@ -87,46 +204,133 @@ pub enum VmmAction {
     /// Set the balloon device or update the one that already exists using the
     /// `BalloonDeviceConfig` as input. This action can only be called before the microVM
     /// has booted.
+    #[cfg(feature = "balloon")]
     SetBalloonDevice(BalloonDeviceConfig),
+    /// Set the policy resizing the balloon based on the memory pressure of the host, after
+    /// microVM start.
+    #[cfg(feature = "balloon")]
+    SetBalloonPolicy(BalloonPolicy),
+    /// Set the console device or update the one that already exists using the
+    /// `ConsoleConfig` as input. This action can only be called before the microVM has booted.
+    #[cfg(feature = "virtio-console")]
+    SetConsoleDevice(ConsoleConfig),
+    /// Set the entropy device or update the one that already exists using the
+    /// `EntropyDeviceConfig` as input. This action can only be called before the microVM
+    /// has booted.
+    #[cfg(feature = "virtio-rng")]
+    SetEntropyDevice(EntropyDeviceConfig),
+    /// Set how the guest clock is kept right when the microVM resumes.
+    #[cfg(target_arch = "x86_64")]
+    SetClockPolicy(ClockPolicy),
+    /// Set the share of a host core each vCPU thread may use, using the `CpuQuotaConfig` as
+    /// input.
+    SetCpuQuota(CpuQuotaConfig),
+    /// Replace the configuration of the microVM with the full configuration held in the
+    /// `VmmConfig`, which is validated as a whole before being applied. This action can only be
+    /// called before the microVM has booted.
+    SetFullVmConfig(Box<VmmConfig>),
+    /// Set the hotplug memory region and its virtio-mem device using the `MemoryHotplugConfig`
+    /// as input. This action can only be called before the microVM has booted.
+    #[cfg(feature = "virtio-mem")]
+    SetMemoryHotplug(MemoryHotplugConfig),
     /// Set the MMDS configuration.
     SetMmdsConfiguration(MmdsConfig),
+    /// Set the action taken by the VMM when the guest kernel panics.
+    SetPanicAction(PanicAction),
+    /// Add a new rate limiter group or replace one that already exists using the
+    /// `RateLimiterGroupConfig` as input. This action can only be called before the microVM has
+    /// booted.
+    SetRateLimiterGroup(RateLimiterGroupConfig),
+    /// Set what the VMM does with the microVM before exiting on `SIGTERM`.
+    #[cfg(target_arch = "x86_64")]
+    SetShutdownBehavior(ShutdownBehaviorConfig),
+    /// Set the TPM using `TpmConfig` as input. This action can only be called before the
+    /// microVM has booted.
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    SetTpm(TpmConfig),
     /// Set the vsock device or update the one that already exists using the
     /// `VsockDeviceConfig` as input. This action can only be called before the microVM has
     /// booted.
+    #[cfg(feature = "vsock")]
     SetVsockDevice(VsockDeviceConfig),
     /// Set the microVM configuration (memory & vcpu) using `VmConfig` as input. This
     /// action can only be called before the microVM has booted.
     SetVmConfiguration(VmConfig),
-    /// Launch the microVM. This action can only be called before the microVM has booted.
-    StartMicroVm,
+    /// Set the watchdog using `WatchdogConfig` as input. This action can only be called before
+    /// the microVM has booted.
+    #[cfg(target_arch = "x86_64")]
+    SetWatchdog(WatchdogConfig),
+    /// Launch the microVM, leaving its vCPUs paused until it is resumed if the flag is set.
+    /// This action can only be called before the microVM has booted.
+    StartMicroVm(bool),
     /// Send CTRL+ALT+DEL to the microVM, using the i8042 keyboard function. If an AT-keyboard
     /// driver is listening on the guest end, this can be used to shut down the microVM gracefully.
     #[cfg(target_arch = "x86_64")]
     SendCtrlAltDel,
+    /// Press the ACPI power button of the microVM. If the guest handles it, this can be used to
+    /// shut down the microVM gracefully.
+    #[cfg(target_arch = "x86_64")]
+    SendPowerButton,
+    /// Tear down the microVM, releasing the guest and its devices, for the VMM to accept the
+    /// configuration of another microVM. This action can only be called after the microVM has
+    /// booted.
+    Teardown,
     /// Update the balloon size, after microVM start.
+    #[cfg(feature = "balloon")]
     UpdateBalloon(BalloonUpdateConfig),
     /// Update the balloon statistics polling interval, after microVM start.
+    #[cfg(feature = "balloon")]
     UpdateBalloonStatistics(BalloonUpdateStatsConfig),
     /// Update existing block device properties such as `path_on_host` or `rate_limiter`.
+    #[cfg(feature = "block")]
     UpdateBlockDevice(BlockDeviceUpdateConfig),
+    /// Update the amount of hotplug memory the guest is requested to plug, after microVM start.
+    #[cfg(feature = "virtio-mem")]
+    UpdateMemoryHotplug(MemoryHotplugSizeUpdate),
     /// Update a network interface, after microVM start. Currently, the only updatable properties
     /// are the RX and TX rate limiters.
+    #[cfg(feature = "net")]
     UpdateNetworkInterface(NetworkInterfaceUpdateConfig),
+    /// Update the vsock device, after microVM start. Currently, the only updatable properties
+    /// are the RX and TX rate limiters.
+    #[cfg(feature = "vsock")]
+    UpdateVsockDevice(VsockDeviceUpdateConfig),
+    /// Check that a microVM could be configured and booted from the `VmmConfig`, without
+    /// applying it. This action can be called at any time.
+    ValidateVmConfig(Box<VmmConfig>),
 }
 
 /// Wrapper for all errors associated with VMM actions.
 #[derive(Debug)]
 pub enum VmmActionError {
     /// The action `SetBalloonDevice` failed because of bad user input.
+    #[cfg(feature = "balloon")]
     BalloonConfig(BalloonConfigError),
+    /// The action `BootDryRun` found the microVM could not be booted, for all these reasons.
+    BootDryRun(Vec<BootCheckError>),
     /// The action `ConfigureBootSource` failed because of bad user input.
     BootSource(BootSourceConfigError),
+    /// The action `CreateCoreDump` failed.
+    #[cfg(target_arch = "x86_64")]
+    CoreDump(CoreDumpError),
     /// The action `CreateSnapshot` failed.
     #[cfg(target_arch = "x86_64")]
     CreateSnapshot(CreateSnapshotError),
+    /// The action `SetConsoleDevice` failed because of bad user input.
+    #[cfg(feature = "virtio-console")]
+    ConsoleConfig(ConsoleConfigError),
+    /// The action `SetCpuQuota` failed because of bad user input.
+    CpuQuotaConfig(CpuQuotaConfigError),
     /// One of the actions `InsertBlockDevice` or `UpdateBlockDevicePath`
     /// failed because of bad user input.
+    #[cfg(feature = "block")]
     DriveConfig(DriveError),
+    /// The action `SetEntropyDevice` failed because of bad user input.
+    #[cfg(feature = "virtio-rng")]
+    EntropyConfig(EntropyConfigError),
+    /// One of the actions `SetFullVmConfig` or `ValidateVmConfig` failed because of bad user
+    /// input.
+    FullVmConfig(ResourcesError),
     /// Internal Vmm error.
     InternalVmm(VmmError),
     /// Loading a microVM snapshot failed.
@@ -139,19 +343,40 @@ pub enum VmmActionError {
     Logger(LoggerConfigError),
     /// One of the actions `GetVmConfiguration` or `SetVmConfiguration` failed because of bad input.
     MachineConfig(VmConfigError),
+    /// One of the hotplug memory actions failed.
+    #[cfg(feature = "virtio-mem")]
+    MemoryHotplugConfig(MemoryHotplugConfigError),
     /// The action `ConfigureMetrics` failed because of bad user input.
     Metrics(MetricsConfigError),
     /// The action `SetMmdsConfiguration` failed because of bad user input.
     MmdsConfig(MmdsConfigError),
     /// The action `InsertNetworkDevice` failed because of bad user input.
+    #[cfg(feature = "net")]
     NetworkConfig(NetworkInterfaceError),
+    /// The action `InsertNullDevice` failed because of bad user input.
+    #[cfg(feature = "null-devices")]
+    NullDeviceConfig(NullDeviceConfigError),
     /// The requested operation is not supported after starting the microVM.
     OperationNotSupportedPostBoot,
     /// The requested operation is not supported before starting the microVM.
     OperationNotSupportedPreBoot,
+    /// The action `InsertPmemDevice` failed because of bad user input.
+    #[cfg(feature = "virtio-pmem")]
+    PmemConfig(PmemConfigError),
+    /// The action `SetRateLimiterGroup` failed because of bad user input.
+    RateLimiterGroupConfig(RateLimiterGroupError),
+    /// The action `InsertSerialPort` failed because of bad user input.
+    SerialPortConfig(SerialConfigError),
+    /// The action `InsertSharedFs` failed because of bad user input.
+    #[cfg(feature = "virtio-fs")]
+    SharedFsConfig(SharedFsConfigError),
     /// The action `StartMicroVm` failed because of an internal error.
     StartMicrovm(StartMicrovmError),
+    /// The action `InsertVfioDevice` failed because of bad user input.
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    VfioConfig(VfioConfigError),
     /// The action `SetVsockDevice` failed because of bad user input.
+    #[cfg(feature = "vsock")]
     VsockConfig(VsockConfigError),
 }
 
@@ -163,11 +388,29 @@ impl Display for VmmActionError {
             f,
             "{}",
             match self {
+                #[cfg(feature = "balloon")]
                 BalloonConfig(err) => err.to_string(),
+                BootDryRun(errors) => format!(
+                    "The microVM cannot be booted: {}",
+                    errors
+                        .iter()
+                        .map(|err| err.to_string())
+                        .collect::<Vec<_>>()
+                        .join("; ")
+                ),
                 BootSource(err) => err.to_string(),
+                #[cfg(feature = "virtio-console")]
+                ConsoleConfig(err) => err.to_string(),
+                #[cfg(target_arch = "x86_64")]
+                CoreDump(err) => err.to_string(),
+                CpuQuotaConfig(err) => err.to_string(),
                 #[cfg(target_arch = "x86_64")]
                 CreateSnapshot(err) => err.to_string(),
+                #[cfg(feature = "block")]
                 DriveConfig(err) => err.to_string(),
+                #[cfg(feature = "virtio-rng")]
+                EntropyConfig(err) => err.to_string(),
+                FullVmConfig(err) => err.to_string(),
                 InternalVmm(err) => format!("Internal Vmm error: {}", err),
                 #[cfg(target_arch = "x86_64")]
                 LoadSnapshot(err) => format!("Load microVM snapshot error: {}", err),
@@ -178,9 +421,14 @@ impl Display for VmmActionError {
                 }
                 Logger(err) => err.to_string(),
                 MachineConfig(err) => err.to_string(),
+                #[cfg(feature = "virtio-mem")]
+                MemoryHotplugConfig(err) => err.to_string(),
                 Metrics(err) => err.to_string(),
                 MmdsConfig(err) => err.to_string(),
+                #[cfg(feature = "net")]
                 NetworkConfig(err) => err.to_string(),
+                #[cfg(feature = "null-devices")]
+                NullDeviceConfig(err) => err.to_string(),
                 OperationNotSupportedPostBoot => {
                     "The requested operation is not supported after starting the microVM."
                         .to_string()
@@ -189,8 +437,17 @@ impl Display for VmmActionError {
                     "The requested operation is not supported before starting the microVM."
                         .to_string()
                 }
+                #[cfg(feature = "virtio-pmem")]
+                PmemConfig(err) => err.to_string(),
+                RateLimiterGroupConfig(err) => err.to_string(),
+                SerialPortConfig(err) => err.to_string(),
+                #[cfg(feature = "virtio-fs")]
+                SharedFsConfig(err) => err.to_string(),
                 StartMicrovm(err) => err.to_string(),
+                #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+                VfioConfig(err) => err.to_string(),
                 // The action `SetVsockDevice` failed because of bad user input.
+                #[cfg(feature = "vsock")]
                 VsockConfig(err) => err.to_string(),
             }
         )
@@ -202,13 +459,38 @@ impl Display for VmmActionError {
 #[derive(Debug, PartialEq)]
 pub enum VmmData {
     /// The balloon device configuration.
+    #[cfg(feature = "balloon")]
     BalloonConfig(BalloonDeviceConfig),
     /// The latest balloon device statistics.
+    #[cfg(feature = "balloon")]
     BalloonStats(BalloonStats),
+    /// The live state of the rate limiter of a drive.
+    #[cfg(feature = "block")]
+    DriveRateLimiter(RateLimiterStats),
     /// No data is sent on the channel.
     Empty,
+    /// The effective configuration of the microVM.
+    FullVmConfig(FullVmConfig),
+    /// The state of the guest kernel and the latest crashes it reported.
+    GuestEvents(GuestEvents),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
+    /// The host resources used by the microVM.
+    MachineStats(MachineStats),
+    /// The status of the hotplug memory.
+    #[cfg(feature = "virtio-mem")]
+    MemoryHotplugStatus(VirtioMemStatus),
+    /// The live state of the rate limiters of a network interface.
+    #[cfg(feature = "net")]
+    NetworkInterfaceRateLimiters(NetRateLimiterStats),
+    /// The live traffic statistics of a network interface.
+    #[cfg(feature = "net")]
+    NetworkInterfaceStats(NetDeviceStats),
+    /// The KVM exits and the time split of each vCPU.
+    VcpuStats(Vec<VcpuStats>),
+    /// The live traffic statistics of the vsock device.
+    #[cfg(feature = "vsock")]
+    VsockStats(VsockDeviceStats),
 }
 
 /// Shorthand result type for external VMM commands.
@@ -249,6 +531,7 @@ impl<'a> PrebootApiController<'a> {
     /// the message transport.
     ///
     /// Returns a populated `VmResources` object and a running `Vmm` object.
+    #[allow(clippy::too_many_arguments)]
     pub fn build_microvm_from_requests<F, G>(
         seccomp_filter: BpfProgram,
         event_manager: &mut EventManager,
@@ -256,6 +539,9 @@ impl<'a> PrebootApiController<'a> {
         recv_req: F,
         respond: G,
         boot_timer_enabled: bool,
+        gdb_socket: Option<PathBuf>,
+        landlock: Option<Vec<PathBuf>>,
+        allow_teardown: bool,
     ) -> (VmResources, Arc<Mutex<Vmm>>)
     where
         F: Fn() -> VmmAction,
@@ -263,6 +549,9 @@ impl<'a> PrebootApiController<'a> {
     {
         let mut vm_resources = VmResources::default();
         vm_resources.boot_timer = boot_timer_enabled;
+        vm_resources.gdb_socket = gdb_socket;
+        vm_resources.landlock = landlock;
+        vm_resources.allow_teardown = allow_teardown;
         let mut preboot_controller = PrebootApiController::new(
             seccomp_filter,
             instance_info,
@@ -282,9 +571,55 @@ impl<'a> PrebootApiController<'a> {
         (vm_resources, vmm)
     }
 
+    /// Restores and resumes a microVM from the snapshot requested by the `RestoreSnapshot`
+    /// panic action of a torn down one. The restored microVM keeps that panic action.
+    ///
+    /// Returns a populated `VmResources` object and a running `Vmm` object.
+    #[cfg(target_arch = "x86_64")]
+    #[allow(clippy::too_many_arguments)]
+    pub fn restore_microvm(
+        seccomp_filter: BpfProgram,
+        event_manager: &mut EventManager,
+        instance_info: InstanceInfo,
+        load_params: &LoadSnapshotParams,
+        boot_timer_enabled: bool,
+        gdb_socket: Option<PathBuf>,
+        landlock: Option<Vec<PathBuf>>,
+        allow_teardown: bool,
+    ) -> result::Result<(VmResources, Arc<Mutex<Vmm>>), VmmActionError> {
+        let mut vm_resources = VmResources::default();
+        vm_resources.boot_timer = boot_timer_enabled;
+        vm_resources.gdb_socket = gdb_socket;
+        vm_resources.landlock = landlock;
+        vm_resources.allow_teardown = allow_teardown;
+        vm_resources.panic_action = PanicAction::RestoreSnapshot {
+            snapshot_path: load_params.snapshot_path.clone(),
+            mem_file_path: load_params.mem_file_path.clone(),
+        };
+        let vmm = {
+            let mut preboot_controller = PrebootApiController::new(
+                seccomp_filter,
+                instance_info,
+                &mut vm_resources,
+                event_manager,
+            );
+            preboot_controller.load_snapshot(load_params)?;
+            // Safe to unwrap because the snapshot was loaded.
+            preboot_controller.built_vmm.take().unwrap()
+        };
+        Ok((vm_resources, vmm))
+    }
+
     /// Handles the incoming preboot request and provides a response for it.
     /// Returns a built/running `Vmm` after handling a successful `StartMicroVm` request.
     pub fn handle_preboot_request(&mut self, request: VmmAction) -> ActionResult {
+        let mut span = Span::new("vmm_action");
+        let result = self.handle_preboot_action(request);
+        span.record_result(&result);
+        result
+    }
+
+    fn handle_preboot_action(&mut self, request: VmmAction) -> ActionResult {
         use self::VmmAction::*;
 
         match request {
@@ -298,33 +633,89 @@ impl<'a> PrebootApiController<'a> {
             ConfigureMetrics(metrics_cfg) => vmm_config::metrics::init_metrics(metrics_cfg)
                 .map(|()| VmmData::Empty)
                 .map_err(VmmActionError::Metrics),
+            #[cfg(feature = "balloon")]
             GetBalloonConfig => self.balloon_config(),
+            GetFullVmConfig => Ok(VmmData::FullVmConfig(
+                self.vm_resources.full_config(InstanceState::NotStarted),
+            )),
             GetVmConfiguration => Ok(VmmData::MachineConfiguration(
                 self.vm_resources.vm_config().clone(),
             )),
+            #[cfg(feature = "block")]
             InsertBlockDevice(config) => self.insert_block_device(config),
+            #[cfg(feature = "net")]
             InsertNetworkDevice(config) => self.insert_net_device(config),
+            #[cfg(feature = "null-devices")]
+            InsertNullDevice(config) => self.insert_null_device(config),
+            #[cfg(feature = "virtio-pmem")]
+            InsertPmemDevice(config) => self.insert_pmem_device(config),
+            InsertSerialPort(config) => self.insert_serial_port(config),
+            #[cfg(feature = "virtio-fs")]
+            InsertSharedFs(config) => self.insert_shared_fs(config),
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            InsertVfioDevice(config) => self.insert_vfio_device(config),
             #[cfg(target_arch = "x86_64")]
             LoadSnapshot(config) => self.load_snapshot(&config),
+            #[cfg(feature = "balloon")]
             SetBalloonDevice(config) => self.set_balloon_device(config),
+            #[cfg(feature = "virtio-console")]
+            SetConsoleDevice(config) => self.set_console_device(config),
+            #[cfg(feature = "virtio-rng")]
+            SetEntropyDevice(config) => self.set_entropy_device(config),
+            #[cfg(target_arch = "x86_64")]
+            SetClockPolicy(policy) => self.set_clock_policy(policy),
+            SetCpuQuota(config) => self.set_cpu_quota(config),
+            SetFullVmConfig(config) => self.set_full_vm_config(*config),
+            #[cfg(feature = "virtio-mem")]
+            SetMemoryHotplug(config) => self.set_memory_hotplug(config),
+            #[cfg(feature = "vsock")]
             SetVsockDevice(config) => self.set_vsock_device(config),
             SetVmConfiguration(config) => self.set_vm_config(config),
             SetMmdsConfiguration(config) => self.set_mmds_config(config),
-            StartMicroVm => self.start_microvm(),
+            SetPanicAction(action) => self.set_panic_action(action),
+            SetRateLimiterGroup(config) => self.set_rate_limiter_group(config),
+            #[cfg(target_arch = "x86_64")]
+            SetShutdownBehavior(config) => self.set_shutdown_behavior(config),
+            #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+            SetTpm(config) => self.set_tpm(config),
+            #[cfg(target_arch = "x86_64")]
+            SetWatchdog(config) => self.set_watchdog(config),
+            StartMicroVm(start_paused) => self.start_microvm(start_paused),
+            ValidateVmConfig(config) => validate_vm_config(*config),
+            BootDryRun => self.boot_dry_run(),
+            FlushMetrics => flush_metrics(),
             // Operations not allowed pre-boot.
-            FlushMetrics
-            | Pause
-            | Resume
-            | GetBalloonStats
-            | UpdateBalloon(_)
-            | UpdateBalloonStatistics(_)
-            | UpdateBlockDevice(_)
+            Pause | Resume | GetGuestEvents | GetMachineStats | GetVcpuStats | Teardown => {
+                Err(VmmActionError::OperationNotSupportedPreBoot)
+            }
+            #[cfg(feature = "block")]
+            GetDriveRateLimiter(_) | UpdateBlockDevice(_) => {
+                Err(VmmActionError::OperationNotSupportedPreBoot)
+            }
+            #[cfg(feature = "net")]
+            GetNetworkInterfaceRateLimiters(_)
+            | GetNetworkInterfaceStats(_)
             | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
+            #[cfg(feature = "balloon")]
+            GetBalloonStats
+            | SetBalloonPolicy(_)
+            | UpdateBalloon(_)
+            | UpdateBalloonStatistics(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
             #[cfg(target_arch = "x86_64")]
-            CreateSnapshot(_) | SendCtrlAltDel => Err(VmmActionError::OperationNotSupportedPreBoot),
+            CreateCoreDump(_) | CreateSnapshot(_) | GracefulShutdown(_) | SendCtrlAltDel
+            | SendPowerButton => Err(VmmActionError::OperationNotSupportedPreBoot),
+            #[cfg(feature = "virtio-mem")]
+            GetMemoryHotplugStatus | UpdateMemoryHotplug(_) => {
+                Err(VmmActionError::OperationNotSupportedPreBoot)
+            }
+            #[cfg(feature = "vsock")]
+            GetVsockStats | UpdateVsockDevice(_) => {
+                Err(VmmActionError::OperationNotSupportedPreBoot)
+            }
         }
     }
 
+    #[cfg(feature = "balloon")]
     fn balloon_config(&mut self) -> ActionResult {
         self.vm_resources
             .balloon
@@ -333,6 +724,7 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::BalloonConfig)
     }
 
+    #[cfg(feature = "block")]
     fn insert_block_device(&mut self, cfg: BlockDeviceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -341,6 +733,7 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::DriveConfig)
     }
 
+    #[cfg(feature = "net")]
     fn insert_net_device(&mut self, cfg: NetworkInterfaceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -349,6 +742,51 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::NetworkConfig)
     }
 
+    #[cfg(feature = "null-devices")]
+    fn insert_null_device(&mut self, cfg: NullDeviceConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_null_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::NullDeviceConfig)
+    }
+
+    #[cfg(feature = "virtio-pmem")]
+    fn insert_pmem_device(&mut self, cfg: PmemConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_pmem_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::PmemConfig)
+    }
+
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    fn insert_vfio_device(&mut self, cfg: VfioConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_vfio_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::VfioConfig)
+    }
+
+    fn insert_serial_port(&mut self, cfg: SerialPortConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_serial_port(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::SerialPortConfig)
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    fn insert_shared_fs(&mut self, cfg: SharedFsConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_shared_fs(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::SharedFsConfig)
+    }
+
+    #[cfg(feature = "balloon")]
     fn set_balloon_device(&mut self, cfg: BalloonDeviceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -357,6 +795,41 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::BalloonConfig)
     }
 
+    #[cfg(feature = "virtio-console")]
+    fn set_console_device(&mut self, cfg: ConsoleConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_console_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::ConsoleConfig)
+    }
+
+    #[cfg(feature = "virtio-rng")]
+    fn set_entropy_device(&mut self, cfg: EntropyDeviceConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_entropy_device(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::EntropyConfig)
+    }
+
+    fn set_full_vm_config(&mut self, cfg: VmmConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .apply_vmm_config(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::FullVmConfig)
+    }
+
+    #[cfg(feature = "virtio-mem")]
+    fn set_memory_hotplug(&mut self, cfg: MemoryHotplugConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_memory_hotplug(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::MemoryHotplugConfig)
+    }
+
     fn set_boot_source(&mut self, cfg: BootSourceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -373,6 +846,44 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::MmdsConfig)
     }
 
+    fn set_panic_action(&mut self, action: PanicAction) -> ActionResult {
+        self.vm_resources.panic_action = action;
+        Ok(VmmData::Empty)
+    }
+
+    fn set_rate_limiter_group(&mut self, cfg: RateLimiterGroupConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources
+            .set_rate_limiter_group(cfg)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::RateLimiterGroupConfig)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn set_clock_policy(&mut self, policy: ClockPolicy) -> ActionResult {
+        self.vm_resources.clock_policy = policy;
+        Ok(VmmData::Empty)
+    }
+
+    fn set_cpu_quota(&mut self, config: CpuQuotaConfig) -> ActionResult {
+        config.validate().map_err(VmmActionError::CpuQuotaConfig)?;
+        self.vm_resources.cpu_quota = config;
+        Ok(VmmData::Empty)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn set_shutdown_behavior(&mut self, config: ShutdownBehaviorConfig) -> ActionResult {
+        self.vm_resources.set_shutdown_behavior(config);
+        Ok(VmmData::Empty)
+    }
+
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    fn set_tpm(&mut self, cfg: TpmConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources.set_tpm(cfg);
+        Ok(VmmData::Empty)
+    }
+
     fn set_vm_config(&mut self, cfg: VmConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -381,6 +892,14 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::MachineConfig)
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn set_watchdog(&mut self, cfg: WatchdogConfig) -> ActionResult {
+        self.boot_path = true;
+        self.vm_resources.set_watchdog(cfg);
+        Ok(VmmData::Empty)
+    }
+
+    #[cfg(feature = "vsock")]
     fn set_vsock_device(&mut self, cfg: VsockDeviceConfig) -> ActionResult {
         self.boot_path = true;
         self.vm_resources
@@ -389,9 +908,22 @@ impl<'a> PrebootApiController<'a> {
             .map_err(VmmActionError::VsockConfig)
     }
 
+    // Reports every reason `start_microvm` would fail for, without building anything.
+    fn boot_dry_run(&self) -> ActionResult {
+        let errors = check_boot_resources(&self.vm_resources);
+        if errors.is_empty() {
+            Ok(VmmData::Empty)
+        } else {
+            Err(VmmActionError::BootDryRun(errors))
+        }
+    }
+
     // On success, this command will end the pre-boot stage and this controller
     // will be replaced by a runtime controller.
-    fn start_microvm(&mut self) -> ActionResult {
+    fn start_microvm(&mut self, start_paused: bool) -> ActionResult {
+        let _span = Span::new("start_microvm");
+        // The configuration may already ask for the vCPUs to stay paused.
+        self.vm_resources.start_paused |= start_paused;
         build_microvm_for_boot(
             &self.vm_resources,
             &mut self.event_manager,
@@ -408,6 +940,7 @@ impl<'a> PrebootApiController<'a> {
     // On success, this command will end the pre-boot stage and this controller
     // will be replaced by a runtime controller.
     fn load_snapshot(&mut self, load_params: &LoadSnapshotParams) -> ActionResult {
+        let _span = Span::new("load_snapshot");
         let load_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
         if self.boot_path {
@@ -419,14 +952,22 @@ impl<'a> PrebootApiController<'a> {
         let result = restore_from_snapshot(
             &mut self.event_manager,
             &self.seccomp_filter,
+            self.vm_resources.allow_teardown,
             load_params,
             VERSION_MAP.clone(),
         )
         .and_then(|vmm| {
-            let ret = if load_params.resume_vm {
-                vmm.lock().expect("Poisoned lock").resume_vm()
-            } else {
-                Ok(())
+            let ret = {
+                let mut locked_vmm = vmm.lock().expect("Poisoned lock");
+                locked_vmm.set_panic_action(self.vm_resources.panic_action.clone());
+                locked_vmm.set_clock_policy(self.vm_resources.clock_policy);
+                locked_vmm.set_shutdown_behavior(self.vm_resources.shutdown_behavior.clone());
+                locked_vmm.set_cpu_quota(self.vm_resources.cpu_quota.quota_pct);
+                if load_params.resume_vm {
+                    locked_vmm.resume_vm()
+                } else {
+                    Ok(())
+                }
             };
             ret.map(|()| {
                 self.built_vmm = Some(vmm);
@@ -436,9 +977,7 @@ impl<'a> PrebootApiController<'a> {
         })
         .map_err(VmmActionError::LoadSnapshot);
 
-        let elapsed_time_us =
-            update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_load_snapshot, load_start_us);
-        info!("'load snapshot' VMM action took {} us.", elapsed_time_us);
+        update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_load_snapshot, load_start_us);
 
         result
     }
@@ -447,18 +986,32 @@ impl<'a> PrebootApiController<'a> {
 /// Enables RPC interaction with a running Firecracker VMM.
 pub struct RuntimeApiController {
     vmm: Arc<Mutex<Vmm>>,
-    vm_config: VmConfig,
+    vm_resources: VmResources,
 }
 
 impl RuntimeApiController {
     /// Handles the incoming runtime `VmmAction` request and provides a response for it.
     pub fn handle_request(&mut self, request: VmmAction) -> ActionResult {
+        let mut span = Span::new("vmm_action");
+        let result = self.handle_action(request);
+        span.record_result(&result);
+        result
+    }
+
+    fn handle_action(&mut self, request: VmmAction) -> ActionResult {
         use self::VmmAction::*;
         match request {
             // Supported operations allowed post-boot.
             #[cfg(target_arch = "x86_64")]
+            CreateCoreDump(path) => {
+                create_core_dump(&mut self.vmm.lock().expect("Poisoned lock"), &path)
+                    .map(|()| VmmData::Empty)
+                    .map_err(VmmActionError::CoreDump)
+            }
+            #[cfg(target_arch = "x86_64")]
             CreateSnapshot(snapshot_create_cfg) => self.create_snapshot(&snapshot_create_cfg),
-            FlushMetrics => self.flush_metrics(),
+            FlushMetrics => flush_metrics(),
+            #[cfg(feature = "balloon")]
             GetBalloonConfig => self
                 .vmm
                 .lock()
@@ -466,6 +1019,7 @@ impl RuntimeApiController {
                 .balloon_config()
                 .map(|state| VmmData::BalloonConfig(BalloonDeviceConfig::from(state)))
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            #[cfg(feature = "balloon")]
             GetBalloonStats => self
                 .vmm
                 .lock()
@@ -473,11 +1027,92 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
-            GetVmConfiguration => Ok(VmmData::MachineConfiguration(self.vm_config.clone())),
+            #[cfg(feature = "virtio-mem")]
+            GetMemoryHotplugStatus => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .memory_hotplug_status()
+                .map(VmmData::MemoryHotplugStatus)
+                .map_err(|e| {
+                    VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::from(e))
+                }),
+            #[cfg(feature = "block")]
+            GetDriveRateLimiter(drive_id) => self.drive_rate_limiter_stats(&drive_id),
+            GetFullVmConfig => Ok(VmmData::FullVmConfig(self.full_vm_config())),
+            GetGuestEvents => Ok(VmmData::GuestEvents(
+                self.vmm.lock().expect("Poisoned lock").guest_events(),
+            )),
+            GetMachineStats => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .machine_stats()
+                .map(VmmData::MachineStats)
+                .map_err(VmmActionError::InternalVmm),
+            #[cfg(feature = "net")]
+            GetNetworkInterfaceRateLimiters(iface_id) => self.net_rate_limiter_stats(&iface_id),
+            #[cfg(feature = "net")]
+            GetNetworkInterfaceStats(iface_id) => self.net_stats(&iface_id),
+            GetVcpuStats => Ok(VmmData::VcpuStats(
+                self.vmm.lock().expect("Poisoned lock").vcpu_stats(),
+            )),
+            GetVmConfiguration => Ok(VmmData::MachineConfiguration(
+                self.vm_resources.vm_config().clone(),
+            )),
+            #[cfg(feature = "vsock")]
+            GetVsockStats => self.vsock_stats(),
+            #[cfg(target_arch = "x86_64")]
+            GracefulShutdown(timeout_ms) => self.graceful_shutdown(timeout_ms),
             Pause => self.pause(),
             Resume => self.resume(),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del(),
+            #[cfg(target_arch = "x86_64")]
+            SendPowerButton => {
+                self.vmm.lock().expect("Poisoned lock").send_power_button();
+                Ok(VmmData::Empty)
+            }
+            SetPanicAction(action) => {
+                self.vmm
+                    .lock()
+                    .expect("Poisoned lock")
+                    .set_panic_action(action);
+                Ok(VmmData::Empty)
+            }
+            #[cfg(target_arch = "x86_64")]
+            SetClockPolicy(policy) => {
+                self.vmm
+                    .lock()
+                    .expect("Poisoned lock")
+                    .set_clock_policy(policy);
+                Ok(VmmData::Empty)
+            }
+            #[cfg(target_arch = "x86_64")]
+            SetShutdownBehavior(config) => {
+                self.vmm
+                    .lock()
+                    .expect("Poisoned lock")
+                    .set_shutdown_behavior(config);
+                Ok(VmmData::Empty)
+            }
+            SetCpuQuota(config) => self.set_cpu_quota(config),
+            Teardown => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .teardown()
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::InternalVmm),
+            #[cfg(feature = "balloon")]
+            SetBalloonPolicy(policy) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .set_balloon_policy(policy)
+                .map(|_| VmmData::Empty)
+                .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            #[cfg(feature = "balloon")]
             UpdateBalloon(balloon_update) => self
                 .vmm
                 .lock()
@@ -485,6 +1120,7 @@ impl RuntimeApiController {
                 .update_balloon_config(balloon_update.amount_mb)
                 .map(|_| VmmData::Empty)
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            #[cfg(feature = "balloon")]
             UpdateBalloonStatistics(balloon_stats_update) => self
                 .vmm
                 .lock()
@@ -492,32 +1128,86 @@ impl RuntimeApiController {
                 .update_balloon_stats_config(balloon_stats_update.stats_polling_interval_s)
                 .map(|_| VmmData::Empty)
                 .map_err(|e| VmmActionError::BalloonConfig(BalloonConfigError::from(e))),
+            #[cfg(feature = "block")]
             UpdateBlockDevice(new_cfg) => self.update_block_device(new_cfg),
+            #[cfg(feature = "virtio-mem")]
+            UpdateMemoryHotplug(size_update) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .update_memory_hotplug_size(size_update.requested_size_mib)
+                .map(|_| VmmData::Empty)
+                .map_err(|e| {
+                    VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::from(e))
+                }),
+            #[cfg(feature = "net")]
             UpdateNetworkInterface(netif_update) => self.update_net_rate_limiters(netif_update),
+            #[cfg(feature = "vsock")]
+            UpdateVsockDevice(vsock_update) => self.update_vsock_rate_limiters(vsock_update),
+            #[cfg(target_arch = "x86_64")]
+            SetVmConfiguration(cfg) => self.set_vcpu_count(cfg),
+            ValidateVmConfig(config) => validate_vm_config(*config),
 
             // Operations not allowed post-boot.
-            ConfigureBootSource(_)
+            BootDryRun
+            | ConfigureBootSource(_)
             | ConfigureLogger(_)
             | ConfigureMetrics(_)
-            | InsertBlockDevice(_)
-            | InsertNetworkDevice(_)
-            | SetBalloonDevice(_)
-            | SetVsockDevice(_)
+            | SetFullVmConfig(_)
             | SetMmdsConfiguration(_)
-            | SetVmConfiguration(_)
-            | StartMicroVm => Err(VmmActionError::OperationNotSupportedPostBoot),
+            | SetRateLimiterGroup(_)
+            | StartMicroVm(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(target_arch = "aarch64")]
+            SetVmConfiguration(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "block")]
+            InsertBlockDevice(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "net")]
+            InsertNetworkDevice(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "balloon")]
+            SetBalloonDevice(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "virtio-console")]
+            SetConsoleDevice(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "virtio-rng")]
+            SetEntropyDevice(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "null-devices")]
+            InsertNullDevice(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "virtio-pmem")]
+            InsertPmemDevice(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            InsertSerialPort(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "virtio-fs")]
+            InsertSharedFs(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+            InsertVfioDevice(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "virtio-mem")]
+            SetMemoryHotplug(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(feature = "vsock")]
+            SetVsockDevice(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+            SetTpm(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
             #[cfg(target_arch = "x86_64")]
-            LoadSnapshot(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
+            LoadSnapshot(_) | SetWatchdog(_) => Err(VmmActionError::OperationNotSupportedPostBoot),
         }
     }
 
     /// Creates a new `RuntimeApiController`.
-    pub fn new(vm_config: VmConfig, vmm: Arc<Mutex<Vmm>>) -> Self {
-        Self { vm_config, vmm }
+    pub fn new(vm_resources: VmResources, vmm: Arc<Mutex<Vmm>>) -> Self {
+        Self { vm_resources, vmm }
+    }
+
+    /// Returns the effective configuration of the microVM. The devices are shared with the
+    /// `Vmm`, so their configuration reflects the updates made after the microVM start.
+    fn full_vm_config(&self) -> FullVmConfig {
+        let state = if self.vmm.lock().expect("Poisoned lock").is_paused() {
+            InstanceState::Paused
+        } else {
+            InstanceState::Running
+        };
+        self.vm_resources.full_config(state)
     }
 
     /// Pauses the microVM by pausing the vCPUs.
     pub fn pause(&mut self) -> ActionResult {
+        let _span = Span::new("pause_vm");
         let pause_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
         self.vmm
@@ -526,15 +1216,24 @@ impl RuntimeApiController {
             .pause_vm()
             .map_err(VmmActionError::InternalVmm)?;
 
-        let elapsed_time_us =
-            update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_pause_vm, pause_start_us);
-        info!("'pause vm' VMM action took {} us.", elapsed_time_us);
+        update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_pause_vm, pause_start_us);
+
+        Ok(VmmData::Empty)
+    }
 
+    /// Sets the CPU quota of the vCPU threads, which takes effect right away.
+    fn set_cpu_quota(&mut self, config: CpuQuotaConfig) -> ActionResult {
+        config.validate().map_err(VmmActionError::CpuQuotaConfig)?;
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .set_cpu_quota(config.quota_pct);
         Ok(VmmData::Empty)
     }
 
     /// Resumes the microVM by resuming the vCPUs.
     pub fn resume(&mut self) -> ActionResult {
+        let _span = Span::new("resume_vm");
         let resume_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
         self.vmm
@@ -543,26 +1242,11 @@ impl RuntimeApiController {
             .resume_vm()
             .map_err(VmmActionError::InternalVmm)?;
 
-        let elapsed_time_us =
-            update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_resume_vm, resume_start_us);
-        info!("'resume vm' VMM action took {} us.", elapsed_time_us);
+        update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_resume_vm, resume_start_us);
 
         Ok(VmmData::Empty)
     }
 
-    /// Write the metrics on user demand (flush). We use the word `flush` here to highlight the fact
-    /// that the metrics will be written immediately.
-    /// Defer to inner Vmm. We'll move to a variant where the Vmm simply exposes functionality like
-    /// getting the dirty pages, and then we'll have the metrics flushing logic entirely on the outside.
-    fn flush_metrics(&mut self) -> ActionResult {
-        // FIXME: we're losing the bool saying whether metrics were actually written.
-        METRICS
-            .write()
-            .map(|_| VmmData::Empty)
-            .map_err(super::Error::Metrics)
-            .map_err(VmmActionError::InternalVmm)
-    }
-
     /// Injects CTRL+ALT+DEL keystroke combo to the inner Vmm (if present).
     #[cfg(target_arch = "x86_64")]
     fn send_ctrl_alt_del(&mut self) -> ActionResult {
@@ -574,43 +1258,91 @@ impl RuntimeApiController {
             .map_err(VmmActionError::InternalVmm)
     }
 
+    // Injects CTRL+ALT+DEL in the i8042 device, and arms the timer stopping the microVM.
+    #[cfg(target_arch = "x86_64")]
+    fn graceful_shutdown(&mut self, timeout_ms: u64) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .graceful_shutdown(timeout_ms)
+            .map(|()| VmmData::Empty)
+            .map_err(VmmActionError::InternalVmm)
+    }
+
+    // Plugs or unplugs vCPUs. Only the vCPU count of the machine configuration can change
+    // after boot.
+    #[cfg(target_arch = "x86_64")]
+    fn set_vcpu_count(&mut self, cfg: VmConfig) -> ActionResult {
+        let vcpu_count = match cfg {
+            VmConfig {
+                vcpu_count: Some(vcpu_count),
+                max_vcpu_count: None,
+                mem_size_mib: None,
+                ht_enabled: None,
+                cpu_template: None,
+                track_dirty_pages: false,
+                pit_enabled: None,
+                pit_reinject_policy: None,
+                hpet_enabled: None,
+                cpu_topology: None,
+                mem_backend: None,
+                nested_virt_enabled: None,
+                msr_policy: None,
+                mmio_layout: None,
+                virtio_transport: None,
+                cpu_template_path: None,
+                cpu_features: None,
+                cpuid_normalization_enabled: None,
+                mem_hints: None,
+                io_threads: None,
+            } => vcpu_count,
+            _ => return Err(VmmActionError::OperationNotSupportedPostBoot),
+        };
+
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .set_vcpu_count(vcpu_count)
+            .map_err(VmmActionError::InternalVmm)?;
+        self.vm_resources.set_vcpu_count(vcpu_count);
+
+        Ok(VmmData::Empty)
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn create_snapshot(&mut self, create_params: &CreateSnapshotParams) -> ActionResult {
+        // KVM does not save the state of the nested guests.
+        if self.vm_resources.vm_config().nested_virt_enabled == Some(true) {
+            return Err(VmmActionError::CreateSnapshot(
+                CreateSnapshotError::NestedVirtEnabled,
+            ));
+        }
+
+        let mut span = Span::new("create_snapshot");
+        span.record(
+            "snapshot_type",
+            format!("{:?}", create_params.snapshot_type),
+        );
         let mut locked_vmm = self.vmm.lock().unwrap();
         let create_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
         create_snapshot(&mut locked_vmm, create_params, VERSION_MAP.clone())
             .map_err(VmmActionError::CreateSnapshot)?;
 
-        match create_params.snapshot_type {
-            SnapshotType::Full => {
-                let elapsed_time_us = update_metric_with_elapsed_time(
-                    &METRICS.latencies_us.vmm_full_create_snapshot,
-                    create_start_us,
-                );
-                info!(
-                    "'create full snapshot' VMM action took {} us.",
-                    elapsed_time_us
-                );
-            }
-            SnapshotType::Diff => {
-                let elapsed_time_us = update_metric_with_elapsed_time(
-                    &METRICS.latencies_us.vmm_diff_create_snapshot,
-                    create_start_us,
-                );
-                info!(
-                    "'create diff snapshot' VMM action took {} us.",
-                    elapsed_time_us
-                );
-            }
-        }
+        let metric = match create_params.snapshot_type {
+            SnapshotType::Full => &METRICS.latencies_us.vmm_full_create_snapshot,
+            SnapshotType::Diff => &METRICS.latencies_us.vmm_diff_create_snapshot,
+        };
+        update_metric_with_elapsed_time(metric, create_start_us);
         Ok(VmmData::Empty)
     }
 
     /// Updates block device properties:
     ///  - path of the host file backing the emulated block device,
     ///    update the disk image on the device and its virtio configuration
-    ///  - rate limiter configuration.
+    ///  - rate limiter configuration, including its adaptive tuning,
+    ///  - weight in the I/O scheduler.
+    #[cfg(feature = "block")]
     fn update_block_device(&mut self, new_cfg: BlockDeviceUpdateConfig) -> ActionResult {
         let mut vmm = self.vmm.lock().expect("Poisoned lock");
         if let Some(new_path) = new_cfg.path_on_host {
@@ -629,10 +1361,63 @@ impl RuntimeApiController {
             .map_err(DriveError::DeviceUpdate)
             .map_err(VmmActionError::DriveConfig)?;
         }
+        // Without an adaptive configuration, the tuning of the rate limiter is left as it is.
+        if let Some(adaptive) = new_cfg.rate_limiter.and_then(|cfg| cfg.adaptive) {
+            let adaptive = adaptive
+                .adaptive_rate()
+                .map_err(DriveError::CreateRateLimiter)
+                .map_err(VmmActionError::DriveConfig)?;
+            vmm.update_block_adaptive_rate_limiter(&new_cfg.drive_id, adaptive)
+                .map_err(DriveError::DeviceUpdate)
+                .map_err(VmmActionError::DriveConfig)?;
+        }
+        if let Some(io_weight) = new_cfg.io_weight {
+            validate_io_weight(io_weight).map_err(VmmActionError::DriveConfig)?;
+            vmm.update_block_io_weight(&new_cfg.drive_id, io_weight)
+                .map_err(DriveError::DeviceUpdate)
+                .map_err(VmmActionError::DriveConfig)?;
+        }
         Ok(VmmData::Empty)
     }
 
+    /// Retrieves the live state of the rate limiter of the block device with `drive_id` id.
+    #[cfg(feature = "block")]
+    fn drive_rate_limiter_stats(&mut self, drive_id: &str) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .block_rate_limiter_stats(drive_id)
+            .map(VmmData::DriveRateLimiter)
+            .map_err(DriveError::DeviceStats)
+            .map_err(VmmActionError::DriveConfig)
+    }
+
+    /// Retrieves the live state of the rate limiters of the net device with `iface_id` id.
+    #[cfg(feature = "net")]
+    fn net_rate_limiter_stats(&mut self, iface_id: &str) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .net_rate_limiter_stats(iface_id)
+            .map(VmmData::NetworkInterfaceRateLimiters)
+            .map_err(NetworkInterfaceError::DeviceStats)
+            .map_err(VmmActionError::NetworkConfig)
+    }
+
+    /// Retrieves the live traffic counters of the net device with `iface_id` id.
+    #[cfg(feature = "net")]
+    fn net_stats(&mut self, iface_id: &str) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .net_stats(iface_id)
+            .map(VmmData::NetworkInterfaceStats)
+            .map_err(NetworkInterfaceError::DeviceStats)
+            .map_err(VmmActionError::NetworkConfig)
+    }
+
     /// Updates configuration for an emulated net device as described in `new_cfg`.
+    #[cfg(feature = "net")]
     fn update_net_rate_limiters(&mut self, new_cfg: NetworkInterfaceUpdateConfig) -> ActionResult {
         self.vmm
             .lock()
@@ -648,14 +1433,79 @@ impl RuntimeApiController {
             .map_err(NetworkInterfaceError::DeviceUpdate)
             .map_err(VmmActionError::NetworkConfig)
     }
+
+    /// Retrieves the live per-port traffic counters and the open connections of the vsock
+    /// device.
+    #[cfg(feature = "vsock")]
+    fn vsock_stats(&mut self) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .vsock_stats()
+            .map(VmmData::VsockStats)
+            .map_err(VsockConfigError::DeviceStats)
+            .map_err(VmmActionError::VsockConfig)
+    }
+
+    /// Updates configuration for the vsock device as described in `new_cfg`.
+    #[cfg(feature = "vsock")]
+    fn update_vsock_rate_limiters(&mut self, new_cfg: VsockDeviceUpdateConfig) -> ActionResult {
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .update_vsock_rate_limiters(
+                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).bandwidth,
+                RateLimiterUpdate::from(new_cfg.rx_rate_limiter).ops,
+                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).bandwidth,
+                RateLimiterUpdate::from(new_cfg.tx_rate_limiter).ops,
+            )
+            .map(|()| VmmData::Empty)
+            .map_err(VsockConfigError::DeviceUpdate)
+            .map_err(VmmActionError::VsockConfig)
+    }
+}
+
+// Writes the metrics on user demand (flush), before or after the microVM is started. We use the
+// word `flush` here to highlight the fact that the metrics will be written immediately.
+fn flush_metrics() -> ActionResult {
+    // FIXME: we're losing the bool saying whether metrics were actually written.
+    METRICS
+        .write()
+        .map(|_| VmmData::Empty)
+        .map_err(super::Error::Metrics)
+        .map_err(VmmActionError::InternalVmm)
+}
+
+// Checks that a microVM could be configured and booted from `config`, which is not applied.
+fn validate_vm_config(config: VmmConfig) -> ActionResult {
+    config
+        .validate()
+        .map(|()| VmmData::Empty)
+        .map_err(VmmActionError::FullVmConfig)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "balloon")]
     use crate::vmm_config::balloon::BalloonBuilder;
     use crate::vmm_config::logger::LoggerLevel;
+    #[cfg(feature = "null-devices")]
+    use crate::vmm_config::null_device::NullDeviceType;
+    #[cfg(feature = "virtio-fs")]
+    use crate::vmm_config::shared_fs::CacheMode;
+    #[cfg(target_arch = "x86_64")]
+    use crate::vmm_config::shutdown_behavior::ShutdownSnapshotConfig;
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    use crate::vmm_config::tpm::{SoftwareTpmConfig, TpmBackendConfig};
+    #[cfg(feature = "vsock")]
+    use crate::vmm_config::vsock::VsockBackendType;
+    #[cfg(target_arch = "x86_64")]
+    use crate::vmm_config::watchdog::WatchdogAction;
+    use crate::vmm_config::{AdaptiveConfig, RateLimiterConfig};
+    #[cfg(feature = "balloon")]
     use devices::virtio::balloon::{BalloonConfig, Error as BalloonError};
+    #[cfg(feature = "vsock")]
     use devices::virtio::VsockError;
     use seccomp::BpfProgramRef;
 
@@ -665,11 +1515,21 @@ mod tests {
         fn eq(&self, other: &VmmActionError) -> bool {
             use VmmActionError::*;
             match (self, other) {
+                #[cfg(feature = "balloon")]
                 (BalloonConfig(_), BalloonConfig(_)) => true,
+                (BootDryRun(_), BootDryRun(_)) => true,
                 (BootSource(_), BootSource(_)) => true,
+                #[cfg(feature = "virtio-console")]
+                (ConsoleConfig(_), ConsoleConfig(_)) => true,
+                #[cfg(target_arch = "x86_64")]
+                (CoreDump(_), CoreDump(_)) => true,
+                (CpuQuotaConfig(_), CpuQuotaConfig(_)) => true,
                 #[cfg(target_arch = "x86_64")]
                 (CreateSnapshot(_), CreateSnapshot(_)) => true,
                 (DriveConfig(_), DriveConfig(_)) => true,
+                #[cfg(feature = "virtio-rng")]
+                (EntropyConfig(_), EntropyConfig(_)) => true,
+                (FullVmConfig(_), FullVmConfig(_)) => true,
                 (InternalVmm(_), InternalVmm(_)) => true,
                 #[cfg(target_arch = "x86_64")]
                 (LoadSnapshot(_), LoadSnapshot(_)) => true,
@@ -677,12 +1537,25 @@ mod tests {
                 (LoadSnapshotNotAllowed, LoadSnapshotNotAllowed) => true,
                 (Logger(_), Logger(_)) => true,
                 (MachineConfig(_), MachineConfig(_)) => true,
+                #[cfg(feature = "virtio-mem")]
+                (MemoryHotplugConfig(_), MemoryHotplugConfig(_)) => true,
                 (Metrics(_), Metrics(_)) => true,
                 (MmdsConfig(_), MmdsConfig(_)) => true,
                 (NetworkConfig(_), NetworkConfig(_)) => true,
+                #[cfg(feature = "null-devices")]
+                (NullDeviceConfig(_), NullDeviceConfig(_)) => true,
                 (OperationNotSupportedPostBoot, OperationNotSupportedPostBoot) => true,
                 (OperationNotSupportedPreBoot, OperationNotSupportedPreBoot) => true,
+                #[cfg(feature = "virtio-pmem")]
+                (PmemConfig(_), PmemConfig(_)) => true,
+                (RateLimiterGroupConfig(_), RateLimiterGroupConfig(_)) => true,
+                (SerialPortConfig(_), SerialPortConfig(_)) => true,
+                #[cfg(feature = "virtio-fs")]
+                (SharedFsConfig(_), SharedFsConfig(_)) => true,
                 (StartMicrovm(_), StartMicrovm(_)) => true,
+                #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+                (VfioConfig(_), VfioConfig(_)) => true,
+                #[cfg(feature = "vsock")]
                 (VsockConfig(_), VsockConfig(_)) => true,
                 _ => false,
             }
@@ -693,17 +1566,51 @@ mod tests {
     #[derive(Default)]
     pub struct MockVmRes {
         vm_config: VmConfig,
+        #[cfg(feature = "balloon")]
         pub balloon: BalloonBuilder,
+        #[cfg(feature = "balloon")]
         balloon_config_called: bool,
+        #[cfg(feature = "balloon")]
         balloon_set: bool,
         boot_cfg_set: bool,
         block_set: bool,
+        #[cfg(feature = "virtio-console")]
+        console_set: bool,
+        #[cfg(feature = "virtio-rng")]
+        entropy_set: bool,
+        full_vm_config_set: bool,
+        #[cfg(feature = "vsock")]
         vsock_set: bool,
         net_set: bool,
+        #[cfg(feature = "null-devices")]
+        null_device_set: bool,
+        #[cfg(feature = "virtio-pmem")]
+        pmem_set: bool,
+        rate_limiter_group_set: bool,
+        serial_port_set: bool,
+        #[cfg(feature = "virtio-fs")]
+        shared_fs_set: bool,
+        #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+        vfio_set: bool,
+        #[cfg(feature = "virtio-mem")]
+        memory_hotplug_set: bool,
         mmds_set: bool,
         pub boot_timer: bool,
-        // when `true`, all self methods are forced to fail
-        pub force_errors: bool,
+        pub start_paused: bool,
+        pub gdb_socket: Option<PathBuf>,
+        pub landlock: Option<Vec<PathBuf>>,
+        pub panic_action: PanicAction,
+        #[cfg(target_arch = "x86_64")]
+        pub clock_policy: ClockPolicy,
+        pub cpu_quota: CpuQuotaConfig,
+        #[cfg(target_arch = "x86_64")]
+        pub shutdown_behavior: ShutdownBehaviorConfig,
+        #[cfg(target_arch = "x86_64")]
+        pub watchdog: Option<WatchdogConfig>,
+        #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+        pub tpm: Option<TpmConfig>,
+        // when `true`, all self methods are forced to fail
+        pub force_errors: bool,
     }
 
     impl MockVmRes {
@@ -711,6 +1618,15 @@ mod tests {
             &self.vm_config
         }
 
+        pub fn full_config(&self, state: InstanceState) -> FullVmConfig {
+            FullVmConfig {
+                machine_config: self.vm_config.clone(),
+                state,
+                ..Default::default()
+            }
+        }
+
+        #[cfg(feature = "balloon")]
         pub fn balloon_config(&mut self) -> Result<BalloonConfig, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -727,6 +1643,12 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(target_arch = "x86_64")]
+        pub fn set_vcpu_count(&mut self, vcpu_count: u8) {
+            self.vm_config.vcpu_count = Some(vcpu_count);
+        }
+
+        #[cfg(feature = "balloon")]
         pub fn set_balloon_device(
             &mut self,
             _: BalloonDeviceConfig,
@@ -770,6 +1692,108 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(feature = "null-devices")]
+        pub fn set_null_device(
+            &mut self,
+            _: NullDeviceConfig,
+        ) -> Result<(), NullDeviceConfigError> {
+            if self.force_errors {
+                return Err(NullDeviceConfigError::CreateNullDevice(
+                    devices::virtio::null::Error::QueueRestoreError,
+                ));
+            }
+            self.null_device_set = true;
+            Ok(())
+        }
+
+        #[cfg(feature = "virtio-pmem")]
+        pub fn set_pmem_device(&mut self, _: PmemConfig) -> Result<(), PmemConfigError> {
+            if self.force_errors {
+                return Err(PmemConfigError::CreatePmem(
+                    devices::virtio::pmem::Error::QueueRestoreError,
+                ));
+            }
+            self.pmem_set = true;
+            Ok(())
+        }
+
+        pub fn set_serial_port(&mut self, cfg: SerialPortConfig) -> Result<(), SerialConfigError> {
+            if self.force_errors {
+                return Err(SerialConfigError::InvalidPortId(cfg.port_id));
+            }
+            self.serial_port_set = true;
+            Ok(())
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        pub fn set_shutdown_behavior(&mut self, cfg: ShutdownBehaviorConfig) {
+            self.shutdown_behavior = cfg;
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        pub fn set_watchdog(&mut self, cfg: WatchdogConfig) {
+            self.watchdog = Some(cfg);
+        }
+
+        #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+        pub fn set_tpm(&mut self, cfg: TpmConfig) {
+            self.tpm = Some(cfg);
+        }
+
+        #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+        pub fn set_vfio_device(&mut self, cfg: VfioConfig) -> Result<(), VfioConfigError> {
+            if self.force_errors {
+                return Err(VfioConfigError::NotBoundToVfio(cfg.sysfs_path));
+            }
+            self.vfio_set = true;
+            Ok(())
+        }
+
+        #[cfg(feature = "virtio-fs")]
+        pub fn set_shared_fs(&mut self, cfg: SharedFsConfig) -> Result<(), SharedFsConfigError> {
+            if self.force_errors {
+                return Err(SharedFsConfigError::TagInUse(cfg.tag));
+            }
+            self.shared_fs_set = true;
+            Ok(())
+        }
+
+        #[cfg(feature = "virtio-console")]
+        pub fn set_console_device(&mut self, _: ConsoleConfig) -> Result<(), ConsoleConfigError> {
+            if self.force_errors {
+                return Err(ConsoleConfigError::MultipleConsolePorts);
+            }
+            self.console_set = true;
+            Ok(())
+        }
+
+        #[cfg(feature = "virtio-rng")]
+        pub fn set_entropy_device(
+            &mut self,
+            _: EntropyDeviceConfig,
+        ) -> Result<(), EntropyConfigError> {
+            if self.force_errors {
+                return Err(EntropyConfigError::CreateRateLimiter(
+                    std::io::Error::from_raw_os_error(0),
+                ));
+            }
+            self.entropy_set = true;
+            Ok(())
+        }
+
+        #[cfg(feature = "virtio-mem")]
+        pub fn set_memory_hotplug(
+            &mut self,
+            _: MemoryHotplugConfig,
+        ) -> Result<(), MemoryHotplugConfigError> {
+            if self.force_errors {
+                return Err(MemoryHotplugConfigError::InvalidBlockSize);
+            }
+            self.memory_hotplug_set = true;
+            Ok(())
+        }
+
+        #[cfg(feature = "vsock")]
         pub fn set_vsock_device(&mut self, _: VsockDeviceConfig) -> Result<(), VsockConfigError> {
             if self.force_errors {
                 return Err(VsockConfigError::CreateVsockDevice(
@@ -787,26 +1811,102 @@ mod tests {
             self.mmds_set = true;
             Ok(())
         }
+
+        pub fn set_rate_limiter_group(
+            &mut self,
+            cfg: RateLimiterGroupConfig,
+        ) -> Result<(), RateLimiterGroupError> {
+            if self.force_errors {
+                return Err(RateLimiterGroupError::GroupInUse(cfg.group_id));
+            }
+            self.rate_limiter_group_set = true;
+            Ok(())
+        }
+
+        pub fn apply_vmm_config(&mut self, _: VmmConfig) -> Result<(), ResourcesError> {
+            if self.force_errors {
+                return Err(ResourcesError::NotMicrovmConfig);
+            }
+            self.full_vm_config_set = true;
+            Ok(())
+        }
     }
 
     // Mock `Vmm` used for testing.
     #[derive(Debug, Default, PartialEq)]
     pub struct MockVmm {
+        #[cfg(feature = "balloon")]
         pub balloon_config_called: bool,
+        #[cfg(feature = "balloon")]
         pub latest_balloon_stats_called: bool,
+        #[cfg(feature = "virtio-mem")]
+        pub memory_hotplug_status_called: bool,
+        pub guest_events_called: bool,
+        pub machine_stats_called: bool,
+        pub net_stats_called: bool,
+        pub block_rate_limiter_stats_called: bool,
+        pub net_rate_limiter_stats_called: bool,
+        pub vcpu_stats_called: bool,
+        pub panic_action: PanicAction,
+        #[cfg(target_arch = "x86_64")]
+        pub clock_policy: ClockPolicy,
+        #[cfg(target_arch = "x86_64")]
+        pub shutdown_behavior: ShutdownBehaviorConfig,
+        pub cpu_quota_pct: Option<u8>,
         pub pause_called: bool,
         pub resume_called: bool,
         #[cfg(target_arch = "x86_64")]
+        pub graceful_shutdown_timeout_ms: Option<u64>,
+        #[cfg(target_arch = "x86_64")]
         pub send_ctrl_alt_del_called: bool,
+        #[cfg(target_arch = "x86_64")]
+        pub send_power_button_called: bool,
+        #[cfg(feature = "balloon")]
+        pub set_balloon_policy_called: bool,
+        pub teardown_called: bool,
+        #[cfg(target_arch = "x86_64")]
+        pub vcpu_count: Option<u8>,
+        #[cfg(feature = "balloon")]
         pub update_balloon_config_called: bool,
+        #[cfg(feature = "balloon")]
         pub update_balloon_stats_config_called: bool,
+        pub update_block_adaptive_rate_limiter_called: bool,
         pub update_block_device_path_called: bool,
+        pub update_block_io_weight_called: bool,
+        #[cfg(feature = "virtio-mem")]
+        pub update_memory_hotplug_size_called: bool,
         pub update_net_rate_limiters_called: bool,
+        #[cfg(feature = "vsock")]
+        pub update_vsock_rate_limiters_called: bool,
+        #[cfg(feature = "vsock")]
+        pub vsock_stats_called: bool,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
 
     impl MockVmm {
+        pub fn set_panic_action(&mut self, action: PanicAction) {
+            self.panic_action = action;
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        pub fn set_clock_policy(&mut self, policy: ClockPolicy) {
+            self.clock_policy = policy;
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        pub fn set_shutdown_behavior(&mut self, config: ShutdownBehaviorConfig) {
+            self.shutdown_behavior = config;
+        }
+
+        pub fn set_cpu_quota(&mut self, quota_pct: u8) {
+            self.cpu_quota_pct = Some(quota_pct);
+        }
+
+        pub fn is_paused(&self) -> bool {
+            self.pause_called && !self.resume_called
+        }
+
         pub fn resume_vm(&mut self) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::VcpuResume);
@@ -834,6 +1934,36 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(target_arch = "x86_64")]
+        pub fn send_power_button(&mut self) {
+            self.send_power_button_called = true;
+        }
+
+        pub fn teardown(&mut self) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::NotReusable);
+            }
+            self.teardown_called = true;
+            Ok(())
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        pub fn graceful_shutdown(&mut self, timeout_ms: u64) -> Result<(), VmmError> {
+            self.send_ctrl_alt_del()?;
+            self.graceful_shutdown_timeout_ms = Some(timeout_ms);
+            Ok(())
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        pub fn set_vcpu_count(&mut self, vcpu_count: u8) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::VcpuHotplugNotConfigured);
+            }
+            self.vcpu_count = Some(vcpu_count);
+            Ok(())
+        }
+
+        #[cfg(feature = "balloon")]
         pub fn balloon_config(&mut self) -> Result<BalloonConfig, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -842,6 +1972,7 @@ mod tests {
             Ok(BalloonConfig::default())
         }
 
+        #[cfg(feature = "balloon")]
         pub fn latest_balloon_stats(&mut self) -> Result<BalloonStats, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -850,6 +1981,7 @@ mod tests {
             Ok(BalloonStats::default())
         }
 
+        #[cfg(feature = "balloon")]
         pub fn update_balloon_config(&mut self, _: u32) -> Result<(), BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -858,6 +1990,7 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(feature = "balloon")]
         pub fn update_balloon_stats_config(&mut self, _: u16) -> Result<(), BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -866,6 +1999,38 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(feature = "balloon")]
+        pub fn set_balloon_policy(&mut self, _: BalloonPolicy) -> Result<(), BalloonError> {
+            if self.force_errors {
+                return Err(BalloonError::DeviceNotFound);
+            }
+            self.set_balloon_policy_called = true;
+            Ok(())
+        }
+
+        #[cfg(feature = "virtio-mem")]
+        pub fn memory_hotplug_status(
+            &mut self,
+        ) -> Result<VirtioMemStatus, devices::virtio::mem::Error> {
+            if self.force_errors {
+                return Err(devices::virtio::mem::Error::DeviceNotFound);
+            }
+            self.memory_hotplug_status_called = true;
+            Ok(VirtioMemStatus::default())
+        }
+
+        #[cfg(feature = "virtio-mem")]
+        pub fn update_memory_hotplug_size(
+            &mut self,
+            _: u64,
+        ) -> Result<(), devices::virtio::mem::Error> {
+            if self.force_errors {
+                return Err(devices::virtio::mem::Error::DeviceNotFound);
+            }
+            self.update_memory_hotplug_size_called = true;
+            Ok(())
+        }
+
         pub fn update_block_device_path(&mut self, _: &str, _: String) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::DeviceManager(
@@ -885,6 +2050,80 @@ mod tests {
             Ok(())
         }
 
+        pub fn update_block_adaptive_rate_limiter(
+            &mut self,
+            _: &str,
+            _: Option<rate_limiter::adaptive::AdaptiveRate>,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.update_block_adaptive_rate_limiter_called = true;
+            Ok(())
+        }
+
+        pub fn update_block_io_weight(&mut self, _: &str, _: u32) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.update_block_io_weight_called = true;
+            Ok(())
+        }
+
+        pub fn guest_events(&mut self) -> GuestEvents {
+            self.guest_events_called = true;
+            GuestEvents::default()
+        }
+
+        pub fn machine_stats(&mut self) -> Result<MachineStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::MachineStats(std::io::Error::from_raw_os_error(
+                    libc::EIO,
+                )));
+            }
+            self.machine_stats_called = true;
+            Ok(MachineStats::default())
+        }
+
+        pub fn vcpu_stats(&mut self) -> Vec<VcpuStats> {
+            self.vcpu_stats_called = true;
+            vec![VcpuStats::default()]
+        }
+
+        pub fn block_rate_limiter_stats(&mut self, _: &str) -> Result<RateLimiterStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.block_rate_limiter_stats_called = true;
+            Ok(RateLimiterStats::default())
+        }
+
+        pub fn net_rate_limiter_stats(&mut self, _: &str) -> Result<NetRateLimiterStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.net_rate_limiter_stats_called = true;
+            Ok(NetRateLimiterStats::default())
+        }
+
+        pub fn net_stats(&mut self, _: &str) -> Result<NetDeviceStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.net_stats_called = true;
+            Ok(NetDeviceStats::default())
+        }
+
         pub fn update_net_rate_limiters(
             &mut self,
             _: &str,
@@ -901,6 +2140,45 @@ mod tests {
             self.update_net_rate_limiters_called = true;
             Ok(())
         }
+
+        #[cfg(feature = "vsock")]
+        pub fn update_vsock_rate_limiters(
+            &mut self,
+            _: rate_limiter::BucketUpdate,
+            _: rate_limiter::BucketUpdate,
+            _: rate_limiter::BucketUpdate,
+            _: rate_limiter::BucketUpdate,
+        ) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::IncorrectDeviceType,
+                ));
+            }
+            self.update_vsock_rate_limiters_called = true;
+            Ok(())
+        }
+
+        #[cfg(feature = "vsock")]
+        pub fn vsock_stats(&mut self) -> Result<VsockDeviceStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.vsock_stats_called = true;
+            Ok(VsockDeviceStats::default())
+        }
+    }
+
+    // Need to redefine this since the non-test one uses real VmResources.
+    pub fn check_boot_resources(vm_res: &VmResources) -> Vec<BootCheckError> {
+        if vm_res.force_errors {
+            return vec![
+                BootCheckError::MissingKernelConfig,
+                BootCheckError::TapNotFound("tap0".to_string()),
+            ];
+        }
+        Vec::new()
     }
 
     // Need to redefine this since the non-test one uses real VmResources
@@ -913,6 +2191,16 @@ mod tests {
         Ok(Arc::new(Mutex::new(MockVmm::default())))
     }
 
+    #[cfg(target_arch = "x86_64")]
+    // Need to redefine this since the non-test one uses real Vmm
+    // instead of our mocks.
+    pub fn create_core_dump(
+        _: &mut Vmm,
+        _: &std::path::Path,
+    ) -> std::result::Result<(), CoreDumpError> {
+        Ok(())
+    }
+
     #[cfg(target_arch = "x86_64")]
     // Need to redefine this since the non-test one uses real Vmm
     // instead of our mocks.
@@ -930,6 +2218,7 @@ mod tests {
     pub fn restore_from_snapshot(
         _: &mut EventManager,
         _: BpfProgramRef,
+        _: bool,
         _: &LoadSnapshotParams,
         _: versionize::VersionMap,
     ) -> Result<Arc<Mutex<Vmm>>, LoadSnapshotError> {
@@ -992,6 +2281,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_flush_metrics() {
+        // The metrics can be flushed on demand before the microVM is started.
+        check_preboot_request(VmmAction::FlushMetrics, |result, _| {
+            assert!(result.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_preboot_get_full_vm_config() {
+        let req = VmmAction::GetFullVmConfig;
+        check_preboot_request(req, |result, _| {
+            assert_eq!(result, Ok(VmmData::FullVmConfig(FullVmConfig::default())));
+        });
+    }
+
     #[test]
     fn test_preboot_get_vm_config() {
         let req = VmmAction::GetVmConfiguration;
@@ -1009,6 +2314,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "balloon")]
     #[test]
     fn test_preboot_get_balloon_config() {
         let req = VmmAction::GetBalloonConfig;
@@ -1034,6 +2340,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "balloon")]
     #[test]
     fn test_preboot_set_balloon_dev() {
         let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
@@ -1043,6 +2350,7 @@ mod tests {
         });
 
         let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
+        #[cfg(feature = "balloon")]
         check_preboot_request_err(
             req,
             VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
@@ -1058,6 +2366,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::new(),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1071,6 +2381,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::new(),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         });
         check_preboot_request_err(
             req,
@@ -1087,6 +2399,9 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            vlan_id: None,
+            dhcp: None,
+            rate_limiter_group: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1100,6 +2415,9 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            vlan_id: None,
+            dhcp: None,
+            rate_limiter_group: None,
         });
         check_preboot_request_err(
             req,
@@ -1109,72 +2427,483 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "null-devices")]
     #[test]
-    fn test_preboot_set_vsock_dev() {
-        let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
-            vsock_id: String::new(),
-            guest_cid: 0,
-            uds_path: String::new(),
+    fn test_preboot_insert_null_dev() {
+        let req = VmmAction::InsertNullDevice(NullDeviceConfig {
+            device_id: String::new(),
+            device_type: NullDeviceType::Sound,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vm_res.vsock_set)
+            assert!(vm_res.null_device_set)
         });
 
-        let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
-            vsock_id: String::new(),
-            guest_cid: 0,
-            uds_path: String::new(),
+        let req = VmmAction::InsertNullDevice(NullDeviceConfig {
+            device_id: String::new(),
+            device_type: NullDeviceType::Sound,
         });
+        #[cfg(feature = "null-devices")]
         check_preboot_request_err(
             req,
-            VmmActionError::VsockConfig(VsockConfigError::CreateVsockDevice(
-                VsockError::BufDescMissing,
+            VmmActionError::NullDeviceConfig(NullDeviceConfigError::CreateNullDevice(
+                devices::virtio::null::Error::QueueRestoreError,
             )),
         );
     }
 
+    #[cfg(feature = "virtio-pmem")]
+    fn default_pmem_config() -> PmemConfig {
+        PmemConfig {
+            pmem_id: String::new(),
+            path_on_host: String::new(),
+            is_read_only: false,
+        }
+    }
+
+    #[cfg(feature = "virtio-pmem")]
     #[test]
-    fn test_preboot_set_mmds_config() {
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
+    fn test_preboot_insert_pmem_device() {
+        let req = VmmAction::InsertPmemDevice(default_pmem_config());
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vm_res.mmds_set)
+            assert!(vm_res.pmem_set)
         });
 
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
+        let req = VmmAction::InsertPmemDevice(default_pmem_config());
         check_preboot_request_err(
             req,
-            VmmActionError::MmdsConfig(MmdsConfigError::InvalidIpv4Addr),
+            VmmActionError::PmemConfig(PmemConfigError::CreatePmem(
+                devices::virtio::pmem::Error::QueueRestoreError,
+            )),
         );
     }
 
-    #[cfg(target_arch = "x86_64")]
-    #[test]
-    fn test_preboot_load_snapshot() {
-        let mut vm_resources = MockVmRes::default();
-        let mut evmgr = EventManager::new().unwrap();
-        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr);
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    fn default_vfio_config() -> VfioConfig {
+        VfioConfig {
+            vfio_id: String::from("nic"),
+            sysfs_path: PathBuf::from("/sys/bus/pci/devices/0000:01:00.0"),
+        }
+    }
 
-        // Without resume.
-        let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
-            snapshot_path: PathBuf::new(),
-            mem_file_path: PathBuf::new(),
-            enable_diff_snapshots: false,
-            resume_vm: false,
+    #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+    #[test]
+    fn test_preboot_insert_vfio_device() {
+        let req = VmmAction::InsertVfioDevice(default_vfio_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.vfio_set)
         });
-        // Request should succeed.
-        preboot.handle_preboot_request(req).unwrap();
-        // Should have built default mock vmm.
-        let vmm = preboot.built_vmm.take().unwrap();
-        assert_eq!(*vmm.lock().unwrap(), MockVmm::default());
+
+        let req = VmmAction::InsertVfioDevice(default_vfio_config());
+        check_preboot_request_err(
+            req,
+            VmmActionError::VfioConfig(VfioConfigError::NotBoundToVfio(
+                default_vfio_config().sysfs_path,
+            )),
+        );
+    }
+
+    fn default_rate_limiter_group_config() -> RateLimiterGroupConfig {
+        RateLimiterGroupConfig {
+            group_id: String::from("disks"),
+            bandwidth: None,
+            ops: None,
+        }
+    }
+
+    #[test]
+    fn test_preboot_set_rate_limiter_group() {
+        let req = VmmAction::SetRateLimiterGroup(default_rate_limiter_group_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.rate_limiter_group_set)
+        });
+
+        let req = VmmAction::SetRateLimiterGroup(default_rate_limiter_group_config());
+        check_preboot_request_err(
+            req,
+            VmmActionError::RateLimiterGroupConfig(RateLimiterGroupError::GroupInUse(
+                String::from("disks"),
+            )),
+        );
+    }
+
+    fn default_serial_port_config() -> SerialPortConfig {
+        SerialPortConfig {
+            port_id: String::from("com2"),
+            file_path: Some(String::new()),
+            pipe_path: None,
+            uds_path: None,
+        }
+    }
+
+    #[test]
+    fn test_preboot_insert_serial_port() {
+        let req = VmmAction::InsertSerialPort(default_serial_port_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.serial_port_set)
+        });
+
+        let req = VmmAction::InsertSerialPort(default_serial_port_config());
+        check_preboot_request_err(
+            req,
+            VmmActionError::SerialPortConfig(SerialConfigError::InvalidPortId(String::from(
+                "com2",
+            ))),
+        );
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    fn default_shared_fs_config() -> SharedFsConfig {
+        SharedFsConfig {
+            fs_id: String::new(),
+            tag: String::from("myfs"),
+            socket_path: String::new(),
+            cache_mode: CacheMode::Auto,
+        }
+    }
+
+    #[cfg(feature = "virtio-fs")]
+    #[test]
+    fn test_preboot_insert_shared_fs() {
+        let req = VmmAction::InsertSharedFs(default_shared_fs_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.shared_fs_set)
+        });
+
+        let req = VmmAction::InsertSharedFs(default_shared_fs_config());
+        check_preboot_request_err(
+            req,
+            VmmActionError::SharedFsConfig(SharedFsConfigError::TagInUse(String::from("myfs"))),
+        );
+    }
+
+    #[cfg(feature = "virtio-console")]
+    fn default_console_config() -> ConsoleConfig {
+        ConsoleConfig {
+            ports: vec![crate::vmm_config::console::ConsolePortConfig {
+                name: String::from("console"),
+                uds_path: Some(String::from("/tmp/console.sock")),
+                pipe_path: None,
+                is_console: true,
+            }],
+        }
+    }
+
+    #[cfg(feature = "virtio-console")]
+    #[test]
+    fn test_preboot_set_console_device() {
+        let req = VmmAction::SetConsoleDevice(default_console_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.console_set)
+        });
+
+        let req = VmmAction::SetConsoleDevice(default_console_config());
+        check_preboot_request_err(
+            req,
+            VmmActionError::ConsoleConfig(ConsoleConfigError::MultipleConsolePorts),
+        );
+    }
+
+    #[cfg(feature = "virtio-rng")]
+    #[test]
+    fn test_preboot_set_entropy_device() {
+        let req = VmmAction::SetEntropyDevice(EntropyDeviceConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.entropy_set)
+        });
+
+        let req = VmmAction::SetEntropyDevice(EntropyDeviceConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::EntropyConfig(EntropyConfigError::CreateRateLimiter(
+                std::io::Error::from_raw_os_error(0),
+            )),
+        );
+    }
+
+    #[cfg(feature = "virtio-mem")]
+    #[test]
+    fn test_preboot_set_memory_hotplug() {
+        let req = VmmAction::SetMemoryHotplug(MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.memory_hotplug_set)
+        });
+
+        let req = VmmAction::SetMemoryHotplug(MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        });
+        check_preboot_request_err(
+            req,
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::InvalidBlockSize),
+        );
+    }
+
+    #[cfg(feature = "vsock")]
+    #[test]
+    fn test_preboot_set_vsock_dev() {
+        let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
+            vsock_id: String::new(),
+            guest_cid: 0,
+            backend: VsockBackendType::Unix,
+            uds_path: String::new(),
+            mmds_port: None,
+            agent_port: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            port_mappings: Vec::new(),
+        });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.vsock_set)
+        });
+
+        let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
+            vsock_id: String::new(),
+            guest_cid: 0,
+            backend: VsockBackendType::Unix,
+            uds_path: String::new(),
+            mmds_port: None,
+            agent_port: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            port_mappings: Vec::new(),
+        });
+        #[cfg(feature = "vsock")]
+        check_preboot_request_err(
+            req,
+            VmmActionError::VsockConfig(VsockConfigError::CreateVsockDevice(
+                VsockError::BufDescMissing,
+            )),
+        );
+    }
+
+    #[test]
+    fn test_preboot_set_mmds_config() {
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig::default());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.mmds_set)
+        });
+
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig::default());
+        check_preboot_request_err(
+            req,
+            VmmActionError::MmdsConfig(MmdsConfigError::InvalidIpv4Addr),
+        );
+    }
+
+    fn default_vmm_config() -> VmmConfig {
+        serde_json::from_str(
+            r#"{
+                "boot-source": { "kernel_image_path": "vmlinux.bin" },
+                "drives": []
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_preboot_set_full_vm_config() {
+        let req = VmmAction::SetFullVmConfig(Box::new(default_vmm_config()));
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.full_vm_config_set)
+        });
+
+        let req = VmmAction::SetFullVmConfig(Box::new(default_vmm_config()));
+        check_preboot_request_err(
+            req,
+            VmmActionError::FullVmConfig(ResourcesError::NotMicrovmConfig),
+        );
+    }
+
+    #[test]
+    fn test_preboot_validate_vm_config() {
+        // The kernel of the default configuration does not exist.
+        let req = VmmAction::ValidateVmConfig(Box::new(default_vmm_config()));
+        check_preboot_request(req, |result, vm_res| {
+            match result {
+                Err(VmmActionError::FullVmConfig(ResourcesError::BootSource(_))) => (),
+                _ => panic!("Unexpected result."),
+            }
+            assert!(!vm_res.full_vm_config_set);
+        });
+    }
+
+    #[test]
+    fn test_preboot_boot_dry_run() {
+        check_preboot_request(VmmAction::BootDryRun, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(!vm_res.start_paused);
+        });
+        check_preboot_request_err(
+            VmmAction::BootDryRun,
+            VmmActionError::BootDryRun(vec![BootCheckError::MissingKernelConfig]),
+        );
+    }
+
+    #[test]
+    fn test_preboot_start_microvm() {
+        check_preboot_request(VmmAction::StartMicroVm(true), |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vm_res.start_paused);
+        });
+        check_preboot_request(VmmAction::StartMicroVm(false), |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(!vm_res.start_paused);
+        });
+    }
+
+    #[test]
+    fn test_preboot_set_panic_action() {
+        let req = VmmAction::SetPanicAction(PanicAction::Pause);
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vm_res.panic_action, PanicAction::Pause);
+        });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_preboot_set_clock_policy() {
+        let req = VmmAction::SetClockPolicy(ClockPolicy::Sync);
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vm_res.clock_policy, ClockPolicy::Sync);
+        });
+    }
+
+    #[test]
+    fn test_preboot_set_cpu_quota() {
+        let req = VmmAction::SetCpuQuota(CpuQuotaConfig { quota_pct: 50 });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vm_res.cpu_quota.quota_pct, 50);
+        });
+
+        let req = VmmAction::SetCpuQuota(CpuQuotaConfig { quota_pct: 0 });
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(
+                result,
+                Err(VmmActionError::CpuQuotaConfig(
+                    CpuQuotaConfigError::InvalidQuota(0)
+                ))
+            );
+            assert_eq!(vm_res.cpu_quota, CpuQuotaConfig::default());
+        });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn default_shutdown_behavior() -> ShutdownBehaviorConfig {
+        ShutdownBehaviorConfig {
+            snapshot: Some(ShutdownSnapshotConfig {
+                snapshot_path: PathBuf::from("vm.snap"),
+                mem_file_path: PathBuf::from("vm.mem"),
+            }),
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_preboot_set_shutdown_behavior() {
+        let config = default_shutdown_behavior();
+        let req = VmmAction::SetShutdownBehavior(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vm_res.shutdown_behavior, config);
+        });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_preboot_set_watchdog() {
+        let config = WatchdogConfig {
+            action: WatchdogAction::Reset,
+        };
+        let req = VmmAction::SetWatchdog(config.clone());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vm_res.watchdog, Some(config));
+        });
+    }
+
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    fn default_tpm_config() -> TpmConfig {
+        TpmConfig {
+            backend: TpmBackendConfig::Software(SoftwareTpmConfig {
+                state_path: String::from("/tpm"),
+            }),
+        }
+    }
+
+    #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+    #[test]
+    fn test_preboot_set_tpm() {
+        let req = VmmAction::SetTpm(default_tpm_config());
+        check_preboot_request(req, |result, vm_res| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vm_res.tpm, Some(default_tpm_config()));
+        });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_preboot_load_snapshot() {
+        let mut vm_resources = MockVmRes::default();
+        let mut evmgr = EventManager::new().unwrap();
+        let mut preboot = default_preboot(&mut vm_resources, &mut evmgr);
+
+        // Without resume.
+        let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
+            snapshot_path: PathBuf::new(),
+            mem_file_path: PathBuf::new(),
+            mem_diff_paths: Vec::new(),
+            enable_diff_snapshots: false,
+            resume_vm: false,
+            #[cfg(feature = "balloon")]
+            balloon_amount_mib: None,
+            #[cfg(feature = "balloon")]
+            deflate_balloon: false,
+            mem_backend: None,
+            mem_hints: None,
+            io_threads: None,
+            mmds_data: None,
+            clone: None,
+        });
+        // Request should succeed.
+        preboot.handle_preboot_request(req).unwrap();
+        // Should have built default mock vmm.
+        let vmm = preboot.built_vmm.take().unwrap();
+        assert_eq!(*vmm.lock().unwrap(), MockVmm::default());
 
         // With resume.
         let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
             snapshot_path: PathBuf::new(),
             mem_file_path: PathBuf::new(),
+            mem_diff_paths: Vec::new(),
             enable_diff_snapshots: false,
             resume_vm: true,
+            #[cfg(feature = "balloon")]
+            balloon_amount_mib: None,
+            #[cfg(feature = "balloon")]
+            deflate_balloon: false,
+            mem_backend: None,
+            mem_hints: None,
+            io_threads: None,
+            mmds_data: None,
+            clone: None,
         });
         // Request should succeed.
         preboot.handle_preboot_request(req).unwrap();
@@ -1187,10 +2916,6 @@ mod tests {
 
     #[test]
     fn test_preboot_disallowed() {
-        check_preboot_request_err(
-            VmmAction::FlushMetrics,
-            VmmActionError::OperationNotSupportedPreBoot,
-        );
         check_preboot_request_err(
             VmmAction::Pause,
             VmmActionError::OperationNotSupportedPreBoot,
@@ -1199,14 +2924,58 @@ mod tests {
             VmmAction::Resume,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        #[cfg(feature = "balloon")]
         check_preboot_request_err(
             VmmAction::GetBalloonStats,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        #[cfg(feature = "virtio-mem")]
+        check_preboot_request_err(
+            VmmAction::GetMemoryHotplugStatus,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        #[cfg(feature = "virtio-mem")]
+        check_preboot_request_err(
+            VmmAction::UpdateMemoryHotplug(MemoryHotplugSizeUpdate {
+                requested_size_mib: 0,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetGuestEvents,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetMachineStats,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetNetworkInterfaceStats(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetDriveRateLimiter(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetNetworkInterfaceRateLimiters(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetVcpuStats,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        #[cfg(feature = "balloon")]
+        check_preboot_request_err(
+            VmmAction::SetBalloonPolicy(BalloonPolicy::default()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        #[cfg(feature = "balloon")]
         check_preboot_request_err(
             VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mb: 0 }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        #[cfg(feature = "balloon")]
         check_preboot_request_err(
             VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
                 stats_polling_interval_s: 0,
@@ -1225,12 +2994,33 @@ mod tests {
             }),
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        #[cfg(feature = "vsock")]
+        check_preboot_request_err(
+            VmmAction::GetVsockStats,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        #[cfg(feature = "vsock")]
+        check_preboot_request_err(
+            VmmAction::UpdateVsockDevice(VsockDeviceUpdateConfig {
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        #[cfg(target_arch = "x86_64")]
+        check_preboot_request_err(
+            VmmAction::CreateCoreDump(PathBuf::from("vmcore")),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         #[cfg(target_arch = "x86_64")]
         check_preboot_request_err(
             VmmAction::CreateSnapshot(CreateSnapshotParams {
                 snapshot_type: SnapshotType::Full,
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                single_file: false,
+                dedup_pages: false,
+                compact_diff: false,
                 version: None,
             }),
             VmmActionError::OperationNotSupportedPreBoot,
@@ -1240,6 +3030,20 @@ mod tests {
             VmmAction::SendCtrlAltDel,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        #[cfg(target_arch = "x86_64")]
+        check_preboot_request_err(
+            VmmAction::SendPowerButton,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        #[cfg(target_arch = "x86_64")]
+        check_preboot_request_err(
+            VmmAction::GracefulShutdown(1000),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::Teardown,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
     }
 
     #[test]
@@ -1254,7 +3058,7 @@ mod tests {
                 1 => VmmAction::FlushMetrics,
                 2 => VmmAction::Pause,
                 3 => VmmAction::Resume,
-                4 => VmmAction::StartMicroVm,
+                4 => VmmAction::StartMicroVm(false),
                 _ => unreachable!(),
             }
         };
@@ -1284,6 +3088,9 @@ mod tests {
             commands,
             expected_resp,
             false,
+            None,
+            None,
+            false,
         );
     }
 
@@ -1292,7 +3099,7 @@ mod tests {
         F: FnOnce(ActionResult, &MockVmm),
     {
         let vmm = Arc::new(Mutex::new(MockVmm::default()));
-        let mut runtime = RuntimeApiController::new(VmConfig::default(), vmm.clone());
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm.clone());
         let res = runtime.handle_request(request);
         check_success(res, &vmm.lock().unwrap());
     }
@@ -1303,11 +3110,56 @@ mod tests {
             force_errors: true,
             ..Default::default()
         }));
-        let mut runtime = RuntimeApiController::new(VmConfig::default(), vmm);
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm);
         let err = runtime.handle_request(request).unwrap_err();
         assert_eq!(err, expected_err);
     }
 
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_runtime_create_core_dump() {
+        let req = VmmAction::CreateCoreDump(PathBuf::from("vmcore"));
+        check_runtime_request(req, |result, _| {
+            assert_eq!(result, Ok(VmmData::Empty));
+        });
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_runtime_create_snapshot() {
+        let req = || {
+            VmmAction::CreateSnapshot(CreateSnapshotParams {
+                snapshot_type: SnapshotType::Full,
+                snapshot_path: PathBuf::new(),
+                mem_file_path: PathBuf::new(),
+                single_file: false,
+                dedup_pages: false,
+                compact_diff: false,
+                version: None,
+            })
+        };
+        check_runtime_request(req(), |result, _| {
+            assert_eq!(result, Ok(VmmData::Empty));
+        });
+
+        // The state of the nested guests would be lost.
+        let vm_res = MockVmRes {
+            vm_config: VmConfig {
+                nested_virt_enabled: Some(true),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(vm_res, vmm);
+        assert_eq!(
+            runtime.handle_request(req()),
+            Err(VmmActionError::CreateSnapshot(
+                CreateSnapshotError::NestedVirtEnabled
+            ))
+        );
+    }
+
     #[test]
     fn test_runtime_get_vm_config() {
         let req = VmmAction::GetVmConfiguration;
@@ -1319,6 +3171,37 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_runtime_get_full_vm_config() {
+        let req = VmmAction::GetFullVmConfig;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::FullVmConfig(FullVmConfig {
+                    state: InstanceState::Running,
+                    ..Default::default()
+                }))
+            );
+        });
+
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm);
+        runtime.handle_request(VmmAction::Pause).unwrap();
+        match runtime.handle_request(VmmAction::GetFullVmConfig) {
+            Ok(VmmData::FullVmConfig(config)) => assert_eq!(config.state, InstanceState::Paused),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_runtime_validate_vm_config() {
+        let req = VmmAction::ValidateVmConfig(Box::new(default_vmm_config()));
+        check_runtime_request(req, |result, _| match result {
+            Err(VmmActionError::FullVmConfig(ResourcesError::BootSource(_))) => (),
+            _ => panic!("Unexpected result."),
+        });
+    }
+
     #[test]
     fn test_runtime_pause() {
         let req = VmmAction::Pause;
@@ -1361,86 +3244,294 @@ mod tests {
         );
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_power_button() {
+        let req = VmmAction::SendPowerButton;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.send_power_button_called)
+        });
+    }
+
+    #[test]
+    fn test_runtime_teardown() {
+        let req = VmmAction::Teardown;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.teardown_called)
+        });
+
+        let req = VmmAction::Teardown;
+        check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::NotReusable));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_graceful_shutdown() {
+        let req = VmmAction::GracefulShutdown(5000);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.send_ctrl_alt_del_called);
+            assert_eq!(vmm.graceful_shutdown_timeout_ms, Some(5000));
+        });
+
+        let req = VmmAction::GracefulShutdown(5000);
+        check_runtime_request_err(
+            req,
+            VmmActionError::InternalVmm(VmmError::I8042Error(
+                devices::legacy::I8042DeviceError::InternalBufferFull,
+            )),
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_set_vcpu_count() {
+        let vcpu_config = VmConfig {
+            vcpu_count: Some(3),
+            mem_size_mib: None,
+            ht_enabled: None,
+            ..Default::default()
+        };
+
+        let vmm = Arc::new(Mutex::new(MockVmm::default()));
+        let mut runtime = RuntimeApiController::new(MockVmRes::default(), vmm.clone());
+        let req = VmmAction::SetVmConfiguration(vcpu_config.clone());
+        assert_eq!(runtime.handle_request(req), Ok(VmmData::Empty));
+        assert_eq!(vmm.lock().unwrap().vcpu_count, Some(3));
+        match runtime.handle_request(VmmAction::GetVmConfiguration) {
+            Ok(VmmData::MachineConfiguration(config)) => assert_eq!(config.vcpu_count, Some(3)),
+            _ => panic!("Unexpected result."),
+        }
+
+        // Nothing but the vCPU count can change after boot.
+        check_runtime_request_err(
+            VmmAction::SetVmConfiguration(VmConfig {
+                mem_size_mib: Some(256),
+                ..vcpu_config.clone()
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+
+        check_runtime_request_err(
+            VmmAction::SetVmConfiguration(vcpu_config),
+            VmmActionError::InternalVmm(VmmError::VcpuHotplugNotConfigured),
+        );
+    }
+
+    #[cfg(feature = "balloon")]
+    #[test]
+    fn test_runtime_balloon_config() {
+        let req = VmmAction::GetBalloonConfig;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::BalloonConfig(BalloonDeviceConfig::default()))
+            );
+            assert!(vmm.balloon_config_called)
+        });
+
+        let req = VmmAction::GetBalloonConfig;
+        #[cfg(feature = "balloon")]
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[cfg(feature = "balloon")]
+    #[test]
+    fn test_runtime_latest_balloon_stats() {
+        let req = VmmAction::GetBalloonStats;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::BalloonStats(BalloonStats::default())));
+            assert!(vmm.latest_balloon_stats_called)
+        });
+
+        let req = VmmAction::GetBalloonStats;
+        #[cfg(feature = "balloon")]
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[cfg(feature = "balloon")]
+    #[test]
+    fn test_runtime_update_balloon_config() {
+        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mb: 0 });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_balloon_config_called)
+        });
+
+        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mb: 0 });
+        #[cfg(feature = "balloon")]
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[cfg(feature = "balloon")]
+    #[test]
+    fn test_runtime_update_balloon_stats_config() {
+        let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
+            stats_polling_interval_s: 0,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_balloon_stats_config_called)
+        });
+
+        let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
+            stats_polling_interval_s: 0,
+        });
+        #[cfg(feature = "balloon")]
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[cfg(feature = "balloon")]
+    #[test]
+    fn test_runtime_set_balloon_policy() {
+        let req = VmmAction::SetBalloonPolicy(BalloonPolicy::default());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_balloon_policy_called)
+        });
+
+        let req = VmmAction::SetBalloonPolicy(BalloonPolicy::default());
+        check_runtime_request_err(
+            req,
+            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+        );
+    }
+
+    #[cfg(feature = "virtio-mem")]
     #[test]
-    fn test_runtime_balloon_config() {
-        let req = VmmAction::GetBalloonConfig;
+    fn test_runtime_memory_hotplug_status() {
+        let req = VmmAction::GetMemoryHotplugStatus;
         check_runtime_request(req, |result, vmm| {
             assert_eq!(
                 result,
-                Ok(VmmData::BalloonConfig(BalloonDeviceConfig::default()))
+                Ok(VmmData::MemoryHotplugStatus(VirtioMemStatus::default()))
             );
-            assert!(vmm.balloon_config_called)
+            assert!(vmm.memory_hotplug_status_called)
         });
 
-        let req = VmmAction::GetBalloonConfig;
+        let req = VmmAction::GetMemoryHotplugStatus;
         check_runtime_request_err(
             req,
-            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::DeviceNotFound),
         );
     }
 
+    #[cfg(feature = "virtio-mem")]
     #[test]
-    fn test_runtime_latest_balloon_stats() {
-        let req = VmmAction::GetBalloonStats;
+    fn test_runtime_update_memory_hotplug() {
+        let req = VmmAction::UpdateMemoryHotplug(MemoryHotplugSizeUpdate {
+            requested_size_mib: 0,
+        });
         check_runtime_request(req, |result, vmm| {
-            assert_eq!(result, Ok(VmmData::BalloonStats(BalloonStats::default())));
-            assert!(vmm.latest_balloon_stats_called)
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_memory_hotplug_size_called)
         });
 
-        let req = VmmAction::GetBalloonStats;
+        let req = VmmAction::UpdateMemoryHotplug(MemoryHotplugSizeUpdate {
+            requested_size_mib: 0,
+        });
         check_runtime_request_err(
             req,
-            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+            VmmActionError::MemoryHotplugConfig(MemoryHotplugConfigError::DeviceNotFound),
         );
     }
 
     #[test]
-    fn test_runtime_update_balloon_config() {
-        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mb: 0 });
+    fn test_runtime_update_block_device_path() {
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            path_on_host: Some(String::new()),
+            ..Default::default()
+        });
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.update_balloon_config_called)
+            assert!(vmm.update_block_device_path_called)
         });
 
-        let req = VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mb: 0 });
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            path_on_host: Some(String::new()),
+            ..Default::default()
+        });
         check_runtime_request_err(
             req,
-            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::IncorrectDeviceType,
+            ))),
         );
     }
 
     #[test]
-    fn test_runtime_update_balloon_stats_config() {
-        let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
-            stats_polling_interval_s: 0,
+    fn test_runtime_update_block_adaptive_rate_limiter() {
+        let adaptive_update = || {
+            VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+                rate_limiter: Some(RateLimiterConfig {
+                    adaptive: Some(AdaptiveConfig {
+                        target_latency_us: 1000,
+                        min_rate_pct: None,
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        };
+        check_runtime_request(adaptive_update(), |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_block_adaptive_rate_limiter_called)
+        });
+
+        // The tuning is left as it is when only the buckets are updated.
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            rate_limiter: Some(RateLimiterConfig::default()),
+            ..Default::default()
         });
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.update_balloon_stats_config_called)
+            assert!(!vmm.update_block_adaptive_rate_limiter_called)
         });
 
-        let req = VmmAction::UpdateBalloonStatistics(BalloonUpdateStatsConfig {
-            stats_polling_interval_s: 0,
-        });
         check_runtime_request_err(
-            req,
-            VmmActionError::BalloonConfig(BalloonConfigError::DeviceNotFound),
+            adaptive_update(),
+            VmmActionError::DriveConfig(DriveError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::IncorrectDeviceType,
+            ))),
         );
     }
 
     #[test]
-    fn test_runtime_update_block_device_path() {
+    fn test_runtime_update_block_io_weight() {
         let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
-            path_on_host: Some(String::new()),
+            io_weight: Some(100),
             ..Default::default()
         });
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
-            assert!(vmm.update_block_device_path_called)
+            assert!(vmm.update_block_io_weight_called)
         });
 
         let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
-            path_on_host: Some(String::new()),
+            io_weight: Some(0),
+            ..Default::default()
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::InvalidIoWeight(0)),
+        );
+
+        let req = VmmAction::UpdateBlockDevice(BlockDeviceUpdateConfig {
+            io_weight: Some(100),
             ..Default::default()
         });
         check_runtime_request_err(
@@ -1476,6 +3567,190 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg(feature = "vsock")]
+    fn test_runtime_update_vsock_rate_limiters() {
+        let req = VmmAction::UpdateVsockDevice(VsockDeviceUpdateConfig {
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.update_vsock_rate_limiters_called)
+        });
+
+        let req = VmmAction::UpdateVsockDevice(VsockDeviceUpdateConfig {
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+        });
+        check_runtime_request_err(
+            req,
+            VmmActionError::VsockConfig(VsockConfigError::DeviceUpdate(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::IncorrectDeviceType,
+            ))),
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "vsock")]
+    fn test_runtime_vsock_stats() {
+        check_runtime_request(VmmAction::GetVsockStats, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::VsockStats(VsockDeviceStats::default())));
+            assert!(vmm.vsock_stats_called)
+        });
+
+        check_runtime_request_err(
+            VmmAction::GetVsockStats,
+            VmmActionError::VsockConfig(VsockConfigError::DeviceStats(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::DeviceNotFound,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_guest_events() {
+        let req = VmmAction::GetGuestEvents;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::GuestEvents(GuestEvents::default())));
+            assert!(vmm.guest_events_called)
+        });
+    }
+
+    #[test]
+    fn test_runtime_machine_stats() {
+        let req = VmmAction::GetMachineStats;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::MachineStats(MachineStats::default())));
+            assert!(vmm.machine_stats_called)
+        });
+
+        let req = VmmAction::GetMachineStats;
+        check_runtime_request_err(
+            req,
+            VmmActionError::InternalVmm(VmmError::MachineStats(std::io::Error::from_raw_os_error(
+                libc::EIO,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_vcpu_stats() {
+        let req = VmmAction::GetVcpuStats;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::VcpuStats(vec![VcpuStats::default()])));
+            assert!(vmm.vcpu_stats_called)
+        });
+    }
+
+    #[test]
+    fn test_runtime_rate_limiter_stats() {
+        let req = VmmAction::GetDriveRateLimiter(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::DriveRateLimiter(RateLimiterStats::default()))
+            );
+            assert!(vmm.block_rate_limiter_stats_called)
+        });
+
+        let req = VmmAction::GetDriveRateLimiter(String::new());
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceStats(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::DeviceNotFound,
+            ))),
+        );
+
+        let req = VmmAction::GetNetworkInterfaceRateLimiters(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::NetworkInterfaceRateLimiters(
+                    NetRateLimiterStats::default()
+                ))
+            );
+            assert!(vmm.net_rate_limiter_stats_called)
+        });
+
+        let req = VmmAction::GetNetworkInterfaceRateLimiters(String::new());
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceStats(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::DeviceNotFound),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_net_stats() {
+        let req = VmmAction::GetNetworkInterfaceStats(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::NetworkInterfaceStats(NetDeviceStats::default()))
+            );
+            assert!(vmm.net_stats_called)
+        });
+
+        let req = VmmAction::GetNetworkInterfaceStats(String::new());
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceStats(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::DeviceNotFound),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_set_panic_action() {
+        let req = VmmAction::SetPanicAction(PanicAction::Pause);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vmm.panic_action, PanicAction::Pause);
+        });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_set_clock_policy() {
+        let req = VmmAction::SetClockPolicy(ClockPolicy::Sync);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vmm.clock_policy, ClockPolicy::Sync);
+        });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_set_shutdown_behavior() {
+        let config = default_shutdown_behavior();
+        let req = VmmAction::SetShutdownBehavior(config.clone());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vmm.shutdown_behavior, config);
+        });
+    }
+
+    #[test]
+    fn test_runtime_set_cpu_quota() {
+        let req = VmmAction::SetCpuQuota(CpuQuotaConfig { quota_pct: 50 });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert_eq!(vmm.cpu_quota_pct, Some(50));
+        });
+
+        let req = VmmAction::SetCpuQuota(CpuQuotaConfig { quota_pct: 101 });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Err(VmmActionError::CpuQuotaConfig(
+                    CpuQuotaConfigError::InvalidQuota(101)
+                ))
+            );
+            assert_eq!(vmm.cpu_quota_pct, None);
+        });
+    }
+
     #[test]
     fn test_runtime_disallowed() {
         check_runtime_request_err(
@@ -1494,6 +3769,9 @@ mod tests {
         check_runtime_request_err(
             VmmAction::ConfigureMetrics(MetricsConfig {
                 metrics_path: PathBuf::new(),
+                line_protocol: None,
+                statsd: None,
+                flush_interval_ms: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1505,6 +3783,8 @@ mod tests {
                 is_read_only: false,
                 drive_id: String::new(),
                 rate_limiter: None,
+                io_weight: None,
+                rate_limiter_group: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1516,31 +3796,112 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: false,
+                vlan_id: None,
+                dhcp: None,
+                rate_limiter_group: None,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        #[cfg(feature = "null-devices")]
+        check_runtime_request_err(
+            VmmAction::InsertNullDevice(NullDeviceConfig {
+                device_id: String::new(),
+                device_type: NullDeviceType::Gpu,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        #[cfg(feature = "virtio-pmem")]
+        check_runtime_request_err(
+            VmmAction::InsertPmemDevice(default_pmem_config()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::InsertSerialPort(default_serial_port_config()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetRateLimiterGroup(default_rate_limiter_group_config()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        #[cfg(target_arch = "x86_64")]
+        check_runtime_request_err(
+            VmmAction::SetWatchdog(WatchdogConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        #[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+        check_runtime_request_err(
+            VmmAction::SetTpm(default_tpm_config()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        #[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+        check_runtime_request_err(
+            VmmAction::InsertVfioDevice(default_vfio_config()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        #[cfg(feature = "virtio-fs")]
+        check_runtime_request_err(
+            VmmAction::InsertSharedFs(default_shared_fs_config()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        #[cfg(feature = "vsock")]
         check_runtime_request_err(
             VmmAction::SetVsockDevice(VsockDeviceConfig {
                 vsock_id: String::new(),
                 guest_cid: 0,
+                backend: VsockBackendType::Unix,
                 uds_path: String::new(),
+                mmds_port: None,
+                agent_port: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                port_mappings: Vec::new(),
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        #[cfg(feature = "balloon")]
         check_runtime_request_err(
             VmmAction::SetBalloonDevice(BalloonDeviceConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
+        #[cfg(feature = "virtio-console")]
+        check_runtime_request_err(
+            VmmAction::SetConsoleDevice(default_console_config()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        #[cfg(feature = "virtio-rng")]
+        check_runtime_request_err(
+            VmmAction::SetEntropyDevice(EntropyDeviceConfig::default()),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        #[cfg(feature = "virtio-mem")]
+        check_runtime_request_err(
+            VmmAction::SetMemoryHotplug(MemoryHotplugConfig {
+                total_size_mib: 1024,
+                block_size_mib: 2,
+            }),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        #[cfg(feature = "vsock")]
         check_runtime_request_err(
             VmmAction::SetVsockDevice(VsockDeviceConfig {
                 vsock_id: String::new(),
                 guest_cid: 0,
+                backend: VsockBackendType::Unix,
                 uds_path: String::new(),
+                mmds_port: None,
+                agent_port: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                port_mappings: Vec::new(),
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
-            VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None }),
+            VmmAction::SetFullVmConfig(Box::new(default_vmm_config())),
+            VmmActionError::OperationNotSupportedPostBoot,
+        );
+        check_runtime_request_err(
+            VmmAction::SetMmdsConfiguration(MmdsConfig::default()),
             VmmActionError::OperationNotSupportedPostBoot,
         );
         check_runtime_request_err(
@@ -1552,8 +3913,18 @@ mod tests {
             VmmAction::LoadSnapshot(LoadSnapshotParams {
                 snapshot_path: PathBuf::new(),
                 mem_file_path: PathBuf::new(),
+                mem_diff_paths: Vec::new(),
                 enable_diff_snapshots: false,
                 resume_vm: false,
+                #[cfg(feature = "balloon")]
+                balloon_amount_mib: None,
+                #[cfg(feature = "balloon")]
+                deflate_balloon: false,
+                mem_backend: None,
+                mem_hints: None,
+                io_threads: None,
+                mmds_data: None,
+                clone: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -1571,8 +3942,18 @@ mod tests {
         let req = VmmAction::LoadSnapshot(LoadSnapshotParams {
             snapshot_path: PathBuf::new(),
             mem_file_path: PathBuf::new(),
+            mem_diff_paths: Vec::new(),
             enable_diff_snapshots: false,
             resume_vm: false,
+            #[cfg(feature = "balloon")]
+            balloon_amount_mib: None,
+            #[cfg(feature = "balloon")]
+            deflate_balloon: false,
+            mem_backend: None,
+            mem_hints: None,
+            io_threads: None,
+            mmds_data: None,
+            clone: None,
         });
         let err = preboot.handle_preboot_request(req);
         assert_eq!(
@@ -1597,6 +3978,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::new(),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertBlockDevice");
 
@@ -1607,23 +3990,104 @@ mod tests {
             rx_rate_limiter: None,
             tx_rate_limiter: None,
             allow_mmds_requests: false,
+            vlan_id: None,
+            dhcp: None,
+            rate_limiter_group: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertNetworkDevice");
 
-        let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
-        verify_load_snap_disallowed_after_boot_resources(req, "SetBalloonDevice");
+        #[cfg(feature = "null-devices")]
+        {
+            let req = VmmAction::InsertNullDevice(NullDeviceConfig {
+                device_id: String::new(),
+                device_type: NullDeviceType::Input,
+            });
+            verify_load_snap_disallowed_after_boot_resources(req, "InsertNullDevice");
+        }
 
-        let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
-            vsock_id: String::new(),
-            guest_cid: 0,
-            uds_path: String::new(),
-        });
-        verify_load_snap_disallowed_after_boot_resources(req, "SetVsockDevice");
+        #[cfg(feature = "virtio-pmem")]
+        {
+            let req = VmmAction::InsertPmemDevice(default_pmem_config());
+            verify_load_snap_disallowed_after_boot_resources(req, "InsertPmemDevice");
+        }
+
+        let req = VmmAction::InsertSerialPort(default_serial_port_config());
+        verify_load_snap_disallowed_after_boot_resources(req, "InsertSerialPort");
+
+        let req = VmmAction::SetRateLimiterGroup(default_rate_limiter_group_config());
+        verify_load_snap_disallowed_after_boot_resources(req, "SetRateLimiterGroup");
+
+        #[cfg(feature = "virtio-fs")]
+        {
+            let req = VmmAction::InsertSharedFs(default_shared_fs_config());
+            verify_load_snap_disallowed_after_boot_resources(req, "InsertSharedFs");
+        }
+
+        #[cfg(feature = "balloon")]
+        {
+            let req = VmmAction::SetBalloonDevice(BalloonDeviceConfig::default());
+            verify_load_snap_disallowed_after_boot_resources(req, "SetBalloonDevice");
+        }
+
+        #[cfg(feature = "virtio-console")]
+        {
+            let req = VmmAction::SetConsoleDevice(default_console_config());
+            verify_load_snap_disallowed_after_boot_resources(req, "SetConsoleDevice");
+        }
+
+        #[cfg(feature = "virtio-rng")]
+        {
+            let req = VmmAction::SetEntropyDevice(EntropyDeviceConfig::default());
+            verify_load_snap_disallowed_after_boot_resources(req, "SetEntropyDevice");
+        }
+
+        #[cfg(feature = "virtio-mem")]
+        {
+            let req = VmmAction::SetMemoryHotplug(MemoryHotplugConfig {
+                total_size_mib: 1024,
+                block_size_mib: 2,
+            });
+            verify_load_snap_disallowed_after_boot_resources(req, "SetMemoryHotplug");
+        }
+
+        #[cfg(feature = "vsock")]
+        {
+            let req = VmmAction::SetVsockDevice(VsockDeviceConfig {
+                vsock_id: String::new(),
+                guest_cid: 0,
+                backend: VsockBackendType::Unix,
+                uds_path: String::new(),
+                mmds_port: None,
+                agent_port: None,
+                rx_rate_limiter: None,
+                tx_rate_limiter: None,
+                port_mappings: Vec::new(),
+            });
+            verify_load_snap_disallowed_after_boot_resources(req, "SetVsockDevice");
+        }
+
+        let req = VmmAction::SetFullVmConfig(Box::new(default_vmm_config()));
+        verify_load_snap_disallowed_after_boot_resources(req, "SetFullVmConfig");
 
         let req = VmmAction::SetVmConfiguration(VmConfig::default());
         verify_load_snap_disallowed_after_boot_resources(req, "SetVmConfiguration");
 
-        let req = VmmAction::SetMmdsConfiguration(MmdsConfig { ipv4_address: None });
+        let req = VmmAction::SetMmdsConfiguration(MmdsConfig::default());
         verify_load_snap_disallowed_after_boot_resources(req, "SetMmdsConfiguration");
+
+        let req = VmmAction::SetWatchdog(WatchdogConfig::default());
+        verify_load_snap_disallowed_after_boot_resources(req, "SetWatchdog");
+
+        #[cfg(feature = "tpm")]
+        {
+            let req = VmmAction::SetTpm(default_tpm_config());
+            verify_load_snap_disallowed_after_boot_resources(req, "SetTpm");
+        }
+
+        #[cfg(feature = "vfio")]
+        {
+            let req = VmmAction::InsertVfioDevice(default_vfio_config());
+            verify_load_snap_disallowed_after_boot_resources(req, "InsertVfioDevice");
+        }
     }
 }
@@ -0,0 +1,314 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::env;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use logger::{error, warn};
+use polly::event_manager::{EventManager, Subscriber};
+use timerfd::{ClockId, SetTimeFlags, TimerFd, TimerState};
+use utils::epoll::{EpollEvent, EventSet};
+
+use crate::lifecycle::LifecycleEventKind;
+
+lazy_static! {
+    /// Static instance used for notifying systemd of the state of the VMM.
+    pub static ref SD_NOTIFY: SdNotify = SdNotify::from_env();
+}
+
+// The environment variables set by systemd for the services it supervises.
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID: &str = "WATCHDOG_PID";
+
+// Builds the address of the notification socket, which is abstract when it starts with '@'.
+fn socket_addr(path: &str) -> Option<(libc::sockaddr_un, libc::socklen_t)> {
+    let bytes = path.as_bytes();
+    // Safe because a zeroed `sockaddr_un` is valid.
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    // The path of a filesystem socket is NUL terminated, unlike an abstract one.
+    let path_len = match bytes.first() {
+        Some(b'/') if bytes.len() < addr.sun_path.len() => bytes.len() + 1,
+        Some(b'@') if bytes.len() <= addr.sun_path.len() => bytes.len(),
+        _ => return None,
+    };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes.iter()) {
+        *dst = *src as libc::c_char;
+    }
+    if bytes[0] == b'@' {
+        addr.sun_path[0] = 0;
+    }
+    let addr_len = mem::size_of::<libc::sa_family_t>() + path_len;
+    Some((addr, addr_len as libc::socklen_t))
+}
+
+/// Sends notifications to systemd on the socket it passes in `NOTIFY_SOCKET`, so the VMM can
+/// run as a `Type=notify` service. It does nothing when the VMM is not run by systemd.
+pub struct SdNotify {
+    socket: Option<(RawFd, libc::sockaddr_un, libc::socklen_t)>,
+}
+
+impl SdNotify {
+    fn from_env() -> Self {
+        let path = match env::var(NOTIFY_SOCKET) {
+            Ok(path) => path,
+            Err(_) => return SdNotify { socket: None },
+        };
+        let (addr, addr_len) = match socket_addr(&path) {
+            Some(addr) => addr,
+            None => {
+                warn!("Ignoring the invalid systemd notification socket {}", path);
+                return SdNotify { socket: None };
+            }
+        };
+        // Safe because the arguments are valid, and we check the result.
+        let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            error!(
+                "Cannot create the systemd notification socket: {}",
+                io::Error::last_os_error()
+            );
+            return SdNotify { socket: None };
+        }
+        SdNotify {
+            socket: Some((fd, addr, addr_len)),
+        }
+    }
+
+    /// Returns whether the VMM notifies systemd.
+    pub fn enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Sends `state`, made of newline separated `VARIABLE=value` assignments, to systemd.
+    pub fn notify(&self, state: &str) {
+        let (fd, addr, addr_len) = match self.socket.as_ref() {
+            Some(socket) => socket,
+            None => return,
+        };
+        // Safe because `state` and `addr` are valid, and we check the result.
+        let ret = unsafe {
+            libc::sendto(
+                *fd,
+                state.as_ptr() as *const libc::c_void,
+                state.len(),
+                libc::MSG_NOSIGNAL,
+                addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                *addr_len,
+            )
+        };
+        if ret < 0 {
+            warn!(
+                "Cannot notify systemd of {:?}: {}",
+                state,
+                io::Error::last_os_error()
+            );
+        }
+    }
+
+    /// Reports the change in the lifecycle of the microVM as the status of the service.
+    pub fn lifecycle_event(&self, kind: &LifecycleEventKind) {
+        if self.enabled() {
+            if let Some(state) = lifecycle_state(kind) {
+                self.notify(state);
+            }
+        }
+    }
+}
+
+// Returns the notification describing the lifecycle event, if the status of the service changes.
+fn lifecycle_state(kind: &LifecycleEventKind) -> Option<&'static str> {
+    use self::LifecycleEventKind::*;
+    match kind {
+        Started | Resumed => Some("STATUS=Running"),
+        Paused => Some("STATUS=Paused"),
+        SnapshotStarted => Some("STATUS=Creating a snapshot"),
+        SnapshotCreated => Some("STATUS=Paused, the snapshot was created"),
+        SnapshotFailed => Some("STATUS=Paused, the snapshot could not be created"),
+        SnapshotLoaded => Some("STATUS=Restored from a snapshot, paused"),
+        Shutdown { .. } => Some("STOPPING=1\nSTATUS=Exiting"),
+        TornDown => Some("STATUS=Torn down, waiting for a new microVM"),
+        _ => None,
+    }
+}
+
+// Returns the interval at which systemd expects the watchdog heartbeats of the process `pid`.
+fn parse_watchdog_interval(
+    usec: Option<&str>,
+    watchdog_pid: Option<&str>,
+    pid: u32,
+) -> Option<Duration> {
+    // The watchdog is meant for another process when the pid is not ours.
+    if let Some(watchdog_pid) = watchdog_pid {
+        if watchdog_pid.parse::<u32>().ok()? != pid {
+            return None;
+        }
+    }
+    match usec?.parse::<u64>().ok()? {
+        0 => None,
+        usec => Some(Duration::from_micros(usec)),
+    }
+}
+
+/// Returns the interval at which systemd expects the watchdog heartbeats, if it watches the VMM.
+pub fn watchdog_interval() -> Option<Duration> {
+    if !SD_NOTIFY.enabled() {
+        return None;
+    }
+    parse_watchdog_interval(
+        env::var(WATCHDOG_USEC).ok().as_deref(),
+        env::var(WATCHDOG_PID).ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+/// Sends the watchdog heartbeats to systemd from the event loop, so that the VMM is restarted
+/// when the loop gets stuck.
+pub struct SdWatchdog {
+    timer_fd: TimerFd,
+}
+
+impl SdWatchdog {
+    /// Creates the watchdog, which sends a heartbeat every half of `interval`.
+    pub fn new(interval: Duration) -> io::Result<Self> {
+        let mut timer_fd = TimerFd::new_custom(ClockId::Monotonic, true, true)?;
+        let period = interval / 2;
+        timer_fd.set_state(
+            TimerState::Periodic {
+                current: period,
+                interval: period,
+            },
+            SetTimeFlags::Default,
+        );
+        Ok(SdWatchdog { timer_fd })
+    }
+}
+
+impl Subscriber for SdWatchdog {
+    fn process(&mut self, event: &EpollEvent, _: &mut EventManager) {
+        if event.fd() == self.timer_fd.as_raw_fd() && event.event_set() == EventSet::IN {
+            self.timer_fd.read();
+            SD_NOTIFY.notify("WATCHDOG=1");
+        } else {
+            error!("Spurious systemd watchdog event!");
+        }
+    }
+
+    fn interest_list(&self) -> Vec<EpollEvent> {
+        vec![EpollEvent::new(
+            EventSet::IN,
+            self.timer_fd.as_raw_fd() as u64,
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_socket_addr() {
+        let (addr, addr_len) = socket_addr("/run/systemd/notify").unwrap();
+        assert_eq!(addr.sun_family, libc::AF_UNIX as libc::sa_family_t);
+        assert_eq!(addr.sun_path[0], b'/' as libc::c_char);
+        assert_eq!(addr.sun_path[19], 0);
+        assert_eq!(addr_len as usize, mem::size_of::<libc::sa_family_t>() + 20);
+
+        let (addr, addr_len) = socket_addr("@notify").unwrap();
+        assert_eq!(addr.sun_path[0], 0);
+        assert_eq!(addr.sun_path[1], b'n' as libc::c_char);
+        assert_eq!(addr_len as usize, mem::size_of::<libc::sa_family_t>() + 7);
+
+        assert!(socket_addr("").is_none());
+        assert!(socket_addr("run/systemd/notify").is_none());
+        assert!(socket_addr(&format!("/{}", "a".repeat(107))).is_none());
+    }
+
+    #[test]
+    fn test_notify() {
+        let path = utils::tempfile::TempFile::new()
+            .unwrap()
+            .as_path()
+            .to_path_buf();
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let (addr, addr_len) = socket_addr(path.to_str().unwrap()).unwrap();
+        let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        assert!(fd >= 0);
+        let sd_notify = SdNotify {
+            socket: Some((fd, addr, addr_len)),
+        };
+        assert!(sd_notify.enabled());
+
+        sd_notify.notify("READY=1");
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        sd_notify.lifecycle_event(&LifecycleEventKind::Paused);
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STATUS=Paused");
+
+        // Nothing is sent when the VMM does not notify systemd.
+        SdNotify { socket: None }.notify("READY=1");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lifecycle_state() {
+        assert_eq!(
+            lifecycle_state(&LifecycleEventKind::Started),
+            Some("STATUS=Running")
+        );
+        assert_eq!(
+            lifecycle_state(&LifecycleEventKind::Shutdown { exit_code: 0 }),
+            Some("STOPPING=1\nSTATUS=Exiting")
+        );
+        assert_eq!(
+            lifecycle_state(&LifecycleEventKind::VcpusPlugged { vcpu_count: 2 }),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_watchdog_interval() {
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), Some("43"), 42),
+            None
+        );
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), Some("pid"), 42),
+            None
+        );
+        assert_eq!(parse_watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog_interval(Some("30s"), None, 42), None);
+        assert_eq!(parse_watchdog_interval(None, None, 42), None);
+    }
+
+    #[test]
+    fn test_sd_watchdog() {
+        let watchdog = SdWatchdog::new(Duration::from_secs(30)).unwrap();
+        assert_eq!(
+            watchdog.interest_list()[0].fd(),
+            watchdog.timer_fd.as_raw_fd()
+        );
+        match watchdog.timer_fd.get_state() {
+            TimerState::Periodic { interval, .. } => assert_eq!(interval, Duration::from_secs(15)),
+            _ => panic!("The timer should be periodic"),
+        }
+    }
+}
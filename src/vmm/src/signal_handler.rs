@@ -1,9 +1,12 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
 use libc::{
-    _exit, c_int, c_void, siginfo_t, SIGBUS, SIGHUP, SIGILL, SIGPIPE, SIGSEGV, SIGSYS, SIGXCPU,
-    SIGXFSZ,
+    _exit, c_int, c_void, siginfo_t, SIGBUS, SIGHUP, SIGILL, SIGPIPE, SIGSEGV, SIGSYS, SIGTERM,
+    SIGXCPU, SIGXFSZ,
 };
 
 use logger::{error, IncMetric, METRICS};
@@ -18,6 +21,27 @@ const SI_OFF_SYSCALL: isize = 6;
 
 const SYS_SECCOMP_CODE: i32 = 1;
 
+// The event fd of the built microVM, which the `SIGTERM` handler writes to.
+static SIGTERM_EVENT_FD: AtomicI32 = AtomicI32::new(-1);
+// Whether `SIGTERM` was intercepted, for the loops which do not run the events of the microVM.
+static SIGTERM_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Makes the `SIGTERM` handler write to `fd`, so that the event loop shuts down the microVM.
+pub(crate) fn set_sigterm_event_fd(fd: RawFd) {
+    SIGTERM_EVENT_FD.store(fd, Ordering::SeqCst);
+}
+
+/// Stops the `SIGTERM` handler from writing to `fd`, unless another microVM replaced it.
+pub(crate) fn clear_sigterm_event_fd(fd: RawFd) {
+    let _ = SIGTERM_EVENT_FD.compare_exchange(fd, -1, Ordering::SeqCst, Ordering::SeqCst);
+}
+
+/// Returns whether `SIGTERM` was intercepted, and waits for the event loop to shut down the
+/// microVM.
+pub fn sigterm_pending() -> bool {
+    SIGTERM_PENDING.load(Ordering::SeqCst)
+}
+
 macro_rules! generate_handler {
     ($fn_name:ident ,$signal_name:ident, $exit_code:ident, $signal_metric:expr, $body:ident) => {
         #[inline(always)]
@@ -121,13 +145,64 @@ generate_handler!(
     log_sigsys_err
 );
 
-generate_handler!(
-    sighup_handler,
-    SIGHUP,
-    FC_EXIT_CODE_SIGHUP,
-    METRICS.signals.sighup,
-    empty_fn
-);
+// Unlike the other signals, `SIGHUP` does not shut down the VM, but makes it reopen its log and
+// metrics files, so that they can be rotated.
+extern "C" fn sighup_handler(num: c_int, info: *mut siginfo_t, _unused: *mut c_void) {
+    // Safe because we're just reading some fields from a supposedly valid argument.
+    let si_signo = unsafe { (*info).si_signo };
+
+    if num != si_signo || num != SIGHUP {
+        // Safe because we're terminating the process anyway.
+        unsafe { _exit(i32::from(super::FC_EXIT_CODE_UNEXPECTED_ERROR)) };
+    }
+    METRICS.signals.sighup.inc();
+
+    // The files are reopened by the event loop, as it is not async-signal-safe.
+    crate::log_rotation::request_log_rotation();
+}
+
+// Unlike the other signals, `SIGTERM` lets the event loop pause the microVM, save its snapshot if
+// asked to, and write its metrics before exiting. Before the microVM is built, there is nothing
+// to save, so the process exits right away.
+extern "C" fn sigterm_handler(num: c_int, info: *mut siginfo_t, _unused: *mut c_void) {
+    // Safe because we're just reading some fields from a supposedly valid argument.
+    let si_signo = unsafe { (*info).si_signo };
+
+    if num != si_signo || num != SIGTERM {
+        // Safe because we're terminating the process anyway.
+        unsafe { _exit(i32::from(super::FC_EXIT_CODE_UNEXPECTED_ERROR)) };
+    }
+    METRICS.signals.sigterm.inc();
+    SIGTERM_PENDING.store(true, Ordering::SeqCst);
+
+    let fd = SIGTERM_EVENT_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let value: u64 = 1;
+        // Safe because the value outlives the call. A failure means that the counter of the
+        // event fd is already set, so there is nothing to do about it.
+        unsafe {
+            libc::write(
+                fd,
+                &value as *const u64 as *const c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+        return;
+    }
+
+    error!("Shutting down after intercepting SIGTERM.");
+    // Write the metrics before exiting.
+    if let Err(e) = METRICS.write() {
+        error!("Failed to write metrics while stopping: {}", e);
+    }
+    // Safe because we're terminating the process anyway. We don't actually do anything when
+    // running unit tests.
+    #[cfg(not(test))]
+    unsafe {
+        _exit(i32::from(super::FC_EXIT_CODE_SIGTERM))
+    };
+}
+
 generate_handler!(
     sigill_handler,
     SIGILL,
@@ -138,7 +213,9 @@ generate_handler!(
 /// Registers all the required signal handlers.
 ///
 /// Custom handlers are installed for: `SIGBUS`, `SIGSEGV`, `SIGSYS`
-/// `SIGXFSZ` `SIGXCPU` `SIGPIPE` `SIGHUP` and `SIGILL`.
+/// `SIGXFSZ` `SIGXCPU` `SIGPIPE` `SIGHUP` `SIGILL` and `SIGTERM`. All of them but `SIGHUP`,
+/// which makes the VMM reopen its log and metrics files, shut down the VM. `SIGTERM` does so
+/// gracefully, from the event loop.
 pub fn register_signal_handlers() -> utils::errno::Result<()> {
     // Call to unsafe register_signal_handler which is considered unsafe because it will
     // register a signal handler which will be called in the current thread and will interrupt
@@ -152,6 +229,7 @@ pub fn register_signal_handlers() -> utils::errno::Result<()> {
     register_signal_handler(SIGPIPE, sigpipe_handler)?;
     register_signal_handler(SIGHUP, sighup_handler)?;
     register_signal_handler(SIGILL, sigill_handler)?;
+    register_signal_handler(SIGTERM, sigterm_handler)?;
     Ok(())
 }
 
@@ -261,6 +339,12 @@ mod tests {
             unsafe {
                 syscall(libc::SYS_kill, process::id(), SIGILL);
             }
+
+            // Call SIGTERM signal handler.
+            assert_eq!(METRICS.signals.sigterm.count(), 0);
+            unsafe {
+                syscall(libc::SYS_kill, process::id(), SIGTERM);
+            }
         });
         assert!(child.join().is_ok());
 
@@ -282,6 +366,8 @@ mod tests {
         assert!(METRICS.signals.sigxcpu.count() >= 1);
         assert!(METRICS.signals.sigpipe.count() >= 1);
         assert!(METRICS.signals.sighup.count() >= 1);
+        assert!(METRICS.signals.sigterm.count() >= 1);
+        assert!(sigterm_pending());
         // Workaround to GitHub issue 2216.
         #[cfg(not(target_arch = "aarch64"))]
         assert!(METRICS.signals.sigill.count() >= 1);
@@ -8,6 +8,22 @@ use std::collections::HashMap;
 // Currently only supports x86_64.
 #[cfg(target_arch = "x86_64")]
 use crate::device_manager::persist::DeviceStates;
+#[cfg(target_arch = "x86_64")]
+use crate::memory_snapshot::{GuestMemoryRegionState, GuestMemoryState};
+#[cfg(target_arch = "x86_64")]
+use crate::persist::MicrovmState;
+#[cfg(target_arch = "x86_64")]
+use crate::vstate::vcpu::VcpuState;
+#[cfg(target_arch = "x86_64")]
+use crate::vstate::vm::VmState;
+#[cfg(all(target_arch = "x86_64", feature = "balloon"))]
+use devices::virtio::balloon::persist::BalloonState;
+#[cfg(all(target_arch = "x86_64", feature = "net"))]
+use devices::virtio::net::persist::NetState;
+#[cfg(all(target_arch = "x86_64", feature = "vsock"))]
+use devices::virtio::vsock::persist::{VsockFrontendState, VsockUdsState};
+#[cfg(target_arch = "x86_64")]
+use mmds::persist::MmdsNetworkStackState;
 
 use lazy_static::lazy_static;
 use versionize::VersionMap;
@@ -21,7 +37,23 @@ lazy_static! {
         #[cfg(target_arch = "x86_64")]
         {
             let mut version_map = VersionMap::new();
-            version_map.new_version().set_type_version(DeviceStates::type_id(), 2);
+            version_map
+                .new_version()
+                .set_type_version(MicrovmState::type_id(), 2)
+                .set_type_version(DeviceStates::type_id(), 2)
+                .set_type_version(GuestMemoryState::type_id(), 2)
+                .set_type_version(GuestMemoryRegionState::type_id(), 2)
+                .set_type_version(MmdsNetworkStackState::type_id(), 2)
+                .set_type_version(VcpuState::type_id(), 2)
+                .set_type_version(VmState::type_id(), 2);
+            #[cfg(feature = "vsock")]
+            version_map
+                .set_type_version(VsockUdsState::type_id(), 2)
+                .set_type_version(VsockFrontendState::type_id(), 2);
+            #[cfg(feature = "net")]
+            version_map.set_type_version(NetState::type_id(), 2);
+            #[cfg(feature = "balloon")]
+            version_map.set_type_version(BalloonState::type_id(), 2);
             version_map
         }
 
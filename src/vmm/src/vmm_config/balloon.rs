@@ -5,6 +5,7 @@ use std::fmt;
 use std::sync::{Arc, Mutex};
 
 pub use devices::virtio::balloon::device::BalloonStats;
+pub use devices::virtio::balloon::BalloonPolicy;
 use devices::virtio::balloon::Error as BalloonError;
 pub use devices::virtio::BALLOON_DEV_ID;
 use devices::virtio::{Balloon, BalloonConfig};
@@ -27,10 +28,14 @@ pub enum BalloonConfigError {
     /// The user polled the statistics of a balloon device that
     /// does not have the statistics enabled.
     StatsNotFound,
+    /// The bounds of the balloon policy are inconsistent.
+    InvalidPolicy(&'static str),
     /// Failed to create a balloon device.
     CreateFailure(devices::virtio::balloon::Error),
     /// Failed to update the configuration of the ballon device.
     UpdateFailure(std::io::Error),
+    /// The guest memory is backed by huge pages, which the balloon cannot give back.
+    IncompatibleMemoryBackend,
 }
 
 impl fmt::Display for BalloonConfigError {
@@ -42,12 +47,17 @@ impl fmt::Display for BalloonConfigError {
             InvalidStatsUpdate => write!(f, "Cannot enable/disable the statistics after boot."),
             TooManyPagesRequested => write!(f, "Amount of pages requested is too large."),
             StatsNotFound => write!(f, "Statistics for the balloon device are not enabled"),
+            InvalidPolicy(reason) => write!(f, "Invalid balloon policy: {}.", reason),
             CreateFailure(e) => write!(f, "Error creating the balloon device: {:?}", e),
             UpdateFailure(e) => write!(
                 f,
                 "Error updating the balloon device configuration: {:?}",
                 e
             ),
+            IncompatibleMemoryBackend => write!(
+                f,
+                "The balloon device cannot be used with guest memory backed by huge pages."
+            ),
         }
     }
 }
@@ -61,6 +71,7 @@ impl From<BalloonError> for BalloonConfigError {
             BalloonError::StatisticsStateChange => Self::InvalidStatsUpdate,
             BalloonError::StatisticsDisabled => Self::StatsNotFound,
             BalloonError::TooManyPagesRequested => Self::TooManyPagesRequested,
+            BalloonError::InvalidPolicy(reason) => Self::InvalidPolicy(reason),
             e => Self::CreateFailure(e),
         }
     }
@@ -80,6 +91,9 @@ pub struct BalloonDeviceConfig {
     /// Interval in seconds between refreshing statistics.
     #[serde(default)]
     pub stats_polling_interval_s: u16,
+    /// Option to reclaim the memory that the guest reports as free.
+    #[serde(default)]
+    pub free_page_reporting: bool,
 }
 
 impl From<BalloonConfig> for BalloonDeviceConfig {
@@ -88,6 +102,7 @@ impl From<BalloonConfig> for BalloonDeviceConfig {
             amount_mb: state.amount_mb,
             deflate_on_oom: state.deflate_on_oom,
             stats_polling_interval_s: state.stats_polling_interval_s,
+            free_page_reporting: state.free_page_reporting,
         }
     }
 }
@@ -138,6 +153,7 @@ impl BalloonBuilder {
                 cfg.amount_mb,
                 cfg.deflate_on_oom,
                 cfg.stats_polling_interval_s,
+                cfg.free_page_reporting,
                 // `restored` flag is false because this code path
                 // is never called by snapshot restore functionality.
                 false,
@@ -171,6 +187,7 @@ pub(crate) mod tests {
             amount_mb: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         }
     }
 
@@ -189,6 +206,7 @@ pub(crate) mod tests {
             amount_mb: 0,
             deflate_on_oom: false,
             stats_polling_interval_s: 0,
+            free_page_reporting: false,
         };
         assert_eq!(default_balloon_config, balloon_config);
         let mut builder = BalloonBuilder::new();
@@ -210,12 +228,14 @@ pub(crate) mod tests {
             amount_mb: 5,
             deflate_on_oom: false,
             stats_polling_interval_s: 3,
+            free_page_reporting: true,
         };
 
         let actual_balloon_config = BalloonDeviceConfig::from(BalloonConfig {
             amount_mb: 5,
             deflate_on_oom: false,
             stats_polling_interval_s: 3,
+            free_page_reporting: true,
         });
 
         assert_eq!(expected_balloon_config, actual_balloon_config);
@@ -19,19 +19,37 @@ use serde::{Deserialize, Serialize};
 pub const DEFAULT_KERNEL_CMDLINE: &str = "reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0 \
                                           i8042.noaux i8042.nomux i8042.nopnp i8042.dumbkbd";
 
+/// The alignment of each of the concatenated initrd images in guest memory, which is the one
+/// the kernel requires of the headers of the cpio archives.
+pub const INITRD_ALIGNMENT: u64 = 4;
+
 /// Strongly typed data structure used to configure the boot source of the
 /// microvm.
-#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BootSourceConfig {
-    /// Path of the kernel image.
-    pub kernel_image_path: String,
+    /// Path of the kernel image. It is left out when booting a firmware.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel_image_path: Option<String>,
     /// Path of the initrd, if there is one.
     pub initrd_path: Option<String>,
+    /// Paths of several initrd images, such as cpio archives, loaded one after the other
+    /// instead of `initrd_path`. The kernel unpacks them as a single initramfs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub initrd_paths: Vec<String>,
     /// The boot arguments to pass to the kernel. If this field is uninitialized, the default
     /// kernel command line is used: `reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0`.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub boot_args: Option<String>,
+    /// Path of the firmware started at the reset vector instead of a kernel, such as OVMF.
+    #[cfg(target_arch = "x86_64")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_path: Option<String>,
+    /// Path of the persistent variable store of the firmware, exposed as a flash right
+    /// below it.
+    #[cfg(target_arch = "x86_64")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nvram_path: Option<String>,
 }
 
 /// Errors associated with actions on `BootSourceConfig`.
@@ -43,6 +61,25 @@ pub enum BootSourceConfigError {
     InvalidInitrdPath(io::Error),
     /// The kernel command line is invalid.
     InvalidKernelCommandLine(String),
+    /// Both a single initrd and a list of initrd images are specified.
+    ConflictingInitrdPaths,
+    /// Neither a kernel nor a firmware is specified.
+    MissingBootImage,
+    /// The firmware file cannot be opened.
+    #[cfg(target_arch = "x86_64")]
+    InvalidFirmwarePath(io::Error),
+    /// The firmware and its variable store are too large, or not made of whole pages.
+    #[cfg(target_arch = "x86_64")]
+    InvalidFirmwareSize(u64),
+    /// The variable store file cannot be opened.
+    #[cfg(target_arch = "x86_64")]
+    InvalidNvramPath(io::Error),
+    /// A firmware is specified along with a kernel or an initrd.
+    #[cfg(target_arch = "x86_64")]
+    KernelWithFirmware,
+    /// A variable store is specified without a firmware.
+    #[cfg(target_arch = "x86_64")]
+    NvramWithoutFirmware,
 }
 
 impl Display for BootSourceConfigError {
@@ -59,6 +96,37 @@ impl Display for BootSourceConfigError {
             InvalidKernelCommandLine(ref e) => {
                 write!(f, "The kernel command line is invalid: {}", e.as_str())
             }
+            ConflictingInitrdPaths => write!(
+                f,
+                "The initrd_path and initrd_paths fields cannot be specified together."
+            ),
+            MissingBootImage => write!(f, "Either a kernel image or a firmware must be specified."),
+            #[cfg(target_arch = "x86_64")]
+            InvalidFirmwarePath(ref e) => write!(f, "The firmware file cannot be opened: {}", e),
+            #[cfg(target_arch = "x86_64")]
+            InvalidFirmwareSize(size) => write!(
+                f,
+                "Invalid firmware size: {} bytes. The firmware and its variable store must be \
+                 made of 4 KiB pages and take up to {} MiB.",
+                size,
+                arch::x86_64::layout::FIRMWARE_MAX_SIZE >> 20
+            ),
+            #[cfg(target_arch = "x86_64")]
+            InvalidNvramPath(ref e) => {
+                write!(f, "The variable store file cannot be opened: {}", e)
+            }
+            #[cfg(target_arch = "x86_64")]
+            KernelWithFirmware => write!(
+                f,
+                "A firmware cannot be booted along with a kernel image or an initrd."
+            ),
+            #[cfg(target_arch = "x86_64")]
+            NvramWithoutFirmware => {
+                write!(
+                    f,
+                    "A variable store can only be specified along with a firmware."
+                )
+            }
         }
     }
 }
@@ -68,8 +136,32 @@ impl Display for BootSourceConfigError {
 pub struct BootConfig {
     /// The commandline validated against correctness.
     pub cmdline: kernel::cmdline::Cmdline,
-    /// The descriptor to the kernel file.
-    pub kernel_file: std::fs::File,
-    /// The descriptor to the initrd file, if there is one
-    pub initrd_file: Option<std::fs::File>,
+    /// The descriptor to the kernel file, unless booting a firmware.
+    pub kernel_file: Option<std::fs::File>,
+    /// The descriptors to the initrd files, in the order they are loaded in.
+    pub initrd_files: Vec<std::fs::File>,
+    /// The descriptor to the firmware file, if booting one.
+    #[cfg(target_arch = "x86_64")]
+    pub firmware_file: Option<std::fs::File>,
+    /// The descriptor to the variable store of the firmware, if there is one.
+    #[cfg(target_arch = "x86_64")]
+    pub nvram_file: Option<std::fs::File>,
+    /// The configuration the boot source was set up with.
+    pub description: BootSourceConfig,
+}
+
+impl BootConfig {
+    /// Returns the size the initrd images take in guest memory, once concatenated.
+    pub fn initrd_size(&self) -> io::Result<u64> {
+        let mut size = 0;
+        for file in self.initrd_files.iter() {
+            size = align_initrd(size) + file.metadata()?.len();
+        }
+        Ok(size)
+    }
+}
+
+/// Returns `offset` rounded up to the alignment of the initrd images.
+pub fn align_initrd(offset: u64) -> u64 {
+    (offset + INITRD_ALIGNMENT - 1) & !(INITRD_ALIGNMENT - 1)
 }
@@ -0,0 +1,339 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+pub use devices::virtio::CONSOLE_DEV_ID;
+use devices::virtio::{console::Error as ConsoleError, Console, Port, PortBackend, MAX_PORTS};
+
+use serde::{Deserialize, Serialize};
+
+type MutexConsole = Arc<Mutex<Console>>;
+
+/// Errors associated with the operations allowed on the console device.
+#[derive(Debug)]
+pub enum ConsoleConfigError {
+    /// Failed to create the console device.
+    CreateConsole(ConsoleError),
+    /// The port name is already used by another port.
+    DuplicatePortName(String),
+    /// A port must be backed by either a unix domain socket or a pipe.
+    InvalidBackend(String),
+    /// The console device must have between 1 and `MAX_PORTS` ports.
+    InvalidPortCount(usize),
+    /// The port name is empty or contains invalid characters.
+    InvalidPortName(String),
+    /// More than one port is marked as the console.
+    MultipleConsolePorts,
+}
+
+impl fmt::Display for ConsoleConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ConsoleConfigError::*;
+        match self {
+            CreateConsole(e) => write!(f, "Error creating the console device: {:?}", e),
+            DuplicatePortName(name) => write!(f, "The port name {} is already in use.", name),
+            InvalidBackend(name) => write!(
+                f,
+                "The port {} must be backed by exactly one of uds_path and pipe_path.",
+                name
+            ),
+            InvalidPortCount(count) => write!(
+                f,
+                "Invalid number of ports: {}. The console device supports 1 to {} ports.",
+                count, MAX_PORTS
+            ),
+            InvalidPortName(name) => write!(
+                f,
+                "Invalid port name: {:?}. Only letters, digits, '.', '_' and '-' are allowed.",
+                name
+            ),
+            MultipleConsolePorts => write!(f, "Only one port can be marked as the console."),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, ConsoleConfigError>;
+
+/// This struct represents the strongly typed equivalent of the json body describing a port of
+/// the console device.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConsolePortConfig {
+    /// The name the guest discovers the port with.
+    pub name: String,
+    /// Path of the unix domain socket created for the port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uds_path: Option<String>,
+    /// Path of an existing named pipe, or file, the guest output is appended to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipe_path: Option<String>,
+    /// Whether the guest uses the port as an interactive console.
+    #[serde(default)]
+    pub is_console: bool,
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from the console device configuration requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConsoleConfig {
+    /// The ports of the console device.
+    pub ports: Vec<ConsolePortConfig>,
+}
+
+impl ConsoleConfig {
+    fn validate(&self) -> Result<()> {
+        if self.ports.is_empty() || self.ports.len() > MAX_PORTS {
+            return Err(ConsoleConfigError::InvalidPortCount(self.ports.len()));
+        }
+
+        let mut names = HashSet::new();
+        for port in self.ports.iter() {
+            if port.name.is_empty()
+                || !port
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+            {
+                return Err(ConsoleConfigError::InvalidPortName(port.name.clone()));
+            }
+            if !names.insert(port.name.as_str()) {
+                return Err(ConsoleConfigError::DuplicatePortName(port.name.clone()));
+            }
+            if port.uds_path.is_some() == port.pipe_path.is_some() {
+                return Err(ConsoleConfigError::InvalidBackend(port.name.clone()));
+            }
+        }
+        if self.ports.iter().filter(|port| port.is_console).count() > 1 {
+            return Err(ConsoleConfigError::MultipleConsolePorts);
+        }
+
+        Ok(())
+    }
+}
+
+/// A builder for the `Console` device from `ConsoleConfig`.
+#[derive(Default)]
+pub struct ConsoleBuilder {
+    inner: Option<MutexConsole>,
+}
+
+impl ConsoleBuilder {
+    /// Creates an empty console device store.
+    pub fn new() -> Self {
+        Self { inner: None }
+    }
+
+    /// Inserts the console device in the store.
+    /// If an entry already exists, it will overwrite it.
+    pub fn set(&mut self, cfg: ConsoleConfig) -> Result<()> {
+        cfg.validate()?;
+
+        // Release the host ends of the current device first, so that the new one can reuse
+        // the same socket paths.
+        self.inner = None;
+
+        let mut ports = Vec::with_capacity(cfg.ports.len());
+        for port in cfg.ports.into_iter() {
+            let backend = match (port.uds_path, port.pipe_path) {
+                (Some(path), None) => PortBackend::Uds(path),
+                (None, Some(path)) => PortBackend::Pipe(path),
+                // The configuration has already been validated.
+                _ => unreachable!(),
+            };
+            ports.push(
+                Port::new(port.name, backend, port.is_console)
+                    .map_err(ConsoleConfigError::CreateConsole)?,
+            );
+        }
+        self.inner = Some(Arc::new(Mutex::new(
+            Console::new(ports).map_err(ConsoleConfigError::CreateConsole)?,
+        )));
+
+        Ok(())
+    }
+
+    /// Provides a reference to the console device if present.
+    pub fn get(&self) -> Option<&MutexConsole> {
+        self.inner.as_ref()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    pub(crate) fn socket_path() -> String {
+        let mut tmp_file = TempFile::new().unwrap();
+        tmp_file.remove().unwrap();
+        tmp_file.as_path().to_str().unwrap().to_string()
+    }
+
+    pub(crate) fn uds_port(name: &str, uds_path: &str, is_console: bool) -> ConsolePortConfig {
+        ConsolePortConfig {
+            name: name.to_string(),
+            uds_path: Some(uds_path.to_string()),
+            pipe_path: None,
+            is_console,
+        }
+    }
+
+    #[test]
+    fn test_console_create() {
+        let mut builder = ConsoleBuilder::new();
+        assert!(builder.get().is_none());
+
+        let logs = TempFile::new().unwrap();
+        let console_path = socket_path();
+        let config = ConsoleConfig {
+            ports: vec![
+                uds_port("console", &console_path, true),
+                ConsolePortConfig {
+                    name: "logs".to_string(),
+                    uds_path: None,
+                    pipe_path: Some(logs.as_path().to_str().unwrap().to_string()),
+                    is_console: false,
+                },
+            ],
+        };
+        builder.set(config.clone()).unwrap();
+        assert_eq!(builder.get().unwrap().lock().unwrap().ports().len(), 2);
+
+        // The device can be reconfigured with the same socket paths.
+        builder.set(config).unwrap();
+        assert_eq!(builder.get().unwrap().lock().unwrap().ports().len(), 2);
+
+        // The pipe must exist.
+        let config = ConsoleConfig {
+            ports: vec![ConsolePortConfig {
+                name: "logs".to_string(),
+                uds_path: None,
+                pipe_path: Some("/invalid/path".to_string()),
+                is_console: false,
+            }],
+        };
+        match builder.set(config) {
+            Err(ConsoleConfigError::CreateConsole(ConsoleError::Backend(_))) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let path = socket_path();
+
+        let config = ConsoleConfig { ports: vec![] };
+        match config.validate() {
+            Err(ConsoleConfigError::InvalidPortCount(0)) => (),
+            _ => panic!("Unexpected result."),
+        }
+
+        let config = ConsoleConfig {
+            ports: (0..=MAX_PORTS)
+                .map(|i| uds_port(&format!("port{}", i), &path, false))
+                .collect(),
+        };
+        match config.validate() {
+            Err(ConsoleConfigError::InvalidPortCount(count)) => assert_eq!(count, MAX_PORTS + 1),
+            _ => panic!("Unexpected result."),
+        }
+
+        for name in &["", "my port", "agent/1"] {
+            let config = ConsoleConfig {
+                ports: vec![uds_port(name, &path, false)],
+            };
+            match config.validate() {
+                Err(ConsoleConfigError::InvalidPortName(_)) => (),
+                _ => panic!("Unexpected result."),
+            }
+        }
+
+        let config = ConsoleConfig {
+            ports: vec![
+                uds_port("agent", &path, false),
+                uds_port("agent", &path, false),
+            ],
+        };
+        match config.validate() {
+            Err(ConsoleConfigError::DuplicatePortName(name)) => assert_eq!(name, "agent"),
+            _ => panic!("Unexpected result."),
+        }
+
+        let config = ConsoleConfig {
+            ports: vec![
+                uds_port("console", &path, true),
+                uds_port("tty", &path, true),
+            ],
+        };
+        match config.validate() {
+            Err(ConsoleConfigError::MultipleConsolePorts) => (),
+            _ => panic!("Unexpected result."),
+        }
+
+        let mut port = uds_port("agent", &path, false);
+        port.pipe_path = Some(path.clone());
+        let config = ConsoleConfig { ports: vec![port] };
+        match config.validate() {
+            Err(ConsoleConfigError::InvalidBackend(name)) => assert_eq!(name, "agent"),
+            _ => panic!("Unexpected result."),
+        }
+
+        let config = ConsoleConfig {
+            ports: vec![
+                uds_port("org.qemu.guest_agent.0", &path, false),
+                uds_port("console", &path, true),
+            ],
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: ConsoleConfig = serde_json::from_str(
+            r#"{"ports": [{"name": "console", "uds_path": "/tmp/console.sock", "is_console": true}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.ports,
+            vec![uds_port("console", "/tmp/console.sock", true)]
+        );
+
+        let config: ConsoleConfig =
+            serde_json::from_str(r#"{"ports": [{"name": "logs", "pipe_path": "/tmp/logs"}]}"#)
+                .unwrap();
+        assert!(!config.ports[0].is_console);
+        assert_eq!(config.ports[0].pipe_path, Some("/tmp/logs".to_string()));
+
+        assert!(serde_json::from_str::<ConsoleConfig>(
+            r#"{"ports": [{"name": "logs", "path": "/tmp/logs"}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use super::ConsoleConfigError::*;
+
+        let err = CreateConsole(ConsoleError::InvalidPortCount(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DuplicatePortName(String::from("agent"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidBackend(String::from("agent"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidPortCount(0);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidPortName(String::from("my port"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = MultipleConsolePorts;
+        let _ = format!("{}{:?}", err, err);
+    }
+}
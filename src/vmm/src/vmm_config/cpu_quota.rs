@@ -0,0 +1,86 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The quota of a vCPU thread which is not throttled.
+pub const UNLIMITED_CPU_QUOTA_PCT: u8 = 100;
+
+/// The period over which the CPU time of the vCPU threads is accounted, in microseconds.
+pub const CPU_QUOTA_PERIOD_US: u64 = 100_000;
+
+/// Errors associated with the CPU quota of the vCPUs.
+#[derive(Debug, PartialEq)]
+pub enum CpuQuotaConfigError {
+    /// The quota is zero or above 100%.
+    InvalidQuota(u8),
+}
+
+impl fmt::Display for CpuQuotaConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::CpuQuotaConfigError::*;
+        match self {
+            InvalidQuota(quota_pct) => write!(
+                f,
+                "Invalid CPU quota: {}%. It must be between 1 and {}.",
+                quota_pct, UNLIMITED_CPU_QUOTA_PCT
+            ),
+        }
+    }
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from the CPU quota related requests.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuQuotaConfig {
+    /// The share of a host core each vCPU thread may use, in percents.
+    pub quota_pct: u8,
+}
+
+impl Default for CpuQuotaConfig {
+    fn default() -> Self {
+        CpuQuotaConfig {
+            quota_pct: UNLIMITED_CPU_QUOTA_PCT,
+        }
+    }
+}
+
+impl CpuQuotaConfig {
+    /// Checks that the quota is a share of one host core.
+    pub fn validate(&self) -> Result<(), CpuQuotaConfigError> {
+        if self.quota_pct == 0 || self.quota_pct > UNLIMITED_CPU_QUOTA_PCT {
+            return Err(CpuQuotaConfigError::InvalidQuota(self.quota_pct));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let config: CpuQuotaConfig = serde_json::from_str(r#"{"quota_pct": 50}"#).unwrap();
+        assert_eq!(config.quota_pct, 50);
+        assert!(config.validate().is_ok());
+        assert!(CpuQuotaConfig::default().validate().is_ok());
+
+        let config = CpuQuotaConfig { quota_pct: 0 };
+        assert_eq!(config.validate(), Err(CpuQuotaConfigError::InvalidQuota(0)));
+        let config = CpuQuotaConfig { quota_pct: 101 };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Invalid CPU quota: 101%. It must be between 1 and 100."
+        );
+
+        assert!(serde_json::from_str::<CpuQuotaConfig>("{}").is_err());
+        assert!(serde_json::from_str::<CpuQuotaConfig>(r#"{"quota_pct": 300}"#).is_err());
+        assert!(
+            serde_json::from_str::<CpuQuotaConfig>(r#"{"quota_pct": 50, "period": 10}"#).is_err()
+        );
+    }
+}
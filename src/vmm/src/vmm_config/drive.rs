@@ -12,8 +12,9 @@ use std::sync::{Arc, Mutex};
 use super::RateLimiterConfig;
 use crate::Error as VmmError;
 use devices::virtio::Block;
+use rate_limiter::fair_share::{MAX_WEIGHT, MIN_WEIGHT};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 type Result<T> = result::Result<T, DriveError>;
 
@@ -29,8 +30,14 @@ pub enum DriveError {
     CreateRateLimiter(io::Error),
     /// Error during drive update (patch).
     DeviceUpdate(VmmError),
+    /// Error while retrieving the drive statistics.
+    DeviceStats(VmmError),
     /// The block device path is invalid.
     InvalidBlockDevicePath,
+    /// The I/O weight is out of range.
+    InvalidIoWeight(u32),
+    /// The rate limiter group does not exist.
+    RateLimiterGroupNotFound(String),
     /// Cannot open block device due to invalid permissions or path.
     OpenBlockDevice(io::Error),
     /// A root block device was already added.
@@ -50,19 +57,28 @@ impl Display for DriveError {
             BlockDeviceUpdateFailed(e) => write!(f, "The update operation failed: {}", e),
             CreateRateLimiter(e) => write!(f, "Cannot create RateLimiter: {}", e),
             DeviceUpdate(e) => write!(f, "Error during drive update (patch): {}", e),
+            DeviceStats(e) => write!(f, "Error retrieving the drive statistics: {}", e),
             InvalidBlockDevicePath => write!(f, "Invalid block device path!"),
+            InvalidIoWeight(weight) => write!(
+                f,
+                "Invalid I/O weight {}, it must be between {} and {}.",
+                weight, MIN_WEIGHT, MAX_WEIGHT
+            ),
             OpenBlockDevice(e) => write!(
                 f,
                 "Cannot open block device. Invalid permission/path: {}",
                 e
             ),
+            RateLimiterGroupNotFound(group_id) => {
+                write!(f, "The rate limiter group {} does not exist.", group_id)
+            }
             RootBlockDeviceAlreadyAdded => write!(f, "A root block device already exists!"),
         }
     }
 }
 
 /// Use this structure to set up the Block Device before booting the kernel.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct BlockDeviceConfig {
     /// Unique identifier of the drive.
@@ -81,6 +97,27 @@ pub struct BlockDeviceConfig {
     pub is_read_only: bool,
     /// Rate Limiter for I/O operations.
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// The weight of the drive when sharing the I/O with the other drives which have one, in
+    /// proportion to their weights.
+    pub io_weight: Option<u32>,
+    /// The rate limiter group whose budget the drive shares with the other devices attached to
+    /// it, on top of its own rate limiter.
+    pub rate_limiter_group: Option<String>,
+}
+
+impl From<&Block> for BlockDeviceConfig {
+    fn from(block: &Block) -> Self {
+        BlockDeviceConfig {
+            drive_id: block.id().clone(),
+            path_on_host: block.file_path().clone(),
+            is_root_device: block.is_root_device(),
+            partuuid: block.partuuid().cloned(),
+            is_read_only: block.is_read_only(),
+            rate_limiter: RateLimiterConfig::from_rate_limiter(block.rate_limiter()),
+            io_weight: block.io_weight(),
+            rate_limiter_group: block.rate_limiter().group_id().map(str::to_string),
+        }
+    }
 }
 
 /// Only provided fields will be updated. I.e. if any optional fields
@@ -94,6 +131,8 @@ pub struct BlockDeviceUpdateConfig {
     pub path_on_host: Option<String>,
     /// New rate limiter config.
     pub rate_limiter: Option<RateLimiterConfig>,
+    /// New I/O weight.
+    pub io_weight: Option<u32>,
 }
 
 /// Wrapper for the collection that holds all the Block Devices
@@ -171,6 +210,14 @@ impl BlockBuilder {
         Ok(())
     }
 
+    /// Returns the current configuration of the block devices.
+    pub fn configs(&self) -> Vec<BlockDeviceConfig> {
+        self.list
+            .iter()
+            .map(|block| BlockDeviceConfig::from(&*block.lock().expect("Poisoned lock")))
+            .collect()
+    }
+
     /// Creates a Block device from a BlockDeviceConfig.
     pub fn create_block(block_device_config: BlockDeviceConfig) -> Result<Block> {
         // check if the path exists
@@ -179,6 +226,11 @@ impl BlockBuilder {
             return Err(DriveError::InvalidBlockDevicePath);
         }
 
+        let io_weight = block_device_config.io_weight;
+        if let Some(weight) = io_weight {
+            validate_io_weight(weight)?;
+        }
+
         let rate_limiter = block_device_config
             .rate_limiter
             .map(super::RateLimiterConfig::try_into)
@@ -186,7 +238,7 @@ impl BlockBuilder {
             .map_err(DriveError::CreateRateLimiter)?;
 
         // Create and return the Block device
-        devices::virtio::Block::new(
+        let mut block = devices::virtio::Block::new(
             block_device_config.drive_id,
             block_device_config.partuuid,
             block_device_config.path_on_host,
@@ -194,10 +246,23 @@ impl BlockBuilder {
             block_device_config.is_root_device,
             rate_limiter.unwrap_or_default(),
         )
-        .map_err(DriveError::CreateBlockDevice)
+        .map_err(DriveError::CreateBlockDevice)?;
+        // A drive overwriting another one with the same ID also replaces its I/O weight.
+        block
+            .set_io_weight(io_weight)
+            .map_err(DriveError::CreateBlockDevice)?;
+        Ok(block)
     }
 }
 
+/// Checks that the I/O weight of a drive is within the range the scheduler supports.
+pub fn validate_io_weight(weight: u32) -> Result<()> {
+    if weight < MIN_WEIGHT || weight > MAX_WEIGHT {
+        return Err(DriveError::InvalidIoWeight(weight));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -221,6 +286,8 @@ mod tests {
                 is_read_only: self.is_read_only,
                 drive_id: self.drive_id.clone(),
                 rate_limiter: None,
+                io_weight: self.io_weight,
+                rate_limiter_group: self.rate_limiter_group.clone(),
             }
         }
     }
@@ -243,6 +310,8 @@ mod tests {
             is_read_only: false,
             drive_id: dummy_id.clone(),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -272,6 +341,8 @@ mod tests {
             is_read_only: true,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -298,6 +369,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -309,6 +382,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -331,6 +406,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -342,6 +419,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
@@ -353,6 +432,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("3"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -389,6 +470,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -400,6 +483,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let dummy_file_3 = TempFile::new().unwrap();
@@ -411,6 +496,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("3"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -448,6 +535,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let dummy_file_2 = TempFile::new().unwrap();
@@ -459,6 +548,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         let mut block_devs = BlockBuilder::new();
@@ -517,6 +608,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("1"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
         // Switch roots and add a PARTUUID for the new one.
         let mut root_block_device_old = root_block_device;
@@ -528,6 +621,8 @@ mod tests {
             is_read_only: false,
             drive_id: String::from("2"),
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
         assert!(block_devs.insert(root_block_device_old).is_ok());
         let root_block_id = root_block_device_new.drive_id.clone();
@@ -550,6 +645,8 @@ mod tests {
             partuuid: Some("0eaa91a0-01".to_string()),
             is_read_only: true,
             rate_limiter: None,
+            io_weight: None,
+            rate_limiter_group: None,
         };
 
         assert_eq!(
@@ -561,5 +658,41 @@ mod tests {
             dummy_block_file.as_path().to_str().unwrap().to_string()
         );
         assert_eq!(block_config.is_read_only, expected_is_read_only);
+
+        let mut block_devs = BlockBuilder::new();
+        block_devs.insert(block_config.clone()).unwrap();
+        assert_eq!(block_devs.configs(), vec![block_config]);
+    }
+
+    #[test]
+    fn test_io_weight() {
+        let dummy_block_file = TempFile::new().unwrap();
+        let mut block_config = BlockDeviceConfig {
+            drive_id: "io_weight_drive".to_string(),
+            path_on_host: dummy_block_file.as_path().to_str().unwrap().to_string(),
+            is_root_device: false,
+            partuuid: None,
+            is_read_only: false,
+            rate_limiter: None,
+            io_weight: Some(MAX_WEIGHT + 1),
+            rate_limiter_group: None,
+        };
+
+        let mut block_devs = BlockBuilder::new();
+        assert_eq!(
+            block_devs.insert(block_config.clone()),
+            Err(DriveError::InvalidIoWeight(MAX_WEIGHT + 1))
+        );
+        block_config.io_weight = Some(MIN_WEIGHT - 1);
+        assert!(block_devs.insert(block_config.clone()).is_err());
+
+        block_config.io_weight = Some(10);
+        block_devs.insert(block_config.clone()).unwrap();
+        assert_eq!(block_devs.configs(), vec![block_config.clone()]);
+
+        // Overwriting the drive without a weight stops sharing its I/O.
+        block_config.io_weight = None;
+        block_devs.insert(block_config.clone()).unwrap();
+        assert_eq!(block_devs.configs(), vec![block_config]);
     }
 }
@@ -0,0 +1,147 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+pub use devices::virtio::ENTROPY_DEV_ID;
+use devices::virtio::{rng::Error as EntropyError, Entropy};
+
+use serde::{Deserialize, Serialize};
+
+use super::RateLimiterConfig;
+
+type MutexEntropy = Arc<Mutex<Entropy>>;
+
+/// Errors associated with the operations allowed on the entropy device.
+#[derive(Debug)]
+pub enum EntropyConfigError {
+    /// Failed to create the entropy device.
+    CreateFailure(EntropyError),
+    /// Failed to create a `RateLimiter` object.
+    CreateRateLimiter(io::Error),
+}
+
+impl fmt::Display for EntropyConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::EntropyConfigError::*;
+        match self {
+            CreateFailure(e) => write!(f, "Error creating the entropy device: {:?}", e),
+            CreateRateLimiter(e) => write!(f, "Cannot create RateLimiter: {}", e),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, EntropyConfigError>;
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from the entropy device configuration requests.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntropyDeviceConfig {
+    /// Rate limiter capping the amount of entropy the guest can request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiterConfig>,
+}
+
+/// A builder for the `Entropy` device from `EntropyDeviceConfig`.
+#[derive(Default)]
+pub struct EntropyDeviceBuilder {
+    inner: Option<MutexEntropy>,
+}
+
+impl EntropyDeviceBuilder {
+    /// Creates an empty entropy device store.
+    pub fn new() -> Self {
+        Self { inner: None }
+    }
+
+    /// Inserts the entropy device in the store.
+    /// If an entry already exists, it will overwrite it.
+    pub fn set(&mut self, cfg: EntropyDeviceConfig) -> Result<()> {
+        let rate_limiter = cfg
+            .rate_limiter
+            .map(RateLimiterConfig::try_into)
+            .transpose()
+            .map_err(EntropyConfigError::CreateRateLimiter)?;
+
+        self.inner = Some(Arc::new(Mutex::new(
+            Entropy::new(rate_limiter.unwrap_or_default())
+                .map_err(EntropyConfigError::CreateFailure)?,
+        )));
+
+        Ok(())
+    }
+
+    /// Provides a reference to the entropy device if present.
+    pub fn get(&self) -> Option<&MutexEntropy> {
+        self.inner.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vmm_config::TokenBucketConfig;
+
+    #[test]
+    fn test_entropy_create() {
+        let mut builder = EntropyDeviceBuilder::new();
+        assert!(builder.get().is_none());
+
+        builder.set(EntropyDeviceConfig::default()).unwrap();
+        assert!(builder
+            .get()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .rate_limiter()
+            .bandwidth()
+            .is_none());
+
+        let config = EntropyDeviceConfig {
+            rate_limiter: Some(RateLimiterConfig {
+                bandwidth: Some(TokenBucketConfig {
+                    size: 1000,
+                    one_time_burst: None,
+                    refill_time: 100,
+                }),
+                ops: None,
+                adaptive: None,
+            }),
+        };
+        builder.set(config).unwrap();
+        assert_eq!(
+            builder
+                .get()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .rate_limiter()
+                .bandwidth()
+                .unwrap()
+                .capacity(),
+            1000
+        );
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: EntropyDeviceConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, EntropyDeviceConfig::default());
+        assert!(serde_json::from_str::<EntropyDeviceConfig>(r#"{"rate": 5}"#).is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use super::EntropyConfigError::*;
+
+        let err = CreateFailure(EntropyError::EventFd(io::Error::from_raw_os_error(0)));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = CreateRateLimiter(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,37 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// How the VMM keeps the guest clock right when the microVM resumes.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ClockPolicy {
+    /// The guest clock is left to KVM. It keeps running while the microVM is paused, and
+    /// starts again from its saved value when a snapshot is loaded.
+    None,
+    /// The guest is told that it was paused, so that it doesn't take the time jump for a
+    /// lockup, and the guest clock is advanced by the time elapsed since the snapshot the
+    /// microVM was loaded from was created.
+    Sync,
+}
+
+impl Default for ClockPolicy {
+    fn default() -> Self {
+        ClockPolicy::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_clock_policy() {
+        assert_eq!(ClockPolicy::default(), ClockPolicy::None);
+        assert_eq!(
+            serde_json::from_str::<ClockPolicy>(r#""Sync""#).unwrap(),
+            ClockPolicy::Sync
+        );
+        assert!(serde_json::from_str::<ClockPolicy>(r#""Freeze""#).is_err());
+    }
+}
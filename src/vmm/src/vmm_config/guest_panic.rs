@@ -0,0 +1,215 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#[cfg(target_arch = "x86_64")]
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// The action taken by the VMM when it detects that the guest kernel panicked.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "action", deny_unknown_fields)]
+pub enum PanicAction {
+    /// The panic is only logged and accounted for in the metrics.
+    None,
+    /// The microVM is paused, so that it can be inspected or snapshotted.
+    Pause,
+    /// The microVM is paused, a full snapshot of it is created and the VMM exits.
+    #[cfg(target_arch = "x86_64")]
+    SnapshotAndStop {
+        /// Path to the file that will contain the microVM state.
+        snapshot_path: PathBuf,
+        /// Path to the file that will contain the guest memory.
+        mem_file_path: PathBuf,
+    },
+    /// The microVM is torn down and a new one is restored from the given full snapshot, which
+    /// applies this action again if its guest kernel panics. Only supported when Firecracker
+    /// builds its microVMs, unlike for the embedded ones.
+    #[cfg(target_arch = "x86_64")]
+    RestoreSnapshot {
+        /// Path to the file that contains the microVM state to be loaded.
+        snapshot_path: PathBuf,
+        /// Path to the file that contains the guest memory to be loaded.
+        mem_file_path: PathBuf,
+    },
+}
+
+impl Default for PanicAction {
+    fn default() -> Self {
+        PanicAction::None
+    }
+}
+
+/// The maximum number of guest events kept by the VMM. The oldest ones are dropped first.
+pub const MAX_GUEST_EVENTS: usize = 64;
+
+/// The state of the guest kernel, as seen by the VMM.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum GuestState {
+    /// No crash was reported.
+    Running,
+    /// The guest kernel reported a crash.
+    Crashed,
+    /// The guest stopped petting the watchdog.
+    Hung,
+}
+
+impl Default for GuestState {
+    fn default() -> Self {
+        GuestState::Running
+    }
+}
+
+/// A guest kernel event noticed by the VMM.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum GuestEventKind {
+    /// The guest kernel panicked.
+    Panicked,
+    /// The guest kernel panicked and is about to boot the crash kernel it loaded.
+    CrashLoaded,
+    /// The guest stopped petting the watchdog.
+    WatchdogExpired,
+}
+
+/// The way the VMM noticed a guest kernel event.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum GuestEventSource {
+    /// The guest kernel panic message showed up on the serial console.
+    Serial,
+    /// The guest kernel reported the event through the pvpanic device.
+    Pvpanic,
+    /// The watchdog expired.
+    Watchdog,
+}
+
+/// A timestamped guest kernel event.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GuestEvent {
+    /// The event.
+    pub kind: GuestEventKind,
+    /// The way the event was noticed.
+    pub source: GuestEventSource,
+    /// The wall clock time of the event, in microseconds since the Unix epoch.
+    pub timestamp_us: u64,
+}
+
+/// The state of the guest kernel and the latest events which changed it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GuestEvents {
+    /// The current state of the guest kernel.
+    pub state: GuestState,
+    /// The latest events, oldest first.
+    pub events: Vec<GuestEvent>,
+}
+
+impl GuestEvents {
+    /// Records an event and moves the guest to the `Crashed` state, or to the `Hung` state when
+    /// the watchdog expired and no crash was reported.
+    pub fn record(&mut self, kind: GuestEventKind, source: GuestEventSource) {
+        if self.events.len() == MAX_GUEST_EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(GuestEvent {
+            kind,
+            source,
+            timestamp_us: utils::time::get_time_us(utils::time::ClockType::Real),
+        });
+        self.state = match kind {
+            GuestEventKind::Panicked | GuestEventKind::CrashLoaded => GuestState::Crashed,
+            GuestEventKind::WatchdogExpired if self.state == GuestState::Crashed => {
+                GuestState::Crashed
+            }
+            GuestEventKind::WatchdogExpired => GuestState::Hung,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_panic_action() {
+        assert_eq!(
+            serde_json::from_str::<PanicAction>(r#"{"action": "None"}"#).unwrap(),
+            PanicAction::None
+        );
+        assert_eq!(
+            serde_json::from_str::<PanicAction>(r#"{"action": "Pause"}"#).unwrap(),
+            PanicAction::Pause
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            serde_json::from_str::<PanicAction>(
+                r#"{
+                    "action": "SnapshotAndStop",
+                    "snapshot_path": "foo",
+                    "mem_file_path": "bar"
+                }"#
+            )
+            .unwrap(),
+            PanicAction::SnapshotAndStop {
+                snapshot_path: PathBuf::from("foo"),
+                mem_file_path: PathBuf::from("bar"),
+            }
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            serde_json::from_str::<PanicAction>(
+                r#"{
+                    "action": "RestoreSnapshot",
+                    "snapshot_path": "foo",
+                    "mem_file_path": "bar"
+                }"#
+            )
+            .unwrap(),
+            PanicAction::RestoreSnapshot {
+                snapshot_path: PathBuf::from("foo"),
+                mem_file_path: PathBuf::from("bar"),
+            }
+        );
+
+        assert!(serde_json::from_str::<PanicAction>(r#"{"action": "Reboot"}"#).is_err());
+        assert!(serde_json::from_str::<PanicAction>(r#"{"action": "SnapshotAndStop"}"#).is_err());
+        assert!(serde_json::from_str::<PanicAction>(
+            r#"{"action": "RestoreSnapshot", "snapshot_path": "foo"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_guest_events() {
+        let mut guest_events = GuestEvents::default();
+        assert_eq!(guest_events.state, GuestState::Running);
+        assert_eq!(
+            serde_json::to_string(&guest_events).unwrap(),
+            r#"{"state":"Running","events":[]}"#
+        );
+
+        guest_events.record(GuestEventKind::Panicked, GuestEventSource::Serial);
+        assert_eq!(guest_events.state, GuestState::Crashed);
+        assert_eq!(guest_events.events.len(), 1);
+        assert_eq!(guest_events.events[0].source, GuestEventSource::Serial);
+        assert!(guest_events.events[0].timestamp_us > 0);
+
+        // A crashed guest stops petting the watchdog, but the crash is what is reported.
+        guest_events.record(GuestEventKind::WatchdogExpired, GuestEventSource::Watchdog);
+        assert_eq!(guest_events.state, GuestState::Crashed);
+
+        let mut hung_guest = GuestEvents::default();
+        hung_guest.record(GuestEventKind::WatchdogExpired, GuestEventSource::Watchdog);
+        assert_eq!(hung_guest.state, GuestState::Hung);
+        hung_guest.record(GuestEventKind::Panicked, GuestEventSource::Serial);
+        assert_eq!(hung_guest.state, GuestState::Crashed);
+
+        // Only the latest events are kept.
+        for _ in 0..MAX_GUEST_EVENTS {
+            guest_events.record(GuestEventKind::CrashLoaded, GuestEventSource::Pvpanic);
+        }
+        assert_eq!(guest_events.events.len(), MAX_GUEST_EVENTS);
+        assert!(guest_events
+            .events
+            .iter()
+            .all(|event| event.kind == GuestEventKind::CrashLoaded));
+    }
+}
@@ -14,3 +14,21 @@ pub struct InstanceInfo {
     /// The name of the application that runs the microVM.
     pub app_name: String,
 }
+
+/// The state of the microVM.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum InstanceState {
+    /// The microVM hasn't been started yet.
+    #[serde(rename = "Not started")]
+    NotStarted,
+    /// The vCPUs of the microVM are paused.
+    Paused,
+    /// The microVM is running.
+    Running,
+}
+
+impl Default for InstanceState {
+    fn default() -> Self {
+        InstanceState::NotStarted
+    }
+}
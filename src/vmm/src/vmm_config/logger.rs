@@ -5,11 +5,18 @@
 use serde::{de, Deserialize, Deserializer, Serialize};
 use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 use super::{open_file_nonblock, FcLineWriter};
 use crate::vmm_config::instance_info::InstanceInfo;
+use lazy_static::lazy_static;
 use logger::{LevelFilter, LOGGER};
 
+lazy_static! {
+    // The named pipe or file the logs are written to, once the logger is configured.
+    static ref LOG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
 /// Enum used for setting the log level.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum LoggerLevel {
@@ -114,6 +121,8 @@ impl LoggerConfig {
 pub enum LoggerConfigError {
     /// Cannot initialize the logger due to bad user input.
     InitializationFailure(String),
+    /// Cannot reopen the named pipe or file the logs are written to.
+    ReopenFailure(String),
 }
 
 impl Display for LoggerConfigError {
@@ -121,6 +130,7 @@ impl Display for LoggerConfigError {
         use self::LoggerConfigError::*;
         match *self {
             InitializationFailure(ref err_msg) => write!(f, "{}", err_msg.replace("\"", "")),
+            ReopenFailure(ref err_msg) => write!(f, "Cannot reopen the log file: {}", err_msg),
         }
     }
 }
@@ -147,7 +157,23 @@ pub fn init_logger(
             ),
             Box::new(writer),
         )
-        .map_err(|e| LoggerConfigError::InitializationFailure(e.to_string()))
+        .map_err(|e| LoggerConfigError::InitializationFailure(e.to_string()))?;
+
+    *LOG_PATH.lock().expect("Poisoned lock") = Some(logger_cfg.log_path);
+    Ok(())
+}
+
+/// Reopens the named pipe or file the logs are written to, if the logger is configured, so that
+/// it can be rotated.
+pub fn reopen_logger() -> std::result::Result<(), LoggerConfigError> {
+    if let Some(log_path) = LOG_PATH.lock().expect("Poisoned lock").as_ref() {
+        let writer = FcLineWriter::new(
+            open_file_nonblock(log_path)
+                .map_err(|e| LoggerConfigError::ReopenFailure(e.to_string()))?,
+        );
+        LOGGER.reopen(Box::new(writer));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -179,8 +205,12 @@ mod tests {
         };
         assert!(init_logger(desc, &default_instance_info).is_err());
 
+        // Reopening the logger before it is configured has no effect.
+        assert!(reopen_logger().is_ok());
+
         // Initializing logger with valid pipe is ok.
         let log_file = TempFile::new().unwrap();
+        let log_path = log_file.as_path().to_path_buf();
         let desc = LoggerConfig {
             log_path: log_file.as_path().to_path_buf(),
             level: LoggerLevel::Info,
@@ -221,6 +251,16 @@ mod tests {
                 assert!(line.contains("Guest-boot-time ="));
             }
         }
+
+        // Validate the logs are written to the new file once the logger is reopened.
+        std::fs::File::create(&log_path).unwrap();
+        assert!(reopen_logger().is_ok());
+        warn!("this is a test after the rotation");
+        let logs = std::fs::read_to_string(&log_path).unwrap();
+        assert!(logs.contains("this is a test after the rotation"));
+
+        std::fs::remove_file(&log_path).unwrap();
+        assert!(reopen_logger().is_err());
     }
 
     #[test]
@@ -234,6 +274,13 @@ mod tests {
             ),
             "Failed to initialize logger"
         );
+        assert_eq!(
+            format!(
+                "{}",
+                LoggerConfigError::ReopenFailure(String::from("No such file or directory"))
+            ),
+            "Cannot reopen the log file: No such file or directory"
+        );
     }
 
     #[test]
@@ -3,38 +3,122 @@
 
 use serde::{de, Deserialize, Serialize};
 use std::fmt;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
 
 /// The default memory size of the VM, in MiB.
 pub const DEFAULT_MEM_SIZE_MIB: usize = 128;
 /// Firecracker aims to support small scale workloads only, so limit the maximum
 /// vCPUs supported.
 pub const MAX_SUPPORTED_VCPUS: u8 = 32;
+/// The maximum number of I/O threads, as the devices of a microVM only keep a few of them busy.
+pub const MAX_IO_THREADS: u8 = 16;
+/// The maximum number of MSR ranges of a policy, as KVM filters at most 16 ranges.
+pub const MAX_MSR_RANGES: usize = 16;
+/// The maximum number of MSRs in a range, as KVM limits the bitmap of a range to 0x600 bytes.
+pub const MAX_MSR_RANGE_COUNT: u32 = 0x600 * 8;
 
 /// Errors associated with configuring the microVM.
 #[derive(Debug, PartialEq)]
 pub enum VmConfigError {
     /// The memory size is smaller than the target size set in the balloon device configuration.
     IncompatibleBalloonSize,
+    /// The memory backend uses huge pages, which cannot be given back to the host by the
+    /// balloon device.
+    IncompatibleMemoryBackend,
+    /// The memory backend uses huge pages, which the host kernel cannot merge or split.
+    IncompatibleMemoryHints,
+    /// The PIT reinject policy was set while the PIT is disabled.
+    IncompatiblePitPolicy,
+    /// The forced CPU features are unknown, or both enabled and disabled.
+    InvalidCpuFeatures(String),
+    /// The CPU topology is invalid. Its number of logical CPUs must match the vcpu count, it can
+    /// have at most 2 threads per core, and the number of logical CPUs per socket must be a power
+    /// of 2 when there are several sockets.
+    InvalidCpuTopology,
+    /// The custom CPU template cannot be read, or does not describe a CPU template.
+    InvalidCustomCpuTemplate(String),
+    /// The number of I/O threads is invalid. It can be at most `MAX_IO_THREADS`.
+    InvalidIoThreads,
+    /// The maximum vcpu count is invalid. It must be at least the vcpu count and, when
+    /// hyperthreading is enabled, either 1 or an even number.
+    InvalidMaxVcpuCount,
     /// The memory size is invalid. The memory can only be an unsigned integer.
     InvalidMemorySize,
+    /// The MMIO layout is invalid. The gap must be a multiple of 2 MiB within the supported
+    /// sizes, and each device slot must get its own IRQ.
+    InvalidMmioLayout,
+    /// The MSR policy is invalid. It can have at most 16 ranges, each holding between 1 and
+    /// 0x3000 MSRs.
+    InvalidMsrPolicy,
     /// The vcpu count is invalid. When hyperthreading is enabled, the `cpu_count` must be either
     /// 1 or an even number.
     InvalidVcpuCount,
     /// Could not get the config of the balloon device from the VM resources, even though a
     /// balloon device was previously installed.
     InvalidVmState,
+    /// Nested virtualization was requested, but the host KVM module does not allow it.
+    NestedVirtUnsupported,
+    /// The memory size is not a multiple of the page size of the memory backend.
+    UnalignedMemorySize,
 }
 
 impl fmt::Display for VmConfigError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::VmConfigError::*;
-        match *self {
+        match self {
             IncompatibleBalloonSize => write!(
                 f,
                 "The memory size (MiB) is smaller than the previously \
                  set balloon device target size.",
             ),
+            IncompatibleMemoryBackend => write!(
+                f,
+                "The memory backend uses huge pages, which cannot be \
+                 used along with a balloon device.",
+            ),
+            IncompatibleMemoryHints => write!(
+                f,
+                "The memory backend uses huge pages, which cannot be \
+                 used along with memory hints.",
+            ),
+            IncompatiblePitPolicy => write!(
+                f,
+                "The PIT reinject policy cannot be set when the PIT is disabled."
+            ),
+            InvalidCpuFeatures(e) => write!(f, "The CPU features are invalid: {}", e),
+            InvalidCpuTopology => write!(
+                f,
+                "The CPU topology is invalid! It must have as many logical CPUs as vCPUs, \
+                 at most 2 threads per core, and a power of 2 logical CPUs per socket \
+                 when there are several sockets.",
+            ),
+            InvalidCustomCpuTemplate(e) => write!(f, "The custom CPU template is invalid: {}", e),
+            InvalidIoThreads => write!(
+                f,
+                "The number of I/O threads is invalid! It can be at most {}.",
+                MAX_IO_THREADS
+            ),
+            InvalidMaxVcpuCount => write!(
+                f,
+                "The maximum vCPU number is invalid! It must be at least the \
+                 vCPU number, and 1 or an even number when hyperthreading is enabled.",
+            ),
             InvalidMemorySize => write!(f, "The memory size (MiB) is invalid.",),
+            InvalidMmioLayout => write!(
+                f,
+                "The MMIO layout is invalid! The gap must be a multiple of 2 MiB \
+                 between {} and {} MiB, with between 1 and {} device slots.",
+                arch::MIN_MMIO_GAP_SIZE >> 20,
+                arch::MAX_MMIO_GAP_SIZE >> 20,
+                arch::MAX_MMIO_SLOTS
+            ),
+            InvalidMsrPolicy => write!(
+                f,
+                "The MSR policy is invalid! It can have at most {} ranges, \
+                 each holding between 1 and {} MSRs.",
+                MAX_MSR_RANGES, MAX_MSR_RANGE_COUNT
+            ),
             InvalidVcpuCount => write!(
                 f,
                 "The vCPU number is invalid! The vCPU number can only \
@@ -45,6 +129,15 @@ impl fmt::Display for VmConfigError {
                 "Could not get the configuration of the previously \
                  installed balloon device to validate the memory size.",
             ),
+            NestedVirtUnsupported => write!(
+                f,
+                "Nested virtualization is not enabled in the KVM module of the host.",
+            ),
+            UnalignedMemorySize => write!(
+                f,
+                "The memory size (MiB) is not a multiple of the page \
+                 size of the memory backend.",
+            ),
         }
     }
 }
@@ -61,6 +154,14 @@ pub struct VmConfig {
         deserialize_with = "validate_vcpu_num"
     )]
     pub vcpu_count: Option<u8>,
+    /// Number of vcpus the microVM can have once vcpus are hotplugged. The vcpus past
+    /// `vcpu_count` are created at boot, but the guest only uses them once plugged.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "validate_vcpu_num"
+    )]
+    pub max_vcpu_count: Option<u8>,
     /// The memory size in MiB.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mem_size_mib: Option<usize>,
@@ -73,16 +174,78 @@ pub struct VmConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(default)]
     pub track_dirty_pages: bool,
+    /// Enables or disables the in-kernel PIT. Guests keeping time with the local APIC timer
+    /// and the TSC can do without it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pit_enabled: Option<bool>,
+    /// The policy used by the in-kernel PIT for timer interrupts missed by the guest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pit_reinject_policy: Option<PitReinjectPolicy>,
+    /// Enables or disables the guest kernel's use of an HPET. Since no HPET is emulated,
+    /// disabling it stops the guest from probing for one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hpet_enabled: Option<bool>,
+    /// The arrangement of the vCPUs in sockets, cores and threads exposed to the guest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_topology: Option<CpuTopology>,
+    /// The kind of host memory backing the guest memory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_backend: Option<MemoryBackend>,
+    /// Exposes the hardware virtualization extensions (VMX or SVM) to the guest, so that it can
+    /// run its own hypervisor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nested_virt_enabled: Option<bool>,
+    /// The MSRs the guest is allowed or denied to access.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msr_policy: Option<MsrPolicy>,
+    /// The size of the memory gap holding the MMIO devices, and their number of slots.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mmio_layout: Option<MmioLayoutConfig>,
+    /// The transport through which the guest reaches the virtio devices.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtio_transport: Option<VirtioTransport>,
+    /// The path of a JSON file describing a custom CPU template, applied after `cpu_template`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_template_path: Option<String>,
+    /// The CPUID feature bits forced on or off, on top of the CPU templates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_features: Option<CpuFeaturesConfig>,
+    /// Shows the guest synthesized cache leaves and brand string, which don't depend on the host
+    /// CPU model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpuid_normalization_enabled: Option<bool>,
+    /// The hints given to the host kernel about the guest memory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_hints: Option<MemoryHints>,
+    /// The number of threads processing the I/O of the block and network devices, besides the
+    /// VMM thread. The devices are spread across the threads.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub io_threads: Option<u8>,
 }
 
 impl Default for VmConfig {
     fn default() -> Self {
         VmConfig {
             vcpu_count: Some(1),
+            max_vcpu_count: None,
             mem_size_mib: Some(DEFAULT_MEM_SIZE_MIB),
             ht_enabled: Some(false),
             cpu_template: None,
             track_dirty_pages: false,
+            pit_enabled: None,
+            pit_reinject_policy: None,
+            hpet_enabled: None,
+            cpu_topology: None,
+            mem_backend: None,
+            nested_virt_enabled: None,
+            msr_policy: None,
+            mmio_layout: None,
+            virtio_transport: None,
+            cpu_template_path: None,
+            cpu_features: None,
+            cpuid_normalization_enabled: None,
+            mem_hints: None,
+            io_threads: None,
         }
     }
 }
@@ -90,20 +253,81 @@ impl Default for VmConfig {
 impl fmt::Display for VmConfig {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let vcpu_count = self.vcpu_count.unwrap_or(1);
+        let max_vcpu_count = self.max_vcpu_count.unwrap_or(vcpu_count);
         let mem_size = self.mem_size_mib.unwrap_or(DEFAULT_MEM_SIZE_MIB);
         let ht_enabled = self.ht_enabled.unwrap_or(false);
         let cpu_template = self
             .cpu_template
             .map_or("Uninitialized".to_string(), |c| c.to_string());
+        let pit_enabled = self.pit_enabled.unwrap_or(true);
+        let pit_reinject_policy = self
+            .pit_reinject_policy
+            .unwrap_or(PitReinjectPolicy::Reinject)
+            .to_string();
+        let hpet_enabled = self.hpet_enabled.unwrap_or(true);
+        let cpu_topology = self
+            .cpu_topology
+            .map_or("Uninitialized".to_string(), |t| t.to_string());
+        let mem_backend = self.mem_backend.unwrap_or_default().to_string();
+        let nested_virt_enabled = self.nested_virt_enabled.unwrap_or(false);
+        let msr_policy = self
+            .msr_policy
+            .as_ref()
+            .map_or("Uninitialized".to_string(), |p| p.to_string());
+        let mmio_layout = self.mmio_layout.unwrap_or_default().to_string();
+        let virtio_transport = self.virtio_transport.unwrap_or_default().to_string();
+        let cpu_template_path = self.cpu_template_path.as_deref().unwrap_or("Uninitialized");
+        let cpu_features = self
+            .cpu_features
+            .as_ref()
+            .map_or("Uninitialized".to_string(), |c| c.to_string());
+        let cpuid_normalization_enabled = self.cpuid_normalization_enabled.unwrap_or(false);
+        let mem_hints = self.mem_hints.unwrap_or_default().to_string();
+        let io_threads = self.io_threads.unwrap_or(0);
         write!(
             f,
-            "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \"ht_enabled\": {:?}, \
-             \"cpu_template\": {:?}, \"track_dirty_pages\": {:?} }}",
-            vcpu_count, mem_size, ht_enabled, cpu_template, self.track_dirty_pages
+            "{{ \"vcpu_count\": {:?}, \"max_vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \
+             \"ht_enabled\": {:?}, \"cpu_template\": {:?}, \"track_dirty_pages\": {:?}, \
+             \"pit_enabled\": {:?}, \"pit_reinject_policy\": {:?}, \
+             \"hpet_enabled\": {:?}, \"cpu_topology\": {:?}, \"mem_backend\": {:?}, \
+             \"nested_virt_enabled\": {:?}, \"msr_policy\": {:?}, \
+             \"mmio_layout\": {:?}, \"virtio_transport\": {:?}, \
+             \"cpu_template_path\": {:?}, \"cpu_features\": {:?}, \
+             \"cpuid_normalization_enabled\": {:?}, \"mem_hints\": {:?}, \
+             \"io_threads\": {:?} }}",
+            vcpu_count,
+            max_vcpu_count,
+            mem_size,
+            ht_enabled,
+            cpu_template,
+            self.track_dirty_pages,
+            pit_enabled,
+            pit_reinject_policy,
+            hpet_enabled,
+            cpu_topology,
+            mem_backend,
+            nested_virt_enabled,
+            msr_policy,
+            mmio_layout,
+            virtio_transport,
+            cpu_template_path,
+            cpu_features,
+            cpuid_normalization_enabled,
+            mem_hints,
+            io_threads
         )
     }
 }
 
+/// Returns whether the KVM module of the host lets the guests run their own hypervisor.
+pub fn nested_virt_supported() -> bool {
+    ["kvm_intel", "kvm_amd"].iter().any(|module| {
+        std::fs::read_to_string(format!("/sys/module/{}/parameters/nested", module))
+            .map(|nested| matches!(nested.trim(), "Y" | "1"))
+            .unwrap_or(false)
+    })
+}
+
 fn validate_vcpu_num<'de, D>(d: D) -> std::result::Result<Option<u8>, D::Error>
 where
     D: de::Deserializer<'de>,
@@ -120,6 +344,368 @@ where
     Ok(val)
 }
 
+/// The arrangement of the vCPUs exposed to the guest. The vCPUs are numbered thread first, then
+/// core, then socket.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuTopology {
+    /// Number of sockets.
+    pub sockets: u8,
+    /// Number of cores per socket.
+    pub cores_per_socket: u8,
+    /// Number of threads per core.
+    pub threads_per_core: u8,
+}
+
+impl CpuTopology {
+    /// Returns the number of logical CPUs of the topology, if it fits in an `u8`.
+    pub fn cpu_count(&self) -> Option<u8> {
+        self.sockets
+            .checked_mul(self.cores_per_socket)
+            .and_then(|cores| cores.checked_mul(self.threads_per_core))
+    }
+
+    /// Checks that the topology can be exposed to a guest with `vcpu_count` vCPUs. The APIC IDs
+    /// of the vCPUs being contiguous, the sockets must have a power of 2 logical CPUs to be told
+    /// apart when there are several of them.
+    pub fn validate(&self, vcpu_count: u8) -> Result<(), VmConfigError> {
+        let cpus_per_socket = u32::from(self.cores_per_socket) * u32::from(self.threads_per_core);
+        if self.sockets == 0
+            || self.cores_per_socket == 0
+            || self.threads_per_core == 0
+            || self.threads_per_core > 2
+            || self.cpu_count() != Some(vcpu_count)
+            || (self.sockets > 1 && !cpus_per_socket.is_power_of_two())
+        {
+            return Err(VmConfigError::InvalidCpuTopology);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CpuTopology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.sockets, self.cores_per_socket, self.threads_per_core
+        )
+    }
+}
+
+/// The kinds of host memory which can back the guest memory.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum MemoryBackend {
+    /// Anonymous memory, with the default page size of the host.
+    #[serde(rename = "anonymous")]
+    Anonymous,
+    /// Memory from the hugetlbfs pool of 2 MiB pages.
+    #[serde(rename = "hugetlbfs_2m")]
+    Hugetlbfs2M,
+    /// Memory from the hugetlbfs pool of 1 GiB pages.
+    #[serde(rename = "hugetlbfs_1g")]
+    Hugetlbfs1G,
+    /// An anonymous file, with the default page size of the host.
+    #[serde(rename = "memfd")]
+    Memfd,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        MemoryBackend::Anonymous
+    }
+}
+
+impl MemoryBackend {
+    /// Returns the size of the huge pages of the backend, in MiB, if it uses huge pages.
+    pub fn huge_page_size_mib(self) -> Option<usize> {
+        match self {
+            MemoryBackend::Hugetlbfs2M => Some(2),
+            MemoryBackend::Hugetlbfs1G => Some(1024),
+            MemoryBackend::Anonymous | MemoryBackend::Memfd => None,
+        }
+    }
+}
+
+impl fmt::Display for MemoryBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryBackend::Anonymous => write!(f, "anonymous"),
+            MemoryBackend::Hugetlbfs2M => write!(f, "hugetlbfs_2m"),
+            MemoryBackend::Hugetlbfs1G => write!(f, "hugetlbfs_1g"),
+            MemoryBackend::Memfd => write!(f, "memfd"),
+        }
+    }
+}
+
+/// The hints given to the host kernel about the use of the guest memory, through madvise(2).
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryHints {
+    /// Lets the kernel samepage merging of the host merge the identical pages of the guest
+    /// memory, within the microVM and with other processes.
+    #[serde(default)]
+    pub mergeable: bool,
+    /// Whether the guest memory should be backed by transparent huge pages. Left to the policy
+    /// of the host when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub huge_pages: Option<bool>,
+}
+
+impl MemoryHints {
+    /// Returns the madvise(2) advices giving the hints.
+    pub fn advices(self) -> Vec<libc::c_int> {
+        let mut advices = Vec::new();
+        if self.mergeable {
+            advices.push(libc::MADV_MERGEABLE);
+        }
+        match self.huge_pages {
+            Some(true) => advices.push(libc::MADV_HUGEPAGE),
+            Some(false) => advices.push(libc::MADV_NOHUGEPAGE),
+            None => (),
+        }
+        advices
+    }
+}
+
+impl fmt::Display for MemoryHints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let huge_pages = self
+            .huge_pages
+            .map_or("default".to_string(), |h| h.to_string());
+        write!(
+            f,
+            "mergeable: {}, huge pages: {}",
+            self.mergeable, huge_pages
+        )
+    }
+}
+
+/// A range of contiguous MSRs.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+pub struct MsrRange {
+    /// The index of the first MSR of the range.
+    pub index: u32,
+    /// The number of MSRs in the range.
+    #[serde(default = "default_msr_count")]
+    pub count: u32,
+}
+
+fn default_msr_count() -> u32 {
+    1
+}
+
+impl MsrRange {
+    /// Returns whether the range holds the MSR `index`.
+    pub fn contains(&self, index: u32) -> bool {
+        index >= self.index && index - self.index < self.count
+    }
+}
+
+/// What the guest gets when it accesses a denied MSR.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+pub enum MsrDenyAction {
+    /// The access raises a general protection fault, as for an MSR the CPU doesn't have.
+    Fault,
+    /// Reads return zero and writes are ignored.
+    ReadZero,
+}
+
+impl Default for MsrDenyAction {
+    fn default() -> Self {
+        MsrDenyAction::Fault
+    }
+}
+
+impl fmt::Display for MsrDenyAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MsrDenyAction::Fault => write!(f, "Fault"),
+            MsrDenyAction::ReadZero => write!(f, "ReadZero"),
+        }
+    }
+}
+
+/// The MSRs the guest may access, on top of the filtering done by KVM itself. The MSRs of the
+/// allowed ranges can be accessed even if they are also in a denied range, and the MSRs out of
+/// any range are left alone.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+pub struct MsrPolicy {
+    /// The ranges of MSRs the guest may access.
+    #[serde(default)]
+    pub allow: Vec<MsrRange>,
+    /// The ranges of MSRs the guest may not access.
+    #[serde(default)]
+    pub deny: Vec<MsrRange>,
+    /// What the guest gets when it accesses a denied MSR.
+    #[serde(default)]
+    pub deny_action: MsrDenyAction,
+}
+
+impl MsrPolicy {
+    /// Checks that KVM can filter the ranges of the policy.
+    pub fn validate(&self) -> Result<(), VmConfigError> {
+        if self.allow.len() + self.deny.len() > MAX_MSR_RANGES {
+            return Err(VmConfigError::InvalidMsrPolicy);
+        }
+        let invalid_range = |range: &MsrRange| {
+            range.count == 0
+                || range.count > MAX_MSR_RANGE_COUNT
+                || range.index.checked_add(range.count).is_none()
+        };
+        if self.allow.iter().chain(self.deny.iter()).any(invalid_range) {
+            return Err(VmConfigError::InvalidMsrPolicy);
+        }
+        Ok(())
+    }
+
+    /// Returns whether the guest may access the MSR `index`.
+    pub fn allows(&self, index: u32) -> bool {
+        self.allow.iter().any(|range| range.contains(index))
+            || !self.deny.iter().any(|range| range.contains(index))
+    }
+}
+
+impl fmt::Display for MsrPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} allowed and {} denied ranges, {}",
+            self.allow.len(),
+            self.deny.len(),
+            self.deny_action
+        )
+    }
+}
+
+/// The CPUID feature bits forced on or off, named as in `/proc/cpuinfo`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuFeaturesConfig {
+    /// The features exposed to the guest. The host has to support them.
+    #[serde(default)]
+    pub enable: Vec<String>,
+    /// The features hidden from the guest.
+    #[serde(default)]
+    pub disable: Vec<String>,
+}
+
+impl CpuFeaturesConfig {
+    /// Checks that the features are known, and that none is both enabled and disabled.
+    pub fn validate(&self) -> Result<(), VmConfigError> {
+        #[cfg(target_arch = "x86_64")]
+        if let Some(name) = self
+            .enable
+            .iter()
+            .chain(self.disable.iter())
+            .find(|name| cpuid::features::CpuidFeature::from_name(name).is_none())
+        {
+            return Err(VmConfigError::InvalidCpuFeatures(format!(
+                "unknown feature {}",
+                name
+            )));
+        }
+        if let Some(name) = self.enable.iter().find(|name| self.disable.contains(name)) {
+            return Err(VmConfigError::InvalidCpuFeatures(format!(
+                "{} is both enabled and disabled",
+                name
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CpuFeaturesConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let enabled = self.enable.iter().map(|name| format!("+{}", name));
+        let disabled = self.disable.iter().map(|name| format!("-{}", name));
+        write!(
+            f,
+            "{}",
+            enabled.chain(disabled).collect::<Vec<_>>().join(" ")
+        )
+    }
+}
+
+/// The layout of the memory gap holding the MMIO devices. The gap ends at 4 GiB on x86_64, where
+/// it splits the guest RAM, and has a fixed size on aarch64.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+pub struct MmioLayoutConfig {
+    /// The size of the gap, in MiB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gap_size_mib: Option<u32>,
+    /// The number of device slots, each getting its own IRQ.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_slots: Option<u32>,
+}
+
+impl MmioLayoutConfig {
+    /// Returns the layout, the defaults filling the unset fields, if it is valid.
+    pub fn layout(&self) -> Result<arch::MmioLayout, VmConfigError> {
+        let default = arch::MmioLayout::default();
+        let layout = arch::MmioLayout {
+            gap_size: self
+                .gap_size_mib
+                .map_or(default.gap_size, |size| u64::from(size) << 20),
+            slots: self.device_slots.unwrap_or(default.slots),
+        };
+        if !layout.is_valid() {
+            return Err(VmConfigError::InvalidMmioLayout);
+        }
+        Ok(layout)
+    }
+}
+
+impl From<arch::MmioLayout> for MmioLayoutConfig {
+    fn from(layout: arch::MmioLayout) -> Self {
+        MmioLayoutConfig {
+            gap_size_mib: Some((layout.gap_size >> 20) as u32),
+            device_slots: Some(layout.slots),
+        }
+    }
+}
+
+impl fmt::Display for MmioLayoutConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let default = arch::MmioLayout::default();
+        write!(
+            f,
+            "{} MiB gap, {} slots",
+            self.gap_size_mib.unwrap_or((default.gap_size >> 20) as u32),
+            self.device_slots.unwrap_or(default.slots)
+        )
+    }
+}
+
+/// The transports exposing the virtio devices to the guest.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum VirtioTransport {
+    /// Each device has its own MMIO region and IRQ, described on the kernel command line.
+    #[serde(rename = "mmio")]
+    Mmio,
+    /// The devices sit on a PCI root bus, and interrupt the guest through MSI-X.
+    #[serde(rename = "pci")]
+    Pci,
+}
+
+impl Default for VirtioTransport {
+    fn default() -> Self {
+        VirtioTransport::Mmio
+    }
+}
+
+impl fmt::Display for VirtioTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VirtioTransport::Mmio => write!(f, "mmio"),
+            VirtioTransport::Pci => write!(f, "pci"),
+        }
+    }
+}
+
 /// Template types available for configuring the CPU features that map
 /// to EC2 instances.
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
@@ -128,6 +714,8 @@ pub enum CpuFeaturesTemplate {
     C3,
     /// T2 Template.
     T2,
+    /// T2A Template, the T2 counterpart for AMD EPYC hosts.
+    T2A,
 }
 
 impl fmt::Display for CpuFeaturesTemplate {
@@ -135,6 +723,25 @@ impl fmt::Display for CpuFeaturesTemplate {
         match self {
             CpuFeaturesTemplate::C3 => write!(f, "C3"),
             CpuFeaturesTemplate::T2 => write!(f, "T2"),
+            CpuFeaturesTemplate::T2A => write!(f, "T2A"),
+        }
+    }
+}
+
+/// Policies for the in-kernel PIT when the guest misses timer interrupts.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum PitReinjectPolicy {
+    /// Missed ticks are queued and reinjected into the guest (the KVM default).
+    Reinject,
+    /// Missed ticks are discarded.
+    Discard,
+}
+
+impl fmt::Display for PitReinjectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PitReinjectPolicy::Reinject => write!(f, "Reinject"),
+            PitReinjectPolicy::Discard => write!(f, "Discard"),
         }
     }
 }
@@ -147,6 +754,222 @@ mod tests {
     fn test_display_cpu_features_template() {
         assert_eq!(CpuFeaturesTemplate::C3.to_string(), "C3".to_string());
         assert_eq!(CpuFeaturesTemplate::T2.to_string(), "T2".to_string());
+        assert_eq!(CpuFeaturesTemplate::T2A.to_string(), "T2A".to_string());
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        assert_eq!(MemoryBackend::default(), MemoryBackend::Anonymous);
+        assert_eq!(MemoryBackend::Anonymous.huge_page_size_mib(), None);
+        assert_eq!(MemoryBackend::Memfd.huge_page_size_mib(), None);
+        assert_eq!(MemoryBackend::Hugetlbfs2M.huge_page_size_mib(), Some(2));
+        assert_eq!(MemoryBackend::Hugetlbfs1G.huge_page_size_mib(), Some(1024));
+
+        for backend in &[
+            MemoryBackend::Anonymous,
+            MemoryBackend::Hugetlbfs2M,
+            MemoryBackend::Hugetlbfs1G,
+            MemoryBackend::Memfd,
+        ] {
+            let json = format!("\"{}\"", backend);
+            assert_eq!(serde_json::to_string(backend).unwrap(), json);
+            assert_eq!(
+                serde_json::from_str::<MemoryBackend>(&json).unwrap(),
+                *backend
+            );
+        }
+    }
+
+    #[test]
+    fn test_memory_hints() {
+        let hints: MemoryHints = serde_json::from_str("{}").unwrap();
+        assert_eq!(hints, MemoryHints::default());
+        assert!(hints.advices().is_empty());
+        assert_eq!(hints.to_string(), "mergeable: false, huge pages: default");
+
+        let hints: MemoryHints =
+            serde_json::from_str(r#"{ "mergeable": true, "huge_pages": false }"#).unwrap();
+        assert_eq!(
+            hints.advices(),
+            vec![libc::MADV_MERGEABLE, libc::MADV_NOHUGEPAGE]
+        );
+        assert_eq!(hints.to_string(), "mergeable: true, huge pages: false");
+
+        let hints: MemoryHints = serde_json::from_str(r#"{ "huge_pages": true }"#).unwrap();
+        assert_eq!(hints.advices(), vec![libc::MADV_HUGEPAGE]);
+
+        assert!(serde_json::from_str::<MemoryHints>(r#"{ "ksm": true }"#).is_err());
+    }
+
+    #[test]
+    fn test_display_pit_reinject_policy() {
+        assert_eq!(PitReinjectPolicy::Reinject.to_string(), "Reinject");
+        assert_eq!(PitReinjectPolicy::Discard.to_string(), "Discard");
+    }
+
+    #[test]
+    fn test_display_vm_config() {
+        let vm_config = VmConfig {
+            pit_reinject_policy: Some(PitReinjectPolicy::Discard),
+            hpet_enabled: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_config.to_string(),
+            format!(
+                "{{ \"vcpu_count\": 1, \"max_vcpu_count\": 1, \"mem_size_mib\": 128, \
+                \"ht_enabled\": false, \"cpu_template\": \"Uninitialized\", \
+                \"track_dirty_pages\": false, \
+                \"pit_enabled\": true, \"pit_reinject_policy\": \"Discard\", \
+                \"hpet_enabled\": false, \
+                \"cpu_topology\": \"Uninitialized\", \"mem_backend\": \"anonymous\", \
+                \"nested_virt_enabled\": false, \"msr_policy\": \"Uninitialized\", \
+                \"mmio_layout\": \"{} MiB gap, {} slots\", \"virtio_transport\": \"mmio\", \
+                \"cpu_template_path\": \"Uninitialized\", \"cpu_features\": \"Uninitialized\", \
+                \"cpuid_normalization_enabled\": false, \
+                \"mem_hints\": \"mergeable: false, huge pages: default\", \
+                \"io_threads\": 0 }}",
+                arch::DEFAULT_MMIO_GAP_SIZE >> 20,
+                arch::MAX_MMIO_SLOTS
+            )
+        );
+    }
+
+    #[test]
+    fn test_mmio_layout() {
+        let config: MmioLayoutConfig = serde_json::from_str(r#"{ "device_slots": 4 }"#).unwrap();
+        let layout = config.layout().unwrap();
+        assert_eq!(layout.gap_size, arch::DEFAULT_MMIO_GAP_SIZE);
+        assert_eq!(layout.slots, 4);
+        assert_eq!(MmioLayoutConfig::from(layout).layout().unwrap(), layout);
+
+        let config = MmioLayoutConfig {
+            gap_size_mib: None,
+            device_slots: Some(0),
+        };
+        assert_eq!(config.layout(), Err(VmConfigError::InvalidMmioLayout));
+        let config = MmioLayoutConfig {
+            gap_size_mib: Some(u32::MAX),
+            device_slots: None,
+        };
+        assert_eq!(config.layout(), Err(VmConfigError::InvalidMmioLayout));
+
+        assert!(serde_json::from_str::<MmioLayoutConfig>(r#"{ "slots": 4 }"#).is_err());
+    }
+
+    #[test]
+    fn test_virtio_transport() {
+        assert_eq!(VirtioTransport::default(), VirtioTransport::Mmio);
+        for transport in &[VirtioTransport::Mmio, VirtioTransport::Pci] {
+            let json = format!("\"{}\"", transport);
+            assert_eq!(serde_json::to_string(transport).unwrap(), json);
+            assert_eq!(
+                serde_json::from_str::<VirtioTransport>(&json).unwrap(),
+                *transport
+            );
+        }
+        assert!(serde_json::from_str::<VirtioTransport>("\"Pci\"").is_err());
+    }
+
+    #[test]
+    fn test_cpu_features() {
+        let features: CpuFeaturesConfig =
+            serde_json::from_str(r#"{ "enable": ["avx2"], "disable": ["avx512f", "pku"] }"#)
+                .unwrap();
+        assert!(features.validate().is_ok());
+        assert_eq!(features.to_string(), "+avx2 -avx512f -pku");
+
+        let features = CpuFeaturesConfig {
+            enable: vec!["avx2".to_string()],
+            disable: vec!["avx2".to_string()],
+        };
+        assert_eq!(
+            features.validate(),
+            Err(VmConfigError::InvalidCpuFeatures(
+                "avx2 is both enabled and disabled".to_string()
+            ))
+        );
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let features = CpuFeaturesConfig {
+                enable: vec![],
+                disable: vec!["avx1024".to_string()],
+            };
+            assert_eq!(
+                features.validate(),
+                Err(VmConfigError::InvalidCpuFeatures(
+                    "unknown feature avx1024".to_string()
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn test_msr_policy() {
+        let range = |index, count| MsrRange { index, count };
+        let mut policy: MsrPolicy = serde_json::from_str(
+            r#"{ "allow": [{ "index": 1536 }], "deny": [{ "index": 1024, "count": 1024 }] }"#,
+        )
+        .unwrap();
+        assert_eq!(policy.allow, vec![range(0x600, 1)]);
+        assert_eq!(policy.deny, vec![range(0x400, 0x400)]);
+        assert_eq!(policy.deny_action, MsrDenyAction::Fault);
+        assert_eq!(policy.to_string(), "1 allowed and 1 denied ranges, Fault");
+        assert!(policy.validate().is_ok());
+
+        assert!(policy.allows(0x3ff));
+        assert!(!policy.allows(0x400));
+        assert!(policy.allows(0x600));
+        assert!(!policy.allows(0x7ff));
+        assert!(policy.allows(0x800));
+
+        policy.deny = vec![range(0x400, MAX_MSR_RANGE_COUNT)];
+        assert!(policy.validate().is_ok());
+        for invalid in &[
+            range(0x400, 0),
+            range(0x400, MAX_MSR_RANGE_COUNT + 1),
+            range(u32::MAX, 1),
+        ] {
+            policy.deny = vec![*invalid];
+            assert_eq!(policy.validate(), Err(VmConfigError::InvalidMsrPolicy));
+        }
+        policy.deny = vec![range(0x400, 1); MAX_MSR_RANGES];
+        assert_eq!(policy.validate(), Err(VmConfigError::InvalidMsrPolicy));
+
+        assert!(serde_json::from_str::<MsrPolicy>(r#"{ "deny_action": "Ignore" }"#).is_err());
+    }
+
+    #[test]
+    fn test_cpu_topology() {
+        let topology = |sockets, cores_per_socket, threads_per_core| CpuTopology {
+            sockets,
+            cores_per_socket,
+            threads_per_core,
+        };
+
+        assert_eq!(topology(1, 3, 2).cpu_count(), Some(6));
+        assert_eq!(topology(16, 16, 2).cpu_count(), None);
+        assert_eq!(topology(2, 4, 1).to_string(), "2:4:1");
+
+        assert!(topology(1, 3, 2).validate(6).is_ok());
+        assert!(topology(2, 2, 2).validate(8).is_ok());
+        assert!(topology(4, 1, 1).validate(4).is_ok());
+
+        for (t, vcpu_count) in &[
+            (topology(1, 3, 2), 4),
+            (topology(0, 3, 2), 0),
+            (topology(1, 0, 2), 0),
+            (topology(1, 2, 0), 0),
+            (topology(1, 1, 4), 4),
+            (topology(2, 3, 1), 6),
+            (topology(16, 16, 2), 0),
+        ] {
+            assert_eq!(
+                t.validate(*vcpu_count),
+                Err(VmConfigError::InvalidCpuTopology)
+            );
+        }
     }
 
     #[test]
@@ -157,5 +980,17 @@ mod tests {
 
         let expected_str = "The memory size (MiB) is invalid.";
         assert_eq!(VmConfigError::InvalidMemorySize.to_string(), expected_str);
+
+        let expected_str = "Nested virtualization is not enabled in the KVM module of the host.";
+        assert_eq!(
+            VmConfigError::NestedVirtUnsupported.to_string(),
+            expected_str
+        );
+
+        let expected_str = "The PIT reinject policy cannot be set when the PIT is disabled.";
+        assert_eq!(
+            VmConfigError::IncompatiblePitPolicy.to_string(),
+            expected_str
+        );
     }
 }
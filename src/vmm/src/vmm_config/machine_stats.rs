@@ -0,0 +1,239 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs;
+use std::io;
+
+use logger::{IncMetric, VcpuExitMetrics};
+use serde::Serialize;
+
+// The prefix of the names of the vCPU threads, followed by the index of the vCPU.
+const VCPU_THREAD_NAME_PREFIX: &str = "fc_vcpu ";
+
+/// The host resources used by the microVM, for billing and placement decisions.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MachineStats {
+    /// The CPU time used by the whole Firecracker process, in microseconds.
+    pub process_cpu_time_us: u64,
+    /// The CPU time used by the VMM thread, in microseconds.
+    pub vmm_cpu_time_us: u64,
+    /// The CPU time used by each vCPU thread, in microseconds, ordered by vCPU index.
+    pub vcpu_cpu_time_us: Vec<u64>,
+    /// The resident set size of the process, in bytes.
+    pub rss_bytes: u64,
+    /// The number of file descriptors open in the process.
+    pub open_fds: u64,
+    /// The size of the guest memory, in bytes.
+    pub guest_memory_bytes: u64,
+    /// The part of the guest memory backed by host memory, in bytes.
+    pub guest_memory_resident_bytes: u64,
+    /// The part of the guest memory given back to the host by the balloon device, in bytes.
+    pub guest_memory_ballooned_bytes: u64,
+    /// The rate at which the guest dirtied its memory since the previous request, in pages per
+    /// second. Only reported when the dirty pages are tracked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dirty_pages_per_sec: Option<u64>,
+}
+
+/// The KVM exits of a vCPU, and how its running time splits between the guest and the VMM, since
+/// the vCPU was created.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct VcpuStats {
+    /// The index of the vCPU.
+    pub vcpu: u8,
+    /// The number of KVM exits for port IO.
+    pub exit_io: u64,
+    /// The number of KVM exits for MMIO.
+    pub exit_mmio: u64,
+    /// The number of KVM exits on a halted vCPU.
+    pub exit_hlt: u64,
+    /// The number of runs failing on a guest memory access the host couldn't fault in.
+    pub exit_epfault: u64,
+    /// The number of runs interrupted by a signal, e.g. to pause the vCPU.
+    pub exit_interrupts: u64,
+    /// The time spent running the guest, in microseconds.
+    pub guest_time_us: u64,
+    /// The time spent in the VMM handling the exits, in microseconds.
+    pub vmm_time_us: u64,
+    /// The time the vCPU thread was paused for having used up its CPU quota, in microseconds.
+    pub throttled_time_us: u64,
+}
+
+impl VcpuStats {
+    /// Reads the statistics of the vCPU `vcpu` out of its metrics.
+    pub fn new(vcpu: u8, metrics: &VcpuExitMetrics) -> Self {
+        VcpuStats {
+            vcpu,
+            exit_io: metrics.exit_io.count() as u64,
+            exit_mmio: metrics.exit_mmio.count() as u64,
+            exit_hlt: metrics.exit_hlt.count() as u64,
+            exit_epfault: metrics.exit_epfault.count() as u64,
+            exit_interrupts: metrics.exit_interrupts.count() as u64,
+            guest_time_us: metrics.guest_time_us.count() as u64,
+            vmm_time_us: metrics.vmm_time_us.count() as u64,
+            throttled_time_us: metrics.throttled_time_us.count() as u64,
+        }
+    }
+}
+
+/// Returns the CPU time used by each vCPU thread of the process, in microseconds, ordered by
+/// vCPU index. The vCPU threads are found by their name.
+pub(crate) fn vcpu_cpu_time_us() -> io::Result<Vec<u64>> {
+    let mut vcpus = Vec::new();
+    for entry in fs::read_dir("/proc/self/task")? {
+        let task = entry?.path();
+        // The thread may have exited in the meantime.
+        let name = match fs::read_to_string(task.join("comm")) {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let index = match name
+            .trim_end()
+            .strip_prefix(VCPU_THREAD_NAME_PREFIX)
+            .and_then(|index| index.parse::<u8>().ok())
+        {
+            Some(index) => index,
+            None => continue,
+        };
+        let stat = match fs::read_to_string(task.join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        vcpus.push((index, parse_cpu_time_us(&stat)?));
+    }
+    vcpus.sort_unstable();
+    Ok(vcpus
+        .into_iter()
+        .map(|(_, cpu_time_us)| cpu_time_us)
+        .collect())
+}
+
+/// Returns the resident set size of the process, in bytes.
+pub(crate) fn rss_bytes() -> io::Result<u64> {
+    let statm = fs::read_to_string("/proc/self/statm")?;
+    // The second field is the number of resident pages.
+    let resident_pages = statm
+        .split_whitespace()
+        .nth(1)
+        .and_then(|pages| pages.parse::<u64>().ok())
+        .ok_or_else(|| invalid_data("/proc/self/statm"))?;
+    Ok(resident_pages * sysconf::page::pagesize() as u64)
+}
+
+/// Returns the number of file descriptors open in the process.
+pub(crate) fn open_fds() -> io::Result<u64> {
+    let entries = fs::read_dir("/proc/self/fd")?.count() as u64;
+    // The directory being listed is open too.
+    Ok(entries.saturating_sub(1))
+}
+
+// Parses the user and system CPU times out of the content of a `/proc/<pid>/task/<tid>/stat`
+// file, in microseconds.
+fn parse_cpu_time_us(stat: &str) -> io::Result<u64> {
+    // The thread name, in the second field, is within parentheses and may contain spaces.
+    let fields: Vec<&str> = stat
+        .rsplitn(2, ')')
+        .next()
+        .ok_or_else(|| invalid_data("stat"))?
+        .split_whitespace()
+        .collect();
+    // `utime` and `stime` are the 14th and 15th fields, counting from 1, the state being the
+    // 3rd one.
+    let parse_field = |index: usize| {
+        fields
+            .get(index)
+            .and_then(|field| field.parse::<u64>().ok())
+            .ok_or_else(|| invalid_data("stat"))
+    };
+    let ticks = parse_field(11)? + parse_field(12)?;
+    // Safe because `sysconf` has no side effects.
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_sec <= 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ticks * 1_000_000 / ticks_per_sec as u64)
+}
+
+fn invalid_data(file: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unexpected content of {}", file),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_time_us() {
+        // Safe because `sysconf` has no side effects.
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+        let stat = "42 (fc_vcpu 0) S 1 42 42 0 -1 4194368 0 0 0 0 150 50 0 0 20 0 1 0";
+        assert_eq!(
+            parse_cpu_time_us(stat).unwrap(),
+            200 * 1_000_000 / ticks_per_sec
+        );
+        // The thread name may contain parentheses and spaces.
+        let stat = "42 (a) b (c) S 1 42 42 0 -1 4194368 0 0 0 0 1 2 0 0 20 0 1 0";
+        assert_eq!(
+            parse_cpu_time_us(stat).unwrap(),
+            3 * 1_000_000 / ticks_per_sec
+        );
+
+        assert!(parse_cpu_time_us("42 (fc_vcpu 0) S 1").is_err());
+        assert!(parse_cpu_time_us("42 (fc_vcpu 0) S 1 42 42 0 -1 0 0 0 0 0 x 50").is_err());
+    }
+
+    #[test]
+    fn test_process_stats() {
+        assert!(rss_bytes().unwrap() > 0);
+        // At least the standard streams are open.
+        assert!(open_fds().unwrap() >= 3);
+    }
+
+    #[test]
+    fn test_vcpu_cpu_time_us() {
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+        let vcpu = std::thread::Builder::new()
+            .name(format!("{}{}", VCPU_THREAD_NAME_PREFIX, 1))
+            .spawn(move || receiver.recv().unwrap())
+            .unwrap();
+        // Other tests may run vCPUs at the same time.
+        assert!(!vcpu_cpu_time_us().unwrap().is_empty());
+        sender.send(()).unwrap();
+        vcpu.join().unwrap();
+    }
+
+    #[test]
+    fn test_serialize_machine_stats() {
+        let stats = MachineStats {
+            vcpu_cpu_time_us: vec![1, 2],
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&stats).unwrap();
+        assert_eq!(value["vcpu_cpu_time_us"], serde_json::json!([1, 2]));
+        assert!(value.get("dirty_pages_per_sec").is_none());
+    }
+
+    #[test]
+    fn test_vcpu_stats() {
+        let metrics = VcpuExitMetrics::default();
+        metrics.exit_mmio.add(3);
+        metrics.guest_time_us.add(100);
+        // The statistics are totals, unaffected by the flushes of the metrics.
+        serde_json::to_string(&metrics).unwrap();
+        metrics.exit_mmio.inc();
+
+        let stats = VcpuStats::new(2, &metrics);
+        assert_eq!(
+            stats,
+            VcpuStats {
+                vcpu: 2,
+                exit_mmio: 4,
+                guest_time_us: 100,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use devices::virtio::mem::{Error as VirtioMemError, MIN_BLOCK_SIZE};
+pub use devices::virtio::mem::{VirtioMemStatus, MEM_DEV_ID};
+
+use serde::{Deserialize, Serialize};
+
+fn default_block_size_mib() -> u64 {
+    MIN_BLOCK_SIZE >> 20
+}
+
+/// Errors associated with the operations allowed on the hotplug memory.
+#[derive(Debug)]
+pub enum MemoryHotplugConfigError {
+    /// The user made a request on an inexistent virtio-mem device.
+    DeviceNotFound,
+    /// The block size is not a power of two, or is too small.
+    InvalidBlockSize,
+    /// The total size is zero, or not a multiple of the block size.
+    InvalidTotalSize,
+    /// The requested size is not a multiple of the block size, or exceeds the total size.
+    InvalidRequestedSize,
+    /// Failed to create the virtio-mem device.
+    CreateFailure(VirtioMemError),
+    /// Failed to notify the driver of the new requested size.
+    UpdateFailure(std::io::Error),
+}
+
+impl fmt::Display for MemoryHotplugConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::MemoryHotplugConfigError::*;
+        match self {
+            DeviceNotFound => write!(f, "No hotplug memory configured."),
+            InvalidBlockSize => write!(
+                f,
+                "The block size must be a power of two of at least {} MiB.",
+                MIN_BLOCK_SIZE >> 20
+            ),
+            InvalidTotalSize => write!(
+                f,
+                "The total size must be a non-zero multiple of the block size."
+            ),
+            InvalidRequestedSize => write!(
+                f,
+                "The requested size must be a multiple of the block size, not exceeding the \
+                 total size."
+            ),
+            CreateFailure(e) => write!(f, "Error creating the virtio-mem device: {:?}", e),
+            UpdateFailure(e) => write!(
+                f,
+                "Error updating the virtio-mem device configuration: {:?}",
+                e
+            ),
+        }
+    }
+}
+
+impl From<VirtioMemError> for MemoryHotplugConfigError {
+    fn from(error: VirtioMemError) -> Self {
+        match error {
+            VirtioMemError::DeviceNotFound => Self::DeviceNotFound,
+            VirtioMemError::InterruptError(io_error) => Self::UpdateFailure(io_error),
+            VirtioMemError::InvalidBlockSize => Self::InvalidBlockSize,
+            VirtioMemError::InvalidRegionSize => Self::InvalidTotalSize,
+            VirtioMemError::InvalidRequestedSize => Self::InvalidRequestedSize,
+            e => Self::CreateFailure(e),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, MemoryHotplugConfigError>;
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from the hotplug memory configuration requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryHotplugConfig {
+    /// Size of the memory region which can be hot(un)plugged, in MiB.
+    pub total_size_mib: u64,
+    /// Size of the blocks the memory is hot(un)plugged in, in MiB.
+    #[serde(default = "default_block_size_mib")]
+    pub block_size_mib: u64,
+}
+
+impl MemoryHotplugConfig {
+    /// Checks that the hotplug memory region can be split into blocks.
+    pub fn validate(&self) -> Result<()> {
+        if !self.block_size_mib.is_power_of_two() || self.block_size_mib << 20 < MIN_BLOCK_SIZE {
+            return Err(MemoryHotplugConfigError::InvalidBlockSize);
+        }
+        if self.total_size_mib == 0 || self.total_size_mib % self.block_size_mib != 0 {
+            return Err(MemoryHotplugConfigError::InvalidTotalSize);
+        }
+
+        Ok(())
+    }
+}
+
+/// The data fed into a hotplug memory update request. Only the amount of memory the guest is
+/// requested to plug can be updated.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryHotplugSizeUpdate {
+    /// Amount of memory the guest is requested to plug, in MiB.
+    pub requested_size_mib: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 2,
+        };
+        assert!(config.validate().is_ok());
+
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 1,
+        };
+        match config.validate() {
+            Err(MemoryHotplugConfigError::InvalidBlockSize) => (),
+            _ => panic!("Unexpected result."),
+        }
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1024,
+            block_size_mib: 6,
+        };
+        match config.validate() {
+            Err(MemoryHotplugConfigError::InvalidBlockSize) => (),
+            _ => panic!("Unexpected result."),
+        }
+        let config = MemoryHotplugConfig {
+            total_size_mib: 1000,
+            block_size_mib: 16,
+        };
+        match config.validate() {
+            Err(MemoryHotplugConfigError::InvalidTotalSize) => (),
+            _ => panic!("Unexpected result."),
+        }
+        let config = MemoryHotplugConfig {
+            total_size_mib: 0,
+            block_size_mib: 2,
+        };
+        match config.validate() {
+            Err(MemoryHotplugConfigError::InvalidTotalSize) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: MemoryHotplugConfig =
+            serde_json::from_str(r#"{"total_size_mib": 512}"#).unwrap();
+        assert_eq!(
+            config,
+            MemoryHotplugConfig {
+                total_size_mib: 512,
+                block_size_mib: 2,
+            }
+        );
+        assert!(serde_json::from_str::<MemoryHotplugConfig>(
+            r#"{"total_size_mib": 512, "requested_size_mib": 256}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use super::MemoryHotplugConfigError::*;
+        use std::io;
+
+        let err = CreateFailure(VirtioMemError::EventFd(io::Error::from_raw_os_error(0)));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UpdateFailure(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DeviceNotFound;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidBlockSize;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidTotalSize;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidRequestedSize;
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -3,18 +3,56 @@
 
 //! Auxiliary module for configuring the metrics system.
 use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 use super::{open_file_nonblock, FcLineWriter};
-use logger::METRICS;
+use lazy_static::lazy_static;
+use logger::{MetricsSink, SinkFormat, DEFAULT_FLUSH_INTERVAL_MS, METRICS};
 
 use serde::{Deserialize, Serialize};
 
+lazy_static! {
+    // The named pipe or file the metrics are written to, once the metrics are configured.
+    static ref METRICS_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// The shortest interval between the periodic flushes of the metrics.
+pub const MIN_FLUSH_INTERVAL_MS: u64 = 100;
+
+/// A remote collector the metrics are pushed to over UDP at each flush.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsSinkConfig {
+    /// The IP address and UDP port of the collector.
+    pub address: SocketAddr,
+    /// Whether the metrics are pushed to the collector.
+    #[serde(default = "default_sink_enabled")]
+    pub enabled: bool,
+}
+
+// Serde does not allow specifying a default value for a field
+// that is not required. The workaround is to specify a function
+// that returns the value.
+fn default_sink_enabled() -> bool {
+    true
+}
+
 /// Strongly typed structure used to describe the metrics system.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct MetricsConfig {
     /// Named pipe or file used as output for metrics.
     pub metrics_path: PathBuf,
+    /// Collector the metrics are pushed to in the InfluxDB line protocol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_protocol: Option<MetricsSinkConfig>,
+    /// Collector the metrics are pushed to as statsd datagrams.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statsd: Option<MetricsSinkConfig>,
+    /// Interval between the periodic flushes of the metrics, 0 disabling them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flush_interval_ms: Option<u64>,
 }
 
 /// Errors associated with actions on the `MetricsConfig`.
@@ -22,6 +60,8 @@ pub struct MetricsConfig {
 pub enum MetricsConfigError {
     /// Cannot initialize the metrics system due to bad user input.
     InitializationFailure(String),
+    /// Cannot reopen the named pipe or file the metrics are written to.
+    ReopenFailure(String),
 }
 
 impl Display for MetricsConfigError {
@@ -29,19 +69,62 @@ impl Display for MetricsConfigError {
         use self::MetricsConfigError::*;
         match *self {
             InitializationFailure(ref err_msg) => write!(f, "{}", err_msg.replace("\"", "")),
+            ReopenFailure(ref err_msg) => write!(f, "Cannot reopen the metrics file: {}", err_msg),
         }
     }
 }
 
 /// Configures the metrics as described in `metrics_cfg`.
 pub fn init_metrics(metrics_cfg: MetricsConfig) -> std::result::Result<(), MetricsConfigError> {
+    let flush_interval_ms = metrics_cfg
+        .flush_interval_ms
+        .unwrap_or(DEFAULT_FLUSH_INTERVAL_MS);
+    if flush_interval_ms > 0 && flush_interval_ms < MIN_FLUSH_INTERVAL_MS {
+        return Err(MetricsConfigError::InitializationFailure(format!(
+            "The metrics flush interval must be 0, to disable the periodic flushes, or at least \
+             {} ms.",
+            MIN_FLUSH_INTERVAL_MS
+        )));
+    }
     let writer = FcLineWriter::new(
         open_file_nonblock(&metrics_cfg.metrics_path)
             .map_err(|e| MetricsConfigError::InitializationFailure(e.to_string()))?,
     );
     METRICS
         .init(Box::new(writer))
-        .map_err(|e| MetricsConfigError::InitializationFailure(e.to_string()))
+        .map_err(|e| MetricsConfigError::InitializationFailure(e.to_string()))?;
+
+    let sinks = [
+        (SinkFormat::LineProtocol, &metrics_cfg.line_protocol),
+        (SinkFormat::Statsd, &metrics_cfg.statsd),
+    ];
+    for (format, sink_cfg) in sinks.iter() {
+        if let Some(sink_cfg) = sink_cfg.as_ref().filter(|sink_cfg| sink_cfg.enabled) {
+            let sink = MetricsSink::new(*format, sink_cfg.address).map_err(|e| {
+                MetricsConfigError::InitializationFailure(format!(
+                    "Cannot push the metrics to {}: {}",
+                    sink_cfg.address, e
+                ))
+            })?;
+            METRICS.add_sink(sink);
+        }
+    }
+    METRICS.set_flush_interval_ms(flush_interval_ms);
+    *METRICS_PATH.lock().expect("Poisoned lock") = Some(metrics_cfg.metrics_path);
+    Ok(())
+}
+
+/// Reopens the named pipe or file the metrics are written to, if the metrics are configured, so
+/// that it can be rotated.
+pub fn reopen_metrics() -> std::result::Result<(), MetricsConfigError> {
+    if let Some(metrics_path) = METRICS_PATH.lock().expect("Poisoned lock").as_ref() {
+        let writer = FcLineWriter::new(
+            open_file_nonblock(metrics_path)
+                .map_err(|e| MetricsConfigError::ReopenFailure(e.to_string()))?,
+        );
+        METRICS.reopen(Box::new(writer));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -54,17 +137,57 @@ mod tests {
         // Error case: initializing metrics with invalid pipe returns error.
         let desc = MetricsConfig {
             metrics_path: PathBuf::from("not_found_file_metrics"),
+            line_protocol: None,
+            statsd: None,
+            flush_interval_ms: None,
         };
         assert!(init_metrics(desc).is_err());
+        // Reopening the metrics before they are configured has no effect.
+        assert!(reopen_metrics().is_ok());
+
+        // Error case: the flush interval is too short.
+        let metrics_file = TempFile::new().unwrap();
+        let desc = MetricsConfig {
+            metrics_path: metrics_file.as_path().to_path_buf(),
+            line_protocol: None,
+            statsd: None,
+            flush_interval_ms: Some(MIN_FLUSH_INTERVAL_MS - 1),
+        };
+        assert_eq!(
+            init_metrics(desc).unwrap_err().to_string(),
+            "The metrics flush interval must be 0, to disable the periodic flushes, or at least \
+             100 ms."
+        );
 
         // Initializing metrics with valid pipe is ok.
         let metrics_file = TempFile::new().unwrap();
+        // The sinks are disabled, as the global metrics are flushed by other tests.
         let desc = MetricsConfig {
             metrics_path: metrics_file.as_path().to_path_buf(),
+            line_protocol: Some(MetricsSinkConfig {
+                address: "127.0.0.1:8089".parse().unwrap(),
+                enabled: false,
+            }),
+            statsd: Some(MetricsSinkConfig {
+                address: "127.0.0.1:8125".parse().unwrap(),
+                enabled: false,
+            }),
+            flush_interval_ms: Some(30000),
         };
 
         assert!(init_metrics(desc.clone()).is_ok());
+        assert_eq!(METRICS.flush_interval_ms(), 30000);
         assert!(init_metrics(desc).is_err());
+
+        // Reopening the metrics fails once the file is gone, and succeeds once it is recreated.
+        std::fs::remove_file(metrics_file.as_path()).unwrap();
+        assert!(reopen_metrics().is_err());
+        std::fs::File::create(metrics_file.as_path()).unwrap();
+        assert!(reopen_metrics().is_ok());
+        assert!(METRICS.write().is_ok());
+        assert!(!std::fs::read_to_string(metrics_file.as_path())
+            .unwrap()
+            .is_empty());
     }
 
     #[test]
@@ -78,5 +201,12 @@ mod tests {
             ),
             "Failed to initialize metrics"
         );
+        assert_eq!(
+            format!(
+                "{}",
+                MetricsConfigError::ReopenFailure(String::from("No such file or directory"))
+            ),
+            "Cannot reopen the metrics file: No such file or directory"
+        );
     }
 }
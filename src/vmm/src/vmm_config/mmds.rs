@@ -1,16 +1,44 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use serde::{export::Formatter, Deserialize};
+use serde::{export::Formatter, Deserialize, Serialize};
 use std::fmt::{Display, Result};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use mmds::data_store::Error as DataStoreError;
+pub use mmds::data_store::MmdsVersion;
+pub use mmds::dynamic::DynamicValue;
+
+/// A field of the MMDS data store whose value is computed when the guest requests it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MmdsDynamicField {
+    /// JSON pointer to the location of the field.
+    pub path: String,
+    /// The way the value of the field is computed.
+    pub value: DynamicValue,
+}
 
 /// Keeps the MMDS configuration.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct MmdsConfig {
     /// MMDS IPv4 configured address.
     pub ipv4_address: Option<Ipv4Addr>,
+    /// MMDS IPv6 address. The MMDS is only reachable over IPv6 when this is set.
+    pub ipv6_address: Option<Ipv6Addr>,
+    /// MMDS version. In V2 mode, the guest has to present a session token on every request.
+    #[serde(default)]
+    pub version: MmdsVersion,
+    /// IPv4 TTL of the packets sent by the MMDS.
+    pub hop_limit: Option<u8>,
+    /// IDs of the network interfaces the MMDS is reachable through. When present, it
+    /// overrides the `allow_mmds_requests` setting of every network interface.
+    pub network_interfaces: Option<Vec<String>>,
+    /// Fields of the data store computed at request time.
+    pub dynamic_fields: Option<Vec<MmdsDynamicField>>,
+    /// Maximum size, in bytes, of the serialized data store.
+    pub data_store_limit: Option<usize>,
 }
 
 impl MmdsConfig {
@@ -26,6 +54,18 @@ impl MmdsConfig {
 pub enum MmdsConfigError {
     /// The provided IPv4 address is not link-local valid.
     InvalidIpv4Addr,
+    /// The provided IPv6 address is neither link-local nor unique local.
+    InvalidIpv6Addr,
+    /// The provided hop limit is zero.
+    InvalidHopLimit,
+    /// The provided list of network interfaces is empty.
+    EmptyNetworkInterfaces,
+    /// The provided network interface ID does not match any configured interface.
+    InvalidNetworkInterfaceId(String),
+    /// The provided path of a dynamic field is not a valid JSON pointer.
+    InvalidDynamicField(DataStoreError),
+    /// The data store is larger than the provided size limit.
+    InvalidDataStoreLimit(DataStoreError),
 }
 
 impl Display for MmdsConfigError {
@@ -34,6 +74,24 @@ impl Display for MmdsConfigError {
             MmdsConfigError::InvalidIpv4Addr => {
                 write!(f, "The MMDS IPv4 address is not link local.")
             }
+            MmdsConfigError::InvalidIpv6Addr => write!(
+                f,
+                "The MMDS IPv6 address is neither link local nor unique local."
+            ),
+            MmdsConfigError::InvalidHopLimit => {
+                write!(f, "The MMDS hop limit must be greater than zero.")
+            }
+            MmdsConfigError::EmptyNetworkInterfaces => write!(
+                f,
+                "The list of network interfaces the MMDS is reachable through is empty."
+            ),
+            MmdsConfigError::InvalidNetworkInterfaceId(iface_id) => write!(
+                f,
+                "The MMDS cannot be bound to the {} network interface, since it does not exist.",
+                iface_id
+            ),
+            MmdsConfigError::InvalidDynamicField(err) => write!(f, "{}", err),
+            MmdsConfigError::InvalidDataStoreLimit(err) => write!(f, "{}", err),
         }
     }
 }
@@ -6,34 +6,83 @@ use std::fs::{File, OpenOptions};
 use std::io;
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use libc::O_NONBLOCK;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use rate_limiter::adaptive::{AdaptiveRate, DEFAULT_MIN_RATE_PCT, MIN_RATE_PCT};
 use rate_limiter::{BucketUpdate, RateLimiter, TokenBucket};
 
 /// Wrapper for configuring the balloon device.
+#[cfg(feature = "balloon")]
 pub mod balloon;
 /// Wrapper for configuring the microVM boot source.
 pub mod boot_source;
+/// Wrapper for configuring the console device.
+#[cfg(feature = "virtio-console")]
+pub mod console;
+/// Wrapper for configuring the CPU quota of the vCPUs.
+pub mod cpu_quota;
 /// Wrapper for configuring the block devices.
+#[cfg(feature = "block")]
 pub mod drive;
+/// Wrapper for configuring the entropy device.
+#[cfg(feature = "virtio-rng")]
+pub mod entropy;
+/// Wrapper for configuring how the guest clock is kept right.
+#[cfg(target_arch = "x86_64")]
+pub mod guest_clock;
+/// Wrapper for configuring the action taken when the guest panics.
+pub mod guest_panic;
 /// Wrapper over the microVM general information attached to the microVM.
 pub mod instance_info;
 /// Wrapper for configuring the logger.
 pub mod logger;
 /// Wrapper for configuring the memory and CPU of the microVM.
 pub mod machine_config;
+/// Wrapper over the host resources used by the microVM.
+pub mod machine_stats;
+/// Wrapper for configuring the hotplug memory of the microVM.
+#[cfg(feature = "virtio-mem")]
+pub mod memory_hotplug;
 /// Wrapper for configuring the metrics.
 pub mod metrics;
 /// Wrapper for configuring the MMDS.
 pub mod mmds;
 /// Wrapper for configuring the network devices attached to the microVM.
+#[cfg(feature = "net")]
 pub mod net;
+/// Wrapper for configuring the null devices attached to the microVM.
+#[cfg(feature = "null-devices")]
+pub mod null_device;
+/// Wrapper for configuring the persistent memory devices attached to the microVM.
+#[cfg(feature = "virtio-pmem")]
+pub mod pmem;
+/// Wrapper for configuring the rate limiter groups shared by the devices.
+pub mod rate_limiter_group;
+/// Wrapper for configuring the serial ports bound to a host backend.
+pub mod serial;
+/// Wrapper for configuring the shared filesystems attached to the microVM.
+#[cfg(feature = "virtio-fs")]
+pub mod shared_fs;
+/// Wrapper for configuring what the VMM does when it receives `SIGTERM`.
+#[cfg(target_arch = "x86_64")]
+pub mod shutdown_behavior;
 /// Wrapper for configuring microVM snapshots and the microVM state.
 pub mod snapshot;
+/// Wrapper for configuring the TPM device.
+#[cfg(all(feature = "tpm", target_arch = "x86_64"))]
+pub mod tpm;
+/// Wrapper for configuring the host PCI functions passed through to the microVM.
+#[cfg(all(feature = "vfio", target_arch = "x86_64"))]
+pub mod vfio;
 /// Wrapper for configuring the vsock devices attached to the microVM.
+#[cfg(feature = "vsock")]
 pub mod vsock;
+/// Wrapper for configuring the watchdog device.
+#[cfg(target_arch = "x86_64")]
+pub mod watchdog;
 
 // TODO: Migrate the VMM public-facing code (i.e. interface) to use stateless structures,
 // for receiving data/args, such as the below `RateLimiterConfig` and `TokenBucketConfig`.
@@ -46,7 +95,7 @@ pub mod vsock;
 
 /// A public-facing, stateless structure, holding all the data we need to create a TokenBucket
 /// (live) object.
-#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct TokenBucketConfig {
     /// See TokenBucket::size.
     pub size: u64,
@@ -58,13 +107,63 @@ pub struct TokenBucketConfig {
 
 /// A public-facing, stateless structure, holding all the data we need to create a RateLimiter
 /// (live) object.
-#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct RateLimiterConfig {
     /// Data used to initialize the RateLimiter::bandwidth bucket.
     pub bandwidth: Option<TokenBucketConfig>,
     /// Data used to initialize the RateLimiter::ops bucket.
     pub ops: Option<TokenBucketConfig>,
+    /// Tuning of the rate of the buckets to the latency of the operations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive: Option<AdaptiveConfig>,
+}
+
+/// A public-facing, stateless structure, holding the data we need to tune a RateLimiter to the
+/// latency of its operations.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdaptiveConfig {
+    /// The latency to keep the operations under, in microseconds. Zero disables the tuning.
+    pub target_latency_us: u64,
+    /// The smallest rate the buckets can be tightened to, in percents of their configured rate.
+    pub min_rate_pct: Option<u64>,
+}
+
+impl AdaptiveConfig {
+    /// Returns the tuning to apply to a `RateLimiter`, or `None` if it is disabled.
+    ///
+    /// # Errors
+    ///
+    /// If the smallest rate is not a percentage, an error is returned.
+    pub fn adaptive_rate(&self) -> io::Result<Option<AdaptiveRate>> {
+        let min_rate_pct = self.min_rate_pct.unwrap_or(DEFAULT_MIN_RATE_PCT);
+        if min_rate_pct < MIN_RATE_PCT || min_rate_pct > 100 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The minimum rate {}% is not between {}% and 100%",
+                    min_rate_pct, MIN_RATE_PCT
+                ),
+            ));
+        }
+        if self.target_latency_us == 0 {
+            return Ok(None);
+        }
+        Ok(Some(AdaptiveRate::new(
+            Duration::from_micros(self.target_latency_us),
+            min_rate_pct,
+        )))
+    }
+}
+
+impl From<&AdaptiveRate> for AdaptiveConfig {
+    fn from(adaptive: &AdaptiveRate) -> Self {
+        AdaptiveConfig {
+            target_latency_us: adaptive.target().as_micros() as u64,
+            min_rate_pct: Some(adaptive.min_rate_pct()),
+        }
+    }
 }
 
 /// A public-facing, stateless structure, specifying RateLimiter properties updates.
@@ -117,14 +216,111 @@ impl TryInto<RateLimiter> for RateLimiterConfig {
     fn try_into(self) -> std::result::Result<RateLimiter, Self::Error> {
         let bw = self.bandwidth.unwrap_or_default();
         let ops = self.ops.unwrap_or_default();
-        RateLimiter::new(
+        let mut rate_limiter = RateLimiter::new(
             bw.size,
             bw.one_time_burst.unwrap_or(0),
             bw.refill_time,
             ops.size,
             ops.one_time_burst.unwrap_or(0),
             ops.refill_time,
-        )
+        )?;
+        if let Some(adaptive) = self.adaptive {
+            rate_limiter.set_adaptive(adaptive.adaptive_rate()?);
+        }
+        Ok(rate_limiter)
+    }
+}
+
+impl From<&TokenBucket> for TokenBucketConfig {
+    fn from(bucket: &TokenBucket) -> Self {
+        let one_time_burst = bucket.initial_one_time_burst();
+        TokenBucketConfig {
+            size: bucket.capacity(),
+            one_time_burst: if one_time_burst == 0 {
+                None
+            } else {
+                Some(one_time_burst)
+            },
+            refill_time: bucket.refill_time_ms(),
+        }
+    }
+}
+
+impl RateLimiterConfig {
+    /// Returns the configuration of a live `RateLimiter`, or `None` if it limits nothing.
+    pub fn from_rate_limiter(rate_limiter: &RateLimiter) -> Option<Self> {
+        let bandwidth = rate_limiter.bandwidth().map(TokenBucketConfig::from);
+        let ops = rate_limiter.ops().map(TokenBucketConfig::from);
+        if bandwidth.is_none() && ops.is_none() {
+            return None;
+        }
+        let adaptive = rate_limiter.adaptive().map(AdaptiveConfig::from);
+        Some(RateLimiterConfig {
+            bandwidth,
+            ops,
+            adaptive,
+        })
+    }
+}
+
+/// A public-facing, stateless structure, holding the live state of a TokenBucket object.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct TokenBucketStats {
+    /// See TokenBucket::size.
+    pub size: u64,
+    /// The one time burst budget left.
+    pub one_time_burst: u64,
+    /// See TokenBucket::refill_time.
+    pub refill_time: u64,
+    /// The number of tokens the bucket is refilled with each second, at its current rate.
+    pub refill_rate: u64,
+    /// The number of tokens the bucket holds now.
+    pub budget: u64,
+}
+
+impl From<&TokenBucket> for TokenBucketStats {
+    fn from(bucket: &TokenBucket) -> Self {
+        TokenBucketStats {
+            size: bucket.capacity(),
+            one_time_burst: bucket.one_time_burst(),
+            refill_time: bucket.refill_time_ms(),
+            refill_rate: bucket.capacity().saturating_mul(1000) / bucket.refill_time_ms()
+                * bucket.rate_pct()
+                / 100,
+            budget: bucket.current_budget(),
+        }
+    }
+}
+
+/// A public-facing, stateless structure, holding the live state of a RateLimiter object, so
+/// that the operators can tell whether it throttles the device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct RateLimiterStats {
+    /// The state of the RateLimiter::bandwidth bucket.
+    pub bandwidth: Option<TokenBucketStats>,
+    /// The state of the RateLimiter::ops bucket.
+    pub ops: Option<TokenBucketStats>,
+    /// Whether the rate limiter is blocked, waiting for its buckets to refill.
+    pub blocked: bool,
+    /// The number of operations throttled for lack of budget.
+    pub throttled_count: u64,
+    /// The total time the rate limiter spent blocked, in microseconds.
+    pub throttled_time_us: u64,
+    /// The rate the adaptive tuning set the buckets to, in percents of their configured rate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adaptive_rate_pct: Option<u64>,
+}
+
+impl From<&RateLimiter> for RateLimiterStats {
+    fn from(rate_limiter: &RateLimiter) -> Self {
+        RateLimiterStats {
+            bandwidth: rate_limiter.bandwidth().map(TokenBucketStats::from),
+            ops: rate_limiter.ops().map(TokenBucketStats::from),
+            blocked: rate_limiter.is_blocked(),
+            throttled_count: rate_limiter.throttled_count(),
+            throttled_time_us: rate_limiter.throttled_time().as_micros() as u64,
+            adaptive_rate_pct: rate_limiter.adaptive().map(AdaptiveRate::rate_pct),
+        }
     }
 }
 
@@ -169,6 +365,7 @@ mod tests {
                 one_time_burst: None,
                 refill_time: REFILL_TIME * 2,
             }),
+            adaptive: None,
         };
         let rl: RateLimiter = rlconf.try_into().unwrap();
         assert_eq!(rl.bandwidth().unwrap().capacity(), SIZE);
@@ -177,6 +374,78 @@ mod tests {
         assert_eq!(rl.ops().unwrap().capacity(), SIZE * 2);
         assert_eq!(rl.ops().unwrap().one_time_burst(), 0);
         assert_eq!(rl.ops().unwrap().refill_time_ms(), REFILL_TIME * 2);
+
+        assert_eq!(RateLimiterConfig::from_rate_limiter(&rl), Some(rlconf));
+        assert!(RateLimiterConfig::from_rate_limiter(&RateLimiter::default()).is_none());
+    }
+
+    #[test]
+    fn test_adaptive_config() {
+        let mut rlconf = RateLimiterConfig {
+            bandwidth: Some(TokenBucketConfig {
+                size: 1000,
+                one_time_burst: None,
+                refill_time: 1000,
+            }),
+            ops: None,
+            adaptive: Some(AdaptiveConfig {
+                target_latency_us: 5000,
+                min_rate_pct: None,
+            }),
+        };
+        let rl: RateLimiter = rlconf.try_into().unwrap();
+        let adaptive = rl.adaptive().unwrap();
+        assert_eq!(adaptive.target(), Duration::from_millis(5));
+        assert_eq!(adaptive.min_rate_pct(), DEFAULT_MIN_RATE_PCT);
+        assert_eq!(
+            RateLimiterStats::from(&rl).adaptive_rate_pct,
+            Some(adaptive.rate_pct())
+        );
+
+        rlconf.adaptive.as_mut().unwrap().min_rate_pct = Some(DEFAULT_MIN_RATE_PCT);
+        assert_eq!(RateLimiterConfig::from_rate_limiter(&rl), Some(rlconf));
+
+        // A zero target disables the tuning.
+        let config = AdaptiveConfig {
+            target_latency_us: 0,
+            min_rate_pct: Some(50),
+        };
+        assert!(config.adaptive_rate().unwrap().is_none());
+
+        // The smallest rate must be a percentage.
+        for min_rate_pct in &[0, 101] {
+            let config = AdaptiveConfig {
+                target_latency_us: 5000,
+                min_rate_pct: Some(*min_rate_pct),
+            };
+            assert_eq!(
+                config.adaptive_rate().unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn test_rate_limiter_stats() {
+        let mut rl = RateLimiter::new(1000, 0, 500, 0, 0, 0).unwrap();
+        assert!(rl.consume(1000, rate_limiter::TokenType::Bytes));
+        assert!(!rl.consume(1, rate_limiter::TokenType::Bytes));
+
+        let stats = RateLimiterStats::from(&rl);
+        let bandwidth = stats.bandwidth.unwrap();
+        assert_eq!(bandwidth.size, 1000);
+        assert_eq!(bandwidth.one_time_burst, 0);
+        assert_eq!(bandwidth.refill_time, 500);
+        assert_eq!(bandwidth.refill_rate, 2000);
+        assert!(bandwidth.budget < 1000);
+        assert!(stats.ops.is_none());
+        assert!(stats.blocked);
+        assert_eq!(stats.throttled_count, 1);
+
+        assert_eq!(
+            RateLimiterStats::from(&RateLimiter::default()),
+            RateLimiterStats::default()
+        );
     }
 
     #[test]
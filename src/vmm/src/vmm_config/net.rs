@@ -3,20 +3,33 @@
 
 use std::convert::TryInto;
 use std::fmt;
+use std::net::Ipv4Addr;
 use std::result;
 use std::sync::{Arc, Mutex};
 
-use super::RateLimiterConfig;
+use super::{RateLimiterConfig, RateLimiterStats};
 use crate::Error as VmmError;
+pub use devices::virtio::net::device::{NetDeviceStats, NetQueueStats};
+use devices::virtio::net::vlan::is_valid_vlan_id;
 use devices::virtio::net::TapError;
 use devices::virtio::Net;
+use dumbo::dhcp::{ConfigError as DhcpConfigError, DhcpServer, DhcpServerConfig};
 use utils::net::mac::MacAddr;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+/// The live state of the rate limiters of a net device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct NetRateLimiterStats {
+    /// The state of the rate limiter for received packages.
+    pub rx_rate_limiter: RateLimiterStats,
+    /// The state of the rate limiter for transmitted packages.
+    pub tx_rate_limiter: RateLimiterStats,
+}
 
 /// This struct represents the strongly typed equivalent of the json body from net iface
 /// related requests.
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct NetworkInterfaceConfig {
     /// ID of the guest network interface.
@@ -36,6 +49,17 @@ pub struct NetworkInterfaceConfig {
     /// same address are intercepted by the device model, and do not reach
     /// the associated TAP device.
     pub allow_mmds_requests: bool,
+    /// If this field is set, the frames sent by the guest are tagged with this 802.1Q VLAN ID
+    /// before reaching the TAP device, and only the frames tagged with it are delivered to the
+    /// guest, after removing the tag.
+    pub vlan_id: Option<u16>,
+    /// If this field is set, the device model answers the DHCP requests sent by the guest
+    /// through this interface, handing out addresses from the configured pool. These requests
+    /// do not reach the associated TAP device.
+    pub dhcp: Option<NetworkInterfaceDhcpConfig>,
+    /// The rate limiter group whose budget the received and transmitted packages share with the
+    /// other devices attached to it, on top of the RX and TX rate limiters.
+    pub rate_limiter_group: Option<String>,
 }
 
 // Serde does not allow specifying a default value for a field
@@ -45,6 +69,78 @@ fn default_allow_mmds_requests() -> bool {
     false
 }
 
+/// The configuration of the DHCP server of a network interface.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkInterfaceDhcpConfig {
+    /// The first address handed out to the guest.
+    pub pool_start: Ipv4Addr,
+    /// The last address handed out to the guest.
+    pub pool_end: Ipv4Addr,
+    /// The subnet mask of the guest.
+    pub subnet_mask: Ipv4Addr,
+    /// The default gateway of the guest. The DHCP server also uses this address.
+    pub gateway: Ipv4Addr,
+    /// The DNS servers advertised to the guest.
+    #[serde(default)]
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// The MTU advertised to the guest.
+    pub mtu: Option<u16>,
+    /// The lease time of the addresses, in seconds.
+    #[serde(default = "default_lease_time_s")]
+    pub lease_time_s: u32,
+}
+
+fn default_lease_time_s() -> u32 {
+    86400
+}
+
+impl From<NetworkInterfaceDhcpConfig> for DhcpServerConfig {
+    fn from(cfg: NetworkInterfaceDhcpConfig) -> Self {
+        DhcpServerConfig {
+            pool_start: cfg.pool_start,
+            pool_end: cfg.pool_end,
+            subnet_mask: cfg.subnet_mask,
+            gateway: cfg.gateway,
+            dns_servers: cfg.dns_servers,
+            mtu: cfg.mtu,
+            lease_time_s: cfg.lease_time_s,
+        }
+    }
+}
+
+impl From<&DhcpServerConfig> for NetworkInterfaceDhcpConfig {
+    fn from(cfg: &DhcpServerConfig) -> Self {
+        NetworkInterfaceDhcpConfig {
+            pool_start: cfg.pool_start,
+            pool_end: cfg.pool_end,
+            subnet_mask: cfg.subnet_mask,
+            gateway: cfg.gateway,
+            dns_servers: cfg.dns_servers.clone(),
+            mtu: cfg.mtu,
+            lease_time_s: cfg.lease_time_s,
+        }
+    }
+}
+
+impl From<&Net> for NetworkInterfaceConfig {
+    fn from(net: &Net) -> Self {
+        NetworkInterfaceConfig {
+            iface_id: net.id().clone(),
+            host_dev_name: net.iface_name(),
+            guest_mac: net.guest_mac().copied(),
+            rx_rate_limiter: RateLimiterConfig::from_rate_limiter(net.rx_rate_limiter()),
+            tx_rate_limiter: RateLimiterConfig::from_rate_limiter(net.tx_rate_limiter()),
+            allow_mmds_requests: net.mmds_enabled(),
+            vlan_id: net.vlan_id(),
+            dhcp: net
+                .dhcp_server()
+                .map(|server| NetworkInterfaceDhcpConfig::from(server.config())),
+            rate_limiter_group: net.rx_rate_limiter().group_id().map(str::to_string),
+        }
+    }
+}
+
 /// The data fed into a network iface update request. Currently, only the RX and TX rate limiters
 /// can be updated.
 #[derive(Debug, Deserialize, PartialEq, Clone)]
@@ -71,8 +167,16 @@ pub enum NetworkInterfaceError {
     GuestMacAddressInUse(String),
     /// Error during interface update (patch).
     DeviceUpdate(VmmError),
+    /// Error while retrieving the interface statistics.
+    DeviceStats(VmmError),
     /// Cannot open/create tap device.
     OpenTap(TapError),
+    /// The VLAN ID is not in the valid range.
+    InvalidVlanId(u16),
+    /// The DHCP server configuration is invalid.
+    InvalidDhcpConfig(DhcpConfigError),
+    /// The rate limiter group does not exist.
+    RateLimiterGroupNotFound(String),
 }
 
 impl fmt::Display for NetworkInterfaceError {
@@ -87,6 +191,7 @@ impl fmt::Display for NetworkInterfaceError {
                 format!("The guest MAC address {} is already in use.", mac_addr)
             ),
             DeviceUpdate(e) => write!(f, "Error during interface update (patch): {}", e),
+            DeviceStats(e) => write!(f, "Error retrieving the interface statistics: {}", e),
             OpenTap(e) => {
                 // We are propagating the Tap Error. This error can contain
                 // imbricated quotes which would result in an invalid json.
@@ -100,6 +205,15 @@ impl fmt::Display for NetworkInterfaceError {
                     tap_err
                 )
             }
+            InvalidVlanId(vlan_id) => write!(
+                f,
+                "Invalid VLAN ID {}. The VLAN ID must be between 1 and 4094.",
+                vlan_id
+            ),
+            InvalidDhcpConfig(e) => write!(f, "Invalid DHCP server configuration: {}", e),
+            RateLimiterGroupNotFound(group_id) => {
+                write!(f, "The rate limiter group {} does not exist.", group_id)
+            }
         }
     }
 }
@@ -131,6 +245,14 @@ impl NetBuilder {
         self.net_devices.iter_mut()
     }
 
+    /// Returns the current configuration of the network devices.
+    pub fn configs(&self) -> Vec<NetworkInterfaceConfig> {
+        self.net_devices
+            .iter()
+            .map(|net| NetworkInterfaceConfig::from(&*net.lock().expect("Poisoned lock")))
+            .collect()
+    }
+
     /// Builds a network device based on a network interface config. Keeps a device reference
     /// in the builder's internal list.
     pub fn build(&mut self, netif_config: NetworkInterfaceConfig) -> Result<Arc<Mutex<Net>>> {
@@ -168,6 +290,18 @@ impl NetBuilder {
 
     /// Creates a Net device from a NetworkInterfaceConfig.
     pub fn create_net(cfg: NetworkInterfaceConfig) -> Result<Net> {
+        if let Some(vlan_id) = cfg.vlan_id {
+            if !is_valid_vlan_id(vlan_id) {
+                return Err(NetworkInterfaceError::InvalidVlanId(vlan_id));
+            }
+        }
+
+        let dhcp_server = cfg
+            .dhcp
+            .map(|dhcp| DhcpServer::new(DhcpServerConfig::from(dhcp)))
+            .transpose()
+            .map_err(NetworkInterfaceError::InvalidDhcpConfig)?;
+
         let rx_rate_limiter = cfg
             .rx_rate_limiter
             .map(super::RateLimiterConfig::try_into)
@@ -180,15 +314,19 @@ impl NetBuilder {
             .map_err(NetworkInterfaceError::CreateRateLimiter)?;
 
         // Create and return the Net device
-        devices::virtio::net::Net::new_with_tap(
+        let mut net = devices::virtio::net::Net::new_with_tap(
             cfg.iface_id,
             cfg.host_dev_name.clone(),
             cfg.guest_mac.as_ref(),
             rx_rate_limiter.unwrap_or_default(),
             tx_rate_limiter.unwrap_or_default(),
             cfg.allow_mmds_requests,
+            cfg.vlan_id,
         )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        net.set_dhcp_server(dhcp_server);
+
+        Ok(net)
     }
 }
 
@@ -216,6 +354,9 @@ mod tests {
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
             allow_mmds_requests: false,
+            vlan_id: None,
+            dhcp: None,
+            rate_limiter_group: None,
         }
     }
 
@@ -228,6 +369,9 @@ mod tests {
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
                 allow_mmds_requests: self.allow_mmds_requests,
+                vlan_id: self.vlan_id,
+                dhcp: self.dhcp.clone(),
+                rate_limiter_group: self.rate_limiter_group.clone(),
             }
         }
     }
@@ -326,6 +470,61 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_insert_vlan() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif = create_netif("id_vlan", "dev_vlan", "01:23:45:67:89:0c");
+        netif.vlan_id = Some(4095);
+        assert_eq!(
+            net_builder.build(netif.clone()).err().unwrap().to_string(),
+            NetworkInterfaceError::InvalidVlanId(4095).to_string()
+        );
+        assert!(net_builder.is_empty());
+
+        netif.vlan_id = Some(100);
+        let net = net_builder.build(netif).unwrap();
+        assert_eq!(net.lock().unwrap().vlan_id(), Some(100));
+    }
+
+    #[test]
+    fn test_insert_dhcp() {
+        let mut net_builder = NetBuilder::new();
+
+        let mut netif = create_netif("id_dhcp", "dev_dhcp", "01:23:45:67:89:0d");
+        let mut dhcp: NetworkInterfaceDhcpConfig = serde_json::from_str(
+            r#"{
+                "pool_start": "10.0.0.2",
+                "pool_end": "10.0.0.254",
+                "subnet_mask": "255.255.255.0",
+                "gateway": "10.0.0.1",
+                "dns_servers": ["10.0.0.1"]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(dhcp.mtu, None);
+        assert_eq!(dhcp.lease_time_s, default_lease_time_s());
+
+        dhcp.gateway = Ipv4Addr::new(10, 0, 0, 2);
+        netif.dhcp = Some(dhcp.clone());
+        assert_eq!(
+            net_builder.build(netif.clone()).err().unwrap().to_string(),
+            NetworkInterfaceError::InvalidDhcpConfig(DhcpConfigError::GatewayInPool(dhcp.gateway))
+                .to_string()
+        );
+        assert!(net_builder.is_empty());
+
+        dhcp.gateway = Ipv4Addr::new(10, 0, 0, 1);
+        netif.dhcp = Some(dhcp);
+        let net = net_builder.build(netif.clone()).unwrap();
+        assert_eq!(
+            net.lock().unwrap().dhcp_server().unwrap().config().gateway,
+            Ipv4Addr::new(10, 0, 0, 1)
+        );
+        // The default rate limiters limit nothing, so they are not part of the configuration.
+        assert_eq!(net_builder.configs(), vec![netif]);
+    }
+
     #[test]
     fn test_error_display() {
         // FIXME: use macro
@@ -340,11 +539,31 @@ mod tests {
             NetworkInterfaceError::DeviceUpdate(VmmError::VcpuExit),
             NetworkInterfaceError::DeviceUpdate(VmmError::VcpuExit)
         );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::DeviceStats(VmmError::VcpuExit),
+            NetworkInterfaceError::DeviceStats(VmmError::VcpuExit)
+        );
         let _ = format!(
             "{}{:?}",
             NetworkInterfaceError::OpenTap(TapError::InvalidIfname),
             NetworkInterfaceError::OpenTap(TapError::InvalidIfname)
         );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidVlanId(0),
+            NetworkInterfaceError::InvalidVlanId(0)
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::InvalidDhcpConfig(DhcpConfigError::InvalidLeaseTime),
+            NetworkInterfaceError::InvalidDhcpConfig(DhcpConfigError::InvalidLeaseTime)
+        );
+        let _ = format!(
+            "{}{:?}",
+            NetworkInterfaceError::RateLimiterGroupNotFound("net".to_string()),
+            NetworkInterfaceError::RateLimiterGroupNotFound("net".to_string())
+        );
     }
 
     #[test]
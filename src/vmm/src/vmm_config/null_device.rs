@@ -0,0 +1,160 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::result;
+use std::sync::{Arc, Mutex};
+
+pub use devices::virtio::null::NullDeviceType;
+use devices::virtio::NullDevice;
+
+use serde::Deserialize;
+
+/// This struct represents the strongly typed equivalent of the json body from null device
+/// related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NullDeviceConfig {
+    /// ID of the null device.
+    pub device_id: String,
+    /// The class of the device exposed to the guest.
+    pub device_type: NullDeviceType,
+}
+
+/// Errors associated with `NullDeviceConfig`.
+#[derive(Debug)]
+pub enum NullDeviceConfigError {
+    /// Could not create the null device.
+    CreateNullDevice(devices::virtio::null::Error),
+}
+
+impl fmt::Display for NullDeviceConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::NullDeviceConfigError::*;
+        match self {
+            CreateNullDevice(e) => write!(f, "Could not create the null device: {:?}", e),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, NullDeviceConfigError>;
+
+/// Builder for a list of null devices.
+#[derive(Default)]
+pub struct NullDeviceBuilder {
+    null_devices: Vec<Arc<Mutex<NullDevice>>>,
+}
+
+impl NullDeviceBuilder {
+    /// Creates an empty list of null devices.
+    pub fn new() -> Self {
+        NullDeviceBuilder {
+            null_devices: Vec::new(),
+        }
+    }
+
+    /// Returns a immutable iterator over the null devices.
+    pub fn iter(&self) -> ::std::slice::Iter<Arc<Mutex<NullDevice>>> {
+        self.null_devices.iter()
+    }
+
+    /// Builds a null device based on a null device config. Keeps a device reference in the
+    /// builder's internal list.
+    pub fn build(&mut self, config: NullDeviceConfig) -> Result<Arc<Mutex<NullDevice>>> {
+        // If this is an update, just remove the old one.
+        if let Some(index) = self
+            .null_devices
+            .iter()
+            .position(|device| device.lock().expect("Poisoned lock").id() == &config.device_id)
+        {
+            self.null_devices.remove(index);
+        }
+
+        let device = Arc::new(Mutex::new(
+            NullDevice::new(config.device_id, config.device_type)
+                .map_err(NullDeviceConfigError::CreateNullDevice)?,
+        ));
+        self.null_devices.push(device.clone());
+
+        Ok(device)
+    }
+
+    /// Returns the configs of the null devices in the list.
+    pub fn configs(&self) -> Vec<NullDeviceConfig> {
+        self.null_devices
+            .iter()
+            .map(|device| {
+                let device = device.lock().expect("Poisoned lock");
+                NullDeviceConfig {
+                    device_id: device.id().clone(),
+                    device_type: device.null_device_type(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_config(device_id: &str, device_type: NullDeviceType) -> NullDeviceConfig {
+        NullDeviceConfig {
+            device_id: device_id.to_string(),
+            device_type,
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let mut builder = NullDeviceBuilder::new();
+        assert_eq!(builder.iter().count(), 0);
+
+        builder
+            .build(create_config("gpu0", NullDeviceType::Gpu))
+            .unwrap();
+        builder
+            .build(create_config("snd0", NullDeviceType::Sound))
+            .unwrap();
+        assert_eq!(
+            builder.configs(),
+            vec![
+                create_config("gpu0", NullDeviceType::Gpu),
+                create_config("snd0", NullDeviceType::Sound)
+            ]
+        );
+
+        // Updating a device replaces it.
+        builder
+            .build(create_config("gpu0", NullDeviceType::Input))
+            .unwrap();
+        assert_eq!(builder.iter().count(), 2);
+        assert_eq!(
+            builder.configs(),
+            vec![
+                create_config("snd0", NullDeviceType::Sound),
+                create_config("gpu0", NullDeviceType::Input)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_deserialization() {
+        let config: NullDeviceConfig =
+            serde_json::from_str(r#"{"device_id": "snd0", "device_type": "Sound"}"#).unwrap();
+        assert_eq!(config, create_config("snd0", NullDeviceType::Sound));
+
+        assert!(serde_json::from_str::<NullDeviceConfig>(
+            r#"{"device_id": "foo", "device_type": "Net"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err = NullDeviceConfigError::CreateNullDevice(devices::virtio::null::Error::EventFd(
+            std::io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::result;
+use std::sync::{Arc, Mutex};
+
+use devices::virtio::pmem::{Error as PmemError, Pmem};
+
+use serde::{Deserialize, Serialize};
+
+/// This struct represents the strongly typed equivalent of the json body from persistent
+/// memory related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PmemConfig {
+    /// ID of the persistent memory device.
+    pub pmem_id: String,
+    /// Path of the file mapped into the guest.
+    pub path_on_host: String,
+    /// If set to true, the guest cannot write to the mapping.
+    #[serde(default)]
+    pub is_read_only: bool,
+}
+
+/// Errors associated with `PmemConfig`.
+#[derive(Debug)]
+pub enum PmemConfigError {
+    /// Could not create the persistent memory device.
+    CreatePmem(PmemError),
+}
+
+impl fmt::Display for PmemConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PmemConfigError::*;
+        match self {
+            CreatePmem(e) => write!(f, "Could not create the persistent memory device: {:?}", e),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, PmemConfigError>;
+
+/// Builder for a list of persistent memory devices.
+#[derive(Default)]
+pub struct PmemBuilder {
+    pmem_devices: Vec<Arc<Mutex<Pmem>>>,
+}
+
+impl PmemBuilder {
+    /// Creates an empty list of persistent memory devices.
+    pub fn new() -> Self {
+        PmemBuilder {
+            pmem_devices: Vec::new(),
+        }
+    }
+
+    /// Returns a immutable iterator over the persistent memory devices.
+    pub fn iter(&self) -> ::std::slice::Iter<Arc<Mutex<Pmem>>> {
+        self.pmem_devices.iter()
+    }
+
+    /// Builds a persistent memory device based on a config, mapping its backing file. Keeps a
+    /// device reference in the builder's internal list.
+    pub fn build(&mut self, config: PmemConfig) -> Result<Arc<Mutex<Pmem>>> {
+        let device = Arc::new(Mutex::new(
+            Pmem::new(config.pmem_id, config.path_on_host, config.is_read_only)
+                .map_err(PmemConfigError::CreatePmem)?,
+        ));
+
+        // If this is an update, the new device replaces the old one.
+        let id = device.lock().expect("Poisoned lock").id().clone();
+        match self
+            .pmem_devices
+            .iter()
+            .position(|pmem| pmem.lock().expect("Poisoned lock").id() == &id)
+        {
+            Some(index) => self.pmem_devices[index] = device.clone(),
+            None => self.pmem_devices.push(device.clone()),
+        }
+
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use devices::virtio::pmem::PMEM_ALIGNMENT;
+    use utils::tempfile::TempFile;
+
+    pub(crate) fn backing_file(size: u64) -> TempFile {
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(size).unwrap();
+        file
+    }
+
+    pub(crate) fn default_config(pmem_id: &str, file: &TempFile) -> PmemConfig {
+        PmemConfig {
+            pmem_id: pmem_id.to_string(),
+            path_on_host: file.as_path().to_str().unwrap().to_string(),
+            is_read_only: false,
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let mut builder = PmemBuilder::new();
+        assert_eq!(builder.iter().count(), 0);
+
+        let file0 = backing_file(PMEM_ALIGNMENT);
+        builder.build(default_config("pmem0", &file0)).unwrap();
+        assert_eq!(builder.iter().count(), 1);
+
+        // The size of the backing file must be aligned.
+        let file1 = backing_file(PMEM_ALIGNMENT + 0x1000);
+        match builder.build(default_config("pmem1", &file1)) {
+            Err(PmemConfigError::CreatePmem(PmemError::BackingFileSize(size))) => {
+                assert_eq!(size, PMEM_ALIGNMENT + 0x1000)
+            }
+            _ => panic!("Unexpected result."),
+        }
+        assert_eq!(builder.iter().count(), 1);
+
+        // Updating a device replaces it.
+        let mut config = default_config("pmem0", &file0);
+        config.is_read_only = true;
+        builder.build(config).unwrap();
+        assert_eq!(builder.iter().count(), 1);
+        assert!(builder
+            .iter()
+            .next()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .is_read_only());
+
+        let file1 = backing_file(2 * PMEM_ALIGNMENT);
+        builder.build(default_config("pmem1", &file1)).unwrap();
+        assert_eq!(builder.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: PmemConfig =
+            serde_json::from_str(r#"{"pmem_id": "pmem0", "path_on_host": "/tmp/rootfs.img"}"#)
+                .unwrap();
+        assert!(!config.is_read_only);
+
+        let config: PmemConfig = serde_json::from_str(
+            r#"{"pmem_id": "pmem0", "path_on_host": "/tmp/rootfs.img", "is_read_only": true}"#,
+        )
+        .unwrap();
+        assert!(config.is_read_only);
+
+        assert!(serde_json::from_str::<PmemConfig>(
+            r#"{"pmem_id": "pmem0", "path_on_host": "/tmp/rootfs.img", "is_root_device": true}"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = PmemConfigError::CreatePmem(PmemError::BackingFileSize(0));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::result;
+use std::sync::Arc;
+
+use super::TokenBucketConfig;
+use rate_limiter::group::RateLimiterGroup;
+use rate_limiter::TokenBucket;
+
+use serde::{Deserialize, Serialize};
+
+/// This struct represents the strongly typed equivalent of the json body from rate limiter
+/// group related requests. The drives and network interfaces attached to the group share its
+/// budget, on top of the budget of their own rate limiters.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimiterGroupConfig {
+    /// Unique identifier of the group.
+    pub group_id: String,
+    /// Data used to initialize the bandwidth bucket shared by the group.
+    pub bandwidth: Option<TokenBucketConfig>,
+    /// Data used to initialize the ops bucket shared by the group.
+    pub ops: Option<TokenBucketConfig>,
+}
+
+/// Errors associated with `RateLimiterGroupConfig`.
+#[derive(Debug, PartialEq)]
+pub enum RateLimiterGroupError {
+    /// The group has devices attached, so it cannot be replaced.
+    GroupInUse(String),
+    /// The group does not limit anything.
+    NoTokenBucket(String),
+}
+
+impl fmt::Display for RateLimiterGroupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::RateLimiterGroupError::*;
+        match self {
+            GroupInUse(group_id) => write!(
+                f,
+                "The rate limiter group {} has devices attached, it cannot be replaced.",
+                group_id
+            ),
+            NoTokenBucket(group_id) => write!(
+                f,
+                "The rate limiter group {} must have a bandwidth or an ops token bucket.",
+                group_id
+            ),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, RateLimiterGroupError>;
+
+fn token_bucket(config: Option<&TokenBucketConfig>) -> Option<TokenBucket> {
+    config.and_then(|config| {
+        TokenBucket::new(
+            config.size,
+            config.one_time_burst.unwrap_or(0),
+            config.refill_time,
+        )
+    })
+}
+
+/// The rate limiter groups the drives and network interfaces can be attached to.
+#[derive(Default)]
+pub struct RateLimiterGroups {
+    groups: BTreeMap<String, (RateLimiterGroupConfig, Arc<RateLimiterGroup>)>,
+}
+
+impl RateLimiterGroups {
+    /// Creates the group described by `config`, or replaces the group with the same ID as long
+    /// as no device is attached to it.
+    pub fn insert(&mut self, config: RateLimiterGroupConfig) -> Result<()> {
+        if let Some((_, group)) = self.groups.get(&config.group_id) {
+            if Arc::strong_count(group) > 1 {
+                return Err(RateLimiterGroupError::GroupInUse(config.group_id));
+            }
+        }
+
+        let bandwidth = token_bucket(config.bandwidth.as_ref());
+        let ops = token_bucket(config.ops.as_ref());
+        if bandwidth.is_none() && ops.is_none() {
+            return Err(RateLimiterGroupError::NoTokenBucket(config.group_id));
+        }
+        let group = Arc::new(RateLimiterGroup::new(
+            config.group_id.clone(),
+            bandwidth,
+            ops,
+        ));
+        self.groups.insert(config.group_id.clone(), (config, group));
+        Ok(())
+    }
+
+    /// Returns the group `group_id`, if it exists.
+    pub fn get(&self, group_id: &str) -> Option<&Arc<RateLimiterGroup>> {
+        self.groups.get(group_id).map(|(_, group)| group)
+    }
+
+    /// Returns the configuration of the groups, sorted by ID.
+    pub fn configs(&self) -> Vec<RateLimiterGroupConfig> {
+        self.groups
+            .values()
+            .map(|(config, _)| config.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_config(group_id: &str, size: u64) -> RateLimiterGroupConfig {
+        RateLimiterGroupConfig {
+            group_id: group_id.to_string(),
+            bandwidth: Some(TokenBucketConfig {
+                size,
+                one_time_burst: None,
+                refill_time: 100,
+            }),
+            ops: None,
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut groups = RateLimiterGroups::default();
+        assert!(groups.get("disks").is_none());
+
+        groups.insert(create_config("net", 1000)).unwrap();
+        groups.insert(create_config("disks", 1000)).unwrap();
+        assert_eq!(groups.get("disks").unwrap().id(), "disks");
+        assert_eq!(
+            groups.configs(),
+            vec![create_config("disks", 1000), create_config("net", 1000)]
+        );
+
+        // A group without devices attached can be replaced.
+        groups.insert(create_config("disks", 2000)).unwrap();
+        assert_eq!(groups.configs()[0], create_config("disks", 2000));
+
+        let _member = RateLimiterGroup::attach(groups.get("disks").unwrap());
+        assert_eq!(
+            groups.insert(create_config("disks", 3000)),
+            Err(RateLimiterGroupError::GroupInUse("disks".to_string()))
+        );
+
+        let mut config = create_config("empty", 0);
+        assert_eq!(
+            groups.insert(config.clone()),
+            Err(RateLimiterGroupError::NoTokenBucket("empty".to_string()))
+        );
+        config.bandwidth = None;
+        assert_eq!(
+            groups.insert(config),
+            Err(RateLimiterGroupError::NoTokenBucket("empty".to_string()))
+        );
+        assert_eq!(groups.configs().len(), 2);
+    }
+
+    #[test]
+    fn test_error_messages() {
+        assert_eq!(
+            RateLimiterGroupError::GroupInUse("disks".to_string()).to_string(),
+            "The rate limiter group disks has devices attached, it cannot be replaced."
+        );
+        assert_eq!(
+            RateLimiterGroupError::NoTokenBucket("disks".to_string()).to_string(),
+            "The rate limiter group disks must have a bandwidth or an ops token bucket."
+        );
+    }
+
+    #[test]
+    fn test_config_deserialization() {
+        let config: RateLimiterGroupConfig = serde_json::from_str(
+            r#"{"group_id": "disks", "bandwidth": {"size": 1000, "refill_time": 100}}"#,
+        )
+        .unwrap();
+        assert_eq!(config, create_config("disks", 1000));
+
+        assert!(serde_json::from_str::<RateLimiterGroupConfig>(
+            r#"{"group_id": "disks", "drives": []}"#
+        )
+        .is_err());
+    }
+}
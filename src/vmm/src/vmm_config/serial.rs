@@ -0,0 +1,329 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::result;
+
+use devices::legacy::ReadableFd;
+
+use serde::{Deserialize, Serialize};
+
+/// The serial ports which can be bound to a host backend. The first one, COM1, is always bound
+/// to the standard input and output of Firecracker.
+#[cfg(target_arch = "x86_64")]
+pub const SERIAL_PORT_IDS: [&str; 3] = ["com2", "com3", "com4"];
+/// The serial ports which can be bound to a host backend. There are no additional serial ports
+/// on aarch64.
+#[cfg(target_arch = "aarch64")]
+pub const SERIAL_PORT_IDS: [&str; 0] = [];
+
+/// Errors associated with the configuration of the serial ports.
+#[derive(Debug)]
+pub enum SerialConfigError {
+    /// A port must be backed by exactly one of a file, a pipe or a unix domain socket.
+    InvalidBackend(String),
+    /// The port does not exist or cannot be configured.
+    InvalidPortId(String),
+}
+
+impl fmt::Display for SerialConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SerialConfigError::*;
+        match self {
+            InvalidBackend(id) => write!(
+                f,
+                "The serial port {} must be backed by exactly one of file_path, pipe_path and \
+                 uds_path.",
+                id
+            ),
+            InvalidPortId(id) => write!(
+                f,
+                "Invalid serial port: {}. The ports which can be configured are: {:?}.",
+                id, SERIAL_PORT_IDS
+            ),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, SerialConfigError>;
+
+/// The host ends of a serial port.
+pub type SerialPortBackend = (Option<Box<dyn ReadableFd + Send>>, Box<dyn Write + Send>);
+
+// The input end of a unix domain socket backing a serial port. Reading it doesn't block, while
+// the guest output still waits for the host to read it, as it does on the standard output.
+struct SocketInput(UnixStream);
+
+impl Read for SocketInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Safe because the buffer is valid for `buf.len()` bytes and we check the return value.
+        let ret = unsafe {
+            libc::recv(
+                self.0.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                libc::MSG_DONTWAIT,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+}
+
+impl AsRawFd for SocketInput {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl ReadableFd for SocketInput {}
+
+/// This struct represents the strongly typed equivalent of the json body from the serial port
+/// related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SerialPortConfig {
+    /// The serial port, one of `com2`, `com3` and `com4`.
+    pub port_id: String,
+    /// Path of the file the guest output is appended to. The file is created if missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<String>,
+    /// Path of an existing named pipe the guest output is written to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipe_path: Option<String>,
+    /// Path of a unix domain socket, listened on by the host, the port connects to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uds_path: Option<String>,
+}
+
+impl SerialPortConfig {
+    fn validate(&self) -> Result<()> {
+        if !SERIAL_PORT_IDS.contains(&self.port_id.as_str()) {
+            return Err(SerialConfigError::InvalidPortId(self.port_id.clone()));
+        }
+        let backends = [&self.file_path, &self.pipe_path, &self.uds_path];
+        if backends.iter().filter(|path| path.is_some()).count() != 1 {
+            return Err(SerialConfigError::InvalidBackend(self.port_id.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Opens the host ends of the port: the input, only available on a unix domain socket, and
+    /// the output.
+    pub fn open(&self) -> io::Result<SerialPortBackend> {
+        match (&self.file_path, &self.pipe_path, &self.uds_path) {
+            (Some(path), None, None) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Ok((None, Box::new(file)))
+            }
+            (None, Some(path), None) => {
+                // Opening a named pipe for both reading and writing doesn't wait for a reader.
+                let pipe = OpenOptions::new().read(true).append(true).open(path)?;
+                Ok((None, Box::new(pipe)))
+            }
+            (None, None, Some(path)) => {
+                let stream = UnixStream::connect(path)?;
+                Ok((
+                    Some(Box::new(SocketInput(stream.try_clone()?))),
+                    Box::new(stream),
+                ))
+            }
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+}
+
+/// Builder for the list of serial ports bound to a host backend.
+#[derive(Default)]
+pub struct SerialPortsBuilder {
+    list: Vec<SerialPortConfig>,
+}
+
+impl SerialPortsBuilder {
+    /// Creates an empty list of serial ports.
+    pub fn new() -> Self {
+        SerialPortsBuilder { list: Vec::new() }
+    }
+
+    /// Returns the serial port configurations.
+    pub fn configs(&self) -> &[SerialPortConfig] {
+        self.list.as_slice()
+    }
+
+    /// Inserts a serial port configuration in the list.
+    /// If the port is already configured, the new configuration replaces the old one.
+    pub fn insert(&mut self, config: SerialPortConfig) -> Result<()> {
+        config.validate()?;
+
+        match self
+            .list
+            .iter()
+            .position(|port| port.port_id == config.port_id)
+        {
+            Some(index) => self.list[index] = config,
+            None => self.list.push(config),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use utils::tempfile::TempFile;
+
+    pub(crate) fn file_port(port_id: &str, file: &TempFile) -> SerialPortConfig {
+        SerialPortConfig {
+            port_id: port_id.to_string(),
+            file_path: Some(file.as_path().to_str().unwrap().to_string()),
+            pipe_path: None,
+            uds_path: None,
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_insert() {
+        let mut builder = SerialPortsBuilder::new();
+        assert_eq!(builder.configs().len(), 0);
+
+        let file = TempFile::new().unwrap();
+        builder.insert(file_port("com2", &file)).unwrap();
+        assert_eq!(builder.configs().len(), 1);
+
+        // Updating a port replaces its configuration.
+        let mut config = file_port("com2", &file);
+        config.file_path = None;
+        config.uds_path = Some("/tmp/com2.sock".to_string());
+        builder.insert(config.clone()).unwrap();
+        assert_eq!(builder.configs(), &[config]);
+
+        builder.insert(file_port("com4", &file)).unwrap();
+        assert_eq!(builder.configs().len(), 2);
+
+        // COM1 is bound to the standard input and output.
+        match builder.insert(file_port("com1", &file)) {
+            Err(SerialConfigError::InvalidPortId(id)) => assert_eq!(id, "com1"),
+            _ => panic!("Unexpected result."),
+        }
+        assert_eq!(builder.configs().len(), 2);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_validate() {
+        let file = TempFile::new().unwrap();
+        for port_id in SERIAL_PORT_IDS.iter() {
+            assert!(file_port(port_id, &file).validate().is_ok());
+        }
+        for port_id in &["", "com5", "COM2"] {
+            match file_port(port_id, &file).validate() {
+                Err(SerialConfigError::InvalidPortId(_)) => (),
+                _ => panic!("Unexpected result."),
+            }
+        }
+
+        let mut config = file_port("com3", &file);
+        config.pipe_path = Some("/tmp/com3.fifo".to_string());
+        match config.validate() {
+            Err(SerialConfigError::InvalidBackend(id)) => assert_eq!(id, "com3"),
+            _ => panic!("Unexpected result."),
+        }
+
+        config.file_path = None;
+        config.pipe_path = None;
+        match config.validate() {
+            Err(SerialConfigError::InvalidBackend(id)) => assert_eq!(id, "com3"),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_open() {
+        // The output is appended to the file.
+        let file = TempFile::new().unwrap();
+        file.as_file().write_all(b"boot\n").unwrap();
+        let (input, mut out) = file_port("com2", &file).open().unwrap();
+        assert!(input.is_none());
+        out.write_all(b"log\n").unwrap();
+        let mut content = String::new();
+        std::fs::File::open(file.as_path())
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "boot\nlog\n");
+
+        // The pipe must exist.
+        let config = SerialPortConfig {
+            port_id: "com2".to_string(),
+            file_path: None,
+            pipe_path: Some("/invalid/path".to_string()),
+            uds_path: None,
+        };
+        assert!(config.open().is_err());
+
+        // The socket must be listened on.
+        let mut tmp_file = TempFile::new().unwrap();
+        tmp_file.remove().unwrap();
+        let path = tmp_file.as_path().to_str().unwrap().to_string();
+        let config = SerialPortConfig {
+            port_id: "com2".to_string(),
+            file_path: None,
+            pipe_path: None,
+            uds_path: Some(path.clone()),
+        };
+        assert!(config.open().is_err());
+
+        // The data flows both ways through the socket.
+        let listener = UnixListener::bind(&path).unwrap();
+        let (input, mut out) = config.open().unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        out.write_all(b"ping").unwrap();
+        let mut data = [0u8; 4];
+        stream.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"ping");
+        let mut input = input.unwrap();
+        assert_eq!(
+            input.read(&mut data).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        stream.write_all(b"pong").unwrap();
+        input.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"pong");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: SerialPortConfig =
+            serde_json::from_str(r#"{"port_id": "com2", "uds_path": "/tmp/com2.sock"}"#).unwrap();
+        assert_eq!(config.uds_path, Some("/tmp/com2.sock".to_string()));
+        assert!(config.file_path.is_none());
+        assert!(config.pipe_path.is_none());
+
+        assert!(serde_json::from_str::<SerialPortConfig>(
+            r#"{"port_id": "com2", "path": "/tmp/com2.log"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use super::SerialConfigError::*;
+
+        let err = InvalidBackend(String::from("com2"));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = InvalidPortId(String::from("com1"));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::result;
+use std::sync::{Arc, Mutex};
+
+pub use devices::virtio::fs::CacheMode;
+use devices::virtio::fs::{Error as SharedFsError, SharedFs, VhostUserFrontend};
+
+use serde::{Deserialize, Serialize};
+
+/// This struct represents the strongly typed equivalent of the json body from shared
+/// filesystem related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SharedFsConfig {
+    /// ID of the shared filesystem device.
+    pub fs_id: String,
+    /// The tag the guest mounts the filesystem with.
+    pub tag: String,
+    /// Path of the unix domain socket the vhost-user backend listens on.
+    pub socket_path: String,
+    /// The caching policy of the shared files.
+    #[serde(default)]
+    pub cache_mode: CacheMode,
+}
+
+/// Errors associated with `SharedFsConfig`.
+#[derive(Debug)]
+pub enum SharedFsConfigError {
+    /// Could not create the shared filesystem device.
+    CreateSharedFs(SharedFsError),
+    /// The tag is already used by another shared filesystem.
+    TagInUse(String),
+}
+
+impl fmt::Display for SharedFsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SharedFsConfigError::*;
+        match self {
+            CreateSharedFs(e) => write!(f, "Could not create the shared filesystem: {:?}", e),
+            TagInUse(tag) => write!(
+                f,
+                "The tag {} is already used by another shared filesystem.",
+                tag
+            ),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, SharedFsConfigError>;
+
+/// Builder for a list of shared filesystem devices.
+#[derive(Default)]
+pub struct SharedFsBuilder {
+    shared_fs_devices: Vec<Arc<Mutex<SharedFs>>>,
+}
+
+impl SharedFsBuilder {
+    /// Creates an empty list of shared filesystem devices.
+    pub fn new() -> Self {
+        SharedFsBuilder {
+            shared_fs_devices: Vec::new(),
+        }
+    }
+
+    /// Returns a immutable iterator over the shared filesystem devices.
+    pub fn iter(&self) -> ::std::slice::Iter<Arc<Mutex<SharedFs>>> {
+        self.shared_fs_devices.iter()
+    }
+
+    /// Builds a shared filesystem device based on a config, connecting it to its vhost-user
+    /// backend. Keeps a device reference in the builder's internal list.
+    pub fn build(&mut self, config: SharedFsConfig) -> Result<Arc<Mutex<SharedFs>>> {
+        let position = self
+            .shared_fs_devices
+            .iter()
+            .position(|device| device.lock().expect("Poisoned lock").id() == &config.fs_id);
+        if self.shared_fs_devices.iter().any(|device| {
+            let device = device.lock().expect("Poisoned lock");
+            device.tag() == &config.tag && device.id() != &config.fs_id
+        }) {
+            return Err(SharedFsConfigError::TagInUse(config.tag));
+        }
+
+        let backend = VhostUserFrontend::connect(&config.socket_path)
+            .map_err(SharedFsError::VhostUser)
+            .map_err(SharedFsConfigError::CreateSharedFs)?;
+        let device = Arc::new(Mutex::new(
+            SharedFs::new(config.fs_id, config.tag, config.cache_mode, backend)
+                .map_err(SharedFsConfigError::CreateSharedFs)?,
+        ));
+
+        // If this is an update, the new device replaces the old one.
+        match position {
+            Some(index) => self.shared_fs_devices[index] = device.clone(),
+            None => self.shared_fs_devices.push(device.clone()),
+        }
+
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::thread;
+
+    use super::*;
+    use utils::tempfile::TempFile;
+
+    /// Spawns a fake vhost-user backend listening on `socket_path`, which accepts a single
+    /// connection and answers the feature negotiation. The thread returns the connection.
+    pub(crate) fn spawn_backend(socket_path: &str) -> thread::JoinHandle<UnixStream> {
+        let listener = UnixListener::bind(socket_path).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // The reply to GET_FEATURES, carrying no feature.
+            let reply: [u32; 5] = [1, 0x5, 8, 0, 0];
+            for word in reply.iter() {
+                stream.write_all(&word.to_ne_bytes()).unwrap();
+            }
+            stream
+        })
+    }
+
+    pub(crate) fn default_config(fs_id: &str, tag: &str, socket_path: &str) -> SharedFsConfig {
+        SharedFsConfig {
+            fs_id: fs_id.to_string(),
+            tag: tag.to_string(),
+            socket_path: socket_path.to_string(),
+            cache_mode: CacheMode::default(),
+        }
+    }
+
+    fn socket_path() -> String {
+        let mut tmp_file = TempFile::new().unwrap();
+        tmp_file.remove().unwrap();
+        tmp_file.as_path().to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_build() {
+        let mut builder = SharedFsBuilder::new();
+        assert_eq!(builder.iter().count(), 0);
+
+        let path0 = socket_path();
+        let backend0 = spawn_backend(&path0);
+        builder
+            .build(default_config("fs0", "tag0", &path0))
+            .unwrap();
+        let _conn0 = backend0.join().unwrap();
+        assert_eq!(builder.iter().count(), 1);
+
+        // Another device cannot use the same tag.
+        match builder.build(default_config("fs1", "tag0", &path0)) {
+            Err(SharedFsConfigError::TagInUse(tag)) => assert_eq!(tag, "tag0"),
+            _ => panic!("Unexpected result."),
+        }
+
+        // The backend must be listening.
+        match builder.build(default_config("fs1", "tag1", &socket_path())) {
+            Err(SharedFsConfigError::CreateSharedFs(SharedFsError::VhostUser(_))) => (),
+            _ => panic!("Unexpected result."),
+        }
+        assert_eq!(builder.iter().count(), 1);
+
+        // Updating a device replaces it.
+        let path1 = socket_path();
+        let backend1 = spawn_backend(&path1);
+        let mut config = default_config("fs0", "tag0", &path1);
+        config.cache_mode = CacheMode::None;
+        builder.build(config).unwrap();
+        let _conn1 = backend1.join().unwrap();
+        assert_eq!(builder.iter().count(), 1);
+        assert_eq!(
+            builder.iter().next().unwrap().lock().unwrap().cache_mode(),
+            CacheMode::None
+        );
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: SharedFsConfig = serde_json::from_str(
+            r#"{"fs_id": "fs0", "tag": "myfs", "socket_path": "/tmp/fs.sock"}"#,
+        )
+        .unwrap();
+        assert_eq!(config, default_config("fs0", "myfs", "/tmp/fs.sock"));
+
+        let config: SharedFsConfig = serde_json::from_str(
+            r#"{"fs_id": "fs0", "tag": "myfs", "socket_path": "/tmp/fs.sock", "cache_mode": "Always"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.cache_mode, CacheMode::Always);
+
+        assert!(serde_json::from_str::<SharedFsConfig>(
+            r#"{"fs_id": "fs0", "tag": "myfs", "socket_path": "/tmp/fs.sock", "cache_mode": "Sometimes"}"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        use super::SharedFsConfigError::*;
+
+        let err = CreateSharedFs(SharedFsError::InvalidTag);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = TagInUse(String::from("myfs"));
+        let _ = format!("{}{:?}", err, err);
+    }
+}
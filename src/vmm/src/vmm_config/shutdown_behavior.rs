@@ -0,0 +1,56 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// The files the snapshot of the microVM is written to when Firecracker receives `SIGTERM`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShutdownSnapshotConfig {
+    /// Path to the file that will contain the microVM state.
+    pub snapshot_path: PathBuf,
+    /// Path to the file that will contain the guest memory.
+    pub mem_file_path: PathBuf,
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from the shutdown behavior related requests.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShutdownBehaviorConfig {
+    /// The full snapshot written before exiting on `SIGTERM`, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<ShutdownSnapshotConfig>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let config: ShutdownBehaviorConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.snapshot, None);
+
+        let config: ShutdownBehaviorConfig = serde_json::from_str(
+            r#"{"snapshot": {"snapshot_path": "/srv/vm.snap", "mem_file_path": "/srv/vm.mem"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.snapshot,
+            Some(ShutdownSnapshotConfig {
+                snapshot_path: PathBuf::from("/srv/vm.snap"),
+                mem_file_path: PathBuf::from("/srv/vm.mem"),
+            })
+        );
+
+        // Both files are required.
+        assert!(serde_json::from_str::<ShutdownBehaviorConfig>(
+            r#"{"snapshot": {"snapshot_path": "/srv/vm.snap"}}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<ShutdownBehaviorConfig>(r#"{"timeout_ms": 10}"#).is_err());
+    }
+}
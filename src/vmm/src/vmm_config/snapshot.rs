@@ -6,6 +6,13 @@
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::guest_clock::ClockPolicy;
+use crate::vmm_config::guest_panic::PanicAction;
+use crate::vmm_config::machine_config::{MemoryBackend, MemoryHints};
+use utils::net::mac::MacAddr;
 
 /// The snapshot type options that are available when
 /// creating a new snapshot.
@@ -33,8 +40,25 @@ pub struct CreateSnapshotParams {
     pub snapshot_type: SnapshotType,
     /// Path to the file that will contain the microVM state.
     pub snapshot_path: PathBuf,
-    /// Path to the file that will contain the guest memory.
+    /// Path to the file that will contain the guest memory. Left empty
+    /// for a single file snapshot.
+    #[serde(default)]
     pub mem_file_path: PathBuf,
+    /// When set to true for a full snapshot, the microVM state and the
+    /// guest memory are both saved to the file at `snapshot_path`, after a
+    /// header describing where they are.
+    #[serde(default)]
+    pub single_file: bool,
+    /// When set to true, the memory file is a page store which can be shared
+    /// with other snapshots, and only the pages it doesn't hold yet are
+    /// appended to it.
+    #[serde(default)]
+    pub dedup_pages: bool,
+    /// When set to true for a diff snapshot, the dirty pages are appended
+    /// to the memory file as records, rather than written at their offset in
+    /// a file the size of the guest memory.
+    #[serde(default)]
+    pub compact_diff: bool,
     /// Optional field for the microVM version. The default
     /// value is the current version.
     pub version: Option<String>,
@@ -45,9 +69,16 @@ pub struct CreateSnapshotParams {
 #[serde(deny_unknown_fields)]
 pub struct LoadSnapshotParams {
     /// Path to the file that contains the microVM state to be loaded.
+    /// A single file snapshot is recognized by its header.
     pub snapshot_path: PathBuf,
-    /// Path to the file that contains the guest memory to be loaded.
+    /// Path to the file that contains the guest memory to be loaded. Left
+    /// empty for a single file snapshot.
+    #[serde(default)]
     pub mem_file_path: PathBuf,
+    /// Paths to the compact diffs applied on top of the memory file, in
+    /// order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mem_diff_paths: Vec<PathBuf>,
     /// Setting this flag will enable KVM dirty page tracking and will
     /// allow taking subsequent incremental snapshots.
     #[serde(default)]
@@ -56,6 +87,157 @@ pub struct LoadSnapshotParams {
     /// is successful.
     #[serde(default)]
     pub resume_vm: bool,
+    /// Overrides the target size of the balloon, in MiB, with which the
+    /// microVM was snapshotted.
+    #[cfg(feature = "balloon")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balloon_amount_mib: Option<u32>,
+    /// When set to true, the balloon is fully deflated once the snapshot
+    /// is loaded.
+    #[cfg(feature = "balloon")]
+    #[serde(default)]
+    pub deflate_balloon: bool,
+    /// The kind of memory backing the restored guest memory. Defaults to
+    /// the memory file being mapped privately.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_backend: Option<MemoryBackend>,
+    /// The hints given to the host kernel about the restored guest memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mem_hints: Option<MemoryHints>,
+    /// The number of threads processing the I/O of the restored block and
+    /// network devices, besides the VMM thread.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_threads: Option<u8>,
+    /// Key/value pairs merged into the MMDS data store once the snapshot is
+    /// loaded, such as the parameters which differ between the clones of a
+    /// microVM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmds_data: Option<Map<String, Value>>,
+    /// The resources which the restored microVM gets of its own, when it is
+    /// a clone of the snapshotted one. Only set through `CloneSnapshotParams`.
+    #[serde(skip)]
+    pub clone: Option<CloneConfig>,
+}
+
+impl LoadSnapshotParams {
+    /// Returns the target size of the balloon to set once the snapshot is
+    /// loaded, if it is overridden.
+    #[cfg(feature = "balloon")]
+    pub fn balloon_target_mib(&self) -> Option<u32> {
+        if self.deflate_balloon {
+            Some(0)
+        } else {
+            self.balloon_amount_mib
+        }
+    }
+}
+
+/// A drive of a clone, backed by a file of its own.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CloneDriveConfig {
+    /// The identifier of the drive in the snapshot.
+    pub drive_id: String,
+    /// The host file backing the drive of the clone.
+    pub path_on_host: String,
+}
+
+/// A network interface of a clone, attached to a TAP device of its own.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CloneNetConfig {
+    /// The identifier of the network interface in the snapshot.
+    pub iface_id: String,
+    /// The TAP device the network interface of the clone is attached to.
+    pub host_dev_name: String,
+    /// The MAC address of the network interface of the clone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guest_mac: Option<MacAddr>,
+}
+
+/// The vsock device of a clone, reached through a socket of its own.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CloneVsockConfig {
+    /// The context identifier of the clone.
+    pub guest_cid: u32,
+    /// The socket the host reaches the clone through. The port mapping
+    /// sockets named after the socket of the snapshot are renamed after it.
+    pub uds_path: String,
+}
+
+/// The resources a clone gets in place of the ones of the snapshotted microVM.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CloneConfig {
+    /// The drives backed by other files.
+    pub drives: Vec<CloneDriveConfig>,
+    /// The network interfaces attached to other TAP devices.
+    pub network_interfaces: Vec<CloneNetConfig>,
+    /// The vsock device reached through another socket.
+    pub vsock: Option<CloneVsockConfig>,
+}
+
+/// Stores the configuration used for cloning a microVM from a template
+/// snapshot.
+///
+/// The memory file is mapped privately, so that the clones of the template
+/// share its pages until they write to them.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CloneSnapshotParams {
+    /// Path to the file that contains the microVM state of the template.
+    pub snapshot_path: PathBuf,
+    /// Path to the file that contains the guest memory of the template.
+    /// Left empty for a single file snapshot.
+    #[serde(default)]
+    pub mem_file_path: PathBuf,
+    /// Setting this flag will enable KVM dirty page tracking for the clone,
+    /// and will allow taking subsequent incremental snapshots of it.
+    #[serde(default)]
+    pub enable_diff_snapshots: bool,
+    /// When set to true, the clone is resumed once it is loaded.
+    #[serde(default)]
+    pub resume_vm: bool,
+    /// The drives backed by other files than the ones of the template.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drives: Vec<CloneDriveConfig>,
+    /// The network interfaces attached to other TAP devices than the ones
+    /// of the template.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network_interfaces: Vec<CloneNetConfig>,
+    /// The vsock device reached through another socket than the one of the
+    /// template.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsock: Option<CloneVsockConfig>,
+    /// Key/value pairs merged into the MMDS data store once the clone is
+    /// loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmds_data: Option<Map<String, Value>>,
+}
+
+impl From<CloneSnapshotParams> for LoadSnapshotParams {
+    fn from(params: CloneSnapshotParams) -> Self {
+        LoadSnapshotParams {
+            snapshot_path: params.snapshot_path,
+            mem_file_path: params.mem_file_path,
+            mem_diff_paths: Vec::new(),
+            enable_diff_snapshots: params.enable_diff_snapshots,
+            resume_vm: params.resume_vm,
+            #[cfg(feature = "balloon")]
+            balloon_amount_mib: None,
+            #[cfg(feature = "balloon")]
+            deflate_balloon: false,
+            mem_backend: None,
+            mem_hints: None,
+            io_threads: None,
+            mmds_data: params.mmds_data,
+            clone: Some(CloneConfig {
+                drives: params.drives,
+                network_interfaces: params.network_interfaces,
+                vsock: params.vsock,
+            }),
+        }
+    }
 }
 
 /// The microVM state options.
@@ -67,10 +249,19 @@ pub enum VmState {
     Resumed,
 }
 
-/// Keeps the microVM state necessary in the snapshotting context.
+/// Keeps the microVM state necessary in the snapshotting context, along with the
+/// action taken when the guest panics.
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Vm {
     /// The microVM state, which can be `paused` or `resumed`.
-    pub state: VmState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<VmState>,
+    /// The action taken by the VMM when the guest kernel panics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panic_action: Option<PanicAction>,
+    /// How the guest clock is kept right when the microVM resumes.
+    #[cfg(target_arch = "x86_64")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_policy: Option<ClockPolicy>,
 }
@@ -0,0 +1,78 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+/// Configuration of the in-process software TPM.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+pub struct SoftwareTpmConfig {
+    /// Path of the file holding the persistent TPM state.
+    pub state_path: String,
+}
+
+/// Configuration of the external swtpm process.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+pub struct SwtpmConfig {
+    /// Path of the swtpm data channel socket.
+    pub socket_path: String,
+}
+
+/// The TPM implementation executing the commands sent by the guest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+#[serde(rename_all = "snake_case")]
+pub enum TpmBackendConfig {
+    /// An in-process software TPM, keeping its persistent state in a host file.
+    Software(SoftwareTpmConfig),
+    /// An external swtpm process, reached through its unix domain socket.
+    Swtpm(SwtpmConfig),
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from the TPM related requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+#[serde(deny_unknown_fields)]
+pub struct TpmConfig {
+    /// The TPM implementation backing the device.
+    pub backend: TpmBackendConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let config: TpmConfig =
+            serde_json::from_str(r#"{"backend": {"software": {"state_path": "/tpm"}}}"#).unwrap();
+        assert_eq!(
+            config.backend,
+            TpmBackendConfig::Software(SoftwareTpmConfig {
+                state_path: "/tpm".to_string()
+            })
+        );
+
+        let config: TpmConfig =
+            serde_json::from_str(r#"{"backend": {"swtpm": {"socket_path": "/swtpm.sock"}}}"#)
+                .unwrap();
+        assert_eq!(
+            config.backend,
+            TpmBackendConfig::Swtpm(SwtpmConfig {
+                socket_path: "/swtpm.sock".to_string()
+            })
+        );
+
+        assert!(serde_json::from_str::<TpmConfig>("{}").is_err());
+        assert!(serde_json::from_str::<TpmConfig>(
+            r#"{"backend": {"libtpms": {"state_path": "/tpm"}}}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<TpmConfig>(
+            r#"{"backend": {"swtpm": {"socket_path": "/swtpm.sock", "path": "/tpm"}}}"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::result;
+
+use serde::{Deserialize, Serialize};
+
+/// The host driver which the functions passed through have to be bound to.
+const VFIO_PCI_DRIVER: &str = "vfio-pci";
+
+/// This struct represents the strongly typed equivalent of the json body from VFIO related
+/// requests.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VfioConfig {
+    /// ID of the host PCI function passed through.
+    pub vfio_id: String,
+    /// Path of the host PCI function in sysfs, such as `/sys/bus/pci/devices/0000:01:00.0`.
+    pub sysfs_path: PathBuf,
+}
+
+/// Errors associated with `VfioConfig`.
+#[derive(Debug, PartialEq)]
+pub enum VfioConfigError {
+    /// The host PCI function is already passed through under another ID.
+    DuplicatePath(String),
+    /// The host PCI function is not bound to the vfio-pci driver.
+    NotBoundToVfio(PathBuf),
+}
+
+impl fmt::Display for VfioConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VfioConfigError::*;
+        match self {
+            DuplicatePath(id) => write!(
+                f,
+                "The host PCI function is already passed through as {}.",
+                id
+            ),
+            NotBoundToVfio(path) => write!(
+                f,
+                "The host PCI function {:?} is not bound to the {} driver.",
+                path, VFIO_PCI_DRIVER
+            ),
+        }
+    }
+}
+
+type Result<T> = result::Result<T, VfioConfigError>;
+
+/// Builder for the list of host PCI functions passed through. The functions are only opened
+/// when the microVM starts, since their DMAs need the guest memory.
+#[derive(Default)]
+pub struct VfioBuilder {
+    configs: Vec<VfioConfig>,
+}
+
+impl VfioBuilder {
+    /// Creates an empty list of host PCI functions.
+    pub fn new() -> Self {
+        VfioBuilder {
+            configs: Vec::new(),
+        }
+    }
+
+    /// Returns a immutable iterator over the configurations of the functions.
+    pub fn iter(&self) -> ::std::slice::Iter<VfioConfig> {
+        self.configs.iter()
+    }
+
+    /// Adds the function described by `config`, after checking that it is bound to vfio-pci.
+    /// A function with the same ID is replaced.
+    pub fn build(&mut self, config: VfioConfig) -> Result<()> {
+        let driver = fs::read_link(config.sysfs_path.join("driver"))
+            .ok()
+            .and_then(|driver| driver.file_name().map(|name| name.to_owned()));
+        if driver.as_ref().and_then(|name| name.to_str()) != Some(VFIO_PCI_DRIVER) {
+            return Err(VfioConfigError::NotBoundToVfio(config.sysfs_path));
+        }
+        if let Some(other) = self
+            .configs
+            .iter()
+            .find(|other| other.sysfs_path == config.sysfs_path && other.vfio_id != config.vfio_id)
+        {
+            return Err(VfioConfigError::DuplicatePath(other.vfio_id.clone()));
+        }
+
+        match self
+            .configs
+            .iter()
+            .position(|other| other.vfio_id == config.vfio_id)
+        {
+            Some(index) => self.configs[index] = config,
+            None => self.configs.push(config),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use utils::tempdir::TempDir;
+
+    // Creates the sysfs directory of a function bound to `driver`.
+    fn sysfs_function(root: &TempDir, name: &str, driver: &str) -> PathBuf {
+        let path = root.as_path().join(name);
+        fs::create_dir(&path).unwrap();
+        symlink(
+            format!("../../../bus/pci/drivers/{}", driver),
+            path.join("driver"),
+        )
+        .unwrap();
+        path
+    }
+
+    fn config(vfio_id: &str, sysfs_path: &PathBuf) -> VfioConfig {
+        VfioConfig {
+            vfio_id: vfio_id.to_string(),
+            sysfs_path: sysfs_path.clone(),
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let root = TempDir::new().unwrap();
+        let function0 = sysfs_function(&root, "0000:01:00.0", VFIO_PCI_DRIVER);
+        let function1 = sysfs_function(&root, "0000:02:00.0", VFIO_PCI_DRIVER);
+        let nvme = sysfs_function(&root, "0000:03:00.0", "nvme");
+        let mut builder = VfioBuilder::new();
+        assert_eq!(builder.iter().count(), 0);
+
+        builder.build(config("nic", &function0)).unwrap();
+        assert_eq!(builder.iter().count(), 1);
+
+        // The function must be bound to vfio-pci.
+        assert_eq!(
+            builder.build(config("disk", &nvme)),
+            Err(VfioConfigError::NotBoundToVfio(nvme.clone()))
+        );
+        let unbound = root.as_path().join("0000:04:00.0");
+        fs::create_dir(&unbound).unwrap();
+        assert_eq!(
+            builder.build(config("disk", &unbound)),
+            Err(VfioConfigError::NotBoundToVfio(unbound.clone()))
+        );
+
+        // A function can't be passed through twice.
+        assert_eq!(
+            builder.build(config("nic1", &function0)),
+            Err(VfioConfigError::DuplicatePath("nic".to_string()))
+        );
+        assert_eq!(builder.iter().count(), 1);
+
+        // Updating a function replaces it.
+        builder.build(config("nic", &function1)).unwrap();
+        assert_eq!(builder.iter().count(), 1);
+        assert_eq!(builder.iter().next().unwrap().sysfs_path, function1);
+
+        builder.build(config("nic1", &function0)).unwrap();
+        assert_eq!(builder.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: VfioConfig = serde_json::from_str(
+            r#"{"vfio_id": "nic", "sysfs_path": "/sys/bus/pci/devices/0000:01:00.0"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.sysfs_path,
+            PathBuf::from("/sys/bus/pci/devices/0000:01:00.0")
+        );
+
+        assert!(serde_json::from_str::<VfioConfig>(
+            r#"{"vfio_id": "nic", "sysfs_path": "/sys/bus/pci/devices/0000:01:00.0", "iommu": 1}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let err = VfioConfigError::DuplicatePath("nic".to_string());
+        assert_eq!(
+            format!("{}", err),
+            "The host PCI function is already passed through as nic."
+        );
+        let err = VfioConfigError::NotBoundToVfio(PathBuf::from("/sys/bus/pci/devices/x"));
+        assert_eq!(
+            format!("{}", err),
+            "The host PCI function \"/sys/bus/pci/devices/x\" is not bound to the vfio-pci driver."
+        );
+    }
+}
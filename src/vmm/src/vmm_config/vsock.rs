@@ -1,38 +1,99 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::HashSet;
+use std::convert::TryInto;
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
-use devices::virtio::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
+use super::RateLimiterConfig;
+use crate::Error as VmmError;
+use devices::virtio::{
+    VhostVsock, VhostVsockError, VhostVsockHandle, Vsock, VsockError, VsockUnixBackend,
+    VsockUnixBackendError, VHOST_VSOCK_PATH,
+};
+pub use devices::virtio::{
+    VsockConnectionInfo, VsockDeviceStats, VsockPortMapping, VsockPortStats,
+};
 
 use serde::{Deserialize, Serialize};
 
 type MutexVsockUnix = Arc<Mutex<Vsock<VsockUnixBackend>>>;
+type MutexVhostVsock = Arc<Mutex<VhostVsock>>;
 
 /// Errors associated with `NetworkInterfaceConfig`.
 #[derive(Debug)]
 pub enum VsockConfigError {
+    /// The agent port is already used by the MMDS or by a port mapping.
+    AgentPortConflict(u32),
     /// Failed to create the backend for the vsock device.
     CreateVsockBackend(VsockUnixBackendError),
     /// Failed to create the vsock device.
     CreateVsockDevice(VsockError),
+    /// Failed to create a rate limiter for the vsock device.
+    CreateRateLimiter(std::io::Error),
+    /// Failed to create the vhost-vsock device.
+    CreateVhostVsock(VhostVsockError),
+    /// Failed to update the vsock device.
+    DeviceUpdate(VmmError),
+    /// Failed to retrieve the vsock device statistics.
+    DeviceStats(VmmError),
+    /// Two port mappings have the same name.
+    DuplicatePortMapping(String),
+    /// The unix backend was requested without a socket path.
+    MissingUdsPath,
+    /// The given setting only applies to the unix backend.
+    UnsupportedByVhost(&'static str),
 }
 
 impl fmt::Display for VsockConfigError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::VsockConfigError::*;
         match *self {
+            AgentPortConflict(port) => write!(
+                f,
+                "The vsock agent port {} is already used by the MMDS or by a port mapping.",
+                port
+            ),
             CreateVsockBackend(ref e) => {
                 write!(f, "Cannot create backend for vsock device: {:?}", e)
             }
             CreateVsockDevice(ref e) => write!(f, "Cannot create vsock device: {:?}", e),
+            CreateRateLimiter(ref e) => write!(f, "Cannot create RateLimiter: {}", e),
+            CreateVhostVsock(ref e) => write!(f, "Cannot create vhost-vsock device: {:?}", e),
+            DeviceUpdate(ref e) => write!(f, "Error during vsock device update (patch): {}", e),
+            DeviceStats(ref e) => write!(f, "Error retrieving the vsock statistics: {}", e),
+            DuplicatePortMapping(ref name) => {
+                write!(f, "Duplicate vsock port mapping name: {}", name)
+            }
+            MissingUdsPath => write!(f, "The unix vsock backend requires a uds_path."),
+            UnsupportedByVhost(setting) => write!(
+                f,
+                "The {} setting is not supported by the vhost vsock backend.",
+                setting
+            ),
         }
     }
 }
 
 type Result<T> = std::result::Result<T, VsockConfigError>;
 
+/// The implementation serving the sockets of the guest.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum VsockBackendType {
+    /// Firecracker multiplexes the guest connections over unix domain sockets.
+    Unix,
+    /// The host kernel serves the guest connections through its `vhost-vsock` device, and the
+    /// host reaches the guest through `AF_VSOCK` sockets.
+    Vhost,
+}
+
+impl Default for VsockBackendType {
+    fn default() -> Self {
+        VsockBackendType::Unix
+    }
+}
+
 /// This struct represents the strongly typed equivalent of the json body
 /// from vsock related requests.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -42,8 +103,39 @@ pub struct VsockDeviceConfig {
     pub vsock_id: String,
     /// A 32-bit Context Identifier (CID) used to identify the guest.
     pub guest_cid: u32,
-    /// Path to local unix socket.
+    /// The implementation serving the sockets of the guest.
+    #[serde(default)]
+    pub backend: VsockBackendType,
+    /// Path to local unix socket. Required by the unix backend only.
+    #[serde(default)]
     pub uds_path: String,
+    /// Vsock port on which the guest reaches the MMDS. Guest connections to this port are
+    /// served by Firecracker instead of being forwarded to `uds_path`.
+    pub mmds_port: Option<u32>,
+    /// Vsock port on which the guest agent connects. Guest connections to this port are handed
+    /// over to the `/agent` API endpoints instead of being forwarded to `uds_path`.
+    pub agent_port: Option<u32>,
+    /// Rate limiter for the data going from the host to the guest.
+    pub rx_rate_limiter: Option<RateLimiterConfig>,
+    /// Rate limiter for the data going from the guest to the host.
+    pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// Named services, each reached by the host through its own Unix socket, instead of
+    /// through `uds_path` and the "CONNECT <port>" handshake.
+    #[serde(default)]
+    pub port_mappings: Vec<VsockPortMapping>,
+}
+
+/// The data fed into a vsock device update request. Currently, only the RX and TX rate limiters
+/// can be updated.
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VsockDeviceUpdateConfig {
+    /// New RX rate limiter config. Only provided data will be updated. I.e. if any optional data
+    /// is missing, it will not be nullified, but left unchanged.
+    pub rx_rate_limiter: Option<RateLimiterConfig>,
+    /// New TX rate limiter config. Only provided data will be updated. I.e. if any optional data
+    /// is missing, it will not be nullified, but left unchanged.
+    pub tx_rate_limiter: Option<RateLimiterConfig>,
 }
 
 struct VsockAndUnixPath {
@@ -51,59 +143,186 @@ struct VsockAndUnixPath {
     uds_path: String,
 }
 
-/// A builder of Vsock with Unix backend from 'VsockDeviceConfig'.
+/// A builder of Vsock with either a Unix or a vhost backend from 'VsockDeviceConfig'.
 #[derive(Default)]
 pub struct VsockBuilder {
     inner: Option<VsockAndUnixPath>,
+    vhost: Option<MutexVhostVsock>,
+    config: Option<VsockDeviceConfig>,
 }
 
 impl VsockBuilder {
-    /// Creates an empty Vsock with Unix backend Store.
+    /// Creates an empty Vsock Store.
     pub fn new() -> Self {
-        Self { inner: None }
+        Self {
+            inner: None,
+            vhost: None,
+            config: None,
+        }
     }
 
-    /// Inserts a Unix backend Vsock in the store.
-    /// If an entry already exists, it will overwrite it.
+    /// Inserts a Vsock in the store, with the backend selected by `cfg`.
+    /// If an entry already exists, whatever its backend, it will overwrite it.
     pub fn insert(&mut self, cfg: VsockDeviceConfig) -> Result<()> {
         // Make sure to drop the old one and remove the socket before creating a new one.
         if let Some(existing) = self.inner.take() {
-            std::fs::remove_file(existing.uds_path)
+            let vsock = existing.vsock.lock().expect("Poisoned lock");
+            Self::remove_sockets(&existing.uds_path, vsock.backend())
                 .map_err(VsockUnixBackendError::UnixBind)
                 .map_err(VsockConfigError::CreateVsockBackend)?;
         }
-        self.inner = Some(VsockAndUnixPath {
-            uds_path: cfg.uds_path.clone(),
-            vsock: Arc::new(Mutex::new(Self::create_unixsock_vsock(cfg)?)),
-        });
+        // Dropping the old vhost device releases its CID in the host kernel.
+        self.vhost = None;
+        self.config = None;
+
+        let config = cfg.clone();
+        match cfg.backend {
+            VsockBackendType::Unix => {
+                self.inner = Some(VsockAndUnixPath {
+                    uds_path: cfg.uds_path.clone(),
+                    vsock: Arc::new(Mutex::new(Self::create_unixsock_vsock(cfg)?)),
+                });
+            }
+            VsockBackendType::Vhost => {
+                self.vhost = Some(Arc::new(Mutex::new(Self::create_vhost_vsock(cfg)?)));
+            }
+        }
+        self.config = Some(config);
         Ok(())
     }
 
-    /// Provides a reference to the Vsock if present.
+    /// Provides a reference to the Vsock with Unix backend if present.
     pub fn get(&self) -> Option<&MutexVsockUnix> {
         self.inner.as_ref().map(|pair| &pair.vsock)
     }
 
+    /// Provides a reference to the Vsock with vhost backend if present.
+    pub fn get_vhost(&self) -> Option<&MutexVhostVsock> {
+        self.vhost.as_ref()
+    }
+
+    /// Returns the current configuration of the vsock device, if present.
+    pub fn config(&self) -> Option<VsockDeviceConfig> {
+        let mut config = self.config.clone()?;
+        // The rate limiters may have been updated after the microVM start.
+        if let Some(vsock) = self.get() {
+            let vsock = vsock.lock().expect("Poisoned lock");
+            config.rx_rate_limiter = RateLimiterConfig::from_rate_limiter(vsock.rx_rate_limiter());
+            config.tx_rate_limiter = RateLimiterConfig::from_rate_limiter(vsock.tx_rate_limiter());
+        }
+        Some(config)
+    }
+
+    /// Creates a Vsock device served by the host kernel from a VsockDeviceConfig.
+    pub fn create_vhost_vsock(cfg: VsockDeviceConfig) -> Result<VhostVsock> {
+        // The kernel serves the guest connections on its own, out of Firecracker's reach.
+        if !cfg.uds_path.is_empty() {
+            return Err(VsockConfigError::UnsupportedByVhost("uds_path"));
+        }
+        if cfg.mmds_port.is_some() {
+            return Err(VsockConfigError::UnsupportedByVhost("mmds_port"));
+        }
+        if cfg.agent_port.is_some() {
+            return Err(VsockConfigError::UnsupportedByVhost("agent_port"));
+        }
+        if cfg.rx_rate_limiter.is_some() || cfg.tx_rate_limiter.is_some() {
+            return Err(VsockConfigError::UnsupportedByVhost("rate_limiter"));
+        }
+        if !cfg.port_mappings.is_empty() {
+            return Err(VsockConfigError::UnsupportedByVhost("port_mappings"));
+        }
+
+        let handle =
+            VhostVsockHandle::open(VHOST_VSOCK_PATH).map_err(VsockConfigError::CreateVhostVsock)?;
+        VhostVsock::new(u64::from(cfg.guest_cid), handle)
+            .map_err(VsockConfigError::CreateVhostVsock)
+    }
+
     /// Creates a Vsock device from a VsockDeviceConfig.
     pub fn create_unixsock_vsock(cfg: VsockDeviceConfig) -> Result<Vsock<VsockUnixBackend>> {
-        let backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path)
+        if cfg.uds_path.is_empty() {
+            return Err(VsockConfigError::MissingUdsPath);
+        }
+
+        let mut names = HashSet::new();
+        if let Some(mapping) = cfg
+            .port_mappings
+            .iter()
+            .find(|mapping| !names.insert(mapping.name.as_str()))
+        {
+            return Err(VsockConfigError::DuplicatePortMapping(mapping.name.clone()));
+        }
+        if let Some(port) = cfg.agent_port {
+            if cfg.mmds_port == Some(port)
+                || cfg
+                    .port_mappings
+                    .iter()
+                    .any(|mapping| mapping.guest_port == port)
+            {
+                return Err(VsockConfigError::AgentPortConflict(port));
+            }
+        }
+
+        let mut backend = VsockUnixBackend::new(u64::from(cfg.guest_cid), cfg.uds_path.clone())
             .map_err(VsockConfigError::CreateVsockBackend)?;
+        backend.set_mmds_port(cfg.mmds_port);
+        backend.set_agent_port(cfg.agent_port);
+        for mapping in cfg.port_mappings {
+            if let Err(err) = backend.add_port_mapping(mapping) {
+                // Unbind the sockets bound so far, so that the configuration can be retried.
+                let _ = Self::remove_sockets(&cfg.uds_path, &backend);
+                return Err(VsockConfigError::CreateVsockBackend(err));
+            }
+        }
+
+        let rx_rate_limiter = cfg
+            .rx_rate_limiter
+            .map(RateLimiterConfig::try_into)
+            .transpose()
+            .map_err(VsockConfigError::CreateRateLimiter)?;
+        let tx_rate_limiter = cfg
+            .tx_rate_limiter
+            .map(RateLimiterConfig::try_into)
+            .transpose()
+            .map_err(VsockConfigError::CreateRateLimiter)?;
 
-        Ok(Vsock::new(u64::from(cfg.guest_cid), backend)
-            .map_err(VsockConfigError::CreateVsockDevice)?)
+        Ok(Vsock::new(
+            u64::from(cfg.guest_cid),
+            backend,
+            rx_rate_limiter.unwrap_or_default(),
+            tx_rate_limiter.unwrap_or_default(),
+        )
+        .map_err(VsockConfigError::CreateVsockDevice)?)
+    }
+
+    /// Removes the Unix sockets bound by `backend`, whose host socket is at `uds_path`.
+    fn remove_sockets(uds_path: &str, backend: &VsockUnixBackend) -> std::io::Result<()> {
+        std::fs::remove_file(uds_path)?;
+        std::fs::remove_file(backend.dgram_sock_path())?;
+        for mapping in backend.port_mappings() {
+            std::fs::remove_file(&mapping.uds_path)?;
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::vmm_config::TokenBucketConfig;
     use utils::tempfile::TempFile;
 
     pub(crate) fn default_config(tmp_sock_file: &TempFile) -> VsockDeviceConfig {
         VsockDeviceConfig {
             vsock_id: "vsock".to_string(),
             guest_cid: 3,
+            backend: VsockBackendType::Unix,
             uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+            mmds_port: None,
+            agent_port: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: None,
+            port_mappings: Vec::new(),
         }
     }
 
@@ -111,8 +330,170 @@ pub(crate) mod tests {
     fn test_vsock_create() {
         let mut tmp_sock_file = TempFile::new().unwrap();
         tmp_sock_file.remove().unwrap();
-        let vsock_config = default_config(&tmp_sock_file);
-        VsockBuilder::create_unixsock_vsock(vsock_config).unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        VsockBuilder::create_unixsock_vsock(vsock_config.clone()).unwrap();
+
+        vsock_config.uds_path = String::new();
+        match VsockBuilder::create_unixsock_vsock(vsock_config) {
+            Err(VsockConfigError::MissingUdsPath) => (),
+            _ => panic!("Test failed."),
+        }
+    }
+
+    #[test]
+    fn test_vhost_vsock_create() {
+        let tmp_sock_file = TempFile::new().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.backend = VsockBackendType::Vhost;
+
+        // The settings of the unix backend are rejected.
+        match VsockBuilder::create_vhost_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::UnsupportedByVhost("uds_path")) => (),
+            _ => panic!("Test failed."),
+        }
+        vsock_config.uds_path = String::new();
+        vsock_config.mmds_port = Some(52);
+        match VsockBuilder::create_vhost_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::UnsupportedByVhost("mmds_port")) => (),
+            _ => panic!("Test failed."),
+        }
+        vsock_config.mmds_port = None;
+        vsock_config.agent_port = Some(53);
+        match VsockBuilder::create_vhost_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::UnsupportedByVhost("agent_port")) => (),
+            _ => panic!("Test failed."),
+        }
+        vsock_config.agent_port = None;
+        vsock_config.tx_rate_limiter = Some(RateLimiterConfig::default());
+        match VsockBuilder::create_vhost_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::UnsupportedByVhost("rate_limiter")) => (),
+            _ => panic!("Test failed."),
+        }
+        vsock_config.tx_rate_limiter = None;
+        vsock_config.port_mappings = vec![VsockPortMapping {
+            name: "service".to_string(),
+            guest_port: 52,
+            uds_path: tmp_sock_file.as_path().to_str().unwrap().to_string(),
+        }];
+        match VsockBuilder::create_vhost_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::UnsupportedByVhost("port_mappings")) => (),
+            _ => panic!("Test failed."),
+        }
+
+        // The device can only be created when the host kernel provides vhost-vsock.
+        vsock_config.port_mappings = Vec::new();
+        let mut store = VsockBuilder::new();
+        match store.insert(vsock_config) {
+            Ok(()) => {
+                assert!(store.get().is_none());
+                assert_eq!(store.get_vhost().unwrap().lock().unwrap().cid(), 3);
+            }
+            Err(VsockConfigError::CreateVhostVsock(_)) => assert!(store.get_vhost().is_none()),
+            Err(e) => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_vsock_mmds_port() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.mmds_port = Some(52);
+        let vsock = VsockBuilder::create_unixsock_vsock(vsock_config).unwrap();
+        assert_eq!(vsock.backend().mmds_port(), Some(52));
+    }
+
+    #[test]
+    fn test_vsock_agent_port() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.mmds_port = Some(52);
+
+        // The agent port cannot be shared with the MMDS or with a port mapping.
+        vsock_config.agent_port = Some(52);
+        match VsockBuilder::create_unixsock_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::AgentPortConflict(52)) => (),
+            _ => panic!("Test failed."),
+        }
+        vsock_config.agent_port = Some(53);
+        vsock_config.port_mappings = vec![VsockPortMapping {
+            name: "service".to_string(),
+            guest_port: 53,
+            uds_path: "service.sock".to_string(),
+        }];
+        match VsockBuilder::create_unixsock_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::AgentPortConflict(53)) => (),
+            _ => panic!("Test failed."),
+        }
+
+        vsock_config.port_mappings = Vec::new();
+        let vsock = VsockBuilder::create_unixsock_vsock(vsock_config).unwrap();
+        assert_eq!(vsock.backend().agent_port(), Some(53));
+    }
+
+    #[test]
+    fn test_vsock_rate_limiters() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        vsock_config.rx_rate_limiter = Some(RateLimiterConfig {
+            bandwidth: Some(TokenBucketConfig {
+                size: 1000,
+                one_time_burst: None,
+                refill_time: 100,
+            }),
+            ops: None,
+            adaptive: None,
+        });
+        let vsock = VsockBuilder::create_unixsock_vsock(vsock_config).unwrap();
+        assert_eq!(
+            vsock.rx_rate_limiter().bandwidth().unwrap().capacity(),
+            1000
+        );
+        assert!(vsock.tx_rate_limiter().bandwidth().is_none());
+        assert!(vsock.tx_rate_limiter().ops().is_none());
+    }
+
+    #[test]
+    fn test_vsock_port_mappings() {
+        let mut tmp_sock_file = TempFile::new().unwrap();
+        tmp_sock_file.remove().unwrap();
+        let mut tmp_mapping_file = TempFile::new().unwrap();
+        tmp_mapping_file.remove().unwrap();
+        let mut vsock_config = default_config(&tmp_sock_file);
+        let mapping = VsockPortMapping {
+            name: "service".to_string(),
+            guest_port: 52,
+            uds_path: tmp_mapping_file.as_path().to_str().unwrap().to_string(),
+        };
+
+        // The mapping names must be unique.
+        vsock_config.port_mappings = vec![mapping.clone(), mapping.clone()];
+        match VsockBuilder::create_unixsock_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::DuplicatePortMapping(name)) => assert_eq!(name, "service"),
+            _ => panic!("Test failed."),
+        }
+
+        // The sockets bound so far are removed when a mapping socket cannot be bound.
+        let mut other_mapping = mapping.clone();
+        other_mapping.name = "other_service".to_string();
+        vsock_config.port_mappings = vec![mapping.clone(), other_mapping];
+        match VsockBuilder::create_unixsock_vsock(vsock_config.clone()) {
+            Err(VsockConfigError::CreateVsockBackend(_)) => (),
+            _ => panic!("Test failed."),
+        }
+        assert!(!tmp_sock_file.as_path().exists());
+        assert!(!tmp_mapping_file.as_path().exists());
+
+        // The sockets are also removed when the device is configured again.
+        let mut store = VsockBuilder::new();
+        vsock_config.port_mappings = vec![mapping.clone()];
+        store.insert(vsock_config.clone()).unwrap();
+        store.insert(vsock_config.clone()).unwrap();
+        let vsock = store.get().unwrap().lock().unwrap();
+        assert_eq!(vsock.backend().port_mappings(), &[mapping]);
+        VsockBuilder::remove_sockets(&vsock_config.uds_path, vsock.backend()).unwrap();
     }
 
     #[test]
@@ -128,15 +509,25 @@ pub(crate) mod tests {
 
         let new_cid = vsock_config.guest_cid + 1;
         vsock_config.guest_cid = new_cid;
-        store.insert(vsock_config).unwrap();
+        store.insert(vsock_config.clone()).unwrap();
         let vsock = store.get().unwrap();
         assert_eq!(vsock.lock().unwrap().cid(), new_cid as u64);
+        assert_eq!(store.config(), Some(vsock_config.clone()));
+
+        // A failed insertion leaves no device behind.
+        vsock_config.uds_path = String::new();
+        assert!(store.insert(vsock_config).is_err());
+        assert!(store.get().is_none());
+        assert!(store.config().is_none());
     }
 
     #[test]
     fn test_error_messages() {
         use super::VsockConfigError::*;
         use std::io;
+        let err = AgentPortConflict(52);
+        let _ = format!("{}{:?}", err, err);
+
         let err = CreateVsockBackend(devices::virtio::VsockUnixBackendError::EpollAdd(
             io::Error::from_raw_os_error(0),
         ));
@@ -146,5 +537,28 @@ pub(crate) mod tests {
             io::Error::from_raw_os_error(0),
         ));
         let _ = format!("{}{:?}", err, err);
+
+        let err = CreateRateLimiter(io::Error::from_raw_os_error(0));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DeviceUpdate(VmmError::VcpuExit);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DeviceStats(VmmError::VcpuExit);
+        let _ = format!("{}{:?}", err, err);
+
+        let err = CreateVhostVsock(devices::virtio::VhostVsockError::Open(
+            io::Error::from_raw_os_error(0),
+        ));
+        let _ = format!("{}{:?}", err, err);
+
+        let err = DuplicatePortMapping("service".to_string());
+        let _ = format!("{}{:?}", err, err);
+
+        let err = MissingUdsPath;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = UnsupportedByVhost("mmds_port");
+        let _ = format!("{}{:?}", err, err);
     }
 }
@@ -0,0 +1,50 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+
+/// The action taken by the VMM when the guest stops petting the watchdog.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, Versionize)]
+pub enum WatchdogAction {
+    /// The expiry is only logged and accounted for in the metrics.
+    None,
+    /// The VMM exits as if the guest rebooted, with a success exit code.
+    Reset,
+    /// The VMM exits with an error exit code.
+    Stop,
+}
+
+impl Default for WatchdogAction {
+    fn default() -> Self {
+        WatchdogAction::None
+    }
+}
+
+/// This struct represents the strongly typed equivalent of the json body
+/// from the watchdog related requests.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// The action taken when the watchdog expires.
+    #[serde(default)]
+    pub action: WatchdogAction,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let config: WatchdogConfig = serde_json::from_str(r#"{"action": "Reset"}"#).unwrap();
+        assert_eq!(config.action, WatchdogAction::Reset);
+
+        let config: WatchdogConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.action, WatchdogAction::None);
+
+        assert!(serde_json::from_str::<WatchdogConfig>(r#"{"action": "Reboot"}"#).is_err());
+        assert!(serde_json::from_str::<WatchdogConfig>(r#"{"timeout": 10}"#).is_err());
+    }
+}
@@ -6,33 +6,39 @@
 // found in the THIRD-PARTY file.
 
 use libc::{c_int, c_void, siginfo_t};
-#[cfg(not(test))]
-use std::sync::Barrier;
 use std::{
     cell::Cell,
     fmt::{Display, Formatter},
     io, result,
-    sync::atomic::{fence, Ordering},
+    sync::atomic::{fence, AtomicU8, Ordering},
     sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    sync::Arc,
     thread,
+    time::Duration,
 };
 
 use crate::{
-    vmm_config::machine_config::CpuFeaturesTemplate, vstate::vm::Vm, FC_EXIT_CODE_GENERIC_ERROR,
-    FC_EXIT_CODE_OK,
+    vmm_config::cpu_quota::UNLIMITED_CPU_QUOTA_PCT,
+    vmm_config::machine_config::{CpuFeaturesConfig, CpuFeaturesTemplate, CpuTopology, MsrPolicy},
+    vstate::vm::Vm,
+    FC_EXIT_CODE_GENERIC_ERROR, FC_EXIT_CODE_OK,
 };
+#[cfg(target_arch = "x86_64")]
+use cpuid::custom::CustomCpuTemplate;
 use kvm_bindings::{KVM_SYSTEM_EVENT_RESET, KVM_SYSTEM_EVENT_SHUTDOWN};
 use kvm_ioctls::VcpuExit;
-use logger::{error, info, IncMetric, METRICS};
+use logger::{error, info, update_metric_with_elapsed_time, IncMetric, VcpuExitMetrics, METRICS};
 use seccomp::{BpfProgram, SeccompFilter};
 use utils::{
     eventfd::EventFd,
     signal::{register_signal_handler, sigrtmin, Killable},
     sm::StateMachine,
+    time::{get_time_us, ClockType},
 };
 
 #[cfg(target_arch = "aarch64")]
 pub(crate) mod aarch64;
+mod throttle;
 #[cfg(target_arch = "x86_64")]
 pub(crate) mod x86_64;
 
@@ -41,6 +47,8 @@ pub(crate) use aarch64::{Error as VcpuError, *};
 #[cfg(target_arch = "x86_64")]
 pub(crate) use x86_64::{Error as VcpuError, *};
 
+use throttle::CpuThrottler;
+
 /// Signal number (SIGRTMIN) used to kick Vcpus.
 pub(crate) const VCPU_RTSIG_OFFSET: i32 = 0;
 
@@ -53,6 +61,8 @@ pub enum Error {
     SignalVcpu(utils::errno::Error),
     /// Kvm Exit is not handled by our implementation.
     UnhandledKvmExit(String),
+    /// The vCPU thread panicked.
+    VcpuJoin,
     /// Wrapper over error triggered by some vcpu action.
     VcpuResponse(VcpuError),
     /// Cannot spawn a new vCPU thread.
@@ -71,6 +81,7 @@ impl Display for Error {
             FaultyKvmExit(ref e) => write!(f, "Received error signaling kvm exit: {}", e),
             SignalVcpu(e) => write!(f, "Failed to signal vcpu: {}", e),
             UnhandledKvmExit(ref e) => write!(f, "Unexpected kvm exit received: {}", e),
+            VcpuJoin => write!(f, "The vcpu thread panicked"),
             VcpuResponse(e) => write!(f, "Failed to run action on vcpu: {}", e),
             VcpuSpawn(e) => write!(f, "Cannot spawn a new vCPU thread: {}", e),
             VcpuTlsInit => write!(f, "Cannot clean init vcpu TLS"),
@@ -90,6 +101,19 @@ pub struct VcpuConfig {
     pub ht_enabled: bool,
     /// CPUID template to use.
     pub cpu_template: Option<CpuFeaturesTemplate>,
+    /// The topology exposed to the guest, if any. Otherwise, all the vCPUs are in one socket.
+    pub cpu_topology: Option<CpuTopology>,
+    /// Exposes the hardware virtualization extensions to the guest.
+    pub nested_virt_enabled: bool,
+    /// The MSRs the guest is allowed or denied to access.
+    pub msr_policy: Option<MsrPolicy>,
+    /// The CPUID and MSR modifications applied after the CPU template.
+    #[cfg(target_arch = "x86_64")]
+    pub custom_cpu_template: Option<CustomCpuTemplate>,
+    /// The CPUID feature bits forced on or off, on top of the CPU templates.
+    pub cpu_features: Option<CpuFeaturesConfig>,
+    /// Shows the guest synthesized cache leaves and brand string.
+    pub cpuid_normalization_enabled: bool,
 }
 
 // Using this for easier explicit type-casting to help IDEs interpret the code.
@@ -110,6 +134,15 @@ pub struct Vcpu {
     response_receiver: Option<Receiver<VcpuResponse>>,
     // The transmitting end of the responses channel owned by the vcpu side.
     response_sender: Sender<VcpuResponse>,
+    // The KVM exits of this vCPU, and the time it spent in the guest and in the VMM.
+    exit_metrics: Arc<VcpuExitMetrics>,
+    // Keeps the CPU time used by the vCPU thread within its quota.
+    throttler: CpuThrottler,
+    // The time the boot started at, until the vCPU first enters the guest.
+    boot_start_us: Option<u64>,
+    // Tells the debugger which vCPU stopped on a breakpoint or after a single step.
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    debug_stop_sender: Option<Sender<u8>>,
 }
 
 impl Vcpu {
@@ -215,6 +248,11 @@ impl Vcpu {
             response_receiver: Some(response_receiver),
             response_sender,
             kvm_vcpu,
+            exit_metrics: METRICS.vcpu.vcpus.get(index),
+            throttler: CpuThrottler::new(Arc::new(AtomicU8::new(UNLIMITED_CPU_QUOTA_PCT))),
+            boot_start_us: None,
+            #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+            debug_stop_sender: None,
         })
     }
 
@@ -223,6 +261,24 @@ impl Vcpu {
         self.kvm_vcpu.mmio_bus = Some(mmio_bus);
     }
 
+    /// Sets the CPU quota of the vCPU thread, in percents of a host core. The quota is shared
+    /// with the VMM, which may change it at any time.
+    pub fn set_cpu_quota(&mut self, quota_pct: Arc<AtomicU8>) {
+        self.throttler = CpuThrottler::new(quota_pct);
+    }
+
+    /// Sets the time the boot started at, so that the vCPU records in the `first_vcpu_run` metric
+    /// how long it took until it first entered the guest.
+    pub fn set_boot_start_us(&mut self, boot_start_us: u64) {
+        self.boot_start_us = Some(boot_start_us);
+    }
+
+    /// Sets the channel on which the vCPU sends its index when it stops for the debugger.
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    pub fn set_debug_stop_sender(&mut self, sender: Sender<u8>) {
+        self.debug_stop_sender = Some(sender);
+    }
+
     /// Moves the vcpu to its own thread and constructs a VcpuHandle.
     /// The handle can be used to control the remote vcpu.
     pub fn start_threaded(mut self, seccomp_filter: BpfProgram) -> Result<VcpuHandle> {
@@ -269,7 +325,11 @@ impl Vcpu {
     fn running(&mut self) -> StateMachine<Self> {
         // This loop is here just for optimizing the emulation path.
         // No point in ticking the state machine if there are no external events.
+        if let Some(boot_start_us) = self.boot_start_us.take() {
+            update_metric_with_elapsed_time(&METRICS.timings_us.first_vcpu_run, boot_start_us);
+        }
         loop {
+            self.throttle();
             match self.run_emulation() {
                 // Emulation ran successfully, continue.
                 Ok(VcpuEmulation::Handled) => (),
@@ -283,6 +343,15 @@ impl Vcpu {
                 // seccomp failure because musl calls `sigprocmask` as part of `pthread_exit`.
                 // So we pause vCPU0 and send a signal to the emulation thread to stop the VMM.
                 Ok(VcpuEmulation::Stopped) => return self.exit(FC_EXIT_CODE_OK),
+                // The vCPU stops for the debugger, which pauses the others.
+                #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+                Ok(VcpuEmulation::DebugStopped) => {
+                    if let Some(sender) = self.debug_stop_sender.as_ref() {
+                        if sender.send(self.kvm_vcpu.index).is_ok() {
+                            return StateMachine::next(Self::paused);
+                        }
+                    }
+                }
                 // Emulation errors lead to vCPU exit.
                 Err(_) => return self.exit(FC_EXIT_CODE_GENERIC_ERROR),
             }
@@ -311,6 +380,15 @@ impl Vcpu {
                     .send(VcpuResponse::Resumed)
                     .expect("failed to send resume status");
             }
+            // The guest of a running Vcpu has nothing to be told.
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::NotifyPause) => {
+                self.response_sender
+                    .send(VcpuResponse::NotAllowed(String::from(
+                        "pause notification unavailable while running",
+                    )))
+                    .expect("failed to send pause notification not allowed status");
+            }
             // SaveState or RestoreState cannot be performed on a running Vcpu.
             Ok(VcpuEvent::SaveState) | Ok(VcpuEvent::RestoreState(_)) => {
                 self.response_sender
@@ -319,6 +397,14 @@ impl Vcpu {
                     )))
                     .expect("failed to send save not allowed status");
             }
+            #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+            Ok(VcpuEvent::Debug(_)) => {
+                self.response_sender
+                    .send(VcpuResponse::NotAllowed(String::from(
+                        "debugging unavailable while running",
+                    )))
+                    .expect("failed to send debug not allowed status");
+            }
             Ok(VcpuEvent::Exit) => return self.exit(FC_EXIT_CODE_GENERIC_ERROR),
             // Unhandled exit of the other end.
             Err(TryRecvError::Disconnected) => {
@@ -350,6 +436,23 @@ impl Vcpu {
                     .expect("vcpu channel unexpectedly closed");
                 StateMachine::next(Self::paused)
             }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::NotifyPause) => {
+                self.kvm_vcpu
+                    .notify_pause()
+                    .map(|()| {
+                        self.response_sender
+                            .send(VcpuResponse::PauseNotified)
+                            .expect("vcpu channel unexpectedly closed");
+                    })
+                    .unwrap_or_else(|e| {
+                        self.response_sender
+                            .send(VcpuResponse::Error(Error::VcpuResponse(e)))
+                            .expect("vcpu channel unexpectedly closed")
+                    });
+
+                StateMachine::next(Self::paused)
+            }
             Ok(VcpuEvent::SaveState) => {
                 // Save vcpu state.
                 self.kvm_vcpu
@@ -383,6 +486,23 @@ impl Vcpu {
 
                 StateMachine::next(Self::paused)
             }
+            #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+            Ok(VcpuEvent::Debug(request)) => {
+                self.kvm_vcpu
+                    .debug(request)
+                    .map(|response| {
+                        self.response_sender
+                            .send(VcpuResponse::Debug(response))
+                            .expect("vcpu channel unexpectedly closed");
+                    })
+                    .unwrap_or_else(|e| {
+                        self.response_sender
+                            .send(VcpuResponse::Error(Error::VcpuResponse(e)))
+                            .expect("vcpu channel unexpectedly closed")
+                    });
+
+                StateMachine::next(Self::paused)
+            }
             Ok(VcpuEvent::Exit) => self.exit(FC_EXIT_CODE_GENERIC_ERROR),
             // Unhandled exit of the other end.
             Err(_) => {
@@ -411,10 +531,9 @@ impl Vcpu {
     #[cfg(not(test))]
     // This is the main loop of the `Exited` state.
     fn exited(&mut self) -> StateMachine<Self> {
-        // Wait indefinitely.
-        // The VMM thread will kill the entire process.
-        let barrier = Barrier::new(2);
-        barrier.wait();
+        // Wait until the VMM thread kills the entire process, or tears down the microVM by
+        // closing the other end of the channel.
+        while self.event_receiver.recv().is_ok() {}
 
         StateMachine::finish()
     }
@@ -429,13 +548,47 @@ impl Vcpu {
         StateMachine::finish()
     }
 
+    // Pauses the vCPU thread until the next accounting period if it used up its CPU quota.
+    fn throttle(&mut self) {
+        if !self.throttler.is_limited() {
+            return;
+        }
+        let pause_us = self.throttler.pause_time_us(
+            get_time_us(ClockType::Monotonic),
+            get_time_us(ClockType::ThreadCpu),
+        );
+        if pause_us > 0 {
+            self.exit_metrics.throttled_time_us.add(pause_us as usize);
+            thread::sleep(Duration::from_micros(pause_us));
+        }
+    }
+
     /// Runs the vCPU in KVM context and handles the kvm exit reason.
     ///
     /// Returns error or enum specifying whether emulation was handled or interrupted.
     pub fn run_emulation(&self) -> Result<VcpuEmulation> {
-        match self.kvm_vcpu.fd.run() {
+        let entry_us = get_time_us(ClockType::Monotonic);
+        let run = self.kvm_vcpu.fd.run();
+        let exit_us = get_time_us(ClockType::Monotonic);
+        let emulation = self.handle_exit(run);
+        self.exit_metrics
+            .guest_time_us
+            .add(exit_us.saturating_sub(entry_us) as usize);
+        self.exit_metrics
+            .vmm_time_us
+            .add(get_time_us(ClockType::Monotonic).saturating_sub(exit_us) as usize);
+        emulation
+    }
+
+    // Emulates the device access which made KVM exit, or tells the state machine what to do.
+    fn handle_exit(
+        &self,
+        run: std::result::Result<VcpuExit, kvm_ioctls::Error>,
+    ) -> Result<VcpuEmulation> {
+        match run {
             Ok(run) => match run {
                 VcpuExit::MmioRead(addr, data) => {
+                    self.exit_metrics.exit_mmio.inc();
                     if let Some(mmio_bus) = &self.kvm_vcpu.mmio_bus {
                         mmio_bus.read(addr, data);
                         METRICS.vcpu.exit_mmio_read.inc();
@@ -443,6 +596,7 @@ impl Vcpu {
                     Ok(VcpuEmulation::Handled)
                 }
                 VcpuExit::MmioWrite(addr, data) => {
+                    self.exit_metrics.exit_mmio.inc();
                     if let Some(mmio_bus) = &self.kvm_vcpu.mmio_bus {
                         mmio_bus.write(addr, data);
                         METRICS.vcpu.exit_mmio_write.inc();
@@ -450,6 +604,7 @@ impl Vcpu {
                     Ok(VcpuEmulation::Handled)
                 }
                 VcpuExit::Hlt => {
+                    self.exit_metrics.exit_hlt.inc();
                     info!("Received KVM_EXIT_HLT signal");
                     Ok(VcpuEmulation::Stopped)
                 }
@@ -495,6 +650,12 @@ impl Vcpu {
                     }
                 },
                 arch_specific_reason => {
+                    if matches!(
+                        arch_specific_reason,
+                        VcpuExit::IoIn(..) | VcpuExit::IoOut(..)
+                    ) {
+                        self.exit_metrics.exit_io.inc();
+                    }
                     // run specific architecture emulation.
                     self.kvm_vcpu.run_arch_emulation(arch_specific_reason)
                 }
@@ -503,13 +664,21 @@ impl Vcpu {
             // error in our code in which case it is better to panic.
             Err(ref e) => {
                 match e.errno() {
-                    libc::EAGAIN => Ok(VcpuEmulation::Handled),
+                    libc::EAGAIN => {
+                        self.exit_metrics.exit_interrupts.inc();
+                        Ok(VcpuEmulation::Handled)
+                    }
                     libc::EINTR => {
+                        self.exit_metrics.exit_interrupts.inc();
                         self.kvm_vcpu.fd.set_kvm_immediate_exit(0);
                         // Notify that this KVM_RUN was interrupted.
                         Ok(VcpuEmulation::Interrupted)
                     }
                     _ => {
+                        // The guest accessed memory the host couldn't fault in.
+                        if e.errno() == libc::EFAULT {
+                            self.exit_metrics.exit_epfault.inc();
+                        }
                         METRICS.vcpu.failures.inc();
                         error!("Failure during vcpu run: {}", e);
                         Err(Error::FaultyKvmExit(format!("{}", e)))
@@ -529,12 +698,18 @@ impl Drop for Vcpu {
 #[derive(Clone)]
 /// List of events that the Vcpu can receive.
 pub enum VcpuEvent {
+    /// Request of the debugger to a paused Vcpu.
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    Debug(DebugRequest),
     /// The vCPU will go to exited state when receiving this message.
     Exit,
     /// Pause the Vcpu.
     Pause,
     /// Event to resume the Vcpu.
     Resume,
+    /// Event to tell the guest that a paused Vcpu was paused, before it resumes.
+    #[cfg(target_arch = "x86_64")]
+    NotifyPause,
     /// Event to restore the state of a paused Vcpu.
     RestoreState(Box<VcpuState>),
     /// Event to save the state of a paused Vcpu.
@@ -543,6 +718,9 @@ pub enum VcpuEvent {
 
 /// List of responses that the Vcpu reports.
 pub enum VcpuResponse {
+    /// Response to a request of the debugger.
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    Debug(DebugResponse),
     /// Requested action encountered an error.
     Error(Error),
     /// Vcpu is stopped.
@@ -551,6 +729,9 @@ pub enum VcpuResponse {
     NotAllowed(String),
     /// Vcpu is paused.
     Paused,
+    /// The guest was told that the Vcpu was paused.
+    #[cfg(target_arch = "x86_64")]
+    PauseNotified,
     /// Vcpu is resumed.
     Resumed,
     /// Vcpu state is restored.
@@ -587,6 +768,11 @@ impl VcpuHandle {
             .send(event)
             .expect("event sender channel closed on vcpu end.");
         // Kick the vcpu so it picks up the message.
+        self.kick()
+    }
+
+    /// Makes the vcpu exit out of `KVM_RUN`, if it is running.
+    pub fn kick(&self) -> Result<()> {
         self.vcpu_thread
             .as_ref()
             // Safe to unwrap since constructor make this 'Some'.
@@ -599,12 +785,25 @@ impl VcpuHandle {
     pub fn response_receiver(&self) -> &Receiver<VcpuResponse> {
         &self.response_receiver
     }
+
+    /// Waits for the thread of the exited vcpu to finish, releasing the vcpu.
+    pub fn join(&mut self) -> Result<()> {
+        // Close the original channel so that the exited Vcpu thread finishes.
+        let (event_sender, _event_receiver) = channel();
+        self.event_sender = event_sender;
+        if let Some(vcpu_thread) = self.vcpu_thread.take() {
+            vcpu_thread.join().map_err(|_| Error::VcpuJoin)?;
+        }
+        Ok(())
+    }
 }
 
 pub enum VcpuEmulation {
     Handled,
     Interrupted,
     Stopped,
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    DebugStopped,
 }
 
 #[cfg(test)]
@@ -618,6 +817,8 @@ mod tests {
 
     use super::*;
     use crate::vstate::vm::{tests::setup_vm, Vm};
+    #[cfg(target_arch = "x86_64")]
+    use arch::x86_64::{BootProtocol, EntryPoint};
     use utils::signal::validate_signal_num;
     use vm_memory::{GuestAddress, GuestMemoryMmap};
 
@@ -628,13 +829,21 @@ mod tests {
             match self {
                 Paused | Resumed | Exited(_) => (),
                 Error(_) | NotAllowed(_) | RestoredState | SavedState(_) => (),
+                #[cfg(target_arch = "x86_64")]
+                PauseNotified => (),
+                #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+                Debug(_) => (),
             };
             match (self, other) {
                 (Paused, Paused) | (Resumed, Resumed) => true,
+                #[cfg(target_arch = "x86_64")]
+                (PauseNotified, PauseNotified) => true,
                 (Exited(code), Exited(other_code)) => code == other_code,
                 (NotAllowed(_), NotAllowed(_))
                 | (RestoredState, RestoredState)
                 | (SavedState(_), SavedState(_)) => true,
+                #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+                (Debug(_), Debug(_)) => true,
                 (Error(ref err), Error(ref other_err)) => {
                     format!("{:?}", err) == format!("{:?}", other_err)
                 }
@@ -648,12 +857,16 @@ mod tests {
             use crate::VcpuResponse::*;
             match self {
                 Paused => write!(f, "VcpuResponse::Paused"),
+                #[cfg(target_arch = "x86_64")]
+                PauseNotified => write!(f, "VcpuResponse::PauseNotified"),
                 Resumed => write!(f, "VcpuResponse::Resumed"),
                 Exited(code) => write!(f, "VcpuResponse::Exited({:?})", code),
                 RestoredState => write!(f, "VcpuResponse::RestoredState"),
                 SavedState(_) => write!(f, "VcpuResponse::SavedState"),
                 Error(ref err) => write!(f, "VcpuResponse::Error({:?})", err),
                 NotAllowed(ref reason) => write!(f, "VcpuResponse::NotAllowed({})", reason),
+                #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+                Debug(_) => write!(f, "VcpuResponse::Debug"),
             }
         }
     }
@@ -661,6 +874,10 @@ mod tests {
     // In tests we need to close any pending Vcpu threads on test completion.
     impl Drop for VcpuHandle {
         fn drop(&mut self) {
+            // The Vcpu thread was already joined.
+            if self.vcpu_thread.is_none() {
+                return;
+            }
             // Make sure the Vcpu is out of KVM_RUN.
             self.send_event(VcpuEvent::Pause).unwrap();
             // Close the original channel so that the Vcpu thread errors and goes to exit state.
@@ -708,7 +925,9 @@ mod tests {
 
         let mut kernel_file = File::open(kernel_path).expect("Cannot open kernel file");
 
-        kernel::loader::load_kernel(vm_memory, &mut kernel_file, 0).expect("Failed to load kernel")
+        kernel::loader::load_kernel(vm_memory, &mut kernel_file, 0)
+            .expect("Failed to load kernel")
+            .entry_addr
     }
 
     fn vcpu_configured_for_boot() -> (VcpuHandle, utils::eventfd::EventFd) {
@@ -732,11 +951,20 @@ mod tests {
                 vcpu_count: 1,
                 ht_enabled: false,
                 cpu_template: None,
+                cpu_topology: None,
+                nested_virt_enabled: false,
+                msr_policy: None,
+                custom_cpu_template: None,
+                cpu_features: None,
+                cpuid_normalization_enabled: false,
             };
             vcpu.kvm_vcpu
                 .configure(
                     &vm_mem,
-                    entry_addr,
+                    Some(EntryPoint {
+                        entry_addr,
+                        protocol: BootProtocol::LinuxBoot,
+                    }),
                     &vcpu_config,
                     vm.supported_cpuid().clone(),
                 )
@@ -759,6 +987,26 @@ mod tests {
         assert!(vcpu.kvm_vcpu.mmio_bus.is_some());
     }
 
+    #[test]
+    fn test_vcpu_exit_metrics() {
+        let (_, vcpu, _) = setup_vcpu(0x1000);
+        assert!(Arc::ptr_eq(
+            &vcpu.exit_metrics,
+            &METRICS.vcpu.vcpus.get(vcpu.kvm_vcpu.index)
+        ));
+
+        let epfaults = vcpu.exit_metrics.exit_epfault.count();
+        let interrupts = vcpu.exit_metrics.exit_interrupts.count();
+        assert!(vcpu
+            .handle_exit(Err(kvm_ioctls::Error::new(libc::EFAULT)))
+            .is_err());
+        assert!(vcpu
+            .handle_exit(Err(kvm_ioctls::Error::new(libc::EAGAIN)))
+            .is_ok());
+        assert!(vcpu.exit_metrics.exit_epfault.count() > epfaults);
+        assert!(vcpu.exit_metrics.exit_interrupts.count() > interrupts);
+    }
+
     #[test]
     fn test_vcpu_tls() {
         let (_, mut vcpu, _) = setup_vcpu(0x1000);
@@ -872,12 +1120,28 @@ mod tests {
         // Queue another Pause event, expect a response.
         queue_event_expect_response(&vcpu_handle, VcpuEvent::Pause, VcpuResponse::Paused);
 
+        // The guest can be told about the pause before resuming.
+        #[cfg(target_arch = "x86_64")]
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::NotifyPause,
+            VcpuResponse::PauseNotified,
+        );
+
         // Queue a Resume event, expect a response.
         queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
 
         // Queue another Resume event, expect a response.
         queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
 
+        // But not while running.
+        #[cfg(target_arch = "x86_64")]
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::NotifyPause,
+            VcpuResponse::NotAllowed(String::new()),
+        );
+
         // Queue another Pause event, expect a response.
         queue_event_expect_response(&vcpu_handle, VcpuEvent::Pause, VcpuResponse::Paused);
 
@@ -934,6 +1198,48 @@ mod tests {
         );
     }
 
+    #[cfg(all(feature = "gdb", target_arch = "x86_64"))]
+    #[test]
+    fn test_vcpu_debug_events() {
+        let (vcpu_handle, _vcpu_exit_evt) = vcpu_configured_for_boot();
+
+        // A paused vCPU serves the requests of the debugger.
+        vcpu_handle
+            .send_event(VcpuEvent::Debug(DebugRequest::GetRegs))
+            .expect("failed to send event to vcpu");
+        let regs = match vcpu_handle
+            .response_receiver()
+            .recv_timeout(Duration::from_millis(1000))
+            .expect("did not receive event response from vcpu")
+        {
+            VcpuResponse::Debug(DebugResponse::Regs(regs)) => regs,
+            _ => panic!("unexpected response"),
+        };
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::Debug(DebugRequest::SetRegs(regs)),
+            VcpuResponse::Debug(DebugResponse::Done),
+        );
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::Debug(DebugRequest::SetGuestDebug(Box::new(
+                kvm_bindings::kvm_guest_debug {
+                    control: kvm_bindings::KVM_GUESTDBG_ENABLE,
+                    ..Default::default()
+                },
+            ))),
+            VcpuResponse::Debug(DebugResponse::Done),
+        );
+
+        // A running one doesn't.
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::Debug(DebugRequest::GetSregs),
+            VcpuResponse::NotAllowed(String::new()),
+        );
+    }
+
     #[test]
     fn test_vcpu_rtsig_offset() {
         assert!(validate_signal_num(sigrtmin() + VCPU_RTSIG_OFFSET).is_ok());
@@ -0,0 +1,91 @@
+// Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use crate::vmm_config::cpu_quota::{CPU_QUOTA_PERIOD_US, UNLIMITED_CPU_QUOTA_PCT};
+
+/// Keeps the CPU time used by a vCPU thread within its quota, by pausing the thread for the rest
+/// of the accounting period once the quota of the period is used up.
+pub struct CpuThrottler {
+    // The quota in percents of a host core, shared by the VMM with all the vCPUs.
+    quota_pct: Arc<AtomicU8>,
+    // When the current accounting period started.
+    period_start_us: u64,
+    // The CPU time used by the thread when the current accounting period started.
+    period_cpu_time_us: u64,
+}
+
+impl CpuThrottler {
+    /// Creates a throttler for the quota `quota_pct`, which may change at any time.
+    pub fn new(quota_pct: Arc<AtomicU8>) -> Self {
+        CpuThrottler {
+            quota_pct,
+            period_start_us: 0,
+            period_cpu_time_us: 0,
+        }
+    }
+
+    /// Specifies if the quota limits the thread.
+    pub fn is_limited(&self) -> bool {
+        self.quota_pct.load(Ordering::Relaxed) < UNLIMITED_CPU_QUOTA_PCT
+    }
+
+    /// Returns how long the thread must be paused to stay within its quota, given the current
+    /// time and the CPU time used by the thread so far.
+    pub fn pause_time_us(&mut self, now_us: u64, cpu_time_us: u64) -> u64 {
+        let quota_pct = u64::from(self.quota_pct.load(Ordering::Relaxed));
+        let period_end_us = self.period_start_us + CPU_QUOTA_PERIOD_US;
+        if quota_pct >= u64::from(UNLIMITED_CPU_QUOTA_PCT) || now_us >= period_end_us {
+            self.start_period(now_us, cpu_time_us);
+            return 0;
+        }
+
+        let used_us = cpu_time_us.saturating_sub(self.period_cpu_time_us);
+        if used_us < CPU_QUOTA_PERIOD_US * quota_pct / 100 {
+            return 0;
+        }
+        // The next period starts when the thread wakes up.
+        self.start_period(period_end_us, cpu_time_us);
+        period_end_us - now_us
+    }
+
+    fn start_period(&mut self, start_us: u64, cpu_time_us: u64) {
+        self.period_start_us = start_us;
+        self.period_cpu_time_us = cpu_time_us;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause_time() {
+        let quota_pct = Arc::new(AtomicU8::new(UNLIMITED_CPU_QUOTA_PCT));
+        let mut throttler = CpuThrottler::new(quota_pct.clone());
+        assert!(!throttler.is_limited());
+        assert_eq!(throttler.pause_time_us(1_000_000, 500_000), 0);
+        assert_eq!(throttler.pause_time_us(1_100_000, 600_000), 0);
+
+        // Half of the period is allowed, the thread must wait for the next one once it is used.
+        quota_pct.store(50, Ordering::Relaxed);
+        assert!(throttler.is_limited());
+        assert_eq!(throttler.pause_time_us(1_140_000, 640_000), 0);
+        assert_eq!(throttler.pause_time_us(1_150_000, 650_000), 50_000);
+
+        // The time spent paused is not accounted for.
+        assert_eq!(throttler.pause_time_us(1_200_000, 650_000), 0);
+        assert_eq!(throttler.pause_time_us(1_260_000, 690_000), 0);
+        assert_eq!(throttler.pause_time_us(1_290_000, 700_000), 10_000);
+
+        // The periods in which the thread was idle are skipped.
+        assert_eq!(throttler.pause_time_us(2_000_000, 700_000), 0);
+        assert_eq!(throttler.pause_time_us(2_050_000, 749_999), 0);
+
+        // Lifting the quota takes effect right away.
+        quota_pct.store(UNLIMITED_CPU_QUOTA_PCT, Ordering::Relaxed);
+        assert_eq!(throttler.pause_time_us(2_060_000, 760_000), 0);
+    }
+}
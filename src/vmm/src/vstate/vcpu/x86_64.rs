@@ -10,27 +10,59 @@ use std::{
     result,
 };
 
-use crate::vmm_config::machine_config::CpuFeaturesTemplate;
+use crate::vmm_config::machine_config::{CpuFeaturesTemplate, MsrPolicy};
 use crate::vstate::{
     vcpu::{VcpuConfig, VcpuEmulation},
     vm::Vm,
 };
-use cpuid::{c3, filter_cpuid, t2, VmSpec};
+use arch::x86_64::EntryPoint;
+use cpuid::common::VENDOR_ID_INTEL;
+use cpuid::custom::CustomCpuTemplate;
+use cpuid::features::set_cpuid_features;
+use cpuid::normalize::normalize_cpuid;
+use cpuid::{c3, disable_nested_virt, filter_cpuid, t2, t2a, VmSpec};
+#[cfg(feature = "gdb")]
+use kvm_bindings::kvm_guest_debug;
 use kvm_bindings::{
     kvm_debugregs, kvm_lapic_state, kvm_mp_state, kvm_regs, kvm_sregs, kvm_vcpu_events, kvm_xcrs,
     kvm_xsave, CpuId, MsrList, Msrs,
 };
 use kvm_ioctls::{VcpuExit, VcpuFd};
 use logger::{error, IncMetric, METRICS};
-use versionize::{VersionMap, Versionize, VersionizeResult};
+#[cfg(feature = "gdb")]
+use utils::ioctl::ioctl_with_ref;
+use utils::ioctl::{ioctl, ioctl_with_val};
+#[cfg(feature = "gdb")]
+use utils::ioctl_iow_nr;
+use utils::{ioctl_expr, ioctl_io_nr, ioctl_ioc_nr};
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
-use vm_memory::{Address, GuestAddress, GuestMemoryMmap};
+use vm_memory::GuestMemoryMmap;
+
+ioctl_io_nr!(KVM_KVMCLOCK_CTRL, kvm_bindings::KVMIO, 0xad);
+ioctl_io_nr!(KVM_SET_TSC_KHZ, kvm_bindings::KVMIO, 0xa2);
+ioctl_io_nr!(KVM_GET_TSC_KHZ, kvm_bindings::KVMIO, 0xa3);
+
+// The exits of the MSR accesses denied by the filter of the VM, when they read as zero.
+const KVM_EXIT_X86_RDMSR: u32 = 29;
+const KVM_EXIT_X86_WRMSR: u32 = 30;
+#[cfg(feature = "gdb")]
+ioctl_iow_nr!(
+    KVM_SET_GUEST_DEBUG,
+    kvm_bindings::KVMIO,
+    0x9b,
+    kvm_guest_debug
+);
 
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug)]
 pub enum Error {
+    /// The forced CPU features cannot be set.
+    CpuFeatures(cpuid::features::Error),
     /// A call to cpuid instruction failed.
     CpuId(cpuid::Error),
+    /// The custom CPU template cannot be applied.
+    CustomCpuTemplate(cpuid::custom::Error),
     /// Error configuring the floating point related registers
     FPUConfiguration(arch::x86_64::regs::Error),
     /// Cannot set the local interruption due to bad configuration.
@@ -57,6 +89,8 @@ pub enum Error {
     VcpuGetRegs(kvm_ioctls::Error),
     /// Failed to get KVM vcpu sregs.
     VcpuGetSregs(kvm_ioctls::Error),
+    /// Failed to get the KVM vcpu TSC frequency.
+    VcpuGetTscKhz(kvm_ioctls::Error),
     /// Failed to get KVM vcpu event.
     VcpuGetVcpuEvents(kvm_ioctls::Error),
     /// Failed to get KVM vcpu xcrs.
@@ -65,10 +99,15 @@ pub enum Error {
     VcpuGetXsave(kvm_ioctls::Error),
     /// Failed to get KVM vcpu cpuid.
     VcpuGetCpuid(kvm_ioctls::Error),
+    /// Failed to tell the guest that it was paused.
+    VcpuKvmClockCtrl(kvm_ioctls::Error),
     /// Failed to set KVM vcpu cpuid.
     VcpuSetCpuid(kvm_ioctls::Error),
     /// Failed to set KVM vcpu debug regs.
     VcpuSetDebugRegs(kvm_ioctls::Error),
+    /// Failed to set the KVM vcpu breakpoints and single stepping.
+    #[cfg(feature = "gdb")]
+    VcpuSetGuestDebug(kvm_ioctls::Error),
     /// Failed to set KVM vcpu lapic.
     VcpuSetLapic(kvm_ioctls::Error),
     /// Failed to set KVM vcpu mp state.
@@ -79,6 +118,8 @@ pub enum Error {
     VcpuSetRegs(kvm_ioctls::Error),
     /// Failed to set KVM vcpu sregs.
     VcpuSetSregs(kvm_ioctls::Error),
+    /// Failed to scale the KVM vcpu TSC to the given frequency, in kHz.
+    VcpuSetTscKhz(u32, kvm_ioctls::Error),
     /// Failed to set KVM vcpu event.
     VcpuSetVcpuEvents(kvm_ioctls::Error),
     /// Failed to set KVM vcpu xcrs.
@@ -92,7 +133,9 @@ impl Display for Error {
         use self::Error::*;
 
         match self {
+            CpuFeatures(e) => write!(f, "Cannot set the CPU features: {}", e),
             CpuId(e) => write!(f, "Cpuid error: {:?}", e),
+            CustomCpuTemplate(e) => write!(f, "Cannot apply the custom CPU template: {}", e),
             LocalIntConfiguration(e) => write!(
                 f,
                 "Cannot set the local interruption due to bad configuration: {:?}",
@@ -118,17 +161,27 @@ impl Display for Error {
             VcpuGetMSRSIncomplete => write!(f, "Unexpected number of MSRS reported by the kernel"),
             VcpuGetRegs(e) => write!(f, "Failed to get KVM vcpu regs: {}", e),
             VcpuGetSregs(e) => write!(f, "Failed to get KVM vcpu sregs: {}", e),
+            VcpuGetTscKhz(e) => write!(f, "Failed to get KVM vcpu TSC frequency: {}", e),
             VcpuGetVcpuEvents(e) => write!(f, "Failed to get KVM vcpu event: {}", e),
             VcpuGetXcrs(e) => write!(f, "Failed to get KVM vcpu xcrs: {}", e),
             VcpuGetXsave(e) => write!(f, "Failed to get KVM vcpu xsave: {}", e),
             VcpuGetCpuid(e) => write!(f, "Failed to get KVM vcpu cpuid: {}", e),
+            VcpuKvmClockCtrl(e) => write!(f, "Failed to notify the guest of the pause: {}", e),
             VcpuSetCpuid(e) => write!(f, "Failed to set KVM vcpu cpuid: {}", e),
             VcpuSetDebugRegs(e) => write!(f, "Failed to set KVM vcpu debug regs: {}", e),
+            #[cfg(feature = "gdb")]
+            VcpuSetGuestDebug(e) => write!(f, "Failed to set KVM vcpu guest debug: {}", e),
             VcpuSetLapic(e) => write!(f, "Failed to set KVM vcpu lapic: {}", e),
             VcpuSetMpState(e) => write!(f, "Failed to set KVM vcpu mp state: {}", e),
             VcpuSetMsrs(e) => write!(f, "Failed to set KVM vcpu msrs: {}", e),
             VcpuSetRegs(e) => write!(f, "Failed to set KVM vcpu regs: {}", e),
             VcpuSetSregs(e) => write!(f, "Failed to set KVM vcpu sregs: {}", e),
+            VcpuSetTscKhz(khz, e) => write!(
+                f,
+                "Failed to scale the KVM vcpu TSC to {} kHz, the host may not support TSC \
+                 scaling: {}",
+                khz, e
+            ),
             VcpuSetVcpuEvents(e) => write!(f, "Failed to set KVM vcpu event: {}", e),
             VcpuSetXcrs(e) => write!(f, "Failed to set KVM vcpu xcrs: {}", e),
             VcpuSetXsave(e) => write!(f, "Failed to set KVM vcpu xsave: {}", e),
@@ -147,6 +200,7 @@ pub struct KvmVcpu {
     pub mmio_bus: Option<devices::Bus>,
 
     msr_list: MsrList,
+    msr_policy: Option<MsrPolicy>,
 }
 
 impl KvmVcpu {
@@ -165,6 +219,7 @@ impl KvmVcpu {
             pio_bus: None,
             mmio_bus: None,
             msr_list: vm.supported_msrs().clone(),
+            msr_policy: None,
         })
     }
 
@@ -173,18 +228,29 @@ impl KvmVcpu {
     /// # Arguments
     ///
     /// * `guest_mem` - The guest memory used by this microvm.
-    /// * `kernel_start_addr` - Offset from `guest_mem` at which the kernel starts.
+    /// * `kernel_entry_point` - Address at which the kernel starts, and its boot protocol. Without
+    ///   a kernel, the vCPU starts a firmware at the reset vector.
     /// * `vcpu_config` - The vCPU configuration.
     /// * `cpuid` - The capabilities exposed by this vCPU.
     pub fn configure(
         &mut self,
         guest_mem: &GuestMemoryMmap,
-        kernel_start_addr: GuestAddress,
+        kernel_entry_point: Option<EntryPoint>,
         vcpu_config: &VcpuConfig,
         mut cpuid: CpuId,
     ) -> Result<()> {
-        let cpuid_vm_spec = VmSpec::new(self.index, vcpu_config.vcpu_count, vcpu_config.ht_enabled)
-            .map_err(Error::CpuId)?;
+        let cpuid_vm_spec = match vcpu_config.cpu_topology {
+            Some(topology) => VmSpec::with_topology(
+                self.index,
+                topology.sockets,
+                topology.cores_per_socket,
+                topology.threads_per_core,
+            ),
+            None => VmSpec::new(self.index, vcpu_config.vcpu_count, vcpu_config.ht_enabled),
+        }
+        .map_err(Error::CpuId)?;
+        // The features which are enabled have to be supported by the host.
+        let supported_cpuid = cpuid.clone();
 
         filter_cpuid(&mut cpuid, &cpuid_vm_spec).map_err(|e| {
             METRICS.vcpu.filter_cpuid.inc();
@@ -194,6 +260,10 @@ impl KvmVcpu {
             );
             Error::CpuId(e)
         })?;
+        // The templates are applied on top of the synthesized leaves.
+        if vcpu_config.cpuid_normalization_enabled {
+            normalize_cpuid(&mut cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?;
+        }
 
         if let Some(template) = vcpu_config.cpu_template {
             match template {
@@ -203,17 +273,65 @@ impl KvmVcpu {
                 CpuFeaturesTemplate::C3 => {
                     c3::set_cpuid_entries(&mut cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?
                 }
+                CpuFeaturesTemplate::T2A => {
+                    t2a::set_cpuid_entries(&mut cpuid, &cpuid_vm_spec).map_err(Error::CpuId)?
+                }
             }
         }
+        if let Some(template) = vcpu_config.custom_cpu_template.as_ref() {
+            template
+                .apply_cpuid(&mut cpuid)
+                .map_err(Error::CustomCpuTemplate)?;
+        }
+        if let Some(features) = vcpu_config.cpu_features.as_ref() {
+            set_cpuid_features(
+                &mut cpuid,
+                &supported_cpuid,
+                &features.enable,
+                &features.disable,
+            )
+            .map_err(Error::CpuFeatures)?;
+        }
+
+        if !vcpu_config.nested_virt_enabled {
+            disable_nested_virt(&mut cpuid);
+        }
 
         self.fd.set_cpuid2(&cpuid).map_err(Error::VcpuSetCpuid)?;
 
         arch::x86_64::msr::setup_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
-        arch::x86_64::regs::setup_regs(&self.fd, kernel_start_addr.raw_value() as u64)
-            .map_err(Error::REGSConfiguration)?;
-        arch::x86_64::regs::setup_fpu(&self.fd).map_err(Error::FPUConfiguration)?;
-        arch::x86_64::regs::setup_sregs(guest_mem, &self.fd).map_err(Error::SREGSConfiguration)?;
+        // AMD guests turn SVM on through EFER, which needs no setup.
+        if vcpu_config.nested_virt_enabled && cpuid_vm_spec.cpu_vendor_id() == VENDOR_ID_INTEL {
+            arch::x86_64::msr::setup_nested_vmx_msrs(&self.fd).map_err(Error::MSRSConfiguration)?;
+        }
+        if let Some(template) = vcpu_config.custom_cpu_template.as_ref() {
+            self.apply_msr_template(template)?;
+        }
+        // A firmware starts from the state KVM creates the vCPU in, in real mode at the reset
+        // vector, like a CPU being powered on.
+        if let Some(entry_point) = kernel_entry_point {
+            arch::x86_64::regs::setup_regs(&self.fd, entry_point)
+                .map_err(Error::REGSConfiguration)?;
+            arch::x86_64::regs::setup_fpu(&self.fd).map_err(Error::FPUConfiguration)?;
+            arch::x86_64::regs::setup_sregs(guest_mem, &self.fd, entry_point.protocol)
+                .map_err(Error::SREGSConfiguration)?;
+        }
         arch::x86_64::interrupts::set_lint(&self.fd).map_err(Error::LocalIntConfiguration)?;
+        // The policy is enforced by the filter of the VM, it is only kept for the snapshots.
+        self.msr_policy = vcpu_config.msr_policy.clone();
+        Ok(())
+    }
+
+    // Modifies the MSRs of the vCPU as `template` describes, from the values they hold.
+    fn apply_msr_template(&self, template: &CustomCpuTemplate) -> Result<()> {
+        let mut msrs = Msrs::from_entries(&template.msr_entries());
+        let num_msrs = msrs.as_fam_struct_ref().nmsrs as usize;
+        let nmsrs = self.fd.get_msrs(&mut msrs).map_err(Error::VcpuGetMsrs)?;
+        if nmsrs != num_msrs {
+            return Err(Error::VcpuGetMSRSIncomplete);
+        }
+        template.apply_msrs(&mut msrs);
+        self.fd.set_msrs(&msrs).map_err(Error::VcpuSetMsrs)?;
         Ok(())
     }
 
@@ -273,6 +391,7 @@ impl KvmVcpu {
             .fd
             .get_vcpu_events()
             .map_err(Error::VcpuGetVcpuEvents)?;
+        let tsc_khz = self.get_tsc_khz()?;
 
         Ok(VcpuState {
             cpuid: self
@@ -288,11 +407,13 @@ impl KvmVcpu {
             vcpu_events,
             xcrs,
             xsave,
+            msr_policy: self.msr_policy.clone(),
+            tsc_khz: Some(tsc_khz),
         })
     }
 
     /// Use provided state to populate KVM internal state.
-    pub fn restore_state(&self, state: &VcpuState) -> Result<()> {
+    pub fn restore_state(&mut self, state: &VcpuState) -> Result<()> {
         /*
          * Ordering requirements:
          *
@@ -314,7 +435,17 @@ impl KvmVcpu {
          *
          * SET_LAPIC must come before SET_MSRS, because the TSC deadline MSR
          * only restores successfully, when the LAPIC is correctly configured.
+         *
+         * SET_TSC_KHZ must come before SET_MSRS, so that the TSC MSR is restored
+         * at the frequency of the source host.
          */
+        if let Some(tsc_khz) = state.tsc_khz {
+            // The guest calibrated its clocks against the TSC frequency of the source host,
+            // so the TSC is scaled when this host runs at a different frequency.
+            if self.get_tsc_khz()? != tsc_khz {
+                self.set_tsc_khz(tsc_khz)?;
+            }
+        }
         self.fd
             .set_cpuid2(&state.cpuid)
             .map_err(Error::VcpuSetCpuid)?;
@@ -339,9 +470,82 @@ impl KvmVcpu {
         self.fd
             .set_vcpu_events(&state.vcpu_events)
             .map_err(Error::VcpuSetVcpuEvents)?;
+        self.msr_policy = state.msr_policy.clone();
         Ok(())
     }
 
+    /// Returns the TSC frequency of the vCPU, in kHz. Until it is scaled, it is the one of the
+    /// host.
+    pub fn get_tsc_khz(&self) -> Result<u32> {
+        // Safe because we know that our file is a vCPU fd, the ioctl takes no argument and we
+        // verify the return result.
+        let ret = unsafe { ioctl(&self.fd, KVM_GET_TSC_KHZ()) };
+        if ret < 0 {
+            return Err(Error::VcpuGetTscKhz(kvm_ioctls::Error::last()));
+        }
+        Ok(ret as u32)
+    }
+
+    // Scales the TSC of the vCPU to `tsc_khz`. It fails when the host can't scale the TSC.
+    fn set_tsc_khz(&self, tsc_khz: u32) -> Result<()> {
+        // Safe because we know that our file is a vCPU fd, the ioctl takes the frequency by
+        // value and we verify the return result.
+        let ret = unsafe { ioctl_with_val(&self.fd, KVM_SET_TSC_KHZ(), u64::from(tsc_khz)) };
+        if ret < 0 {
+            return Err(Error::VcpuSetTscKhz(tsc_khz, kvm_ioctls::Error::last()));
+        }
+        Ok(())
+    }
+
+    /// Tells the guest that it was paused, so that its soft lockup detector ignores the time
+    /// jump once it resumes. The vCPU must be paused. Guests which don't use the KVM clock are
+    /// left alone.
+    pub fn notify_pause(&self) -> Result<()> {
+        // Safe because we know that our file is a vCPU fd, the ioctl takes no argument and we
+        // verify the return result.
+        let ret = unsafe { ioctl(&self.fd, KVM_KVMCLOCK_CTRL()) };
+        if ret < 0 {
+            let err = kvm_ioctls::Error::last();
+            // The guest hasn't set the KVM clock up.
+            if err.errno() != libc::EINVAL {
+                return Err(Error::VcpuKvmClockCtrl(err));
+            }
+        }
+        Ok(())
+    }
+
+    /// Serves a request of the debugger. The vCPU must be paused.
+    #[cfg(feature = "gdb")]
+    pub fn debug(&self, request: DebugRequest) -> Result<DebugResponse> {
+        match request {
+            DebugRequest::GetRegs => self
+                .fd
+                .get_regs()
+                .map(|regs| DebugResponse::Regs(Box::new(regs)))
+                .map_err(Error::VcpuGetRegs),
+            DebugRequest::GetSregs => self
+                .fd
+                .get_sregs()
+                .map(|sregs| DebugResponse::Sregs(Box::new(sregs)))
+                .map_err(Error::VcpuGetSregs),
+            DebugRequest::SetRegs(regs) => self
+                .fd
+                .set_regs(&regs)
+                .map(|()| DebugResponse::Done)
+                .map_err(Error::VcpuSetRegs),
+            DebugRequest::SetGuestDebug(debug) => {
+                // Safe because we know that our file is a vCPU fd, we know the kernel will only
+                // read the correct amount of memory from our pointer, and we verify the return
+                // result.
+                let ret = unsafe { ioctl_with_ref(&self.fd, KVM_SET_GUEST_DEBUG(), &*debug) };
+                if ret < 0 {
+                    return Err(Error::VcpuSetGuestDebug(kvm_ioctls::Error::last()));
+                }
+                Ok(DebugResponse::Done)
+            }
+        }
+    }
+
     /// Runs the vCPU in KVM context and handles the kvm exit reason.
     ///
     /// Returns error or enum specifying whether emulation was handled or interrupted.
@@ -361,6 +565,13 @@ impl KvmVcpu {
                 }
                 Ok(VcpuEmulation::Handled)
             }
+            // A breakpoint was hit or a single step completed.
+            #[cfg(feature = "gdb")]
+            VcpuExit::Debug => Ok(VcpuEmulation::DebugStopped),
+            // An access to an MSR denied by the policy. KVM has set the value read to zero
+            // and the error to none before exiting, so the guest just goes on.
+            VcpuExit::Unsupported(KVM_EXIT_X86_RDMSR)
+            | VcpuExit::Unsupported(KVM_EXIT_X86_WRMSR) => Ok(VcpuEmulation::Handled),
             unexpected_exit => {
                 METRICS.vcpu.failures.inc();
                 // TODO: Are we sure we want to finish running a vcpu upon
@@ -375,6 +586,31 @@ impl KvmVcpu {
     }
 }
 
+/// Requests of the debugger, served by a paused vCPU.
+#[cfg(feature = "gdb")]
+#[derive(Clone)]
+pub enum DebugRequest {
+    /// Get the general purpose registers.
+    GetRegs,
+    /// Get the special registers, which tell how the guest virtual addresses are translated.
+    GetSregs,
+    /// Set the general purpose registers.
+    SetRegs(Box<kvm_regs>),
+    /// Set the breakpoints and the single stepping of the vCPU.
+    SetGuestDebug(Box<kvm_guest_debug>),
+}
+
+/// Responses of a vCPU to the requests of the debugger.
+#[cfg(feature = "gdb")]
+pub enum DebugResponse {
+    /// The request was served.
+    Done,
+    /// The general purpose registers.
+    Regs(Box<kvm_regs>),
+    /// The special registers.
+    Sregs(Box<kvm_sregs>),
+}
+
 #[derive(Clone, Versionize)]
 /// Structure holding VCPU kvm state.
 // NOTICE: Any changes to this structure require a snapshot version bump.
@@ -389,6 +625,34 @@ pub struct VcpuState {
     vcpu_events: kvm_vcpu_events,
     xcrs: kvm_xcrs,
     xsave: kvm_xsave,
+    #[version(start = 2, ser_fn = "msr_policy_serialize")]
+    pub msr_policy: Option<MsrPolicy>,
+    /// The TSC frequency of the source host, in kHz. It is unknown for the snapshots of the
+    /// first version.
+    #[version(start = 2)]
+    pub tsc_khz: Option<u32>,
+}
+
+impl VcpuState {
+    /// The general purpose registers of the vCPU.
+    pub fn regs(&self) -> &kvm_regs {
+        &self.regs
+    }
+
+    /// The special registers of the vCPU.
+    pub fn sregs(&self) -> &kvm_sregs {
+        &self.sregs
+    }
+
+    fn msr_policy_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.msr_policy.is_some() {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the MSR policy.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -398,8 +662,15 @@ mod tests {
     use std::os::unix::io::AsRawFd;
 
     use super::*;
+    use crate::version_map::VERSION_MAP;
+    use crate::vmm_config::machine_config::{
+        nested_virt_supported, CpuFeaturesConfig, CpuTopology, MsrRange,
+    };
     use crate::vstate::vm::{tests::setup_vm, Vm};
-    use cpuid::common::{get_vendor_id_from_host, VENDOR_ID_INTEL};
+    use arch::x86_64::BootProtocol;
+    use cpuid::common::get_vendor_id_from_host;
+    use kvm_bindings::{kvm_msr_entry, KVM_MAX_CPUID_ENTRIES};
+    use vm_memory::GuestAddress;
 
     impl Default for VcpuState {
         fn default() -> Self {
@@ -414,6 +685,8 @@ mod tests {
                 vcpu_events: Default::default(),
                 xcrs: Default::default(),
                 xsave: Default::default(),
+                msr_policy: None,
+                tsc_khz: None,
             }
         }
     }
@@ -433,12 +706,21 @@ mod tests {
             vcpu_count: 1,
             ht_enabled: false,
             cpu_template: None,
+            cpu_topology: None,
+            nested_virt_enabled: false,
+            msr_policy: None,
+            custom_cpu_template: None,
+            cpu_features: None,
+            cpuid_normalization_enabled: false,
         };
 
         assert!(vcpu
             .configure(
                 &vm_mem,
-                GuestAddress(0),
+                Some(EntryPoint {
+                    entry_addr: GuestAddress(0),
+                    protocol: BootProtocol::LinuxBoot,
+                }),
                 &vcpu_config,
                 vm.supported_cpuid().clone()
             )
@@ -448,7 +730,10 @@ mod tests {
         vcpu_config.cpu_template = Some(CpuFeaturesTemplate::T2);
         let t2_res = vcpu.configure(
             &vm_mem,
-            GuestAddress(arch::get_kernel_start()),
+            Some(EntryPoint {
+                entry_addr: GuestAddress(arch::get_kernel_start()),
+                protocol: BootProtocol::PvhBoot,
+            }),
             &vcpu_config,
             vm.supported_cpuid().clone(),
         );
@@ -457,7 +742,22 @@ mod tests {
         vcpu_config.cpu_template = Some(CpuFeaturesTemplate::C3);
         let c3_res = vcpu.configure(
             &vm_mem,
-            GuestAddress(0),
+            Some(EntryPoint {
+                entry_addr: GuestAddress(0),
+                protocol: BootProtocol::LinuxBoot,
+            }),
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        );
+
+        // Test configure while using the T2A template.
+        vcpu_config.cpu_template = Some(CpuFeaturesTemplate::T2A);
+        let t2a_res = vcpu.configure(
+            &vm_mem,
+            Some(EntryPoint {
+                entry_addr: GuestAddress(0),
+                protocol: BootProtocol::LinuxBoot,
+            }),
             &vcpu_config,
             vm.supported_cpuid().clone(),
         );
@@ -466,24 +766,316 @@ mod tests {
             VENDOR_ID_INTEL => {
                 assert!(t2_res.is_ok());
                 assert!(c3_res.is_ok());
+                assert!(t2a_res.is_err());
             }
             _ => {
                 assert!(t2_res.is_err());
                 assert!(c3_res.is_err());
+                assert!(t2a_res.is_ok());
             }
         }
+
+        // Test configure with a topology of 2 sockets.
+        vcpu_config.cpu_template = None;
+        vcpu_config.vcpu_count = 4;
+        vcpu_config.ht_enabled = true;
+        vcpu_config.cpu_topology = Some(CpuTopology {
+            sockets: 2,
+            cores_per_socket: 1,
+            threads_per_core: 2,
+        });
+        assert!(vcpu
+            .configure(
+                &vm_mem,
+                Some(EntryPoint {
+                    entry_addr: GuestAddress(0),
+                    protocol: BootProtocol::LinuxBoot,
+                }),
+                &vcpu_config,
+                vm.supported_cpuid().clone()
+            )
+            .is_ok());
+
+        // Test configure with nested virtualization, when the host allows it.
+        if nested_virt_supported() {
+            vcpu_config.nested_virt_enabled = true;
+            assert!(vcpu
+                .configure(
+                    &vm_mem,
+                    Some(EntryPoint {
+                        entry_addr: GuestAddress(0),
+                        protocol: BootProtocol::LinuxBoot,
+                    }),
+                    &vcpu_config,
+                    vm.supported_cpuid().clone()
+                )
+                .is_ok());
+        }
     }
 
     #[test]
-    fn test_vcpu_cpuid_restore() {
+    fn test_configure_vcpu_with_custom_template() {
+        let (vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        // Hides the hypervisor from the CPUID, and disables the fast strings in
+        // IA32_MISC_ENABLE.
+        let template = CustomCpuTemplate::from_json(
+            r#"{
+                "cpuid_modifiers": [{
+                    "leaf": "0x1",
+                    "modifiers": [{
+                        "register": "ecx",
+                        "bitmap": "0b0xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx"
+                    }]
+                }],
+                "msr_modifiers": [{ "addr": "0x1a0", "bitmap": "0b0" }]
+            }"#,
+        )
+        .unwrap();
+        let vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cpu_template: None,
+            cpu_topology: None,
+            nested_virt_enabled: false,
+            msr_policy: None,
+            custom_cpu_template: Some(template),
+            cpu_features: None,
+            cpuid_normalization_enabled: false,
+        };
+        vcpu.configure(
+            &vm_mem,
+            Some(EntryPoint {
+                entry_addr: GuestAddress(0),
+                protocol: BootProtocol::LinuxBoot,
+            }),
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        )
+        .unwrap();
+
+        let cpuid = vcpu.fd.get_cpuid2(KVM_MAX_CPUID_ENTRIES).unwrap();
+        let entry = cpuid
+            .as_slice()
+            .iter()
+            .find(|entry| entry.function == 0x1)
+            .unwrap();
+        assert_eq!(entry.ecx >> 31, 0);
+        let mut msrs = Msrs::from_entries(&[kvm_msr_entry {
+            index: 0x1a0,
+            ..Default::default()
+        }]);
+        assert_eq!(vcpu.fd.get_msrs(&mut msrs).unwrap(), 1);
+        assert_eq!(msrs.as_slice()[0].data & 1, 0);
+    }
+
+    #[test]
+    fn test_configure_vcpu_with_cpu_features() {
+        let (vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        let mut vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cpu_template: None,
+            cpu_topology: None,
+            nested_virt_enabled: false,
+            msr_policy: None,
+            custom_cpu_template: None,
+            cpu_features: Some(CpuFeaturesConfig {
+                enable: vec![],
+                disable: vec!["sse4_2".to_string()],
+            }),
+            cpuid_normalization_enabled: false,
+        };
+        let entry_point = Some(EntryPoint {
+            entry_addr: GuestAddress(0),
+            protocol: BootProtocol::LinuxBoot,
+        });
+        vcpu.configure(
+            &vm_mem,
+            entry_point,
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        )
+        .unwrap();
+
+        let cpuid = vcpu.fd.get_cpuid2(KVM_MAX_CPUID_ENTRIES).unwrap();
+        let entry = cpuid
+            .as_slice()
+            .iter()
+            .find(|entry| entry.function == 0x1)
+            .unwrap();
+        assert_eq!(entry.ecx >> 20 & 1, 0);
+
+        // The Xeon Phi instructions are not supported by the hosts.
+        vcpu_config.cpu_features = Some(CpuFeaturesConfig {
+            enable: vec!["avx512_4fmaps".to_string()],
+            disable: vec![],
+        });
+        match vcpu.configure(
+            &vm_mem,
+            entry_point,
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        ) {
+            Err(Error::CpuFeatures(cpuid::features::Error::UnsupportedFeature(_))) => (),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
+    #[test]
+    fn test_configure_vcpu_with_cpuid_normalization() {
+        use cpuid::common::get_vendor_id_from_host;
+
+        let (vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        let vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cpu_template: None,
+            cpu_topology: None,
+            nested_virt_enabled: false,
+            msr_policy: None,
+            custom_cpu_template: None,
+            cpu_features: None,
+            cpuid_normalization_enabled: true,
+        };
+        vcpu.configure(
+            &vm_mem,
+            Some(EntryPoint {
+                entry_addr: GuestAddress(0),
+                protocol: BootProtocol::LinuxBoot,
+            }),
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        )
+        .unwrap();
+
+        // The L3 cache is the fourth subleaf of the cache parameters leaf.
+        let function = if &get_vendor_id_from_host().unwrap() == VENDOR_ID_INTEL {
+            0x4
+        } else {
+            0x8000_001d
+        };
+        let cpuid = vcpu.fd.get_cpuid2(KVM_MAX_CPUID_ENTRIES).unwrap();
+        let l3 = cpuid
+            .as_slice()
+            .iter()
+            .find(|entry| entry.function == function && entry.index == 3)
+            .unwrap();
+        assert_eq!(l3.eax >> 5 & 0x7, 3);
+        // 32 MiB in sets of 16 ways of 64 bytes lines.
+        assert_eq!(l3.ecx + 1, 32 << 10);
+    }
+
+    #[test]
+    fn test_configure_vcpu_for_firmware() {
+        let (vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        let vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cpu_template: None,
+            cpu_topology: None,
+            nested_virt_enabled: false,
+            msr_policy: None,
+            custom_cpu_template: None,
+            cpu_features: None,
+            cpuid_normalization_enabled: false,
+        };
+
+        vcpu.configure(&vm_mem, None, &vcpu_config, vm.supported_cpuid().clone())
+            .unwrap();
+
+        // The vCPU starts at the reset vector, right below 4 GiB.
+        let regs = vcpu.fd.get_regs().unwrap();
+        let sregs = vcpu.fd.get_sregs().unwrap();
+        assert_eq!(sregs.cs.base + regs.rip, 0xffff_fff0);
+        assert_eq!(sregs.cr0 & 1, 0);
+    }
+
+    #[test]
+    fn test_notify_pause() {
         let (_vm, vcpu, _) = setup_vcpu(0x1000);
+        // The guest didn't set the KVM clock up, which is not an error.
+        vcpu.notify_pause().unwrap();
+    }
+
+    #[test]
+    fn test_vcpu_msr_policy_state() {
+        let (vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
+        let policy = MsrPolicy {
+            deny: vec![MsrRange {
+                index: 0x10,
+                count: 1,
+            }],
+            ..Default::default()
+        };
+        let vcpu_config = VcpuConfig {
+            vcpu_count: 1,
+            ht_enabled: false,
+            cpu_template: None,
+            cpu_topology: None,
+            nested_virt_enabled: false,
+            msr_policy: Some(policy.clone()),
+            custom_cpu_template: None,
+            cpu_features: None,
+            cpuid_normalization_enabled: false,
+        };
+        vcpu.configure(&vm_mem, None, &vcpu_config, vm.supported_cpuid().clone())
+            .unwrap();
+        let state = vcpu.save_state().unwrap();
+        assert_eq!(state.msr_policy, Some(policy.clone()));
+
+        // The snapshots of the first version can't hold the policy.
+        let mut buf = vec![0; 0x10000];
+        assert!(state
+            .serialize(&mut buf.as_mut_slice(), &VERSION_MAP, 1)
+            .is_err());
+        state
+            .serialize(&mut buf.as_mut_slice(), &VERSION_MAP, 2)
+            .unwrap();
+        let state = VcpuState::deserialize(&mut buf.as_slice(), &VERSION_MAP, 2).unwrap();
+
+        let (_vm, mut vcpu, _) = setup_vcpu(0x10000);
+        vcpu.restore_state(&state).unwrap();
+        assert_eq!(vcpu.save_state().unwrap().msr_policy, Some(policy));
+    }
+
+    #[test]
+    fn test_vcpu_tsc_khz_state() {
+        let (_vm, mut vcpu, _) = setup_vcpu(0x1000);
+        let tsc_khz = vcpu.get_tsc_khz().unwrap();
+        assert!(tsc_khz > 0);
+        let mut state = vcpu.save_state().unwrap();
+        assert_eq!(state.tsc_khz, Some(tsc_khz));
+
+        // The frequency is unknown in the snapshots of the first version.
+        let mut buf = vec![0; 0x10000];
+        state
+            .serialize(&mut buf.as_mut_slice(), &VERSION_MAP, 1)
+            .unwrap();
+        let old_state = VcpuState::deserialize(&mut buf.as_slice(), &VERSION_MAP, 1).unwrap();
+        assert_eq!(old_state.tsc_khz, None);
+        vcpu.restore_state(&old_state).unwrap();
+        assert_eq!(vcpu.get_tsc_khz().unwrap(), tsc_khz);
+
+        // A snapshot taken on a slower host is scaled, unless the host can't scale the TSC.
+        let (_vm, mut vcpu, _) = setup_vcpu(0x1000);
+        state.tsc_khz = Some(tsc_khz / 2);
+        match vcpu.restore_state(&state) {
+            Ok(()) => assert_eq!(vcpu.get_tsc_khz().unwrap(), tsc_khz / 2),
+            Err(Error::VcpuSetTscKhz(khz, _)) => assert_eq!(khz, tsc_khz / 2),
+            Err(e) => panic!("Unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_vcpu_cpuid_restore() {
+        let (_vm, mut vcpu, _) = setup_vcpu(0x1000);
         let mut state = vcpu.save_state().unwrap();
         // Mutate the cpuid.
         state.cpuid.as_mut_slice()[0].eax = 0x1234_5678;
         assert!(vcpu.restore_state(&state).is_ok());
 
         unsafe { libc::close(vcpu.fd.as_raw_fd()) };
-        let (_vm, vcpu, _) = setup_vcpu(0x1000);
+        let (_vm, mut vcpu, _) = setup_vcpu(0x1000);
         assert!(vcpu.restore_state(&state).is_ok());
 
         // Validate the mutated cpuid is saved.
@@ -14,18 +14,64 @@ use std::{
 use arch::aarch64::gic::GICDevice;
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
-    kvm_clock_data, kvm_irqchip, kvm_pit_config, kvm_pit_state2, CpuId, MsrList,
-    KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE,
-    KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
+    kvm_clock_data, kvm_enable_cap, kvm_irqchip, kvm_pit_config, kvm_pit_state2,
+    kvm_reinject_control, CpuId, MsrList, KVMIO, KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC,
+    KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE, KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
 };
 use kvm_bindings::{kvm_userspace_memory_region, KVM_MEM_LOG_DIRTY_PAGES};
 use kvm_ioctls::{Kvm, VmFd};
 #[cfg(target_arch = "x86_64")]
-use versionize::{VersionMap, Versionize, VersionizeResult};
+use utils::ioctl::ioctl_with_ref;
+#[cfg(target_arch = "x86_64")]
+use utils::{ioctl_expr, ioctl_io_nr, ioctl_ioc_nr, ioctl_iow_nr};
+#[cfg(target_arch = "x86_64")]
+use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 #[cfg(target_arch = "x86_64")]
 use versionize_derive::Versionize;
 use vm_memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::machine_config::{MsrDenyAction, MsrPolicy, MsrRange, MAX_MSR_RANGES};
+
+// `kvm-ioctls` does not wrap these ioctls, so we define them here.
+#[cfg(target_arch = "x86_64")]
+ioctl_io_nr!(KVM_REINJECT_CONTROL, KVMIO, 0x71);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_SET_IDENTITY_MAP_ADDR, KVMIO, 0x48, u64);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_ENABLE_CAP, KVMIO, 0xa3, kvm_enable_cap);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_X86_SET_MSR_FILTER, KVMIO, 0xc6, KvmMsrFilter);
+
+// The MSR filtering of KVM, which `kvm-bindings` does not define either.
+#[cfg(target_arch = "x86_64")]
+const KVM_CAP_X86_USER_SPACE_MSR: u32 = 188;
+#[cfg(target_arch = "x86_64")]
+const KVM_MSR_EXIT_REASON_FILTER: u64 = 1 << 1;
+#[cfg(target_arch = "x86_64")]
+const KVM_MSR_FILTER_DEFAULT_ALLOW: u32 = 0;
+#[cfg(target_arch = "x86_64")]
+const KVM_MSR_FILTER_READ: u32 = 1 << 0;
+#[cfg(target_arch = "x86_64")]
+const KVM_MSR_FILTER_WRITE: u32 = 1 << 1;
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct KvmMsrFilterRange {
+    flags: u32,
+    nmsrs: u32,
+    base: u32,
+    bitmap: *const u8,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+struct KvmMsrFilter {
+    flags: u32,
+    ranges: [KvmMsrFilterRange; MAX_MSR_RANGES],
+}
+
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug)]
 pub enum Error {
@@ -54,11 +100,20 @@ pub enum Error {
     /// Failed to set KVM vm pit state.
     VmSetPit2(kvm_ioctls::Error),
     #[cfg(target_arch = "x86_64")]
+    /// Failed to set the address of the EPT identity map.
+    VmSetIdentityMap(utils::errno::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// Failed to set the KVM vm pit reinjection policy.
+    VmSetPitReinject(utils::errno::Error),
+    #[cfg(target_arch = "x86_64")]
     /// Failed to set KVM vm clock.
     VmSetClock(kvm_ioctls::Error),
     #[cfg(target_arch = "x86_64")]
     /// Failed to set KVM vm irqchip.
     VmSetIrqChip(kvm_ioctls::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// Failed to set the MSR filter of the vm.
+    VmSetMsrFilter(utils::errno::Error),
     /// Cannot configure the microvm.
     VmSetup(kvm_ioctls::Error),
 }
@@ -88,9 +143,15 @@ impl Display for Error {
             #[cfg(target_arch = "x86_64")]
             VmSetPit2(e) => write!(f, "Failed to set KVM vm pit state: {}", e),
             #[cfg(target_arch = "x86_64")]
+            VmSetIdentityMap(e) => write!(f, "Failed to set KVM vm identity map address: {}", e),
+            #[cfg(target_arch = "x86_64")]
+            VmSetPitReinject(e) => write!(f, "Failed to set KVM vm pit reinject policy: {}", e),
+            #[cfg(target_arch = "x86_64")]
             VmSetClock(e) => write!(f, "Failed to set KVM vm clock: {}", e),
             #[cfg(target_arch = "x86_64")]
             VmSetIrqChip(e) => write!(f, "Failed to set KVM vm irqchip: {}", e),
+            #[cfg(target_arch = "x86_64")]
+            VmSetMsrFilter(e) => write!(f, "Failed to set KVM vm MSR filter: {}", e),
         }
     }
 }
@@ -106,6 +167,11 @@ pub struct Vm {
     supported_cpuid: CpuId,
     #[cfg(target_arch = "x86_64")]
     supported_msrs: MsrList,
+    // Whether the in-kernel PIT was created, and whether it reinjects the missed interrupts.
+    #[cfg(target_arch = "x86_64")]
+    pit_enabled: bool,
+    #[cfg(target_arch = "x86_64")]
+    pit_reinject: bool,
 
     // Arm specific fields.
     // On aarch64 we need to keep around the fd obtained by creating the VGIC device.
@@ -133,6 +199,10 @@ impl Vm {
             supported_cpuid,
             #[cfg(target_arch = "x86_64")]
             supported_msrs,
+            #[cfg(target_arch = "x86_64")]
+            pit_enabled: false,
+            #[cfg(target_arch = "x86_64")]
+            pit_reinject: true,
             #[cfg(target_arch = "aarch64")]
             irqchip_handle: None,
         })
@@ -162,22 +232,118 @@ impl Vm {
         }
         self.set_kvm_memory_regions(guest_mem, track_dirty_pages)?;
         #[cfg(target_arch = "x86_64")]
-        self.fd
-            .set_tss_address(arch::x86_64::layout::KVM_TSS_ADDRESS as usize)
-            .map_err(Error::VmSetup)?;
+        {
+            // The identity map must be placed before creating the vCPUs, or KVM picks its own
+            // address, right below the default one of the TSS.
+            let identity_map_addr = arch::x86_64::layout::KVM_IDENTITY_MAP_ADDRESS;
+            // Safe because we know that our file is a VM fd, we know the kernel will only read
+            // the correct amount of memory from our pointer, and we verify the return result.
+            let ret = unsafe {
+                ioctl_with_ref(&self.fd, KVM_SET_IDENTITY_MAP_ADDR(), &identity_map_addr)
+            };
+            if ret < 0 {
+                return Err(Error::VmSetIdentityMap(utils::errno::Error::last()));
+            }
+            self.fd
+                .set_tss_address(arch::x86_64::layout::KVM_TSS_ADDRESS as usize)
+                .map_err(Error::VmSetup)?;
+        }
 
         Ok(())
     }
 
-    /// Creates the irq chip and an in-kernel device model for the PIT.
+    /// Creates the irq chip.
     #[cfg(target_arch = "x86_64")]
     pub fn setup_irqchip(&self) -> Result<()> {
-        self.fd.create_irq_chip().map_err(Error::VmSetup)?;
+        self.fd.create_irq_chip().map_err(Error::VmSetup)
+    }
+
+    /// Creates an in-kernel device model for the PIT. Must be called after `setup_irqchip`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn create_pit(&mut self) -> Result<()> {
         let mut pit_config = kvm_pit_config::default();
         // We need to enable the emulation of a dummy speaker port stub so that writing to port 0x61
         // (i.e. KVM_SPEAKER_BASE_ADDRESS) does not trigger an exit to user space.
         pit_config.flags = KVM_PIT_SPEAKER_DUMMY;
-        self.fd.create_pit2(pit_config).map_err(Error::VmSetup)
+        self.fd.create_pit2(pit_config).map_err(Error::VmSetup)?;
+        self.pit_enabled = true;
+        Ok(())
+    }
+
+    /// Sets whether the in-kernel PIT reinjects the timer interrupts missed by the guest.
+    /// Must be called after `create_pit`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_pit_reinject(&mut self, reinject: bool) -> Result<()> {
+        let reinject_control = kvm_reinject_control {
+            pit_reinject: reinject as u8,
+            ..Default::default()
+        };
+        // Safe because we know that our file is a VM fd, we know the kernel will only read
+        // the correct amount of memory from our pointer, and we verify the return result.
+        let ret = unsafe { ioctl_with_ref(&self.fd, KVM_REINJECT_CONTROL(), &reinject_control) };
+        if ret < 0 {
+            return Err(Error::VmSetPitReinject(utils::errno::Error::last()));
+        }
+        self.pit_reinject = reinject;
+        Ok(())
+    }
+
+    /// Filters the accesses of the guest to the MSRs, as set by the policy. The denied accesses
+    /// exit to the VMM when the policy reads them as zero, and raise a fault otherwise.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_msr_policy(&self, policy: &MsrPolicy) -> Result<()> {
+        if policy.deny_action == MsrDenyAction::ReadZero {
+            let mut cap = kvm_enable_cap {
+                cap: KVM_CAP_X86_USER_SPACE_MSR,
+                ..Default::default()
+            };
+            cap.args[0] = KVM_MSR_EXIT_REASON_FILTER;
+            // Safe because we know that our file is a VM fd, we know the kernel will only read
+            // the correct amount of memory from our pointer, and we verify the return result.
+            let ret = unsafe { ioctl_with_ref(&self.fd, KVM_ENABLE_CAP(), &cap) };
+            if ret < 0 {
+                return Err(Error::VmSetMsrFilter(utils::errno::Error::last()));
+            }
+        }
+
+        // KVM checks the ranges in order, so the allowed ranges come first to take precedence.
+        let bitmap_len = |range: &MsrRange| (range.count as usize + 7) / 8;
+        let bitmaps: Vec<Vec<u8>> = policy
+            .allow
+            .iter()
+            .map(|range| vec![0xff; bitmap_len(range)])
+            .chain(policy.deny.iter().map(|range| vec![0; bitmap_len(range)]))
+            .collect();
+        let mut filter = KvmMsrFilter {
+            flags: KVM_MSR_FILTER_DEFAULT_ALLOW,
+            ranges: [KvmMsrFilterRange {
+                flags: 0,
+                nmsrs: 0,
+                base: 0,
+                bitmap: std::ptr::null(),
+            }; MAX_MSR_RANGES],
+        };
+        for (filter_range, (range, bitmap)) in filter.ranges.iter_mut().zip(
+            policy
+                .allow
+                .iter()
+                .chain(policy.deny.iter())
+                .zip(bitmaps.iter()),
+        ) {
+            *filter_range = KvmMsrFilterRange {
+                flags: KVM_MSR_FILTER_READ | KVM_MSR_FILTER_WRITE,
+                nmsrs: range.count,
+                base: range.index,
+                bitmap: bitmap.as_ptr(),
+            };
+        }
+        // Safe because we know that our file is a VM fd, the bitmaps outlive the call and hold
+        // the bits of each range, and we verify the return result.
+        let ret = unsafe { ioctl_with_ref(&self.fd, KVM_X86_SET_MSR_FILTER(), &filter) };
+        if ret < 0 {
+            return Err(Error::VmSetMsrFilter(utils::errno::Error::last()));
+        }
+        Ok(())
     }
 
     /// Creates the GIC (Global Interrupt Controller).
@@ -207,7 +373,11 @@ impl Vm {
     #[cfg(target_arch = "x86_64")]
     /// Saves and returns the Kvm Vm state.
     pub fn save_state(&self) -> Result<VmState> {
-        let pitstate = self.fd.get_pit2().map_err(Error::VmGetPit2)?;
+        let pitstate = if self.pit_enabled {
+            self.fd.get_pit2().map_err(Error::VmGetPit2)?
+        } else {
+            kvm_pit_state2::default()
+        };
 
         let mut clock = self.fd.get_clock().map_err(Error::VmGetClock)?;
         // This bit is not accepted in SET_CLOCK, clear it.
@@ -233,6 +403,8 @@ impl Vm {
 
         Ok(VmState {
             pitstate,
+            pit_enabled: self.pit_enabled,
+            pit_reinject: self.pit_reinject,
             clock,
             pic_master,
             pic_slave,
@@ -241,11 +413,17 @@ impl Vm {
     }
 
     #[cfg(target_arch = "x86_64")]
-    /// Restores the Kvm Vm state.
-    pub fn restore_state(&self, state: &VmState) -> Result<()> {
-        self.fd
-            .set_pit2(&state.pitstate)
-            .map_err(Error::VmSetPit2)?;
+    /// Restores the Kvm Vm state, creating the PIT if the VM had one. Must be called after
+    /// `setup_irqchip`.
+    pub fn restore_state(&mut self, state: &VmState) -> Result<()> {
+        if state.pit_enabled {
+            self.create_pit()?;
+            self.fd
+                .set_pit2(&state.pitstate)
+                .map_err(Error::VmSetPit2)?;
+            // The reinjection policy is not part of the PIT state KVM saves.
+            self.set_pit_reinject(state.pit_reinject)?;
+        }
         self.fd.set_clock(&state.clock).map_err(Error::VmSetClock)?;
         self.fd
             .set_irqchip(&state.pic_master)
@@ -259,6 +437,16 @@ impl Vm {
         Ok(())
     }
 
+    #[cfg(target_arch = "x86_64")]
+    /// Moves the KVM clock forward, as if the guest had kept running for `elapsed_ns`.
+    pub fn advance_clock(&self, elapsed_ns: u64) -> Result<()> {
+        let mut clock = self.fd.get_clock().map_err(Error::VmGetClock)?;
+        clock.clock = clock.clock.saturating_add(elapsed_ns);
+        // This bit is not accepted in SET_CLOCK, clear it.
+        clock.flags &= !KVM_CLOCK_TSC_STABLE;
+        self.fd.set_clock(&clock).map_err(Error::VmSetClock)
+    }
+
     pub(crate) fn set_kvm_memory_regions(
         &self,
         guest_mem: &GuestMemoryMmap,
@@ -293,12 +481,53 @@ impl Vm {
 // NOTICE: Any changes to this structure require a snapshot version bump.
 pub struct VmState {
     pitstate: kvm_pit_state2,
+    /// Whether the VM has a PIT. The snapshots of the first version always have one.
+    #[version(
+        start = 2,
+        default_fn = "default_pit_enabled",
+        ser_fn = "pit_serialize"
+    )]
+    pit_enabled: bool,
+    /// Whether the PIT reinjects the missed timer interrupts, as KVM does by default.
+    #[version(start = 2, default_fn = "default_pit_reinject")]
+    pit_reinject: bool,
     clock: kvm_clock_data,
     pic_master: kvm_irqchip,
     pic_slave: kvm_irqchip,
     ioapic: kvm_irqchip,
 }
 
+#[cfg(target_arch = "x86_64")]
+impl VmState {
+    /// Whether the VM has a PIT.
+    pub fn pit_enabled(&self) -> bool {
+        self.pit_enabled
+    }
+
+    /// Whether the PIT of the VM reinjects the missed timer interrupts.
+    pub fn pit_reinject(&self) -> bool {
+        self.pit_reinject
+    }
+
+    fn default_pit_enabled(_: u16) -> bool {
+        true
+    }
+
+    fn default_pit_reinject(_: u16) -> bool {
+        true
+    }
+
+    fn pit_serialize(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && !(self.pit_enabled && self.pit_reinject) {
+            return Err(VersionizeError::Semantic(
+                "Target version does not implement the PIT configuration.".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -364,23 +593,125 @@ pub(crate) mod tests {
         // Irqchips, clock and pitstate are not configured so trying to save state should fail.
         assert!(vm.save_state().is_err());
 
-        let (vm, _mem) = setup_vm(0x1000);
+        let (mut vm, _mem) = setup_vm(0x1000);
         vm.setup_irqchip().unwrap();
+        vm.create_pit().unwrap();
+        vm.set_pit_reinject(false).unwrap();
 
         let vm_state = vm.save_state().unwrap();
         assert_eq!(
             vm_state.pitstate.flags | KVM_PIT_SPEAKER_DUMMY,
             KVM_PIT_SPEAKER_DUMMY
         );
+        assert!(vm_state.pit_enabled);
+        assert!(!vm_state.pit_reinject);
         assert_eq!(vm_state.clock.flags & KVM_CLOCK_TSC_STABLE, 0);
         assert_eq!(vm_state.pic_master.chip_id, KVM_IRQCHIP_PIC_MASTER);
         assert_eq!(vm_state.pic_slave.chip_id, KVM_IRQCHIP_PIC_SLAVE);
         assert_eq!(vm_state.ioapic.chip_id, KVM_IRQCHIP_IOAPIC);
 
-        let (vm, _mem) = setup_vm(0x1000);
+        // The PIT is created along with its reinjection policy.
+        let (mut vm, _mem) = setup_vm(0x1000);
         vm.setup_irqchip().unwrap();
+        assert!(vm.restore_state(&vm_state).is_ok());
+        assert!(vm.pit_enabled);
+        assert!(!vm.pit_reinject);
+        assert!(vm.fd.get_pit2().is_ok());
 
+        // A VM without a PIT is restored without one.
+        let (vm, _mem) = setup_vm(0x1000);
+        vm.setup_irqchip().unwrap();
+        let vm_state = vm.save_state().unwrap();
+        assert!(!vm_state.pit_enabled);
+        let (mut vm, _mem) = setup_vm(0x1000);
+        vm.setup_irqchip().unwrap();
         assert!(vm.restore_state(&vm_state).is_ok());
+        assert!(!vm.pit_enabled);
+        assert!(vm.fd.get_pit2().is_err());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_vm_state_versionize() {
+        let (mut vm, _mem) = setup_vm(0x1000);
+        vm.setup_irqchip().unwrap();
+        vm.create_pit().unwrap();
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(VmState::type_id(), 2);
+
+        // The snapshots of the first version always have a PIT, reinjecting the interrupts.
+        let mut buf = Vec::new();
+        vm.save_state()
+            .unwrap()
+            .serialize(&mut buf, &version_map, 1)
+            .unwrap();
+        let vm_state = VmState::deserialize(&mut buf.as_slice(), &version_map, 1).unwrap();
+        assert!(vm_state.pit_enabled && vm_state.pit_reinject);
+
+        vm.set_pit_reinject(false).unwrap();
+        let vm_state = vm.save_state().unwrap();
+        assert!(vm_state
+            .serialize(&mut Vec::new(), &version_map, 1)
+            .is_err());
+        let mut buf = Vec::new();
+        vm_state.serialize(&mut buf, &version_map, 2).unwrap();
+        let mut vm_state = VmState::deserialize(&mut buf.as_slice(), &version_map, 2).unwrap();
+        assert!(vm_state.pit_enabled && !vm_state.pit_reinject);
+
+        vm_state.pit_enabled = false;
+        assert!(vm_state
+            .serialize(&mut Vec::new(), &version_map, 1)
+            .is_err());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_advance_clock() {
+        let (vm, _mem) = setup_vm(0x1000);
+        let clock = vm.fd.get_clock().unwrap().clock;
+
+        // An hour goes by in the guest.
+        let hour_ns = 3600 * 1_000_000_000;
+        vm.advance_clock(hour_ns).unwrap();
+        assert!(vm.fd.get_clock().unwrap().clock >= clock + hour_ns);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_set_pit_reinject() {
+        let (mut vm, _mem) = setup_vm(0x1000);
+        vm.setup_irqchip().unwrap();
+        // The PIT has not been created yet.
+        assert!(vm.set_pit_reinject(false).is_err());
+
+        vm.create_pit().unwrap();
+        assert!(vm.set_pit_reinject(false).is_ok());
+        assert!(!vm.pit_reinject);
+        assert!(vm.set_pit_reinject(true).is_ok());
+        assert!(vm.pit_reinject);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_set_msr_policy() {
+        let (vm, _mem) = setup_vm(0x1000);
+        let mut policy = MsrPolicy {
+            allow: vec![MsrRange {
+                index: 0x10,
+                count: 1,
+            }],
+            deny: vec![MsrRange {
+                index: 0,
+                count: 0x100,
+            }],
+            deny_action: MsrDenyAction::Fault,
+        };
+        assert!(vm.set_msr_policy(&policy).is_ok());
+
+        policy.deny_action = MsrDenyAction::ReadZero;
+        assert!(vm.set_msr_policy(&policy).is_ok());
     }
 
     #[test]
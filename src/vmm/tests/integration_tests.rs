@@ -20,7 +20,7 @@ use utils::tempfile::TempFile;
 #[cfg(target_arch = "x86_64")]
 use vmm::builder::build_microvm_from_snapshot;
 use vmm::builder::{build_microvm_for_boot, setup_serial_device};
-use vmm::default_syscalls::get_seccomp_filter;
+use vmm::default_syscalls::{get_seccomp_filter, get_teardown_seccomp_filter};
 #[cfg(target_arch = "x86_64")]
 use vmm::persist;
 #[cfg(target_arch = "x86_64")]
@@ -177,6 +177,48 @@ fn test_vmm_seccomp() {
     }
 }
 
+#[test]
+fn test_teardown_and_boot_again() {
+    // Tests that the VMM thread, restricted by the built-in teardown filter, builds and runs
+    // another microVM once the first one is torn down, which the default filter prevents.
+    let pid = unsafe { libc::fork() };
+    match pid {
+        0 => {
+            set_panic_hook();
+
+            let boot_source_cfg: BootSourceConfig =
+                MockBootSourceConfig::new().with_default_boot_args().into();
+            let mut resources: VmResources = MockVmResources::new()
+                .with_boot_source(boot_source_cfg)
+                .into();
+            resources.allow_teardown = true;
+            let filter = get_teardown_seccomp_filter(SeccompLevel::Advanced).unwrap();
+
+            let mut event_manager = EventManager::new().unwrap();
+            let vmm = build_microvm_for_boot(&resources, &mut event_manager, &filter).unwrap();
+            vmm.lock().unwrap().teardown().unwrap();
+            event_manager.remove_all_subscribers().unwrap();
+            assert_eq!(Arc::strong_count(&vmm), 1);
+            drop(vmm);
+
+            let vmm = build_microvm_for_boot(&resources, &mut event_manager, &filter).unwrap();
+
+            // On x86_64, the vmm should exit once its workload completes and signals the exit event.
+            // On aarch64, the test kernel doesn't exit, so the vmm is force-stopped.
+            let _ = event_manager.run_with_timeout(500).unwrap();
+
+            #[cfg(target_arch = "x86_64")]
+            vmm.lock().unwrap().stop(-1); // If we got here, something went wrong.
+            #[cfg(target_arch = "aarch64")]
+            vmm.lock().unwrap().stop(0);
+        }
+        vmm_pid => {
+            // Parent process: wait for the vmm to exit.
+            wait_vmm_child_process(vmm_pid);
+        }
+    }
+}
+
 #[test]
 fn test_exit_vcpus() {
     // Tests that exiting vCPUs works.
@@ -206,14 +248,17 @@ fn test_pause_resume_microvm() {
             set_panic_hook();
 
             let (vmm, mut event_manager) = default_vmm(None);
+            assert!(!vmm.lock().unwrap().is_paused());
 
             // There's a race between this thread and the vcpu thread, but this thread
             // should be able to pause vcpu thread before it finishes running its test-binary.
             assert!(vmm.lock().unwrap().pause_vm().is_ok());
+            assert!(vmm.lock().unwrap().is_paused());
             // Pausing again the microVM should not fail (microVM remains in the
             // `Paused` state).
             assert!(vmm.lock().unwrap().pause_vm().is_ok());
             assert!(vmm.lock().unwrap().resume_vm().is_ok());
+            assert!(!vmm.lock().unwrap().is_paused());
 
             let _ = event_manager.run_with_timeout(500).unwrap();
 
@@ -354,6 +399,9 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
                 snapshot_type,
                 snapshot_path: snapshot_file.as_path().to_path_buf(),
                 mem_file_path: memory_file.as_path().to_path_buf(),
+                single_file: false,
+                dedup_pages: false,
+                compact_diff: false,
                 version: Some(String::from("0.24.0")),
             };
 
@@ -389,8 +437,11 @@ fn verify_create_snapshot(is_diff: bool) -> (TempFile, TempFile) {
 
             // Verify deserialized data.
             // The default vmm has no devices and one vCPU.
+            #[cfg(feature = "block")]
             assert_eq!(restored_microvm_state.device_states.block_devices.len(), 0);
+            #[cfg(feature = "net")]
             assert_eq!(restored_microvm_state.device_states.net_devices.len(), 0);
+            #[cfg(feature = "vsock")]
             assert!(restored_microvm_state.device_states.vsock_device.is_none());
             assert_eq!(restored_microvm_state.vcpu_states.len(), 1);
         }
@@ -430,7 +481,9 @@ fn verify_load_snapshot(snapshot_file: TempFile, memory_file: TempFile) {
                 microvm_state,
                 mem,
                 false,
+                0,
                 &empty_seccomp_filter,
+                false,
             )
             .unwrap();
             // For now we're happy we got this far, we don't test what the guest is actually doing.
@@ -463,6 +516,97 @@ fn test_create_and_load_snapshot() {
     verify_load_snapshot(snapshot_file, memory_file);
 }
 
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn test_create_and_load_single_file_snapshot() {
+    use std::path::PathBuf;
+    use vmm::vmm_config::snapshot::LoadSnapshotParams;
+
+    let snapshot_file = TempFile::new().unwrap();
+
+    let pid = unsafe { libc::fork() };
+    match pid {
+        0 => {
+            set_panic_hook();
+
+            let (vmm, _) = create_vmm(Some(NOISY_KERNEL_IMAGE), false);
+
+            // Be sure that the microVM is running.
+            thread::sleep(Duration::from_millis(200));
+
+            // Pause microVM.
+            vmm.lock().unwrap().pause_vm().unwrap();
+
+            // Create the snapshot, with no memory file.
+            let snapshot_params = CreateSnapshotParams {
+                snapshot_type: SnapshotType::Full,
+                snapshot_path: snapshot_file.as_path().to_path_buf(),
+                mem_file_path: PathBuf::new(),
+                single_file: true,
+                dedup_pages: false,
+                compact_diff: false,
+                version: None,
+            };
+
+            {
+                let mut locked_vmm = vmm.lock().unwrap();
+                persist::create_snapshot(&mut locked_vmm, &snapshot_params, VERSION_MAP.clone())
+                    .unwrap();
+            }
+
+            vmm.lock().unwrap().stop(0);
+        }
+        vmm_pid => {
+            // Parent process: wait for the vmm to exit.
+            wait_vmm_child_process(vmm_pid);
+        }
+    }
+
+    // The snapshot file holds the guest memory.
+    let snapshot_len = snapshot_file.as_file().metadata().unwrap().len();
+    assert!(snapshot_len >> 20 >= 1);
+
+    let pid = unsafe { libc::fork() };
+    match pid {
+        0 => {
+            set_panic_hook();
+            let mut event_manager = EventManager::new().unwrap();
+            let empty_seccomp_filter = get_seccomp_filter(SeccompLevel::None).unwrap();
+
+            // The format of the snapshot is recognized.
+            let load_params = LoadSnapshotParams {
+                snapshot_path: snapshot_file.as_path().to_path_buf(),
+                mem_file_path: PathBuf::new(),
+                mem_diff_paths: Vec::new(),
+                enable_diff_snapshots: false,
+                resume_vm: false,
+                #[cfg(feature = "balloon")]
+                balloon_amount_mib: None,
+                #[cfg(feature = "balloon")]
+                deflate_balloon: false,
+                mem_backend: None,
+                mem_hints: None,
+                io_threads: None,
+                mmds_data: None,
+                clone: None,
+            };
+            let vmm = persist::restore_from_snapshot(
+                &mut event_manager,
+                &empty_seccomp_filter,
+                false,
+                &load_params,
+                VERSION_MAP.clone(),
+            )
+            .unwrap();
+            vmm.lock().unwrap().stop(0);
+        }
+        vmm_pid => {
+            // Parent process: wait for the vmm to exit.
+            wait_vmm_child_process(vmm_pid);
+        }
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 #[test]
 fn test_snapshot_cpu_vendor() {
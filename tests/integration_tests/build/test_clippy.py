@@ -6,11 +6,14 @@
 import platform
 import pytest
 import framework.utils as utils
+from framework.defs import FC_WORKSPACE_DIR
 
 SUCCESS_CODE = 0
 MACHINE = platform.machine()
 TARGETS = ["{}-unknown-linux-gnu".format(MACHINE),
            "{}-unknown-linux-musl".format(MACHINE)]
+# The crates whose optional devices are picked with cargo features.
+FEATURE_CRATES = ["devices", "vmm", "api_server", "firecracker"]
 
 
 @pytest.mark.parametrize(
@@ -22,3 +25,28 @@ def test_rust_clippy(target):
     utils.run_cmd(
         'cargo clippy --target {} --all --profile test'
         ' -- -D warnings'.format(target))
+
+
+@pytest.mark.parametrize(
+    "crate",
+    FEATURE_CRATES
+)
+def test_rust_clippy_no_default_features(crate):
+    """Fails if a crate does not build cleanly without its optional devices."""
+    # The features are picked per package, so cargo is run in the crate.
+    utils.run_cmd(
+        'cd {}/src/{} && cargo clippy --target {}-unknown-linux-gnu'
+        ' --no-default-features -- -D warnings'.format(
+            FC_WORKSPACE_DIR, crate, MACHINE))
+
+
+@pytest.mark.skipif(
+    MACHINE != "x86_64",
+    reason="VFIO passthrough is only supported on x86_64."
+)
+def test_rust_clippy_vfio():
+    """Fails if the VFIO passthrough does not build cleanly."""
+    utils.run_cmd(
+        'cd {}/src/firecracker && cargo clippy --target {}-unknown-linux-gnu'
+        ' --all-targets --features vfio -- -D warnings'.format(
+            FC_WORKSPACE_DIR, MACHINE))
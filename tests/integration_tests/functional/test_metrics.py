@@ -42,6 +42,7 @@ def test_flush_metrics(test_microvm_with_api):
         'put_api_requests',
         'rtc',
         'seccomp',
+        'timings_us',
         'vcpu',
         'vmm',
         'uart',
@@ -363,7 +363,8 @@ def test_mmds_dummy(test_microvm_with_ssh):
     response = test_microvm.mmds.patch(json=dummy_json)
     assert test_microvm.api_session.is_status_bad_request(response.status_code)
     fault_json = {
-        "fault_message": "The MMDS data store is not initialized."
+        "fault_message": "The MMDS data store is not initialized.",
+        "error_code": "InvalidState"
     }
     assert response.json() == fault_json
 
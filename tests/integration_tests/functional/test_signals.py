@@ -6,7 +6,7 @@ import json
 import os
 from signal import \
     (SIGBUS, SIGRTMIN, SIGSEGV, SIGXFSZ,
-     SIGXCPU, SIGPIPE, SIGHUP, SIGILL)
+     SIGXCPU, SIGPIPE, SIGHUP, SIGILL, SIGTERM)
 from time import sleep
 import resource as res
 import pytest
@@ -22,12 +22,13 @@ signum_str = {
     SIGPIPE: "sigpipe",
     SIGHUP: "sighup",
     SIGILL: "sigill",
+    SIGTERM: "sigterm",
 }
 
 
 @pytest.mark.parametrize(
     "signum",
-    [SIGBUS, SIGSEGV, SIGXFSZ, SIGXCPU, SIGPIPE, SIGHUP, SIGILL]
+    [SIGBUS, SIGSEGV, SIGXFSZ, SIGXCPU, SIGPIPE, SIGILL]
 )
 def test_generic_signal_handler(test_microvm_with_api, signum):
     """Test signal handling for all handled signals."""
@@ -66,6 +67,83 @@ def test_generic_signal_handler(test_microvm_with_api, signum):
     assert metric_line["signals"][signum_str[signum]] == 1
 
 
+def test_sighup_reopens_files(test_microvm_with_api):
+    """Test that SIGHUP reopens the metrics file instead of shutting down."""
+    microvm = test_microvm_with_api
+    microvm.spawn()
+
+    # We don't need to monitor the memory for this test.
+    microvm.memory_monitor = None
+
+    microvm.basic_config()
+
+    # Configure metrics based on a file.
+    metrics_path = os.path.join(microvm.path, 'metrics_file')
+    utils.run_cmd("touch {}".format(metrics_path))
+    response = microvm.metrics.put(
+        metrics_path=microvm.create_jailed_resource(metrics_path)
+    )
+    assert microvm.api_session.is_status_no_content(response.status_code)
+
+    microvm.start()
+    firecracker_pid = int(microvm.jailer_clone_pid)
+    sleep(0.5)
+
+    # Rotate the metrics file.
+    metrics_jail_path = os.path.join(microvm.chroot(), metrics_path)
+    os.rename(metrics_jail_path, metrics_jail_path + '.1')
+    utils.run_cmd("touch {}".format(metrics_jail_path))
+    os.chmod(metrics_jail_path, 0o666)
+
+    os.kill(firecracker_pid, SIGHUP)
+    sleep(0.5)
+
+    response = microvm.actions.put(action_type='FlushMetrics')
+    assert microvm.api_session.is_status_no_content(response.status_code)
+
+    with open(metrics_jail_path + '.1') as rotated:
+        assert len(rotated.readlines()) == 1
+    with open(metrics_jail_path) as metrics_fd:
+        metric_line = json.loads(metrics_fd.readlines()[0])
+    assert metric_line["signals"]["sighup"] == 1
+    utils.run_cmd("ps -p {}".format(firecracker_pid))
+
+
+def test_sigterm_shuts_down_gracefully(test_microvm_with_api):
+    """Test that SIGTERM pauses the microVM and flushes the metrics."""
+    microvm = test_microvm_with_api
+    microvm.spawn()
+
+    # We don't need to monitor the memory for this test.
+    microvm.memory_monitor = None
+
+    microvm.basic_config()
+
+    # Configure metrics based on a file.
+    metrics_path = os.path.join(microvm.path, 'metrics_fifo')
+    utils.run_cmd("touch {}".format(metrics_path))
+    response = microvm.metrics.put(
+        metrics_path=microvm.create_jailed_resource(metrics_path)
+    )
+    assert microvm.api_session.is_status_no_content(response.status_code)
+
+    microvm.start()
+    firecracker_pid = int(microvm.jailer_clone_pid)
+    sleep(0.5)
+
+    metrics_jail_path = os.path.join(microvm.chroot(), metrics_path)
+    metrics_fd = open(metrics_jail_path)
+
+    line_metrics = metrics_fd.readlines()
+    assert len(line_metrics) == 1
+
+    os.kill(firecracker_pid, SIGTERM)
+    microvm.check_log_message('Shutting down after intercepting SIGTERM.')
+
+    metric_line = json.loads(metrics_fd.readlines()[0])
+    assert metric_line["signals"][signum_str[SIGTERM]] == 1
+
+
 def test_sigxfsz_handler(test_microvm_with_api):
     """Test intercepting and handling SIGXFSZ."""
     microvm = test_microvm_with_api